@@ -1,3 +1,5 @@
+mod proxy;
+
 use core::fmt;
 use std::collections::BTreeMap;
 use std::fmt::Debug;
@@ -6,45 +8,102 @@ use std::path::PathBuf;
 use std::process::exit;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{ffi, fs, result};
 
 use bitcoin::{secp256k1, Address, Network, Transaction};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use fedimint_aead::get_password_hash;
 use fedimint_client::module::gen::{
     ClientModuleGen, ClientModuleGenRegistry, ClientModuleGenRegistryExt,
 };
 use fedimint_core::admin_client::WsAdminClient;
 use fedimint_core::api::{
-    FederationApiExt, FederationError, GlobalFederationApi, IFederationApi, WsClientConnectInfo,
-    WsFederationApi,
+    ConnectionOptions, FederationApiExt, FederationError, GlobalFederationApi, IFederationApi,
+    TlsRoots, WsClientConnectInfo, WsFederationApi,
 };
 use fedimint_core::config::{load_from_file, ClientConfig, FederationId};
 use fedimint_core::core::{ModuleInstanceId, ModuleKind};
 use fedimint_core::db::{Database, DatabaseValue};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::version::ApiVersionSet;
 use fedimint_core::module::{ApiAuth, ApiRequestErased};
 use fedimint_core::query::EventuallyConsistent;
-use fedimint_core::task::TaskGroup;
-use fedimint_core::{Amount, OutPoint, PeerId, TieredMulti, TransactionId};
+use fedimint_core::task::{self, TaskGroup};
+use fedimint_core::{Amount, AmountUnit, Feerate, OutPoint, PeerId, TieredMulti, TransactionId};
 use fedimint_ln_client::LightningClientGen;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_client::MintClientGen;
-use mint_client::mint::SpendableNote;
+use mint_client::amount_format::{AmountFormatter, DisplayUnit, ExchangeRate, Locale};
+use mint_client::mint::{IssuanceDiscrepancy, P2pkNote, SpendableNote};
 use mint_client::modules::ln::contracts::ContractId;
+use mint_client::modules::ln::GatewayFee;
 use mint_client::modules::wallet::txoproof::TxOutProof;
-use mint_client::modules::wallet::WalletClientGen;
+use mint_client::modules::wallet::{PegOutFees, WalletClientGen};
+use mint_client::receipt::{verify_receipt, PaymentReceipt};
+use mint_client::secret::Mnemonic;
 use mint_client::utils::{
     from_hex, parse_bitcoin_amount, parse_ecash, parse_fedimint_amount, parse_node_pub_key,
-    parse_peer_id, serialize_ecash,
+    parse_p2pk_ecash, parse_peer_id, serialize_ecash, serialize_p2pk_ecash,
+};
+use mint_client::{
+    Client, ClientError, IncomingPaymentOutcome, NoteRefreshEvent, OutgoingPaymentOutcome,
+    ReceivePreview, UserClientConfig, DEFAULT_HEDGING_DELAY,
 };
-use mint_client::{Client, UserClientConfig};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use thiserror::Error;
-use tracing::info;
+use tokio::net::UnixStream;
+use tracing::{info, warn};
 use url::Url;
 
+use crate::proxy::ProxyRequest;
+
+/// Version of the structure printed with `--format json`, bumped whenever
+/// fields of an output or error change incompatibly
+const JSON_OUTPUT_VERSION: u32 = 1;
+
+/// How the cli prints the outputs and errors of commands
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum, Serialize, Deserialize)]
+enum OutputFormat {
+    /// The output or error itself as pretty printed JSON
+    Text,
+    /// The output or error wrapped into a versioned [`JsonOutput`] on a single
+    /// line, errors are printed to stdout as well
+    Json,
+}
+
+/// Which state `restore` recovers from the federation
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+enum RestoreScope {
+    /// All notes, pending issuances and note indices, then the lightning
+    /// contracts
+    All,
+    /// Only the spendable notes, keeping pending issuances and note indices
+    Notes,
+    /// Only the states of stored lightning contracts, without scanning epochs
+    LnContracts,
+}
+
+/// Which root certificates the guardians' TLS certificates are verified
+/// against, see [`TlsRoots`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+enum CliTlsRoots {
+    /// The Mozilla root certificates bundled with the client
+    Webpki,
+    /// The root certificates of the operating system
+    Native,
+}
+
+impl From<CliTlsRoots> for TlsRoots {
+    fn from(roots: CliTlsRoots) -> Self {
+        match roots {
+            CliTlsRoots::Webpki => TlsRoots::WebPki,
+            CliTlsRoots::Native => TlsRoots::Native,
+        }
+    }
+}
+
 /// Type of output the cli produces
 #[derive(Serialize)]
 #[serde(rename_all(serialize = "snake_case"))]
@@ -79,8 +138,29 @@ enum CliOutput {
         note: String,
     },
 
+    NoteDecode {
+        federation_id: Option<FederationId>,
+        total_amount: Amount,
+        total_num_notes: usize,
+        details: BTreeMap<Amount, usize>,
+        notes: Vec<NoteDetails>,
+    },
+
+    NoteValidate {
+        federation_id: FederationId,
+        all_valid: bool,
+        spent_amount: Amount,
+        notes: Vec<NoteDetails>,
+    },
+
     PegOut {
         tx_id: bitcoin::Txid,
+        fees: PegOutFees,
+        total_cost: Amount,
+    },
+
+    BumpPegOut {
+        id: TransactionId,
     },
 
     LnPay {
@@ -91,6 +171,22 @@ enum CliOutput {
         issuance: Vec<OutPoint>,
     },
 
+    RefreshNotes {
+        event: Option<NoteRefreshEvent>,
+    },
+
+    CheckIssuance {
+        discrepancies: Vec<IssuanceDiscrepancy>,
+    },
+
+    ExportMnemonic {
+        mnemonic: String,
+    },
+
+    ImportMnemonic {
+        word_count: usize,
+    },
+
     Info {
         federation_id: FederationId,
         network: Network,
@@ -98,12 +194,25 @@ enum CliOutput {
         total_amount: Amount,
         total_num_notes: usize,
         details: BTreeMap<Amount, usize>,
+        /// The total amount in the unit and locale of the cli
+        formatted_total: String,
+        /// The number of notes of each denomination
+        formatted_notes: Vec<String>,
+    },
+
+    ConvertAmount {
+        amount: Amount,
+        formatted: String,
     },
 
     LnInvoice {
         invoice: lightning_invoice::Invoice,
     },
 
+    LnPreviewReceive {
+        preview: ReceivePreview,
+    },
+
     WaitInvoice {
         paid_in_tx: OutPoint,
     },
@@ -112,6 +221,20 @@ enum CliOutput {
         reached: u64,
     },
 
+    AwaitDeposit {
+        issuance: Vec<OutPoint>,
+    },
+
+    AwaitInvoice {
+        amount: Amount,
+        issuance: Vec<OutPoint>,
+    },
+
+    AwaitLnPay {
+        contract_id: ContractId,
+        outcome: OutgoingPaymentOutcome,
+    },
+
     ConnectInfo {
         connect_info: WsClientConnectInfo,
     },
@@ -121,10 +244,28 @@ enum CliOutput {
         id: FederationId,
     },
 
+    Receipt {
+        receipt: PaymentReceipt,
+    },
+
+    VerifyReceipt {
+        txid: TransactionId,
+        out_point: Option<OutPoint>,
+        transaction: String,
+    },
+
     JoinFederation {
         joined: String,
     },
 
+    ConnectionOptions {
+        options: ConnectionOptions,
+    },
+
+    LnGatewayFee {
+        fee: GatewayFee,
+    },
+
     ListGateways {
         num_gateways: usize,
         gateways: Value,
@@ -146,9 +287,43 @@ enum CliOutput {
         count: u64,
     },
 
+    ApiVersions {
+        versions: ApiVersionSet,
+    },
+
     Raw(serde_json::Value),
 }
 
+/// A single note of an ecash token
+#[derive(Serialize)]
+struct NoteDetails {
+    amount: Amount,
+    nonce: String,
+    /// Whether the federation of our config signed the note, `None` without a
+    /// config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    valid_signature: Option<bool>,
+    /// Whether the federation saw the note spent already, `None` if it wasn't
+    /// asked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spent: Option<bool>,
+    /// Why the federation wouldn't accept spending the note
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl NoteDetails {
+    fn new(amount: Amount, note: &SpendableNote) -> Self {
+        NoteDetails {
+            amount,
+            nonce: note.note.0 .0.to_string(),
+            valid_signature: None,
+            spent: None,
+            error: None,
+        }
+    }
+}
+
 impl fmt::Display for CliOutput {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", serde_json::to_string_pretty(self).unwrap())
@@ -156,7 +331,7 @@ impl fmt::Display for CliOutput {
 }
 
 /// Types of error the cli return
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 enum CliErrorKind {
     NetworkError,
     IOError,
@@ -170,6 +345,25 @@ enum CliErrorKind {
     GeneralFailure,
 }
 
+impl CliErrorKind {
+    /// Stable code of the error kind, scripts should match on it rather than
+    /// on the message
+    fn code(&self) -> u32 {
+        match self {
+            CliErrorKind::NetworkError => 1,
+            CliErrorKind::IOError => 2,
+            CliErrorKind::InvalidValue => 3,
+            CliErrorKind::OSError => 4,
+            CliErrorKind::GeneralFederationError => 5,
+            CliErrorKind::AlreadySpent => 6,
+            CliErrorKind::Timeout => 7,
+            CliErrorKind::InsufficientBalance => 8,
+            CliErrorKind::SerializationError => 9,
+            CliErrorKind::GeneralFailure => 10,
+        }
+    }
+}
+
 /// `Result` with `CliError` as `Error`
 type CliResult<E> = Result<E, CliError>;
 
@@ -281,6 +475,122 @@ impl fmt::Display for CliError {
     }
 }
 
+/// What the cli prints with `--format json`
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    /// Always [`JSON_OUTPUT_VERSION`]
+    version: u32,
+    success: bool,
+    #[serde(flatten)]
+    body: JsonOutputBody<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonOutputBody<'a> {
+    Result { result: &'a CliOutput },
+    Error { error: JsonError<'a> },
+}
+
+#[derive(Serialize)]
+struct JsonError<'a> {
+    kind: CliErrorKind,
+    code: u32,
+    message: &'a str,
+    raw_error: Option<String>,
+}
+
+impl<'a> JsonOutput<'a> {
+    fn new(result: &'a CliOutputResult) -> Self {
+        let body = match result {
+            Ok(output) => JsonOutputBody::Result { result: output },
+            Err(err) => JsonOutputBody::Error {
+                error: JsonError {
+                    kind: err.kind,
+                    code: err.kind.code(),
+                    message: &err.message,
+                    raw_error: err.raw_error.as_ref().map(|e| e.to_string()),
+                },
+            },
+        };
+
+        JsonOutput {
+            version: JSON_OUTPUT_VERSION,
+            success: result.is_ok(),
+            body,
+        }
+    }
+}
+
+/// Whether the raw `args` or the `FM_CLI_FORMAT` variable ask for JSON, for
+/// when the arguments can't be parsed
+fn json_format_requested(args: &[String], env_format: Option<&str>) -> bool {
+    env_format == Some("json")
+        || args.iter().any(|arg| arg == "--format=json")
+        || args
+            .windows(2)
+            .any(|pair| pair[0] == "--format" && pair[1] == "json")
+}
+
+/// What an invocation of the cli prints, rendered in the requested format so
+/// the invocation a command was proxied for can print it
+#[derive(Debug, Serialize, Deserialize)]
+struct CommandOutput {
+    stdout: Option<String>,
+    stderr: Option<String>,
+    success: bool,
+}
+
+impl CommandOutput {
+    fn new(format: OutputFormat, result: &CliOutputResult) -> Self {
+        let (stdout, stderr) = match (format, result) {
+            (OutputFormat::Json, _) => (
+                Some(
+                    serde_json::to_string(&JsonOutput::new(result)).expect("cli output serializes"),
+                ),
+                None,
+            ),
+            (OutputFormat::Text, Ok(output)) => (Some(output.to_string()), None),
+            (OutputFormat::Text, Err(err)) => (None, Some(err.to_string())),
+        };
+
+        CommandOutput {
+            stdout,
+            stderr,
+            success: result.is_ok(),
+        }
+    }
+
+    fn print(&self) {
+        // ignore if there's anyone reading the stuff we're writing out
+        if let Some(stdout) = &self.stdout {
+            let _ = writeln!(std::io::stdout(), "{stdout}");
+        }
+        if let Some(stderr) = &self.stderr {
+            let _ = writeln!(std::io::stderr(), "{stderr}");
+        }
+    }
+}
+
+/// Whether the invocation shares the client database with others, see
+/// [`proxy`]
+enum SharedDb {
+    /// The invocation doesn't share the database, e.g. because it couldn't
+    /// open it
+    Unshared,
+    /// The invocation holds the database and serves the others at the socket
+    /// with the tasks of `task_group`
+    Serving {
+        socket: PathBuf,
+        task_group: TaskGroup,
+    },
+    /// Another invocation holds the database and serves at the socket
+    HeldBy(UnixStream),
+    /// Another invocation holds the database without serving it, e.g. while
+    /// it finishes the commands it runs before exiting
+    Locked,
+}
+
 #[derive(Parser)]
 #[command(version)]
 struct Opts {
@@ -288,8 +598,47 @@ struct Opts {
     #[arg(long = "data-dir", alias = "workdir", env = "FM_DATA_DIR")]
     workdir: Option<PathBuf>,
 
+    /// Format of the output, `json` prints a stable structure meant to be
+    /// parsed by scripts
+    #[arg(
+        long = "format",
+        global = true,
+        value_enum,
+        default_value = "text",
+        env = "FM_CLI_FORMAT"
+    )]
+    format: OutputFormat,
+
+    /// Unit amounts are displayed in and entered amounts default to: `msat`,
+    /// `sat`, `btc` or the code of a fiat currency given with `--btc-price`
+    #[arg(long, global = true, default_value = "msat")]
+    unit: String,
+
+    /// Price of one bitcoin in the fiat currency of `--unit`
+    #[arg(long, global = true)]
+    btc_price: Option<f64>,
+
+    /// Locale whose separators the numbers of amounts use, e.g. `de-DE`,
+    /// defaults to `en`
+    #[arg(long, global = true)]
+    locale: Option<String>,
+
+    /// Milliseconds to wait for a guardian before asking another one too, for
+    /// requests that need a single valid response, 0 asks all at once
+    #[arg(
+        long,
+        global = true,
+        env = "FM_HEDGING_DELAY_MS",
+        default_value_t = DEFAULT_HEDGING_DELAY.as_millis() as u64
+    )]
+    hedging_delay_ms: u64,
+
     #[clap(subcommand)]
     command: Command,
+
+    /// Database opened before the command ran, shared with other invocations
+    #[clap(skip)]
+    db: Option<Database>,
 }
 
 impl Opts {
@@ -299,6 +648,28 @@ impl Opts {
             .ok_or_cli_msg(CliErrorKind::IOError, "`--data-dir=` argument not set.")
     }
 
+    fn amount_formatter(&self) -> CliResult<AmountFormatter> {
+        let unit = match (self.unit.parse::<AmountUnit>(), self.btc_price) {
+            (Ok(unit), _) => DisplayUnit::Bitcoin(unit),
+            (Err(_), Some(btc_price)) => DisplayUnit::Fiat(
+                ExchangeRate::new(&self.unit, btc_price)
+                    .map_err_cli_msg(CliErrorKind::InvalidValue, "invalid exchange rate")?,
+            ),
+            (Err(e), None) => {
+                return Err(e).map_err_cli_msg(
+                    CliErrorKind::InvalidValue,
+                    format!(
+                        "unknown unit {}, fiat currencies need --btc-price",
+                        self.unit
+                    ),
+                )
+            }
+        };
+        let locale = self.locale.as_deref().map_or(Locale::EN, Locale::from_tag);
+
+        Ok(AmountFormatter::new(unit, locale))
+    }
+
     fn load_config(&self) -> CliResult<UserClientConfig> {
         let cfg_path = self.workdir()?.join("client.json");
         load_from_file(&cfg_path).map_err_cli_msg(CliErrorKind::IOError, "could not load config")
@@ -325,6 +696,10 @@ impl Opts {
     }
 
     fn load_db(&self, decoders: &ModuleDecoderRegistry) -> CliResult<Database> {
+        if let Some(db) = &self.db {
+            return Ok(db.clone());
+        }
+
         let db = self.load_rocks_db()?;
         Ok(Database::new(db, decoders.clone()))
     }
@@ -334,17 +709,50 @@ impl Opts {
         module_gens: &ClientModuleGenRegistry,
     ) -> CliResult<Client<UserClientConfig>> {
         let cfg = self.load_config()?;
+        cfg.0
+            .check_legacy_module_instances()
+            .map_err_cli_general()?;
         let decoders = self.load_decoders(&cfg, module_gens);
         let db = self.load_db(&decoders)?;
 
-        Ok(Client::new(
+        let hedging_delay =
+            (self.hedging_delay_ms > 0).then(|| Duration::from_millis(self.hedging_delay_ms));
+        let client = Client::new_with_hedging(
             cfg.clone(),
             decoders,
             module_gens.clone(),
             db,
             Default::default(),
+            hedging_delay,
         )
-        .await)
+        .await
+        .map_err_cli_msg(CliErrorKind::GeneralFailure, "could not open the client")?;
+
+        // no other client runs on the database, so operations in flight were
+        // interrupted by a crash
+        for operation_id in client.recover_interrupted_operations().await {
+            warn!(
+                "Operation {} was interrupted before its transaction was built",
+                bitcoin_hashes::hex::ToHex::to_hex(&operation_id[..])
+            );
+        }
+
+        // Users rarely check for deprecated note tiers themselves, so our notes
+        // of them are reissued whenever the client is used
+        match client
+            .refresh_deprecated_notes_if_due(rand::rngs::OsRng)
+            .await
+        {
+            Ok(Some(NoteRefreshEvent::Reissued { amount, .. })) => {
+                info!("Reissued notes worth {amount} in deprecated tiers");
+            }
+            Ok(Some(NoteRefreshEvent::ManualActionRequired { amount, reason, .. })) => {
+                warn!("Notes worth {amount} in deprecated tiers need manual action: {reason}");
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to check for deprecated note tiers: {e}"),
+        }
+        Ok(client)
     }
 }
 
@@ -385,28 +793,93 @@ enum Command {
         notes: TieredMulti<SpendableNote>,
     },
 
+    /// Inspect an ecash token received out of band
+    #[clap(subcommand)]
+    Note(NoteCommand),
+
     /// Prepare notes to send to a third party as a payment
     Spend {
         #[clap(value_parser = parse_fedimint_amount)]
         amount: Amount,
     },
 
+    /// Prepare notes to send to a third party as a payment, bound to their
+    /// public key so nobody else can spend them
+    SpendP2pk {
+        #[clap(value_parser = parse_fedimint_amount)]
+        amount: Amount,
+        /// Public key of the recipient
+        recipient: secp256k1::XOnlyPublicKey,
+    },
+
+    /// Reissue notes that were bound to our public key with `spend-p2pk`
+    ReissueP2pk {
+        #[clap(value_parser = parse_p2pk_ecash)]
+        notes: TieredMulti<P2pkNote>,
+        /// File holding the hex-encoded secret key to the public key the
+        /// notes are bound to, read from stdin if not set so it doesn't end up
+        /// in the shell history or the process list
+        #[clap(long)]
+        secret_key_file: Option<PathBuf>,
+    },
+
     /// Withdraw funds from the federation
     PegOut {
         address: Address,
         #[clap(value_parser = parse_bitcoin_amount)]
         satoshis: bitcoin::Amount,
+        /// Fee rate of the on-chain transaction in sats per kvB, defaults to
+        /// the federation's estimate
+        #[clap(long)]
+        fee_rate: Option<u64>,
+    },
+
+    /// Bump the fees of one of our unconfirmed peg-outs
+    BumpPegOut {
+        /// Bitcoin transaction id of the peg-out
+        txid: bitcoin::Txid,
+        /// Address the peg-out pays to
+        address: Address,
+        /// Increase of the fee rate in sats per kvB
+        fee_rate: u64,
     },
 
     /// Pay a lightning invoice via a gateway
-    LnPay { bolt11: lightning_invoice::Invoice },
+    LnPay {
+        bolt11: lightning_invoice::Invoice,
+        /// Node public key of the gateway paying the invoice, the active
+        /// gateway if unset
+        #[clap(long)]
+        gateway: Option<secp256k1::PublicKey>,
+    },
 
     /// Fetch (re-)issued notes and finalize issuance process
     Fetch,
 
+    /// Reissue notes in tiers the federation deprecated before they expire
+    RefreshNotes,
+
+    /// Check that all ecash requested from the federation, e.g. change, was
+    /// issued or is still pending
+    CheckIssuance,
+
+    /// Print the mnemonic the client's secrets are derived from
+    ExportMnemonic,
+
+    /// Derive the client's secrets from the mnemonic of another wallet to
+    /// recover its funds, has to run before any other command after joining
+    ImportMnemonic {
+        /// Words of the mnemonic, separated by spaces
+        mnemonic: Mnemonic,
+    },
+
     /// Display wallet info (holdings, tiers)
     Info,
 
+    /// Convert an amount entered in any unit to msat and the unit of `--unit`,
+    /// e.g. `1.5 sat` or `0,0001 BTC` with `--locale de`
+    ConvertAmount { amount: String },
+
     /// Create a lightning invoice to receive payment via gateway
     LnInvoice {
         #[clap(value_parser = parse_fedimint_amount)]
@@ -414,6 +887,32 @@ enum Command {
         #[clap(default_value = "")]
         description: String,
         expiry_time: Option<u64>,
+        /// Node public key of the gateway the invoice is routed through, the
+        /// active gateway if unset
+        #[clap(long)]
+        gateway: Option<secp256k1::PublicKey>,
+    },
+
+    /// Show the invoice amount to request to receive `amount` of ecash after
+    /// fees, and what the payer pays the gateway on top
+    LnPreviewReceive {
+        #[clap(value_parser = parse_fedimint_amount)]
+        amount: Amount,
+        /// Node public key of the gateway the invoice is routed through, the
+        /// active gateway if unset
+        #[clap(long)]
+        gateway: Option<secp256k1::PublicKey>,
+    },
+
+    /// Set the fee the offers of new invoices pay the gateway funding their
+    /// incoming contract, which it claims from the payment
+    SetLnGatewayFee {
+        /// Flat fee in millisatoshis
+        #[clap(long, default_value = "0")]
+        base_msat: u32,
+        /// Fee in millionths of the invoice amount
+        #[clap(long, default_value = "0")]
+        proportional_millionths: u32,
     },
 
     /// Wait for incoming invoice to be paid
@@ -422,6 +921,50 @@ enum Command {
     /// Wait for the fed to reach a consensus block height
     WaitBlockHeight { height: u64 },
 
+    /// Wait until a peg-in transaction was accepted and fetch the issued notes
+    AwaitDeposit {
+        /// Transaction id printed by `peg-in`
+        txid: TransactionId,
+        /// Seconds to wait before giving up, waits forever if not set
+        #[clap(long = "timeout")]
+        timeout: Option<u64>,
+    },
+
+    /// Wait until an invoice created with `ln-invoice` was paid, then claim
+    /// the payment and fetch the issued notes
+    AwaitInvoice {
+        invoice: lightning_invoice::Invoice,
+        /// Seconds to wait before giving up, waits forever if not set
+        #[clap(long = "timeout")]
+        timeout: Option<u64>,
+    },
+
+    /// Wait until the gateway paid an outgoing lightning payment or claim the
+    /// refund if it failed
+    AwaitLnPay {
+        /// Contract id printed by `ln-pay`
+        contract_id: ContractId,
+        /// Seconds to wait before giving up, waits forever if not set
+        #[clap(long = "timeout")]
+        timeout: Option<u64>,
+    },
+
+    /// Print a receipt proving that the federation accepted a transaction,
+    /// waits until the federation signed the transaction's epoch
+    Receipt {
+        txid: TransactionId,
+        /// Index of the output of the transaction the receipt is about
+        #[clap(long = "out-idx")]
+        out_idx: Option<u64>,
+    },
+
+    /// Verify a receipt printed by `receipt` against the epoch the guardians
+    /// of the federation in the config signed
+    VerifyReceipt {
+        /// Receipt in the JSON format printed by `receipt`
+        receipt: String,
+    },
+
     /// Decode connection info into its JSON representation
     DecodeConnectInfo { connect_info: WsClientConnectInfo },
 
@@ -439,6 +982,23 @@ enum Command {
     /// Join a federation using it's ConnectInfo
     JoinFederation { connect: String },
 
+    /// Set how the client connects to the guardians, e.g. through Tor, unset
+    /// options are reset to their defaults
+    SetConnectionOptions {
+        /// SOCKS5 proxy to connect through, e.g. `socks5h://127.0.0.1:9050`
+        #[clap(long)]
+        proxy: Option<Url>,
+        /// User agent sent when connecting
+        #[clap(long)]
+        user_agent: Option<String>,
+        /// Root certificates to verify the guardians' TLS certificates against
+        #[clap(long, value_enum, default_value = "webpki")]
+        tls_roots: CliTlsRoots,
+        /// Seconds to wait for a connection before giving up
+        #[clap(long)]
+        connect_timeout: Option<u64>,
+    },
+
     /// List registered gateways
     ListGateways,
 
@@ -449,11 +1009,12 @@ enum Command {
         pubkey: secp256k1::PublicKey,
     },
 
-    /// Upload the (encrypted) snapshot of mint notes to federation
+    /// Upload the (encrypted) snapshot of mint notes to federation, including
+    /// the notes of sub-accounts
     Backup,
 
     /// Restore the previously created backup of mint notes (with `backup`
-    /// command)
+    /// command), including the notes of sub-accounts
     Restore {
         /// The amount of nonces to look ahead when scanning epoch history (per
         /// amount tier)
@@ -463,6 +1024,14 @@ enum Command {
         /// notes in some rare situations.
         #[clap(long = "gap-limit", default_value = "100")]
         gap_limit: usize,
+        /// Restore only part of the client's state, which is faster if the
+        /// rest is intact
+        #[clap(long, value_enum, default_value = "all")]
+        scope: RestoreScope,
+        /// Third party archive of the epoch history to recover from if the
+        /// guardians pruned the epochs since our backup, can be repeated
+        #[clap(long = "epoch-archive")]
+        epoch_archives: Vec<Url>,
     },
 
     /// Wipe the notes data from the DB. Useful for testing backup & restore
@@ -487,6 +1056,9 @@ enum Command {
     /// Gets the current epoch count
     EpochCount,
 
+    /// Negotiate the API versions the client and the guardians have in common
+    ApiVersions,
+
     /// Call module-specific commands
     Module {
         id: ModuleSelector,
@@ -496,6 +1068,23 @@ enum Command {
     },
 }
 
+#[derive(Subcommand, Clone)]
+enum NoteCommand {
+    /// Show the tiers and amounts of the notes without contacting the
+    /// federation, signatures are checked if a config is available
+    Decode {
+        #[clap(value_parser = parse_ecash)]
+        notes: TieredMulti<SpendableNote>,
+    },
+
+    /// Check the signatures of the notes and ask the federation whether any of
+    /// them were spent already, without spending them
+    Validate {
+        #[clap(value_parser = parse_ecash)]
+        notes: TieredMulti<SpendableNote>,
+    },
+}
+
 #[derive(Clone)]
 pub enum ModuleSelector {
     Id(ModuleInstanceId),
@@ -514,12 +1103,39 @@ impl FromStr for ModuleSelector {
     }
 }
 
+/// Sets whether the federation of `client` signed each of the `notes`
+async fn check_note_signatures(
+    client: &Client<UserClientConfig>,
+    notes: &TieredMulti<SpendableNote>,
+    details: &mut [NoteDetails],
+) {
+    for ((amount, note), details) in notes.iter_items().zip(details) {
+        let note = TieredMulti::from_iter([(amount, *note)]);
+        details.valid_signature = Some(client.validate_note_signatures(&note).await.is_ok());
+    }
+}
+
+/// Awaits `future`, failing with a [`CliErrorKind::Timeout`] after `timeout`
+/// seconds if set
+async fn await_with_timeout<T>(
+    timeout: Option<u64>,
+    future: impl std::future::Future<Output = T>,
+) -> CliResult<T> {
+    match timeout {
+        Some(secs) => task::timeout(Duration::from_secs(secs), future)
+            .await
+            .map_err_cli_msg(CliErrorKind::Timeout, "timeout reached"),
+        None => Ok(future.await),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct PayRequest {
     notes: TieredMulti<SpendableNote>,
     invoice: lightning_invoice::Invoice,
 }
 
+#[derive(Clone)]
 pub struct FedimintCli {
     module_gens: ClientModuleGenRegistry,
 }
@@ -559,20 +1175,153 @@ impl FedimintCli {
     }
 
     pub async fn run(self) {
-        let cli = Opts::parse();
+        let mut cli = match Opts::try_parse() {
+            Ok(cli) => cli,
+            Err(err) => Self::exit_with_parse_error(err),
+        };
+        let format = cli.format;
+
+        let deadline = Instant::now() + proxy::DB_RELEASE_TIMEOUT;
+        let shared_db = loop {
+            match self.share_db(&mut cli).await {
+                SharedDb::Locked if Instant::now() < deadline => {
+                    task::sleep(proxy::DB_RETRY_INTERVAL).await;
+                }
+                shared_db => break shared_db,
+            }
+        };
 
-        match self.handle_command(cli).await {
-            Ok(output) => {
-                // ignore if there's anyone reading the stuff we're writing out
-                let _ = writeln!(std::io::stdout(), "{output}");
+        let output = match shared_db {
+            SharedDb::HeldBy(stream) => {
+                let request = ProxyRequest {
+                    args: std::env::args().skip(1).collect(),
+                    format,
+                };
+                proxy::forward(stream, &request).await.unwrap_or_else(|e| {
+                    let result = Err(e).map_err_cli_msg(
+                        CliErrorKind::IOError,
+                        "client database is held by another process that can't be reached",
+                    );
+                    CommandOutput::new(format, &result)
+                })
+            }
+            SharedDb::Serving { socket, task_group } => {
+                let result = self.handle_command(cli).await;
+                // the socket stays until the database is released, so others
+                // wait for us instead of failing to open it
+                if let Err(e) = task_group.shutdown_join_all(None).await {
+                    warn!("Failed to finish proxied commands: {e}");
+                }
+                let _ = fs::remove_file(socket);
+                CommandOutput::new(format, &result)
             }
-            Err(err) => {
-                let _ = writeln!(std::io::stderr(), "{err}");
-                exit(1);
+            SharedDb::Unshared | SharedDb::Locked => {
+                CommandOutput::new(format, &self.handle_command(cli).await)
             }
+        };
+        output.print();
+
+        if !output.success {
+            exit(1);
         }
     }
 
+    /// Opens the client database and lets other invocations run their
+    /// commands over it, or finds the invocation already holding it
+    async fn share_db(&self, cli: &mut Opts) -> SharedDb {
+        // without a config no command needs the database
+        let Ok(cfg) = cli.load_config() else {
+            return SharedDb::Unshared;
+        };
+        let Ok(workdir) = cli.workdir().cloned() else {
+            return SharedDb::Unshared;
+        };
+        let socket = workdir.join(proxy::SOCKET_FILE);
+        let db = match cli.load_rocks_db() {
+            Ok(db) => Database::new(db, cli.load_decoders(&cfg, &self.module_gens)),
+            Err(_) if socket.exists() => {
+                return match proxy::connect(&socket).await {
+                    Ok(stream) => SharedDb::HeldBy(stream),
+                    Err(_) => SharedDb::Locked,
+                }
+            }
+            Err(_) => return SharedDb::Unshared,
+        };
+        cli.db = Some(db.clone());
+
+        let listener = match proxy::bind(&socket) {
+            Ok(listener) => listener,
+            Err(e) => {
+                warn!(
+                    "Can't share the client database at {}: {e}",
+                    socket.display()
+                );
+                return SharedDb::Unshared;
+            }
+        };
+        let mut task_group = TaskGroup::new();
+        let serve_group = task_group.make_subgroup().await;
+        let cli = self.clone();
+        task_group
+            .spawn("Serve proxied commands", |_| {
+                proxy::serve(
+                    listener,
+                    move |request| {
+                        cli.clone()
+                            .handle_proxied(request, workdir.clone(), db.clone())
+                    },
+                    serve_group,
+                )
+            })
+            .await;
+        SharedDb::Serving { socket, task_group }
+    }
+
+    /// Runs a command another invocation sent over the shared database `db`
+    async fn handle_proxied(
+        self,
+        request: ProxyRequest,
+        workdir: PathBuf,
+        db: Database,
+    ) -> CommandOutput {
+        let args = std::iter::once("fedimint-cli".to_string()).chain(request.args);
+        let result = match Opts::try_parse_from(args) {
+            Ok(mut cli) => {
+                cli.workdir = Some(workdir);
+                cli.db = Some(db);
+                self.handle_command(cli).await
+            }
+            Err(e) => Err(e).map_err_cli_msg(CliErrorKind::InvalidValue, "invalid arguments"),
+        };
+        CommandOutput::new(request.format, &result)
+    }
+
+    /// Prints invalid arguments as an error of the requested format, since the
+    /// format itself couldn't be parsed we look for it in the raw arguments
+    fn exit_with_parse_error(err: clap::Error) -> ! {
+        use clap::error::ErrorKind;
+
+        let args: Vec<String> = std::env::args().collect();
+        let env_format = std::env::var("FM_CLI_FORMAT").ok();
+        if !json_format_requested(&args, env_format.as_deref())
+            || matches!(
+                err.kind(),
+                ErrorKind::DisplayHelp | ErrorKind::DisplayVersion
+            )
+        {
+            err.exit()
+        }
+
+        let result: CliOutputResult = Err(CliError {
+            kind: CliErrorKind::InvalidValue,
+            message: err.to_string().trim().to_string(),
+            raw_error: None,
+        });
+        let json = serde_json::to_string(&JsonOutput::new(&result)).expect("cli output serializes");
+        let _ = writeln!(std::io::stdout(), "{json}");
+        exit(2)
+    }
+
     async fn handle_command(&self, cli: Opts) -> CliOutputResult {
         let mut task_group = TaskGroup::new();
         let mut rng = rand::rngs::OsRng;
@@ -599,6 +1348,38 @@ impl FedimintCli {
                     .map_err_cli_msg(CliErrorKind::IOError, "couldn't write config")?;
                 Ok(CliOutput::JoinFederation { joined: connect })
             }
+            Command::SetConnectionOptions {
+                proxy,
+                user_agent,
+                tls_roots,
+                connect_timeout,
+            } => {
+                let options = ConnectionOptions {
+                    proxy,
+                    user_agent,
+                    tls_roots: tls_roots.into(),
+                    connect_timeout: connect_timeout.map(Duration::from_secs),
+                };
+                cli.build_client(&self.module_gens)
+                    .await?
+                    .set_connection_options(options.clone())
+                    .await;
+                Ok(CliOutput::ConnectionOptions { options })
+            }
+            Command::SetLnGatewayFee {
+                base_msat,
+                proportional_millionths,
+            } => {
+                let fee = GatewayFee {
+                    base_msat,
+                    proportional_millionths,
+                };
+                cli.build_client(&self.module_gens)
+                    .await?
+                    .set_incoming_gateway_fee(fee)
+                    .await;
+                Ok(CliOutput::LnGatewayFee { fee })
+            }
             Command::Api { method, arg } => {
                 let arg: Value = serde_json::from_str(&arg).unwrap();
                 let ws_api: Arc<_> = WsFederationApi::from_config(
@@ -675,6 +1456,66 @@ impl FedimintCli {
                     }),
                 }
             }
+            Command::Note(NoteCommand::Decode { notes }) => {
+                let mut details: Vec<_> = notes
+                    .iter_items()
+                    .map(|(amount, note)| NoteDetails::new(amount, note))
+                    .collect();
+
+                // a config tells us whether the notes were issued by its federation
+                let mut federation_id = None;
+                if cli.workdir.is_some() {
+                    let client = cli.build_client(&self.module_gens).await?;
+                    check_note_signatures(&client, &notes, &mut details).await;
+                    if details
+                        .iter()
+                        .all(|note| note.valid_signature == Some(true))
+                    {
+                        federation_id = Some(client.config().as_ref().federation_id.clone());
+                    }
+                }
+
+                Ok(CliOutput::NoteDecode {
+                    federation_id,
+                    total_amount: notes.total_amount(),
+                    total_num_notes: notes.count_items(),
+                    details: notes
+                        .iter()
+                        .map(|(amount, notes)| (amount.to_owned(), notes.len()))
+                        .collect(),
+                    notes: details,
+                })
+            }
+            Command::Note(NoteCommand::Validate { notes }) => {
+                let client = cli.build_client(&self.module_gens).await?;
+                let mut details: Vec<_> = notes
+                    .iter_items()
+                    .map(|(amount, note)| NoteDetails::new(amount, note))
+                    .collect();
+                check_note_signatures(&client, &notes, &mut details).await;
+
+                let validation = client.check_notes_spendable(&notes).await.map_err_cli_msg(
+                    CliErrorKind::NetworkError,
+                    "failed to check notes with the federation",
+                )?;
+                for (idx, note) in (0u64..).zip(details.iter_mut()) {
+                    note.spent = Some(validation.spent_inputs.contains(&idx));
+                    note.error = validation.input_errors.get(&idx).cloned();
+                }
+
+                Ok(CliOutput::NoteValidate {
+                    federation_id: client.config().as_ref().federation_id.clone(),
+                    all_valid: details
+                        .iter()
+                        .all(|note| note.valid_signature == Some(true) && note.error.is_none()),
+                    spent_amount: details
+                        .iter()
+                        .filter(|note| note.spent == Some(true))
+                        .map(|note| note.amount)
+                        .sum(),
+                    notes: details,
+                })
+            }
             Command::Spend { amount } => cli
                 .build_client(&self.module_gens)
                 .await?
@@ -687,6 +1528,50 @@ impl FedimintCli {
                     CliErrorKind::GeneralFederationError,
                     "failed to execute spend (no further information)",
                 ),
+            Command::SpendP2pk { amount, recipient } => {
+                let client = cli.build_client(&self.module_gens).await?;
+                async {
+                    let outpoint = client.pay_to_pubkey(amount, recipient, rng).await?;
+                    client.await_outpoint_outcome(outpoint).await?;
+                    client.fetch_p2pk_notes(outpoint).await
+                }
+                .await
+                .map(|v| CliOutput::Spend {
+                    note: serialize_p2pk_ecash(&v),
+                })
+                .map_err_cli_msg(
+                    CliErrorKind::GeneralFederationError,
+                    "failed to execute p2pk spend",
+                )
+            }
+            Command::ReissueP2pk {
+                notes,
+                secret_key_file,
+            } => {
+                let secret_key = match secret_key_file {
+                    Some(path) => fs::read_to_string(path)
+                        .map_err_cli_msg(CliErrorKind::IOError, "Unable to open secret key file")?,
+                    None => {
+                        let mut secret_key = String::new();
+                        std::io::stdin()
+                            .read_line(&mut secret_key)
+                            .map_err_cli_msg(CliErrorKind::IOError, "Unable to read secret key")?;
+                        secret_key
+                    }
+                };
+                let secret_key = secp256k1::SecretKey::from_str(secret_key.trim())
+                    .map_err_cli_msg(CliErrorKind::InvalidValue, "invalid secret key")?;
+                let client = cli.build_client(&self.module_gens).await?;
+                let key = secp256k1::KeyPair::from_secret_key(&client.context().secp, &secret_key);
+                client
+                    .reissue_p2pk(notes, &key, &mut rng)
+                    .await
+                    .map(|v| CliOutput::Reissue { id: (v) })
+                    .map_err_cli_msg(
+                        CliErrorKind::GeneralFederationError,
+                        "could not reissue p2pk notes",
+                    )
+            }
             Command::Fetch => cli
                 .build_client(&self.module_gens)
                 .await?
@@ -697,7 +1582,55 @@ impl FedimintCli {
                     CliErrorKind::GeneralFederationError,
                     "failed to fetch notes",
                 ),
+            Command::RefreshNotes => cli
+                .build_client(&self.module_gens)
+                .await?
+                .refresh_deprecated_notes(&mut rng)
+                .await
+                .map(|event| CliOutput::RefreshNotes { event })
+                .map_err_cli_msg(
+                    CliErrorKind::GeneralFederationError,
+                    "failed to refresh notes in deprecated tiers",
+                ),
+            Command::CheckIssuance => cli
+                .build_client(&self.module_gens)
+                .await?
+                .mint_client()
+                .check_issuance()
+                .await
+                .map(|discrepancies| CliOutput::CheckIssuance { discrepancies })
+                .map_err_cli_msg(
+                    CliErrorKind::GeneralFederationError,
+                    "failed to check issuance",
+                ),
+            Command::ExportMnemonic => cli
+                .build_client(&self.module_gens)
+                .await?
+                .mnemonic()
+                .await
+                .map(|mnemonic| CliOutput::ExportMnemonic {
+                    mnemonic: mnemonic.to_string(),
+                })
+                .map_err_cli_msg(
+                    CliErrorKind::GeneralFederationError,
+                    "client has no mnemonic",
+                ),
+            Command::ImportMnemonic { mnemonic } => {
+                let cfg = cli.load_config()?;
+                let decoders = cli.load_decoders(&cfg, &self.module_gens);
+                let db = cli.load_db(&decoders)?;
+                Client::<UserClientConfig>::import_mnemonic(&db, &mnemonic)
+                    .await
+                    .map(|_| CliOutput::ImportMnemonic {
+                        word_count: mnemonic.word_count(),
+                    })
+                    .map_err_cli_msg(
+                        CliErrorKind::GeneralFederationError,
+                        "could not import mnemonic",
+                    )
+            }
             Command::Info => {
+                let formatter = cli.amount_formatter()?;
                 let client = cli.build_client(&self.module_gens).await?;
                 let notes = client.notes().await;
                 let details_vec = notes
@@ -712,17 +1645,37 @@ impl FedimintCli {
                     total_amount: (notes.total_amount()),
                     total_num_notes: (notes.count_items()),
                     details: (details_vec),
+                    formatted_total: formatter.format(notes.total_amount()),
+                    formatted_notes: formatter.format_notes(&notes.summary()),
+                })
+            }
+            Command::ConvertAmount { amount } => {
+                let formatter = cli.amount_formatter()?;
+                let amount = formatter
+                    .parse(&amount)
+                    .map_err_cli_msg(CliErrorKind::InvalidValue, "invalid amount")?;
+
+                Ok(CliOutput::ConvertAmount {
+                    amount,
+                    formatted: formatter.format(amount),
                 })
             }
-            Command::PegOut { address, satoshis } => {
+            Command::PegOut {
+                address,
+                satoshis,
+                fee_rate,
+            } => {
                 let client = cli.build_client(&self.module_gens).await?;
+                let fee_rate = fee_rate.map(|sats_per_kvb| Feerate { sats_per_kvb });
                 let peg_out = client
-                    .new_peg_out_with_fees(satoshis, address)
+                    .new_peg_out_with_fees(satoshis, address, fee_rate)
                     .await
                     .map_err_cli_msg(
                         CliErrorKind::GeneralFederationError,
                         "failed to request peg-out",
                     )?;
+                let fees = peg_out.fees.clone();
+                let total_cost = client.peg_out_cost(&peg_out);
                 let out_point = client.peg_out(peg_out, &mut rng).await.map_err_cli_msg(
                     CliErrorKind::GeneralFederationError,
                     "failed to commit peg-out",
@@ -731,21 +1684,56 @@ impl FedimintCli {
                     .wallet_client()
                     .await_peg_out_outcome(out_point)
                     .await
-                    .map(|tx_id| CliOutput::PegOut { tx_id })
+                    .map(|tx_id| CliOutput::PegOut {
+                        tx_id,
+                        fees,
+                        total_cost,
+                    })
                     .map_err_cli_msg(
                         CliErrorKind::GeneralFederationError,
                         "invalid peg-out outcome",
                     )
             }
-            Command::LnPay { bolt11 } => {
+            Command::BumpPegOut {
+                txid,
+                address,
+                fee_rate,
+            } => {
                 let client = cli.build_client(&self.module_gens).await?;
-                let (contract_id, outpoint) = client
-                    .fund_outgoing_ln_contract(bolt11, &mut rng)
+                client
+                    .bump_peg_out_fees(
+                        txid,
+                        &address,
+                        Feerate {
+                            sats_per_kvb: fee_rate,
+                        },
+                        &mut rng,
+                    )
                     .await
+                    .map(|id| CliOutput::BumpPegOut { id })
                     .map_err_cli_msg(
                         CliErrorKind::GeneralFederationError,
-                        "Failure creating outgoing LN contract",
-                    )?;
+                        "failed to bump peg-out fees",
+                    )
+            }
+            Command::LnPay { bolt11, gateway } => {
+                let client = cli.build_client(&self.module_gens).await?;
+                let funded = match gateway {
+                    Some(node_pub_key) => {
+                        let gateway = client.fetch_gateway(node_pub_key).await.map_err_cli_msg(
+                            CliErrorKind::GeneralFederationError,
+                            "gateway is not registered with the federation",
+                        )?;
+                        client
+                            .fund_outgoing_ln_contract_via(bolt11, &gateway, &mut rng)
+                            .await
+                    }
+                    None => client.fund_outgoing_ln_contract(bolt11, &mut rng).await,
+                };
+                let (contract_id, outpoint) = funded.map_err_cli_msg(
+                    CliErrorKind::GeneralFederationError,
+                    "Failure creating outgoing LN contract",
+                )?;
                 client
                     .await_outgoing_contract_acceptance(outpoint)
                     .await
@@ -765,18 +1753,59 @@ impl FedimintCli {
                 amount,
                 description,
                 expiry_time,
-            } => cli
-                .build_client(&self.module_gens)
-                .await?
-                .generate_confirmed_invoice(amount, description, &mut rng, expiry_time)
-                .await
-                .map(|confirmed_invoice| CliOutput::LnInvoice {
-                    invoice: (confirmed_invoice.invoice),
-                })
-                .map_err_cli_msg(
-                    CliErrorKind::GeneralFederationError,
-                    "couldn't create invoice",
-                ),
+                gateway,
+            } => {
+                let client = cli.build_client(&self.module_gens).await?;
+                let confirmed_invoice = match gateway {
+                    Some(node_pub_key) => {
+                        let gateway = client.fetch_gateway(node_pub_key).await.map_err_cli_msg(
+                            CliErrorKind::GeneralFederationError,
+                            "gateway is not registered with the federation",
+                        )?;
+                        client
+                            .generate_confirmed_invoice_via(
+                                &gateway,
+                                amount,
+                                description,
+                                &mut rng,
+                                expiry_time,
+                            )
+                            .await
+                    }
+                    None => {
+                        client
+                            .generate_confirmed_invoice(amount, description, &mut rng, expiry_time)
+                            .await
+                    }
+                };
+                confirmed_invoice
+                    .map(|confirmed_invoice| CliOutput::LnInvoice {
+                        invoice: (confirmed_invoice.invoice),
+                    })
+                    .map_err_cli_msg(
+                        CliErrorKind::GeneralFederationError,
+                        "couldn't create invoice",
+                    )
+            }
+            Command::LnPreviewReceive { amount, gateway } => {
+                let client = cli.build_client(&self.module_gens).await?;
+                let preview = match gateway {
+                    Some(node_pub_key) => {
+                        let gateway = client.fetch_gateway(node_pub_key).await.map_err_cli_msg(
+                            CliErrorKind::GeneralFederationError,
+                            "gateway is not registered with the federation",
+                        )?;
+                        Ok(client.preview_receive_via(&gateway, amount))
+                    }
+                    None => client.preview_receive(amount).await,
+                };
+                preview
+                    .map(|preview| CliOutput::LnPreviewReceive { preview })
+                    .map_err_cli_msg(
+                        CliErrorKind::GeneralFederationError,
+                        "couldn't find a gateway to receive through",
+                    )
+            }
             Command::WaitInvoice { invoice } => {
                 let contract_id = (*invoice.payment_hash()).into();
                 cli.build_client(&self.module_gens)
@@ -795,11 +1824,74 @@ impl FedimintCli {
                 .await
                 .map(|_| CliOutput::WaitBlockHeight { reached: (height) })
                 .map_err_cli_msg(CliErrorKind::Timeout, "timeout reached"),
+            Command::AwaitDeposit { txid, timeout } => {
+                let client = cli.build_client(&self.module_gens).await?;
+                await_with_timeout(timeout, client.await_transaction_notes(txid))
+                    .await?
+                    .map(|issuance| CliOutput::AwaitDeposit { issuance })
+                    .map_err_cli_msg(CliErrorKind::GeneralFederationError, "peg-in failed")
+            }
+            Command::AwaitInvoice { invoice, timeout } => {
+                let client = cli.build_client(&self.module_gens).await?;
+                let contract_id = (*invoice.payment_hash()).into();
+                await_with_timeout(timeout, async {
+                    let IncomingPaymentOutcome { amount, issuance } =
+                        client.await_incoming_payment(contract_id, &mut rng).await?;
+                    Ok::<_, ClientError>(CliOutput::AwaitInvoice { amount, issuance })
+                })
+                .await?
+                .map_err_cli_msg(
+                    CliErrorKind::GeneralFederationError,
+                    "failed to claim invoice payment",
+                )
+            }
+            Command::AwaitLnPay {
+                contract_id,
+                timeout,
+            } => {
+                let client = cli.build_client(&self.module_gens).await?;
+                await_with_timeout(
+                    timeout,
+                    client.await_outgoing_payment(contract_id, &mut rng),
+                )
+                .await?
+                .map(|outcome| CliOutput::AwaitLnPay {
+                    contract_id,
+                    outcome,
+                })
+                .map_err_cli_msg(
+                    CliErrorKind::GeneralFederationError,
+                    "failed to await lightning payment",
+                )
+            }
             Command::ConnectInfo => Ok(CliOutput::ConnectInfo {
                 connect_info: WsClientConnectInfo::from_honest_peers(
                     cli.build_client(&self.module_gens).await?.config().as_ref(),
                 ),
             }),
+            Command::Receipt { txid, out_idx } => cli
+                .build_client(&self.module_gens)
+                .await?
+                .transaction_receipt(txid, out_idx.map(|out_idx| OutPoint { txid, out_idx }))
+                .await
+                .map(|receipt| CliOutput::Receipt { receipt })
+                .map_err_cli_msg(
+                    CliErrorKind::GeneralFederationError,
+                    "failed to create receipt",
+                ),
+            Command::VerifyReceipt { receipt } => {
+                let cfg = cli.load_config()?;
+                let receipt: PaymentReceipt = serde_json::from_str(&receipt)
+                    .map_err_cli_msg(CliErrorKind::SerializationError, "failed to parse receipt")?;
+                let transaction = verify_receipt(&receipt, cfg.as_ref())
+                    .await
+                    .map_err_cli_msg(CliErrorKind::InvalidValue, "invalid receipt")?;
+                Ok(CliOutput::VerifyReceipt {
+                    txid: receipt.txid,
+                    out_point: receipt.out_point,
+                    transaction: format!("{transaction:?}"),
+                })
+            }
             Command::DecodeConnectInfo { connect_info } => Ok(CliOutput::DecodeConnectInfo {
                 urls: connect_info.urls,
                 id: connect_info.id,
@@ -861,19 +1953,53 @@ impl FedimintCli {
             Command::Backup => cli
                 .build_client(&self.module_gens)
                 .await?
-                .mint_client()
                 .back_up_ecash_to_federation()
                 .await
                 .map(|_| CliOutput::Backup)
                 .map_err_cli_msg(CliErrorKind::GeneralFederationError, "failed"),
-            Command::Restore { gap_limit } => cli
-                .build_client(&self.module_gens)
-                .await?
-                .mint_client()
-                .restore_ecash_from_federation(gap_limit, &mut task_group)
-                .await
-                .map(|_| CliOutput::Backup)
-                .map_err_cli_msg(CliErrorKind::GeneralFederationError, "failed"),
+            Command::Restore {
+                gap_limit,
+                scope,
+                epoch_archives,
+            } => {
+                let client = cli
+                    .build_client(&self.module_gens)
+                    .await?
+                    .with_epoch_archives(epoch_archives);
+                let mint_client = client.mint_client();
+                let restored = match scope {
+                    RestoreScope::All => mint_client
+                        .restore_ecash_from_federation(gap_limit, &mut task_group)
+                        .await
+                        .map(|_| ()),
+                    RestoreScope::Notes => mint_client
+                        .restore_notes_from_federation(gap_limit, &mut task_group)
+                        .await
+                        .map(|_| ()),
+                    RestoreScope::LnContracts => Ok(()),
+                };
+                restored.map_err_cli_msg(CliErrorKind::GeneralFederationError, "failed")?;
+                if scope != RestoreScope::LnContracts {
+                    client
+                        .restore_sub_accounts_from_federation(
+                            gap_limit,
+                            scope == RestoreScope::Notes,
+                            &mut task_group,
+                        )
+                        .await
+                        .map_err_cli_msg(
+                            CliErrorKind::GeneralFederationError,
+                            "failed to restore sub-accounts",
+                        )?;
+                }
+                if scope != RestoreScope::Notes {
+                    client.restore_contract_states().await.map_err_cli_msg(
+                        CliErrorKind::GeneralFederationError,
+                        "failed to restore lightning contracts",
+                    )?;
+                }
+                Ok(CliOutput::Backup)
+            }
             Command::WipeNotes => cli
                 .build_client(&self.module_gens)
                 .await?
@@ -935,6 +2061,16 @@ impl FedimintCli {
                     .await?;
                 Ok(CliOutput::EpochCount { count })
             }
+            Command::ApiVersions => cli
+                .build_client(&self.module_gens)
+                .await?
+                .negotiate_api_versions()
+                .await
+                .map(|versions| CliOutput::ApiVersions { versions })
+                .map_err_cli_msg(
+                    CliErrorKind::NetworkError,
+                    "failed to negotiate API versions",
+                ),
             Command::Module { id, arg } => {
                 let cfg = cli.load_config()?;
                 let decoders = cli.load_decoders(&cfg, &self.module_gens);
@@ -970,3 +2106,124 @@ impl FedimintCli {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::future::pending;
+
+    use bitcoin::hashes::Hash;
+    use clap::Parser;
+    use fedimint_core::{OutPoint, TransactionId};
+    use mint_client::modules::ln::contracts::ContractId;
+    use mint_client::OutgoingPaymentOutcome;
+    use serde_json::Value;
+
+    use super::{
+        await_with_timeout, json_format_requested, CliError, CliErrorKind, CliOutput,
+        CliOutputResult, CommandOutput, Opts, OutputFormat, JSON_OUTPUT_VERSION,
+    };
+
+    fn json_stdout(output: &CommandOutput) -> Value {
+        serde_json::from_str(output.stdout.as_ref().expect("JSON goes to stdout")).unwrap()
+    }
+
+    #[test]
+    fn test_json_output_of_result() {
+        let result: CliOutputResult = Ok(CliOutput::VersionHash {
+            hash: "abcd".to_string(),
+        });
+
+        let output = CommandOutput::new(OutputFormat::Json, &result);
+        assert!(output.success);
+        assert!(output.stderr.is_none());
+        let json = json_stdout(&output);
+        assert_eq!(json["version"], JSON_OUTPUT_VERSION);
+        assert_eq!(json["success"], true);
+        assert_eq!(json["result"]["hash"], "abcd");
+        assert!(json.get("error").is_none());
+    }
+
+    #[test]
+    fn test_json_output_of_error() {
+        let result: CliOutputResult = Err(CliError {
+            kind: CliErrorKind::InsufficientBalance,
+            message: "not enough notes".to_string(),
+            raw_error: Some(anyhow::anyhow!("missing 10 msat")),
+        });
+
+        // errors are printed to stdout as well, so scripts read only one stream
+        let output = CommandOutput::new(OutputFormat::Json, &result);
+        assert!(!output.success);
+        assert!(output.stderr.is_none());
+        let json = json_stdout(&output);
+        assert_eq!(json["version"], JSON_OUTPUT_VERSION);
+        assert_eq!(json["success"], false);
+        assert_eq!(json["error"]["kind"], "InsufficientBalance");
+        assert_eq!(json["error"]["code"], 8);
+        assert_eq!(json["error"]["message"], "not enough notes");
+        assert_eq!(json["error"]["raw_error"], "missing 10 msat");
+        assert!(json.get("result").is_none());
+
+        let output = CommandOutput::new(OutputFormat::Text, &result);
+        assert!(output.stdout.is_none());
+        assert!(output.stderr.unwrap().contains("not enough notes"));
+    }
+
+    #[test]
+    fn test_json_format_of_invalid_args() {
+        let args = |args: &[&str]| -> Vec<String> {
+            std::iter::once("fedimint-cli")
+                .chain(args.iter().copied())
+                .map(str::to_string)
+                .collect()
+        };
+
+        let unknown = args(&["--format", "json", "unknown-command"]);
+        assert!(Opts::try_parse_from(&unknown).is_err());
+        assert!(json_format_requested(&unknown, None));
+        assert!(json_format_requested(&args(&["--format=json", "x"]), None));
+        assert!(json_format_requested(&args(&["x"]), Some("json")));
+
+        assert!(!json_format_requested(
+            &args(&["--format", "text", "x"]),
+            None
+        ));
+        assert!(!json_format_requested(&args(&["x", "json"]), Some("text")));
+        assert!(Opts::try_parse_from(args(&["--format", "yaml", "version-hash"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_await_with_timeout() {
+        assert_eq!(await_with_timeout(None, async { 42 }).await.unwrap(), 42);
+        assert_eq!(
+            await_with_timeout(Some(10), async { 42 }).await.unwrap(),
+            42
+        );
+
+        let err = await_with_timeout(Some(0), pending::<()>())
+            .await
+            .unwrap_err();
+        assert!(matches!(err.kind, CliErrorKind::Timeout));
+    }
+
+    #[test]
+    fn test_await_ln_pay_output() {
+        let outpoint = OutPoint {
+            txid: TransactionId::from_slice(&[1; 32]).unwrap(),
+            out_idx: 0,
+        };
+        let outcome = |outcome| {
+            serde_json::to_value(CliOutput::AwaitLnPay {
+                contract_id: ContractId::from_slice(&[2; 32]).unwrap(),
+                outcome,
+            })
+            .unwrap()
+        };
+
+        assert_eq!(outcome(OutgoingPaymentOutcome::Paid)["outcome"], "paid");
+        assert_eq!(
+            outcome(OutgoingPaymentOutcome::Refunded(outpoint))["outcome"]["refunded"],
+            serde_json::to_value(outpoint).unwrap()
+        );
+    }
+}
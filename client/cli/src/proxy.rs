@@ -0,0 +1,237 @@
+//! Lets invocations of the cli share the client database while another one,
+//! e.g. a long running `await-invoice`, holds it open
+//!
+//! RocksDB can only be opened by one process, so the invocation that opens the
+//! database first runs the commands of the others until its own command is
+//! done. They reach it over a unix socket in the data dir. Since the commands
+//! run in the serving process, options read from environment variables apply
+//! as set there, except for the data dir and the output format.
+//!
+//! Once its own command is done the serving invocation removes the socket and
+//! finishes the commands it is running before it exits and releases the
+//! database. Invocations that find the database locked without a socket to
+//! reach wait for that, see [`DB_RELEASE_TIMEOUT`].
+
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+
+use fedimint_core::task::TaskGroup;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, warn};
+
+use crate::{CommandOutput, OutputFormat};
+
+/// Name of the socket in the data dir
+pub(crate) const SOCKET_FILE: &str = "client.sock";
+
+/// How long an invocation waits for the one holding the database to either
+/// serve it or release the database
+pub(crate) const DB_RELEASE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long an invocation waits before trying to open or reach the database
+/// again
+pub(crate) const DB_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A command sent to the invocation holding the database
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ProxyRequest {
+    /// Arguments of the invocation, without the name of the binary
+    pub(crate) args: Vec<String>,
+    pub(crate) format: OutputFormat,
+}
+
+/// Connects to the invocation serving at `socket`, which fails if it stopped
+/// serving
+pub(crate) async fn connect(socket: &Path) -> std::io::Result<UnixStream> {
+    UnixStream::connect(socket).await
+}
+
+/// Runs `request` in the invocation `stream` is connected to and returns what
+/// it printed
+pub(crate) async fn forward(
+    mut stream: UnixStream,
+    request: &ProxyRequest,
+) -> anyhow::Result<CommandOutput> {
+    stream.write_all(&to_line(request)?).await?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).await?;
+    anyhow::ensure!(
+        !response.is_empty(),
+        "Connection closed before the command finished"
+    );
+    Ok(serde_json::from_str(&response)?)
+}
+
+/// Binds the socket at `socket`, replacing one an invocation that crashed left
+/// behind
+pub(crate) fn bind(socket: &Path) -> std::io::Result<UnixListener> {
+    match std::fs::remove_file(socket) {
+        Ok(()) => debug!("Removed stale socket {}", socket.display()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    UnixListener::bind(socket)
+}
+
+/// Runs the commands sent to `listener` with `handle` until `task_group`
+/// shuts down
+///
+/// Every command runs in its own task of `task_group`, so a long running one
+/// doesn't hold up the others. Joining the group waits for the commands that
+/// are still running.
+pub(crate) async fn serve<F, Fut>(listener: UnixListener, handle: F, mut task_group: TaskGroup)
+where
+    F: Fn(ProxyRequest) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = CommandOutput> + Send + 'static,
+{
+    let mut shutdown_rx = task_group.make_handle().make_shutdown_rx().await;
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept proxied command: {e}");
+                    continue;
+                }
+            },
+            _ = &mut shutdown_rx => break,
+        };
+
+        let handle = handle.clone();
+        task_group
+            .spawn("Proxied command", |_| async move {
+                if let Err(e) = serve_connection(stream, &handle).await {
+                    warn!("Failed to serve proxied command: {e}");
+                }
+            })
+            .await;
+    }
+}
+
+async fn serve_connection<F, Fut>(stream: UnixStream, handle: &F) -> anyhow::Result<()>
+where
+    F: Fn(ProxyRequest) -> Fut,
+    Fut: Future<Output = CommandOutput>,
+{
+    let (reader, mut writer) = stream.into_split();
+    let mut request = String::new();
+    BufReader::new(reader).read_line(&mut request).await?;
+    let request: ProxyRequest = serde_json::from_str(&request)?;
+
+    debug!(args = ?request.args, "Running proxied command");
+    let output = handle(request).await;
+    writer.write_all(&to_line(&output)?).await?;
+    Ok(())
+}
+
+fn to_line(value: &impl Serialize) -> serde_json::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use fedimint_core::task::TaskGroup;
+    use tokio::sync::Notify;
+
+    use super::{bind, connect, forward, serve, ProxyRequest};
+    use crate::{CommandOutput, OutputFormat};
+
+    fn socket_path() -> PathBuf {
+        std::env::temp_dir().join(format!("fedimint-cli-{}.sock", rand::random::<u64>()))
+    }
+
+    async fn run(socket: &PathBuf, arg: &str) -> Option<String> {
+        let request = ProxyRequest {
+            args: vec![arg.to_string()],
+            format: OutputFormat::Text,
+        };
+        let stream = connect(socket).await.unwrap();
+        forward(stream, &request).await.unwrap().stdout
+    }
+
+    /// Serves commands that echo their argument, `wait` only finishes once
+    /// `release` ran or the returned [`Notify`] was notified
+    async fn serve_echo(socket: &PathBuf) -> (TaskGroup, Arc<Notify>) {
+        let listener = bind(socket).unwrap();
+        let released = Arc::new(Notify::new());
+        let handle_released = released.clone();
+        let handle = move |request: ProxyRequest| {
+            let released = handle_released.clone();
+            async move {
+                let arg = request.args[0].clone();
+                match arg.as_str() {
+                    "wait" => released.notified().await,
+                    "release" => released.notify_one(),
+                    _ => {}
+                }
+                CommandOutput {
+                    stdout: Some(arg),
+                    stderr: None,
+                    success: true,
+                }
+            }
+        };
+
+        let mut task_group = TaskGroup::new();
+        let serve_group = task_group.make_subgroup().await;
+        task_group
+            .spawn("Serve proxied commands", |_| {
+                serve(listener, handle, serve_group)
+            })
+            .await;
+        (task_group, released)
+    }
+
+    #[tokio::test]
+    async fn proxied_commands_run_concurrently() {
+        let socket = socket_path();
+        let (task_group, _) = serve_echo(&socket).await;
+
+        let waiting = tokio::spawn({
+            let socket = socket.clone();
+            async move { run(&socket, "wait").await }
+        });
+        assert_eq!(run(&socket, "release").await.as_deref(), Some("release"));
+        assert_eq!(waiting.await.unwrap().as_deref(), Some("wait"));
+
+        task_group.shutdown_join_all(None).await.unwrap();
+        let _ = std::fs::remove_file(socket);
+    }
+
+    #[tokio::test]
+    async fn running_commands_finish_before_the_holder_exits() {
+        let socket = socket_path();
+        let (task_group, released) = serve_echo(&socket).await;
+
+        let stream = connect(&socket).await.unwrap();
+        let waiting = tokio::spawn(async move {
+            let request = ProxyRequest {
+                args: vec!["wait".to_string()],
+                format: OutputFormat::Text,
+            };
+            forward(stream, &request).await.unwrap().stdout
+        });
+        // connections are accepted in order, so `wait` runs once this returns
+        assert_eq!(run(&socket, "echo").await.as_deref(), Some("echo"));
+
+        let joined = tokio::spawn(task_group.shutdown_join_all(None));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!joined.is_finished());
+
+        released.notify_one();
+        joined.await.unwrap().unwrap();
+        assert_eq!(waiting.await.unwrap().as_deref(), Some("wait"));
+        let _ = std::fs::remove_file(socket);
+    }
+}
@@ -0,0 +1,389 @@
+//! Formatting of amounts for users and parsing of the amounts they enter, in
+//! any bitcoin unit or in a fiat currency at an exchange rate
+//!
+//! Numbers follow the separators of a [`Locale`], e.g. `1.234,5 sat` in
+//! German, so the same input can mean different amounts in different locales.
+
+use std::fmt::Debug;
+
+use anyhow::{bail, ensure, format_err};
+use async_trait::async_trait;
+use fedimint_core::{Amount, AmountUnit, Tiered};
+
+const MSATS_PER_BTC: u64 = 100_000_000_000;
+
+/// Price of bitcoin in a fiat currency
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExchangeRate {
+    /// Code of the currency, e.g. `USD`
+    pub currency: String,
+    /// Price of one bitcoin in the currency
+    pub btc_price: f64,
+}
+
+impl ExchangeRate {
+    pub fn new(currency: &str, btc_price: f64) -> anyhow::Result<Self> {
+        ensure!(
+            btc_price.is_finite() && btc_price > 0.0,
+            "Invalid bitcoin price {btc_price}"
+        );
+        ensure!(
+            !currency.is_empty() && currency.chars().all(char::is_alphabetic),
+            "Invalid currency {currency}"
+        );
+        Ok(ExchangeRate {
+            currency: currency.to_uppercase(),
+            btc_price,
+        })
+    }
+
+    /// Value of `amount` in the currency
+    pub fn to_fiat(&self, amount: Amount) -> f64 {
+        amount.msats as f64 / MSATS_PER_BTC as f64 * self.btc_price
+    }
+
+    /// Amount worth `value` of the currency rounded to the msat, `None` if it
+    /// is negative or doesn't fit
+    pub fn from_fiat(&self, value: f64) -> Option<Amount> {
+        let msats = (value / self.btc_price * MSATS_PER_BTC as f64).round();
+        if msats.is_finite() && msats >= 0.0 && msats < u64::MAX as f64 {
+            Some(Amount::from_msats(msats as u64))
+        } else {
+            None
+        }
+    }
+}
+
+/// Source of exchange rates, e.g. the API of an exchange, for wallets to
+/// display amounts in fiat currencies
+#[async_trait]
+pub trait ExchangeRateProvider: Debug + Send + Sync {
+    /// Current price of bitcoin in `currency`
+    async fn exchange_rate(&self, currency: &str) -> anyhow::Result<ExchangeRate>;
+}
+
+/// Provides the same rate every time, e.g. one the user entered
+#[async_trait]
+impl ExchangeRateProvider for ExchangeRate {
+    async fn exchange_rate(&self, currency: &str) -> anyhow::Result<ExchangeRate> {
+        ensure!(
+            currency.eq_ignore_ascii_case(&self.currency),
+            "No exchange rate for {currency}"
+        );
+        Ok(self.clone())
+    }
+}
+
+/// Separators of the numbers in amounts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    pub decimal_separator: char,
+    /// Separates groups of thousands, numbers aren't grouped if unset
+    pub group_separator: Option<char>,
+}
+
+impl Locale {
+    /// `1,234.5`
+    pub const EN: Locale = Locale {
+        decimal_separator: '.',
+        group_separator: Some(','),
+    };
+    /// `1.234,5`
+    pub const DE: Locale = Locale {
+        decimal_separator: ',',
+        group_separator: Some('.'),
+    };
+    /// `1 234,5` with a narrow no-break space
+    pub const FR: Locale = Locale {
+        decimal_separator: ',',
+        group_separator: Some('\u{202f}'),
+    };
+    /// `1234.5`, e.g. for output parsed by scripts
+    pub const POSIX: Locale = Locale {
+        decimal_separator: '.',
+        group_separator: None,
+    };
+
+    /// Separators conventional for the language of a BCP 47 tag like `de-AT`
+    /// or a POSIX locale like `de_AT.UTF-8`, [`Locale::EN`] for unknown
+    /// languages
+    pub fn from_tag(tag: &str) -> Self {
+        let language = tag
+            .split(['-', '_', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match language.as_str() {
+            "c" | "posix" => Locale::POSIX,
+            "da" | "de" | "es" | "id" | "it" | "nl" | "pt" | "tr" | "vi" => Locale::DE,
+            "cs" | "fi" | "fr" | "hu" | "nb" | "no" | "pl" | "ru" | "sk" | "sv" | "uk" => {
+                Locale::FR
+            }
+            _ => Locale::EN,
+        }
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::EN
+    }
+}
+
+/// Unit amounts are displayed in
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisplayUnit {
+    Bitcoin(AmountUnit),
+    Fiat(ExchangeRate),
+}
+
+/// Formats and parses amounts in a unit and locale chosen by the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmountFormatter {
+    pub unit: DisplayUnit,
+    pub locale: Locale,
+}
+
+impl AmountFormatter {
+    pub fn new(unit: DisplayUnit, locale: Locale) -> Self {
+        AmountFormatter { unit, locale }
+    }
+
+    /// Displays amounts in `currency` at the current rate of `provider`
+    pub async fn fiat(
+        provider: &dyn ExchangeRateProvider,
+        currency: &str,
+        locale: Locale,
+    ) -> anyhow::Result<Self> {
+        let rate = provider.exchange_rate(currency).await?;
+        Ok(AmountFormatter::new(DisplayUnit::Fiat(rate), locale))
+    }
+
+    /// Formats `amount` exact to the msat in bitcoin units and rounded to
+    /// cents in fiat currencies, e.g. `1,234.5 sat` or `0.37 USD`
+    pub fn format(&self, amount: Amount) -> String {
+        match &self.unit {
+            DisplayUnit::Bitcoin(unit) => self.format_bitcoin(amount, *unit),
+            DisplayUnit::Fiat(rate) => {
+                let cents = (rate.to_fiat(amount) * 100.0).round() as u64;
+                let number = self.format_number(cents / 100, &format!("{:02}", cents % 100));
+                format!("{number} {}", rate.currency)
+            }
+        }
+    }
+
+    /// One line per denomination of ecash notes with the number of notes of
+    /// it, e.g. `3 x 1,024 msat`. Denominations are shown in msat rather than
+    /// in a fiat currency, in which most of them round to nothing.
+    pub fn format_notes(&self, notes: &Tiered<usize>) -> Vec<String> {
+        let unit = match &self.unit {
+            DisplayUnit::Bitcoin(unit) => *unit,
+            DisplayUnit::Fiat(_) => AmountUnit::Msat,
+        };
+        notes
+            .iter()
+            .map(|(denomination, count)| {
+                format!("{count} x {}", self.format_bitcoin(denomination, unit))
+            })
+            .collect()
+    }
+
+    /// Parses an amount a user entered, e.g. `1.234,5 sat` with
+    /// [`Locale::DE`]. The number is in the unit of the formatter unless it's
+    /// followed by another one.
+    pub fn parse(&self, input: &str) -> anyhow::Result<Amount> {
+        let input = input.trim();
+        let (number, unit) = match input.find(char::is_alphabetic) {
+            Some(i) => (input[..i].trim(), self.parse_unit(input[i..].trim())?),
+            None => (input, self.unit.clone()),
+        };
+        let (whole, fraction) = self.split_number(number)?;
+
+        match unit {
+            DisplayUnit::Bitcoin(unit) => {
+                let decimals = unit.decimals() as usize;
+                ensure!(
+                    fraction.len() <= decimals,
+                    "Amounts in {unit} have at most {decimals} decimals"
+                );
+                let fraction = if fraction.is_empty() {
+                    0
+                } else {
+                    format!("{fraction:0<decimals$}").parse::<u64>()?
+                };
+                whole
+                    .parse::<u64>()?
+                    .checked_mul(10u64.pow(unit.decimals()))
+                    .and_then(|msats| msats.checked_add(fraction))
+                    .map(Amount::from_msats)
+                    .ok_or_else(|| format_err!("Amount {input} is too large"))
+            }
+            DisplayUnit::Fiat(rate) => {
+                let value = format!("{whole}.{fraction}0").parse::<f64>()?;
+                rate.from_fiat(value)
+                    .ok_or_else(|| format_err!("Amount {input} is too large"))
+            }
+        }
+    }
+
+    fn parse_unit(&self, unit: &str) -> anyhow::Result<DisplayUnit> {
+        if let Ok(unit) = unit.parse::<AmountUnit>() {
+            return Ok(DisplayUnit::Bitcoin(unit));
+        }
+        match &self.unit {
+            DisplayUnit::Fiat(rate) if unit.eq_ignore_ascii_case(&rate.currency) => {
+                Ok(self.unit.clone())
+            }
+            DisplayUnit::Fiat(rate) => {
+                bail!(
+                    "Unknown unit {unit}, expected msat, sat, btc or {}",
+                    rate.currency
+                )
+            }
+            DisplayUnit::Bitcoin(_) => bail!("Unknown unit {unit}, expected msat, sat or btc"),
+        }
+    }
+
+    /// Splits a number into its whole and fractional digits, dropping the
+    /// group separators and whitespace
+    fn split_number(&self, number: &str) -> anyhow::Result<(String, String)> {
+        let digits = number
+            .chars()
+            .filter(|c| Some(*c) != self.locale.group_separator && !c.is_whitespace())
+            .collect::<String>();
+        let (whole, fraction) = digits
+            .split_once(self.locale.decimal_separator)
+            .unwrap_or((&digits, ""));
+
+        ensure!(
+            !whole.is_empty() || !fraction.is_empty(),
+            "Missing the number of the amount"
+        );
+        ensure!(
+            whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit()),
+            "Invalid number {number}"
+        );
+        let whole = if whole.is_empty() { "0" } else { whole };
+        Ok((whole.to_owned(), fraction.to_owned()))
+    }
+
+    fn format_bitcoin(&self, amount: Amount, unit: AmountUnit) -> String {
+        let decimals = unit.decimals() as usize;
+        let scale = 10u64.pow(unit.decimals());
+        let fraction = match amount.msats % scale {
+            0 => String::new(),
+            fraction => format!("{fraction:0decimals$}"),
+        };
+        let number = self.format_number(amount.msats / scale, fraction.trim_end_matches('0'));
+        format!("{number} {unit}")
+    }
+
+    fn format_number(&self, whole: u64, fraction: &str) -> String {
+        let digits = whole.to_string();
+        let mut number = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                number.extend(self.locale.group_separator);
+            }
+            number.push(digit);
+        }
+        if !fraction.is_empty() {
+            number.push(self.locale.decimal_separator);
+            number.push_str(fraction);
+        }
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::{Amount, AmountUnit, Tiered};
+
+    use super::{AmountFormatter, DisplayUnit, ExchangeRate, Locale};
+
+    fn formatter(unit: AmountUnit, locale: Locale) -> AmountFormatter {
+        AmountFormatter::new(DisplayUnit::Bitcoin(unit), locale)
+    }
+
+    #[test]
+    fn formats_amounts() {
+        let amount = Amount::from_msats(1_234_500);
+        assert_eq!(
+            formatter(AmountUnit::Sat, Locale::EN).format(amount),
+            "1,234.5 sat"
+        );
+        assert_eq!(
+            formatter(AmountUnit::Sat, Locale::DE).format(amount),
+            "1.234,5 sat"
+        );
+        assert_eq!(
+            formatter(AmountUnit::Msat, Locale::FR).format(amount),
+            "1\u{202f}234\u{202f}500 msat"
+        );
+        assert_eq!(
+            formatter(AmountUnit::Btc, Locale::POSIX).format(amount),
+            "0.000012345 BTC"
+        );
+        assert_eq!(
+            formatter(AmountUnit::Sat, Locale::EN).format(Amount::ZERO),
+            "0 sat"
+        );
+
+        let notes = [(Amount::from_msats(1024), 3), (Amount::from_sats(1), 1)]
+            .into_iter()
+            .collect::<Tiered<usize>>();
+        assert_eq!(
+            formatter(AmountUnit::Msat, Locale::EN).format_notes(&notes),
+            vec!["1 x 1,000 msat", "3 x 1,024 msat"]
+        );
+    }
+
+    #[test]
+    fn parses_amounts() {
+        let en = formatter(AmountUnit::Sat, Locale::EN);
+        assert_eq!(en.parse("1,234.5").unwrap(), Amount::from_msats(1_234_500));
+        assert_eq!(en.parse(" 0.001 BTC ").unwrap(), Amount::from_sats(100_000));
+        assert_eq!(en.parse(".5sat").unwrap(), Amount::from_msats(500));
+        assert!(en.parse("0.0001 sat").is_err());
+        assert!(en.parse("1.2.3").is_err());
+        assert!(en.parse("1 bits").is_err());
+        assert!(en.parse("").is_err());
+        assert!(en.parse("200000000000 BTC").is_err());
+
+        let de = formatter(AmountUnit::Sat, Locale::DE);
+        assert_eq!(de.parse("1.234,5").unwrap(), Amount::from_msats(1_234_500));
+
+        let fr = formatter(AmountUnit::Msat, Locale::FR);
+        let amount = Amount::from_msats(1_234_500);
+        assert_eq!(fr.parse(&fr.format(amount)).unwrap(), amount);
+        assert_eq!(fr.parse("1 234 500").unwrap(), amount);
+    }
+
+    #[test]
+    fn converts_fiat() {
+        let rate = ExchangeRate::new("usd", 30_000.0).unwrap();
+        let formatter = AmountFormatter::new(DisplayUnit::Fiat(rate), Locale::EN);
+        assert_eq!(formatter.format(Amount::from_sats(100_000)), "30.00 USD");
+        assert_eq!(formatter.format(Amount::from_sats(1_234)), "0.37 USD");
+        assert_eq!(formatter.parse("30").unwrap(), Amount::from_sats(100_000));
+        assert_eq!(
+            formatter.parse("1.5 usd").unwrap(),
+            Amount::from_sats(5_000)
+        );
+        assert_eq!(formatter.parse("10 sat").unwrap(), Amount::from_sats(10));
+        assert!(formatter.parse("1 EUR").is_err());
+        assert!(ExchangeRate::new("USD", 0.0).is_err());
+    }
+
+    #[test]
+    fn locale_from_tag() {
+        assert_eq!(Locale::from_tag("de_AT.UTF-8"), Locale::DE);
+        assert_eq!(Locale::from_tag("fr-CA"), Locale::FR);
+        assert_eq!(Locale::from_tag("C"), Locale::POSIX);
+        assert_eq!(Locale::from_tag("en_US"), Locale::EN);
+        assert_eq!(Locale::from_tag("xx"), Locale::EN);
+    }
+}
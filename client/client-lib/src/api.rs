@@ -1,15 +1,18 @@
 pub mod fake;
 
-use bitcoin::Address;
+use bitcoin::{Address, Txid};
 use bitcoin_hashes::sha256::Hash as Sha256Hash;
-use fedimint_core::api::{FederationApiExt, FederationResult, IFederationApi};
+use fedimint_core::api::{
+    negotiated_api_versions, FederationApiExt, FederationResult, IFederationApi,
+};
 use fedimint_core::core::{
     LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
     LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
-use fedimint_core::module::ApiRequestErased;
+use fedimint_core::module::{ApiRequestErased, ModuleConsensusVersion};
 use fedimint_core::query::{
-    CurrentConsensus, EventuallyConsistent, Retry404, UnionResponses, UnionResponsesSingle,
+    CurrentConsensus, EventuallyConsistent, Retry404, UnionPages, UnionResponses,
+    UnionResponsesSingle,
 };
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::{apply, async_trait_maybe_send, NumPeers};
@@ -17,7 +20,7 @@ use fedimint_mint_client::common::db::ECashUserBackupSnapshot;
 
 use crate::modules::ln::contracts::incoming::IncomingContractOffer;
 use crate::modules::ln::contracts::ContractId;
-use crate::modules::ln::{ContractAccount, LightningGateway};
+use crate::modules::ln::{ContractAccount, LightningGateway, PAGINATION_API_VERSION};
 use crate::modules::wallet::PegOutFees;
 
 #[apply(async_trait_maybe_send!)]
@@ -30,6 +33,8 @@ pub trait LnFederationApi {
     async fn fetch_gateways(&self) -> FederationResult<Vec<LightningGateway>>;
     async fn register_gateway(&self, gateway: &LightningGateway) -> FederationResult<()>;
     async fn offer_exists(&self, payment_hash: Sha256Hash) -> FederationResult<bool>;
+    /// Fetches the lightning consensus version the federation runs at
+    async fn fetch_ln_consensus_version(&self) -> FederationResult<ModuleConsensusVersion>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -58,10 +63,28 @@ where
     }
 
     async fn fetch_gateways(&self) -> FederationResult<Vec<LightningGateway>> {
-        self.request_with_strategy(
-            UnionResponses::new(self.all_members().threshold()),
-            format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_LN}/list_gateways"),
-            ApiRequestErased::default(),
+        let required = self.all_members().threshold();
+        // guardians we couldn't negotiate with may predate the paginated endpoint
+        let paginated = negotiated_api_versions(self)
+            .await
+            .map_or(false, |versions| {
+                versions.supports_module(LEGACY_HARDCODED_INSTANCE_ID_LN, PAGINATION_API_VERSION)
+            });
+        if !paginated {
+            return self
+                .request_with_strategy(
+                    UnionResponses::new(required),
+                    format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_LN}/list_gateways"),
+                    ApiRequestErased::default(),
+                )
+                .await;
+        }
+
+        self.request_pages::<_, secp256k1::PublicKey, _>(
+            move || UnionPages::new(required),
+            format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_LN}/list_gateways_page"),
+            None,
+            None,
         )
         .await
     }
@@ -82,6 +105,14 @@ where
             Err(e) => Err(e),
         }
     }
+
+    async fn fetch_ln_consensus_version(&self) -> FederationResult<ModuleConsensusVersion> {
+        self.request_eventually_consistent(
+            format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_LN}/consensus_version"),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -139,6 +170,11 @@ pub trait WalletFederationApi {
         address: &Address,
         amount: bitcoin::Amount,
     ) -> FederationResult<Option<PegOutFees>>;
+    /// Fetches the wallet consensus version the federation runs at
+    async fn fetch_wallet_consensus_version(&self) -> FederationResult<ModuleConsensusVersion>;
+    /// Fetches the fees of the pending peg-out `txid`, `None` while the
+    /// guardians haven't signed it or once it confirmed
+    async fn fetch_pending_peg_out_fees(&self, txid: Txid) -> FederationResult<Option<PegOutFees>>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -166,4 +202,20 @@ where
         )
         .await
     }
+
+    async fn fetch_wallet_consensus_version(&self) -> FederationResult<ModuleConsensusVersion> {
+        self.request_eventually_consistent(
+            format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_WALLET}/consensus_version"),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn fetch_pending_peg_out_fees(&self, txid: Txid) -> FederationResult<Option<PegOutFees>> {
+        self.request_eventually_consistent(
+            format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_WALLET}/pending_peg_out_fees"),
+            ApiRequestErased::new(txid),
+        )
+        .await
+    }
 }
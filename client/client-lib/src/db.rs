@@ -1,14 +1,29 @@
+use fedimint_core::api::ConnectionOptions;
+use fedimint_core::db::namespace::DbPrefixRegistry;
+use fedimint_core::db::{Database, DatabaseVersion, MigrationMap, NamespaceVersionKey};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::impl_db_record;
+use fedimint_core::{impl_db_lookup, impl_db_record};
 use serde::Serialize;
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-use crate::ClientSecret;
+use crate::operation::{Operation, OperationId};
+use crate::secret::ClientMnemonic;
+use crate::sub_account::{SubAccount, SubAccountId};
+use crate::{ln, mint, wallet, ClientSecret};
+
+/// Version of the stores of the client without migrations, they are migrated
+/// independently since every one tracks its version in its own namespace
+pub const CLIENT_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
     ClientSecret = 0x29,
+    ClientMnemonic = 0x31,
+    Operation = 0x33,
+    ConnectionOptions = 0x35,
+    SubAccount = 0x38,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -25,3 +40,114 @@ impl_db_record!(
     value = ClientSecret,
     db_prefix = DbKeyPrefix::ClientSecret
 );
+
+/// Entropy of the mnemonic the client's secrets are derived from, clients that
+/// only have a legacy [`ClientSecretKey`] don't have one
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ClientMnemonicKey;
+
+impl_db_record!(
+    key = ClientMnemonicKey,
+    value = ClientMnemonic,
+    db_prefix = DbKeyPrefix::ClientMnemonic
+);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OperationKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OperationKeyPrefix;
+
+impl_db_record!(
+    key = OperationKey,
+    value = Operation,
+    db_prefix = DbKeyPrefix::Operation
+);
+impl_db_lookup!(key = OperationKey, query_prefix = OperationKeyPrefix);
+
+/// Options the client connects to the guardians of its federation with,
+/// default options if unset
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ConnectionOptionsKey;
+
+impl_db_record!(
+    key = ConnectionOptionsKey,
+    value = ConnectionOptions,
+    db_prefix = DbKeyPrefix::ConnectionOptions
+);
+
+/// Name and spending limit of a sub-account, its notes are kept in the
+/// isolated key space of [`SubAccountId::db_instance_id`]
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct SubAccountKey(pub SubAccountId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SubAccountKeyPrefix;
+
+impl_db_record!(
+    key = SubAccountKey,
+    value = SubAccount,
+    db_prefix = DbKeyPrefix::SubAccount
+);
+impl_db_lookup!(key = SubAccountKey, query_prefix = SubAccountKeyPrefix);
+
+/// Registers the namespaces of the client's stores, migrates each of them to
+/// its current version and makes sure no other keys are in `db`
+///
+/// Clients are built on an open database over and over, so once every store
+/// is at its current version only their versions are read.
+pub async fn migrate_client_database(db: &Database) -> anyhow::Result<()> {
+    let stores = client_stores();
+
+    let mut dbtx = db.begin_transaction().await;
+    let mut current = true;
+    for (name, _, version) in &stores {
+        let disk_version = dbtx.get_value(&NamespaceVersionKey(name.to_string())).await;
+        current &= disk_version.as_ref() == Some(version);
+    }
+    if current {
+        return Ok(());
+    }
+
+    let mut namespaces = DbPrefixRegistry::new();
+    for (name, prefixes, version) in stores {
+        let namespace = namespaces.register_prefixes(name, prefixes)?;
+        // the migration maps borrow the database view, so they are built here
+        let migrations = match name {
+            "ln" => ln::db::get_database_migrations(),
+            _ => MigrationMap::new(),
+        };
+        namespace
+            .apply_migrations(&namespace.database(db), version, migrations)
+            .await?;
+    }
+
+    namespaces.verify(db).await?;
+    Ok(())
+}
+
+/// The name, key prefixes and current version of each store of the client
+fn client_stores() -> [(&'static str, Vec<u8>, DatabaseVersion); 4] {
+    [
+        (
+            "client",
+            DbKeyPrefix::iter().map(|p| p as u8).collect(),
+            CLIENT_DATABASE_VERSION,
+        ),
+        (
+            "mint",
+            mint::db::DbKeyPrefix::iter().map(|p| p as u8).collect(),
+            CLIENT_DATABASE_VERSION,
+        ),
+        (
+            "ln",
+            ln::db::DbKeyPrefix::iter().map(|p| p as u8).collect(),
+            ln::db::DATABASE_VERSION,
+        ),
+        (
+            "wallet",
+            wallet::db::DbKeyPrefix::iter().map(|p| p as u8).collect(),
+            CLIENT_DATABASE_VERSION,
+        ),
+    ]
+}
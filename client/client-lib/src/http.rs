@@ -0,0 +1,96 @@
+//! HTTP requests of the client to gateways, which are made with the same
+//! [`ConnectionOptions`] as the connections to the guardians so e.g. a proxy
+//! applies to all of the client's traffic
+
+#[cfg(not(target_family = "wasm"))]
+use anyhow::Context;
+use fedimint_core::api::ConnectionOptions;
+
+/// Builds a client that connects through the proxy of `options` and sends
+/// its user agent, verifying servers against its TLS roots
+#[cfg(not(target_family = "wasm"))]
+pub fn http_client(options: &ConnectionOptions) -> anyhow::Result<reqwest::Client> {
+    let tls_config = fedimint_core::api::tls_client_config(options.tls_roots)?;
+    let mut builder = reqwest::Client::builder().use_preconfigured_tls(tls_config);
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.as_str()).context("Invalid proxy")?);
+    }
+    if let Some(user_agent) = &options.user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+    if let Some(connect_timeout) = options.connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    Ok(builder.build()?)
+}
+
+/// Browsers manage connections themselves, so the options are ignored
+#[cfg(target_family = "wasm")]
+pub fn http_client(_options: &ConnectionOptions) -> anyhow::Result<reqwest::Client> {
+    Ok(reqwest::Client::new())
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use fedimint_core::api::ConnectionOptions;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::http_client;
+
+    #[tokio::test]
+    async fn requests_go_through_the_proxy() {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = http_client(&ConnectionOptions {
+            proxy: Some(
+                format!("socks5h://{}", proxy.local_addr().unwrap())
+                    .parse()
+                    .unwrap(),
+            ),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let request = tokio::spawn(async move {
+            client
+                .post("http://gateway.example:8175/pay_invoice")
+                .send()
+                .await
+        });
+
+        // the gateway's host is resolved by the proxy, so we are asked to
+        // connect to it with a SOCKS5 greeting
+        let (mut stream, _) = proxy.accept().await.unwrap();
+        assert_eq!(stream.read_u8().await.unwrap(), 5);
+
+        drop(stream);
+        assert!(request.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_send_the_user_agent() {
+        let gateway = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/pay_invoice", gateway.local_addr().unwrap());
+        let client = http_client(&ConnectionOptions {
+            user_agent: Some("fedimint".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let request = tokio::spawn(async move { client.post(url).send().await });
+
+        let (mut stream, _) = gateway.accept().await.unwrap();
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap().to_lowercase();
+        assert!(head.contains("user-agent: fedimint\r\n"), "{head}");
+
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(request.await.unwrap().unwrap().status().is_success());
+    }
+}
@@ -1,8 +1,14 @@
+pub mod amount_format;
 pub mod api;
 pub mod db;
+pub mod http;
 pub mod ln;
 pub mod mint;
+pub mod operation;
 pub mod outcome;
+pub mod receipt;
+pub mod secret;
+pub mod sub_account;
 pub mod transaction;
 pub mod utils;
 pub mod wallet;
@@ -13,6 +19,7 @@ pub mod modules {
     };
 }
 
+use std::collections::{BTreeSet, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::iter::once;
 use std::ops::Add;
@@ -26,31 +33,36 @@ use bitcoin::{secp256k1, Address, Transaction as BitcoinTransaction};
 use bitcoin_hashes::{sha256, Hash};
 use fedimint_client::module::gen::{ClientModuleGenRegistry, ClientModuleGenRegistryExt};
 use fedimint_core::api::{
-    DynFederationApi, FederationError, GlobalFederationApi, MemberError, OutputOutcomeError,
-    WsFederationApi,
+    ConnectionOptions, DynFederationApi, FederationError, GlobalFederationApi, IFederationApi,
+    MemberError, OutputOutcomeError, WsFederationApi,
 };
+use fedimint_core::cancellable::{Cancellable, Cancelled};
 use fedimint_core::config::{ClientConfig, FederationId};
 use fedimint_core::core::{
     LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
     LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
-use fedimint_core::db::Database;
+use fedimint_core::db::{AutocommitError, Database, DatabaseTransaction};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::epoch::SignedEpochOutcome;
+use fedimint_core::epoch::{LimitError, SignedEpochOutcome};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::version::{
+    ApiVersionSet, SupportedApiVersionsSummary, SupportedCoreApiVersions,
+    SupportedModuleApiVersions, CORE_API_VERSIONS, CORE_CONSENSUS_VERSION,
+};
 use fedimint_core::module::ModuleCommon;
-use fedimint_core::outcome::TransactionStatus;
-use fedimint_core::task::{self, sleep};
+use fedimint_core::outcome::{TransactionStatus, TransactionValidation};
+use fedimint_core::task::{self, sleep, TaskGroup};
 use fedimint_core::tiered::InvalidAmountTierError;
-use fedimint_core::{Amount, OutPoint, TieredMulti, TransactionId};
+use fedimint_core::{Amount, FeeRate, Feerate, OutPoint, PeerId, TieredMulti, TransactionId};
 use fedimint_derive_secret::{ChildId, DerivableSecret};
 use fedimint_ln_client::{LightningModuleTypes, LightningOutputOutcome};
 use fedimint_logging::LOG_WALLET;
 use fedimint_mint_client::MintModuleTypes;
 use fedimint_wallet_client::WalletModuleTypes;
-use fedimint_wallet_common::Rbf;
+use fedimint_wallet_common::{PegOutFees, Rbf, OWNED_PEG_OUTS_VERSION};
 use futures::stream::{self, FuturesUnordered};
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use itertools::{Either, Itertools};
 use lightning::ln::PaymentSecret;
 use lightning::routing::gossip::RoutingFees;
@@ -63,51 +75,95 @@ use modules::mint::MintOutputOutcome;
 use rand::distributions::Standard;
 use rand::prelude::*;
 use rand::rngs::OsRng;
-use rand::{thread_rng, CryptoRng, Rng, RngCore};
-use secp256k1_zkp::{All, Secp256k1};
+use rand::{CryptoRng, Rng, RngCore};
+use secp256k1_zkp::{All, Secp256k1, XOnlyPublicKey};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use threshold_crypto::PublicKey;
-use tracing::{debug, info, instrument, trace};
+use tracing::{debug, info, instrument, trace, warn};
 use url::Url;
 
-use crate::db::ClientSecretKey;
+use crate::db::{
+    ClientMnemonicKey, ClientSecretKey, ConnectionOptionsKey, OperationKey, OperationKeyPrefix,
+    SubAccountKey, SubAccountKeyPrefix,
+};
 use crate::ln::db::{
-    OutgoingContractAccountKey, OutgoingContractAccountKeyPrefix, OutgoingPaymentClaimKey,
-    OutgoingPaymentClaimKeyPrefix, OutgoingPaymentKey,
+    AbandonedOfferKey, EcashReserveKey, GatewayFeeClaimKey, GatewayFeeClaimKeyPrefix,
+    HtlcAmountBandKey, IncomingGatewayFeeKey, LightningAddressKey, LnurlPaymentKey,
+    LnurlPaymentKeyPrefix, OrderInvoiceKey, OutgoingContractAccountKey,
+    OutgoingContractAccountKeyPrefix, OutgoingPaymentClaimKey, OutgoingPaymentClaimKeyPrefix,
+    OutgoingPaymentKey, PaymentHistoryKey, PaymentHistoryKeyPrefix, PreimagePurchaseKey,
+    PreimagePurchaseKeyPrefix, RoutingPausedKey,
 };
-use crate::ln::incoming::ConfirmedInvoice;
+use crate::ln::history::{PaymentHistoryEntry, PaymentKind};
+use crate::ln::incoming::{
+    ConfirmedInvoice, IncomingContractAccount, PreimagePurchase, PreimagePurchaseState,
+};
+use crate::ln::lnurl::{LightningAddress, LnurlPayment};
 use crate::ln::outgoing::OutgoingContractAccount;
-use crate::ln::{LnClient, LnClientError};
-use crate::mint::db::{NoteKey, PendingNotesKeyPrefix};
-use crate::mint::{MintClient, MintClientError, SpendableNote};
+use crate::ln::{HtlcAmountBand, LnClient, LnClientError};
+use crate::mint::db::{
+    LastNoteRefreshKey, NoteKey, P2pkOutputFinalizationKey, PendingNotesKey, PendingNotesKeyPrefix,
+};
+use crate::mint::{MintClient, MintClientError, P2pkNote, SpendableNote};
 use crate::modules::ln::config::LightningClientConfig;
 use crate::modules::ln::contracts::incoming::{IncomingContract, IncomingContractOffer};
 use crate::modules::ln::contracts::{
     Contract, ContractId, DecryptedPreimage, IdentifiableContract, Preimage,
 };
-use crate::modules::ln::{ContractOutput, LightningGateway, LightningOutput};
-use crate::modules::mint::config::MintClientConfig;
-use crate::modules::mint::{BlindNonce, MintOutput};
+use crate::modules::ln::{
+    ContractOutput, GatewayFee, GatewayLiquidity, LightningGateway, LightningOutput,
+    GATEWAY_FEE_VERSION,
+};
+use crate::modules::mint::config::{MintClientConfig, TierDeprecation};
+use crate::modules::mint::{BlindNonce, MintInput, MintOutput};
 use crate::modules::wallet::config::WalletClientConfig;
 use crate::modules::wallet::txoproof::TxOutProof;
 use crate::modules::wallet::{PegOut, WalletInput, WalletOutput};
+use crate::operation::{Operation, OperationId, OperationKind, OperationState};
 use crate::outcome::legacy::OutputOutcome;
+use crate::receipt::{verify_receipt_epoch, PaymentReceipt};
+use crate::secret::{ClientMnemonic, Mnemonic};
+use crate::sub_account::{SpendLimit, SubAccount, SubAccountClient, SubAccountId};
 use crate::transaction::legacy::{Input, Output, Transaction as LegacyTransaction};
 use crate::transaction::TransactionBuilder;
 use crate::utils::{network_to_currency, ClientContext};
-use crate::wallet::{WalletClient, WalletClientError};
+use crate::wallet::{fit_peg_out_to_balance, WalletClient, WalletClientError};
 
 /// Number of blocks until outgoing lightning contracts times out and user
 /// client can get refund
 const OUTGOING_LN_CONTRACT_TIMELOCK: u64 = 500;
+/// Number of times a transaction is rebuilt with other notes after some of its
+/// notes were spent by a concurrent transaction
+const MAX_CONFLICT_RESUBMISSIONS: usize = 3;
+/// Time a request that needs a single valid response waits for a guardian
+/// before another one is asked too
+pub const DEFAULT_HEDGING_DELAY: Duration = Duration::from_millis(500);
 /// Mint module's secret key derivation child id
 pub const MINT_SECRET_CHILD_ID: ChildId = ChildId(0);
+/// Wallet module's secret key derivation child id
+pub const WALLET_SECRET_CHILD_ID: ChildId = ChildId(1);
+/// Sub-accounts' secret key derivation child id
+pub const SUB_ACCOUNTS_SECRET_CHILD_ID: ChildId = ChildId(2);
+/// Lightning module's secret key derivation child id
+pub const LN_SECRET_CHILD_ID: ChildId = ChildId(3);
+/// How often clients check for deprecated note tiers and reissue their notes
+/// of them
+pub const NOTE_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 type Result<T> = std::result::Result<T, ClientError>;
 pub type GatewayClient = Client<GatewayClientConfig>;
 pub type UserClient = Client<UserClientConfig>;
 
+/// What an invoice tells the payer the payment is for
+#[derive(Debug, Clone)]
+pub enum PaymentDescription {
+    Direct(String),
+    /// Hash of a description that is too long for the invoice or that the
+    /// payer received by other means, e.g. LNURL metadata
+    Hash(sha256::Hash),
+}
+
 #[derive(Debug)]
 pub struct PaymentParameters {
     pub max_delay: u64,
@@ -120,6 +176,97 @@ pub struct PaymentParameters {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct UserClientConfig(pub ClientConfig);
 
+/// What receiving a lightning payment through a gateway costs, see
+/// [`Client::preview_receive`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReceivePreview {
+    /// Node public key of the gateway the invoice would be routed through
+    pub gateway: secp256k1::PublicKey,
+    /// Amount to request in the invoice
+    pub invoice_amount: Amount,
+    /// Routing fee the gateway charges on the last hop, which the payer pays
+    /// on top of the invoice amount
+    pub gateway_fee: Amount,
+    /// Total the payer pays, the invoice amount and the gateway fee
+    pub payer_amount: Amount,
+    /// Fee the federation charges for claiming the incoming contract
+    pub federation_fee: Amount,
+    /// Ecash we are issued once we claimed the incoming contract
+    pub net_amount: Amount,
+}
+
+/// One order of a point-of-sale batch, see [`Client::create_invoices`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InvoiceRequest {
+    /// Id the merchant's point-of-sale system knows the order by
+    pub order_id: String,
+    pub amount: Amount,
+    pub description: String,
+    /// Seconds until the invoice expires, the invoice's default if unset
+    pub expiry_time: Option<u64>,
+}
+
+/// Confirmed invoice of an order of a point-of-sale batch
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderInvoice {
+    pub order_id: String,
+    pub invoice: ConfirmedInvoice,
+}
+
+/// Payment to the invoice of an order, see [`Client::await_order_payments`]
+#[derive(Debug)]
+pub struct OrderPayment {
+    pub order_id: String,
+    pub outcome: Result<IncomingPaymentOutcome>,
+}
+
+/// How an outgoing lightning payment ended
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutgoingPaymentOutcome {
+    /// The gateway claimed the contract, so it paid the invoice
+    Paid,
+    /// The gateway didn't pay the invoice and the contract was refunded to us
+    /// in the transaction of the out point
+    Refunded(OutPoint),
+}
+
+/// A payment to one of our invoices we claimed, we were issued notes in the
+/// out points of `issuance`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct IncomingPaymentOutcome {
+    pub amount: Amount,
+    pub issuance: Vec<OutPoint>,
+}
+
+/// What [`Client::refresh_deprecated_notes`] did about our notes in tiers the
+/// federation deprecated
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteRefreshEvent {
+    /// The notes were reissued into other tiers in the transaction of the out
+    /// point
+    Reissued { amount: Amount, outpoint: OutPoint },
+    /// The notes couldn't be reissued automatically, the user has to do it
+    /// before the deadline or they are lost
+    ManualActionRequired {
+        amount: Amount,
+        deadline: Option<u64>,
+        reason: String,
+    },
+}
+
+/// A transaction the federation refused because it spends notes that were
+/// already spent, which [`Client::submit_tx_with_change`] couldn't resolve by
+/// replacing them with other notes
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TransactionConflictEvent {
+    /// Number of times the transaction was rebuilt with other notes
+    pub resubmissions: usize,
+    /// Why the last submission was refused
+    pub reason: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct GatewayClientConfig {
     pub client_config: ClientConfig,
@@ -139,6 +286,8 @@ impl GatewayClientConfig {
     pub fn to_gateway_registration_info(
         &self,
         route_hints: Vec<modules::ln::route_hints::RouteHint>,
+        fees: GatewayFee,
+        liquidity: Option<GatewayLiquidity>,
         time_to_live: Duration,
     ) -> LightningGateway {
         LightningGateway {
@@ -147,6 +296,8 @@ impl GatewayClientConfig {
             node_pub_key: self.node_pub_key,
             api: self.api.clone(),
             route_hints,
+            fees,
+            liquidity,
             valid_until: fedimint_core::time::now() + time_to_live,
         }
     }
@@ -175,8 +326,26 @@ impl<C> Client<C> {
     pub fn root_secret(&self) -> &DerivableSecret {
         &self.root_secret
     }
+
+    /// Recovers from the epochs kept by the third party archives at `urls` if
+    /// the guardians and their archives pruned them
+    ///
+    /// The archived epochs are signed by the federation, so the archives don't
+    /// have to be trusted.
+    pub fn with_epoch_archives(mut self, urls: Vec<Url>) -> Self {
+        let mut context = (*self.context).clone();
+        // every archive serves the endpoints of a guardian with the archive role
+        context.epoch_archives = urls
+            .into_iter()
+            .map(|url| WsFederationApi::new(vec![(PeerId::from(0), url)]).into())
+            .collect();
+        self.context = Arc::new(context);
+        self
+    }
 }
 
+/// Random secret of clients created before their secrets were derived from a
+/// mnemonic, see [`secret`]
 #[derive(Encodable, Decodable)]
 pub struct ClientSecret([u8; 64]);
 
@@ -202,10 +371,66 @@ impl AsRef<ClientConfig> for UserClientConfig {
 }
 
 impl PaymentParameters {
+    /// Most the gateway may spend on routing fees without losing money
+    pub fn max_fee(&self) -> Amount {
+        self.max_send_amount.saturating_sub(self.invoice_amount)
+    }
+
+    /// [`Self::max_fee`] relative to the invoice amount
+    pub fn max_fee_rate(&self) -> FeeRate {
+        FeeRate::from_fee(self.invoice_amount, self.max_fee()).unwrap_or(FeeRate::ZERO)
+    }
+
     // FIXME: change to absolute fee to avoid rounding errors
     pub fn max_fee_percent(&self) -> f64 {
-        let max_absolute_fee = self.max_send_amount - self.invoice_amount;
-        (max_absolute_fee.msats as f64) / (self.invoice_amount.msats as f64)
+        self.max_fee_rate().as_fraction()
+    }
+
+    /// Checks that the invoice of `account` is the one these parameters were
+    /// validated for and that paying it lets us claim the contract
+    pub fn verify_invoice(
+        &self,
+        account: &OutgoingContractAccount,
+    ) -> std::result::Result<(), ContractInvoiceError> {
+        let invoice = &account.contract.invoice;
+
+        let invoice_hash = *invoice.payment_hash();
+        if invoice_hash != account.contract.hash || invoice_hash != self.payment_hash {
+            return Err(ContractInvoiceError::PaymentHashMismatch {
+                invoice: invoice_hash,
+                contract: account.contract.hash,
+            });
+        }
+
+        let invoice_amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .ok_or(ContractInvoiceError::MissingAmount)?,
+        );
+        if invoice_amount != self.invoice_amount {
+            return Err(ContractInvoiceError::AmountMismatch {
+                invoice: invoice_amount,
+                validated: self.invoice_amount,
+            });
+        }
+        if account.amount < invoice_amount {
+            return Err(ContractInvoiceError::AmountExceedsContract {
+                invoice: invoice_amount,
+                contract: account.amount,
+            });
+        }
+
+        let expires_at = invoice.duration_since_epoch() + invoice.expiry_time();
+        let now = fedimint_core::time::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        if expires_at <= now {
+            return Err(ContractInvoiceError::Expired {
+                expired_at: expires_at.as_secs(),
+            });
+        }
+
+        Ok(())
     }
 }
 
@@ -218,175 +443,1072 @@ impl<T> Client<T> {
 // TODO: `get_module` is parsing `serde_json::Value` every time, which is not
 // best for performance
 impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
-    pub fn db(&self) -> &Database {
-        &self.context.db
-    }
-
-    pub fn ln_client(&self) -> LnClient {
-        LnClient {
-            config: self
-                .config
-                .as_ref()
-                .get_first_module_by_kind::<LightningClientConfig>("ln")
-                .expect("needs lightning module client config")
-                .1,
-            context: self.context.clone(),
+    /// Creates an invoice routed through `gateway` and the offer that lets the
+    /// gateway buy its preimage `raw_payment_secret` once it receives the
+    /// payment, earning `gateway_fee` of `amount`
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_invoice_and_offer<R: RngCore + CryptoRng>(
+        &self,
+        gateway: &LightningGateway,
+        amount: Amount,
+        description: PaymentDescription,
+        raw_payment_secret: [u8; 32],
+        mut rng: R,
+        expiry_time: Option<u64>,
+        gateway_fee: Amount,
+    ) -> Result<(Invoice, Output)> {
+        // the federation only accepts offers selling the preimage for more than
+        // the gateway's fee
+        if amount <= gateway_fee {
+            return Err(ClientError::InvoiceBelowGatewayFee(gateway_fee));
         }
-    }
+        let payment_hash = bitcoin::secp256k1::hashes::sha256::Hash::hash(&raw_payment_secret);
+        let payment_secret = PaymentSecret(raw_payment_secret);
 
-    pub fn mint_client(&self) -> MintClient {
-        MintClient {
-            config: self
-                .config
-                .as_ref()
-                .get_first_module_by_kind::<MintClientConfig>("mint")
-                .expect("needs mint module client config")
-                .1,
-            epoch_pk: self.config.as_ref().epoch_pk,
-            context: self.context.clone(),
-            secret: Self::mint_secret_static(&self.root_secret),
-        }
-    }
+        // Temporary lightning node pubkey
+        let (node_secret_key, node_public_key) = self.context.secp.generate_keypair(&mut rng);
 
-    pub fn wallet_client(&self) -> WalletClient {
-        WalletClient {
-            config: self
-                .config
+        // Route hint instructing payer how to route to gateway
+        let route_hint_last_hop = RouteHintHop {
+            src_node_id: gateway.node_pub_key,
+            short_channel_id: gateway.mint_channel_id,
+            fees: RoutingFees {
+                base_msat: gateway.fees.base_msat,
+                proportional_millionths: gateway.fees.proportional_millionths,
+            },
+            cltv_expiry_delta: 30,
+            htlc_minimum_msat: None,
+            htlc_maximum_msat: None,
+        };
+        let route_hints = if gateway.route_hints.is_empty() {
+            vec![RouteHint(vec![route_hint_last_hop])]
+        } else {
+            gateway
+                .route_hints
+                .iter()
+                .map(|rh| {
+                    RouteHint(
+                        rh.to_ldk_route_hint()
+                            .0
+                            .iter()
+                            .cloned()
+                            .chain(once(route_hint_last_hop.clone()))
+                            .collect(),
+                    )
+                })
+                .collect()
+        };
+
+        let duration_since_epoch = fedimint_core::time::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+
+        let invoice_builder = InvoiceBuilder::new(network_to_currency(
+            self.config
                 .as_ref()
                 .get_first_module_by_kind::<WalletClientConfig>("wallet")
-                .expect("needs wallet module client config")
-                .1,
+                .expect("must have wallet config available")
+                .1
+                .network,
+        ))
+        .amount_milli_satoshis(amount.msats);
+        let invoice_builder = match description {
+            PaymentDescription::Direct(description) => invoice_builder.description(description),
+            PaymentDescription::Hash(hash) => invoice_builder.description_hash(hash),
+        };
+        let mut invoice_builder = invoice_builder
+            .payment_hash(payment_hash)
+            .payment_secret(payment_secret)
+            .duration_since_epoch(duration_since_epoch)
+            .min_final_cltv_expiry(18)
+            .payee_pub_key(node_public_key)
+            .expiry_time(Duration::from_secs(
+                expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME),
+            ));
 
-            context: self.context.clone(),
+        for rh in route_hints {
+            invoice_builder = invoice_builder.private_route(rh);
         }
-    }
-
-    pub fn config(&self) -> T {
-        self.config.clone()
-    }
-
-    /// Verifies the config using the federation id
-    pub async fn verify_config(&self, id: &FederationId) -> Result<()> {
-        let config = self
-            .context
-            .api
-            .download_client_config(id, self.context.module_gens.to_common())
-            .await
-            .map_err(|_| ClientError::ConfigVerify(ConfigVerifyError::InvalidSignature))?;
 
-        let api_hash = config
-            .consensus_hash(&self.context.module_gens.to_common())
-            .map_err(|_| ClientError::ConfigVerify(ConfigVerifyError::CannotHash))?;
+        let invoice = invoice_builder.build_signed(|hash| {
+            self.context
+                .secp
+                .sign_ecdsa_recoverable(hash, &node_secret_key)
+        })?;
 
-        let self_hash = self
-            .config
-            .as_ref()
-            .consensus_hash(&self.context.module_gens.to_common())
-            .map_err(|_| ClientError::ConfigVerify(ConfigVerifyError::CannotHash))?;
+        let offer_output = self.ln_client().create_offer_output(
+            amount,
+            payment_hash,
+            Preimage(raw_payment_secret),
+            expiry_time,
+            gateway_fee,
+        );
+        let ln_output = Output::LN(offer_output);
 
-        if api_hash != self_hash {
-            Err(ClientError::ConfigVerify(
-                ConfigVerifyError::MismatchingConfigs,
-            ))
-        } else {
-            Ok(())
-        }
+        Ok((invoice, ln_output))
     }
 
-    pub async fn new(
-        config: T,
-        decoders: ModuleDecoderRegistry,
-        module_gens: ClientModuleGenRegistry,
-        db: Database,
-        secp: Secp256k1<All>,
-    ) -> Self {
-        let api = WsFederationApi::from_config(config.as_ref());
-        Self::new_with_api(config, decoders, module_gens, db, api.into(), secp).await
+    pub async fn fetch_registered_gateways(&self) -> Result<Vec<LightningGateway>> {
+        Ok(self.context.api.fetch_gateways().await?)
     }
 
-    pub async fn new_with_api(
-        config: T,
-        decoders: ModuleDecoderRegistry,
-        module_gens: ClientModuleGenRegistry,
-        db: Database,
-        api: DynFederationApi,
-        secp: Secp256k1<All>,
-    ) -> Client<T> {
-        let root_secret = Self::get_secret(&db).await;
-        Self {
-            config,
-            context: Arc::new(ClientContext {
-                decoders,
-                module_gens,
-                db,
-                api,
-                secp,
-            }),
-            root_secret,
-        }
+    /// Largest invoice amount we can pay with our notes through
+    /// [`Self::fund_outgoing_ln_contract_via`], after the fees of spending the
+    /// notes and funding the contract
+    pub async fn max_outgoing_payment(&self) -> Amount {
+        let notes = self.mint_client().notes().await;
+        let fees = self.mint_client().config.fee_consensus.note_spend_abs
+            * (notes.count_items() as u64)
+            + self.ln_client().config.fee_consensus.contract_output;
+        LnClient::max_invoice_amount(notes.total_amount().saturating_sub(fees))
     }
 
-    /// Fetches the client secret from the database or generates a new one if
-    /// none is present
-    async fn get_secret(db: &Database) -> DerivableSecret {
-        let mut tx = db.begin_transaction().await;
-        let client_secret = tx.get_value(&ClientSecretKey).await;
-        let secret = if let Some(client_secret) = client_secret {
-            client_secret
-        } else {
-            let secret: ClientSecret = thread_rng().gen();
-            let no_replacement = tx.insert_entry(&ClientSecretKey, &secret).await.is_none();
-            assert!(
-                no_replacement,
-                "We would have overwritten our secret key, aborting!"
-            );
-            secret
-        };
-        tx.commit_tx().await;
-        secret.into_root_secret()
+    /// Funds an outgoing contract paying `invoice` that `gateway` can claim
+    /// by routing the payment
+    pub async fn fund_outgoing_ln_contract_via<R: RngCore + CryptoRng>(
+        &self,
+        invoice: Invoice,
+        gateway: &LightningGateway,
+        mut rng: R,
+    ) -> Result<(ContractId, OutPoint)> {
+        let operation_id = rng.gen();
+        self.fund_outgoing_ln_contract_with_id(operation_id, invoice, gateway, rng)
+            .await
     }
 
-    pub async fn peg_in<R: RngCore + CryptoRng>(
+    /// Like [`Self::fund_outgoing_ln_contract_via`], but tracks the funding as
+    /// operation `operation_id` that can be queried with
+    /// [`Self::get_operation`] and cancelled with [`Self::cancel_operation`]
+    pub async fn fund_outgoing_ln_contract_with_id<R: RngCore + CryptoRng>(
         &self,
-        txout_proof: TxOutProof,
-        btc_transaction: BitcoinTransaction,
+        operation_id: OperationId,
+        invoice: Invoice,
+        gateway: &LightningGateway,
         mut rng: R,
-    ) -> Result<TransactionId> {
+    ) -> Result<(ContractId, OutPoint)> {
+        let mut dbtx = self.context.db.begin_transaction().await;
         let mut tx = TransactionBuilder::default();
 
-        let (peg_in_key, peg_in_proof) = self
-            .wallet_client()
-            .create_pegin_input(txout_proof, btc_transaction)
+        let consensus_height = self.context.api.fetch_consensus_block_height().await?;
+        let absolute_timelock = consensus_height + OUTGOING_LN_CONTRACT_TIMELOCK;
+
+        let contract = self
+            .ln_client()
+            .create_outgoing_output(&mut dbtx, invoice, gateway, absolute_timelock as u32)
             .await?;
 
-        tx.input(
-            &mut vec![peg_in_key],
-            Input::Wallet(WalletInput(Box::new(peg_in_proof))),
-        );
+        let contract_id = contract.contract.contract_id();
+        let amount = contract.amount;
 
-        self.submit_tx_with_change(tx, &mut rng).await
+        self.create_operation(
+            &mut dbtx,
+            operation_id,
+            OperationKind::OutgoingPayment { contract_id },
+        )
+        .await?;
+        dbtx.commit_tx().await;
+
+        let result = async {
+            let (mut keys, input) = self.mint_client().select_input(amount).await?;
+            tx.input(&mut keys, input);
+            tx.output(Output::LN(LightningOutput::Contract(contract)));
+            self.submit_operation_tx(operation_id, tx, &mut rng).await
+        }
+        .await;
+        let txid = self.finish_operation(operation_id, result).await?;
+        let outpoint = OutPoint { txid, out_idx: 0 };
+
+        debug!("Funded outgoing contract {} in {}", contract_id, outpoint);
+        Ok((contract_id, outpoint))
     }
 
-    /// Submits a transaction to the fed, making change using our change module
-    ///
-    /// TODO: For safety, if the submission fails, the DB write still occurs.
-    /// We should instead ensure the state of the client and consensus are
-    /// always the same.
-    pub async fn submit_tx_with_change<R: RngCore + CryptoRng>(
+    pub async fn await_outgoing_contract_acceptance(&self, outpoint: OutPoint) -> Result<()> {
+        self.context
+            .api
+            .await_output_outcome::<LightningOutputOutcome>(
+                outpoint,
+                Duration::from_secs(30),
+                &self.context.decoders,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Notify `gateway` that we've escrowed notes it can claim by routing our
+    /// payment and wait for it to do so, the notes are refunded if it fails
+    pub async fn await_outgoing_contract_execution_via(
+        &self,
+        contract_id: ContractId,
+        gateway: &LightningGateway,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<()> {
+        let payload =
+            PayInvoicePayload::new(self.config.as_ref().federation_id.clone(), contract_id);
+
+        let options = self.connection_options().await;
+        let client = http::http_client(&options).map_err(ClientError::InvalidConnectionOptions)?;
+        let future = client
+            .post(
+                gateway
+                    .api
+                    .join("pay_invoice")
+                    .expect("'pay_invoice' contains no invalid characters for a URL")
+                    .as_str(),
+            )
+            .json(&payload)
+            .send();
+        let result = fedimint_core::task::timeout(Duration::from_secs(120), future)
+            .await
+            .map_err(|_| ClientError::OutgoingPaymentTimeout)?
+            .map_err(ClientError::HttpError);
+
+        match result {
+            Ok(response) => {
+                if response.status().is_success() {
+                    return Ok(());
+                }
+
+                fedimint_core::task::timeout(
+                    Duration::from_secs(10),
+                    self.ln_client().await_outgoing_refundable(contract_id),
+                )
+                .await
+                .map_err(|_| ClientError::FailedPaymentNoRefund)??;
+
+                self.try_refund_outgoing_contract(contract_id, rng).await?;
+                Err(ClientError::RefundedFailedPayment)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Claims a refund for an expired or cancelled outgoing contract
+    ///
+    /// This can be necessary when the Lightning gateway cannot route the
+    /// payment, is malicious or offline. The function returns the out point
+    /// of the e-cash output generated as change.
+    pub async fn try_refund_outgoing_contract(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<OutPoint> {
+        let contract_data = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&OutgoingPaymentKey(contract_id))
+            .await
+            .ok_or(ClientError::RefundUnknownOutgoingContract)?;
+
+        let mut tx = TransactionBuilder::default();
+        let (refund_key, refund_input) = self
+            .ln_client()
+            .create_refund_outgoing_contract_input(&contract_data);
+        tx.input(&mut vec![*refund_key], Input::LN(refund_input));
+        let txid = self.submit_tx_with_change(tx, rng).await?;
+        let out_point = OutPoint { txid, out_idx: 0 };
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.remove_entry(&OutgoingPaymentKey(contract_id))
+            .await
+            .ok_or(ClientError::DeleteUnknownOutgoingContract)?;
+        // the refund settles the operation of the payment, if we track one
+        let operations = dbtx
+            .find_by_prefix(&OperationKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        for (key, mut operation) in operations {
+            if operation.kind == (OperationKind::OutgoingPayment { contract_id })
+                && !operation.state.is_final()
+            {
+                operation.state = OperationState::Refunded { out_point };
+                dbtx.insert_entry(&key, &operation).await;
+            }
+        }
+        dbtx.commit_tx().await;
+
+        Ok(out_point)
+    }
+
+    pub async fn get_operation(&self, operation_id: OperationId) -> Option<Operation> {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&OperationKey(operation_id))
+            .await
+    }
+
+    /// Returns all operations of the client, oldest first
+    pub async fn list_operations(&self) -> Vec<(OperationId, Operation)> {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&OperationKeyPrefix)
+            .await
+            .map(|(OperationKey(operation_id), operation)| (operation_id, operation))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .sorted_by_key(|(_, operation)| operation.created_at)
+            .collect()
+    }
+
+    /// Restores the states of our outgoing contracts from the federation,
+    /// including the ones of outgoing payments in the operation log we no
+    /// longer store, see [`LnClient::restore_contract_states`]
+    pub async fn restore_contract_states(&self) -> Result<()> {
+        let known_contracts =
+            self.list_operations()
+                .await
+                .into_iter()
+                .filter_map(|(_, operation)| match operation.kind {
+                    OperationKind::OutgoingPayment { contract_id } => Some(contract_id),
+                    _ => None,
+                });
+        Ok(self
+            .ln_client()
+            .restore_contract_states(known_contracts)
+            .await?)
+    }
+
+    /// Cancels an operation and returns its new state
+    ///
+    /// Operations whose transaction wasn't submitted yet are always
+    /// cancellable. Once submitted, only outgoing payments can be cancelled
+    /// by refunding their contract, which requires the gateway to have
+    /// cancelled it or its timelock to have expired.
+    pub async fn cancel_operation(
+        &self,
+        operation_id: OperationId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<Operation> {
+        let operation = self
+            .context
+            .db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    let mut operation = dbtx
+                        .get_value(&OperationKey(operation_id))
+                        .await
+                        .ok_or(ClientError::UnknownOperation)?;
+                    if operation.state == OperationState::Created {
+                        // the contract was never funded, so there is nothing to refund
+                        if let OperationKind::OutgoingPayment { contract_id } = operation.kind {
+                            dbtx.remove_entry(&OutgoingPaymentKey(contract_id)).await;
+                        }
+                        operation.state = OperationState::Cancelled;
+                        dbtx.insert_entry(&OperationKey(operation_id), &operation)
+                            .await;
+                    }
+                    Ok(operation)
+                })
+            })
+            .await?;
+
+        match (&operation.kind, &operation.state) {
+            (_, OperationState::Cancelled) => Ok(operation),
+            (OperationKind::OutgoingPayment { contract_id }, OperationState::Submitted { .. }) => {
+                if !self
+                    .ln_client()
+                    .is_outgoing_contract_refundable(*contract_id)
+                    .await?
+                {
+                    return Err(ClientError::OperationNotCancellable(
+                        "the contract can't be refunded before its timelock expires".to_string(),
+                    ));
+                }
+
+                self.try_refund_outgoing_contract(*contract_id, rng).await?;
+                self.get_operation(operation_id)
+                    .await
+                    .ok_or(ClientError::UnknownOperation)
+            }
+            (_, state) => Err(ClientError::OperationNotCancellable(format!(
+                "the operation is {state:?}"
+            ))),
+        }
+    }
+
+    async fn create_operation(
         &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        kind: OperationKind,
+    ) -> Result<()> {
+        let created_at = fedimint_core::time::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("time to be after the unix epoch")
+            .as_secs();
+        let operation = Operation {
+            kind,
+            state: OperationState::Created,
+            created_at,
+        };
+        if dbtx
+            .insert_entry(&OperationKey(operation_id), &operation)
+            .await
+            .is_some()
+        {
+            return Err(ClientError::OperationExists);
+        }
+        Ok(())
+    }
+
+    /// Updates a submitted operation with the outcome of its transaction and
+    /// returns the operation
+    ///
+    /// Outgoing payments only complete once the gateway claimed their
+    /// contract, refunding it marks them [`OperationState::Refunded`] instead.
+    pub async fn update_operation(&self, operation_id: OperationId) -> Result<Operation> {
+        let mut operation = self
+            .get_operation(operation_id)
+            .await
+            .ok_or(ClientError::UnknownOperation)?;
+        let OperationState::Submitted { txid } = operation.state else {
+            return Ok(operation);
+        };
+
+        let state = match self.context.api.fetch_tx_outcome(&txid).await? {
+            None => return Ok(operation),
+            Some(TransactionStatus::Rejected(error)) => OperationState::Failed { error },
+            Some(TransactionStatus::Accepted { .. }) => match &operation.kind {
+                OperationKind::OutgoingPayment { contract_id } => {
+                    let contract = self.ln_client().get_outgoing_contract(*contract_id).await?;
+                    if contract.amount != Amount::ZERO {
+                        return Ok(operation);
+                    }
+                    OperationState::Completed { txid }
+                }
+                _ => OperationState::Completed { txid },
+            },
+        };
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        // a refund might have settled the operation in the meantime
+        if let Some(stored) = dbtx.get_value(&OperationKey(operation_id)).await {
+            operation = stored;
+            if operation.state == (OperationState::Submitted { txid }) {
+                operation.state = state;
+                dbtx.insert_entry(&OperationKey(operation_id), &operation)
+                    .await;
+            }
+        }
+        dbtx.commit_tx().await;
+        Ok(operation)
+    }
+
+    /// Fails the operations a crash interrupted before their transaction was
+    /// built and returns their ids
+    ///
+    /// Such operations spent none of our notes, see
+    /// [`OperationState::Submitting`]. Must only be called while the client
+    /// runs no operations, e.g. when it starts.
+    pub async fn recover_interrupted_operations(&self) -> Vec<OperationId> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let operations = dbtx
+            .find_by_prefix(&OperationKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut interrupted = vec![];
+        for (OperationKey(operation_id), mut operation) in operations {
+            if !matches!(
+                operation.state,
+                OperationState::Created | OperationState::Submitting
+            ) {
+                continue;
+            }
+            // the contract was never funded, like when cancelling the operation
+            if let OperationKind::OutgoingPayment { contract_id } = operation.kind {
+                dbtx.remove_entry(&OutgoingPaymentKey(contract_id)).await;
+            }
+            operation.state = OperationState::Failed {
+                error: "interrupted before its transaction was built".to_string(),
+            };
+            dbtx.insert_entry(&OperationKey(operation_id), &operation)
+                .await;
+            interrupted.push(operation_id);
+        }
+        dbtx.commit_tx().await;
+        interrupted
+    }
+
+    /// Submits the transaction of an operation, unless the operation was
+    /// cancelled while the transaction was being built
+    async fn submit_operation_tx<R: RngCore + CryptoRng>(
+        &self,
+        operation_id: OperationId,
         tx: TransactionBuilder,
         rng: R,
     ) -> Result<TransactionId> {
+        self.start_operation_submission(operation_id).await?;
+        self.submit_tx_with_change_for(Some(operation_id), tx, rng)
+            .await
+    }
+
+    /// Moves an operation to [`OperationState::Submitting`], unless it was
+    /// cancelled
+    async fn start_operation_submission(&self, operation_id: OperationId) -> Result<()> {
+        self.context
+            .db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    let mut operation = dbtx
+                        .get_value(&OperationKey(operation_id))
+                        .await
+                        .ok_or(ClientError::UnknownOperation)?;
+                    if operation.state != OperationState::Created {
+                        return Err(ClientError::OperationCancelled);
+                    }
+                    operation.state = OperationState::Submitting;
+                    dbtx.insert_entry(&OperationKey(operation_id), &operation)
+                        .await;
+                    Ok(())
+                })
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Records the outcome of building and submitting the transaction of an
+    /// operation
+    async fn finish_operation(
+        &self,
+        operation_id: OperationId,
+        result: Result<TransactionId>,
+    ) -> Result<TransactionId> {
+        // a cancelled operation already is in its final state
+        if !matches!(result, Err(ClientError::OperationCancelled)) {
+            let state = match &result {
+                Ok(txid) => OperationState::Submitted { txid: *txid },
+                Err(e) => OperationState::Failed {
+                    error: e.to_string(),
+                },
+            };
+            self.set_operation_state(operation_id, state).await;
+        }
+        result
+    }
+
+    async fn set_operation_state(&self, operation_id: OperationId, state: OperationState) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        if let Some(mut operation) = dbtx.get_value(&OperationKey(operation_id)).await {
+            operation.state = state;
+            dbtx.insert_entry(&OperationKey(operation_id), &operation)
+                .await;
+        }
+        dbtx.commit_tx().await;
+    }
+
+    /// Claims the incoming contract whose decrypted preimage is the public key
+    /// of `keypair`
+    pub async fn claim_incoming_contract_with_key(
+        &self,
+        contract_id: ContractId,
+        keypair: KeyPair,
+        mut rng: impl RngCore + CryptoRng,
+    ) -> Result<OutPoint> {
+        let contract = self.ln_client().get_incoming_contract(contract_id).await?;
+
+        let operation_id = rng.gen();
+        let mut dbtx = self.context.db.begin_transaction().await;
+        self.create_operation(
+            &mut dbtx,
+            operation_id,
+            OperationKind::IncomingPayment { contract_id },
+        )
+        .await?;
+        dbtx.commit_tx().await;
+
+        // Input claims this contract
+        let mut tx = TransactionBuilder::default();
+        tx.input(&mut vec![keypair], Input::LN(contract.claim()));
+        let result = self.submit_operation_tx(operation_id, tx, &mut rng).await;
+        let txid = self.finish_operation(operation_id, result).await?;
+
+        Ok(OutPoint { txid, out_idx: 0 })
+    }
+
+    pub fn db(&self) -> &Database {
+        &self.context.db
+    }
+
+    pub fn ln_client(&self) -> LnClient {
+        LnClient {
+            config: self
+                .config
+                .as_ref()
+                .get_first_module_by_kind::<LightningClientConfig>("ln")
+                .expect("needs lightning module client config")
+                .1,
+            context: self.context.clone(),
+            secret: self.root_secret.child_key(LN_SECRET_CHILD_ID),
+        }
+    }
+
+    pub fn mint_client(&self) -> MintClient {
+        MintClient {
+            config: self
+                .config
+                .as_ref()
+                .get_first_module_by_kind::<MintClientConfig>("mint")
+                .expect("needs mint module client config")
+                .1,
+            epoch_pk: self.config.as_ref().epoch_pk,
+            context: self.context.clone(),
+            secret: Self::mint_secret_static(&self.root_secret),
+        }
+    }
+
+    pub fn wallet_client(&self) -> WalletClient {
+        WalletClient {
+            config: self
+                .config
+                .as_ref()
+                .get_first_module_by_kind::<WalletClientConfig>("wallet")
+                .expect("needs wallet module client config")
+                .1,
+
+            context: self.context.clone(),
+        }
+    }
+
+    pub fn config(&self) -> T {
+        self.config.clone()
+    }
+
+    /// Verifies the config using the federation id
+    pub async fn verify_config(&self, id: &FederationId) -> Result<()> {
+        let config = self
+            .context
+            .api
+            .download_client_config(id, self.context.module_gens.to_common())
+            .await
+            .map_err(|_| ClientError::ConfigVerify(ConfigVerifyError::InvalidSignature))?;
+
+        let api_hash = config
+            .consensus_hash(&self.context.module_gens.to_common())
+            .map_err(|_| ClientError::ConfigVerify(ConfigVerifyError::CannotHash))?;
+
+        let self_hash = self
+            .config
+            .as_ref()
+            .consensus_hash(&self.context.module_gens.to_common())
+            .map_err(|_| ClientError::ConfigVerify(ConfigVerifyError::CannotHash))?;
+
+        if api_hash != self_hash {
+            Err(ClientError::ConfigVerify(
+                ConfigVerifyError::MismatchingConfigs,
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// API versions of the core and the federation's modules this client
+    /// implements, modules it doesn't implement are left out
+    pub fn supported_api_versions(&self) -> SupportedApiVersionsSummary {
+        SupportedApiVersionsSummary {
+            core: SupportedCoreApiVersions {
+                core_consensus: CORE_CONSENSUS_VERSION,
+                api: CORE_API_VERSIONS.to_vec(),
+            },
+            modules: self
+                .config
+                .as_ref()
+                .modules
+                .keys()
+                .filter_map(|id| {
+                    let (module_consensus, api) = match *id {
+                        LEGACY_HARDCODED_INSTANCE_ID_MINT => (
+                            fedimint_mint_client::CONSENSUS_VERSION,
+                            fedimint_mint_client::API_VERSIONS,
+                        ),
+                        LEGACY_HARDCODED_INSTANCE_ID_LN => (
+                            fedimint_ln_client::CONSENSUS_VERSION,
+                            fedimint_ln_client::API_VERSIONS,
+                        ),
+                        LEGACY_HARDCODED_INSTANCE_ID_WALLET => (
+                            fedimint_wallet_client::CONSENSUS_VERSION,
+                            fedimint_wallet_client::API_VERSIONS,
+                        ),
+                        _ => return None,
+                    };
+                    let versions = SupportedModuleApiVersions {
+                        module_consensus,
+                        api: api.to_vec(),
+                    };
+                    Some((*id, versions))
+                })
+                .collect(),
+        }
+    }
+
+    /// Negotiates the API versions with the guardians, afterwards requests in
+    /// formats a threshold of them doesn't support are no longer sent
+    ///
+    /// Clients negotiate on their own before the first request depending on
+    /// the versions, this re-negotiates e.g. after the guardians upgraded.
+    pub async fn negotiate_api_versions(&self) -> Result<ApiVersionSet> {
+        self.context
+            .api
+            .negotiate_api_versions(&self.supported_api_versions())
+            .await
+            .map_err(ClientError::ApiVersionNegotiation)
+    }
+
+    pub async fn new(
+        config: T,
+        decoders: ModuleDecoderRegistry,
+        module_gens: ClientModuleGenRegistry,
+        db: Database,
+        secp: Secp256k1<All>,
+    ) -> Result<Self> {
+        Self::new_with_hedging(
+            config,
+            decoders,
+            module_gens,
+            db,
+            secp,
+            Some(DEFAULT_HEDGING_DELAY),
+        )
+        .await
+    }
+
+    /// Like [`Self::new`], but hedges requests after `hedging_delay` instead of
+    /// the default, or asks all guardians at once if it's `None`
+    pub async fn new_with_hedging(
+        config: T,
+        decoders: ModuleDecoderRegistry,
+        module_gens: ClientModuleGenRegistry,
+        db: Database,
+        secp: Secp256k1<All>,
+        hedging_delay: Option<Duration>,
+    ) -> Result<Self> {
+        let options = db
+            .begin_transaction()
+            .await
+            .get_value(&ConnectionOptionsKey)
+            .await
+            .unwrap_or_default();
+        let mut api =
+            WsFederationApi::from_config(config.as_ref()).with_connection_options(options);
+        if let Some(delay) = hedging_delay {
+            api = api.with_hedging(delay);
+        }
+        Self::new_with_api(config, decoders, module_gens, db, api.into(), secp).await
+    }
+
+    /// Options the client connects to the guardians with, e.g. a proxy
+    pub async fn connection_options(&self) -> ConnectionOptions {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&ConnectionOptionsKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Saves the options the client connects to the guardians of this
+    /// federation with, they take effect the next time the client is created
+    pub async fn set_connection_options(&self, options: ConnectionOptions) {
         let mut dbtx = self.context.db.begin_transaction().await;
-        let final_tx = tx.build(self, &mut dbtx, rng).await;
+        dbtx.insert_entry(&ConnectionOptionsKey, &options).await;
         dbtx.commit_tx().await;
-        let result = self
+    }
+
+    /// Creates a client talking to the federation over `api`, fails if `db`
+    /// can't be migrated to the current version of the client
+    pub async fn new_with_api(
+        config: T,
+        decoders: ModuleDecoderRegistry,
+        module_gens: ClientModuleGenRegistry,
+        db: Database,
+        api: DynFederationApi,
+        secp: Secp256k1<All>,
+    ) -> Result<Client<T>> {
+        crate::db::migrate_client_database(&db)
+            .await
+            .map_err(ClientError::DatabaseMigration)?;
+        let root_secret = Self::get_secret(&db, &config.as_ref().federation_id).await;
+        let client = Self {
+            config,
+            context: Arc::new(ClientContext {
+                decoders,
+                module_gens,
+                db,
+                api,
+                epoch_archives: vec![],
+                secp,
+            }),
+            root_secret,
+        };
+        // the versions are negotiated before the first request depending on them
+        client
             .context
             .api
-            .submit_transaction(final_tx.into_type_erased())
+            .set_client_api_versions(client.supported_api_versions());
+        Ok(client)
+    }
+
+    /// Fetches the client secret from the database or generates a new mnemonic
+    /// if none is present
+    ///
+    /// Clients that only have a legacy random secret keep using it, since their
+    /// notes were derived from it.
+    async fn get_secret(db: &Database, federation_id: &FederationId) -> DerivableSecret {
+        let mut tx = db.begin_transaction().await;
+        if let Some(legacy_secret) = tx.get_value(&ClientSecretKey).await {
+            tx.commit_tx().await;
+            return legacy_secret.into_root_secret();
+        }
+
+        let mnemonic = if let Some(mnemonic) = tx.get_value(&ClientMnemonicKey).await {
+            mnemonic
+        } else {
+            let mnemonic = ClientMnemonic::generate(&mut OsRng);
+            let no_replacement = tx
+                .insert_entry(&ClientMnemonicKey, &mnemonic)
+                .await
+                .is_none();
+            assert!(
+                no_replacement,
+                "We would have overwritten our mnemonic, aborting!"
+            );
+            mnemonic
+        };
+        tx.commit_tx().await;
+        mnemonic.federation_secret(federation_id)
+    }
+
+    /// Stores `mnemonic` to derive the secrets of a client that will be
+    /// created on `db`, so it recovers the funds of a wallet with the same
+    /// mnemonic
+    ///
+    /// Fails if the client already has another secret, since replacing it
+    /// would lose access to its notes.
+    pub async fn import_mnemonic(db: &Database, mnemonic: &Mnemonic) -> Result<()> {
+        let mnemonic = ClientMnemonic::from(mnemonic);
+        let mut tx = db.begin_transaction().await;
+        if tx.get_value(&ClientSecretKey).await.is_some() {
+            return Err(ClientError::LegacySecret);
+        }
+        match tx.get_value(&ClientMnemonicKey).await {
+            Some(existing) if existing != mnemonic => return Err(ClientError::SecretAlreadySet),
+            Some(_) => {}
+            None => {
+                tx.insert_new_entry(&ClientMnemonicKey, &mnemonic).await;
+            }
+        }
+        tx.commit_tx().await;
+        Ok(())
+    }
+
+    /// Mnemonic the client's secrets are derived from, to be written down so
+    /// the wallet can be recovered
+    pub async fn mnemonic(&self) -> Result<Mnemonic> {
+        let mut tx = self.context.db.begin_transaction().await;
+        tx.get_value(&ClientMnemonicKey)
+            .await
+            .map(|mnemonic| mnemonic.to_mnemonic())
+            .ok_or(ClientError::LegacySecret)
+    }
+
+    pub async fn peg_in<R: RngCore + CryptoRng>(
+        &self,
+        txout_proof: TxOutProof,
+        btc_transaction: BitcoinTransaction,
+        mut rng: R,
+    ) -> Result<TransactionId> {
+        let operation_id = rng.gen();
+        self.peg_in_with_id(operation_id, txout_proof, btc_transaction, rng)
+            .await
+    }
+
+    /// Like [`Self::peg_in`], but tracks the peg-in as operation
+    /// `operation_id` that can be queried with [`Self::get_operation`]
+    pub async fn peg_in_with_id<R: RngCore + CryptoRng>(
+        &self,
+        operation_id: OperationId,
+        txout_proof: TxOutProof,
+        btc_transaction: BitcoinTransaction,
+        mut rng: R,
+    ) -> Result<TransactionId> {
+        let mut tx = TransactionBuilder::default();
+
+        let (peg_in_key, peg_in_proof) = self
+            .wallet_client()
+            .create_pegin_input(txout_proof, btc_transaction)
             .await?;
 
-        Ok(result)
+        let mut dbtx = self.context.db.begin_transaction().await;
+        self.create_operation(
+            &mut dbtx,
+            operation_id,
+            OperationKind::PegIn {
+                outpoint: peg_in_proof.outpoint(),
+            },
+        )
+        .await?;
+        dbtx.commit_tx().await;
+
+        tx.input(
+            &mut vec![peg_in_key],
+            Input::Wallet(WalletInput::PegIn(Box::new(peg_in_proof))),
+        );
+
+        let result = self.submit_operation_tx(operation_id, tx, &mut rng).await;
+        self.finish_operation(operation_id, result).await
+    }
+
+    /// Submits a transaction to the fed, making change using our change module
+    ///
+    /// If some of the notes of the transaction were spent by a concurrent
+    /// transaction, e.g. of another operation that selected the same notes,
+    /// they are replaced by other notes of our wallet and the transaction is
+    /// resubmitted, up to [`MAX_CONFLICT_RESUBMISSIONS`] times. Returns a
+    /// [`TransactionConflictEvent`] if that doesn't resolve the conflict.
+    ///
+    /// TODO: For safety, if the submission fails, the DB write still occurs.
+    /// We should instead ensure the state of the client and consensus are
+    /// always the same.
+    pub async fn submit_tx_with_change<R: RngCore + CryptoRng>(
+        &self,
+        tx: TransactionBuilder,
+        rng: R,
+    ) -> Result<TransactionId> {
+        self.submit_tx_with_change_for(None, tx, rng).await
+    }
+
+    /// Like [`Self::submit_tx_with_change`], recording the transaction in the
+    /// state of the operation `operation_id`, if any
+    async fn submit_tx_with_change_for<R: RngCore + CryptoRng>(
+        &self,
+        operation_id: Option<OperationId>,
+        mut tx: TransactionBuilder,
+        mut rng: R,
+    ) -> Result<TransactionId> {
+        let mut resubmissions = 0;
+        loop {
+            let reason = match self
+                .submit_tx_once(operation_id, tx.clone(), &mut rng)
+                .await
+            {
+                Err(ClientError::TransactionConflict(reason)) => reason,
+                result => return result,
+            };
+
+            // the notes that aren't in our wallet anymore were spent by the
+            // conflicting transaction
+            let wallet: HashSet<_> = self
+                .mint_client()
+                .notes()
+                .await
+                .iter_items()
+                .map(|(_, note)| note.note.0)
+                .collect();
+            let spent: Vec<_> = tx
+                .nonces()
+                .into_iter()
+                .filter(|nonce| !wallet.contains(nonce))
+                .collect();
+            if spent.is_empty() || resubmissions == MAX_CONFLICT_RESUBMISSIONS {
+                let event = TransactionConflictEvent {
+                    resubmissions,
+                    reason,
+                };
+                warn!(?event, "Transaction conflict needs manual action");
+                return Err(ClientError::UnresolvedConflict(event));
+            }
+
+            resubmissions += 1;
+            let amount = tx.remove_notes(&spent);
+            info!(%amount, resubmissions, "Replacing spent notes of a conflicting transaction");
+            let (mut keys, input) = self.mint_client().select_input(amount).await?;
+            tx.input(&mut keys, input);
+        }
+    }
+
+    /// Builds and submits a transaction once, see
+    /// [`Self::submit_tx_with_change`]
+    ///
+    /// Returns [`ClientError::TransactionConflict`] if some of its notes were
+    /// spent already, the other notes are back in the wallet then.
+    async fn submit_tx_once<R: RngCore + CryptoRng>(
+        &self,
+        operation_id: Option<OperationId>,
+        tx: TransactionBuilder,
+        rng: R,
+    ) -> Result<TransactionId> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let change_idx = tx.num_outputs();
+        let final_tx = match tx.build(self, &mut dbtx, rng).await {
+            Err(MintClientError::NoteAlreadySpent) => {
+                return Err(ClientError::TransactionConflict(
+                    MintClientError::NoteAlreadySpent.to_string(),
+                ))
+            }
+            result => result?,
+        };
+        let erased_tx = final_tx.clone().into_type_erased();
+        // the federation would reject the transaction, so we keep the notes it spends
+        self.config.as_ref().limits.check_transaction(&erased_tx)?;
+        if let Some(operation_id) = operation_id {
+            // committed with the notes the transaction spends, so operations a
+            // crash interrupted before spent none
+            if let Some(mut operation) = dbtx.get_value(&OperationKey(operation_id)).await {
+                operation.state = OperationState::Submitted {
+                    txid: final_tx.tx_hash(),
+                };
+                dbtx.insert_entry(&OperationKey(operation_id), &operation)
+                    .await;
+            }
+        }
+        // a concurrent transaction of ours committed spending the same notes
+        if let Err(e) = dbtx.commit_tx_result().await {
+            return Err(ClientError::TransactionConflict(e.to_string()));
+        }
+
+        match self.context.api.submit_transaction(erased_tx).await {
+            Ok(txid) => Ok(txid),
+            Err(e) if e.is_conflict() => {
+                self.revert_conflicting_tx(&final_tx, change_idx).await?;
+                Err(ClientError::TransactionConflict(e.to_string()))
+            }
+            Err(e) => Err(match e.limit_error() {
+                Some(limit) => ClientError::LimitExceeded(limit),
+                None => ClientError::MintApiError(e),
+            }),
+        }
+    }
+
+    /// Returns the notes of a transaction the federation refused for spending
+    /// already spent notes to our wallet, except for the spent ones
+    async fn revert_conflicting_tx(&self, tx: &LegacyTransaction, change_idx: usize) -> Result<()> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let pending = dbtx
+            .get_value(&PendingNotesKey(tx.tx_hash()))
+            .await
+            .unwrap_or_default();
+        let spent: Vec<_> = self
+            .check_notes_spendable(&pending)
+            .await?
+            .into_iter()
+            .zip(pending.iter_items())
+            .filter_map(|(error, (_, note))| error.map(|_| note.note.0))
+            .collect();
+
+        self.mint_client()
+            .revert_change(tx, &mut dbtx, change_idx, &spent)
+            .await;
+        dbtx.commit_tx().await;
+        Ok(())
     }
 
     /// Spent some [`SpendableNote`]s to receive a freshly minted ones
@@ -404,6 +1526,7 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         notes: TieredMulti<SpendableNote>,
         mut rng: R,
     ) -> Result<OutPoint> {
+        let operation_id = rng.gen();
         // Ensure we have the notes in the DB (in case we received them from another
         // user)
         let mut dbtx = self.context.db.begin_transaction().await;
@@ -414,12 +1537,27 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
             };
             dbtx.insert_entry(&key, &note).await;
         }
+        self.create_operation(
+            &mut dbtx,
+            operation_id,
+            OperationKind::Reissue {
+                amount: notes.total_amount(),
+            },
+        )
+        .await?;
         dbtx.commit_tx().await;
 
-        let mut tx = TransactionBuilder::default();
-        let (mut keys, input) = MintClient::ecash_input(notes)?;
-        tx.input(&mut keys, input);
-        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+        let result = async {
+            let mut tx = TransactionBuilder::default();
+            let (mut keys, input) = MintClient::ecash_input(notes)?;
+            tx.input(&mut keys, input);
+            self.start_operation_submission(operation_id).await?;
+            // replacing notes that were spent already with our own would hide that
+            // the notes we received are worthless
+            self.submit_tx_once(Some(operation_id), tx, &mut rng).await
+        }
+        .await;
+        let txid = self.finish_operation(operation_id, result).await?;
 
         Ok(OutPoint { txid, out_idx: 0 })
     }
@@ -440,6 +1578,36 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         })
     }
 
+    /// Checks whether the federation would accept spending each of the notes
+    /// without spending them. The `i`th input of the returned validation spends
+    /// the `i`th note of [`TieredMulti::iter_items`], so its errors tell why
+    /// that note can't be spent and its `spent_inputs` which notes were spent
+    /// already.
+    pub async fn check_notes_spendable(
+        &self,
+        notes: &TieredMulti<SpendableNote>,
+    ) -> Result<TransactionValidation> {
+        // every note is spent in its own input, so the input errors tell us
+        // which of the notes can't be spent
+        let inputs: Vec<_> = notes
+            .iter_items()
+            .map(|(amount, note)| {
+                Input::Mint(MintInput(TieredMulti::from_iter([(amount, note.note)])))
+            })
+            .collect();
+        let tx = LegacyTransaction {
+            inputs,
+            outputs: vec![],
+            signature: None,
+        };
+
+        Ok(self
+            .context
+            .api
+            .validate_transaction(&tx.into_type_erased())
+            .await?)
+    }
+
     /// Pay by creating notes provided (and most probably controlled) by the
     /// recipient.
     ///
@@ -472,6 +1640,73 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         Ok(OutPoint { txid, out_idx: 0 })
     }
 
+    /// Pay by creating notes bound to the `recipient`'s public key
+    ///
+    /// Spending the notes takes the recipient's secret key, see
+    /// [`Self::reissue_p2pk`], so unlike the notes of [`Self::spend_ecash`]
+    /// they are safe in backups or databases that may leak.
+    ///
+    /// Returns the `OutPoint` of the notes, [`Self::fetch_p2pk_notes`] returns
+    /// them once the federation issued them.
+    pub async fn pay_to_pubkey<R: RngCore + CryptoRng>(
+        &self,
+        amount: Amount,
+        recipient: XOnlyPublicKey,
+        mut rng: R,
+    ) -> Result<OutPoint> {
+        let mut tx = TransactionBuilder::default();
+
+        let (mut keys, input) = self.mint_client().select_input(amount).await?;
+        tx.input(&mut keys, input);
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let (issuance, blind_nonces) = self
+            .mint_client()
+            .create_p2pk_ecash(amount, recipient, &mut dbtx, &mut rng)
+            .await;
+        let out_idx = tx.output(Output::Mint(MintOutput(blind_nonces)));
+
+        // Without the blinding keys the notes can never be fetched, so save them
+        // before submitting
+        let final_tx = tx.build(self, &mut dbtx, &mut rng).await?;
+        let outpoint = OutPoint {
+            txid: final_tx.tx_hash(),
+            out_idx,
+        };
+        dbtx.insert_new_entry(&P2pkOutputFinalizationKey(outpoint), &issuance)
+            .await;
+        dbtx.commit_tx().await;
+
+        self.context
+            .api
+            .submit_transaction(final_tx.into_type_erased())
+            .await?;
+
+        Ok(outpoint)
+    }
+
+    /// Returns the notes created by [`Self::pay_to_pubkey`] once the
+    /// federation issued them
+    pub async fn fetch_p2pk_notes(&self, outpoint: OutPoint) -> Result<TieredMulti<P2pkNote>> {
+        Ok(self.mint_client().fetch_p2pk_notes(outpoint).await?)
+    }
+
+    /// Reissues notes bound to our public key into notes of our own, `key` is
+    /// the key pair the notes were bound to
+    pub async fn reissue_p2pk<R: RngCore + CryptoRng>(
+        &self,
+        notes: TieredMulti<P2pkNote>,
+        key: &KeyPair,
+        rng: R,
+    ) -> Result<OutPoint> {
+        let notes = notes
+            .into_iter()
+            .map(|(amount, note)| Ok((amount, note.into_spendable(&self.context.secp, key)?)))
+            .collect::<Result<TieredMulti<_>>>()?;
+
+        self.reissue(notes, rng).await
+    }
+
     /// Receive e-cash directly from another user when online (vs. offline
     /// transfer)
     ///
@@ -491,25 +1726,98 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         dbtx.commit_tx().await;
     }
 
+    /// Creates a peg-out of `amount` to `recipient` paying the on-chain fees
+    /// at `fee_rate`, or at the rate the federation estimates if it's `None`
+    ///
+    /// The peg-out is owned by a key derived from `recipient`, so its fees can
+    /// be bumped with [`Self::bump_peg_out_fees`] while it's unconfirmed, if
+    /// the federation runs a wallet consensus version with owned peg-outs.
     pub async fn new_peg_out_with_fees(
         &self,
         amount: bitcoin::Amount,
         recipient: Address,
+        fee_rate: Option<Feerate>,
     ) -> Result<PegOut> {
-        let fees = self
+        let estimate = self
             .context
             .api
             .fetch_peg_out_fees(&recipient, amount)
-            .await?;
-        fees.map(|fees| PegOut {
+            .await?
+            .ok_or(ClientError::PegOutWaitingForUTXOs)?;
+        let fees = match fee_rate {
+            Some(fee_rate) if fee_rate < estimate.fee_rate => {
+                return Err(ClientError::PegOutFeeRateTooLow {
+                    fee_rate,
+                    min: estimate.fee_rate,
+                })
+            }
+            Some(fee_rate) => PegOutFees {
+                fee_rate,
+                ..estimate
+            },
+            None => estimate,
+        };
+
+        let owner = self
+            .owned_peg_outs_active()
+            .await
+            .then(|| self.peg_out_owner_key(&recipient).x_only_public_key().0);
+        Ok(PegOut {
             recipient,
             amount,
             fees,
+            owner,
+        })
+    }
+
+    /// Whether the guardians accept peg-outs with an owner, which they reject
+    /// until the federation activated them
+    ///
+    /// Guardians too old to tell their version don't accept them either.
+    async fn owned_peg_outs_active(&self) -> bool {
+        self.context
+            .api
+            .fetch_wallet_consensus_version()
+            .await
+            .map_or(false, |version| version >= OWNED_PEG_OUTS_VERSION)
+    }
+
+    /// Total amount of ecash a peg-out costs, including the on-chain fees and
+    /// the fee of the federation
+    pub fn peg_out_cost(&self, peg_out: &PegOut) -> Amount {
+        self.wallet_client().config.fee_consensus.peg_out_abs
+            + (peg_out.amount + peg_out.fees.amount()).into()
+    }
+
+    /// Key owning our peg-outs to `recipient`, it's derived from the address
+    /// so the owner of a peg-out can be found again from its recipient
+    fn peg_out_owner_key(&self, recipient: &Address) -> KeyPair {
+        let hash = sha256::Hash::hash(recipient.script_pubkey().as_bytes());
+        let mut id = [0u8; 8];
+        id.copy_from_slice(&hash[..8]);
+        self.root_secret
+            .child_key(WALLET_SECRET_CHILD_ID)
+            .child_key(ChildId(u64::from_le_bytes(id)))
+            .to_secp_key(&self.context.secp)
+    }
+
+    /// Creates a peg-out of our whole balance to `recipient`, the fees of the
+    /// federation and of the on-chain transaction are paid out of it
+    pub async fn new_peg_out_all(&self, recipient: Address) -> Result<PegOut> {
+        let notes = self.mint_client().notes().await;
+        let fees = self.mint_client().config.fee_consensus.note_spend_abs
+            * (notes.count_items() as u64)
+            + self.wallet_client().config.fee_consensus.peg_out_abs;
+        let available = notes.total_amount().saturating_sub(fees);
+
+        fit_peg_out_to_balance(available, |amount| {
+            self.new_peg_out_with_fees(amount, recipient.clone(), None)
         })
-        .ok_or(ClientError::PegOutWaitingForUTXOs)
+        .await?
+        .ok_or(ClientError::PegOutBalanceTooSmall(notes.total_amount()))
     }
 
-    pub async fn rbf_tx(&self, rbf: Rbf) -> Result<OutPoint> {
+    pub async fn rbf_tx<R: RngCore + CryptoRng>(&self, rbf: Rbf, mut rng: R) -> Result<OutPoint> {
         let mut tx = TransactionBuilder::default();
 
         let amount = rbf.fees.amount().into();
@@ -517,7 +1825,7 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         tx.input(&mut keys, input);
         let peg_out_idx = tx.output(Output::Wallet(WalletOutput::Rbf(rbf)));
 
-        let fedimint_tx_id = self.submit_tx_with_change(tx, &mut OsRng).await?;
+        let fedimint_tx_id = self.submit_tx_with_change(tx, &mut rng).await?;
 
         Ok(OutPoint {
             txid: fedimint_tx_id,
@@ -525,27 +1833,84 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         })
     }
 
+    /// Raises the fee rate of our unconfirmed peg-out `txid` to `recipient` by
+    /// `fee_rate`, signed with the key owning the peg-out
+    ///
+    /// The weight the added fees are paid for is the one of the peg-out
+    /// transaction the guardians stored. Unlike [`Self::rbf_tx`] this only
+    /// works for peg-outs we created, since the federation doesn't let anyone
+    /// else bump them this way.
+    pub async fn bump_peg_out_fees<R: RngCore + CryptoRng>(
+        &self,
+        txid: bitcoin::Txid,
+        recipient: &Address,
+        fee_rate: Feerate,
+        mut rng: R,
+    ) -> Result<TransactionId> {
+        let pending = self
+            .context
+            .api
+            .fetch_pending_peg_out_fees(txid)
+            .await?
+            .ok_or(ClientError::PegOutNotPending(txid))?;
+        let fees = PegOutFees {
+            fee_rate,
+            total_weight: pending.total_weight,
+        };
+
+        let mut tx = TransactionBuilder::default();
+
+        let amount = self.wallet_client().config.fee_consensus.peg_out_abs + fees.amount().into();
+        let (mut keys, input) = self.mint_client().select_input(amount).await?;
+        tx.input(&mut keys, input);
+        tx.input(
+            &mut vec![self.peg_out_owner_key(recipient)],
+            Input::Wallet(WalletInput::Rbf(Rbf { fees, txid })),
+        );
+
+        self.submit_tx_with_change(tx, &mut rng).await
+    }
+
     pub async fn peg_out<R: RngCore + CryptoRng>(
         &self,
         peg_out: PegOut,
         mut rng: R,
     ) -> Result<OutPoint> {
+        let operation_id = rng.gen();
+        self.peg_out_with_id(operation_id, peg_out, rng).await
+    }
+
+    /// Like [`Self::peg_out`], but tracks the peg-out as operation
+    /// `operation_id` that can be queried with [`Self::get_operation`]
+    pub async fn peg_out_with_id<R: RngCore + CryptoRng>(
+        &self,
+        operation_id: OperationId,
+        peg_out: PegOut,
+        mut rng: R,
+    ) -> Result<OutPoint> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        self.create_operation(
+            &mut dbtx,
+            operation_id,
+            OperationKind::PegOut {
+                recipient: peg_out.recipient.clone(),
+                amount: peg_out.amount,
+            },
+        )
+        .await?;
+        dbtx.commit_tx().await;
+
         let mut tx = TransactionBuilder::default();
 
-        let funding_amount = self
-            .config
-            .as_ref()
-            .get_first_module_by_kind::<WalletClientConfig>("wallet")
-            .expect("missing wallet module config")
-            .1
-            .fee_consensus
-            .peg_out_abs
-            + (peg_out.amount + peg_out.fees.amount()).into();
-        let (mut keys, input) = self.mint_client().select_input(funding_amount).await?;
-        tx.input(&mut keys, input);
+        let funding_amount = self.peg_out_cost(&peg_out);
         let peg_out_idx = tx.output(Output::Wallet(WalletOutput::PegOut(peg_out)));
-
-        let fedimint_tx_id = self.submit_tx_with_change(tx, &mut rng).await?;
+        let result = async {
+            let (mut keys, input) = self.mint_client().select_input(funding_amount).await?;
+            tx.input(&mut keys, input);
+            self.submit_operation_tx(operation_id, tx, &mut rng).await
+        }
+        .await;
+        let fedimint_tx_id = self.finish_operation(operation_id, result).await?;
 
         Ok(OutPoint {
             txid: fedimint_tx_id,
@@ -582,28 +1947,9 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         amount: Amount,
         rng: R,
     ) -> Result<TieredMulti<SpendableNote>> {
-        let notes = self.mint_client().select_notes(amount).await?;
-        let mut dbtx = self.context.db.begin_transaction().await;
-
-        let final_notes = if notes.total_amount() == amount {
-            notes
-        } else {
-            let mut tx = TransactionBuilder::default();
-
-            let (mut keys, input) = MintClient::ecash_input(notes)?;
-            tx.input(&mut keys, input);
-            let txid = self.submit_tx_with_change(tx, rng).await?;
-            let outpoint = OutPoint { txid, out_idx: 0 };
-
-            self.mint_client()
-                .await_fetch_notes(&mut dbtx, &outpoint)
-                .await?;
-            self.mint_client().select_notes(amount).await?
-        };
-        if final_notes.total_amount() != amount {
-            return Err(ClientError::SpendReusedNote);
-        }
+        let final_notes = self.select_exact_notes(amount, rng).await?;
 
+        let mut dbtx = self.context.db.begin_transaction().await;
         for (amount, note) in final_notes.iter_items() {
             dbtx.remove_entry(&NoteKey {
                 amount,
@@ -616,6 +1962,37 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         Ok(final_notes)
     }
 
+    /// Selects notes of exactly `amount`, reissuing some of ours for change
+    /// first if we don't have them, without removing them from the database
+    async fn select_exact_notes<R: RngCore + CryptoRng>(
+        &self,
+        amount: Amount,
+        rng: R,
+    ) -> Result<TieredMulti<SpendableNote>> {
+        let notes = self.mint_client().select_notes(amount).await?;
+        if notes.total_amount() == amount {
+            return Ok(notes);
+        }
+
+        let mut tx = TransactionBuilder::default();
+        let (mut keys, input) = MintClient::ecash_input(notes)?;
+        tx.input(&mut keys, input);
+        let txid = self.submit_tx_with_change(tx, rng).await?;
+        let outpoint = OutPoint { txid, out_idx: 0 };
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        self.mint_client()
+            .await_fetch_notes(&mut dbtx, &outpoint)
+            .await?;
+        dbtx.commit_tx().await;
+
+        let notes = self.mint_client().select_notes(amount).await?;
+        if notes.total_amount() != amount {
+            return Err(ClientError::SpendReusedNote);
+        }
+        Ok(notes)
+    }
+
     /// For tests only: Select notes of a given amount, and then remint them,
     /// remove the amount of notes from the database and return it to the user.
     ///
@@ -661,10 +2038,135 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
     /// [`MintClientError::is_retryable`] to determine if the operation
     /// should be retried at a later time.
     pub async fn fetch_notes<'a>(&self, outpoint: OutPoint) -> Result<()> {
+        self.context
+            .db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    self.mint_client()
+                        .fetch_notes(dbtx, outpoint)
+                        .await
+                        .map_err(ClientError::from)
+                })
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Checks the federation's current config for deprecated note tiers and
+    /// reissues our notes of them into other tiers
+    ///
+    /// Should be called regularly, returns `None` if we have no notes in
+    /// deprecated tiers.
+    pub async fn refresh_deprecated_notes<R: RngCore + CryptoRng>(
+        &self,
+        rng: R,
+    ) -> Result<Option<NoteRefreshEvent>> {
+        let updated = self
+            .context
+            .api
+            .download_client_config(
+                &self.config.as_ref().federation_id,
+                self.context.module_gens.to_common(),
+            )
+            .await?;
+        let deprecation = self.mint_client().tier_deprecation_from_config(&updated)?;
+        self.reissue_deprecated_notes(deprecation, rng).await
+    }
+
+    /// Like [`Client::refresh_deprecated_notes`], but only if the last check
+    /// was at least [`NOTE_REFRESH_INTERVAL`] ago, so it can be called
+    /// whenever the client is used
+    pub async fn refresh_deprecated_notes_if_due<R: RngCore + CryptoRng>(
+        &self,
+        rng: R,
+    ) -> Result<Option<NoteRefreshEvent>> {
+        let now = fedimint_core::time::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("time to be after the unix epoch")
+            .as_secs();
+
         let mut dbtx = self.context.db.begin_transaction().await;
-        self.mint_client().fetch_notes(&mut dbtx, outpoint).await?;
+        let last_refresh = dbtx.get_value(&LastNoteRefreshKey).await;
+        if last_refresh.map_or(false, |last| {
+            now < last.saturating_add(NOTE_REFRESH_INTERVAL.as_secs())
+        }) {
+            return Ok(None);
+        }
+        // Recorded before the check, so an unreachable federation doesn't slow
+        // down every use of the client
+        dbtx.insert_entry(&LastNoteRefreshKey, &now).await;
         dbtx.commit_tx().await;
-        Ok(())
+
+        self.refresh_deprecated_notes(rng).await
+    }
+
+    /// Stops issuing notes in the tiers of `deprecation` and reissues our notes
+    /// of them into other tiers
+    ///
+    /// Returns `None` if we have no notes in the deprecated tiers.
+    pub async fn reissue_deprecated_notes<R: RngCore + CryptoRng>(
+        &self,
+        deprecation: TierDeprecation,
+        rng: R,
+    ) -> Result<Option<NoteRefreshEvent>> {
+        let mint = self.mint_client();
+        let mut dbtx = self.context.db.begin_transaction().await;
+        mint.set_tier_deprecation(&mut dbtx, &deprecation).await;
+        dbtx.commit_tx().await;
+
+        let notes: TieredMulti<SpendableNote> = mint
+            .notes()
+            .await
+            .into_iter()
+            .filter(|(amount, _)| deprecation.tiers.contains(amount))
+            .collect();
+        if notes.count_items() == 0 {
+            return Ok(None);
+        }
+
+        let amount = notes.total_amount();
+        let manual_action = |reason: String| {
+            warn!(
+                %amount,
+                deadline = ?deprecation.deadline,
+                "Notes in deprecated tiers need manual action: {reason}"
+            );
+            Some(NoteRefreshEvent::ManualActionRequired {
+                amount,
+                deadline: deprecation.deadline,
+                reason,
+            })
+        };
+
+        let now = fedimint_core::time::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("time to be after the unix epoch")
+            .as_secs();
+        if deprecation
+            .deadline
+            .map_or(false, |deadline| deadline <= now)
+        {
+            return Ok(manual_action("the deadline passed".to_string()));
+        }
+        // Change is made in the smallest tier, so there are no other tiers to
+        // reissue into
+        if mint
+            .config
+            .tbs_pks
+            .tiers()
+            .next()
+            .map_or(true, |smallest| deprecation.tiers.contains(smallest))
+        {
+            return Ok(manual_action("the smallest tier is deprecated".to_string()));
+        }
+
+        match self.reissue(notes, rng).await {
+            Ok(outpoint) => {
+                info!(%amount, "Reissued notes in deprecated tiers");
+                Ok(Some(NoteRefreshEvent::Reissued { amount, outpoint }))
+            }
+            Err(e) => Ok(manual_action(format!("reissuing failed: {e}"))),
+        }
     }
 
     /// Should be called after any transaction that might have failed in order
@@ -738,6 +2240,12 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
                 Err(error) => Either::Left(error.into()),
             });
 
+        // Ecash that never arrives, e.g. the change of a rejected transaction,
+        // would otherwise only show as a lower balance, the check warns about it
+        if let Err(e) = self.mint_client().check_issuance().await {
+            warn!(%e, "Failed to check the ecash issued to us");
+        }
+
         if errors.is_empty() {
             Ok(outpoints)
         } else {
@@ -764,30 +2272,117 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
             .fetch_epoch_history(epoch, epoch_pk, &self.context.decoders)
             .await?)
     }
-}
 
-impl Client<UserClientConfig> {
-    pub async fn fetch_registered_gateways(&self) -> Result<Vec<LightningGateway>> {
-        Ok(self.context.api.fetch_gateways().await?)
+    /// Returns a receipt proving that the federation accepted transaction
+    /// `txid`, optionally pointing at one of its outputs
+    ///
+    /// The guardians sign an epoch while processing the next one, so this
+    /// waits for the epoch after the transaction's.
+    pub async fn transaction_receipt(
+        &self,
+        txid: TransactionId,
+        out_point: Option<OutPoint>,
+    ) -> Result<PaymentReceipt> {
+        let epoch = match self.context.api.await_tx_outcome(&txid).await? {
+            TransactionStatus::Accepted { epoch, .. } => epoch,
+            TransactionStatus::Rejected(e) => return Err(ClientError::RejectedTransaction(e)),
+        };
+        self.context.api.await_epoch(epoch + 1).await?;
+
+        Ok(PaymentReceipt {
+            federation_id: self.config.as_ref().federation_id.clone(),
+            txid,
+            out_point,
+            epoch,
+        })
+    }
+
+    /// Returns a receipt for the transaction of a submitted operation, pointing
+    /// at the output the operation created
+    pub async fn operation_receipt(&self, operation_id: OperationId) -> Result<PaymentReceipt> {
+        let operation = self
+            .get_operation(operation_id)
+            .await
+            .ok_or(ClientError::UnknownOperation)?;
+        let (OperationState::Submitted { txid } | OperationState::Completed { txid }) =
+            operation.state
+        else {
+            return Err(ClientError::NoOperationReceipt(format!(
+                "the operation is {:?}",
+                operation.state
+            )));
+        };
+
+        let receipt = self.transaction_receipt(txid, None).await?;
+        let epoch = self
+            .fetch_epoch_history(receipt.epoch, self.config.as_ref().epoch_pk)
+            .await?;
+        let transaction = verify_receipt_epoch(&receipt, self.config.as_ref(), epoch)
+            .map_err(|e| ClientError::NoOperationReceipt(e.to_string()))?;
+        let out_idx = operation_output(&operation.kind, &transaction).ok_or_else(|| {
+            ClientError::NoOperationReceipt("the transaction has no output of it".to_string())
+        })?;
+        Ok(PaymentReceipt {
+            out_point: Some(OutPoint { txid, out_idx }),
+            ..receipt
+        })
     }
+}
 
+impl Client<UserClientConfig> {
+    /// Returns the gateway payments are routed through unless another one is
+    /// chosen for them, switching to the first registered one if there's none
+    /// or it stopped serving the federation
     pub async fn fetch_active_gateway(&self) -> Result<LightningGateway> {
-        // FIXME: forgetting about old gws might not always be ideal. We assume that the
-        // gateway stays the same except for route hints for now.
-        if let Some(gateway) = self
+        let Some(cached) = self
             .context
             .db
             .begin_transaction()
             .await
             .get_value(&LightningGatewayKey)
             .await
-            .filter(|gw| gw.valid_until > fedimint_core::time::now())
-        {
-            return Ok(gateway);
+        else {
+            return self.switch_active_gateway(None).await;
+        };
+
+        // The gateway may have registered again since, e.g. with other fees or
+        // another key its contracts have to be addressed to
+        match self.fetch_gateway(cached.node_pub_key).await {
+            Ok(gateway) => {
+                if gateway != cached {
+                    let mut dbtx = self.context.db.begin_transaction().await;
+                    dbtx.insert_entry(&LightningGatewayKey, &gateway).await;
+                    dbtx.commit_tx().await;
+                }
+                Ok(gateway)
+            }
+            Err(ClientError::GatewayNotFound) => self.switch_active_gateway(None).await,
+            Err(e) if cached.valid_until > fedimint_core::time::now() => {
+                debug!("Could not refresh the active gateway, using the cached one: {e}");
+                Ok(cached)
+            }
+            Err(e) => Err(e),
         }
+    }
 
-        self.switch_active_gateway(None).await
+    /// Returns the registration of the gateway of the lightning node
+    /// `node_pub_key`, e.g. to route a payment through it rather than through
+    /// the active gateway
+    pub async fn fetch_gateway(
+        &self,
+        node_pub_key: secp256k1::PublicKey,
+    ) -> Result<LightningGateway> {
+        let now = fedimint_core::time::now();
+        self.fetch_registered_gateways()
+            .await?
+            .into_iter()
+            .find(|gateway| gateway.node_pub_key == node_pub_key && gateway.valid_until > now)
+            .ok_or_else(|| {
+                debug!("Could not find gateway with public key {:?}", node_pub_key);
+                ClientError::GatewayNotFound
+            })
     }
+
     /// Switches the clients active gateway to a registered gateway with the
     /// given node pubkey. If no pubkey is given (node_pub_key == None) the
     /// first available registered gateway is activated. This behavior is
@@ -803,117 +2398,65 @@ impl Client<UserClientConfig> {
             return Err(ClientError::NoGateways);
         };
         let gateway = match node_pub_key {
-            // If a pubkey was provided, try to select and activate a gateway with that pubkey.
-            Some(pub_key) => gateways
-                .into_iter()
-                .find(|g| g.node_pub_key == pub_key)
-                .ok_or_else(|| {
-                    debug!("Could not find gateway with public key {:?}", pub_key);
-                    ClientError::GatewayNotFound
-                })?,
-            // Otherwise (no pubkey provided), select and activate the first registered gateway.
-            None => {
-                debug!("No public key for gateway supplied, using first registered one");
-                gateways[0].clone()
-            }
-        };
-        let mut dbtx = self.context.db.begin_transaction().await;
-        dbtx.insert_entry(&LightningGatewayKey, &gateway).await;
-        dbtx.commit_tx().await;
-        Ok(gateway)
-    }
-
-    pub async fn fund_outgoing_ln_contract<R: RngCore + CryptoRng>(
-        &self,
-        invoice: Invoice,
-        mut rng: R,
-    ) -> Result<(ContractId, OutPoint)> {
-        let gateway = self.fetch_active_gateway().await?;
-        let mut dbtx = self.context.db.begin_transaction().await;
-        let mut tx = TransactionBuilder::default();
-
-        let consensus_height = self.context.api.fetch_consensus_block_height().await?;
-        let absolute_timelock = consensus_height + OUTGOING_LN_CONTRACT_TIMELOCK;
-
-        let contract = self
-            .ln_client()
-            .create_outgoing_output(
-                &mut dbtx,
-                invoice,
-                &gateway,
-                absolute_timelock as u32,
-                &mut rng,
-            )
-            .await?;
-
-        dbtx.commit_tx().await;
-
-        let (contract_id, amount) = match &contract {
-            LightningOutput::Contract(c) => {
-                let contract_id = c.contract.contract_id();
-                let amount = c.amount;
-                (contract_id, amount)
+            // If a pubkey was provided, try to select and activate a gateway with that pubkey.
+            Some(pub_key) => self.fetch_gateway(pub_key).await?,
+            // Otherwise (no pubkey provided), select and activate the first registered gateway.
+            None => {
+                debug!("No public key for gateway supplied, using first registered one");
+                gateways[0].clone()
             }
-            LightningOutput::Offer(_) | LightningOutput::CancelOutgoing { .. } => {
-                panic!()
-            } // FIXME: impl TryFrom
         };
-
-        let (mut keys, input) = self.mint_client().select_input(amount).await?;
-        tx.input(&mut keys, input);
-        tx.output(Output::LN(contract));
-        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
-        let outpoint = OutPoint { txid, out_idx: 0 };
-
-        debug!("Funded outgoing contract {} in {}", contract_id, outpoint);
-        Ok((contract_id, outpoint))
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&LightningGatewayKey, &gateway).await;
+        dbtx.commit_tx().await;
+        Ok(gateway)
     }
 
-    /// Claims a refund for an expired or cancelled outgoing contract
-    ///
-    /// This can be necessary when the Lightning gateway cannot route the
-    /// payment, is malicious or offline. The function returns the out point
-    /// of the e-cash output generated as change.
-    pub async fn try_refund_outgoing_contract(
+    /// Returns the active gateway if `can_route` a payment of `amount` over
+    /// it according to the liquidity it reported, otherwise switches to the
+    /// first registered gateway that can
+    async fn fetch_gateway_routing(
         &self,
-        contract_id: ContractId,
-        rng: impl RngCore + CryptoRng,
-    ) -> Result<OutPoint> {
-        let contract_data = self
-            .context
-            .db
-            .begin_transaction()
-            .await
-            .get_value(&OutgoingPaymentKey(contract_id))
-            .await
-            .ok_or(ClientError::RefundUnknownOutgoingContract)?;
-
-        let mut tx = TransactionBuilder::default();
-        let (refund_key, refund_input) = self
-            .ln_client()
-            .create_refund_outgoing_contract_input(&contract_data);
-        tx.input(&mut vec![*refund_key], Input::LN(refund_input));
-        let txid = self.submit_tx_with_change(tx, rng).await?;
+        amount: Amount,
+        can_route: fn(&LightningGateway, Amount) -> bool,
+    ) -> Result<LightningGateway> {
+        let active_gateway = self.fetch_active_gateway().await?;
+        if can_route(&active_gateway, amount) {
+            return Ok(active_gateway);
+        }
 
+        let now = fedimint_core::time::now();
+        let gateway = self
+            .fetch_registered_gateways()
+            .await?
+            .into_iter()
+            .find(|gw| gw.valid_until > now && can_route(gw, amount))
+            .ok_or(ClientError::NoGatewayWithLiquidity(amount))?;
+        debug!(
+            "Active gateway can't route {}, switching to {}",
+            amount, gateway.node_pub_key
+        );
         let mut dbtx = self.context.db.begin_transaction().await;
-        dbtx.remove_entry(&OutgoingPaymentKey(contract_id))
-            .await
-            .ok_or(ClientError::DeleteUnknownOutgoingContract)?;
+        dbtx.insert_entry(&LightningGatewayKey, &gateway).await;
         dbtx.commit_tx().await;
-
-        Ok(OutPoint { txid, out_idx: 0 })
+        Ok(gateway)
     }
 
-    pub async fn await_outgoing_contract_acceptance(&self, outpoint: OutPoint) -> Result<()> {
-        self.context
-            .api
-            .await_output_outcome::<LightningOutputOutcome>(
-                outpoint,
-                Duration::from_secs(30),
-                &self.context.decoders,
-            )
+    pub async fn fund_outgoing_ln_contract<R: RngCore + CryptoRng>(
+        &self,
+        invoice: Invoice,
+        rng: R,
+    ) -> Result<(ContractId, OutPoint)> {
+        let amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .ok_or(ClientError::InvoiceMissingAmount)?,
+        );
+        let gateway = self
+            .fetch_gateway_routing(amount, LightningGateway::can_pay)
             .await?;
-        Ok(())
+        self.fund_outgoing_ln_contract_via(invoice, &gateway, rng)
+            .await
     }
 
     /// Waits for the federation to sign an ecash note.
@@ -946,37 +2489,280 @@ impl Client<UserClientConfig> {
             .map_err(|_| ClientError::Timeout)?
     }
 
+    /// Previews receiving `net_amount` of ecash through the gateway
+    /// [`Self::generate_confirmed_invoice`] routes invoices of it through,
+    /// including the amount the invoice has to request
+    pub async fn preview_receive(&self, net_amount: Amount) -> Result<ReceivePreview> {
+        let invoice_amount = net_amount + self.ln_client().config.fee_consensus.contract_input;
+        let gateway = self
+            .fetch_gateway_routing(invoice_amount, LightningGateway::can_receive)
+            .await?;
+        Ok(self.preview_receive_via(&gateway, net_amount))
+    }
+
+    /// Like [`Self::preview_receive`], but for invoices routed through
+    /// `gateway` instead of the active gateway
+    pub fn preview_receive_via(
+        &self,
+        gateway: &LightningGateway,
+        net_amount: Amount,
+    ) -> ReceivePreview {
+        let federation_fee = self.ln_client().config.fee_consensus.contract_input;
+        let invoice_amount = net_amount + federation_fee;
+        let gateway_fee = gateway.fees.amount(invoice_amount);
+        ReceivePreview {
+            gateway: gateway.node_pub_key,
+            invoice_amount,
+            gateway_fee,
+            payer_amount: invoice_amount + gateway_fee,
+            federation_fee,
+            net_amount,
+        }
+    }
+
+    /// Fee our offers pay the gateway funding their incoming contracts, none
+    /// unless it was set
+    pub async fn incoming_gateway_fee(&self) -> GatewayFee {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&IncomingGatewayFeeKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Fee our new offers pay the gateway, none while the federation doesn't
+    /// accept gateway fees yet
+    ///
+    /// Guardians too old to tell their version don't accept them either.
+    async fn offer_gateway_fee(&self) -> GatewayFee {
+        let fee = self.incoming_gateway_fee().await;
+        if fee == GatewayFee::default() {
+            return fee;
+        }
+        let active = self
+            .context
+            .api
+            .fetch_ln_consensus_version()
+            .await
+            .map_or(false, |version| version >= GATEWAY_FEE_VERSION);
+        if !active {
+            debug!("The federation doesn't accept gateway fees yet, offering none");
+            return GatewayFee::default();
+        }
+        fee
+    }
+
+    /// Sets the fee the offers of invoices created from now on pay the gateway,
+    /// which it claims from the incoming contract besides buying the preimage
+    pub async fn set_incoming_gateway_fee(&self, fee: GatewayFee) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&IncomingGatewayFeeKey, &fee).await;
+        dbtx.commit_tx().await;
+    }
+
     pub async fn generate_confirmed_invoice<R: RngCore + CryptoRng>(
         &self,
         amount: Amount,
         description: String,
+        rng: R,
+        expiry_time: Option<u64>,
+    ) -> Result<ConfirmedInvoice> {
+        let gateway = self
+            .fetch_gateway_routing(amount, LightningGateway::can_receive)
+            .await?;
+        self.generate_confirmed_invoice_via(&gateway, amount, description, rng, expiry_time)
+            .await
+    }
+
+    /// Like [`Self::generate_confirmed_invoice`], but the invoice is routed
+    /// through `gateway` instead of the active gateway
+    pub async fn generate_confirmed_invoice_via<R: RngCore + CryptoRng>(
+        &self,
+        gateway: &LightningGateway,
+        amount: Amount,
+        description: String,
         mut rng: R,
         expiry_time: Option<u64>,
     ) -> Result<ConfirmedInvoice> {
         let (txid, invoice, payment_keypair) = self
-            .generate_unconfirmed_invoice_and_submit(amount, description, &mut rng, expiry_time)
+            .submit_invoice_offer(gateway, amount, description, &mut rng, expiry_time)
             .await?;
 
         self.await_invoice_confirmation(txid, invoice, payment_keypair)
             .await
     }
+
+    /// Creates the invoices of a batch of point-of-sale orders, announcing
+    /// their offers in as few transactions as the federation's limit on
+    /// outputs allows
+    ///
+    /// All invoices are routed through the gateway that can receive the
+    /// largest order. Use [`Self::await_order_payments`] to learn which orders
+    /// get paid.
+    ///
+    /// The invoice of every order is saved once the federation accepted its
+    /// offer, see [`Self::order_invoice`]. If creating a batch fails part way,
+    /// creating it again returns the invoices of the orders that already got
+    /// one and only creates the missing ones.
+    pub async fn create_invoices<R: RngCore + CryptoRng>(
+        &self,
+        batch: Vec<InvoiceRequest>,
+        mut rng: R,
+    ) -> Result<Vec<OrderInvoice>> {
+        let mut pending = vec![];
+        for order in &batch {
+            if self.order_invoice(&order.order_id).await.is_none() {
+                pending.push(order.clone());
+            }
+        }
+        self.create_order_invoices(&pending, &mut rng).await?;
+
+        let mut invoices = Vec::with_capacity(batch.len());
+        for order in batch {
+            let invoice = self
+                .order_invoice(&order.order_id)
+                .await
+                .expect("every order of the batch has an invoice");
+            invoices.push(invoice);
+        }
+        Ok(invoices)
+    }
+
+    /// Invoice of a point-of-sale order created by [`Self::create_invoices`]
+    pub async fn order_invoice(&self, order_id: &str) -> Option<OrderInvoice> {
+        let contract_id = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&OrderInvoiceKey(order_id.to_string()))
+            .await?;
+        let invoice = self
+            .ln_client()
+            .get_confirmed_invoice(contract_id)
+            .await
+            .ok()?;
+        Some(OrderInvoice {
+            order_id: order_id.to_string(),
+            invoice,
+        })
+    }
+
+    /// Announces the offers of the invoices of `orders` and saves every
+    /// invoice under its order id once its offer was accepted
+    async fn create_order_invoices<R: RngCore + CryptoRng>(
+        &self,
+        orders: &[InvoiceRequest],
+        mut rng: R,
+    ) -> Result<()> {
+        let Some(largest) = orders.iter().map(|order| order.amount).max() else {
+            return Ok(());
+        };
+        let gateway = self
+            .fetch_gateway_routing(largest, LightningGateway::can_receive)
+            .await?;
+
+        // one output of every transaction is left for the change
+        let max_offers = self
+            .config
+            .as_ref()
+            .limits
+            .max_transaction_outputs
+            .saturating_sub(1)
+            .max(1);
+        let gateway_fee = self.offer_gateway_fee().await;
+        for orders in orders.chunks(max_offers as usize) {
+            let mut tx = TransactionBuilder::default();
+            let mut offers = Vec::with_capacity(orders.len());
+            for order in orders {
+                let payment_keypair = KeyPair::new(&self.context.secp, &mut rng);
+                let (invoice, ln_output) = self.generate_unconfirmed_invoice_via(
+                    &gateway,
+                    order.amount,
+                    order.description.clone(),
+                    payment_keypair,
+                    &mut rng,
+                    order.expiry_time,
+                    gateway_fee.amount(order.amount),
+                )?;
+                let out_idx = tx.output(ln_output);
+                offers.push((order.order_id.clone(), out_idx, invoice, payment_keypair));
+            }
+
+            let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+            for (order_id, out_idx, invoice, payment_keypair) in offers {
+                let invoice = self
+                    .await_offer_confirmation(OutPoint { txid, out_idx }, invoice, payment_keypair)
+                    .await?;
+                let mut dbtx = self.context.db.begin_transaction().await;
+                dbtx.insert_entry(&OrderInvoiceKey(order_id), &invoice.contract_id())
+                    .await;
+                dbtx.commit_tx().await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stream of the payments to the invoices of `orders`, in the order they
+    /// are paid
+    ///
+    /// Every paid invoice is claimed right away. Orders that are never paid
+    /// never show up, so the stream only ends once all orders were paid.
+    /// Every claim gets its own rng seeded from `rng`.
+    pub fn await_order_payments<'a, R: RngCore + CryptoRng>(
+        &'a self,
+        orders: &[OrderInvoice],
+        mut rng: R,
+    ) -> impl Stream<Item = OrderPayment> + 'a {
+        orders
+            .iter()
+            .map(|order| {
+                let order_id = order.order_id.clone();
+                let contract_id = order.invoice.contract_id();
+                let order_rng = StdRng::from_rng(&mut rng).expect("rng failed");
+                async move {
+                    let outcome = self.await_incoming_payment(contract_id, order_rng).await;
+                    OrderPayment { order_id, outcome }
+                }
+            })
+            .collect::<FuturesUnordered<_>>()
+    }
+
     pub async fn generate_unconfirmed_invoice_and_submit<R: RngCore + CryptoRng>(
         &self,
         amount: Amount,
         description: String,
+        rng: R,
+        expiry_time: Option<u64>,
+    ) -> Result<(TransactionId, Invoice, KeyPair)> {
+        let gateway = self
+            .fetch_gateway_routing(amount, LightningGateway::can_receive)
+            .await?;
+        self.submit_invoice_offer(&gateway, amount, description, rng, expiry_time)
+            .await
+    }
+
+    async fn submit_invoice_offer<R: RngCore + CryptoRng>(
+        &self,
+        gateway: &LightningGateway,
+        amount: Amount,
+        description: String,
         mut rng: R,
         expiry_time: Option<u64>,
     ) -> Result<(TransactionId, Invoice, KeyPair)> {
         let payment_keypair = KeyPair::new(&self.context.secp, &mut rng);
-        let (invoice, ln_output) = self
-            .generate_unconfirmed_invoice(
-                amount,
-                description,
-                payment_keypair,
-                &mut rng,
-                expiry_time,
-            )
-            .await?;
+        let gateway_fee = self.offer_gateway_fee().await.amount(amount);
+        let (invoice, ln_output) = self.generate_unconfirmed_invoice_via(
+            gateway,
+            amount,
+            description,
+            payment_keypair,
+            &mut rng,
+            expiry_time,
+            gateway_fee,
+        )?;
 
         // There is no input here because this is just an announcement
         let mut tx = TransactionBuilder::default();
@@ -991,101 +2777,70 @@ impl Client<UserClientConfig> {
         amount: Amount,
         description: String,
         payment_keypair: KeyPair,
+        rng: R,
+        expiry_time: Option<u64>,
+    ) -> Result<(Invoice, Output)> {
+        let gateway = self
+            .fetch_gateway_routing(amount, LightningGateway::can_receive)
+            .await?;
+        let gateway_fee = self.offer_gateway_fee().await.amount(amount);
+        self.generate_unconfirmed_invoice_via(
+            &gateway,
+            amount,
+            description,
+            payment_keypair,
+            rng,
+            expiry_time,
+            gateway_fee,
+        )
+    }
+
+    /// Creates an invoice routed through `gateway` and the output announcing
+    /// its offer, without submitting it
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate_unconfirmed_invoice_via<R: RngCore + CryptoRng>(
+        &self,
+        gateway: &LightningGateway,
+        amount: Amount,
+        description: String,
+        payment_keypair: KeyPair,
         mut rng: R,
         expiry_time: Option<u64>,
+        gateway_fee: Amount,
     ) -> Result<(Invoice, Output)> {
-        let gateway = self.fetch_active_gateway().await?;
         let raw_payment_secret: [u8; 32] = payment_keypair.x_only_public_key().0.serialize();
-        let payment_hash = bitcoin::secp256k1::hashes::sha256::Hash::hash(&raw_payment_secret);
-        let payment_secret = PaymentSecret(raw_payment_secret);
-
-        // Temporary lightning node pubkey
-        let (node_secret_key, node_public_key) = self.context.secp.generate_keypair(&mut rng);
-
-        // Route hint instructing payer how to route to gateway
-        let route_hint_last_hop = RouteHintHop {
-            src_node_id: gateway.node_pub_key,
-            short_channel_id: gateway.mint_channel_id,
-            fees: RoutingFees {
-                base_msat: 0,
-                proportional_millionths: 0,
-            },
-            cltv_expiry_delta: 30,
-            htlc_minimum_msat: None,
-            htlc_maximum_msat: None,
-        };
-        let route_hints = if gateway.route_hints.is_empty() {
-            vec![RouteHint(vec![route_hint_last_hop])]
-        } else {
-            gateway
-                .route_hints
-                .iter()
-                .map(|rh| {
-                    RouteHint(
-                        rh.to_ldk_route_hint()
-                            .0
-                            .iter()
-                            .cloned()
-                            .chain(once(route_hint_last_hop.clone()))
-                            .collect(),
-                    )
-                })
-                .collect()
-        };
-
-        let duration_since_epoch = fedimint_core::time::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
-
-        let mut invoice_builder = InvoiceBuilder::new(network_to_currency(
-            self.config
-                .0
-                .get_first_module_by_kind::<WalletClientConfig>("wallet")
-                .expect("must have wallet config available")
-                .1
-                .network,
-        ))
-        .amount_milli_satoshis(amount.msats)
-        .description(description)
-        .payment_hash(payment_hash)
-        .payment_secret(payment_secret)
-        .duration_since_epoch(duration_since_epoch)
-        .min_final_cltv_expiry(18)
-        .payee_pub_key(node_public_key)
-        .expiry_time(Duration::from_secs(
-            expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME),
-        ));
-
-        for rh in route_hints {
-            invoice_builder = invoice_builder.private_route(rh);
-        }
-
-        let invoice = invoice_builder.build_signed(|hash| {
-            self.context
-                .secp
-                .sign_ecdsa_recoverable(hash, &node_secret_key)
-        })?;
-
-        let offer_output = self.ln_client().create_offer_output(
+        self.create_invoice_and_offer(
+            gateway,
             amount,
-            payment_hash,
-            Preimage(raw_payment_secret),
+            PaymentDescription::Direct(description),
+            raw_payment_secret,
+            &mut rng,
             expiry_time,
-        );
-        let ln_output = Output::LN(offer_output);
+            gateway_fee,
+        )
+    }
 
-        Ok((invoice, ln_output))
+    pub async fn await_invoice_confirmation(
+        &self,
+        txid: TransactionId,
+        invoice: Invoice,
+        payment_keypair: KeyPair,
+    ) -> Result<ConfirmedInvoice> {
+        let outpoint = OutPoint { txid, out_idx: 0 };
+        self.await_offer_confirmation(outpoint, invoice, payment_keypair)
+            .await
     }
 
-    pub async fn await_invoice_confirmation(
+    /// Waits for the federation to accept the offer of an invoice announced
+    /// in `outpoint` and saves the invoice
+    async fn await_offer_confirmation(
         &self,
-        txid: TransactionId,
+        outpoint: OutPoint,
         invoice: Invoice,
         payment_keypair: KeyPair,
     ) -> Result<ConfirmedInvoice> {
         // Await acceptance by the federation
         let timeout = std::time::Duration::from_secs(15);
-        let outpoint = OutPoint { txid, out_idx: 0 };
         self.context
             .api
             .await_output_outcome::<LightningOutputOutcome>(
@@ -1105,20 +2860,17 @@ impl Client<UserClientConfig> {
     pub async fn claim_incoming_contract(
         &self,
         contract_id: ContractId,
-        mut rng: impl RngCore + CryptoRng,
+        rng: impl RngCore + CryptoRng,
     ) -> Result<OutPoint> {
-        // Lookup contract and "confirmed invoice"
-        let contract = self.ln_client().get_incoming_contract(contract_id).await?;
+        // Lookup "confirmed invoice"
         let ci = self.ln_client().get_confirmed_invoice(contract_id).await?;
-
-        // Input claims this contract
-        let mut tx = TransactionBuilder::default();
-        tx.input(&mut vec![ci.keypair], Input::LN(contract.claim()));
-        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+        let outpoint = self
+            .claim_incoming_contract_with_key(contract_id, ci.keypair, rng)
+            .await?;
 
         // TODO: Update database if invoice is paid or expired
 
-        Ok(OutPoint { txid, out_idx: 0 })
+        Ok(outpoint)
     }
 
     /// Notify gateway that we've escrowed notes they can claim by routing our
@@ -1128,43 +2880,416 @@ impl Client<UserClientConfig> {
         contract_id: ContractId,
         rng: impl RngCore + CryptoRng,
     ) -> Result<()> {
-        let gateway = self.fetch_active_gateway().await?;
+        let gateway = self.fetch_contract_gateway(contract_id).await?;
+        self.await_outgoing_contract_execution_via(contract_id, &gateway, rng)
+            .await
+    }
 
-        let payload = PayInvoicePayload::new(self.config.0.federation_id.clone(), contract_id);
+    /// Returns the registered gateway the outgoing contract `contract_id` is
+    /// addressed to, which needn't be the active gateway
+    pub async fn fetch_contract_gateway(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<LightningGateway> {
+        let gateway_key = self
+            .ln_client()
+            .get_outgoing_contract(contract_id)
+            .await
+            .map_err(ClientError::LnClientError)?
+            .contract
+            .gateway_key;
 
-        let future = reqwest::Client::new()
-            .post(
-                gateway
-                    .api
-                    .join("pay_invoice")
-                    .expect("'pay_invoice' contains no invalid characters for a URL")
-                    .as_str(),
-            )
-            .json(&payload)
-            .send();
-        let result = fedimint_core::task::timeout(Duration::from_secs(120), future)
+        let now = fedimint_core::time::now();
+        self.fetch_registered_gateways()
+            .await?
+            .into_iter()
+            .find(|gateway| gateway.mint_pub_key == gateway_key && gateway.valid_until > now)
+            .ok_or(ClientError::GatewayNotFound)
+    }
+
+    /// Waits for the federation to accept or reject a transaction we
+    /// submitted and fetches the notes it issued to us
+    pub async fn await_transaction_notes(&self, txid: TransactionId) -> Result<Vec<OutPoint>> {
+        if let TransactionStatus::Rejected(e) = self.context.api.await_tx_outcome(&txid).await? {
+            return Err(ClientError::RejectedTransaction(e));
+        }
+
+        let mut issuance = vec![];
+        for (outpoint, _) in self.list_active_issuances().await {
+            if outpoint.txid != txid {
+                continue;
+            }
+            self.await_outpoint_outcome(outpoint).await?;
+            self.fetch_notes(outpoint).await?;
+            issuance.push(outpoint);
+        }
+        Ok(issuance)
+    }
+
+    /// Waits until a gateway funded the incoming contract of one of our
+    /// invoices, checking again after every epoch of the federation
+    pub async fn await_incoming_contract(
+        &self,
+        contract_id: ContractId,
+    ) -> Result<IncomingContractAccount> {
+        loop {
+            // fetched first, so we don't miss the epoch funding the contract
+            let epoch = self.context.api.fetch_epoch_count().await?;
+            match self.ln_client().get_incoming_contract(contract_id).await {
+                Ok(contract) => return Ok(contract),
+                Err(LnClientError::WrongAccountType) => return Err(ClientError::WrongContractType),
+                Err(e) => debug!(%contract_id, %e, "Incoming contract isn't funded yet"),
+            }
+            self.context.api.await_epoch(epoch).await?;
+        }
+    }
+
+    /// Waits until a gateway funded the incoming contract of one of our
+    /// invoices, then claims it and fetches the issued notes
+    ///
+    /// The federation rejects funding that doesn't match the invoice's amount,
+    /// so the invoice stays payable until a gateway funds it correctly.
+    pub async fn await_incoming_payment(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<IncomingPaymentOutcome> {
+        let contract = self.await_incoming_contract(contract_id).await?;
+        let outpoint = self.claim_incoming_contract(contract_id, rng).await?;
+        let issuance = self.await_transaction_notes(outpoint.txid).await?;
+        Ok(IncomingPaymentOutcome {
+            amount: contract.amount,
+            issuance,
+        })
+    }
+
+    /// Waits until the gateway either claimed our outgoing contract or it can
+    /// be refunded, in which case we claim the refund
+    pub async fn await_outgoing_payment(
+        &self,
+        contract_id: ContractId,
+        mut rng: impl RngCore + CryptoRng,
+    ) -> Result<OutgoingPaymentOutcome> {
+        loop {
+            let epoch = self.context.api.fetch_epoch_count().await?;
+            let contract = self.ln_client().get_outgoing_contract(contract_id).await?;
+            let payment = self
+                .context
+                .db
+                .begin_transaction()
+                .await
+                .get_value(&OutgoingPaymentKey(contract_id))
+                .await;
+
+            if contract.amount == Amount::ZERO {
+                // without our payment the contract was already refunded to us
+                if payment.is_none() {
+                    return Err(ClientError::RefundedFailedPayment);
+                }
+                let mut dbtx = self.context.db.begin_transaction().await;
+                dbtx.remove_entry(&OutgoingPaymentKey(contract_id)).await;
+                dbtx.commit_tx().await;
+                return Ok(OutgoingPaymentOutcome::Paid);
+            }
+
+            if payment.is_some()
+                && self
+                    .ln_client()
+                    .is_outgoing_contract_refundable(contract_id)
+                    .await?
+            {
+                let outpoint = self
+                    .try_refund_outgoing_contract(contract_id, &mut rng)
+                    .await?;
+                self.await_transaction_notes(outpoint.txid).await?;
+                return Ok(OutgoingPaymentOutcome::Refunded(outpoint));
+            }
+
+            self.context.api.await_epoch(epoch).await?;
+        }
+    }
+
+    /// Creates a sub-account with its own notes, whose spending is limited by
+    /// `limit`
+    pub async fn create_sub_account(
+        &self,
+        name: String,
+        limit: Option<SpendLimit>,
+    ) -> Result<SubAccountId> {
+        let name = &name;
+        self.context
+            .db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    let id = match dbtx
+                        .find_by_prefix(&SubAccountKeyPrefix)
+                        .await
+                        .map(|(SubAccountKey(id), _)| id)
+                        .collect::<Vec<_>>()
+                        .await
+                        .into_iter()
+                        .max()
+                    {
+                        None => SubAccountId(0),
+                        Some(id) if id < SubAccountId::MAX => SubAccountId(id.0 + 1),
+                        Some(_) => return Err(ClientError::TooManySubAccounts),
+                    };
+                    dbtx.insert_new_entry(
+                        &SubAccountKey(id),
+                        &SubAccount::new(name.clone(), limit),
+                    )
+                    .await;
+                    Ok(id)
+                })
+            })
             .await
-            .map_err(|_| ClientError::OutgoingPaymentTimeout)?
-            .map_err(ClientError::HttpError);
+            .map_err(ClientError::from)
+    }
 
-        match result {
-            Ok(response) => {
-                if response.status().is_success() {
-                    return Ok(());
+    pub async fn list_sub_accounts(&self) -> Vec<(SubAccountId, SubAccount)> {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&SubAccountKeyPrefix)
+            .await
+            .map(|(SubAccountKey(id), account)| (id, account))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .sorted_by_key(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Replaces the spending limit of a sub-account, spends of the current
+    /// period count towards the new limit
+    pub async fn set_sub_account_limit(
+        &self,
+        id: SubAccountId,
+        limit: Option<SpendLimit>,
+    ) -> Result<()> {
+        self.context
+            .db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    let mut account = dbtx
+                        .get_value(&SubAccountKey(id))
+                        .await
+                        .ok_or(ClientError::UnknownSubAccount(id))?;
+                    if limit.is_none() {
+                        account.spends.clear();
+                    }
+                    account.limit = limit;
+                    dbtx.insert_entry(&SubAccountKey(id), &account).await;
+                    Ok::<_, ClientError>(())
+                })
+            })
+            .await
+            .map_err(ClientError::from)
+    }
+
+    /// Returns a handle to spend from and receive into a sub-account, which
+    /// uses this client's config and connections
+    pub async fn sub_account(&self, id: SubAccountId) -> Result<SubAccountClient> {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&SubAccountKey(id))
+            .await
+            .ok_or(ClientError::UnknownSubAccount(id))?;
+        self.sub_account_client(id).await
+    }
+
+    /// Like [`Self::sub_account`], also for sub-accounts missing from the
+    /// database
+    async fn sub_account_client(&self, id: SubAccountId) -> Result<SubAccountClient> {
+        let db = self.context.db.new_isolated(id.db_instance_id());
+        crate::db::migrate_client_database(&db)
+            .await
+            .map_err(ClientError::DatabaseMigration)?;
+        let client = Client {
+            config: self.config.clone(),
+            context: Arc::new(ClientContext {
+                decoders: self.context.decoders.clone(),
+                module_gens: self.context.module_gens.clone(),
+                db,
+                api: self.context.api.clone(),
+                epoch_archives: self.context.epoch_archives.clone(),
+                secp: self.context.secp.clone(),
+            }),
+            root_secret: id.root_secret(&self.root_secret),
+        };
+        Ok(SubAccountClient::new(id, self.context.db.clone(), client))
+    }
+
+    /// Moves `amount` of this client's ecash into a sub-account, which doesn't
+    /// count towards its limit
+    ///
+    /// Returns the out point of the sub-account's reissuance, see
+    /// [`Client::fetch_all_notes`].
+    pub async fn fund_sub_account<R: RngCore + CryptoRng>(
+        &self,
+        id: SubAccountId,
+        amount: Amount,
+        mut rng: R,
+    ) -> Result<OutPoint> {
+        let sub_account = self.sub_account(id).await?;
+        let notes = self.select_exact_notes(amount, &mut rng).await?;
+        self.move_notes(&notes, None, Some(id)).await?;
+        sub_account.reissue(notes, rng).await
+    }
+
+    /// Moves `amount` of a sub-account's ecash back into this client, which
+    /// doesn't count towards the sub-account's limit
+    pub async fn withdraw_from_sub_account<R: RngCore + CryptoRng>(
+        &self,
+        id: SubAccountId,
+        amount: Amount,
+        mut rng: R,
+    ) -> Result<OutPoint> {
+        let sub_account = self.sub_account(id).await?;
+        let notes = sub_account
+            .unlimited()
+            .select_exact_notes(amount, &mut rng)
+            .await?;
+        self.move_notes(&notes, Some(id), None).await?;
+        self.reissue(notes, rng).await
+    }
+
+    /// Moves `notes` between this client and a sub-account, `None` being this
+    /// client, in a single transaction of the database they share
+    ///
+    /// A crash before the reissuance that follows leaves the notes with their
+    /// new owner, whereas spending and reissuing them separately would lose
+    /// them.
+    async fn move_notes(
+        &self,
+        notes: &TieredMulti<SpendableNote>,
+        from: Option<SubAccountId>,
+        to: Option<SubAccountId>,
+    ) -> Result<()> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        for (amount, note) in notes.iter_items() {
+            let key = NoteKey {
+                amount,
+                nonce: note.note.0,
+            };
+            let removed = match from {
+                Some(id) => {
+                    dbtx.with_module_prefix(id.db_instance_id())
+                        .remove_entry(&key)
+                        .await
+                }
+                None => dbtx.remove_entry(&key).await,
+            };
+            // spent concurrently since we selected it
+            if removed.is_none() {
+                return Err(ClientError::SpendReusedNote);
+            }
+            match to {
+                Some(id) => {
+                    dbtx.with_module_prefix(id.db_instance_id())
+                        .insert_entry(&key, note)
+                        .await
                 }
+                None => dbtx.insert_entry(&key, note).await,
+            };
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| ClientError::TransactionConflict(e.to_string()))
+    }
 
-                fedimint_core::task::timeout(
-                    Duration::from_secs(10),
-                    self.ln_client().await_outgoing_refundable(contract_id),
-                )
+    /// Backs up the notes of this client and of each of its sub-accounts to
+    /// the federation, see [`MintClient::back_up_ecash_to_federation`]
+    pub async fn back_up_ecash_to_federation(&self) -> Result<()> {
+        self.mint_client()
+            .back_up_ecash_to_federation()
+            .await
+            .map_err(ClientError::EcashBackup)?;
+        for (id, _) in self.list_sub_accounts().await {
+            self.sub_account_client(id)
+                .await?
+                .unlimited()
+                .mint_client()
+                .back_up_ecash_to_federation()
                 .await
-                .map_err(|_| ClientError::FailedPaymentNoRefund)??;
+                .map_err(ClientError::EcashBackup)?;
+        }
+        Ok(())
+    }
 
-                self.try_refund_outgoing_contract(contract_id, rng).await?;
-                Err(ClientError::RefundedFailedPayment)
+    /// Restores the notes of the sub-accounts from their backups like
+    /// [`MintClient::restore_ecash_from_federation`], or only their spendable
+    /// notes like [`MintClient::restore_notes_from_federation`] if
+    /// `notes_only`
+    ///
+    /// Sub-accounts are numbered consecutively, so the ones missing from the
+    /// database are the ones with a backup up to the first id without one.
+    /// Their names and limits weren't backed up, they are recreated without
+    /// allowing any spending until [`Self::set_sub_account_limit`] is called.
+    pub async fn restore_sub_accounts_from_federation(
+        &self,
+        gap_limit: usize,
+        notes_only: bool,
+        task_group: &mut TaskGroup,
+    ) -> Result<Cancellable<Vec<SubAccountId>>> {
+        let known: BTreeSet<SubAccountId> = self
+            .list_sub_accounts()
+            .await
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+
+        let mut restored = vec![];
+        for id in (0..=SubAccountId::MAX.0).map(SubAccountId) {
+            let mint_client = self.sub_account_client(id).await?.unlimited().mint_client();
+            if !known.contains(&id) {
+                let backup = mint_client
+                    .download_ecash_backup_from_federation()
+                    .await
+                    .map_err(ClientError::EcashBackup)?;
+                if backup.is_none() {
+                    if known.range(id..).next().is_none() {
+                        break;
+                    }
+                    continue;
+                }
+                let locked = SpendLimit {
+                    amount: Amount::ZERO,
+                    period_secs: 0,
+                };
+                self.context
+                    .db
+                    .autocommit_bounded(|dbtx| {
+                        Box::pin(async move {
+                            dbtx.insert_entry(
+                                &SubAccountKey(id),
+                                &SubAccount::new(format!("Sub-account {id}"), Some(locked)),
+                            )
+                            .await;
+                            Ok::<_, ClientError>(())
+                        })
+                    })
+                    .await?;
             }
-            Err(e) => Err(e),
+
+            let result = if notes_only {
+                mint_client
+                    .restore_notes_from_federation(gap_limit, task_group)
+                    .await
+            } else {
+                mint_client
+                    .restore_ecash_from_federation(gap_limit, task_group)
+                    .await
+            };
+            if let Err(Cancelled) = result.map_err(ClientError::EcashBackup)? {
+                return Ok(Err(Cancelled));
+            }
+            restored.push(id);
         }
+        Ok(Ok(restored))
     }
 }
 
@@ -1189,14 +3314,16 @@ impl Client<GatewayClientConfig> {
     ) -> Result<PaymentParameters> {
         let our_pub_key = secp256k1_zkp::XOnlyPublicKey::from_keypair(&self.config.redeem_key).0;
 
-        if account.contract.cancelled {
-            return Err(ClientError::CancelledContract);
-        }
-
+        // Contracts addressed to other gateways of the federation are checked first,
+        // so they are ignored rather than acted upon
         if account.contract.gateway_key != our_pub_key {
             return Err(ClientError::NotOurKey);
         }
 
+        if account.contract.cancelled {
+            return Err(ClientError::CancelledContract);
+        }
+
         let invoice: Invoice = account.contract.invoice.clone();
         let invoice_amount = Amount::from_msats(
             invoice
@@ -1244,14 +3371,22 @@ impl Client<GatewayClientConfig> {
     /// Note though that extended periods of staying offline will result in loss
     /// of funds anyway if the client can not claim the respective contract
     /// in time.
-    pub async fn save_outgoing_payment(&self, contract: OutgoingContractAccount) {
-        let mut dbtx = self.context.db.begin_transaction().await;
-        dbtx.insert_entry(
-            &OutgoingContractAccountKey(contract.contract.contract_id()),
-            &contract,
-        )
-        .await;
-        dbtx.commit_tx().await;
+    pub async fn save_outgoing_payment(&self, contract: OutgoingContractAccount) -> Result<()> {
+        self.context
+            .db
+            .autocommit_bounded(|dbtx| {
+                let contract = contract.clone();
+                Box::pin(async move {
+                    dbtx.insert_entry(
+                        &OutgoingContractAccountKey(contract.contract.contract_id()),
+                        &contract,
+                    )
+                    .await;
+                    Ok::<_, ClientError>(())
+                })
+            })
+            .await?;
+        Ok(())
     }
 
     /// Lists all previously saved transactions that have not been driven to
@@ -1271,12 +3406,17 @@ impl Client<GatewayClientConfig> {
     /// Abort payment if our node can't route it and give money back to user
     pub async fn abort_outgoing_payment(&self, contract_id: ContractId) -> Result<()> {
         // FIXME: needs outbox pattern
-        let mut dbtx = self.context.db.begin_transaction().await;
-        let contract_account = dbtx
-            .remove_entry(&OutgoingContractAccountKey(contract_id))
-            .await
-            .ok_or(ClientError::CancelUnknownOutgoingContract)?;
-        dbtx.commit_tx().await;
+        let contract_account = self
+            .context
+            .db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    dbtx.remove_entry(&OutgoingContractAccountKey(contract_id))
+                        .await
+                        .ok_or(ClientError::CancelUnknownOutgoingContract)
+                })
+            })
+            .await?;
 
         self.cancel_outgoing_contract(contract_account).await
     }
@@ -1323,19 +3463,43 @@ impl Client<GatewayClientConfig> {
         &self,
         contract_id: ContractId,
         preimage: Preimage,
+        routing_fee: Amount,
         rng: impl RngCore + CryptoRng,
     ) -> Result<OutPoint> {
-        let mut dbtx = self.context.db.begin_transaction().await;
         let mut tx = TransactionBuilder::default();
 
         let contract = self.ln_client().get_outgoing_contract(contract_id).await?;
+        // we paid the invoice and what the lightning network charged for
+        // routing it, the rest of the contract is our fee
+        let paid = contract
+            .contract
+            .invoice
+            .amount_milli_satoshis()
+            .map_or(contract.amount, Amount::from_msats)
+            .saturating_add(routing_fee);
+        let payment = PaymentHistoryEntry {
+            timestamp: unix_now(),
+            kind: PaymentKind::Outgoing { contract_id },
+            amount: contract.amount,
+            fee: contract.amount.saturating_sub(paid),
+        };
         let input = Input::LN(contract.claim(preimage));
 
-        dbtx.remove_entry(&OutgoingContractAccountKey(contract_id))
-            .await;
-        dbtx.insert_entry(&OutgoingPaymentClaimKey(contract_id), &())
-            .await;
-        dbtx.commit_tx().await;
+        self.context
+            .db
+            .autocommit_bounded(|dbtx| {
+                let payment = payment.clone();
+                Box::pin(async move {
+                    dbtx.remove_entry(&OutgoingContractAccountKey(contract_id))
+                        .await;
+                    dbtx.insert_entry(&OutgoingPaymentClaimKey(contract_id), &())
+                        .await;
+                    dbtx.insert_entry(&PaymentHistoryKey(payment.kind.clone()), &payment)
+                        .await;
+                    Ok::<_, ClientError>(())
+                })
+            })
+            .await?;
 
         tx.input(&mut vec![self.config.redeem_key], input);
         let txid = self.submit_tx_with_change(tx, rng).await?;
@@ -1363,6 +3527,17 @@ impl Client<GatewayClientConfig> {
         // first span to show the span start
         info!("buy_preimage_offer");
         // Fetch offer for this payment hash
+        if self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&AbandonedOfferKey(*payment_hash))
+            .await
+            .is_some()
+        {
+            return Err(ClientError::AbandonedOffer);
+        }
         let offer: IncomingContractOffer = self.ln_client().get_offer(*payment_hash).await?;
 
         if &offer.amount > htlc_amount {
@@ -1384,19 +3559,136 @@ impl Client<GatewayClientConfig> {
             encrypted_preimage: offer.encrypted_preimage.clone(),
             decrypted_preimage: DecryptedPreimage::Pending,
             gateway_key: our_pub_key,
+            gateway_fee: offer.gateway_fee,
         });
         let incoming_output = Output::LN(LightningOutput::Contract(ContractOutput {
             amount: offer.amount,
             contract: contract.clone(),
         }));
 
-        // Submit transaction
-        builder.output(incoming_output);
-        let txid = self.submit_tx_with_change(builder, rng).await?;
-        let outpoint = OutPoint { txid, out_idx: 0 };
+        // Submit transaction
+        builder.output(incoming_output);
+        let txid = self.submit_tx_with_change(builder, rng).await?;
+        let outpoint = OutPoint { txid, out_idx: 0 };
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(
+            &PreimagePurchaseKey(contract.contract_id()),
+            &PreimagePurchase {
+                out_point: outpoint,
+                amount: offer.amount,
+                state: PreimagePurchaseState::AwaitingDecryption,
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        Ok((outpoint, contract.contract_id()))
+    }
+
+    /// Records a payment in the history the accounting export is built from,
+    /// replacing an earlier entry of the same payment
+    pub async fn record_payment(&self, kind: PaymentKind, amount: Amount, fee: Amount) {
+        let entry = PaymentHistoryEntry {
+            timestamp: unix_now(),
+            kind,
+            amount,
+            fee,
+        };
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&PaymentHistoryKey(entry.kind.clone()), &entry)
+            .await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Returns the payments recorded from `from` until before `to`, in
+    /// seconds since the unix epoch, oldest first
+    pub async fn payment_history(
+        &self,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Vec<PaymentHistoryEntry> {
+        let mut entries = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&PaymentHistoryKeyPrefix)
+            .await
+            .map(|(_, entry)| entry)
+            .filter(|entry| {
+                let in_range = from.map_or(true, |from| from <= entry.timestamp)
+                    && to.map_or(true, |to| entry.timestamp < to);
+                futures::future::ready(in_range)
+            })
+            .collect::<Vec<_>>()
+            .await;
+        entries.sort_by_key(|entry| entry.timestamp);
+        entries
+    }
+
+    /// Returns the ledger of the preimages we bought by funding incoming
+    /// contracts
+    pub async fn list_preimage_purchases(&self) -> Vec<(ContractId, PreimagePurchase)> {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&PreimagePurchaseKeyPrefix)
+            .await
+            .map(|(PreimagePurchaseKey(contract_id), purchase)| (contract_id, purchase))
+            .collect()
+            .await
+    }
+
+    pub async fn set_preimage_purchase_state(
+        &self,
+        contract_id: ContractId,
+        state: PreimagePurchaseState,
+    ) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        if let Some(mut purchase) = dbtx.get_value(&PreimagePurchaseKey(contract_id)).await {
+            purchase.state = state;
+            dbtx.insert_entry(&PreimagePurchaseKey(contract_id), &purchase)
+                .await;
+        }
+        dbtx.commit_tx().await;
+    }
 
-        // FIXME: Save this contract in DB
-        Ok((outpoint, contract.contract_id()))
+    /// Reclaims the funds of a preimage purchase in the
+    /// [`PreimagePurchaseState::Reclaiming`] state and returns its new state
+    ///
+    /// The funds can only be reclaimed once the federation failed to decrypt
+    /// the preimage, until then the purchase stays in its state.
+    pub async fn reclaim_preimage_purchase(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<PreimagePurchaseState> {
+        let purchase = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&PreimagePurchaseKey(contract_id))
+            .await
+            .ok_or(ClientError::UnknownPreimagePurchase)?;
+        if purchase.state != PreimagePurchaseState::Reclaiming {
+            return Ok(purchase.state);
+        }
+
+        let contract = self.ln_client().get_incoming_contract(contract_id).await?;
+        let state = match contract.contract.decrypted_preimage {
+            DecryptedPreimage::Pending => return Ok(PreimagePurchaseState::Reclaiming),
+            DecryptedPreimage::Some(_) => PreimagePurchaseState::Forfeited,
+            DecryptedPreimage::Invalid => {
+                let txid = self.refund_incoming_contract(contract_id, rng).await?;
+                PreimagePurchaseState::Reclaimed { txid }
+            }
+        };
+        self.set_preimage_purchase_state(contract_id, state.clone())
+            .await;
+        Ok(state)
     }
 
     /// Claw back funds after incoming contract that had invalid preimage
@@ -1419,6 +3711,73 @@ impl Client<GatewayClientConfig> {
         Ok(mint_tx_id)
     }
 
+    /// Claims the fee the offer of an incoming contract we funded pays us, once
+    /// the federation decrypted its `preimage`
+    ///
+    /// Returns `None` if the offer didn't pay a fee or we claimed it already.
+    #[instrument(name = "Client::claim_gateway_fee", skip(self, preimage, rng))]
+    pub async fn claim_gateway_fee(
+        &self,
+        contract_id: ContractId,
+        preimage: Preimage,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<Option<OutPoint>> {
+        let contract_account = self.ln_client().get_incoming_contract(contract_id).await?;
+        if contract_account.contract.gateway_fee == Amount::ZERO {
+            return Ok(None);
+        }
+
+        let mut builder = TransactionBuilder::default();
+        builder.input(
+            &mut vec![self.config.redeem_key],
+            Input::LN(contract_account.claim_gateway_fee(preimage)),
+        );
+        let txid = self.submit_tx_with_change(builder, rng).await?;
+        Ok(Some(OutPoint { txid, out_idx: 0 }))
+    }
+
+    /// Remembers to claim the gateway fee of an incoming contract with the
+    /// decrypted `preimage`, see [`Self::claim_queued_gateway_fees`]
+    pub async fn queue_gateway_fee_claim(&self, contract_id: ContractId, preimage: Preimage) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&GatewayFeeClaimKey(contract_id), &preimage)
+            .await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Claims the queued gateway fees, the ones failing to be claimed stay
+    /// queued for the next call
+    ///
+    /// Claiming is idempotent, since the federation reports no fee for a
+    /// contract whose fee was claimed already.
+    pub async fn claim_queued_gateway_fees(&self, mut rng: impl RngCore + CryptoRng) {
+        let claims: Vec<_> = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&GatewayFeeClaimKeyPrefix)
+            .await
+            .collect()
+            .await;
+
+        for (GatewayFeeClaimKey(contract_id), preimage) in claims {
+            match self
+                .claim_gateway_fee(contract_id, preimage, &mut rng)
+                .await
+            {
+                Ok(_) => {
+                    let mut dbtx = self.context.db.begin_transaction().await;
+                    dbtx.remove_entry(&GatewayFeeClaimKey(contract_id)).await;
+                    dbtx.commit_tx().await;
+                }
+                Err(error) => {
+                    warn!(%error, %contract_id, "Failed to claim the gateway fee of the contract");
+                }
+            }
+        }
+    }
+
     /// Lists all claim transactions for outgoing contracts that we have
     /// submitted but were not part of the consensus yet.
     pub async fn list_pending_claimed_outgoing(&self) -> Vec<ContractId> {
@@ -1501,10 +3860,16 @@ impl Client<GatewayClientConfig> {
         // to fetch the blind signatures for the newly issued notes, but as long as the
         // federation is honest as a whole they will produce the signatures, so we don't
         // have to worry
-        let mut dbtx = self.context.db.begin_transaction().await;
-        dbtx.remove_entry(&OutgoingPaymentClaimKey(contract_id))
-            .await;
-        dbtx.commit_tx().await;
+        self.context
+            .db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    dbtx.remove_entry(&OutgoingPaymentClaimKey(contract_id))
+                        .await;
+                    Ok::<_, ClientError>(())
+                })
+            })
+            .await?;
         Ok(())
     }
 
@@ -1525,6 +3890,310 @@ impl Client<GatewayClientConfig> {
             .await
             .map_err(ClientError::MintApiError)
     }
+
+    /// Registers the lightning address `name`, payments to it can be claimed
+    /// with the secret key of `pubkey`
+    pub async fn register_lightning_address(
+        &self,
+        name: String,
+        pubkey: XOnlyPublicKey,
+    ) -> Result<()> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let key = LightningAddressKey(name.clone());
+        if dbtx.get_value(&key).await.is_some() {
+            return Err(ClientError::LightningAddressTaken(name));
+        }
+        dbtx.insert_new_entry(&key, &LightningAddress { pubkey })
+            .await;
+        dbtx.commit_tx().await;
+        Ok(())
+    }
+
+    pub async fn lightning_address(&self, name: &str) -> Option<LightningAddress> {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&LightningAddressKey(name.to_string()))
+            .await
+    }
+
+    /// Creates an invoice paying the lightning address `name` through us and
+    /// waits until the federation accepted its offer
+    ///
+    /// The preimage of the invoice is the key of the address tweaked with a
+    /// fresh tweak, so nobody but the owner of the address can claim the
+    /// incoming contract. The tweak is kept as a [`LnurlPayment`] until the
+    /// owner fetches it.
+    pub async fn create_lnurl_invoice<R: RngCore + CryptoRng>(
+        &self,
+        name: &str,
+        amount: Amount,
+        description_hash: sha256::Hash,
+        route_hints: Vec<modules::ln::route_hints::RouteHint>,
+        fees: GatewayFee,
+        mut rng: R,
+    ) -> Result<Invoice> {
+        let address = self
+            .lightning_address(name)
+            .await
+            .ok_or_else(|| ClientError::UnknownLightningAddress(name.to_string()))?;
+
+        let tweak = secp256k1_zkp::SecretKey::new(&mut rng);
+        let (preimage_key, _parity) = address
+            .pubkey
+            .add_tweak(&self.context.secp, &tweak.into())
+            .map_err(|_| ClientError::InvalidPreimage)?;
+
+        // the gateway doesn't announce itself through this, so the ttl is irrelevant
+        let gateway =
+            self.config
+                .to_gateway_registration_info(route_hints, fees, None, Duration::ZERO);
+        let (invoice, ln_output) = self.create_invoice_and_offer(
+            &gateway,
+            amount,
+            PaymentDescription::Hash(description_hash),
+            preimage_key.serialize(),
+            &mut rng,
+            None,
+            Amount::ZERO,
+        )?;
+
+        let mut tx = TransactionBuilder::default();
+        tx.output(ln_output);
+        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+        self.context
+            .api
+            .await_output_outcome::<LightningOutputOutcome>(
+                OutPoint { txid, out_idx: 0 },
+                Duration::from_secs(15),
+                &self.context.decoders,
+            )
+            .await?;
+
+        let payment = LnurlPayment {
+            name: name.to_string(),
+            amount,
+            tweak: tweak.secret_bytes(),
+        };
+        let contract_id = ContractId::from_hash(*invoice.payment_hash());
+        self.context
+            .db
+            .autocommit_bounded(|dbtx| {
+                let payment = payment.clone();
+                Box::pin(async move {
+                    dbtx.insert_entry(&LnurlPaymentKey(contract_id), &payment)
+                        .await;
+                    Ok::<_, ClientError>(())
+                })
+            })
+            .await?;
+
+        Ok(invoice)
+    }
+
+    /// Returns the payments to the lightning address `name` by the id of their
+    /// incoming contract
+    pub async fn lnurl_payments(&self, name: &str) -> Vec<(ContractId, LnurlPayment)> {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&LnurlPaymentKeyPrefix)
+            .await
+            .filter_map(|(key, payment)| async move {
+                (payment.name == name).then_some((key.0, payment))
+            })
+            .collect()
+            .await
+    }
+
+    /// Ecash the gateway keeps besides what it needs to buy the preimage of an
+    /// HTLC, zero unless the operator set it
+    pub async fn ecash_reserve(&self) -> Amount {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&EcashReserveKey)
+            .await
+            .unwrap_or(Amount::ZERO)
+    }
+
+    pub async fn set_ecash_reserve(&self, reserve: Amount) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&EcashReserveKey, &reserve).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Amounts of the HTLCs the gateway intercepts, unbounded unless the
+    /// operator set a band
+    pub async fn htlc_amount_band(&self) -> HtlcAmountBand {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&HtlcAmountBandKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn set_htlc_amount_band(&self, band: HtlcAmountBand) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&HtlcAmountBandKey, &band).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Whether the operator paused routing payments for the federation, which
+    /// lasts until it is resumed even if the gateway restarts
+    pub async fn routing_paused(&self) -> bool {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&RoutingPausedKey)
+            .await
+            .is_some()
+    }
+
+    pub async fn set_routing_paused(&self, paused: bool) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        if paused {
+            dbtx.insert_entry(&RoutingPausedKey, &()).await;
+        } else {
+            dbtx.remove_entry(&RoutingPausedKey).await;
+        }
+        dbtx.commit_tx().await;
+    }
+
+    /// Creates an invoice of `amount` for an offer of the gateway itself, so
+    /// operators can route test payments through the federation
+    ///
+    /// The incoming contract of the invoice is claimed with the returned
+    /// keypair.
+    pub async fn create_test_invoice<R: RngCore + CryptoRng>(
+        &self,
+        amount: Amount,
+        route_hints: Vec<modules::ln::route_hints::RouteHint>,
+        fees: GatewayFee,
+        mut rng: R,
+    ) -> Result<(Invoice, KeyPair)> {
+        let payment_keypair = KeyPair::new(&self.context.secp, &mut rng);
+        let gateway =
+            self.config
+                .to_gateway_registration_info(route_hints, fees, None, Duration::ZERO);
+        let (invoice, ln_output) = self.create_invoice_and_offer(
+            &gateway,
+            amount,
+            PaymentDescription::Direct("Gateway test payment".to_string()),
+            payment_keypair.x_only_public_key().0.serialize(),
+            &mut rng,
+            None,
+            Amount::ZERO,
+        )?;
+
+        let mut tx = TransactionBuilder::default();
+        tx.output(ln_output);
+        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+        self.context
+            .api
+            .await_output_outcome::<LightningOutputOutcome>(
+                OutPoint { txid, out_idx: 0 },
+                Duration::from_secs(15),
+                &self.context.decoders,
+            )
+            .await?;
+
+        Ok((invoice, payment_keypair))
+    }
+
+    /// Stops buying the preimage of the offer of a failed test invoice, since
+    /// the offer can't be removed from the federation and the keys claiming its
+    /// contract are gone
+    pub async fn abandon_test_invoice(&self, invoice: &Invoice) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&AbandonedOfferKey(*invoice.payment_hash()), &())
+            .await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Claims the incoming contract of a test invoice once its preimage was
+    /// decrypted and waits until the federation accepted the claim
+    pub async fn claim_test_invoice<R: RngCore + CryptoRng>(
+        &self,
+        contract_id: ContractId,
+        payment_keypair: KeyPair,
+        rng: R,
+    ) -> Result<()> {
+        let outpoint = self
+            .claim_incoming_contract_with_key(contract_id, payment_keypair, rng)
+            .await?;
+        if let TransactionStatus::Rejected(e) =
+            self.context.api.await_tx_outcome(&outpoint.txid).await?
+        {
+            return Err(ClientError::RejectedTransaction(e));
+        }
+        Ok(())
+    }
+
+    /// Funds an outgoing contract paying `invoice` that the gateway itself
+    /// claims, the contract can be refunded with
+    /// [`Client::try_refund_outgoing_contract`] if the test payment fails
+    pub async fn fund_test_payment<R: RngCore + CryptoRng>(
+        &self,
+        invoice: Invoice,
+        route_hints: Vec<modules::ln::route_hints::RouteHint>,
+        rng: R,
+    ) -> Result<ContractId> {
+        // outgoing contracts only need the gateway's key, not its fees
+        let gateway = self.config.to_gateway_registration_info(
+            route_hints,
+            GatewayFee::default(),
+            None,
+            Duration::ZERO,
+        );
+        let (contract_id, outpoint) = self
+            .fund_outgoing_ln_contract_via(invoice, &gateway, rng)
+            .await?;
+        self.context
+            .api
+            .await_output_outcome::<LightningOutputOutcome>(
+                outpoint,
+                Duration::from_secs(30),
+                &self.context.decoders,
+            )
+            .await?;
+
+        Ok(contract_id)
+    }
+
+    /// Cancels the outgoing contract of a failed test payment and refunds it
+    pub async fn refund_test_payment<R: RngCore + CryptoRng>(
+        &self,
+        contract_id: ContractId,
+        rng: R,
+    ) -> Result<OutPoint> {
+        let contract_account = self.fetch_outgoing_contract(contract_id).await?;
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.remove_entry(&OutgoingContractAccountKey(contract_id))
+            .await;
+        dbtx.commit_tx().await;
+
+        if !contract_account.contract.cancelled {
+            self.cancel_outgoing_contract(contract_account).await?;
+        }
+        self.ln_client()
+            .await_outgoing_refundable(contract_id)
+            .await?;
+        self.try_refund_outgoing_contract(contract_id, rng).await
+    }
+
+    /// Drops the refund data of a test payment the gateway claimed itself
+    pub async fn forget_test_payment(&self, contract_id: ContractId) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.remove_entry(&OutgoingPaymentKey(contract_id)).await;
+        dbtx.commit_tx().await;
+    }
 }
 
 impl Distribution<ClientSecret> for Standard {
@@ -1548,6 +4217,47 @@ impl Debug for ClientSecret {
     }
 }
 
+/// Seconds since the unix epoch
+fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("time to be after the unix epoch")
+        .as_secs()
+}
+
+/// Index of the output an operation created in its transaction, change outputs
+/// of outgoing payments and peg-outs are skipped
+fn operation_output(
+    kind: &OperationKind,
+    transaction: &fedimint_core::transaction::Transaction,
+) -> Option<u64> {
+    let module = match kind {
+        OperationKind::OutgoingPayment { .. } => LEGACY_HARDCODED_INSTANCE_ID_LN,
+        OperationKind::PegOut { .. } => LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+        OperationKind::PegIn { .. }
+        | OperationKind::IncomingPayment { .. }
+        | OperationKind::Reissue { .. } => LEGACY_HARDCODED_INSTANCE_ID_MINT,
+    };
+    let position = transaction.outputs.iter().position(|output| {
+        if output.module_instance_id() != module {
+            return false;
+        }
+        match kind {
+            OperationKind::OutgoingPayment { contract_id } => {
+                match output.as_any().downcast_ref::<LightningOutput>() {
+                    Some(LightningOutput::Contract(ContractOutput {
+                        contract: Contract::Outgoing(contract),
+                        ..
+                    })) => contract.contract_id() == *contract_id,
+                    _ => false,
+                }
+            }
+            _ => true,
+        }
+    })?;
+    Some(position as u64)
+}
+
 /// Builds a fake module registry which is only usable for decoding messages
 /// since the client isn't modularized yet but we need the decoding
 /// functionality.
@@ -1596,6 +4306,28 @@ pub mod serde_keypair {
     }
 }
 
+/// Ways in which the invoice of an outgoing contract can disagree with the
+/// contract or the payment parameters validated for it
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum ContractInvoiceError {
+    #[error("Invoice pays hash {invoice}, but the contract is locked to {contract}")]
+    PaymentHashMismatch {
+        invoice: sha256::Hash,
+        contract: sha256::Hash,
+    },
+    #[error("Invoice is missing amount")]
+    MissingAmount,
+    #[error("Invoice asks for {invoice}, but the payment was validated for {validated}")]
+    AmountMismatch { invoice: Amount, validated: Amount },
+    #[error("Invoice asks for {invoice}, more than the contract's {contract}")]
+    AmountExceedsContract { invoice: Amount, contract: Amount },
+    #[error("Invoice expired at {expired_at}")]
+    Expired {
+        /// Expiry as seconds since the unix epoch
+        expired_at: u64,
+    },
+}
+
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("Error querying federation: {0}")]
@@ -1612,6 +4344,12 @@ pub enum ClientError {
     PegInAmountTooSmall,
     #[error("Peg-out waiting for UTXOs")]
     PegOutWaitingForUTXOs,
+    #[error("A balance of {0} doesn't cover the fees of a peg-out")]
+    PegOutBalanceTooSmall(Amount),
+    #[error("Peg-out fee rate {fee_rate:?} is below the federation's estimate {min:?}")]
+    PegOutFeeRateTooLow { fee_rate: Feerate, min: Feerate },
+    #[error("Peg-out {0} isn't waiting for confirmation")]
+    PegOutNotPending(bitcoin::Txid),
     #[error("Timed out while waiting for contract to be accepted")]
     WaitContractTimeout,
     #[error("Error fetching offer")]
@@ -1632,6 +4370,8 @@ pub enum ClientError {
     NoOffer,
     #[error("Invalid offer")]
     InvalidOffer,
+    #[error("The offer belongs to a failed test payment")]
+    AbandonedOffer,
     #[error("Wrong contract type")]
     WrongContractType,
     #[error("Wrong transaction type")]
@@ -1644,8 +4384,12 @@ pub enum ClientError {
     NoGateways,
     #[error("Federation has no registered lightning gateway with the given node public key")]
     GatewayNotFound,
+    #[error("Federation has no registered lightning gateway with the liquidity to route {0}")]
+    NoGatewayWithLiquidity(Amount),
     #[error("HTTP Error {0}")]
     HttpError(#[from] reqwest::Error),
+    #[error("Invalid connection options: {0}")]
+    InvalidConnectionOptions(anyhow::Error),
     #[error("Outgoing payment timeout")]
     OutgoingPaymentTimeout,
     #[error("Invalid amount tier {0:?}")]
@@ -1674,6 +4418,70 @@ pub enum ClientError {
     ConfigVerify(ConfigVerifyError),
     #[error("Failed to fetch notes we expected to be issued {0:?}")]
     UnableToFetchAllNotes(Vec<ClientError>, Vec<OutPoint>),
+    #[error("The federation rejected the transaction: {0}")]
+    RejectedTransaction(String),
+    #[error("Failed to commit to the database after {0} attempts: {1}")]
+    CommitFailed(usize, anyhow::Error),
+    #[error("Unknown lightning address {0}")]
+    UnknownLightningAddress(String),
+    #[error("Lightning address {0} is already registered")]
+    LightningAddressTaken(String),
+    #[error("Invalid invoice of outgoing contract: {0}")]
+    InvalidContractInvoice(#[from] ContractInvoiceError),
+    #[error("The client uses a legacy secret that has no mnemonic")]
+    LegacySecret,
+    #[error("The client already has a different secret")]
+    SecretAlreadySet,
+    #[error("Failed to negotiate API versions: {0}")]
+    ApiVersionNegotiation(anyhow::Error),
+    #[error("Failed to migrate the client database: {0}")]
+    DatabaseMigration(anyhow::Error),
+    #[error("Unknown operation")]
+    UnknownOperation,
+    #[error("An operation with the same id already exists")]
+    OperationExists,
+    #[error("The operation was cancelled")]
+    OperationCancelled,
+    #[error("The operation can't be cancelled: {0}")]
+    OperationNotCancellable(String),
+    #[error("The operation has no receipt: {0}")]
+    NoOperationReceipt(String),
+    #[error("The transaction exceeds the limits of the federation: {0}")]
+    LimitExceeded(#[from] LimitError),
+    #[error("The transaction spends notes that were already spent: {0}")]
+    TransactionConflict(String),
+    #[error(
+        "The transaction still conflicts after {} resubmissions: {}",
+        .0.resubmissions,
+        .0.reason
+    )]
+    UnresolvedConflict(TransactionConflictEvent),
+    #[error("We didn't buy a preimage for this contract")]
+    UnknownPreimagePurchase,
+    #[error("Unknown sub-account {0}")]
+    UnknownSubAccount(SubAccountId),
+    #[error(
+        "Spending {amount} exceeds the limit of the sub-account, {remaining} is left in this period"
+    )]
+    SpendLimitExceeded { amount: Amount, remaining: Amount },
+    #[error("No more sub-accounts can be created")]
+    TooManySubAccounts,
+    #[error("Ecash backup error: {0}")]
+    EcashBackup(anyhow::Error),
+    #[error("The invoice amount doesn't exceed the fee {0} its offer pays the gateway")]
+    InvoiceBelowGatewayFee(Amount),
+}
+
+impl From<AutocommitError<ClientError>> for ClientError {
+    fn from(e: AutocommitError<ClientError>) -> Self {
+        match e {
+            AutocommitError::CommitFailed {
+                attempts,
+                last_error,
+            } => ClientError::CommitFailed(attempts, last_error),
+            AutocommitError::ClosureError { error, .. } => error,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
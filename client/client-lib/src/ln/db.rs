@@ -1,13 +1,19 @@
+use bitcoin_hashes::sha256;
+use fedimint_core::db::{DatabaseTransaction, DatabaseVersion, MigrationMap};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
+use futures::FutureExt;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
-use super::incoming::ConfirmedInvoice;
+use super::history::{PaymentHistoryEntry, PaymentKind};
+use super::incoming::{ConfirmedInvoice, PreimagePurchase};
+use super::lnurl::{LightningAddress, LnurlPayment};
 use super::outgoing::OutgoingContractAccount;
+use super::HtlcAmountBand;
 use crate::ln::outgoing::OutgoingContractData;
-use crate::modules::ln::contracts::ContractId;
-use crate::modules::ln::LightningGateway;
+use crate::modules::ln::contracts::{ContractId, Preimage};
+use crate::modules::ln::{GatewayFee, LightningGateway};
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -17,6 +23,17 @@ pub enum DbKeyPrefix {
     OutgoingContractAccount = 0x25,
     ConfirmedInvoice = 0x26,
     LightningGateway = 0x28,
+    LightningAddress = 0x2c,
+    LnurlPayment = 0x2d,
+    EcashReserve = 0x2e,
+    HtlcAmountBand = 0x32,
+    PreimagePurchase = 0x34,
+    PaymentHistory = 0x37,
+    IncomingGatewayFee = 0x39,
+    AbandonedOffer = 0x3a,
+    OrderInvoice = 0x3c,
+    RoutingPaused = 0x3d,
+    GatewayFeeClaim = 0x3e,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -104,3 +121,205 @@ impl_db_lookup!(
     key = LightningGatewayKey,
     query_prefix = LightningGatewayKeyPrefix
 );
+
+/// Version of the lightning store, see [`get_database_migrations`]
+pub const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+
+pub fn get_database_migrations<'a>() -> MigrationMap<'a> {
+    let mut migrations = MigrationMap::new();
+    migrations.insert(DatabaseVersion(0), |dbtx| {
+        migrate_ln_db_version_0(dbtx).boxed()
+    });
+    migrations
+}
+
+/// Migrates the lightning store from version 0 to version 1, which added
+/// [`LightningGateway::liquidity`]. The active gateway is only cached, so it's
+/// dropped and fetched from the federation again when it's used next.
+async fn migrate_ln_db_version_0<'a, 'b>(
+    dbtx: &'b mut DatabaseTransaction<'a>,
+) -> Result<(), anyhow::Error> {
+    dbtx.remove_by_prefix(&LightningGatewayKeyPrefix).await;
+    Ok(())
+}
+
+/// Lightning addresses registered with the gateway, keyed by their name
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct LightningAddressKey(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LightningAddressKeyPrefix;
+
+impl_db_record!(
+    key = LightningAddressKey,
+    value = LightningAddress,
+    db_prefix = DbKeyPrefix::LightningAddress,
+);
+impl_db_lookup!(
+    key = LightningAddressKey,
+    query_prefix = LightningAddressKeyPrefix
+);
+
+/// Payments to lightning addresses, keyed by the id of their incoming contract
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct LnurlPaymentKey(pub ContractId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LnurlPaymentKeyPrefix;
+
+impl_db_record!(
+    key = LnurlPaymentKey,
+    value = LnurlPayment,
+    db_prefix = DbKeyPrefix::LnurlPayment,
+);
+impl_db_lookup!(key = LnurlPaymentKey, query_prefix = LnurlPaymentKeyPrefix);
+
+/// Ecash the gateway keeps in the federation besides what an HTLC needs
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct EcashReserveKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EcashReserveKeyPrefix;
+
+impl_db_record!(
+    key = EcashReserveKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::EcashReserve,
+);
+impl_db_lookup!(key = EcashReserveKey, query_prefix = EcashReserveKeyPrefix);
+
+/// Fee the client's offers pay the gateway funding their incoming contracts
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct IncomingGatewayFeeKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct IncomingGatewayFeeKeyPrefix;
+
+impl_db_record!(
+    key = IncomingGatewayFeeKey,
+    value = GatewayFee,
+    db_prefix = DbKeyPrefix::IncomingGatewayFee,
+);
+impl_db_lookup!(
+    key = IncomingGatewayFeeKey,
+    query_prefix = IncomingGatewayFeeKeyPrefix
+);
+
+/// Amounts of the HTLCs the gateway intercepts for the federation
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct HtlcAmountBandKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct HtlcAmountBandKeyPrefix;
+
+impl_db_record!(
+    key = HtlcAmountBandKey,
+    value = HtlcAmountBand,
+    db_prefix = DbKeyPrefix::HtlcAmountBand,
+);
+impl_db_lookup!(
+    key = HtlcAmountBandKey,
+    query_prefix = HtlcAmountBandKeyPrefix
+);
+
+/// Payments of the gateway, keyed by the payment so recording one twice keeps
+/// a single entry
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PaymentHistoryKey(pub PaymentKind);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PaymentHistoryKeyPrefix;
+
+impl_db_record!(
+    key = PaymentHistoryKey,
+    value = PaymentHistoryEntry,
+    db_prefix = DbKeyPrefix::PaymentHistory,
+);
+impl_db_lookup!(
+    key = PaymentHistoryKey,
+    query_prefix = PaymentHistoryKeyPrefix
+);
+
+/// Ledger of the preimages the gateway bought by funding incoming contracts
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PreimagePurchaseKey(pub ContractId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PreimagePurchaseKeyPrefix;
+
+impl_db_record!(
+    key = PreimagePurchaseKey,
+    value = PreimagePurchase,
+    db_prefix = DbKeyPrefix::PreimagePurchase,
+);
+impl_db_lookup!(
+    key = PreimagePurchaseKey,
+    query_prefix = PreimagePurchaseKeyPrefix
+);
+
+/// Payment hashes of the gateway's own offers whose test payment failed, the
+/// gateway doesn't buy their preimages since it dropped the keys claiming them
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct AbandonedOfferKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct AbandonedOfferKeyPrefix;
+
+impl_db_record!(
+    key = AbandonedOfferKey,
+    value = (),
+    db_prefix = DbKeyPrefix::AbandonedOffer,
+);
+impl_db_lookup!(
+    key = AbandonedOfferKey,
+    query_prefix = AbandonedOfferKeyPrefix
+);
+
+/// Contracts of the invoices of point-of-sale orders, keyed by the order id
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OrderInvoiceKey(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OrderInvoiceKeyPrefix;
+
+impl_db_record!(
+    key = OrderInvoiceKey,
+    value = ContractId,
+    db_prefix = DbKeyPrefix::OrderInvoice,
+);
+impl_db_lookup!(key = OrderInvoiceKey, query_prefix = OrderInvoiceKeyPrefix);
+
+/// Present while the operator paused routing payments for the federation
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct RoutingPausedKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RoutingPausedKeyPrefix;
+
+impl_db_record!(
+    key = RoutingPausedKey,
+    value = (),
+    db_prefix = DbKeyPrefix::RoutingPaused,
+);
+impl_db_lookup!(
+    key = RoutingPausedKey,
+    query_prefix = RoutingPausedKeyPrefix
+);
+
+/// Preimages of the incoming contracts whose gateway fee we haven't claimed
+/// yet, keyed by the contract
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct GatewayFeeClaimKey(pub ContractId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GatewayFeeClaimKeyPrefix;
+
+impl_db_record!(
+    key = GatewayFeeClaimKey,
+    value = Preimage,
+    db_prefix = DbKeyPrefix::GatewayFeeClaim,
+);
+impl_db_lookup!(
+    key = GatewayFeeClaimKey,
+    query_prefix = GatewayFeeClaimKeyPrefix
+);
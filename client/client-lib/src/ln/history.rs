@@ -0,0 +1,62 @@
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, TransactionId};
+use serde::{Deserialize, Serialize};
+
+use crate::modules::ln::contracts::ContractId;
+
+/// Entry of the gateway's payment history, which its accounting export is
+/// built from
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PaymentHistoryEntry {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub kind: PaymentKind,
+    /// Ecash the payment added to or spent from the gateway's balance, see
+    /// [`PaymentKind`]
+    pub amount: Amount,
+    /// Fee the gateway earned, zero for deposits and withdrawals
+    pub fee: Amount,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub enum PaymentKind {
+    /// HTLC paid to a user of the federation, the gateway spent `amount` of
+    /// ecash and received `amount + fee` over lightning
+    Incoming { contract_id: ContractId },
+    /// Invoice paid for a user of the federation, the gateway claimed `amount`
+    /// of ecash and paid `amount - fee` over lightning
+    Outgoing { contract_id: ContractId },
+    /// Peg-in issuing `amount` of ecash
+    Deposit { txid: TransactionId },
+    /// Peg-out spending `amount` of ecash, including the on-chain fees
+    Withdrawal { txid: TransactionId },
+}
+
+impl PaymentKind {
+    /// Whether the payment added to the gateway's ecash balance
+    pub fn is_credit(&self) -> bool {
+        matches!(
+            self,
+            PaymentKind::Outgoing { .. } | PaymentKind::Deposit { .. }
+        )
+    }
+
+    /// Contract id or transaction id identifying the payment
+    pub fn reference(&self) -> String {
+        match self {
+            PaymentKind::Incoming { contract_id } | PaymentKind::Outgoing { contract_id } => {
+                contract_id.to_string()
+            }
+            PaymentKind::Deposit { txid } | PaymentKind::Withdrawal { txid } => txid.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaymentKind::Incoming { .. } => "incoming",
+            PaymentKind::Outgoing { .. } => "outgoing",
+            PaymentKind::Deposit { .. } => "deposit",
+            PaymentKind::Withdrawal { .. } => "withdrawal",
+        }
+    }
+}
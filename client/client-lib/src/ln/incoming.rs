@@ -1,11 +1,13 @@
 use bitcoin::secp256k1::KeyPair;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::Amount;
+use fedimint_core::{Amount, OutPoint, TransactionId};
 use lightning_invoice::Invoice;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::modules::ln::contracts::incoming::IncomingContract;
-use crate::modules::ln::contracts::{ContractId, IdentifiableContract};
+use crate::modules::ln::contracts::{
+    ContractId, DecryptedPreimage, IdentifiableContract, Preimage,
+};
 use crate::modules::ln::LightningInput;
 
 #[derive(Debug, Clone, Encodable, Decodable)]
@@ -15,13 +17,66 @@ pub struct IncomingContractAccount {
 }
 
 impl IncomingContractAccount {
+    /// Claims the funds of the contract, except the fee the gateway can still
+    /// claim once the preimage was decrypted
     pub fn claim(&self) -> LightningInput {
+        let amount = match self.contract.decrypted_preimage {
+            DecryptedPreimage::Some(_) => self.amount.saturating_sub(self.contract.gateway_fee),
+            _ => self.amount,
+        };
         LightningInput {
             contract_id: self.contract.contract_id(),
-            amount: self.amount,
+            amount,
             witness: None,
         }
     }
+
+    /// Claims the fee of the gateway that funded the contract, which proves
+    /// it bought the preimage by presenting it
+    pub fn claim_gateway_fee(&self, preimage: Preimage) -> LightningInput {
+        LightningInput {
+            contract_id: self.contract.contract_id(),
+            amount: self.contract.gateway_fee,
+            witness: Some(preimage),
+        }
+    }
+}
+
+/// Entry of the gateway's ledger of preimages it bought from the federation by
+/// funding an incoming contract
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PreimagePurchase {
+    /// Out point of the output funding the incoming contract
+    pub out_point: OutPoint,
+    pub amount: Amount,
+    pub state: PreimagePurchaseState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub enum PreimagePurchaseState {
+    /// Waiting for the federation to decrypt the preimage
+    AwaitingDecryption,
+    /// The preimage was decrypted, the funds were paid to the recipient
+    Decrypted,
+    /// The preimage was decrypted, but the HTLC could not be settled with it,
+    /// recorded by gateways that didn't retry settling
+    SettleFailed,
+    /// The payment failed, the funds of the contract are reclaimed once the
+    /// federation failed to decrypt the preimage
+    Reclaiming,
+    /// The funds were reclaimed by transaction `txid`
+    Reclaimed { txid: TransactionId },
+    /// The preimage was decrypted after the payment failed, so the funds were
+    /// paid to the recipient and can't be reclaimed
+    Forfeited,
+    /// The preimage was decrypted, but settling the HTLC failed so far, the
+    /// gateway keeps retrying until it succeeds
+    SettlePending {
+        intercepted_htlc_id: Vec<u8>,
+        preimage: Preimage,
+        /// Fee the HTLC pays us, recorded once it is settled
+        fee: Amount,
+    },
 }
 
 // TODO: should this have some kind of "state" enum - e.g. pending, paid,
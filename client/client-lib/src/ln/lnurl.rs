@@ -0,0 +1,38 @@
+use bitcoin::secp256k1::{KeyPair, Scalar, Secp256k1, Verification, XOnlyPublicKey};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+/// A lightning address registered with a gateway, payments to it are claimable
+/// with the secret key of `pubkey`
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct LightningAddress {
+    pub pubkey: XOnlyPublicKey,
+}
+
+/// A payment to a lightning address whose offer was accepted by the federation
+///
+/// The preimage of the payment is the address' key tweaked with `tweak`, so the
+/// user can sweep the incoming contract with the secret key returned by
+/// [`LnurlPayment::claim_keypair`].
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct LnurlPayment {
+    /// Name of the lightning address that was paid
+    pub name: String,
+    pub amount: Amount,
+    pub tweak: [u8; 32],
+}
+
+impl LnurlPayment {
+    /// Tweaks the keypair of the lightning address to the one claiming the
+    /// incoming contract of this payment
+    pub fn claim_keypair<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        address_keypair: KeyPair,
+    ) -> Result<KeyPair, bitcoin::secp256k1::Error> {
+        let tweak = Scalar::from_be_bytes(self.tweak)
+            .map_err(|_| bitcoin::secp256k1::Error::InvalidTweak)?;
+        address_keypair.add_xonly_tweak(secp, &tweak)
+    }
+}
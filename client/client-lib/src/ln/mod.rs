@@ -1,30 +1,38 @@
 // TODO: once user and mint client are merged, make this private again
 pub mod db;
+pub mod history;
 pub mod incoming;
+pub mod lnurl;
 pub mod outgoing;
 
 use std::sync::Arc;
 use std::time::Duration;
 
 use bitcoin_hashes::sha256::Hash as Sha256Hash;
+use bitcoin_hashes::Hash;
 use fedimint_core::api::FederationError;
 use fedimint_core::config::FederationId;
 use fedimint_core::core::client::ClientModule;
 use fedimint_core::core::Decoder;
 use fedimint_core::db::DatabaseTransaction;
+use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{ModuleCommon, TransactionItemAmount};
 use fedimint_core::task::timeout;
-use fedimint_core::Amount;
+use fedimint_core::{Amount, FeeRate};
+use fedimint_derive_secret::{ChildId, DerivableSecret};
 use futures::StreamExt;
 use lightning_invoice::Invoice;
-use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::warn;
 
 use self::db::ConfirmedInvoiceKey;
 use self::incoming::ConfirmedInvoice;
 use crate::api::{LnFederationApi, WalletFederationApi};
-use crate::ln::db::{OutgoingPaymentKey, OutgoingPaymentKeyPrefix};
+use crate::ln::db::{
+    OutgoingContractAccountKey, OutgoingContractAccountKeyPrefix, OutgoingPaymentKey,
+    OutgoingPaymentKeyPrefix,
+};
 use crate::ln::incoming::IncomingContractAccount;
 use crate::ln::outgoing::{OutgoingContractAccount, OutgoingContractData};
 use crate::modules::ln::config::LightningClientConfig;
@@ -39,10 +47,17 @@ use crate::modules::ln::{
 };
 use crate::utils::ClientContext;
 
+/// Added to the invoice amount when funding an outgoing contract, so the
+/// gateway can pay routing fees
+const OUTGOING_FEE_MARGIN: FeeRate = FeeRate::from_ppm(10_000);
+
 #[derive(Debug)]
 pub struct LnClient {
     pub config: LightningClientConfig,
     pub context: Arc<ClientContext>,
+    /// Derives the keys of our outgoing contracts, so they can be refunded
+    /// after the contracts were lost from the database
+    pub secret: DerivableSecret,
 }
 
 impl ClientModule for LnClient {
@@ -78,6 +93,21 @@ impl ClientModule for LnClient {
 
 #[allow(dead_code)]
 impl LnClient {
+    /// Largest invoice amount whose outgoing contract, including the fee
+    /// margin for the gateway, can be funded with `available`
+    pub fn max_invoice_amount(available: Amount) -> Amount {
+        let msats = u128::from(available.msats) * 1_000_000
+            / (1_000_000 + u128::from(OUTGOING_FEE_MARGIN.ppm));
+        Amount::from_msats(msats.try_into().expect("smaller than the available amount"))
+    }
+
+    /// Amount of the outgoing contract paying an invoice of `invoice_amount`,
+    /// including the fee margin for the gateway
+    pub fn outgoing_contract_amount(invoice_amount: Amount) -> Option<Amount> {
+        // TODO: better define fee handling
+        OUTGOING_FEE_MARGIN.checked_add_fee(invoice_amount)
+    }
+
     /// Create an output that incentivizes a Lighning gateway to pay an invoice
     /// for us. It has time till the block height defined by `timelock`,
     /// after that we can claim our money back.
@@ -87,19 +117,18 @@ impl LnClient {
         invoice: Invoice,
         gateway: &LightningGateway,
         timelock: u32,
-        mut rng: impl RngCore + CryptoRng + 'a,
-    ) -> Result<LightningOutput> {
+    ) -> Result<ContractOutput> {
         let contract_amount = {
-            let invoice_amount_msat = invoice
-                .amount_milli_satoshis()
-                .ok_or(LnClientError::MissingInvoiceAmount)?;
-            // TODO: better define fee handling
-            // Add 1% fee margin
-            let contract_amount_msat = invoice_amount_msat + (invoice_amount_msat / 100);
-            Amount::from_msats(contract_amount_msat)
+            let invoice_amount = Amount::from_msats(
+                invoice
+                    .amount_milli_satoshis()
+                    .ok_or(LnClientError::MissingInvoiceAmount)?,
+            );
+            Self::outgoing_contract_amount(invoice_amount)
+                .ok_or(LnClientError::InvoiceAmountTooLarge)?
         };
 
-        let user_sk = bitcoin::KeyPair::new(&self.context.secp, &mut rng);
+        let user_sk = self.outgoing_contract_key(invoice.payment_hash());
 
         let contract = OutgoingContract {
             hash: *invoice.payment_hash(),
@@ -124,10 +153,10 @@ impl LnClient {
         )
         .await;
 
-        Ok(LightningOutput::Contract(ContractOutput {
+        Ok(ContractOutput {
             amount: contract_amount,
             contract: Contract::Outgoing(contract),
-        }))
+        })
     }
 
     pub async fn get_contract_account(&self, id: ContractId) -> Result<ContractAccount> {
@@ -231,6 +260,7 @@ impl LnClient {
         payment_hash: Sha256Hash,
         payment_secret: Preimage,
         expiry_time: Option<u64>,
+        gateway_fee: Amount,
     ) -> LightningOutput {
         LightningOutput::Offer(IncomingContractOffer {
             amount,
@@ -240,6 +270,7 @@ impl LnClient {
                 &self.config.threshold_pub_key,
             ),
             expiry_time,
+            gateway_fee,
         })
     }
 
@@ -280,6 +311,116 @@ impl LnClient {
         Ok(confirmed_invoice)
     }
 
+    /// Key of our outgoing contract paying the invoice of `payment_hash`
+    fn outgoing_contract_key(&self, payment_hash: &Sha256Hash) -> bitcoin::KeyPair {
+        let index = u64::from_be_bytes(
+            payment_hash.into_inner()[..8]
+                .try_into()
+                .expect("hash is longer than 8 bytes"),
+        );
+        self.secret
+            .child_key(ChildId(index))
+            .to_secp_key(&self.context.secp)
+    }
+
+    /// Re-fetches the outgoing contracts we store from the federation, so a
+    /// client whose contract states got stale or lost their updates after a
+    /// crash sees which ones were cancelled, claimed or refunded
+    ///
+    /// Contracts of `known_contracts` we no longer store, e.g. the ones of the
+    /// operation log, are re-derived if they still hold funds and we can
+    /// refund them. Contracts whose funds are gone are dropped. Unlike
+    /// restoring the notes this needs no epoch history, only one request per
+    /// contract.
+    pub async fn restore_contract_states(
+        &self,
+        known_contracts: impl IntoIterator<Item = ContractId>,
+    ) -> Result<()> {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let payments: Vec<_> = dbtx
+            .find_by_prefix(&OutgoingPaymentKeyPrefix)
+            .await
+            .collect()
+            .await;
+        let pending: Vec<_> = dbtx
+            .find_by_prefix(&OutgoingContractAccountKeyPrefix)
+            .await
+            .collect()
+            .await;
+        dbtx.commit_tx().await;
+
+        let lost: Vec<_> = known_contracts
+            .into_iter()
+            .filter(|contract_id| {
+                !payments
+                    .iter()
+                    .any(|(OutgoingPaymentKey(stored), _)| stored == contract_id)
+            })
+            .collect();
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        for contract_id in lost {
+            let Some(account) = self.fetch_outgoing_contract_state(contract_id).await else {
+                continue;
+            };
+            let recovery_key = self.outgoing_contract_key(&account.contract.hash);
+            if account.amount == Amount::ZERO
+                || account.contract.user_key != recovery_key.x_only_public_key().0
+            {
+                continue;
+            }
+            dbtx.insert_entry(
+                &OutgoingPaymentKey(contract_id),
+                &OutgoingContractData {
+                    recovery_key,
+                    contract_account: account,
+                },
+            )
+            .await;
+        }
+        for (OutgoingPaymentKey(contract_id), mut payment) in payments {
+            let Some(account) = self.fetch_outgoing_contract_state(contract_id).await else {
+                continue;
+            };
+            if account.amount == Amount::ZERO {
+                dbtx.remove_entry(&OutgoingPaymentKey(contract_id)).await;
+            } else {
+                payment.contract_account = account;
+                dbtx.insert_entry(&OutgoingPaymentKey(contract_id), &payment)
+                    .await;
+            }
+        }
+        for (OutgoingContractAccountKey(contract_id), _) in pending {
+            let Some(account) = self.fetch_outgoing_contract_state(contract_id).await else {
+                continue;
+            };
+            if account.amount == Amount::ZERO {
+                dbtx.remove_entry(&OutgoingContractAccountKey(contract_id))
+                    .await;
+            } else {
+                dbtx.insert_entry(&OutgoingContractAccountKey(contract_id), &account)
+                    .await;
+            }
+        }
+        dbtx.commit_tx().await;
+        Ok(())
+    }
+
+    /// Current state of a stored outgoing contract, `None` if the federation
+    /// can't tell, in which case we keep what we stored
+    async fn fetch_outgoing_contract_state(
+        &self,
+        contract_id: ContractId,
+    ) -> Option<OutgoingContractAccount> {
+        match self.get_outgoing_contract(contract_id).await {
+            Ok(account) => Some(account),
+            Err(e) => {
+                warn!("Failed to restore state of outgoing contract {contract_id}: {e}");
+                None
+            }
+        }
+    }
+
     /// Used by gateway to prematurely return funds to the user if the payment
     /// failed
     pub fn create_cancel_outgoing_output(
@@ -309,12 +450,41 @@ impl PayInvoicePayload {
     }
 }
 
+/// Amounts of the HTLCs a gateway intercepts for a federation, the ones outside
+/// of it are failed before the gateway buys their preimage
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct HtlcAmountBand {
+    pub min: Option<Amount>,
+    pub max: Option<Amount>,
+}
+
+impl HtlcAmountBand {
+    pub fn contains(&self, amount: Amount) -> bool {
+        self.min.map_or(true, |min| min <= amount) && self.max.map_or(true, |max| amount <= max)
+    }
+}
+
+impl std::fmt::Display for HtlcAmountBand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, "{min} to {max}"),
+            (Some(min), None) => write!(f, "at least {min}"),
+            (None, Some(max)) => write!(f, "at most {max}"),
+            (None, None) => write!(f, "any amount"),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, LnClientError>;
 
 #[derive(Debug, Error)]
 pub enum LnClientError {
     #[error("We can't pay an amountless invoice")]
     MissingInvoiceAmount,
+    #[error("Invoice amount is too large to pay")]
+    InvoiceAmountTooLarge,
     #[error("Mint API error: {0}")]
     ApiError(FederationError),
     #[error("Timeout")]
@@ -339,6 +509,7 @@ mod tests {
     use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
     use fedimint_core::{Amount, OutPoint, ServerModule, TransactionId};
+    use fedimint_derive_secret::DerivableSecret;
     use fedimint_ln_server::{Lightning, LightningGen};
     use fedimint_testing::FakeFed;
     use lightning_invoice::Invoice;
@@ -346,11 +517,13 @@ mod tests {
     use url::Url;
 
     use crate::api::fake::FederationApiFaker;
-    use crate::ln::LnClient;
+    use crate::ln::outgoing::OutgoingContractAccount;
+    use crate::ln::{HtlcAmountBand, LnClient, OUTGOING_FEE_MARGIN};
     use crate::modules::ln::config::LightningClientConfig;
+    use crate::modules::ln::contracts::outgoing::OutgoingContract;
     use crate::modules::ln::contracts::{ContractId, IdentifiableContract};
-    use crate::modules::ln::{LightningGateway, LightningOutput};
-    use crate::{module_decode_stubs, ClientContext};
+    use crate::modules::ln::{GatewayFee, LightningGateway, LightningOutput};
+    use crate::{module_decode_stubs, ClientContext, ContractInvoiceError, PaymentParameters};
 
     type Fed = FakeFed<Lightning>;
 
@@ -453,6 +626,7 @@ mod tests {
             module_gens: Default::default(),
             db: Database::new(MemDatabase::new(), module_decode_stubs()),
             api: api.into(),
+            epoch_archives: vec![],
             secp: secp256k1_zkp::Secp256k1::new(),
         };
 
@@ -461,12 +635,12 @@ mod tests {
 
     #[test_log::test(tokio::test)]
     async fn test_outgoing() {
-        let mut rng = rand::thread_rng();
         let (fed, client_config, client_context) = new_mint_and_client().await;
 
         let client = LnClient {
             config: client_config,
             context: Arc::new(client_context),
+            secret: DerivableSecret::new_root(&[], &[]).child_key(crate::LN_SECRET_CHILD_ID),
         };
 
         fed.lock().await.set_block_height(1);
@@ -494,6 +668,8 @@ mod tests {
                 api: Url::parse("http://example.com")
                     .expect("Could not parse URL to generate GatewayClientConfig API endpoint"),
                 route_hints: vec![],
+                fees: GatewayFee::default(),
+                liquidity: None,
                 valid_until: fedimint_core::time::now(),
             }
         };
@@ -501,20 +677,20 @@ mod tests {
 
         let mut dbtx = client.context.db.begin_transaction().await;
         let output = client
-            .create_outgoing_output(&mut dbtx, invoice.clone(), &gateway, timelock, &mut rng)
+            .create_outgoing_output(&mut dbtx, invoice.clone(), &gateway, timelock)
             .await
             .unwrap();
 
         dbtx.commit_tx().await;
 
-        let contract = match &output {
-            LightningOutput::Contract(c) => &c.contract,
-            _ => unreachable!(),
-        };
+        let contract = &output.contract;
 
         fed.lock()
             .await
-            .consensus_round(&[], &[(out_point, output.clone())])
+            .consensus_round(
+                &[],
+                &[(out_point, LightningOutput::Contract(output.clone()))],
+            )
             .await;
 
         let contract_acc = client
@@ -565,4 +741,86 @@ mod tests {
             .unwrap();
         assert_eq!(account.amount, Amount::ZERO);
     }
+
+    #[test]
+    fn verifies_contract_invoice() {
+        let invoice: Invoice =
+            "lnbcrt1u1pslya9jpp58005t06rezrqx2g6e84j44gs0aalcxfc47nzu97040fjzfrl\
+        cmasdq8w3jhxaqxqyjw5qcqp2sp5huz0lzk5v47kfdd58d0k96gm06kr2rkedgr5j8488jaqk44puz6s9qyyssqexyz\
+        s9rzrhu73625ag4ndtw4fqmstrnuaukh3z427la6mn2m2u25zy7j2jfk36pcsz5hl4m07ehcmhvh729424tjagv4lx2\
+        vgdsgy3sqphsc92"
+                .parse()
+                .unwrap();
+        let invoice_amount = Amount::from_msats(invoice.amount_milli_satoshis().unwrap());
+        let key = secp256k1_zkp::XOnlyPublicKey::from_slice(&[42; 32][..]).unwrap();
+        let mut account = OutgoingContractAccount {
+            amount: invoice_amount,
+            contract: OutgoingContract {
+                hash: *invoice.payment_hash(),
+                gateway_key: key,
+                timelock: 42,
+                user_key: key,
+                invoice,
+                cancelled: false,
+            },
+        };
+        let mut params = PaymentParameters {
+            max_delay: 10,
+            invoice_amount,
+            max_send_amount: invoice_amount,
+            payment_hash: account.contract.hash,
+            maybe_internal: false,
+        };
+
+        // the invoice is from 2021, everything else matches
+        assert!(matches!(
+            params.verify_invoice(&account),
+            Err(ContractInvoiceError::Expired { .. })
+        ));
+
+        account.amount = invoice_amount - Amount::from_msats(1);
+        assert!(matches!(
+            params.verify_invoice(&account),
+            Err(ContractInvoiceError::AmountExceedsContract { .. })
+        ));
+
+        params.invoice_amount = Amount::from_msats(1);
+        assert!(matches!(
+            params.verify_invoice(&account),
+            Err(ContractInvoiceError::AmountMismatch { .. })
+        ));
+
+        account.contract.hash = sha256::Hash::hash(b"other preimage");
+        assert!(matches!(
+            params.verify_invoice(&account),
+            Err(ContractInvoiceError::PaymentHashMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn max_invoice_amount_fits_contract() {
+        for msats in [0, 1, 999, 1_000_000, 123_456_789, u64::MAX] {
+            let available = Amount::from_msats(msats);
+            let invoice_amount = LnClient::max_invoice_amount(available);
+            let contract_amount = OUTGOING_FEE_MARGIN.checked_add_fee(invoice_amount).unwrap();
+            assert!(contract_amount <= available);
+            // rounding leaves at most a few msats unused
+            assert!(available - contract_amount <= Amount::from_msats(2));
+        }
+    }
+
+    #[test]
+    fn htlc_amount_band_bounds() {
+        let msats = Amount::from_msats;
+        assert!(HtlcAmountBand::default().contains(msats(u64::MAX)));
+
+        let band = HtlcAmountBand {
+            min: Some(msats(1_000)),
+            max: Some(msats(10_000)),
+        };
+        assert!(!band.contains(msats(999)));
+        assert!(band.contains(msats(1_000)));
+        assert!(band.contains(msats(10_000)));
+        assert!(!band.contains(msats(10_001)));
+    }
 }
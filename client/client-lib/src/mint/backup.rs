@@ -9,13 +9,16 @@
 
 use std::cmp::{max, Reverse};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::iter::once;
 use std::ops::Range;
 
 use anyhow::Result;
 use fedimint_core::api::{FederationError, GlobalFederationApi};
 use fedimint_core::cancellable::{Cancellable, Cancelled};
 use fedimint_core::core::LEGACY_HARDCODED_INSTANCE_ID_MINT;
-use fedimint_core::epoch::{ConsensusItem, SignedEpochOutcome};
+use fedimint_core::epoch::{
+    ConsensusItem, EpochArchiveInfo, SignedEpochOutcome, MIN_EPOCHS_BEFORE_CHECKPOINT,
+};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::{NumPeers, PeerId};
 use fedimint_logging::LOG_ECASH_RECOVERY;
@@ -23,10 +26,10 @@ use fedimint_mint_client::{BackupRequest, SignedBackupRequest};
 use tbs::{combine_valid_shares, verify_blind_share, BlindedMessage, PublicKeyShare};
 use tracing::{error, info};
 
-use super::db::NextECashNoteIndexKeyPrefix;
+use super::db::{IssuanceLedgerKey, NextECashNoteIndexKeyPrefix};
 use super::*;
 use crate::api::MintFederationApi;
-use crate::modules::mint::{MintConsensusItem, MintInput, MintOutput};
+use crate::modules::mint::{MintCheckpoint, MintConsensusItem, MintInput, MintOutput};
 
 impl MintClient {
     /// Prepare an encrypted backup and send it to federation for storing
@@ -38,38 +41,18 @@ impl MintClient {
         Ok(())
     }
 
+    /// Restores all the note-related data from the federation, replacing what
+    /// is in the database
     pub async fn restore_ecash_from_federation(
         &self,
         gap_limit: usize,
         task_group: &mut TaskGroup,
     ) -> Result<Cancellable<()>> {
-        let backup = if let Some(backup) = self.download_ecash_backup_from_federation().await? {
-            backup
-        } else {
-            warn!(
-                target: LOG_ECASH_RECOVERY,
-                id=%self.get_backup_id(),
-                "Could not find any valid existing backup. Will attempt to restore from scratch. This might take a long time."
-            );
-            PlaintextEcashBackup::new_empty()
-        };
-
-        let mut task_group = task_group.make_subgroup().await;
-
-        // TODO: If the client attempts any operations between while the recovery is
-        // working, the recovery code will most probably miss them, which might
-        // lead to incorrect state. We should probably lock everything in some
-        // way during recovery for corectness.
-        let snapshot = match self
-            .restore_current_state_from_backup(&mut task_group, backup, gap_limit)
-            .await?
-        {
+        let snapshot = match self.recover_ecash_state(gap_limit, task_group).await? {
             Ok(o) => o,
             Err(Cancelled) => return Ok(Err(Cancelled)),
         };
 
-        task_group.join_all(None).await?;
-
         info!("Writing out the recovered state to the database");
 
         let mut dbtx = self.start_dbtx().await;
@@ -84,7 +67,10 @@ impl MintClient {
             dbtx.insert_entry(&key, &note).await;
         }
 
+        // Restored notes weren't requested by this database, only the pending
+        // issuances are still expected
         for (txid, issuance_requests) in snapshot.unconfirmed_notes {
+            Self::record_requested(&mut dbtx, issuance_requests.note_amount()).await;
             dbtx.insert_entry(&OutputFinalizationKey(txid), &issuance_requests)
                 .await;
         }
@@ -98,6 +84,195 @@ impl MintClient {
         Ok(Ok(()))
     }
 
+    /// Restores only the spendable notes from the federation, for a wallet that
+    /// lost its note store but still has its pending issuances and note
+    /// indices
+    ///
+    /// If no notes were issued since the last backup, its notes that weren't
+    /// spent since are restored without scanning the epochs. Otherwise the
+    /// epochs since the backup are scanned like in a full recovery.
+    ///
+    /// Pending issuances found during the recovery are added to the ones in the
+    /// database and note indices never move backwards, so notes issued since
+    /// the last backup are neither lost nor issued twice.
+    pub async fn restore_notes_from_federation(
+        &self,
+        gap_limit: usize,
+        task_group: &mut TaskGroup,
+    ) -> Result<Cancellable<()>> {
+        let backup = self.download_ecash_backup_from_federation().await?;
+
+        if let Some(backup) = backup.as_ref() {
+            if self.no_notes_issued_since(backup).await {
+                info!("No notes were issued since the backup, restoring its unspent notes");
+                self.restore_unspent_backup_notes(backup).await?;
+                return Ok(Ok(()));
+            }
+        }
+
+        let snapshot = match self
+            .recover_ecash_state_from(backup, gap_limit, task_group)
+            .await?
+        {
+            Ok(o) => o,
+            Err(Cancelled) => return Ok(Err(Cancelled)),
+        };
+
+        info!("Writing out the recovered notes to the database");
+
+        let mut dbtx = self.start_dbtx().await;
+
+        dbtx.remove_by_prefix(&NoteKeyPrefix).await;
+        for (amount, note) in snapshot.spendable_notes {
+            let key = NoteKey {
+                amount,
+                nonce: note.note.0,
+            };
+            dbtx.insert_entry(&key, &note).await;
+        }
+
+        for (txid, issuance_requests) in snapshot.unconfirmed_notes {
+            let key = OutputFinalizationKey(txid);
+            if dbtx.get_value(&key).await.is_none() {
+                Self::record_requested(&mut dbtx, issuance_requests.note_amount()).await;
+                dbtx.insert_new_entry(&key, &issuance_requests).await;
+            }
+        }
+
+        for (amount, note_idx) in snapshot.next_note_idx.iter() {
+            let local_idx = self.get_next_note_index(&mut dbtx, amount).await;
+            dbtx.insert_entry(
+                &NextECashNoteIndexKey(amount),
+                &max(local_idx, *note_idx).as_u64(),
+            )
+            .await;
+        }
+        dbtx.commit_tx_result().await?;
+
+        Ok(Ok(()))
+    }
+
+    /// Recovers the state of our notes from the latest backup and the epochs
+    /// since then
+    /// Whether the note indices in the database are the ones of `backup`, so
+    /// no notes were issued since it was taken. Indices that are behind the
+    /// backup mean they were lost too, so we can't tell.
+    async fn no_notes_issued_since(&self, backup: &PlaintextEcashBackup) -> bool {
+        let mut dbtx = self.start_dbtx().await;
+        let local_idx: BTreeMap<Amount, NoteIndex> = dbtx
+            .find_by_prefix(&NextECashNoteIndexKeyPrefix)
+            .await
+            .map(|(key, idx)| (key.0, NoteIndex::from_u64(idx)))
+            .collect()
+            .await;
+        let backup_idx: BTreeMap<Amount, NoteIndex> = backup
+            .next_note_idx
+            .iter()
+            .map(|(amount, idx)| (amount, *idx))
+            .collect();
+
+        local_idx == backup_idx
+    }
+
+    /// Writes the notes of `backup` the federation didn't see spent yet and
+    /// its pending issuances missing from the database
+    async fn restore_unspent_backup_notes(&self, backup: &PlaintextEcashBackup) -> Result<()> {
+        // every note is spent in its own input, so the validation tells us
+        // which of the notes were spent since the backup
+        let notes: Vec<_> = backup.notes.iter_items().collect();
+        let inputs = notes
+            .iter()
+            .map(|(amount, note)| {
+                Input::Mint(MintInput(TieredMulti::from_iter([(*amount, note.note)])))
+            })
+            .collect();
+        let tx = Transaction {
+            inputs,
+            outputs: vec![],
+            signature: None,
+        };
+        let spent_inputs = if notes.is_empty() {
+            BTreeSet::new()
+        } else {
+            self.context
+                .api
+                .validate_transaction(&tx.into_type_erased())
+                .await?
+                .spent_inputs
+        };
+
+        let mut dbtx = self.start_dbtx().await;
+        dbtx.remove_by_prefix(&NoteKeyPrefix).await;
+        for (idx, (amount, note)) in notes.into_iter().enumerate() {
+            if spent_inputs.contains(&(idx as u64)) {
+                continue;
+            }
+            let key = NoteKey {
+                amount,
+                nonce: note.note.0,
+            };
+            dbtx.insert_entry(&key, note).await;
+        }
+
+        for (key, issuance_requests) in &backup.pending_notes {
+            if dbtx.get_value(key).await.is_none() {
+                Self::record_requested(&mut dbtx, issuance_requests.note_amount()).await;
+                dbtx.insert_new_entry(key, issuance_requests).await;
+            }
+        }
+        dbtx.commit_tx_result().await?;
+
+        Ok(())
+    }
+
+    async fn recover_ecash_state(
+        &self,
+        gap_limit: usize,
+        task_group: &mut TaskGroup,
+    ) -> Result<Cancellable<EcashRecoveryFinalState>> {
+        let backup = self.download_ecash_backup_from_federation().await?;
+        self.recover_ecash_state_from(backup, gap_limit, task_group)
+            .await
+    }
+
+    /// Recovers the ecash state by scanning the epochs since `backup`, or since
+    /// the beginning without one
+    async fn recover_ecash_state_from(
+        &self,
+        backup: Option<PlaintextEcashBackup>,
+        gap_limit: usize,
+        task_group: &mut TaskGroup,
+    ) -> Result<Cancellable<EcashRecoveryFinalState>> {
+        let backup = if let Some(backup) = backup {
+            backup
+        } else {
+            warn!(
+                target: LOG_ECASH_RECOVERY,
+                id=%self.get_backup_id(),
+                "Could not find any valid existing backup. Will attempt to restore from scratch. This might take a long time."
+            );
+            PlaintextEcashBackup::new_empty()
+        };
+
+        let mut task_group = task_group.make_subgroup().await;
+
+        // TODO: If the client attempts any operations between while the recovery is
+        // working, the recovery code will most probably miss them, which might
+        // lead to incorrect state. We should probably lock everything in some
+        // way during recovery for corectness.
+        let snapshot = match self
+            .restore_current_state_from_backup(&mut task_group, backup, gap_limit)
+            .await?
+        {
+            Ok(o) => o,
+            Err(Cancelled) => return Ok(Err(Cancelled)),
+        };
+
+        task_group.join_all(None).await?;
+
+        Ok(Ok(snapshot))
+    }
+
     pub async fn wipe_notes(&self) -> Result<()> {
         let mut dbtx = self.start_dbtx().await;
         Self::wipe_notes_static(&mut dbtx).await?;
@@ -113,6 +288,7 @@ impl MintClient {
         dbtx.remove_by_prefix(&NoteKeyPrefix).await;
         dbtx.remove_by_prefix(&OutputFinalizationKeyPrefix).await;
         dbtx.remove_by_prefix(&NextECashNoteIndexKeyPrefix).await;
+        dbtx.remove_entry(&IssuanceLedgerKey).await;
         Ok(())
     }
 
@@ -227,6 +403,40 @@ impl MintClient {
         Ok(())
     }
 
+    /// Fetches `epoch` from the archives of the guardians, or else from the
+    /// third party archives
+    async fn fetch_archived_epoch(
+        &self,
+        epoch: u64,
+    ) -> std::result::Result<SignedEpochOutcome, FederationError> {
+        let guardians = once(&self.context.api);
+        let mut last_error = None;
+        for api in guardians.chain(self.context.epoch_archives.iter()) {
+            match api
+                .fetch_archived_epoch_history(epoch, self.epoch_pk, &self.context.decoders)
+                .await
+            {
+                Ok(outcome) => return Ok(outcome),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("the guardians were asked"))
+    }
+
+    /// Fetches the epoch history kept by the archives of the guardians and the
+    /// third party archives, skipping the ones that can't be reached
+    async fn fetch_epoch_archives(&self) -> Vec<EpochArchiveInfo> {
+        let guardians = once(&self.context.api);
+        let mut archives = vec![];
+        for api in guardians.chain(self.context.epoch_archives.iter()) {
+            match api.fetch_epoch_archives().await {
+                Ok(infos) => archives.extend(infos.into_values()),
+                Err(e) => info!(target: LOG_ECASH_RECOVERY, %e, "Epoch archives unavailable"),
+            }
+        }
+        archives
+    }
+
     /// Fetch epochs in a given range and send them over `sender`
     ///
     /// Since WASM's `spawn` does not support join handles, we indicate
@@ -246,13 +456,17 @@ impl MintClient {
             .map(move |epoch| {
                 Box::pin(async move {
                     info!(epoch, "Fetching epoch");
-                    (
-                        epoch,
-                        self.context
-                            .api
-                            .fetch_epoch_history(epoch, self.epoch_pk, &self.context.decoders)
-                            .await,
-                    )
+                    let outcome = match self
+                        .context
+                        .api
+                        .fetch_epoch_history(epoch, self.epoch_pk, &self.context.decoders)
+                        .await
+                    {
+                        Ok(outcome) => Ok(outcome),
+                        // the guardians might have pruned the epoch, which archives keep
+                        Err(e) => self.fetch_archived_epoch(epoch).await.map_err(|_| e),
+                    };
+                    (epoch, outcome)
                 })
             })
             .buffered(8)
@@ -270,11 +484,41 @@ impl MintClient {
                 return Err(e.into());
             }
         };
-        // TODO: This -1 is probably not necessary, as it should be enough to start from
-        // the exact epoch the snapshot was taken, but it is harmless to start from any
-        // epoch in the past, and starting a bit earlier makes it more robust in face of
-        // some inconsistency that we've missed.
-        let start_epoch = backup.epoch_count.saturating_sub(1);
+        // The epochs before the latest checkpoint might have been pruned, so if our
+        // backup is older than it we have to bootstrap from the checkpoint instead
+        let checkpoint = self
+            .context
+            .api
+            .fetch_epoch_checkpoint(self.epoch_pk)
+            .await?
+            .filter(|checkpoint| backup.epoch_count <= checkpoint.checkpoint.epoch);
+
+        // Unless an archive still has all epochs since our backup, then we replay
+        // them like the guardians never pruned anything
+        let checkpoint = match checkpoint {
+            Some(checkpoint) => {
+                let backup_epoch = backup.epoch_count.saturating_sub(1);
+                let archived = self
+                    .fetch_epoch_archives()
+                    .await
+                    .iter()
+                    .any(|archive| archive.contains(backup_epoch));
+                (!archived).then_some(checkpoint)
+            }
+            None => None,
+        };
+
+        let start_epoch = match &checkpoint {
+            Some(checkpoint) => checkpoint
+                .checkpoint
+                .epoch
+                .saturating_sub(MIN_EPOCHS_BEFORE_CHECKPOINT),
+            // TODO: This -1 is probably not necessary, as it should be enough to start from
+            // the exact epoch the snapshot was taken, but it is harmless to start from any
+            // epoch in the past, and starting a bit earlier makes it more robust in face of
+            // some inconsistency that we've missed.
+            None => backup.epoch_count.saturating_sub(1),
+        };
         let epoch_range = start_epoch..current_epoch_count;
 
         info!(
@@ -282,6 +526,7 @@ impl MintClient {
             start_epoch, current_epoch_count, "Recovering from snapshot"
         );
 
+        let backup_epoch_count = backup.epoch_count;
         let mut tracker = EcashRecoveryTracker::from_backup(
             backup,
             self.secret.clone(),
@@ -289,6 +534,40 @@ impl MintClient {
             self.config.tbs_pks.clone(),
             self.config.peer_tbs_pks.clone(),
         );
+
+        if let Some(checkpoint) = checkpoint {
+            // every checkpoint only contains the changes since the previous one, so
+            // we follow the links back to the one that covers the epoch of our backup
+            let mut checkpoints = vec![checkpoint.checkpoint];
+            while let Some((epoch, hash)) = checkpoints.last().and_then(|last| last.previous) {
+                if epoch < backup_epoch_count {
+                    break;
+                }
+                checkpoints.push(
+                    self.context
+                        .api
+                        .fetch_epoch_checkpoint_at(epoch, hash)
+                        .await?,
+                );
+            }
+
+            for checkpoint in checkpoints.iter().rev() {
+                info!(
+                    target: LOG_ECASH_RECOVERY,
+                    epoch = checkpoint.epoch,
+                    "Recovering from checkpoint"
+                );
+                let state = checkpoint
+                    .modules
+                    .get(&LEGACY_HARDCODED_INSTANCE_ID_MINT)
+                    .ok_or_else(|| anyhow::format_err!("Checkpoint is missing the mint state"))?;
+                let mint_checkpoint = MintCheckpoint::consensus_decode(
+                    &mut &state[..],
+                    &ModuleDecoderRegistry::default(),
+                )?;
+                tracker.handle_checkpoint(&mint_checkpoint);
+            }
+        }
         let task_handle = task_group.make_handle();
 
         let mut epoch_stream = self.fetch_epochs_stream(epoch_range);
@@ -563,6 +842,94 @@ impl EcashRecoveryTracker {
         note_idx_ref.advance();
     }
 
+    /// Recovers the notes issued and spent between a checkpoint and the
+    /// previous one
+    ///
+    /// Has to be called for the checkpoints in the order they were created and
+    /// before handling any epochs, which can start up to
+    /// [`MIN_EPOCHS_BEFORE_CHECKPOINT`] epochs before the last checkpoint.
+    pub fn handle_checkpoint(&mut self, checkpoint: &MintCheckpoint) {
+        let issued: HashMap<BlindedMessage, tbs::BlindedSignature> = checkpoint
+            .issued
+            .iter()
+            .map(|(blind_nonce, bsig)| (blind_nonce.0, *bsig))
+            .collect();
+
+        // Outputs from the backup that were signed in the meantime
+        let signed_outputs: Vec<_> = self
+            .pending_outputs
+            .iter()
+            .filter(|(_, (items, _))| {
+                items
+                    .iter_items()
+                    .all(|(_, (msg, _))| issued.contains_key(msg))
+            })
+            .map(|(out_point, _)| *out_point)
+            .collect();
+        for out_point in signed_outputs {
+            let (items, _) = self
+                .pending_outputs
+                .remove(&out_point)
+                .expect("must be in the map already");
+            for (amount, (msg, iss_request)) in items.iter_items() {
+                if let Some(iss_request) = iss_request {
+                    self.add_issued_note(amount, iss_request, issued[msg]);
+                }
+            }
+        }
+
+        // Every note we find extends the pending pool, so we repeat until none of
+        // the pending nonces was issued
+        loop {
+            let found: Vec<_> = self
+                .pending_nonces
+                .keys()
+                .filter(|msg| issued.contains_key(msg))
+                .copied()
+                .collect();
+            if found.is_empty() {
+                break;
+            }
+
+            for msg in found {
+                let (iss_request, note_idx, amount) = self
+                    .pending_nonces
+                    .remove(&msg)
+                    .expect("must be in the map already");
+                self.observe_nonce_idx_being_used(amount, note_idx);
+                self.add_issued_note(amount, &iss_request, issued[&msg]);
+            }
+        }
+
+        for nonce in &checkpoint.spent {
+            self.spendable_note_by_nonce.remove(nonce);
+        }
+    }
+
+    fn add_issued_note(
+        &mut self,
+        amount: Amount,
+        iss_request: &NoteIssuanceRequest,
+        bsig: tbs::BlindedSignature,
+    ) {
+        let amount_key = *self
+            .tbs_pks
+            .tier(&amount)
+            .expect("must have keys for all amounts here");
+        match iss_request.finalize(bsig, amount_key) {
+            Ok(note) => {
+                self.spendable_note_by_nonce
+                    .insert(iss_request.nonce(), (amount, note));
+            }
+            Err(error) => {
+                warn!(
+                    target: LOG_ECASH_RECOVERY,
+                    %amount, ?error, "Checkpoint contains an invalid signature for our note"
+                );
+            }
+        }
+    }
+
     pub fn handle_input(&mut self, input: &MintInput) {
         // We attempt to delete any nonce we see as spent, simple
         for (_amt, note) in input.0.iter_items() {
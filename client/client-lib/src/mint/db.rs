@@ -3,7 +3,8 @@ use fedimint_core::{impl_db_lookup, impl_db_record, Amount, OutPoint, TieredMult
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
-use crate::mint::{NoteIssuanceRequests, SpendableNote};
+use crate::mint::{IssuanceLedger, NoteIssuanceRequests, P2pkIssuanceRequests, SpendableNote};
+use crate::modules::mint::config::TierDeprecation;
 use crate::modules::mint::Nonce;
 
 #[repr(u8)]
@@ -14,6 +15,10 @@ pub enum DbKeyPrefix {
     PendingNotes = 0x27,
     NextECashNoteIndex = 0x2a,
     NotesPerDenomination = 0x2b,
+    P2pkOutputFinalizationData = 0x2f,
+    TierDeprecation = 0x30,
+    IssuanceLedger = 0x36,
+    LastNoteRefresh = 0x3b,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -67,6 +72,24 @@ impl_db_lookup!(
     query_prefix = OutputFinalizationKeyPrefix
 );
 
+/// Issuance of notes bound to a recipient's public key, see
+/// [`crate::Client::pay_to_pubkey`]
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct P2pkOutputFinalizationKey(pub OutPoint);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct P2pkOutputFinalizationKeyPrefix;
+
+impl_db_record!(
+    key = P2pkOutputFinalizationKey,
+    value = P2pkIssuanceRequests,
+    db_prefix = DbKeyPrefix::P2pkOutputFinalizationData,
+);
+impl_db_lookup!(
+    key = P2pkOutputFinalizationKey,
+    query_prefix = P2pkOutputFinalizationKeyPrefix
+);
+
 #[derive(Debug, Clone, Encodable, Decodable, Serialize)]
 pub struct NextECashNoteIndexKey(pub Amount);
 
@@ -87,3 +110,34 @@ impl_db_lookup!(
 pub struct NotesPerDenominationKey;
 
 impl_db_record!(key = NotesPerDenominationKey, value = u16, db_prefix = 0);
+
+/// Deprecated tiers last announced by the federation, no new notes are issued
+/// in them
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct TierDeprecationKey;
+
+impl_db_record!(
+    key = TierDeprecationKey,
+    value = TierDeprecation,
+    db_prefix = DbKeyPrefix::TierDeprecation,
+);
+
+/// Unix time in seconds at which the client last checked for deprecated tiers
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct LastNoteRefreshKey;
+
+impl_db_record!(
+    key = LastNoteRefreshKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::LastNoteRefresh,
+);
+
+/// Totals of the ecash requested from and issued by the federation
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct IssuanceLedgerKey;
+
+impl_db_record!(
+    key = IssuanceLedgerKey,
+    value = IssuanceLedger,
+    db_prefix = DbKeyPrefix::IssuanceLedger,
+);
@@ -5,26 +5,36 @@ use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
 
-use db::{NoteKey, NoteKeyPrefix, OutputFinalizationKey, OutputFinalizationKeyPrefix};
+use db::{
+    IssuanceLedgerKey, NoteKey, NoteKeyPrefix, OutputFinalizationKey, OutputFinalizationKeyPrefix,
+    P2pkOutputFinalizationKey,
+};
 use fedimint_core::api::{GlobalFederationApi, MemberError, OutputOutcomeError};
+use fedimint_core::config::ClientConfig;
 use fedimint_core::core::client::ClientModule;
 use fedimint_core::core::Decoder;
-use fedimint_core::db::DatabaseTransaction;
+use fedimint_core::db::{AutocommitError, DatabaseTransaction};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{ModuleCommon, TransactionItemAmount};
+use fedimint_core::outcome::TransactionStatus;
 use fedimint_core::tiered::InvalidAmountTierError;
 use fedimint_core::{Amount, OutPoint, Tiered, TieredMulti, TransactionId};
 use fedimint_mint_client::MintModuleTypes;
 use futures::{Future, StreamExt};
-use secp256k1_zkp::{KeyPair, Secp256k1, Signing};
+use rand::{CryptoRng, RngCore};
+use secp256k1_zkp::{KeyPair, Scalar, Secp256k1, SecretKey, Signing, Verification, XOnlyPublicKey};
 use serde::{Deserialize, Serialize};
-use tbs::{blind_message, unblind_signature, AggregatePublicKey, BlindedSignature, BlindingKey};
+use tbs::{
+    blind_message, unblind_signature, AggregatePublicKey, BlindedSignature, BlindingKey, FromRandom,
+};
 use thiserror::Error;
 use tracing::{debug, error, trace, warn};
 
-use crate::mint::db::{NextECashNoteIndexKey, NotesPerDenominationKey, PendingNotesKey};
-use crate::modules::mint::config::MintClientConfig;
+use crate::mint::db::{
+    NextECashNoteIndexKey, NotesPerDenominationKey, PendingNotesKey, TierDeprecationKey,
+};
+use crate::modules::mint::config::{MintClientConfig, TierDeprecation, META_TIER_DEPRECATION_KEY};
 use crate::modules::mint::{
     BlindNonce, MintInput, MintOutput, MintOutputBlindSignatures, MintOutputOutcome, Nonce, Note,
 };
@@ -145,6 +155,37 @@ pub struct NoteIssuanceRequests {
     notes: TieredMulti<NoteIssuanceRequest>,
 }
 
+/// Totals of the ecash the client requested from the federation, e.g. as
+/// change, and the ecash the federation issued for it
+///
+/// Whatever was requested is either issued or still pending, see
+/// [`MintClient::check_issuance`].
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, Encodable, Decodable,
+)]
+pub struct IssuanceLedger {
+    pub requested: Amount,
+    pub issued: Amount,
+}
+
+/// A mismatch between the ecash the client requested and the ecash it was
+/// issued, found by [`MintClient::check_issuance`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssuanceDiscrepancy {
+    /// The transaction of a pending issuance was rejected, so its ecash, e.g.
+    /// the change of a payment, will never be issued
+    Rejected {
+        outpoint: OutPoint,
+        amount: Amount,
+        reason: String,
+    },
+    /// Ecash was requested but is neither issued nor pending anymore
+    Missing { amount: Amount },
+    /// More ecash was issued or is pending than was requested
+    OverIssued { amount: Amount },
+}
+
 /// A [`Note`] with associated secret key that allows to proof ownership (spend
 /// it)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, Encodable, Decodable)]
@@ -153,6 +194,129 @@ pub struct SpendableNote {
     pub spend_key: KeyPair,
 }
 
+/// A [`Note`] bound to the public key of its recipient
+///
+/// Its spend key is the recipient's key tweaked by `tweak`, so only the holder
+/// of the recipient's secret key can spend it. Unlike a [`SpendableNote`] it
+/// can be backed up or sent over channels that may leak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct P2pkNote {
+    pub note: Note,
+    pub tweak: [u8; 32],
+}
+
+impl P2pkNote {
+    /// Derives the note's spend key from the recipient's `key`
+    pub fn into_spendable<C: Verification>(
+        self,
+        ctx: &Secp256k1<C>,
+        key: &KeyPair,
+    ) -> Result<SpendableNote> {
+        let tweak = Scalar::from_be_bytes(self.tweak).map_err(|_| MintClientError::WrongP2pkKey)?;
+        let spend_key = key
+            .add_xonly_tweak(ctx, &tweak)
+            .map_err(|_| MintClientError::WrongP2pkKey)?;
+        if &spend_key.x_only_public_key().0 != self.note.spend_key() {
+            return Err(MintClientError::WrongP2pkKey);
+        }
+
+        Ok(SpendableNote {
+            note: self.note,
+            spend_key,
+        })
+    }
+}
+
+/// Single [`P2pkNote`] issuance request to the mint
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize, Encodable, Decodable)]
+pub struct P2pkIssuanceRequest {
+    /// Nonce of the note, the recipient's public key tweaked by `tweak`
+    nonce: Nonce,
+    tweak: [u8; 32],
+    /// Key to unblind the blind signature supplied by the mint for this note
+    blinding_key: BlindingKey,
+}
+
+impl P2pkIssuanceRequest {
+    fn new<C, R>(ctx: &Secp256k1<C>, recipient: XOnlyPublicKey, rng: &mut R) -> (Self, BlindNonce)
+    where
+        C: Verification,
+        R: RngCore + CryptoRng,
+    {
+        let tweak = SecretKey::new(rng).secret_bytes();
+        let (nonce, _parity) = recipient
+            .add_tweak(
+                ctx,
+                &Scalar::from_be_bytes(tweak).expect("secret keys are valid scalars"),
+            )
+            .expect("tweaking fails with negligible probability");
+        let nonce = Nonce(nonce);
+        let blinding_key = BlindingKey::from_random(rng);
+        let blinded_nonce = blind_message(nonce.to_message(), blinding_key);
+
+        let request = P2pkIssuanceRequest {
+            nonce,
+            tweak,
+            blinding_key,
+        };
+
+        (request, BlindNonce(blinded_nonce))
+    }
+
+    fn finalize(
+        &self,
+        bsig: BlindedSignature,
+        mint_pub_key: AggregatePublicKey,
+    ) -> std::result::Result<P2pkNote, NoteFinalizationError> {
+        let sig = unblind_signature(self.blinding_key, bsig);
+        let note = Note(self.nonce, sig);
+        if note.verify(mint_pub_key) {
+            Ok(P2pkNote {
+                note,
+                tweak: self.tweak,
+            })
+        } else {
+            Err(NoteFinalizationError::InvalidSignature)
+        }
+    }
+}
+
+/// Multiple [`P2pkNote`] issuance requests
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, Encodable, Decodable)]
+pub struct P2pkIssuanceRequests {
+    notes: TieredMulti<P2pkIssuanceRequest>,
+}
+
+impl P2pkIssuanceRequests {
+    /// Finalize the issuance request using the blind signatures from the mint,
+    /// see [`NoteIssuanceRequests::finalize`]
+    pub fn finalize(
+        &self,
+        bsigs: MintOutputBlindSignatures,
+        mint_pub_key: &Tiered<AggregatePublicKey>,
+    ) -> std::result::Result<TieredMulti<P2pkNote>, NoteFinalizationError> {
+        if !self.notes.structural_eq(&bsigs.0) {
+            return Err(NoteFinalizationError::WrongMintAnswer);
+        }
+
+        self.notes
+            .iter_items()
+            .zip(bsigs.0)
+            .enumerate()
+            .map(|(idx, ((amt, note_req), (_amt, bsig)))| {
+                let note = note_req
+                    .finalize(bsig, *mint_pub_key.tier(&amt)?)
+                    .map_err(|_| NoteFinalizationError::InvalidSignatureAtIdx(idx))?;
+                Ok((amt, note))
+            })
+            .collect()
+    }
+
+    pub fn note_amount(&self) -> Amount {
+        self.notes.total_amount()
+    }
+}
+
 impl ClientModule for MintClient {
     const KIND: &'static str = "mint";
     type Module = MintModuleTypes;
@@ -183,12 +347,15 @@ impl MintClient {
 
     /// Adds the final amounts of `change` to the tx before submitting it
     /// Allows for multiple `change` outputs
+    ///
+    /// Fails if a note of the tx was already spent by a concurrent
+    /// transaction of ours.
     pub async fn finalize_change(
         &self,
         tx: &mut Transaction,
         dbtx: &mut DatabaseTransaction<'_>,
         change: Vec<Amount>,
-    ) {
+    ) -> Result<()> {
         // remove the spent ecash from the DB
         let mut input_ecash: Vec<(Amount, SpendableNote)> = vec![];
         for input in &tx.inputs {
@@ -198,7 +365,10 @@ impl MintClient {
                         amount,
                         nonce: note.0,
                     };
-                    let spendable = dbtx.get_value(&key).await.expect("Missing note");
+                    let spendable = dbtx
+                        .get_value(&key)
+                        .await
+                        .ok_or(MintClientError::NoteAlreadySpent)?;
                     input_ecash.push((amount, spendable));
                     dbtx.remove_entry(&key).await;
                 }
@@ -228,6 +398,7 @@ impl MintClient {
 
         // write ecash outputs to db to await for tx success to be fetched later
         for (out_idx, notes) in change_outputs.iter() {
+            Self::record_requested(dbtx, notes.note_amount()).await;
             dbtx.insert_new_entry(
                 &OutputFinalizationKey(OutPoint {
                     txid,
@@ -237,6 +408,44 @@ impl MintClient {
             )
             .await;
         }
+        Ok(())
+    }
+
+    /// Undoes [`Self::finalize_change`] for a tx the federation refused,
+    /// returning its notes to the wallet except for the `spent` ones and
+    /// forgetting the change requested by its outputs from `change_idx` on
+    pub async fn revert_change(
+        &self,
+        tx: &Transaction,
+        dbtx: &mut DatabaseTransaction<'_>,
+        change_idx: usize,
+        spent: &[Nonce],
+    ) {
+        let txid = tx.tx_hash();
+        if let Some(pending) = dbtx.remove_entry(&PendingNotesKey(txid)).await {
+            for (amount, note) in pending {
+                if !spent.contains(&note.note.0) {
+                    let key = NoteKey {
+                        amount,
+                        nonce: note.note.0,
+                    };
+                    dbtx.insert_entry(&key, &note).await;
+                }
+            }
+        }
+
+        let mut ledger = Self::issuance_ledger(dbtx).await;
+        for out_idx in change_idx..tx.outputs.len() {
+            let outpoint = OutPoint {
+                txid,
+                out_idx: out_idx as u64,
+            };
+            if let Some(issuance) = dbtx.remove_entry(&OutputFinalizationKey(outpoint)).await {
+                ledger.requested -= issuance.note_amount();
+            }
+        }
+        dbtx.insert_entry(&IssuanceLedgerKey, &ledger).await;
+        Self::check_issuance_ceiling(dbtx).await;
     }
 
     pub async fn set_notes_per_denomination(&self, notes: u16) {
@@ -251,6 +460,64 @@ impl MintClient {
             .unwrap_or(self.config.max_notes_per_denomination - 1)
     }
 
+    /// Tiers new notes are issued in, which leaves out deprecated tiers unless
+    /// that leaves amounts unrepresentable
+    async fn issuance_tiers(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> Tiered<AggregatePublicKey> {
+        let deprecated = self.tier_deprecation(dbtx).await.tiers;
+        let tiers: Tiered<AggregatePublicKey> = self
+            .config
+            .tbs_pks
+            .iter()
+            .filter(|(amount, _)| !deprecated.contains(amount))
+            .map(|(amount, key)| (amount, *key))
+            .collect();
+
+        if tiers.tiers().next() != self.config.tbs_pks.tiers().next() {
+            warn!("Can't avoid deprecated tiers without the smallest one");
+            return self.config.tbs_pks.clone();
+        }
+        tiers
+    }
+
+    pub async fn tier_deprecation(&self, dbtx: &mut DatabaseTransaction<'_>) -> TierDeprecation {
+        dbtx.get_value(&TierDeprecationKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    pub async fn set_tier_deprecation(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        deprecation: &TierDeprecation,
+    ) {
+        dbtx.insert_entry(&TierDeprecationKey, deprecation).await;
+    }
+
+    /// Reads the tiers deprecated by the federation from its `updated` config,
+    /// tiers our config has but `updated` lacks count as deprecated too
+    pub fn tier_deprecation_from_config(&self, updated: &ClientConfig) -> Result<TierDeprecation> {
+        let mut deprecation: TierDeprecation = match updated.meta.get(META_TIER_DEPRECATION_KEY) {
+            Some(json) => serde_json::from_str(json)
+                .map_err(|e| MintClientError::InvalidTierDeprecation(e.into()))?,
+            None => TierDeprecation::default(),
+        };
+
+        let (_, updated_mint) = updated
+            .get_first_module_by_kind::<MintClientConfig>("mint")
+            .map_err(MintClientError::InvalidTierDeprecation)?;
+        deprecation.tiers.extend(
+            self.config
+                .tbs_pks
+                .tiers()
+                .filter(|amount| updated_mint.tbs_pks.get(**amount).is_none()),
+        );
+
+        Ok(deprecation)
+    }
+
     /// Generates unsigned ecash, along with the private keys that can spend it
     async fn create_ecash(
         &self,
@@ -263,7 +530,7 @@ impl MintClient {
         let denominations = TieredMulti::represent_amount(
             amount,
             &self.notes().await,
-            &self.config.tbs_pks,
+            &self.issuance_tiers(dbtx).await,
             notes_per_denomination,
         );
         for (amt, num) in denominations.iter() {
@@ -286,6 +553,64 @@ impl MintClient {
         (note_finalization_data, sig_req.0)
     }
 
+    /// Generates unsigned ecash bound to `recipient`'s public key
+    pub async fn create_p2pk_ecash<R: RngCore + CryptoRng>(
+        &self,
+        amount: Amount,
+        recipient: XOnlyPublicKey,
+        dbtx: &mut DatabaseTransaction<'_>,
+        mut rng: R,
+    ) -> (P2pkIssuanceRequests, TieredMulti<BlindNonce>) {
+        // We don't know which notes the recipient has, so just aim for a few of
+        // every denomination
+        let notes_per_denomination = self.notes_per_denomination(dbtx).await;
+        let denominations = TieredMulti::represent_amount(
+            amount,
+            &TieredMulti::<()>::default(),
+            &self.issuance_tiers(dbtx).await,
+            notes_per_denomination,
+        );
+
+        let mut amount_requests = Vec::new();
+        for (amt, num) in denominations.iter() {
+            for _ in 0..*num {
+                let (request, blind_nonce) =
+                    P2pkIssuanceRequest::new(&self.context.secp, recipient, &mut rng);
+                amount_requests.push(((amt, request), (amt, blind_nonce)));
+            }
+        }
+        let (notes, blind_nonces) = amount_requests.into_iter().unzip();
+
+        (P2pkIssuanceRequests { notes }, blind_nonces)
+    }
+
+    /// Returns the notes bound to a public key issued at `outpoint`
+    ///
+    /// Since only their recipient can spend them, the issuance is kept and the
+    /// notes can be fetched again.
+    pub async fn fetch_p2pk_notes(&self, outpoint: OutPoint) -> Result<TieredMulti<P2pkNote>> {
+        let issuance = self
+            .start_dbtx()
+            .await
+            .get_value(&P2pkOutputFinalizationKey(outpoint))
+            .await
+            .ok_or(MintClientError::FinalizationError(
+                NoteFinalizationError::UnknownIssuance,
+            ))?;
+
+        let bsig = self
+            .context
+            .api
+            .fetch_output_outcome::<MintOutputOutcome>(outpoint, &self.context.decoders)
+            .await?
+            .ok_or(MintClientError::OutputNotReadyYet(outpoint))?
+            .as_ref()
+            .cloned()
+            .ok_or(MintClientError::OutputNotReadyYet(outpoint))?;
+
+        Ok(issuance.finalize(bsig, &self.config.tbs_pks)?)
+    }
+
     pub async fn select_input(&self, amount: Amount) -> Result<(Vec<KeyPair>, Input)> {
         Self::ecash_input(self.select_notes(amount).await?)
     }
@@ -406,6 +731,7 @@ impl MintClient {
             .create_ecash(amount, notes_per_denomination, dbtx)
             .await;
         let out_point = create_tx(notes).await;
+        Self::record_requested(dbtx, finalization.note_amount()).await;
         dbtx.insert_new_entry(&OutputFinalizationKey(out_point), &finalization)
             .await;
     }
@@ -466,6 +792,10 @@ impl MintClient {
 
         let notes = issuance.finalize(bsig, &self.config.tbs_pks)?;
 
+        let mut ledger = Self::issuance_ledger(dbtx).await;
+        ledger.issued += notes.total_amount();
+        dbtx.insert_entry(&IssuanceLedgerKey, &ledger).await;
+
         for (amount, note) in notes.into_iter() {
             let key = NoteKey {
                 amount,
@@ -475,10 +805,96 @@ impl MintClient {
             dbtx.insert_new_entry(&key, &value).await;
         }
         dbtx.remove_entry(&OutputFinalizationKey(outpoint)).await;
+        Self::check_issuance_ceiling(dbtx).await;
 
         Ok(())
     }
 
+    pub async fn issuance_ledger(dbtx: &mut DatabaseTransaction<'_>) -> IssuanceLedger {
+        dbtx.get_value(&IssuanceLedgerKey).await.unwrap_or_default()
+    }
+
+    /// Adds `amount` to the ecash we expect the federation to issue
+    async fn record_requested(dbtx: &mut DatabaseTransaction<'_>, amount: Amount) {
+        let mut ledger = Self::issuance_ledger(dbtx).await;
+        ledger.requested += amount;
+        dbtx.insert_entry(&IssuanceLedgerKey, &ledger).await;
+    }
+
+    /// Checks that the ecash issued to us and the issuances still pending
+    /// don't exceed the ceiling of what we requested, warning if they do
+    ///
+    /// Runs with every change of the ledger, unlike [`Self::check_issuance`]
+    /// it needs no requests to the federation.
+    pub async fn check_issuance_ceiling(
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> Option<IssuanceDiscrepancy> {
+        let ledger = Self::issuance_ledger(dbtx).await;
+        let pending = dbtx
+            .find_by_prefix(&OutputFinalizationKeyPrefix)
+            .await
+            .map(|(_, issuance)| issuance.note_amount())
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .sum::<Amount>();
+
+        let accounted = ledger.issued + pending;
+        if accounted <= ledger.requested {
+            return None;
+        }
+        let discrepancy = IssuanceDiscrepancy::OverIssued {
+            amount: accounted - ledger.requested,
+        };
+        warn!(?discrepancy, ?ledger, %pending, "Ecash issuance exceeds the requested ceiling");
+        Some(discrepancy)
+    }
+
+    /// Compares the ecash requested from the federation with the ecash it
+    /// issued and the issuances still pending, warning about every mismatch
+    ///
+    /// Without the check, ecash that never arrives, e.g. the change of a
+    /// rejected transaction, only shows as a lower balance.
+    pub async fn check_issuance(&self) -> Result<Vec<IssuanceDiscrepancy>> {
+        let ledger = Self::issuance_ledger(&mut self.start_dbtx().await).await;
+
+        let mut discrepancies = vec![];
+        let mut pending = Amount::ZERO;
+        for (outpoint, issuance) in self.list_active_issuances().await {
+            let amount = issuance.note_amount();
+            pending += amount;
+            let status = self
+                .context
+                .api
+                .fetch_tx_outcome(&outpoint.txid)
+                .await
+                .map_err(OutputOutcomeError::from)?;
+            if let Some(TransactionStatus::Rejected(reason)) = status {
+                discrepancies.push(IssuanceDiscrepancy::Rejected {
+                    outpoint,
+                    amount,
+                    reason,
+                });
+            }
+        }
+
+        let accounted = ledger.issued + pending;
+        if accounted < ledger.requested {
+            discrepancies.push(IssuanceDiscrepancy::Missing {
+                amount: ledger.requested - accounted,
+            });
+        } else if ledger.requested < accounted {
+            discrepancies.push(IssuanceDiscrepancy::OverIssued {
+                amount: accounted - ledger.requested,
+            });
+        }
+
+        for discrepancy in &discrepancies {
+            warn!(?discrepancy, ?ledger, %pending, "Ecash issuance discrepancy");
+        }
+        Ok(discrepancies)
+    }
+
     pub async fn list_active_issuances(&self) -> Vec<(OutPoint, NoteIssuanceRequests)> {
         self.context
             .db
@@ -501,10 +917,11 @@ impl MintClient {
         let mut futures = FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
         for (outpoint, _) in active_issuances {
             futures.push(Box::pin(async {
-                let mut dbtx = self.context.db.begin_transaction().await;
-                let res = self.await_fetch_notes(&mut dbtx, outpoint).await;
-                dbtx.commit_tx().await;
-                res
+                self.context
+                    .db
+                    .autocommit_bounded(|dbtx| Box::pin(self.await_fetch_notes(dbtx, outpoint)))
+                    .await
+                    .map_err(MintClientError::from)
             }))
         }
 
@@ -624,6 +1041,26 @@ pub enum MintClientError {
     InvalidOutcomeType(OutPoint),
     #[error("One of the notes meant to be spent is unspendable")]
     ReceivedUspendableNote,
+    #[error("One of the notes meant to be spent was spent by a concurrent transaction")]
+    NoteAlreadySpent,
+    #[error("The note is not bound to the given key")]
+    WrongP2pkKey,
+    #[error("Invalid tier deprecation announced by the federation: {0}")]
+    InvalidTierDeprecation(anyhow::Error),
+    #[error("Failed to commit to the database after {0} attempts: {1}")]
+    CommitFailed(usize, anyhow::Error),
+}
+
+impl From<AutocommitError<MintClientError>> for MintClientError {
+    fn from(e: AutocommitError<MintClientError>) -> Self {
+        match e {
+            AutocommitError::CommitFailed {
+                attempts,
+                last_error,
+            } => MintClientError::CommitFailed(attempts, last_error),
+            AutocommitError::ClosureError { error, .. } => error,
+        }
+    }
 }
 
 impl MintClientError {
@@ -649,7 +1086,7 @@ impl From<InvalidAmountTierError> for NoteFinalizationError {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::{BTreeMap, HashSet};
+    use std::collections::{BTreeMap, BTreeSet, HashSet};
     use std::sync::Arc;
 
     use bitcoin::hashes::Hash;
@@ -660,18 +1097,23 @@ mod tests {
     };
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::Database;
+    use fedimint_core::encoding::Encodable;
     use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
     use fedimint_core::{Amount, OutPoint, ServerModule, Tiered, TransactionId};
     use fedimint_mint_server::{Mint, MintGen, MintGenParams};
     use fedimint_testing::FakeFed;
     use futures::executor::block_on;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
     use tokio::sync::Mutex;
 
     use crate::api::fake::FederationApiFaker;
-    use crate::mint::db::NextECashNoteIndexKey;
-    use crate::mint::MintClient;
-    use crate::modules::mint::config::MintClientConfig;
+    use crate::mint::db::{
+        IssuanceLedgerKey, NextECashNoteIndexKey, OutputFinalizationKey, P2pkOutputFinalizationKey,
+    };
+    use crate::mint::{IssuanceDiscrepancy, MintClient, MintClientError};
+    use crate::modules::mint::config::{MintClientConfig, TierDeprecation};
     use crate::modules::mint::MintOutput;
     use crate::transaction::legacy::Input;
     use crate::{
@@ -768,6 +1210,7 @@ mod tests {
             module_gens: Default::default(),
             db: Database::new(MemDatabase::new(), module_decode_stubs()),
             api: api.into(),
+            epoch_archives: vec![],
             secp: secp256k1_zkp::Secp256k1::new(),
         };
 
@@ -818,6 +1261,131 @@ mod tests {
         assert_eq!(client.notes().await.total_amount(), ISSUE_AMOUNT)
     }
 
+    #[test_log::test(tokio::test)]
+    async fn check_issuance() {
+        let (fed, client_config, client_context) = new_mint_and_client().await;
+
+        let context = Arc::new(client_context);
+        let client = MintClient {
+            epoch_pk: threshold_crypto::SecretKey::random().public_key(),
+            config: client_config,
+            context: context.clone(),
+            secret: DerivableSecret::new_root(&[], &[]).child_key(MINT_SECRET_CHILD_ID),
+        };
+
+        const ISSUE_AMOUNT: Amount = Amount::from_sats(12);
+        issue_notes(&fed, &client, &context.db, ISSUE_AMOUNT).await;
+
+        let mut dbtx = context.db.begin_transaction().await;
+        let ledger = MintClient::issuance_ledger(&mut dbtx).await;
+        assert_eq!(ledger.requested, ISSUE_AMOUNT);
+        assert_eq!(ledger.issued, ISSUE_AMOUNT);
+        assert_eq!(client.check_issuance().await.unwrap(), vec![]);
+
+        // An issuance that disappears without its notes being issued
+        let out_point = OutPoint {
+            txid: TransactionId::from_inner([0x43; 32]),
+            out_idx: 0,
+        };
+        client
+            .receive_notes(ISSUE_AMOUNT, &mut dbtx, |_| async { out_point })
+            .await;
+        dbtx.remove_entry(&OutputFinalizationKey(out_point)).await;
+        dbtx.commit_tx().await;
+
+        assert_eq!(
+            client.check_issuance().await.unwrap(),
+            vec![IssuanceDiscrepancy::Missing {
+                amount: ISSUE_AMOUNT
+            }]
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn issuance_ceiling_flags_over_issuance() {
+        let (fed, client_config, client_context) = new_mint_and_client().await;
+
+        let context = Arc::new(client_context);
+        let client = MintClient {
+            epoch_pk: threshold_crypto::SecretKey::random().public_key(),
+            config: client_config,
+            context: context.clone(),
+            secret: DerivableSecret::new_root(&[], &[]).child_key(MINT_SECRET_CHILD_ID),
+        };
+
+        const ISSUE_AMOUNT: Amount = Amount::from_sats(12);
+        issue_notes(&fed, &client, &context.db, ISSUE_AMOUNT).await;
+        let mut dbtx = context.db.begin_transaction().await;
+        assert_eq!(MintClient::check_issuance_ceiling(&mut dbtx).await, None);
+
+        // pending issuances count against the ceiling
+        let out_point = OutPoint {
+            txid: TransactionId::from_inner([0x43; 32]),
+            out_idx: 0,
+        };
+        client
+            .receive_notes(ISSUE_AMOUNT, &mut dbtx, |_| async { out_point })
+            .await;
+        assert_eq!(MintClient::check_issuance_ceiling(&mut dbtx).await, None);
+
+        // a ledger that lost track of a request
+        let mut ledger = MintClient::issuance_ledger(&mut dbtx).await;
+        ledger.requested = ISSUE_AMOUNT;
+        dbtx.insert_entry(&IssuanceLedgerKey, &ledger).await;
+        assert_eq!(
+            MintClient::check_issuance_ceiling(&mut dbtx).await,
+            Some(IssuanceDiscrepancy::OverIssued {
+                amount: ISSUE_AMOUNT
+            })
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn transactions_are_reproducible_from_seed() {
+        const SPEND_AMOUNT: Amount = Amount::from_sats(21);
+
+        let (fed, client_config, client_context) = new_mint_and_client().await;
+
+        let context = Arc::new(client_context);
+        let client = MintClient {
+            epoch_pk: threshold_crypto::SecretKey::random().public_key(),
+            config: client_config,
+            context: context.clone(),
+            secret: DerivableSecret::new_root(&[], &[]).child_key(MINT_SECRET_CHILD_ID),
+        };
+
+        issue_notes(&fed, &client, &context.db, SPEND_AMOUNT * 2).await;
+
+        // Builds a tx without committing it, so the change notes are derived
+        // from the same note index every time
+        let build = |seed: u64| {
+            let client = client.clone();
+            async move {
+                let mut dbtx = client.context.db.begin_transaction().await;
+                let notes = client.select_notes(SPEND_AMOUNT).await.unwrap();
+                let (mut spend_keys, ecash_input) = MintClient::ecash_input(notes).unwrap();
+
+                let mut builder = TransactionBuilder::default();
+                builder.input(&mut spend_keys, ecash_input);
+                let tx = builder
+                    .build_with_change(
+                        client.clone(),
+                        &mut dbtx,
+                        StdRng::seed_from_u64(seed),
+                        vec![Amount::from_sats(1), Amount::from_sats(10)],
+                        &client.context.secp,
+                    )
+                    .await
+                    .unwrap();
+                drop(dbtx);
+                tx.consensus_encode_to_vec().unwrap()
+            }
+        };
+
+        assert_eq!(build(42).await, build(42).await);
+        assert_ne!(build(42).await, build(43).await);
+    }
+
     #[test_log::test(tokio::test)]
     async fn create_input() {
         const SPEND_AMOUNT: Amount = Amount::from_sats(21);
@@ -853,7 +1421,8 @@ mod tests {
                 vec![Amount::from_sats(0)],
                 secp,
             )
-            .await;
+            .await
+            .unwrap();
         dbtx.commit_tx().await;
 
         if let Input::Mint(input) = ecash_input {
@@ -895,7 +1464,8 @@ mod tests {
                 vec![Amount::from_sats(0)],
                 secp,
             )
-            .await;
+            .await
+            .unwrap();
         dbtx.commit_tx().await;
 
         if let Input::Mint(input) = ecash_input {
@@ -914,6 +1484,105 @@ mod tests {
         }
     }
 
+    #[test_log::test(tokio::test)]
+    async fn no_notes_issued_in_deprecated_tiers() {
+        let (fed, client_config, client_context) = new_mint_and_client().await;
+
+        let context = Arc::new(client_context);
+        let client = MintClient {
+            epoch_pk: threshold_crypto::SecretKey::random().public_key(),
+            config: client_config,
+            context: context.clone(),
+            secret: DerivableSecret::new_root(&[], &[]).child_key(MINT_SECRET_CHILD_ID),
+        };
+
+        let mut dbtx = context.db.begin_transaction().await;
+        let deprecation = TierDeprecation {
+            tiers: BTreeSet::from([Amount::from_sats(10)]),
+            deadline: None,
+        };
+        client.set_tier_deprecation(&mut dbtx, &deprecation).await;
+        dbtx.commit_tx().await;
+
+        const ISSUE_AMOUNT: Amount = Amount::from_sats(45);
+        issue_notes(&fed, &client, &context.db, ISSUE_AMOUNT).await;
+
+        let notes = client.notes().await;
+        assert_eq!(notes.total_amount(), ISSUE_AMOUNT);
+        assert!(notes.get(Amount::from_sats(10)).is_none());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn p2pk_notes_need_recipient_key() {
+        const P2PK_AMOUNT: Amount = Amount::from_sats(12);
+
+        let (fed, client_config, client_context) = new_mint_and_client().await;
+
+        let context = Arc::new(client_context);
+        let client = MintClient {
+            epoch_pk: threshold_crypto::SecretKey::random().public_key(),
+            config: client_config,
+            context: context.clone(),
+            secret: DerivableSecret::new_root(&[], &[]).child_key(MINT_SECRET_CHILD_ID),
+        };
+        let secp = &client.context.secp;
+        let recipient = secp256k1_zkp::KeyPair::new(secp, &mut rand::rngs::OsRng);
+        let other = secp256k1_zkp::KeyPair::new(secp, &mut rand::rngs::OsRng);
+
+        let out_point = OutPoint {
+            txid: TransactionId::from_inner([0x42; 32]),
+            out_idx: 0,
+        };
+        let mut dbtx = context.db.begin_transaction().await;
+        let (issuance, blind_nonces) = client
+            .create_p2pk_ecash(
+                P2PK_AMOUNT,
+                recipient.x_only_public_key().0,
+                &mut dbtx,
+                rand::rngs::OsRng,
+            )
+            .await;
+        dbtx.insert_new_entry(&P2pkOutputFinalizationKey(out_point), &issuance)
+            .await;
+        dbtx.commit_tx().await;
+
+        let mut fed_lock = fed.lock().await;
+        fed_lock
+            .consensus_round(&[], &[(out_point, MintOutput(blind_nonces))])
+            .await;
+        fed_lock.consensus_round(&[], &[]).await;
+        drop(fed_lock);
+
+        let notes = client.fetch_p2pk_notes(out_point).await.unwrap();
+        assert_eq!(notes.total_amount(), P2PK_AMOUNT);
+        // The notes aren't ours to spend
+        assert_eq!(client.notes().await.total_amount(), Amount::ZERO);
+
+        for (_, note) in notes.iter_items() {
+            assert!(matches!(
+                note.into_spendable(secp, &other),
+                Err(MintClientError::WrongP2pkKey)
+            ));
+        }
+        let spendable = notes
+            .into_iter()
+            .map(|(amt, note)| (amt, note.into_spendable(secp, &recipient).unwrap()))
+            .collect();
+        let (spend_keys, ecash_input) = MintClient::ecash_input(spendable).unwrap();
+
+        if let Input::Mint(input) = ecash_input {
+            let meta = fed.lock().await.verify_input(&input).await.unwrap();
+            assert_eq!(meta.amount.amount, P2PK_AMOUNT);
+            assert_eq!(
+                meta.keys,
+                spend_keys
+                    .into_iter()
+                    .map(|key| secp256k1_zkp::XOnlyPublicKey::from_keypair(&key).0)
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
     #[allow(clippy::needless_collect)]
     #[tokio::test(flavor = "multi_thread")]
     async fn test_parallel_issuance() {
@@ -939,6 +1608,7 @@ mod tests {
                 module_gens: Default::default(),
                 db: Database::new(db, module_decode_stubs()),
                 api: WsFederationApi::new(vec![]).into(),
+                epoch_archives: vec![],
                 secp: Default::default(),
             }),
             secret: DerivableSecret::new_root(&[], &[]).child_key(MINT_SECRET_CHILD_ID),
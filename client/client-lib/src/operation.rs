@@ -0,0 +1,74 @@
+//! Tracks operations of the client that take several steps to complete, so
+//! they can be queried and cancelled while they are in flight
+
+pub use fedimint_client::sm::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, OutPoint, TransactionId};
+use serde::{Deserialize, Serialize};
+
+use crate::modules::ln::contracts::ContractId;
+
+/// What an operation does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum OperationKind {
+    /// Funding an outgoing contract to pay an invoice
+    OutgoingPayment { contract_id: ContractId },
+    /// Sending `amount` on-chain to `recipient`
+    PegOut {
+        recipient: bitcoin::Address,
+        #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+        amount: bitcoin::Amount,
+    },
+    /// Claiming the on-chain deposit `outpoint` to our peg-in address
+    PegIn { outpoint: bitcoin::OutPoint },
+    /// Claiming an incoming contract funded by a gateway paying our invoice
+    IncomingPayment { contract_id: ContractId },
+    /// Exchanging notes we received for freshly issued ones
+    Reissue { amount: Amount },
+}
+
+/// Progress of an operation, see [`OperationState::is_final`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum OperationState {
+    /// The transaction of the operation is being built, cancelling it only
+    /// stops the submission
+    Created,
+    /// The transaction is being built, the notes it spends and this state are
+    /// committed together once it is
+    Submitting,
+    /// The transaction spending our notes was built and handed to the
+    /// federation
+    Submitted { txid: TransactionId },
+    /// The federation accepted the transaction and, for outgoing payments,
+    /// the gateway claimed the contract
+    Completed { txid: TransactionId },
+    /// Building or submitting the transaction failed, or the federation
+    /// rejected it
+    Failed { error: String },
+    /// The operation was cancelled before its transaction was submitted
+    Cancelled,
+    /// The funds of the operation were refunded to the client, the change
+    /// e-cash is issued at `out_point`
+    Refunded { out_point: OutPoint },
+}
+
+impl OperationState {
+    /// Returns true if the state of the operation won't change anymore
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            OperationState::Completed { .. }
+                | OperationState::Failed { .. }
+                | OperationState::Cancelled
+                | OperationState::Refunded { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct Operation {
+    pub kind: OperationKind,
+    pub state: OperationState,
+    /// Seconds since the unix epoch the operation was created at
+    pub created_at: u64,
+}
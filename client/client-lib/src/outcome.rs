@@ -1,9 +1,5 @@
 pub mod legacy {
     use fedimint_core::api::OutputOutcomeError;
-    use fedimint_core::core::{
-        LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
-        LEGACY_HARDCODED_INSTANCE_ID_WALLET,
-    };
     use fedimint_core::encoding::{Decodable, Encodable};
     use fedimint_core::module::ModuleCommon;
     use fedimint_core::CoreError;
@@ -23,27 +19,21 @@ pub mod legacy {
     }
 
     impl From<fedimint_core::core::DynOutputOutcome> for OutputOutcome {
+        /// Converts by the type of the outcome, which the decoder of its
+        /// module instance determined, rather than by the instance id
         fn from(oo: fedimint_core::core::DynOutputOutcome) -> Self {
-            match oo.module_instance_id() {
-                LEGACY_HARDCODED_INSTANCE_ID_LN => OutputOutcome::LN(
-                    oo.as_any()
-                        .downcast_ref::<LightningOutputOutcome>()
-                        .expect("Module key matches")
-                        .clone(),
-                ),
-                LEGACY_HARDCODED_INSTANCE_ID_MINT => OutputOutcome::Mint(
-                    oo.as_any()
-                        .downcast_ref::<MintOutputOutcome>()
-                        .expect("Module key matches")
-                        .clone(),
-                ),
-                LEGACY_HARDCODED_INSTANCE_ID_WALLET => OutputOutcome::Wallet(
-                    oo.as_any()
-                        .downcast_ref::<WalletOutputOutcome>()
-                        .expect("Module key matches")
-                        .clone(),
-                ),
-                _ => panic!("Unknown Module"),
+            let any = oo.as_any();
+            if let Some(outcome) = any.downcast_ref::<LightningOutputOutcome>() {
+                OutputOutcome::LN(outcome.clone())
+            } else if let Some(outcome) = any.downcast_ref::<MintOutputOutcome>() {
+                OutputOutcome::Mint(outcome.clone())
+            } else if let Some(outcome) = any.downcast_ref::<WalletOutputOutcome>() {
+                OutputOutcome::Wallet(outcome.clone())
+            } else {
+                panic!(
+                    "Outcome of unsupported module instance {}",
+                    oo.module_instance_id()
+                )
             }
         }
     }
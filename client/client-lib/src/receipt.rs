@@ -0,0 +1,119 @@
+//! Receipts proving that the federation accepted a transaction, e.g. for a
+//! merchant to show that a payment happened
+//!
+//! A receipt only names the epoch the transaction was accepted in, anyone
+//! holding the federation's public config can verify it with
+//! [`verify_receipt`], which fetches the epoch signed by the guardians, or
+//! with [`verify_receipt_epoch`] against an epoch they already have.
+
+use fedimint_core::api::{GlobalFederationApi, WsFederationApi};
+use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::epoch::{ConsensusItem, EpochVerifyError, SignedEpochOutcome};
+use fedimint_core::transaction::Transaction;
+use fedimint_core::{OutPoint, TransactionId};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::module_decode_stubs;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentReceipt {
+    pub federation_id: FederationId,
+    pub txid: TransactionId,
+    /// Output of the transaction the receipt is about, e.g. the contract
+    /// funded by a lightning payment
+    pub out_point: Option<OutPoint>,
+    /// Epoch the federation accepted the transaction in
+    pub epoch: u64,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ReceiptError {
+    #[error("The receipt is for federation {0}")]
+    WrongFederation(FederationId),
+    #[error("The epoch of the receipt can't be fetched: {0}")]
+    EpochUnavailable(String),
+    #[error("The receipt is for epoch {0}")]
+    WrongEpoch(u64),
+    #[error("The epoch of the receipt doesn't match its hash")]
+    InvalidEpochHash,
+    #[error("The epoch of the receipt is not signed by the federation")]
+    MissingSignature,
+    #[error("The signature of the federation over the epoch is invalid")]
+    InvalidSignature,
+    #[error("Transaction {0} is not part of the epoch")]
+    TransactionNotIncluded(TransactionId),
+    #[error("The federation rejected transaction {0}")]
+    TransactionRejected(TransactionId),
+    #[error("Transaction {txid} has no output {out_point}")]
+    InvalidOutPoint {
+        txid: TransactionId,
+        out_point: OutPoint,
+    },
+}
+
+/// Verifies a receipt against the epoch signed by the guardians of the
+/// federation in `config` and returns the accepted transaction, so its outputs
+/// can be inspected
+pub async fn verify_receipt(
+    receipt: &PaymentReceipt,
+    config: &ClientConfig,
+) -> Result<Transaction, ReceiptError> {
+    if receipt.federation_id != config.federation_id {
+        return Err(ReceiptError::WrongFederation(receipt.federation_id.clone()));
+    }
+
+    let epoch = WsFederationApi::from_config(config)
+        .fetch_epoch_history(receipt.epoch, config.epoch_pk, &module_decode_stubs())
+        .await
+        .map_err(|e| ReceiptError::EpochUnavailable(e.to_string()))?;
+    verify_receipt_epoch(receipt, config, epoch)
+}
+
+/// Verifies a receipt against `epoch`, which has to be signed by the
+/// federation in `config`, and returns the accepted transaction
+pub fn verify_receipt_epoch(
+    receipt: &PaymentReceipt,
+    config: &ClientConfig,
+    epoch: SignedEpochOutcome,
+) -> Result<Transaction, ReceiptError> {
+    if receipt.federation_id != config.federation_id {
+        return Err(ReceiptError::WrongFederation(receipt.federation_id.clone()));
+    }
+    if epoch.outcome.epoch != receipt.epoch {
+        return Err(ReceiptError::WrongEpoch(receipt.epoch));
+    }
+    if epoch.outcome.consensus_hash().ok() != Some(epoch.hash) {
+        return Err(ReceiptError::InvalidEpochHash);
+    }
+    epoch.verify_sig(&config.epoch_pk).map_err(|e| match e {
+        EpochVerifyError::MissingSignature => ReceiptError::MissingSignature,
+        _ => ReceiptError::InvalidSignature,
+    })?;
+
+    let transaction = epoch
+        .outcome
+        .items
+        .into_iter()
+        .flat_map(|(_, items)| items)
+        .find_map(|item| match item {
+            ConsensusItem::Transaction(tx) if tx.tx_hash() == receipt.txid => Some(tx),
+            _ => None,
+        })
+        .ok_or(ReceiptError::TransactionNotIncluded(receipt.txid))?;
+    if epoch.outcome.rejected_txs.contains(&receipt.txid) {
+        return Err(ReceiptError::TransactionRejected(receipt.txid));
+    }
+
+    if let Some(out_point) = receipt.out_point {
+        if out_point.txid != receipt.txid || out_point.out_idx >= transaction.outputs.len() as u64 {
+            return Err(ReceiptError::InvalidOutPoint {
+                txid: receipt.txid,
+                out_point,
+            });
+        }
+    }
+
+    Ok(transaction)
+}
@@ -0,0 +1,137 @@
+//! Deterministic derivation of the client's secrets from a BIP-39 mnemonic
+//!
+//! Wallets restoring the same mnemonic derive the same secrets, so a user can
+//! move between them and recover their ecash from the federations' backups.
+//! The derivation works as follows:
+//!
+//! 1. The seed is the mnemonic's BIP-39 seed with an empty passphrase.
+//! 2. The root secret is [`DerivableSecret::new_root`] of the seed salted with
+//!    [`ROOT_SECRET_SALT`].
+//! 3. The secret of a federation is the root's child
+//!    [`FEDERATION_SECRETS_CHILD_ID`], then that secret's child given by the
+//!    first 8 bytes, as little endian integer, of the SHA-256 hash of the
+//!    federation id.
+//!
+//! Every child key is derived with HKDF, so knowing a federation's secret
+//! reveals nothing about the root or other federations' secrets. Module secrets
+//! like the mint's [`crate::MINT_SECRET_CHILD_ID`] are children of the
+//! federation secret.
+//!
+//! Clients that generated a random [`crate::ClientSecret`] before mnemonics
+//! existed keep using it as federation secret, since their notes were derived
+//! from it. Their secret can't be exported as mnemonic.
+
+use std::fmt::{Debug, Formatter};
+
+pub use bip39::Mnemonic;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::config::FederationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_derive_secret::{ChildId, DerivableSecret};
+use rand::{CryptoRng, RngCore};
+use serde::Serialize;
+
+/// Salt of the root secret derived from the mnemonic's seed
+pub const ROOT_SECRET_SALT: &[u8] = b"Fedimint Client Salt";
+/// Child of the root secret whose children are the federation secrets
+pub const FEDERATION_SECRETS_CHILD_ID: ChildId = ChildId(0);
+/// Entropy of newly generated mnemonics, which makes them 12 words long
+const MNEMONIC_ENTROPY_BYTES: usize = 16;
+
+/// Entropy of the client's mnemonic as stored in the database
+#[derive(Clone, Eq, PartialEq, Encodable, Decodable)]
+pub struct ClientMnemonic(Vec<u8>);
+
+impl ClientMnemonic {
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        let mut entropy = [0u8; MNEMONIC_ENTROPY_BYTES];
+        rng.fill_bytes(&mut entropy);
+        Self(entropy.to_vec())
+    }
+
+    pub fn to_mnemonic(&self) -> Mnemonic {
+        Mnemonic::from_entropy(&self.0).expect("only valid entropy is stored")
+    }
+
+    /// Secret all federation secrets are derived from
+    pub fn root_secret(&self) -> DerivableSecret {
+        DerivableSecret::new_root(&self.to_mnemonic().to_seed(""), ROOT_SECRET_SALT)
+    }
+
+    /// Secret of the client of `federation_id`
+    pub fn federation_secret(&self, federation_id: &FederationId) -> DerivableSecret {
+        self.root_secret()
+            .child_key(FEDERATION_SECRETS_CHILD_ID)
+            .child_key(federation_child_id(federation_id))
+    }
+}
+
+impl From<&Mnemonic> for ClientMnemonic {
+    fn from(mnemonic: &Mnemonic) -> Self {
+        Self(mnemonic.to_entropy())
+    }
+}
+
+impl Serialize for ClientMnemonic {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl Debug for ClientMnemonic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ClientMnemonic([redacted])")
+    }
+}
+
+/// Child of [`FEDERATION_SECRETS_CHILD_ID`] holding the secret of
+/// `federation_id`
+pub fn federation_child_id(federation_id: &FederationId) -> ChildId {
+    let hash = sha256::Hash::hash(&federation_id.0.to_bytes());
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&hash[..8]);
+    ChildId(u64::from_le_bytes(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use fedimint_core::config::FederationId;
+
+    use super::{ClientMnemonic, Mnemonic};
+
+    const WORDS: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    fn federation_id() -> FederationId {
+        FederationId(threshold_crypto::SecretKey::random().public_key())
+    }
+
+    #[test]
+    fn imported_mnemonic_derives_same_federation_secrets() {
+        let mnemonic = Mnemonic::from_str(WORDS).unwrap();
+        let generated = ClientMnemonic::generate(&mut rand::rngs::OsRng);
+        let imported = ClientMnemonic::from(&mnemonic);
+        assert_eq!(imported.to_mnemonic().to_string(), WORDS);
+        assert_eq!(generated.to_mnemonic().word_count(), 12);
+
+        let (fed_a, fed_b) = (federation_id(), federation_id());
+        let restored = ClientMnemonic::from(&imported.to_mnemonic());
+        assert_eq!(
+            format!("{:?}", imported.federation_secret(&fed_a)),
+            format!("{:?}", restored.federation_secret(&fed_a))
+        );
+        assert_ne!(
+            format!("{:?}", imported.federation_secret(&fed_a)),
+            format!("{:?}", imported.federation_secret(&fed_b))
+        );
+        assert_ne!(
+            format!("{:?}", imported.federation_secret(&fed_a)),
+            format!("{:?}", generated.federation_secret(&fed_a))
+        );
+    }
+}
@@ -0,0 +1,384 @@
+//! Sub-accounts of a client, e.g. the allowance of a child
+//!
+//! A sub-account holds its own notes, derived from its own branch of the
+//! client's secret and stored in its own part of the client's database, so its
+//! balance is separate from the client's. It shares the client's config,
+//! database and connections to the guardians instead of running a client of
+//! its own. What a sub-account spends can be limited per period. The limit is
+//! only enforced by this client, anyone with the client's secret can spend all
+//! notes of its sub-accounts.
+
+use std::fmt::{Display, Formatter};
+
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::Database;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, OutPoint, TieredMulti};
+use fedimint_derive_secret::{ChildId, DerivableSecret};
+use lightning_invoice::Invoice;
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::db::SubAccountKey;
+use crate::ln::incoming::ConfirmedInvoice;
+use crate::ln::{LnClient, LnClientError};
+use crate::mint::SpendableNote;
+use crate::modules::ln::contracts::ContractId;
+use crate::{Client, ClientError, IncomingPaymentOutcome, Result, UserClientConfig};
+
+/// Isolated key space of the first sub-account in the client's database, far
+/// above the instance ids of modules
+const SUB_ACCOUNT_DB_OFFSET: ModuleInstanceId = 0x8000;
+/// Salt of the root secret of a sub-account
+const SUB_ACCOUNT_SECRET_SALT: &[u8] = b"Fedimint Sub-Account Salt";
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Encodable,
+    Decodable,
+    Serialize,
+    Deserialize,
+)]
+pub struct SubAccountId(pub u16);
+
+impl SubAccountId {
+    /// Largest id, so the key spaces of all sub-accounts fit above
+    /// [`SUB_ACCOUNT_DB_OFFSET`]
+    pub const MAX: SubAccountId = SubAccountId(ModuleInstanceId::MAX - SUB_ACCOUNT_DB_OFFSET);
+
+    /// Key space of the sub-account in the client's database, see
+    /// [`Database::new_isolated`]
+    pub fn db_instance_id(self) -> ModuleInstanceId {
+        SUB_ACCOUNT_DB_OFFSET + self.0
+    }
+
+    /// Root secret the sub-account's notes are derived from like the notes of
+    /// a client from its secret, a child of the client's `root_secret`
+    pub fn root_secret(self, root_secret: &DerivableSecret) -> DerivableSecret {
+        let seed: [u8; 64] = root_secret
+            .child_key(crate::SUB_ACCOUNTS_SECRET_CHILD_ID)
+            .child_key(ChildId(u64::from(self.0)))
+            .to_random_bytes();
+        DerivableSecret::new_root(&seed, SUB_ACCOUNT_SECRET_SALT)
+    }
+}
+
+impl Display for SubAccountId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Most a sub-account may spend within any `period_secs` long window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct SpendLimit {
+    pub amount: Amount,
+    pub period_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct SubAccountSpend {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct SubAccount {
+    pub name: String,
+    /// Spending is unlimited if `None`
+    pub limit: Option<SpendLimit>,
+    /// Spends that still count towards the limit
+    pub spends: Vec<SubAccountSpend>,
+}
+
+impl SubAccount {
+    pub fn new(name: String, limit: Option<SpendLimit>) -> Self {
+        Self {
+            name,
+            limit,
+            spends: vec![],
+        }
+    }
+
+    /// What the sub-account spent in the window of its limit ending at `now`
+    pub fn spent(&self, now: u64) -> Amount {
+        let Some(limit) = self.limit else {
+            return Amount::ZERO;
+        };
+        self.spends
+            .iter()
+            .filter(|spend| spend.timestamp + limit.period_secs > now)
+            .map(|spend| spend.amount)
+            .sum()
+    }
+
+    /// What the sub-account can still spend at `now`, `None` if it isn't
+    /// limited
+    pub fn remaining(&self, now: u64) -> Option<Amount> {
+        self.limit
+            .map(|limit| limit.amount.saturating_sub(self.spent(now)))
+    }
+
+    /// Counts a spend of `amount` at `now` towards the limit, fails if it
+    /// would exceed it
+    pub fn charge(&mut self, now: u64, amount: Amount) -> Result<SubAccountSpend> {
+        if let Some(remaining) = self.remaining(now) {
+            if amount > remaining {
+                return Err(ClientError::SpendLimitExceeded { amount, remaining });
+            }
+        }
+
+        // spends that left the window don't count anymore
+        if let Some(limit) = self.limit {
+            self.spends
+                .retain(|spend| spend.timestamp + limit.period_secs > now);
+        }
+        let spend = SubAccountSpend {
+            timestamp: now,
+            amount,
+        };
+        if self.limit.is_some() {
+            self.spends.push(spend.clone());
+        }
+        Ok(spend)
+    }
+
+    /// Takes back a spend that failed, so it doesn't count towards the limit
+    pub fn refund(&mut self, spend: &SubAccountSpend) {
+        if let Some(idx) = self.spends.iter().position(|s| s == spend) {
+            self.spends.remove(idx);
+        }
+    }
+}
+
+/// Handle to spend from and receive into a sub-account, see
+/// [`Client::sub_account`]
+///
+/// Spending through the handle counts towards the sub-account's limit.
+pub struct SubAccountClient {
+    id: SubAccountId,
+    /// Database of the client the sub-account belongs to, which keeps the
+    /// sub-account's limit
+    parent_db: Database,
+    client: Client<UserClientConfig>,
+}
+
+impl SubAccountClient {
+    pub(crate) fn new(
+        id: SubAccountId,
+        parent_db: Database,
+        client: Client<UserClientConfig>,
+    ) -> Self {
+        Self {
+            id,
+            parent_db,
+            client,
+        }
+    }
+
+    pub fn id(&self) -> SubAccountId {
+        self.id
+    }
+
+    /// The notes of the sub-account, without the spending limit, for the
+    /// client the sub-account belongs to only
+    pub(crate) fn unlimited(&self) -> &Client<UserClientConfig> {
+        &self.client
+    }
+
+    pub async fn balance(&self) -> Amount {
+        self.client.notes().await.total_amount()
+    }
+
+    /// What the sub-account can still spend in the current period, `None` if
+    /// it isn't limited
+    pub async fn remaining(&self) -> Result<Option<Amount>> {
+        Ok(self.info().await?.remaining(crate::unix_now()))
+    }
+
+    pub async fn info(&self) -> Result<SubAccount> {
+        self.parent_db
+            .begin_transaction()
+            .await
+            .get_value(&SubAccountKey(self.id))
+            .await
+            .ok_or(ClientError::UnknownSubAccount(self.id))
+    }
+
+    /// Reissues `notes` received by the sub-account
+    pub async fn reissue<R: RngCore + CryptoRng>(
+        &self,
+        notes: TieredMulti<SpendableNote>,
+        rng: R,
+    ) -> Result<OutPoint> {
+        self.client.reissue(notes, rng).await
+    }
+
+    pub async fn fetch_all_notes(&self) -> Result<Vec<OutPoint>> {
+        self.client.fetch_all_notes().await
+    }
+
+    /// Creates an invoice paying into the sub-account, see
+    /// [`Client::await_incoming_payment`] to claim it
+    pub async fn generate_confirmed_invoice<R: RngCore + CryptoRng>(
+        &self,
+        amount: Amount,
+        description: String,
+        rng: R,
+        expiry_time: Option<u64>,
+    ) -> Result<ConfirmedInvoice> {
+        self.client
+            .generate_confirmed_invoice(amount, description, rng, expiry_time)
+            .await
+    }
+
+    pub async fn await_incoming_payment(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<IncomingPaymentOutcome> {
+        self.client.await_incoming_payment(contract_id, rng).await
+    }
+
+    /// Like [`Client::spend_ecash`], fails if `amount` exceeds what the
+    /// sub-account can still spend
+    pub async fn spend_ecash<R: RngCore + CryptoRng>(
+        &self,
+        amount: Amount,
+        rng: R,
+    ) -> Result<TieredMulti<SpendableNote>> {
+        let spend = self.charge(amount).await?;
+        let result = self.client.spend_ecash(amount, rng).await;
+        if result.is_err() {
+            self.refund(spend).await?;
+        }
+        result
+    }
+
+    /// Like [`Client::fund_outgoing_ln_contract`], the whole contract including
+    /// the gateway's fee margin counts towards the limit
+    pub async fn fund_outgoing_ln_contract<R: RngCore + CryptoRng>(
+        &self,
+        invoice: Invoice,
+        rng: R,
+    ) -> Result<(ContractId, OutPoint)> {
+        let invoice_amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .ok_or(ClientError::InvoiceMissingAmount)?,
+        );
+        let contract_amount = LnClient::outgoing_contract_amount(invoice_amount)
+            .ok_or(LnClientError::InvoiceAmountTooLarge)?;
+
+        let spend = self.charge(contract_amount).await?;
+        let result = self.client.fund_outgoing_ln_contract(invoice, rng).await;
+        if result.is_err() {
+            self.refund(spend).await?;
+        }
+        result
+    }
+
+    async fn charge(&self, amount: Amount) -> Result<SubAccountSpend> {
+        let id = self.id;
+        let now = crate::unix_now();
+        self.parent_db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    let mut account = dbtx
+                        .get_value(&SubAccountKey(id))
+                        .await
+                        .ok_or(ClientError::UnknownSubAccount(id))?;
+                    let spend = account.charge(now, amount)?;
+                    dbtx.insert_entry(&SubAccountKey(id), &account).await;
+                    Ok::<_, ClientError>(spend)
+                })
+            })
+            .await
+            .map_err(ClientError::from)
+    }
+
+    async fn refund(&self, spend: SubAccountSpend) -> Result<()> {
+        let id = self.id;
+        let spend = &spend;
+        self.parent_db
+            .autocommit_bounded(|dbtx| {
+                Box::pin(async move {
+                    let mut account = dbtx
+                        .get_value(&SubAccountKey(id))
+                        .await
+                        .ok_or(ClientError::UnknownSubAccount(id))?;
+                    account.refund(spend);
+                    dbtx.insert_entry(&SubAccountKey(id), &account).await;
+                    Ok::<_, ClientError>(())
+                })
+            })
+            .await
+            .map_err(ClientError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+    use fedimint_derive_secret::DerivableSecret;
+
+    use super::{SpendLimit, SubAccount, SubAccountId};
+    use crate::ClientError;
+
+    #[test]
+    fn spends_count_within_their_period() {
+        let mut account = SubAccount::new(
+            "pocket money".to_string(),
+            Some(SpendLimit {
+                amount: Amount::from_sats(1_000),
+                period_secs: 100,
+            }),
+        );
+
+        account.charge(0, Amount::from_sats(600)).unwrap();
+        let spend = account.charge(50, Amount::from_sats(400)).unwrap();
+        assert!(matches!(
+            account.charge(60, Amount::from_sats(1)),
+            Err(ClientError::SpendLimitExceeded { .. })
+        ));
+
+        account.refund(&spend);
+        assert_eq!(account.remaining(60), Some(Amount::from_sats(400)));
+
+        // the first spend left the window
+        account.charge(100, Amount::from_sats(1_000)).unwrap();
+        assert_eq!(account.spends.len(), 1);
+        assert_eq!(account.remaining(199), Some(Amount::ZERO));
+    }
+
+    #[test]
+    fn unlimited_accounts_keep_no_spends() {
+        let mut account = SubAccount::new("savings".to_string(), None);
+        account
+            .charge(0, Amount::from_sats(u64::MAX / 1_000))
+            .unwrap();
+        assert!(account.spends.is_empty());
+        assert_eq!(account.remaining(0), None);
+    }
+
+    #[test]
+    fn sub_accounts_derive_separate_secrets() {
+        let root = DerivableSecret::new_root(&[42; 32], &[]);
+        let first = SubAccountId(0).root_secret(&root);
+        let second = SubAccountId(1).root_secret(&root);
+        assert_eq!(first.level(), 0);
+        assert_ne!(
+            first.to_random_bytes::<32>(),
+            second.to_random_bytes::<32>()
+        );
+        assert_eq!(SubAccountId::MAX.db_instance_id(), u16::MAX);
+    }
+}
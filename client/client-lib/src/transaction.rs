@@ -10,9 +10,12 @@ use secp256k1::Secp256k1;
 
 use crate::modules::ln::contracts::ContractOutcome;
 use crate::modules::ln::LightningOutputOutcome;
+use crate::modules::mint::{MintInput, Nonce};
 use crate::outcome::legacy::OutputOutcome;
 use crate::transaction::legacy::{Input, Output, Transaction};
-use crate::{module_decode_stubs, Client, DecryptedPreimage, MintClient, MintOutputOutcome};
+use crate::{
+    module_decode_stubs, Client, DecryptedPreimage, MintClient, MintClientError, MintOutputOutcome,
+};
 
 /// Old transaction definition used by old client.
 pub mod legacy {
@@ -196,6 +199,7 @@ pub trait Final {
     fn is_final(&self) -> bool;
 }
 
+#[derive(Clone)]
 pub struct TransactionBuilder {
     keys: Vec<KeyPair>,
     tx: Transaction,
@@ -267,12 +271,17 @@ impl TransactionBuilder {
     }
 
     /// Builds and signs the final transaction with correct change
+    ///
+    /// Besides the state of the client the transaction only depends on `rng`,
+    /// so building it twice with the same seed yields byte-identical
+    /// transactions. Only use seeded rngs in tests, the signing nonces are
+    /// derived from it.
     pub async fn build<C: AsRef<ClientConfig> + Clone + Send, R: RngCore + CryptoRng>(
         self,
         client: &Client<C>,
         dbtx: &mut DatabaseTransaction<'_>,
         rng: R,
-    ) -> Transaction {
+    ) -> Result<Transaction, MintClientError> {
         let change =
             self.input_amount(client) - self.output_amount(client) - self.fee_amount(client);
         self.build_with_change(
@@ -295,10 +304,10 @@ impl TransactionBuilder {
         mut rng: R,
         change: Vec<Amount>,
         secp: &Secp256k1<secp256k1_zkp::All>,
-    ) -> Transaction {
+    ) -> Result<Transaction, MintClientError> {
         change_module
             .finalize_change(&mut self.tx, dbtx, change)
-            .await;
+            .await?;
 
         let txid = self.tx.tx_hash();
         if !self.keys.is_empty() {
@@ -307,7 +316,52 @@ impl TransactionBuilder {
             self.tx.signature = Some(signature);
         }
 
+        Ok(self.tx)
+    }
+
+    /// Number of outputs added so far, change outputs are added after them
+    pub fn num_outputs(&self) -> usize {
+        self.tx.outputs.len()
+    }
+
+    /// Nonces of the notes spent by the e-cash inputs
+    pub fn nonces(&self) -> Vec<Nonce> {
         self.tx
+            .inputs
+            .iter()
+            .filter_map(|input| match input {
+                Input::Mint(MintInput(notes)) => Some(notes.iter_items().map(|(_, note)| note.0)),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Removes the notes with the given nonces from the e-cash inputs, e.g.
+    /// because they were spent by another transaction, and returns their
+    /// total amount
+    pub fn remove_notes(&mut self, nonces: &[Nonce]) -> Amount {
+        let mut removed = Amount::ZERO;
+        for input in &mut self.tx.inputs {
+            if let Input::Mint(MintInput(notes)) = input {
+                removed += notes
+                    .iter_items()
+                    .filter(|(_, note)| nonces.contains(&note.0))
+                    .map(|(amount, _)| amount)
+                    .sum();
+                *notes = notes
+                    .iter_items()
+                    .filter(|(_, note)| !nonces.contains(&note.0))
+                    .map(|(amount, note)| (amount, *note))
+                    .collect();
+            }
+        }
+        self.tx.inputs.retain(
+            |input| !matches!(input, Input::Mint(MintInput(notes)) if notes.count_items() == 0),
+        );
+        self.keys
+            .retain(|key| !nonces.contains(&Nonce(key.x_only_public_key().0)));
+        removed
     }
 
     fn input_amount_iter<'a, C>(
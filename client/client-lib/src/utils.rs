@@ -11,7 +11,7 @@ use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::{ParseAmountError, PeerId, TieredMulti};
 use lightning_invoice::Currency;
 
-use crate::mint::SpendableNote;
+use crate::mint::{P2pkNote, SpendableNote};
 
 pub fn parse_ecash(s: &str) -> anyhow::Result<TieredMulti<SpendableNote>> {
     let bytes = base64::decode(s)?;
@@ -27,6 +27,20 @@ pub fn serialize_ecash(c: &TieredMulti<SpendableNote>) -> String {
     base64::encode(&bytes)
 }
 
+pub fn parse_p2pk_ecash(s: &str) -> anyhow::Result<TieredMulti<P2pkNote>> {
+    let bytes = base64::decode(s)?;
+    Ok(Decodable::consensus_decode(
+        &mut std::io::Cursor::new(bytes),
+        &ModuleDecoderRegistry::default(),
+    )?)
+}
+
+pub fn serialize_p2pk_ecash(c: &TieredMulti<P2pkNote>) -> String {
+    let mut bytes = Vec::new();
+    Encodable::consensus_encode(c, &mut bytes).expect("encodes correctly");
+    base64::encode(&bytes)
+}
+
 pub fn from_hex<D: Decodable>(s: &str) -> Result<D, anyhow::Error> {
     let bytes = Vec::from_hex(s)?;
     Ok(D::consensus_decode(
@@ -65,12 +79,15 @@ pub fn parse_peer_id(s: &str) -> Result<PeerId, ParseIntError> {
     Ok(PeerId::from(s.parse::<u16>()?))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientContext {
     pub decoders: ModuleDecoderRegistry,
     pub module_gens: ClientModuleGenRegistry,
     pub db: Database,
     pub api: DynFederationApi,
+    /// Archives of the epoch history run by third parties, asked for the
+    /// epochs the guardians and their archives don't keep anymore
+    pub epoch_archives: Vec<DynFederationApi>,
     pub secp: secp256k1_zkp::Secp256k1<secp256k1_zkp::All>,
 }
 
@@ -8,6 +8,7 @@ use fedimint_core::core::Decoder;
 use fedimint_core::db::DatabaseTransaction;
 use fedimint_core::module::{ModuleCommon, TransactionItemAmount};
 use fedimint_core::Amount;
+use futures::Future;
 use rand::{CryptoRng, RngCore};
 use thiserror::Error;
 use tracing::debug;
@@ -15,12 +16,18 @@ use tracing::debug;
 use crate::modules::wallet::config::WalletClientConfig;
 use crate::modules::wallet::tweakable::Tweakable;
 use crate::modules::wallet::txoproof::{PegInProof, PegInProofError, TxOutProof};
-use crate::modules::wallet::{WalletInput, WalletModuleTypes, WalletOutput, WalletOutputOutcome};
+use crate::modules::wallet::{
+    PegOut, WalletInput, WalletModuleTypes, WalletOutput, WalletOutputOutcome,
+};
 use crate::utils::ClientContext;
 use crate::MemberError;
 
 pub mod db;
 
+/// Number of times we fetch the fees of a peg-out of our whole balance before
+/// giving up on finding an amount covering them
+const MAX_PEG_OUT_FEE_ROUNDS: usize = 3;
+
 /// Federation module client for the Wallet module. It can both create
 /// transaction inputs and outputs of the wallet (on-chain) type.
 #[derive(Debug)]
@@ -38,9 +45,15 @@ impl ClientModule for WalletClient {
     }
 
     fn input_amount(&self, input: &WalletInput) -> TransactionItemAmount {
-        TransactionItemAmount {
-            amount: Amount::from_sats(input.tx_output().value),
-            fee: self.config.fee_consensus.peg_in_abs,
+        match input {
+            WalletInput::PegIn(proof) => TransactionItemAmount {
+                amount: Amount::from_sats(proof.tx_output().value),
+                fee: self.config.fee_consensus.peg_in_abs,
+            },
+            WalletInput::Rbf(rbf) => TransactionItemAmount {
+                amount: Amount::ZERO,
+                fee: self.config.fee_consensus.peg_out_abs + rbf.fees.amount().into(),
+            },
         }
     }
 
@@ -97,34 +110,50 @@ impl WalletClient {
         address
     }
 
+    /// Finds the output of `btc_transaction` paying one of our peg-in
+    /// addresses and the tweak secret of that address
+    async fn find_pegin_output(
+        &self,
+        btc_transaction: &bitcoin::Transaction,
+    ) -> Option<(usize, [u8; 32])> {
+        for (idx, out) in btc_transaction.output.iter().enumerate() {
+            debug!(output_script = ?out.script_pubkey);
+            let result = self
+                .context
+                .db
+                .begin_transaction()
+                .await
+                .get_value(&PegInKey {
+                    peg_in_script: out.script_pubkey.clone(),
+                })
+                .await
+                .map(|tweak_secret| (idx, tweak_secret));
+            if result.is_some() {
+                return result;
+            }
+        }
+        None
+    }
+
+    /// Ecash a peg-in of `btc_transaction` issues after fees, `None` if it
+    /// doesn't pay any of our peg-in addresses
+    pub async fn pegin_amount(&self, btc_transaction: &bitcoin::Transaction) -> Option<Amount> {
+        let (output_idx, _) = self.find_pegin_output(btc_transaction).await?;
+        Some(
+            Amount::from_sats(btc_transaction.output[output_idx].value)
+                .saturating_sub(self.config.fee_consensus.peg_in_abs),
+        )
+    }
+
     pub async fn create_pegin_input(
         &self,
         txout_proof: TxOutProof,
         btc_transaction: bitcoin::Transaction,
     ) -> Result<(KeyPair, PegInProof)> {
-        let output_function = || async {
-            for (idx, out) in btc_transaction.output.iter().enumerate() {
-                debug!(output_script = ?out.script_pubkey);
-                let result = self
-                    .context
-                    .db
-                    .begin_transaction()
-                    .await
-                    .get_value(&PegInKey {
-                        peg_in_script: out.script_pubkey.clone(),
-                    })
-                    .await
-                    .map(|tweak_secret| (idx, tweak_secret));
-                if result.is_some() {
-                    return result;
-                }
-            }
-            None
-        };
-
-        let (output_idx, secret_tweak_key_bytes) = output_function()
-            .await
-            .ok_or(WalletClientError::NoMatchingPegInFound)?;
+        let (output_idx, secret_tweak_key_bytes) =
+            self.find_pegin_output(&btc_transaction)
+                .await
+                .ok_or(WalletClientError::NoMatchingPegInFound)?;
 
         let secret_tweak_key =
             bitcoin::KeyPair::from_seckey_slice(&self.context.secp, &secret_tweak_key_bytes)
@@ -168,6 +197,36 @@ impl WalletClient {
     }
 }
 
+/// Finds the largest peg-out whose amount and on-chain fees are covered by
+/// `available`, returns `None` if it doesn't even cover the fees
+///
+/// The on-chain fees depend on the UTXOs the federation spends for the
+/// amount, so starting with all of `available` we lower the amount by the fees
+/// of the last peg-out `new_peg_out` created until it covers them.
+pub async fn fit_peg_out_to_balance<F, Fut, E>(
+    available: Amount,
+    mut new_peg_out: F,
+) -> std::result::Result<Option<PegOut>, E>
+where
+    F: FnMut(bitcoin::Amount) -> Fut,
+    Fut: Future<Output = std::result::Result<PegOut, E>>,
+{
+    let mut amount = bitcoin::Amount::from_sat(available.msats / 1000);
+    for _ in 0..MAX_PEG_OUT_FEE_ROUNDS {
+        if amount == bitcoin::Amount::ZERO {
+            break;
+        }
+        let peg_out = new_peg_out(amount).await?;
+        let on_chain_fees = Amount::from(peg_out.fees.amount());
+        if Amount::from(amount) + on_chain_fees <= available {
+            return Ok(Some(peg_out));
+        }
+        amount = bitcoin::Amount::from_sat(available.saturating_sub(on_chain_fees).msats / 1000);
+    }
+
+    Ok(None)
+}
+
 type Result<T> = std::result::Result<T, WalletClientError>;
 
 #[derive(Error, Debug)]
@@ -186,6 +245,7 @@ pub enum WalletClientError {
 
 #[cfg(test)]
 mod tests {
+    use std::convert::Infallible;
     use std::str::FromStr;
     use std::sync::Arc;
     use std::time::Duration;
@@ -202,7 +262,7 @@ mod tests {
     use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
     use fedimint_core::task::TaskGroup;
-    use fedimint_core::{Feerate, OutPoint, ServerModule, TransactionId};
+    use fedimint_core::{Amount, Feerate, OutPoint, ServerModule, TransactionId};
     use fedimint_testing::btc::bitcoind::{FakeBitcoindRpc, FakeBitcoindRpcController};
     use fedimint_testing::FakeFed;
     use fedimint_wallet_server::{Wallet, WalletGen, WalletGenParams};
@@ -211,7 +271,7 @@ mod tests {
     use crate::api::fake::FederationApiFaker;
     use crate::modules::wallet::config::WalletClientConfig;
     use crate::modules::wallet::{PegOut, PegOutFees, WalletOutput, WalletOutputOutcome};
-    use crate::wallet::WalletClient;
+    use crate::wallet::{fit_peg_out_to_balance, WalletClient};
     use crate::{module_decode_stubs, ClientContext};
 
     type Fed = FakeFed<Wallet>;
@@ -312,6 +372,7 @@ mod tests {
             module_gens: Default::default(),
             db: Database::new(MemDatabase::new(), module_decode_stubs()),
             api: api.into(),
+            epoch_archives: vec![],
             secp: secp256k1_zkp::Secp256k1::new(),
         };
 
@@ -351,6 +412,7 @@ mod tests {
             recipient: addr.clone(),
             amount,
             fees: PegOutFees::new(1000, 883),
+            owner: None,
         };
 
         // agree on output
@@ -413,4 +475,41 @@ mod tests {
             .await;
         assert!(wallet_value > bitcoin::Amount::from_sat(0));
     }
+
+    fn peg_out_with_fees(amount: bitcoin::Amount, fee_sats: u64) -> PegOut {
+        PegOut {
+            recipient: Address::from_str("msFGPqHVk8rbARMd69FfGYxwcboZLemdBi").unwrap(),
+            amount,
+            // a fee rate of 1 sat/vB
+            fees: PegOutFees::new(1000, fee_sats),
+            owner: None,
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn peg_out_fits_balance_after_fees() {
+        let mut rounds = 0;
+        let peg_out = fit_peg_out_to_balance(Amount::from_sats(100_000), |amount| {
+            rounds += 1;
+            async move { Ok::<_, Infallible>(peg_out_with_fees(amount, 1_000)) }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        // the first peg-out of the whole balance can't cover its fees
+        assert_eq!(rounds, 2);
+        assert_eq!(peg_out.amount, bitcoin::Amount::from_sat(99_000));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn peg_out_of_balance_below_fees_fails() {
+        let peg_out = fit_peg_out_to_balance(Amount::from_sats(500), |amount| async move {
+            Ok::<_, Infallible>(peg_out_with_fees(amount, 1_000))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(peg_out, None);
+    }
 }
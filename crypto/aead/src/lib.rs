@@ -91,7 +91,13 @@ pub fn get_encryption_key(password: &str, salt: &str) -> Result<LessSafeKey> {
     argon2()
         .hash_password_into(password.as_bytes(), salt.as_bytes(), &mut key)
         .map_err(|e| format_err!("could not hash password").context(e))?;
-    let key = UnboundKey::new(&ring::aead::CHACHA20_POLY1305, &key)
+    encryption_key_from_bytes(&key)
+}
+
+/// Key used to encrypt data stored on the filesystem from raw key bytes, e.g.
+/// a key provided by a KMS instead of being derived from a password
+pub fn encryption_key_from_bytes(key: &[u8]) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&ring::aead::CHACHA20_POLY1305, key)
         .map_err(|_| anyhow::Error::msg("Unable to create key"))?;
     Ok(LessSafeKey::new(key))
 }
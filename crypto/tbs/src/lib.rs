@@ -122,6 +122,12 @@ impl BlindingKey {
     }
 }
 
+impl FromRandom for BlindingKey {
+    fn from_random(rng: &mut impl RngCore) -> Self {
+        BlindingKey(Scalar::from_random(rng))
+    }
+}
+
 /// * `threshold`: how many signature shares are needed to produce a signature
 /// * `keys`: how many keys to generate
 pub fn dealer_keygen(
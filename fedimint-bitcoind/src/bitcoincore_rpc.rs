@@ -235,6 +235,25 @@ where
             Ok(_) => {}
         })
     }
+
+    #[instrument(
+        skip(self),
+        name = "bitcoincore_rpc::get_utxo_amount",
+        level = "DEBUG",
+        ret,
+        err
+    )]
+    async fn get_utxo_amount(
+        &self,
+        outpoint: &bitcoin::OutPoint,
+    ) -> Result<Option<bitcoin::Amount>> {
+        Ok(fedimint_core::task::block_in_place(|| {
+            self.0
+                .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))
+                .map_err(anyhow::Error::from)
+        })?
+        .map(|txout| txout.value))
+    }
 }
 
 pub struct ElectrumClient(electrum_client::Client);
@@ -84,6 +84,15 @@ pub trait IBitcoindRpc: Debug + Send + Sync {
     ) -> Result<bool> {
         bail!("was_transaction_confirmed_in call not supported in standard (non-electrum/esplora) backends")
     }
+
+    /// Returns the amount of an output if it exists and wasn't spent, also
+    /// considering transactions in the mempool (only bitcoind)
+    async fn get_utxo_amount(
+        &self,
+        _outpoint: &bitcoin::OutPoint,
+    ) -> Result<Option<bitcoin::Amount>> {
+        bail!("get_utxo_amount call not supported in electrum/esplora backends")
+    }
 }
 
 dyn_newtype_define! {
@@ -184,4 +193,12 @@ where
         })
         .await
     }
+
+    async fn get_utxo_amount(
+        &self,
+        outpoint: &bitcoin::OutPoint,
+    ) -> Result<Option<bitcoin::Amount>> {
+        self.retry_call(|| async { self.inner.get_utxo_amount(outpoint).await })
+            .await
+    }
 }
@@ -22,9 +22,7 @@ pub trait ClientModuleGenRegistryExt {
 
 impl ClientModuleGenRegistryExt for ClientModuleGenRegistry {
     fn to_common(&self) -> CommonModuleGenRegistry {
-        self.legacy_init_order_iter()
-            .map(|(_k, v)| v.to_dyn_common())
-            .collect()
+        self.iter().map(|(_k, v)| v.to_dyn_common()).collect()
     }
 }
 
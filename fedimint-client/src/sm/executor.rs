@@ -10,11 +10,10 @@ use fedimint_core::core::{IntoDynInstance, ModuleInstanceId};
 use fedimint_core::db::{AutocommitError, Database, DatabaseKeyWithNotify, DatabaseTransaction};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::task::TaskGroup;
-use futures::future::select_all;
+use fedimint_core::task::{sleep, TaskGroup};
+use futures::future::{select, select_all, Either};
+use futures::lock::Mutex;
 use futures::stream::StreamExt;
-use tokio::select;
-use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
 
 use crate::sm::state::{DynContext, DynState};
@@ -214,13 +213,14 @@ where
             .spawn("state_machine_executor", move |handle| async move {
                 let shutdown_future = handle.make_shutdown_rx().await;
                 let executor_runner = task_runner_inner.run(&context);
-                select! {
-                    _ = shutdown_future => {
+                futures::pin_mut!(executor_runner);
+                match select(shutdown_future, executor_runner).await {
+                    Either::Left(_) => {
                         info!("Shutting down state machine executor runner");
-                    },
-                    _ = executor_runner => {
+                    }
+                    Either::Right(_) => {
                         error!("State machine executor runner exited unexpectedly!");
-                    },
+                    }
                 };
             })
             .await;
@@ -249,7 +249,7 @@ where
         if active_states.is_empty() {
             // FIXME: what to do in this case? Probably best to subscribe to DB eventually
             debug!("No state transitions available, waiting before re-trying");
-            tokio::time::sleep(EXECUTOR_POLL_INTERVAL).await;
+            sleep(EXECUTOR_POLL_INTERVAL).await;
             return Ok(());
         }
 
@@ -572,7 +572,7 @@ mod tests {
     use fedimint_core::db::Database;
     use fedimint_core::encoding::{Decodable, Encodable};
     use fedimint_core::module::registry::ModuleDecoderRegistry;
-    use fedimint_core::task::TaskGroup;
+    use fedimint_core::task::{sleep, TaskGroup};
     use tokio::sync::broadcast::Sender;
     use tracing::{info, trace};
 
@@ -743,9 +743,9 @@ mod tests {
         );
 
         // TODO build await fn+timeout or allow manual driving of executor
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        sleep(Duration::from_secs(1)).await;
         sender.send(0).unwrap();
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        sleep(Duration::from_secs(2)).await;
 
         assert!(
             executor
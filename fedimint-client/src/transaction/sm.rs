@@ -6,6 +6,7 @@ use fedimint_core::api::GlobalFederationApi;
 use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::outcome::TransactionStatus;
+use fedimint_core::task::sleep;
 use fedimint_core::time::now;
 use fedimint_core::transaction::Transaction;
 use fedimint_core::TransactionId;
@@ -112,7 +113,8 @@ impl State for TxSubmissionStates {
                                     txid,
                                     tx,
                                     next_submission,
-                                } = state else {
+                                } = state
+                                else {
                                     panic!("Wrong input state for transition fn");
                                 };
 
@@ -167,7 +169,7 @@ async fn trigger_created_submit(
     next_submission: SystemTime,
     context: DynGlobalClientContext,
 ) -> Result<(), String> {
-    tokio::time::sleep(
+    sleep(
         next_submission
             .duration_since(now())
             .unwrap_or(Duration::ZERO),
@@ -198,7 +200,7 @@ async fn trigger_created_accepted(
                 }
             }
         }
-        tokio::time::sleep(FETCH_INTERVAL).await;
+        sleep(FETCH_INTERVAL).await;
     }
 }
 
@@ -222,13 +224,12 @@ mod tests {
     use fedimint_core::db::Database;
     use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::module::ApiRequestErased;
-    use fedimint_core::task::TaskGroup;
+    use fedimint_core::task::{sleep, timeout, TaskGroup};
     use fedimint_core::transaction::SerdeTransaction;
     use fedimint_core::{PeerId, TransactionId};
     use rand::thread_rng;
     use serde_json::Value;
     use tokio::sync::Mutex;
-    use tokio::time::{sleep, timeout};
 
     use crate::sm::{
         ActiveState, ClientSMDatabaseTransaction, DynState, Executor, InactiveState, OperationId,
@@ -1,14 +1,19 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
+use std::time::{Duration, SystemTime};
 
-use bitcoin_hashes::sha256;
+use bitcoin_hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
 use fedimint_core::task::MaybeSend;
 use serde::{Deserialize, Serialize};
 use tokio_rustls::rustls;
 use url::Url;
 
 use crate::api::{DynFederationApi, FederationApiExt, FederationResult, WsFederationApi};
-use crate::config::ServerModuleGenParamsRegistry;
+use crate::config::{ApiEndpoint, ServerModuleGenParamsRegistry};
+use crate::core::ModuleInstanceId;
+use crate::epoch::{
+    ConsensusLimits, ConsensusParams, ConsensusVersionActivation, ParamsChangeProposal, StateHash,
+};
 use crate::module::{ApiAuth, ApiRequestErased};
 use crate::PeerId;
 
@@ -47,15 +52,34 @@ impl WsAdminClient {
             .await
     }
 
+    /// During config gen, creates a one-time token on the leader that has to be
+    /// handed to a guardian so it can join config gen
+    pub async fn create_config_gen_token(&self) -> FederationResult<String> {
+        self.request_auth("create_config_gen_token", ApiRequestErased::default())
+            .await
+    }
+
     /// During config gen, used for an API-to-API call that adds a peer's server
     /// connection info to the leader.
     ///
     /// Note this call will fail until the leader has their API running and has
     /// `set_server_connections` so clients should retry.
     ///
-    /// This call is not authenticated because it's guardian-to-guardian
-    pub async fn add_config_gen_peer(&self, peer: PeerServerParams) -> FederationResult<()> {
-        self.request("add_config_gen_peer", ApiRequestErased::new(peer))
+    /// This call is authenticated by the one-time `token` the leader created
+    /// for the peer instead of the admin auth because it's
+    /// guardian-to-guardian. The token itself is never sent, only its id
+    /// and an HMAC of `peer`.
+    pub async fn add_config_gen_peer(
+        &self,
+        token: &str,
+        peer: PeerServerParams,
+    ) -> FederationResult<()> {
+        let request = ConfigGenPeerRequest {
+            token_id: config_gen_token_id(token),
+            hmac: config_gen_hmac(token, &peer),
+            peer,
+        };
+        self.request("add_config_gen_peer", ApiRequestErased::new(request))
             .await
     }
 
@@ -85,6 +109,124 @@ impl WsAdminClient {
             .await
     }
 
+    /// Votes for replacing the guardians of the federation with the peers
+    /// reachable at `api_endpoints`, once a threshold of guardians voted for
+    /// the same change the federation shuts down so the keys can be reshared
+    pub async fn propose_peer_set_change(
+        &self,
+        api_endpoints: BTreeMap<PeerId, ApiEndpoint>,
+    ) -> FederationResult<()> {
+        self.request_auth(
+            "propose_peer_set_change",
+            ApiRequestErased::new(api_endpoints),
+        )
+        .await
+    }
+
+    /// Votes for activating the consensus rules of `version` starting with
+    /// `epoch`, once a threshold of guardians voted for the same activation
+    /// guardians that don't support `version` stop before processing `epoch`
+    pub async fn schedule_consensus_version(
+        &self,
+        version: u32,
+        epoch: u64,
+    ) -> FederationResult<()> {
+        self.request_auth(
+            "schedule_consensus_version",
+            ApiRequestErased::new(ConsensusVersionActivation { version, epoch }),
+        )
+        .await
+    }
+
+    /// Returns the consensus versions supported by the guardians and the
+    /// scheduled activations
+    pub async fn consensus_versions(&self) -> FederationResult<ConsensusVersionStatus> {
+        self.request_auth("consensus_versions", ApiRequestErased::default())
+            .await
+    }
+
+    /// Votes for new timing and batching params of consensus rounds, they
+    /// become active once a threshold of guardians voted for the same params
+    pub async fn propose_consensus_params(&self, params: ConsensusParams) -> FederationResult<()> {
+        self.request_auth("propose_consensus_params", ApiRequestErased::new(params))
+            .await
+    }
+
+    /// Returns the consensus params that are currently active
+    pub async fn consensus_params(&self) -> FederationResult<ConsensusParams> {
+        self.request_auth("consensus_params", ApiRequestErased::default())
+            .await
+    }
+
+    /// Proposes to change the consensus limits and the parameters of modules
+    /// starting with the epoch of `proposal`, the change is scheduled once a
+    /// threshold of guardians proposed the same one
+    pub async fn propose_params_change(
+        &self,
+        proposal: ParamsChangeProposal,
+    ) -> FederationResult<()> {
+        self.request_auth("propose_params_change", ApiRequestErased::new(proposal))
+            .await
+    }
+
+    /// Returns the active consensus limits, the scheduled changes and the
+    /// pending proposals
+    pub async fn params_changes(&self) -> FederationResult<ParamsChangeStatus> {
+        self.request_auth("params_changes", ApiRequestErased::default())
+            .await
+    }
+
+    /// Drops the connection to `peer`, if any, and connects to it again right
+    /// away, lifting a ban of its endpoint
+    pub async fn reconnect_peer(&self, peer: PeerId) -> FederationResult<()> {
+        self.request_auth("reconnect_peer", ApiRequestErased::new(peer))
+            .await
+    }
+
+    /// Drops the connection to `peer` and neither connects to it nor accepts
+    /// its connections for `duration`, e.g. while its endpoint misbehaves
+    pub async fn ban_peer_endpoint(
+        &self,
+        peer: PeerId,
+        duration: Duration,
+    ) -> FederationResult<()> {
+        self.request_auth(
+            "ban_peer_endpoint",
+            ApiRequestErased::new(PeerEndpointBan { peer, duration }),
+        )
+        .await
+    }
+
+    /// Returns the state of our guardian: the current epoch, the connection
+    /// status of our peers, the backlog of proposals waiting for consensus and
+    /// the size of our database
+    pub async fn status(&self) -> FederationResult<GuardianStatus> {
+        self.request_auth("status", ApiRequestErased::default())
+            .await
+    }
+
+    /// Returns the result of the last background integrity check of our
+    /// guardian's database, `None` if no check finished yet
+    pub async fn integrity_report(&self) -> FederationResult<Option<IntegrityReport>> {
+        self.request_auth("integrity_report", ApiRequestErased::default())
+            .await
+    }
+
+    /// Returns our guardian's hash of its state after the last epoch and the
+    /// peers whose hashes differed from ours since it started
+    pub async fn state_hash_status(&self) -> FederationResult<StateHashStatus> {
+        self.request_auth("state_hash_status", ApiRequestErased::default())
+            .await
+    }
+
+    /// Writes a consistent backup of our guardian's database and configs to
+    /// `location`, which is either a local directory or an
+    /// `s3://<bucket>/<prefix>` url
+    pub async fn backup(&self, location: String) -> FederationResult<BackupInfo> {
+        self.request_auth("backup", ApiRequestErased::new(location))
+            .await
+    }
+
     /// Gets the default config gen params which can be configured by the
     /// leader, gives them a template to modify
     pub async fn get_default_config_gen_params(&self) -> FederationResult<ConfigGenParamsRequest> {
@@ -115,6 +257,20 @@ impl WsAdminClient {
         .await
     }
 
+    /// During config gen, used for an API-to-API call that fetches the
+    /// consensus config gen params from the leader with the `token` we joined
+    /// with, the caller has to verify the HMAC of the response
+    pub async fn get_leader_config_gen_params(
+        &self,
+        token: &str,
+    ) -> FederationResult<LeaderConfigGenParams> {
+        self.request(
+            "get_leader_config_gen_params",
+            ApiRequestErased::new(config_gen_token_id(token)),
+        )
+        .await
+    }
+
     /// Runs DKG, can only be called once after configs have been generated in
     /// `get_consensus_config_gen_params`.  If DKG fails this returns a 500
     /// error and config gen must be restarted.
@@ -140,6 +296,12 @@ impl WsAdminClient {
             .await
     }
 
+    /// After our configs were verified, starts consensus with them
+    pub async fn start_consensus(&self) -> FederationResult<()> {
+        self.request_auth("start_consensus", ApiRequestErased::default())
+            .await
+    }
+
     async fn request_auth<Ret>(
         &self,
         method: &str,
@@ -163,6 +325,157 @@ impl WsAdminClient {
     }
 }
 
+/// Status of a guardian returned by the admin API
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct GuardianStatus {
+    /// Number of epochs the guardian has processed
+    pub epoch_count: u64,
+    /// Status of every other guardian as seen by us
+    pub peers: BTreeMap<PeerId, PeerStatus>,
+    /// Number of API events (e.g. transactions) waiting to be proposed
+    pub pending_proposals: usize,
+    /// Size of the database on disk in bytes, if the backend reports it
+    pub db_size: Option<u64>,
+}
+
+/// Status of a peer as seen by our guardian
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct PeerStatus {
+    pub connection: PeerConnectionStatus,
+    /// When the peer last contributed to an epoch since our guardian started
+    pub last_contribution: Option<SystemTime>,
+    /// The last epoch the peer contributed to since our guardian started
+    pub last_contribution_epoch: Option<u64>,
+    /// Traffic exchanged with the peer since our guardian started
+    #[serde(default)]
+    pub traffic: PeerTraffic,
+    /// How reliably we can connect to the peer
+    #[serde(default)]
+    pub health: PeerHealth,
+}
+
+/// Health of our connection to a peer since our guardian started
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct PeerHealth {
+    /// From 0 if the recent connections to the peer failed or dropped, to 100
+    /// if they succeeded and held
+    pub score: u8,
+    /// Failed attempts to connect to the peer since the last successful one
+    pub failed_attempts: u64,
+    /// Connections established with the peer
+    pub connects: u64,
+    /// Established connections that dropped
+    pub disconnects: u64,
+    /// Until when our operator banned the peer's endpoint
+    pub banned_until: Option<SystemTime>,
+}
+
+/// Bans the endpoint of a peer for a while, see
+/// [`WsAdminClient::ban_peer_endpoint`]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct PeerEndpointBan {
+    pub peer: PeerId,
+    pub duration: Duration,
+}
+
+/// Traffic exchanged with a peer, counting the messages of all connections
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct PeerTraffic {
+    pub bytes_received: u64,
+    pub messages_received: u64,
+    pub bytes_sent: u64,
+    pub messages_sent: u64,
+    /// How often we paused reading from the peer because it exceeded its rate
+    /// limits
+    pub throttled: u64,
+    /// How often we disconnected the peer because it exceeded its limits
+    pub limit_disconnects: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub enum PeerConnectionStatus {
+    Connected,
+    Disconnected,
+}
+
+/// Consensus versions known to a guardian
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ConsensusVersionStatus {
+    /// Newest consensus version supported by the guardian's binary
+    pub ours: u32,
+    /// Newest consensus version each guardian announced to support
+    pub supported: BTreeMap<PeerId, u32>,
+    /// Activation epoch of every consensus version a threshold of guardians
+    /// voted for
+    pub scheduled: BTreeMap<u32, u64>,
+}
+
+/// Changes of the consensus limits and module parameters known to a guardian
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ParamsChangeStatus {
+    /// Limits transactions submitted now are checked against
+    pub active: ConsensusLimits,
+    /// Changes a threshold of guardians proposed that didn't activate yet, by
+    /// their activation epoch
+    pub scheduled: BTreeMap<u64, ParamsChangeProposal>,
+    /// Pending proposal of every guardian that proposed a change which wasn't
+    /// scheduled yet
+    pub proposals: BTreeMap<PeerId, ParamsChangeProposal>,
+}
+
+/// Result of re-verifying the invariants of a guardian's database
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct IntegrityReport {
+    /// Number of epochs the guardian had processed when the check ran
+    pub epoch_count: u64,
+    /// When the check finished
+    pub checked_at: SystemTime,
+    /// Violated invariants of the consensus state outside of the modules
+    pub consensus: Vec<String>,
+    /// Violated invariants of every module with at least one violation
+    pub modules: BTreeMap<ModuleInstanceId, Vec<String>>,
+}
+
+impl IntegrityReport {
+    pub fn is_consistent(&self) -> bool {
+        self.consensus.is_empty() && self.modules.is_empty()
+    }
+}
+
+/// Comparison of a guardian's state hashes with the ones its peers
+/// contributed
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct StateHashStatus {
+    /// Latest hash of our state, `None` if we didn't hash it yet
+    pub ours: Option<StateHash>,
+    /// Latest differing hash of every peer whose hash differed from ours since
+    /// the guardian started
+    pub divergences: BTreeMap<PeerId, StateHashDivergence>,
+}
+
+/// Hash of a peer's state that differs from ours after the same epoch
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct StateHashDivergence {
+    pub epoch: u64,
+    pub ours: sha256::Hash,
+    pub theirs: sha256::Hash,
+    /// When we processed the epoch the peer contributed its hash in
+    pub detected_at: SystemTime,
+}
+
+/// Describes a guardian backup, stored alongside it so it can be verified
+/// against the federation before it is restored
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct BackupInfo {
+    /// Number of epochs contained in the backed up database
+    pub epoch_count: u64,
+    /// Hash of the last epoch outcome in the backed up database, `None` if no
+    /// epoch was processed yet
+    pub epoch_hash: Option<sha256::Hash>,
+    /// Code version of the guardian that created the backup
+    pub code_version: String,
+}
+
 /// Sent by admin user to the API
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConfigGenConnectionsRequest {
@@ -171,6 +484,41 @@ pub struct ConfigGenConnectionsRequest {
     /// Url of "leader" guardian to send our connection info to
     /// Will be `None` if we are the leader
     pub leader_api_url: Option<Url>,
+    /// One-time token created by the leader, required if we are not the leader
+    #[serde(default)]
+    pub leader_token: Option<String>,
+}
+
+/// Sent by a guardian to the leader to join config gen
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ConfigGenPeerRequest {
+    /// Id of the one-time token the leader created for the guardian
+    pub token_id: sha256::Hash,
+    /// Connection info of the guardian
+    pub peer: PeerServerParams,
+    /// HMAC of `peer` keyed with the token
+    pub hmac: sha256::Hash,
+}
+
+/// Sent by the leader to a guardian that joined config gen
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct LeaderConfigGenParams {
+    pub consensus: ConfigGenParamsConsensus,
+    /// HMAC of `consensus` keyed with the token the guardian joined with
+    pub hmac: sha256::Hash,
+}
+
+/// Identifies a one-time config gen token without revealing it
+pub fn config_gen_token_id(token: &str) -> sha256::Hash {
+    sha256::Hash::hash(token.as_bytes())
+}
+
+/// Authenticates a config gen message with a one-time token, the config gen
+/// API is not encrypted so the token must never be sent itself
+pub fn config_gen_hmac<T: Serialize>(token: &str, msg: &T) -> sha256::Hash {
+    let mut engine = HmacEngine::<sha256::Hash>::new(token.as_bytes());
+    engine.input(&serde_json::to_vec(msg).expect("serializes"));
+    sha256::Hash::from_inner(Hmac::from_engine(engine).into_inner())
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -180,6 +528,9 @@ pub struct PeerServerParams {
     pub p2p_url: Url,
     pub api_url: Url,
     pub name: String,
+    /// More urls peers can reach us at if `p2p_url` fails, e.g. over IPv6
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_p2p_urls: Vec<Url>,
 }
 
 /// The config gen params that need to be in consensus, sent by the config gen
@@ -1,6 +1,7 @@
 use std::borrow::Cow;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::Hash;
 use std::io::{Cursor, Read};
 use std::pin::Pin;
 use std::str::FromStr;
@@ -18,38 +19,56 @@ use fedimint_core::config::{
 };
 use fedimint_core::fmt_utils::AbbreviateDebug;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::task::{sleep, MaybeSend, MaybeSync, RwLock, RwLockWriteGuard};
+use fedimint_core::task::{
+    sleep, timeout, Elapsed, MaybeSend, MaybeSync, RwLock, RwLockWriteGuard,
+};
 use fedimint_core::{
-    apply, async_trait_maybe_send, dyn_newtype_define, NumPeers, OutPoint, PeerId, TransactionId,
+    apply, async_trait_maybe_send, dyn_newtype_define, maybe_add_send, NumPeers, OutPoint, PeerId,
+    TransactionId,
 };
 use fedimint_logging::LOG_NET_API;
 use futures::stream::FuturesUnordered;
 use futures::{Future, StreamExt};
-#[cfg(not(target_family = "wasm"))]
-use jsonrpsee_core::client::CertificateStore;
-use jsonrpsee_core::client::ClientT;
+use jsonrpsee_core::client::{ClientT, SubscriptionClientT};
 use jsonrpsee_core::Error as JsonRpcError;
 #[cfg(target_family = "wasm")]
 use jsonrpsee_wasm_client::{Client as WsClient, WasmClientBuilder as WsClientBuilder};
 #[cfg(not(target_family = "wasm"))]
-use jsonrpsee_ws_client::{WsClient, WsClientBuilder};
+use jsonrpsee_ws_client::WsClient;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
-use threshold_crypto::{PublicKey, PK_SIZE};
+use threshold_crypto::{PublicKey, PublicKeySet, PublicKeyShare, SignatureShare, PK_SIZE};
 use tracing::{debug, error, instrument, trace};
 use url::Url;
 
 use crate::core::OutputOutcome;
-use crate::epoch::{SerdeEpochHistory, SignedEpochOutcome};
-use crate::module::ApiRequestErased;
-use crate::outcome::TransactionStatus;
+use crate::encoding::Encodable;
+use crate::epoch::{
+    EpochArchiveInfo, EpochCheckpoint, ExplorerEpoch, LimitError, SerdeEpochHistory,
+    SerdeSignature, SerdeStateSnapshot, SignedEpochCheckpoint, SignedEpochOutcome,
+    SignedStateSnapshot, StateSnapshot, StateSnapshotShare,
+};
+use crate::module::audit::SignedAuditSummary;
+use crate::module::version::{ApiVersionSet, SupportedApiVersionsSummary};
+use crate::module::{
+    ApiAuth, ApiRequestErased, ApiVersion, CONFLICT_ERROR_CODE, LIMIT_EXCEEDED_ERROR_CODE,
+};
+use crate::outcome::{TransactionStatus, TransactionValidation};
+use crate::pagination::{Page, PageRequest, MAX_PAGES};
 use crate::query::{
-    CurrentConsensus, EventuallyConsistent, QueryStep, QueryStrategy, UnionResponses,
-    VerifiableResponse,
+    CurrentConsensus, EventuallyConsistent, QueryStep, QueryStrategy, TrustAllPeers,
+    UnionResponses, VerifiableResponse,
 };
+use crate::signed_api::{EquivocationReport, ResponseVerifier, SignedApiResponse};
 use crate::transaction::{SerdeTransaction, Transaction};
 
+mod connection;
+
+#[cfg(not(target_family = "wasm"))]
+pub use connection::native::tls_client_config;
+pub use connection::{ConnectionOptions, TlsRoots};
+
 pub type MemberResult<T> = result::Result<T, MemberError>;
 
 pub type JsonRpcResult<T> = result::Result<T, jsonrpsee_core::Error>;
@@ -76,7 +95,10 @@ impl MemberError {
             MemberError::Rpc(rpc_e) => match rpc_e {
                 JsonRpcError::Transport(_) => true,
                 JsonRpcError::Internal(_) => true,
-                JsonRpcError::Call(jsonrpsee_types::error::CallError::Custom(e)) => e.code() == 404,
+                // not found yet or throttled by the server's rate limits
+                JsonRpcError::Call(jsonrpsee_types::error::CallError::Custom(e)) => {
+                    e.code() == 404 || e.code() == 429
+                }
                 _ => false,
             },
             MemberError::InvalidResponse(_) => false,
@@ -106,6 +128,35 @@ impl FederationError {
     pub fn is_retryable(&self) -> bool {
         self.0.iter().any(|(_, e)| e.is_retryable())
     }
+
+    /// Returns the limit a submitted transaction exceeds if the guardians
+    /// rejected it for that reason
+    pub fn limit_error(&self) -> Option<LimitError> {
+        self.0.values().find_map(|e| match e {
+            MemberError::Rpc(JsonRpcError::Call(jsonrpsee_types::error::CallError::Custom(e)))
+                if e.code() == LIMIT_EXCEEDED_ERROR_CODE =>
+            {
+                serde_json::from_str(e.data()?.get()).ok()
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns true if the guardians rejected a submitted transaction because
+    /// it spends inputs that were already spent
+    ///
+    /// Every guardian that failed has to report the conflict. Submitting only
+    /// fails once more guardians failed than can be malicious, so a single
+    /// guardian can't make us revert a transaction the federation accepts.
+    pub fn is_conflict(&self) -> bool {
+        !self.0.is_empty() && self.0.values().all(|e| {
+            matches!(
+                e,
+                MemberError::Rpc(JsonRpcError::Call(jsonrpsee_types::error::CallError::Custom(e)))
+                    if e.code() == CONFLICT_ERROR_CODE
+            )
+        })
+    }
 }
 
 type OutputOutcomeResult<O> = result::Result<O, OutputOutcomeError>;
@@ -137,6 +188,32 @@ pub trait IFederationApi: Debug + MaybeSend + MaybeSync {
     /// API call to the federation would be inconvenient.
     fn all_members(&self) -> &BTreeSet<PeerId>;
 
+    /// Hedging of the requests that only need a single valid response, see
+    /// [`FederationApiExt::request_hedged`]
+    fn hedging(&self) -> Option<&RequestHedging> {
+        None
+    }
+
+    /// API versions negotiated with the guardians, `None` until
+    /// [`GlobalFederationApi::negotiate_api_versions`] succeeded
+    fn api_versions(&self) -> Option<ApiVersionSet> {
+        None
+    }
+
+    /// Remembers the negotiated API versions, implementations that don't
+    /// store them keep sending requests in the oldest formats
+    fn set_api_versions(&self, _versions: ApiVersionSet) {}
+
+    /// API versions of the client using this API, the versions are negotiated
+    /// with the guardians before the first request depending on them
+    fn client_api_versions(&self) -> Option<SupportedApiVersionsSummary> {
+        None
+    }
+
+    /// Sets the API versions of the client using this API, implementations
+    /// that don't store them only negotiate when asked to
+    fn set_client_api_versions(&self, _versions: SupportedApiVersionsSummary) {}
+
     /// Make request to a specific federation member by `peer_id`
     async fn request_raw(
         &self,
@@ -154,103 +231,26 @@ pub trait FederationApiExt: IFederationApi {
     /// merge the responses.
     async fn request_with_strategy<MemberRet: serde::de::DeserializeOwned, FedRet: Debug>(
         &self,
-        mut strategy: impl QueryStrategy<MemberRet, FedRet> + MaybeSend,
+        strategy: impl QueryStrategy<MemberRet, FedRet> + MaybeSend,
         method: String,
         params: ApiRequestErased,
     ) -> FederationResult<FedRet> {
-        #[cfg(not(target_family = "wasm"))]
-        let mut futures = FuturesUnordered::<Pin<Box<dyn Future<Output = _> + Send>>>::new();
-        #[cfg(target_family = "wasm")]
-        let mut futures = FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
-
-        let peers = self.all_members();
-
-        for peer_id in peers {
-            futures.push(Box::pin(async {
-                PeerResponse {
-                    peer: *peer_id,
-                    result: self
-                        .request_raw(*peer_id, &method, &[params.to_json()])
-                        .await
-                        .map(AbbreviateDebug),
-                }
-            }));
-        }
+        drive_strategy(self, strategy, method, params, None).await
+    }
 
-        let mut member_delay_ms = BTreeMap::new();
-        let mut member_errors = BTreeMap::new();
-
-        // Delegates the response handling to the `QueryStrategy` with an exponential
-        // back-off with every new set of requests
-        let max_delay_ms = 1000;
-        loop {
-            let response = futures.next().await;
-            trace!(?response, method, params = ?AbbreviateDebug(params.to_json()), "Received member response");
-            match response {
-                Some(PeerResponse { peer, result }) => {
-                    let result: MemberResult<MemberRet> =
-                        result.map_err(MemberError::Rpc).and_then(|o| {
-                            serde_json::from_value::<MemberRet>(o.0)
-                                .map_err(|e| MemberError::ResponseDeserialization(e.into()))
-                        });
-
-                    let strategy_step = strategy.process(peer, result);
-                    trace!(
-                        method,
-                        ?params,
-                        ?strategy_step,
-                        "Taking strategy step to the response after member response"
-                    );
-                    match strategy_step {
-                        QueryStep::RetryMembers(peers) => {
-                            for retry_peer in peers {
-                                member_errors.remove(&retry_peer);
-
-                                let mut delay_ms =
-                                    member_delay_ms.get(&retry_peer).copied().unwrap_or(10);
-                                delay_ms = cmp::min(max_delay_ms, delay_ms * 2);
-                                member_delay_ms.insert(retry_peer, delay_ms);
-
-                                futures.push(Box::pin({
-                                    let method = &method;
-                                    let params = &params;
-                                    async move {
-                                        // Note: we need to sleep inside the retrying future,
-                                        // so that `futures` is being polled continuously
-                                        sleep(Duration::from_millis(delay_ms)).await;
-                                        PeerResponse {
-                                            peer: retry_peer,
-                                            result: self
-                                                .request_raw(
-                                                    retry_peer,
-                                                    method,
-                                                    &[params.to_json()],
-                                                )
-                                                .await
-                                                .map(AbbreviateDebug),
-                                        }
-                                    }
-                                }));
-                            }
-                        }
-                        QueryStep::FailMembers(failed) => {
-                            for (failed_peer, error) in failed {
-                                member_errors.insert(failed_peer, error);
-                            }
-                        }
-                        QueryStep::Continue => {}
-                        QueryStep::Failure(failed) => {
-                            for (failed_peer, error) in failed {
-                                member_errors.insert(failed_peer, error);
-                            }
-                            return Err(FederationError(member_errors));
-                        }
-                        QueryStep::Success(response) => return Ok(response),
-                    }
-                }
-                None => return Err(FederationError(BTreeMap::new())),
-            }
-        }
+    /// Like [`Self::request_with_strategy`], but hedges the request if
+    /// [`IFederationApi::hedging`] is configured
+    ///
+    /// Only strategies that can succeed with the response of a single peer
+    /// should be used, e.g. [`VerifiableResponse`], otherwise the peers are
+    /// effectively asked one after another.
+    async fn request_hedged<MemberRet: serde::de::DeserializeOwned, FedRet: Debug>(
+        &self,
+        strategy: impl QueryStrategy<MemberRet, FedRet> + MaybeSend,
+        method: String,
+        params: ApiRequestErased,
+    ) -> FederationResult<FedRet> {
+        drive_strategy(self, strategy, method, params, self.hedging()).await
     }
 
     async fn request_union<Ret>(
@@ -269,6 +269,60 @@ pub trait FederationApiExt: IFederationApi {
         .await
     }
 
+    /// Fetches the items of a paginated list endpoint following `cursor`,
+    /// requesting pages until the list ends, `max_items` were fetched or
+    /// [`MAX_PAGES`] were requested
+    ///
+    /// Every page is requested with a new strategy, e.g.
+    /// [`crate::query::UnionPages`]. A page without new items ends the list
+    /// too, peers could otherwise keep us requesting the same items.
+    async fn request_pages<T, C, S>(
+        &self,
+        strategy: impl Fn() -> S + MaybeSend + MaybeSync,
+        method: String,
+        cursor: Option<C>,
+        max_items: Option<usize>,
+    ) -> FederationResult<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned + Eq + Hash + Debug + Clone + MaybeSend,
+        C: serde::de::DeserializeOwned + Serialize + Ord + Debug + Clone + MaybeSend + MaybeSync,
+        S: QueryStrategy<Page<T, C>> + MaybeSend,
+    {
+        let mut request = PageRequest {
+            cursor,
+            limit: None,
+        };
+        let mut items: Vec<T> = vec![];
+        let mut seen = HashSet::new();
+        for requested in 1..=MAX_PAGES {
+            if let Some(max_items) = max_items {
+                request.limit = Some(max_items - items.len());
+            }
+            let page: Page<T, C> = self
+                .request_with_strategy(strategy(), method.clone(), ApiRequestErased::new(&request))
+                .await?;
+            let fetched = items.len();
+            for item in page.items {
+                if seen.insert(item.clone()) {
+                    items.push(item);
+                }
+            }
+
+            let done = items.len() == fetched
+                || max_items.map_or(false, |max_items| items.len() >= max_items);
+            match page.next_cursor {
+                // peers have to move the cursor forward, otherwise we'd never finish
+                Some(next) if !done && request.is_after(&next) => request.cursor = Some(next),
+                _ => break,
+            }
+            if requested == MAX_PAGES {
+                debug!(target: LOG_NET_API, %method, "Stopped after requesting {MAX_PAGES} pages");
+            }
+        }
+        items.truncate(max_items.unwrap_or(usize::MAX));
+        Ok(items)
+    }
+
     async fn request_current_consensus<Ret>(
         &self,
         method: String,
@@ -305,6 +359,234 @@ pub trait FederationApiExt: IFederationApi {
 #[apply(async_trait_maybe_send!)]
 impl<T: ?Sized> FederationApiExt for T where T: IFederationApi {}
 
+type PeerResponseFuture<'a> =
+    Pin<Box<maybe_add_send!(dyn Future<Output = PeerResponse<AbbreviateDebug<Value>>> + 'a)>>;
+
+/// Requests `method` from `peer` after waiting for `delay`
+fn request_peer<'a, A: IFederationApi + ?Sized>(
+    api: &'a A,
+    peer: PeerId,
+    method: &'a str,
+    params: &'a ApiRequestErased,
+    delay: Duration,
+) -> PeerResponseFuture<'a> {
+    Box::pin(async move {
+        // Note: we need to sleep inside the retrying future,
+        // so that `futures` is being polled continuously
+        if delay != Duration::ZERO {
+            sleep(delay).await;
+        }
+        PeerResponse {
+            peer,
+            result: api
+                .request_raw(peer, method, &[params.to_json()])
+                .await
+                .map(AbbreviateDebug),
+        }
+    })
+}
+
+/// Sends the request to the peers and delegates the response handling to
+/// `strategy`
+///
+/// Without `hedging` all peers are asked at once, otherwise one peer at a time
+/// and another one whenever no response arrived within the hedging delay or a
+/// response didn't satisfy `strategy`.
+async fn drive_strategy<A, MemberRet, FedRet>(
+    api: &A,
+    mut strategy: impl QueryStrategy<MemberRet, FedRet> + MaybeSend,
+    method: String,
+    params: ApiRequestErased,
+    hedging: Option<&RequestHedging>,
+) -> FederationResult<FedRet>
+where
+    A: IFederationApi + ?Sized,
+    MemberRet: serde::de::DeserializeOwned,
+    FedRet: Debug,
+{
+    let mut futures = FuturesUnordered::<PeerResponseFuture<'_>>::new();
+
+    // peers that weren't asked yet when hedging
+    let mut unrequested = VecDeque::new();
+    match hedging {
+        Some(hedging) => {
+            unrequested.extend(hedging.peer_order(api.all_members()));
+            if let Some(peer) = unrequested.pop_front() {
+                hedging.record_request(peer);
+                futures.push(request_peer(api, peer, &method, &params, Duration::ZERO));
+            }
+        }
+        None => {
+            for peer in api.all_members() {
+                futures.push(request_peer(api, *peer, &method, &params, Duration::ZERO));
+            }
+        }
+    }
+
+    let mut member_delay_ms = BTreeMap::new();
+    let mut member_errors = BTreeMap::new();
+
+    // Delegates the response handling to the `QueryStrategy` with an exponential
+    // back-off with every new set of requests
+    let max_delay_ms = 1000;
+    loop {
+        let response = match hedging {
+            Some(hedging) if !unrequested.is_empty() => {
+                let response = timeout(hedging.delay, futures.next()).await;
+                match response {
+                    Ok(response) => response,
+                    Err(Elapsed) => {
+                        // the peers asked so far are slow, ask another one as well
+                        let peer = unrequested.pop_front().expect("checked above");
+                        debug!(target: LOG_NET_API, %peer, method, "Hedging request");
+                        hedging.record_request(peer);
+                        futures.push(request_peer(api, peer, &method, &params, Duration::ZERO));
+                        continue;
+                    }
+                }
+            }
+            _ => futures.next().await,
+        };
+        trace!(?response, method, params = ?AbbreviateDebug(params.to_json()), "Received member response");
+
+        let Some(PeerResponse { peer, result }) = response else {
+            match (hedging, unrequested.pop_front()) {
+                (Some(hedging), Some(peer)) => {
+                    hedging.record_request(peer);
+                    futures.push(request_peer(api, peer, &method, &params, Duration::ZERO));
+                    continue;
+                }
+                _ => return Err(FederationError(BTreeMap::new())),
+            }
+        };
+
+        let result: MemberResult<MemberRet> = result.map_err(MemberError::Rpc).and_then(|o| {
+            serde_json::from_value::<MemberRet>(o.0)
+                .map_err(|e| MemberError::ResponseDeserialization(e.into()))
+        });
+
+        let strategy_step = strategy.process(peer, result);
+        trace!(
+            method,
+            ?params,
+            ?strategy_step,
+            "Taking strategy step to the response after member response"
+        );
+        match strategy_step {
+            QueryStep::RetryMembers(peers) => {
+                for retry_peer in peers {
+                    member_errors.remove(&retry_peer);
+
+                    let mut delay_ms = member_delay_ms.get(&retry_peer).copied().unwrap_or(10);
+                    delay_ms = cmp::min(max_delay_ms, delay_ms * 2);
+                    member_delay_ms.insert(retry_peer, delay_ms);
+
+                    futures.push(request_peer(
+                        api,
+                        retry_peer,
+                        &method,
+                        &params,
+                        Duration::from_millis(delay_ms),
+                    ));
+                }
+            }
+            QueryStep::FailMembers(failed) => {
+                for (failed_peer, error) in failed {
+                    member_errors.insert(failed_peer, error);
+                }
+            }
+            QueryStep::Continue => {}
+            QueryStep::Failure(failed) => {
+                for (failed_peer, error) in failed {
+                    member_errors.insert(failed_peer, error);
+                }
+                return Err(FederationError(member_errors));
+            }
+            QueryStep::Success(response) => {
+                if let Some(hedging) = hedging {
+                    hedging.record_win(peer);
+                }
+                return Ok(response);
+            }
+        }
+
+        // the response didn't satisfy the strategy, so don't wait for the delay
+        // before asking the next peer
+        if let Some(hedging) = hedging {
+            if let Some(peer) = unrequested.pop_front() {
+                hedging.record_request(peer);
+                futures.push(request_peer(api, peer, &method, &params, Duration::ZERO));
+            }
+        }
+    }
+}
+
+/// Statistics of the hedged requests sent to a peer
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HedgingPeerStats {
+    /// Number of hedged requests sent to the peer
+    pub requests: u64,
+    /// Number of hedged requests the response of the peer was taken for
+    pub wins: u64,
+}
+
+impl HedgingPeerStats {
+    /// Share of the requests the peer won, peers that weren't asked yet are
+    /// assumed to win every request so they get asked
+    pub fn win_rate(&self) -> f64 {
+        if self.requests == 0 {
+            1.0
+        } else {
+            self.wins as f64 / self.requests as f64
+        }
+    }
+}
+
+/// Asks one guardian at a time and another one only if no response arrived
+/// within `delay`, so slow guardians don't inflate the latency of requests
+/// that only need a single valid response
+///
+/// Guardians are asked in the order of their win rates.
+#[derive(Debug)]
+pub struct RequestHedging {
+    delay: Duration,
+    stats: std::sync::Mutex<BTreeMap<PeerId, HedgingPeerStats>>,
+}
+
+impl RequestHedging {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            stats: Default::default(),
+        }
+    }
+
+    /// Returns the statistics of all peers that were sent hedged requests
+    pub fn stats(&self) -> BTreeMap<PeerId, HedgingPeerStats> {
+        self.stats.lock().expect("locking can't fail").clone()
+    }
+
+    /// Orders `peers` by their win rates, the best first
+    fn peer_order(&self, peers: &BTreeSet<PeerId>) -> Vec<PeerId> {
+        let stats = self.stats.lock().expect("locking can't fail");
+        let win_rate = |peer: &PeerId| stats.get(peer).copied().unwrap_or_default().win_rate();
+
+        let mut peers: Vec<PeerId> = peers.iter().copied().collect();
+        peers.sort_by(|a, b| win_rate(b).total_cmp(&win_rate(a)));
+        peers
+    }
+
+    fn record_request(&self, peer: PeerId) {
+        let mut stats = self.stats.lock().expect("locking can't fail");
+        stats.entry(peer).or_default().requests += 1;
+    }
+
+    fn record_win(&self, peer: PeerId) {
+        let mut stats = self.stats.lock().expect("locking can't fail");
+        stats.entry(peer).or_default().wins += 1;
+    }
+}
+
 dyn_newtype_define! {
     pub DynFederationApi(Arc<IFederationApi>)
 }
@@ -318,6 +600,14 @@ impl AsRef<dyn IFederationApi + 'static> for DynFederationApi {
 #[apply(async_trait_maybe_send!)]
 pub trait GlobalFederationApi {
     async fn submit_transaction(&self, tx: Transaction) -> FederationResult<TransactionId>;
+
+    /// Validate a transaction against the current state of the guardians
+    /// without submitting it
+    async fn validate_transaction(
+        &self,
+        tx: &Transaction,
+    ) -> FederationResult<TransactionValidation>;
+
     async fn fetch_tx_outcome(
         &self,
         txid: &TransactionId,
@@ -331,8 +621,59 @@ pub trait GlobalFederationApi {
         decoders: &ModuleDecoderRegistry,
     ) -> FederationResult<SignedEpochOutcome>;
 
+    /// Fetch the latest threshold signed checkpoint, `None` if the federation
+    /// didn't create one yet
+    async fn fetch_epoch_checkpoint(
+        &self,
+        epoch_pk: PublicKey,
+    ) -> FederationResult<Option<SignedEpochCheckpoint>>;
+
+    /// Fetch the checkpoint of `epoch` a later checkpoint links to with `hash`
+    async fn fetch_epoch_checkpoint_at(
+        &self,
+        epoch: u64,
+        hash: sha256::Hash,
+    ) -> FederationResult<EpochCheckpoint>;
+
+    /// Fetch `epoch` from the guardians with the archive role, which keep the
+    /// epochs the others pruned
+    async fn fetch_archived_epoch_history(
+        &self,
+        epoch: u64,
+        epoch_pk: PublicKey,
+        decoders: &ModuleDecoderRegistry,
+    ) -> FederationResult<SignedEpochOutcome>;
+
+    /// Fetch the epoch history kept by each guardian with the archive role
+    async fn fetch_epoch_archives(&self) -> FederationResult<BTreeMap<PeerId, EpochArchiveInfo>>;
+
     async fn fetch_epoch_count(&self) -> FederationResult<u64>;
 
+    /// Fetch `epoch` with its transactions decoded, as shown by explorers
+    async fn explore_epoch(&self, epoch: u64) -> FederationResult<ExplorerEpoch>;
+
+    /// Fetch up to `count` epochs starting at `from`, as shown by explorers
+    async fn explore_epochs(&self, from: u64, count: usize)
+        -> FederationResult<Vec<ExplorerEpoch>>;
+
+    /// Await a guardian finishing `epoch`, which is pushed to us if the
+    /// guardians support subscriptions
+    async fn await_epoch(&self, epoch: u64) -> FederationResult<()>;
+
+    /// Fetch the latest snapshot of the federation state whose hash a
+    /// threshold of guardians signed
+    async fn fetch_state_snapshot(
+        &self,
+        epoch_pks: &PublicKeySet,
+    ) -> FederationResult<SignedStateSnapshot>;
+
+    /// Fetch the audit summaries of all responding guardians, each signed by
+    /// the guardian that computed it
+    async fn fetch_audit_summaries(
+        &self,
+        epoch_pks: &PublicKeySet,
+    ) -> FederationResult<BTreeMap<PeerId, SignedAuditSummary>>;
+
     async fn fetch_output_outcome<R>(
         &self,
         out_point: OutPoint,
@@ -359,7 +700,53 @@ pub trait GlobalFederationApi {
 
     /// Fetches the server consensus hash if enough peers agree on it
     async fn consensus_config_hash(&self) -> FederationResult<sha256::Hash>;
+
+    /// Fetches the API versions of the first threshold of guardians to respond
+    async fn fetch_api_versions(
+        &self,
+    ) -> FederationResult<BTreeMap<PeerId, SupportedApiVersionsSummary>>;
+
+    /// Negotiates the API versions `client` has in common with a threshold of
+    /// the guardians and gates newer request formats behind them
+    async fn negotiate_api_versions(
+        &self,
+        client: &SupportedApiVersionsSummary,
+    ) -> anyhow::Result<ApiVersionSet>;
+}
+
+/// Core API version that added the `/subscribe_*` endpoints
+pub const SUBSCRIPTIONS_API_VERSION: ApiVersion = ApiVersion { major: 0, minor: 1 };
+
+/// API versions negotiated with the guardians, negotiating them first if the
+/// client told us its versions and they weren't negotiated yet
+pub async fn negotiated_api_versions<A>(api: &A) -> Option<ApiVersionSet>
+where
+    A: GlobalFederationApi + IFederationApi + MaybeSync + ?Sized,
+{
+    if let Some(versions) = api.api_versions() {
+        return Some(versions);
+    }
+    let client = api.client_api_versions()?;
+    match api.negotiate_api_versions(&client).await {
+        Ok(versions) => Some(versions),
+        Err(e) => {
+            debug!(target: LOG_NET_API, err = %e, "Failed to negotiate API versions");
+            None
+        }
+    }
 }
+
+/// Whether the guardians may support subscriptions, which is assumed if the
+/// API versions couldn't be negotiated
+async fn may_subscribe<A>(api: &A) -> bool
+where
+    A: GlobalFederationApi + IFederationApi + MaybeSync + ?Sized,
+{
+    negotiated_api_versions(api).await.map_or(true, |versions| {
+        versions.supports_core(SUBSCRIPTIONS_API_VERSION)
+    })
+}
+
 fn map_tx_outcome_outpoint<R>(
     tx_outcome: TransactionStatus,
     out_point: OutPoint,
@@ -394,6 +781,32 @@ where
     }
 }
 
+/// Decodes the epochs returned by peers before verifying them with `strategy`
+struct ValidHistoryWrapper {
+    decoders: ModuleDecoderRegistry,
+    strategy: VerifiableResponse<SignedEpochOutcome>,
+}
+
+impl QueryStrategy<SerdeEpochHistory, SignedEpochOutcome> for ValidHistoryWrapper {
+    fn process(
+        &mut self,
+        peer: PeerId,
+        result: MemberResult<SerdeEpochHistory>,
+    ) -> QueryStep<SignedEpochOutcome> {
+        let response = result.and_then(|hist| {
+            hist.try_into_inner(&self.decoders)
+                .map_err(|e| MemberError::Rpc(jsonrpsee_core::Error::Custom(e.to_string())))
+        });
+        match self.strategy.process(peer, response) {
+            QueryStep::RetryMembers(r) => QueryStep::RetryMembers(r),
+            QueryStep::FailMembers(failed) => QueryStep::FailMembers(failed),
+            QueryStep::Continue => QueryStep::Continue,
+            QueryStep::Success(res) => QueryStep::Success(res),
+            QueryStep::Failure(failed) => QueryStep::Failure(failed),
+        }
+    }
+}
+
 #[apply(async_trait_maybe_send!)]
 impl<T: ?Sized> GlobalFederationApi for T
 where
@@ -408,6 +821,17 @@ where
         .await
     }
 
+    async fn validate_transaction(
+        &self,
+        tx: &Transaction,
+    ) -> FederationResult<TransactionValidation> {
+        self.request_current_consensus(
+            "/validate_transaction".to_owned(),
+            ApiRequestErased::new(&SerdeTransaction::from(tx)),
+        )
+        .await
+    }
+
     /// Fetch the outcome of an entire transaction
     async fn fetch_tx_outcome(
         &self,
@@ -417,10 +841,38 @@ where
             .await
     }
 
-    /// Await the outcome of an entire transaction
+    /// Await the outcome of an entire transaction, the guardians push the
+    /// outcome to us if they support subscriptions
     async fn await_tx_outcome(&self, tx: &TransactionId) -> FederationResult<TransactionStatus> {
-        self.request_current_consensus("/wait_transaction".to_owned(), ApiRequestErased::new(tx))
+        if !may_subscribe(self).await {
+            return self
+                .request_current_consensus(
+                    "/wait_transaction".to_owned(),
+                    ApiRequestErased::new(tx),
+                )
+                .await;
+        }
+
+        match self
+            .request_current_consensus(
+                "/subscribe_transaction".to_owned(),
+                ApiRequestErased::new(tx),
+            )
             .await
+        {
+            Ok(status) => Ok(status),
+            Err(e) => {
+                debug!(
+                    target: LOG_NET_API,
+                    %e, "Subscribing to transaction failed, waiting instead"
+                );
+                self.request_current_consensus(
+                    "/wait_transaction".to_owned(),
+                    ApiRequestErased::new(tx),
+                )
+                .await
+            }
+        }
     }
 
     async fn fetch_epoch_history(
@@ -432,31 +884,6 @@ where
         // TODO: make this function avoid clone
         let decoders = decoders.clone();
 
-        struct ValidHistoryWrapper {
-            decoders: ModuleDecoderRegistry,
-            strategy: VerifiableResponse<SignedEpochOutcome>,
-        }
-
-        impl QueryStrategy<SerdeEpochHistory, SignedEpochOutcome> for ValidHistoryWrapper {
-            fn process(
-                &mut self,
-                peer: PeerId,
-                result: MemberResult<SerdeEpochHistory>,
-            ) -> QueryStep<SignedEpochOutcome> {
-                let response = result.and_then(|hist| {
-                    hist.try_into_inner(&self.decoders)
-                        .map_err(|e| MemberError::Rpc(jsonrpsee_core::Error::Custom(e.to_string())))
-                });
-                match self.strategy.process(peer, response) {
-                    QueryStep::RetryMembers(r) => QueryStep::RetryMembers(r),
-                    QueryStep::FailMembers(failed) => QueryStep::FailMembers(failed),
-                    QueryStep::Continue => QueryStep::Continue,
-                    QueryStep::Success(res) => QueryStep::Success(res),
-                    QueryStep::Failure(failed) => QueryStep::Failure(failed),
-                }
-            }
-        }
-
         let qs = ValidHistoryWrapper {
             decoders,
             strategy: VerifiableResponse::new(
@@ -466,7 +893,7 @@ where
             ),
         };
 
-        self.request_with_strategy::<SerdeEpochHistory, _>(
+        self.request_hedged::<SerdeEpochHistory, _>(
             qs,
             "/fetch_epoch_history".to_owned(),
             ApiRequestErased::new(epoch),
@@ -474,6 +901,129 @@ where
         .await
     }
 
+    async fn fetch_epoch_checkpoint(
+        &self,
+        epoch_pk: PublicKey,
+    ) -> FederationResult<Option<SignedEpochCheckpoint>> {
+        // peers only return signed checkpoints, so `None` can only be trusted if
+        // enough peers agree on it
+        let qs = VerifiableResponse::new(
+            self.all_members().one_honest(),
+            true,
+            move |checkpoint: &Option<SignedEpochCheckpoint>| {
+                checkpoint
+                    .as_ref()
+                    .map_or(false, |checkpoint| checkpoint.verify_sig(&epoch_pk).is_ok())
+            },
+        );
+
+        self.request_hedged(
+            qs,
+            "/fetch_epoch_checkpoint".to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn fetch_epoch_checkpoint_at(
+        &self,
+        epoch: u64,
+        hash: sha256::Hash,
+    ) -> FederationResult<EpochCheckpoint> {
+        // the hash comes from a signed checkpoint, so one honest peer suffices
+        let qs = VerifiableResponse::new(
+            self.all_members().one_honest(),
+            false,
+            move |checkpoint: &SignedEpochCheckpoint| {
+                checkpoint.checkpoint.epoch == epoch
+                    && checkpoint.checkpoint.consensus_hash().ok() == Some(hash)
+            },
+        );
+
+        let signed: SignedEpochCheckpoint = self
+            .request_hedged(
+                qs,
+                "/fetch_epoch_checkpoint_at".to_owned(),
+                ApiRequestErased::new(epoch),
+            )
+            .await?;
+        Ok(signed.checkpoint)
+    }
+
+    async fn fetch_archived_epoch_history(
+        &self,
+        epoch: u64,
+        epoch_pk: PublicKey,
+        decoders: &ModuleDecoderRegistry,
+    ) -> FederationResult<SignedEpochOutcome> {
+        // Guardians without the archive role reject the request, a single signed
+        // epoch from any archive is enough
+        let qs = ValidHistoryWrapper {
+            decoders: decoders.clone(),
+            strategy: VerifiableResponse::new(
+                self.all_members().total(),
+                false,
+                move |epoch: &SignedEpochOutcome| epoch.verify_sig(&epoch_pk).is_ok(),
+            ),
+        };
+
+        self.request_with_strategy::<SerdeEpochHistory, _>(
+            qs,
+            "/fetch_archived_epoch_history".to_owned(),
+            ApiRequestErased::new(epoch),
+        )
+        .await
+    }
+
+    async fn fetch_epoch_archives(&self) -> FederationResult<BTreeMap<PeerId, EpochArchiveInfo>> {
+        /// Collects the archives until every peer responded
+        struct Archives {
+            total: usize,
+            responded: BTreeSet<PeerId>,
+            archives: BTreeMap<PeerId, EpochArchiveInfo>,
+            errors: BTreeMap<PeerId, MemberError>,
+        }
+
+        impl QueryStrategy<Option<EpochArchiveInfo>, BTreeMap<PeerId, EpochArchiveInfo>> for Archives {
+            fn process(
+                &mut self,
+                peer: PeerId,
+                result: MemberResult<Option<EpochArchiveInfo>>,
+            ) -> QueryStep<BTreeMap<PeerId, EpochArchiveInfo>> {
+                self.responded.insert(peer);
+                match result {
+                    Ok(Some(archive)) => {
+                        self.archives.insert(peer, archive);
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        self.errors.insert(peer, e);
+                    }
+                }
+
+                if self.responded.len() < self.total {
+                    QueryStep::Continue
+                } else if self.errors.len() == self.total {
+                    QueryStep::Failure(std::mem::take(&mut self.errors))
+                } else {
+                    QueryStep::Success(std::mem::take(&mut self.archives))
+                }
+            }
+        }
+
+        self.request_with_strategy(
+            Archives {
+                total: self.all_members().total(),
+                responded: BTreeSet::new(),
+                archives: BTreeMap::new(),
+                errors: BTreeMap::new(),
+            },
+            "/fetch_epoch_archive_info".to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
     async fn fetch_epoch_count(&self) -> FederationResult<u64> {
         self.request_eventually_consistent(
             "/fetch_epoch_count".to_owned(),
@@ -482,6 +1032,234 @@ where
         .await
     }
 
+    async fn explore_epoch(&self, epoch: u64) -> FederationResult<ExplorerEpoch> {
+        self.request_eventually_consistent(
+            "/explore_epoch".to_owned(),
+            ApiRequestErased::new(epoch),
+        )
+        .await
+    }
+
+    async fn explore_epochs(
+        &self,
+        from: u64,
+        count: usize,
+    ) -> FederationResult<Vec<ExplorerEpoch>> {
+        if count == 0 {
+            return Ok(vec![]);
+        }
+        let required = self.all_members().one_honest();
+        self.request_pages(
+            move || EventuallyConsistent::new(required),
+            "/explore_epochs".to_owned(),
+            from.checked_sub(1),
+            Some(count),
+        )
+        .await
+    }
+
+    async fn await_epoch(&self, epoch: u64) -> FederationResult<()> {
+        // we only wait for the epoch to re-check state, so the first guardian
+        // pushing it is good enough
+        let subscribed = if may_subscribe(self).await {
+            self.request_with_strategy::<Value, _>(
+                TrustAllPeers,
+                "/subscribe_epochs".to_owned(),
+                ApiRequestErased::new(epoch),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        } else {
+            Err("guardians don't support subscriptions".to_owned())
+        };
+
+        if let Err(e) = subscribed {
+            debug!(
+                target: LOG_NET_API,
+                %e, "Subscribing to epochs failed, polling instead"
+            );
+            while self.fetch_epoch_count().await? <= epoch {
+                crate::task::sleep(Duration::from_secs(1)).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn fetch_state_snapshot(
+        &self,
+        epoch_pks: &PublicKeySet,
+    ) -> FederationResult<SignedStateSnapshot> {
+        /// Combines the signature shares once a threshold of peers signed the
+        /// same snapshot
+        struct ThresholdSignedShares {
+            pks: PublicKeySet,
+            shares: BTreeMap<(u64, sha256::Hash), BTreeMap<PeerId, SignatureShare>>,
+            current: CurrentConsensus<(u64, sha256::Hash)>,
+        }
+
+        impl QueryStrategy<Option<StateSnapshotShare>, (u64, sha256::Hash, SerdeSignature)>
+            for ThresholdSignedShares
+        {
+            fn process(
+                &mut self,
+                peer: PeerId,
+                result: MemberResult<Option<StateSnapshotShare>>,
+            ) -> QueryStep<(u64, sha256::Hash, SerdeSignature)> {
+                let result = result.and_then(|share| {
+                    let share = share.ok_or_else(|| {
+                        MemberError::InvalidResponse("No state snapshot".to_string())
+                    })?;
+                    let pk = self.pks.public_key_share(peer.to_usize());
+                    if !pk.verify(&share.signature.0, share.hash) {
+                        return Err(MemberError::InvalidResponse(
+                            "Invalid signature share".to_string(),
+                        ));
+                    }
+
+                    self.shares
+                        .entry((share.epoch, share.hash))
+                        .or_default()
+                        .insert(peer, share.signature.0);
+                    Ok((share.epoch, share.hash))
+                });
+
+                match self.current.process(peer, result) {
+                    QueryStep::Success((epoch, hash)) => {
+                        let shares = self.shares[&(epoch, hash)]
+                            .iter()
+                            .map(|(peer, share)| (peer.to_usize(), share));
+                        match self.pks.combine_signatures(shares) {
+                            Ok(sig) => QueryStep::Success((epoch, hash, SerdeSignature(sig))),
+                            Err(e) => QueryStep::Failure(BTreeMap::from([(
+                                peer,
+                                MemberError::InvalidResponse(e.to_string()),
+                            )])),
+                        }
+                    }
+                    QueryStep::RetryMembers(r) => QueryStep::RetryMembers(r),
+                    QueryStep::FailMembers(failed) => QueryStep::FailMembers(failed),
+                    QueryStep::Continue => QueryStep::Continue,
+                    QueryStep::Failure(failed) => QueryStep::Failure(failed),
+                }
+            }
+        }
+
+        struct ValidSnapshotWrapper {
+            strategy: VerifiableResponse<StateSnapshot>,
+        }
+
+        impl QueryStrategy<SerdeStateSnapshot, StateSnapshot> for ValidSnapshotWrapper {
+            fn process(
+                &mut self,
+                peer: PeerId,
+                result: MemberResult<SerdeStateSnapshot>,
+            ) -> QueryStep<StateSnapshot> {
+                let response = result.and_then(|snapshot| {
+                    snapshot
+                        .try_into_inner(&ModuleDecoderRegistry::default())
+                        .map_err(|e| MemberError::Rpc(jsonrpsee_core::Error::Custom(e.to_string())))
+                });
+                self.strategy.process(peer, response)
+            }
+        }
+
+        let (epoch, hash, signature) = self
+            .request_with_strategy(
+                ThresholdSignedShares {
+                    pks: epoch_pks.clone(),
+                    shares: BTreeMap::new(),
+                    current: CurrentConsensus::new(self.all_members().threshold()),
+                },
+                "/fetch_state_snapshot_share".to_owned(),
+                ApiRequestErased::default(),
+            )
+            .await?;
+
+        let snapshot = self
+            .request_hedged::<SerdeStateSnapshot, _>(
+                ValidSnapshotWrapper {
+                    strategy: VerifiableResponse::new(
+                        self.all_members().one_honest(),
+                        false,
+                        move |snapshot: &StateSnapshot| {
+                            snapshot.consensus_hash().ok() == Some(hash)
+                        },
+                    ),
+                },
+                "/fetch_state_snapshot".to_owned(),
+                ApiRequestErased::new(epoch),
+            )
+            .await?;
+
+        Ok(SignedStateSnapshot {
+            snapshot,
+            signature,
+        })
+    }
+
+    async fn fetch_audit_summaries(
+        &self,
+        epoch_pks: &PublicKeySet,
+    ) -> FederationResult<BTreeMap<PeerId, SignedAuditSummary>> {
+        /// Collects the summaries with a valid signature until every peer
+        /// responded
+        struct SignedSummaries {
+            pks: PublicKeySet,
+            total: usize,
+            responded: BTreeSet<PeerId>,
+            summaries: BTreeMap<PeerId, SignedAuditSummary>,
+            errors: BTreeMap<PeerId, MemberError>,
+        }
+
+        impl QueryStrategy<SignedAuditSummary, BTreeMap<PeerId, SignedAuditSummary>> for SignedSummaries {
+            fn process(
+                &mut self,
+                peer: PeerId,
+                result: MemberResult<SignedAuditSummary>,
+            ) -> QueryStep<BTreeMap<PeerId, SignedAuditSummary>> {
+                self.responded.insert(peer);
+                match result {
+                    Ok(summary)
+                        if summary.verify_sig(&self.pks.public_key_share(peer.to_usize())) =>
+                    {
+                        self.summaries.insert(peer, summary);
+                    }
+                    Ok(_) => {
+                        self.errors.insert(
+                            peer,
+                            MemberError::InvalidResponse("Invalid signature".to_string()),
+                        );
+                    }
+                    Err(e) => {
+                        self.errors.insert(peer, e);
+                    }
+                }
+
+                if self.responded.len() < self.total {
+                    QueryStep::Continue
+                } else if self.summaries.is_empty() {
+                    QueryStep::Failure(std::mem::take(&mut self.errors))
+                } else {
+                    QueryStep::Success(std::mem::take(&mut self.summaries))
+                }
+            }
+        }
+
+        self.request_with_strategy(
+            SignedSummaries {
+                pks: epoch_pks.clone(),
+                total: self.all_members().total(),
+                responded: BTreeSet::new(),
+                summaries: BTreeMap::new(),
+                errors: BTreeMap::new(),
+            },
+            "/audit".to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
     async fn fetch_output_outcome<R>(
         &self,
         out_point: OutPoint,
@@ -535,7 +1313,7 @@ where
             },
         );
 
-        self.request_with_strategy(qs, "/config".to_owned(), ApiRequestErased::default())
+        self.request_hedged(qs, "/config".to_owned(), ApiRequestErased::default())
             .await
             .map(|cfg| cfg.client)
     }
@@ -545,6 +1323,74 @@ where
             .await
             .map(|cfg: ConfigResponse| cfg.consensus_hash)
     }
+
+    async fn fetch_api_versions(
+        &self,
+    ) -> FederationResult<BTreeMap<PeerId, SupportedApiVersionsSummary>> {
+        /// Collects the responses until a threshold of peers responded with
+        /// their versions, guardians that predate version negotiation fail to
+        /// respond
+        struct ThresholdVersions {
+            total: usize,
+            threshold: usize,
+            versions: BTreeMap<PeerId, SupportedApiVersionsSummary>,
+            errors: BTreeMap<PeerId, MemberError>,
+        }
+
+        impl
+            QueryStrategy<
+                SupportedApiVersionsSummary,
+                BTreeMap<PeerId, SupportedApiVersionsSummary>,
+            > for ThresholdVersions
+        {
+            fn process(
+                &mut self,
+                peer: PeerId,
+                result: MemberResult<SupportedApiVersionsSummary>,
+            ) -> QueryStep<BTreeMap<PeerId, SupportedApiVersionsSummary>> {
+                match result {
+                    Ok(versions) => {
+                        self.versions.insert(peer, versions);
+                    }
+                    Err(e) => {
+                        self.errors.insert(peer, e);
+                    }
+                }
+
+                if self.versions.len() >= self.threshold {
+                    QueryStep::Success(std::mem::take(&mut self.versions))
+                } else if self.errors.len() > self.total - self.threshold {
+                    QueryStep::Failure(std::mem::take(&mut self.errors))
+                } else {
+                    QueryStep::Continue
+                }
+            }
+        }
+
+        self.request_with_strategy(
+            ThresholdVersions {
+                total: self.all_members().total(),
+                threshold: self.all_members().threshold(),
+                versions: BTreeMap::new(),
+                errors: BTreeMap::new(),
+            },
+            "/api_version".to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn negotiate_api_versions(
+        &self,
+        client: &SupportedApiVersionsSummary,
+    ) -> anyhow::Result<ApiVersionSet> {
+        let peers = self.fetch_api_versions().await?;
+        let versions =
+            ApiVersionSet::negotiate(client, peers.values(), self.all_members().threshold())?;
+        debug!(target: LOG_NET_API, ?versions, "Negotiated API versions");
+        self.set_api_versions(versions.clone());
+        Ok(versions)
+    }
 }
 
 /// Mint API client that will try to run queries against all `members` expecting
@@ -554,6 +1400,14 @@ where
 pub struct WsFederationApi<C = WsClient> {
     peers: BTreeSet<PeerId>,
     members: Vec<FederationMember<C>>,
+    /// Sent with all requests that don't set their own auth, required if the
+    /// federation restricts its user endpoints
+    auth: Option<ApiAuth>,
+    /// Verifies the signatures of guardians that sign their responses
+    verifier: Option<ResponseVerifier>,
+    hedging: Option<RequestHedging>,
+    api_versions: std::sync::RwLock<Option<ApiVersionSet>>,
+    client_api_versions: std::sync::RwLock<Option<SupportedApiVersionsSummary>>,
 }
 
 #[derive(Debug)]
@@ -561,6 +1415,7 @@ struct FederationMember<C> {
     url: Url,
     peer_id: PeerId,
     client: RwLock<Option<C>>,
+    options: ConnectionOptions,
 }
 
 /// Information required for client to construct [`WsFederationApi`] instance
@@ -687,6 +1542,35 @@ impl<C: JsonRpcClient + Debug + MaybeSend + MaybeSync> IFederationApi for WsFede
         &self.peers
     }
 
+    fn hedging(&self) -> Option<&RequestHedging> {
+        self.hedging.as_ref()
+    }
+
+    fn api_versions(&self) -> Option<ApiVersionSet> {
+        self.api_versions
+            .read()
+            .expect("locking can't fail")
+            .clone()
+    }
+
+    fn set_api_versions(&self, versions: ApiVersionSet) {
+        *self.api_versions.write().expect("locking can't fail") = Some(versions);
+    }
+
+    fn client_api_versions(&self) -> Option<SupportedApiVersionsSummary> {
+        self.client_api_versions
+            .read()
+            .expect("locking can't fail")
+            .clone()
+    }
+
+    fn set_client_api_versions(&self, versions: SupportedApiVersionsSummary) {
+        *self
+            .client_api_versions
+            .write()
+            .expect("locking can't fail") = Some(versions);
+    }
+
     async fn request_raw(
         &self,
         peer_id: PeerId,
@@ -699,34 +1583,100 @@ impl<C: JsonRpcClient + Debug + MaybeSend + MaybeSync> IFederationApi for WsFede
             .find(|m| m.peer_id == peer_id)
             .ok_or_else(|| JsonRpcError::Custom(format!("Invalid peer_id: {peer_id}")))?;
 
-        member.request(method, params).await
+        let mut params = match &self.auth {
+            Some(auth) => with_default_auth(params, auth),
+            None => params.to_vec(),
+        };
+
+        let Some(verifier) = &self.verifier else {
+            return member.request(method, &params).await;
+        };
+
+        for param in &mut params {
+            if let Some(request) = param.as_object_mut() {
+                request.insert("sign_response".to_string(), Value::Bool(true));
+            }
+        }
+        let response = member.request(method, &params).await?;
+
+        // guardians running older versions don't know about signed responses
+        let Ok(signed) = serde_json::from_value::<SignedApiResponse>(response.clone()) else {
+            return Ok(response);
+        };
+        let request_params = params
+            .first()
+            .and_then(|request| request.get("params"))
+            .cloned()
+            .unwrap_or(Value::Null);
+
+        verifier
+            .verify(peer_id, method, &request_params, signed)
+            .map_err(|e| JsonRpcError::Custom(e.to_string()))
     }
 }
 
+/// Subscription methods the client uses, with the method that ends the
+/// subscription
+pub const SUBSCRIPTION_METHODS: &[(&str, &str)] = &[
+    ("/subscribe_transaction", "/unsubscribe_transaction"),
+    ("/subscribe_epochs", "/unsubscribe_epochs"),
+];
+
 #[apply(async_trait_maybe_send!)]
 pub trait JsonRpcClient: ClientT + Sized {
-    async fn connect(url: &Url) -> result::Result<Self, JsonRpcError>;
+    async fn connect(url: &Url, options: &ConnectionOptions) -> result::Result<Self, JsonRpcError>;
     fn is_connected(&self) -> bool;
+
+    /// Subscribes with `method` and returns the first item pushed by the
+    /// server, clients without subscription support return an error
+    async fn subscribe_first(
+        &self,
+        method: &str,
+        _unsubscribe_method: &str,
+        _params: &[Value],
+    ) -> result::Result<Value, JsonRpcError> {
+        Err(JsonRpcError::Custom(format!(
+            "Subscription {method} is not supported"
+        )))
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
 impl JsonRpcClient for WsClient {
-    async fn connect(url: &Url) -> result::Result<Self, JsonRpcError> {
+    async fn connect(url: &Url, options: &ConnectionOptions) -> result::Result<Self, JsonRpcError> {
         #[cfg(not(target_family = "wasm"))]
-        return WsClientBuilder::default()
-            .certificate_store(CertificateStore::WebPki)
-            .build(url_to_string_with_default_port(url)) // Hack for default ports, see fn docs
-            .await;
+        return connection::native::connect(url, options).await;
 
         #[cfg(target_family = "wasm")]
-        WsClientBuilder::default()
-            .build(url_to_string_with_default_port(url)) // Hack for default ports, see fn docs
-            .await
+        {
+            // browsers manage the connection themselves
+            let _ = options;
+            WsClientBuilder::default()
+                .build(url_to_string_with_default_port(url)) // Hack for default ports, see fn docs
+                .await
+        }
     }
 
     fn is_connected(&self) -> bool {
         self.is_connected()
     }
+
+    async fn subscribe_first(
+        &self,
+        method: &str,
+        unsubscribe_method: &str,
+        params: &[Value],
+    ) -> result::Result<Value, JsonRpcError> {
+        let mut subscription =
+            SubscriptionClientT::subscribe::<Value, _>(self, method, params, unsubscribe_method)
+                .await?;
+        let item = subscription.next().await.ok_or_else(|| {
+            JsonRpcError::Custom(format!("Subscription {method} ended without an item"))
+        })?;
+        // the server ends the subscription after pushing the outcome anyway
+        let _ = subscription.unsubscribe().await;
+        item
+    }
 }
 
 impl WsFederationApi<WsClient> {
@@ -735,9 +1685,15 @@ impl WsFederationApi<WsClient> {
         Self::new_with_client(members)
     }
 
-    /// Creates a new API client from a client config
+    /// Creates a new API client from a client config, verifying signed
+    /// responses if the config contains the guardians' keys
     pub fn from_config(config: &ClientConfig) -> Self {
-        Self::from_urls(&config.into())
+        let api = Self::from_urls(&config.into());
+        if config.api_pks.is_empty() {
+            api
+        } else {
+            api.with_signed_responses(config.api_pks.clone())
+        }
     }
 
     /// Creates a new API client from connection info
@@ -758,11 +1714,84 @@ impl WsFederationApi<WsClient> {
     }
 }
 
+/// Sets `auth` in all requests in `params` that are not authenticated yet
+fn with_default_auth(params: &[Value], auth: &ApiAuth) -> Vec<Value> {
+    params
+        .iter()
+        .cloned()
+        .map(|mut param| {
+            if let Some(request_auth) = param.get_mut("auth").filter(|a| a.is_null()) {
+                *request_auth = serde_json::to_value(auth).expect("can't fail");
+            }
+            param
+        })
+        .collect()
+}
+
 impl<C> WsFederationApi<C> {
     pub fn peers(&self) -> Vec<PeerId> {
         self.members.iter().map(|member| member.peer_id).collect()
     }
 
+    /// Authenticates all requests with the user auth of the federation
+    pub fn with_auth(self, auth: ApiAuth) -> Self {
+        Self {
+            auth: Some(auth),
+            ..self
+        }
+    }
+
+    /// Asks the guardians to sign their responses and verifies the signatures
+    /// with their epoch public key shares `pks`
+    pub fn with_signed_responses(self, pks: BTreeMap<PeerId, PublicKeyShare>) -> Self {
+        Self {
+            verifier: Some(ResponseVerifier::new(pks)),
+            ..self
+        }
+    }
+
+    /// Connects to the guardians with `options`, e.g. through a proxy, takes
+    /// effect for connections made afterwards
+    pub fn with_connection_options(self, options: ConnectionOptions) -> Self {
+        Self {
+            members: self
+                .members
+                .into_iter()
+                .map(|member| FederationMember {
+                    options: options.clone(),
+                    ..member
+                })
+                .collect(),
+            ..self
+        }
+    }
+
+    /// Hedges requests that only need a single valid response, asking another
+    /// guardian whenever none responded within `delay`
+    pub fn with_hedging(self, delay: Duration) -> Self {
+        Self {
+            hedging: Some(RequestHedging::new(delay)),
+            ..self
+        }
+    }
+
+    /// Statistics of the hedged requests per guardian, empty if requests
+    /// aren't hedged
+    pub fn hedging_stats(&self) -> BTreeMap<PeerId, HedgingPeerStats> {
+        self.hedging
+            .as_ref()
+            .map(RequestHedging::stats)
+            .unwrap_or_default()
+    }
+
+    /// Guardians that were caught signing conflicting responses
+    pub fn equivocation_reports(&self) -> Vec<EquivocationReport> {
+        self.verifier
+            .as_ref()
+            .map(ResponseVerifier::reports)
+            .unwrap_or_default()
+    }
+
     /// Creates a new API client
     pub fn new_with_client(members: Vec<(PeerId, Url)>) -> Self {
         WsFederationApi {
@@ -780,9 +1809,15 @@ impl<C> WsFederationApi<C> {
                         peer_id,
                         url,
                         client: RwLock::new(None),
+                        options: ConnectionOptions::default(),
                     }
                 })
                 .collect(),
+            auth: None,
+            verifier: None,
+            hedging: None,
+            api_versions: Default::default(),
+            client_api_versions: Default::default(),
         }
     }
 }
@@ -799,7 +1834,7 @@ impl<C: JsonRpcClient> FederationMember<C> {
         let rclient = self.client.read().await;
         match &*rclient {
             Some(client) if client.is_connected() => {
-                return client.request::<_, _>(method, params).await;
+                return Self::call(client, method, params).await;
             }
             _ => {}
         };
@@ -812,25 +1847,17 @@ impl<C: JsonRpcClient> FederationMember<C> {
             Some(client) if client.is_connected() => {
                 // other task has already connected it
                 let rclient = RwLockWriteGuard::downgrade(wclient);
-                rclient
-                    .as_ref()
-                    .unwrap()
-                    .request::<_, _>(method, params)
-                    .await?
+                Self::call(rclient.as_ref().unwrap(), method, params).await?
             }
             _ => {
                 // write lock is acquired before creating a new client
                 // so only one task will try to create a new client
-                match C::connect(&self.url).await {
+                match C::connect(&self.url, &self.options).await {
                     Ok(client) => {
                         *wclient = Some(client);
                         // drop the write lock before making the request
                         let rclient = RwLockWriteGuard::downgrade(wclient);
-                        rclient
-                            .as_ref()
-                            .unwrap()
-                            .request::<_, _>(method, params)
-                            .await?
+                        Self::call(rclient.as_ref().unwrap(), method, params).await?
                     }
                     Err(err) => {
                         error!(
@@ -842,6 +1869,18 @@ impl<C: JsonRpcClient> FederationMember<C> {
             }
         })
     }
+
+    /// Subscribes for the methods in [`SUBSCRIPTION_METHODS`], sends a request
+    /// for all other methods
+    async fn call(client: &C, method: &str, params: &[Value]) -> JsonRpcResult<Value> {
+        match SUBSCRIPTION_METHODS
+            .iter()
+            .find(|(subscribe, _)| *subscribe == method)
+        {
+            Some((_, unsubscribe)) => client.subscribe_first(method, unsubscribe, params).await,
+            None => client.request::<_, _>(method, params).await,
+        }
+    }
 }
 
 /// `jsonrpsee` converts the `Url` to a `&str` internally and then parses it as
@@ -851,7 +1890,7 @@ impl<C: JsonRpcClient> FederationMember<C> {
 /// fully standard compliant, but work for our use case).
 ///
 /// See <https://github.com/paritytech/jsonrpsee/issues/554#issue-1048646896>
-fn url_to_string_with_default_port(url: &Url) -> String {
+pub(crate) fn url_to_string_with_default_port(url: &Url) -> String {
     format!(
         "{}://{}:{}{}",
         url.scheme(),
@@ -882,6 +1921,10 @@ mod tests {
     use tracing::error;
 
     use super::*;
+    use crate::module::version::{
+        SupportedCoreApiVersions, CORE_API_VERSIONS, CORE_CONSENSUS_VERSION,
+    };
+    use crate::pagination::DEFAULT_PAGE_LIMIT;
 
     type Result<T = ()> = std::result::Result<T, JsonRpcError>;
 
@@ -903,7 +1946,7 @@ mod tests {
             self.0.is_connected()
         }
 
-        async fn connect(_url: &Url) -> Result<Self> {
+        async fn connect(_url: &Url, _options: &ConnectionOptions) -> Result<Self> {
             Ok(Self(C::connect().await?))
         }
     }
@@ -1012,7 +2055,7 @@ mod tests {
                 error!(target: LOG_NET_API, "connect");
                 let id = CONNECTION_COUNT.fetch_add(1, Ordering::SeqCst);
                 // slow down
-                tokio::time::sleep(Duration::from_millis(100)).await;
+                crate::task::sleep(Duration::from_millis(100)).await;
                 if FAIL.lock().unwrap().contains(&id) {
                     Err(jsonrpsee_core::Error::Transport(anyhow!(
                         "intentional error"
@@ -1108,4 +2151,247 @@ mod tests {
         let connect_parsed_json: WsClientConnectInfo = serde_json::from_str(&json).unwrap();
         assert_eq!(connect_parsed_json, connect_parsed);
     }
+
+    #[test]
+    fn conflicts_need_all_failed_guardians() {
+        let conflict = || {
+            MemberError::Rpc(JsonRpcError::Call(
+                jsonrpsee_types::error::CallError::Custom(
+                    jsonrpsee_types::error::ErrorObject::owned(
+                        CONFLICT_ERROR_CODE,
+                        "Input was already spent",
+                        None::<()>,
+                    ),
+                ),
+            ))
+        };
+        let other = || MemberError::InvalidResponse("timeout".to_string());
+
+        let all = FederationError(BTreeMap::from([
+            (PeerId::from(0), conflict()),
+            (PeerId::from(1), conflict()),
+        ]));
+        assert!(all.is_conflict());
+
+        let single = FederationError(BTreeMap::from([
+            (PeerId::from(0), conflict()),
+            (PeerId::from(1), other()),
+        ]));
+        assert!(!single.is_conflict());
+        assert!(!FederationError(BTreeMap::new()).is_conflict());
+    }
+
+    #[test]
+    fn sets_default_auth() {
+        let user_auth = ApiAuth("user".to_string());
+        let admin_auth = ApiAuth("admin".to_string());
+        let params = [
+            ApiRequestErased::new(1).to_json(),
+            ApiRequestErased::new(2).with_auth(&admin_auth).to_json(),
+        ];
+
+        let params = with_default_auth(&params, &user_auth);
+        assert_eq!(params[0]["auth"], serde_json::to_value(&user_auth).unwrap());
+        assert_eq!(
+            params[1]["auth"],
+            serde_json::to_value(&admin_auth).unwrap()
+        );
+    }
+
+    /// Responds with the id of the peer, the first peer never responds
+    #[derive(Debug)]
+    struct FirstPeerHangs {
+        peers: BTreeSet<PeerId>,
+        hedging: RequestHedging,
+    }
+
+    #[apply(async_trait_maybe_send!)]
+    impl IFederationApi for FirstPeerHangs {
+        fn all_members(&self) -> &BTreeSet<PeerId> {
+            &self.peers
+        }
+
+        fn hedging(&self) -> Option<&RequestHedging> {
+            Some(&self.hedging)
+        }
+
+        async fn request_raw(
+            &self,
+            peer_id: PeerId,
+            _method: &str,
+            _params: &[Value],
+        ) -> result::Result<Value, jsonrpsee_core::Error> {
+            if peer_id == PeerId::from(0) {
+                std::future::pending::<()>().await;
+            }
+            Ok(serde_json::to_value(peer_id).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn hedges_requests_to_slow_peers() {
+        let api = FirstPeerHangs {
+            peers: (0..4).map(PeerId::from).collect(),
+            hedging: RequestHedging::new(Duration::from_millis(10)),
+        };
+
+        for _ in 0..2 {
+            let response: PeerId = api
+                .request_hedged(TrustAllPeers, "".to_owned(), ApiRequestErased::default())
+                .await
+                .unwrap();
+            assert_eq!(response, PeerId::from(1));
+        }
+
+        // the hanging peer is asked last once it lost a request
+        let stats = api.hedging.stats();
+        assert_eq!(
+            stats[&PeerId::from(0)],
+            HedgingPeerStats {
+                requests: 1,
+                wins: 0
+            }
+        );
+        assert_eq!(
+            stats[&PeerId::from(1)],
+            HedgingPeerStats {
+                requests: 2,
+                wins: 2
+            }
+        );
+        assert_eq!(stats.len(), 2);
+    }
+
+    /// Responds with the API versions the guardians support, the first peer
+    /// never responds
+    #[derive(Debug)]
+    struct VersionedPeers {
+        peers: BTreeSet<PeerId>,
+        api_versions: std::sync::RwLock<Option<ApiVersionSet>>,
+        client_api_versions: SupportedApiVersionsSummary,
+    }
+
+    #[apply(async_trait_maybe_send!)]
+    impl IFederationApi for VersionedPeers {
+        fn all_members(&self) -> &BTreeSet<PeerId> {
+            &self.peers
+        }
+
+        fn api_versions(&self) -> Option<ApiVersionSet> {
+            self.api_versions.read().unwrap().clone()
+        }
+
+        fn set_api_versions(&self, versions: ApiVersionSet) {
+            *self.api_versions.write().unwrap() = Some(versions);
+        }
+
+        fn client_api_versions(&self) -> Option<SupportedApiVersionsSummary> {
+            Some(self.client_api_versions.clone())
+        }
+
+        async fn request_raw(
+            &self,
+            peer_id: PeerId,
+            _method: &str,
+            _params: &[Value],
+        ) -> result::Result<Value, jsonrpsee_core::Error> {
+            if peer_id == PeerId::from(0) {
+                std::future::pending::<()>().await;
+            }
+            Ok(serde_json::to_value(&self.client_api_versions).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn negotiates_api_versions_with_threshold_before_subscribing() {
+        let api = VersionedPeers {
+            peers: (0..4).map(PeerId::from).collect(),
+            api_versions: Default::default(),
+            client_api_versions: SupportedApiVersionsSummary {
+                core: SupportedCoreApiVersions {
+                    core_consensus: CORE_CONSENSUS_VERSION,
+                    api: CORE_API_VERSIONS.to_vec(),
+                },
+                modules: BTreeMap::new(),
+            },
+        };
+
+        // the hanging peer doesn't keep the others from agreeing
+        let versions = api.fetch_api_versions().await.unwrap();
+        assert_eq!(versions.len(), 3);
+
+        assert!(api.api_versions().is_none());
+        assert!(may_subscribe(&api).await);
+        assert!(api
+            .api_versions()
+            .unwrap()
+            .supports_core(SUBSCRIPTIONS_API_VERSION));
+    }
+
+    /// Serves a list of `len` items, where the peers move the cursor forward
+    /// by a single item per page if `single_steps`
+    #[derive(Debug)]
+    struct PagedPeers {
+        peers: BTreeSet<PeerId>,
+        len: u64,
+        single_steps: bool,
+        requests: AtomicUsize,
+    }
+
+    #[apply(async_trait_maybe_send!)]
+    impl IFederationApi for PagedPeers {
+        fn all_members(&self) -> &BTreeSet<PeerId> {
+            &self.peers
+        }
+
+        async fn request_raw(
+            &self,
+            _peer_id: PeerId,
+            _method: &str,
+            params: &[Value],
+        ) -> result::Result<Value, jsonrpsee_core::Error> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let request: PageRequest<u64> =
+                serde_json::from_value(params[0]["params"].clone()).unwrap();
+            let mut page = request.page((0..self.len).map(|item| (item, item)));
+            if self.single_steps {
+                page.next_cursor = page
+                    .items
+                    .first()
+                    .copied()
+                    .filter(|_| page.next_cursor.is_some());
+            }
+            Ok(serde_json::to_value(page).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn request_pages_limits_the_requested_pages() {
+        let api = PagedPeers {
+            peers: BTreeSet::from([PeerId::from(0)]),
+            len: 250,
+            single_steps: false,
+            requests: AtomicUsize::new(0),
+        };
+        let items: Vec<u64> = api
+            .request_pages(|| TrustAllPeers, "/list".to_owned(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(items, (0..250).collect::<Vec<_>>());
+        assert_eq!(api.requests.load(Ordering::SeqCst), 3);
+
+        // moving the cursor a single item at a time only brings one new item
+        // per page, so we stop after a bounded number of pages
+        let api = PagedPeers {
+            len: 100_000,
+            single_steps: true,
+            ..api
+        };
+        let items: Vec<u64> = api
+            .request_pages(|| TrustAllPeers, "/list".to_owned(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(items.len(), DEFAULT_PAGE_LIMIT + MAX_PAGES - 1);
+        assert_eq!(api.requests.load(Ordering::SeqCst), 3 + MAX_PAGES);
+    }
 }
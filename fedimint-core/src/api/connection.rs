@@ -0,0 +1,205 @@
+//! Options for the connections of [`WsFederationApi`](super::WsFederationApi)
+//! to the guardians, e.g. to route them over Tor, which clients also apply to
+//! their requests to gateways
+//!
+//! Browsers manage connections themselves, so on wasm the options are ignored.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::encoding::{Decodable, Encodable};
+
+/// How the client connects to the APIs of the guardians
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct ConnectionOptions {
+    /// SOCKS5 proxy the connections are made through, e.g.
+    /// `socks5h://127.0.0.1:9050` for Tor
+    pub proxy: Option<Url>,
+    /// Sent as the `User-Agent` of the websocket handshake, e.g. a generic
+    /// one that doesn't reveal the client and its version
+    pub user_agent: Option<String>,
+    /// Roots the TLS certificates of the guardians are verified against
+    pub tls_roots: TlsRoots,
+    /// Time to establish a connection before giving up, uses the default of
+    /// the websocket client if unset
+    pub connect_timeout: Option<Duration>,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsRoots {
+    /// The Mozilla root certificates bundled with the client
+    #[default]
+    WebPki,
+    /// The root certificates of the operating system
+    Native,
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(super) mod native {
+    use std::sync::Arc;
+
+    use anyhow::{anyhow, Context};
+    use jsonrpsee_core::client::CertificateStore;
+    use jsonrpsee_core::Error as JsonRpcError;
+    use jsonrpsee_ws_client::{HeaderMap, HeaderValue, WsClient, WsClientBuilder};
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls::{self, OwnedTrustAnchor, RootCertStore, ServerName};
+    use tokio_rustls::TlsConnector;
+    use tokio_socks::tcp::Socks5Stream;
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+    use url::Url;
+
+    use super::{ConnectionOptions, TlsRoots};
+    use crate::api::url_to_string_with_default_port;
+
+    /// Connects to the guardian API at `url`, through the proxy of `options`
+    /// if it sets one
+    pub async fn connect(url: &Url, options: &ConnectionOptions) -> Result<WsClient, JsonRpcError> {
+        let mut builder = WsClientBuilder::default().certificate_store(match options.tls_roots {
+            TlsRoots::WebPki => CertificateStore::WebPki,
+            TlsRoots::Native => CertificateStore::Native,
+        });
+        if let Some(user_agent) = &options.user_agent {
+            let user_agent = HeaderValue::from_str(user_agent)
+                .map_err(|e| JsonRpcError::Custom(format!("Invalid user agent: {e}")))?;
+            let mut headers = HeaderMap::new();
+            headers.insert("User-Agent", user_agent);
+            builder = builder.set_headers(headers);
+        }
+        if let Some(connect_timeout) = options.connect_timeout {
+            builder = builder.connection_timeout(connect_timeout);
+        }
+
+        // Hack for default ports, see fn docs
+        let target = url_to_string_with_default_port(url);
+        let Some(proxy) = &options.proxy else {
+            return builder.build(target).await;
+        };
+
+        let connect = connect_over_proxy(proxy, url, options.tls_roots);
+        let stream = match options.connect_timeout {
+            Some(connect_timeout) => crate::task::timeout(connect_timeout, connect)
+                .await
+                .map_err(|_| anyhow!("Connecting over the proxy timed out"))
+                .and_then(|result| result),
+            None => connect.await,
+        }
+        .map_err(JsonRpcError::Transport)?;
+
+        match stream {
+            ProxiedStream::Plain(stream) => {
+                builder.build_with_stream(target, stream.compat()).await
+            }
+            ProxiedStream::Tls(stream) => builder.build_with_stream(target, stream.compat()).await,
+        }
+    }
+
+    enum ProxiedStream {
+        Plain(Socks5Stream<TcpStream>),
+        Tls(Box<tokio_rustls::client::TlsStream<Socks5Stream<TcpStream>>>),
+    }
+
+    /// Opens a stream to the host of `url` through the SOCKS5 `proxy`, which
+    /// resolves the host itself, and wraps it in TLS for `wss` urls
+    async fn connect_over_proxy(
+        proxy: &Url,
+        url: &Url,
+        tls_roots: TlsRoots,
+    ) -> anyhow::Result<ProxiedStream> {
+        let proxy_addr = format!(
+            "{}:{}",
+            proxy.host_str().context("Proxy url has no host")?,
+            proxy.port().unwrap_or(1080)
+        );
+        let host = url.host_str().expect("Asserted on construction");
+        let port = url
+            .port_or_known_default()
+            .expect("Asserted on construction");
+
+        let stream = Socks5Stream::connect(proxy_addr.as_str(), (host, port)).await?;
+        if url.scheme() != "wss" {
+            return Ok(ProxiedStream::Plain(stream));
+        }
+
+        let server_name = ServerName::try_from(host)?;
+        let stream = TlsConnector::from(Arc::new(tls_client_config(tls_roots)?))
+            .connect(server_name, stream)
+            .await?;
+        Ok(ProxiedStream::Tls(Box::new(stream)))
+    }
+
+    /// TLS config verifying servers against `tls_roots`
+    pub fn tls_client_config(tls_roots: TlsRoots) -> anyhow::Result<rustls::ClientConfig> {
+        Ok(rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store(tls_roots)?)
+            .with_no_client_auth())
+    }
+
+    fn root_store(tls_roots: TlsRoots) -> anyhow::Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        match tls_roots {
+            TlsRoots::WebPki => {
+                roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
+                    |anchor| {
+                        OwnedTrustAnchor::from_subject_spki_name_constraints(
+                            anchor.subject,
+                            anchor.spki,
+                            anchor.name_constraints,
+                        )
+                    },
+                ));
+            }
+            TlsRoots::Native => {
+                for certificate in rustls_native_certs::load_native_certs()? {
+                    roots.add(&rustls::Certificate(certificate.0))?;
+                }
+            }
+        }
+        Ok(roots)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        use super::connect;
+        use crate::api::ConnectionOptions;
+        use crate::task::{self, channel};
+
+        #[tokio::test]
+        async fn connections_go_through_the_proxy() {
+            let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let options = ConnectionOptions {
+                proxy: Some(
+                    format!("socks5h://{}", proxy.local_addr().unwrap())
+                        .parse()
+                        .unwrap(),
+                ),
+                ..Default::default()
+            };
+
+            let url = "ws://guardian.example:5000".parse().unwrap();
+            let (connected_tx, connected_rx) = channel::oneshot::channel();
+            task::spawn("connect through the proxy", async move {
+                let _ = connected_tx.send(connect(&url, &options).await);
+            });
+
+            // the guardian's host is resolved by the proxy, so we are asked
+            // to connect to it with a SOCKS5 greeting
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            assert_eq!(stream.read_u8().await.unwrap(), 5);
+
+            drop(stream);
+            assert!(connected_rx.await.unwrap().is_err());
+        }
+    }
+}
@@ -12,10 +12,10 @@ use bitcoin_hashes::sha256::{Hash as Sha256, HashEngine};
 use bitcoin_hashes::{hex, sha256};
 use fedimint_core::cancellable::Cancelled;
 use fedimint_core::core::{
-    ModuleInstanceId, ModuleKind, LEGACY_HARDCODED_INSTANCE_ID_LN,
-    LEGACY_HARDCODED_INSTANCE_ID_MINT, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+    legacy_hardcoded_instance_id, ModuleInstanceId, ModuleKind,
+    LEGACY_HARDCODED_INSTANCE_ID_WALLET, LEGACY_HARDCODED_MODULE_KINDS,
 };
-use fedimint_core::encoding::Encodable;
+use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{BitcoinHash, ModuleDecoderRegistry};
 use serde::de::DeserializeOwned;
 use serde::ser::SerializeMap;
@@ -26,6 +26,7 @@ use threshold_crypto::group::{Curve, Group, GroupEncoding};
 use threshold_crypto::{G1Projective, G2Projective, Signature};
 use url::Url;
 
+use crate::epoch::ConsensusLimits;
 use crate::module::{DynCommonModuleGen, DynServerModuleGen, IDynCommonModuleGen};
 use crate::task::{MaybeSend, MaybeSync};
 use crate::{maybe_add_send_sync, PeerId};
@@ -93,7 +94,7 @@ impl JsonWithKind {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub struct ApiEndpoint {
     /// The peer's API websocket network address and port (e.g.
     /// `ws://10.42.0.10:5000`)
@@ -113,12 +114,24 @@ pub struct ClientConfig {
     pub api_endpoints: BTreeMap<PeerId, ApiEndpoint>,
     /// Threshold pubkey for authenticating epoch history
     pub epoch_pk: threshold_crypto::PublicKey,
+    /// Epoch pubkey shares of the guardians for verifying signed API responses
+    #[serde(default)]
+    pub api_pks: BTreeMap<PeerId, threshold_crypto::PublicKeyShare>,
     /// Configs from other client modules
     #[encodable_ignore]
     pub modules: BTreeMap<ModuleInstanceId, ClientModuleConfig>,
     // TODO: make it a String -> serde_json::Value map?
     /// Additional config the federation wants to transmit to the clients
     pub meta: BTreeMap<String, String>,
+    /// Limits the transactions submitted to the federation have to stay
+    /// within
+    ///
+    /// Not part of the config hash, so the hashes the guardians signed before
+    /// the limits were published stay valid. The guardians enforce the limits
+    /// anyway, clients only check them to fail early.
+    #[serde(default)]
+    #[encodable_ignore]
+    pub limits: ConsensusLimits,
 }
 
 /// The API response for configuration requests
@@ -239,6 +252,52 @@ impl ClientConfig {
         Ok((*id, serde_json::from_value(module_cfg.value().clone())?))
     }
 
+    /// Iterate over the instance ids of the modules and their kinds
+    pub fn iter_module_instances(
+        &self,
+    ) -> impl Iterator<Item = (ModuleInstanceId, &ModuleKind)> + '_ {
+        self.modules.iter().map(|(id, module)| (*id, module.kind()))
+    }
+
+    /// Configs of the modules by their kind and instance id
+    pub fn module_registry(&self) -> ModuleConfigRegistry {
+        ModuleConfigRegistry::new(self.modules.clone())
+    }
+
+    /// Instance id of the module of `kind`, fails unless the federation runs
+    /// exactly one of them
+    pub fn module_instance_id(
+        &self,
+        kind: impl Into<ModuleKind>,
+    ) -> anyhow::Result<ModuleInstanceId> {
+        self.module_registry().instance_id(&kind.into())
+    }
+
+    /// Checks that the modules with hardcoded instance ids, which the client
+    /// isn't modularized for yet, are at these ids and no other module takes
+    /// them
+    ///
+    /// Federations whose configs were generated before the legacy ids were
+    /// reserved may have assigned them to other modules, the client would
+    /// attribute their inputs and outputs to the wrong modules.
+    pub fn check_legacy_module_instances(&self) -> anyhow::Result<()> {
+        for kind in LEGACY_HARDCODED_MODULE_KINDS {
+            self.module_instance_id(kind)?;
+        }
+        for (id, kind) in self.iter_module_instances() {
+            match legacy_hardcoded_instance_id(kind) {
+                Some(legacy_id) if legacy_id != id => bail!(
+                    "Module {kind} has instance id {id}, but the client only supports {legacy_id}"
+                ),
+                None if id <= LEGACY_HARDCODED_INSTANCE_ID_WALLET => bail!(
+                    "Module {kind} has instance id {id}, the client expects another module there"
+                ),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     // TODO: rename this and above
     pub fn get_first_module_by_kind_cfg(
         &self,
@@ -253,6 +312,79 @@ impl ClientConfig {
     }
 }
 
+/// Configs of a federation's modules by their kind and instance id
+///
+/// The instance ids are assigned when the federation is set up, so they
+/// differ between federations running the same modules. Code looking for a
+/// module of some kind finds its instance here instead of assuming an id.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ModuleConfigRegistry(
+    BTreeMap<ModuleKind, BTreeMap<ModuleInstanceId, ClientModuleConfig>>,
+);
+
+impl ModuleConfigRegistry {
+    pub fn new(modules: impl IntoIterator<Item = (ModuleInstanceId, ClientModuleConfig)>) -> Self {
+        let mut registry = BTreeMap::<_, BTreeMap<_, _>>::new();
+        for (id, config) in modules {
+            registry
+                .entry(config.kind().clone())
+                .or_default()
+                .insert(id, config);
+        }
+        Self(registry)
+    }
+
+    /// Iterate over the instance ids of the modules and their kinds
+    pub fn iter_module_instances(
+        &self,
+    ) -> impl Iterator<Item = (ModuleInstanceId, &ModuleKind)> + '_ {
+        self.0
+            .iter()
+            .flat_map(|(kind, instances)| instances.keys().map(move |id| (*id, kind)))
+    }
+
+    /// Iterate over the instances of modules of `kind` and their configs
+    pub fn instances(
+        &self,
+        kind: &ModuleKind,
+    ) -> impl Iterator<Item = (ModuleInstanceId, &ClientModuleConfig)> + '_ {
+        self.0
+            .get(kind)
+            .into_iter()
+            .flatten()
+            .map(|(id, config)| (*id, config))
+    }
+
+    /// Kind of the module instance `id`, if the federation runs it
+    pub fn kind(&self, id: ModuleInstanceId) -> Option<&ModuleKind> {
+        self.iter_module_instances()
+            .find(|(instance, _)| *instance == id)
+            .map(|(_, kind)| kind)
+    }
+
+    /// Instance id of the module of `kind`, fails unless the federation runs
+    /// exactly one of them
+    pub fn instance_id(&self, kind: &ModuleKind) -> anyhow::Result<ModuleInstanceId> {
+        let mut ids = self.instances(kind).map(|(id, _)| id);
+        match (ids.next(), ids.next()) {
+            (Some(id), None) => Ok(id),
+            (None, _) => bail!("Federation runs no module of kind {kind}"),
+            (Some(_), Some(_)) => bail!("Federation runs several modules of kind {kind}"),
+        }
+    }
+
+    /// Decoders of all module instances, by the module gen of their kind
+    pub fn decoders<M>(
+        &self,
+        module_gens: &ModuleGenRegistry<M>,
+    ) -> anyhow::Result<ModuleDecoderRegistry>
+    where
+        M: AsRef<dyn IDynCommonModuleGen + Send + Sync + 'static>,
+    {
+        module_gens.decoders(self.iter_module_instances())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ModuleGenRegistry<M>(BTreeMap<ModuleKind, M>);
 
@@ -272,11 +404,7 @@ pub type ServerModuleGenRegistry = ModuleGenRegistry<DynServerModuleGen>;
 impl ServerModuleGenRegistry {
     // TODO: Remove this with modularization
     pub fn legacy_init_modules(&self) -> BTreeMap<u16, (ModuleKind, DynServerModuleGen)> {
-        let mut modules = BTreeMap::new();
-        for (id, (kind, gen)) in self.legacy_init_order_iter().into_iter().enumerate() {
-            modules.insert(u16::try_from(id).expect("cannot fail"), (kind, gen));
-        }
-        modules
+        self.legacy_instance_ids()
     }
 }
 
@@ -383,25 +511,52 @@ impl<M> ModuleGenRegistry<M> {
         self.0.get(k)
     }
 
+    /// Whether a module generator of a given `kind` was attached
+    pub fn contains(&self, k: &ModuleKind) -> bool {
+        self.0.contains_key(k)
+    }
+
+    /// Iterate over all attached module generators, ordered by their kind
+    pub fn iter(&self) -> impl Iterator<Item = (&ModuleKind, &M)> {
+        self.0.iter()
+    }
+
+    /// Kinds of all attached module generators
+    pub fn kinds(&self) -> impl Iterator<Item = &ModuleKind> {
+        self.0.keys()
+    }
+
     /// Return legacy initialization order. See [`LegacyInitOrderIter`].
     pub fn legacy_init_order_iter(&self) -> LegacyInitOrderIter<M>
     where
         M: Clone,
     {
-        for hardcoded_module in ["mint", "ln", "wallet"] {
-            if !self
-                .0
-                .contains_key(&ModuleKind::from_static_str(hardcoded_module))
-            {
-                panic!("Missing {hardcoded_module} module");
-            }
-        }
-
         LegacyInitOrderIter {
-            next_id: 0,
             rest: self.0.clone(),
         }
     }
+
+    /// Assigns the instance ids of new federations: the modules with
+    /// hardcoded ids get these, the other modules the ids after them in order
+    /// of their kind
+    ///
+    /// The hardcoded ids are left free if their module isn't attached, so a
+    /// legacy client never mistakes another module for it.
+    pub fn legacy_instance_ids(&self) -> BTreeMap<ModuleInstanceId, (ModuleKind, M)>
+    where
+        M: Clone,
+    {
+        let mut next_id = LEGACY_HARDCODED_INSTANCE_ID_WALLET + 1;
+        let mut modules = BTreeMap::new();
+        for (kind, gen) in self.legacy_init_order_iter() {
+            let id = legacy_hardcoded_instance_id(&kind).unwrap_or_else(|| {
+                next_id += 1;
+                next_id - 1
+            });
+            modules.insert(id, (kind, gen));
+        }
+        modules
+    }
 }
 
 impl ModuleGenRegistry<ConfigGenParams> {
@@ -472,15 +627,13 @@ where
 }
 
 /// Iterate over module generators in a legacy, hardcoded order: ln, mint,
-/// wallet, rest... Returning each `kind` exactly once, so that
-/// `LEGACY_HARDCODED_` constants correspond to correct module kind.
+/// wallet, rest... Returning each `kind` exactly once, see
+/// [`ModuleGenRegistry::legacy_instance_ids`] for the ids they are assigned.
 ///
 /// We would like to get rid of it eventually, but old client and test code
 /// assumes it in multiple places, and it will take work to fix it, while we
 /// want new code to not assume this 1:1 relationship.
 pub struct LegacyInitOrderIter<M> {
-    /// Counter of what module id will this returned value get assigned
-    next_id: ModuleInstanceId,
     rest: BTreeMap<ModuleKind, M>,
 }
 
@@ -488,35 +641,13 @@ impl<M> Iterator for LegacyInitOrderIter<M> {
     type Item = (ModuleKind, M);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ret = match self.next_id {
-            LEGACY_HARDCODED_INSTANCE_ID_LN => {
-                let kind = ModuleKind::from_static_str("ln");
-                Some((
-                    kind.clone(),
-                    self.rest.remove(&kind).expect("checked in constructor"),
-                ))
+        for kind in LEGACY_HARDCODED_MODULE_KINDS {
+            let kind = ModuleKind::from_static_str(kind);
+            if let Some(gen) = self.rest.remove(&kind) {
+                return Some((kind, gen));
             }
-            LEGACY_HARDCODED_INSTANCE_ID_MINT => {
-                let kind = ModuleKind::from_static_str("mint");
-                Some((
-                    kind.clone(),
-                    self.rest.remove(&kind).expect("checked in constructor"),
-                ))
-            }
-            LEGACY_HARDCODED_INSTANCE_ID_WALLET => {
-                let kind = ModuleKind::from_static_str("wallet");
-                Some((
-                    kind.clone(),
-                    self.rest.remove(&kind).expect("checked in constructor"),
-                ))
-            }
-            _ => self.rest.pop_first(),
-        };
-
-        if ret.is_some() {
-            self.next_id += 1;
         }
-        ret
+        self.rest.pop_first()
     }
 }
 
@@ -613,6 +744,19 @@ impl ServerModuleConfig {
     }
 }
 
+/// Config of a module before the guardians of the federation changed, used to
+/// reshare the module's keys to the new guardians
+#[derive(Debug, Clone)]
+pub struct ServerModuleReshareConfig {
+    /// Peers of the federation before the change
+    pub old_peers: Vec<PeerId>,
+    /// Consensus config of the module before the change
+    pub consensus: JsonWithKind,
+    /// Our old peer id and private config if we were a guardian before the
+    /// change
+    pub dealer: Option<(PeerId, JsonWithKind)>,
+}
+
 /// Consensus-critical part of a server side module config
 pub trait TypedServerModuleConsensusConfig: DeserializeOwned + Serialize + Encodable {
     /// Derive client side config for this module (type-erased)
@@ -748,6 +892,16 @@ pub enum DkgMessage<G: DkgGroup> {
         #[serde(with = "serde_impl::scalar")] Scalar,
     ),
     Extract(#[serde(with = "serde_commit")] Vec<G>),
+    /// Commitment to the polynomial an old peer reshares its key share
+    /// (`PeerId`) with
+    ReshareCommit(PeerId, #[serde(with = "serde_commit")] Vec<G>),
+    /// Sent by new peers that don't hold a share of the key being reshared
+    ReshareSkip,
+    /// Share of an old peer's key share for the receiving peer
+    ReshareShare(#[serde(with = "serde_impl::scalar")] Scalar),
+    /// Hash of all resharing commitments the sending peer received, so peers
+    /// detect dealers that sent different commitments to different peers
+    ReshareEcho(Sha256),
 }
 
 /// Defines a group (e.g. G1 or G2) that we can generate keys for
@@ -850,3 +1004,101 @@ pub mod serde_binary_human_readable {
         }
     }
 }
+
+/// Like [`serde_binary_human_readable`] for optional fields, which have to be
+/// skipped if `None` and default to `None`, so `Some` values are encoded the
+/// same way as non-optional ones
+pub mod serde_option_binary_human_readable {
+    use serde::de::DeserializeOwned;
+    use serde::{Deserializer, Serialize, Serializer};
+
+    use super::serde_binary_human_readable;
+
+    pub fn serialize<T: Serialize, S: Serializer>(x: &Option<T>, s: S) -> Result<S::Ok, S::Error> {
+        match x {
+            Some(x) => serde_binary_human_readable::serialize(x, s),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'d, T: DeserializeOwned, D: Deserializer<'d>>(
+        d: D,
+    ) -> Result<Option<T>, D::Error> {
+        serde_binary_human_readable::deserialize(d).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::config::{ClientModuleConfig, ModuleConfigRegistry, ModuleGenRegistry};
+    use crate::core::ModuleKind;
+
+    fn registry(kinds: &[&'static str]) -> ModuleGenRegistry<&'static str> {
+        ModuleGenRegistry(BTreeMap::from_iter(
+            kinds
+                .iter()
+                .map(|kind| (ModuleKind::from_static_str(kind), *kind)),
+        ))
+    }
+
+    #[test]
+    fn legacy_init_order_keeps_hardcoded_ids() {
+        let order = registry(&["dummy", "wallet", "mint", "ln", "abc"])
+            .legacy_init_order_iter()
+            .map(|(_, gen)| gen)
+            .collect::<Vec<_>>();
+
+        assert_eq!(order, vec!["ln", "mint", "wallet", "abc", "dummy"]);
+    }
+
+    #[test]
+    fn legacy_init_order_without_hardcoded_modules() {
+        let order = registry(&["stability", "mint", "dummy"])
+            .legacy_init_order_iter()
+            .map(|(_, gen)| gen)
+            .collect::<Vec<_>>();
+
+        assert_eq!(order, vec!["mint", "dummy", "stability"]);
+    }
+
+    #[test]
+    fn legacy_instance_ids_are_never_taken_by_other_modules() {
+        let ids = registry(&["stability", "mint", "dummy"])
+            .legacy_instance_ids()
+            .into_iter()
+            .map(|(id, (_, gen))| (id, gen))
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec![(1, "mint"), (3, "dummy"), (4, "stability")]);
+    }
+
+    #[test]
+    fn module_config_registry_finds_instances_by_kind() {
+        let config = |kind| {
+            ClientModuleConfig::from_typed(ModuleKind::from_static_str(kind), &0u64).unwrap()
+        };
+        let registry = ModuleConfigRegistry::new([
+            (5, config("mint")),
+            (2, config("dummy")),
+            (7, config("dummy")),
+        ]);
+
+        let mint = ModuleKind::from_static_str("mint");
+        assert_eq!(registry.instance_id(&mint).unwrap(), 5);
+        assert_eq!(
+            registry.kind(7),
+            Some(&ModuleKind::from_static_str("dummy"))
+        );
+        assert_eq!(registry.kind(1), None);
+
+        // the instance of a kind is ambiguous if the federation runs several
+        assert!(registry
+            .instance_id(&ModuleKind::from_static_str("dummy"))
+            .is_err());
+        assert!(registry
+            .instance_id(&ModuleKind::from_static_str("ln"))
+            .is_err());
+    }
+}
@@ -50,12 +50,26 @@ pub const LEGACY_HARDCODED_INSTANCE_ID_LN: ModuleInstanceId = 0;
 pub const LEGACY_HARDCODED_INSTANCE_ID_MINT: ModuleInstanceId = 1;
 pub const LEGACY_HARDCODED_INSTANCE_ID_WALLET: ModuleInstanceId = 2;
 
+/// Kinds of the modules with hardcoded instance ids, in order of their ids
+pub const LEGACY_HARDCODED_MODULE_KINDS: [&str; 3] = ["ln", "mint", "wallet"];
+
+/// Instance id the pre-modularization code expects the module of `kind` at,
+/// if it's one of the modules with hardcoded ids
+pub fn legacy_hardcoded_instance_id(kind: &ModuleKind) -> Option<ModuleInstanceId> {
+    match kind.as_str() {
+        "ln" => Some(LEGACY_HARDCODED_INSTANCE_ID_LN),
+        "mint" => Some(LEGACY_HARDCODED_INSTANCE_ID_MINT),
+        "wallet" => Some(LEGACY_HARDCODED_INSTANCE_ID_WALLET),
+        _ => None,
+    }
+}
+
 /// A type of a module
 ///
 /// This is a short string that identifies type of a module.
 /// Authors of 3rd party modules are free to come up with a string,
 /// long enough to avoid conflicts with similar modules.
-#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ModuleKind(Cow<'static, str>);
 
 impl ModuleKind {
@@ -107,7 +121,14 @@ macro_rules! module_dyn_newtype_impl_encode_decode {
                 modules: &$crate::module::registry::ModuleDecoderRegistry,
             ) -> Result<Self, fedimint_core::encoding::DecodeError> {
                 let key = fedimint_core::core::ModuleInstanceId::consensus_decode(reader, modules)?;
-                modules.get_expect(key).decode(reader, key)
+                modules
+                    .get(key)
+                    .ok_or_else(|| {
+                        fedimint_core::encoding::DecodeError::new_custom(anyhow::format_err!(
+                            "Unknown module instance {key}"
+                        ))
+                    })?
+                    .decode(reader, key)
             }
         }
     };
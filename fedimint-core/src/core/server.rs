@@ -9,14 +9,16 @@ use std::sync::Arc;
 
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::interconnect::ModuleInterconect;
-use fedimint_core::{apply, async_trait_maybe_send, OutPoint, PeerId};
+use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint, PeerId};
 
 use super::*;
 use crate::db::ModuleDatabaseTransaction;
+use crate::epoch::ModuleParams;
 use crate::maybe_add_send_sync;
 use crate::module::{
-    ApiEndpoint, ApiEndpointContext, ApiRequestErased, ApiVersion, ConsensusProposal, InputMeta,
-    ModuleCommon, ModuleConsensusVersion, ModuleError, ServerModule, TransactionItemAmount,
+    ApiEndpoint, ApiEndpointContext, ApiRequestErased, ApiVersion, ConsensusProposal,
+    CoreConsensusVersion, InputMeta, ModuleCommon, ModuleConsensusVersion, ModuleError,
+    ServerModule, TransactionItemAmount,
 };
 use crate::task::{MaybeSend, MaybeSync};
 
@@ -81,6 +83,25 @@ pub trait IServerModule: Debug {
         consensus_items: Vec<(PeerId, DynModuleConsensusItem)>,
     );
 
+    /// Called in the epoch the federation activates the core consensus
+    /// `version`, before `begin_consensus_epoch`
+    async fn activate_consensus_version(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        version: CoreConsensusVersion,
+    );
+
+    /// Checks the adjustable `params` a guardian proposes to change
+    fn validate_params(&self, params: &ModuleParams) -> anyhow::Result<()>;
+
+    /// Called in the epoch a change of the module's adjustable `params`
+    /// activates, before `begin_consensus_epoch`
+    async fn activate_params(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        params: &ModuleParams,
+    );
+
     /// Some modules may have slow to verify inputs that would block transaction
     /// processing. If the slow part of verification can be modeled as a
     /// pure function not involving any system state we can build a lookup
@@ -181,6 +202,38 @@ pub trait IServerModule: Debug {
         audit: &mut Audit,
     );
 
+    /// Returns the part of the module state clients need to recover without
+    /// the epoch history before a checkpoint, if any
+    async fn checkpoint(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Option<Vec<u8>>;
+
+    /// Re-verifies the invariants of the module state, returns a description
+    /// of every violation
+    async fn verify_integrity(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Vec<String>;
+
+    /// Returns the key prefixes of entries that differ between guardians
+    fn local_db_prefixes(&self) -> Vec<u8>;
+
+    /// Returns the key prefixes of the module's consensus state
+    fn consensus_db_prefixes(&self) -> Vec<u8>;
+
+    /// Returns the amount of `input` shown by the epoch explorer, `None` if it
+    /// isn't public
+    fn public_input_amount(&self, input: &DynInput) -> Option<Amount>;
+
+    /// Returns the amount of `output` shown by the epoch explorer, `None` if
+    /// it isn't public
+    fn public_output_amount(&self, output: &DynOutput) -> Option<Amount>;
+
+    /// Kinds of modules that have to finish `begin_consensus_epoch` and
+    /// `end_consensus_epoch` before this module starts them
+    fn epoch_dependencies(&self) -> Vec<ModuleKind>;
+
     /// Returns a list of custom API endpoints defined by the module. These are
     /// made available both to users as well as to other modules. They thus
     /// should be deterministic, only dependant on their input and the
@@ -260,6 +313,26 @@ where
         .await
     }
 
+    async fn activate_consensus_version(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        version: CoreConsensusVersion,
+    ) {
+        <Self as ServerModule>::activate_consensus_version(self, dbtx, version).await
+    }
+
+    fn validate_params(&self, params: &ModuleParams) -> anyhow::Result<()> {
+        <Self as ServerModule>::validate_params(self, params)
+    }
+
+    async fn activate_params(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        params: &ModuleParams,
+    ) {
+        <Self as ServerModule>::activate_params(self, dbtx, params).await
+    }
+
     /// Some modules may have slow to verify inputs that would block transaction
     /// processing. If the slow part of verification can be modeled as a
     /// pure function not involving any system state we can build a lookup
@@ -431,23 +504,76 @@ where
         <Self as ServerModule>::audit(self, dbtx, audit).await
     }
 
+    async fn checkpoint(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Option<Vec<u8>> {
+        <Self as ServerModule>::checkpoint(self, dbtx).await
+    }
+
+    async fn verify_integrity(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Vec<String> {
+        <Self as ServerModule>::verify_integrity(self, dbtx).await
+    }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        <Self as ServerModule>::local_db_prefixes(self)
+    }
+
+    fn consensus_db_prefixes(&self) -> Vec<u8> {
+        <Self as ServerModule>::consensus_db_prefixes(self)
+    }
+
+    fn public_input_amount(&self, input: &DynInput) -> Option<Amount> {
+        <Self as ServerModule>::public_input_amount(
+            self,
+            input
+                .as_any()
+                .downcast_ref::<<<Self as ServerModule>::Common as ModuleCommon>::Input>()
+                .expect("incorrect input type passed to module plugin"),
+        )
+    }
+
+    fn public_output_amount(&self, output: &DynOutput) -> Option<Amount> {
+        <Self as ServerModule>::public_output_amount(
+            self,
+            output
+                .as_any()
+                .downcast_ref::<<<Self as ServerModule>::Common as ModuleCommon>::Output>()
+                .expect("incorrect output type passed to module plugin"),
+        )
+    }
+
+    fn epoch_dependencies(&self) -> Vec<ModuleKind> {
+        <Self as ServerModule>::epoch_dependencies(self)
+    }
+
     fn api_endpoints(&self) -> Vec<ApiEndpoint<DynServerModule>> {
         <Self as ServerModule>::api_endpoints(self)
             .into_iter()
-            .map(|ApiEndpoint { path, handler }| ApiEndpoint {
-                path,
-                handler: Box::new(
-                    move |module: &DynServerModule,
-                          context: ApiEndpointContext<'_>,
-                          value: ApiRequestErased| {
-                        let typed_module = module
-                            .as_any()
-                            .downcast_ref::<T>()
-                            .expect("the dispatcher should always call with the right module");
-                        Box::pin(handler(typed_module, context, value))
-                    },
-                ),
-            })
+            .map(
+                |ApiEndpoint {
+                     path,
+                     auth_tier,
+                     handler,
+                 }| ApiEndpoint {
+                    path,
+                    auth_tier,
+                    handler: Box::new(
+                        move |module: &DynServerModule,
+                              context: ApiEndpointContext<'_>,
+                              value: ApiRequestErased| {
+                            let typed_module = module
+                                .as_any()
+                                .downcast_ref::<T>()
+                                .expect("the dispatcher should always call with the right module");
+                            Box::pin(handler(typed_module, context, value))
+                        },
+                    ),
+                },
+            )
             .collect()
     }
 }
@@ -7,8 +7,10 @@ use bitcoin_hashes::hex::ToHex;
 use futures::stream;
 use macro_rules_attribute::apply;
 
+use super::watch::{KeyMutation, PrefixWatchers};
 use super::{
-    IDatabase, IDatabaseTransaction, ISingleUseDatabaseTransaction, SingleUseDatabaseTransaction,
+    IDatabase, IDatabaseTransaction, ISingleUseDatabaseTransaction, MutationStream,
+    SingleUseDatabaseTransaction,
 };
 use crate::async_trait_maybe_send;
 use crate::db::PrefixStream;
@@ -33,6 +35,7 @@ pub enum DatabaseOperation {
 #[derive(Debug, Default)]
 pub struct MemDatabase {
     data: Mutex<BTreeMap<Vec<u8>, Vec<u8>>>,
+    watchers: PrefixWatchers,
 }
 
 #[derive(Debug)]
@@ -79,6 +82,10 @@ impl IDatabase for MemDatabase {
         let single_use = SingleUseDatabaseTransaction::new(memtx);
         Box::new(single_use)
     }
+
+    fn watch_prefix(&self, key_prefix: &[u8]) -> Result<MutationStream> {
+        Ok(self.watchers.watch(key_prefix))
+    }
 }
 
 // In-memory database transaction should only be used for test code and never
@@ -126,6 +133,7 @@ impl<'a> IDatabaseTransaction<'a> for MemTransaction<'a> {
     }
 
     async fn commit_tx(self) -> Result<()> {
+        let mut mutations = Vec::with_capacity(self.operations.len());
         for op in self.operations {
             match op {
                 DatabaseOperation::Insert(insert_op) => {
@@ -133,13 +141,19 @@ impl<'a> IDatabaseTransaction<'a> for MemTransaction<'a> {
                         .data
                         .lock()
                         .unwrap()
-                        .insert(insert_op.key, insert_op.value);
+                        .insert(insert_op.key.clone(), insert_op.value.clone());
+                    mutations.push(KeyMutation::Insert {
+                        key: insert_op.key,
+                        value: insert_op.value,
+                    });
                 }
                 DatabaseOperation::Delete(delete_op) => {
                     self.db.data.lock().unwrap().remove(&delete_op.key);
+                    mutations.push(KeyMutation::Remove { key: delete_op.key });
                 }
             }
         }
+        self.db.watchers.publish(&mutations);
 
         Ok(())
     }
@@ -240,4 +254,9 @@ mod tests {
     async fn test_module_db() {
         fedimint_core::db::verify_module_db(database(), module_database(1)).await;
     }
+
+    #[test_log::test(tokio::test)]
+    async fn test_watch_prefix() {
+        fedimint_core::db::verify_watch_prefix(database()).await;
+    }
 }
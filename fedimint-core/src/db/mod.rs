@@ -2,7 +2,9 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
@@ -22,15 +24,22 @@ use crate::task::{MaybeSend, MaybeSync};
 use crate::{async_trait_maybe_send, maybe_add_send};
 
 pub mod mem_impl;
+pub mod namespace;
 pub mod notifications;
+pub mod watch;
 
 pub use test_utils::*;
 
 use self::notifications::{Notifications, NotifyingTransaction};
+use self::watch::KeyMutation;
 use crate::module::registry::ModuleDecoderRegistry;
 
 pub const MODULE_GLOBAL_PREFIX: u8 = 0xff;
 
+/// Number of attempts [`Database::autocommit_bounded`] makes to commit a
+/// transaction before giving up
+pub const MAX_COMMIT_ATTEMPTS: usize = 10;
+
 pub trait DatabaseKeyPrefix: Debug {
     fn to_bytes(&self) -> Vec<u8>;
 }
@@ -83,9 +92,29 @@ pub trait DatabaseValue: Sized + Debug {
 
 pub type PrefixStream<'a> = Pin<Box<maybe_add_send!(dyn Stream<Item = (Vec<u8>, Vec<u8>)> + 'a)>>;
 
+pub type MutationStream = Pin<Box<maybe_add_send!(dyn Stream<Item = KeyMutation>)>>;
+
 #[apply(async_trait_maybe_send!)]
 pub trait IDatabase: Debug + MaybeSend + MaybeSync + 'static {
     async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>>;
+
+    /// Returns the approximate size of the database on disk in bytes, `None`
+    /// if the backend can't tell
+    fn size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Writes a consistent copy of the whole database to `path`, which must
+    /// not exist yet
+    fn checkpoint(&self, _path: &Path) -> Result<()> {
+        anyhow::bail!("The database backend does not support checkpoints")
+    }
+
+    /// Returns a stream of the mutations of keys starting with `key_prefix`
+    /// made by transactions committed after this call, in commit order
+    fn watch_prefix(&self, _key_prefix: &[u8]) -> Result<MutationStream> {
+        anyhow::bail!("The database backend does not support watching prefixes")
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -100,9 +129,32 @@ pub struct Database {
 struct DatabaseInner<Db: IDatabase + ?Sized> {
     notifications: Notifications,
     module_decoders: ModuleDecoderRegistry,
+    commit_counters: CommitCounters,
     db: Db,
 }
 
+/// Counts the commits made through [`Database::autocommit`], shared by all
+/// isolated views of a database
+#[derive(Debug, Default)]
+struct CommitCounters {
+    commits: AtomicU64,
+    conflicts: AtomicU64,
+    exhausted: AtomicU64,
+}
+
+/// Snapshot of the commit counters of a database, see
+/// [`Database::commit_stats`]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize)]
+pub struct CommitStats {
+    /// Transactions committed by `autocommit`
+    pub commits: u64,
+    /// Commits that failed, usually because of a conflict with a concurrent
+    /// transaction, whether they were retried or not
+    pub conflicts: u64,
+    /// Times `autocommit` gave up after the maximum number of attempts
+    pub exhausted: u64,
+}
+
 /// Error returned when the autocommit function fails
 #[derive(Debug, Error)]
 pub enum AutocommitError<E> {
@@ -133,6 +185,7 @@ impl Database {
             db,
             notifications: Notifications::new(),
             module_decoders,
+            commit_counters: CommitCounters::default(),
         };
 
         Self {
@@ -166,11 +219,48 @@ impl Database {
         }
     }
 
+    /// Returns the approximate size of the whole database on disk in bytes, see
+    /// [`IDatabase::size`]
+    pub fn size(&self) -> Option<u64> {
+        self.inner_db.db.size()
+    }
+
+    /// Writes a consistent copy of the whole database to `path`, see
+    /// [`IDatabase::checkpoint`]
+    pub fn checkpoint(&self, path: &Path) -> Result<()> {
+        self.inner_db.db.checkpoint(path)
+    }
+
+    /// Returns how many transactions were committed through
+    /// [`Database::autocommit`] and how often committing failed
+    pub fn commit_stats(&self) -> CommitStats {
+        let counters = &self.inner_db.commit_counters;
+        CommitStats {
+            commits: counters.commits.load(Ordering::Relaxed),
+            conflicts: counters.conflicts.load(Ordering::Relaxed),
+            exhausted: counters.exhausted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Like [`Database::autocommit`], but gives up after
+    /// [`MAX_COMMIT_ATTEMPTS`], for writes that are only retried because of
+    /// conflicts with concurrent transactions
+    pub async fn autocommit_bounded<'s: 'dt, 'dt, F, T, E>(
+        &'s self,
+        tx_fn: F,
+    ) -> Result<T, AutocommitError<E>>
+    where
+        for<'a> F: Fn(&'a mut DatabaseTransaction<'dt>) -> BoxFuture<'a, Result<T, E>>,
+    {
+        self.autocommit(tx_fn, Some(MAX_COMMIT_ATTEMPTS)).await
+    }
+
     /// Runs a closure with a reference to a database transaction and tries to
     /// commit the transaction if the closure returns `Ok` and rolls it back
     /// otherwise. If committing fails the closure is run for up to
     /// `max_attempts` times. If `max_attempts` is `None` it will run
-    /// `usize::MAX` times which is close enough to infinite times.
+    /// `usize::MAX` times which is close enough to infinite times. Commits and
+    /// failed commits are counted in [`Database::commit_stats`].
     ///
     /// The closure `tx_fn` provided should not have side effects outside of the
     /// database transaction provided, or if it does these should be
@@ -202,6 +292,7 @@ impl Database {
         for<'a> F: Fn(&'a mut DatabaseTransaction<'dt>) -> BoxFuture<'a, Result<T, E>>,
     {
         assert_ne!(max_attempts, Some(0));
+        let counters = &self.inner_db.commit_counters;
         let mut curr_attempts: usize = 0;
 
         loop {
@@ -218,18 +309,33 @@ impl Database {
             match tx_fn(&mut dbtx).await {
                 Ok(val) => match dbtx.commit_tx_result().await {
                     Ok(()) => {
+                        counters.commits.fetch_add(1, Ordering::Relaxed);
                         return Ok(val);
                     }
                     Err(err) => {
+                        counters.conflicts.fetch_add(1, Ordering::Relaxed);
                         if max_attempts
                             .map(|max_att| max_att <= curr_attempts)
                             .unwrap_or(false)
                         {
+                            counters.exhausted.fetch_add(1, Ordering::Relaxed);
+                            warn!(
+                                target: LOG_DB,
+                                attempts = curr_attempts,
+                                %err,
+                                "Giving up committing database transaction"
+                            );
                             return Err(AutocommitError::CommitFailed {
                                 attempts: curr_attempts,
                                 last_error: err,
                             });
                         }
+                        debug!(
+                            target: LOG_DB,
+                            attempts = curr_attempts,
+                            %err,
+                            "Retrying database transaction after failed commit"
+                        );
                     }
                 },
                 Err(err) => {
@@ -254,16 +360,8 @@ impl Database {
     where
         K: DatabaseKey + DatabaseRecord + DatabaseKeyWithNotify,
     {
-        let key_bytes = if let Some(module_id) = self.module_instance_id {
-            let mut prefix_bytes = vec![MODULE_GLOBAL_PREFIX];
-            module_id
-                .consensus_encode(&mut prefix_bytes)
-                .expect("Error encoding module instance id as prefix");
-            prefix_bytes.extend(key.to_bytes());
-            prefix_bytes
-        } else {
-            key.to_bytes()
-        };
+        let mut key_bytes = self.module_prefix_bytes();
+        key_bytes.extend(key.to_bytes());
         loop {
             // register for notification
             let notify = self.inner_db.notifications.register(&key_bytes);
@@ -310,6 +408,64 @@ impl Database {
     {
         self.wait_key_check(key, std::convert::identity).await.0
     }
+
+    /// Returns a stream of the records starting with `key_prefix` that are
+    /// changed by transactions committed after this call, with their new value
+    /// or `None` if they were removed. Changes that can't be decoded are
+    /// skipped.
+    pub fn watch_prefix<KP>(
+        &self,
+        key_prefix: &KP,
+    ) -> Result<impl Stream<Item = (KP::Record, Option<<KP::Record as DatabaseRecord>::Value>)>>
+    where
+        KP: DatabaseLookup,
+        KP::Record: DatabaseKey,
+    {
+        let module_prefix_len = self.module_prefix_bytes().len();
+        let mut prefix_bytes = self.module_prefix_bytes();
+        prefix_bytes.extend(key_prefix.to_bytes());
+        let decoders = self.inner_db.module_decoders.clone();
+
+        let mutations = self.inner_db.db.watch_prefix(&prefix_bytes)?;
+        Ok(mutations.filter_map(move |mutation| {
+            let key = <KP::Record as DatabaseKey>::from_bytes(
+                &mutation.key()[module_prefix_len..],
+                &decoders,
+            );
+            let value = match &mutation {
+                KeyMutation::Insert { value, .. } => {
+                    <<KP::Record as DatabaseRecord>::Value as DatabaseValue>::from_bytes(
+                        value, &decoders,
+                    )
+                    .map(Some)
+                }
+                KeyMutation::Remove { .. } => Ok(None),
+            };
+            let decoded = match (key, value) {
+                (Ok(key), Ok(value)) => Some((key, value)),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!(target: LOG_DB, ?mutation, %e, "Skipping undecodable key mutation");
+                    None
+                }
+            };
+            futures::future::ready(decoded)
+        }))
+    }
+
+    /// Prefix of all keys of the module this database is isolated to, empty if
+    /// it isn't isolated
+    fn module_prefix_bytes(&self) -> Vec<u8> {
+        match self.module_instance_id {
+            Some(module_id) => {
+                let mut prefix_bytes = vec![MODULE_GLOBAL_PREFIX];
+                module_id
+                    .consensus_encode(&mut prefix_bytes)
+                    .expect("Error encoding module instance id as prefix");
+                prefix_bytes
+            }
+            None => vec![],
+        }
+    }
 }
 
 /// Fedimint requires that the database implementation implement Snapshot
@@ -510,12 +666,13 @@ impl<'a, Tx: IDatabaseTransaction<'a> + Send> ISingleUseDatabaseTransaction<'a>
 #[derive(Clone)]
 pub struct CommitTracker {
     is_committed: bool,
-    has_writes: bool,
+    /// Number of inserts and removals made through the transaction
+    num_writes: usize,
 }
 
 impl Drop for CommitTracker {
     fn drop(&mut self) {
-        if self.has_writes && !self.is_committed {
+        if self.num_writes > 0 && !self.is_committed {
             warn!(
                 target: LOG_DB,
                 "DatabaseTransaction has writes and has not called commit."
@@ -689,7 +846,7 @@ impl<'isolated, T: Send + Encodable> ModuleDatabaseTransaction<'isolated, T> {
     where
         K: DatabaseKey + DatabaseRecord,
     {
-        self.commit_tracker.has_writes = true;
+        self.commit_tracker.num_writes += 1;
         self.isolated_tx
             .raw_insert_bytes(&key.to_bytes(), value.to_bytes())
             .await
@@ -705,7 +862,7 @@ impl<'isolated, T: Send + Encodable> ModuleDatabaseTransaction<'isolated, T> {
     where
         K: DatabaseKey + DatabaseRecord,
     {
-        self.commit_tracker.has_writes = true;
+        self.commit_tracker.num_writes += 1;
         let prev_val = self
             .isolated_tx
             .raw_insert_bytes(&key.to_bytes(), value.to_bytes())
@@ -726,7 +883,7 @@ impl<'isolated, T: Send + Encodable> ModuleDatabaseTransaction<'isolated, T> {
     where
         K: DatabaseKey + DatabaseRecord,
     {
-        self.commit_tracker.has_writes = true;
+        self.commit_tracker.num_writes += 1;
         let key_bytes = key.to_bytes();
         match self
             .isolated_tx
@@ -747,7 +904,7 @@ impl<'isolated, T: Send + Encodable> ModuleDatabaseTransaction<'isolated, T> {
     where
         KP: DatabaseLookup,
     {
-        self.commit_tracker.has_writes = true;
+        self.commit_tracker.num_writes += 1;
         self.isolated_tx
             .raw_remove_by_prefix(&key_prefix.to_bytes())
             .await
@@ -857,6 +1014,146 @@ impl<'isolated, 'parent, T: Send + Encodable + 'isolated> ISingleUseDatabaseTran
     }
 }
 
+/// Lets several modules use a [`DatabaseTransaction`] concurrently, e.g. to
+/// process a consensus epoch in parallel. Every module gets an isolated
+/// transaction from [`Self::module_tx`], their reads and writes are serialized
+/// but can interleave, so they may only touch their own namespace.
+pub struct SharedDatabaseTransaction<'a, 'parent> {
+    tx: futures::lock::Mutex<&'a mut dyn ISingleUseDatabaseTransaction<'parent>>,
+    decoders: &'a ModuleDecoderRegistry,
+    commit_tracker: &'a mut CommitTracker,
+    /// Writes of the module transactions, added to `commit_tracker` on drop
+    num_writes: AtomicUsize,
+    /// Keys the module transactions modified, added to `tx` on drop since
+    /// the modules can't wait for its lock when adding them
+    notification_keys: std::sync::Mutex<Vec<Vec<u8>>>,
+}
+
+impl<'a, 'parent> SharedDatabaseTransaction<'a, 'parent> {
+    pub fn module_tx(
+        &self,
+        module_instance_id: ModuleInstanceId,
+    ) -> SharedModuleTransaction<'_, 'a, 'parent> {
+        SharedModuleTransaction {
+            tx: LockedDatabaseTransaction {
+                tx: &self.tx,
+                notification_keys: &self.notification_keys,
+            },
+            module_instance_id,
+            decoders: self.decoders,
+            commit_tracker: CommitTracker {
+                is_committed: false,
+                num_writes: 0,
+            },
+            num_writes: &self.num_writes,
+        }
+    }
+}
+
+impl<'a, 'parent> Drop for SharedDatabaseTransaction<'a, 'parent> {
+    fn drop(&mut self) {
+        self.commit_tracker.num_writes += self.num_writes.load(Ordering::Relaxed);
+        let notification_keys = self
+            .notification_keys
+            .get_mut()
+            .expect("Notification keys lock poisoned");
+        for key in notification_keys.drain(..) {
+            self.tx
+                .get_mut()
+                .add_notification_key(&key)
+                .expect("Notifications not setup properly");
+        }
+    }
+}
+
+/// Isolated transaction of one of the modules sharing a
+/// [`SharedDatabaseTransaction`]
+pub struct SharedModuleTransaction<'s, 'a, 'parent> {
+    tx: LockedDatabaseTransaction<'s, 'a, 'parent>,
+    module_instance_id: ModuleInstanceId,
+    decoders: &'s ModuleDecoderRegistry,
+    commit_tracker: CommitTracker,
+    num_writes: &'s AtomicUsize,
+}
+
+impl<'s, 'a: 's, 'parent: 'a> SharedModuleTransaction<'s, 'a, 'parent> {
+    pub fn dbtx(&mut self) -> ModuleDatabaseTransaction<'_, ModuleInstanceId> {
+        ModuleDatabaseTransaction::new(
+            &mut self.tx,
+            Some(self.module_instance_id),
+            self.decoders,
+            &mut self.commit_tracker,
+        )
+    }
+}
+
+impl<'s, 'a, 'parent> Drop for SharedModuleTransaction<'s, 'a, 'parent> {
+    fn drop(&mut self) {
+        // the writes are committed along with the shared transaction
+        self.num_writes
+            .fetch_add(self.commit_tracker.num_writes, Ordering::Relaxed);
+        self.commit_tracker.is_committed = true;
+    }
+}
+
+/// Takes the lock of a [`SharedDatabaseTransaction`] for every access
+struct LockedDatabaseTransaction<'s, 'a, 'parent> {
+    tx: &'s futures::lock::Mutex<&'a mut dyn ISingleUseDatabaseTransaction<'parent>>,
+    notification_keys: &'s std::sync::Mutex<Vec<Vec<u8>>>,
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<'s, 'a: 's, 'parent: 'a> ISingleUseDatabaseTransaction<'s>
+    for LockedDatabaseTransaction<'s, 'a, 'parent>
+{
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.tx.lock().await.raw_insert_bytes(key, value).await
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.tx.lock().await.raw_get_bytes(key).await
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.tx.lock().await.raw_remove_entry(key).await
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        // the stream can't hold the lock, so the entries are read up front
+        let mut tx = self.tx.lock().await;
+        let entries = tx
+            .raw_find_by_prefix(key_prefix)
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
+        self.tx.lock().await.raw_remove_by_prefix(key_prefix).await
+    }
+
+    async fn commit_tx(&mut self) -> Result<()> {
+        panic!("DatabaseTransaction shared by modules cannot be committed by them");
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        anyhow::bail!("Rolling back a shared transaction would undo writes of other modules")
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        anyhow::bail!("Savepoints of a shared transaction would span writes of other modules")
+    }
+
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        self.notification_keys
+            .lock()
+            .expect("Notification keys lock poisoned")
+            .push(key.to_vec());
+        Ok(())
+    }
+}
+
 /// `DatabaseTransaction` is the parent-level database transaction that can
 /// modify the database. The owner of the `DatabaseTransaction` is responsible
 /// for managing the lifetime of the `DatabaseTransaction`, either by committing
@@ -897,7 +1194,7 @@ impl<'parent> DatabaseTransaction<'parent> {
             decoders,
             commit_tracker: CommitTracker {
                 is_committed: false,
-                has_writes: false,
+                num_writes: 0,
             },
         }
     }
@@ -914,6 +1211,18 @@ impl<'parent> DatabaseTransaction<'parent> {
         )
     }
 
+    /// Shares the transaction between modules that use it concurrently, see
+    /// [`SharedDatabaseTransaction`]
+    pub fn share_between_modules(&mut self) -> SharedDatabaseTransaction<'_, 'parent> {
+        SharedDatabaseTransaction {
+            tx: futures::lock::Mutex::new(self.tx.as_mut()),
+            decoders: &self.decoders,
+            commit_tracker: &mut self.commit_tracker,
+            num_writes: AtomicUsize::new(0),
+            notification_keys: std::sync::Mutex::new(vec![]),
+        }
+    }
+
     pub fn get_isolated(&mut self) -> ModuleDatabaseTransaction<'_, ModuleInstanceId> {
         ModuleDatabaseTransaction::new(
             self.tx.as_mut(),
@@ -937,6 +1246,12 @@ impl<'parent> DatabaseTransaction<'parent> {
         }
     }
 
+    /// Returns the number of inserts and removals made through this
+    /// transaction so far
+    pub fn num_writes(&self) -> usize {
+        self.commit_tracker.num_writes
+    }
+
     pub async fn commit_tx_result(mut self) -> Result<()> {
         self.commit_tracker.is_committed = true;
         return self.tx.commit_tx().await;
@@ -1005,7 +1320,7 @@ impl<'parent> DatabaseTransaction<'parent> {
     where
         K: DatabaseKey + DatabaseRecord,
     {
-        self.commit_tracker.has_writes = true;
+        self.commit_tracker.num_writes += 1;
         if <K as DatabaseKey>::NOTIFY_ON_MODIFY {
             self.add_notification_key(key);
         }
@@ -1025,7 +1340,7 @@ impl<'parent> DatabaseTransaction<'parent> {
     where
         K: DatabaseKey + DatabaseRecord,
     {
-        self.commit_tracker.has_writes = true;
+        self.commit_tracker.num_writes += 1;
         if <K as DatabaseKey>::NOTIFY_ON_MODIFY {
             self.add_notification_key(key);
         }
@@ -1059,7 +1374,7 @@ impl<'parent> DatabaseTransaction<'parent> {
     where
         K: DatabaseKey + DatabaseRecord,
     {
-        self.commit_tracker.has_writes = true;
+        self.commit_tracker.num_writes += 1;
         if <K as DatabaseKey>::NOTIFY_ON_MODIFY {
             self.add_notification_key(key);
         }
@@ -1083,13 +1398,45 @@ impl<'parent> DatabaseTransaction<'parent> {
     where
         KP: DatabaseLookup,
     {
-        self.commit_tracker.has_writes = true;
+        self.commit_tracker.num_writes += 1;
         self.tx
             .raw_remove_by_prefix(&key_prefix.to_bytes())
             .await
             .expect("Unrecoverable error occurred while removing by prefix");
     }
 
+    /// Returns the undecoded keys and values of all entries starting with
+    /// `key_prefix`, for copying the database without knowing its schema
+    #[instrument(level = "debug", skip_all)]
+    pub async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.tx
+            .raw_find_by_prefix(key_prefix)
+            .await
+            .expect("Error doing prefix search in database")
+            .collect()
+            .await
+    }
+
+    /// Inserts an undecoded entry, see [`Self::raw_find_by_prefix`]
+    #[instrument(level = "debug", skip_all)]
+    pub async fn raw_insert_bytes(&mut self, key: &[u8], value: Vec<u8>) {
+        self.commit_tracker.num_writes += 1;
+        self.tx
+            .raw_insert_bytes(key, value)
+            .await
+            .expect("Unrecoverable error while inserting into the database");
+    }
+
+    /// Removes an undecoded entry, see [`Self::raw_find_by_prefix`]
+    #[instrument(level = "debug", skip_all)]
+    pub async fn raw_remove_entry(&mut self, key: &[u8]) {
+        self.commit_tracker.num_writes += 1;
+        self.tx
+            .raw_remove_entry(key)
+            .await
+            .expect("Unrecoverable error occurred while removing an entry from the database");
+    }
+
     #[instrument(level = "debug", skip_all, ret)]
     pub async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
         self.tx.rollback_tx_to_savepoint().await
@@ -1261,6 +1608,24 @@ impl_db_record!(
     db_prefix = DbKeyPrefix::DatabaseVersion
 );
 
+/// Version of a [`namespace::DatabaseNamespace::Prefixes`] namespace, which
+/// shares the root key space with other namespaces
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct NamespaceVersionKey(pub String);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct NamespaceVersionKeyPrefix;
+
+impl_db_record!(
+    key = NamespaceVersionKey,
+    value = DatabaseVersion,
+    db_prefix = DbKeyPrefix::NamespaceVersion
+);
+impl_db_lookup!(
+    key = NamespaceVersionKey,
+    query_prefix = NamespaceVersionKeyPrefix
+);
+
 impl std::fmt::Display for DatabaseVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", self.0)
@@ -1283,6 +1648,7 @@ impl std::fmt::Display for DbKeyPrefix {
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
     DatabaseVersion = 0x50,
+    NamespaceVersion = 0x51,
 }
 
 #[derive(Debug, Error)]
@@ -1374,8 +1740,22 @@ pub async fn apply_migrations<'a>(
     target_db_version: DatabaseVersion,
     migrations: MigrationMap<'a>,
 ) -> Result<(), anyhow::Error> {
+    apply_migrations_with_key(db, kind, &DatabaseVersionKey, target_db_version, migrations).await
+}
+
+/// Like [`apply_migrations`], but keeps the on disk version under `version_key`
+async fn apply_migrations_with_key<'a, K>(
+    db: &'a Database,
+    kind: String,
+    version_key: &K,
+    target_db_version: DatabaseVersion,
+    migrations: MigrationMap<'a>,
+) -> Result<(), anyhow::Error>
+where
+    K: DatabaseKey + DatabaseRecord<Value = DatabaseVersion>,
+{
     let mut dbtx = db.begin_transaction().await;
-    let disk_version = dbtx.get_value(&DatabaseVersionKey).await;
+    let disk_version = dbtx.get_value(version_key).await;
     let db_version = if let Some(disk_version) = disk_version {
         let mut current_db_version = disk_version;
 
@@ -1393,14 +1773,12 @@ pub async fn apply_migrations<'a>(
             }
 
             current_db_version.increment();
-            dbtx.insert_entry(&DatabaseVersionKey, &current_db_version)
-                .await;
+            dbtx.insert_entry(version_key, &current_db_version).await;
         }
 
         current_db_version
     } else {
-        dbtx.insert_entry(&DatabaseVersionKey, &target_db_version)
-            .await;
+        dbtx.insert_entry(version_key, &target_db_version).await;
         target_db_version
     };
 
@@ -1411,13 +1789,14 @@ pub async fn apply_migrations<'a>(
 
 #[allow(unused_imports)]
 mod test_utils {
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::time::Duration;
 
     use futures::{Future, FutureExt, StreamExt};
 
     use super::{
-        apply_migrations, Database, DatabaseTransaction, DatabaseVersion, DatabaseVersionKey,
-        MigrationMap,
+        apply_migrations, CommitStats, Database, DatabaseTransaction, DatabaseVersion,
+        DatabaseVersionKey, MigrationMap,
     };
     use crate::core::ModuleKind;
     use crate::db::mem_impl::MemDatabase;
@@ -1767,6 +2146,78 @@ mod test_utils {
         dbtx3.commit_tx_result().await.expect_err("Expecting an error to be returned because this transaction is in a write-write conflict with dbtx");
     }
 
+    pub async fn verify_autocommit_retries_conflicts(db: Database) {
+        let runs = AtomicUsize::new(0);
+
+        let value = db
+            .autocommit_bounded::<_, _, ()>(|dbtx| {
+                let db = &db;
+                let runs = &runs;
+                Box::pin(async move {
+                    let value = dbtx.get_value(&TestKey(1)).await.map_or(0, |v| v.0) + 1;
+                    dbtx.insert_entry(&TestKey(1), &TestVal(value)).await;
+
+                    // a concurrent write to the same key makes the first commit fail
+                    if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                        let mut concurrent = db.begin_transaction().await;
+                        concurrent.insert_entry(&TestKey(1), &TestVal(10)).await;
+                        concurrent.commit_tx().await;
+                    }
+                    Ok(value)
+                })
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(value, 11);
+        assert_eq!(
+            db.commit_stats(),
+            CommitStats {
+                commits: 1,
+                conflicts: 1,
+                exhausted: 0
+            }
+        );
+    }
+
+    pub async fn verify_watch_prefix(db: Database) {
+        let mut watch = Box::pin(db.watch_prefix(&DbPrefixTestPrefix).unwrap());
+        let module_db = db.new_isolated(1);
+        let mut module_watch = Box::pin(module_db.watch_prefix(&DbPrefixTestPrefix).unwrap());
+
+        // changes that were rolled back aren't seen
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(1), &TestVal(1)).await;
+        dbtx.rollback_tx_to_savepoint().await.unwrap();
+        dbtx.commit_tx().await;
+        assert!(future_returns_shortly(watch.next()).await.is_none());
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(1), &TestVal(2)).await;
+        dbtx.insert_entry(&AltTestKey(1), &TestVal(3)).await;
+        dbtx.commit_tx().await;
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.remove_entry(&TestKey(1)).await;
+        dbtx.commit_tx().await;
+
+        let (key, value) = watch.next().await.unwrap();
+        assert_eq!((key.0, value.map(|v| v.0)), (1, Some(2)));
+        let (key, value) = watch.next().await.unwrap();
+        assert_eq!((key.0, value.map(|v| v.0)), (1, None));
+        assert!(future_returns_shortly(watch.next()).await.is_none());
+
+        // watching an isolated database only sees the keys of its module
+        assert!(future_returns_shortly(module_watch.next()).await.is_none());
+        let mut module_dbtx = module_db.begin_transaction().await;
+        module_dbtx.insert_entry(&TestKey(3), &TestVal(4)).await;
+        module_dbtx.commit_tx().await;
+
+        let (key, value) = module_watch.next().await.unwrap();
+        assert_eq!((key.0, value.map(|v| v.0)), (3, Some(4)));
+        assert!(future_returns_shortly(watch.next()).await.is_none());
+    }
+
     pub async fn verify_string_prefix(db: Database) {
         let mut dbtx = db.begin_transaction().await;
         dbtx.insert_entry(&PercentTestKey(100), &TestVal(101)).await;
@@ -2046,8 +2497,8 @@ mod test_utils {
         use async_trait::async_trait;
 
         use crate::db::{
-            AutocommitError, IDatabase, IDatabaseTransaction, ISingleUseDatabaseTransaction,
-            SingleUseDatabaseTransaction,
+            AutocommitError, CommitStats, IDatabase, IDatabaseTransaction,
+            ISingleUseDatabaseTransaction, SingleUseDatabaseTransaction,
         };
         use crate::ModuleDecoderRegistry;
 
@@ -2118,6 +2569,14 @@ mod test_utils {
             }
             AutocommitError::ClosureError { .. } => panic!("Closure did not return error"),
         }
+        assert_eq!(
+            db.commit_stats(),
+            CommitStats {
+                commits: 0,
+                conflicts: 5,
+                exhausted: 1
+            }
+        );
     }
 }
 
@@ -2140,6 +2599,51 @@ mod tests {
         join_handle
     }
 
+    #[tokio::test]
+    async fn test_shared_module_transactions() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let key_task = waiter(&db.new_isolated(2), TestKey(1)).await;
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let shared_dbtx = dbtx.share_between_modules();
+            let mut first = shared_dbtx.module_tx(1);
+            let mut second = shared_dbtx.module_tx(2);
+            futures::join!(
+                async {
+                    let mut dbtx = first.dbtx();
+                    dbtx.insert_entry(&TestKey(1), &TestVal(1)).await;
+                    dbtx.insert_entry(&TestKey(2), &TestVal(2)).await;
+                },
+                async {
+                    let mut dbtx = second.dbtx();
+                    dbtx.insert_entry(&TestKey(1), &TestVal(3)).await;
+                }
+            );
+        }
+        assert_eq!(dbtx.num_writes(), 3);
+        dbtx.commit_tx().await;
+        assert_eq!(
+            future_returns_shortly(async { key_task.await.unwrap() }).await,
+            Some(TestVal(3)),
+            "should notify"
+        );
+
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.with_module_prefix(1).get_value(&TestKey(2)).await,
+            Some(TestVal(2))
+        );
+        assert_eq!(
+            dbtx.with_module_prefix(2).get_value(&TestKey(1)).await,
+            Some(TestVal(3))
+        );
+        assert_eq!(
+            dbtx.with_module_prefix(2).get_value(&TestKey(2)).await,
+            None
+        );
+    }
+
     #[tokio::test]
     async fn test_wait_key_before_transaction() {
         let key = TestKey(1);
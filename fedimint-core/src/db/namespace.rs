@@ -0,0 +1,328 @@
+//! Splits a database between the stores sharing it, so each of them tracks its
+//! own version and can be migrated without knowing about the keys of the others
+
+use std::collections::BTreeMap;
+
+use fedimint_logging::LOG_DB;
+use thiserror::Error;
+use tracing::info;
+
+use super::{
+    apply_migrations, apply_migrations_with_key, Database, DatabaseVersion, DbKeyPrefix,
+    MigrationMap, NamespaceVersionKey, MODULE_GLOBAL_PREFIX,
+};
+use crate::core::ModuleInstanceId;
+
+/// Prefixes of the root key space that no store can register, since they hold
+/// the versions of the namespaces and the key spaces of the module instances
+pub const RESERVED_PREFIXES: [u8; 3] = [
+    DbKeyPrefix::DatabaseVersion as u8,
+    DbKeyPrefix::NamespaceVersion as u8,
+    MODULE_GLOBAL_PREFIX,
+];
+
+/// The part of a database owned by one store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseNamespace {
+    /// The isolated key space of a module instance, see
+    /// [`Database::new_isolated`]
+    Module {
+        module_instance_id: ModuleInstanceId,
+        kind: String,
+    },
+    /// The keys of the root key space starting with one of `prefixes`
+    Prefixes { name: String, prefixes: Vec<u8> },
+}
+
+impl DatabaseNamespace {
+    pub fn name(&self) -> &str {
+        match self {
+            DatabaseNamespace::Module { kind, .. } => kind,
+            DatabaseNamespace::Prefixes { name, .. } => name,
+        }
+    }
+
+    /// Returns the view of the root database `db` the store of the namespace
+    /// works on
+    pub fn database(&self, db: &Database) -> Database {
+        match self {
+            DatabaseNamespace::Module {
+                module_instance_id, ..
+            } => db.new_isolated(*module_instance_id),
+            DatabaseNamespace::Prefixes { .. } => db.clone(),
+        }
+    }
+
+    /// Migrates the namespace from its version on disk to `target_db_version`
+    /// like [`apply_migrations`], the versions of the other namespaces stay
+    /// untouched
+    ///
+    /// `db` has to be the view returned by [`DatabaseNamespace::database`].
+    /// The migrations of a [`DatabaseNamespace::Prefixes`] namespace run on
+    /// the root key space and must only touch the keys of its own prefixes.
+    pub async fn apply_migrations<'a>(
+        &self,
+        db: &'a Database,
+        target_db_version: DatabaseVersion,
+        migrations: MigrationMap<'a>,
+    ) -> anyhow::Result<()> {
+        match self {
+            DatabaseNamespace::Module {
+                module_instance_id,
+                kind,
+            } => {
+                anyhow::ensure!(
+                    db.module_instance_id == Some(*module_instance_id),
+                    "Database of module {kind} is not isolated to its instance"
+                );
+                apply_migrations(db, kind.clone(), target_db_version, migrations).await
+            }
+            DatabaseNamespace::Prefixes { name, .. } => {
+                anyhow::ensure!(
+                    db.module_instance_id.is_none(),
+                    "Database of {name} is isolated to a module instance"
+                );
+                apply_migrations_with_key(
+                    db,
+                    name.clone(),
+                    &NamespaceVersionKey(name.clone()),
+                    target_db_version,
+                    migrations,
+                )
+                .await
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum NamespaceError {
+    #[error("Prefix {prefix:#04x} of {name} is reserved")]
+    ReservedPrefix { name: String, prefix: u8 },
+    #[error("Prefix {prefix:#04x} of {name} is already owned by {owner}")]
+    PrefixTaken {
+        name: String,
+        prefix: u8,
+        owner: String,
+    },
+    #[error("Module instance {module_instance_id} of {kind} is already owned by {owner}")]
+    ModuleTaken {
+        kind: String,
+        module_instance_id: ModuleInstanceId,
+        owner: String,
+    },
+    #[error("The database has keys with prefixes no namespace owns: {prefixes:02x?}")]
+    UnownedKeys { prefixes: Vec<u8> },
+}
+
+/// Registers the namespaces of a database, making sure that no two stores
+/// claim the same keys
+#[derive(Debug, Default)]
+pub struct DbPrefixRegistry {
+    prefixes: BTreeMap<u8, String>,
+    modules: BTreeMap<ModuleInstanceId, String>,
+}
+
+impl DbPrefixRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a namespace of the root key space owning all keys starting
+    /// with one of `prefixes`
+    pub fn register_prefixes(
+        &mut self,
+        name: &str,
+        prefixes: impl IntoIterator<Item = u8>,
+    ) -> Result<DatabaseNamespace, NamespaceError> {
+        let prefixes = prefixes.into_iter().collect::<Vec<_>>();
+        for &prefix in &prefixes {
+            if RESERVED_PREFIXES.contains(&prefix) {
+                return Err(NamespaceError::ReservedPrefix {
+                    name: name.to_string(),
+                    prefix,
+                });
+            }
+            if let Some(owner) = self.prefixes.get(&prefix) {
+                return Err(NamespaceError::PrefixTaken {
+                    name: name.to_string(),
+                    prefix,
+                    owner: owner.clone(),
+                });
+            }
+        }
+
+        for &prefix in &prefixes {
+            self.prefixes.insert(prefix, name.to_string());
+        }
+        Ok(DatabaseNamespace::Prefixes {
+            name: name.to_string(),
+            prefixes,
+        })
+    }
+
+    /// Registers the isolated key space of a module instance
+    pub fn register_module(
+        &mut self,
+        module_instance_id: ModuleInstanceId,
+        kind: &str,
+    ) -> Result<DatabaseNamespace, NamespaceError> {
+        if let Some(owner) = self.modules.get(&module_instance_id) {
+            return Err(NamespaceError::ModuleTaken {
+                kind: kind.to_string(),
+                module_instance_id,
+                owner: owner.clone(),
+            });
+        }
+
+        self.modules.insert(module_instance_id, kind.to_string());
+        Ok(DatabaseNamespace::Module {
+            module_instance_id,
+            kind: kind.to_string(),
+        })
+    }
+
+    /// Returns the name of the namespace owning the keys starting with
+    /// `prefix`
+    pub fn owner(&self, prefix: u8) -> Option<&str> {
+        self.prefixes.get(&prefix).map(String::as_str)
+    }
+
+    /// Fails if the root key space of `db` has keys no namespace owns, since
+    /// they would be iterated over by the store that registers their prefix
+    /// next
+    pub async fn verify(&self, db: &Database) -> Result<(), NamespaceError> {
+        let mut dbtx = db.begin_transaction().await;
+        let mut unowned = vec![];
+        for prefix in u8::MIN..=u8::MAX {
+            if RESERVED_PREFIXES.contains(&prefix) || self.prefixes.contains_key(&prefix) {
+                continue;
+            }
+            if !dbtx.raw_find_by_prefix(&[prefix]).await.is_empty() {
+                unowned.push(prefix);
+            }
+        }
+
+        if !unowned.is_empty() {
+            return Err(NamespaceError::UnownedKeys { prefixes: unowned });
+        }
+
+        info!(
+            target: LOG_DB,
+            prefixes = self.prefixes.len(),
+            modules = self.modules.len(),
+            "Verified database namespaces"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::{DbPrefixRegistry, NamespaceError};
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::{
+        Database, DatabaseVersion, DatabaseVersionKey, MigrationMap, NamespaceVersionKey,
+    };
+    use crate::module::registry::ModuleDecoderRegistry;
+
+    #[test]
+    fn test_registry_rejects_overlapping_prefixes() {
+        let mut registry = DbPrefixRegistry::new();
+        registry.register_prefixes("a", [0x01, 0x02]).unwrap();
+        registry.register_module(0, "mint").unwrap();
+
+        assert_eq!(
+            registry.register_prefixes("b", [0x03, 0x02]),
+            Err(NamespaceError::PrefixTaken {
+                name: "b".to_string(),
+                prefix: 0x02,
+                owner: "a".to_string(),
+            })
+        );
+        assert!(matches!(
+            registry.register_prefixes("c", [0x50]),
+            Err(NamespaceError::ReservedPrefix { .. })
+        ));
+        assert!(matches!(
+            registry.register_module(0, "ln"),
+            Err(NamespaceError::ModuleTaken { .. })
+        ));
+
+        // failed registrations don't claim any prefix
+        assert_eq!(registry.owner(0x02), Some("a"));
+        assert_eq!(registry.owner(0x03), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_namespaces_track_versions_independently() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut registry = DbPrefixRegistry::new();
+        let first = registry.register_prefixes("first", [0x01]).unwrap();
+        let second = registry.register_prefixes("second", [0x02]).unwrap();
+        let module = registry.register_module(3, "module").unwrap();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &NamespaceVersionKey("second".to_string()),
+            &DatabaseVersion(0),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        first
+            .apply_migrations(
+                &first.database(&db),
+                DatabaseVersion(0),
+                MigrationMap::new(),
+            )
+            .await
+            .unwrap();
+        let mut migrations = MigrationMap::new();
+        migrations.insert(DatabaseVersion(0), |_| async { Ok(()) }.boxed());
+        second
+            .apply_migrations(&second.database(&db), DatabaseVersion(1), migrations)
+            .await
+            .unwrap();
+        let module_db = module.database(&db);
+        module
+            .apply_migrations(&module_db, DatabaseVersion(2), MigrationMap::new())
+            .await
+            .unwrap();
+
+        // a namespace has to be migrated on its own view of the database
+        assert!(module
+            .apply_migrations(&db, DatabaseVersion(2), MigrationMap::new())
+            .await
+            .is_err());
+
+        let mut dbtx = db.begin_transaction().await;
+        let version = |name: &str| NamespaceVersionKey(name.to_string());
+        assert_eq!(
+            dbtx.get_value(&version("first")).await,
+            Some(DatabaseVersion(0))
+        );
+        assert_eq!(
+            dbtx.get_value(&version("second")).await,
+            Some(DatabaseVersion(1))
+        );
+        assert_eq!(dbtx.get_value(&DatabaseVersionKey).await, None);
+        let mut module_dbtx = module_db.begin_transaction().await;
+        assert_eq!(
+            module_dbtx.get_value(&DatabaseVersionKey).await,
+            Some(DatabaseVersion(2))
+        );
+
+        registry.verify(&db).await.unwrap();
+        dbtx.raw_insert_bytes(&[0x04, 0x00], vec![]).await;
+        dbtx.commit_tx().await;
+        assert_eq!(
+            registry.verify(&db).await,
+            Err(NamespaceError::UnownedKeys {
+                prefixes: vec![0x04]
+            })
+        );
+    }
+}
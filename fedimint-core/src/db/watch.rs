@@ -0,0 +1,69 @@
+//! Lets components wait for changes of a range of keys instead of polling the
+//! database
+
+use std::sync::Mutex;
+
+use futures::channel::mpsc;
+
+use super::MutationStream;
+
+/// A change of a key made by a committed transaction
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum KeyMutation {
+    /// The key was inserted or its value overwritten
+    Insert { key: Vec<u8>, value: Vec<u8> },
+    /// The key was removed, possibly without having existed before
+    Remove { key: Vec<u8> },
+}
+
+impl KeyMutation {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            KeyMutation::Insert { key, .. } | KeyMutation::Remove { key } => key,
+        }
+    }
+}
+
+/// Keeps the prefixes watched on a database, backends publish the mutations of
+/// every committed transaction to it
+#[derive(Debug, Default)]
+pub struct PrefixWatchers {
+    watchers: Mutex<Vec<(Vec<u8>, mpsc::UnboundedSender<KeyMutation>)>>,
+}
+
+impl PrefixWatchers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a stream of all mutations of keys starting with `prefix` that
+    /// are published after this call
+    pub fn watch(&self, prefix: &[u8]) -> MutationStream {
+        let (sender, receiver) = mpsc::unbounded();
+        self.watchers
+            .lock()
+            .expect("locking can't fail")
+            .push((prefix.to_vec(), sender));
+        Box::pin(receiver)
+    }
+
+    /// Sends `mutations` to the streams watching their keys, has to be called
+    /// after the transaction making them was committed
+    pub fn publish(&self, mutations: &[KeyMutation]) {
+        if mutations.is_empty() {
+            return;
+        }
+
+        // a failed send means the stream was dropped, so its watcher is removed
+        self.watchers
+            .lock()
+            .expect("locking can't fail")
+            .retain(|(prefix, sender)| {
+                mutations
+                    .iter()
+                    .filter(|mutation| mutation.key().starts_with(prefix))
+                    .all(|mutation| sender.unbounded_send(mutation.clone()).is_ok())
+                    && !sender.is_closed()
+            });
+    }
+}
@@ -0,0 +1,277 @@
+//! Snapshots of consensus encodings, so tests fail when the encoding of a
+//! type changes without its consensus version changing along with it
+//!
+//! A crate keeps one snapshot per consensus version in its
+//! `tests/encodings/` directory, mapping the names of sample values to the hex
+//! of their encoding. [`EncodingSnapshot::check`] compares the samples against
+//! the snapshot of the current version:
+//!
+//! * a sample encoding differently than recorded fails the check, the encoding
+//!   has to stay the same or the consensus version has to be bumped
+//! * samples missing from the snapshot, e.g. of new types, are recorded and
+//!   have to be committed with the change, unless [`FREEZE_ENV`] is set, which
+//!   fails the check instead
+//! * a missing snapshot fails the check, so a snapshot that wasn't committed
+//!   can't pass unnoticed, the snapshot of a version that was just bumped is
+//!   recorded with [`RECORD_ENV`] set
+//!
+//! Snapshots of older versions are kept, so the history of the encodings
+//! stays reviewable. Modules register their types with
+//! [`encoding_snapshot_test`](crate::encoding_snapshot_test).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::encoding::Encodable;
+
+/// Fails checks that would record new samples, e.g. in CI where recorded
+/// snapshots would be lost
+pub const FREEZE_ENV: &str = "FM_FREEZE_ENCODINGS";
+
+/// Records snapshots that don't exist yet, e.g. after bumping the consensus
+/// version, instead of failing the check
+pub const RECORD_ENV: &str = "FM_RECORD_ENCODINGS";
+
+/// Encodings of sample values, compared against a recorded snapshot
+#[derive(Debug, Default)]
+pub struct EncodingSnapshot {
+    samples: BTreeMap<String, String>,
+}
+
+impl EncodingSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the encoding of `value` under `name`
+    ///
+    /// # Panics
+    /// If a sample with the same name was already added
+    pub fn add(&mut self, name: &str, value: &impl Encodable) -> &mut Self {
+        let hex = value
+            .consensus_encode_to_hex()
+            .expect("Encoding to a vector can't fail");
+        assert!(
+            self.samples.insert(name.to_owned(), hex).is_none(),
+            "Sample {name} was added twice"
+        );
+        self
+    }
+
+    /// Compares the samples against the snapshot of `consensus_version` in
+    /// `dir` and records the ones missing from it
+    ///
+    /// # Panics
+    /// If a sample encodes differently than recorded, the snapshot is missing
+    /// while [`RECORD_ENV`] isn't set, or samples are missing while
+    /// [`FREEZE_ENV`] is set
+    pub fn check(&self, dir: impl AsRef<Path>, consensus_version: u32) {
+        let freeze = std::env::var_os(FREEZE_ENV).is_some();
+        let record = std::env::var_os(RECORD_ENV).is_some();
+        self.check_snapshot(dir.as_ref(), consensus_version, freeze, record);
+    }
+
+    fn check_snapshot(&self, dir: &Path, consensus_version: u32, freeze: bool, record: bool) {
+        let path = snapshot_path(dir, consensus_version);
+        let mut recorded = match read_snapshot(&path) {
+            Some(recorded) => recorded,
+            None => {
+                assert!(
+                    record && !freeze,
+                    "The snapshot {} is missing, commit it or run the test with {RECORD_ENV} \
+                     set to record it",
+                    path.display()
+                );
+                BTreeMap::new()
+            }
+        };
+
+        let changed = self
+            .samples
+            .iter()
+            .filter(|(name, hex)| {
+                recorded
+                    .get(*name)
+                    .map_or(false, |recorded| recorded != *hex)
+            })
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert!(
+            changed.is_empty(),
+            "The encodings of {changed:?} differ from {}, bump the consensus version or keep \
+             the encodings as they were",
+            path.display()
+        );
+
+        let missing = self
+            .samples
+            .iter()
+            .filter(|(name, _)| !recorded.contains_key(*name))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return;
+        }
+
+        let names = missing.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert!(
+            !freeze,
+            "The encodings of {names:?} are missing from {}, run the test without \
+             {FREEZE_ENV} to record them",
+            path.display()
+        );
+        eprintln!(
+            "Recording the encodings of {names:?} in {}, commit them with the change",
+            path.display()
+        );
+        recorded.extend(
+            missing
+                .into_iter()
+                .map(|(name, hex)| (name.clone(), hex.clone())),
+        );
+        write_snapshot(&path, &recorded);
+    }
+}
+
+fn snapshot_path(dir: &Path, consensus_version: u32) -> PathBuf {
+    dir.join(format!("v{consensus_version}.json"))
+}
+
+fn read_snapshot(path: &Path) -> Option<BTreeMap<String, String>> {
+    match fs::read_to_string(path) {
+        Ok(json) => Some(
+            serde_json::from_str(&json)
+                .unwrap_or_else(|e| panic!("Invalid snapshot {}: {e}", path.display())),
+        ),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => panic!("Failed to read snapshot {}: {e}", path.display()),
+    }
+}
+
+fn write_snapshot(path: &Path, snapshot: &BTreeMap<String, String>) {
+    let dir = path.parent().expect("Snapshots are stored in a directory");
+    fs::create_dir_all(dir).expect("Failed to create the snapshot directory");
+
+    let mut json = serde_json::to_string_pretty(snapshot).expect("Serializing a map can't fail");
+    json.push('\n');
+    fs::write(path, json).expect("Failed to write the snapshot");
+}
+
+/// Defines a test comparing the encodings of sample values against the
+/// snapshots in the `tests/encodings/` directory of the calling crate, see
+/// [`encoding::compat`](crate::encoding::compat)
+///
+/// ```ignore
+/// encoding_snapshot_test!(mint_encodings, CONSENSUS_VERSION.0, {
+///     "MintInput" => sample_input(),
+///     "MintOutput" => sample_output(),
+/// });
+/// ```
+#[macro_export]
+macro_rules! encoding_snapshot_test {
+    ($test:ident, $version:expr, { $($name:literal => $value:expr),* $(,)? }) => {
+        #[test]
+        fn $test() {
+            let mut snapshot = $crate::encoding::compat::EncodingSnapshot::new();
+            $(snapshot.add($name, &$value);)*
+            snapshot.check(
+                ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/encodings"),
+                $version,
+            );
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn snapshot_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("fedimint-encodings-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn records_missing_samples() {
+        let dir = snapshot_dir("record");
+        EncodingSnapshot::new()
+            .add("u32", &1u32)
+            .check_snapshot(&dir, 0, false, true);
+        EncodingSnapshot::new()
+            .add("u32", &1u32)
+            .add("u64", &2u64)
+            .check_snapshot(&dir, 0, false, false);
+
+        let recorded = read_snapshot(&snapshot_path(&dir, 0)).unwrap();
+        assert_eq!(recorded["u32"], "01000000");
+        assert_eq!(recorded["u64"], "0200000000000000");
+    }
+
+    #[test]
+    #[should_panic(expected = "differ from")]
+    fn fails_on_changed_encoding() {
+        let dir = snapshot_dir("change");
+        EncodingSnapshot::new()
+            .add("value", &1u32)
+            .check_snapshot(&dir, 0, false, true);
+        EncodingSnapshot::new()
+            .add("value", &1u64)
+            .check_snapshot(&dir, 0, false, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "are missing from")]
+    fn fails_on_missing_sample_when_frozen() {
+        let dir = snapshot_dir("freeze");
+        EncodingSnapshot::new()
+            .add("value", &1u32)
+            .check_snapshot(&dir, 0, false, true);
+        EncodingSnapshot::new()
+            .add("value", &1u32)
+            .add("other", &2u32)
+            .check_snapshot(&dir, 0, true, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "is missing")]
+    fn fails_on_missing_snapshot() {
+        let dir = snapshot_dir("missing");
+        EncodingSnapshot::new()
+            .add("value", &1u32)
+            .check_snapshot(&dir, 0, false, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "is missing")]
+    fn does_not_record_snapshot_when_frozen() {
+        let dir = snapshot_dir("record-frozen");
+        EncodingSnapshot::new()
+            .add("value", &1u32)
+            .check_snapshot(&dir, 0, true, true);
+    }
+
+    #[test]
+    fn accepts_changed_encoding_with_new_version() {
+        let dir = snapshot_dir("bump");
+        EncodingSnapshot::new()
+            .add("value", &1u32)
+            .check_snapshot(&dir, 0, false, true);
+        EncodingSnapshot::new()
+            .add("value", &1u64)
+            .check_snapshot(&dir, 1, false, true);
+
+        assert_eq!(
+            read_snapshot(&snapshot_path(&dir, 0)).unwrap()["value"],
+            "01000000"
+        );
+        assert_eq!(
+            read_snapshot(&snapshot_path(&dir, 1)).unwrap()["value"],
+            "0100000000000000"
+        );
+    }
+}
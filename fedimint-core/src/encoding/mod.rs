@@ -3,16 +3,19 @@
 //! need to be encoded to binary will be migrated to this interface.
 
 mod btc;
+pub mod compat;
 mod secp256k1;
 mod tbs;
 mod tls;
 
+use std::cell::Cell;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
 use std::io::{self, Error, Read, Write};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::format_err;
+use bincode::Options;
 use bitcoin_hashes::hex::ToHex;
 use bitcoin_hashes::sha256::HashEngine;
 use bitcoin_hashes::{sha256, Hash};
@@ -23,6 +26,19 @@ use url::Url;
 
 use crate::module::registry::ModuleDecoderRegistry;
 
+/// Maximum number of elements of a collection and of bytes of a string that
+/// are decoded, so a malformed length can't make decoding loop or allocate for
+/// a long time
+pub const MAX_DECODE_LENGTH: u64 = 16 * 1024 * 1024;
+
+/// Maximum number of nested boxes and vectors that are decoded, so malformed
+/// input of a recursive type can't overflow the stack
+pub const MAX_DECODE_DEPTH: usize = 64;
+
+thread_local! {
+    static DECODE_DEPTH: Cell<usize> = Cell::new(0);
+}
+
 /// Object-safe trait for things that can encode themselves
 ///
 /// Like `rust-bitcoin`'s `consensus_encode`, but without generics,
@@ -209,11 +225,40 @@ where
         d: &mut D,
         modules: &ModuleDecoderRegistry,
     ) -> Result<Self, DecodeError> {
-        let len = u64::consensus_decode(d, modules)?;
-        (0..len).map(|_| T::consensus_decode(d, modules)).collect()
+        let len = decode_length(d, modules)?;
+        decode_nested(|| (0..len).map(|_| T::consensus_decode(d, modules)).collect())
     }
 }
 
+/// Decodes the length of a collection, which mustn't exceed
+/// [`MAX_DECODE_LENGTH`]
+fn decode_length<D: std::io::Read>(
+    d: &mut D,
+    modules: &ModuleDecoderRegistry,
+) -> Result<u64, DecodeError> {
+    let len = u64::consensus_decode(d, modules)?;
+    if len > MAX_DECODE_LENGTH {
+        return Err(DecodeError(format_err!(
+            "Length {len} exceeds the maximum of {MAX_DECODE_LENGTH}"
+        )));
+    }
+    Ok(len)
+}
+
+/// Runs `decode` one level deeper, failing once more than
+/// [`MAX_DECODE_DEPTH`] levels are nested
+fn decode_nested<T>(decode: impl FnOnce() -> Result<T, DecodeError>) -> Result<T, DecodeError> {
+    let depth = DECODE_DEPTH.with(|depth| depth.get()) + 1;
+    if depth > MAX_DECODE_DEPTH {
+        return Err(DecodeError::from_str("Maximum decoding depth exceeded"));
+    }
+
+    DECODE_DEPTH.with(|d| d.set(depth));
+    let result = decode();
+    DECODE_DEPTH.with(|d| d.set(depth - 1));
+    result
+}
+
 impl<T, const SIZE: usize> Encodable for [T; SIZE]
 where
     T: Encodable,
@@ -296,7 +341,7 @@ where
         d: &mut D,
         modules: &ModuleDecoderRegistry,
     ) -> Result<Self, DecodeError> {
-        Ok(Box::new(T::consensus_decode(d, modules)?))
+        decode_nested(|| Ok(Box::new(T::consensus_decode(d, modules)?)))
     }
 }
 
@@ -346,7 +391,9 @@ impl Decodable for SystemTime {
         modules: &ModuleDecoderRegistry,
     ) -> Result<Self, DecodeError> {
         let duration = Duration::consensus_decode(d, modules)?;
-        Ok(UNIX_EPOCH + duration)
+        UNIX_EPOCH
+            .checked_add(duration)
+            .ok_or_else(|| DecodeError::from_str("Time out of range"))
     }
 }
 
@@ -366,7 +413,12 @@ impl Decodable for Duration {
         modules: &ModuleDecoderRegistry,
     ) -> Result<Self, DecodeError> {
         let secs = Decodable::consensus_decode(d, modules)?;
-        let nsecs = Decodable::consensus_decode(d, modules)?;
+        let nsecs: u32 = Decodable::consensus_decode(d, modules)?;
+        // `Duration::new` would carry excess nanoseconds into the seconds, which
+        // can overflow
+        if nsecs >= 1_000_000_000 {
+            return Err(DecodeError::from_str("Nanoseconds out of range"));
+        }
         Ok(Duration::new(secs, nsecs))
     }
 }
@@ -467,7 +519,7 @@ where
         modules: &ModuleDecoderRegistry,
     ) -> Result<Self, DecodeError> {
         let mut res = BTreeMap::new();
-        let len = u64::consensus_decode(d, modules)?;
+        let len = decode_length(d, modules)?;
         for _ in 0..len {
             let amt = K::consensus_decode(d, modules)?;
             let v = V::consensus_decode(d, modules)?;
@@ -502,7 +554,7 @@ where
         modules: &ModuleDecoderRegistry,
     ) -> Result<Self, DecodeError> {
         let mut res = BTreeSet::new();
-        let len = u64::consensus_decode(d, modules)?;
+        let len = decode_length(d, modules)?;
         for _ in 0..len {
             let k = K::consensus_decode(d, modules)?;
             if !res.insert(k) {
@@ -564,8 +616,14 @@ where
         r: &mut R,
         _modules: &ModuleDecoderRegistry,
     ) -> Result<Self, DecodeError> {
+        // same options as `bincode::deserialize_from`, but with a limit
         Ok(Self(
-            bincode::deserialize_from(r).map_err(|e| DecodeError(e.into()))?,
+            bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .allow_trailing_bytes()
+                .with_limit(MAX_DECODE_LENGTH)
+                .deserialize_from(r)
+                .map_err(|e| DecodeError(e.into()))?,
         ))
     }
 }
@@ -716,4 +774,39 @@ mod tests {
                 .is_err()
         );
     }
+
+    #[test]
+    fn test_decode_limits() {
+        fn decode<T: Decodable>(bytes: Vec<u8>) -> Result<T, DecodeError> {
+            T::consensus_decode(&mut Cursor::new(bytes), &ModuleDecoderRegistry::default())
+        }
+
+        // a vector of zero sized items would otherwise be decoded for a very long time
+        let too_long = (MAX_DECODE_LENGTH + 1).consensus_encode_to_vec().unwrap();
+        assert!(decode::<Vec<()>>(too_long.clone()).is_err());
+        assert!(decode::<BTreeSet<()>>(too_long).is_err());
+        let max_len = MAX_DECODE_LENGTH.consensus_encode_to_vec().unwrap();
+        assert_eq!(
+            decode::<Vec<()>>(max_len).unwrap().len() as u64,
+            MAX_DECODE_LENGTH
+        );
+
+        #[derive(Debug, Encodable, Decodable, Eq, PartialEq)]
+        struct Nested(Option<Box<Nested>>);
+
+        let mut nested = Nested(None);
+        for _ in 0..=MAX_DECODE_DEPTH {
+            nested = Nested(Some(Box::new(nested)));
+        }
+        assert!(decode::<Nested>(nested.consensus_encode_to_vec().unwrap()).is_err());
+        // the depth is reset after decoding failed
+        test_roundtrip(Nested(Some(Box::new(Nested(None)))));
+
+        let invalid_nanos = (u64::MAX, 1_000_000_000u32)
+            .consensus_encode_to_vec()
+            .unwrap();
+        assert!(decode::<Duration>(invalid_nanos).is_err());
+        let out_of_range = (u64::MAX, 0u32).consensus_encode_to_vec().unwrap();
+        assert!(decode::<SystemTime>(out_of_range).is_err());
+    }
 }
@@ -58,6 +58,12 @@ impl Encodable for threshold_crypto::PublicKey {
     }
 }
 
+impl Encodable for threshold_crypto::PublicKeyShare {
+    fn consensus_encode<W: Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        self.to_bytes().consensus_encode(writer)
+    }
+}
+
 impl Encodable for tbs::AggregatePublicKey {
     fn consensus_encode<W: Write>(&self, writer: &mut W) -> Result<usize, Error> {
         self.0.to_compressed().consensus_encode(writer)
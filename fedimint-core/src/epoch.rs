@@ -1,13 +1,17 @@
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use bitcoin_hashes::sha256::Hash as Sha256;
-use fedimint_core::core::DynModuleConsensusItem as ModuleConsensusItem;
+use fedimint_core::config::ApiEndpoint;
+use fedimint_core::core::{
+    DynModuleConsensusItem as ModuleConsensusItem, ModuleInstanceId, ModuleKind,
+};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable, UnzipConsensus};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::SerdeModuleEncoding;
-use fedimint_core::{PeerId, TransactionId};
+use fedimint_core::{Amount, PeerId, TransactionId};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use threshold_crypto::{PublicKey, PublicKeySet, Signature, SignatureShare};
 
 use crate::transaction::Transaction;
@@ -26,12 +30,228 @@ pub enum ConsensusItem {
     Transaction(Transaction),
     /// Any data that modules require consensus on
     Module(ModuleConsensusItem),
+    /// Vote to change the guardians of the federation
+    PeerSetChange(PeerSetChange),
+    /// Threshold sign the latest checkpoint for verification via the API
+    EpochCheckpointSignatureShare(SerdeSignatureShare),
+    /// Announces the newest consensus version the guardian's binary supports
+    SupportedConsensusVersion(SupportedConsensusVersion),
+    /// Vote to activate a new consensus version starting with an epoch
+    ConsensusVersionActivation(ConsensusVersionActivation),
+    /// Vote to change the timing and batching of consensus rounds
+    ConsensusParams(ConsensusParams),
+    /// Proposal to change the consensus limits starting with an epoch
+    ParamsChangeProposal(ParamsChangeProposal),
+    /// Hash of the guardian's consensus state after an epoch, so guardians
+    /// notice early when their states diverge
+    StateHash(StateHash),
 }
 
 /// May eventually contains consensus info about the upgrade
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
 pub struct ConsensusUpgrade;
 
+/// Vote to change the guardians of the federation from the peers with the API
+/// endpoints `from` to the peers with the API endpoints `to`
+///
+/// Once a threshold of the guardians voted for the same change the federation
+/// shuts down after the epoch and the keys are reshared to the new peers.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PeerSetChange {
+    pub from: BTreeMap<PeerId, ApiEndpoint>,
+    pub to: BTreeMap<PeerId, ApiEndpoint>,
+}
+
+/// The newest version of the consensus rules a guardian's binary supports
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SupportedConsensusVersion {
+    pub version: u32,
+}
+
+/// Vote to switch to the consensus rules of `version` starting with `epoch`
+///
+/// Once a threshold of the guardians voted for the same activation guardians
+/// running a binary that doesn't support `version` stop before processing
+/// `epoch`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ConsensusVersionActivation {
+    pub version: u32,
+    pub epoch: u64,
+}
+
+/// Timing and batching of consensus rounds, agreed on by the federation
+///
+/// The initial parameters are part of the consensus config, they are replaced
+/// once a threshold of the guardians voted for the same parameters.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(default)]
+pub struct ConsensusParams {
+    /// Minimum time between the start of two epochs in milliseconds, items
+    /// arriving in the meantime are batched into the next epoch
+    pub round_interval_ms: u64,
+    /// Maximum number of transactions and module items in a proposal, items
+    /// that don't fit are proposed in a later epoch
+    pub max_proposal_items: u64,
+    /// Maximum number of items a module may contribute to a proposal, modules
+    /// without an entry are only limited by `max_proposal_items`
+    pub module_batch_limits: BTreeMap<ModuleInstanceId, u64>,
+}
+
+/// Proposal to replace the [`ConsensusLimits`] with `limits` and to change
+/// the adjustable parameters of modules starting with `epoch`
+///
+/// Once a threshold of the guardians proposed the same change it is scheduled
+/// and every guardian applies it from `epoch` on, so the parameters never
+/// differ between guardians while processing an epoch.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ParamsChangeProposal {
+    pub epoch: u64,
+    pub limits: ConsensusLimits,
+    /// Parameters to change by module, the ones left out keep their values
+    ///
+    /// Client configs keep the values the federation was set up with, so
+    /// changed fees have to be announced to the users separately.
+    #[serde(default)]
+    pub module_params: BTreeMap<ModuleInstanceId, ModuleParams>,
+}
+
+/// Adjustable parameters of a module by name, e.g. its fees in msats or the
+/// confirmation depth of the wallet
+pub type ModuleParams = BTreeMap<String, u64>;
+
+/// Hash of the canonical encoding of a guardian's consensus state after
+/// processing `epoch`
+///
+/// Entries that legitimately differ between guardians, like their epoch
+/// history or the shares they are about to propose, are left out, so the
+/// hashes of all honest guardians are equal.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct StateHash {
+    pub epoch: u64,
+    pub hash: Sha256,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self {
+            round_interval_ms: 0,
+            max_proposal_items: 10_000,
+            module_batch_limits: BTreeMap::new(),
+        }
+    }
+}
+
+/// Limits on the size of the transactions and module consensus items the
+/// federation processes, so oversized ones can't stall the epochs
+///
+/// They are part of the consensus config and published in the client config,
+/// so clients can keep their transactions within them. Guardians can replace
+/// them at runtime with a [`ParamsChangeProposal`], the client config keeps
+/// the initial limits.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+#[serde(default)]
+pub struct ConsensusLimits {
+    /// Maximum number of inputs of a transaction
+    pub max_transaction_inputs: u64,
+    /// Maximum number of outputs of a transaction
+    pub max_transaction_outputs: u64,
+    /// Maximum size of the encoded transaction in bytes
+    pub max_transaction_size: u64,
+    /// Maximum size of an encoded module consensus item in bytes, for modules
+    /// without an entry in `module_item_sizes`
+    pub max_module_item_size: u64,
+    /// Maximum size of an encoded consensus item of a module in bytes
+    pub module_item_sizes: BTreeMap<ModuleInstanceId, u64>,
+}
+
+impl Default for ConsensusLimits {
+    fn default() -> Self {
+        Self {
+            max_transaction_inputs: 1_000,
+            max_transaction_outputs: 1_000,
+            max_transaction_size: 1_000_000,
+            max_module_item_size: 1_000_000,
+            module_item_sizes: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConsensusLimits {
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<(), LimitError> {
+        let inputs = transaction.inputs.len() as u64;
+        if inputs > self.max_transaction_inputs {
+            return Err(LimitError::TooManyInputs {
+                inputs,
+                max: self.max_transaction_inputs,
+            });
+        }
+
+        let outputs = transaction.outputs.len() as u64;
+        if outputs > self.max_transaction_outputs {
+            return Err(LimitError::TooManyOutputs {
+                outputs,
+                max: self.max_transaction_outputs,
+            });
+        }
+
+        let size = encoded_size(transaction);
+        if size > self.max_transaction_size {
+            return Err(LimitError::TransactionTooLarge {
+                size,
+                max: self.max_transaction_size,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn check_module_item(&self, item: &ModuleConsensusItem) -> Result<(), LimitError> {
+        let module_instance_id = item.module_instance_id();
+        let max = self
+            .module_item_sizes
+            .get(&module_instance_id)
+            .copied()
+            .unwrap_or(self.max_module_item_size);
+        let size = encoded_size(item);
+        if size > max {
+            return Err(LimitError::ModuleItemTooLarge {
+                module_instance_id,
+                size,
+                max,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn encoded_size(value: &impl Encodable) -> u64 {
+    value
+        .consensus_encode(&mut std::io::sink())
+        .expect("Writing to a sink can't fail") as u64
+}
+
+/// Why a transaction or consensus item exceeds the [`ConsensusLimits`],
+/// returned to the submitters of rejected transactions as the data of the API
+/// error
+#[derive(Debug, Clone, Eq, PartialEq, Error, Serialize, Deserialize)]
+pub enum LimitError {
+    #[error("Transaction has {inputs} inputs, at most {max} are allowed")]
+    TooManyInputs { inputs: u64, max: u64 },
+    #[error("Transaction has {outputs} outputs, at most {max} are allowed")]
+    TooManyOutputs { outputs: u64, max: u64 },
+    #[error("Transaction has {size} bytes, at most {max} are allowed")]
+    TransactionTooLarge { size: u64, max: u64 },
+    #[error(
+        "Consensus item of module {module_instance_id} has {size} bytes, at most {max} are allowed"
+    )]
+    ModuleItemTooLarge {
+        module_instance_id: ModuleInstanceId,
+        size: u64,
+        max: u64,
+    },
+}
+
 pub type SerdeConsensusItem = SerdeModuleEncoding<ConsensusItem>;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -166,6 +386,197 @@ impl SignedEpochOutcome {
     }
 }
 
+/// Epochs before a checkpoint that are never pruned
+///
+/// Outputs issued shortly before a checkpoint might only be signed after it,
+/// so clients recovering from a checkpoint start scanning the epoch history
+/// this many epochs before it.
+pub const MIN_EPOCHS_BEFORE_CHECKPOINT: u64 = 10;
+
+/// Changes to the federation state up to `epoch` that let clients recover
+/// without the epoch history before it, so guardians can prune it
+///
+/// Each checkpoint only contains the changes since the previous one and
+/// commits to it by its hash, so the signature of the latest checkpoint
+/// covers all earlier ones.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct EpochCheckpoint {
+    pub epoch: u64,
+    /// Hash of the outcome of `epoch`, linking the checkpoint to the epoch
+    /// history
+    pub epoch_hash: Sha256,
+    /// Epoch and hash of the previous checkpoint, `None` for the first one
+    pub previous: Option<(u64, Sha256)>,
+    /// Changes to the module states since the previous checkpoint in a module
+    /// specific encoding, modules that clients don't recover from the epoch
+    /// history are omitted
+    pub modules: BTreeMap<ModuleInstanceId, Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SignedEpochCheckpoint {
+    pub checkpoint: EpochCheckpoint,
+    pub hash: Sha256,
+    pub signature: Option<SerdeSignature>,
+}
+
+impl SignedEpochCheckpoint {
+    pub fn new(checkpoint: EpochCheckpoint) -> Self {
+        SignedEpochCheckpoint {
+            hash: checkpoint.consensus_hash().expect("Hashes"),
+            checkpoint,
+            signature: None,
+        }
+    }
+
+    /// Combines the signature shares over the checkpoint contained in
+    /// `outcome`, returning the peers that contributed valid shares if there
+    /// were not enough of them
+    pub fn add_sig(
+        &mut self,
+        pks: &PublicKeySet,
+        outcome: &BTreeMap<PeerId, Vec<ConsensusItem>>,
+    ) -> Result<(), EpochVerifyError> {
+        let mut contributing_peers = HashSet::new();
+
+        let sigs: BTreeMap<_, _> = outcome
+            .iter()
+            .flat_map(|(peer, items)| items.iter().map(|i| (*peer, i)))
+            .filter_map(|(peer, item)| match item {
+                ConsensusItem::EpochCheckpointSignatureShare(SerdeSignatureShare(sig)) => {
+                    Some((peer, sig))
+                }
+                _ => None,
+            })
+            .filter(|(peer, sig)| {
+                let pub_key = pks.public_key_share(peer.to_usize());
+                pub_key.verify(sig, self.hash)
+            })
+            .map(|(peer, sig)| {
+                contributing_peers.insert(peer);
+                (peer.to_usize(), sig)
+            })
+            .collect();
+
+        if let Ok(final_sig) = pks.combine_signatures(sigs) {
+            assert!(pks.public_key().verify(&final_sig, self.hash));
+
+            self.signature = Some(SerdeSignature(final_sig));
+            Ok(())
+        } else {
+            Err(EpochVerifyError::NotEnoughValidSigShares(
+                contributing_peers,
+            ))
+        }
+    }
+
+    pub fn verify_sig(&self, pk: &PublicKey) -> Result<(), EpochVerifyError> {
+        if Some(self.hash) != self.checkpoint.consensus_hash().ok() {
+            return Err(EpochVerifyError::InvalidEpochHash);
+        }
+
+        match &self.signature {
+            Some(sig) if pk.verify(&sig.0, self.hash) => Ok(()),
+            Some(_) => Err(EpochVerifyError::InvalidSignature),
+            None => Err(EpochVerifyError::MissingSignature),
+        }
+    }
+}
+
+/// The epoch history a guardian with the archive role keeps, which includes
+/// the epochs the other guardians pruned
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct EpochArchiveInfo {
+    /// First epoch the archive holds, it holds every epoch after it
+    pub earliest_epoch: u64,
+    pub epoch_count: u64,
+}
+
+impl EpochArchiveInfo {
+    pub fn contains(&self, epoch: u64) -> bool {
+        (self.earliest_epoch..self.epoch_count).contains(&epoch)
+    }
+}
+
+/// An epoch decoded for block-explorer-style tooling, see
+/// `FedimintConsensus::explore_epoch`
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ExplorerEpoch {
+    pub epoch: u64,
+    pub hash: Sha256,
+    pub last_hash: Option<Sha256>,
+    /// Whether the federation already threshold signed the epoch
+    pub signed: bool,
+    /// Transactions in the order they were first proposed
+    pub transactions: Vec<ExplorerTransaction>,
+    /// Number of the other consensus items each peer contributed
+    pub other_items: BTreeMap<PeerId, usize>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ExplorerTransaction {
+    pub txid: TransactionId,
+    pub proposed_by: Vec<PeerId>,
+    /// Transactions proposed but failing validation, e.g. double spends, are
+    /// part of the epoch too
+    pub accepted: bool,
+    pub inputs: Vec<ExplorerItem>,
+    pub outputs: Vec<ExplorerItem>,
+}
+
+/// An input or output of an [`ExplorerTransaction`]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ExplorerItem {
+    pub module_instance_id: ModuleInstanceId,
+    pub module_kind: ModuleKind,
+    pub description: String,
+    /// Amount the item moves, `None` if the module keeps it private
+    pub amount: Option<Amount>,
+}
+
+/// Copy of a guardian's consensus state after `epoch`, lets a guardian that
+/// fell far behind skip replaying the epoch history
+///
+/// Contains the raw database entries that are the same on all guardians, of
+/// the epoch history only `epoch` itself is included.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct StateSnapshot {
+    pub epoch: u64,
+    pub entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+pub type SerdeStateSnapshot = SerdeModuleEncoding<StateSnapshot>;
+
+/// Signature share of a guardian over the hash of its latest [`StateSnapshot`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshotShare {
+    pub epoch: u64,
+    pub hash: Sha256,
+    pub signature: SerdeSignatureShare,
+}
+
+/// [`StateSnapshot`] whose hash was threshold signed by the federation
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SignedStateSnapshot {
+    pub snapshot: StateSnapshot,
+    pub signature: SerdeSignature,
+}
+
+impl SignedStateSnapshot {
+    pub fn verify_sig(&self, pk: &PublicKey) -> Result<(), EpochVerifyError> {
+        let hash = self
+            .snapshot
+            .consensus_hash()
+            .map_err(|_| EpochVerifyError::InvalidEpochHash)?;
+
+        if pk.verify(&self.signature.0, hash) {
+            Ok(())
+        } else {
+            Err(EpochVerifyError::InvalidSignature)
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum EpochVerifyError {
     MissingSignature,
@@ -189,7 +600,9 @@ impl Decodable for SerdeSignature {
     ) -> Result<Self, DecodeError> {
         let mut bytes = [0u8; 96];
         d.read_exact(&mut bytes).map_err(DecodeError::from_err)?;
-        Ok(SerdeSignature(Signature::from_bytes(bytes).unwrap()))
+        Ok(SerdeSignature(
+            Signature::from_bytes(bytes).map_err(|_| DecodeError::from_str("Invalid signature"))?,
+        ))
     }
 }
 
@@ -207,25 +620,30 @@ impl Decodable for SerdeSignatureShare {
         let mut bytes = [0u8; 96];
         d.read_exact(&mut bytes).map_err(DecodeError::from_err)?;
         Ok(SerdeSignatureShare(
-            SignatureShare::from_bytes(bytes).unwrap(),
+            SignatureShare::from_bytes(bytes)
+                .map_err(|_| DecodeError::from_str("Invalid signature share"))?,
         ))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::{BTreeSet, HashSet};
+    use std::collections::{BTreeMap, BTreeSet, HashSet};
+    use std::io::Cursor;
 
     use bitcoin::hashes::Hash;
-    use fedimint_core::encoding::Encodable;
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::PeerId;
     use rand::rngs::OsRng;
     use threshold_crypto::{SecretKey, SecretKeySet};
 
     use crate::epoch::{
-        ConsensusItem, EpochOutcome, EpochVerifyError, SerdeSignature, SerdeSignatureShare, Sha256,
-        SignedEpochOutcome,
+        ConsensusItem, ConsensusLimits, EpochCheckpoint, EpochOutcome, EpochVerifyError,
+        LimitError, SerdeSignature, SerdeSignatureShare, Sha256, SignedEpochCheckpoint,
+        SignedEpochOutcome, SignedStateSnapshot, StateSnapshot,
     };
+    use crate::transaction::Transaction;
 
     fn signed_history(
         epoch: u16,
@@ -370,4 +788,125 @@ mod tests {
             Err(EpochVerifyError::InvalidSignature)
         );
     }
+
+    #[test]
+    fn adds_sig_to_checkpoint() {
+        let mut rng = OsRng;
+        let sk_set = SecretKeySet::random(1, &mut rng);
+        let pk_set = sk_set.public_keys();
+
+        let mut checkpoint = SignedEpochCheckpoint::new(EpochCheckpoint {
+            epoch: 9,
+            epoch_hash: Hash::hash(b"epoch"),
+            previous: None,
+            modules: BTreeMap::from([(0, vec![1, 2, 3])]),
+        });
+        assert_eq!(
+            checkpoint.verify_sig(&pk_set.public_key()),
+            Err(EpochVerifyError::MissingSignature)
+        );
+
+        let shares: BTreeMap<PeerId, Vec<ConsensusItem>> = (0..2)
+            .map(|peer| {
+                let sig = sk_set.secret_key_share(peer).sign(checkpoint.hash);
+                (
+                    PeerId::from(peer as u16),
+                    vec![ConsensusItem::EpochCheckpointSignatureShare(
+                        SerdeSignatureShare(sig),
+                    )],
+                )
+            })
+            .collect();
+
+        let one_share = shares.clone().into_iter().take(1).collect();
+        assert_eq!(
+            checkpoint.add_sig(&pk_set, &one_share),
+            Err(EpochVerifyError::NotEnoughValidSigShares(HashSet::from([
+                PeerId::from(0)
+            ])))
+        );
+
+        checkpoint.add_sig(&pk_set, &shares).unwrap();
+        assert_eq!(checkpoint.verify_sig(&pk_set.public_key()), Ok(()));
+
+        checkpoint.checkpoint.epoch = 10;
+        assert_eq!(
+            checkpoint.verify_sig(&pk_set.public_key()),
+            Err(EpochVerifyError::InvalidEpochHash)
+        );
+    }
+
+    #[test]
+    fn verifies_state_snapshot_sig() {
+        let sk_set = SecretKeySet::random(1, &mut OsRng);
+        let pk_set = sk_set.public_keys();
+
+        let snapshot = StateSnapshot {
+            epoch: 999,
+            entries: BTreeMap::from([(vec![0x06], vec![1, 2, 3])]),
+        };
+        let hash = snapshot.consensus_hash().unwrap();
+        let shares: BTreeMap<_, _> = (0..2)
+            .map(|peer| (peer, sk_set.secret_key_share(peer).sign(hash)))
+            .collect();
+        let mut signed = SignedStateSnapshot {
+            snapshot,
+            signature: SerdeSignature(pk_set.combine_signatures(&shares).unwrap()),
+        };
+        assert_eq!(signed.verify_sig(&pk_set.public_key()), Ok(()));
+
+        signed.snapshot.entries.insert(vec![0x02], vec![]);
+        assert_eq!(
+            signed.verify_sig(&pk_set.public_key()),
+            Err(EpochVerifyError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn malformed_items_fail_to_decode() {
+        let modules = ModuleDecoderRegistry::default();
+
+        // not a point on the curve
+        let signature = [0xffu8; 96].consensus_encode_to_vec().unwrap();
+        assert!(SerdeSignature::consensus_decode(&mut Cursor::new(&signature), &modules).is_err());
+        assert!(
+            SerdeSignatureShare::consensus_decode(&mut Cursor::new(&signature), &modules).is_err()
+        );
+
+        // a module item of a module instance we don't know
+        let module_item = (4u64, 7u16).consensus_encode_to_vec().unwrap();
+        assert!(ConsensusItem::consensus_decode(&mut Cursor::new(&module_item), &modules).is_err());
+    }
+
+    #[test]
+    fn transactions_exceeding_limits_are_rejected() {
+        let transaction = Transaction {
+            inputs: vec![],
+            outputs: vec![],
+            signature: None,
+        };
+        let size = transaction.consensus_encode_to_vec().unwrap().len() as u64;
+        let limits = ConsensusLimits {
+            max_transaction_size: size,
+            ..ConsensusLimits::default()
+        };
+        assert_eq!(limits.check_transaction(&transaction), Ok(()));
+
+        let limits = ConsensusLimits {
+            max_transaction_size: size - 1,
+            ..limits
+        };
+        let error = limits.check_transaction(&transaction).unwrap_err();
+        assert_eq!(
+            error,
+            LimitError::TransactionTooLarge {
+                size,
+                max: size - 1
+            }
+        );
+
+        // submitters get the error as the data of the API error
+        let data = serde_json::to_value(&error).unwrap();
+        assert_eq!(serde_json::from_value::<LimitError>(data).unwrap(), error);
+    }
 }
@@ -37,7 +37,9 @@ pub mod macros;
 pub mod module;
 pub mod net;
 pub mod outcome;
+pub mod pagination;
 pub mod query;
+pub mod signed_api;
 pub mod task;
 pub mod tiered;
 pub mod tiered_multi;
@@ -114,6 +116,159 @@ impl Amount {
             msats: self.msats.saturating_sub(other.msats),
         }
     }
+
+    pub fn saturating_add(self, other: Amount) -> Self {
+        Amount {
+            msats: self.msats.saturating_add(other.msats),
+        }
+    }
+
+    /// Returns `None` on overflow instead of wrapping around like `+` in
+    /// release builds
+    pub fn checked_add(self, other: Amount) -> Option<Self> {
+        Some(Amount {
+            msats: self.msats.checked_add(other.msats)?,
+        })
+    }
+
+    /// Returns `None` if `other` is larger than `self`
+    pub fn checked_sub(self, other: Amount) -> Option<Self> {
+        Some(Amount {
+            msats: self.msats.checked_sub(other.msats)?,
+        })
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Self> {
+        Some(Amount {
+            msats: self.msats.checked_mul(factor)?,
+        })
+    }
+
+    /// Formats the amount in `unit` without losing precision, e.g. `1.5 sat`
+    pub fn to_string_in(self, unit: AmountUnit) -> String {
+        let decimals = unit.decimals();
+        let scale = 10u64.pow(decimals);
+        let whole = self.msats / scale;
+        let fraction = self.msats % scale;
+
+        if fraction == 0 {
+            format!("{whole} {unit}")
+        } else {
+            let fraction = format!("{fraction:0width$}", width = decimals as usize);
+            format!("{whole}.{} {unit}", fraction.trim_end_matches('0'))
+        }
+    }
+}
+
+/// Unit an [`Amount`] is displayed in
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountUnit {
+    Msat,
+    Sat,
+    Btc,
+}
+
+impl AmountUnit {
+    /// Decimals an amount in the unit needs to be exact to the msat
+    pub fn decimals(self) -> u32 {
+        match self {
+            AmountUnit::Msat => 0,
+            AmountUnit::Sat => 3,
+            AmountUnit::Btc => 11,
+        }
+    }
+}
+
+impl std::fmt::Display for AmountUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmountUnit::Msat => write!(f, "msat"),
+            AmountUnit::Sat => write!(f, "sat"),
+            AmountUnit::Btc => write!(f, "BTC"),
+        }
+    }
+}
+
+impl FromStr for AmountUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "msat" => Ok(AmountUnit::Msat),
+            "sat" => Ok(AmountUnit::Sat),
+            "btc" => Ok(AmountUnit::Btc),
+            _ => Err(anyhow::format_err!(
+                "Unknown unit {s}, expected msat, sat or btc"
+            )),
+        }
+    }
+}
+
+/// Proportional fee in parts per million of an amount, like the proportional
+/// routing fees of Lightning, unlike [`Feerate`] for on-chain fees
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    Default,
+    Deserialize,
+    Serialize,
+    Encodable,
+    Decodable,
+)]
+pub struct FeeRate {
+    pub ppm: u64,
+}
+
+impl FeeRate {
+    pub const ZERO: Self = Self { ppm: 0 };
+
+    pub const fn from_ppm(ppm: u64) -> Self {
+        FeeRate { ppm }
+    }
+
+    /// Returns the rate of `fee` charged on `amount`, rounded down, or `None`
+    /// if `amount` is zero or the rate doesn't fit
+    pub fn from_fee(amount: Amount, fee: Amount) -> Option<Self> {
+        if amount == Amount::ZERO {
+            return None;
+        }
+        let ppm = u128::from(fee.msats) * 1_000_000 / u128::from(amount.msats);
+        Some(FeeRate {
+            ppm: ppm.try_into().ok()?,
+        })
+    }
+
+    /// Returns the fee on `amount`, rounded down like Lightning's proportional
+    /// fees, or `None` if it overflows
+    pub fn checked_fee(self, amount: Amount) -> Option<Amount> {
+        let fee = u128::from(amount.msats) * u128::from(self.ppm) / 1_000_000;
+        Some(Amount {
+            msats: fee.try_into().ok()?,
+        })
+    }
+
+    /// Returns `amount` plus the fee on it, or `None` if it overflows
+    pub fn checked_add_fee(self, amount: Amount) -> Option<Amount> {
+        amount.checked_add(self.checked_fee(amount)?)
+    }
+
+    /// The rate as a fraction of the amount, e.g. `0.01` for 1%
+    pub fn as_fraction(self) -> f64 {
+        self.ppm as f64 / 1_000_000.0
+    }
+}
+
+impl std::fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ppm", self.ppm)
+    }
 }
 
 /// Shorthand for [`Amount::from_msats`]
@@ -381,3 +536,64 @@ pub enum CoreError {
     #[error("Mismatching outcome variant: expected {0}, got {1}")]
     MismatchingVariant(&'static str, &'static str),
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{Amount, AmountUnit, FeeRate};
+
+    #[test]
+    fn checked_amount_arithmetic() {
+        let max = Amount::from_msats(u64::MAX);
+        assert_eq!(max.checked_add(Amount::from_msats(1)), None);
+        assert_eq!(max.saturating_add(Amount::from_msats(1)), max);
+        assert_eq!(Amount::ZERO.checked_sub(Amount::from_msats(1)), None);
+        assert_eq!(max.checked_mul(2), None);
+        assert_eq!(
+            Amount::from_sats(2).checked_sub(Amount::from_sats(1)),
+            Some(Amount::from_sats(1))
+        );
+    }
+
+    #[test]
+    fn fee_rate_math() {
+        let one_percent = FeeRate::from_ppm(10_000);
+        // rounded down like lightning fees
+        assert_eq!(
+            one_percent.checked_fee(Amount::from_msats(199)),
+            Some(Amount::from_msats(1))
+        );
+        assert_eq!(
+            one_percent.checked_add_fee(Amount::from_sats(1)),
+            Some(Amount::from_msats(1010))
+        );
+        // the fee itself fits even though the intermediate product doesn't
+        assert_eq!(
+            one_percent.checked_fee(Amount::from_msats(u64::MAX)),
+            Some(Amount::from_msats(u64::MAX / 100))
+        );
+        assert_eq!(
+            one_percent.checked_add_fee(Amount::from_msats(u64::MAX)),
+            None
+        );
+        assert_eq!(
+            FeeRate::from_fee(Amount::from_sats(1), Amount::from_msats(10)),
+            Some(one_percent)
+        );
+        assert_eq!(FeeRate::from_fee(Amount::ZERO, Amount::from_sats(1)), None);
+        assert_eq!(one_percent.as_fraction(), 0.01);
+    }
+
+    #[test]
+    fn displays_amounts_in_units() {
+        let amount = Amount::from_msats(1_500);
+        assert_eq!(amount.to_string_in(AmountUnit::Msat), "1500 msat");
+        assert_eq!(amount.to_string_in(AmountUnit::Sat), "1.5 sat");
+        assert_eq!(amount.to_string_in(AmountUnit::Btc), "0.000000015 BTC");
+        assert_eq!(
+            Amount::from_sats(100_000_000).to_string_in(AmountUnit::Btc),
+            "1 BTC"
+        );
+        assert_eq!("BTC".parse::<AmountUnit>().unwrap(), AmountUnit::Btc);
+        assert!("bits".parse::<AmountUnit>().is_err());
+    }
+}
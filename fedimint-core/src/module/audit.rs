@@ -1,9 +1,19 @@
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::encoding::{Decodable, Encodable};
 use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use threshold_crypto::PublicKeyShare;
 
 use crate::core::ModuleInstanceId;
 use crate::db::{DatabaseKey, DatabaseLookup, DatabaseRecord, ModuleDatabaseTransaction};
+use crate::epoch::SerdeSignatureShare;
+use crate::Amount;
+
+/// Separates the signatures of audit summaries from other uses of the epoch key
+const AUDIT_SUMMARY_TAG: &str = "fedimint-audit-summary";
 
 #[derive(Default)]
 pub struct Audit {
@@ -23,6 +33,28 @@ impl Audit {
         }
     }
 
+    /// Sum of all items the federation owns
+    pub fn assets(&self) -> Amount {
+        Amount::from_msats(
+            self.items
+                .iter()
+                .filter(|item| item.milli_sat > 0)
+                .map(|item| item.milli_sat.unsigned_abs())
+                .sum(),
+        )
+    }
+
+    /// Sum of all items the federation owes its users
+    pub fn liabilities(&self) -> Amount {
+        Amount::from_msats(
+            self.items
+                .iter()
+                .filter(|item| item.milli_sat < 0)
+                .map(|item| item.milli_sat.unsigned_abs())
+                .sum(),
+        )
+    }
+
     pub async fn add_items<KP, F>(
         &mut self,
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
@@ -68,3 +100,119 @@ impl Display for AuditItem {
         formatter.write_fmt(format_args!("{:>+15.3}|{}", sats, self.name))
     }
 }
+
+/// Assets and liabilities of a single module
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ModuleAuditSummary {
+    pub kind: String,
+    pub assets: Amount,
+    pub liabilities: Amount,
+}
+
+impl ModuleAuditSummary {
+    pub fn new(kind: String, audit: &Audit) -> Self {
+        Self {
+            kind,
+            assets: audit.assets(),
+            liabilities: audit.liabilities(),
+        }
+    }
+
+    pub fn net_assets_msat(&self) -> i64 {
+        self.assets.msats as i64 - self.liabilities.msats as i64
+    }
+}
+
+/// Balance sheet of all modules, computed after `epoch_count` epochs were
+/// processed
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct AuditSummary {
+    pub epoch_count: u64,
+    pub modules: BTreeMap<ModuleInstanceId, ModuleAuditSummary>,
+}
+
+impl AuditSummary {
+    /// Net position of the federation, it is solvent if this isn't negative
+    pub fn net_assets_msat(&self) -> i64 {
+        self.modules
+            .values()
+            .map(ModuleAuditSummary::net_assets_msat)
+            .sum()
+    }
+
+    /// Hash signed by a guardian vouching for the summary
+    pub fn signing_hash(&self) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        AUDIT_SUMMARY_TAG
+            .to_string()
+            .consensus_encode(&mut engine)
+            .expect("writing to a hash engine can't fail");
+        self.consensus_encode(&mut engine)
+            .expect("writing to a hash engine can't fail");
+        sha256::Hash::from_engine(engine)
+    }
+}
+
+/// [`AuditSummary`] signed with the epoch key share of the guardian that
+/// computed it
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SignedAuditSummary {
+    pub summary: AuditSummary,
+    pub signature: SerdeSignatureShare,
+}
+
+impl SignedAuditSummary {
+    pub fn verify_sig(&self, pk: &PublicKeyShare) -> bool {
+        pk.verify(&self.signature.0, self.summary.signing_hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rand::rngs::OsRng;
+    use threshold_crypto::SecretKeySet;
+
+    use super::{AuditSummary, ModuleAuditSummary, SignedAuditSummary};
+    use crate::epoch::SerdeSignatureShare;
+    use crate::Amount;
+
+    #[test]
+    fn signs_audit_summary() {
+        let sks = SecretKeySet::random(1, &mut OsRng);
+        let summary = AuditSummary {
+            epoch_count: 10,
+            modules: BTreeMap::from([
+                (
+                    0,
+                    ModuleAuditSummary {
+                        kind: "ln".to_string(),
+                        assets: Amount::ZERO,
+                        liabilities: Amount::from_sats(2),
+                    },
+                ),
+                (
+                    1,
+                    ModuleAuditSummary {
+                        kind: "wallet".to_string(),
+                        assets: Amount::from_sats(5),
+                        liabilities: Amount::ZERO,
+                    },
+                ),
+            ]),
+        };
+        assert_eq!(summary.net_assets_msat(), 3_000);
+
+        let mut signed = SignedAuditSummary {
+            signature: SerdeSignatureShare(sks.secret_key_share(0).sign(summary.signing_hash())),
+            summary,
+        };
+        let pks = sks.public_keys();
+        assert!(signed.verify_sig(&pks.public_key_share(0)));
+        assert!(!signed.verify_sig(&pks.public_key_share(1)));
+
+        signed.summary.epoch_count += 1;
+        assert!(!signed.verify_sig(&pks.public_key_share(0)));
+    }
+}
@@ -1,6 +1,7 @@
 pub mod audit;
 pub mod interconnect;
 pub mod registry;
+pub mod version;
 use std::collections::{BTreeMap, HashSet};
 use std::ffi::OsString;
 use std::fmt::Debug;
@@ -8,6 +9,7 @@ use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::Arc;
 
+use anyhow::format_err;
 use bitcoin_hashes::sha256;
 use bitcoin_hashes::sha256::Hash;
 use futures::Future;
@@ -18,7 +20,7 @@ use serde_json::Value;
 use thiserror::Error;
 use tracing::instrument;
 
-use crate::config::{ConfigGenParams, DkgPeerMsg, ServerModuleConfig};
+use crate::config::{ConfigGenParams, DkgPeerMsg, ServerModuleConfig, ServerModuleReshareConfig};
 use crate::core::{
     Decoder, DecoderBuilder, Input, ModuleConsensusItem, ModuleInstanceId, ModuleKind, Output,
     OutputOutcome,
@@ -27,6 +29,7 @@ use crate::db::{
     Database, DatabaseTransaction, DatabaseVersion, MigrationMap, ModuleDatabaseTransaction,
 };
 use crate::encoding::{Decodable, DecodeError, Encodable};
+use crate::epoch::ModuleParams;
 use crate::module::audit::Audit;
 use crate::module::interconnect::ModuleInterconect;
 use crate::net::peers::MuxPeerConnections;
@@ -67,6 +70,10 @@ pub struct ApiRequest<T> {
     pub auth: Option<ApiAuth>,
     /// Parameters required by the API
     pub params: T,
+    /// Asks the guardian to return a
+    /// [`SignedApiResponse`](crate::signed_api::SignedApiResponse)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sign_response: bool,
 }
 
 pub type ApiRequestErased = ApiRequest<JsonValue>;
@@ -76,6 +83,7 @@ impl Default for ApiRequestErased {
         Self {
             auth: None,
             params: JsonValue::Null,
+            sign_response: false,
         }
     }
 }
@@ -86,6 +94,7 @@ impl ApiRequestErased {
             auth: None,
             params: serde_json::to_value(params)
                 .expect("parameter serialization error - this should not happen"),
+            sign_response: false,
         }
     }
 
@@ -96,7 +105,7 @@ impl ApiRequestErased {
     pub fn with_auth(self, auth: &ApiAuth) -> Self {
         Self {
             auth: Some(auth.clone()),
-            params: self.params,
+            ..self
         }
     }
 
@@ -106,6 +115,7 @@ impl ApiRequestErased {
         Ok(ApiRequest {
             auth: self.auth,
             params: serde_json::from_value::<T>(self.params)?,
+            sign_response: self.sign_response,
         })
     }
 }
@@ -114,15 +124,39 @@ impl ApiRequestErased {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ApiAuth(pub String);
 
+/// Authentication required to call an API endpoint, ordered from the least to
+/// the most privileged tier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ApiAuthTier {
+    /// Queries of the public config and consensus state anyone may make
+    Public,
+    /// Endpoints for the users of the federation, which require the user auth
+    /// if the guardian configured one
+    User,
+    /// Endpoints for the guardian's operator, which require the admin password
+    Admin,
+}
+
 #[derive(Debug)]
 pub struct ApiError {
     pub code: i32,
     pub message: String,
+    /// Structured details of the error for clients to act on
+    pub data: Option<serde_json::Value>,
 }
 
 impl ApiError {
     pub fn new(code: i32, message: String) -> Self {
-        Self { code, message }
+        Self {
+            code,
+            message,
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = Some(data);
+        self
     }
 
     pub fn not_found(message: String) -> Self {
@@ -137,26 +171,57 @@ impl ApiError {
         Self::new(401, "Request missing required authorization".to_string())
     }
 
+    /// The request was rejected because the client exceeded the server's rate
+    /// or concurrency limits, it can be retried later
+    pub fn too_many_requests(message: String) -> Self {
+        Self::new(429, message)
+    }
+
     pub fn server_error(message: String) -> Self {
         Self::new(500, message)
     }
+
+    /// The transaction exceeds the limits of the federation, the
+    /// [`LimitError`](crate::epoch::LimitError) is attached as data
+    pub fn limit_exceeded(error: &crate::epoch::LimitError) -> Self {
+        Self::new(LIMIT_EXCEEDED_ERROR_CODE, error.to_string())
+            .with_data(serde_json::to_value(error).expect("Serialization can't fail"))
+    }
+
+    /// The transaction spends an input that was already spent, e.g. by a
+    /// concurrent transaction of the same user, it might be accepted with other
+    /// inputs
+    pub fn conflict(message: String) -> Self {
+        Self::new(CONFLICT_ERROR_CODE, message)
+    }
 }
 
+/// Code of API errors rejecting requests that exceed the limits of the
+/// federation
+pub const LIMIT_EXCEEDED_ERROR_CODE: i32 = 413;
+
+/// Code of API errors rejecting transactions that spend already spent inputs
+pub const CONFLICT_ERROR_CODE: i32 = 409;
+
+/// Code of the error closing a subscription the server evicted because it was
+/// idle for too long, clients still interested in it have to subscribe again
+pub const SUBSCRIPTION_EVICTED_ERROR_CODE: i32 = 408;
+
 /// State made available to all API endpoints for handling a request
 pub struct ApiEndpointContext<'a> {
     dbtx: DatabaseTransaction<'a>,
-    has_auth: bool,
+    auth_tier: ApiAuthTier,
     module_id: Option<ModuleInstanceId>,
 }
 
 impl<'a> ApiEndpointContext<'a> {
     pub fn new(
-        has_auth: bool,
+        auth_tier: ApiAuthTier,
         dbtx: DatabaseTransaction<'a>,
         module_id: Option<ModuleInstanceId>,
     ) -> Self {
         Self {
-            has_auth,
+            auth_tier,
             dbtx,
             module_id,
         }
@@ -173,14 +238,18 @@ impl<'a> ApiEndpointContext<'a> {
     /// Whether the request was authenticated as the guardian who controls this
     /// fedimint server
     pub fn has_auth(&self) -> bool {
-        self.has_auth
+        self.auth_tier == ApiAuthTier::Admin
+    }
+
+    /// The most privileged tier of endpoints the request was authenticated for
+    pub fn auth_tier(&self) -> ApiAuthTier {
+        self.auth_tier
     }
 
     /// Attempts to commit the dbtx or returns an ApiError
     pub async fn commit_tx_result(self) -> Result<(), ApiError> {
-        self.dbtx.commit_tx_result().await.map_err(|_err| ApiError {
-            code: 500,
-            message: "API server error when writing to database".to_string(),
+        self.dbtx.commit_tx_result().await.map_err(|_err| {
+            ApiError::server_error("API server error when writing to database".to_string())
         })
     }
 }
@@ -192,6 +261,9 @@ pub trait TypedApiEndpoint {
     /// example: /transaction
     const PATH: &'static str;
 
+    /// Authentication a request needs to call the endpoint
+    const AUTH_TIER: ApiAuthTier = ApiAuthTier::User;
+
     type Param: serde::de::DeserializeOwned + Send;
     type Response: serde::Serialize;
 
@@ -207,10 +279,13 @@ pub mod __reexports {
     pub use serde_json;
 }
 
+/// Endpoints require [`ApiAuthTier::User`] unless another tier is given after
+/// the path.
+///
 /// # Example
 ///
 /// ```rust
-/// # use fedimint_core::module::{api_endpoint, ApiEndpoint, registry::ModuleInstanceId};
+/// # use fedimint_core::module::{api_endpoint, ApiAuthTier, ApiEndpoint, registry::ModuleInstanceId};
 /// struct State;
 ///
 /// let _: ApiEndpoint<State> = api_endpoint! {
@@ -219,18 +294,38 @@ pub mod __reexports {
 ///         Ok(0)
 ///     }
 /// };
+///
+/// let _: ApiEndpoint<State> = api_endpoint! {
+///     "/admin_foobar",
+///     ApiAuthTier::Admin,
+///     async |state: &State, _dbtx, params: ()| -> i32 {
+///         Ok(0)
+///     }
+/// };
 /// ```
 #[macro_export]
 macro_rules! __api_endpoint {
     (
         $path:expr,
         async |$state:ident: &$state_ty:ty, $context:ident, $param:ident: $param_ty:ty| -> $resp_ty:ty $body:block
+    ) => {
+        $crate::__api_endpoint! {
+            $path,
+            $crate::module::ApiAuthTier::User,
+            async |$state: &$state_ty, $context, $param: $param_ty| -> $resp_ty $body
+        }
+    };
+    (
+        $path:expr,
+        $auth_tier:expr,
+        async |$state:ident: &$state_ty:ty, $context:ident, $param:ident: $param_ty:ty| -> $resp_ty:ty $body:block
     ) => {{
         struct Endpoint;
 
         #[$crate::apply($crate::async_trait_maybe_send!)]
         impl $crate::module::TypedApiEndpoint for Endpoint {
             const PATH: &'static str = $path;
+            const AUTH_TIER: $crate::module::ApiAuthTier = $auth_tier;
             type State = $state_ty;
             type Param = $param_ty;
             type Response = $resp_ty;
@@ -249,7 +344,7 @@ macro_rules! __api_endpoint {
 }
 
 pub use __api_endpoint as api_endpoint;
-use fedimint_core::config::{DkgResult, ModuleConfigResponse};
+use fedimint_core::config::{DkgError, DkgResult, ModuleConfigResponse};
 
 use self::registry::ModuleDecoderRegistry;
 
@@ -268,6 +363,9 @@ pub struct ApiEndpoint<M> {
     /// under `/module/module_instance_id/transaction` depending on the
     /// module name returned by `[FedertionModule::api_base_name]`.
     pub path: &'static str,
+    /// Authentication a request needs to call the endpoint, checked before the
+    /// handler is called
+    pub auth_tier: ApiAuthTier,
     /// Handler for the API call that takes the following arguments:
     ///   * Reference to the module which defined it
     ///   * Request parameters parsed into JSON `[Value](serde_json::Value)`
@@ -309,6 +407,7 @@ impl ApiEndpoint<()> {
 
         ApiEndpoint {
             path: E::PATH,
+            auth_tier: E::AUTH_TIER,
             handler: Box::new(|m, mut context, request| {
                 Box::pin(async move {
                     let request = request
@@ -328,6 +427,10 @@ impl ApiEndpoint<()> {
 
 #[derive(Error, Debug)]
 pub enum ModuleError {
+    /// An input was already spent, so the transaction might be accepted if
+    /// it's rebuilt with other inputs
+    #[error("Input was already spent: {0}")]
+    Conflict(anyhow::Error),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -344,6 +447,7 @@ pub enum ModuleError {
 pub trait IntoModuleError {
     type Target;
     fn into_module_error_other(self) -> Self::Target;
+    fn into_module_error_conflict(self) -> Self::Target;
 }
 
 impl<O, E> IntoModuleError for Result<O, E>
@@ -355,6 +459,10 @@ where
     fn into_module_error_other(self) -> Self::Target {
         self.map_err(|e| ModuleError::Other(e.into()))
     }
+
+    fn into_module_error_conflict(self) -> Self::Target {
+        self.map_err(|e| ModuleError::Conflict(e.into()))
+    }
 }
 
 /// Operations common to Server and Client side module gen dyn newtypes
@@ -439,6 +547,13 @@ pub trait IServerModuleGen: IDynCommonModuleGen {
         params: &ConfigGenParams,
     ) -> DkgResult<ServerModuleConfig>;
 
+    async fn distributed_reshare(
+        &self,
+        peers: &PeerHandle,
+        params: &ConfigGenParams,
+        old: &ServerModuleReshareConfig,
+    ) -> DkgResult<ServerModuleConfig>;
+
     fn to_config_response(&self, config: serde_json::Value)
         -> anyhow::Result<ModuleConfigResponse>;
 
@@ -487,7 +602,9 @@ impl AsRef<dyn IDynCommonModuleGen + Send + Sync + 'static> for DynServerModuleG
 ///
 /// See [`ModuleConsensusVersion`] for more details on how it interacts with
 /// module's consensus.
-#[derive(Debug, Copy, Clone)]
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, Encodable, Decodable,
+)]
 pub struct CoreConsensusVersion(pub u32);
 
 /// Consensus version of a specific module instance
@@ -513,7 +630,9 @@ pub struct CoreConsensusVersion(pub u32);
 /// by running two instances of the module at the same time (each of different
 /// `ModuleKind` version), allow users to slowly migrate to a new one.
 /// This avoids complex and error-prone server-side consensus-migration logic.
-#[derive(Debug, Copy, Clone)]
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize, Encodable, Decodable,
+)]
 pub struct ModuleConsensusVersion(pub u32);
 
 /// Api version supported by a core server or a client/server module at a given
@@ -533,7 +652,7 @@ pub struct ModuleConsensusVersion(pub u32);
 /// backward compatibility on both client and server side to accommodate end
 /// user client devices receiving updates at a pace hard to control, and
 /// technical and coordination challenges of upgrading servers.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ApiVersion {
     /// Major API version
     ///
@@ -622,6 +741,23 @@ pub trait ServerModuleGen: ExtendsCommonModuleGen + Sized {
         params: &ConfigGenParams,
     ) -> DkgResult<ServerModuleConfig>;
 
+    /// Generates the module config for a new set of guardians, keeping the
+    /// public keys of the `old` federation by resharing its threshold keys
+    ///
+    /// Modules whose keys can't be reshared, e.g. because they are committed
+    /// to on-chain, keep the default implementation which fails.
+    async fn distributed_reshare(
+        &self,
+        _peers: &PeerHandle,
+        _params: &ConfigGenParams,
+        _old: &ServerModuleReshareConfig,
+    ) -> DkgResult<ServerModuleConfig> {
+        Err(DkgError::Failed(format_err!(
+            "Module {} does not support resharing its keys",
+            Self::kind()
+        )))
+    }
+
     fn to_config_response(&self, config: serde_json::Value)
         -> anyhow::Result<ModuleConfigResponse>;
 
@@ -677,6 +813,15 @@ where
         <Self as ServerModuleGen>::distributed_gen(self, peers, params).await
     }
 
+    async fn distributed_reshare(
+        &self,
+        peers: &PeerHandle,
+        params: &ConfigGenParams,
+        old: &ServerModuleReshareConfig,
+    ) -> DkgResult<ServerModuleConfig> {
+        <Self as ServerModuleGen>::distributed_reshare(self, peers, params, old).await
+    }
+
     fn to_config_response(
         &self,
         config: serde_json::Value,
@@ -832,6 +977,40 @@ pub trait ServerModule: Debug + Sized {
         consensus_items: Vec<(PeerId, <Self::Common as ModuleCommon>::ConsensusItem)>,
     );
 
+    /// Called in the epoch the federation activates the core consensus
+    /// `version`, before `begin_consensus_epoch`, so the module can switch to
+    /// the rules of the [`ModuleConsensusVersion`] it runs at that version
+    ///
+    /// Federations start out at version 0 and only activate versions every
+    /// guardian's binary supports, modules running a single version ignore it.
+    async fn activate_consensus_version(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        _version: CoreConsensusVersion,
+    ) {
+    }
+
+    /// Checks the adjustable `params` a guardian proposes to change, see
+    /// [`ParamsChangeProposal`](crate::epoch::ParamsChangeProposal)
+    ///
+    /// Modules without adjustable parameters reject all of them.
+    fn validate_params(&self, params: &ModuleParams) -> anyhow::Result<()> {
+        match params.keys().next() {
+            Some(name) => Err(format_err!("Unknown parameter {name}")),
+            None => Ok(()),
+        }
+    }
+
+    /// Called in the epoch a change of the module's adjustable `params`
+    /// activates, before `begin_consensus_epoch`, so the module applies them
+    /// from this epoch on
+    async fn activate_params(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        _params: &ModuleParams,
+    ) {
+    }
+
     /// Some modules may have slow to verify inputs that would block transaction
     /// processing. If the slow part of verification can be modeled as a
     /// pure function not involving any system state we can build a lookup
@@ -934,6 +1113,76 @@ pub trait ServerModule: Debug + Sized {
         audit: &mut Audit,
     );
 
+    /// Returns the part of the module state clients need to recover without
+    /// the epoch history before a checkpoint, in a module specific encoding.
+    ///
+    /// Has to be deterministic since it is threshold signed by the guardians.
+    /// Modules whose clients don't recover from the epoch history return
+    /// `None`.
+    async fn checkpoint(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Re-verifies the invariants of the module state and returns a
+    /// description of every violation, an empty list if the state is
+    /// consistent.
+    ///
+    /// Runs periodically in the background on a snapshot of the database, so
+    /// it may be slow and query external services, but must not write.
+    async fn verify_integrity(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Vec<String> {
+        vec![]
+    }
+
+    /// Returns the key prefixes of entries that differ between guardians,
+    /// like shares the guardian is about to propose or data submitted to
+    /// its API only
+    ///
+    /// They are left out of the state snapshots lagging guardians sync from.
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// Returns the key prefixes of the module's consensus state, which only
+    /// accepted transactions and agreed consensus items write to
+    ///
+    /// Only these entries are hashed into the state hash the guardians
+    /// compare every few epochs.
+    fn consensus_db_prefixes(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    /// Returns the amount of `input` shown by the epoch explorer, `None` if it
+    /// isn't public or can't be known without the module state
+    fn public_input_amount(
+        &self,
+        _input: &<Self::Common as ModuleCommon>::Input,
+    ) -> Option<Amount> {
+        None
+    }
+
+    /// Returns the amount of `output` shown by the epoch explorer, `None` if it
+    /// isn't public or can't be known without the module state
+    fn public_output_amount(
+        &self,
+        _output: &<Self::Common as ModuleCommon>::Output,
+    ) -> Option<Amount> {
+        None
+    }
+
+    /// Kinds of modules that have to finish `begin_consensus_epoch` and
+    /// `end_consensus_epoch` before this module starts them, because the step
+    /// relies on what they do in it. Modules that don't depend on each other
+    /// run these steps concurrently.
+    fn epoch_dependencies(&self) -> Vec<ModuleKind> {
+        vec![]
+    }
+
     /// Returns a list of custom API endpoints defined by the module. These are
     /// made available both to users as well as to other modules. They thus
     /// should be deterministic, only dependant on their input and the
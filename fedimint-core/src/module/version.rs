@@ -0,0 +1,227 @@
+//! Negotiation of the API versions a client and the guardians use
+//!
+//! Every guardian lists the [`ApiVersion`]s its core and modules support at
+//! their consensus versions on its `/api_version` endpoint. For the core and
+//! every module the client picks the newest major version it supports that a
+//! threshold of guardians supports as well, together with the newest minor
+//! version all of these guardians support. Clients only send requests in
+//! formats the negotiated versions include, so they keep working while the
+//! guardians upgrade one by one.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use anyhow::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::core::ModuleInstanceId;
+use crate::module::{ApiVersion, CoreConsensusVersion, ModuleConsensusVersion};
+
+/// Newest version of the consensus rules of the core, has to be bumped with
+/// every incompatible change to how epochs are processed
+///
+/// Modules switch to their newer consensus versions together with the core,
+/// version 1 activates the first of them and version 3 the gateway fees of the
+/// lightning module.
+pub const CORE_CONSENSUS_VERSION: CoreConsensusVersion = STATE_HASH_VERSION;
+
+/// Core consensus version that added
+/// [`ConsensusItem::ParamsChangeProposal`](crate::epoch::ConsensusItem::ParamsChangeProposal),
+/// guardians don't propose changes until the federation runs it
+pub const PARAMS_CHANGE_VERSION: CoreConsensusVersion = CoreConsensusVersion(2);
+
+/// Core consensus version that added
+/// [`ConsensusItem::StateHash`](crate::epoch::ConsensusItem::StateHash),
+/// guardians don't hash their state until the federation runs it
+pub const STATE_HASH_VERSION: CoreConsensusVersion = CoreConsensusVersion(4);
+
+/// API versions of the core endpoints, minor version 1 added the `/subscribe_*`
+/// endpoints pushing transaction outcomes and epochs
+pub const CORE_API_VERSIONS: &[ApiVersion] = &[ApiVersion { major: 0, minor: 1 }];
+
+/// API versions of the core endpoints
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SupportedCoreApiVersions {
+    pub core_consensus: CoreConsensusVersion,
+    /// One version per supported major version, see [`ApiVersion::minor`] for
+    /// its meaning on clients and guardians
+    pub api: Vec<ApiVersion>,
+}
+
+/// API versions of a module's endpoints
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SupportedModuleApiVersions {
+    pub module_consensus: ModuleConsensusVersion,
+    /// One version per supported major version, see [`ApiVersion::minor`] for
+    /// its meaning on clients and guardians
+    pub api: Vec<ApiVersion>,
+}
+
+/// API versions a client or a guardian supports
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SupportedApiVersionsSummary {
+    pub core: SupportedCoreApiVersions,
+    pub modules: BTreeMap<ModuleInstanceId, SupportedModuleApiVersions>,
+}
+
+/// API versions a client negotiated with the guardians
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ApiVersionSet {
+    pub core: ApiVersion,
+    /// Modules without a common version are missing, only requests of their
+    /// first API version should be sent to them
+    pub modules: BTreeMap<ModuleInstanceId, ApiVersion>,
+}
+
+impl ApiVersionSet {
+    /// Negotiates the versions `client` has in common with at least
+    /// `threshold` of the guardians, whose supported versions are `peers`
+    ///
+    /// Fails if there is no common core version.
+    pub fn negotiate<'a>(
+        client: &SupportedApiVersionsSummary,
+        peers: impl IntoIterator<Item = &'a SupportedApiVersionsSummary>,
+        threshold: usize,
+    ) -> anyhow::Result<Self> {
+        let peers: Vec<_> = peers.into_iter().collect();
+
+        let core = common_version(
+            &client.core.api,
+            peers
+                .iter()
+                .filter(|peer| peer.core.core_consensus == client.core.core_consensus)
+                .map(|peer| peer.core.api.as_slice()),
+            threshold,
+        )
+        .ok_or_else(|| format_err!("No core API version is supported by enough guardians"))?;
+
+        let modules = client
+            .modules
+            .iter()
+            .filter_map(|(id, module)| {
+                let version = common_version(
+                    &module.api,
+                    peers
+                        .iter()
+                        .filter_map(|peer| peer.modules.get(id))
+                        .filter(|peer| peer.module_consensus == module.module_consensus)
+                        .map(|peer| peer.api.as_slice()),
+                    threshold,
+                )?;
+                Some((*id, version))
+            })
+            .collect();
+
+        Ok(Self { core, modules })
+    }
+
+    /// Whether the guardians support the core API `version`, which requests
+    /// of newer formats need to check before they are sent
+    pub fn supports_core(&self, version: ApiVersion) -> bool {
+        self.core.supports(version)
+    }
+
+    /// Whether the guardians support the API `version` of module `id`
+    pub fn supports_module(&self, id: ModuleInstanceId, version: ApiVersion) -> bool {
+        self.modules
+            .get(&id)
+            .map_or(false, |negotiated| negotiated.supports(version))
+    }
+}
+
+impl ApiVersion {
+    /// Whether a guardian serving this version can handle requests of
+    /// `version`
+    pub fn supports(&self, version: ApiVersion) -> bool {
+        self.major == version.major && version.minor <= self.minor
+    }
+}
+
+/// Newest of the `client` versions at least `threshold` of the `peers` support,
+/// with the newest minor version these peers support
+fn common_version<'a>(
+    client: &[ApiVersion],
+    peers: impl Iterator<Item = &'a [ApiVersion]>,
+    threshold: usize,
+) -> Option<ApiVersion> {
+    let peers: Vec<_> = peers.collect();
+    let mut client = client.to_vec();
+    client.sort_by_key(|version| Reverse(version.major));
+
+    client.into_iter().find_map(|wanted| {
+        let mut minors: Vec<u32> = peers
+            .iter()
+            .filter_map(|versions| versions.iter().find(|v| v.supports(wanted)))
+            .map(|version| version.minor)
+            .collect();
+        if minors.is_empty() || minors.len() < threshold {
+            return None;
+        }
+        minors.sort_unstable_by_key(|minor| Reverse(*minor));
+        Some(ApiVersion {
+            major: wanted.major,
+            minor: minors[threshold.max(1) - 1],
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{
+        ApiVersionSet, SupportedApiVersionsSummary, SupportedCoreApiVersions,
+        SupportedModuleApiVersions,
+    };
+    use crate::module::{ApiVersion, CoreConsensusVersion, ModuleConsensusVersion};
+
+    fn summary(core: &[(u32, u32)], module: &[(u32, u32)]) -> SupportedApiVersionsSummary {
+        let versions = |versions: &[(u32, u32)]| {
+            versions
+                .iter()
+                .map(|(major, minor)| ApiVersion {
+                    major: *major,
+                    minor: *minor,
+                })
+                .collect()
+        };
+        SupportedApiVersionsSummary {
+            core: SupportedCoreApiVersions {
+                core_consensus: CoreConsensusVersion(0),
+                api: versions(core),
+            },
+            modules: BTreeMap::from([(
+                0,
+                SupportedModuleApiVersions {
+                    module_consensus: ModuleConsensusVersion(0),
+                    api: versions(module),
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn negotiates_versions_supported_by_threshold() {
+        let client = summary(&[(0, 0), (1, 1)], &[(0, 2)]);
+        let peers = [
+            summary(&[(0, 3), (1, 2)], &[(0, 2)]),
+            summary(&[(0, 3), (1, 1)], &[(0, 1)]),
+            summary(&[(0, 2), (1, 3)], &[(0, 3)]),
+            summary(&[(0, 1)], &[(0, 2)]),
+        ];
+
+        let set = ApiVersionSet::negotiate(&client, &peers, 3).unwrap();
+        assert_eq!(set.core, ApiVersion { major: 1, minor: 1 });
+        assert_eq!(set.modules[&0], ApiVersion { major: 0, minor: 2 });
+        assert!(set.supports_core(ApiVersion { major: 1, minor: 0 }));
+        assert!(!set.supports_core(ApiVersion { major: 1, minor: 2 }));
+        assert!(!set.supports_core(ApiVersion { major: 0, minor: 0 }));
+
+        // only two guardians support the newer major version
+        let set = ApiVersionSet::negotiate(&client, &peers[1..], 3).unwrap();
+        assert_eq!(set.core, ApiVersion { major: 0, minor: 1 });
+        assert!(!set.modules.contains_key(&0));
+
+        assert!(ApiVersionSet::negotiate(&summary(&[(2, 0)], &[]), &peers, 3).is_err());
+    }
+}
@@ -4,12 +4,11 @@ use std::time::Duration;
 use async_trait::async_trait;
 use fedimint_core::cancellable::{Cancellable, Cancelled};
 use fedimint_core::net::peers::{IPeerConnections, PeerConnections};
-use fedimint_core::task::TaskHandle;
+use fedimint_core::task::channel::mpsc::{self, Receiver, Sender};
+use fedimint_core::task::{sleep, TaskHandle};
 use fedimint_core::PeerId;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::time::sleep;
 
 struct FakePeerConnections<Msg> {
     tx: Sender<Msg>,
@@ -53,7 +52,7 @@ where
 
 /// Create a fake link between `peer1` and `peer2` for test purposes
 ///
-/// `buf_size` controls the size of the `tokio::mpsc::channel` used
+/// `buf_size` controls the size of the `mpsc::channel` used
 /// under the hood (both ways).
 pub fn make_fake_peer_connection<Msg>(
     peer1: PeerId,
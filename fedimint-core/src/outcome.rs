@@ -1,3 +1,5 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use fedimint_core::module::SerdeModuleEncoding;
 use serde::{Deserialize, Serialize};
 
@@ -15,4 +17,29 @@ pub enum TransactionStatus {
     },
 }
 
+/// Result of validating a transaction against the current state of a guardian
+/// without submitting it, the transaction would be accepted if it is valid
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TransactionValidation {
+    /// Errors of the invalid inputs by their index
+    pub input_errors: BTreeMap<u64, String>,
+    /// Indices of the inputs that were spent already, which might be accepted
+    /// by the federation if rebuilt with other inputs
+    #[serde(default)]
+    pub spent_inputs: BTreeSet<u64>,
+    /// Errors of the invalid outputs by their index
+    pub output_errors: BTreeMap<u64, String>,
+    /// Errors of the transaction as a whole, like an invalid signature or
+    /// inputs and outputs that don't balance
+    pub transaction_errors: Vec<String>,
+}
+
+impl TransactionValidation {
+    pub fn is_valid(&self) -> bool {
+        self.input_errors.is_empty()
+            && self.output_errors.is_empty()
+            && self.transaction_errors.is_empty()
+    }
+}
+
 pub type SerdeOutputOutcome = SerdeModuleEncoding<fedimint_core::core::DynOutputOutcome>;
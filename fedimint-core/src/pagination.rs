@@ -0,0 +1,128 @@
+//! Cursor-based pagination of the API's list endpoints
+//!
+//! List endpoints take a [`PageRequest`] and return a [`Page`] of the items
+//! following its cursor. The cursor is the key the endpoint sorts its items
+//! by, so pages stay consistent while items are added or removed in between
+//! requests.
+
+use serde::{Deserialize, Serialize};
+
+/// Items returned if the request doesn't set a limit
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+
+/// Items returned at most, whatever limit the request sets
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+/// Pages the client requests at most for a single list, peers moving the
+/// cursor forward by a single item at a time would otherwise make us request
+/// a page for every item
+pub const MAX_PAGES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageRequest<C> {
+    /// Only items after this cursor are returned, all if `None`
+    pub cursor: Option<C>,
+    pub limit: Option<usize>,
+}
+
+impl<C> Default for PageRequest<C> {
+    fn default() -> Self {
+        Self {
+            cursor: None,
+            limit: None,
+        }
+    }
+}
+
+/// Items of a list following the cursor of a [`PageRequest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T, C> {
+    pub items: Vec<T>,
+    /// Cursor to request the next page with, `None` on the last page
+    pub next_cursor: Option<C>,
+}
+
+impl<C: Ord + Clone> PageRequest<C> {
+    pub fn after(cursor: C) -> Self {
+        Self {
+            cursor: Some(cursor),
+            limit: None,
+        }
+    }
+
+    /// Number of items to return, capped at [`MAX_PAGE_LIMIT`]
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    /// Whether an item with `cursor` belongs on a page after this request's
+    /// cursor
+    pub fn is_after(&self, cursor: &C) -> bool {
+        self.cursor.as_ref().map_or(true, |after| cursor > after)
+    }
+
+    /// Selects the page of `items` following the requested cursor, `items`
+    /// have to be sorted by their cursor
+    pub fn page<T>(&self, items: impl IntoIterator<Item = (C, T)>) -> Page<T, C> {
+        let limit = self.limit();
+        let mut items = items
+            .into_iter()
+            .skip_while(|(cursor, _)| !self.is_after(cursor))
+            .take(limit + 1)
+            .collect::<Vec<_>>();
+
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|(cursor, _)| cursor.clone())
+        } else {
+            None
+        };
+
+        Page {
+            items: items.into_iter().map(|(_, item)| item).collect(),
+            next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Page, PageRequest, MAX_PAGE_LIMIT};
+
+    fn items(count: u64) -> impl Iterator<Item = (u64, u64)> {
+        (0..count).map(|i| (i, i * 10))
+    }
+
+    #[test]
+    fn pages_cover_all_items_once() {
+        let mut request = PageRequest {
+            cursor: None,
+            limit: Some(3),
+        };
+        let mut fetched = vec![];
+        loop {
+            let Page { items, next_cursor } = request.page(items(10));
+            fetched.extend(items);
+            match next_cursor {
+                Some(cursor) => request.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(fetched, items(10).map(|(_, item)| item).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn limit_is_capped() {
+        let request = PageRequest::<u64> {
+            cursor: None,
+            limit: Some(usize::MAX),
+        };
+        assert_eq!(request.limit(), MAX_PAGE_LIMIT);
+
+        let page = PageRequest::after(7).page(items(10));
+        assert_eq!(page.items, vec![80, 90]);
+        assert_eq!(page.next_cursor, None);
+    }
+}
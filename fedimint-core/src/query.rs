@@ -1,5 +1,6 @@
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::Debug;
+use std::hash::Hash;
 use std::mem;
 
 use fedimint_core::task::{MaybeSend, MaybeSync};
@@ -10,6 +11,7 @@ use tracing::debug;
 
 use crate::api;
 use crate::api::MemberError;
+use crate::pagination::Page;
 
 /// Returns a result from the first responding peer
 pub struct TrustAllPeers;
@@ -114,6 +116,71 @@ impl<R: Debug + Eq + Clone> QueryStrategy<Vec<R>> for UnionResponses<R> {
     }
 }
 
+/// Returns the deduplicated union of the pages of `required` number of
+/// responses
+///
+/// The next cursor is the smallest one any peer returned, so no item is
+/// skipped if the peers' pages end at different items.
+pub struct UnionPages<T, C> {
+    responses: HashSet<PeerId>,
+    existing_results: Vec<T>,
+    seen: HashSet<T>,
+    next_cursor: Option<C>,
+    current: CurrentConsensus<Page<T, C>>,
+    required: usize,
+}
+
+impl<T, C> UnionPages<T, C> {
+    pub fn new(required: usize) -> Self {
+        Self {
+            responses: HashSet::new(),
+            existing_results: vec![],
+            seen: HashSet::new(),
+            next_cursor: None,
+            current: CurrentConsensus::new(required),
+            required,
+        }
+    }
+}
+
+impl<T, C> QueryStrategy<Page<T, C>> for UnionPages<T, C>
+where
+    T: Debug + Eq + Hash + Clone,
+    C: Debug + Ord + Clone,
+{
+    fn process(
+        &mut self,
+        peer: PeerId,
+        result: api::MemberResult<Page<T, C>>,
+    ) -> QueryStep<Page<T, C>> {
+        match result {
+            Ok(page) => {
+                for item in page.items {
+                    if self.seen.insert(item.clone()) {
+                        self.existing_results.push(item);
+                    }
+                }
+                self.next_cursor = match (self.next_cursor.take(), page.next_cursor) {
+                    (Some(existing), Some(new)) => Some(existing.min(new)),
+                    (existing, new) => existing.or(new),
+                };
+
+                self.responses.insert(peer);
+
+                if self.responses.len() >= self.required {
+                    QueryStep::Success(Page {
+                        items: mem::take(&mut self.existing_results),
+                        next_cursor: self.next_cursor.take(),
+                    })
+                } else {
+                    QueryStep::Continue
+                }
+            }
+            Err(e) => self.current.process(peer, Err(e)),
+        }
+    }
+}
+
 /// Returns the deduplicated union of `required` number of responses
 ///
 /// Unlike [`UnionResponses`], it works with single values, not `Vec`s.
@@ -0,0 +1,274 @@
+//! Guardians can sign their API responses with their epoch key share, which
+//! lets clients prove that a guardian gave conflicting responses
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use anyhow::{bail, format_err};
+use bitcoin_hashes::{sha256, Hash};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use threshold_crypto::{PublicKeyShare, SecretKeyShare};
+use tracing::warn;
+
+use crate::encoding::Encodable;
+use crate::epoch::SerdeSignatureShare;
+use crate::PeerId;
+
+/// Separates the signatures of API responses from other uses of the epoch key
+const API_RESPONSE_TAG: &str = "fedimint-api-response";
+
+/// Methods whose responses by a guardian never change for the same parameters,
+/// so a guardian signing two different responses is equivocating
+pub const IMMUTABLE_METHODS: &[&str] = &["/wait_transaction"];
+
+/// Subscriptions that push the same response as the method they replace, they
+/// are signed and tracked as that method so an outcome pushed by a guardian
+/// can be compared with one it returned
+const SUBSCRIPTION_ALIASES: &[(&str, &str)] = &[("/subscribe_transaction", "/wait_transaction")];
+
+/// How many responses to immutable methods we keep to compare them with later
+/// responses
+const MAX_TRACKED_RESPONSES: usize = 256;
+
+/// Response of a guardian to a request with `sign_response` set, the signature
+/// is `None` if the guardian doesn't sign its responses
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SignedApiResponse {
+    pub response: Value,
+    pub signature: Option<SerdeSignatureShare>,
+}
+
+/// Hash signed by `peer` for `response`, it commits to the request so a signed
+/// response can't be passed off as the response to another request
+pub fn response_hash(peer: PeerId, method: &str, params: &Value, response: &Value) -> sha256::Hash {
+    let method = signed_method(method);
+    let mut engine = sha256::Hash::engine();
+    API_RESPONSE_TAG
+        .to_string()
+        .consensus_encode(&mut engine)
+        .expect("writing to a hash engine can't fail");
+    peer.consensus_encode(&mut engine)
+        .expect("writing to a hash engine can't fail");
+    for value in [method.to_string(), params.to_string(), response.to_string()] {
+        value
+            .consensus_encode(&mut engine)
+            .expect("writing to a hash engine can't fail");
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+/// The method a response to `method` is signed as
+fn signed_method(method: &str) -> &str {
+    SUBSCRIPTION_ALIASES
+        .iter()
+        .find(|(subscription, _)| *subscription == method)
+        .map_or(method, |(_, replaced)| replaced)
+}
+
+/// Signs the API responses of our guardian
+#[derive(Debug)]
+pub struct ResponseSigner {
+    peer: PeerId,
+    key: SecretKeyShare,
+}
+
+impl ResponseSigner {
+    pub fn new(peer: PeerId, key: SecretKeyShare) -> Self {
+        Self { peer, key }
+    }
+
+    pub fn sign(&self, method: &str, params: &Value, response: Value) -> SignedApiResponse {
+        let hash = response_hash(self.peer, method, params, &response);
+        SignedApiResponse {
+            response,
+            signature: Some(SerdeSignatureShare(self.key.sign(hash))),
+        }
+    }
+}
+
+/// A guardian signed two different responses to the same request to one of the
+/// [`IMMUTABLE_METHODS`], the signatures prove that it equivocated
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EquivocationReport {
+    pub peer: PeerId,
+    pub method: String,
+    pub params: Value,
+    pub first: SignedApiResponse,
+    pub second: SignedApiResponse,
+}
+
+/// Verifies the signed responses of the guardians and reports guardians that
+/// equivocate
+#[derive(Debug)]
+pub struct ResponseVerifier {
+    pks: BTreeMap<PeerId, PublicKeyShare>,
+    /// Earlier signed responses to immutable methods by guardian and hash of
+    /// the request
+    tracked: Mutex<VecDeque<((PeerId, sha256::Hash), SignedApiResponse)>>,
+    reports: Mutex<Vec<EquivocationReport>>,
+}
+
+impl ResponseVerifier {
+    pub fn new(pks: BTreeMap<PeerId, PublicKeyShare>) -> Self {
+        Self {
+            pks,
+            tracked: Mutex::new(VecDeque::new()),
+            reports: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns the response if it is unsigned or carries a valid signature of
+    /// `peer`
+    pub fn verify(
+        &self,
+        peer: PeerId,
+        method: &str,
+        params: &Value,
+        signed: SignedApiResponse,
+    ) -> anyhow::Result<Value> {
+        let Some(signature) = &signed.signature else {
+            return Ok(signed.response);
+        };
+        let method = signed_method(method);
+
+        let pk = self
+            .pks
+            .get(&peer)
+            .ok_or_else(|| format_err!("No public key for peer {peer}"))?;
+        if !pk.verify(
+            &signature.0,
+            response_hash(peer, method, params, &signed.response),
+        ) {
+            bail!("Invalid response signature from peer {peer}");
+        }
+
+        if IMMUTABLE_METHODS.contains(&method) {
+            self.track(peer, method, params, &signed);
+        }
+
+        Ok(signed.response)
+    }
+
+    /// All guardians that were caught equivocating so far
+    pub fn reports(&self) -> Vec<EquivocationReport> {
+        self.reports.lock().expect("locks").clone()
+    }
+
+    fn track(&self, peer: PeerId, method: &str, params: &Value, signed: &SignedApiResponse) {
+        let key = (
+            peer,
+            sha256::Hash::hash(format!("{method}{params}").as_bytes()),
+        );
+        let mut tracked = self.tracked.lock().expect("locks");
+
+        let first = tracked
+            .iter()
+            .find(|(tracked_key, _)| *tracked_key == key)
+            .map(|(_, first)| first.clone());
+
+        match first {
+            Some(first) if first.response != signed.response => {
+                warn!(%peer, method, %params, "Guardian signed conflicting responses");
+                self.reports
+                    .lock()
+                    .expect("locks")
+                    .push(EquivocationReport {
+                        peer,
+                        method: method.to_string(),
+                        params: params.clone(),
+                        first,
+                        second: signed.clone(),
+                    });
+            }
+            Some(_) => {}
+            None => {
+                if tracked.len() == MAX_TRACKED_RESPONSES {
+                    tracked.pop_front();
+                }
+                tracked.push_back((key, signed.clone()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rand::rngs::OsRng;
+    use serde_json::json;
+    use threshold_crypto::SecretKeySet;
+
+    use super::{ResponseSigner, ResponseVerifier};
+    use crate::PeerId;
+
+    #[test]
+    fn reports_equivocating_guardian() {
+        let sks = SecretKeySet::random(1, &mut OsRng);
+        let peers = [PeerId::from(0), PeerId::from(1)];
+        let signers =
+            peers.map(|peer| ResponseSigner::new(peer, sks.secret_key_share(peer.to_usize())));
+        let verifier = ResponseVerifier::new(
+            peers
+                .iter()
+                .map(|&peer| (peer, sks.public_keys().public_key_share(peer.to_usize())))
+                .collect::<BTreeMap<_, _>>(),
+        );
+
+        let method = "/wait_transaction";
+        let params = json!("txid");
+
+        // a response signed by another guardian is rejected
+        let forged = signers[1].sign(method, &params, json!("accepted"));
+        assert!(verifier.verify(peers[0], method, &params, forged).is_err());
+
+        let accepted = signers[0].sign(method, &params, json!("accepted"));
+        assert_eq!(
+            verifier
+                .verify(peers[0], method, &params, accepted.clone())
+                .unwrap(),
+            json!("accepted")
+        );
+        verifier
+            .verify(peers[0], method, &params, accepted)
+            .unwrap();
+        assert!(verifier.reports().is_empty());
+
+        let rejected = signers[0].sign(method, &params, json!("rejected"));
+        verifier
+            .verify(peers[0], method, &params, rejected)
+            .unwrap();
+
+        let reports = verifier.reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].peer, peers[0]);
+        assert_eq!(reports[0].first.response, json!("accepted"));
+        assert_eq!(reports[0].second.response, json!("rejected"));
+    }
+
+    #[test]
+    fn pushed_outcomes_are_compared_with_returned_ones() {
+        let sks = SecretKeySet::random(0, &mut OsRng);
+        let peer = PeerId::from(0);
+        let signer = ResponseSigner::new(peer, sks.secret_key_share(0));
+        let verifier = ResponseVerifier::new(BTreeMap::from([(
+            peer,
+            sks.public_keys().public_key_share(0),
+        )]));
+        let params = json!("txid");
+
+        let returned = signer.sign("/wait_transaction", &params, json!("accepted"));
+        verifier
+            .verify(peer, "/wait_transaction", &params, returned)
+            .unwrap();
+
+        let pushed = signer.sign("/subscribe_transaction", &params, json!("rejected"));
+        verifier
+            .verify(peer, "/subscribe_transaction", &params, pushed)
+            .unwrap();
+
+        let reports = verifier.reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].method, "/wait_transaction");
+    }
+}
@@ -1,11 +1,17 @@
+//! Async runtime abstraction, so code reachable from clients doesn't depend on
+//! Tokio and also runs in the browser
+//!
+//! Tasks are spawned with a [`TaskGroup`] or [`spawn`], and [`sleep`],
+//! [`timeout`] and the locks are implemented with Tokio natively and with
+//! `wasm-bindgen-futures` and `gloo-timers` on wasm. Channels are taken from
+//! [`channel`].
 #![cfg_attr(target_family = "wasm", allow(dead_code))]
 
 use std::collections::VecDeque;
 use std::future::Future;
-use std::pin::Pin;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::SeqCst;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
 
 use fedimint_logging::LOG_TASK;
@@ -28,6 +34,31 @@ type JoinHandle<T> = futures::future::Ready<anyhow::Result<T>>;
 #[error("deadline has elapsed")]
 pub struct Elapsed;
 
+/// Names of the tasks that didn't finish cleanly when joining a [`TaskGroup`]
+#[derive(Debug, Default, Error)]
+#[error("Tasks did not finish cleanly, failed: {failed:?}, hung: {hung:?}")]
+pub struct TaskGroupJoinError {
+    /// Tasks that panicked or were cancelled
+    pub failed: Vec<String>,
+    /// Tasks that didn't finish within their join timeout and were aborted
+    pub hung: Vec<String>,
+}
+
+impl TaskGroupJoinError {
+    fn is_empty(&self) -> bool {
+        self.failed.is_empty() && self.hung.is_empty()
+    }
+}
+
+/// A spawned task waiting to be joined
+#[derive(Debug)]
+struct TaskJoin {
+    name: String,
+    /// Overrides the timeout passed to [`TaskGroup::join_all`]
+    join_timeout: Option<Duration>,
+    handle: JoinHandle<()>,
+}
+
 #[derive(Debug, Default)]
 struct TaskGroupInner {
     /// Was the shutdown requested, either externally or due to any task
@@ -35,11 +66,16 @@ struct TaskGroupInner {
     is_shutting_down: AtomicBool,
     #[allow(clippy::type_complexity)]
     on_shutdown: Mutex<Vec<Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send + 'static>>>,
-    join: Mutex<VecDeque<(String, JoinHandle<()>)>>,
+    join: Mutex<VecDeque<TaskJoin>>,
+    /// Subgroups that are shut down and joined together with this group
+    subgroups: Mutex<Vec<TaskGroup>>,
+    /// Group this one is a subgroup of, which forgets it once it was joined
+    parent: Option<Weak<TaskGroupInner>>,
 }
 
 impl TaskGroupInner {
-    pub async fn shutdown(&self) {
+    /// Shuts down this group only, see [`TaskGroup::shutdown`]
+    async fn shutdown(&self) {
         // Note: set the flag before starting to call shutdown handlers
         // to avoid confusion.
         self.is_shutting_down.store(true, SeqCst);
@@ -84,33 +120,39 @@ impl TaskGroup {
     /// Create a sub-group
     ///
     /// Task subgroup works like an independent [`TaskGroup`], but the parent
-    /// `TaskGroup` will propagate the shut down signal to a sub-group.
+    /// `TaskGroup` will propagate the shut down signal to a sub-group, and a
+    /// subgroup of a group that is shutting down already starts shut down.
     ///
     /// In contrast to using the parent group directly, a subgroup allows
     /// calling [`Self::join_all`] and detecting any panics on just a
     /// subset of tasks.
     ///
-    /// The code create a subgroup is responsible for calling
-    /// [`Self::join_all`]. If it won't, the parent subgroup **will not**
-    /// detect any panics in the tasks spawned by the subgroup.
+    /// Joining the parent also joins all subgroups that weren't joined yet,
+    /// so their panics are reported to the parent as well.
     pub async fn make_subgroup(&self) -> TaskGroup {
-        let new_tg = Self::new();
-        self.make_handle()
-            .on_shutdown({
-                let new_tg = self.clone();
-                Box::new(move || {
-                    Box::pin(async move {
-                        new_tg.shutdown().await;
-                    })
-                })
-            })
-            .await;
+        let new_tg = TaskGroup {
+            inner: Arc::new(TaskGroupInner {
+                is_shutting_down: AtomicBool::new(self.is_shutting_down()),
+                parent: Some(Arc::downgrade(&self.inner)),
+                ..Default::default()
+            }),
+        };
+        self.inner.subgroups.lock().await.push(new_tg.clone());
 
         new_tg
     }
 
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.is_shutting_down.load(SeqCst)
+    }
+
+    /// Shuts down this group and then its subgroups, parents first
     pub async fn shutdown(&self) {
-        self.inner.shutdown().await
+        let mut groups = VecDeque::from([self.clone()]);
+        while let Some(group) = groups.pop_front() {
+            group.inner.shutdown().await;
+            groups.extend(group.inner.subgroups.lock().await.iter().cloned());
+        }
     }
 
     pub async fn shutdown_join_all(
@@ -171,22 +213,49 @@ impl TaskGroup {
         Fut: Future<Output = R> + Send + 'static,
         R: Send + 'static,
     {
-        let name = name.into();
-        let mut guard = TaskPanicGuard {
-            name: name.clone(),
-            inner: self.inner.clone(),
-            completed: false,
-        };
+        self.spawn_inner(name.into(), None, f).await
+    }
+
+    /// Like [`Self::spawn`], but [`Self::join_all`] waits at most
+    /// `join_timeout` for the task instead of the timeout it was called with
+    /// and aborts the task if it doesn't finish in time
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn spawn_with_join_timeout<Fut, R>(
+        &mut self,
+        name: impl Into<String>,
+        join_timeout: Duration,
+        f: impl FnOnce(TaskHandle) -> Fut + Send + 'static,
+    ) -> oneshot::Receiver<R>
+    where
+        Fut: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.spawn_inner(name.into(), Some(join_timeout), f).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn spawn_inner<Fut, R>(
+        &mut self,
+        name: String,
+        join_timeout: Option<Duration>,
+        f: impl FnOnce(TaskHandle) -> Fut + Send + 'static,
+    ) -> oneshot::Receiver<R>
+    where
+        Fut: Future<Output = R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut guard = self.make_panic_guard(&name);
         let handle = self.make_handle();
 
         let (tx, rx) = oneshot::channel();
         if let Some(handle) = self::imp::spawn(async move {
+            let result = f(handle).await;
+            guard.completed = true;
             // if receiver is not interested, just drop the message
-            let _ = tx.send(f(handle).await);
+            let _ = tx.send(result);
         }) {
-            self.inner.join.lock().await.push_back((name, handle));
+            self.push_join(name, join_timeout, handle).await;
         }
-        guard.completed = true;
 
         rx
     }
@@ -200,19 +269,15 @@ impl TaskGroup {
         Fut: Future<Output = ()> + 'static,
     {
         let name = name.into();
-        let mut guard = TaskPanicGuard {
-            name: name.clone(),
-            inner: self.inner.clone(),
-            completed: false,
-        };
+        let mut guard = self.make_panic_guard(&name);
         let handle = self.make_handle();
 
         if let Some(handle) = self::imp::spawn_local(async move {
             f(handle).await;
+            guard.completed = true;
         }) {
-            self.inner.join.lock().await.push_back((name, handle));
+            self.push_join(name, None, handle).await;
         }
-        guard.completed = true;
     }
     // TODO: Send vs lack of Send bound; do something about it
     #[cfg(target_family = "wasm")]
@@ -226,70 +291,109 @@ impl TaskGroup {
         R: 'static,
     {
         let name = name.into();
-        let mut guard = TaskPanicGuard {
-            name: name.clone(),
-            inner: self.inner.clone(),
-            completed: false,
-        };
+        let mut guard = self.make_panic_guard(&name);
         let handle = self.make_handle();
 
         let (tx, rx) = oneshot::channel();
         if let Some(handle) = self::imp::spawn(async move {
-            let _ = tx.send(f(handle).await);
+            let result = f(handle).await;
+            guard.completed = true;
+            let _ = tx.send(result);
         }) {
-            self.inner.join.lock().await.push_back((name, handle));
+            self.push_join(name, None, handle).await;
         }
-        guard.completed = true;
 
         rx
     }
 
+    fn make_panic_guard(&self, name: &str) -> TaskPanicGuard {
+        TaskPanicGuard {
+            name: name.to_string(),
+            inner: self.inner.clone(),
+            completed: false,
+        }
+    }
+
+    async fn push_join(
+        &self,
+        name: String,
+        join_timeout: Option<Duration>,
+        handle: JoinHandle<()>,
+    ) {
+        self.inner.join.lock().await.push_back(TaskJoin {
+            name,
+            join_timeout,
+            handle,
+        });
+    }
+
+    /// Waits for all tasks of this group and its subgroups to finish
+    ///
+    /// Every task is waited for at most its own join timeout, if it was
+    /// spawned with one, or `join_timeout` otherwise. Tasks that don't finish
+    /// in time are aborted. The returned error is a [`TaskGroupJoinError`]
+    /// naming the tasks that failed or hung.
     pub async fn join_all(self, join_timeout: Option<Duration>) -> Result<(), anyhow::Error> {
-        let mut errors = vec![];
-        while let Some((name, join)) = self.inner.join.lock().await.pop_front() {
+        let mut errors = TaskGroupJoinError::default();
+        let mut groups = VecDeque::from([self.clone()]);
+        while let Some(group) = groups.pop_front() {
+            group.join_tasks(join_timeout, &mut errors).await;
+            groups.extend(std::mem::take(&mut *group.inner.subgroups.lock().await));
+        }
+
+        if let Some(parent) = self.inner.parent.as_ref().and_then(Weak::upgrade) {
+            parent
+                .subgroups
+                .lock()
+                .await
+                .retain(|subgroup| !Arc::ptr_eq(&subgroup.inner, &self.inner));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.into())
+        }
+    }
+
+    /// Joins the tasks of this group only, see [`Self::join_all`]
+    async fn join_tasks(&self, join_timeout: Option<Duration>, errors: &mut TaskGroupJoinError) {
+        loop {
+            // the lock mustn't be held while waiting, tasks may still spawn others
+            let Some(TaskJoin {
+                name,
+                join_timeout: task_join_timeout,
+                mut handle,
+            }) = self.inner.join.lock().await.pop_front()
+            else {
+                break;
+            };
             debug!("Waiting for {name} task to finish");
 
-            #[cfg(not(target_family = "wasm"))]
-            let join_future: Pin<Box<dyn Future<Output = _> + Send>> =
-                if let Some(join_timeout) = join_timeout {
-                    Box::pin(timeout(join_timeout, join))
-                } else {
-                    Box::pin(async move { Ok(join.await) })
-                };
-
-            #[cfg(target_family = "wasm")]
-            let join_future: Pin<Box<dyn Future<Output = _>>> =
-                if let Some(join_timeout) = join_timeout {
-                    Box::pin(timeout(join_timeout, join))
-                } else {
-                    Box::pin(async move { Ok(join.await) })
-                };
-
-            match join_future.await {
+            let join_result = match task_join_timeout.or(join_timeout) {
+                Some(join_timeout) => timeout(join_timeout, &mut handle).await,
+                None => Ok((&mut handle).await),
+            };
+
+            match join_result {
                 Ok(Ok(())) => {
                     info!(target: LOG_TASK, "{name} task finished");
                 }
                 Ok(Err(e)) => {
                     error!(target: LOG_TASK, "Thread {name} panicked with: {e}");
-                    errors.push(e);
+                    errors.failed.push(name);
                 }
                 Err(Elapsed) => {
                     warn!(
                         target: LOG_TASK,
-                        "{name} task hit timeout while shutting down"
-                    )
+                        "{name} task hit timeout while shutting down, aborting it"
+                    );
+                    #[cfg(not(target_family = "wasm"))]
+                    handle.abort();
+                    errors.hung.push(name);
                 }
             }
         }
-
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            let num_errors = errors.len();
-            Err(anyhow::Error::msg(format!(
-                "{num_errors} tasks did not finish cleanly: {errors:?}"
-            )))
-        }
     }
 }
 
@@ -309,10 +413,17 @@ impl TaskPanicGuard {
 impl Drop for TaskPanicGuard {
     fn drop(&mut self) {
         if !self.completed {
-            info!(
-                target: LOG_TASK,
-                "Task {} shut down uncleanly. Shutting down task group.", self.name
-            );
+            if std::thread::panicking() {
+                error!(
+                    target: LOG_TASK,
+                    "Task {} panicked. Shutting down task group.", self.name
+                );
+            } else {
+                info!(
+                    target: LOG_TASK,
+                    "Task {} shut down uncleanly. Shutting down task group.", self.name
+                );
+            }
             self.inner.is_shutting_down.store(true, SeqCst);
         }
     }
@@ -356,6 +467,25 @@ impl TaskHandle {
     }
 }
 
+/// Spawns a detached task outside of any [`TaskGroup`]
+///
+/// Runs on Tokio natively and on the browser's event loop on wasm, so client
+/// code never calls `tokio::spawn` directly.
+pub fn spawn<F>(name: &str, future: F)
+where
+    F: Future<Output = ()> + MaybeSend + 'static,
+{
+    debug!(target: LOG_TASK, name, "spawning detached task");
+    self::imp::spawn(future);
+}
+
+/// Channels to communicate between tasks
+///
+/// Tokio's channels don't need its runtime, so they work on every target.
+pub mod channel {
+    pub use tokio::sync::{broadcast, mpsc, oneshot, watch};
+}
+
 #[cfg(not(target_family = "wasm"))]
 mod imp {
     pub use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
@@ -562,3 +692,63 @@ impl<T: Sync> MaybeSync for T {}
 
 #[cfg(target_family = "wasm")]
 impl<T> MaybeSync for T {}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use std::time::Duration;
+
+    use super::{TaskGroup, TaskGroupJoinError};
+
+    #[tokio::test]
+    async fn shutdown_propagates_to_subgroups() {
+        let parent = TaskGroup::new();
+        let mut subgroup = parent.make_subgroup().await;
+        let mut nested = subgroup.make_subgroup().await;
+
+        for group in [&mut subgroup, &mut nested] {
+            group
+                .spawn("wait for shutdown", |handle| async move {
+                    while !handle.is_shutting_down() {
+                        super::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+                .await;
+        }
+
+        parent.shutdown().await;
+        assert!(subgroup.is_shutting_down());
+        assert!(nested.is_shutting_down());
+        // a subgroup of a group that is shutting down starts shut down
+        assert!(parent.make_subgroup().await.is_shutting_down());
+
+        parent.join_all(Some(Duration::from_secs(5))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn join_reports_failed_and_hung_tasks() {
+        let mut parent = TaskGroup::new();
+        let mut subgroup = parent.make_subgroup().await;
+
+        parent.spawn("finishes", |_| async {}).await;
+        subgroup
+            .spawn("panics", |_| async { panic!("task failed") })
+            .await;
+        subgroup
+            .spawn_with_join_timeout("hangs", Duration::from_millis(50), |_| {
+                std::future::pending::<()>()
+            })
+            .await;
+
+        // the subgroup's tasks are joined with the parent
+        let error = parent
+            .join_all(None)
+            .await
+            .unwrap_err()
+            .downcast::<TaskGroupJoinError>()
+            .unwrap();
+        assert_eq!(error.failed, vec!["panics".to_string()]);
+        assert_eq!(error.hung, vec!["hangs".to_string()]);
+        // the panic shut down the subgroup
+        assert!(subgroup.is_shutting_down());
+    }
+}
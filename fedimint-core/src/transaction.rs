@@ -179,3 +179,23 @@ pub enum TransactionError {
     #[error("The transaction did not have a signature although there were inputs to be signed")]
     MissingSignature,
 }
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::encoding::Decodable;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+
+    use super::Transaction;
+
+    #[test]
+    fn transactions_of_unknown_module_instances_fail_to_decode() {
+        // one input of module instance 5, which the decoders don't know
+        let mut bytes = 1u64.to_le_bytes().to_vec();
+        bytes.extend(5u16.to_le_bytes());
+
+        let error =
+            Transaction::consensus_decode(&mut bytes.as_slice(), &ModuleDecoderRegistry::default())
+                .unwrap_err();
+        assert_eq!(error.to_string(), "Unknown module instance 5");
+    }
+}
@@ -6,17 +6,14 @@ use fedimint_core::config::ServerModuleGenRegistry;
 use fedimint_core::db::notifications::Notifications;
 use fedimint_core::db::{DatabaseTransaction, DatabaseVersionKey, SingleUseDatabaseTransaction};
 use fedimint_core::encoding::Encodable;
-use fedimint_core::module::DynServerModuleGen;
 use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::{push_db_key_items, push_db_pair_items, push_db_pair_items_no_serde};
-use fedimint_ln_server::LightningGen;
-use fedimint_mint_server::MintGen;
 use fedimint_rocksdb::RocksDbReadOnly;
-use fedimint_server::config::io::read_server_config;
+use fedimint_server::config::io::read_server_config_with_key;
+use fedimint_server::config::keys::ConfigKeySource;
 use fedimint_server::config::ServerConfig;
 use fedimint_server::db as ConsensusRange;
-use fedimint_wallet_server::WalletGen;
 use futures::StreamExt;
 use mint_client::db as ClientRange;
 use mint_client::ln::db as ClientLightningRange;
@@ -24,6 +21,8 @@ use mint_client::mint::db as ClientMintRange;
 use mint_client::wallet::db as ClientWalletRange;
 use strum::IntoEnumIterator;
 
+use crate::DbKind;
+
 #[derive(Debug, serde::Serialize)]
 struct SerdeWrapper(#[serde(with = "hex::serde")] Vec<u8>);
 
@@ -41,6 +40,7 @@ impl SerdeWrapper {
 pub struct DatabaseDump<'a> {
     serialized: BTreeMap<String, Box<dyn Serialize>>,
     read_only: DatabaseTransaction<'a>,
+    kind: DbKind,
     modules: Vec<String>,
     prefixes: Vec<String>,
     cfg: Option<ServerConfig>,
@@ -48,12 +48,15 @@ pub struct DatabaseDump<'a> {
 }
 
 impl<'a> DatabaseDump<'a> {
-    pub fn new(
+    pub async fn new(
         cfg_dir: PathBuf,
         data_dir: String,
         password: String,
+        config_key: ConfigKeySource,
+        kind: DbKind,
         modules: Vec<String>,
         prefixes: Vec<String>,
+        module_inits: ServerModuleGenRegistry,
     ) -> DatabaseDump<'a> {
         let read_only = match RocksDbReadOnly::open_read_only(data_dir) {
             Ok(db) => db,
@@ -65,7 +68,13 @@ impl<'a> DatabaseDump<'a> {
 
         // leak here is OK, it only happens once.
         let notifications = Box::leak(Box::new(Notifications::new()));
-        if modules.contains(&"client".to_string()) {
+        // `client` as module selected client databases before there was `kind`
+        let kind = if modules.contains(&"client".to_string()) {
+            DbKind::Client
+        } else {
+            kind
+        };
+        if kind != DbKind::Server {
             let dbtx = DatabaseTransaction::new(
                 Box::new(single_use),
                 ModuleDecoderRegistry::default(),
@@ -74,6 +83,7 @@ impl<'a> DatabaseDump<'a> {
             return DatabaseDump {
                 serialized: BTreeMap::new(),
                 read_only: dbtx,
+                kind,
                 modules,
                 prefixes,
                 cfg: None,
@@ -81,19 +91,15 @@ impl<'a> DatabaseDump<'a> {
             };
         }
 
-        let module_inits = ServerModuleGenRegistry::from(vec![
-            DynServerModuleGen::from(WalletGen),
-            DynServerModuleGen::from(MintGen),
-            DynServerModuleGen::from(LightningGen),
-        ]);
-
-        let cfg = read_server_config(&password, cfg_dir).unwrap();
+        let key = config_key.config_key(&password, &cfg_dir).await.unwrap();
+        let cfg = read_server_config_with_key(&key, cfg_dir).unwrap();
         let decoders = module_inits.decoders(cfg.iter_module_instances()).unwrap();
         let dbtx = DatabaseTransaction::new(Box::new(single_use), decoders, notifications);
 
         DatabaseDump {
             serialized: BTreeMap::new(),
             read_only: dbtx,
+            kind,
             modules,
             prefixes,
             cfg: Some(cfg),
@@ -103,16 +109,15 @@ impl<'a> DatabaseDump<'a> {
 }
 
 impl<'a> DatabaseDump<'a> {
-    /// Prints the contents of the BTreeMap to a pretty JSON string
-    fn print_database(&self) {
-        let json = serde_json::to_string_pretty(&self.serialized).unwrap();
-        println!("{json}");
+    /// Whether the range of `module` was selected to be dumped
+    fn is_selected(&self, module: &str) -> bool {
+        self.modules.is_empty() || self.modules.contains(&module.to_string())
     }
 
     /// Iterates through all the specified ranges in the database and retrieves
-    /// the data for each range. Prints serialized contents at the end.
-    pub async fn dump_database(&mut self) {
-        if self.modules.is_empty() || self.modules.contains(&"consensus".to_string()) {
+    /// the data for each range. Returns the serialized contents as pretty JSON.
+    pub async fn dump_database(&mut self) -> String {
+        if self.kind == DbKind::Server && self.is_selected("consensus") {
             self.retrieve_consensus_data().await;
         }
 
@@ -150,14 +155,23 @@ impl<'a> DatabaseDump<'a> {
 
         // TODO: When the client is modularized, these don't need to be hardcoded
         // anymore
-        if !self.modules.is_empty() && self.modules.contains(&"client".to_string()) {
-            self.retrieve_client_data().await;
-            self.retrieve_ln_client_data().await;
-            self.retrieve_mint_client_data().await;
-            self.retrieve_wallet_client_data().await;
+        if self.kind != DbKind::Server {
+            let all = self.is_selected("client");
+            if all {
+                self.retrieve_client_data().await;
+            }
+            if all || self.is_selected("ln") {
+                self.retrieve_ln_client_data().await;
+            }
+            if all || self.is_selected("mint") {
+                self.retrieve_mint_client_data().await;
+            }
+            if all || self.is_selected("wallet") {
+                self.retrieve_wallet_client_data().await;
+            }
         }
 
-        self.print_database();
+        serde_json::to_string_pretty(&self.serialized).expect("Dump serializes to JSON")
     }
 
     /// Iterates through each of the prefixes within the consensus range and
@@ -233,6 +247,123 @@ impl<'a> DatabaseDump<'a> {
                         consensus.insert("ConsensusUpgrade".to_string(), Box::new(upgrade));
                     }
                 }
+                ConsensusRange::DbKeyPrefix::PeerSetChangeVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::PeerSetChangeVoteKeyPrefix,
+                        ConsensusRange::PeerSetChangeVoteKey,
+                        fedimint_core::epoch::PeerSetChange,
+                        consensus,
+                        "Peer Set Change Votes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ApprovedPeerSetChange => {
+                    let approved = dbtx
+                        .get_value(&ConsensusRange::ApprovedPeerSetChangeKey)
+                        .await;
+                    if let Some(approved) = approved {
+                        consensus.insert("ApprovedPeerSetChange".to_string(), Box::new(approved));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::EpochCheckpoint => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::EpochCheckpointKeyPrefix,
+                        ConsensusRange::EpochCheckpointKey,
+                        fedimint_core::epoch::SignedEpochCheckpoint,
+                        consensus,
+                        "Epoch Checkpoints"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::EarliestEpoch => {
+                    let earliest_epoch = dbtx.get_value(&ConsensusRange::EarliestEpochKey).await;
+                    if let Some(earliest_epoch) = earliest_epoch {
+                        consensus.insert("EarliestEpoch".to_string(), Box::new(earliest_epoch));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::PeerConsensusVersion => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::PeerConsensusVersionKeyPrefix,
+                        ConsensusRange::PeerConsensusVersionKey,
+                        u32,
+                        consensus,
+                        "Peer Consensus Versions"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ConsensusVersionVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ConsensusVersionVoteKeyPrefix,
+                        ConsensusRange::ConsensusVersionVoteKey,
+                        fedimint_core::epoch::ConsensusVersionActivation,
+                        consensus,
+                        "Consensus Version Votes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ScheduledConsensusVersion => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ScheduledConsensusVersionKeyPrefix,
+                        ConsensusRange::ScheduledConsensusVersionKey,
+                        u64,
+                        consensus,
+                        "Scheduled Consensus Versions"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::StateSnapshot => {
+                    let snapshot = dbtx.get_value(&ConsensusRange::StateSnapshotKey).await;
+                    if let Some(snapshot) = snapshot {
+                        consensus
+                            .insert("StateSnapshotEpoch".to_string(), Box::new(snapshot.epoch));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ConsensusParamsVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ConsensusParamsVoteKeyPrefix,
+                        ConsensusRange::ConsensusParamsVoteKey,
+                        fedimint_core::epoch::ConsensusParams,
+                        consensus,
+                        "Consensus Params Votes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ConsensusParams => {
+                    let params = dbtx.get_value(&ConsensusRange::ConsensusParamsKey).await;
+                    if let Some(params) = params {
+                        consensus.insert("ConsensusParams".to_string(), Box::new(params));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ParamsChangeVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ParamsChangeVoteKeyPrefix,
+                        ConsensusRange::ParamsChangeVoteKey,
+                        fedimint_core::epoch::ParamsChangeProposal,
+                        consensus,
+                        "Params Change Votes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ScheduledParamsChange => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ScheduledParamsChangeKeyPrefix,
+                        ConsensusRange::ScheduledParamsChangeKey,
+                        fedimint_core::epoch::ParamsChangeProposal,
+                        consensus,
+                        "Scheduled Params Changes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::StateHash => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::StateHashKeyPrefix,
+                        ConsensusRange::StateHashKey,
+                        bitcoin_hashes::sha256::Hash,
+                        consensus,
+                        "State Hashes"
+                    );
+                }
                 // Module is a global prefix for all module data
                 ConsensusRange::DbKeyPrefix::Module => {}
             }
@@ -302,6 +433,105 @@ impl<'a> DatabaseDump<'a> {
                         "Outgoing Payment Claims"
                     );
                 }
+                ClientLightningRange::DbKeyPrefix::LightningAddress => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::LightningAddressKeyPrefix,
+                        ClientLightningRange::LightningAddressKey,
+                        mint_client::ln::lnurl::LightningAddress,
+                        ln_client,
+                        "Lightning Addresses"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::LnurlPayment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::LnurlPaymentKeyPrefix,
+                        ClientLightningRange::LnurlPaymentKey,
+                        mint_client::ln::lnurl::LnurlPayment,
+                        ln_client,
+                        "LNURL Payments"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::EcashReserve => {
+                    let reserve = dbtx.get_value(&ClientLightningRange::EcashReserveKey).await;
+                    if let Some(reserve) = reserve {
+                        ln_client.insert("EcashReserve".to_string(), Box::new(reserve));
+                    }
+                }
+                ClientLightningRange::DbKeyPrefix::AbandonedOffer => {
+                    push_db_key_items!(
+                        dbtx,
+                        ClientLightningRange::AbandonedOfferKeyPrefix,
+                        ClientLightningRange::AbandonedOfferKey,
+                        ln_client,
+                        "Abandoned Offers"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::OrderInvoice => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::OrderInvoiceKeyPrefix,
+                        ClientLightningRange::OrderInvoiceKey,
+                        mint_client::modules::ln::contracts::ContractId,
+                        ln_client,
+                        "Order Invoices"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::IncomingGatewayFee => {
+                    let fee = dbtx
+                        .get_value(&ClientLightningRange::IncomingGatewayFeeKey)
+                        .await;
+                    if let Some(fee) = fee {
+                        ln_client.insert("IncomingGatewayFee".to_string(), Box::new(fee));
+                    }
+                }
+                ClientLightningRange::DbKeyPrefix::HtlcAmountBand => {
+                    let band = dbtx
+                        .get_value(&ClientLightningRange::HtlcAmountBandKey)
+                        .await;
+                    if let Some(band) = band {
+                        ln_client.insert("HtlcAmountBand".to_string(), Box::new(band));
+                    }
+                }
+                ClientLightningRange::DbKeyPrefix::RoutingPaused => {
+                    let paused = dbtx
+                        .get_value(&ClientLightningRange::RoutingPausedKey)
+                        .await;
+                    if let Some(paused) = paused {
+                        ln_client.insert("RoutingPaused".to_string(), Box::new(paused));
+                    }
+                }
+                ClientLightningRange::DbKeyPrefix::GatewayFeeClaim => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::GatewayFeeClaimKeyPrefix,
+                        ClientLightningRange::GatewayFeeClaimKey,
+                        mint_client::modules::ln::contracts::Preimage,
+                        ln_client,
+                        "Gateway Fee Claims"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::PreimagePurchase => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::PreimagePurchaseKeyPrefix,
+                        ClientLightningRange::PreimagePurchaseKey,
+                        mint_client::ln::incoming::PreimagePurchase,
+                        ln_client,
+                        "Preimage Purchases"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::PaymentHistory => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::PaymentHistoryKeyPrefix,
+                        ClientLightningRange::PaymentHistoryKey,
+                        mint_client::ln::history::PaymentHistoryEntry,
+                        ln_client,
+                        "Payment History"
+                    );
+                }
             }
         }
 
@@ -368,6 +598,34 @@ impl<'a> DatabaseDump<'a> {
                         mint_client.insert("NotesPerDenomination".to_string(), Box::new(notes));
                     }
                 }
+                ClientMintRange::DbKeyPrefix::P2pkOutputFinalizationData => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientMintRange::P2pkOutputFinalizationKeyPrefix,
+                        ClientMintRange::P2pkOutputFinalizationKey,
+                        mint_client::mint::P2pkIssuanceRequests,
+                        mint_client,
+                        "P2PK Output Finalization"
+                    );
+                }
+                ClientMintRange::DbKeyPrefix::TierDeprecation => {
+                    let deprecation = dbtx.get_value(&ClientMintRange::TierDeprecationKey).await;
+                    if let Some(deprecation) = deprecation {
+                        mint_client.insert("TierDeprecation".to_string(), Box::new(deprecation));
+                    }
+                }
+                ClientMintRange::DbKeyPrefix::LastNoteRefresh => {
+                    let last_refresh = dbtx.get_value(&ClientMintRange::LastNoteRefreshKey).await;
+                    if let Some(last_refresh) = last_refresh {
+                        mint_client.insert("LastNoteRefresh".to_string(), Box::new(last_refresh));
+                    }
+                }
+                ClientMintRange::DbKeyPrefix::IssuanceLedger => {
+                    let ledger = dbtx.get_value(&ClientMintRange::IssuanceLedgerKey).await;
+                    if let Some(ledger) = ledger {
+                        mint_client.insert("IssuanceLedger".to_string(), Box::new(ledger));
+                    }
+                }
             }
         }
 
@@ -423,6 +681,46 @@ impl<'a> DatabaseDump<'a> {
                         client.insert("Client Secret".to_string(), Box::new(secret));
                     }
                 }
+                ClientRange::DbKeyPrefix::ClientMnemonic => {
+                    let mnemonic = self
+                        .read_only
+                        .get_value(&ClientRange::ClientMnemonicKey)
+                        .await;
+                    if let Some(mnemonic) = mnemonic {
+                        client.insert("Client Mnemonic".to_string(), Box::new(mnemonic));
+                    }
+                }
+                ClientRange::DbKeyPrefix::Operation => {
+                    let dbtx = &mut self.read_only;
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientRange::OperationKeyPrefix,
+                        ClientRange::OperationKey,
+                        mint_client::operation::Operation,
+                        client,
+                        "Operations"
+                    );
+                }
+                ClientRange::DbKeyPrefix::ConnectionOptions => {
+                    let options = self
+                        .read_only
+                        .get_value(&ClientRange::ConnectionOptionsKey)
+                        .await;
+                    if let Some(options) = options {
+                        client.insert("Connection Options".to_string(), Box::new(options));
+                    }
+                }
+                ClientRange::DbKeyPrefix::SubAccount => {
+                    let dbtx = &mut self.read_only;
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientRange::SubAccountKeyPrefix,
+                        ClientRange::SubAccountKey,
+                        mint_client::sub_account::SubAccount,
+                        client,
+                        "Sub-Accounts"
+                    );
+                }
             }
         }
 
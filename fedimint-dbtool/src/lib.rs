@@ -0,0 +1,379 @@
+#![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use bitcoin_hashes::hex::ToHex;
+use bytes::Bytes;
+use clap::{Parser, Subcommand, ValueEnum};
+use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::db::{Database, IDatabase};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::ServerModuleGen;
+use fedimint_ln_server::LightningGen;
+use fedimint_logging::TracingSetup;
+use fedimint_mint_server::MintGen;
+use fedimint_server::config::io::read_server_config_with_key;
+use fedimint_server::config::keys::ConfigKeySource;
+use fedimint_wallet_server::WalletGen;
+use futures::StreamExt;
+
+use crate::dump::DatabaseDump;
+use crate::repair::{delete_entries, RawEntry, RepairPattern};
+
+mod dump;
+mod repair;
+
+/// Which program the database belongs to, which determines how its entries
+/// are decoded
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum DbKind {
+    /// Database of a guardian, decoding it requires the guardian's config
+    Server,
+    /// Database of `fedimint-cli` or another user client
+    Client,
+    /// Database of a federation client of the gateway, which uses the same
+    /// ranges as user clients
+    Gateway,
+}
+
+#[derive(Debug, Clone, Parser)]
+struct Options {
+    database: String,
+    #[command(subcommand)]
+    command: DbCommand,
+}
+
+/// Tool to inspect and manipulate rocksdb databases. All binary arguments
+/// (keys, values) have to be hex encoded.
+#[derive(Debug, Clone, Subcommand)]
+enum DbCommand {
+    /// List all key-value pairs where the key begins with `prefix`
+    List {
+        #[arg(value_parser = hex_parser)]
+        prefix: Bytes,
+    },
+    /// Write a key-value pair to the database, overwriting the previous value
+    /// if present
+    Write {
+        #[arg(value_parser = hex_parser)]
+        key: Bytes,
+        #[arg(value_parser = hex_parser)]
+        value: Bytes,
+    },
+    /// Delete a single entry from the database identified by `key`
+    Delete {
+        #[arg(value_parser = hex_parser)]
+        key: Bytes,
+    },
+    /// Dump a subset of the specified database and serialize the retrieved data
+    /// to JSON. Module and prefix are used to specify which subset of the
+    /// database to dump. Password is used to decrypt the server's
+    /// configuration file. If dumping the client database, the password can
+    /// be an arbitrary string.
+    ///
+    /// Modules of client databases are `client`, `ln`, `mint` and `wallet`.
+    Dump {
+        cfg_dir: PathBuf,
+        #[arg(env = "FM_PASSWORD")]
+        password: String,
+        /// Source of the key the server's private config is encrypted with
+        #[arg(
+            long = "config-key",
+            env = "FM_CONFIG_KEY_SOURCE",
+            default_value = "password"
+        )]
+        config_key: ConfigKeySource,
+        #[arg(required = false)]
+        modules: Option<String>,
+        #[arg(required = false)]
+        prefixes: Option<String>,
+        #[arg(long = "kind", value_enum, default_value = "server")]
+        kind: DbKind,
+        /// File the JSON is written to instead of stdout
+        #[arg(long = "output")]
+        output: Option<PathBuf>,
+    },
+    /// Find entries matching a known corruption pattern. Nothing is changed
+    /// unless `--apply` is set, in which case the entries are written to
+    /// `--backup` before they are deleted.
+    Repair {
+        #[arg(value_enum)]
+        pattern: RepairPattern,
+        /// Config dir of the guardian, required to decode server databases
+        #[arg(long = "cfg-dir")]
+        cfg_dir: Option<PathBuf>,
+        #[arg(long = "password", env = "FM_PASSWORD")]
+        password: Option<String>,
+        /// Source of the key the server's private config is encrypted with
+        #[arg(
+            long = "config-key",
+            env = "FM_CONFIG_KEY_SOURCE",
+            default_value = "password"
+        )]
+        config_key: ConfigKeySource,
+        #[arg(long = "kind", value_enum, default_value = "server")]
+        kind: DbKind,
+        #[arg(long = "apply", requires = "backup")]
+        apply: bool,
+        /// File the deleted entries are written to, it must not exist yet
+        #[arg(long = "backup")]
+        backup: Option<PathBuf>,
+    },
+    /// Delete all entries whose key begins with `prefix`. Nothing is changed
+    /// unless `--apply` is set, in which case the entries are written to
+    /// `--backup` before they are deleted.
+    DeletePrefix {
+        #[arg(value_parser = hex_parser)]
+        prefix: Bytes,
+        #[arg(long = "apply", requires = "backup")]
+        apply: bool,
+        /// File the deleted entries are written to, it must not exist yet
+        #[arg(long = "backup")]
+        backup: Option<PathBuf>,
+    },
+}
+
+fn hex_parser(hex: &str) -> Result<Bytes> {
+    let bytes: Vec<u8> = bitcoin_hashes::hex::FromHex::from_hex(hex)?;
+    Ok(bytes.into())
+}
+
+fn print_kv(key: &[u8], value: &[u8]) {
+    println!("{} {}", key.to_hex(), value.to_hex());
+}
+
+/// Returns the decoders of the modules a guardian was configured with
+async fn server_decoders(
+    module_inits: &ServerModuleGenRegistry,
+    cfg_dir: Option<PathBuf>,
+    password: Option<String>,
+    config_key: &ConfigKeySource,
+) -> Result<ModuleDecoderRegistry> {
+    let Some(cfg_dir) = cfg_dir else {
+        bail!("Decoding a server database requires --cfg-dir");
+    };
+    if password.is_none() && *config_key == ConfigKeySource::Password {
+        bail!("Decoding a server database requires --password or --config-key");
+    }
+    let key = config_key
+        .config_key(&password.unwrap_or_default(), &cfg_dir)
+        .await?;
+    let cfg = read_server_config_with_key(&key, cfg_dir)?;
+    module_inits.decoders(cfg.iter_module_instances())
+}
+
+/// Deletes `entries` if `apply` is set, otherwise only reports what would be
+/// deleted
+async fn delete_guarded(
+    db: &Database,
+    entries: Vec<RawEntry>,
+    apply: bool,
+    backup: Option<PathBuf>,
+) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&entries)?);
+    match backup {
+        Some(backup) if apply && !entries.is_empty() => {
+            let mut dbtx = db.begin_transaction().await;
+            delete_entries(&mut dbtx, &entries, &backup).await?;
+            dbtx.commit_tx().await;
+            eprintln!(
+                "Deleted {} entries, backup written to {}",
+                entries.len(),
+                backup.display()
+            );
+        }
+        _ => eprintln!(
+            "Found {} entries, pass --apply and --backup to delete them",
+            entries.len()
+        ),
+    }
+    Ok(())
+}
+
+/// `dbtool` builder with a custom set of modules
+///
+/// Server databases can only be decoded if the gens of all modules the
+/// guardian was configured with are registered, so binaries of federations
+/// running third party modules need to build their own `dbtool`.
+///
+/// Example:
+///
+/// ```
+/// use fedimint_dbtool::FedimintDbTool;
+/// use fedimint_ln_server::LightningGen;
+/// use fedimint_mint_server::MintGen;
+/// use fedimint_wallet_server::WalletGen;
+///
+/// // Note: not called `main` to avoid rustdoc executing it
+/// // #[tokio::main]
+/// async fn main_() -> anyhow::Result<()> {
+///     FedimintDbTool::new()?
+///         // use `.with_default_modules()` to avoid having
+///         // to import these manually
+///         .with_module(WalletGen)
+///         .with_module(MintGen)
+///         .with_module(LightningGen)
+///         .run()
+///         .await
+/// }
+/// ```
+pub struct FedimintDbTool {
+    module_inits: ServerModuleGenRegistry,
+    options: Options,
+}
+
+impl FedimintDbTool {
+    pub fn new() -> Result<FedimintDbTool> {
+        TracingSetup::default().init()?;
+
+        Ok(Self {
+            module_inits: ServerModuleGenRegistry::new(),
+            options: Options::parse(),
+        })
+    }
+
+    pub fn with_module<T>(mut self, gen: T) -> Self
+    where
+        T: ServerModuleGen + 'static + Send + Sync,
+    {
+        self.module_inits.attach(gen);
+        self
+    }
+
+    pub fn with_default_modules(self) -> Self {
+        self.with_module(LightningGen)
+            .with_module(MintGen)
+            .with_module(WalletGen)
+    }
+
+    pub async fn run(self) -> Result<()> {
+        run(self.options, self.module_inits).await
+    }
+}
+
+async fn run(options: Options, module_inits: ServerModuleGenRegistry) -> Result<()> {
+    match options.command {
+        DbCommand::List { prefix } => {
+            let rocksdb: Box<dyn IDatabase> =
+                Box::new(fedimint_rocksdb::RocksDb::open(&options.database).unwrap());
+            let mut dbtx = rocksdb.begin_transaction().await;
+            let prefix_iter = dbtx
+                .raw_find_by_prefix(&prefix)
+                .await?
+                .collect::<Vec<_>>()
+                .await;
+            for (key, value) in prefix_iter {
+                print_kv(&key, &value);
+            }
+            dbtx.commit_tx().await.expect("Error committing to RocksDb");
+        }
+        DbCommand::Write { key, value } => {
+            let rocksdb: Box<dyn IDatabase> =
+                Box::new(fedimint_rocksdb::RocksDb::open(&options.database).unwrap());
+            let mut dbtx = rocksdb.begin_transaction().await;
+            dbtx.raw_insert_bytes(&key, value.into())
+                .await
+                .expect("Error inserting entry into RocksDb");
+            dbtx.commit_tx().await.expect("Error committing to RocksDb");
+        }
+        DbCommand::Delete { key } => {
+            let rocksdb: Box<dyn IDatabase> =
+                Box::new(fedimint_rocksdb::RocksDb::open(&options.database).unwrap());
+            let mut dbtx = rocksdb.begin_transaction().await;
+            dbtx.raw_remove_entry(&key)
+                .await
+                .expect("Error removing entry from RocksDb");
+            dbtx.commit_tx().await.expect("Error committing to RocksDb");
+        }
+        DbCommand::Dump {
+            cfg_dir,
+            modules,
+            prefixes,
+            password,
+            config_key,
+            kind,
+            output,
+        } => {
+            let modules = match modules {
+                Some(mods) => mods
+                    .split(',')
+                    .map(|s| s.to_string().to_lowercase())
+                    .collect::<Vec<String>>(),
+                None => Vec::new(),
+            };
+
+            let prefix_names = match prefixes {
+                Some(db_prefixes) => db_prefixes
+                    .split(',')
+                    .map(|s| s.to_string().to_lowercase())
+                    .collect::<Vec<String>>(),
+                None => Vec::new(),
+            };
+
+            let mut dbdump = DatabaseDump::new(
+                cfg_dir,
+                options.database,
+                password,
+                config_key,
+                kind,
+                modules,
+                prefix_names,
+                module_inits,
+            )
+            .await;
+            let json = dbdump.dump_database().await;
+            match output {
+                Some(output) => fs::write(output, json)?,
+                None => println!("{json}"),
+            }
+        }
+        DbCommand::Repair {
+            pattern,
+            cfg_dir,
+            password,
+            config_key,
+            kind,
+            apply,
+            backup,
+        } => {
+            let decoders = match kind {
+                DbKind::Server => {
+                    server_decoders(&module_inits, cfg_dir, password, &config_key).await?
+                }
+                DbKind::Client | DbKind::Gateway => ModuleDecoderRegistry::default(),
+            };
+            let db = Database::new(
+                fedimint_rocksdb::RocksDb::open(&options.database)?,
+                decoders.clone(),
+            );
+            let mut dbtx = db.begin_transaction().await;
+            let entries = pattern.find(&mut dbtx, kind, &decoders).await;
+            dbtx.commit_tx().await;
+
+            delete_guarded(&db, entries, apply, backup).await?;
+        }
+        DbCommand::DeletePrefix {
+            prefix,
+            apply,
+            backup,
+        } => {
+            let db = Database::new(
+                fedimint_rocksdb::RocksDb::open(&options.database)?,
+                ModuleDecoderRegistry::default(),
+            );
+            let mut dbtx = db.begin_transaction().await;
+            let entries = dbtx
+                .raw_find_by_prefix(&prefix)
+                .await
+                .into_iter()
+                .map(|(key, value)| RawEntry::new("prefix", &key, &value))
+                .collect();
+            dbtx.commit_tx().await;
+
+            delete_guarded(&db, entries, apply, backup).await?;
+        }
+    }
+
+    Ok(())
+}
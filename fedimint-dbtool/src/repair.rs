@@ -0,0 +1,480 @@
+//! Finds and removes database entries that are known to keep a guardian or
+//! client from starting
+use std::fs;
+use std::path::Path;
+
+use anyhow::{ensure, Context};
+use bitcoin_hashes::hex::ToHex;
+use clap::ValueEnum;
+use fedimint_core::db::{
+    DatabaseKey, DatabaseKeyPrefix, DatabaseLookup, DatabaseRecord, DatabaseTransaction,
+    DatabaseValue,
+};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_server::db as ConsensusRange;
+use mint_client::db as ClientRange;
+use mint_client::ln::db as ClientLightningRange;
+use mint_client::mint::db as ClientMintRange;
+use mint_client::wallet::db as ClientWalletRange;
+use serde::{Deserialize, Serialize};
+
+use crate::DbKind;
+
+/// Kind of broken entries `repair` looks for
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum RepairPattern {
+    /// Entries of the consensus or client ranges whose key or value can't be
+    /// decoded, e.g. after downgrading from a version with another encoding.
+    /// Reading them panics, so the guardian or client can't start.
+    Undecodable,
+}
+
+/// An undecoded entry, deleted entries are written to the backup in this form
+/// so they can be restored with the `write` command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawEntry {
+    /// Name of the range the entry was found in
+    pub range: String,
+    pub key: String,
+    pub value: String,
+}
+
+impl RawEntry {
+    pub fn new(range: &str, key: &[u8], value: &[u8]) -> Self {
+        RawEntry {
+            range: range.to_string(),
+            key: key.to_hex(),
+            value: value.to_hex(),
+        }
+    }
+}
+
+impl RepairPattern {
+    /// Returns the entries matching the pattern in a database of `kind`
+    pub async fn find(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        kind: DbKind,
+        decoders: &ModuleDecoderRegistry,
+    ) -> Vec<RawEntry> {
+        match self {
+            RepairPattern::Undecodable => match kind {
+                DbKind::Server => find_undecodable_consensus(dbtx, decoders).await,
+                DbKind::Client | DbKind::Gateway => find_undecodable_client(dbtx, decoders).await,
+            },
+        }
+    }
+}
+
+/// Writes `entries` to `backup` and deletes them, refuses to overwrite an
+/// existing backup
+pub async fn delete_entries(
+    dbtx: &mut DatabaseTransaction<'_>,
+    entries: &[RawEntry],
+    backup: &Path,
+) -> anyhow::Result<()> {
+    ensure!(
+        !backup.exists(),
+        "Backup {} exists already, refusing to overwrite it",
+        backup.display()
+    );
+    fs::write(backup, serde_json::to_string_pretty(entries)?)
+        .with_context(|| format!("Unable to write backup {}", backup.display()))?;
+
+    for entry in entries {
+        let key: Vec<u8> = bitcoin_hashes::hex::FromHex::from_hex(&entry.key)?;
+        dbtx.raw_remove_entry(&key).await;
+    }
+    Ok(())
+}
+
+/// Returns the entries starting with `prefix` that don't decode as records of
+/// the prefix
+async fn undecodable<KP>(
+    dbtx: &mut DatabaseTransaction<'_>,
+    prefix: &KP,
+    range: &str,
+    decoders: &ModuleDecoderRegistry,
+) -> Vec<RawEntry>
+where
+    KP: DatabaseLookup,
+    KP::Record: DatabaseKey,
+{
+    dbtx.raw_find_by_prefix(&prefix.to_bytes())
+        .await
+        .into_iter()
+        .filter(|(key, value)| {
+            <KP::Record as DatabaseKey>::from_bytes(key, decoders).is_err()
+                || <<KP::Record as DatabaseRecord>::Value as DatabaseValue>::from_bytes(
+                    value, decoders,
+                )
+                .is_err()
+        })
+        .map(|(key, value)| RawEntry::new(range, &key, &value))
+        .collect()
+}
+
+/// Entries of the module ranges are not checked, since their records are only
+/// known to the modules
+async fn find_undecodable_consensus(
+    dbtx: &mut DatabaseTransaction<'_>,
+    decoders: &ModuleDecoderRegistry,
+) -> Vec<RawEntry> {
+    let mut found = vec![];
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::AcceptedTransactionKeyPrefix,
+            "AcceptedTransaction",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::DropPeerKeyPrefix,
+            "DropPeer",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::RejectedTransactionKeyPrefix,
+            "RejectedTransaction",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::EpochHistoryKeyPrefix,
+            "EpochHistory",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(undecodable(dbtx, &ConsensusRange::LastEpochKey, "LastEpoch", decoders).await);
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::ClientConfigSignatureKeyPrefix,
+            "ClientConfigSignature",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::ConsensusUpgradeKey,
+            "ConsensusUpgrade",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::PeerSetChangeVoteKeyPrefix,
+            "PeerSetChangeVote",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::ApprovedPeerSetChangeKey,
+            "ApprovedPeerSetChange",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::EpochCheckpointKeyPrefix,
+            "EpochCheckpoint",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::EarliestEpochKey,
+            "EarliestEpoch",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::PeerConsensusVersionKeyPrefix,
+            "PeerConsensusVersion",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::ConsensusVersionVoteKeyPrefix,
+            "ConsensusVersionVote",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::ScheduledConsensusVersionKeyPrefix,
+            "ScheduledConsensusVersion",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::StateSnapshotKey,
+            "StateSnapshot",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::ConsensusParamsVoteKeyPrefix,
+            "ConsensusParamsVote",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ConsensusRange::ConsensusParamsKey,
+            "ConsensusParams",
+            decoders,
+        )
+        .await,
+    );
+    found
+}
+
+async fn find_undecodable_client(
+    dbtx: &mut DatabaseTransaction<'_>,
+    decoders: &ModuleDecoderRegistry,
+) -> Vec<RawEntry> {
+    let mut found = vec![];
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientRange::ClientSecretKey,
+            "ClientSecret",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientRange::ClientMnemonicKey,
+            "ClientMnemonic",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientRange::OperationKeyPrefix,
+            "Operation",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::ConfirmedInvoiceKeyPrefix,
+            "ConfirmedInvoice",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::LightningGatewayKeyPrefix,
+            "LightningGateway",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::OutgoingContractAccountKeyPrefix,
+            "OutgoingContractAccount",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::OutgoingPaymentKeyPrefix,
+            "OutgoingPayment",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::OutgoingPaymentClaimKeyPrefix,
+            "OutgoingPaymentClaim",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::LightningAddressKeyPrefix,
+            "LightningAddress",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::LnurlPaymentKeyPrefix,
+            "LnurlPayment",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::EcashReserveKeyPrefix,
+            "EcashReserve",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::HtlcAmountBandKeyPrefix,
+            "HtlcAmountBand",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientLightningRange::PreimagePurchaseKeyPrefix,
+            "PreimagePurchase",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(undecodable(dbtx, &ClientMintRange::NoteKeyPrefix, "Note", decoders).await);
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientMintRange::OutputFinalizationKeyPrefix,
+            "OutputFinalizationData",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientMintRange::PendingNotesKeyPrefix,
+            "PendingNotes",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientMintRange::NextECashNoteIndexKeyPrefix,
+            "NextECashNoteIndex",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(
+        undecodable(
+            dbtx,
+            &ClientMintRange::NotesPerDenominationKey,
+            "NotesPerDenomination",
+            decoders,
+        )
+        .await,
+    );
+    found.extend(undecodable(dbtx, &ClientWalletRange::PegInPrefixKey, "PegIn", decoders).await);
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, DatabaseKeyPrefix};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_server::db::{LastEpochKey, RejectedTransactionKeyPrefix};
+
+    use super::{delete_entries, RepairPattern};
+    use crate::DbKind;
+
+    #[tokio::test]
+    async fn finds_and_deletes_undecodable_entries() {
+        let decoders = ModuleDecoderRegistry::default();
+        let db = Database::new(MemDatabase::new(), decoders.clone());
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&LastEpochKey, &fedimint_server::db::EpochHistoryKey(3))
+            .await;
+        // a rejected transaction whose key is missing most of the txid
+        dbtx.raw_insert_bytes(&[0x04, 0xab], b"\x05error".to_vec())
+            .await;
+        dbtx.commit_tx().await;
+
+        let mut dbtx = db.begin_transaction().await;
+        let found = RepairPattern::Undecodable
+            .find(&mut dbtx, DbKind::Server, &decoders)
+            .await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].range, "RejectedTransaction");
+        assert_eq!(found[0].key, "04ab");
+
+        let dir = tempfile::tempdir().unwrap();
+        let backup = dir.path().join("backup.json");
+        delete_entries(&mut dbtx, &found, &backup).await.unwrap();
+        // an existing backup is never overwritten
+        assert!(delete_entries(&mut dbtx, &found, &backup).await.is_err());
+        dbtx.commit_tx().await;
+
+        let mut dbtx = db.begin_transaction().await;
+        assert!(dbtx
+            .raw_find_by_prefix(&RejectedTransactionKeyPrefix.to_bytes())
+            .await
+            .is_empty());
+        assert!(dbtx.get_value(&LastEpochKey).await.is_some());
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Outcome of running a workload
+#[derive(Debug, Serialize)]
+pub struct LoadTestReport {
+    pub workload: String,
+    pub operations: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub duration_secs: f64,
+    /// Succeeded operations per second
+    pub throughput: f64,
+    /// Latencies of the succeeded operations, `None` if none succeeded
+    pub latency_ms: Option<LatencyPercentiles>,
+    /// Number of failed operations by the step they failed in
+    pub failures: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LatencyPercentiles {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+    pub mean: u64,
+}
+
+impl LoadTestReport {
+    pub fn new(
+        workload: &str,
+        outcomes: Vec<anyhow::Result<Duration>>,
+        duration: Duration,
+    ) -> LoadTestReport {
+        let operations = outcomes.len();
+        let mut latencies = vec![];
+        let mut failures = BTreeMap::new();
+        for outcome in outcomes {
+            match outcome {
+                Ok(latency) => latencies.push(latency),
+                Err(e) => *failures.entry(e.to_string()).or_default() += 1,
+            }
+        }
+
+        LoadTestReport {
+            workload: workload.to_string(),
+            operations,
+            succeeded: latencies.len(),
+            failed: operations - latencies.len(),
+            duration_secs: duration.as_secs_f64(),
+            throughput: latencies.len() as f64 / duration.as_secs_f64().max(f64::EPSILON),
+            latency_ms: LatencyPercentiles::new(latencies),
+            failures,
+        }
+    }
+}
+
+impl LatencyPercentiles {
+    pub fn new(mut latencies: Vec<Duration>) -> Option<LatencyPercentiles> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+
+        let millis = |latency: &Duration| latency.as_millis() as u64;
+        // nearest-rank percentile
+        let percentile = |p: usize| millis(&latencies[(p * latencies.len() + 99) / 100 - 1]);
+        let total: Duration = latencies.iter().sum();
+
+        Some(LatencyPercentiles {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: millis(latencies.last().expect("not empty")),
+            mean: millis(&(total / latencies.len() as u32)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+
+    use super::{LatencyPercentiles, LoadTestReport};
+
+    #[test]
+    fn report_has_percentiles_and_failure_breakdown() {
+        let mut outcomes: Vec<_> = (1..=100)
+            .rev()
+            .map(|ms| Ok(Duration::from_millis(ms)))
+            .collect();
+        outcomes.push(Err(anyhow!("reissue")));
+        outcomes.push(Err(anyhow!("reissue")));
+        outcomes.push(Err(anyhow!("timeout")));
+
+        let report = LoadTestReport::new("reissue", outcomes, Duration::from_secs(10));
+        assert_eq!(report.operations, 103);
+        assert_eq!(report.succeeded, 100);
+        assert_eq!(report.failed, 3);
+        assert_eq!(report.throughput, 10.0);
+        assert_eq!(report.failures["reissue"], 2);
+        assert_eq!(report.failures["timeout"], 1);
+        assert_eq!(
+            report.latency_ms,
+            Some(LatencyPercentiles {
+                p50: 50,
+                p90: 90,
+                p99: 99,
+                max: 100,
+                mean: 50,
+            })
+        );
+
+        assert_eq!(LatencyPercentiles::new(vec![]), None);
+        assert_eq!(
+            LatencyPercentiles::new(vec![Duration::from_millis(7)]).map(|l| l.p99),
+            Some(7)
+        );
+    }
+}
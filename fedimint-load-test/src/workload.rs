@@ -0,0 +1,187 @@
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use bitcoincore_rpc::RpcApi;
+use fedimint_core::encoding::Decodable;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::sleep;
+use fedimint_core::Amount;
+use futures::{Future, StreamExt};
+use lightning_invoice::Invoice;
+use mint_client::modules::wallet::txoproof::TxOutProof;
+use mint_client::{Client, UserClientConfig};
+use rand::rngs::OsRng;
+use tracing::{debug, warn};
+
+/// How often bitcoind is polled while waiting for a peg-in to confirm
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Runs `operations` operations built by `operation`, at most `concurrency` at
+/// the same time, returns how long each one took or why it failed
+///
+/// Operations running for longer than `timeout` fail with `timeout`.
+pub async fn run<F, Fut>(
+    operations: usize,
+    concurrency: usize,
+    timeout: Duration,
+    operation: F,
+) -> Vec<anyhow::Result<Duration>>
+where
+    F: Fn(usize) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    futures::stream::iter(0..operations)
+        .map(|idx| {
+            let operation = operation(idx);
+            async move {
+                let start = Instant::now();
+                let outcome = match fedimint_core::task::timeout(timeout, operation).await {
+                    Ok(Ok(())) => Ok(start.elapsed()),
+                    Ok(Err(e)) => Err(e),
+                    Err(_) => Err(anyhow!("timeout")),
+                };
+                match &outcome {
+                    Ok(latency) => debug!(idx, ?latency, "Operation succeeded"),
+                    Err(e) => warn!(idx, "Operation failed: {e:#}"),
+                }
+                outcome
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Spends notes of `amount` and reissues them, until the reissued notes were
+/// fetched
+pub async fn reissue(client: &Client<UserClientConfig>, amount: Amount) -> anyhow::Result<()> {
+    let notes = client.spend_ecash(amount, OsRng).await.context("spend")?;
+    let outpoint = client.reissue(notes, OsRng).await.context("reissue")?;
+    client
+        .await_transaction_notes(outpoint.txid)
+        .await
+        .context("await notes")?;
+    Ok(())
+}
+
+/// Pays `invoice` through the federation's active gateway, until the gateway
+/// claimed the outgoing contract
+pub async fn ln_pay(client: &Client<UserClientConfig>, invoice: Invoice) -> anyhow::Result<()> {
+    let (contract_id, outpoint) = client
+        .fund_outgoing_ln_contract(invoice, OsRng)
+        .await
+        .context("fund contract")?;
+    client
+        .await_outgoing_contract_acceptance(outpoint)
+        .await
+        .context("contract acceptance")?;
+    client
+        .await_outgoing_contract_execution(contract_id, OsRng)
+        .await
+        .context("gateway payment")?;
+    Ok(())
+}
+
+/// Sends `amount` from bitcoind's wallet to a new peg-in address and pegs it
+/// in, until the issued notes were fetched
+///
+/// Blocks have to be mined for the peg-in to confirm, see [`mine_blocks`].
+pub async fn peg_in(
+    client: &Client<UserClientConfig>,
+    bitcoind: &Arc<bitcoincore_rpc::Client>,
+    amount: bitcoin::Amount,
+) -> anyhow::Result<()> {
+    let address = client.get_new_pegin_address(OsRng).await;
+    let txid = rpc(bitcoind, move |bitcoind| {
+        bitcoind.send_to_address(&address, amount, None, None, None, None, None, None)
+    })
+    .await
+    .context("send to address")?;
+
+    let block_height = loop {
+        let info = rpc(bitcoind, move |bitcoind| {
+            bitcoind.get_raw_transaction_info(&txid, None)
+        })
+        .await
+        .context("confirmation")?;
+        if let Some(block_hash) = info.blockhash {
+            let header = rpc(bitcoind, move |bitcoind| {
+                bitcoind.get_block_header_info(&block_hash)
+            })
+            .await
+            .context("confirmation")?;
+            break header.height as u64;
+        }
+        sleep(CONFIRMATION_POLL_INTERVAL).await;
+    };
+
+    let proof = rpc(bitcoind, move |bitcoind| {
+        bitcoind.get_tx_out_proof(&[txid], None)
+    })
+    .await
+    .context("txout proof")?;
+    let proof =
+        TxOutProof::consensus_decode(&mut Cursor::new(proof), &ModuleDecoderRegistry::default())
+            .context("txout proof")?;
+    let transaction = rpc(bitcoind, move |bitcoind| {
+        bitcoind.get_raw_transaction(&txid, None)
+    })
+    .await
+    .context("txout proof")?;
+
+    // the federation only accepts the peg-in once it reached finality, waiting
+    // for it times out every 30 seconds
+    while client
+        .await_consensus_block_height(block_height)
+        .await
+        .is_err()
+    {
+        debug!(
+            block_height,
+            "Federation didn't reach the peg-in's block yet"
+        );
+    }
+
+    let txid = client
+        .peg_in(proof, transaction, OsRng)
+        .await
+        .context("peg-in")?;
+    client
+        .await_transaction_notes(txid)
+        .await
+        .context("await notes")?;
+    Ok(())
+}
+
+/// Mines a block every `interval` until the returned task is aborted, which
+/// only works on regtest
+pub fn mine_blocks(
+    bitcoind: Arc<bitcoincore_rpc::Client>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let mined = rpc(&bitcoind, |bitcoind| {
+                let address = bitcoind.get_new_address(None, None)?;
+                bitcoind.generate_to_address(1, &address)
+            })
+            .await;
+            if let Err(e) = mined {
+                warn!("Failed to mine block: {e:#}");
+            }
+            sleep(interval).await;
+        }
+    })
+}
+
+/// Runs a blocking bitcoind RPC call without blocking the other operations
+async fn rpc<T, F>(bitcoind: &Arc<bitcoincore_rpc::Client>, call: F) -> anyhow::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&bitcoincore_rpc::Client) -> bitcoincore_rpc::Result<T> + Send + 'static,
+{
+    let bitcoind = bitcoind.clone();
+    Ok(tokio::task::spawn_blocking(move || call(&bitcoind)).await??)
+}
@@ -1,8 +1,13 @@
 use std::io;
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::time::Duration;
 
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer};
 
@@ -19,15 +24,70 @@ pub const LOG_WALLET: &str = "wallet";
 pub const LOG_TEST: &str = "test";
 pub const LOG_ECASH_RECOVERY: &str = "ecash-recovery";
 
+/// Format of the log lines written to stderr
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, carrying the fields of all enclosing spans
+    /// such as `epoch`, `peer_id`, `module` and `txid`
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => bail!("Unknown log format {other}, expected text or json"),
+        }
+    }
+}
+
+/// Layer writing the log lines in `format` to `writer`
+fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync + 'static>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
 /// Consolidates the setup of server tracing into a helper
 #[derive(Default)]
 pub struct TracingSetup {
+    log_format: LogFormat,
+    default_filter: Option<String>,
     tokio_console_bind: Option<SocketAddr>,
     with_jaeger: bool,
     with_chrome: bool,
 }
 
 impl TracingSetup {
+    /// Format of the log lines written to stderr
+    pub fn log_format(&mut self, format: LogFormat) -> &mut Self {
+        self.log_format = format;
+        self
+    }
+
+    /// Filter applied if `RUST_LOG` is not set, `info` by default
+    pub fn default_filter(&mut self, filter: &str) -> &mut Self {
+        self.default_filter = Some(filter.to_string());
+        self
+    }
+
     /// Setup a console server for tokio logging <https://docs.rs/console-subscriber>
     #[cfg(feature = "telemetry")]
     pub fn tokio_console_bind(&mut self, address: Option<SocketAddr>) -> &mut Self {
@@ -51,11 +111,9 @@ impl TracingSetup {
 
     /// Initialize the logging, must be called for tracing to begin
     pub fn init(&self) -> anyhow::Result<()> {
-        let filter_layer =
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-        let fmt_layer = tracing_subscriber::fmt::layer()
-            .with_writer(io::stderr)
-            .with_filter(filter_layer);
+        let filter_layer = EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new(self.default_filter.as_deref().unwrap_or("info")));
+        let fmt_layer = fmt_layer(self.log_format, io::stderr).with_filter(filter_layer);
 
         let console_opt = || -> Option<Box<dyn Layer<_> + Send + Sync + 'static>> {
             #[cfg(feature = "telemetry")]
@@ -108,3 +166,83 @@ impl TracingSetup {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::{fmt_layer, LogFormat};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_lines(format: LogFormat) -> String {
+        let buf = SharedBuf::default();
+        let writer = buf.clone();
+        let subscriber =
+            tracing_subscriber::registry().with(fmt_layer(format, move || writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _peer = info_span!("peer", peer_id = 2).entered();
+            let _module = info_span!("module", module = "mint").entered();
+            let _tx = info_span!("tx", txid = "deadbeef").entered();
+            tracing::info!(amount = 42, "Processed input");
+        });
+
+        let bytes = buf.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn json_logs_carry_the_fields_of_all_spans() {
+        let lines = log_lines(LogFormat::Json);
+        let line: serde_json::Value =
+            serde_json::from_str(lines.lines().next().expect("one line logged")).unwrap();
+
+        assert_eq!(line["fields"]["message"], "Processed input");
+        assert_eq!(line["fields"]["amount"], 42);
+
+        let spans = line["spans"].as_array().expect("span list is logged");
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[0]["peer_id"], 2);
+        assert_eq!(spans[1]["module"], "mint");
+        assert_eq!(spans[2]["txid"], "deadbeef");
+        assert!(line.get("span").is_none());
+    }
+
+    #[test]
+    fn text_logs_are_not_json() {
+        let lines = log_lines(LogFormat::Text);
+        assert!(lines.contains("Processed input"));
+        assert!(lines.contains("txid"));
+        assert!(serde_json::from_str::<serde_json::Value>(lines.trim()).is_err());
+    }
+
+    #[test]
+    fn log_format_parses_from_cli_and_config() {
+        assert_eq!(LogFormat::from_str("json").unwrap(), LogFormat::Json);
+        assert_eq!(LogFormat::from_str("text").unwrap(), LogFormat::Text);
+        assert!(LogFormat::from_str("yaml").is_err());
+
+        assert_eq!(
+            serde_json::from_str::<LogFormat>("\"json\"").unwrap(),
+            LogFormat::Json
+        );
+        assert!(serde_json::from_str::<LogFormat>("\"Json\"").is_err());
+    }
+}
@@ -3,8 +3,9 @@ use std::path::Path;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use fedimint_core::db::watch::{KeyMutation, PrefixWatchers};
 use fedimint_core::db::{
-    IDatabase, IDatabaseTransaction, ISingleUseDatabaseTransaction, PrefixStream,
+    IDatabase, IDatabaseTransaction, ISingleUseDatabaseTransaction, MutationStream, PrefixStream,
     SingleUseDatabaseTransaction,
 };
 use futures::stream;
@@ -13,21 +14,32 @@ use rocksdb::{OptimisticTransactionDB, OptimisticTransactionOptions, WriteOption
 use tracing::warn;
 
 #[derive(Debug)]
-pub struct RocksDb(rocksdb::OptimisticTransactionDB);
+pub struct RocksDb {
+    db: rocksdb::OptimisticTransactionDB,
+    watchers: PrefixWatchers,
+}
 
 pub struct RocksDbReadOnly(rocksdb::DB);
 
-pub struct RocksDbTransaction<'a>(rocksdb::Transaction<'a, rocksdb::OptimisticTransactionDB>);
+pub struct RocksDbTransaction<'a> {
+    tx: rocksdb::Transaction<'a, rocksdb::OptimisticTransactionDB>,
+    watchers: &'a PrefixWatchers,
+    /// Mutations published to the watchers once the transaction is committed
+    mutations: Vec<KeyMutation>,
+    /// Number of mutations when each savepoint was set, RocksDB keeps a stack
+    /// of savepoints and rolling back pops the latest one
+    savepoints: Vec<usize>,
+}
 
 impl RocksDb {
     pub fn open(db_path: impl AsRef<Path>) -> Result<RocksDb, rocksdb::Error> {
         let db: rocksdb::OptimisticTransactionDB =
             rocksdb::OptimisticTransactionDB::<rocksdb::SingleThreaded>::open_default(&db_path)?;
-        Ok(RocksDb(db))
+        Ok(db.into())
     }
 
     pub fn inner(&self) -> &rocksdb::OptimisticTransactionDB {
-        &self.0
+        &self.db
     }
 }
 
@@ -41,13 +53,16 @@ impl RocksDbReadOnly {
 
 impl From<rocksdb::OptimisticTransactionDB> for RocksDb {
     fn from(db: OptimisticTransactionDB) -> Self {
-        RocksDb(db)
+        RocksDb {
+            db,
+            watchers: PrefixWatchers::new(),
+        }
     }
 }
 
 impl From<RocksDb> for rocksdb::OptimisticTransactionDB {
     fn from(db: RocksDb) -> Self {
-        db.0
+        db.db
     }
 }
 
@@ -56,34 +71,60 @@ impl IDatabase for RocksDb {
     async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>> {
         let mut optimistic_options = OptimisticTransactionOptions::default();
         optimistic_options.set_snapshot(true);
-        let mut rocksdb_tx = RocksDbTransaction(
-            self.0
+        let mut rocksdb_tx = RocksDbTransaction {
+            tx: self
+                .db
                 .transaction_opt(&WriteOptions::default(), &optimistic_options),
-        );
+            watchers: &self.watchers,
+            mutations: vec![],
+            savepoints: vec![],
+        };
         rocksdb_tx.set_tx_savepoint().await;
         let single_use = SingleUseDatabaseTransaction::new(rocksdb_tx);
         Box::new(single_use)
     }
+
+    fn size(&self) -> Option<u64> {
+        self.db
+            .property_int_value("rocksdb.total-sst-files-size")
+            .ok()
+            .flatten()
+    }
+
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        rocksdb::checkpoint::Checkpoint::new(&self.db)?.create_checkpoint(path)?;
+        Ok(())
+    }
+
+    fn watch_prefix(&self, key_prefix: &[u8]) -> Result<MutationStream> {
+        Ok(self.watchers.watch(key_prefix))
+    }
 }
 
 #[async_trait]
 impl<'a> IDatabaseTransaction<'a> for RocksDbTransaction<'a> {
     async fn raw_insert_bytes(&mut self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
         fedimint_core::task::block_in_place(|| {
-            let val = self.0.get(key).unwrap();
-            self.0.put(key, value)?;
+            let val = self.tx.get(key).unwrap();
+            self.tx.put(key, &value)?;
+            self.mutations.push(KeyMutation::Insert {
+                key: key.to_vec(),
+                value,
+            });
             Ok(val)
         })
     }
 
     async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        fedimint_core::task::block_in_place(|| Ok(self.0.snapshot().get(key)?))
+        fedimint_core::task::block_in_place(|| Ok(self.tx.snapshot().get(key)?))
     }
 
     async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         fedimint_core::task::block_in_place(|| {
-            let val = self.0.get(key).unwrap();
-            self.0.delete(key)?;
+            let val = self.tx.get(key).unwrap();
+            self.tx.delete(key)?;
+            self.mutations
+                .push(KeyMutation::Remove { key: key.to_vec() });
             Ok(val)
         })
     }
@@ -93,7 +134,7 @@ impl<'a> IDatabaseTransaction<'a> for RocksDbTransaction<'a> {
             let prefix = key_prefix.to_vec();
             let mut options = rocksdb::ReadOptions::default();
             options.set_iterate_range(rocksdb::PrefixRange(prefix.clone()));
-            let iter = self.0.snapshot().iterator_opt(
+            let iter = self.tx.snapshot().iterator_opt(
                 rocksdb::IteratorMode::From(&prefix, rocksdb::Direction::Forward),
                 options,
             );
@@ -113,14 +154,19 @@ impl<'a> IDatabaseTransaction<'a> for RocksDbTransaction<'a> {
 
     async fn commit_tx(self) -> Result<()> {
         fedimint_core::task::block_in_place(|| {
-            self.0.commit()?;
+            self.tx.commit()?;
+            self.watchers.publish(&self.mutations);
             Ok(())
         })
     }
 
     async fn rollback_tx_to_savepoint(&mut self) {
-        fedimint_core::task::block_in_place(|| match self.0.rollback_to_savepoint() {
-            Ok(()) => {}
+        fedimint_core::task::block_in_place(|| match self.tx.rollback_to_savepoint() {
+            Ok(()) => {
+                if let Some(num_mutations) = self.savepoints.pop() {
+                    self.mutations.truncate(num_mutations);
+                }
+            }
             _ => {
                 warn!("Rolling back database transaction without a set savepoint");
             }
@@ -129,7 +175,8 @@ impl<'a> IDatabaseTransaction<'a> for RocksDbTransaction<'a> {
 
     async fn set_tx_savepoint(&mut self) {
         fedimint_core::task::block_in_place(|| {
-            self.0.set_savepoint();
+            self.tx.set_savepoint();
+            self.savepoints.push(self.mutations.len());
         })
     }
 }
@@ -272,6 +319,19 @@ mod fedimint_rocksdb_tests {
             .await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_watch_prefix() {
+        fedimint_core::db::verify_watch_prefix(open_temp_db("fcb-rocksdb-test-watch-prefix")).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_autocommit_retries_conflicts() {
+        fedimint_core::db::verify_autocommit_retries_conflicts(open_temp_db(
+            "fcb-rocksdb-test-autocommit-conflicts",
+        ))
+        .await;
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_dbtx_remove_by_prefix() {
         fedimint_core::db::verify_remove_by_prefix(open_temp_db(
@@ -0,0 +1,249 @@
+//! Archive of a federation's epoch history run by a third party
+//!
+//! The archive downloads the epochs the guardians signed and serves them with
+//! the endpoints of guardians with the archive role, so clients can recover
+//! from epochs every guardian pruned. Epochs are threshold signed, so clients
+//! don't have to trust the archive.
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use fedimint_core::api::{DynFederationApi, GlobalFederationApi};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::Database;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::epoch::{
+    EpochArchiveInfo, EpochVerifyError, SerdeEpochHistory, SignedEpochOutcome,
+};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::{
+    api_endpoint, ApiAuthTier, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased,
+};
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_logging::LOG_NET_API;
+use jsonrpsee::RpcModule;
+use threshold_crypto::PublicKey;
+use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+
+use crate::config::ApiLimits;
+use crate::db::{EarliestEpochKey, EpochHistoryKey, LastEpochKey};
+use crate::net::api::{
+    attach_endpoints, loopback_ephemeral, start_server, HasApiContext, RpcHandlerCtx,
+};
+use crate::net::front::serve_api_front;
+use crate::net::limits::{RequestLimiter, SourceLimiter};
+use crate::net::sessions::SubscriptionTracker;
+
+/// Epoch history of a federation kept by a third party
+pub struct EpochArchive {
+    db: Database,
+    api: DynFederationApi,
+    epoch_pk: PublicKey,
+    decoders: ModuleDecoderRegistry,
+}
+
+impl EpochArchive {
+    pub fn new(
+        db: Database,
+        api: DynFederationApi,
+        epoch_pk: PublicKey,
+        decoders: ModuleDecoderRegistry,
+    ) -> Self {
+        Self {
+            db,
+            api,
+            epoch_pk,
+            decoders,
+        }
+    }
+
+    /// Returns the epoch history we keep, `None` until the first sync
+    pub async fn info(&self) -> Option<EpochArchiveInfo> {
+        let mut dbtx = self.db.begin_transaction().await;
+        let last_epoch = dbtx.get_value(&LastEpochKey).await?;
+        let earliest_epoch = dbtx.get_value(&EarliestEpochKey).await.unwrap_or(0);
+        Some(EpochArchiveInfo {
+            earliest_epoch,
+            epoch_count: last_epoch.0 + 1,
+        })
+    }
+
+    pub async fn epoch_history(&self, epoch: u64) -> Option<SignedEpochOutcome> {
+        self.db
+            .begin_transaction()
+            .await
+            .get_value(&EpochHistoryKey(epoch))
+            .await
+    }
+
+    /// Downloads the epochs the federation finished since the last sync,
+    /// starting at `first_epoch` if we didn't archive any yet
+    ///
+    /// Every epoch has to be signed by the federation and has to follow the
+    /// previous one, so a malicious guardian can't make us archive forged
+    /// epochs. Returns the number of epochs we archived.
+    pub async fn sync(&self, first_epoch: u64) -> anyhow::Result<u64> {
+        let epoch_count = self.api.fetch_epoch_count().await?;
+        let mut prev_epoch = match self.info().await {
+            Some(info) => Some(
+                self.epoch_history(info.epoch_count - 1)
+                    .await
+                    .context("Last archived epoch is missing")?,
+            ),
+            None => None,
+        };
+        let mut next_epoch = prev_epoch
+            .as_ref()
+            .map_or(first_epoch, |epoch| epoch.outcome.epoch + 1);
+
+        // the signature of the last epoch is only added once the next epoch is
+        // finished
+        let mut archived = 0;
+        while next_epoch + 1 < epoch_count {
+            let epoch = match self
+                .api
+                .fetch_epoch_history(next_epoch, self.epoch_pk, &self.decoders)
+                .await
+            {
+                Ok(epoch) => epoch,
+                // the guardians might have pruned the epoch, which their archives keep
+                Err(e) => self
+                    .api
+                    .fetch_archived_epoch_history(next_epoch, self.epoch_pk, &self.decoders)
+                    .await
+                    .map_err(|_| e)?,
+            };
+            let verified = match &prev_epoch {
+                // nothing to chain the first archived epoch to
+                None if next_epoch > 0 => {
+                    if epoch.outcome.consensus_hash().ok() == Some(epoch.hash) {
+                        epoch.verify_sig(&self.epoch_pk)
+                    } else {
+                        Err(EpochVerifyError::InvalidEpochHash)
+                    }
+                }
+                _ => epoch
+                    .verify_hash(&prev_epoch)
+                    .and_then(|_| epoch.verify_sig(&self.epoch_pk)),
+            };
+            if let Err(e) = verified {
+                bail!("Invalid epoch {next_epoch}: {e:?}");
+            }
+
+            let mut dbtx = self.db.begin_transaction().await;
+            if prev_epoch.is_none() {
+                dbtx.insert_entry(&EarliestEpochKey, &next_epoch).await;
+            }
+            dbtx.insert_entry(&EpochHistoryKey(next_epoch), &epoch)
+                .await;
+            dbtx.insert_entry(&LastEpochKey, &EpochHistoryKey(next_epoch))
+                .await;
+            dbtx.commit_tx_result().await?;
+
+            prev_epoch = Some(epoch);
+            next_epoch += 1;
+            archived += 1;
+        }
+        Ok(archived)
+    }
+}
+
+#[async_trait]
+impl HasApiContext<EpochArchive> for EpochArchive {
+    async fn context(
+        &self,
+        _request: &ApiRequestErased,
+        id: Option<ModuleInstanceId>,
+    ) -> (&EpochArchive, ApiEndpointContext<'_>) {
+        (
+            self,
+            ApiEndpointContext::new(ApiAuthTier::Public, self.db.begin_transaction().await, id),
+        )
+    }
+}
+
+/// The endpoints of guardians with the archive role
+fn archive_endpoints() -> Vec<ApiEndpoint<EpochArchive>> {
+    vec![
+        api_endpoint! {
+            "/fetch_archived_epoch_history",
+            ApiAuthTier::Public,
+            async |archive: &EpochArchive, _context, epoch: u64| -> SerdeEpochHistory {
+                let epoch = archive.epoch_history(epoch).await.ok_or_else(|| ApiError::not_found(String::from("epoch not found")))?;
+                Ok((&epoch).into())
+            }
+        },
+        api_endpoint! {
+            "/fetch_epoch_archive_info",
+            ApiAuthTier::Public,
+            async |archive: &EpochArchive, _context, _v: ()| -> Option<EpochArchiveInfo> {
+                Ok(archive.info().await)
+            }
+        },
+    ]
+}
+
+/// Serves `archive` on `bind` and syncs it with the federation every
+/// `sync_interval` until shutdown
+pub async fn run_epoch_archive(
+    archive: EpochArchive,
+    bind: SocketAddr,
+    max_connections: u32,
+    limits: &ApiLimits,
+    first_epoch: u64,
+    sync_interval: Duration,
+    task_group: &mut TaskGroup,
+) -> anyhow::Result<()> {
+    let archive = Arc::new(archive);
+
+    let sync_archive = archive.clone();
+    task_group
+        .spawn("epoch-archive-sync", move |handle| async move {
+            while !handle.is_shutting_down() {
+                match sync_archive.sync(first_epoch).await {
+                    Ok(0) => {}
+                    Ok(archived) => info!(target: LOG_NET_API, archived, "Archived epochs"),
+                    Err(e) => warn!(target: LOG_NET_API, err = %e, "Could not sync the archive"),
+                }
+                sleep(sync_interval).await;
+            }
+        })
+        .await;
+
+    let mut rpc_module = RpcModule::new(RpcHandlerCtx {
+        rpc_context: archive,
+        limiter: Arc::new(RequestLimiter::new(limits)),
+        sessions: Arc::new(SubscriptionTracker::new(Duration::from_secs(
+            limits.subscription_idle_timeout_secs,
+        ))),
+        signer: None,
+    });
+    attach_endpoints(&mut rpc_module, archive_endpoints(), None);
+
+    // like the guardians' API the connections go through the front enforcing
+    // the per source limits
+    let (target, server_handle) = start_server(
+        loopback_ephemeral(bind),
+        max_connections,
+        limits,
+        rpc_module,
+    )
+    .await;
+    let listener = match TcpListener::bind(bind).await {
+        Ok(listener) => listener,
+        Err(e) => bail!("Could not bind the archive API to {bind}: {e}"),
+    };
+    let shutdown_rx = task_group.make_handle().make_shutdown_rx().await;
+    let source_limiter = SourceLimiter::new(limits);
+    info!(target: LOG_NET_API, %bind, "Serving the epoch archive");
+    if let Err(e) = serve_api_front(listener, target, source_limiter, shutdown_rx).await {
+        error!(target: LOG_NET_API, err = %e, "Could not serve the archive API");
+    }
+
+    let _ = server_handle.stop();
+    server_handle.stopped().await;
+    Ok(())
+}
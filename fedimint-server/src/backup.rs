@@ -0,0 +1,378 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, format_err, Context};
+use fedimint_core::admin_client::BackupInfo;
+use fedimint_core::api::{GlobalFederationApi, WsFederationApi};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_logging::LOG_CORE;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use tracing::info;
+
+use crate::config::io::{CODE_VERSION, DB_FILE, JSON_EXT};
+use crate::config::ServerConfig;
+use crate::consensus::FedimintConsensus;
+
+/// Name of the file describing a backup, see [`BackupInfo`]
+pub const BACKUP_INFO_FILE: &str = "backup";
+
+/// Environment variable with the url of the S3-compatible endpoint backups are
+/// written to, the credentials are read from the `AWS_ACCESS_KEY_ID` and
+/// `AWS_SECRET_ACCESS_KEY` variables
+pub const FM_BACKUP_S3_ENDPOINT_ENV: &str = "FM_BACKUP_S3_ENDPOINT";
+
+/// Environment variable with the region of the S3-compatible endpoint
+pub const FM_BACKUP_S3_REGION_ENV: &str = "FM_BACKUP_S3_REGION";
+
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+/// Directory inside the config dir that backups are staged in before they are
+/// uploaded
+const BACKUP_STAGING_DIR: &str = "backup-staging";
+
+/// How often we try to checkpoint the database between two epochs before
+/// giving up
+const MAX_CHECKPOINT_ATTEMPTS: usize = 10;
+
+/// Where a backup is written to or restored from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BackupLocation {
+    Dir(PathBuf),
+    S3 { bucket: String, prefix: String },
+}
+
+impl FromStr for BackupLocation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("s3://") {
+            Some(bucket_and_prefix) => {
+                let (bucket, prefix) = bucket_and_prefix
+                    .split_once('/')
+                    .unwrap_or((bucket_and_prefix, ""));
+                if bucket.is_empty() {
+                    bail!("Missing bucket name in backup location {s}");
+                }
+
+                Ok(BackupLocation::S3 {
+                    bucket: bucket.to_owned(),
+                    prefix: prefix.trim_matches('/').to_owned(),
+                })
+            }
+            None => Ok(BackupLocation::Dir(PathBuf::from(s))),
+        }
+    }
+}
+
+/// Writes a backup of the database and all config files of our guardian to
+/// `location`
+///
+/// The database checkpoint is taken between two epochs, so the backup
+/// contains exactly the state after the epoch described by the returned
+/// [`BackupInfo`].
+pub async fn create_backup(
+    consensus: &FedimintConsensus,
+    location: &BackupLocation,
+) -> anyhow::Result<BackupInfo> {
+    let config_dir = consensus
+        .config_dir
+        .as_ref()
+        .ok_or_else(|| format_err!("Config directory is unknown, unable to back up configs"))?;
+
+    match location {
+        BackupLocation::Dir(dir) => write_backup(consensus, config_dir, dir).await,
+        BackupLocation::S3 { bucket, prefix } => {
+            let staging_dir = config_dir.join(BACKUP_STAGING_DIR);
+            let result = async {
+                let info = write_backup(consensus, config_dir, &staging_dir).await?;
+                upload_dir(&s3_bucket(bucket)?, prefix, &staging_dir).await?;
+                Ok(info)
+            }
+            .await;
+
+            if staging_dir.exists() {
+                fs::remove_dir_all(&staging_dir)?;
+            }
+            result
+        }
+    }
+}
+
+async fn write_backup(
+    consensus: &FedimintConsensus,
+    config_dir: &Path,
+    dir: &Path,
+) -> anyhow::Result<BackupInfo> {
+    if dir.exists() {
+        bail!("Backup directory {dir:?} already exists");
+    }
+    fs::create_dir_all(dir)?;
+
+    // the database lives in a sub-directory, so we only copy the config files
+    for entry in fs::read_dir(config_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::copy(entry.path(), dir.join(entry.file_name()))?;
+        }
+    }
+
+    let info = checkpoint_database(consensus, &dir.join(DB_FILE)).await?;
+    let info_file = fs::File::create(dir.join(BACKUP_INFO_FILE).with_extension(JSON_EXT))?;
+    serde_json::to_writer_pretty(info_file, &info)?;
+
+    info!(target: LOG_CORE, epoch_count = info.epoch_count, ?dir, "Created backup");
+    Ok(info)
+}
+
+/// Checkpoints the database, retrying if an epoch was processed while doing so
+async fn checkpoint_database(
+    consensus: &FedimintConsensus,
+    path: &Path,
+) -> anyhow::Result<BackupInfo> {
+    for _ in 0..MAX_CHECKPOINT_ATTEMPTS {
+        let epoch_count = consensus.get_epoch_count().await;
+        consensus.db.checkpoint(path)?;
+
+        if epoch_count == consensus.get_epoch_count().await {
+            let epoch_hash = match epoch_count.checked_sub(1) {
+                Some(last_epoch) => Some(
+                    consensus
+                        .epoch_history(last_epoch)
+                        .await
+                        .ok_or_else(|| format_err!("Missing epoch history for {last_epoch}"))?
+                        .hash,
+                ),
+                None => None,
+            };
+
+            return Ok(BackupInfo {
+                epoch_count,
+                epoch_hash,
+                code_version: CODE_VERSION.to_string(),
+            });
+        }
+
+        fs::remove_dir_all(path)?;
+    }
+
+    bail!("Consensus kept processing epochs while checkpointing the database")
+}
+
+/// Copies a backup from `location` into the empty data directory `data_dir`
+///
+/// The restored backup has to be checked with [`verify_backup`] before
+/// rejoining consensus.
+pub async fn restore_backup(location: &BackupLocation, data_dir: &Path) -> anyhow::Result<()> {
+    if data_dir.join(DB_FILE).exists() {
+        bail!("Refusing to restore a backup over the existing database in {data_dir:?}");
+    }
+    fs::create_dir_all(data_dir)?;
+
+    match location {
+        BackupLocation::Dir(dir) => copy_dir(dir, data_dir)?,
+        BackupLocation::S3 { bucket, prefix } => {
+            download_dir(&s3_bucket(bucket)?, prefix, data_dir).await?
+        }
+    }
+
+    info!(target: LOG_CORE, ?location, ?data_dir, "Restored backup");
+    Ok(())
+}
+
+/// Reads the [`BackupInfo`] left in `data_dir` by [`restore_backup`], `None`
+/// if no backup was restored
+pub fn read_backup_info(data_dir: &Path) -> anyhow::Result<Option<BackupInfo>> {
+    let path = data_dir.join(BACKUP_INFO_FILE).with_extension(JSON_EXT);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+}
+
+/// Checks that the last epoch in a restored backup was signed by the
+/// federation, so we don't rejoin consensus with a diverging history
+pub async fn verify_backup(
+    cfg: &ServerConfig,
+    info: &BackupInfo,
+    decoders: &ModuleDecoderRegistry,
+) -> anyhow::Result<()> {
+    let (Some(last_epoch), Some(epoch_hash)) = (info.epoch_count.checked_sub(1), info.epoch_hash)
+    else {
+        return Ok(());
+    };
+
+    let api = WsFederationApi::new(
+        cfg.consensus
+            .api_endpoints
+            .iter()
+            .map(|(&peer, endpoint)| (peer, endpoint.url.clone()))
+            .collect(),
+    );
+    let outcome = api
+        .fetch_epoch_history(
+            last_epoch,
+            cfg.consensus.epoch_pk_set.public_key(),
+            decoders,
+        )
+        .await
+        .context("Unable to fetch the backed up epoch from the federation")?;
+
+    if outcome.hash != epoch_hash {
+        bail!(
+            "Backed up epoch {last_epoch} has hash {epoch_hash}, but the federation signed {}",
+            outcome.hash
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the paths of all files in `dir` and its sub-directories relative to
+/// `dir`
+fn list_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![PathBuf::new()];
+
+    while let Some(relative_dir) = dirs.pop() {
+        for entry in fs::read_dir(dir.join(&relative_dir))? {
+            let entry = entry?;
+            let relative_path = relative_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(relative_path);
+            } else {
+                files.push(relative_path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    for relative_path in list_files(from)? {
+        let path = to.join(&relative_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from.join(&relative_path), path)?;
+    }
+    Ok(())
+}
+
+fn s3_bucket(name: &str) -> anyhow::Result<Bucket> {
+    let endpoint = std::env::var(FM_BACKUP_S3_ENDPOINT_ENV)
+        .with_context(|| format!("{FM_BACKUP_S3_ENDPOINT_ENV} has to be set for S3 backups"))?;
+    let region =
+        std::env::var(FM_BACKUP_S3_REGION_ENV).unwrap_or_else(|_| DEFAULT_S3_REGION.to_string());
+
+    Ok(Bucket::new(
+        name,
+        Region::Custom { region, endpoint },
+        Credentials::from_env()?,
+    )?
+    .with_path_style())
+}
+
+fn s3_key(prefix: &str, relative_path: &str) -> String {
+    if prefix.is_empty() {
+        relative_path.to_owned()
+    } else {
+        format!("{prefix}/{relative_path}")
+    }
+}
+
+async fn upload_dir(bucket: &Bucket, prefix: &str, dir: &Path) -> anyhow::Result<()> {
+    for relative_path in list_files(dir)? {
+        let relative_key = relative_path
+            .iter()
+            .map(|component| {
+                component
+                    .to_str()
+                    .ok_or_else(|| format_err!("Invalid file name {relative_path:?}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .join("/");
+        let key = s3_key(prefix, &relative_key);
+
+        let response = bucket
+            .put_object(&key, &fs::read(dir.join(&relative_path))?)
+            .await?;
+        if response.status_code() != 200 {
+            bail!(
+                "Uploading {key} failed with status {}",
+                response.status_code()
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn download_dir(bucket: &Bucket, prefix: &str, dir: &Path) -> anyhow::Result<()> {
+    let list_prefix = s3_key(prefix, "");
+    let keys = bucket
+        .list(list_prefix.clone(), None)
+        .await?
+        .into_iter()
+        .flat_map(|result| result.contents)
+        .map(|object| object.key)
+        .collect::<Vec<_>>();
+
+    if keys.is_empty() {
+        bail!("No backup found at {list_prefix}");
+    }
+
+    for key in keys {
+        let relative_path = key
+            .strip_prefix(&list_prefix)
+            .expect("listed keys start with the prefix");
+        let path = dir.join(relative_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let response = bucket.get_object(&key).await?;
+        if response.status_code() != 200 {
+            bail!(
+                "Downloading {key} failed with status {}",
+                response.status_code()
+            );
+        }
+        fs::write(path, response.bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::BackupLocation;
+
+    #[test]
+    fn parses_backup_locations() {
+        assert_eq!(
+            "/var/backups/fedimint".parse::<BackupLocation>().unwrap(),
+            BackupLocation::Dir(PathBuf::from("/var/backups/fedimint"))
+        );
+        assert_eq!(
+            "s3://guardian/backups/epoch-100/"
+                .parse::<BackupLocation>()
+                .unwrap(),
+            BackupLocation::S3 {
+                bucket: "guardian".to_string(),
+                prefix: "backups/epoch-100".to_string()
+            }
+        );
+        assert_eq!(
+            "s3://guardian".parse::<BackupLocation>().unwrap(),
+            BackupLocation::S3 {
+                bucket: "guardian".to_string(),
+                prefix: "".to_string()
+            }
+        );
+        assert!("s3:///backups".parse::<BackupLocation>().is_err());
+    }
+}
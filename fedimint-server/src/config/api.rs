@@ -1,54 +1,84 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
 use std::iter::once;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
+use bitcoin_hashes::hex::ToHex;
 use bitcoin_hashes::sha256::HashEngine;
 use bitcoin_hashes::{sha256, Hash};
+use fedimint_aead::random_salt;
 use fedimint_core::admin_client::{
-    ConfigGenConnectionsRequest, ConfigGenParamsConsensus, ConfigGenParamsRequest,
-    PeerServerParams, WsAdminClient,
+    config_gen_hmac, config_gen_token_id, ConfigGenConnectionsRequest, ConfigGenParamsConsensus,
+    ConfigGenParamsRequest, ConfigGenPeerRequest, LeaderConfigGenParams, PeerServerParams,
+    WsAdminClient,
 };
 use fedimint_core::config::ServerModuleGenRegistry;
 use fedimint_core::core::{ModuleInstanceId, ModuleKind};
 use fedimint_core::db::Database;
 use fedimint_core::encoding::Encodable;
 use fedimint_core::module::{
-    api_endpoint, ApiAuth, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased,
-    DynServerModuleGen,
+    api_endpoint, ApiAuth, ApiAuthTier, ApiEndpoint, ApiEndpointContext, ApiError,
+    ApiRequestErased, DynServerModuleGen,
 };
 use fedimint_core::task::TaskGroup;
 use fedimint_core::PeerId;
 use itertools::Itertools;
 use jsonrpsee::server::{ServerBuilder, ServerHandle};
 use jsonrpsee::RpcModule;
+use rand::rngs::OsRng;
+use rand::RngCore;
 use tokio::sync::Notify;
 use tokio_rustls::rustls;
 use tracing::error;
 use url::Url;
 
-use crate::config::{gen_cert_and_key, ServerConfig, ServerConfigConsensus, ServerConfigParams};
+use crate::config::io::{write_server_config, SALT_FILE};
+use crate::config::{
+    gen_cert_and_key, ApiLimits, PeerLimits, ServerConfig, ServerConfigConsensus,
+    ServerConfigParams,
+};
 use crate::net::api::{attach_endpoints, HasApiContext, RpcHandlerCtx};
 use crate::net::connect::TlsConfig;
+use crate::net::limits::RequestLimiter;
 use crate::net::peers::{DelayCalculator, NetworkConfig};
 
 pub type ApiResult<T> = std::result::Result<T, ApiError>;
 
+/// How long a guardian has to join config gen with a token the leader created
+const CONFIG_GEN_TOKEN_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// One-time token created by the leader for a guardian to join config gen
+#[derive(Debug, Clone)]
+struct ConfigGenToken {
+    token: String,
+    /// The token can't be redeemed anymore afterwards
+    expires_at: SystemTime,
+    /// API url of the peer that redeemed the token
+    redeemed_by: Option<Url>,
+}
+
 /// Serves the config gen API endpoints
 pub struct ConfigGenApi {
     /// Directory the configs will be created in
-    _data_dir: PathBuf,
+    data_dir: PathBuf,
     /// In-memory state machine
     state: Mutex<ConfigApiState>,
+    /// One-time tokens we created as the leader by their id
+    tokens: Mutex<BTreeMap<sha256::Hash, ConfigGenToken>>,
     /// DB not really used
     db: Database,
     /// Our connection info configured locally
     our_connections: ConfigGenConnections,
     /// Notify if we receive connections from peer
     notify_peer_connection: Notify,
+    /// Notify once the verified configs were written to the data dir
+    notify_configs_written: Notify,
+    /// Notify once the guardian wants to start consensus with the configs
+    notify_start_consensus: Notify,
     /// The default params for the modules
     default_params: ConfigGenParamsRequest,
     /// Modules that will generate configs
@@ -67,11 +97,14 @@ impl ConfigGenApi {
         registry: ServerModuleGenRegistry,
     ) -> Self {
         Self {
-            _data_dir: data_dir,
+            data_dir,
             state: Mutex::new(ConfigApiState::SetPassword),
+            tokens: Default::default(),
             db,
             our_connections,
             notify_peer_connection: Default::default(),
+            notify_configs_written: Default::default(),
+            notify_start_consensus: Default::default(),
             default_params,
             module_gens,
             registry,
@@ -99,6 +132,9 @@ impl ConfigGenApi {
         if rustls::ServerName::try_from(request.our_name.as_str()).is_err() {
             return Self::bad_request("Name must be a valid domain string");
         }
+        if request.leader_api_url.is_some() && request.leader_token.is_none() {
+            return Self::bad_request("A token from the leader is required to join config gen");
+        }
 
         let connection = {
             let mut state = self.state.lock().expect("lock poisoned");
@@ -120,19 +156,45 @@ impl ConfigGenApi {
             // it's not used in the WS client for anything, perhaps it should be removed
             let client = WsAdminClient::new(url, PeerId::from(0), connection.auth.clone());
             client
-                .add_config_gen_peer(connection.as_peer_info())
+                .add_config_gen_peer(&connection.leader_token(), connection.as_peer_info())
                 .await
-                .map_err(|_| ApiError::not_found("Unable to connect to the leader".to_string()))?;
+                .map_err(|_| {
+                    ApiError::not_found(
+                        "Unable to connect to the leader or the token was rejected".to_string(),
+                    )
+                })?;
         }
 
         self.notify_peer_connection.notify_one();
         Ok(())
     }
 
+    /// Creates a one-time token that the leader hands to a guardian, which
+    /// joins config gen by sending it along with its connection info
+    pub fn create_config_gen_token(&self) -> ApiResult<String> {
+        self.get_connection_state()?;
+
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+        let token = bytes.to_hex();
+        self.tokens.lock().expect("lock poisoned").insert(
+            config_gen_token_id(&token),
+            ConfigGenToken {
+                token: token.clone(),
+                expires_at: fedimint_core::time::now() + CONFIG_GEN_TOKEN_EXPIRY,
+                redeemed_by: None,
+            },
+        );
+
+        Ok(token)
+    }
+
     /// Called from `set_config_gen_connections` to add a peer's connection info
     /// to the leader
-    pub fn add_config_gen_peer(&self, peer: PeerServerParams) -> ApiResult<()> {
+    pub fn add_config_gen_peer(&self, request: ConfigGenPeerRequest) -> ApiResult<()> {
         let mut connection = self.get_connection_state()?;
+        self.redeem_token(&request)?;
+        let peer = request.peer;
         connection.peers.insert(peer.api_url.clone(), peer);
 
         let mut state = self.state.lock().expect("lock poisoned");
@@ -190,6 +252,31 @@ impl ConfigGenApi {
         Ok(())
     }
 
+    /// Returns the consensus config gen params we set as the leader to a peer
+    /// that joined with the token with `token_id`, authenticated with the token
+    pub fn get_leader_config_gen_params(
+        &self,
+        token_id: sha256::Hash,
+    ) -> ApiResult<LeaderConfigGenParams> {
+        let token = match self.tokens.lock().expect("lock poisoned").get(&token_id) {
+            Some(ConfigGenToken {
+                token,
+                redeemed_by: Some(_),
+                ..
+            }) => token.clone(),
+            _ => return Err(ApiError::unauthorized()),
+        };
+
+        let state = self.state.lock().expect("lock poisoned");
+        match &*state {
+            ConfigApiState::VerifyConfigParams(_, params) => Ok(LeaderConfigGenParams {
+                hmac: config_gen_hmac(&token, &params.consensus),
+                consensus: params.consensus.clone(),
+            }),
+            _ => Self::bad_request("Leader has not set the consensus params yet"),
+        }
+    }
+
     /// Gets the consensus config gen params, if we have a leader get it from
     /// the leader
     pub async fn get_consensus_config_gen_params(&self) -> ApiResult<ConfigGenParamsConsensus> {
@@ -212,10 +299,13 @@ impl ConfigGenApi {
             ))?;
 
         let client = WsAdminClient::new(url, PeerId::from(0), connection.auth.clone());
-        let consensus = client
-            .get_consensus_config_gen_params()
+        let LeaderConfigGenParams { consensus, hmac } = client
+            .get_leader_config_gen_params(&connection.leader_token())
             .await
             .map_err(|_| ApiError::not_found("Unable to get params from leader".to_string()))?;
+        if hmac != config_gen_hmac(&connection.leader_token(), &consensus) {
+            return Self::bad_request("Params from the leader were not authenticated by our token");
+        }
 
         self.set_config_state(connection, consensus.clone())?;
 
@@ -254,6 +344,13 @@ impl ConfigGenApi {
             &mut subgroup,
         )
         .await;
+        // frees the P2P port so consensus can bind to it later
+        if let Err(e) = task_group.shutdown_join_all(None).await {
+            error!(
+                target: fedimint_logging::LOG_NET_PEER_DKG,
+                "Unable to shut down DKG tasks: {:?}", e
+            );
+        }
 
         let mut state = self.state.lock().expect("lock poisoned");
         match config {
@@ -288,8 +385,8 @@ impl ConfigGenApi {
         }
     }
 
-    /// Returns the consensus config hash, tweaked by our TLS cert, to be shared
-    /// with other peers
+    /// Verifies the config hashes of all peers, only then the configs are
+    /// written to the data dir
     pub async fn verify_configs(&self, user_hashes: BTreeSet<sha256::Hash>) -> ApiResult<()> {
         let mut state = self.state.lock().expect("lock poisoned");
 
@@ -303,11 +400,68 @@ impl ConfigGenApi {
             .values()
             .cloned()
             .collect();
-        if user_hashes == hashes {
-            *state = ConfigApiState::RunningConsensus(auth);
-            Ok(())
-        } else {
-            Self::bad_request("Config verification failed")
+        if user_hashes != hashes {
+            return Self::bad_request("Config verification failed");
+        }
+
+        self.write_configs(&auth, &config)
+            .map_err(|e| ApiError::server_error(format!("Unable to write the configs: {e}")))?;
+        *state = ConfigApiState::RunningConsensus(auth);
+        self.notify_configs_written.notify_one();
+        Ok(())
+    }
+
+    /// Waits until the configs were verified and written to the data dir
+    pub async fn await_configs_written(&self) {
+        self.notify_configs_written.notified().await;
+    }
+
+    /// Once the configs were verified, signals that consensus should be started
+    pub fn start_consensus(&self) -> ApiResult<()> {
+        let state = self.state.lock().expect("lock poisoned");
+
+        match &*state {
+            ConfigApiState::RunningConsensus(_) => {
+                self.notify_start_consensus.notify_one();
+                Ok(())
+            }
+            _ => Self::bad_request("Must verify the configs first"),
+        }
+    }
+
+    /// Waits until the guardian called `start_consensus`
+    pub async fn await_start_consensus(&self) {
+        self.notify_start_consensus.notified().await;
+    }
+
+    /// Encrypts the private config with the password set in `set_password`
+    fn write_configs(&self, auth: &ApiAuth, config: &ServerConfig) -> anyhow::Result<()> {
+        let salt_path = self.data_dir.join(SALT_FILE);
+        if !salt_path.exists() {
+            fs::write(salt_path, random_salt())?;
+        }
+
+        write_server_config(config, self.data_dir.clone(), &auth.0, &self.registry)
+    }
+
+    /// Redeems the token of `request` for the peer sending it, the peer may
+    /// redeem it again to update its connection info
+    fn redeem_token(&self, request: &ConfigGenPeerRequest) -> ApiResult<()> {
+        let mut tokens = self.tokens.lock().expect("lock poisoned");
+        let token = tokens
+            .get_mut(&request.token_id)
+            .ok_or_else(ApiError::unauthorized)?;
+        if request.hmac != config_gen_hmac(&token.token, &request.peer) {
+            return Err(ApiError::unauthorized());
+        }
+
+        let api_url = &request.peer.api_url;
+        if token.redeemed_by.is_none() && fedimint_core::time::now() < token.expires_at {
+            token.redeemed_by = Some(api_url.clone());
+        }
+        match &token.redeemed_by {
+            Some(redeemed_by) if redeemed_by == api_url => Ok(()),
+            _ => Err(ApiError::unauthorized()),
         }
     }
 
@@ -368,6 +522,7 @@ impl ConfigGenApi {
             our_private_key: connection.tls_private,
             api_auth: connection.auth,
             p2p_bind: connection.our_connections.p2p_bind,
+            extra_p2p_binds: connection.our_connections.extra_p2p_binds,
             api_bind: connection.our_connections.api_bind,
         };
 
@@ -419,6 +574,9 @@ impl ConfigGenParams {
                     .iter()
                     .map(|(id, peer)| (*id, peer.p2p_url.clone()))
                     .collect(),
+                extra_bind_addrs: self.local.extra_p2p_binds,
+                extra_peer_urls: ServerConfigParams::extra_p2p_urls(&self.consensus.peers),
+                limits: PeerLimits::default(),
             },
             api_network: NetworkConfig {
                 identity: self.local.our_id,
@@ -429,9 +587,14 @@ impl ConfigGenParams {
                     .iter()
                     .map(|(id, peer)| (*id, peer.api_url.clone()))
                     .collect(),
+                extra_bind_addrs: vec![],
+                extra_peer_urls: HashMap::new(),
+                limits: PeerLimits::default(),
             },
             meta: self.consensus.requested.meta,
             modules: self.consensus.requested.modules,
+            tor: None,
+            onion_service_key: None,
         }
     }
 }
@@ -447,6 +610,8 @@ pub struct ConfigGenParamsLocal {
     pub api_auth: ApiAuth,
     /// Bind address for P2P communication
     pub p2p_bind: SocketAddr,
+    /// More bind addresses for P2P communication, e.g. an IPv6 one
+    pub extra_p2p_binds: Vec<SocketAddr>,
     /// Bind address for API communication
     pub api_bind: SocketAddr,
 }
@@ -462,6 +627,10 @@ pub struct ConfigGenConnections {
     pub p2p_url: Url,
     /// Url for our API connection
     pub api_url: Url,
+    /// More bind addresses for our P2P connection, e.g. an IPv6 one
+    pub extra_p2p_binds: Vec<SocketAddr>,
+    /// More urls peers can reach our P2P connection at, advertised to them
+    pub extra_p2p_urls: Vec<Url>,
 }
 
 /// State held by the API after receiving a `ConfigGenConnectionsRequest`
@@ -510,9 +679,14 @@ impl ConfigGenConnectionsState {
             p2p_url: self.our_connections.p2p_url.clone(),
             api_url: self.our_connections.api_url.clone(),
             name: self.request.our_name.clone(),
+            extra_p2p_urls: self.our_connections.extra_p2p_urls.clone(),
         }
     }
 
+    fn leader_token(&self) -> String {
+        self.request.leader_token.clone().unwrap_or_default()
+    }
+
     fn get_peer_info(&self) -> Vec<PeerServerParams> {
         self.peers
             .values()
@@ -567,12 +741,20 @@ impl HasApiContext<ConfigGenApi> for ConfigGenApi {
             ConfigApiState::RunningConsensus(api_auth) => Some(api_auth) == auth,
         };
 
-        (self, ApiEndpointContext::new(has_auth, dbtx, id))
+        // there is no user auth before the config was generated
+        let auth_tier = if has_auth {
+            ApiAuthTier::Admin
+        } else {
+            ApiAuthTier::User
+        };
+
+        (self, ApiEndpointContext::new(auth_tier, dbtx, id))
     }
 }
 
-/// Starts the configuration server
-// TODO: combine with net::run_server and replace DKG CLI with the API
+/// Starts the configuration server, the returned API can be used to wait for
+/// the configs being written
+// TODO: combine with net::run_server
 pub async fn run_server(
     data_dir: PathBuf,
     our_connections: ConfigGenConnections,
@@ -580,29 +762,34 @@ pub async fn run_server(
     default_params: ConfigGenParamsRequest,
     module_gens: BTreeMap<u16, (ModuleKind, DynServerModuleGen)>,
     registry: ServerModuleGenRegistry,
-) -> ServerHandle {
+) -> (ServerHandle, Arc<ConfigGenApi>) {
+    let api = Arc::new(ConfigGenApi::new(
+        data_dir,
+        our_connections.clone(),
+        db,
+        default_params,
+        module_gens,
+        registry,
+    ));
     let state = RpcHandlerCtx {
-        rpc_context: Arc::new(ConfigGenApi::new(
-            data_dir,
-            our_connections.clone(),
-            db,
-            default_params,
-            module_gens,
-            registry,
-        )),
+        rpc_context: api.clone(),
+        limiter: Arc::new(RequestLimiter::new(&ApiLimits::default())),
+        signer: None,
     };
     let mut rpc_module = RpcModule::new(state);
 
     attach_endpoints(&mut rpc_module, config_endpoints(), None);
 
-    ServerBuilder::new()
+    let handle = ServerBuilder::new()
         .max_connections(10)
         .ping_interval(Duration::from_secs(10))
         .build(&our_connections.api_bind.to_string())
         .await
         .expect("Could not start API server")
         .start(rpc_module)
-        .expect("Could not start API server")
+        .expect("Could not start API server");
+
+    (handle, api)
 }
 
 /// Returns the endpoints that are necessary prior to the config being generated
@@ -610,28 +797,38 @@ fn config_endpoints() -> Vec<ApiEndpoint<ConfigGenApi>> {
     vec![
         api_endpoint! {
             "set_password",
-            async |config: &ConfigGenApi, context, auth: ApiAuth| -> () {
-                check_auth(context)?;
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context, auth: ApiAuth| -> () {
                 config.set_password(auth)
             }
         },
         api_endpoint! {
             "set_config_gen_connections",
-            async |config: &ConfigGenApi, context, server: ConfigGenConnectionsRequest| -> () {
-                check_auth(context)?;
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context, server: ConfigGenConnectionsRequest| -> () {
                 config.set_config_gen_connections(server).await
             }
         },
+        api_endpoint! {
+            "create_config_gen_token",
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context, _v: ()| -> String {
+                config.create_config_gen_token()
+            }
+        },
         api_endpoint! {
             "add_config_gen_peer",
-            async |config: &ConfigGenApi, context, peer: PeerServerParams| -> () {
-                // No auth required since this is an API-to-API call and the peer connections will be manually accepted or not in the UI
+            ApiAuthTier::Public,
+            async |config: &ConfigGenApi, context, request: ConfigGenPeerRequest| -> () {
+                // No admin auth since this is an API-to-API call, the peer
+                // authenticates with the one-time token instead
                 check_no_auth(context)?;
-                config.add_config_gen_peer(peer)
+                config.add_config_gen_peer(request)
             }
         },
         api_endpoint! {
             "get_config_gen_peers",
+            ApiAuthTier::Public,
             async |config: &ConfigGenApi, context, _v: ()| -> Vec<PeerServerParams> {
                 check_no_auth(context)?;
                 config.get_config_gen_peers()
@@ -639,6 +836,7 @@ fn config_endpoints() -> Vec<ApiEndpoint<ConfigGenApi>> {
         },
         api_endpoint! {
             "await_config_gen_peers",
+            ApiAuthTier::Public,
             async |config: &ConfigGenApi, context, peers: usize| -> Vec<PeerServerParams> {
                 check_no_auth(context)?;
                 config.await_config_gen_peers(peers).await
@@ -646,46 +844,62 @@ fn config_endpoints() -> Vec<ApiEndpoint<ConfigGenApi>> {
         },
         api_endpoint! {
             "get_default_config_gen_params",
-            async |config: &ConfigGenApi, context,  _v: ()| -> ConfigGenParamsRequest {
-                check_auth(context)?;
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context,  _v: ()| -> ConfigGenParamsRequest {
                 config.get_default_config_gen_params()
             }
         },
         api_endpoint! {
             "set_config_gen_params",
-            async |config: &ConfigGenApi, context, params: ConfigGenParamsRequest| -> () {
-                check_auth(context)?;
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context, params: ConfigGenParamsRequest| -> () {
                 config.set_config_gen_params(params).await
             }
         },
         api_endpoint! {
             "get_consensus_config_gen_params",
+            ApiAuthTier::Public,
             async |config: &ConfigGenApi, context, _v: ()| -> ConfigGenParamsConsensus {
                 check_no_auth(context)?;
                 config.get_consensus_config_gen_params().await
             }
         },
+        api_endpoint! {
+            "get_leader_config_gen_params",
+            ApiAuthTier::Public,
+            async |config: &ConfigGenApi, context, token_id: sha256::Hash| -> LeaderConfigGenParams {
+                check_no_auth(context)?;
+                config.get_leader_config_gen_params(token_id)
+            }
+        },
         api_endpoint! {
             "run_dkg",
-            async |config: &ConfigGenApi, context, _v: ()| -> () {
-                check_auth(context)?;
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context, _v: ()| -> () {
                 config.run_dkg().await
             }
         },
         api_endpoint! {
             "get_verify_config_hash",
-            async |config: &ConfigGenApi, context, _v: ()| -> sha256::Hash {
-                check_auth(context)?;
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context, _v: ()| -> sha256::Hash {
                 config.get_verify_config_hash()
             }
         },
         api_endpoint! {
             "verify_configs",
-            async |config: &ConfigGenApi, context, user_hashes: BTreeSet<sha256::Hash>| -> () {
-                check_auth(context)?;
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context, user_hashes: BTreeSet<sha256::Hash>| -> () {
                 config.verify_configs(user_hashes).await
             }
         },
+        api_endpoint! {
+            "start_consensus",
+            ApiAuthTier::Admin,
+            async |config: &ConfigGenApi, _context, _v: ()| -> () {
+                config.start_consensus()
+            }
+        },
     ]
 }
 
@@ -699,33 +913,33 @@ fn check_no_auth(context: &mut ApiEndpointContext) -> ApiResult<()> {
     }
 }
 
-fn check_auth(context: &mut ApiEndpointContext) -> ApiResult<()> {
-    if !context.has_auth() {
-        Err(ApiError::unauthorized())
-    } else {
-        Ok(())
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeSet;
     use std::path::PathBuf;
+    use std::time::Duration;
     use std::{env, fs};
 
-    use fedimint_core::admin_client::WsAdminClient;
+    use fedimint_core::admin_client::{
+        config_gen_hmac, config_gen_token_id, ConfigGenPeerRequest, PeerServerParams, WsAdminClient,
+    };
     use fedimint_core::api::FederationResult;
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::Database;
     use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::module::ApiAuth;
+    use fedimint_core::task::timeout;
     use fedimint_core::PeerId;
     use futures::future::join_all;
     use itertools::Itertools;
     use jsonrpsee::server::ServerHandle;
     use url::Url;
 
-    use crate::config::api::{run_server, ConfigGenConnections, ConfigGenConnectionsRequest};
+    use crate::config::api::{
+        run_server, ConfigApiState, ConfigGenApi, ConfigGenConnections, ConfigGenConnectionsRequest,
+    };
+    use crate::config::gen_cert_and_key;
+    use crate::config::io::{read_server_config, CONSENSUS_CONFIG, JSON_EXT};
 
     /// Helper in config API tests for simulating a guardian's client and server
     struct TestConfigApi {
@@ -734,6 +948,7 @@ mod tests {
         auth: ApiAuth,
         name: String,
         our_connections: ConfigGenConnections,
+        data_dir: PathBuf,
     }
 
     impl TestConfigApi {
@@ -743,6 +958,8 @@ mod tests {
             let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
 
             let name = format!("peer{name_suffix}").to_string();
+            let data_dir = data_dir.join(&name);
+            fs::create_dir(data_dir.clone()).expect("Unable to create peer dir");
             let api_bind = format!("127.0.0.1:{port}").parse().expect("parses");
             let api_url: Url = format!("ws://127.0.0.1:{port}").parse().expect("parses");
             let p2p_bind = format!("127.0.0.1:{}", port + 1).parse().expect("parses");
@@ -754,8 +971,10 @@ mod tests {
                 api_bind,
                 p2p_url,
                 api_url: api_url.clone(),
+                extra_p2p_binds: vec![],
+                extra_p2p_urls: vec![],
             };
-            let server = run_server(
+            let (server, _) = run_server(
                 data_dir.clone(),
                 our_connections.clone(),
                 db,
@@ -774,6 +993,7 @@ mod tests {
                 auth,
                 name,
                 our_connections,
+                data_dir,
             }
         }
 
@@ -783,16 +1003,162 @@ mod tests {
         }
 
         /// Helper function using generated urls
-        async fn set_connections(&self, leader: &Option<Url>) -> FederationResult<()> {
+        async fn set_connections(
+            &self,
+            leader: &Option<Url>,
+            token: Option<String>,
+        ) -> FederationResult<()> {
             self.client
                 .set_config_gen_connections(ConfigGenConnectionsRequest {
                     our_name: self.name.clone(),
                     leader_api_url: leader.clone(),
+                    leader_token: token,
                 })
                 .await
         }
     }
 
+    /// Creates a leader's API without serving it, ready to create tokens
+    async fn token_leader() -> ConfigGenApi {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let our_connections = ConfigGenConnections {
+            p2p_bind: "127.0.0.1:18201".parse().expect("parses"),
+            api_bind: "127.0.0.1:18200".parse().expect("parses"),
+            p2p_url: "ws://127.0.0.1:18201".parse().expect("parses"),
+            api_url: "ws://127.0.0.1:18200".parse().expect("parses"),
+            extra_p2p_binds: vec![],
+            extra_p2p_urls: vec![],
+        };
+        let leader = ConfigGenApi::new(
+            env::temp_dir(),
+            our_connections,
+            db,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        leader
+            .set_password(ApiAuth("password".to_string()))
+            .unwrap();
+        leader
+            .set_config_gen_connections(ConfigGenConnectionsRequest {
+                our_name: "leader".to_string(),
+                leader_api_url: None,
+                leader_token: None,
+            })
+            .await
+            .unwrap();
+        leader
+    }
+
+    fn peer_info(port: u16) -> PeerServerParams {
+        let name = format!("peer{port}");
+        PeerServerParams {
+            cert: gen_cert_and_key(&name).expect("generates").0,
+            p2p_url: format!("ws://127.0.0.1:{}", port + 1)
+                .parse()
+                .expect("parses"),
+            api_url: format!("ws://127.0.0.1:{port}").parse().expect("parses"),
+            name,
+            extra_p2p_urls: vec![],
+        }
+    }
+
+    /// Request a peer sends to join with `token`
+    fn join_request(token: &str, peer: PeerServerParams) -> ConfigGenPeerRequest {
+        ConfigGenPeerRequest {
+            token_id: config_gen_token_id(token),
+            hmac: config_gen_hmac(token, &peer),
+            peer,
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_config_gen_token_rejections() {
+        let leader = token_leader().await;
+        let token = leader.create_config_gen_token().unwrap();
+        let peer = peer_info(18202);
+
+        // Tokens the leader didn't create are rejected
+        let wrong = leader.add_config_gen_peer(join_request("wrong", peer.clone()));
+        assert_eq!(wrong.unwrap_err().code, 401);
+
+        // Knowing the id of a token isn't enough to redeem it
+        let mut forged = join_request("wrong", peer.clone());
+        forged.token_id = config_gen_token_id(&token);
+        assert_eq!(leader.add_config_gen_peer(forged).unwrap_err().code, 401);
+
+        // The connection info can't be swapped out on the way to the leader
+        let mut tampered = join_request(&token, peer.clone());
+        tampered.peer.cert = peer_info(18204).cert;
+        assert_eq!(leader.add_config_gen_peer(tampered).unwrap_err().code, 401);
+
+        // Only peers that joined get the params
+        let params = leader.get_leader_config_gen_params(config_gen_token_id(&token));
+        assert_eq!(params.unwrap_err().code, 401);
+
+        // The peer may update its connection info, but the token can't be replayed
+        // for another peer
+        leader
+            .add_config_gen_peer(join_request(&token, peer.clone()))
+            .unwrap();
+        leader
+            .add_config_gen_peer(join_request(&token, peer.clone()))
+            .unwrap();
+        let replayed = leader.add_config_gen_peer(join_request(&token, peer_info(18204)));
+        assert_eq!(replayed.unwrap_err().code, 401);
+
+        // Tokens that weren't redeemed in time expire
+        let expired = leader.create_config_gen_token().unwrap();
+        leader
+            .tokens
+            .lock()
+            .unwrap()
+            .get_mut(&config_gen_token_id(&expired))
+            .unwrap()
+            .expires_at = fedimint_core::time::now() - Duration::from_secs(1);
+        let late = leader.add_config_gen_peer(join_request(&expired, peer_info(18204)));
+        assert_eq!(late.unwrap_err().code, 401);
+
+        let names: Vec<_> = leader
+            .get_config_gen_peers()
+            .unwrap()
+            .into_iter()
+            .map(|peer| peer.name)
+            .sorted()
+            .collect();
+        assert_eq!(names, vec!["leader", "peer18202"]);
+
+        // The peer authenticates the params with its token
+        leader
+            .set_config_gen_params(Default::default())
+            .await
+            .unwrap();
+        let params = leader
+            .get_leader_config_gen_params(config_gen_token_id(&token))
+            .unwrap();
+        assert_eq!(params.hmac, config_gen_hmac(&token, &params.consensus));
+        assert_ne!(params.hmac, config_gen_hmac(&expired, &params.consensus));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_start_consensus_after_verifying() {
+        let leader = token_leader().await;
+
+        // the configs weren't even generated yet
+        let early = leader.start_consensus();
+        assert_eq!(early.unwrap_err().code, 400);
+        let not_started = timeout(Duration::from_millis(100), leader.await_start_consensus());
+        assert!(not_started.await.is_err());
+
+        *leader.state.lock().unwrap() =
+            ConfigApiState::RunningConsensus(ApiAuth("password".to_string()));
+        leader.start_consensus().unwrap();
+        timeout(Duration::from_secs(10), leader.await_start_consensus())
+            .await
+            .expect("consensus is started");
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_config_api() {
         let (parent, _maybe_tmp_dir_guard) = match env::var("FM_TEST_DIR") {
@@ -819,23 +1185,50 @@ mod tests {
         assert!(leader.set_password().await.is_err());
 
         // We can call this twice to change the leader name
-        leader.set_connections(&None).await.unwrap();
+        leader.set_connections(&None, None).await.unwrap();
         leader.name = "leader".to_string();
-        leader.set_connections(&None).await.unwrap();
+        leader.set_connections(&None, None).await.unwrap();
 
-        // Setup followers and send connection info
+        // Setup followers and send connection info with a one-time token
+        let leader_url = Some(leader.our_connections.api_url.clone());
+        let mut tokens = vec![];
         let mut followers = vec![];
         for i in 1..=3 {
             let port = base_port + (i * 2);
             let mut follower = TestConfigApi::new(port, i, data_dir.clone()).await;
             follower.set_password().await.unwrap();
-            let leader_url = Some(leader.our_connections.api_url.clone());
-            follower.set_connections(&leader_url).await.unwrap();
+
+            // Joining requires a token created by the leader
+            assert!(follower.set_connections(&leader_url, None).await.is_err());
+            let invalid = Some("invalid".to_string());
+            assert!(follower
+                .set_connections(&leader_url, invalid)
+                .await
+                .is_err());
+
+            let token = leader.client.create_config_gen_token().await.unwrap();
+            follower
+                .set_connections(&leader_url, Some(token.clone()))
+                .await
+                .unwrap();
             follower.name = format!("{}_", follower.name);
-            follower.set_connections(&leader_url).await.unwrap();
+            follower
+                .set_connections(&leader_url, Some(token.clone()))
+                .await
+                .unwrap();
+            tokens.push(token);
             followers.push(follower);
         }
 
+        // A token can only be redeemed by one peer
+        let intruder = TestConfigApi::new(base_port + 8, 4, data_dir.clone()).await;
+        intruder.set_password().await.unwrap();
+        assert!(intruder
+            .set_connections(&leader_url, Some(tokens[0].clone()))
+            .await
+            .is_err());
+        intruder.server.stop().expect("server stops");
+
         // Confirm we can get peer servers if we are the leader
         let peers = leader.client.await_config_gen_peers(4).await.unwrap();
         let names: Vec<_> = peers.into_iter().map(|peer| peer.name).sorted().collect();
@@ -874,13 +1267,34 @@ mod tests {
         for peer in &followers {
             hashes.insert(peer.client.get_verify_config_hash().await.unwrap());
         }
+        // Nothing is written before the hashes were verified
+        let consensus_path = |peer: &TestConfigApi| {
+            peer.data_dir
+                .join(CONSENSUS_CONFIG)
+                .with_extension(JSON_EXT)
+        };
+        assert!(followers.iter().all(|peer| !consensus_path(peer).exists()));
+
+        let mut wrong_hashes = hashes.clone();
+        wrong_hashes.pop_first();
+        assert!(followers[0]
+            .client
+            .verify_configs(wrong_hashes)
+            .await
+            .is_err());
+        assert!(!consensus_path(&followers[0]).exists());
+
+        assert!(followers[0].client.start_consensus().await.is_err());
         for peer in &followers {
-            peer.client.verify_configs(hashes.clone()).await.unwrap()
+            peer.client.verify_configs(hashes.clone()).await.unwrap();
+            peer.client.start_consensus().await.unwrap();
         }
 
         for peer in followers {
+            let config = read_server_config(&peer.auth.0, peer.data_dir.clone()).unwrap();
+            assert_eq!(config.private.api_auth, peer.auth);
             peer.server.stop().expect("server stops");
         }
-        fs::remove_dir(data_dir).expect("Unable to remove dir");
+        fs::remove_dir_all(data_dir).expect("Unable to remove dir");
     }
 }
@@ -1,7 +1,8 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::io::Write;
+use std::ops::{Add, Mul};
 
 use anyhow::{ensure, format_err};
 use async_trait::async_trait;
@@ -12,14 +13,15 @@ use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::module::PeerHandle;
 use fedimint_core::net::peers::MuxPeerConnections;
 use fedimint_core::{BitcoinHash, PeerId};
+use hbbft::crypto::group::GroupEncoding;
 use hbbft::crypto::poly::Commitment;
 use hbbft::crypto::{G1Projective, G2Projective, PublicKeySet, SecretKeyShare};
 use rand::{CryptoRng, RngCore};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tbs::hash::hash_bytes_to_curve;
-use tbs::poly::Poly;
-use tbs::Scalar;
+use tbs::poly::{interpolate_zero, Poly};
+use tbs::{Aggregatable, Scalar};
 use threshold_crypto::serde_impl::SerdeSecret;
 
 use crate::*;
@@ -204,6 +206,12 @@ impl<G: DkgGroup> Dkg<G> {
                     }));
                 }
             }
+            DkgMessage::ReshareCommit(..)
+            | DkgMessage::ReshareSkip
+            | DkgMessage::ReshareShare(_)
+            | DkgMessage::ReshareEcho(_) => {
+                return Err(format_err!("{peer} sent a resharing message during DKG"));
+            }
         }
 
         Ok(DkgStep::Messages(vec![]))
@@ -230,6 +238,291 @@ impl<G: DkgGroup> Dkg<G> {
     }
 }
 
+impl<G: DkgGroup> DkgProtocol<G> for Dkg<G> {
+    fn step(&mut self, peer: PeerId, msg: DkgMessage<G>) -> anyhow::Result<DkgStep<G>> {
+        Dkg::step(self, peer, msg)
+    }
+}
+
+/// A protocol producing threshold keys that [`DkgRunner`] can drive
+trait DkgProtocol<G: DkgGroup>: Send {
+    /// Processes a `msg` from `peer`, returning messages to send or our keys
+    fn step(&mut self, peer: PeerId, msg: DkgMessage<G>) -> anyhow::Result<DkgStep<G>>;
+}
+
+struct Reshare<G> {
+    gen_g: G,
+    peers: Vec<PeerId>,
+    our_id: PeerId,
+    threshold: usize,
+    old: ReshareKeys<G>,
+    announced: BTreeSet<PeerId>,
+    commitments: BTreeMap<PeerId, (PeerId, Vec<G>)>,
+    sk_shares: BTreeMap<PeerId, Scalar>,
+    echoes: BTreeMap<PeerId, Sha256>,
+}
+
+/// Resharing of an existing threshold key to a new set of peers, based on
+/// "Verifiable Secret Redistribution for Archive Systems" by
+/// Theodore M. Wong, Chenxi Wang and Jeannette M. Wing
+///
+/// Every new peer that held a share of the old key deals it with Feldman-VSS,
+/// committing to a polynomial whose constant term is its old share. The new
+/// peers verify the commitments against the old public key shares and
+/// interpolate the dealt shares into their new share, so the public key stays
+/// the same while the old shares become useless for the new federation.
+///
+/// The commitments are sent point-to-point, so before accepting the shares all
+/// peers echo a hash of the commitments they received and abort if any hash
+/// differs from theirs.
+///
+/// Fails with any non-cooperative peers and requires at least the old
+/// threshold of old peers to take part.
+impl<G: DkgGroup> Reshare<G> {
+    /// Creates the resharing and the first step of the algorithm
+    pub fn new(
+        group: G,
+        our_id: PeerId,
+        peers: Vec<PeerId>,
+        threshold: usize,
+        old: ReshareKeys<G>,
+        rng: &mut impl rand::RngCore,
+    ) -> (Self, DkgStep<G>) {
+        let dealer = old.dealer;
+        let mut reshare = Reshare {
+            gen_g: group,
+            peers,
+            our_id,
+            threshold,
+            old,
+            announced: BTreeSet::from([our_id]),
+            commitments: Default::default(),
+            sk_shares: Default::default(),
+            echoes: Default::default(),
+        };
+
+        let step = match dealer {
+            Some((old_id, old_sks)) => {
+                let mut coefficients: Vec<Scalar> =
+                    Poly::<Scalar, Scalar>::random(threshold - 1, rng)
+                        .coefficients()
+                        .copied()
+                        .collect();
+                coefficients[0] = old_sks;
+                let poly: Poly<Scalar, Scalar> = Poly::from(coefficients);
+
+                let commit: Vec<G> = poly.coefficients().map(|c| group * *c).collect();
+                reshare.commitments.insert(our_id, (old_id, commit.clone()));
+
+                let mut messages = vec![];
+                for peer in &reshare.peers {
+                    let share = poly.evaluate(scalar(peer));
+
+                    if *peer == our_id {
+                        reshare.sk_shares.insert(our_id, share);
+                    } else {
+                        messages.push((*peer, DkgMessage::ReshareCommit(old_id, commit.clone())));
+                        messages.push((*peer, DkgMessage::ReshareShare(share)));
+                    }
+                }
+                DkgStep::Messages(messages)
+            }
+            None => reshare.broadcast(DkgMessage::ReshareSkip),
+        };
+
+        (reshare, step)
+    }
+
+    /// Runs a single step of the resharing, processing a `msg` from `peer`
+    pub fn step(&mut self, peer: PeerId, msg: DkgMessage<G>) -> anyhow::Result<DkgStep<G>> {
+        match msg {
+            DkgMessage::ReshareCommit(old_id, commit) => {
+                ensure!(self.threshold == commit.len(), "wrong degree from {peer}");
+                let pk_share = self
+                    .old
+                    .pk_shares
+                    .get(&old_id)
+                    .ok_or_else(|| format_err!("{peer} claims unknown old peer {old_id}"))?;
+                ensure!(
+                    commit[0] == *pk_share,
+                    "{peer} did not commit to the key share of {old_id}"
+                );
+                ensure!(
+                    !self
+                        .commitments
+                        .iter()
+                        .any(|(dealer, (id, _))| *dealer != peer && *id == old_id),
+                    "{peer} and another peer both dealt the key share of {old_id}"
+                );
+
+                match self.commitments.get(&peer) {
+                    Some(old) if *old != (old_id, commit.clone()) => {
+                        return Err(format_err!("{peer} sent us two commitments!"))
+                    }
+                    _ => self.commitments.insert(peer, (old_id, commit)),
+                };
+                self.announced.insert(peer);
+            }
+            DkgMessage::ReshareSkip => {
+                ensure!(
+                    !self.commitments.contains_key(&peer),
+                    "{peer} both dealt and skipped"
+                );
+                self.announced.insert(peer);
+            }
+            DkgMessage::ReshareShare(share) => {
+                match self.sk_shares.get(&peer) {
+                    Some(old) if *old != share => {
+                        return Err(format_err!("{peer} sent us two shares!"))
+                    }
+                    _ => self.sk_shares.insert(peer, share),
+                };
+            }
+            DkgMessage::ReshareEcho(hash) => {
+                match self.echoes.get(&peer) {
+                    Some(old) if *old != hash => {
+                        return Err(format_err!("{peer} sent us two echoes!"))
+                    }
+                    _ => self.echoes.insert(peer, hash),
+                };
+            }
+            _ => return Err(format_err!("{peer} sent a DKG message during resharing")),
+        }
+
+        self.try_finish()
+    }
+
+    /// Echoes the commitments once every peer announced whether it deals and
+    /// we received all shares, and combines the dealt shares once every peer
+    /// echoed the same commitments
+    fn try_finish(&mut self) -> anyhow::Result<DkgStep<G>> {
+        let missing_share = self
+            .commitments
+            .keys()
+            .any(|dealer| !self.sk_shares.contains_key(dealer));
+        if self.announced.len() < self.peers.len() || missing_share {
+            return Ok(DkgStep::Messages(vec![]));
+        }
+
+        ensure!(
+            self.commitments.len() >= self.old.threshold,
+            "only {} old peers dealt their key shares, {} are required",
+            self.commitments.len(),
+            self.old.threshold
+        );
+        if let Some(peer) = self
+            .sk_shares
+            .keys()
+            .find(|peer| !self.commitments.contains_key(peer))
+        {
+            return Err(format_err!("{peer} sent a share without dealing"));
+        }
+
+        // Feldman-VSS verifies the shares match the commitments
+        for (dealer, (_, commit)) in &self.commitments {
+            let share_product = self.gen_g * self.sk_shares[dealer];
+            let commit_product: G = commit
+                .iter()
+                .enumerate()
+                .map(|(idx, commit)| *commit * scalar(&self.our_id).pow(&[idx as u64, 0, 0, 0]))
+                .reduce(|a, b| a + b)
+                .expect("sums");
+
+            ensure!(share_product == commit_product, "bad share from {dealer}");
+        }
+
+        if !self.echoes.contains_key(&self.our_id) {
+            let hash = self.hash_commitments();
+            self.echoes.insert(self.our_id, hash);
+            return Ok(self.broadcast(DkgMessage::ReshareEcho(hash)));
+        }
+        if self.echoes.len() < self.peers.len() {
+            return Ok(DkgStep::Messages(vec![]));
+        }
+        let our_echo = self.echoes[&self.our_id];
+        if let Some(peer) = self
+            .echoes
+            .keys()
+            .find(|peer| self.echoes[peer] != our_echo)
+        {
+            return Err(format_err!(
+                "{peer} received different resharing commitments than we did"
+            ));
+        }
+
+        let sks = interpolate(
+            self.commitments
+                .iter()
+                .map(|(dealer, (old_id, _))| (scalar(old_id), self.sk_shares[dealer]))
+                .collect(),
+        );
+
+        let pks: Vec<G> = (0..self.threshold)
+            .map(|idx| {
+                interpolate(
+                    self.commitments
+                        .values()
+                        .map(|(old_id, commit)| (scalar(old_id), commit[idx]))
+                        .collect(),
+                )
+            })
+            .collect();
+
+        ensure!(
+            pks[0] == self.old.public_key,
+            "resharing changed the public key"
+        );
+
+        Ok(DkgStep::Result(DkgKeys {
+            public_key_set: pks,
+            secret_key_share: sks,
+        }))
+    }
+
+    /// Hashes the commitments of all dealers together with the old peers whose
+    /// key shares they deal
+    fn hash_commitments(&self) -> Sha256 {
+        let mut engine = HashEngine::default();
+        for (dealer, (old_id, commit)) in &self.commitments {
+            engine
+                .write_all(&u16::from(*dealer).to_le_bytes())
+                .expect("hashes");
+            engine
+                .write_all(&u16::from(*old_id).to_le_bytes())
+                .expect("hashes");
+            for element in commit {
+                engine
+                    .write_all(element.to_bytes().as_ref())
+                    .expect("hashes");
+            }
+        }
+        Sha256::from_engine(engine)
+    }
+
+    fn broadcast(&self, msg: DkgMessage<G>) -> DkgStep<G> {
+        let others = self.peers.iter().filter(|p| **p != self.our_id);
+        DkgStep::Messages(others.map(|peer| (*peer, msg.clone())).collect())
+    }
+}
+
+impl<G: DkgGroup> DkgProtocol<G> for Reshare<G> {
+    fn step(&mut self, peer: PeerId, msg: DkgMessage<G>) -> anyhow::Result<DkgStep<G>> {
+        Reshare::step(self, peer, msg)
+    }
+}
+
+/// Interpolates the value at 0 of the polynomial going through `points`
+fn interpolate<T>(points: Vec<(Scalar, T)>) -> T
+where
+    T: Copy + Mul<Scalar, Output = T> + Add<T, Output = T>,
+{
+    match points.as_slice() {
+        // a single share can only come from a constant polynomial
+        [(_, value)] => *value,
+        _ => interpolate_zero(points.into_iter()),
+    }
+}
+
 /// PeerIds are offset by 1, since evaluating a poly at 0 reveals the secret
 pub fn scalar(peer: &PeerId) -> Scalar {
     Scalar::from(peer.to_usize() as u64 + 1)
@@ -302,14 +595,66 @@ where
     where
         DkgMessage<G>: ISupportedDkgMessage,
     {
-        let mut dkgs: HashMap<T, Dkg<G>> = HashMap::new();
+        self.reshare(module_id, group, HashMap::new(), connections, rng)
+            .await
+    }
+
+    /// Reshares the `old` G2 keys used in `tbs` to our peers
+    pub async fn reshare_g2(
+        &mut self,
+        module_id: ModuleInstanceId,
+        old: HashMap<T, ReshareKeys<G2Projective>>,
+        connections: &MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> DkgResult<HashMap<T, DkgKeys<G2Projective>>> {
+        self.reshare(module_id, G2Projective::generator(), old, connections, rng)
+            .await
+    }
+
+    /// Reshares the `old` G1 keys used in `threshold_crypto` to our peers
+    pub async fn reshare_g1(
+        &mut self,
+        module_id: ModuleInstanceId,
+        old: HashMap<T, ReshareKeys<G1Projective>>,
+        connections: &MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> DkgResult<HashMap<T, DkgKeys<G1Projective>>> {
+        self.reshare(module_id, G1Projective::generator(), old, connections, rng)
+            .await
+    }
+
+    /// Runs the resharing algorithms with our peers, keeping the public keys of
+    /// the `old` keys
+    ///
+    /// Keys without `old` keys are generated from scratch like in [`Self::run`]
+    pub async fn reshare<G: DkgGroup>(
+        &mut self,
+        module_id: ModuleInstanceId,
+        group: G,
+        mut old: HashMap<T, ReshareKeys<G>>,
+        connections: &MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> DkgResult<HashMap<T, DkgKeys<G>>>
+    where
+        DkgMessage<G>: ISupportedDkgMessage,
+    {
+        let mut dkgs: HashMap<T, Box<dyn DkgProtocol<G>>> = HashMap::new();
         let mut results: HashMap<T, DkgKeys<G>> = HashMap::new();
 
         // create the dkgs and send our initial messages
         for (key, threshold) in self.dkg_config.iter() {
             let our_id = self.our_id;
             let peers = self.peers.clone();
-            let (dkg, step) = Dkg::new(group, our_id, peers, *threshold, rng);
+            let (dkg, step): (Box<dyn DkgProtocol<G>>, _) = match old.remove(key) {
+                Some(old) => {
+                    let (reshare, step) = Reshare::new(group, our_id, peers, *threshold, old, rng);
+                    (Box::new(reshare), step)
+                }
+                None => {
+                    let (dkg, step) = Dkg::new(group, our_id, peers, *threshold, rng);
+                    (Box::new(dkg), step)
+                }
+            };
             if let DkgStep::Messages(messages) = step {
                 for (peer, msg) in messages {
                     connections
@@ -406,15 +751,105 @@ impl DkgKeys<G1Projective> {
     }
 }
 
+/// An existing threshold key that is reshared to a new set of peers
+#[derive(Debug, Clone)]
+pub struct ReshareKeys<G> {
+    /// Public key shares of the old peers
+    pub pk_shares: BTreeMap<PeerId, G>,
+    /// Public key that is kept by the resharing
+    pub public_key: G,
+    /// Number of old key shares needed to reconstruct the key
+    pub threshold: usize,
+    /// Our old peer id and secret key share if we held a share of the key
+    pub dealer: Option<(PeerId, Scalar)>,
+}
+
+impl ReshareKeys<G2Projective> {
+    pub fn tbs(
+        pk_shares: BTreeMap<PeerId, tbs::PublicKeyShare>,
+        dealer: Option<(PeerId, tbs::SecretKeyShare)>,
+    ) -> Self {
+        let threshold = pk_shares.threshold();
+        let public_key = pk_shares
+            .values()
+            .copied()
+            .collect::<Vec<_>>()
+            .aggregate(threshold);
+
+        ReshareKeys {
+            pk_shares: pk_shares
+                .into_iter()
+                .map(|(peer, pk)| (peer, G2Projective::from(pk.0)))
+                .collect(),
+            public_key: G2Projective::from(public_key.0),
+            threshold,
+            dealer: dealer.map(|(peer, sks)| (peer, sks.0)),
+        }
+    }
+}
+
+impl ReshareKeys<G1Projective> {
+    pub fn threshold_crypto(
+        public_key_set: &PublicKeySet,
+        old_peers: &[PeerId],
+        dealer: Option<(PeerId, &SerdeSecret<SecretKeyShare>)>,
+    ) -> anyhow::Result<Self> {
+        let pk_shares = old_peers
+            .iter()
+            .map(|peer| {
+                let pk = public_key_set.public_key_share(peer.to_usize()).to_bytes();
+                Ok((*peer, decode_point(&pk)?))
+            })
+            .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+        let dealer = match dealer {
+            Some((peer, sks)) => {
+                // `threshold_crypto` doesn't expose the scalar of a key share, but serializes
+                // it as the 32 canonical bytes `tbs` uses too
+                let bytes: [u8; 32] = bincode::serialize(sks)?
+                    .try_into()
+                    .map_err(|_| format_err!("Unexpected secret key share encoding"))?;
+                let sks = Option::<Scalar>::from(Scalar::from_bytes(&bytes))
+                    .ok_or_else(|| format_err!("Invalid secret key share"))?;
+                ensure!(
+                    pk_shares.get(&peer) == Some(&(G1Projective::generator() * sks)),
+                    "Secret key share doesn't match the public key share of {peer}"
+                );
+                Some((peer, sks))
+            }
+            None => None,
+        };
+
+        Ok(ReshareKeys {
+            pk_shares,
+            public_key: decode_point(&public_key_set.public_key().to_bytes())?,
+            threshold: public_key_set.threshold() + 1,
+            dealer,
+        })
+    }
+}
+
+fn decode_point<G: DkgGroup>(bytes: &[u8]) -> anyhow::Result<G> {
+    let mut repr = G::Repr::default();
+    ensure!(repr.as_ref().len() == bytes.len(), "Wrong point length");
+    repr.as_mut().copy_from_slice(bytes);
+    Option::from(G::from_bytes(&repr)).ok_or_else(|| format_err!("Invalid point encoding"))
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::{HashMap, VecDeque};
+    use std::collections::{BTreeMap, HashMap, VecDeque};
 
+    use fedimint_core::config::DkgMessage;
     use hbbft::crypto::group::Curve;
     use hbbft::crypto::{G1Projective, G2Projective};
     use rand::rngs::OsRng;
+    use tbs::poly::Poly;
+    use tbs::Scalar;
 
-    use crate::config::distributedgen::{scalar, Dkg, DkgGroup, DkgKeys, DkgStep, ThresholdKeys};
+    use crate::config::distributedgen::{
+        scalar, Dkg, DkgGroup, DkgKeys, DkgProtocol, DkgStep, Reshare, ReshareKeys, ThresholdKeys,
+    };
     use crate::PeerId;
 
     #[test_log::test]
@@ -441,6 +876,94 @@ mod tests {
         }
     }
 
+    #[test_log::test]
+    fn test_reshare() {
+        // old peer 3 leaves, the others get new ids and two new peers join
+        let old_ids = HashMap::from([
+            (PeerId::from(0), PeerId::from(2)),
+            (PeerId::from(1), PeerId::from(0)),
+            (PeerId::from(2), PeerId::from(1)),
+        ]);
+
+        let old_keys = run(G1Projective::generator());
+        let old_pk = old_keys[&PeerId::from(0)].public_key_set[0];
+        for (peer, keys) in reshare(G1Projective::generator(), &old_keys, &old_ids) {
+            let ThresholdKeys {
+                public_key_set,
+                secret_key_share,
+            } = keys.threshold_crypto();
+            assert_eq!(public_key_set.threshold(), 3);
+            assert_eq!(keys.public_key_set[0], old_pk);
+            assert_eq!(
+                public_key_set.public_key_share(peer.to_usize()),
+                secret_key_share.public_key_share()
+            );
+        }
+
+        let old_keys = run(G2Projective::generator());
+        let old_pk = old_keys[&PeerId::from(0)].public_key_set[0];
+        for (peer, keys) in reshare(G2Projective::generator(), &old_keys, &old_ids) {
+            let (pk, sk) = keys.tbs();
+            assert_eq!(pk.coefficients().len(), 4);
+            assert_eq!(*pk.coefficients().next().unwrap(), old_pk);
+            assert_eq!(
+                pk.evaluate(scalar(&peer)).to_affine(),
+                sk.to_pub_key_share().0
+            );
+        }
+    }
+
+    #[test_log::test]
+    fn test_reshare_detects_equivocating_dealer() {
+        let group = G1Projective::generator();
+        let old_ids = HashMap::from([
+            (PeerId::from(0), PeerId::from(2)),
+            (PeerId::from(1), PeerId::from(0)),
+            (PeerId::from(2), PeerId::from(1)),
+        ]);
+        let old_keys = run(group);
+        let (mut reshares, mut steps) = start_reshare(group, &old_keys, &old_ids);
+
+        // peer 0 deals its old share to peer 1 with a different polynomial than
+        // to everyone else, which peer 1 can't detect from its share alone
+        let mut coefficients: Vec<Scalar> = Poly::<Scalar, Scalar>::random(3, &mut OsRng)
+            .coefficients()
+            .copied()
+            .collect();
+        coefficients[0] = old_keys[&PeerId::from(2)].secret_key_share;
+        let poly: Poly<Scalar, Scalar> = Poly::from(coefficients);
+        let commit: Vec<G1Projective> = poly.coefficients().map(|c| group * *c).collect();
+        let equivocating = [
+            DkgMessage::ReshareCommit(PeerId::from(2), commit),
+            DkgMessage::ReshareShare(poly.evaluate(scalar(&PeerId::from(1)))),
+        ];
+        for (peer, step) in steps.iter_mut() {
+            if let (0, DkgStep::Messages(messages)) = (peer.to_usize(), step) {
+                messages.retain(|(receiver, _)| *receiver != PeerId::from(1));
+                messages.extend(
+                    equivocating
+                        .iter()
+                        .map(|msg| (PeerId::from(1), msg.clone())),
+                );
+            }
+        }
+
+        let mut errors = vec![];
+        while let Some((peer, step)) = steps.pop_front() {
+            if let DkgStep::Messages(messages) = step {
+                for (receive_peer, msg) in messages {
+                    match reshares.get_mut(&receive_peer).unwrap().step(peer, msg) {
+                        Ok(step) => steps.push_back((receive_peer, step)),
+                        Err(e) => errors.push(e.to_string()),
+                    }
+                }
+            }
+        }
+        assert!(errors
+            .iter()
+            .any(|e| e.contains("received different resharing commitments")));
+    }
+
     fn run<G: DkgGroup>(group: G) -> HashMap<PeerId, DkgKeys<G>> {
         let mut rng = OsRng::default();
         let num_peers = 4;
@@ -449,7 +972,6 @@ mod tests {
 
         let mut steps: VecDeque<(PeerId, DkgStep<G>)> = VecDeque::new();
         let mut dkgs: HashMap<PeerId, Dkg<G>> = HashMap::new();
-        let mut keys: HashMap<PeerId, DkgKeys<G>> = HashMap::new();
 
         for peer in &peers {
             let (dkg, step) = Dkg::new(group, *peer, peers.clone(), threshold, &mut rng);
@@ -457,7 +979,66 @@ mod tests {
             steps.push_back((*peer, step));
         }
 
-        while keys.len() < peers.len() {
+        run_steps(dkgs, steps)
+    }
+
+    /// Reshares `old_keys` to 5 new peers, where `old_ids` maps the new ids of
+    /// the old peers that take part to their old ids
+    fn reshare<G: DkgGroup>(
+        group: G,
+        old_keys: &HashMap<PeerId, DkgKeys<G>>,
+        old_ids: &HashMap<PeerId, PeerId>,
+    ) -> HashMap<PeerId, DkgKeys<G>> {
+        let (reshares, steps) = start_reshare(group, old_keys, old_ids);
+        run_steps(reshares, steps)
+    }
+
+    /// Creates the resharings of [`reshare`] and their first steps
+    #[allow(clippy::type_complexity)]
+    fn start_reshare<G: DkgGroup>(
+        group: G,
+        old_keys: &HashMap<PeerId, DkgKeys<G>>,
+        old_ids: &HashMap<PeerId, PeerId>,
+    ) -> (HashMap<PeerId, Reshare<G>>, VecDeque<(PeerId, DkgStep<G>)>) {
+        let mut rng = OsRng::default();
+        let num_peers = 5;
+        let threshold = 4;
+        let peers = (0..num_peers as u16).map(PeerId::from).collect::<Vec<_>>();
+
+        let pk_shares: BTreeMap<PeerId, G> = old_keys
+            .iter()
+            .map(|(peer, keys)| (*peer, group * keys.secret_key_share))
+            .collect();
+        let public_key = old_keys[&PeerId::from(0)].public_key_set[0];
+
+        let mut steps: VecDeque<(PeerId, DkgStep<G>)> = VecDeque::new();
+        let mut reshares: HashMap<PeerId, Reshare<G>> = HashMap::new();
+
+        for peer in &peers {
+            let old = ReshareKeys {
+                pk_shares: pk_shares.clone(),
+                public_key,
+                threshold: 3,
+                dealer: old_ids
+                    .get(peer)
+                    .map(|old_id| (*old_id, old_keys[old_id].secret_key_share)),
+            };
+            let (reshare, step) =
+                Reshare::new(group, *peer, peers.clone(), threshold, old, &mut rng);
+            reshares.insert(*peer, reshare);
+            steps.push_back((*peer, step));
+        }
+
+        (reshares, steps)
+    }
+
+    fn run_steps<G: DkgGroup, P: DkgProtocol<G>>(
+        mut dkgs: HashMap<PeerId, P>,
+        mut steps: VecDeque<(PeerId, DkgStep<G>)>,
+    ) -> HashMap<PeerId, DkgKeys<G>> {
+        let mut keys: HashMap<PeerId, DkgKeys<G>> = HashMap::new();
+
+        while keys.len() < dkgs.len() {
             match steps.pop_front() {
                 Some((peer, DkgStep::Messages(messages))) => {
                     for (receive_peer, msg) in messages {
@@ -489,6 +1070,21 @@ pub trait PeerHandleOps {
     where
         T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash + Sync;
 
+    async fn reshare_g1<T>(
+        &self,
+        v: T,
+        old: ReshareKeys<G1Projective>,
+    ) -> DkgResult<HashMap<T, DkgKeys<G1Projective>>>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash + Sync;
+
+    async fn reshare_multi_g2<T>(
+        &self,
+        old: HashMap<T, ReshareKeys<G2Projective>>,
+    ) -> DkgResult<HashMap<T, DkgKeys<G2Projective>>>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash + Sync;
+
     async fn exchange_pubkeys(
         &self,
         key: secp256k1::PublicKey,
@@ -516,6 +1112,38 @@ impl<'a> PeerHandleOps for PeerHandle<'a> {
             .await
     }
 
+    async fn reshare_g1<T>(
+        &self,
+        v: T,
+        old: ReshareKeys<G1Projective>,
+    ) -> DkgResult<HashMap<T, DkgKeys<G1Projective>>>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash + Sync,
+    {
+        let mut dkg = DkgRunner::new(v.clone(), self.peers.threshold(), &self.our_id, &self.peers);
+        dkg.reshare_g1(
+            self.module_instance_id,
+            HashMap::from([(v, old)]),
+            self.connections,
+            &mut OsRng,
+        )
+        .await
+    }
+
+    async fn reshare_multi_g2<T>(
+        &self,
+        old: HashMap<T, ReshareKeys<G2Projective>>,
+    ) -> DkgResult<HashMap<T, DkgKeys<G2Projective>>>
+    where
+        T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash + Sync,
+    {
+        let keys = old.keys().cloned().collect();
+        let mut dkg = DkgRunner::multi(keys, self.peers.threshold(), &self.our_id, &self.peers);
+
+        dkg.reshare_g2(self.module_instance_id, old, self.connections, &mut OsRng)
+            .await
+    }
+
     async fn exchange_pubkeys(
         &self,
         key: secp256k1::PublicKey,
@@ -16,7 +16,10 @@ use serde::Serialize;
 use tokio_rustls::rustls;
 use url::Url;
 
-use crate::config::{gen_cert_and_key, ServerConfig};
+use crate::config::{
+    gen_cert_and_key, ServerConfig, ServerConfigConsensus, ServerConfigLocal, ServerConfigPrivate,
+};
+use crate::net::tor::TorConfig;
 
 /// Version of the server code (should be the same among peers)
 pub const CODE_VERSION: &str = env!("CODE_VERSION");
@@ -39,6 +42,10 @@ pub const CLIENT_CONNECT_FILE: &str = "client-connect";
 /// Salt backup for combining with the private key
 pub const SALT_FILE: &str = "private.salt";
 
+/// Config key wrapped by a KMS or an HSM, if the private config is encrypted
+/// with such a key
+pub const WRAPPED_CONFIG_KEY: &str = "private.key";
+
 /// Database file name
 pub const DB_FILE: &str = "database";
 
@@ -48,6 +55,12 @@ pub const TLS_PK: &str = "tls-pk";
 /// TLS public cert
 pub const TLS_CERT: &str = "tls-cert";
 
+/// How we connect to our peers through Tor, if we do
+pub const TOR_CONFIG: &str = "tor";
+
+/// Encrypted private key of our onion service
+pub const ONION_SERVICE_KEY: &str = "onion-service-key";
+
 pub const JSON_EXT: &str = "json";
 const ENCRYPTED_EXT: &str = "encrypt";
 
@@ -64,11 +77,17 @@ pub fn create_cert(
     gen_tls(&dir_out_path, p2p_url, api_url, guardian_name, &key)
 }
 
+/// Parses a cert string `<p2p urls>@<api url>@<name>@<hex cert>`, where the
+/// P2P urls are separated by commas, the first one preferred
 pub fn parse_peer_params(url: String) -> anyhow::Result<PeerServerParams> {
     let split: Vec<&str> = url.split('@').collect();
 
     ensure!(split.len() == 4, "Cert string has wrong number of fields");
-    let p2p_url = split[0].parse()?;
+    let mut p2p_urls = split[0]
+        .split(',')
+        .map(|url| url.parse())
+        .collect::<Result<Vec<Url>, _>>()?;
+    let p2p_url = p2p_urls.remove(0);
     let api_url = split[1].parse()?;
     let hex_cert = Vec::from_hex(split[3])?;
     Ok(PeerServerParams {
@@ -76,6 +95,7 @@ pub fn parse_peer_params(url: String) -> anyhow::Result<PeerServerParams> {
         p2p_url,
         api_url,
         name: split[2].to_string(),
+        extra_p2p_urls: p2p_urls,
     })
 }
 
@@ -96,18 +116,84 @@ fn gen_tls(
     Ok(cert_url)
 }
 
+/// Stores how we connect to our peers through Tor and the key of our onion
+/// service next to our cert, config generation puts them into our configs
+pub fn write_tor_config(
+    dir_out_path: &Path,
+    tor: &TorConfig,
+    onion_service_key: Option<&str>,
+    password: &str,
+) -> anyhow::Result<()> {
+    let salt = fs::read_to_string(dir_out_path.join(SALT_FILE))?;
+    let key = get_encryption_key(password, &salt)?;
+
+    plaintext_json_write(tor, dir_out_path.join(TOR_CONFIG))?;
+    if let Some(onion_service_key) = onion_service_key {
+        encrypted_write(
+            onion_service_key.as_bytes().to_vec(),
+            &key,
+            dir_out_path.join(ONION_SERVICE_KEY),
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads what [`write_tor_config`] stored, `None` if we don't connect through
+/// Tor
+pub fn read_tor_config(
+    dir_out_path: &Path,
+    key: &LessSafeKey,
+) -> anyhow::Result<Option<(TorConfig, Option<String>)>> {
+    if !dir_out_path
+        .join(TOR_CONFIG)
+        .with_extension(JSON_EXT)
+        .exists()
+    {
+        return Ok(None);
+    }
+
+    let tor = plaintext_json_read(dir_out_path.join(TOR_CONFIG))?;
+    let key_path = dir_out_path.join(ONION_SERVICE_KEY);
+    let onion_service_key = if key_path.exists() {
+        Some(String::from_utf8(encrypted_read(key, key_path)?)?)
+    } else {
+        None
+    };
+    Ok(Some((tor, onion_service_key)))
+}
+
 /// Reads the server from the local, private, and consensus cfg files
 pub fn read_server_config(password: &str, path: PathBuf) -> anyhow::Result<ServerConfig> {
     let salt = fs::read_to_string(path.join(SALT_FILE))?;
     let key = get_encryption_key(password, &salt)?;
+    read_server_config_with_key(&key, path)
+}
 
+/// Like [`read_server_config`], but decrypts the private cfg file with a key
+/// from a [`crate::config::keys::ConfigKeySource`]
+pub fn read_server_config_with_key(
+    key: &LessSafeKey,
+    path: PathBuf,
+) -> anyhow::Result<ServerConfig> {
     Ok(ServerConfig {
         consensus: plaintext_json_read(path.join(CONSENSUS_CONFIG))?,
         local: plaintext_json_read(path.join(LOCAL_CONFIG))?,
-        private: encrypted_json_read(&key, path.join(PRIVATE_CONFIG))?,
+        private: encrypted_json_read(key, path.join(PRIVATE_CONFIG))?,
     })
 }
 
+/// Reads only the plaintext local cfg file, e.g. to learn how to log before
+/// the private cfg file can be decrypted
+pub fn read_local_config(path: PathBuf) -> anyhow::Result<ServerConfigLocal> {
+    plaintext_json_read(path.join(LOCAL_CONFIG))
+}
+
+/// Reads only the public consensus cfg file, e.g. of a federation we are
+/// not a guardian of yet
+pub fn read_consensus_config(path: PathBuf) -> anyhow::Result<ServerConfigConsensus> {
+    plaintext_json_read(path.join(CONSENSUS_CONFIG))
+}
+
 /// Reads a plaintext json file into a struct
 fn plaintext_json_read<T: Serialize + DeserializeOwned>(path: PathBuf) -> anyhow::Result<T> {
     let string = fs::read_to_string(path.with_extension(JSON_EXT))?;
@@ -124,6 +210,25 @@ fn encrypted_json_read<T: Serialize + DeserializeOwned>(
     Ok(serde_json::from_str(&string)?)
 }
 
+/// Encrypts the private cfg file with `new_key` instead of `old_key`
+pub fn reencrypt_private_config(
+    old_key: &LessSafeKey,
+    new_key: &LessSafeKey,
+    path: PathBuf,
+) -> anyhow::Result<()> {
+    let private: ServerConfigPrivate = encrypted_json_read(old_key, path.join(PRIVATE_CONFIG))?;
+    encrypted_json_write(&private, new_key, path.join(PRIVATE_CONFIG))
+}
+
+/// Replaces the private cfg file with `private` encrypted with `key`
+pub fn write_private_config(
+    private: &ServerConfigPrivate,
+    key: &LessSafeKey,
+    path: PathBuf,
+) -> anyhow::Result<()> {
+    encrypted_json_write(private, key, path.join(PRIVATE_CONFIG))
+}
+
 /// Writes the server into configuration files (private keys encrypted)
 pub fn write_server_config(
     server: &ServerConfig,
@@ -0,0 +1,474 @@
+//! Lets guardians keep their secrets outside of the password-encrypted config,
+//! e.g. in a KMS or an HSM
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, format_err};
+use async_trait::async_trait;
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use bitcoin_hashes::{sha256, Hash};
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::aead::GcmParams;
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use fedimint_aead::{encryption_key_from_bytes, get_encryption_key, LessSafeKey};
+use hbbft::crypto::{PublicKeyShare, SecretKeyShare, SignatureShare};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::{json, Value};
+use tokio::process::Command;
+
+use crate::config::io::{SALT_FILE, WRAPPED_CONFIG_KEY};
+
+/// Length of the key encrypting the private config
+const CONFIG_KEY_LEN: usize = 32;
+
+/// Length of the IV of keys wrapped with AES-GCM by an HSM
+const PKCS11_IV_LEN: usize = 12;
+
+/// Time a key helper or KMS has to answer, a stuck helper must not stall
+/// consensus
+const KEY_BACKEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Environment variable holding the user PIN of the PKCS#11 token
+const PKCS11_PIN_ENV: &str = "FM_PKCS11_PIN";
+
+/// Where the key that encrypts the private config comes from
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ConfigKeySource {
+    /// Derived from the guardian's password and the salt in the config dir
+    Password,
+    /// Hex encoded key injected through the environment variable
+    Env(String),
+    /// Program and arguments of a helper that prints the hex encoded key, so
+    /// the key can be unwrapped by a KMS or an HSM
+    Command(Vec<String>),
+    /// Key stored wrapped in the config dir and unwrapped by an AES key of a
+    /// PKCS#11 token, e.g. an HSM
+    Pkcs11(Pkcs11Key),
+    /// Key stored wrapped in the config dir and unwrapped by a key of the
+    /// transit engine of a HashiCorp Vault, found through the usual
+    /// `VAULT_ADDR` and `VAULT_TOKEN` variables
+    Vault(String),
+}
+
+/// AES key of a PKCS#11 token that wraps the config key, the token's user
+/// PIN is read from `FM_PKCS11_PIN`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Pkcs11Key {
+    /// The PKCS#11 library of the token's vendor
+    pub module: PathBuf,
+    pub token_label: String,
+    pub key_label: String,
+}
+
+impl ConfigKeySource {
+    /// Returns the key the private config in `config_dir` is encrypted with
+    pub async fn config_key(
+        &self,
+        password: &str,
+        config_dir: &Path,
+    ) -> anyhow::Result<LessSafeKey> {
+        let bytes = match self {
+            ConfigKeySource::Password => {
+                let salt = tokio::fs::read_to_string(config_dir.join(SALT_FILE)).await?;
+                return get_encryption_key(password, &salt);
+            }
+            ConfigKeySource::Env(var) => Vec::<u8>::from_hex(
+                std::env::var(var)
+                    .map_err(|_| format_err!("Config key variable {var} is not set"))?
+                    .trim(),
+            )?,
+            ConfigKeySource::Command(command) => {
+                Vec::<u8>::from_hex(run_helper(command, &[]).await?.trim())?
+            }
+            ConfigKeySource::Pkcs11(key) => {
+                let wrapped = read_wrapped_key(config_dir).await?;
+                let key = key.clone();
+                tokio::task::spawn_blocking(move || key.unwrap_key(&wrapped)).await??
+            }
+            ConfigKeySource::Vault(key) => {
+                let wrapped = String::from_utf8(read_wrapped_key(config_dir).await?)?;
+                vault_unwrap(key, &wrapped).await?
+            }
+        };
+
+        ensure!(
+            bytes.len() == CONFIG_KEY_LEN,
+            "Config key must be {CONFIG_KEY_LEN} bytes"
+        );
+        encryption_key_from_bytes(&bytes)
+    }
+
+    /// Returns a key to encrypt the private config in `config_dir` with from
+    /// now on, sources that keep the key wrapped in the config dir store a
+    /// newly generated one
+    pub async fn new_config_key(
+        &self,
+        password: &str,
+        config_dir: &Path,
+    ) -> anyhow::Result<LessSafeKey> {
+        let mut bytes = [0u8; CONFIG_KEY_LEN];
+        let wrapped = match self {
+            ConfigKeySource::Password | ConfigKeySource::Env(_) | ConfigKeySource::Command(_) => {
+                return self.config_key(password, config_dir).await;
+            }
+            ConfigKeySource::Pkcs11(key) => {
+                OsRng.fill_bytes(&mut bytes);
+                let key = key.clone();
+                tokio::task::spawn_blocking(move || key.wrap_key(&bytes)).await??
+            }
+            ConfigKeySource::Vault(key) => {
+                OsRng.fill_bytes(&mut bytes);
+                vault_wrap(key, &bytes).await?.into_bytes()
+            }
+        };
+
+        tokio::fs::write(config_dir.join(WRAPPED_CONFIG_KEY), wrapped).await?;
+        encryption_key_from_bytes(&bytes)
+    }
+}
+
+impl FromStr for ConfigKeySource {
+    type Err = anyhow::Error;
+
+    /// Parses `password`, `env:<variable>`, `command:<program> [args...]`,
+    /// `pkcs11:<module>:<token label>:<key label>` or `vault:<key name>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "password" {
+            return Ok(ConfigKeySource::Password);
+        }
+
+        match s.split_once(':') {
+            Some(("env", var)) if !var.is_empty() => Ok(ConfigKeySource::Env(var.to_string())),
+            Some(("command", command)) => Ok(ConfigKeySource::Command(parse_command(command)?)),
+            Some(("pkcs11", key)) => match key.splitn(3, ':').collect::<Vec<_>>()[..] {
+                [module, token_label, key_label]
+                    if [module, token_label, key_label]
+                        .iter()
+                        .all(|s| !s.is_empty()) =>
+                {
+                    Ok(ConfigKeySource::Pkcs11(Pkcs11Key {
+                        module: PathBuf::from(module),
+                        token_label: token_label.to_string(),
+                        key_label: key_label.to_string(),
+                    }))
+                }
+                _ => bail!("Invalid PKCS#11 key {key}, expected <module>:<token>:<key>"),
+            },
+            Some(("vault", key)) if !key.is_empty() => Ok(ConfigKeySource::Vault(key.to_string())),
+            _ => bail!(
+                "Invalid config key source {s}, expected password, env:<variable>, \
+                 command:<program>, pkcs11:<module>:<token>:<key> or vault:<key>"
+            ),
+        }
+    }
+}
+
+impl Pkcs11Key {
+    /// Encrypts `key` with the token's AES key, the IV is prepended
+    fn wrap_key(&self, key: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let (session, handle) = self.open()?;
+        let mut iv = [0u8; PKCS11_IV_LEN];
+        OsRng.fill_bytes(&mut iv);
+        let mechanism = Mechanism::AesGcm(GcmParams::new(&iv, &[], 128.into()));
+
+        let mut wrapped = iv.to_vec();
+        wrapped.extend(session.encrypt(&mechanism, handle, key)?);
+        Ok(wrapped)
+    }
+
+    /// Decrypts a key wrapped by [`Pkcs11Key::wrap_key`]
+    fn unwrap_key(&self, wrapped: &[u8]) -> anyhow::Result<Vec<u8>> {
+        ensure!(
+            wrapped.len() > PKCS11_IV_LEN,
+            "Wrapped config key is too short"
+        );
+        let (iv, ciphertext) = wrapped.split_at(PKCS11_IV_LEN);
+        let (session, handle) = self.open()?;
+        let mechanism = Mechanism::AesGcm(GcmParams::new(iv, &[], 128.into()));
+        Ok(session.decrypt(&mechanism, handle, ciphertext)?)
+    }
+
+    /// Logs into the token and finds the wrapping key
+    fn open(&self) -> anyhow::Result<(Session, ObjectHandle)> {
+        let pkcs11 = Pkcs11::new(&self.module)?;
+        pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+        let mut slot = None;
+        for candidate in pkcs11.get_slots_with_token()? {
+            if pkcs11.get_token_info(candidate)?.label().trim() == self.token_label {
+                slot = Some(candidate);
+                break;
+            }
+        }
+        let slot = slot.ok_or_else(|| format_err!("No PKCS#11 token {}", self.token_label))?;
+
+        let pin = std::env::var(PKCS11_PIN_ENV)
+            .map_err(|_| format_err!("The PKCS#11 PIN has to be set in {PKCS11_PIN_ENV}"))?;
+        let session = pkcs11.open_ro_session(slot)?;
+        session.login(UserType::User, Some(&pin))?;
+
+        let handle = session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::SECRET_KEY),
+                Attribute::Label(self.key_label.as_bytes().to_vec()),
+            ])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| format_err!("No PKCS#11 key {}", self.key_label))?;
+        Ok((session, handle))
+    }
+}
+
+/// Encrypts `key` with the Vault transit key `name`
+async fn vault_wrap(name: &str, key: &[u8]) -> anyhow::Result<String> {
+    let response =
+        vault_request("encrypt", name, json!({ "plaintext": base64::encode(key) })).await?;
+    response["data"]["ciphertext"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| format_err!("Vault returned no ciphertext"))
+}
+
+/// Decrypts a key wrapped by [`vault_wrap`]
+async fn vault_unwrap(name: &str, wrapped: &str) -> anyhow::Result<Vec<u8>> {
+    let response = vault_request("decrypt", name, json!({ "ciphertext": wrapped.trim() })).await?;
+    let plaintext = response["data"]["plaintext"]
+        .as_str()
+        .ok_or_else(|| format_err!("Vault returned no plaintext"))?;
+    Ok(base64::decode(plaintext)?)
+}
+
+async fn vault_request(operation: &str, name: &str, body: Value) -> anyhow::Result<Value> {
+    let addr = std::env::var("VAULT_ADDR").map_err(|_| format_err!("VAULT_ADDR is not set"))?;
+    let token = std::env::var("VAULT_TOKEN").map_err(|_| format_err!("VAULT_TOKEN is not set"))?;
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/v1/transit/{operation}/{name}",
+            addr.trim_end_matches('/')
+        ))
+        .header("X-Vault-Token", token)
+        .timeout(KEY_BACKEND_TIMEOUT)
+        .json(&body)
+        .send()
+        .await?;
+    ensure!(
+        response.status().is_success(),
+        "Vault {operation} with key {name} failed with {}",
+        response.status()
+    );
+    Ok(response.json().await?)
+}
+
+async fn read_wrapped_key(config_dir: &Path) -> anyhow::Result<Vec<u8>> {
+    tokio::fs::read(config_dir.join(WRAPPED_CONFIG_KEY))
+        .await
+        .map_err(|e| format_err!("Unable to read the wrapped config key: {e}"))
+}
+
+/// Signs consensus messages with our epoch key share
+#[async_trait]
+pub trait EpochSigner: Debug + Send + Sync {
+    async fn sign(&self, hash: sha256::Hash) -> anyhow::Result<SignatureShare>;
+}
+
+/// Signs with the epoch key share from our private config
+#[derive(Debug)]
+pub struct LocalEpochSigner(pub SecretKeyShare);
+
+#[async_trait]
+impl EpochSigner for LocalEpochSigner {
+    async fn sign(&self, hash: sha256::Hash) -> anyhow::Result<SignatureShare> {
+        Ok(self.0.sign(hash))
+    }
+}
+
+/// Used if our private config holds no epoch key share and no other signer was
+/// set, fails every signature
+#[derive(Debug)]
+pub struct MissingEpochSigner;
+
+#[async_trait]
+impl EpochSigner for MissingEpochSigner {
+    async fn sign(&self, _hash: sha256::Hash) -> anyhow::Result<SignatureShare> {
+        bail!("Our epoch key share is held outside of the config, but no epoch signer was set")
+    }
+}
+
+/// Signs with a helper that holds the epoch key share, e.g. in an HSM
+///
+/// The helper is called with the hex encoded hash as its last argument and
+/// prints the hex encoded signature share. HSMs can't sign with threshold BLS
+/// keys through PKCS#11, so the helper has to hold the key share itself, e.g.
+/// inside a secure enclave.
+#[derive(Debug)]
+pub struct CommandEpochSigner {
+    command: Vec<String>,
+}
+
+impl CommandEpochSigner {
+    /// Parses the program and arguments of the helper from `command`
+    pub fn new(command: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            command: parse_command(command)?,
+        })
+    }
+}
+
+#[async_trait]
+impl EpochSigner for CommandEpochSigner {
+    async fn sign(&self, hash: sha256::Hash) -> anyhow::Result<SignatureShare> {
+        let hex = run_helper(&self.command, &[hash.to_hex()]).await?;
+        let bytes: [u8; 96] = Vec::<u8>::from_hex(hex.trim())?
+            .try_into()
+            .map_err(|_| format_err!("Signature share must be 96 bytes"))?;
+        SignatureShare::from_bytes(bytes)
+            .map_err(|e| format_err!("Invalid signature share from epoch signer: {e}"))
+    }
+}
+
+/// Checks that `signer` holds the key share of `pk_share`, so a misconfigured
+/// signer is noticed before consensus starts
+pub async fn check_epoch_signer(
+    signer: &dyn EpochSigner,
+    pk_share: &PublicKeyShare,
+) -> anyhow::Result<()> {
+    let hash = sha256::Hash::hash(b"fedimint-epoch-signer-check");
+    ensure!(
+        pk_share.verify(&signer.sign(hash).await?, hash),
+        "Epoch signer doesn't hold our epoch key share"
+    );
+    Ok(())
+}
+
+fn parse_command(command: &str) -> anyhow::Result<Vec<String>> {
+    let command: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+    ensure!(!command.is_empty(), "Helper command is empty");
+    Ok(command)
+}
+
+/// Runs a key helper and returns what it printed, the helper is killed if it
+/// doesn't finish in time
+async fn run_helper(command: &[String], extra_args: &[String]) -> anyhow::Result<String> {
+    let output = tokio::time::timeout(
+        KEY_BACKEND_TIMEOUT,
+        Command::new(&command[0])
+            .args(&command[1..])
+            .args(extra_args)
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| format_err!("Key helper {} timed out", command[0]))?
+    .map_err(|e| format_err!("Unable to run key helper {}: {e}", command[0]))?;
+    ensure!(
+        output.status.success(),
+        "Key helper {} failed with {}",
+        command[0],
+        output.status
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use bitcoin_hashes::hex::ToHex;
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_aead::{decrypt, encrypt};
+    use hbbft::crypto::SecretKeySet;
+    use rand::rngs::OsRng;
+
+    use super::{
+        CommandEpochSigner, ConfigKeySource, EpochSigner, LocalEpochSigner, MissingEpochSigner,
+        Pkcs11Key,
+    };
+
+    #[test]
+    fn parses_config_key_sources() {
+        assert_eq!(
+            "password".parse::<ConfigKeySource>().unwrap(),
+            ConfigKeySource::Password
+        );
+        assert_eq!(
+            "env:FM_CONFIG_KEY".parse::<ConfigKeySource>().unwrap(),
+            ConfigKeySource::Env("FM_CONFIG_KEY".to_string())
+        );
+        assert_eq!(
+            "command:kms-helper --key guardian"
+                .parse::<ConfigKeySource>()
+                .unwrap(),
+            ConfigKeySource::Command(vec![
+                "kms-helper".to_string(),
+                "--key".to_string(),
+                "guardian".to_string()
+            ])
+        );
+        assert_eq!(
+            "pkcs11:/usr/lib/softhsm/libsofthsm2.so:guardian:config"
+                .parse::<ConfigKeySource>()
+                .unwrap(),
+            ConfigKeySource::Pkcs11(Pkcs11Key {
+                module: PathBuf::from("/usr/lib/softhsm/libsofthsm2.so"),
+                token_label: "guardian".to_string(),
+                key_label: "config".to_string(),
+            })
+        );
+        assert_eq!(
+            "vault:fedimint".parse::<ConfigKeySource>().unwrap(),
+            ConfigKeySource::Vault("fedimint".to_string())
+        );
+        assert!("env:".parse::<ConfigKeySource>().is_err());
+        assert!("command:".parse::<ConfigKeySource>().is_err());
+        assert!("pkcs11:module:token".parse::<ConfigKeySource>().is_err());
+        assert!("vault:".parse::<ConfigKeySource>().is_err());
+        assert!("hsm".parse::<ConfigKeySource>().is_err());
+    }
+
+    #[tokio::test]
+    async fn env_and_command_provide_the_same_key() {
+        let hex = [7u8; 32].to_hex();
+        std::env::set_var("FM_TEST_CONFIG_KEY", &hex);
+
+        let from_env = ConfigKeySource::Env("FM_TEST_CONFIG_KEY".to_string())
+            .config_key("", Path::new("."))
+            .await
+            .unwrap();
+        let from_command = ConfigKeySource::Command(vec!["echo".to_string(), hex])
+            .config_key("", Path::new("."))
+            .await
+            .unwrap();
+
+        let mut ciphertext = encrypt(b"secret".to_vec(), &from_env).unwrap();
+        assert_eq!(decrypt(&mut ciphertext, &from_command).unwrap(), b"secret");
+
+        let short = ConfigKeySource::Command(vec!["echo".to_string(), "00".to_string()]);
+        assert!(short.config_key("", Path::new(".")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn command_signer_returns_signature_share() {
+        let sks = SecretKeySet::random(1, &mut OsRng).secret_key_share(0);
+        let hash = sha256::Hash::hash(b"epoch");
+        let share = LocalEpochSigner(sks.clone()).sign(hash).await.unwrap();
+        assert!(sks.public_key_share().verify(&share, hash));
+
+        // `printf` ignores the hash argument and prints the share created above
+        let helper = format!("printf {}", share.to_bytes().to_hex());
+        let signer = CommandEpochSigner::new(&helper).unwrap();
+        assert_eq!(signer.sign(hash).await.unwrap(), share);
+
+        // `echo` prints the hash after the share, which is no valid share
+        let helper = format!("echo {}", share.to_bytes().to_hex());
+        let signer = CommandEpochSigner::new(&helper).unwrap();
+        assert!(signer.sign(hash).await.is_err());
+
+        assert!(MissingEpochSigner.sign(hash).await.is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::{bail, format_err, Context};
@@ -18,11 +18,14 @@ use fedimint_core::config::{
 use fedimint_core::core::{
     ModuleInstanceId, ModuleKind, MODULE_INSTANCE_ID_DKG_DONE, MODULE_INSTANCE_ID_GLOBAL,
 };
+use fedimint_core::epoch::{ConsensusLimits, ConsensusParams};
 use fedimint_core::module::{ApiAuth, DynServerModuleGen, PeerHandle};
-use fedimint_core::net::peers::{IMuxPeerConnections, IPeerConnections, PeerConnections};
+use fedimint_core::net::peers::{
+    IMuxPeerConnections, IPeerConnections, MuxPeerConnections, PeerConnections,
+};
 use fedimint_core::task::{timeout, Elapsed, TaskGroup};
 use fedimint_core::PeerId;
-use fedimint_logging::{LOG_NET_PEER, LOG_NET_PEER_DKG};
+use fedimint_logging::{LogFormat, LOG_NET_PEER, LOG_NET_PEER_DKG};
 use hbbft::crypto::serde_impl::SerdeSecret;
 use hbbft::NetworkInfo;
 use itertools::Itertools;
@@ -33,21 +36,26 @@ use tokio_rustls::rustls;
 use tracing::{error, info};
 use url::Url;
 
-use crate::config::distributedgen::{DkgRunner, ThresholdKeys};
-use crate::config::io::{parse_peer_params, CODE_VERSION, SALT_FILE, TLS_CERT, TLS_PK};
+use crate::config::distributedgen::{DkgRunner, ReshareKeys, ThresholdKeys};
+use crate::config::io::{
+    parse_peer_params, read_tor_config, CODE_VERSION, SALT_FILE, TLS_CERT, TLS_PK,
+};
 use crate::fedimint_core::encoding::Encodable;
 use crate::fedimint_core::{BitcoinHash, NumPeers};
 use crate::multiplexed::PeerConnectionMultiplexer;
 use crate::net::connect::{parse_host_port, Connector, TlsConfig};
 use crate::net::peers::{DelayCalculator, NetworkConfig};
+use crate::net::tls::ApiTlsConfig;
+use crate::net::tor::TorConfig;
 use crate::{ReconnectPeerConnections, TlsTcpConnector};
 
 pub mod api;
 pub mod distributedgen;
 pub mod io;
+pub mod keys;
 
 /// The maximum open connections the API can handle
-const DEFAULT_MAX_CLIENT_CONNECTIONS: u32 = 1000;
+pub const DEFAULT_MAX_CLIENT_CONNECTIONS: u32 = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// All the serializable configuration for the fedimint server
@@ -82,9 +90,18 @@ pub struct ServerConfigPrivate {
     /// Secret key for contributing to HBBFT consensus
     #[serde(with = "serde_binary_human_readable")]
     pub hbbft_sks: SerdeSecret<hbbft::crypto::SecretKeyShare>,
-    /// Secret key for signing consensus epochs
-    #[serde(with = "serde_binary_human_readable")]
-    pub epoch_sks: SerdeSecret<hbbft::crypto::SecretKeyShare>,
+    /// Secret key for signing consensus epochs, `None` if it is held by an
+    /// external epoch signer
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_option_binary_human_readable"
+    )]
+    pub epoch_sks: Option<SerdeSecret<hbbft::crypto::SecretKeyShare>>,
+    /// Private key of the onion service we publish through Tor's control port,
+    /// in the `ED25519-V3:<base64>` format of Tor's `ADD_ONION` command
+    #[serde(default)]
+    pub onion_service_key: Option<String>,
     /// Secret material from modules
     pub modules: BTreeMap<ModuleInstanceId, JsonWithKind>,
 }
@@ -112,6 +129,13 @@ pub struct ServerConfigConsensus {
     pub modules: BTreeMap<ModuleInstanceId, JsonWithKind>,
     /// Additional config the federation wants to transmit to the clients
     pub meta: BTreeMap<String, String>,
+    /// Timing and batching of consensus rounds until the guardians vote for
+    /// different params
+    #[serde(default)]
+    pub consensus_params: ConsensusParams,
+    /// Limits on the size of transactions and module consensus items
+    #[serde(default)]
+    pub consensus_limits: ConsensusLimits,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -122,14 +146,231 @@ pub struct ServerConfigLocal {
     pub identity: PeerId,
     /// Our bind address for communicating with peers
     pub fed_bind: SocketAddr,
+    /// More addresses we listen on for peers besides `fed_bind`, e.g. an IPv6
+    /// one
+    #[serde(default)]
+    pub extra_fed_binds: Vec<SocketAddr>,
+    /// More urls of peers we try in order if connecting to the one in
+    /// `p2p_endpoints` fails
+    #[serde(default)]
+    pub extra_p2p_urls: BTreeMap<PeerId, Vec<Url>>,
     /// Our bind address for our API endpoints
     pub api_bind: SocketAddr,
+    /// More addresses we serve the API on besides `api_bind`
+    #[serde(default)]
+    pub extra_api_binds: Vec<SocketAddr>,
+    /// Our bind address for the admin API endpoints, if `Some` they are
+    /// served there instead of on `api_bind`
+    #[serde(default)]
+    pub admin_bind: Option<SocketAddr>,
+    /// Unix domain socket the admin API is served on in addition, so local
+    /// tools can reach it without opening a port
+    #[serde(default)]
+    pub admin_socket: Option<PathBuf>,
+    /// Serve the API over TLS in addition, so guardians don't need a reverse
+    /// proxy in front of it
+    #[serde(default)]
+    pub api_tls: Option<ApiTlsConfig>,
+    /// Auth users have to send to call the user endpoints of the API, if
+    /// `None` they are open to everyone
+    #[serde(default)]
+    pub user_auth: Option<ApiAuth>,
+    /// Whether we sign API responses with our epoch key share if clients ask
+    /// for it, so they can prove we contradicted the federation
+    #[serde(default)]
+    pub sign_api_responses: bool,
     /// How many API connections we will accept
     pub max_connections: u32,
+    /// How many epochs before the latest checkpoint we keep in the epoch
+    /// history, the full history is kept if `None`. Peers that fall further
+    /// behind can't catch up by downloading the epochs from us.
+    #[serde(default)]
+    pub epoch_retention: Option<u64>,
+    /// Whether we take the archive role: we keep the full epoch history
+    /// regardless of `epoch_retention` and serve it to clients recovering
+    /// from before the epochs the other peers pruned
+    #[serde(default)]
+    pub archive: bool,
+    /// Storage engine of our database, can't be changed once the database
+    /// was created
+    #[serde(default)]
+    pub database_backend: DatabaseBackend,
+    /// Limits protecting the API from being overloaded by clients
+    #[serde(default)]
+    pub api_limits: ApiLimits,
+    /// Limits of the pool of transactions waiting to be proposed
+    #[serde(default)]
+    pub mempool_limits: MempoolLimits,
+    /// Limits on the traffic we accept from every peer
+    #[serde(default)]
+    pub peer_limits: PeerLimits,
+    /// Connect to our peers through Tor, peers can then be reached at onion
+    /// addresses in `p2p_endpoints`
+    #[serde(default)]
+    pub tor: Option<TorConfig>,
+    /// How often we re-verify the invariants of our database in the
+    /// background
+    #[serde(default)]
+    pub integrity_checks: IntegrityCheckConfig,
+    /// Format of the logs we write, `--log-format` takes precedence
+    #[serde(default)]
+    pub log_format: LogFormat,
     /// Non-consensus, non-private configuration from modules
     pub modules: BTreeMap<ModuleInstanceId, JsonWithKind>,
 }
 
+/// Limits applied to every connection and request of the API
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct ApiLimits {
+    /// Maximum size of a request in bytes
+    pub max_request_size: u32,
+    /// Maximum size of a response in bytes
+    pub max_response_size: u32,
+    /// Maximum number of active subscriptions on a single connection
+    pub max_subscriptions_per_connection: u32,
+    /// Number of requests per second we serve on average, further requests
+    /// are rejected until the budget is refilled
+    pub requests_per_second: u32,
+    /// Number of requests that can be served in a burst above
+    /// `requests_per_second`
+    pub request_burst: u32,
+    /// Maximum number of requests we process at the same time
+    pub max_concurrent_requests: u32,
+    /// Number of requests per second we read from a single IP address on
+    /// average, reading further requests is delayed until its budget is
+    /// refilled
+    pub requests_per_second_per_source: u32,
+    /// Number of requests a single IP address can send in a burst above
+    /// `requests_per_second_per_source`
+    pub request_burst_per_source: u32,
+    /// Maximum number of connections from a single IP address
+    pub max_connections_per_source: u32,
+    /// Maximum number of active subscriptions over all connections
+    pub max_subscriptions: u32,
+    /// Subscriptions that didn't deliver anything for this many seconds are
+    /// evicted, which frees the ones of clients that vanished
+    pub subscription_idle_timeout_secs: u64,
+    /// How often we sweep idle subscriptions in seconds
+    pub subscription_sweep_interval_secs: u64,
+    /// Number of epochs per second the explorer endpoints decode on average,
+    /// pages are shortened to the remaining budget and requests are rejected
+    /// once it is spent
+    pub explored_epochs_per_second: u32,
+    /// Number of epochs the explorer endpoints can decode in a burst above
+    /// `explored_epochs_per_second`
+    pub explored_epoch_burst: u32,
+}
+
+impl Default for ApiLimits {
+    fn default() -> Self {
+        Self {
+            max_request_size: 10 * 1024 * 1024,
+            max_response_size: 10 * 1024 * 1024,
+            max_subscriptions_per_connection: 32,
+            requests_per_second: 1000,
+            request_burst: 2000,
+            max_concurrent_requests: 500,
+            requests_per_second_per_source: 50,
+            request_burst_per_source: 200,
+            max_connections_per_source: 32,
+            max_subscriptions: 10_000,
+            subscription_idle_timeout_secs: 30 * 60,
+            subscription_sweep_interval_secs: 60,
+            explored_epochs_per_second: 10,
+            explored_epoch_burst: 50,
+        }
+    }
+}
+
+/// Limits on the messages we read from every peer, so a misbehaving peer
+/// can't flood us
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct PeerLimits {
+    /// Maximum size of a message in bytes, peers sending larger messages are
+    /// disconnected
+    pub max_message_size: u64,
+    /// Number of bytes per second we read from a peer on average
+    pub bytes_per_second: u32,
+    /// Number of bytes a peer can send in a burst above `bytes_per_second`
+    pub byte_burst: u32,
+    /// Number of messages per second we read from a peer on average
+    pub messages_per_second: u32,
+    /// Number of messages a peer can send in a burst above
+    /// `messages_per_second`
+    pub message_burst: u32,
+    /// Peers exceeding their rates are throttled by pausing reading from them,
+    /// if they would have to be paused for longer than this many seconds they
+    /// are disconnected instead
+    pub max_throttle_secs: u64,
+}
+
+impl Default for PeerLimits {
+    fn default() -> Self {
+        Self {
+            max_message_size: 64 * 1024 * 1024,
+            bytes_per_second: 8 * 1024 * 1024,
+            byte_burst: 128 * 1024 * 1024,
+            messages_per_second: 1000,
+            message_burst: 10_000,
+            max_throttle_secs: 30,
+        }
+    }
+}
+
+/// Limits of the pool of submitted transactions that weren't processed by
+/// consensus yet
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct MempoolLimits {
+    /// Maximum number of pending transactions, once reached transactions are
+    /// only accepted if they pay a higher fee rate than a pending one
+    pub max_transactions: usize,
+    /// Maximum number of pending transactions that have to be signed by the
+    /// same key
+    pub max_transactions_per_source: usize,
+    /// Maximum number of transactions we include in a single proposal
+    pub max_proposal_transactions: usize,
+}
+
+impl Default for MempoolLimits {
+    fn default() -> Self {
+        Self {
+            max_transactions: 10_000,
+            max_transactions_per_source: 100,
+            max_proposal_transactions: 1_000,
+        }
+    }
+}
+
+/// Schedule of the background integrity checks of the guardian database
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct IntegrityCheckConfig {
+    /// Seconds between the end of a check and the start of the next one,
+    /// checks are disabled if `0`
+    pub interval_secs: u64,
+}
+
+impl Default for IntegrityCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 60 * 60,
+        }
+    }
+}
+
+/// Storage engines `fedimintd` can keep its database in
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DatabaseBackend {
+    #[default]
+    RocksDb,
+    /// Lightweight alternative for small federations
+    Sqlite,
+}
+
 #[derive(Debug, Clone)]
 /// All the parameters necessary for generating the `ServerConfig` during setup
 ///
@@ -155,6 +396,10 @@ pub struct ServerConfigParams {
     /// Params for the modules we wish to configure, can contain custom
     /// parameters
     pub modules: ServerModuleGenParamsRegistry,
+    /// How we connect to our peers through Tor, if we do
+    pub tor: Option<TorConfig>,
+    /// Private key of the onion service our peers reach us at
+    pub onion_service_key: Option<String>,
 }
 
 impl ServerConfigConsensus {
@@ -196,9 +441,15 @@ impl ServerConfigConsensus {
         let client = ClientConfig {
             federation_id: FederationId(self.auth_pk_set.public_key()),
             epoch_pk: self.epoch_pk_set.public_key(),
+            api_pks: self
+                .api_endpoints
+                .keys()
+                .map(|&peer| (peer, self.epoch_pk_set.public_key_share(peer.to_usize())))
+                .collect(),
             api_endpoints: self.api_endpoints.clone(),
             modules: modules.into_iter().map(|(k, v)| (k, v.client)).collect(),
             meta: self.meta.clone(),
+            limits: self.consensus_limits.clone(),
         };
 
         Ok(ConfigResponse {
@@ -233,15 +484,38 @@ impl ServerConfig {
             tls_key: params.tls.our_private_key.clone(),
             auth_sks: auth_keys.secret_key_share,
             hbbft_sks: hbbft_keys.secret_key_share,
-            epoch_sks: epoch_keys.secret_key_share,
+            epoch_sks: Some(epoch_keys.secret_key_share),
+            onion_service_key: params.onion_service_key.clone(),
             modules: Default::default(),
         };
         let local = ServerConfigLocal {
             p2p_endpoints: params.peers(),
             identity,
             fed_bind: params.p2p_network.bind_addr,
+            extra_fed_binds: params.p2p_network.extra_bind_addrs.clone(),
+            extra_p2p_urls: params
+                .p2p_network
+                .extra_peer_urls
+                .iter()
+                .map(|(peer, urls)| (*peer, urls.clone()))
+                .collect(),
             api_bind: params.api_network.bind_addr,
+            extra_api_binds: params.api_network.extra_bind_addrs.clone(),
+            admin_bind: None,
+            admin_socket: None,
+            api_tls: None,
+            user_auth: None,
+            sign_api_responses: false,
             max_connections: DEFAULT_MAX_CLIENT_CONNECTIONS,
+            epoch_retention: None,
+            archive: false,
+            database_backend: DatabaseBackend::default(),
+            api_limits: ApiLimits::default(),
+            mempool_limits: MempoolLimits::default(),
+            peer_limits: PeerLimits::default(),
+            tor: params.tor.clone(),
+            integrity_checks: IntegrityCheckConfig::default(),
+            log_format: LogFormat::default(),
             modules: Default::default(),
         };
         let consensus = ServerConfigConsensus {
@@ -253,6 +527,8 @@ impl ServerConfig {
             tls_certs: params.tls.peer_certs.clone(),
             modules: Default::default(),
             meta: params.meta,
+            consensus_params: ConsensusParams::default(),
+            consensus_limits: ConsensusLimits::default(),
         };
         let mut cfg = Self {
             consensus,
@@ -332,8 +608,10 @@ impl ServerConfig {
         let private = self.private.clone();
         let id = identity.to_usize();
 
-        if private.epoch_sks.public_key_share() != consensus.epoch_pk_set.public_key_share(id) {
-            bail!("Epoch private key doesn't match pubkey share");
+        if let Some(epoch_sks) = &private.epoch_sks {
+            if epoch_sks.public_key_share() != consensus.epoch_pk_set.public_key_share(id) {
+                bail!("Epoch private key doesn't match pubkey share");
+            }
         }
         if private.hbbft_sks.public_key_share() != consensus.hbbft_pk_set.public_key_share(id) {
             bail!("HBBFT private key doesn't match pubkey share");
@@ -430,7 +708,7 @@ impl ServerConfig {
     ) -> DkgResult<Self> {
         let server_conn = connect(
             params.p2p_network.clone(),
-            params.tls.clone(),
+            params.connector(),
             delay_calculator,
             task_group,
         )
@@ -481,6 +759,163 @@ impl ServerConfig {
             );
         }
 
+        Self::confirm_dkg_done(&connections, peers, our_id).await?;
+
+        let server = ServerConfig::from(
+            params.clone(),
+            *our_id,
+            auth_keys,
+            epoch_keys,
+            hbbft_keys,
+            module_cfgs,
+        );
+
+        info!(
+            target: LOG_NET_PEER,
+            "Distributed key generation has completed successfully!"
+        );
+
+        Ok(server)
+    }
+
+    /// Runs the distributed key gen algorithm for a new set of guardians of the
+    /// federation with the consensus config `old`
+    ///
+    /// The threshold keys of the federation and its modules are reshared so
+    /// clients and the signed epoch history stay valid, only the HBBFT keys are
+    /// generated from scratch. `dealer` is our config in the old federation if
+    /// we were one of its guardians, at least a threshold of them needs to take
+    /// part.
+    pub async fn distributed_reshare(
+        params: &ServerConfigParams,
+        old: &ServerConfigConsensus,
+        dealer: Option<&ServerConfig>,
+        registry: BTreeMap<u16, (ModuleKind, DynServerModuleGen)>,
+        delay_calculator: DelayCalculator,
+        task_group: &mut TaskGroup,
+    ) -> DkgResult<Self> {
+        let peers = &params.peer_ids;
+        let our_id = &params.our_id;
+        if peers.len() == 1 {
+            return Err(format_err!("Resharing to a single guardian is not supported").into());
+        }
+
+        let server_conn = connect(
+            params.p2p_network.clone(),
+            params.connector(),
+            delay_calculator,
+            task_group,
+        )
+        .await;
+        let connections = PeerConnectionMultiplexer::new(server_conn).into_dyn();
+        let mut rng = OsRng;
+
+        info!(
+            target: LOG_NET_PEER_DKG,
+            "Peer {} resharing the federation keys...", our_id
+        );
+
+        let old_peers: Vec<PeerId> = old.api_endpoints.keys().copied().collect();
+        let epoch_dealer = match dealer {
+            Some(cfg) => {
+                let epoch_sks = cfg.private.epoch_sks.as_ref().ok_or_else(|| {
+                    format_err!("Resharing requires our epoch key share in the private config")
+                })?;
+                Some((cfg.local.identity, epoch_sks))
+            }
+            None => None,
+        };
+        let old_keys = HashMap::from([
+            (
+                KeyType::Auth,
+                ReshareKeys::threshold_crypto(
+                    &old.auth_pk_set,
+                    &old_peers,
+                    dealer.map(|cfg| (cfg.local.identity, &cfg.private.auth_sks)),
+                )?,
+            ),
+            (
+                KeyType::Epoch,
+                ReshareKeys::threshold_crypto(&old.epoch_pk_set, &old_peers, epoch_dealer)?,
+            ),
+        ]);
+
+        // hbbft keys are only used between the guardians, so we don't need to keep them
+        let mut dkg = DkgRunner::new(KeyType::Hbbft, peers.one_honest(), our_id, peers);
+        dkg.add(KeyType::Auth, peers.threshold());
+        dkg.add(KeyType::Epoch, peers.threshold());
+
+        let keys = dkg
+            .reshare_g1(MODULE_INSTANCE_ID_GLOBAL, old_keys, &connections, &mut rng)
+            .await?;
+        let auth_keys = keys[&KeyType::Auth].threshold_crypto();
+        let hbbft_keys = keys[&KeyType::Hbbft].threshold_crypto();
+        let epoch_keys = keys[&KeyType::Epoch].threshold_crypto();
+
+        let mut module_cfgs: BTreeMap<ModuleInstanceId, ServerModuleConfig> = Default::default();
+
+        let null_config_gen = ConfigGenParams::null();
+        for (module_instance_id, (kind, gen)) in registry {
+            let old_consensus = old.modules.get(&module_instance_id).ok_or_else(|| {
+                format_err!("Module {module_instance_id} did not exist in the old federation")
+            })?;
+            let old_module = ServerModuleReshareConfig {
+                old_peers: old_peers.clone(),
+                consensus: old_consensus.clone(),
+                dealer: dealer
+                    .map(|cfg| {
+                        let private =
+                            cfg.private
+                                .modules
+                                .get(&module_instance_id)
+                                .ok_or_else(|| {
+                                    format_err!(
+                                        "Missing private config of module {module_instance_id}"
+                                    )
+                                })?;
+                        Ok::<_, anyhow::Error>((cfg.local.identity, private.clone()))
+                    })
+                    .transpose()?,
+            };
+
+            let dkg = PeerHandle::new(&connections, module_instance_id, *our_id, peers.clone());
+            module_cfgs.insert(
+                module_instance_id,
+                gen.distributed_reshare(
+                    &dkg,
+                    params.modules.get(&kind).unwrap_or(&null_config_gen),
+                    &old_module,
+                )
+                .await?,
+            );
+        }
+
+        Self::confirm_dkg_done(&connections, peers, our_id).await?;
+
+        let mut server = ServerConfig::from(
+            params.clone(),
+            *our_id,
+            auth_keys,
+            epoch_keys,
+            hbbft_keys,
+            module_cfgs,
+        );
+        server.consensus.meta = old.meta.clone();
+
+        info!(
+            target: LOG_NET_PEER,
+            "Resharing the federation keys has completed successfully!"
+        );
+
+        Ok(server)
+    }
+
+    /// Makes a best effort to wait for all peers to finish the key generation
+    async fn confirm_dkg_done(
+        connections: &MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
+        peers: &[PeerId],
+        our_id: &PeerId,
+    ) -> DkgResult<()> {
         info!(
             target: LOG_NET_PEER_DKG,
             "Sending confirmations to other peers."
@@ -519,21 +954,7 @@ impl ServerConfig {
             error!(target: LOG_NET_PEER_DKG, "Timeout waiting for dkg completion confirmation from other peers");
         };
 
-        let server = ServerConfig::from(
-            params.clone(),
-            *our_id,
-            auth_keys,
-            epoch_keys,
-            hbbft_keys,
-            module_cfgs,
-        );
-
-        info!(
-            target: LOG_NET_PEER,
-            "Distributed key generation has completed successfully!"
-        );
-
-        Ok(server)
+        Ok(())
     }
 }
 
@@ -556,6 +977,14 @@ impl ServerConfig {
                 .iter()
                 .map(|(&id, endpoint)| (id, endpoint.url.clone()))
                 .collect(),
+            extra_bind_addrs: self.local.extra_fed_binds.clone(),
+            extra_peer_urls: self
+                .local
+                .extra_p2p_urls
+                .iter()
+                .map(|(&id, urls)| (id, urls.clone()))
+                .collect(),
+            limits: self.local.peer_limits.clone(),
         }
     }
 
@@ -638,7 +1067,7 @@ impl ServerConfigParams {
             .map(|(peer, _)| *peer)
             .ok_or_else(|| anyhow::Error::msg("Our id not found"))?;
 
-        Ok(ServerConfigParams::gen_params(
+        let mut params = ServerConfigParams::gen_params(
             ApiAuth(api_auth),
             bind_p2p,
             bind_api,
@@ -647,7 +1076,12 @@ impl ServerConfigParams {
             &peers,
             federation_name,
             module_params,
-        ))
+        );
+        if let Some((tor, onion_service_key)) = read_tor_config(dir_out_path, &key)? {
+            params.tor = Some(tor);
+            params.onion_service_key = onion_service_key;
+        }
+        Ok(params)
     }
 
     /// Generates the parameters necessary for running server config generation
@@ -683,10 +1117,25 @@ impl ServerConfigParams {
             peer_ids: peers.keys().cloned().collect(),
             api_auth,
             tls,
-            p2p_network: Self::gen_network(&bind_p2p, &our_id, peers, |params| params.p2p_url),
+            p2p_network: NetworkConfig {
+                extra_peer_urls: Self::extra_p2p_urls(peers),
+                ..Self::gen_network(&bind_p2p, &our_id, peers, |params| params.p2p_url)
+            },
             api_network: Self::gen_network(&bind_api, &our_id, peers, |params| params.api_url),
             meta: BTreeMap::from([(META_FEDERATION_NAME_KEY.to_owned(), federation_name)]),
             modules,
+            tor: None,
+            onion_service_key: None,
+        }
+    }
+
+    /// Connects to our peers during config generation, through Tor if we run
+    /// behind an onion service
+    fn connector(&self) -> TlsTcpConnector {
+        let connector = TlsTcpConnector::new(self.tls.clone(), self.our_id);
+        match &self.tor {
+            Some(tor) => connector.with_tor(tor.clone(), self.onion_service_key.clone()),
+            None => connector,
         }
     }
 
@@ -706,9 +1155,21 @@ impl ServerConfigParams {
                     (*peer, url)
                 })
                 .collect(),
+            extra_bind_addrs: vec![],
+            extra_peer_urls: HashMap::new(),
+            limits: PeerLimits::default(),
         }
     }
 
+    /// The additional P2P urls the peers advertised
+    pub fn extra_p2p_urls(peers: &BTreeMap<PeerId, PeerServerParams>) -> HashMap<PeerId, Vec<Url>> {
+        peers
+            .iter()
+            .filter(|(_, params)| !params.extra_p2p_urls.is_empty())
+            .map(|(peer, params)| (*peer, params.extra_p2p_urls.clone()))
+            .collect()
+    }
+
     /// config for servers running on different ports on a local network
     pub fn gen_local(
         peers: &[PeerId],
@@ -736,6 +1197,7 @@ impl ServerConfigParams {
                     p2p_url: p2p_url.parse().expect("Should parse"),
                     api_url: api_url.parse().expect("Should parse"),
                     name: format!("peer-{}", peer.to_usize()),
+                    extra_p2p_urls: vec![],
                 };
                 (*peer, params)
             })
@@ -765,14 +1227,14 @@ impl ServerConfigParams {
 
 pub async fn connect<T>(
     network: NetworkConfig,
-    certs: TlsConfig,
+    connector: TlsTcpConnector,
     delay_calculator: DelayCalculator,
     task_group: &mut TaskGroup,
 ) -> PeerConnections<T>
 where
     T: std::fmt::Debug + Clone + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
 {
-    let connector = TlsTcpConnector::new(certs, network.identity).into_dyn();
+    let connector = connector.into_dyn();
     ReconnectPeerConnections::new(network, delay_calculator, connector, task_group)
         .await
         .into_dyn()
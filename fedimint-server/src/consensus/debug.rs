@@ -22,6 +22,7 @@ fn item_message(item: &ConsensusItem) -> String {
     match item {
         ConsensusItem::EpochOutcomeSignatureShare(_) => "Outcome Signature".to_string(),
         ConsensusItem::ClientConfigSignatureShare(_) => "Client Config Signature".to_string(),
+        ConsensusItem::EpochCheckpointSignatureShare(_) => "Checkpoint Signature".to_string(),
         // TODO: make this nice again
         ConsensusItem::Module(mci) => {
             format!("Module CI: module={} ci={}", mci.module_instance_id(), mci)
@@ -40,5 +41,30 @@ fn item_message(item: &ConsensusItem) -> String {
             tx_debug
         }
         ConsensusItem::ConsensusUpgrade(_) => "Consensus Upgrade".to_string(),
+        ConsensusItem::PeerSetChange(change) => {
+            format!("Peer Set Change to {} peers", change.to.len())
+        }
+        ConsensusItem::SupportedConsensusVersion(supported) => {
+            format!("Supports Consensus Version {}", supported.version)
+        }
+        ConsensusItem::ConsensusVersionActivation(activation) => format!(
+            "Activate Consensus Version {} at epoch {}",
+            activation.version, activation.epoch
+        ),
+        ConsensusItem::ConsensusParams(params) => format!(
+            "Consensus Params with round interval {}ms and at most {} items",
+            params.round_interval_ms, params.max_proposal_items
+        ),
+        ConsensusItem::ParamsChangeProposal(proposal) => format!(
+            "Change Consensus Params at epoch {} to transactions of at most {} bytes and \
+             module params {:?}",
+            proposal.epoch, proposal.limits.max_transaction_size, proposal.module_params
+        ),
+        ConsensusItem::StateHash(state_hash) => {
+            format!(
+                "State Hash {} of epoch {}",
+                state_hash.hash, state_hash.epoch
+            )
+        }
     }
 }
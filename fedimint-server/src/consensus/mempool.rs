@@ -0,0 +1,297 @@
+//! Bounded pool of transactions waiting to be proposed to consensus
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use fedimint_core::encoding::Encodable;
+use fedimint_core::{Amount, TransactionId};
+use secp256k1_zkp::XOnlyPublicKey;
+use thiserror::Error;
+
+use crate::config::MempoolLimits;
+use crate::transaction::Transaction;
+
+/// Transactions are attributed to the keys that have to sign them, so a
+/// submitter can only have as many pending transactions as the funds they own
+/// allow and can't crowd out other submitters. Transactions without signing
+/// keys share the `None` source.
+pub type TransactionSource = Option<XOnlyPublicKey>;
+
+/// Transactions paying a higher fee per byte are proposed first, transactions
+/// paying the same rate in the order they were submitted
+type Priority = (Reverse<u64>, u64);
+
+#[derive(Debug)]
+struct PendingTransaction {
+    transaction: Transaction,
+    /// Every key signing the transaction, the first one groups the
+    /// transaction for fairness
+    sources: BTreeSet<TransactionSource>,
+    priority: Priority,
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum MempoolError {
+    #[error("Too many pending transactions signed by {0:?}")]
+    SourceFull(TransactionSource),
+    #[error(
+        "Pending transaction pool is full and the fee rate is too low to replace a transaction"
+    )]
+    Full,
+}
+
+#[derive(Debug)]
+pub struct Mempool {
+    limits: MempoolLimits,
+    transactions: HashMap<TransactionId, PendingTransaction>,
+    by_priority: BTreeSet<(Priority, TransactionId)>,
+    per_source: HashMap<TransactionSource, usize>,
+    next_seq: u64,
+}
+
+impl Mempool {
+    pub fn new(limits: MempoolLimits) -> Self {
+        Self {
+            limits,
+            transactions: HashMap::new(),
+            by_priority: BTreeSet::new(),
+            per_source: HashMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn contains(&self, txid: &TransactionId) -> bool {
+        self.transactions.contains_key(txid)
+    }
+
+    /// Adds a transaction paying `fee` that has to be signed by `keys`, if
+    /// the pool is full it replaces the transaction with the lowest priority if
+    /// that pays a lower fee rate
+    pub fn insert(
+        &mut self,
+        transaction: Transaction,
+        keys: impl IntoIterator<Item = XOnlyPublicKey>,
+        fee: Amount,
+    ) -> Result<(), MempoolError> {
+        let txid = transaction.tx_hash();
+        if self.transactions.contains_key(&txid) {
+            return Ok(());
+        }
+
+        let mut sources = keys.into_iter().map(Some).collect::<BTreeSet<_>>();
+        if sources.is_empty() {
+            sources.insert(None);
+        }
+        if let Some(source) = sources.iter().find(|source| {
+            self.per_source.get(source).copied().unwrap_or(0)
+                >= self.limits.max_transactions_per_source
+        }) {
+            return Err(MempoolError::SourceFull(*source));
+        }
+
+        let priority = (Reverse(fee_rate(&transaction, fee)), self.next_seq);
+        if self.transactions.len() >= self.limits.max_transactions {
+            match self.by_priority.iter().next_back() {
+                Some(&(lowest, lowest_txid)) if priority.0 < lowest.0 => {
+                    self.remove(&lowest_txid);
+                }
+                _ => return Err(MempoolError::Full),
+            }
+        }
+
+        self.next_seq += 1;
+        for source in &sources {
+            *self.per_source.entry(*source).or_default() += 1;
+        }
+        self.by_priority.insert((priority, txid));
+        self.transactions.insert(
+            txid,
+            PendingTransaction {
+                transaction,
+                sources,
+                priority,
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes a transaction once consensus processed it
+    pub fn remove(&mut self, txid: &TransactionId) {
+        let Some(pending) = self.transactions.remove(txid) else {
+            return;
+        };
+
+        self.by_priority.remove(&(pending.priority, *txid));
+        for source in &pending.sources {
+            if let Some(count) = self.per_source.get_mut(source) {
+                *count -= 1;
+                if *count == 0 {
+                    self.per_source.remove(source);
+                }
+            }
+        }
+    }
+
+    /// Selects the transactions for our next proposal
+    ///
+    /// Sources take turns in the order of their best transaction, so every
+    /// source gets a share of the proposal even if one source submits more
+    /// transactions with a higher fee rate.
+    pub fn proposal(&self) -> Vec<Transaction> {
+        let mut queues: BTreeMap<Priority, VecDeque<&Transaction>> = BTreeMap::new();
+        let mut best_of_source: HashMap<TransactionSource, Priority> = HashMap::new();
+
+        for (priority, txid) in &self.by_priority {
+            let pending = &self.transactions[txid];
+            let source = pending.sources.iter().next().copied().flatten();
+            let best = *best_of_source.entry(source).or_insert(*priority);
+            queues
+                .entry(best)
+                .or_default()
+                .push_back(&pending.transaction);
+        }
+
+        let mut proposal = vec![];
+        while proposal.len() < self.limits.max_proposal_transactions && !queues.is_empty() {
+            queues.retain(|_, queue| match queue.pop_front() {
+                Some(transaction) if proposal.len() < self.limits.max_proposal_transactions => {
+                    proposal.push(transaction.clone());
+                    !queue.is_empty()
+                }
+                _ => false,
+            });
+        }
+        proposal
+    }
+}
+
+/// Fee rate in msat per 1000 bytes of the encoded transaction
+fn fee_rate(transaction: &Transaction, fee: Amount) -> u64 {
+    let size = transaction
+        .consensus_encode_to_vec()
+        .map_or(1, |bytes| bytes.len()) as u64;
+    fee.msats.saturating_mul(1000) / size.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::core::{DynInput, DynOutput};
+    use fedimint_core::{Amount, TransactionId};
+    use fedimint_dummy_common::{DummyInput, DummyOutput};
+    use secp256k1_zkp::{KeyPair, XOnlyPublicKey, SECP256K1};
+
+    use super::{Mempool, MempoolError};
+    use crate::config::MempoolLimits;
+    use crate::transaction::Transaction;
+
+    /// Transactions are made unique by their number of outputs
+    fn transaction(outputs: usize) -> Transaction {
+        Transaction {
+            inputs: vec![DynInput::from_typed(0, DummyInput)],
+            outputs: (0..outputs)
+                .map(|_| DynOutput::from_typed(0, DummyOutput))
+                .collect(),
+            signature: None,
+        }
+    }
+
+    fn key(submitter: u8) -> XOnlyPublicKey {
+        KeyPair::from_seckey_slice(SECP256K1, &[submitter + 1; 32])
+            .expect("valid key")
+            .x_only_public_key()
+            .0
+    }
+
+    fn txids(transactions: &[Transaction]) -> Vec<TransactionId> {
+        transactions.iter().map(Transaction::tx_hash).collect()
+    }
+
+    #[test]
+    fn proposes_by_fee_rate_and_alternates_sources() {
+        let mut mempool = Mempool::new(MempoolLimits::default());
+        let spam = (1..=3).map(transaction).collect::<Vec<_>>();
+        let other = transaction(4);
+
+        for tx in &spam {
+            mempool
+                .insert(tx.clone(), [key(0)], Amount::from_sats(10))
+                .unwrap();
+        }
+        mempool
+            .insert(other.clone(), [key(1)], Amount::ZERO)
+            .unwrap();
+        // submitting a transaction twice has no effect
+        mempool
+            .insert(other.clone(), [key(1)], Amount::ZERO)
+            .unwrap();
+        assert_eq!(mempool.len(), 4);
+
+        // the higher fee rate of the first spam transaction doesn't starve the
+        // other submitter, spam transactions with the same fee are ordered by
+        // size
+        assert_eq!(
+            txids(&mempool.proposal()),
+            txids(&[spam[0].clone(), other, spam[1].clone(), spam[2].clone()])
+        );
+
+        mempool.remove(&spam[0].tx_hash());
+        assert_eq!(mempool.len(), 3);
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let mut mempool = Mempool::new(MempoolLimits {
+            max_transactions: 3,
+            max_transactions_per_source: 2,
+            max_proposal_transactions: 2,
+        });
+
+        mempool
+            .insert(transaction(1), [key(0)], Amount::ZERO)
+            .unwrap();
+        mempool
+            .insert(transaction(2), [key(0), key(1)], Amount::ZERO)
+            .unwrap();
+        assert_eq!(
+            mempool.insert(transaction(3), [key(0)], Amount::from_sats(1)),
+            Err(MempoolError::SourceFull(Some(key(0))))
+        );
+        // every signing key counts towards its own cap
+        assert_eq!(
+            mempool.insert(transaction(3), [key(2), key(0)], Amount::from_sats(1)),
+            Err(MempoolError::SourceFull(Some(key(0))))
+        );
+
+        mempool.insert(transaction(4), [], Amount::ZERO).unwrap();
+        assert_eq!(
+            mempool.insert(transaction(5), [key(2)], Amount::ZERO),
+            Err(MempoolError::Full)
+        );
+
+        // a higher fee rate replaces the transaction with the lowest priority
+        let paying = transaction(5);
+        mempool
+            .insert(paying.clone(), [key(2)], Amount::from_sats(1))
+            .unwrap();
+        assert_eq!(mempool.len(), 3);
+        assert!(!mempool
+            .proposal()
+            .iter()
+            .any(|tx| tx.tx_hash() == transaction(4).tx_hash()));
+        assert_eq!(mempool.proposal().len(), 2);
+        assert_eq!(mempool.proposal()[0].tx_hash(), paying.tx_hash());
+
+        // removing a transaction frees the caps of all its keys
+        mempool.remove(&transaction(2).tx_hash());
+        mempool
+            .insert(transaction(6), [key(0), key(1)], Amount::from_sats(1))
+            .unwrap();
+    }
+}
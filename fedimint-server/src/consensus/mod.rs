@@ -2,47 +2,77 @@
 
 pub mod debug;
 mod interconnect;
+pub mod mempool;
+mod stages;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::OsString;
 use std::iter::FromIterator;
 use std::os::unix::prelude::OsStrExt;
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::format_err;
-use fedimint_core::config::{ConfigResponse, ServerModuleGenRegistry};
-use fedimint_core::core::ModuleInstanceId;
+use bitcoin_hashes::sha256;
+use fedimint_core::admin_client::{
+    ConsensusVersionStatus, GuardianStatus, IntegrityReport, ParamsChangeStatus,
+    PeerConnectionStatus, PeerStatus, StateHashDivergence, StateHashStatus,
+};
+use fedimint_core::config::{ApiEndpoint, ConfigResponse, ServerModuleGenRegistry};
+use fedimint_core::core::{DynModuleConsensusItem, ModuleInstanceId};
+use fedimint_core::db::namespace::DbPrefixRegistry;
 use fedimint_core::db::{
-    apply_migrations, Database, DatabaseTransaction, ModuleDatabaseTransaction,
+    apply_migrations, AutocommitError, Database, DatabaseKeyPrefix, DatabaseTransaction,
+    ModuleDatabaseTransaction, MODULE_GLOBAL_PREFIX,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::epoch::*;
-use fedimint_core::module::audit::Audit;
+use fedimint_core::module::audit::{Audit, AuditSummary, ModuleAuditSummary, SignedAuditSummary};
 use fedimint_core::module::registry::{
     ModuleDecoderRegistry, ModuleRegistry, ServerModuleRegistry,
 };
-use fedimint_core::module::{ModuleError, TransactionItemAmount};
-use fedimint_core::outcome::TransactionStatus;
+use fedimint_core::module::version::{
+    SupportedApiVersionsSummary, SupportedCoreApiVersions, SupportedModuleApiVersions,
+    CORE_API_VERSIONS, CORE_CONSENSUS_VERSION, PARAMS_CHANGE_VERSION, STATE_HASH_VERSION,
+};
+use fedimint_core::module::{CoreConsensusVersion, ModuleError, TransactionItemAmount};
+use fedimint_core::outcome::{TransactionStatus, TransactionValidation};
 use fedimint_core::server::{DynServerModule, DynVerificationCache};
-use fedimint_core::task::TaskGroup;
+use fedimint_core::task::{sleep, TaskGroup, TaskHandle};
 use fedimint_core::{Amount, NumPeers, OutPoint, PeerId, TransactionId};
 use fedimint_logging::{LOG_CONSENSUS, LOG_CORE};
-use futures::future::select_all;
+use futures::future::{join_all, select_all};
 use futures::StreamExt;
 use hbbft::honey_badger::Batch;
 use itertools::Itertools;
 use thiserror::Error;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tracing::{debug, error, info, info_span, instrument, trace, warn, Instrument};
 
+use crate::config::keys::{EpochSigner, LocalEpochSigner, MissingEpochSigner};
 use crate::config::ServerConfig;
 use crate::consensus::interconnect::FedimintInterconnect;
+use crate::consensus::mempool::{Mempool, MempoolError};
 use crate::consensus::TransactionSubmissionError::TransactionReplayError;
 use crate::db::{
-    get_global_database_migrations, AcceptedTransactionKey, ClientConfigSignatureKey,
-    ConsensusUpgradeKey, DropPeerKey, DropPeerKeyPrefix, EpochHistoryKey, LastEpochKey,
-    RejectedTransactionKey, GLOBAL_DATABASE_VERSION,
+    consensus_state_hash, get_global_database_migrations, global_db_prefixes, is_local_db_key,
+    AcceptedTransactionKey, ApprovedPeerSetChange, ApprovedPeerSetChangeKey,
+    ClientConfigSignatureKey, ConsensusParamsKey, ConsensusParamsVoteKey,
+    ConsensusParamsVoteKeyPrefix, ConsensusUpgradeKey, ConsensusVersionVoteKey,
+    ConsensusVersionVoteKeyPrefix, DropPeerKey, DropPeerKeyPrefix, EarliestEpochKey,
+    EpochCheckpointKey, EpochCheckpointKeyPrefix, EpochHistoryKey, EpochHistoryKeyPrefix,
+    LastEpochKey, ParamsChangeVoteKey, ParamsChangeVoteKeyPrefix, PeerConsensusVersionKey,
+    PeerConsensusVersionKeyPrefix, PeerSetChangeVoteKey, PeerSetChangeVoteKeyPrefix,
+    RejectedTransactionKey, ScheduledConsensusVersionKey, ScheduledConsensusVersionKeyPrefix,
+    ScheduledParamsChangeKey, ScheduledParamsChangeKeyPrefix, StateHashKey, StateHashKeyPrefix,
+    StateSnapshotKey, GLOBAL_DATABASE_VERSION,
+};
+use crate::metrics;
+use crate::net::limits::ExplorerLimiter;
+use crate::net::peers::{
+    PeerConnectionStatusMap, PeerControl, PeerControls, PeerHealthMap, PeerTrafficMap,
 };
 use crate::transaction::{Transaction, TransactionError};
 
@@ -50,9 +80,32 @@ pub type HbbftSerdeConsensusOutcome = hbbft::honey_badger::Batch<Vec<SerdeConsen
 pub type HbbftConsensusOutcome = hbbft::honey_badger::Batch<Vec<ConsensusItem>, PeerId>;
 pub type HbbftMessage = hbbft::honey_badger::Message<PeerId>;
 
-/// How many txs can be stored in memory before blocking the API
+/// How many API events can be queued before blocking the API
 const TRANSACTION_BUFFER_SIZE: usize = 1000;
 
+/// How many epochs pass between two checkpoints of the federation state
+const EPOCH_CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// How often we store a snapshot of our consensus state, snapshots are taken
+/// with checkpoints so this has to be a multiple of
+/// [`EPOCH_CHECKPOINT_INTERVAL`]
+///
+/// Copying the state is expensive, a lagging guardian replays the epochs after
+/// the latest snapshot instead.
+const STATE_SNAPSHOT_INTERVAL: u64 = 10 * EPOCH_CHECKPOINT_INTERVAL;
+
+/// How many epochs pass between two hashes of our consensus state, hashing
+/// reads the whole state
+pub const STATE_HASH_INTERVAL: u64 = 100;
+
+/// How many epochs we keep our state hashes for, peers contribute their hash
+/// of an epoch in one of the following epochs
+const STATE_HASH_RETENTION: u64 = 16;
+
+/// Newest version of the consensus rules this binary implements, see
+/// [`CORE_CONSENSUS_VERSION`]
+pub const CONSENSUS_VERSION: u32 = CORE_CONSENSUS_VERSION.0;
+
 // TODO remove HBBFT `Batch` from `ConsensusOutcome`
 #[derive(Debug, Clone)]
 pub struct ConsensusOutcomeConversion(pub HbbftConsensusOutcome);
@@ -83,8 +136,13 @@ pub struct ConsensusProposal {
 /// Events that can be sent from the API to consensus thread
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub enum ApiEvent {
-    Transaction(Transaction),
+    /// A transaction was added to the mempool, only wakes up consensus
+    Transaction(TransactionId),
     UpgradeSignal,
+    PeerSetChange(PeerSetChange),
+    ConsensusVersionActivation(ConsensusVersionActivation),
+    ConsensusParams(ConsensusParams),
+    ParamsChangeProposal(ParamsChangeProposal),
 }
 
 // TODO: we should make other fields private and get rid of this
@@ -110,6 +168,52 @@ pub struct FedimintConsensus {
     /// Cache of `ApiEvent` to include in a proposal
     // TODO should be able to eventually remove this Mutex
     pub api_event_cache: Mutex<HashSet<ApiEvent>>,
+
+    /// Submitted transactions waiting to be proposed
+    pub mempool: Mutex<Mempool>,
+
+    /// Connection status of our peers, set once the networking layer is
+    /// started
+    pub connection_status: PeerConnectionStatusMap,
+
+    /// Traffic exchanged with our peers, set once the networking layer is
+    /// started
+    pub peer_traffic: PeerTrafficMap,
+
+    /// Connection health of our peers, set once the networking layer is
+    /// started
+    pub peer_health: PeerHealthMap,
+
+    /// Reconnects or bans our peers on request of our operator, set once the
+    /// networking layer is started
+    pub peer_controls: PeerControls,
+
+    /// Directory our config files were read from, if it is set they are
+    /// included in backups
+    pub config_dir: Option<PathBuf>,
+
+    /// Signs epochs, checkpoints, snapshots and audits with our epoch key
+    /// share, which may be held outside of the config
+    pub epoch_signer: Arc<dyn EpochSigner>,
+
+    /// Last epoch each peer contributed to and when we processed it
+    last_contributions: Mutex<BTreeMap<PeerId, (u64, SystemTime)>>,
+
+    /// Result of the last background integrity check
+    last_integrity_report: Mutex<Option<IntegrityReport>>,
+
+    /// Latest state hash of every peer that differed from ours
+    state_hash_divergences: Mutex<BTreeMap<PeerId, StateHashDivergence>>,
+
+    /// Audit summary we signed last, it only changes with the epoch count
+    audit_summary: tokio::sync::Mutex<Option<SignedAuditSummary>>,
+
+    /// Module items that exceeded the batch limits of our last proposal, they
+    /// are proposed before the other items of their module next time
+    deferred_module_items: Mutex<HashSet<DynModuleConsensusItem>>,
+
+    /// Budget of the explorer endpoints, which decode whole epochs
+    pub explorer_limiter: ExplorerLimiter,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
@@ -151,6 +255,10 @@ impl FedimintConsensus {
 
         let env = Self::get_env_vars_map();
 
+        // The global stores keep their version under the root version key, every
+        // module instance tracks its own in its isolated key space
+        let mut namespaces = DbPrefixRegistry::new();
+        namespaces.register_prefixes("Global", global_db_prefixes())?;
         apply_migrations(
             &db,
             "Global".to_string(),
@@ -168,14 +276,15 @@ impl FedimintConsensus {
             info!(target: LOG_CORE,
                 module_instance_id = *module_id, kind = %kind, "Init module");
 
-            let isolated_db = db.new_isolated(*module_id);
-            apply_migrations(
-                &isolated_db,
-                init.module_kind().to_string(),
-                init.database_version(),
-                init.get_database_migrations(),
-            )
-            .await?;
+            let namespace = namespaces.register_module(*module_id, kind.as_str())?;
+            let isolated_db = namespace.database(&db);
+            namespace
+                .apply_migrations(
+                    &isolated_db,
+                    init.database_version(),
+                    init.get_database_migrations(),
+                )
+                .await?;
 
             let module = init
                 .init(
@@ -187,6 +296,7 @@ impl FedimintConsensus {
                 .await?;
             modules.insert(*module_id, module);
         }
+        namespaces.verify(&db).await?;
 
         let (api_sender, api_receiver) = mpsc::channel(TRANSACTION_BUFFER_SIZE);
         let client_cfg = cfg.consensus.to_config_response(&module_inits);
@@ -194,12 +304,25 @@ impl FedimintConsensus {
         Ok((
             Self {
                 modules: ModuleRegistry::from(modules),
+                mempool: Mutex::new(Mempool::new(cfg.local.mempool_limits.clone())),
+                explorer_limiter: ExplorerLimiter::new(&cfg.local.api_limits, Instant::now()),
+                epoch_signer: local_epoch_signer(&cfg),
                 cfg,
                 client_cfg,
                 module_inits,
                 db,
                 api_sender,
                 api_event_cache: Default::default(),
+                connection_status: Default::default(),
+                peer_traffic: Default::default(),
+                peer_health: Default::default(),
+                peer_controls: Default::default(),
+                config_dir: None,
+                last_contributions: Default::default(),
+                last_integrity_report: Default::default(),
+                state_hash_divergences: Default::default(),
+                audit_summary: Default::default(),
+                deferred_module_items: Default::default(),
             },
             api_receiver,
         ))
@@ -218,12 +341,25 @@ impl FedimintConsensus {
         (
             Self {
                 modules,
+                mempool: Mutex::new(Mempool::new(cfg.local.mempool_limits.clone())),
+                explorer_limiter: ExplorerLimiter::new(&cfg.local.api_limits, Instant::now()),
+                epoch_signer: local_epoch_signer(&cfg),
                 cfg,
                 client_cfg,
                 module_inits,
                 db,
                 api_sender,
                 api_event_cache: Default::default(),
+                connection_status: Default::default(),
+                peer_traffic: Default::default(),
+                peer_health: Default::default(),
+                peer_controls: Default::default(),
+                config_dir: None,
+                last_contributions: Default::default(),
+                last_integrity_report: Default::default(),
+                state_hash_divergences: Default::default(),
+                audit_summary: Default::default(),
+                deferred_module_items: Default::default(),
             },
             api_receiver,
         )
@@ -243,6 +379,7 @@ impl FedimintConsensus {
         self.modules.decoder_registry()
     }
 
+    #[instrument(skip_all, fields(txid = %transaction.tx_hash()))]
     pub async fn submit_transaction(
         &self,
         transaction: Transaction,
@@ -259,6 +396,10 @@ impl FedimintConsensus {
         let tx_hash = transaction.tx_hash();
         debug!(%tx_hash, "Received mint transaction");
 
+        self.consensus_limits()
+            .await
+            .check_transaction(&transaction)?;
+
         let mut funding_verifier = FundingVerifier::default();
 
         let mut pub_keys = Vec::new();
@@ -281,10 +422,10 @@ impl FedimintConsensus {
                 .await
                 .map_err(|e| TransactionSubmissionError::ModuleError(tx_hash, e))?;
 
-            pub_keys.push(meta.puk_keys);
+            pub_keys.extend(meta.puk_keys);
             funding_verifier.add_input(meta.amount);
         }
-        transaction.validate_signature(pub_keys.into_iter().flatten())?;
+        transaction.validate_signature(pub_keys.iter().copied())?;
 
         for output in &transaction.outputs {
             let amount = self
@@ -299,13 +440,103 @@ impl FedimintConsensus {
             funding_verifier.add_output(amount);
         }
 
+        let fee = funding_verifier.fee_amount;
         funding_verifier.verify_funding()?;
 
-        self.api_sender
-            .send(ApiEvent::Transaction(transaction))
-            .await
-            .map_err(|_e| TransactionSubmissionError::TxChannelError)?;
-        Ok(())
+        self.mempool
+            .lock()
+            .expect("locks")
+            .insert(transaction, pub_keys, fee)?;
+
+        // if the channel is full consensus will wake up anyway
+        match self.api_sender.try_send(ApiEvent::Transaction(tx_hash)) {
+            Err(TrySendError::Closed(_)) => Err(TransactionSubmissionError::TxChannelError),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validates `transaction` against our current state like
+    /// [`Self::submit_transaction`] without submitting it, collecting the
+    /// errors of all inputs and outputs instead of stopping at the first one
+    pub async fn validate_transaction(&self, transaction: &Transaction) -> TransactionValidation {
+        let tx_hash = transaction.tx_hash();
+        let mut validation = TransactionValidation::default();
+
+        if self.transaction_status(tx_hash).await.is_some() {
+            validation
+                .transaction_errors
+                .push(TransactionReplayError(tx_hash).to_string());
+        }
+
+        if let Err(e) = self.consensus_limits().await.check_transaction(transaction) {
+            validation.transaction_errors.push(e.to_string());
+        }
+
+        let mut funding_verifier = FundingVerifier::default();
+        let mut pub_keys = Vec::new();
+
+        // Create read-only DB tx so that the read state is consistent
+        let mut dbtx = self.db.begin_transaction().await;
+
+        for (idx, input) in transaction.inputs.iter().enumerate() {
+            let module = self.modules.get_expect(input.module_instance_id());
+
+            let cache = module.build_verification_cache(&[input.clone()]);
+            let interconnect = self.build_interconnect();
+            match module
+                .validate_input(
+                    &interconnect,
+                    &mut dbtx.with_module_prefix(input.module_instance_id()),
+                    &cache,
+                    input,
+                )
+                .await
+            {
+                Ok(meta) => {
+                    pub_keys.push(meta.puk_keys);
+                    funding_verifier.add_input(meta.amount);
+                }
+                Err(e) => {
+                    if matches!(e, ModuleError::Conflict(_)) {
+                        validation.spent_inputs.insert(idx as u64);
+                    }
+                    validation.input_errors.insert(idx as u64, e.to_string());
+                }
+            }
+        }
+
+        for (idx, output) in transaction.outputs.iter().enumerate() {
+            match self
+                .modules
+                .get_expect(output.module_instance_id())
+                .validate_output(
+                    &mut dbtx.with_module_prefix(output.module_instance_id()),
+                    output,
+                )
+                .await
+            {
+                Ok(amount) => funding_verifier.add_output(amount),
+                Err(e) => {
+                    validation.output_errors.insert(idx as u64, e.to_string());
+                }
+            }
+        }
+
+        // the signature and funding can only be checked against the keys and
+        // amounts of valid inputs and outputs
+        if validation.input_errors.is_empty() {
+            if let Err(e) = transaction.validate_signature(pub_keys.into_iter().flatten()) {
+                validation.transaction_errors.push(e.to_string());
+            }
+
+            if validation.output_errors.is_empty() {
+                if let Err(e) = funding_verifier.verify_funding() {
+                    validation.transaction_errors.push(e.to_string());
+                }
+            }
+        }
+
+        validation
     }
 
     /// Calculate the result of the `consensus_outcome` and save it/them.
@@ -318,13 +549,16 @@ impl FedimintConsensus {
     /// **Note**: `reference_rejected_txs` **must** come from a
     /// validated/trustworthy source and be correct, or it can cause a
     /// panic.
+    ///
+    /// Nothing is processed if the federation activated a consensus version
+    /// this binary doesn't support at or before the epoch of the outcome.
     #[instrument(skip_all, fields(epoch = consensus_outcome.epoch))]
     pub async fn process_consensus_outcome(
         &self,
         consensus_outcome: HbbftConsensusOutcome,
         reference_rejected_txs: Option<BTreeSet<TransactionId>>,
-    ) -> SignedEpochOutcome {
-        let epoch_history = self
+    ) -> Result<SignedEpochOutcome, UnsupportedConsensusVersion> {
+        let result = self
             .db
             .autocommit(
                 |dbtx| {
@@ -335,23 +569,63 @@ impl FedimintConsensus {
                         let epoch = consensus_outcome.epoch;
                         let outcome = consensus_outcome.clone();
 
+                        // Processing the epoch with outdated consensus rules could diverge
+                        // from the rest of the federation
+                        if let Some((version, activation_epoch)) =
+                            unsupported_consensus_version(dbtx, epoch).await
+                        {
+                            return Err(UnsupportedConsensusVersion {
+                                version,
+                                activation_epoch,
+                            });
+                        }
+
                         let UnzipConsensusItem {
                             epoch_outcome_signature_share: _epoch_outcome_signature_share_cis,
                             client_config_signature_share: _client_config_signature_share_cis,
                             transaction: transaction_cis,
                             consensus_upgrade: consensus_upgrade_cis,
                             module: module_cis,
+                            peer_set_change: peer_set_change_cis,
+                            epoch_checkpoint_signature_share: _epoch_checkpoint_signature_share_cis,
+                            supported_consensus_version: supported_consensus_version_cis,
+                            consensus_version_activation: consensus_version_activation_cis,
+                            consensus_params: consensus_params_cis,
+                            params_change_proposal: params_change_proposal_cis,
+                            state_hash: state_hash_cis,
                         } = consensus_outcome
                             .contributions
                             .into_iter()
                             .flat_map(|(peer, cis)| cis.into_iter().map(move |ci| (peer, ci)))
                             .unzip_consensus_item();
 
-                        self.process_module_consensus_items(dbtx, &module_cis).await;
+                        // changes proposed in this epoch activate in a later one
+                        let limits = self.consensus_limits_at(dbtx, epoch).await;
+
+                        self.activate_consensus_versions(dbtx, epoch).await;
+                        self.activate_params_changes(dbtx, epoch).await;
+
+                        self.process_module_consensus_items(dbtx, &limits, &module_cis)
+                            .await;
                         self.process_upgrade_items(dbtx, &consensus_upgrade_cis).await;
+                        self.process_peer_set_change_items(dbtx, epoch, &peer_set_change_cis)
+                            .await;
+                        self.process_consensus_version_items(
+                            dbtx,
+                            epoch,
+                            &supported_consensus_version_cis,
+                            &consensus_version_activation_cis,
+                        )
+                        .await;
+                        self.process_consensus_params_items(dbtx, &consensus_params_cis)
+                            .await;
+                        self.process_params_change_items(dbtx, epoch, &params_change_proposal_cis)
+                            .await;
+                        let state_hash_checks =
+                            self.check_state_hash_items(dbtx, epoch, &state_hash_cis).await;
 
                         let rejected_txs = self
-                            .process_transactions(dbtx, epoch, &transaction_cis)
+                            .process_transactions(dbtx, epoch, &limits, &transaction_cis)
                             .await;
 
                         if let Some(reference_rejected_txs) = reference_rejected_txs.as_ref() {
@@ -365,23 +639,76 @@ impl FedimintConsensus {
                             );
                         }
 
+                        let num_rejected_txs = rejected_txs.len();
                         let epoch_history = self
                             .finalize_process_epoch(dbtx, outcome.clone(), rejected_txs)
                             .await;
-                        Result::<_, ()>::Ok(epoch_history)
+                        Ok((
+                            epoch_history,
+                            num_rejected_txs,
+                            dbtx.num_writes(),
+                            state_hash_checks,
+                        ))
                     })
                 },
                 Some(100),
             )
-            .await
-            .expect("Committing consensus epoch failed");
+            .await;
+        let (epoch_history, num_rejected_txs, num_writes, state_hash_checks) = match result {
+            Ok(processed) => processed,
+            Err(AutocommitError::ClosureError { error, .. }) => return Err(error),
+            Err(AutocommitError::CommitFailed { last_error, .. }) => {
+                panic!("Committing consensus epoch failed: {last_error}")
+            }
+        };
+
+        metrics::REJECTED_ITEMS
+            .with_label_values(&["transaction"])
+            .inc_by(num_rejected_txs as u64);
+        metrics::EPOCH_DB_WRITES.observe(num_writes as f64);
+        self.record_state_hash_checks(state_hash_checks);
+
+        {
+            let now = SystemTime::now();
+            let mut last_contributions = self.last_contributions.lock().expect("locks");
+            for peer in consensus_outcome.contributions.keys() {
+                last_contributions.insert(*peer, (consensus_outcome.epoch, now));
+            }
+        }
 
         let audit = self.audit().await;
         if audit.sum().milli_sat < 0 {
             panic!("Balance sheet of the fed has gone negative, this should never happen! {audit}")
         }
 
-        epoch_history
+        Ok(epoch_history)
+    }
+
+    /// Lets the modules switch to the rules of the consensus versions that
+    /// activate in `epoch`
+    async fn activate_consensus_versions(&self, dbtx: &mut DatabaseTransaction<'_>, epoch: u64) {
+        let activated = dbtx
+            .find_by_prefix(&ScheduledConsensusVersionKeyPrefix)
+            .await
+            .map(|(key, activation_epoch)| (key.0, activation_epoch))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter(|(_, activation_epoch)| *activation_epoch == epoch)
+            .map(|(version, _)| version)
+            .sorted();
+
+        for version in activated {
+            info!(target: LOG_CONSENSUS, version, "Activating consensus version");
+            for (module_instance_id, module) in self.modules.iter_modules() {
+                module
+                    .activate_consensus_version(
+                        &mut dbtx.with_module_prefix(module_instance_id),
+                        CoreConsensusVersion(version),
+                    )
+                    .await;
+            }
+        }
     }
 
     /// Calls `begin_consensus_epoch` on all modules, dispatching their
@@ -389,6 +716,7 @@ impl FedimintConsensus {
     async fn process_module_consensus_items(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
+        limits: &ConsensusLimits,
         module_cis: &[(PeerId, fedimint_core::core::DynModuleConsensusItem)],
     ) {
         let per_module_cis: HashMap<
@@ -396,17 +724,53 @@ impl FedimintConsensus {
             Vec<(PeerId, fedimint_core::core::DynModuleConsensusItem)>,
         > = module_cis
             .iter()
+            .filter(|(peer, mci)| match limits.check_module_item(mci) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!(target: LOG_CONSENSUS, %peer, "Dropping consensus item: {e}");
+                    metrics::REJECTED_ITEMS.with_label_values(&["module"]).inc();
+                    false
+                }
+            })
             .cloned()
             .into_group_map_by(|(_peer, mci)| mci.module_instance_id());
 
-        for (module_key, module_cis) in per_module_cis {
-            self.modules
-                .get_expect(module_key)
-                .begin_consensus_epoch(&mut dbtx.with_module_prefix(module_key), module_cis)
-                .await;
+        for stage in self.epoch_stages() {
+            let shared_dbtx = dbtx.share_between_modules();
+            let begin_epochs = stage.into_iter().filter_map(|module_key| {
+                let module_cis = per_module_cis.get(&module_key)?.clone();
+                let mut module_dbtx = shared_dbtx.module_tx(module_key);
+                Some(async move {
+                    let _timer = metrics::MODULE_PROCESSING_SECONDS
+                        .with_label_values(&[
+                            module_key.to_string().as_str(),
+                            "begin_consensus_epoch",
+                        ])
+                        .start_timer();
+                    self.modules
+                        .get_expect(module_key)
+                        .begin_consensus_epoch(&mut module_dbtx.dbtx(), module_cis)
+                        .instrument(info_span!("Beginning module epoch", module = module_key))
+                        .await;
+                })
+            });
+            join_all(begin_epochs).await;
         }
     }
 
+    /// Stages in which the modules begin and end epochs, the modules of a
+    /// stage concurrently, see [`stages::epoch_stages`]
+    fn epoch_stages(&self) -> Vec<Vec<ModuleInstanceId>> {
+        let kinds = self
+            .modules
+            .iter_modules()
+            .map(|(id, _)| (id, self.cfg.consensus.modules[&id].kind().clone()))
+            .collect();
+        stages::epoch_stages(&kinds, |id| {
+            self.modules.get_expect(id).epoch_dependencies()
+        })
+    }
+
     /// Applies all valid fedimint transactions to the database transaction
     /// `dbtx` and returns a set of invalid transactions that were filtered
     /// out
@@ -414,6 +778,7 @@ impl FedimintConsensus {
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
         epoch: u64,
+        limits: &ConsensusLimits,
         transactions: &[(PeerId, Transaction)],
     ) -> BTreeSet<TransactionId> {
         // Process transactions
@@ -422,25 +787,24 @@ impl FedimintConsensus {
         let caches = self.build_verification_caches(transactions.iter().map(|(_, tx)| tx));
         let mut processed_txs: HashSet<TransactionId> = HashSet::new();
 
-        for (_, transaction) in transactions.iter().cloned() {
+        for (peer, transaction) in transactions.iter().cloned() {
             let txid: TransactionId = transaction.tx_hash();
             if !processed_txs.insert(txid) {
                 // Avoid processing duplicate tx from different peers
                 continue;
             }
 
-            let span = info_span!("Processing transaction");
+            let span = info_span!("Processing transaction", %txid, peer_id = %peer);
             async {
                 trace!(?transaction);
-                let event = ApiEvent::Transaction(transaction.clone());
-                self.api_event_cache.lock().unwrap().remove(&event);
+                self.mempool.lock().expect("locks").remove(&txid);
 
                 dbtx.set_tx_savepoint()
                     .await
                     .expect("Error setting transaction savepoint");
                 // TODO: use borrowed transaction
                 match self
-                    .process_transaction(dbtx, transaction.clone(), &caches)
+                    .process_transaction(dbtx, transaction.clone(), limits, &caches)
                     .await
                 {
                     Ok(()) => {
@@ -491,110 +855,507 @@ impl FedimintConsensus {
             .save_epoch_history(outcome.clone(), dbtx, &mut drop_peers, rejected_txs)
             .await;
 
-        for (module_key, module) in self.modules.iter_modules() {
-            let module_drop_peers = module
-                .end_consensus_epoch(&epoch_peers, &mut dbtx.with_module_prefix(module_key))
-                .await;
-            drop_peers.extend(module_drop_peers);
+        for stage in self.epoch_stages() {
+            let shared_dbtx = dbtx.share_between_modules();
+            let epoch_peers = &epoch_peers;
+            let end_epochs = stage.into_iter().map(|module_key| {
+                let mut module_dbtx = shared_dbtx.module_tx(module_key);
+                async move {
+                    let _timer = metrics::MODULE_PROCESSING_SECONDS
+                        .with_label_values(&[
+                            module_key.to_string().as_str(),
+                            "end_consensus_epoch",
+                        ])
+                        .start_timer();
+                    self.modules
+                        .get_expect(module_key)
+                        .end_consensus_epoch(epoch_peers, &mut module_dbtx.dbtx())
+                        .instrument(info_span!("Ending module epoch", module = module_key))
+                        .await
+                }
+            });
+            // the results keep the order of the modules, so every peer drops the
+            // same peers in the same order
+            drop_peers.extend(join_all(end_epochs).await.into_iter().flatten());
         }
 
+        self.save_epoch_checkpoint(dbtx, &outcome, &epoch_history)
+            .await;
+
         for peer in drop_peers {
             dbtx.insert_entry(&DropPeerKey(peer), &()).await;
         }
 
+        self.save_state_hash(dbtx, epoch_history.outcome.epoch)
+            .await;
+
         epoch_history
     }
 
-    /// If the client config hash isn't already signed, aggregate signature
-    /// shares from peers dropping those that don't contribute.
-    async fn save_client_config_sig(
-        &self,
-        dbtx: &mut DatabaseTransaction<'_>,
-        outcome: &HbbftConsensusOutcome,
-        drop_peers: &mut Vec<PeerId>,
-    ) {
-        let config = self.get_config_with_sig(&mut dbtx.get_isolated()).await;
+    /// Stores the hash of our consensus state after every
+    /// [`STATE_HASH_INTERVAL`]th epoch, which we contribute to the next epoch
+    ///
+    /// Has to run after everything else the epoch writes. Only hashes once the
+    /// federation runs [`STATE_HASH_VERSION`] in the next epoch, before that
+    /// we couldn't contribute the hash.
+    async fn save_state_hash(&self, dbtx: &mut DatabaseTransaction<'_>, epoch: u64) {
+        if (epoch + 1) % STATE_HASH_INTERVAL == 0
+            && consensus_version_at(dbtx, epoch + 1).await >= STATE_HASH_VERSION
+        {
+            let hash = consensus_state_hash(dbtx, &self.module_consensus_key_prefixes()).await;
+            dbtx.insert_entry(&StateHashKey(epoch), &hash).await;
+        }
 
-        if config.client_hash_signature.is_none() {
-            let maybe_client_hash = config.client.consensus_hash(&self.module_inits.to_common());
-            let client_hash = maybe_client_hash.expect("hashes");
-            let peers: Vec<PeerId> = outcome.contributions.keys().cloned().collect();
-            let mut contributing_peers = HashSet::new();
-            let pks = self.cfg.consensus.auth_pk_set.clone();
+        let stale_hashes = dbtx
+            .find_by_prefix(&StateHashKeyPrefix)
+            .await
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        for key in stale_hashes
+            .into_iter()
+            .filter(|key| key.0 + STATE_HASH_RETENTION <= epoch)
+        {
+            dbtx.remove_entry(&key).await;
+        }
+    }
 
-            let shares: BTreeMap<_, _> = outcome
-                .contributions
-                .iter()
-                .flat_map(|(peer, items)| items.iter().map(|i| (*peer, i)))
-                .filter_map(|(peer, item)| match item {
-                    ConsensusItem::ClientConfigSignatureShare(SerdeSignatureShare(sig)) => {
-                        Some((peer, sig))
-                    }
-                    _ => None,
-                })
-                .filter(|(peer, sig)| {
-                    let pub_key = pks.public_key_share(peer.to_usize());
-                    pub_key.verify(sig, client_hash)
-                })
-                .map(|(peer, sig)| {
-                    contributing_peers.insert(peer);
-                    (peer.to_usize(), sig)
+    /// Key prefixes of the entries our modules keep for themselves, which
+    /// differ between guardians
+    fn module_local_key_prefixes(&self) -> Vec<Vec<u8>> {
+        self.module_key_prefixes(|module| module.local_db_prefixes())
+    }
+
+    /// Key prefixes of the consensus state of our modules, which is hashed into
+    /// the state hash
+    fn module_consensus_key_prefixes(&self) -> Vec<Vec<u8>> {
+        self.module_key_prefixes(|module| module.consensus_db_prefixes())
+    }
+
+    /// Prepends the key space of each module instance to the `prefixes` of
+    /// its module
+    fn module_key_prefixes(&self, prefixes: impl Fn(&DynServerModule) -> Vec<u8>) -> Vec<Vec<u8>> {
+        self.modules
+            .iter_modules()
+            .flat_map(|(module_instance_id, module)| {
+                prefixes(module).into_iter().map(move |prefix| {
+                    let mut key_prefix = vec![MODULE_GLOBAL_PREFIX];
+                    module_instance_id
+                        .consensus_encode(&mut key_prefix)
+                        .expect("Encodes");
+                    key_prefix.push(prefix);
+                    key_prefix
                 })
-                .collect();
+            })
+            .collect()
+    }
 
-            if let Ok(final_sig) = pks.combine_signatures(shares) {
-                assert!(pks.public_key().verify(&final_sig, client_hash));
-                let serde_sig = SerdeSignature(final_sig);
-                dbtx.insert_entry(&ClientConfigSignatureKey, &serde_sig)
-                    .await;
-            } else {
-                warn!(
-                    target: LOG_CONSENSUS,
-                    "Did not receive enough valid client config sig shares"
-                )
+    /// Pairs the state hashes our peers contributed with our hash of the same
+    /// epoch, skipping epochs we have no hash of
+    ///
+    /// Hashes are ignored until the federation runs [`STATE_HASH_VERSION`].
+    async fn check_state_hash_items(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        epoch: u64,
+        state_hash_cis: &[(PeerId, StateHash)],
+    ) -> Vec<(PeerId, StateHash, sha256::Hash)> {
+        let mut checks = vec![];
+        if consensus_version_at(dbtx, epoch).await < STATE_HASH_VERSION {
+            return checks;
+        }
+        for (peer, theirs) in state_hash_cis {
+            if *peer == self.cfg.local.identity {
+                continue;
+            }
+            if let Some(ours) = dbtx.get_value(&StateHashKey(theirs.epoch)).await {
+                checks.push((*peer, *theirs, ours));
             }
+        }
+        checks
+    }
 
-            for peer in peers {
-                if !contributing_peers.contains(&peer) {
-                    drop_peers.push(peer);
-                }
+    /// Raises an alarm for every peer whose state hash differs from ours,
+    /// runs once the epoch that contained the hashes is committed
+    fn record_state_hash_checks(&self, checks: Vec<(PeerId, StateHash, sha256::Hash)>) {
+        let now = SystemTime::now();
+        let mut divergences = self.state_hash_divergences.lock().expect("locks");
+        for (peer, theirs, ours) in checks {
+            let peer_label = peer.to_string();
+            let diverged = theirs.hash != ours;
+            metrics::STATE_HASH_DIVERGED
+                .with_label_values(&[peer_label.as_str()])
+                .set(i64::from(diverged));
+            if !diverged {
+                continue;
             }
+
+            error!(
+                target: LOG_CONSENSUS,
+                %peer,
+                epoch = theirs.epoch,
+                %ours,
+                theirs = %theirs.hash,
+                "State of peer diverged from ours"
+            );
+            metrics::STATE_HASH_DIVERGENCES
+                .with_label_values(&[peer_label.as_str()])
+                .inc();
+            divergences.insert(
+                peer,
+                StateHashDivergence {
+                    epoch: theirs.epoch,
+                    ours,
+                    theirs: theirs.hash,
+                    detected_at: now,
+                },
+            );
         }
     }
 
-    /// Adds any new upgrade items to the set of signaling peers
-    async fn process_upgrade_items(
+    /// Aggregates the signature shares for the pending checkpoint and creates
+    /// a new checkpoint every `EPOCH_CHECKPOINT_INTERVAL` epochs
+    ///
+    /// Has to run after the modules ended the epoch so the checkpoint contains
+    /// the state after `epoch_history`.
+    async fn save_epoch_checkpoint(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
-        upgrade_signals: &[(PeerId, ConsensusUpgrade)],
+        outcome: &HbbftConsensusOutcome,
+        epoch_history: &SignedEpochOutcome,
     ) {
-        if !upgrade_signals.is_empty() {
-            let maybe_exists = dbtx.get_value(&ConsensusUpgradeKey).await;
-            let mut peers = maybe_exists.unwrap_or_default();
-            peers.extend(upgrade_signals.iter().map(|(peer, _)| peer));
-            dbtx.insert_entry(&ConsensusUpgradeKey, &peers).await;
+        if let Some(mut pending) = Self::pending_epoch_checkpoint(dbtx).await {
+            let pks = &self.cfg.consensus.epoch_pk_set;
+            let epoch = pending.checkpoint.epoch;
 
-            // Remove our upgrade signal event if we signaled
-            if peers.contains(&self.cfg.local.identity) {
-                let mut cache = self.api_event_cache.lock().expect("locks");
-                cache.remove(&ApiEvent::UpgradeSignal);
+            // Peers that didn't process the checkpoint epoch yet can't sign it, so
+            // we keep it pending instead of dropping them
+            if pending.add_sig(pks, &outcome.contributions).is_ok() {
+                info!(target: LOG_CONSENSUS, "Signed checkpoint of epoch {}", epoch);
+                dbtx.insert_entry(&EpochCheckpointKey(epoch), &pending)
+                    .await;
+
+                self.prune_epoch_history(dbtx, epoch).await;
+            }
+        }
+
+        let epoch = epoch_history.outcome.epoch;
+        if (epoch + 1) % EPOCH_CHECKPOINT_INTERVAL == 0 {
+            let mut modules = BTreeMap::new();
+            for (module_instance_id, module) in self.modules.iter_modules() {
+                let state = module
+                    .checkpoint(&mut dbtx.with_module_prefix(module_instance_id))
+                    .await;
+                if let Some(state) = state {
+                    modules.insert(module_instance_id, state);
+                }
+            }
+
+            // A checkpoint that didn't get signed until now is superseded, but we
+            // keep it since the new one only contains the changes after it
+            let previous = Self::last_epoch_checkpoint(dbtx).await;
+            if let Some(stale) = previous.as_ref().filter(|last| last.signature.is_none()) {
+                warn!(
+                    target: LOG_CONSENSUS,
+                    "Checkpoint of epoch {} was never signed", stale.checkpoint.epoch
+                );
+            }
+
+            let checkpoint = SignedEpochCheckpoint::new(EpochCheckpoint {
+                epoch,
+                epoch_hash: epoch_history.hash,
+                previous: previous.map(|previous| (previous.checkpoint.epoch, previous.hash)),
+                modules,
+            });
+            dbtx.insert_entry(&EpochCheckpointKey(epoch), &checkpoint)
+                .await;
+
+            if (epoch + 1) % STATE_SNAPSHOT_INTERVAL == 0 {
+                self.save_state_snapshot(dbtx, epoch).await;
             }
         }
     }
 
-    /// Returns true if a threshold of peers have signaled to upgrade
-    pub async fn is_at_upgrade_threshold(&self) -> bool {
-        self.db
-            .begin_transaction()
-            .await
-            .get_value(&ConsensusUpgradeKey)
+    /// Stores a snapshot of our consensus state after `epoch`, guardians that
+    /// fell behind can sync from it instead of replaying the epoch history
+    async fn save_state_snapshot(&self, dbtx: &mut DatabaseTransaction<'_>, epoch: u64) {
+        let module_local_prefixes = self.module_local_key_prefixes();
+        let epoch_history_key = EpochHistoryKey(epoch).to_bytes();
+        let entries = dbtx
+            .raw_find_by_prefix(&[])
             .await
-            .filter(|peers| peers.len() >= self.cfg.consensus.api_endpoints.threshold())
-            .is_some()
+            .into_iter()
+            .filter(|(key, _)| {
+                *key == epoch_history_key || is_consensus_key(key, &module_local_prefixes)
+            })
+            .collect();
+
+        dbtx.insert_entry(&StateSnapshotKey, &StateSnapshot { epoch, entries })
+            .await;
     }
 
-    /// Sends an upgrade signal to the fedimint server thread
-    pub async fn signal_upgrade(&self) -> Result<(), SendError<ApiEvent>> {
+    /// Returns our signature share over our latest state snapshot
+    pub async fn state_snapshot_share(&self) -> Option<StateSnapshotShare> {
+        let snapshot = self
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&StateSnapshotKey)
+            .await?;
+        let hash = snapshot.consensus_hash().expect("Hashes");
+
+        Some(StateSnapshotShare {
+            epoch: snapshot.epoch,
+            hash,
+            signature: self.sign_epoch_hash(hash).await?,
+        })
+    }
+
+    /// Signs `hash` with our epoch key share, logs if the signer failed
+    async fn sign_epoch_hash(&self, hash: sha256::Hash) -> Option<SerdeSignatureShare> {
+        match self.epoch_signer.sign(hash).await {
+            Ok(signature) => Some(SerdeSignatureShare(signature)),
+            Err(e) => {
+                error!(target: LOG_CONSENSUS, %hash, "Epoch signer failed: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// Returns our latest state snapshot if it was taken after `epoch`
+    pub async fn state_snapshot(&self, epoch: u64) -> Option<StateSnapshot> {
+        self.db
+            .begin_transaction()
+            .await
+            .get_value(&StateSnapshotKey)
+            .await
+            .filter(|snapshot| snapshot.epoch == epoch)
+    }
+
+    /// Replaces our consensus state with a snapshot signed by the federation,
+    /// returning the epoch history of the snapshot's epoch to continue from
+    ///
+    /// Our epoch history is removed since it doesn't link up with the snapshot,
+    /// the entries our modules keep for themselves are kept.
+    pub async fn install_state_snapshot(
+        &self,
+        snapshot: StateSnapshot,
+    ) -> anyhow::Result<SignedEpochOutcome> {
+        let module_local_prefixes = self.module_local_key_prefixes();
+        let mut dbtx = self.db.begin_transaction().await;
+
+        for (key, _) in dbtx.raw_find_by_prefix(&[]).await {
+            if is_consensus_key(&key, &module_local_prefixes) {
+                dbtx.raw_remove_entry(&key).await;
+            }
+        }
+        dbtx.remove_by_prefix(&EpochHistoryKeyPrefix).await;
+
+        for (key, value) in &snapshot.entries {
+            dbtx.raw_insert_bytes(key, value.clone()).await;
+        }
+        dbtx.insert_entry(&EarliestEpochKey, &snapshot.epoch).await;
+
+        let epoch_history = dbtx
+            .get_value(&EpochHistoryKey(snapshot.epoch))
+            .await
+            .ok_or_else(|| format_err!("Snapshot is missing the epoch history"))?;
+        dbtx.insert_entry(&StateSnapshotKey, &snapshot).await;
+        dbtx.commit_tx_result().await?;
+
+        Ok(epoch_history)
+    }
+
+    /// Removes the epochs more than `epoch_retention` epochs before the signed
+    /// checkpoint of `checkpoint_epoch` from the epoch history, archives keep
+    /// all epochs
+    async fn prune_epoch_history(&self, dbtx: &mut DatabaseTransaction<'_>, checkpoint_epoch: u64) {
+        if self.cfg.local.archive {
+            return;
+        }
+
+        let retention = match self.cfg.local.epoch_retention {
+            Some(retention) => retention.max(MIN_EPOCHS_BEFORE_CHECKPOINT),
+            None => return,
+        };
+
+        let earliest_epoch = dbtx.get_value(&EarliestEpochKey).await.unwrap_or(0);
+        let prune_before = checkpoint_epoch.saturating_sub(retention);
+        if prune_before <= earliest_epoch {
+            return;
+        }
+
+        for epoch in earliest_epoch..prune_before {
+            dbtx.remove_entry(&EpochHistoryKey(epoch)).await;
+        }
+        dbtx.insert_entry(&EarliestEpochKey, &prune_before).await;
+
+        info!(
+            target: LOG_CONSENSUS,
+            "Pruned epochs {} to {} from the epoch history",
+            earliest_epoch,
+            prune_before - 1
+        );
+    }
+
+    /// Returns the checkpoint that still needs to be threshold signed
+    async fn pending_epoch_checkpoint(
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> Option<SignedEpochCheckpoint> {
+        Self::last_epoch_checkpoint(dbtx)
+            .await
+            .filter(|checkpoint| checkpoint.signature.is_none())
+    }
+
+    /// Returns the latest checkpoint, signed or not
+    async fn last_epoch_checkpoint(
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> Option<SignedEpochCheckpoint> {
+        dbtx.find_by_prefix(&EpochCheckpointKeyPrefix)
+            .await
+            .map(|(_, checkpoint)| checkpoint)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .max_by_key(|checkpoint| checkpoint.checkpoint.epoch)
+    }
+
+    /// Returns the epoch history we keep if we have the archive role
+    pub async fn epoch_archive_info(&self) -> Option<EpochArchiveInfo> {
+        if !self.cfg.local.archive {
+            return None;
+        }
+
+        // restoring from a state snapshot skips the epochs before it, even for
+        // archives
+        let earliest_epoch = self
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&EarliestEpochKey)
+            .await
+            .unwrap_or(0);
+        Some(EpochArchiveInfo {
+            earliest_epoch,
+            epoch_count: self.get_epoch_count().await,
+        })
+    }
+
+    /// Returns the latest threshold signed checkpoint clients can recover from
+    pub async fn latest_epoch_checkpoint(&self) -> Option<SignedEpochCheckpoint> {
+        self.db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&EpochCheckpointKeyPrefix)
+            .await
+            .map(|(_, checkpoint)| checkpoint)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter(|checkpoint| checkpoint.signature.is_some())
+            .max_by_key(|checkpoint| checkpoint.checkpoint.epoch)
+    }
+
+    /// Returns the checkpoint of `epoch`, which clients follow the links of
+    /// the later checkpoints to
+    pub async fn epoch_checkpoint(&self, epoch: u64) -> Option<SignedEpochCheckpoint> {
+        self.db
+            .begin_transaction()
+            .await
+            .get_value(&EpochCheckpointKey(epoch))
+            .await
+    }
+
+    /// If the client config hash isn't already signed, aggregate signature
+    /// shares from peers dropping those that don't contribute.
+    async fn save_client_config_sig(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        outcome: &HbbftConsensusOutcome,
+        drop_peers: &mut Vec<PeerId>,
+    ) {
+        let config = self.get_config_with_sig(&mut dbtx.get_isolated()).await;
+
+        if config.client_hash_signature.is_none() {
+            let maybe_client_hash = config.client.consensus_hash(&self.module_inits.to_common());
+            let client_hash = maybe_client_hash.expect("hashes");
+            let peers: Vec<PeerId> = outcome.contributions.keys().cloned().collect();
+            let mut contributing_peers = HashSet::new();
+            let pks = self.cfg.consensus.auth_pk_set.clone();
+
+            let shares: BTreeMap<_, _> = outcome
+                .contributions
+                .iter()
+                .flat_map(|(peer, items)| items.iter().map(|i| (*peer, i)))
+                .filter_map(|(peer, item)| match item {
+                    ConsensusItem::ClientConfigSignatureShare(SerdeSignatureShare(sig)) => {
+                        Some((peer, sig))
+                    }
+                    _ => None,
+                })
+                .filter(|(peer, sig)| {
+                    let pub_key = pks.public_key_share(peer.to_usize());
+                    pub_key.verify(sig, client_hash)
+                })
+                .map(|(peer, sig)| {
+                    contributing_peers.insert(peer);
+                    (peer.to_usize(), sig)
+                })
+                .collect();
+
+            if let Ok(final_sig) = pks.combine_signatures(shares) {
+                assert!(pks.public_key().verify(&final_sig, client_hash));
+                let serde_sig = SerdeSignature(final_sig);
+                dbtx.insert_entry(&ClientConfigSignatureKey, &serde_sig)
+                    .await;
+            } else {
+                warn!(
+                    target: LOG_CONSENSUS,
+                    "Did not receive enough valid client config sig shares"
+                )
+            }
+
+            for peer in peers {
+                if !contributing_peers.contains(&peer) {
+                    drop_peers.push(peer);
+                }
+            }
+        }
+    }
+
+    /// Adds any new upgrade items to the set of signaling peers
+    async fn process_upgrade_items(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        upgrade_signals: &[(PeerId, ConsensusUpgrade)],
+    ) {
+        if !upgrade_signals.is_empty() {
+            let maybe_exists = dbtx.get_value(&ConsensusUpgradeKey).await;
+            let mut peers = maybe_exists.unwrap_or_default();
+            peers.extend(upgrade_signals.iter().map(|(peer, _)| peer));
+            dbtx.insert_entry(&ConsensusUpgradeKey, &peers).await;
+
+            // Remove our upgrade signal event if we signaled
+            if peers.contains(&self.cfg.local.identity) {
+                let mut cache = self.api_event_cache.lock().expect("locks");
+                cache.remove(&ApiEvent::UpgradeSignal);
+            }
+        }
+    }
+
+    /// Returns true if a threshold of peers have signaled to upgrade
+    pub async fn is_at_upgrade_threshold(&self) -> bool {
+        self.db
+            .begin_transaction()
+            .await
+            .get_value(&ConsensusUpgradeKey)
+            .await
+            .filter(|peers| peers.len() >= self.cfg.consensus.api_endpoints.threshold())
+            .is_some()
+    }
+
+    /// Sends an upgrade signal to the fedimint server thread
+    pub async fn signal_upgrade(&self) -> Result<(), SendError<ApiEvent>> {
         self.api_sender.send(ApiEvent::UpgradeSignal).await
     }
 
@@ -615,6 +1376,492 @@ impl FedimintConsensus {
         }
     }
 
+    /// Records the votes for changing the guardians and approves a change once
+    /// a threshold of peers voted for it
+    ///
+    /// Votes are only counted if they change our current set of guardians, so
+    /// peers replaying the history of the federation don't act on changes
+    /// that were already applied.
+    async fn process_peer_set_change_items(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        epoch: u64,
+        votes: &[(PeerId, PeerSetChange)],
+    ) {
+        let current = &self.cfg.consensus.api_endpoints;
+        let votes = votes
+            .iter()
+            .filter(|(_, change)| change.from == *current && change.to != *current)
+            .collect::<Vec<_>>();
+
+        if votes.is_empty() || dbtx.get_value(&ApprovedPeerSetChangeKey).await.is_some() {
+            return;
+        }
+
+        for (peer, change) in votes {
+            dbtx.insert_entry(&PeerSetChangeVoteKey(*peer), change)
+                .await;
+
+            // Remove our vote event if we voted
+            if *peer == self.cfg.local.identity {
+                let mut cache = self.api_event_cache.lock().expect("locks");
+                cache.remove(&ApiEvent::PeerSetChange(change.clone()));
+            }
+        }
+
+        let approved = dbtx
+            .find_by_prefix(&PeerSetChangeVoteKeyPrefix)
+            .await
+            .map(|(_, change)| change)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .counts()
+            .into_iter()
+            .find(|(_, votes)| *votes >= current.threshold());
+
+        if let Some((change, _)) = approved {
+            info!(
+                target: LOG_CONSENSUS,
+                "Guardians approved changing the federation to {} peers after epoch {}",
+                change.to.len(),
+                epoch
+            );
+            dbtx.insert_entry(
+                &ApprovedPeerSetChangeKey,
+                &ApprovedPeerSetChange { change, epoch },
+            )
+            .await;
+        }
+    }
+
+    /// Returns the change of guardians a threshold of peers voted for, if we
+    /// didn't switch to the new guardians yet
+    pub async fn approved_peer_set_change(&self) -> Option<ApprovedPeerSetChange> {
+        self.db
+            .begin_transaction()
+            .await
+            .get_value(&ApprovedPeerSetChangeKey)
+            .await
+            .filter(|approved| approved.change.from == self.cfg.consensus.api_endpoints)
+    }
+
+    /// Sends our vote for changing the guardians to the fedimint server thread
+    pub async fn propose_peer_set_change(
+        &self,
+        to: BTreeMap<PeerId, ApiEndpoint>,
+    ) -> Result<(), SendError<ApiEvent>> {
+        let change = PeerSetChange {
+            from: self.cfg.consensus.api_endpoints.clone(),
+            to,
+        };
+        self.api_sender.send(ApiEvent::PeerSetChange(change)).await
+    }
+
+    /// Called after switching to the new guardians to remove the votes of the
+    /// old guardians
+    pub async fn remove_peer_set_change_items(&self) {
+        let mut dbtx = self.db.begin_transaction().await;
+        let approved = dbtx.get_value(&ApprovedPeerSetChangeKey).await;
+
+        if approved.map_or(false, |approved| {
+            approved.change.to == self.cfg.consensus.api_endpoints
+        }) {
+            dbtx.remove_entry(&ApprovedPeerSetChangeKey).await;
+            dbtx.remove_by_prefix(&PeerSetChangeVoteKeyPrefix).await;
+            dbtx.commit_tx().await;
+        }
+    }
+
+    /// Records the consensus versions supported by our peers and schedules the
+    /// activation of a version once a threshold of peers voted for it
+    ///
+    /// Only votes for activating a version after the current epoch are
+    /// counted, so a late threshold can't change how past epochs were
+    /// processed.
+    async fn process_consensus_version_items(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        epoch: u64,
+        supported: &[(PeerId, SupportedConsensusVersion)],
+        votes: &[(PeerId, ConsensusVersionActivation)],
+    ) {
+        for (peer, supported) in supported {
+            dbtx.insert_entry(&PeerConsensusVersionKey(*peer), &supported.version)
+                .await;
+        }
+
+        for (peer, activation) in votes {
+            // Remove our vote event even if the vote is invalid, it won't become valid
+            if *peer == self.cfg.local.identity {
+                let mut cache = self.api_event_cache.lock().expect("locks");
+                cache.remove(&ApiEvent::ConsensusVersionActivation(activation.clone()));
+            }
+
+            let already_scheduled = dbtx
+                .get_value(&ScheduledConsensusVersionKey(activation.version))
+                .await
+                .is_some();
+            if activation.epoch <= epoch || already_scheduled {
+                continue;
+            }
+
+            dbtx.insert_entry(&ConsensusVersionVoteKey(*peer), activation)
+                .await;
+        }
+
+        let scheduled = dbtx
+            .find_by_prefix(&ConsensusVersionVoteKeyPrefix)
+            .await
+            .map(|(_, activation)| activation)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter(|activation| activation.epoch > epoch)
+            .counts()
+            .into_iter()
+            .find(|(_, votes)| *votes >= self.cfg.consensus.api_endpoints.threshold());
+
+        if let Some((activation, _)) = scheduled {
+            info!(
+                target: LOG_CONSENSUS,
+                "Guardians scheduled consensus version {} to activate at epoch {}",
+                activation.version,
+                activation.epoch
+            );
+            if CONSENSUS_VERSION < activation.version {
+                warn!(
+                    target: LOG_CONSENSUS,
+                    "This binary only supports consensus version {}, upgrade before epoch {}",
+                    CONSENSUS_VERSION,
+                    activation.epoch
+                );
+            }
+
+            dbtx.insert_entry(
+                &ScheduledConsensusVersionKey(activation.version),
+                &activation.epoch,
+            )
+            .await;
+            dbtx.remove_by_prefix(&ConsensusVersionVoteKeyPrefix).await;
+        }
+    }
+
+    /// Records the votes for new consensus params, replacing the active params
+    /// once a threshold of peers voted for the same params
+    async fn process_consensus_params_items(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        votes: &[(PeerId, ConsensusParams)],
+    ) {
+        for (peer, params) in votes {
+            if *peer == self.cfg.local.identity {
+                let mut cache = self.api_event_cache.lock().expect("locks");
+                cache.remove(&ApiEvent::ConsensusParams(params.clone()));
+            }
+
+            dbtx.insert_entry(&ConsensusParamsVoteKey(*peer), params)
+                .await;
+        }
+
+        let approved = dbtx
+            .find_by_prefix(&ConsensusParamsVoteKeyPrefix)
+            .await
+            .map(|(_, params)| params)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .counts()
+            .into_iter()
+            .find(|(_, votes)| *votes >= self.cfg.consensus.api_endpoints.threshold());
+
+        if let Some((params, _)) = approved {
+            info!(target: LOG_CONSENSUS, ?params, "Guardians changed the consensus params");
+            dbtx.insert_entry(&ConsensusParamsKey, &params).await;
+            dbtx.remove_by_prefix(&ConsensusParamsVoteKeyPrefix).await;
+        }
+    }
+
+    /// Sends our vote for new consensus params to the fedimint server thread
+    pub async fn propose_consensus_params(
+        &self,
+        params: ConsensusParams,
+    ) -> Result<(), SendError<ApiEvent>> {
+        self.api_sender
+            .send(ApiEvent::ConsensusParams(params))
+            .await
+    }
+
+    /// Returns the consensus params that are currently active
+    pub async fn consensus_params(&self) -> ConsensusParams {
+        self.db
+            .begin_transaction()
+            .await
+            .get_value(&ConsensusParamsKey)
+            .await
+            .unwrap_or_else(|| self.cfg.consensus.consensus_params.clone())
+    }
+
+    /// Records the proposals to change the consensus limits and module
+    /// parameters and schedules a change once a threshold of peers proposed
+    /// the same one
+    ///
+    /// Like for consensus versions only proposals activating after the current
+    /// epoch are counted, and only once the federation runs
+    /// [`PARAMS_CHANGE_VERSION`].
+    async fn process_params_change_items(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        epoch: u64,
+        proposals: &[(PeerId, ParamsChangeProposal)],
+    ) {
+        let active = consensus_version_at(dbtx, epoch).await >= PARAMS_CHANGE_VERSION;
+
+        for (peer, proposal) in proposals {
+            // Remove our proposal event even if it is invalid, it won't become valid
+            if *peer == self.cfg.local.identity {
+                let mut cache = self.api_event_cache.lock().expect("locks");
+                cache.remove(&ApiEvent::ParamsChangeProposal(proposal.clone()));
+            }
+
+            if !active || proposal.epoch <= epoch {
+                continue;
+            }
+
+            dbtx.insert_entry(&ParamsChangeVoteKey(*peer), proposal)
+                .await;
+        }
+
+        let approved = dbtx
+            .find_by_prefix(&ParamsChangeVoteKeyPrefix)
+            .await
+            .map(|(_, proposal)| proposal)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter(|proposal| proposal.epoch > epoch)
+            .counts()
+            .into_iter()
+            .find(|(_, votes)| *votes >= self.cfg.consensus.api_endpoints.threshold());
+
+        if let Some((proposal, _)) = approved {
+            info!(
+                target: LOG_CONSENSUS,
+                limits = ?proposal.limits,
+                module_params = ?proposal.module_params,
+                "Guardians scheduled new consensus params for epoch {}",
+                proposal.epoch
+            );
+            dbtx.insert_entry(&ScheduledParamsChangeKey(proposal.epoch), &proposal)
+                .await;
+            dbtx.remove_by_prefix(&ParamsChangeVoteKeyPrefix).await;
+        }
+    }
+
+    /// Lets the modules apply the changes of their parameters that activate in
+    /// `epoch`
+    async fn activate_params_changes(&self, dbtx: &mut DatabaseTransaction<'_>, epoch: u64) {
+        let Some(change) = dbtx.get_value(&ScheduledParamsChangeKey(epoch)).await else {
+            return;
+        };
+
+        for (module_instance_id, params) in &change.module_params {
+            let Some(module) = self.modules.get(*module_instance_id) else {
+                warn!(target: LOG_CONSENSUS, module_instance_id, "Params of unknown module");
+                continue;
+            };
+            info!(target: LOG_CONSENSUS, module_instance_id, ?params, "Activating module params");
+            module
+                .activate_params(&mut dbtx.with_module_prefix(*module_instance_id), params)
+                .await;
+        }
+    }
+
+    /// Checks a proposal to change the params before we propose it, the
+    /// federation has to run [`PARAMS_CHANGE_VERSION`] already
+    pub async fn check_params_change(&self, proposal: &ParamsChangeProposal) -> anyhow::Result<()> {
+        let next_epoch = self.get_epoch_count().await;
+        if proposal.epoch <= next_epoch {
+            return Err(format_err!("Activation epoch has to be in the future"));
+        }
+        let version =
+            consensus_version_at(&mut self.db.begin_transaction().await, next_epoch).await;
+        if version < PARAMS_CHANGE_VERSION {
+            return Err(format_err!(
+                "Params can be changed from consensus version {} on, the federation runs {}",
+                PARAMS_CHANGE_VERSION.0,
+                version.0
+            ));
+        }
+
+        // a limit of zero would reject every transaction or module item
+        let limits = &proposal.limits;
+        let global_limits = [
+            limits.max_transaction_inputs,
+            limits.max_transaction_outputs,
+            limits.max_transaction_size,
+            limits.max_module_item_size,
+        ];
+        if global_limits.contains(&0) || limits.module_item_sizes.values().any(|&size| size == 0) {
+            return Err(format_err!("Limits have to be positive"));
+        }
+        if let Some(id) = limits
+            .module_item_sizes
+            .keys()
+            .find(|id| self.modules.get(**id).is_none())
+        {
+            return Err(format_err!("Unknown module instance {id}"));
+        }
+
+        for (id, params) in &proposal.module_params {
+            let module = self
+                .modules
+                .get(*id)
+                .ok_or_else(|| format_err!("Unknown module instance {id}"))?;
+            module
+                .validate_params(params)
+                .map_err(|e| format_err!("Invalid params of module instance {id}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Sends our proposal to change the consensus limits and module parameters
+    /// to the fedimint server thread
+    pub async fn propose_params_change(
+        &self,
+        proposal: ParamsChangeProposal,
+    ) -> Result<(), SendError<ApiEvent>> {
+        self.api_sender
+            .send(ApiEvent::ParamsChangeProposal(proposal))
+            .await
+    }
+
+    /// Returns the consensus limits enforced in `epoch`, set by the latest
+    /// change scheduled for it or an earlier epoch
+    async fn consensus_limits_at(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        epoch: u64,
+    ) -> ConsensusLimits {
+        dbtx.find_by_prefix(&ScheduledParamsChangeKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter(|(key, _)| key.0 <= epoch)
+            .max_by_key(|(key, _)| key.0)
+            .map_or_else(
+                || self.cfg.consensus.consensus_limits.clone(),
+                |(_, change)| change.limits,
+            )
+    }
+
+    /// Returns the consensus limits transactions submitted now are checked
+    /// against, the ones of the next epoch
+    pub async fn consensus_limits(&self) -> ConsensusLimits {
+        let epoch = self.get_epoch_count().await;
+        let mut dbtx = self.db.begin_transaction().await;
+        self.consensus_limits_at(&mut dbtx, epoch).await
+    }
+
+    /// Returns the active consensus limits together with the scheduled changes
+    /// and the pending proposals
+    pub async fn params_changes(&self) -> ParamsChangeStatus {
+        let epoch = self.get_epoch_count().await;
+        let mut dbtx = self.db.begin_transaction().await;
+        let active = self.consensus_limits_at(&mut dbtx, epoch).await;
+        let scheduled = dbtx
+            .find_by_prefix(&ScheduledParamsChangeKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter(|(key, _)| key.0 > epoch)
+            .map(|(key, change)| (key.0, change))
+            .collect();
+        let proposals = dbtx
+            .find_by_prefix(&ParamsChangeVoteKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .filter(|(_, proposal)| proposal.epoch > epoch)
+            .map(|(key, proposal)| (key.0, proposal))
+            .collect();
+
+        ParamsChangeStatus {
+            active,
+            scheduled,
+            proposals,
+        }
+    }
+
+    /// Sends our vote for activating a consensus version to the fedimint server
+    /// thread
+    pub async fn schedule_consensus_version(
+        &self,
+        activation: ConsensusVersionActivation,
+    ) -> Result<(), SendError<ApiEvent>> {
+        self.api_sender
+            .send(ApiEvent::ConsensusVersionActivation(activation))
+            .await
+    }
+
+    /// API versions of the core and the modules we serve, which clients
+    /// negotiate their request formats with
+    pub fn api_versions(&self) -> SupportedApiVersionsSummary {
+        SupportedApiVersionsSummary {
+            core: SupportedCoreApiVersions {
+                core_consensus: CORE_CONSENSUS_VERSION,
+                api: CORE_API_VERSIONS.to_vec(),
+            },
+            modules: self
+                .modules
+                .iter_modules()
+                .map(|(id, module)| {
+                    let (module_consensus, api) = module.versions();
+                    (
+                        id,
+                        SupportedModuleApiVersions {
+                            module_consensus,
+                            api: api.to_vec(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the consensus versions supported by our peers and the scheduled
+    /// activations
+    pub async fn consensus_versions(&self) -> ConsensusVersionStatus {
+        let mut dbtx = self.db.begin_transaction().await;
+        let supported = dbtx
+            .find_by_prefix(&PeerConsensusVersionKeyPrefix)
+            .await
+            .map(|(key, version)| (key.0, version))
+            .collect()
+            .await;
+        let scheduled = dbtx
+            .find_by_prefix(&ScheduledConsensusVersionKeyPrefix)
+            .await
+            .map(|(key, epoch)| (key.0, epoch))
+            .collect()
+            .await;
+
+        ConsensusVersionStatus {
+            ours: CONSENSUS_VERSION,
+            supported,
+            scheduled,
+        }
+    }
+
+    /// Returns the newest consensus version this binary doesn't support
+    /// together with its activation epoch, if it is active in `epoch`
+    pub async fn unsupported_consensus_version(&self, epoch: u64) -> Option<(u32, u64)> {
+        unsupported_consensus_version(&mut self.db.begin_transaction().await, epoch).await
+    }
+
     pub async fn get_config_with_sig(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
@@ -627,6 +1874,59 @@ impl FedimintConsensus {
         client
     }
 
+    /// Drops our connection to `peer` and connects again right away
+    pub async fn reconnect_peer(&self, peer: PeerId) -> anyhow::Result<()> {
+        self.peer_controls.send(peer, PeerControl::Reconnect).await
+    }
+
+    /// Drops our connection to `peer` and refuses new ones for `duration`
+    pub async fn ban_peer_endpoint(&self, peer: PeerId, duration: Duration) -> anyhow::Result<()> {
+        let banned_until = tokio::time::Instant::now() + duration;
+        self.peer_controls
+            .send(peer, PeerControl::Ban(banned_until))
+            .await
+    }
+
+    /// Returns the status of our guardian for the admin API
+    pub async fn guardian_status(&self) -> GuardianStatus {
+        let connection_status = self.connection_status.read().expect("locks").clone();
+        let peer_traffic = self.peer_traffic.read().expect("locks").clone();
+        let peer_health = self.peer_health.read().expect("locks").clone();
+        let last_contributions = self.last_contributions.lock().expect("locks").clone();
+
+        let peers = self
+            .cfg
+            .consensus
+            .api_endpoints
+            .keys()
+            .filter(|&&peer| peer != self.cfg.local.identity)
+            .map(|&peer| {
+                let last_contribution = last_contributions.get(&peer);
+                let status = PeerStatus {
+                    connection: connection_status
+                        .get(&peer)
+                        .copied()
+                        .unwrap_or(PeerConnectionStatus::Disconnected),
+                    last_contribution: last_contribution.map(|(_, time)| *time),
+                    last_contribution_epoch: last_contribution.map(|(epoch, _)| *epoch),
+                    traffic: peer_traffic.get(&peer).cloned().unwrap_or_default(),
+                    health: peer_health.get(&peer).cloned().unwrap_or_default(),
+                };
+                (peer, status)
+            })
+            .collect();
+
+        let pending_proposals = self.api_event_cache.lock().expect("locks").len()
+            + self.mempool.lock().expect("locks").len();
+
+        GuardianStatus {
+            epoch_count: self.get_epoch_count().await,
+            peers,
+            pending_proposals,
+            db_size: self.db.size(),
+        }
+    }
+
     pub async fn get_epoch_count(&self) -> u64 {
         self.db
             .begin_transaction()
@@ -645,6 +1945,84 @@ impl FedimintConsensus {
             .await
     }
 
+    /// Waits until the federation signed the epoch history of `epoch`
+    pub async fn wait_signed_epoch_history(&self, epoch: u64) -> SignedEpochOutcome {
+        self.db
+            .wait_key_check(&EpochHistoryKey(epoch), |outcome| {
+                outcome.filter(|outcome| outcome.signature.is_some())
+            })
+            .await
+            .0
+    }
+
+    /// Decodes the transactions of `epoch` for block explorers, showing only
+    /// the amounts the modules consider public
+    pub async fn explore_epoch(&self, epoch: u64) -> Option<ExplorerEpoch> {
+        let history = self.epoch_history(epoch).await?;
+        let rejected_txs = &history.outcome.rejected_txs;
+
+        let mut transactions: Vec<ExplorerTransaction> = vec![];
+        let mut other_items = BTreeMap::new();
+        for (peer, items) in &history.outcome.items {
+            for item in items {
+                let ConsensusItem::Transaction(tx) = item else {
+                    *other_items.entry(*peer).or_insert(0) += 1;
+                    continue;
+                };
+
+                let txid = tx.tx_hash();
+                if let Some(explored) = transactions.iter_mut().find(|t| t.txid == txid) {
+                    explored.proposed_by.push(*peer);
+                    continue;
+                }
+
+                let inputs = tx
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        let id = input.module_instance_id();
+                        ExplorerItem {
+                            module_instance_id: id,
+                            module_kind: self.cfg.consensus.modules[&id].kind().clone(),
+                            description: input.to_string(),
+                            amount: self.modules.get_expect(id).public_input_amount(input),
+                        }
+                    })
+                    .collect();
+                let outputs = tx
+                    .outputs
+                    .iter()
+                    .map(|output| {
+                        let id = output.module_instance_id();
+                        ExplorerItem {
+                            module_instance_id: id,
+                            module_kind: self.cfg.consensus.modules[&id].kind().clone(),
+                            description: output.to_string(),
+                            amount: self.modules.get_expect(id).public_output_amount(output),
+                        }
+                    })
+                    .collect();
+
+                transactions.push(ExplorerTransaction {
+                    txid,
+                    proposed_by: vec![*peer],
+                    accepted: !rejected_txs.contains(&txid),
+                    inputs,
+                    outputs,
+                });
+            }
+        }
+
+        Some(ExplorerEpoch {
+            epoch,
+            hash: history.hash,
+            last_hash: history.outcome.last_hash,
+            signed: history.signature.is_some(),
+            transactions,
+            other_items,
+        })
+    }
+
     async fn save_epoch_history<'a>(
         &self,
         outcome: HbbftConsensusOutcome,
@@ -699,6 +2077,11 @@ impl FedimintConsensus {
     }
 
     pub async fn await_consensus_proposal(&self) {
+        // transactions that didn't fit into the last proposal are still waiting
+        if !self.mempool.lock().expect("locks").is_empty() {
+            return;
+        }
+
         let proposal_futures = self
             .modules
             .iter_modules()
@@ -724,18 +2107,48 @@ impl FedimintConsensus {
             .collect()
             .await;
 
+        let next_epoch = dbtx
+            .get_value(&LastEpochKey)
+            .await
+            .map_or(0, |key| key.0 + 1);
+        // peers running an older version couldn't decode the proposal
+        let params_changes_active =
+            consensus_version_at(&mut dbtx, next_epoch).await >= PARAMS_CHANGE_VERSION;
+        let state_hashes_active =
+            consensus_version_at(&mut dbtx, next_epoch).await >= STATE_HASH_VERSION;
+
         let mut items: Vec<ConsensusItem> = self
             .api_event_cache
             .lock()
             .unwrap()
             .iter()
             .cloned()
-            .map(|event| match event {
-                ApiEvent::Transaction(tx) => ConsensusItem::Transaction(tx),
-                ApiEvent::UpgradeSignal => ConsensusItem::ConsensusUpgrade(ConsensusUpgrade),
+            .filter_map(|event| match event {
+                // transactions are proposed from the mempool
+                ApiEvent::Transaction(_) => None,
+                ApiEvent::UpgradeSignal => Some(ConsensusItem::ConsensusUpgrade(ConsensusUpgrade)),
+                ApiEvent::PeerSetChange(change) => Some(ConsensusItem::PeerSetChange(change)),
+                ApiEvent::ConsensusVersionActivation(activation) => {
+                    Some(ConsensusItem::ConsensusVersionActivation(activation))
+                }
+                ApiEvent::ConsensusParams(params) => Some(ConsensusItem::ConsensusParams(params)),
+                ApiEvent::ParamsChangeProposal(proposal) => {
+                    params_changes_active.then_some(ConsensusItem::ParamsChangeProposal(proposal))
+                }
             })
             .collect();
+
+        // Module items and transactions that don't fit into the proposal stay in
+        // the module state and the mempool and are proposed in a later epoch
+        let params = dbtx
+            .get_value(&ConsensusParamsKey)
+            .await
+            .unwrap_or_else(|| self.cfg.consensus.consensus_params.clone());
+        let limits = self.consensus_limits_at(&mut dbtx, next_epoch).await;
+        let mut remaining_items = params.max_proposal_items as usize;
         let mut force_new_epoch = false;
+        let previously_deferred = self.deferred_module_items.lock().expect("locks").clone();
+        let mut deferred = HashSet::new();
 
         for (instance_id, module) in self.modules.iter_modules() {
             let consensus_proposal = module
@@ -745,21 +2158,93 @@ impl FedimintConsensus {
                 force_new_epoch = true;
             }
 
-            items.extend(
-                consensus_proposal
-                    .into_items()
-                    .into_iter()
-                    .map(ConsensusItem::Module),
-            );
+            let batch_limit = params
+                .module_batch_limits
+                .get(&instance_id)
+                .map_or(remaining_items, |&limit| {
+                    remaining_items.min(limit as usize)
+                });
+            // peers drop items exceeding the limits, so there is no point in
+            // proposing them
+            let (mut module_items, newer_items): (Vec<_>, Vec<_>) = consensus_proposal
+                .into_items()
+                .into_iter()
+                .filter(|item| {
+                    let check = limits.check_module_item(item);
+                    if let Err(e) = &check {
+                        warn!(target: LOG_CONSENSUS, "Not proposing consensus item: {e}");
+                    }
+                    check.is_ok()
+                })
+                .partition(|item| previously_deferred.contains(item));
+            // items deferred last time go first, so items the module proposes in
+            // every epoch can't starve the others
+            module_items.extend(newer_items);
+
+            let excess_items = module_items.split_off(module_items.len().min(batch_limit));
+            if !excess_items.is_empty() {
+                debug!(
+                    target: LOG_CONSENSUS,
+                    module_instance_id = instance_id,
+                    deferred = excess_items.len(),
+                    "Deferring consensus items to the next proposal"
+                );
+                force_new_epoch = true;
+            }
+            deferred.extend(excess_items);
+
+            remaining_items -= module_items.len();
+            items.extend(module_items.into_iter().map(ConsensusItem::Module));
         }
+        *self.deferred_module_items.lock().expect("locks") = deferred;
+
+        items.extend(
+            self.mempool
+                .lock()
+                .expect("locks")
+                .proposal()
+                .into_iter()
+                .take(remaining_items)
+                .map(ConsensusItem::Transaction),
+        );
 
         if let Some(epoch) = dbtx.get_value(&LastEpochKey).await {
             let last_epoch = dbtx.get_value(&epoch).await.unwrap();
-            let sig = self.cfg.private.epoch_sks.0.sign(last_epoch.hash);
-            let item = ConsensusItem::EpochOutcomeSignatureShare(SerdeSignatureShare(sig));
-            items.push(item);
+            if let Some(sig) = self.sign_epoch_hash(last_epoch.hash).await {
+                items.push(ConsensusItem::EpochOutcomeSignatureShare(sig));
+            }
+            // peers running an older version couldn't decode the hash
+            let state_hash = if state_hashes_active {
+                dbtx.get_value(&StateHashKey(epoch.0)).await
+            } else {
+                None
+            };
+            if let Some(hash) = state_hash {
+                items.push(ConsensusItem::StateHash(StateHash {
+                    epoch: epoch.0,
+                    hash,
+                }));
+            }
         };
 
+        // Announce the consensus version we support until it was recorded by consensus
+        let our_version = dbtx
+            .get_value(&PeerConsensusVersionKey(self.cfg.local.identity))
+            .await;
+        if our_version != Some(CONSENSUS_VERSION) {
+            items.push(ConsensusItem::SupportedConsensusVersion(
+                SupportedConsensusVersion {
+                    version: CONSENSUS_VERSION,
+                },
+            ));
+        }
+
+        if let Some(checkpoint) = Self::pending_epoch_checkpoint(&mut dbtx).await {
+            if let Some(sig) = self.sign_epoch_hash(checkpoint.hash).await {
+                items.push(ConsensusItem::EpochCheckpointSignatureShare(sig));
+            }
+        }
+
         // Add a signature share for the client config hash if we don't have it signed
         // yet
         let client = self.get_config_with_sig(&mut dbtx.get_isolated()).await;
@@ -782,6 +2267,7 @@ impl FedimintConsensus {
         &self,
         dbtx: &mut DatabaseTransaction<'a>,
         transaction: Transaction,
+        limits: &ConsensusLimits,
         caches: &VerificationCaches,
     ) -> Result<(), TransactionSubmissionError> {
         let mut funding_verifier = FundingVerifier::default();
@@ -796,6 +2282,8 @@ impl FedimintConsensus {
             return Err(TransactionReplayError(tx_hash));
         }
 
+        limits.check_transaction(&transaction)?;
+
         let mut pub_keys = Vec::new();
         for input in transaction.inputs.iter() {
             let meta = self
@@ -913,6 +2401,23 @@ impl FedimintConsensus {
         None
     }
 
+    /// Whether the transaction is pending in our mempool or was processed
+    /// already, waiting for any other transaction could take forever
+    pub async fn is_known_transaction(&self, txid: TransactionId) -> bool {
+        if self.mempool.lock().expect("locks").contains(&txid) {
+            return true;
+        }
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.get_value(&AcceptedTransactionKey(txid))
+            .await
+            .is_some()
+            || dbtx
+                .get_value(&RejectedTransactionKey(txid))
+                .await
+                .is_some()
+    }
+
     fn build_verification_caches<'a>(
         &self,
         transactions: impl Iterator<Item = &'a Transaction> + Send,
@@ -924,6 +2429,8 @@ impl FedimintConsensus {
 
         // TODO: should probably run in parallel, but currently only the mint does
         // anything at all
+        // transactions of unknown module instances fail to decode, so every
+        // input belongs to one of our modules
         let caches = module_inputs
             .into_iter()
             .map(|(module_key, inputs)| {
@@ -946,6 +2453,162 @@ impl FedimintConsensus {
         audit
     }
 
+    /// Audits every module at the same epoch boundary and signs the result, so
+    /// anyone can monitor the solvency of the federation
+    ///
+    /// The summary is computed and signed once per epoch, requests in between
+    /// get the cached summary.
+    pub async fn audit_summary(&self) -> anyhow::Result<SignedAuditSummary> {
+        // concurrent requests wait for the summary of the first one
+        let mut cached = self.audit_summary.lock().await;
+
+        // all reads happen on the snapshot of the same transaction, so the
+        // summary is consistent with the epoch count
+        let mut dbtx = self.db.begin_transaction().await;
+        let epoch_count = dbtx
+            .get_value(&LastEpochKey)
+            .await
+            .map_or(0, |last_epoch| last_epoch.0 + 1);
+        if let Some(summary) = cached
+            .as_ref()
+            .filter(|cached| cached.summary.epoch_count == epoch_count)
+        {
+            return Ok(summary.clone());
+        }
+
+        let mut modules = BTreeMap::new();
+        for (module_instance_id, module) in self.modules.iter_modules() {
+            let mut audit = Audit::default();
+            module
+                .audit(&mut dbtx.with_module_prefix(module_instance_id), &mut audit)
+                .await;
+
+            let kind = self.cfg.consensus.modules[&module_instance_id]
+                .kind()
+                .to_string();
+            modules.insert(module_instance_id, ModuleAuditSummary::new(kind, &audit));
+        }
+
+        let summary = AuditSummary {
+            epoch_count,
+            modules,
+        };
+        let signature = SerdeSignatureShare(self.epoch_signer.sign(summary.signing_hash()).await?);
+        let signed = SignedAuditSummary { summary, signature };
+        *cached = Some(signed.clone());
+        Ok(signed)
+    }
+
+    /// Re-verifies the invariants of our database on a snapshot and keeps the
+    /// report for the admin API, violations are logged and exported as
+    /// metrics
+    pub async fn check_integrity(&self) -> IntegrityReport {
+        // all checks read the snapshot of the same transaction
+        let mut dbtx = self.db.begin_transaction().await;
+        let last_epoch = dbtx.get_value(&LastEpochKey).await;
+
+        let mut consensus = vec![];
+        if let Some(last_epoch) = &last_epoch {
+            if dbtx.get_value(last_epoch).await.is_none() {
+                consensus.push(format!(
+                    "Last epoch {} is missing from the epoch history",
+                    last_epoch.0
+                ));
+            }
+        }
+
+        let mut audit = Audit::default();
+        let mut modules = BTreeMap::new();
+        for (module_instance_id, module) in self.modules.iter_modules() {
+            let mut module_dbtx = dbtx.with_module_prefix(module_instance_id);
+            module.audit(&mut module_dbtx, &mut audit).await;
+
+            let violations = module.verify_integrity(&mut module_dbtx).await;
+            metrics::INTEGRITY_VIOLATIONS
+                .with_label_values(&[module_instance_id.to_string().as_str()])
+                .set(violations.len() as i64);
+            for violation in &violations {
+                error!(
+                    target: LOG_CONSENSUS,
+                    module_instance_id, %violation, "Module state violates an invariant"
+                );
+            }
+            if !violations.is_empty() {
+                modules.insert(module_instance_id, violations);
+            }
+        }
+
+        let net_assets = audit.sum().milli_sat;
+        if net_assets < 0 {
+            consensus.push(format!("Liabilities exceed assets by {} msat", -net_assets));
+        }
+
+        metrics::INTEGRITY_VIOLATIONS
+            .with_label_values(&["consensus"])
+            .set(consensus.len() as i64);
+        for violation in &consensus {
+            error!(
+                target: LOG_CONSENSUS,
+                %violation, "Consensus state violates an invariant"
+            );
+        }
+
+        let report = IntegrityReport {
+            epoch_count: last_epoch.map_or(0, |last_epoch| last_epoch.0 + 1),
+            checked_at: SystemTime::now(),
+            consensus,
+            modules,
+        };
+        *self.last_integrity_report.lock().expect("locks") = Some(report.clone());
+        report
+    }
+
+    /// Checks the integrity of our database every `interval` until we shut
+    /// down
+    pub async fn run_integrity_checks(&self, interval: Duration, task_handle: TaskHandle) {
+        let mut shutdown_rx = task_handle.make_shutdown_rx().await;
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => break,
+                _ = sleep(interval) => {}
+            }
+
+            let report = self.check_integrity().await;
+            if report.is_consistent() {
+                debug!(
+                    target: LOG_CONSENSUS,
+                    epoch_count = report.epoch_count, "Integrity check passed"
+                );
+            }
+        }
+    }
+
+    /// Report of the last integrity check, `None` if no check finished yet
+    pub fn integrity_report(&self) -> Option<IntegrityReport> {
+        self.last_integrity_report.lock().expect("locks").clone()
+    }
+
+    /// Our latest hash of the state and the peers whose hashes differed from
+    /// ours since we started
+    pub async fn state_hash_status(&self) -> StateHashStatus {
+        let ours = self
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&StateHashKeyPrefix)
+            .await
+            .map(|(StateHashKey(epoch), hash)| StateHash { epoch, hash })
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .max_by_key(|state_hash| state_hash.epoch);
+
+        StateHashStatus {
+            ours,
+            divergences: self.state_hash_divergences.lock().expect("locks").clone(),
+        }
+    }
+
     fn build_interconnect(&self) -> FedimintInterconnect {
         FedimintInterconnect { fedimint: self }
     }
@@ -985,6 +2648,68 @@ impl Default for FundingVerifier {
     }
 }
 
+/// Signs with the epoch key share of our private config, if it holds one,
+/// otherwise another signer has to be set
+fn local_epoch_signer(cfg: &ServerConfig) -> Arc<dyn EpochSigner> {
+    match &cfg.private.epoch_sks {
+        Some(sks) => Arc::new(LocalEpochSigner(sks.0.clone())),
+        None => Arc::new(MissingEpochSigner),
+    }
+}
+
+/// Whether `key` belongs to the consensus state, which is the same on all
+/// guardians
+fn is_consensus_key(key: &[u8], module_local_prefixes: &[Vec<u8>]) -> bool {
+    !is_local_db_key(key)
+        && !module_local_prefixes
+            .iter()
+            .any(|key_prefix| key.starts_with(key_prefix))
+}
+
+/// Returns the core consensus version the federation runs in `epoch`, the
+/// newest one that activated in or before it
+async fn consensus_version_at(
+    dbtx: &mut DatabaseTransaction<'_>,
+    epoch: u64,
+) -> CoreConsensusVersion {
+    dbtx.find_by_prefix(&ScheduledConsensusVersionKeyPrefix)
+        .await
+        .map(|(key, activation_epoch)| (key.0, activation_epoch))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .filter(|(_, activation_epoch)| *activation_epoch <= epoch)
+        .map(|(version, _)| CoreConsensusVersion(version))
+        .max()
+        .unwrap_or(CoreConsensusVersion(0))
+}
+
+/// Returns the newest consensus version this binary doesn't support together
+/// with its activation epoch, if it is active in `epoch`
+async fn unsupported_consensus_version(
+    dbtx: &mut DatabaseTransaction<'_>,
+    epoch: u64,
+) -> Option<(u32, u64)> {
+    dbtx.find_by_prefix(&ScheduledConsensusVersionKeyPrefix)
+        .await
+        .map(|(key, activation_epoch)| (key.0, activation_epoch))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .filter(|(version, activation_epoch)| {
+            CONSENSUS_VERSION < *version && *activation_epoch <= epoch
+        })
+        .max()
+}
+
+/// The federation activated a consensus version this binary doesn't support
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Error)]
+#[error("Consensus version {version} activated in epoch {activation_epoch} is not supported")]
+pub struct UnsupportedConsensusVersion {
+    pub version: u32,
+    pub activation_epoch: u64,
+}
+
 #[derive(Debug, Error)]
 pub enum TransactionSubmissionError {
     #[error("High level transaction error: {0}")]
@@ -993,6 +2718,10 @@ pub enum TransactionSubmissionError {
     ModuleError(TransactionId, ModuleError),
     #[error("Transaction channel was closed")]
     TxChannelError,
+    #[error("Transaction was not accepted: {0}")]
+    MempoolError(#[from] MempoolError),
     #[error("Transaction was already successfully processed: {0}")]
     TransactionReplayError(TransactionId),
+    #[error("Transaction exceeds the limits of the federation: {0}")]
+    LimitExceeded(#[from] LimitError),
 }
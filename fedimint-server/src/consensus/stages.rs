@@ -0,0 +1,96 @@
+//! Order in which modules begin and end consensus epochs
+//!
+//! Modules declare the kinds of modules whose epoch processing they depend
+//! on. They are grouped into stages that run one after another, the modules of
+//! a stage run concurrently since none of them depends on another one.
+use std::collections::{BTreeMap, BTreeSet};
+
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+
+/// Groups the modules of `kinds` into stages, every module in a later stage
+/// than the modules of the kinds it `depends_on`. Modules whose dependencies
+/// are cyclic run one by one after all others.
+pub fn epoch_stages(
+    kinds: &BTreeMap<ModuleInstanceId, ModuleKind>,
+    depends_on: impl Fn(ModuleInstanceId) -> Vec<ModuleKind>,
+) -> Vec<Vec<ModuleInstanceId>> {
+    let mut dependencies: BTreeMap<ModuleInstanceId, BTreeSet<ModuleInstanceId>> = kinds
+        .keys()
+        .map(|id| {
+            let dependency_kinds = depends_on(*id);
+            let dependencies = kinds
+                .iter()
+                .filter(|(other, kind)| *other != id && dependency_kinds.contains(*kind))
+                .map(|(other, _)| *other)
+                .collect();
+            (*id, dependencies)
+        })
+        .collect();
+
+    let mut stages = vec![];
+    while !dependencies.is_empty() {
+        let stage = dependencies
+            .iter()
+            .filter(|(_, dependencies)| dependencies.is_empty())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        if stage.is_empty() {
+            stages.extend(dependencies.keys().map(|id| vec![*id]));
+            break;
+        }
+
+        for id in &stage {
+            dependencies.remove(id);
+        }
+        for remaining in dependencies.values_mut() {
+            remaining.retain(|id| !stage.contains(id));
+        }
+        stages.push(stage);
+    }
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::core::ModuleKind;
+
+    use super::epoch_stages;
+
+    fn kinds(kinds: &[&'static str]) -> BTreeMap<u16, ModuleKind> {
+        kinds
+            .iter()
+            .enumerate()
+            .map(|(id, kind)| (id as u16, ModuleKind::from_static_str(kind)))
+            .collect()
+    }
+
+    #[test]
+    fn independent_modules_share_a_stage() {
+        let kinds = kinds(&["ln", "mint", "wallet"]);
+        assert_eq!(epoch_stages(&kinds, |_| vec![]), vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn dependent_modules_run_after_their_dependencies() {
+        let kinds = kinds(&["ln", "mint", "wallet", "stability"]);
+        let stages = epoch_stages(&kinds, |id| match id {
+            3 => vec![ModuleKind::from_static_str("wallet")],
+            2 => vec![ModuleKind::from_static_str("mint")],
+            _ => vec![],
+        });
+        assert_eq!(stages, vec![vec![0, 1], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn cyclic_modules_run_one_by_one() {
+        let kinds = kinds(&["ln", "mint", "wallet"]);
+        let stages = epoch_stages(&kinds, |id| match id {
+            1 => vec![ModuleKind::from_static_str("wallet")],
+            2 => vec![ModuleKind::from_static_str("mint")],
+            _ => vec![],
+        });
+        assert_eq!(stages, vec![vec![0], vec![1], vec![2]]);
+    }
+}
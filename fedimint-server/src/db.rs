@@ -1,11 +1,16 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 
-use fedimint_core::db::{DatabaseVersion, MigrationMap, MODULE_GLOBAL_PREFIX};
+use bitcoin_hashes::sha256;
+use fedimint_core::db::{DatabaseTransaction, DatabaseVersion, MigrationMap, MODULE_GLOBAL_PREFIX};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::epoch::{SerdeSignature, SignedEpochOutcome};
+use fedimint_core::epoch::{
+    ConsensusParams, ConsensusVersionActivation, ParamsChangeProposal, PeerSetChange,
+    SerdeSignature, SignedEpochCheckpoint, SignedEpochOutcome, StateSnapshot,
+};
 use fedimint_core::{impl_db_lookup, impl_db_record, PeerId, TransactionId};
 use serde::Serialize;
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 use crate::consensus::AcceptedTransaction;
@@ -22,6 +27,19 @@ pub enum DbKeyPrefix {
     LastEpoch = 0x06,
     ClientConfigSignature = 0x07,
     ConsensusUpgrade = 0x08,
+    PeerSetChangeVote = 0x09,
+    ApprovedPeerSetChange = 0x0a,
+    EpochCheckpoint = 0x0b,
+    EarliestEpoch = 0x0c,
+    PeerConsensusVersion = 0x0d,
+    ConsensusVersionVote = 0x0e,
+    ScheduledConsensusVersion = 0x0f,
+    StateSnapshot = 0x10,
+    ConsensusParamsVote = 0x11,
+    ConsensusParams = 0x12,
+    ParamsChangeVote = 0x13,
+    ScheduledParamsChange = 0x14,
+    StateHash = 0x15,
     Module = MODULE_GLOBAL_PREFIX,
 }
 
@@ -88,6 +106,7 @@ impl_db_record!(
     key = EpochHistoryKey,
     value = SignedEpochOutcome,
     db_prefix = DbKeyPrefix::EpochHistory,
+    notify_on_modify = true,
 );
 impl_db_lookup!(key = EpochHistoryKey, query_prefix = EpochHistoryKeyPrefix);
 
@@ -125,6 +144,277 @@ impl_db_record!(
     db_prefix = DbKeyPrefix::ConsensusUpgrade,
 );
 
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PeerSetChangeVoteKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerSetChangeVoteKeyPrefix;
+
+impl_db_record!(
+    key = PeerSetChangeVoteKey,
+    value = PeerSetChange,
+    db_prefix = DbKeyPrefix::PeerSetChangeVote,
+);
+impl_db_lookup!(
+    key = PeerSetChangeVoteKey,
+    query_prefix = PeerSetChangeVoteKeyPrefix
+);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ApprovedPeerSetChangeKey;
+
+impl_db_record!(
+    key = ApprovedPeerSetChangeKey,
+    value = ApprovedPeerSetChange,
+    db_prefix = DbKeyPrefix::ApprovedPeerSetChange,
+);
+
+/// A change of the guardians a threshold of peers voted for
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize)]
+pub struct ApprovedPeerSetChange {
+    pub change: PeerSetChange,
+    /// The last epoch run by the old guardians
+    pub epoch: u64,
+}
+
+#[derive(Debug, Copy, Clone, Encodable, Decodable, Serialize)]
+pub struct EpochCheckpointKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EpochCheckpointKeyPrefix;
+
+impl_db_record!(
+    key = EpochCheckpointKey,
+    value = SignedEpochCheckpoint,
+    db_prefix = DbKeyPrefix::EpochCheckpoint,
+);
+impl_db_lookup!(
+    key = EpochCheckpointKey,
+    query_prefix = EpochCheckpointKeyPrefix
+);
+
+/// The first epoch that was not pruned from the epoch history
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct EarliestEpochKey;
+
+impl_db_record!(
+    key = EarliestEpochKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::EarliestEpoch,
+);
+
+/// The newest consensus version a peer announced to support
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PeerConsensusVersionKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerConsensusVersionKeyPrefix;
+
+impl_db_record!(
+    key = PeerConsensusVersionKey,
+    value = u32,
+    db_prefix = DbKeyPrefix::PeerConsensusVersion,
+);
+impl_db_lookup!(
+    key = PeerConsensusVersionKey,
+    query_prefix = PeerConsensusVersionKeyPrefix
+);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ConsensusVersionVoteKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ConsensusVersionVoteKeyPrefix;
+
+impl_db_record!(
+    key = ConsensusVersionVoteKey,
+    value = ConsensusVersionActivation,
+    db_prefix = DbKeyPrefix::ConsensusVersionVote,
+);
+impl_db_lookup!(
+    key = ConsensusVersionVoteKey,
+    query_prefix = ConsensusVersionVoteKeyPrefix
+);
+
+/// The epoch a threshold of peers voted to activate a consensus version in
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ScheduledConsensusVersionKey(pub u32);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ScheduledConsensusVersionKeyPrefix;
+
+impl_db_record!(
+    key = ScheduledConsensusVersionKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ScheduledConsensusVersion,
+);
+impl_db_lookup!(
+    key = ScheduledConsensusVersionKey,
+    query_prefix = ScheduledConsensusVersionKeyPrefix
+);
+
+/// Snapshot of our consensus state taken with the latest checkpoint, served to
+/// guardians that fell behind
+#[derive(Debug, Encodable, Decodable)]
+pub struct StateSnapshotKey;
+
+impl_db_record!(
+    key = StateSnapshotKey,
+    value = StateSnapshot,
+    db_prefix = DbKeyPrefix::StateSnapshot,
+);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ConsensusParamsVoteKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ConsensusParamsVoteKeyPrefix;
+
+impl_db_record!(
+    key = ConsensusParamsVoteKey,
+    value = ConsensusParams,
+    db_prefix = DbKeyPrefix::ConsensusParamsVote,
+);
+impl_db_lookup!(
+    key = ConsensusParamsVoteKey,
+    query_prefix = ConsensusParamsVoteKeyPrefix
+);
+
+/// Consensus params a threshold of peers voted for, replacing the params of
+/// the consensus config
+#[derive(Debug, Encodable, Decodable)]
+pub struct ConsensusParamsKey;
+
+impl_db_record!(
+    key = ConsensusParamsKey,
+    value = ConsensusParams,
+    db_prefix = DbKeyPrefix::ConsensusParams,
+);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ParamsChangeVoteKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ParamsChangeVoteKeyPrefix;
+
+impl_db_record!(
+    key = ParamsChangeVoteKey,
+    value = ParamsChangeProposal,
+    db_prefix = DbKeyPrefix::ParamsChangeVote,
+);
+impl_db_lookup!(
+    key = ParamsChangeVoteKey,
+    query_prefix = ParamsChangeVoteKeyPrefix
+);
+
+/// Change of the consensus limits and module parameters a threshold of peers
+/// proposed, applied from the epoch of the key on
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ScheduledParamsChangeKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ScheduledParamsChangeKeyPrefix;
+
+impl_db_record!(
+    key = ScheduledParamsChangeKey,
+    value = ParamsChangeProposal,
+    db_prefix = DbKeyPrefix::ScheduledParamsChange,
+);
+impl_db_lookup!(
+    key = ScheduledParamsChangeKey,
+    query_prefix = ScheduledParamsChangeKeyPrefix
+);
+
+/// Hash of our consensus state after the epoch of the key, compared with the
+/// hashes our peers contribute
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct StateHashKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct StateHashKeyPrefix;
+
+impl_db_record!(
+    key = StateHashKey,
+    value = sha256::Hash,
+    db_prefix = DbKeyPrefix::StateHash,
+);
+impl_db_lookup!(key = StateHashKey, query_prefix = StateHashKeyPrefix);
+
+/// Prefixes of entries that differ between guardians and are left out of
+/// state snapshots and state hashes
+pub const LOCAL_DB_PREFIXES: &[DbKeyPrefix] = &[
+    DbKeyPrefix::DropPeer,
+    DbKeyPrefix::EpochHistory,
+    DbKeyPrefix::EarliestEpoch,
+    DbKeyPrefix::StateSnapshot,
+    DbKeyPrefix::StateHash,
+];
+
+/// Prefixes of the global entries hashed into the state hash, which are only
+/// written while processing the consensus items of an epoch
+///
+/// Rejected transactions are hashed separately, see [`consensus_state_hash`].
+pub const STATE_HASH_DB_PREFIXES: &[DbKeyPrefix] = &[
+    DbKeyPrefix::AcceptedTransaction,
+    DbKeyPrefix::LastEpoch,
+    DbKeyPrefix::ConsensusUpgrade,
+    DbKeyPrefix::PeerSetChangeVote,
+    DbKeyPrefix::ApprovedPeerSetChange,
+    DbKeyPrefix::PeerConsensusVersion,
+    DbKeyPrefix::ConsensusVersionVote,
+    DbKeyPrefix::ScheduledConsensusVersion,
+    DbKeyPrefix::ConsensusParamsVote,
+    DbKeyPrefix::ConsensusParams,
+    DbKeyPrefix::ParamsChangeVote,
+    DbKeyPrefix::ScheduledParamsChange,
+];
+
+/// Hashes the canonical encoding of the entries under the
+/// [`STATE_HASH_DB_PREFIXES`] and the `module_key_prefixes`
+///
+/// Rejected transactions are hashed by their id only, the error message
+/// depends on the version of the guardian that rejected them.
+pub async fn consensus_state_hash(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_key_prefixes: &[Vec<u8>],
+) -> sha256::Hash {
+    let key_prefixes = STATE_HASH_DB_PREFIXES
+        .iter()
+        .map(|prefix| vec![prefix.clone() as u8])
+        .chain(module_key_prefixes.iter().cloned());
+
+    // sorted by key, the databases return entries in different orders
+    let mut entries = BTreeMap::new();
+    for key_prefix in key_prefixes {
+        entries.extend(dbtx.raw_find_by_prefix(&key_prefix).await);
+    }
+    let rejected_transactions = dbtx
+        .raw_find_by_prefix(&[DbKeyPrefix::RejectedTransaction as u8])
+        .await
+        .into_iter()
+        .map(|(key, _)| (key, vec![]));
+    entries.extend(rejected_transactions);
+
+    entries.consensus_hash().expect("Hashes")
+}
+
+/// Whether an undecoded key belongs to one of the [`LOCAL_DB_PREFIXES`]
+pub fn is_local_db_key(key: &[u8]) -> bool {
+    key.first().map_or(false, |&key_prefix| {
+        LOCAL_DB_PREFIXES
+            .iter()
+            .any(|local| local.clone() as u8 == key_prefix)
+    })
+}
+
+/// Prefixes of the global stores of the consensus, the modules keep their keys
+/// in the isolated key spaces of their instances instead
+pub fn global_db_prefixes() -> impl Iterator<Item = u8> {
+    DbKeyPrefix::iter()
+        .filter(|prefix| !matches!(prefix, DbKeyPrefix::Module))
+        .map(|prefix| prefix as u8)
+}
+
 pub fn get_global_database_migrations<'a>() -> MigrationMap<'a> {
     MigrationMap::new()
 }
@@ -339,6 +629,22 @@ mod fedimint_migration_tests {
                             DbKeyPrefix::ConsensusUpgrade => {
                                 assert!(dbtx.get_value(&ConsensusUpgradeKey).await.is_some());
                             }
+                            // Peer set changes were added after the v0 snapshot was taken
+                            DbKeyPrefix::PeerSetChangeVote | DbKeyPrefix::ApprovedPeerSetChange => {}
+                            // Checkpoints were added after the v0 snapshot was taken
+                            DbKeyPrefix::EpochCheckpoint | DbKeyPrefix::EarliestEpoch => {}
+                            // Consensus versions were added after the v0 snapshot was taken
+                            DbKeyPrefix::PeerConsensusVersion
+                            | DbKeyPrefix::ConsensusVersionVote
+                            | DbKeyPrefix::ScheduledConsensusVersion => {}
+                            // State snapshots were added after the v0 snapshot was taken
+                            DbKeyPrefix::StateSnapshot => {}
+                            // Consensus params were added after the v0 snapshot was taken
+                            DbKeyPrefix::ConsensusParamsVote | DbKeyPrefix::ConsensusParams => {}
+                            // Params changes were added after the v0 snapshot was taken
+                            DbKeyPrefix::ParamsChangeVote | DbKeyPrefix::ScheduledParamsChange => {}
+                            // State hashes were added after the v0 snapshot was taken
+                            DbKeyPrefix::StateHash => {}
                             // Module prefix is reserved for modules, no migration testing is needed
                             DbKeyPrefix::Module => {}
                     }
@@ -352,3 +658,82 @@ mod fedimint_migration_tests {
         .await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, MODULE_GLOBAL_PREFIX};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::{PeerId, TransactionId};
+
+    use super::{
+        consensus_state_hash, ConsensusUpgradeKey, DropPeerKey, EpochHistoryKey, LastEpochKey,
+        RejectedTransactionKey, StateHashKey,
+    };
+
+    const MODULE_CONSENSUS_KEY: [u8; 3] = [MODULE_GLOBAL_PREFIX, 0, 0x10];
+    const MODULE_LOCAL_KEY: [u8; 3] = [MODULE_GLOBAL_PREFIX, 0, 0x11];
+
+    /// Writes the same consensus state to a new database, `guardian` picks
+    /// the local entries
+    async fn guardian_db(guardian: u8) -> Database {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = db.begin_transaction().await;
+        let txid = TransactionId::from_inner([1; 32]);
+
+        dbtx.insert_entry(&LastEpochKey, &EpochHistoryKey(99)).await;
+        dbtx.insert_entry(&ConsensusUpgradeKey, &BTreeSet::from([PeerId::from(0)]))
+            .await;
+        dbtx.raw_insert_bytes(&MODULE_CONSENSUS_KEY, vec![1, 2, 3])
+            .await;
+
+        dbtx.insert_entry(
+            &RejectedTransactionKey(txid),
+            &format!("rejected by guardian {guardian}"),
+        )
+        .await;
+        dbtx.insert_entry(&DropPeerKey(PeerId::from(u16::from(guardian))), &())
+            .await;
+        dbtx.insert_entry(
+            &StateHashKey(guardian.into()),
+            &sha256::Hash::hash(&[guardian]),
+        )
+        .await;
+        dbtx.raw_insert_bytes(&MODULE_LOCAL_KEY, vec![guardian])
+            .await;
+
+        dbtx.commit_tx().await;
+        db
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn state_hash_ignores_local_data() {
+        let module_key_prefixes = vec![MODULE_CONSENSUS_KEY.to_vec()];
+        let first = guardian_db(1).await;
+        let second = guardian_db(2).await;
+
+        let first_hash =
+            consensus_state_hash(&mut first.begin_transaction().await, &module_key_prefixes).await;
+        let second_hash =
+            consensus_state_hash(&mut second.begin_transaction().await, &module_key_prefixes).await;
+        assert_eq!(first_hash, second_hash);
+
+        // the module entry is only hashed as part of the module's consensus state
+        let unhashed_module = consensus_state_hash(&mut first.begin_transaction().await, &[]).await;
+        assert_ne!(first_hash, unhashed_module);
+
+        let mut dbtx = second.begin_transaction().await;
+        dbtx.insert_entry(
+            &RejectedTransactionKey(TransactionId::from_inner([2; 32])),
+            &String::new(),
+        )
+        .await;
+        assert_ne!(
+            first_hash,
+            consensus_state_hash(&mut dbtx, &module_key_prefixes).await
+        );
+    }
+}
@@ -5,7 +5,7 @@ use std::cmp::min;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::future::Future;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::bail;
 use config::ServerConfig;
@@ -59,11 +59,27 @@ pub mod config;
 /// Implementation of multiplexed peer connections
 pub mod multiplexed;
 
+/// Backups of the guardian's database and configs
+pub mod backup;
+
+/// Replaying a guardian's recorded epoch history to debug consensus
+pub mod replay;
+
+/// Archives of the epoch history run by third parties
+pub mod archive;
+
+/// Prometheus metrics of consensus and module processing
+pub mod metrics;
+
 type PeerMessage = (PeerId, EpochMessage);
 
 /// how many epochs ahead of consensus to rejoin
 const NUM_EPOCHS_REJOIN_AHEAD: u64 = 10;
 
+/// How many epochs we have to fall behind before syncing from a state snapshot
+/// instead of replaying the epoch history
+const STATE_SYNC_MIN_EPOCHS: u64 = 1000;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum EpochMessage {
@@ -110,6 +126,9 @@ pub struct FedimintServer {
     pub last_processed_epoch: Option<SignedEpochOutcome>,
     /// Used for decoding module specific-values
     pub decoders: ModuleDecoderRegistry,
+    /// When we last proposed in an epoch, used to space out epochs by the
+    /// round interval of the consensus params
+    pub last_epoch_start: Option<Instant>,
 }
 
 impl FedimintServer {
@@ -129,9 +148,22 @@ impl FedimintServer {
             .consensus
             .to_config_response(&server_consensus.module_inits);
 
+        let integrity_check_interval = cfg.local.integrity_checks.interval_secs;
+        if integrity_check_interval != 0 {
+            let integrity_consensus = server_consensus.clone();
+            task_group
+                .spawn("integrity-checker", move |handle| async move {
+                    integrity_consensus
+                        .run_integrity_checks(Duration::from_secs(integrity_check_interval), handle)
+                        .await
+                })
+                .await;
+        }
+
+        let api_task_group = task_group.make_subgroup().await;
         task_group
             .spawn("api-server", |handle| {
-                net::api::run_server(cfg, server_consensus, handle)
+                net::api::run_server(cfg, server_consensus, handle, api_task_group)
             })
             .await;
 
@@ -163,8 +195,11 @@ impl FedimintServer {
         decoders: ModuleDecoderRegistry,
         task_group: &mut TaskGroup,
     ) -> Self {
-        let connector: PeerConnector<EpochMessage> =
-            TlsTcpConnector::new(cfg.tls_config(), cfg.local.identity).into_dyn();
+        let mut connector = TlsTcpConnector::new(cfg.tls_config(), cfg.local.identity);
+        if let Some(tor) = cfg.local.tor.clone() {
+            connector = connector.with_tor(tor, cfg.private.onion_service_key.clone());
+        }
+        let connector: PeerConnector<EpochMessage> = connector.into_dyn();
 
         Self::new_with(
             cfg.clone(),
@@ -180,7 +215,7 @@ impl FedimintServer {
 
     pub async fn new_with(
         cfg: ServerConfig,
-        consensus: FedimintConsensus,
+        mut consensus: FedimintConsensus,
         api_receiver: Receiver<ApiEvent>,
         connector: PeerConnector<EpochMessage>,
         decoders: ModuleDecoderRegistry,
@@ -196,8 +231,12 @@ impl FedimintServer {
             connector,
             task_group,
         )
-        .await
-        .into_dyn();
+        .await;
+        consensus.connection_status = connections.connection_status();
+        consensus.peer_traffic = connections.traffic();
+        consensus.peer_health = connections.health();
+        consensus.peer_controls = connections.controls();
+        let connections = connections.into_dyn();
 
         let net_info = NetworkInfo::new(
             cfg.local.identity,
@@ -215,7 +254,12 @@ impl FedimintServer {
             .clone()
             .into_iter()
             .map(|(id, node)| (id, node.url));
-        let api = WsFederationApi::new(api_endpoints.collect());
+        let mut api = WsFederationApi::new(api_endpoints.collect());
+        // the state snapshots are served to users of the federation only, which
+        // share the user auth of the guardians
+        if let Some(user_auth) = cfg.local.user_auth.clone() {
+            api = api.with_auth(user_auth);
+        }
 
         FedimintServer {
             task_group: task_group.clone(),
@@ -230,6 +274,7 @@ impl FedimintServer {
             run_empty_epochs: 0,
             last_processed_epoch: None,
             decoders,
+            last_epoch_start: None,
         }
     }
 
@@ -243,6 +288,32 @@ impl FedimintServer {
             return self.task_group.shutdown().await;
         }
 
+        self.consensus.remove_peer_set_change_items().await;
+        if let Some(approved) = self.consensus.approved_peer_set_change().await {
+            error!(
+                target: LOG_CORE,
+                "Guardians approved a change to {} peers in epoch {}, reshare the keys before restarting fedimintd",
+                approved.change.to.len(),
+                approved.epoch
+            );
+            return self.task_group.shutdown().await;
+        }
+
+        let next_epoch = self.next_epoch_to_process();
+        if let Some((version, epoch)) = self
+            .consensus
+            .unsupported_consensus_version(next_epoch)
+            .await
+        {
+            error!(
+                target: LOG_CORE,
+                "Guardians activated consensus version {} in epoch {}, upgrade fedimintd before restarting",
+                version,
+                epoch
+            );
+            return self.task_group.shutdown().await;
+        }
+
         // FIXME: reusing the wallet CI leads to duplicate randomness beacons, not a
         // problem for change, but maybe later for other use cases
         let mut rng = OsRng;
@@ -280,6 +351,33 @@ impl FedimintServer {
                 self.task_group.shutdown().await;
                 break;
             }
+
+            if let Some(approved) = self.consensus.approved_peer_set_change().await {
+                info!(
+                    target: LOG_CONSENSUS,
+                    "Guardians approved a change to {} peers in epoch {}, shutting down to reshare the keys",
+                    approved.change.to.len(),
+                    approved.epoch
+                );
+                self.task_group.shutdown().await;
+                break;
+            }
+
+            let next_epoch = self.next_epoch_to_process();
+            if let Some((version, epoch)) = self
+                .consensus
+                .unsupported_consensus_version(next_epoch)
+                .await
+            {
+                error!(
+                    target: LOG_CONSENSUS,
+                    "Guardians activated consensus version {} in epoch {}, shutting down to upgrade fedimintd",
+                    version,
+                    epoch
+                );
+                self.task_group.shutdown().await;
+                break;
+            }
         }
 
         info!(target: LOG_CONSENSUS, "Consensus task shut down");
@@ -322,6 +420,14 @@ impl FedimintServer {
         &mut self,
         last_outcome: HbbftConsensusOutcome,
     ) -> Result<(), EpochVerifyError> {
+        if last_outcome
+            .epoch
+            .saturating_sub(self.next_epoch_to_process())
+            >= STATE_SYNC_MIN_EPOCHS
+        {
+            self.sync_state(last_outcome.epoch).await;
+        }
+
         let mut epochs: Vec<_> = vec![];
         // for checking the hashes of the epoch history
         let mut prev_epoch: Option<SignedEpochOutcome> = self.last_processed_epoch.clone();
@@ -376,7 +482,7 @@ impl FedimintServer {
 
             if at_know_trusted_checkpoint {
                 for (items, epoch, _prev_epoch_hash, rejected_txs) in epochs.drain(..) {
-                    let epoch = self
+                    let epoch = match self
                         .consensus
                         .process_consensus_outcome(
                             Batch {
@@ -385,7 +491,15 @@ impl FedimintServer {
                             },
                             rejected_txs.clone(),
                         )
-                        .await;
+                        .await
+                    {
+                        Ok(epoch) => epoch,
+                        // `run_consensus` shuts down once it sees the unsupported version
+                        Err(e) => {
+                            warn!(target: LOG_CONSENSUS, err = %e, "Stopped processing epochs");
+                            return Ok(());
+                        }
+                    };
                     self.last_processed_epoch = Some(epoch);
                 }
             }
@@ -394,6 +508,46 @@ impl FedimintServer {
         Ok(())
     }
 
+    /// Installs the latest state snapshot signed by the federation if it lets
+    /// us skip epochs before `until_epoch`, if that fails we replay the epoch
+    /// history instead
+    async fn sync_state(&mut self, until_epoch: u64) {
+        let next_epoch = self.next_epoch_to_process();
+        let epoch_pks = &self.cfg.consensus.epoch_pk_set;
+
+        let result: anyhow::Result<Option<SignedEpochOutcome>> = async {
+            let signed = self.api.fetch_state_snapshot(epoch_pks).await?;
+            signed
+                .verify_sig(&epoch_pks.public_key())
+                .map_err(|e| anyhow::format_err!("Invalid state snapshot: {e:?}"))?;
+
+            let epoch = signed.snapshot.epoch;
+            if epoch < next_epoch || until_epoch <= epoch {
+                return Ok(None);
+            }
+
+            info!(
+                target: LOG_CONSENSUS,
+                "Installing state snapshot of epoch {}", epoch
+            );
+            Ok(Some(
+                self.consensus
+                    .install_state_snapshot(signed.snapshot)
+                    .await?,
+            ))
+        }
+        .await;
+
+        match result {
+            Ok(Some(epoch_history)) => self.last_processed_epoch = Some(epoch_history),
+            Ok(None) => {}
+            Err(e) => warn!(
+                target: LOG_CONSENSUS,
+                "Unable to sync from a state snapshot, replaying the epoch history: {e}"
+            ),
+        }
+    }
+
     /// The main consensus function:
     /// 1. Await a new proposal event or receiving a proposal from peers
     /// 2. Send the `ConsensusProposal` to peers
@@ -409,8 +563,11 @@ impl FedimintServer {
               _ = Pin::new(&mut self.api_receiver).peek() => (),
               () = self.consensus.await_consensus_proposal() => (),
             }
+            self.await_round_interval().await;
+            self.last_epoch_start = Some(Instant::now());
             self.save_events_to_consensus_cache();
             let proposal = proposal.await;
+            metrics::PROPOSAL_ITEMS.observe(proposal.items.len() as f64);
             let epoch = self.hbbft.epoch();
             self.hbbft.skip_to_epoch(epoch + 1);
             return Ok(vec![HbbftConsensusOutcome {
@@ -426,12 +583,19 @@ impl FedimintServer {
                     break self.handle_message(msg).await?
                 }
                 EpochTriggerEvent::NewMessage(msg) => self.handle_message(msg).await?,
-                _ => break vec![],
+                EpochTriggerEvent::RunEpochRequest => break vec![],
+                EpochTriggerEvent::ApiEvent | EpochTriggerEvent::ModuleProposalEvent => {
+                    self.await_round_interval().await;
+                    break vec![];
+                }
             };
         };
+        self.last_epoch_start = Some(Instant::now());
         self.save_events_to_consensus_cache();
+        let epoch_timer = metrics::EPOCH_DURATION_SECONDS.start_timer();
 
         let proposal = proposal.await;
+        metrics::PROPOSAL_ITEMS.observe(proposal.items.len() as f64);
         for peer in proposal.drop_peers.iter() {
             self.connections.ban_peer(*peer).await;
         }
@@ -442,14 +606,30 @@ impl FedimintServer {
             let msg = self.connections.receive().await?;
             outcomes = self.handle_message(msg).await?;
         }
+        epoch_timer.observe_duration();
         Ok(outcomes)
     }
 
+    /// Waits until the round interval passed since our last epoch started, so
+    /// items arriving in the meantime are batched into the next epoch
+    async fn await_round_interval(&self) {
+        let Some(last_epoch_start) = self.last_epoch_start else {
+            return;
+        };
+
+        let interval =
+            Duration::from_millis(self.consensus.consensus_params().await.round_interval_ms);
+        sleep(interval.saturating_sub(last_epoch_start.elapsed())).await;
+    }
+
     // save any API events we have in the channel
     fn save_events_to_consensus_cache(&mut self) {
         let mut event_cache = self.consensus.api_event_cache.lock().unwrap();
         while let Some(Some(event)) = self.api_receiver.next().now_or_never() {
-            event_cache.insert(event);
+            // transactions are already waiting in the mempool
+            if !matches!(event, ApiEvent::Transaction(_)) {
+                event_cache.insert(event);
+            }
         }
     }
 
@@ -474,6 +654,9 @@ impl FedimintServer {
             let (outcome, ban_peers) =
                 module_parse_outcome(outcome, &self.consensus.modules.decoder_registry());
             for peer in ban_peers {
+                metrics::REJECTED_ITEMS
+                    .with_label_values(&["undecodable"])
+                    .inc();
                 self.connections.ban_peer(peer).await;
             }
             outcomes.push(outcome);
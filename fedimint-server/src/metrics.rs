@@ -0,0 +1,168 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounterVec, IntGaugeVec,
+    TextEncoder,
+};
+
+/// Time from proposing in an epoch until consensus produced an outcome
+pub static EPOCH_DURATION_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "fedimint_consensus_epoch_duration_seconds",
+        "Time from proposing in an epoch until consensus produced an outcome",
+        exponential_buckets(0.01, 2.0, 14).expect("valid buckets")
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of consensus items we proposed in an epoch
+pub static PROPOSAL_ITEMS: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "fedimint_consensus_proposal_items",
+        "Number of consensus items we proposed in an epoch",
+        exponential_buckets(1.0, 2.0, 12).expect("valid buckets")
+    )
+    .expect("metric is only registered once")
+});
+
+/// Time a module took to process an epoch, by module instance id and the stage
+/// of processing (`begin_consensus_epoch` or `end_consensus_epoch`)
+pub static MODULE_PROCESSING_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "fedimint_module_processing_duration_seconds",
+        "Time a module took to process an epoch",
+        &["module", "stage"],
+        exponential_buckets(0.001, 2.0, 14).expect("valid buckets")
+    )
+    .expect("metric is only registered once")
+});
+
+/// Time until a message sent to a peer was acknowledged, since acks are sent
+/// along with the peer's own messages this is an upper bound of the round-trip
+/// time
+pub static PEER_ROUND_TRIP_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "fedimint_peer_round_trip_seconds",
+        "Time until a message sent to a peer was acknowledged",
+        &["peer"],
+        exponential_buckets(0.001, 2.0, 16).expect("valid buckets")
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of consensus items we rejected, by kind of item
+pub static REJECTED_ITEMS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "fedimint_consensus_rejected_items_total",
+        "Number of consensus items we rejected",
+        &["kind"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of database writes made while processing an epoch
+pub static EPOCH_DB_WRITES: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "fedimint_consensus_epoch_db_writes",
+        "Number of database writes made while processing an epoch",
+        exponential_buckets(1.0, 2.0, 16).expect("valid buckets")
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of violated invariants found by the last integrity check, by module
+/// instance id or `consensus` for the state outside of the modules
+pub static INTEGRITY_VIOLATIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "fedimint_integrity_violations",
+        "Number of violated invariants found by the last integrity check",
+        &["module"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Whether the last state hash a peer contributed differed from our hash of
+/// the same epoch, by peer
+pub static STATE_HASH_DIVERGED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "fedimint_state_hash_diverged",
+        "Whether the last state hash a peer contributed differed from ours",
+        &["peer"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of state hashes our peers contributed that differed from ours, by
+/// peer
+pub static STATE_HASH_DIVERGENCES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "fedimint_state_hash_divergences_total",
+        "Number of state hashes our peers contributed that differed from ours",
+        &["peer"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of active API subscriptions, by kind of subscription
+pub static API_ACTIVE_SUBSCRIPTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "fedimint_api_active_subscriptions",
+        "Number of active API subscriptions",
+        &["kind"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of API subscriptions we evicted, by kind of subscription and reason
+pub static API_SUBSCRIPTION_EVICTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "fedimint_api_subscription_evictions_total",
+        "Number of API subscriptions we evicted",
+        &["kind", "reason"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Renders all registered metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("encoding metrics into a vec can't fail");
+    String::from_utf8(buffer).expect("Prometheus text format is utf-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, MODULE_PROCESSING_SECONDS, PEER_ROUND_TRIP_SECONDS, REJECTED_ITEMS};
+
+    #[test]
+    fn test_render_labeled_metrics() {
+        MODULE_PROCESSING_SECONDS
+            .with_label_values(&["42", "end_consensus_epoch"])
+            .observe(0.5);
+        PEER_ROUND_TRIP_SECONDS
+            .with_label_values(&["42"])
+            .observe(0.01);
+        REJECTED_ITEMS.with_label_values(&["test_item"]).inc_by(3);
+
+        let rendered = render();
+        assert!(rendered.contains(concat!(
+            "fedimint_module_processing_duration_seconds_count",
+            "{module=\"42\",stage=\"end_consensus_epoch\"} 1"
+        )));
+        assert!(rendered.contains("fedimint_peer_round_trip_seconds_count{peer=\"42\"} 1"));
+        assert!(rendered.contains("fedimint_consensus_rejected_items_total{kind=\"test_item\"} 3"));
+    }
+
+    #[test]
+    fn test_reject_wrong_labels() {
+        // every observation has to name the module and the stage
+        assert!(MODULE_PROCESSING_SECONDS
+            .get_metric_with_label_values(&["42"])
+            .is_err());
+        assert!(REJECTED_ITEMS
+            .get_metric_with_label_values(&["test_item", "extra"])
+            .is_err());
+    }
+}
@@ -1,37 +1,97 @@
 //! Implements the client API through which users interact with the federation
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Formatter};
+use std::net::SocketAddr;
 use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use async_trait::async_trait;
+use fedimint_core::admin_client::{
+    BackupInfo, ConsensusVersionStatus, GuardianStatus, IntegrityReport, ParamsChangeStatus,
+    PeerEndpointBan, StateHashStatus,
+};
 use fedimint_core::config::ConfigResponse;
 use fedimint_core::core::ModuleInstanceId;
-use fedimint_core::epoch::SerdeEpochHistory;
+use fedimint_core::epoch::{
+    ConsensusParams, ConsensusVersionActivation, EpochArchiveInfo, ExplorerEpoch,
+    ParamsChangeProposal, SerdeEpochHistory, SerdeSignatureShare, SerdeStateSnapshot,
+    SignedEpochCheckpoint, StateSnapshotShare,
+};
+use fedimint_core::module::audit::SignedAuditSummary;
+use fedimint_core::module::version::SupportedApiVersionsSummary;
 use fedimint_core::module::{
-    api_endpoint, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased,
+    api_endpoint, ApiAuthTier, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequest,
+    ApiRequestErased, ModuleError,
 };
-use fedimint_core::outcome::TransactionStatus;
+use fedimint_core::outcome::{TransactionStatus, TransactionValidation};
+use fedimint_core::pagination::{Page, PageRequest};
 use fedimint_core::server::DynServerModule;
-use fedimint_core::task::TaskHandle;
-use fedimint_core::TransactionId;
+use fedimint_core::signed_api::{response_hash, SignedApiResponse};
+use fedimint_core::task::{TaskGroup, TaskHandle};
+use fedimint_core::{PeerId, TransactionId};
 use fedimint_logging::LOG_NET_API;
 use futures::FutureExt;
-use jsonrpsee::server::ServerBuilder;
+use jsonrpsee::server::{ServerBuilder, ServerHandle};
 use jsonrpsee::types::error::CallError;
-use jsonrpsee::types::ErrorObject;
+use jsonrpsee::types::{ErrorObject, Params};
 use jsonrpsee::RpcModule;
-use tracing::{debug, error};
+use serde::de::DeserializeOwned;
+use tokio::net::TcpListener;
+use tracing::{debug, error, info_span, Instrument};
 
-use crate::config::ServerConfig;
-use crate::consensus::FedimintConsensus;
+use crate::backup::{create_backup, BackupLocation};
+use crate::config::keys::EpochSigner;
+use crate::config::{ApiLimits, ServerConfig};
+use crate::consensus::{FedimintConsensus, TransactionSubmissionError};
+use crate::net::front::serve_api_front;
+use crate::net::limits::{RequestLimiter, SourceLimiter};
+use crate::net::sessions::{SubscriptionTracker, TrackedSubscription};
+use crate::net::tls::serve_api_tls;
 use crate::transaction::SerdeTransaction;
 
 /// A state that has context for the API, passed to each rpc handler callback
 #[derive(Clone)]
 pub struct RpcHandlerCtx<M> {
     pub rpc_context: Arc<M>,
+    pub limiter: Arc<RequestLimiter>,
+    /// Tracks the subscriptions of the server so idle ones can be evicted
+    pub sessions: Arc<SubscriptionTracker>,
+    /// Signs responses for clients asking for it, `None` if we don't sign
+    pub signer: Option<Arc<ApiResponseSigner>>,
+}
+
+/// Signs API responses with our epoch signer, see
+/// [`fedimint_core::signed_api`]
+#[derive(Debug)]
+pub struct ApiResponseSigner {
+    peer: PeerId,
+    signer: Arc<dyn EpochSigner>,
+}
+
+impl ApiResponseSigner {
+    pub fn new(peer: PeerId, signer: Arc<dyn EpochSigner>) -> Self {
+        Self { peer, signer }
+    }
+
+    async fn sign(
+        &self,
+        method: &str,
+        params: &serde_json::Value,
+        response: serde_json::Value,
+    ) -> Result<SignedApiResponse, ApiError> {
+        let hash = response_hash(self.peer, method, params, &response);
+        let signature = self
+            .signer
+            .sign(hash)
+            .await
+            .map_err(|e| ApiError::server_error(format!("Unable to sign the response: {e}")))?;
+        Ok(SignedApiResponse {
+            response,
+            signature: Some(SerdeSignatureShare(signature)),
+        })
+    }
 }
 
 impl<M: Debug> Debug for RpcHandlerCtx<M> {
@@ -63,7 +123,7 @@ impl HasApiContext<FedimintConsensus> for FedimintConsensus {
         (
             self,
             ApiEndpointContext::new(
-                request.auth == Some(self.cfg.private.api_auth.clone()),
+                self.auth_tier(request),
                 self.db.begin_transaction().await,
                 id,
             ),
@@ -71,6 +131,19 @@ impl HasApiContext<FedimintConsensus> for FedimintConsensus {
     }
 }
 
+impl FedimintConsensus {
+    /// The tier the auth of `request` grants access to
+    fn auth_tier<T>(&self, request: &ApiRequest<T>) -> ApiAuthTier {
+        if request.auth.as_ref() == Some(&self.cfg.private.api_auth) {
+            ApiAuthTier::Admin
+        } else if self.cfg.local.user_auth.is_none() || request.auth == self.cfg.local.user_auth {
+            ApiAuthTier::User
+        } else {
+            ApiAuthTier::Public
+        }
+    }
+}
+
 #[async_trait]
 impl HasApiContext<DynServerModule> for FedimintConsensus {
     async fn context(
@@ -90,43 +163,264 @@ pub async fn run_server(
     cfg: ServerConfig,
     fedimint: Arc<FedimintConsensus>,
     task_handle: TaskHandle,
+    mut task_group: TaskGroup,
 ) {
+    let limits = &cfg.local.api_limits;
+    let signer = cfg.local.sign_api_responses.then(|| {
+        Arc::new(ApiResponseSigner::new(
+            cfg.local.identity,
+            fedimint.epoch_signer.clone(),
+        ))
+    });
+    let sessions = Arc::new(SubscriptionTracker::new(Duration::from_secs(
+        limits.subscription_idle_timeout_secs,
+    )));
+    let sweep_interval = Duration::from_secs(limits.subscription_sweep_interval_secs);
+    let sweeper_sessions = sessions.clone();
+    task_group
+        .spawn("api-subscription-sweeper", move |handle| async move {
+            let shutdown_rx = handle.make_shutdown_rx().await;
+            sweeper_sessions
+                .run_sweeper(sweep_interval, shutdown_rx)
+                .await
+        })
+        .await;
+
     let state = RpcHandlerCtx {
         rpc_context: fedimint.clone(),
+        limiter: Arc::new(RequestLimiter::new(limits)),
+        sessions: sessions.clone(),
+        signer: signer.clone(),
     };
     let mut rpc_module = RpcModule::new(state);
 
     attach_endpoints(&mut rpc_module, server_endpoints(), None);
+    attach_subscriptions(&mut rpc_module);
+
+    // the admin endpoints are only served publicly if there is no separate
+    // admin bind address
+    if cfg.local.admin_bind.is_none() {
+        attach_endpoints(&mut rpc_module, admin_endpoints(), None);
+    }
 
     for (id, module) in fedimint.modules.iter_modules() {
         attach_endpoints(&mut rpc_module, module.api_endpoints(), Some(id));
     }
 
-    debug!(addr = cfg.local.api_bind.to_string(), "Starting WSServer");
-    let server = ServerBuilder::new()
-        .max_connections(cfg.local.max_connections)
-        .ping_interval(Duration::from_secs(10))
-        .build(&cfg.local.api_bind.to_string())
+    // the public API is served on a loopback address, the connections to our
+    // API binds are forwarded to it by the front enforcing the per source limits
+    let mut server_handles = vec![];
+    let (api_target, api_handle) = start_server(
+        loopback_ephemeral(cfg.local.api_bind),
+        cfg.local.max_connections,
+        limits,
+        rpc_module,
+    )
+    .await;
+    server_handles.push(api_handle);
+
+    let source_limiter = SourceLimiter::new(limits);
+    let api_listener = TcpListener::bind(cfg.local.api_bind)
         .await
         .context(format!("Bind address: {}", cfg.local.api_bind))
         .expect("Could not start API server");
+    let mut listeners = vec![api_listener];
+    // an extra address that isn't available, e.g. since the machine has no
+    // IPv6, shouldn't keep the guardian from serving the API on the others
+    for bind in &cfg.local.extra_api_binds {
+        match TcpListener::bind(bind).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => {
+                error!(target: LOG_NET_API, %bind, err = %e, "Could not bind extra API address");
+            }
+        }
+    }
+    for listener in listeners {
+        let source_limiter = source_limiter.clone();
+        let shutdown_rx = task_handle.make_shutdown_rx().await;
+        tokio::spawn(async move {
+            if let Err(e) = serve_api_front(listener, api_target, source_limiter, shutdown_rx).await
+            {
+                error!(target: LOG_NET_API, err = %e, "Could not serve the API");
+            }
+        });
+    }
 
-    let server_handle = server
-        .start(rpc_module)
-        .expect("Could not start API server");
+    if let Some(admin_bind) = cfg.local.admin_bind {
+        // the admin server has its own limiter, so public clients exhausting
+        // the limits don't lock out the guardian
+        let mut admin_rpc_module = RpcModule::new(RpcHandlerCtx {
+            rpc_context: fedimint.clone(),
+            limiter: Arc::new(RequestLimiter::new(limits)),
+            sessions,
+            signer,
+        });
+        attach_endpoints(&mut admin_rpc_module, admin_endpoints(), None);
+
+        server_handles.push(
+            start_server(
+                admin_bind,
+                cfg.local.max_connections,
+                limits,
+                admin_rpc_module,
+            )
+            .await
+            .1,
+        );
+    }
+
+    #[cfg(unix)]
+    if let Some(admin_socket) = cfg.local.admin_socket.clone() {
+        let target = cfg.local.admin_bind.map_or(api_target, loopback);
+        let shutdown_rx = task_handle.make_shutdown_rx().await;
+        tokio::spawn(async move {
+            if let Err(e) = proxy_unix_socket(&admin_socket, target, shutdown_rx).await {
+                error!(target: LOG_NET_API, err = %e, "Could not serve the admin socket");
+            }
+        });
+    }
+
+    if let Some(api_tls) = cfg.local.api_tls.clone() {
+        let target = api_target;
+        let source_limiter = source_limiter.clone();
+        let shutdown_rx = task_handle.make_shutdown_rx().await;
+        tokio::spawn(async move {
+            if let Err(e) = serve_api_tls(api_tls, target, source_limiter, shutdown_rx).await {
+                error!(target: LOG_NET_API, err = %e, "Could not serve the API over TLS");
+            }
+        });
+    }
 
-    let stop_handle = server_handle.clone();
+    let stop_handles = server_handles.clone();
 
     task_handle
         .on_shutdown(Box::new(move || {
             Box::pin(async move {
-                // ignore errors: we don't care if already stopped
-                let _ = stop_handle.stop();
+                for stop_handle in stop_handles {
+                    // ignore errors: we don't care if already stopped
+                    let _ = stop_handle.stop();
+                }
             })
         }))
         .await;
 
-    server_handle.stopped().await
+    for server_handle in server_handles {
+        server_handle.stopped().await
+    }
+}
+
+pub(crate) async fn start_server<M>(
+    bind: SocketAddr,
+    max_connections: u32,
+    limits: &ApiLimits,
+    rpc_module: RpcModule<RpcHandlerCtx<M>>,
+) -> (SocketAddr, ServerHandle)
+where
+    M: Send + Sync + 'static,
+{
+    debug!(addr = bind.to_string(), "Starting WSServer");
+    let server = ServerBuilder::new()
+        .max_connections(max_connections)
+        .max_request_body_size(limits.max_request_size)
+        .max_response_body_size(limits.max_response_size)
+        .max_subscriptions_per_connection(limits.max_subscriptions_per_connection)
+        .ping_interval(Duration::from_secs(10))
+        .build(&bind.to_string())
+        .await
+        .context(format!("Bind address: {bind}"))
+        .expect("Could not start API server");
+    let local_addr = server
+        .local_addr()
+        .expect("Bound API server has an address");
+
+    let handle = server
+        .start(rpc_module)
+        .expect("Could not start API server");
+    (local_addr, handle)
+}
+
+/// Address to connect to a server bound to `bind` on the same machine
+fn loopback(mut bind: SocketAddr) -> SocketAddr {
+    if bind.ip().is_unspecified() {
+        match bind {
+            SocketAddr::V4(_) => bind.set_ip(std::net::Ipv4Addr::LOCALHOST.into()),
+            SocketAddr::V6(_) => bind.set_ip(std::net::Ipv6Addr::LOCALHOST.into()),
+        }
+    }
+    bind
+}
+
+/// Loopback address of the same IP version as `bind` with a port assigned by
+/// the OS
+pub(crate) fn loopback_ephemeral(bind: SocketAddr) -> SocketAddr {
+    match bind {
+        SocketAddr::V4(_) => SocketAddr::new(std::net::Ipv4Addr::LOCALHOST.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(std::net::Ipv6Addr::LOCALHOST.into(), 0),
+    }
+}
+
+/// Forwards every connection to the unix socket at `path` to the API server
+/// at `target` until shutdown, since the API server only listens on TCP
+///
+/// Only our own user may use the socket, so other users of the machine can't
+/// reach the admin API through it.
+#[cfg(unix)]
+async fn proxy_unix_socket(
+    path: &std::path::Path,
+    target: SocketAddr,
+    mut shutdown_rx: tokio::sync::oneshot::Receiver<()>,
+) -> anyhow::Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    // a socket left behind by a previous run would fail the bind
+    if tokio::fs::try_exists(path).await? {
+        tokio::fs::remove_file(path).await?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    // connections made before the permissions were set are refused by
+    // checking the user of every peer
+    let owner = tokio::fs::metadata(path).await?.uid();
+    tracing::info!(
+        target: LOG_NET_API,
+        path = %path.display(),
+        "Serving the admin API on unix socket"
+    );
+
+    loop {
+        let mut incoming = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = &mut shutdown_rx => break,
+        };
+        match incoming.peer_cred() {
+            Ok(cred) if cred.uid() == owner => {}
+            Ok(cred) => {
+                tracing::warn!(
+                    target: LOG_NET_API,
+                    uid = cred.uid(),
+                    "Refusing admin socket connection of another user"
+                );
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!(target: LOG_NET_API, err = %e, "Could not check admin socket peer");
+                continue;
+            }
+        }
+        tokio::spawn(async move {
+            let result = async {
+                let mut outgoing = tokio::net::TcpStream::connect(target).await?;
+                tokio::io::copy_bidirectional(&mut incoming, &mut outgoing).await
+            }
+            .await;
+            if let Err(e) = result {
+                tracing::warn!(target: LOG_NET_API, err = %e, "Admin socket connection failed");
+            }
+        });
+    }
+
+    tokio::fs::remove_file(path).await?;
+    Ok(())
 }
 
 const API_ENDPOINT_TIMEOUT: Duration = Duration::from_secs(60);
@@ -152,6 +446,7 @@ pub fn attach_endpoints<State, T>(
         // Another memory leak that is fine because the function is only called once at
         // startup
         let handler: &'static _ = Box::leak(endpoint.handler);
+        let auth_tier = endpoint.auth_tier;
 
         rpc_module
             .register_async_method(path, move |params, rpc_state| async move {
@@ -163,15 +458,36 @@ pub fn attach_endpoints<State, T>(
                 // are only reading and the few that do write anything are atomic. Lastly, this
                 // is only the last line of defense
                 AssertUnwindSafe(tokio::time::timeout(API_ENDPOINT_TIMEOUT, async {
-                    let request = serde_json::from_value(params)
+                    // held until the request was processed
+                    let _permit = rpc_state.limiter.admit()?;
+                    let request: ApiRequestErased = serde_json::from_value(params)
                         .map_err(|e| ApiError::bad_request(e.to_string()))?;
                     let (state, context) = rpc_context.context(&request, module_instance_id).await;
+                    if context.auth_tier() < auth_tier {
+                        return Err(ApiError::unauthorized());
+                    }
 
-                    let res = (handler)(state, context, request).await;
+                    let sign_params = request.sign_response.then(|| request.params.clone());
+                    let response = (handler)(state, context, request).await?;
 
-                    res
+                    let Some(params) = sign_params else {
+                        return Ok(response);
+                    };
+                    let signed = match &rpc_state.signer {
+                        Some(signer) => signer.sign(path, &params, response).await?,
+                        None => SignedApiResponse {
+                            response,
+                            signature: None,
+                        },
+                    };
+                    Ok(serde_json::to_value(signed).expect("encoding error"))
                 }))
                 .catch_unwind()
+                .instrument(info_span!(
+                    "API request",
+                    method = path,
+                    module = module_instance_id
+                ))
                 .await
                 .map_err(|_| {
                     error!(
@@ -187,16 +503,162 @@ pub fn attach_endpoints<State, T>(
                 .map_err(|tokio::time::error::Elapsed { .. }| {
                     jsonrpsee::core::Error::RequestTimeout
                 })?
-                .map_err(|e| {
-                    jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
-                        e.code, e.message, None::<()>,
-                    )))
-                })
+                .map_err(into_rpc_error)
             })
             .expect("Failed to register async method");
     }
 }
 
+fn into_rpc_error(e: ApiError) -> jsonrpsee::core::Error {
+    jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
+        e.code, e.message, e.data,
+    )))
+}
+
+/// Epochs a subscription may start behind our current epoch, older epochs
+/// have to be fetched from the epoch history
+const MAX_EPOCH_SUBSCRIPTION_BACKLOG: u64 = 100;
+
+/// Attaches the subscriptions through which clients get transaction outcomes
+/// and epochs pushed instead of polling for them
+fn attach_subscriptions(rpc_module: &mut RpcModule<RpcHandlerCtx<FedimintConsensus>>) {
+    rpc_module
+        .register_subscription(
+            "/subscribe_transaction",
+            "/transaction_outcome",
+            "/unsubscribe_transaction",
+            |params, mut sink, rpc_state| {
+                let (request, subscription) = match admit_subscription::<TransactionId>(
+                    "transaction",
+                    ApiAuthTier::User,
+                    params,
+                    &rpc_state,
+                ) {
+                    Ok(admitted) => admitted,
+                    Err(e) => {
+                        let _ = sink.reject(into_rpc_error(e));
+                        return Ok(());
+                    }
+                };
+
+                let fedimint = rpc_state.rpc_context.clone();
+                let signer = rpc_state.signer.clone();
+                tokio::spawn(async move {
+                    let txid = request.params;
+                    // a client waiting for a transaction we never saw would hold its permit
+                    // until evicted, it can still wait for the outcome with a bounded request
+                    if !fedimint.is_known_transaction(txid).await {
+                        let _ = sink.reject(into_rpc_error(ApiError::not_found(format!(
+                            "transaction {txid} is unknown"
+                        ))));
+                        return;
+                    }
+                    if sink.accept().is_err() {
+                        return;
+                    }
+
+                    let outcome = futures::stream::once(async move {
+                        let status = fedimint.wait_transaction_status(txid).await;
+                        let response = serde_json::to_value(status).expect("encoding error");
+                        if !request.sign_response {
+                            return response;
+                        }
+                        let signed = match &signer {
+                            Some(signer) => signer
+                                .sign(
+                                    "/subscribe_transaction",
+                                    &serde_json::to_value(txid).expect("encoding error"),
+                                    response.clone(),
+                                )
+                                .await
+                                .ok(),
+                            None => None,
+                        };
+                        // the outcome is still pushed if signing failed, the client then
+                        // treats it like the response of a guardian that doesn't sign
+                        let signed = signed.unwrap_or(SignedApiResponse {
+                            response,
+                            signature: None,
+                        });
+                        serde_json::to_value(signed).expect("encoding error")
+                    });
+                    subscription.pipe(sink, Box::pin(outcome)).await;
+                });
+                Ok(())
+            },
+        )
+        .expect("Failed to register subscription");
+
+    rpc_module
+        .register_subscription(
+            "/subscribe_epochs",
+            "/epoch",
+            "/unsubscribe_epochs",
+            |params, mut sink, rpc_state| {
+                let (request, subscription) = match admit_subscription::<u64>(
+                    "epochs",
+                    ApiAuthTier::Public,
+                    params,
+                    &rpc_state,
+                ) {
+                    Ok(admitted) => admitted,
+                    Err(e) => {
+                        let _ = sink.reject(into_rpc_error(e));
+                        return Ok(());
+                    }
+                };
+                sink.accept()?;
+
+                let fedimint = rpc_state.rpc_context.clone();
+                tokio::spawn(async move {
+                    let first_epoch = fedimint
+                        .get_epoch_count()
+                        .await
+                        .saturating_sub(MAX_EPOCH_SUBSCRIPTION_BACKLOG)
+                        .max(request.params);
+                    let epochs = futures::stream::unfold(first_epoch, move |epoch| {
+                        let fedimint = fedimint.clone();
+                        async move {
+                            let outcome = fedimint.wait_signed_epoch_history(epoch).await;
+                            Some((SerdeEpochHistory::from(&outcome), epoch + 1))
+                        }
+                    });
+                    subscription.pipe(sink, Box::pin(epochs)).await;
+                });
+                Ok(())
+            },
+        )
+        .expect("Failed to register subscription");
+}
+
+/// Admits a subscription of `kind` requiring `auth_tier` and parses its
+/// request, the returned subscription has to be held until it ended
+fn admit_subscription<P: DeserializeOwned>(
+    kind: &'static str,
+    auth_tier: ApiAuthTier,
+    params: Params,
+    rpc_state: &RpcHandlerCtx<FedimintConsensus>,
+) -> Result<(ApiRequest<P>, TrackedSubscription), ApiError> {
+    // subscribing counts against the request rate, but the subscription only
+    // holds a subscription permit
+    drop(rpc_state.limiter.admit()?);
+
+    let request = params
+        .one::<ApiRequestErased>()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?
+        .to_typed::<P>()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    if rpc_state.rpc_context.auth_tier(&request) < auth_tier {
+        return Err(ApiError::unauthorized());
+    }
+
+    let permit = rpc_state.limiter.admit_subscription()?;
+    Ok((request, rpc_state.sessions.track(kind, permit)))
+}
+
+/// Epochs `/explore_epochs` returns at most in a single page
+const MAX_EXPLORED_EPOCHS: usize = 20;
+
 fn server_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
     vec![
         api_endpoint! {
@@ -208,11 +670,27 @@ fn server_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
 
                 fedimint.submit_transaction(transaction)
                     .await
-                    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+                    .map_err(|e| match e {
+                        TransactionSubmissionError::LimitExceeded(limit) => {
+                            ApiError::limit_exceeded(&limit)
+                        }
+                        e @ TransactionSubmissionError::ModuleError(_, ModuleError::Conflict(_)) => {
+                            ApiError::conflict(e.to_string())
+                        }
+                        e => ApiError::bad_request(e.to_string()),
+                    })?;
 
                 Ok(tx_id)
             }
         },
+        api_endpoint! {
+            "/validate_transaction",
+            async |fedimint: &FedimintConsensus, _context, serde_transaction: SerdeTransaction| -> TransactionValidation {
+                let transaction = serde_transaction.try_into_inner(&fedimint.modules.decoder_registry()).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                Ok(fedimint.validate_transaction(&transaction).await)
+            }
+        },
         api_endpoint! {
             "/fetch_transaction",
             async |fedimint: &FedimintConsensus, _context, tx_hash: TransactionId| -> Option<TransactionStatus> {
@@ -239,33 +717,298 @@ fn server_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
         },
         api_endpoint! {
             "/fetch_epoch_history",
+            ApiAuthTier::Public,
             async |fedimint: &FedimintConsensus, _context, epoch: u64| -> SerdeEpochHistory {
                 let epoch = fedimint.epoch_history(epoch).await.ok_or_else(|| ApiError::not_found(String::from("epoch not found")))?;
                 Ok((&epoch).into())
             }
         },
+        api_endpoint! {
+            "/fetch_archived_epoch_history",
+            ApiAuthTier::Public,
+            async |fedimint: &FedimintConsensus, _context, epoch: u64| -> SerdeEpochHistory {
+                if fedimint.epoch_archive_info().await.is_none() {
+                    return Err(ApiError::not_found(String::from("not an archive")));
+                }
+                let epoch = fedimint.epoch_history(epoch).await.ok_or_else(|| ApiError::not_found(String::from("epoch not found")))?;
+                Ok((&epoch).into())
+            }
+        },
+        api_endpoint! {
+            "/explore_epoch",
+            async |fedimint: &FedimintConsensus, _context, epoch: u64| -> ExplorerEpoch {
+                fedimint.explorer_limiter.admit(1, Instant::now())?;
+                fedimint.explore_epoch(epoch).await.ok_or_else(|| ApiError::not_found(String::from("epoch not found")))
+            }
+        },
+        api_endpoint! {
+            "/explore_epochs",
+            async |fedimint: &FedimintConsensus, _context, request: PageRequest<u64>| -> Page<ExplorerEpoch, u64> {
+                let epoch_count = fedimint.get_epoch_count().await;
+                let from = request.cursor.map_or(0, |cursor| cursor.saturating_add(1));
+                let remaining = epoch_count.saturating_sub(from).try_into().unwrap_or(usize::MAX);
+                let limit = request.limit().min(MAX_EXPLORED_EPOCHS).min(remaining);
+                if limit == 0 {
+                    return Ok(Page { items: vec![], next_cursor: None });
+                }
+
+                // the page is shortened to the epochs left in the explorer's budget
+                let limit = fedimint.explorer_limiter.admit(limit, Instant::now())?;
+                let mut items = vec![];
+                for epoch in (from..).take(limit) {
+                    match fedimint.explore_epoch(epoch).await {
+                        Some(explored) => items.push(explored),
+                        None => break,
+                    }
+                }
+
+                let next = from + items.len() as u64;
+                let next_cursor = (items.len() == limit && next < epoch_count).then(|| next - 1);
+                Ok(Page { items, next_cursor })
+            }
+        },
+        api_endpoint! {
+            "/fetch_epoch_archive_info",
+            ApiAuthTier::Public,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> Option<EpochArchiveInfo> {
+                Ok(fedimint.epoch_archive_info().await)
+            }
+        },
+        api_endpoint! {
+            "/fetch_epoch_checkpoint",
+            ApiAuthTier::Public,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> Option<SignedEpochCheckpoint> {
+                Ok(fedimint.latest_epoch_checkpoint().await)
+            }
+        },
+        api_endpoint! {
+            "/fetch_epoch_checkpoint_at",
+            ApiAuthTier::Public,
+            async |fedimint: &FedimintConsensus, _context, epoch: u64| -> SignedEpochCheckpoint {
+                fedimint.epoch_checkpoint(epoch).await.ok_or_else(|| ApiError::not_found(String::from("checkpoint not found")))
+            }
+        },
+        api_endpoint! {
+            "/fetch_state_snapshot_share",
+            ApiAuthTier::User,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> Option<StateSnapshotShare> {
+                Ok(fedimint.state_snapshot_share().await)
+            }
+        },
+        api_endpoint! {
+            "/fetch_state_snapshot",
+            ApiAuthTier::User,
+            async |fedimint: &FedimintConsensus, _context, epoch: u64| -> SerdeStateSnapshot {
+                let snapshot = fedimint.state_snapshot(epoch).await.ok_or_else(|| ApiError::not_found(String::from("snapshot not found")))?;
+                Ok((&snapshot).into())
+            }
+        },
+        api_endpoint! {
+            "/audit",
+            ApiAuthTier::Public,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> SignedAuditSummary {
+                fedimint
+                    .audit_summary()
+                    .await
+                    .map_err(|e| ApiError::server_error(e.to_string()))
+            }
+        },
         api_endpoint! {
             "/fetch_epoch_count",
+            ApiAuthTier::Public,
             async |fedimint: &FedimintConsensus, _context, _v: ()| -> u64 {
                 Ok(fedimint.get_epoch_count().await)
             }
         },
+        api_endpoint! {
+            "/api_version",
+            ApiAuthTier::Public,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> SupportedApiVersionsSummary {
+                Ok(fedimint.api_versions())
+            }
+        },
         api_endpoint! {
             "/config",
+            ApiAuthTier::Public,
             async |fedimint: &FedimintConsensus, context, _v: ()| -> ConfigResponse {
                 Ok(fedimint.get_config_with_sig(&mut context.dbtx()).await)
             }
         },
+    ]
+}
+
+/// Endpoints for the guardian's operator, served on the admin bind address if
+/// one is configured
+fn admin_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
+    vec![
         api_endpoint! {
             "upgrade",
-            async |fedimint: &FedimintConsensus, context, _v: ()| -> () {
-                if context.has_auth() {
-                    fedimint.signal_upgrade().await.map_err(|_| ApiError::server_error("Unable to send signal to server".to_string()))?;
-                    Ok(())
-                } else {
-                    Err(ApiError::unauthorized())
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> () {
+                fedimint.signal_upgrade().await.map_err(|_| ApiError::server_error("Unable to send signal to server".to_string()))?;
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            "propose_peer_set_change",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, to: BTreeMap<PeerId, fedimint_core::config::ApiEndpoint>| -> () {
+                // peer ids have to be contiguous for the key resharing
+                if to.is_empty() || !to.keys().copied().eq((0..to.len() as u16).map(PeerId::from)) {
+                    return Err(ApiError::bad_request("Peer ids have to be numbered from 0 to n-1".to_string()));
+                }
+
+                fedimint.propose_peer_set_change(to).await.map_err(|_| ApiError::server_error("Unable to send signal to server".to_string()))?;
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            "status",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> GuardianStatus {
+                Ok(fedimint.guardian_status().await)
+            }
+        },
+        api_endpoint! {
+            "reconnect_peer",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, peer: PeerId| -> () {
+                fedimint.reconnect_peer(peer).await.map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            "ban_peer_endpoint",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, ban: PeerEndpointBan| -> () {
+                fedimint.ban_peer_endpoint(ban.peer, ban.duration).await.map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            "integrity_report",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> Option<IntegrityReport> {
+                Ok(fedimint.integrity_report())
+            }
+        },
+        api_endpoint! {
+            "state_hash_status",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> StateHashStatus {
+                Ok(fedimint.state_hash_status().await)
+            }
+        },
+        api_endpoint! {
+            "backup",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, location: String| -> BackupInfo {
+                let location: BackupLocation = location.parse().map_err(|e: anyhow::Error| ApiError::bad_request(e.to_string()))?;
+                create_backup(fedimint, &location).await.map_err(|e| ApiError::server_error(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            "schedule_consensus_version",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, activation: ConsensusVersionActivation| -> () {
+                if activation.epoch <= fedimint.get_epoch_count().await {
+                    return Err(ApiError::bad_request("Activation epoch has to be in the future".to_string()));
+                }
+
+                fedimint.schedule_consensus_version(activation).await.map_err(|_| ApiError::server_error("Unable to send signal to server".to_string()))?;
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            "consensus_versions",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> ConsensusVersionStatus {
+                Ok(fedimint.consensus_versions().await)
+            }
+        },
+        api_endpoint! {
+            "propose_consensus_params",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, params: ConsensusParams| -> () {
+                // items beyond a limit of zero would never be proposed
+                if params.max_proposal_items == 0 || params.module_batch_limits.values().any(|&limit| limit == 0) {
+                    return Err(ApiError::bad_request("Proposal limits have to be positive".to_string()));
                 }
+                if let Some(id) = params.module_batch_limits.keys().find(|id| !fedimint.cfg.consensus.modules.contains_key(id)) {
+                    return Err(ApiError::bad_request(format!("Unknown module instance {id}")));
+                }
+
+                fedimint.propose_consensus_params(params).await.map_err(|_| ApiError::server_error("Unable to send signal to server".to_string()))?;
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            "consensus_params",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> ConsensusParams {
+                Ok(fedimint.consensus_params().await)
+            }
+        },
+        api_endpoint! {
+            "propose_params_change",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, proposal: ParamsChangeProposal| -> () {
+                fedimint
+                    .check_params_change(&proposal)
+                    .await
+                    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+                fedimint.propose_params_change(proposal).await.map_err(|_| ApiError::server_error("Unable to send signal to server".to_string()))?;
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            "params_changes",
+            ApiAuthTier::Admin,
+            async |fedimint: &FedimintConsensus, _context, _v: ()| -> ParamsChangeStatus {
+                Ok(fedimint.params_changes().await)
             }
         },
     ]
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UnixStream};
+
+    use super::proxy_unix_socket;
+
+    #[tokio::test]
+    async fn admin_socket_is_private_and_forwards_to_the_api() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("admin.sock");
+        let api = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = api.local_addr().unwrap();
+
+        let (shutdown, shutdown_rx) = tokio::sync::oneshot::channel();
+        let proxy_path = path.clone();
+        let proxy =
+            tokio::spawn(async move { proxy_unix_socket(&proxy_path, target, shutdown_rx).await });
+        while !path.exists() {
+            tokio::task::yield_now().await;
+        }
+        // the permissions are set right after the bind
+        let mut client = loop {
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            if mode & 0o777 == 0o600 {
+                break UnixStream::connect(&path).await.unwrap();
+            }
+            tokio::task::yield_now().await;
+        };
+
+        client.write_all(b"ping").await.unwrap();
+        let (mut forwarded, _) = api.accept().await.unwrap();
+        let mut received = [0; 4];
+        forwarded.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"ping");
+
+        shutdown.send(()).unwrap();
+        proxy.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+}
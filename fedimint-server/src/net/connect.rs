@@ -11,15 +11,18 @@ use std::sync::Arc;
 use anyhow::format_err;
 use async_trait::async_trait;
 use fedimint_core::PeerId;
+use fedimint_logging::LOG_NET_PEER;
 use futures::Stream;
 use tokio::io::{ReadHalf, WriteHalf};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
 use tokio_rustls::rustls::RootCertStore;
 use tokio_rustls::{rustls, TlsAcceptor, TlsConnector, TlsStream};
+use tracing::info;
 use url::Url;
 
 use crate::net::framed::{AnyFramedTransport, BidiFramed, FramedTransport};
+use crate::net::tor::{publish_onion_service, TorConfig};
 
 /// Shared [`Connector`] trait object
 pub type SharedAnyConnector<M> = Arc<dyn Connector<M> + Send + Sync + Unpin + 'static>;
@@ -46,6 +49,16 @@ pub trait Connector<M> {
     /// Listen for incoming connections on `bind_addr`
     async fn listen(&self, bind_addr: SocketAddr) -> Result<ConnectionListener<M>, anyhow::Error>;
 
+    /// Listen for incoming connections on another address besides the one
+    /// passed to [`Connector::listen`], which isn't published e.g. as onion
+    /// service again
+    async fn listen_additional(
+        &self,
+        bind_addr: SocketAddr,
+    ) -> Result<ConnectionListener<M>, anyhow::Error> {
+        self.listen(bind_addr).await
+    }
+
     /// Transform this concrete `Connector` into an owned trait object version
     /// of itself
     fn into_dyn(self) -> AnyConnector<M>
@@ -66,6 +79,10 @@ pub struct TlsTcpConnector {
     /// understands
     cert_store: RootCertStore,
     peer_names: BTreeMap<PeerId, String>,
+    /// Routes our connections through Tor if `Some`
+    tor: Option<TorConfig>,
+    /// Private key of the onion service published through Tor's control port
+    onion_service_key: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -95,8 +112,18 @@ impl TlsTcpConnector {
             peer_certs: Arc::new(PeerCertStore::new(cfg.peer_certs)),
             cert_store,
             peer_names: cfg.peer_names,
+            tor: None,
+            onion_service_key: None,
         }
     }
+
+    /// Connects to peers through Tor as configured in `tor`, publishing our
+    /// onion service with `onion_service_key` if Tor should set it up
+    pub fn with_tor(mut self, tor: TorConfig, onion_service_key: Option<String>) -> Self {
+        self.tor = Some(tor);
+        self.onion_service_key = onion_service_key;
+        self
+    }
 }
 
 impl PeerCertStore {
@@ -173,13 +200,13 @@ where
         let fake_domain = rustls::ServerName::try_from(self.peer_names[&peer].as_str())
             .expect("Always a valid DNS name");
 
+        let stream = match &self.tor {
+            Some(tor) => tor.connect(&destination).await?,
+            None => TcpStream::connect(parse_host_port(destination)?).await?,
+        };
+
         let connector = TlsConnector::from(Arc::new(cfg));
-        let tls_conn = connector
-            .connect(
-                fake_domain,
-                TcpStream::connect(parse_host_port(destination)?).await?,
-            )
-            .await?;
+        let tls_conn = connector.connect(fake_domain, stream).await?;
 
         let (_, tls_session) = tls_conn.get_ref();
         let auth_peer = self
@@ -200,6 +227,28 @@ where
     }
 
     async fn listen(&self, bind_addr: SocketAddr) -> Result<ConnectionListener<M>, anyhow::Error> {
+        self.bind(bind_addr, true).await
+    }
+
+    async fn listen_additional(
+        &self,
+        bind_addr: SocketAddr,
+    ) -> Result<ConnectionListener<M>, anyhow::Error> {
+        self.bind(bind_addr, false).await
+    }
+}
+
+impl TlsTcpConnector {
+    /// Accepts authenticated connections on `bind_addr`, publishing it as
+    /// onion service if configured and `publish`
+    async fn bind<M>(
+        &self,
+        bind_addr: SocketAddr,
+        publish: bool,
+    ) -> Result<ConnectionListener<M>, anyhow::Error>
+    where
+        M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
+    {
         let verifier = AllowAnyAuthenticatedClient::new(self.cert_store.clone());
         let config = rustls::ServerConfig::builder()
             .with_safe_defaults()
@@ -212,15 +261,41 @@ where
         let listener = TcpListener::bind(bind_addr).await?;
         let peer_certs = self.peer_certs.clone();
 
-        let stream = futures::stream::unfold(listener, move |mut listener| {
-            let acceptor = TlsAcceptor::from(Arc::new(config.clone()));
-            let peer_certs = peer_certs.clone();
+        // the onion service is removed once the control connection is closed, so it
+        // lives as long as the listener
+        let onion_service = self
+            .tor
+            .as_ref()
+            .and_then(|tor| tor.onion_service.as_ref())
+            .filter(|_| publish);
+        let control = match onion_service {
+            Some(onion_service) => {
+                let key = self.onion_service_key.as_ref().ok_or_else(|| {
+                    format_err!("Missing onion service key in the private config")
+                })?;
+                let (control, onion_address) =
+                    publish_onion_service(onion_service, key, bind_addr).await?;
+                info!(
+                    target: LOG_NET_PEER,
+                    %onion_address,
+                    port = onion_service.port,
+                    "Published onion service"
+                );
+                Some(control)
+            }
+            None => None,
+        };
+
+        let stream =
+            futures::stream::unfold((listener, control), move |(mut listener, control)| {
+                let acceptor = TlsAcceptor::from(Arc::new(config.clone()));
+                let peer_certs = peer_certs.clone();
 
-            Box::pin(async move {
-                let res = peer_certs.accept_connection(&mut listener, &acceptor).await;
-                Some((res, listener))
-            })
-        });
+                Box::pin(async move {
+                    let res = peer_certs.accept_connection(&mut listener, &acceptor).await;
+                    Some((res, (listener, control)))
+                })
+            });
         Ok(Box::pin(stream))
     }
 }
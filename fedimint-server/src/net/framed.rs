@@ -10,6 +10,7 @@ use std::task::{Context, Poll};
 use bytes::{Buf, BufMut, BytesMut};
 use fedimint_logging::LOG_NET_PEER;
 use futures::{Sink, Stream};
+use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio_util::codec::{FramedRead, FramedWrite};
@@ -31,6 +32,17 @@ pub trait FramedTransport<T>:
         &'_ mut (dyn Stream<Item = Result<T, anyhow::Error>> + Send + Unpin),
     );
 
+    /// Fails to receive frames longer than `max_size` bytes with
+    /// [`FrameTooLarge`] as soon as their length prefix is read, so they are
+    /// never buffered
+    fn set_max_frame_size(&mut self, max_size: u64);
+
+    /// Length in bytes of the last frame sent over the transport
+    fn last_sent_size(&self) -> u64;
+
+    /// Length in bytes of the last frame received over the transport
+    fn last_received_size(&self) -> u64;
+
     /// Transforms concrete `FramedTransport` object into an owned trait object
     fn into_dyn(self) -> AnyFramedTransport<T>
     where
@@ -64,9 +76,21 @@ pub struct BidiFramed<T, WH, RH> {
 /// Framed codec that uses [`bincode`] to encode structs with [`serde`] support
 #[derive(Debug)]
 pub struct BincodeCodec<T> {
+    max_frame_size: u64,
+    /// Length of the last frame encoded or decoded, excluding the length field
+    last_frame_size: u64,
     _pd: PhantomData<T>,
 }
 
+/// Error returned when the length prefix of a received frame exceeds the
+/// maximum frame size of the transport
+#[derive(Debug, Error)]
+#[error("Frame of {size} bytes exceeds the limit of {limit} bytes")]
+pub struct FrameTooLarge {
+    pub size: u64,
+    pub limit: u64,
+}
+
 impl<T, WH, RH> BidiFramed<T, WH, RH>
 where
     WH: AsyncWrite,
@@ -169,11 +193,25 @@ where
         let (sink, stream) = self.borrow_parts();
         (&mut *sink, &mut *stream)
     }
+
+    fn set_max_frame_size(&mut self, max_size: u64) {
+        self.stream.decoder_mut().max_frame_size = max_size;
+    }
+
+    fn last_sent_size(&self) -> u64 {
+        self.sink.encoder().last_frame_size
+    }
+
+    fn last_received_size(&self) -> u64 {
+        self.stream.decoder().last_frame_size
+    }
 }
 
 impl<T> BincodeCodec<T> {
     fn new() -> BincodeCodec<T> {
         BincodeCodec {
+            max_frame_size: u64::MAX,
+            last_frame_size: 0,
             _pd: Default::default(),
         }
     }
@@ -205,6 +243,7 @@ where
         let new_len = dst.len();
         let encoded_len = new_len - old_len - 8;
         dst[old_len..old_len + 8].copy_from_slice(&encoded_len.to_le_bytes()[..]);
+        self.last_frame_size = encoded_len as u64;
 
         Ok(())
     }
@@ -223,6 +262,14 @@ where
         }
 
         let length = u64::from_le_bytes(src[0..8].try_into().expect("correct length"));
+        if length > self.max_frame_size {
+            return Err(FrameTooLarge {
+                size: length,
+                limit: self.max_frame_size,
+            }
+            .into());
+        }
+
         if src.len() < (length as usize) + 8 {
             trace!(length, buffern_len = src.len(), "Received partial message");
             return Ok(None);
@@ -234,6 +281,7 @@ where
             .read_exact(&mut [0u8; 8][..])
             .expect("minimum length checked");
 
+        self.last_frame_size = length;
         Ok(bincode::deserialize_from(src.reader()).map(Option::Some)?)
     }
 }
@@ -246,7 +294,7 @@ mod tests {
     use serde::{Deserialize, Serialize};
     use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
 
-    use crate::net::framed::BidiFramed;
+    use crate::net::framed::{BidiFramed, FrameTooLarge, FramedTransport};
 
     #[tokio::test]
     async fn test_roundtrip() {
@@ -313,4 +361,42 @@ mod tests {
 
         assert!(received.is_err());
     }
+
+    #[tokio::test]
+    async fn test_frame_sizes() {
+        let (sender, recipient) = tokio::io::duplex(1024);
+        let mut framed_sender =
+            BidiFramed::<Vec<u8>, WriteHalf<DuplexStream>, ReadHalf<DuplexStream>>::new(sender);
+        let mut framed_recipient =
+            BidiFramed::<Vec<u8>, WriteHalf<DuplexStream>, ReadHalf<DuplexStream>>::new(recipient);
+
+        framed_sender.send(vec![0; 100]).await.unwrap();
+        // bincode prefixes the vector with its length
+        assert_eq!(framed_sender.last_sent_size(), 108);
+
+        framed_recipient.next().await.unwrap().unwrap();
+        assert_eq!(framed_recipient.last_received_size(), 108);
+    }
+
+    #[tokio::test]
+    async fn test_reject_oversized_frame_from_length() {
+        let (mut sender, recipient) = tokio::io::duplex(1024);
+        let mut framed_recipient =
+            BidiFramed::<Vec<u8>, WriteHalf<DuplexStream>, ReadHalf<DuplexStream>>::new(recipient);
+        framed_recipient.set_max_frame_size(1000);
+
+        // only the length is sent, the frame must be rejected without waiting
+        // for its body
+        sender.write_all(&1001u64.to_le_bytes()).await.unwrap();
+
+        let err = tokio::time::timeout(Duration::from_secs(1), framed_recipient.next())
+            .await
+            .expect("Rejected without reading the frame")
+            .unwrap()
+            .unwrap_err();
+        let err = err
+            .downcast_ref::<FrameTooLarge>()
+            .expect("Rejected for its size");
+        assert_eq!((err.size, err.limit), (1001, 1000));
+    }
 }
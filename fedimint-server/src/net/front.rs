@@ -0,0 +1,231 @@
+//! Accepts the public API connections in front of the API server to enforce
+//! the per source limits, since the API server doesn't tell its handlers
+//! which connection or address a request came from
+//!
+//! Connections are forwarded to the API server on a loopback address. Their
+//! HTTP requests and websocket frames are parsed, but not altered, to count
+//! the requests read from each source.
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::bail;
+use fedimint_logging::LOG_NET_API;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use crate::net::limits::{SourceConnection, SourceLimiter};
+
+/// Maximum size of the head of an HTTP request, including the one upgrading
+/// the connection to a websocket
+const MAX_HTTP_HEAD_SIZE: usize = 16 * 1024;
+
+/// Accepts connections on `listener` and forwards the ones admitted by
+/// `limiter` to the API server at `target` until shutdown
+pub async fn serve_api_front(
+    listener: TcpListener,
+    target: SocketAddr,
+    limiter: Arc<SourceLimiter>,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) -> anyhow::Result<()> {
+    loop {
+        let (incoming, source) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown_rx => break,
+        };
+        let connection = match limiter.admit_connection(source.ip(), Instant::now()) {
+            Ok(connection) => connection,
+            Err(e) => {
+                debug!(target: LOG_NET_API, err = %e, "Rejected API connection");
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            if let Err(e) = forward_api_connection(incoming, target, connection).await {
+                debug!(target: LOG_NET_API, err = %e, "API connection failed");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Forwards `incoming` to the API server at `target`, pausing reading from it
+/// whenever its source exceeds its request rate
+pub async fn forward_api_connection<S>(
+    incoming: S,
+    target: SocketAddr,
+    connection: SourceConnection,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let outgoing = TcpStream::connect(target).await?;
+    let (incoming_read, mut incoming_write) = tokio::io::split(incoming);
+    let (mut outgoing_read, outgoing_write) = outgoing.into_split();
+    let mut incoming_read = BufReader::new(incoming_read);
+    let mut outgoing_write = BufWriter::new(outgoing_write);
+
+    let requests = async {
+        let result = forward_requests(&mut incoming_read, &mut outgoing_write, &connection).await;
+        outgoing_write.shutdown().await?;
+        result
+    };
+    let responses = async {
+        tokio::io::copy(&mut outgoing_read, &mut incoming_write).await?;
+        incoming_write.shutdown().await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    tokio::try_join!(requests, responses)?;
+    Ok(())
+}
+
+/// Copies HTTP requests until one upgrades the connection to a websocket,
+/// then frame by frame. Every HTTP request and websocket frame counts as a
+/// request of the source of `connection`.
+async fn forward_requests<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    connection: &SourceConnection,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    while let Some(head) = read_http_head(reader).await? {
+        throttle(connection).await;
+        writer.write_all(head.as_bytes()).await?;
+        writer.flush().await?;
+
+        if header_value(&head, "upgrade")
+            .map_or(false, |value| value.eq_ignore_ascii_case("websocket"))
+        {
+            return forward_frames(reader, writer, connection).await;
+        }
+
+        if header_value(&head, "transfer-encoding").is_some() {
+            bail!("Chunked HTTP requests are not supported");
+        }
+        let body_len = match header_value(&head, "content-length") {
+            Some(len) => len.parse::<u64>()?,
+            None => 0,
+        };
+        if tokio::io::copy(&mut (&mut *reader).take(body_len), writer).await? != body_len {
+            return Ok(());
+        }
+        writer.flush().await?;
+    }
+    Ok(())
+}
+
+/// Copies websocket frames, every frame that doesn't continue a message
+/// counts as a request, control frames too so pings can't flood us
+async fn forward_frames<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    connection: &SourceConnection,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let mut header = [0u8; 2];
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        writer.write_all(&header).await?;
+
+        let opcode = header[0] & 0x0f;
+        let masked = header[1] & 0x80 != 0;
+        let payload_len = match header[1] & 0x7f {
+            126 => {
+                let len = reader.read_u16().await?;
+                writer.write_u16(len).await?;
+                u64::from(len)
+            }
+            127 => {
+                let len = reader.read_u64().await?;
+                writer.write_u64(len).await?;
+                len
+            }
+            len => u64::from(len),
+        };
+        if masked {
+            let mut mask = [0u8; 4];
+            reader.read_exact(&mut mask).await?;
+            writer.write_all(&mask).await?;
+        }
+
+        if opcode != 0 {
+            throttle(connection).await;
+        }
+
+        if tokio::io::copy(&mut (&mut *reader).take(payload_len), writer).await? != payload_len {
+            return Ok(());
+        }
+        writer.flush().await?;
+    }
+}
+
+/// Pauses until the source of `connection` may send another request
+async fn throttle(connection: &SourceConnection) {
+    if let Some(delay) = connection.admit_request(Instant::now()) {
+        debug!(
+            target: LOG_NET_API,
+            source = %connection.source(),
+            ?delay,
+            "Throttling API connection"
+        );
+        fedimint_core::task::sleep(delay).await;
+    }
+}
+
+/// Reads the head of an HTTP request, `None` if the connection was closed
+/// before another request started
+async fn read_http_head<R>(reader: &mut R) -> anyhow::Result<Option<String>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HTTP_HEAD_SIZE {
+            bail!("HTTP request head exceeds {MAX_HTTP_HEAD_SIZE} bytes");
+        }
+        match reader.read_u8().await {
+            Ok(byte) => head.push(byte),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && head.is_empty() => {
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(Some(String::from_utf8(head)?))
+}
+
+/// Value of the header `name` of an HTTP request head, header names are case
+/// insensitive
+fn header_value<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::header_value;
+
+    #[test]
+    fn header_values_are_case_insensitive() {
+        let head = "GET / HTTP/1.1\r\nHost: localhost\r\nupgrade: WebSocket\r\n\r\n";
+        assert_eq!(header_value(head, "Upgrade"), Some("WebSocket"));
+        assert_eq!(header_value(head, "host"), Some("localhost"));
+        assert_eq!(header_value(head, "content-length"), None);
+    }
+}
@@ -0,0 +1,511 @@
+//! Throttles API requests and peer messages so clients and peers can't
+//! overload the guardian
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use fedimint_core::module::ApiError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::{ApiLimits, PeerLimits};
+
+/// Enforces the request rate and concurrency limits of [`ApiLimits`] on all
+/// requests to an API server
+#[derive(Debug)]
+pub struct RequestLimiter {
+    rate: Mutex<TokenBucket>,
+    requests_per_second: u32,
+    concurrent_requests: Arc<Semaphore>,
+    subscriptions: Arc<Semaphore>,
+}
+
+impl RequestLimiter {
+    pub fn new(limits: &ApiLimits) -> Self {
+        Self {
+            rate: Mutex::new(TokenBucket::new(
+                limits.requests_per_second,
+                limits.request_burst,
+                Instant::now(),
+            )),
+            requests_per_second: limits.requests_per_second,
+            concurrent_requests: Arc::new(Semaphore::new(limits.max_concurrent_requests as usize)),
+            subscriptions: Arc::new(Semaphore::new(limits.max_subscriptions as usize)),
+        }
+    }
+
+    /// Admits a request, the returned permit has to be held until the request
+    /// was processed
+    pub fn admit(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        if !self
+            .rate
+            .lock()
+            .expect("locking can't fail")
+            .try_take(Instant::now())
+        {
+            return Err(ApiError::too_many_requests(format!(
+                "Rate limit of {} requests per second exceeded",
+                self.requests_per_second
+            )));
+        }
+
+        self.concurrent_requests
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| ApiError::too_many_requests("Too many concurrent requests".to_string()))
+    }
+
+    /// Admits a subscription, the returned permit has to be held until the
+    /// subscription ended
+    pub fn admit_subscription(&self) -> Result<OwnedSemaphorePermit, ApiError> {
+        self.subscriptions
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| ApiError::too_many_requests("Too many active subscriptions".to_string()))
+    }
+}
+
+/// Enforces the explorer limits of [`ApiLimits`], exploring an epoch decodes
+/// all of its items so every explored epoch counts against the budget
+#[derive(Debug)]
+pub struct ExplorerLimiter {
+    epochs: Mutex<TokenBucket>,
+    epochs_per_second: u32,
+}
+
+impl ExplorerLimiter {
+    pub fn new(limits: &ApiLimits, now: Instant) -> Self {
+        Self {
+            epochs: Mutex::new(TokenBucket::new(
+                limits.explored_epochs_per_second,
+                limits.explored_epoch_burst,
+                now,
+            )),
+            epochs_per_second: limits.explored_epochs_per_second,
+        }
+    }
+
+    /// Admits exploring up to `epochs` epochs and returns how many of them
+    /// fit into the remaining budget, fails if not even one does
+    pub fn admit(&self, epochs: usize, now: Instant) -> Result<usize, ApiError> {
+        let mut bucket = self.epochs.lock().expect("locking can't fail");
+        bucket.refill(now);
+
+        let admitted = (bucket.tokens.max(0.0) as usize).min(epochs);
+        if admitted == 0 {
+            return Err(ApiError::too_many_requests(format!(
+                "Rate limit of {} explored epochs per second exceeded",
+                self.epochs_per_second
+            )));
+        }
+        bucket.take(admitted as f64);
+        Ok(admitted)
+    }
+}
+
+/// Number of tracked sources at which we start dropping the ones without
+/// connections whose budget was refilled
+const MIN_SOURCE_PRUNE_THRESHOLD: usize = 1024;
+
+/// Enforces the per source limits of [`ApiLimits`], a source being the IP
+/// address clients connect from
+#[derive(Debug)]
+pub struct SourceLimiter {
+    requests_per_second: u32,
+    request_burst: u32,
+    max_connections: u32,
+    sources: Mutex<Sources>,
+}
+
+#[derive(Debug)]
+struct Sources {
+    by_ip: HashMap<IpAddr, SourceState>,
+    prune_threshold: usize,
+}
+
+#[derive(Debug)]
+struct SourceState {
+    connections: u32,
+    requests: TokenBucket,
+}
+
+impl SourceLimiter {
+    pub fn new(limits: &ApiLimits) -> Arc<Self> {
+        Arc::new(Self {
+            requests_per_second: limits.requests_per_second_per_source,
+            request_burst: limits.request_burst_per_source,
+            max_connections: limits.max_connections_per_source,
+            sources: Mutex::new(Sources {
+                by_ip: HashMap::new(),
+                prune_threshold: MIN_SOURCE_PRUNE_THRESHOLD,
+            }),
+        })
+    }
+
+    /// Admits a connection from `source`, the returned connection has to be
+    /// held until it was closed
+    pub fn admit_connection(
+        self: &Arc<Self>,
+        source: IpAddr,
+        now: Instant,
+    ) -> anyhow::Result<SourceConnection> {
+        let mut sources = self.sources.lock().expect("locking can't fail");
+
+        // sources are kept after their last connection closed until their
+        // budget is refilled, otherwise reconnecting would reset it
+        if sources.by_ip.len() >= sources.prune_threshold {
+            sources
+                .by_ip
+                .retain(|_, state| state.connections > 0 || !state.requests.is_full(now));
+            sources.prune_threshold = (sources.by_ip.len() * 2).max(MIN_SOURCE_PRUNE_THRESHOLD);
+        }
+
+        let state = sources.by_ip.entry(source).or_insert_with(|| SourceState {
+            connections: 0,
+            requests: TokenBucket::new(self.requests_per_second, self.request_burst, now),
+        });
+        if state.connections >= self.max_connections {
+            bail!(
+                "Source {source} exceeded the limit of {} connections",
+                self.max_connections
+            );
+        }
+        state.connections += 1;
+
+        Ok(SourceConnection {
+            limiter: self.clone(),
+            source,
+        })
+    }
+}
+
+/// Connection admitted by a [`SourceLimiter`], requests read from it count
+/// against the budget of its source
+#[derive(Debug)]
+pub struct SourceConnection {
+    limiter: Arc<SourceLimiter>,
+    source: IpAddr,
+}
+
+impl SourceConnection {
+    pub fn source(&self) -> IpAddr {
+        self.source
+    }
+
+    /// Accounts for a request read from the connection and returns for how
+    /// long we have to pause reading from it to keep its source within its
+    /// rate
+    pub fn admit_request(&self, now: Instant) -> Option<Duration> {
+        let mut sources = self.limiter.sources.lock().expect("locking can't fail");
+        let state = sources
+            .by_ip
+            .get_mut(&self.source)
+            .expect("sources with connections are never removed");
+
+        let delay = state.requests.delay_for(1.0, now);
+        state.requests.take(1.0);
+        Some(delay).filter(|delay| !delay.is_zero())
+    }
+}
+
+impl Drop for SourceConnection {
+    fn drop(&mut self) {
+        let mut sources = self.limiter.sources.lock().expect("locking can't fail");
+        if let Some(state) = sources.by_ip.get_mut(&self.source) {
+            state.connections -= 1;
+        }
+    }
+}
+
+/// Enforces the [`PeerLimits`] on the messages we read from a peer
+#[derive(Debug)]
+pub struct PeerLimiter {
+    bytes: TokenBucket,
+    messages: TokenBucket,
+    max_message_size: u64,
+    max_throttle: Duration,
+}
+
+impl PeerLimiter {
+    pub fn new(limits: &PeerLimits, now: Instant) -> Self {
+        Self {
+            bytes: TokenBucket::new(limits.bytes_per_second, limits.byte_burst, now),
+            messages: TokenBucket::new(limits.messages_per_second, limits.message_burst, now),
+            max_message_size: limits.max_message_size,
+            max_throttle: Duration::from_secs(limits.max_throttle_secs),
+        }
+    }
+
+    /// Largest message the peer may send us in bytes
+    pub fn max_message_size(&self) -> u64 {
+        self.max_message_size
+    }
+
+    /// Accounts for a message of `size` bytes read from the peer and returns
+    /// for how long we have to pause reading from the peer to keep it within
+    /// its rates, fails if the peer should be disconnected instead
+    pub fn admit(&mut self, size: u64, now: Instant) -> anyhow::Result<Option<Duration>> {
+        if size > self.max_message_size {
+            bail!(
+                "Message of {size} bytes exceeds the limit of {} bytes",
+                self.max_message_size
+            );
+        }
+
+        let delay = self
+            .bytes
+            .delay_for(size as f64, now)
+            .max(self.messages.delay_for(1.0, now));
+        if delay > self.max_throttle {
+            bail!("Peer exceeded its rate limits, it would have to be throttled for {delay:?}");
+        }
+
+        self.bytes.take(size as f64);
+        self.messages.take(1.0);
+        Ok(Some(delay).filter(|delay| !delay.is_zero()))
+    }
+}
+
+/// Refills `refill_per_second` tokens per second up to `capacity`, every
+/// request takes one token
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(refill_per_second: u32, burst: u32, now: Instant) -> Self {
+        let capacity = f64::from(refill_per_second.saturating_add(burst).max(1));
+        Self {
+            tokens: capacity,
+            capacity,
+            refill_per_second: f64::from(refill_per_second),
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Time until the bucket holds `amount` tokens
+    fn delay_for(&mut self, amount: f64, now: Instant) -> Duration {
+        self.refill(now);
+
+        let missing = amount - self.tokens;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else if self.refill_per_second == 0.0 {
+            Duration::MAX
+        } else {
+            Duration::from_secs_f64(missing / self.refill_per_second)
+        }
+    }
+
+    /// Takes `amount` tokens, leaving the bucket in debt if it holds less
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount;
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{ExplorerLimiter, PeerLimiter, RequestLimiter, SourceLimiter, TokenBucket};
+    use crate::config::{ApiLimits, PeerLimits};
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, 5, start);
+
+        assert!((0..15).all(|_| bucket.try_take(start)));
+        assert!(!bucket.try_take(start));
+
+        let later = start + Duration::from_millis(500);
+        assert!((0..5).all(|_| bucket.try_take(later)));
+        assert!(!bucket.try_take(later));
+
+        // the bucket never holds more than its capacity
+        let much_later = later + Duration::from_secs(60);
+        assert!((0..15).all(|_| bucket.try_take(much_later)));
+        assert!(!bucket.try_take(much_later));
+    }
+
+    #[test]
+    fn limiter_caps_concurrent_requests() {
+        let limiter = RequestLimiter::new(&ApiLimits {
+            max_concurrent_requests: 2,
+            ..Default::default()
+        });
+
+        let first = limiter.admit().unwrap();
+        let _second = limiter.admit().unwrap();
+        assert_eq!(limiter.admit().unwrap_err().code, 429);
+
+        drop(first);
+        assert!(limiter.admit().is_ok());
+    }
+
+    #[test]
+    fn limiter_caps_subscriptions() {
+        let limiter = RequestLimiter::new(&ApiLimits {
+            max_subscriptions: 1,
+            ..Default::default()
+        });
+
+        let subscription = limiter.admit_subscription().unwrap();
+        assert_eq!(limiter.admit_subscription().unwrap_err().code, 429);
+        // subscriptions don't count against the concurrent requests
+        assert!(limiter.admit().is_ok());
+
+        drop(subscription);
+        assert!(limiter.admit_subscription().is_ok());
+    }
+
+    #[test]
+    fn explorer_limiter_shortens_pages_to_the_budget() {
+        let start = Instant::now();
+        let limiter = ExplorerLimiter::new(
+            &ApiLimits {
+                explored_epochs_per_second: 10,
+                explored_epoch_burst: 20,
+                ..Default::default()
+            },
+            start,
+        );
+
+        assert_eq!(limiter.admit(25, start).unwrap(), 25);
+        assert_eq!(limiter.admit(25, start).unwrap(), 5);
+        assert_eq!(limiter.admit(1, start).unwrap_err().code, 429);
+
+        // partially refilled budgets admit whole epochs only
+        let later = start + Duration::from_millis(250);
+        assert_eq!(limiter.admit(25, later).unwrap(), 2);
+        assert_eq!(limiter.admit(1, later).unwrap_err().code, 429);
+    }
+
+    #[test]
+    fn source_limiter_caps_connections_per_source() {
+        let limiter = SourceLimiter::new(&ApiLimits {
+            max_connections_per_source: 2,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let source = [10, 0, 0, 1].into();
+
+        let first = limiter.admit_connection(source, now).unwrap();
+        let _second = limiter.admit_connection(source, now).unwrap();
+        assert!(limiter.admit_connection(source, now).is_err());
+        // other sources are not affected
+        assert!(limiter.admit_connection([10, 0, 0, 2].into(), now).is_ok());
+
+        drop(first);
+        assert!(limiter.admit_connection(source, now).is_ok());
+    }
+
+    #[test]
+    fn source_limiter_throttles_requests_per_source() {
+        let limiter = SourceLimiter::new(&ApiLimits {
+            requests_per_second_per_source: 10,
+            request_burst_per_source: 0,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let source = [10, 0, 0, 1].into();
+
+        let first = limiter.admit_connection(source, now).unwrap();
+        let second = limiter.admit_connection(source, now).unwrap();
+        let other = limiter.admit_connection([10, 0, 0, 2].into(), now).unwrap();
+
+        // connections of a source share its budget
+        assert!((0..5).all(|_| first.admit_request(now).is_none()));
+        assert!((0..5).all(|_| second.admit_request(now).is_none()));
+        assert_eq!(first.admit_request(now), Some(Duration::from_millis(100)));
+        assert!(other.admit_request(now).is_none());
+
+        // reconnecting doesn't reset the budget
+        drop(first);
+        drop(second);
+        let third = limiter.admit_connection(source, now).unwrap();
+        assert_eq!(third.admit_request(now), Some(Duration::from_millis(200)));
+    }
+
+    fn peer_limits() -> PeerLimits {
+        PeerLimits {
+            max_message_size: 1000,
+            bytes_per_second: 1000,
+            byte_burst: 1000,
+            messages_per_second: 10,
+            message_burst: 0,
+            max_throttle_secs: 5,
+        }
+    }
+
+    #[test]
+    fn peer_limiter_throttles_above_rates() {
+        let start = Instant::now();
+        let mut limiter = PeerLimiter::new(&peer_limits(), start);
+
+        assert_eq!(limiter.admit(1000, start).unwrap(), None);
+        assert_eq!(limiter.admit(1000, start).unwrap(), None);
+        // the byte budget is exhausted, the next message has to wait for a refill
+        assert_eq!(
+            limiter.admit(500, start).unwrap(),
+            Some(Duration::from_millis(500))
+        );
+
+        // the message rate applies independently of the size of the messages
+        let later = start + Duration::from_secs(10);
+        assert!((0..10).all(|_| limiter.admit(1, later).unwrap().is_none()));
+        assert_eq!(
+            limiter.admit(1, later).unwrap(),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn peer_limiter_rejects_oversized_messages() {
+        let start = Instant::now();
+        let mut limiter = PeerLimiter::new(&peer_limits(), start);
+
+        assert!(limiter.admit(1001, start).is_err());
+        // rejected messages don't count against the rates
+        assert_eq!(limiter.admit(1000, start).unwrap(), None);
+    }
+
+    #[test]
+    fn peer_limiter_rejects_peers_throttled_too_long() {
+        let start = Instant::now();
+        let mut limiter = PeerLimiter::new(&peer_limits(), start);
+
+        assert!((0..7).all(|_| limiter.admit(1000, start).is_ok()));
+        assert!(limiter.admit(1000, start).is_err());
+
+        let later = start + Duration::from_secs(2);
+        assert!(limiter.admit(1000, later).is_ok());
+    }
+}
@@ -1,5 +1,10 @@
 pub mod api;
 pub mod connect;
 pub mod framed;
+pub mod front;
+pub mod limits;
 pub mod peers;
 mod queue;
+pub mod sessions;
+pub mod tls;
+pub mod tor;
@@ -6,13 +6,15 @@
 //! details.
 
 use std::cmp::min;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::ops::Sub;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 use async_trait::async_trait;
+use fedimint_core::admin_client::{PeerConnectionStatus, PeerHealth, PeerTraffic};
 use fedimint_core::cancellable::{Cancellable, Cancelled};
 use fedimint_core::net::peers::IPeerConnections;
 use fedimint_core::task::{TaskGroup, TaskHandle};
@@ -29,17 +31,65 @@ use tokio::time::Instant;
 use tracing::{debug, info, instrument, trace, warn};
 use url::Url;
 
+use crate::config::PeerLimits;
+use crate::metrics::PEER_ROUND_TRIP_SECONDS;
 use crate::net::connect::{AnyConnector, SharedAnyConnector};
-use crate::net::framed::AnyFramedTransport;
+use crate::net::framed::{AnyFramedTransport, FrameTooLarge};
+use crate::net::limits::PeerLimiter;
 use crate::net::queue::{MessageId, MessageQueue, UniqueMessage};
 
 /// Maximum connection failures we consider for our back-off strategy
 const MAX_FAIL_RECONNECT_COUNTER: u64 = 300;
 
+/// Delay before the first reconnection attempt, doubled with every failed one
+const BASE_RECONNECT_DELAY_SECS: f64 = 2.0;
+
+/// Upper bound of the delay between reconnection attempts
+const MAX_RECONNECT_DELAY_SECS: f64 = 600.0;
+
 /// Owned [`Connector`](crate::net::connect::Connector) trait object used by
 /// [`ReconnectPeerConnections`]
 pub type PeerConnector<M> = AnyConnector<PeerMessage<M>>;
 
+/// Connection status of every peer, updated by the peer connection state
+/// machines
+pub type PeerConnectionStatusMap = Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>;
+
+/// Traffic exchanged with every peer, updated by the peer connection state
+/// machines
+pub type PeerTrafficMap = Arc<RwLock<BTreeMap<PeerId, PeerTraffic>>>;
+
+/// Connection health of every peer, updated by the peer connection state
+/// machines
+pub type PeerHealthMap = Arc<RwLock<BTreeMap<PeerId, PeerHealth>>>;
+
+/// Commands of our operator for the connection to a peer
+#[derive(Debug, Clone, Copy)]
+pub enum PeerControl {
+    /// Drops the connection and connects again right away, lifting a ban
+    Reconnect,
+    /// Drops the connection and neither connects to the peer nor accepts its
+    /// connections until then
+    Ban(Instant),
+}
+
+/// Sends [`PeerControl`]s to the connection state machines of our peers
+#[derive(Debug, Clone, Default)]
+pub struct PeerControls {
+    senders: BTreeMap<PeerId, Sender<PeerControl>>,
+}
+
+impl PeerControls {
+    pub async fn send(&self, peer: PeerId, control: PeerControl) -> anyhow::Result<()> {
+        self.senders
+            .get(&peer)
+            .ok_or_else(|| anyhow::anyhow!("Unknown peer {peer}"))?
+            .send(control)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection to peer {peer} was shut down"))
+    }
+}
+
 /// Connection manager that automatically reconnects to peers
 ///
 /// `ReconnectPeerConnections` is based on a
@@ -49,11 +99,15 @@ pub type PeerConnector<M> = AnyConnector<PeerMessage<M>>;
 /// authenticated and encrypted.
 pub struct ReconnectPeerConnections<T> {
     connections: HashMap<PeerId, PeerConnection<T>>,
+    connection_status: PeerConnectionStatusMap,
+    traffic: PeerTrafficMap,
+    health: PeerHealthMap,
 }
 
 struct PeerConnection<T> {
     outgoing: Sender<T>,
     incoming: Receiver<T>,
+    control: Sender<PeerControl>,
 }
 
 /// Specifies the network configuration for federation-internal communication
@@ -66,6 +120,16 @@ pub struct NetworkConfig {
     pub bind_addr: SocketAddr,
     /// Map of all peers' connection information we want to be connected to
     pub peers: HashMap<PeerId, Url>,
+    /// More addresses we listen on besides `bind_addr`, e.g. an IPv6 one
+    #[serde(default)]
+    pub extra_bind_addrs: Vec<SocketAddr>,
+    /// More urls of peers we try in order if connecting to the one in
+    /// `peers` fails
+    #[serde(default)]
+    pub extra_peer_urls: HashMap<PeerId, Vec<Url>>,
+    /// Limits on the messages we read from every peer
+    #[serde(default)]
+    pub limits: PeerLimits,
 }
 
 /// Internal message type for [`ReconnectPeerConnections`], just public because
@@ -82,7 +146,10 @@ struct PeerConnectionStateMachine<M> {
 }
 
 /// Calculates delays for reconnecting to peers
-/// The default values are in the order of seconds
+///
+/// Delays double with every failed attempt up to a maximum and are jittered,
+/// so guardians that lost their connections at the same time don't reconnect
+/// in lockstep. The default values are in the order of seconds.
 #[derive(Debug, Clone, Copy)]
 pub struct DelayCalculator {
     scaling_factor: f64,
@@ -103,8 +170,12 @@ impl DelayCalculator {
     };
 
     fn reconnection_delay(&self, disconnect_count: u64) -> Duration {
-        let scaling_factor = disconnect_count as f64 * self.scaling_factor;
-        let delay: f64 = thread_rng().gen_range(1.0 * scaling_factor..4.0 * scaling_factor);
+        let exponent = disconnect_count.saturating_sub(1).min(32) as i32;
+        let backoff = (BASE_RECONNECT_DELAY_SECS * 2f64.powi(exponent))
+            .min(MAX_RECONNECT_DELAY_SECS)
+            * self.scaling_factor;
+        // at least half the backoff so delays keep growing, the rest is random
+        let delay: f64 = thread_rng().gen_range(backoff / 2.0..=backoff);
         Duration::from_secs_f64(delay)
     }
 }
@@ -120,11 +191,24 @@ struct CommonPeerConnectionState<M> {
     incoming: Sender<M>,
     outgoing: Receiver<M>,
     peer: PeerId,
-    peer_address: Url,
+    /// Urls of the peer, tried in order when connecting
+    peer_addresses: Vec<Url>,
     delay_calculator: DelayCalculator,
     connect: SharedAnyConnector<PeerMessage<M>>,
     incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
     last_received: Option<MessageId>,
+    connection_status: PeerConnectionStatusMap,
+    traffic: PeerTrafficMap,
+    health: PeerHealthMap,
+    control: Receiver<PeerControl>,
+    /// We neither connect to the peer nor accept its connections until then
+    banned_until: Option<Instant>,
+    /// Enforces our limits on the messages the peer sends us, kept across
+    /// connections so reconnecting doesn't reset them
+    limiter: PeerLimiter,
+    /// When the unacknowledged messages sent over the current connection were
+    /// sent, used to measure the round-trip time
+    sent_at: VecDeque<(MessageId, Instant)>,
 }
 
 struct DisconnectedPeerConnectionState {
@@ -134,6 +218,9 @@ struct DisconnectedPeerConnectionState {
 
 struct ConnectedPeerConnectionState<M> {
     connection: AnyFramedTransport<PeerMessage<M>>,
+    /// We don't read from the connection until then since the peer exceeded
+    /// its rate limits
+    throttled_until: Option<Instant>,
 }
 
 enum PeerConnectionState<M> {
@@ -157,12 +244,24 @@ where
         task_group: &mut TaskGroup,
     ) -> Self {
         let shared_connector: SharedAnyConnector<PeerMessage<T>> = connect.into();
+        let connection_status: PeerConnectionStatusMap = Arc::new(RwLock::new(
+            cfg.peers
+                .keys()
+                .filter(|&&peer| peer != cfg.identity)
+                .map(|&peer| (peer, PeerConnectionStatus::Disconnected))
+                .collect(),
+        ));
+        let traffic = PeerTrafficMap::default();
+        let health = PeerHealthMap::default();
 
         let (connection_senders, connections) = cfg
             .peers
             .iter()
             .filter(|(&peer, _)| peer != cfg.identity)
             .map(|(&peer, peer_address)| {
+                let peer_addresses = std::iter::once(peer_address.clone())
+                    .chain(cfg.extra_peer_urls.get(&peer).cloned().unwrap_or_default())
+                    .collect();
                 let (connection_sender, connection_receiver) =
                     tokio::sync::mpsc::channel::<AnyFramedTransport<PeerMessage<T>>>(4);
                 (
@@ -171,10 +270,14 @@ where
                         peer,
                         PeerConnection::new(
                             peer,
-                            peer_address.clone(),
+                            peer_addresses,
                             delay_calculator,
                             shared_connector.clone(),
                             connection_receiver,
+                            connection_status.clone(),
+                            traffic.clone(),
+                            health.clone(),
+                            cfg.limits.clone(),
                             task_group,
                         ),
                     ),
@@ -188,7 +291,42 @@ where
             })
             .await;
 
-        ReconnectPeerConnections { connections }
+        ReconnectPeerConnections {
+            connections,
+            connection_status,
+            traffic,
+            health,
+        }
+    }
+
+    /// Returns a handle to the connection health of our peers which stays up
+    /// to date as connections are made and dropped
+    pub fn health(&self) -> PeerHealthMap {
+        self.health.clone()
+    }
+
+    /// Returns a handle to send [`PeerControl`]s to the connections to our
+    /// peers
+    pub fn controls(&self) -> PeerControls {
+        PeerControls {
+            senders: self
+                .connections
+                .iter()
+                .map(|(&peer, connection)| (peer, connection.control.clone()))
+                .collect(),
+        }
+    }
+
+    /// Returns a handle to the connection status of our peers which stays up
+    /// to date as peers connect and disconnect
+    pub fn connection_status(&self) -> PeerConnectionStatusMap {
+        self.connection_status.clone()
+    }
+
+    /// Returns a handle to the traffic exchanged with our peers which stays up
+    /// to date as messages are sent and received
+    pub fn traffic(&self) -> PeerTrafficMap {
+        self.traffic.clone()
     }
 
     async fn run_listen_task(
@@ -197,10 +335,19 @@ where
         mut connection_senders: HashMap<PeerId, Sender<AnyFramedTransport<PeerMessage<T>>>>,
         task_handle: TaskHandle,
     ) {
-        let mut listener = connect
+        let mut listeners = vec![connect
             .listen(cfg.bind_addr)
             .await
-            .expect("Could not bind port");
+            .expect("Could not bind port")];
+        for bind_addr in &cfg.extra_bind_addrs {
+            listeners.push(
+                connect
+                    .listen_additional(*bind_addr)
+                    .await
+                    .expect("Could not bind port"),
+            );
+        }
+        let mut listener = futures::stream::select_all(listeners);
 
         let mut shutdown_rx = task_handle.make_shutdown_rx().await;
 
@@ -317,6 +464,7 @@ where
 
     async fn state_transition(self, task_handle: &TaskHandle) -> Option<Self> {
         let PeerConnectionStateMachine { mut common, state } = self;
+        let was_connected = matches!(state, PeerConnectionState::Connected(_));
 
         match state {
             PeerConnectionState::Disconnected(disconnected) => {
@@ -330,9 +478,33 @@ where
                     .await
             }
         }
-        .map(|new_state| PeerConnectionStateMachine {
-            common,
-            state: new_state,
+        .map(|new_state| {
+            let status = match new_state {
+                PeerConnectionState::Connected(_) => PeerConnectionStatus::Connected,
+                PeerConnectionState::Disconnected(_) => PeerConnectionStatus::Disconnected,
+            };
+            match (was_connected, status) {
+                (false, PeerConnectionStatus::Connected) => common.update_health(|health| {
+                    health.connects += 1;
+                    health.failed_attempts = 0;
+                    health.score = next_score(health.score, true);
+                }),
+                (true, PeerConnectionStatus::Disconnected) => common.update_health(|health| {
+                    health.disconnects += 1;
+                    health.score = next_score(health.score, false);
+                }),
+                _ => {}
+            }
+            common
+                .connection_status
+                .write()
+                .expect("lock poisoned")
+                .insert(common.peer, status);
+
+            PeerConnectionStateMachine {
+                common,
+                state: new_state,
+            }
         })
     }
 }
@@ -346,6 +518,7 @@ where
         mut connected: ConnectedPeerConnectionState<M>,
         task_handle: &TaskHandle,
     ) -> Option<PeerConnectionState<M>> {
+        let throttled_until = connected.throttled_until;
         Some(tokio::select! {
             maybe_msg = self.outgoing.recv() => {
                 match maybe_msg {
@@ -372,9 +545,17 @@ where
                     },
                 }
             },
-            Some(msg_res) = connected.connection.next() => {
+            Some(msg_res) = connected.connection.next(), if throttled_until.is_none() => {
                 self.receive_message(connected, msg_res).await
             },
+            Some(control) = self.control.recv() => {
+                self.handle_control(control)
+            },
+            () = tokio::time::sleep_until(throttled_until.unwrap_or_else(Instant::now)),
+                if throttled_until.is_some() => {
+                connected.throttled_until = None;
+                PeerConnectionState::Connected(connected)
+            },
             _ = task_handle.make_shutdown_rx().await => {
                 return None;
             },
@@ -390,10 +571,17 @@ where
             peer = ?self.peer, %disconnect_count,
             resend_queue_len = self.resend_queue.queue.len(),
             "Received incoming connection");
+        // oversized messages are rejected from their length before we buffer them
+        new_connection.set_max_frame_size(self.limiter.max_message_size());
         match self.resend_buffer_contents(&mut new_connection).await {
-            Ok(()) => PeerConnectionState::Connected(ConnectedPeerConnectionState {
-                connection: new_connection,
-            }),
+            Ok(()) => {
+                // resent messages would skew the round-trip times
+                self.sent_at.clear();
+                PeerConnectionState::Connected(ConnectedPeerConnectionState {
+                    connection: new_connection,
+                    throttled_until: None,
+                })
+            }
             Err(e) => self.disconnect_err(e, disconnect_count),
         }
     }
@@ -403,12 +591,12 @@ where
         connection: &mut AnyFramedTransport<PeerMessage<M>>,
     ) -> Result<(), anyhow::Error> {
         for msg in self.resend_queue.iter().cloned() {
-            connection
-                .send(PeerMessage {
-                    msg,
-                    ack: self.last_received,
-                })
-                .await?
+            let msg = PeerMessage {
+                msg,
+                ack: self.last_received,
+            };
+            connection.send(msg).await?;
+            self.record_sent(connection.last_sent_size());
         }
 
         Ok(())
@@ -449,27 +637,51 @@ where
     ) -> PeerConnectionState<M> {
         let umsg = self.resend_queue.push(msg);
         trace!(target: LOG_NET_PEER, peer = ?self.peer, id = ?umsg.id, "Sending outgoing message");
+        self.sent_at.push_back((umsg.id, Instant::now()));
 
-        match connected
-            .connection
-            .send(PeerMessage {
-                msg: umsg,
-                ack: self.last_received,
-            })
-            .await
-        {
-            Ok(()) => PeerConnectionState::Connected(connected),
+        let msg = PeerMessage {
+            msg: umsg,
+            ack: self.last_received,
+        };
+        match connected.connection.send(msg).await {
+            Ok(()) => {
+                self.record_sent(connected.connection.last_sent_size());
+                PeerConnectionState::Connected(connected)
+            }
             Err(e) => self.disconnect_err(e, 0),
         }
     }
 
     async fn receive_message(
         &mut self,
-        connected: ConnectedPeerConnectionState<M>,
+        mut connected: ConnectedPeerConnectionState<M>,
         msg_res: Result<PeerMessage<M>, anyhow::Error>,
     ) -> PeerConnectionState<M> {
+        let limited = match &msg_res {
+            Ok(_) => self.limit_message(connected.connection.last_received_size()),
+            Err(e) if e.is::<FrameTooLarge>() => Err(anyhow::anyhow!("{e}")),
+            Err(_) => Ok(None),
+        };
+        let throttle = match limited {
+            Ok(throttle) => throttle,
+            Err(e) => {
+                // backing off longer with every violation keeps a misbehaving
+                // peer from taking up our resources
+                let limit_disconnects = self.update_traffic(|traffic| {
+                    traffic.limit_disconnects += 1;
+                    traffic.limit_disconnects
+                });
+                warn!(target: LOG_NET_PEER, peer = ?self.peer, %e, "Peer exceeded its limits");
+                self.last_received = None;
+                return self.disconnect_err(e, limit_disconnects);
+            }
+        };
+
         match self.receive_message_inner(msg_res).await {
-            Ok(()) => PeerConnectionState::Connected(connected),
+            Ok(()) => {
+                connected.throttled_until = throttle.map(|delay| Instant::now() + delay);
+                PeerConnectionState::Connected(connected)
+            }
             Err(e) => {
                 self.last_received = None;
                 self.disconnect_err(e, 0)
@@ -477,6 +689,34 @@ where
         }
     }
 
+    /// Accounts for a message received from the peer and returns for how long
+    /// we pause reading from the peer to keep it within its rate limits
+    fn limit_message(&mut self, size: u64) -> anyhow::Result<Option<Duration>> {
+        self.update_traffic(|traffic| {
+            traffic.bytes_received += size;
+            traffic.messages_received += 1;
+        });
+
+        let throttle = self.limiter.admit(size, Instant::now().into_std())?;
+        if let Some(delay) = throttle {
+            debug!(target: LOG_NET_PEER, peer = ?self.peer, ?delay, "Throttling peer");
+            self.update_traffic(|traffic| traffic.throttled += 1);
+        }
+        Ok(throttle)
+    }
+
+    fn record_sent(&self, size: u64) {
+        self.update_traffic(|traffic| {
+            traffic.bytes_sent += size;
+            traffic.messages_sent += 1;
+        });
+    }
+
+    fn update_traffic<R>(&self, update: impl FnOnce(&mut PeerTraffic) -> R) -> R {
+        let mut traffic = self.traffic.write().expect("lock poisoned");
+        update(traffic.entry(self.peer).or_default())
+    }
+
     async fn receive_message_inner(
         &mut self,
         msg_res: Result<PeerMessage<M>, anyhow::Error>,
@@ -504,6 +744,7 @@ where
         self.last_received = Some(expected);
         if let Some(ack) = ack {
             self.resend_queue.ack(ack);
+            self.observe_round_trip(ack);
         }
 
         if self.incoming.send(msg.msg).await.is_err() {
@@ -518,6 +759,21 @@ where
         Ok(())
     }
 
+    fn observe_round_trip(&mut self, ack: MessageId) {
+        let mut acked_sent_at = None;
+        while let Some((_, sent_at)) = self.sent_at.front().filter(|(id, _)| *id <= ack) {
+            acked_sent_at = Some(*sent_at);
+            self.sent_at.pop_front();
+        }
+
+        // only the newest acknowledged message tells us how long the ack took
+        if let Some(sent_at) = acked_sent_at {
+            PEER_ROUND_TRIP_SECONDS
+                .with_label_values(&[self.peer.to_string().as_str()])
+                .observe(sent_at.elapsed().as_secs_f64());
+        }
+    }
+
     async fn state_transition_disconnected(
         &mut self,
         disconnected: DisconnectedPeerConnectionState,
@@ -548,6 +804,9 @@ where
             () = tokio::time::sleep_until(disconnected.reconnect_at) => {
                 self.reconnect(disconnected).await
             },
+            Some(control) = self.control.recv() => {
+                self.handle_control(control)
+            },
             _ = task_handle.make_shutdown_rx().await => {
                 return None;
             },
@@ -569,6 +828,10 @@ where
         disconnect: DisconnectedPeerConnectionState,
         new_connection: AnyFramedTransport<PeerMessage<M>>,
     ) -> PeerConnectionState<M> {
+        if self.is_banned() {
+            debug!(target: LOG_NET_PEER, peer = ?self.peer, "Rejecting connection of banned peer");
+            return PeerConnectionState::Disconnected(disconnect);
+        }
         self.connect(new_connection, disconnect.failed_reconnect_counter)
             .await
     }
@@ -577,19 +840,73 @@ where
         &mut self,
         disconnected: DisconnectedPeerConnectionState,
     ) -> PeerConnectionState<M> {
+        if let Some(banned_until) = self.banned_until.filter(|_| self.is_banned()) {
+            return PeerConnectionState::Disconnected(DisconnectedPeerConnectionState {
+                reconnect_at: banned_until,
+                ..disconnected
+            });
+        }
+        self.lift_ban();
+
         match self.try_reconnect().await {
             Ok(conn) => {
                 self.connect(conn, disconnected.failed_reconnect_counter)
                     .await
             }
-            Err(e) => self.disconnect_err(e, disconnected.failed_reconnect_counter),
+            Err(e) => {
+                self.update_health(|health| {
+                    health.failed_attempts += 1;
+                    health.score = next_score(health.score, false);
+                });
+                self.disconnect_err(e, disconnected.failed_reconnect_counter)
+            }
         }
     }
 
+    fn is_banned(&self) -> bool {
+        self.banned_until
+            .map_or(false, |banned_until| Instant::now() < banned_until)
+    }
+
+    fn lift_ban(&mut self) {
+        if self.banned_until.take().is_some() {
+            self.update_health(|health| health.banned_until = None);
+        }
+    }
+
+    /// Drops the connection, if any, and schedules the next attempt as our
+    /// operator commanded
+    fn handle_control(&mut self, control: PeerControl) -> PeerConnectionState<M> {
+        let reconnect_at = match control {
+            PeerControl::Reconnect => {
+                info!(target: LOG_NET_PEER, peer = ?self.peer, "Reconnecting on request");
+                self.lift_ban();
+                Instant::now()
+            }
+            PeerControl::Ban(banned_until) => {
+                warn!(target: LOG_NET_PEER, peer = ?self.peer, "Banning peer endpoint on request");
+                let banned_for = banned_until.saturating_duration_since(Instant::now());
+                self.banned_until = Some(banned_until);
+                self.update_health(|health| {
+                    health.banned_until = Some(fedimint_core::time::now() + banned_for);
+                });
+                banned_until
+            }
+        };
+        PeerConnectionState::Disconnected(DisconnectedPeerConnectionState {
+            reconnect_at,
+            failed_reconnect_counter: 0,
+        })
+    }
+
+    fn update_health(&self, update: impl FnOnce(&mut PeerHealth)) {
+        let mut health = self.health.write().expect("lock poisoned");
+        update(health.entry(self.peer).or_default())
+    }
+
     async fn try_reconnect(&self) -> Result<AnyFramedTransport<PeerMessage<M>>, anyhow::Error> {
         debug!(target: LOG_NET_PEER, "Trying to reconnect");
-        let addr = self.peer_address.clone();
-        let (connected_peer, conn) = self.connect.connect_framed(addr, self.peer).await?;
+        let (connected_peer, conn) = self.connect_any_address().await?;
 
         if connected_peer == self.peer {
             Ok(conn)
@@ -600,22 +917,52 @@ where
             ))
         }
     }
+
+    /// Connects to the first of the peer's urls that accepts the connection
+    async fn connect_any_address(
+        &self,
+    ) -> Result<(PeerId, AnyFramedTransport<PeerMessage<M>>), anyhow::Error> {
+        let mut last_error = None;
+        for addr in &self.peer_addresses {
+            match self.connect.connect_framed(addr.clone(), self.peer).await {
+                Ok(connection) => return Ok(connection),
+                Err(e) => {
+                    debug!(target: LOG_NET_PEER, %addr, err = %e, "Could not connect to peer url");
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("peers have at least one url"))
+    }
+}
+
+/// Moves the health score of a peer towards 100 after a successful connection
+/// and towards 0 after a failed or dropped one, weighting recent outcomes most
+fn next_score(score: u8, success: bool) -> u8 {
+    let outcome = if success { 100 } else { 0 };
+    ((u16::from(score) * 3 + outcome) / 4) as u8
 }
 
 impl<M> PeerConnection<M>
 where
     M: Debug + Clone + Send + Sync + 'static,
 {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         id: PeerId,
-        peer_address: Url,
+        peer_addresses: Vec<Url>,
         delay_calculator: DelayCalculator,
         connect: SharedAnyConnector<PeerMessage<M>>,
         incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
+        connection_status: PeerConnectionStatusMap,
+        traffic: PeerTrafficMap,
+        health: PeerHealthMap,
+        limits: PeerLimits,
         task_group: &mut TaskGroup,
     ) -> PeerConnection<M> {
         let (outgoing_sender, outgoing_receiver) = tokio::sync::mpsc::channel::<M>(1024);
         let (incoming_sender, incoming_receiver) = tokio::sync::mpsc::channel::<M>(1024);
+        let (control_sender, control_receiver) = tokio::sync::mpsc::channel::<PeerControl>(4);
 
         futures::executor::block_on(task_group.spawn(
             format!("io-thread-peer-{id}"),
@@ -624,10 +971,15 @@ where
                     incoming_sender,
                     outgoing_receiver,
                     id,
-                    peer_address,
+                    peer_addresses,
                     delay_calculator,
                     connect,
                     incoming_connections,
+                    connection_status,
+                    traffic,
+                    health,
+                    control_receiver,
+                    limits,
                     &handle,
                 )
                 .await
@@ -637,6 +989,7 @@ where
         PeerConnection {
             outgoing: outgoing_sender,
             incoming: incoming_receiver,
+            control: control_sender,
         }
     }
 
@@ -654,10 +1007,15 @@ where
         incoming: Sender<M>,
         outgoing: Receiver<M>,
         peer: PeerId,
-        peer_address: Url,
+        peer_addresses: Vec<Url>,
         delay_calculator: DelayCalculator,
         connect: SharedAnyConnector<PeerMessage<M>>,
         incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
+        connection_status: PeerConnectionStatusMap,
+        traffic: PeerTrafficMap,
+        health: PeerHealthMap,
+        control: Receiver<PeerControl>,
+        limits: PeerLimits,
         task_handle: &TaskHandle,
     ) {
         let common = CommonPeerConnectionState {
@@ -665,11 +1023,18 @@ where
             incoming,
             outgoing,
             peer,
-            peer_address,
+            peer_addresses,
             delay_calculator,
             connect,
             incoming_connections,
             last_received: None,
+            connection_status,
+            traffic,
+            health,
+            control,
+            banned_until: None,
+            limiter: PeerLimiter::new(&limits, std::time::Instant::now()),
+            sent_at: VecDeque::new(),
         };
         let initial_state = common.disconnect(0);
 
@@ -684,14 +1049,15 @@ where
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
     use std::time::Duration;
 
-    use fedimint_core::task::TaskGroup;
+    use fedimint_core::admin_client::PeerConnectionStatus;
+    use fedimint_core::task::{sleep, TaskGroup};
     use fedimint_core::PeerId;
     use futures::Future;
 
-    use super::DelayCalculator;
+    use super::{next_score, DelayCalculator, MAX_FAIL_RECONNECT_COUNTER};
     use crate::net::connect::mock::{MockNetwork, StreamReliability};
     use crate::net::connect::Connector;
     use crate::net::peers::{IPeerConnections, NetworkConfig, ReconnectPeerConnections};
@@ -730,6 +1096,9 @@ mod tests {
                     identity: PeerId::from(id),
                     bind_addr: bind.parse().unwrap(),
                     peers: peers_ref.clone(),
+                    extra_bind_addrs: vec![],
+                    extra_peer_urls: Default::default(),
+                    limits: Default::default(),
                 };
                 let connect = net_ref
                     .connector(cfg.identity, StreamReliability::MILDLY_UNRELIABLE)
@@ -763,6 +1132,135 @@ mod tests {
         task_group.join_all(None).await.unwrap();
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_connect_extra_url() {
+        let task_group = TaskGroup::new();
+
+        {
+            let net = MockNetwork::new();
+            let url = |addr: &str| format!("http://{addr}").parse().unwrap();
+
+            // peer 1 can only reach peer 2 through its extra url, peer 2 can't
+            // reach peer 1 at all, so the connection must be made by peer 1
+            let cfg_a = NetworkConfig {
+                identity: PeerId::from(1),
+                bind_addr: "127.0.0.1:1000".parse().unwrap(),
+                peers: HashMap::from([
+                    (PeerId::from(1), url("127.0.0.1:1000")),
+                    (PeerId::from(2), url("127.0.0.1:2000")),
+                ]),
+                extra_bind_addrs: vec![],
+                extra_peer_urls: HashMap::from([(PeerId::from(2), vec![url("127.0.0.1:2001")])]),
+                limits: Default::default(),
+            };
+            let cfg_b = NetworkConfig {
+                identity: PeerId::from(2),
+                bind_addr: "127.0.0.1:1999".parse().unwrap(),
+                peers: HashMap::from([
+                    (PeerId::from(1), url("127.0.0.1:1001")),
+                    (PeerId::from(2), url("127.0.0.1:2000")),
+                ]),
+                extra_bind_addrs: vec!["127.0.0.1:2001".parse().unwrap()],
+                extra_peer_urls: HashMap::new(),
+                limits: Default::default(),
+            };
+
+            let net_ref = &net;
+            let build_peers = move |cfg: NetworkConfig, mut task_group: TaskGroup| async move {
+                let connect = net_ref
+                    .connector(cfg.identity, StreamReliability::MILDLY_UNRELIABLE)
+                    .into_dyn();
+                ReconnectPeerConnections::<u64>::new(
+                    cfg,
+                    DelayCalculator::TEST_DEFAULT,
+                    connect,
+                    &mut task_group,
+                )
+                .await
+            };
+
+            let mut peers_a = build_peers(cfg_a, task_group.clone()).await;
+            let mut peers_b = build_peers(cfg_b, task_group.clone()).await;
+
+            peers_a.send(&[PeerId::from(2)], 42).await.unwrap();
+            let recv = timeout(peers_b.receive()).await.unwrap().unwrap();
+            assert_eq!(recv.0, PeerId::from(1));
+            assert_eq!(recv.1, 42);
+        }
+
+        task_group.shutdown().await;
+        task_group.join_all(None).await.unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_connection_status() {
+        let task_group = TaskGroup::new();
+
+        {
+            let net = MockNetwork::new();
+            let url = |addr: &str| format!("http://{addr}").parse().unwrap();
+            // peer 3 never comes online
+            let peers = HashMap::from([
+                (PeerId::from(1), url("127.0.0.1:1000")),
+                (PeerId::from(2), url("127.0.0.1:2000")),
+                (PeerId::from(3), url("127.0.0.1:3000")),
+            ]);
+
+            let peers_ref = &peers;
+            let net_ref = &net;
+            let build_peers = move |bind: &'static str, id: u16, mut task_group: TaskGroup| async move {
+                let cfg = NetworkConfig {
+                    identity: PeerId::from(id),
+                    bind_addr: bind.parse().unwrap(),
+                    peers: peers_ref.clone(),
+                    extra_bind_addrs: vec![],
+                    extra_peer_urls: Default::default(),
+                    limits: Default::default(),
+                };
+                let connect = net_ref
+                    .connector(cfg.identity, StreamReliability::FullyReliable)
+                    .into_dyn();
+                ReconnectPeerConnections::<u64>::new(
+                    cfg,
+                    DelayCalculator::TEST_DEFAULT,
+                    connect,
+                    &mut task_group,
+                )
+                .await
+            };
+
+            let mut peers_a = build_peers("127.0.0.1:1000", 1, task_group.clone()).await;
+            let status = peers_a.connection_status();
+            assert_eq!(
+                *status.read().unwrap(),
+                BTreeMap::from([
+                    (PeerId::from(2), PeerConnectionStatus::Disconnected),
+                    (PeerId::from(3), PeerConnectionStatus::Disconnected),
+                ])
+            );
+
+            let mut peers_b = build_peers("127.0.0.1:2000", 2, task_group.clone()).await;
+            peers_a.send(&[PeerId::from(2)], 42).await.unwrap();
+            timeout(peers_b.receive()).await.unwrap().unwrap();
+
+            timeout(async {
+                while status.read().unwrap()[&PeerId::from(2)] != PeerConnectionStatus::Connected {
+                    sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("peer 2 is reported as connected");
+            assert_eq!(
+                status.read().unwrap()[&PeerId::from(3)],
+                PeerConnectionStatus::Disconnected
+            );
+            assert!(!status.read().unwrap().contains_key(&PeerId::from(1)));
+        }
+
+        task_group.shutdown().await;
+        task_group.join_all(None).await.unwrap();
+    }
+
     #[test]
     fn test_delay_calculator() {
         // Test delays should be on the order of milliseconds, not seconds.
@@ -774,4 +1272,33 @@ mod tests {
         assert!((1..10).contains(&c.reconnection_delay(1).as_secs()));
         assert!((100..1000).contains(&c.reconnection_delay(100).as_secs()));
     }
+
+    #[test]
+    fn test_delay_backoff() {
+        let c = DelayCalculator::default();
+        // every failure doubles the backoff, of which at least half is waited
+        for failures in 1..8 {
+            let backoff = 2f64 * 2f64.powi(failures as i32 - 1);
+            let delay = c.reconnection_delay(failures).as_secs_f64();
+            assert!((backoff / 2.0..=backoff).contains(&delay));
+        }
+        // capped so peers that were down for long are retried regularly
+        for failures in [20, MAX_FAIL_RECONNECT_COUNTER, u64::MAX] {
+            assert!(c.reconnection_delay(failures).as_secs() <= 600);
+        }
+    }
+
+    #[test]
+    fn test_health_score() {
+        let mut score = 0;
+        for _ in 0..20 {
+            score = next_score(score, true);
+        }
+        assert!(score >= 95);
+
+        let dropped = next_score(score, false);
+        assert!(dropped < score);
+        assert!(next_score(0, false) == 0);
+        assert!(next_score(100, true) == 100);
+    }
 }
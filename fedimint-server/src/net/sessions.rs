@@ -0,0 +1,246 @@
+//! Garbage collection of stale API subscriptions
+//!
+//! Clients that vanish without closing their websocket leave subscriptions
+//! waiting for transactions or epochs that may never come, each holding a task
+//! and a subscription permit. Every subscription is tracked here and a
+//! periodic sweep evicts those that didn't deliver anything for longer than
+//! the idle timeout. Evicted subscriptions are closed with an error of code
+//! [`SUBSCRIPTION_EVICTED_ERROR_CODE`], so clients still interested in them
+//! know to subscribe again.
+//!
+//! A session, i.e. a websocket connection, can't hold more than
+//! [`ApiLimits::max_subscriptions_per_connection`] subscriptions, which the
+//! API server enforces on every connection, and a source can't open more than
+//! [`ApiLimits::max_connections_per_source`] sessions.
+//!
+//! [`ApiLimits::max_subscriptions_per_connection`]: crate::config::ApiLimits::max_subscriptions_per_connection
+//! [`ApiLimits::max_connections_per_source`]: crate::config::ApiLimits::max_connections_per_source
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use fedimint_core::module::SUBSCRIPTION_EVICTED_ERROR_CODE;
+use fedimint_logging::LOG_NET_API;
+use futures::{Stream, StreamExt};
+use jsonrpsee::types::ErrorObject;
+use jsonrpsee::SubscriptionSink;
+use serde::Serialize;
+use tokio::sync::{oneshot, Notify, OwnedSemaphorePermit};
+use tracing::debug;
+
+use crate::metrics::{API_ACTIVE_SUBSCRIPTIONS, API_SUBSCRIPTION_EVICTIONS};
+
+/// Tracks the active subscriptions of an API server and evicts idle ones
+#[derive(Debug)]
+pub struct SubscriptionTracker {
+    subscriptions: Mutex<BTreeMap<u64, TrackedEntry>>,
+    next_id: AtomicU64,
+    idle_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct TrackedEntry {
+    kind: &'static str,
+    last_activity: Instant,
+    evicted: Arc<Notify>,
+}
+
+impl SubscriptionTracker {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            subscriptions: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(0),
+            idle_timeout,
+        }
+    }
+
+    /// Starts tracking a subscription of `kind`, which stays tracked until the
+    /// returned handle is dropped
+    pub fn track(
+        self: &Arc<Self>,
+        kind: &'static str,
+        permit: OwnedSemaphorePermit,
+    ) -> TrackedSubscription {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let evicted = Arc::new(Notify::new());
+        self.lock().insert(
+            id,
+            TrackedEntry {
+                kind,
+                last_activity: Instant::now(),
+                evicted: evicted.clone(),
+            },
+        );
+        API_ACTIVE_SUBSCRIPTIONS.with_label_values(&[kind]).inc();
+
+        TrackedSubscription {
+            tracker: self.clone(),
+            id,
+            evicted,
+            _permit: permit,
+        }
+    }
+
+    /// Number of tracked subscriptions
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Evicts all subscriptions that were idle for longer than the idle
+    /// timeout at `now`, returns how many were evicted
+    pub fn sweep(&self, now: Instant) -> usize {
+        let mut subscriptions = self.lock();
+        let idle: Vec<u64> = subscriptions
+            .iter()
+            .filter(|(_, entry)| {
+                now.saturating_duration_since(entry.last_activity) > self.idle_timeout
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &idle {
+            let entry = subscriptions.remove(id).expect("id was just found");
+            API_ACTIVE_SUBSCRIPTIONS
+                .with_label_values(&[entry.kind])
+                .dec();
+            API_SUBSCRIPTION_EVICTIONS
+                .with_label_values(&[entry.kind, "idle"])
+                .inc();
+            // stored so a subscription that isn't waiting yet still ends
+            entry.evicted.notify_one();
+        }
+        idle.len()
+    }
+
+    /// Sweeps every `interval` until `shutdown_rx` fires
+    pub async fn run_sweeper(&self, interval: Duration, mut shutdown_rx: oneshot::Receiver<()>) {
+        loop {
+            tokio::select! {
+                _ = &mut shutdown_rx => break,
+                _ = fedimint_core::task::sleep(interval) => {}
+            }
+
+            let evicted = self.sweep(Instant::now());
+            if evicted != 0 {
+                debug!(
+                    target: LOG_NET_API,
+                    evicted,
+                    remaining = self.len(),
+                    "Evicted idle subscriptions"
+                );
+            }
+        }
+    }
+
+    fn touch(&self, id: u64, now: Instant) {
+        if let Some(entry) = self.lock().get_mut(&id) {
+            entry.last_activity = now;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, TrackedEntry>> {
+        self.subscriptions.lock().expect("locking can't fail")
+    }
+}
+
+/// A tracked subscription, holds the subscription's permit until it ended
+#[derive(Debug)]
+pub struct TrackedSubscription {
+    tracker: Arc<SubscriptionTracker>,
+    id: u64,
+    evicted: Arc<Notify>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl TrackedSubscription {
+    /// Pipes `stream` into `sink` until the stream ends, the client goes away
+    /// or the subscription gets evicted, which closes it with an error
+    pub async fn pipe<S, T>(self, mut sink: SubscriptionSink, stream: S)
+    where
+        S: Stream<Item = T> + Send + Unpin,
+        T: Serialize,
+    {
+        let stream = stream.inspect(|_| self.tracker.touch(self.id, Instant::now()));
+        let evicted = tokio::select! {
+            _ = sink.pipe_from_stream(stream) => false,
+            _ = self.evicted.notified() => true,
+        };
+
+        if evicted {
+            debug!(target: LOG_NET_API, id = self.id, "Closing evicted subscription");
+            sink.close(ErrorObject::owned(
+                SUBSCRIPTION_EVICTED_ERROR_CODE,
+                "Subscription was idle for too long, subscribe again",
+                None::<()>,
+            ));
+        }
+    }
+}
+
+impl Drop for TrackedSubscription {
+    fn drop(&mut self) {
+        // already removed if it was evicted
+        if let Some(entry) = self.tracker.lock().remove(&self.id) {
+            API_ACTIVE_SUBSCRIPTIONS
+                .with_label_values(&[entry.kind])
+                .dec();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use tokio::sync::Semaphore;
+
+    use super::SubscriptionTracker;
+
+    #[test]
+    fn sweep_evicts_idle_subscriptions() {
+        let start = Instant::now();
+        let tracker = Arc::new(SubscriptionTracker::new(Duration::from_secs(60)));
+        let permits = Arc::new(Semaphore::new(2));
+
+        let idle = tracker.track("epochs", permits.clone().try_acquire_owned().unwrap());
+        let active = tracker.track("epochs", permits.clone().try_acquire_owned().unwrap());
+        assert_eq!(tracker.len(), 2);
+
+        assert_eq!(tracker.sweep(start), 0);
+
+        tracker.touch(active.id, start + Duration::from_secs(50));
+        assert_eq!(tracker.sweep(start + Duration::from_secs(61)), 1);
+        assert_eq!(tracker.len(), 1);
+
+        // the evicted subscription releases its permit once it ended
+        assert_eq!(permits.available_permits(), 0);
+        drop(idle);
+        assert_eq!(permits.available_permits(), 1);
+
+        drop(active);
+        assert!(tracker.is_empty());
+        assert_eq!(permits.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn evicted_subscriptions_are_notified_before_they_wait() {
+        let start = Instant::now();
+        let tracker = Arc::new(SubscriptionTracker::new(Duration::from_secs(60)));
+        let permits = Arc::new(Semaphore::new(1));
+
+        let subscription = tracker.track("transaction", permits.try_acquire_owned().unwrap());
+        assert_eq!(tracker.sweep(start + Duration::from_secs(61)), 1);
+
+        // the subscription only starts waiting after it was evicted, but still
+        // learns about it and gets closed
+        tokio::time::timeout(Duration::from_secs(1), subscription.evicted.notified())
+            .await
+            .expect("eviction was stored");
+    }
+}
@@ -0,0 +1,687 @@
+//! Serves the API over TLS, with a certificate read from files or provisioned
+//! and renewed through ACME
+//!
+//! The TLS listener terminates TLS and forwards the connections to the API
+//! server like [`serve_api_front`](crate::net::front::serve_api_front) does,
+//! so the limits of their source apply to them too.
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, format_err, Context};
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_logging::LOG_NET_API;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, Order, OrderStatus,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{oneshot, Semaphore};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::{any_supported_type, CertifiedKey};
+use tokio_rustls::rustls::{self, Certificate, PrivateKey};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+use crate::net::front::forward_api_connection;
+use crate::net::limits::{SourceConnection, SourceLimiter};
+
+/// Directory of the production ACME server of Let's Encrypt
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// Protocol the ACME server negotiates to validate a TLS-ALPN-01 challenge
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Path HTTP-01 challenge tokens are fetched from
+const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// How often we check whether the certificate has to be renewed, certificates
+/// from files are re-read this often
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long we wait after failing to get a certificate, ACME servers limit
+/// the failed validations per hour
+const RENEWAL_RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Age at which we renew an ACME certificate, a month before the 90 days
+/// certificates of Let's Encrypt expire
+const RENEW_AFTER: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+
+/// How long we wait at most between polls of an order being validated
+const MAX_ORDER_POLL_DELAY: Duration = Duration::from_secs(16);
+
+/// How long a client may take to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS handshakes we run at once, further connections are closed until one
+/// finished, so clients from many sources can't make us run unlimited
+/// handshakes
+const MAX_PENDING_HANDSHAKES: usize = 256;
+
+/// How long the ACME challenge server waits for a request
+const HTTP_CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections the ACME challenge server handles at once
+const MAX_CHALLENGE_CONNECTIONS: usize = 64;
+
+const ACME_ACCOUNT_FILE: &str = "account.json";
+const ACME_CERTIFICATE_FILE: &str = "certificate.json";
+
+/// How we serve the API over TLS
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApiTlsConfig {
+    /// Address we serve the API on over TLS, e.g. `0.0.0.0:443`
+    pub bind: SocketAddr,
+    pub certificate: ApiCertificate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiCertificate {
+    /// PEM files of the certificate chain and its private key, re-read
+    /// periodically so certificates renewed by other tools are picked up
+    Files {
+        cert_chain: PathBuf,
+        private_key: PathBuf,
+    },
+    /// Certificate we provision and renew through ACME
+    Acme(AcmeConfig),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct AcmeConfig {
+    /// Domains the certificate is issued for, all of them have to resolve to
+    /// us
+    pub domains: Vec<String>,
+    /// Email the ACME server sends notices about the certificate to
+    #[serde(default)]
+    pub contact_email: Option<String>,
+    /// Directory of the ACME server
+    #[serde(default = "default_acme_directory")]
+    pub directory: Url,
+    pub challenge: AcmeChallenge,
+    /// Directory the ACME account and the certificate are stored in
+    pub cache_dir: PathBuf,
+}
+
+fn default_acme_directory() -> Url {
+    LETS_ENCRYPT_DIRECTORY.parse().expect("valid url")
+}
+
+/// How the ACME server validates that we control the domains
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AcmeChallenge {
+    /// The ACME server fetches a token over plain HTTP, `bind` has to be
+    /// reachable on port 80 of the domains
+    Http01 { bind: SocketAddr },
+    /// The ACME server validates a special certificate of the TLS listener,
+    /// which has to be reachable on port 443 of the domains
+    TlsAlpn01,
+}
+
+/// Certificate the TLS listener presents, swapped on renewal, and the
+/// certificates of pending TLS-ALPN-01 challenges by domain
+#[derive(Default)]
+struct CertResolver {
+    current: RwLock<Option<Arc<CertifiedKey>>>,
+    challenges: RwLock<BTreeMap<String, Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let is_challenge = client_hello
+            .alpn()
+            .map_or(false, |mut protocols| protocols.any(|p| p == ACME_TLS_ALPN));
+        if is_challenge {
+            let domain = client_hello.server_name()?;
+            return self
+                .challenges
+                .read()
+                .expect("lock poisoned")
+                .get(domain)
+                .cloned();
+        }
+
+        self.current.read().expect("lock poisoned").clone()
+    }
+}
+
+/// Key authorizations of pending HTTP-01 challenges by token
+type HttpChallenges = Arc<RwLock<BTreeMap<String, String>>>;
+
+/// Certificate stored in the ACME cache directory
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredCertificate {
+    /// Seconds since the unix epoch
+    issued_at: u64,
+    domains: Vec<String>,
+    cert_chain_pem: String,
+    private_key_pem: String,
+}
+
+impl StoredCertificate {
+    fn needs_renewal(&self, domains: &[String]) -> bool {
+        self.domains != domains
+            || unix_now().saturating_sub(self.issued_at) >= RENEW_AFTER.as_secs()
+    }
+}
+
+/// Serves the API server at `target` over TLS on the bind address of `config`
+/// until shutdown, keeping the certificate up to date in the background
+///
+/// Connections are admitted by `limiter` before their handshake, like the ones
+/// of the plain API listeners.
+pub async fn serve_api_tls(
+    config: ApiTlsConfig,
+    target: SocketAddr,
+    limiter: Arc<SourceLimiter>,
+    mut shutdown_rx: oneshot::Receiver<()>,
+) -> anyhow::Result<()> {
+    let resolver = Arc::new(CertResolver::default());
+    let mut tls_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    tls_config.alpn_protocols = vec![b"http/1.1".to_vec(), ACME_TLS_ALPN.to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+
+    // we listen before getting a certificate, the ACME server connects to us to
+    // validate TLS-ALPN-01 challenges
+    let listener = TcpListener::bind(config.bind)
+        .await
+        .with_context(|| format!("Bind address: {}", config.bind))?;
+    info!(target: LOG_NET_API, bind = %config.bind, "Serving the API over TLS");
+
+    let http_challenges = HttpChallenges::default();
+    let mut background = vec![];
+    if let ApiCertificate::Acme(AcmeConfig {
+        challenge: AcmeChallenge::Http01 { bind },
+        ..
+    }) = &config.certificate
+    {
+        let listener = TcpListener::bind(bind)
+            .await
+            .with_context(|| format!("Bind address: {bind}"))?;
+        background.push(tokio::spawn(serve_http_challenges(
+            listener,
+            http_challenges.clone(),
+        )));
+    }
+    background.push(tokio::spawn(keep_certificate_current(
+        config.certificate,
+        resolver,
+        http_challenges,
+    )));
+
+    let handshakes = Arc::new(Semaphore::new(MAX_PENDING_HANDSHAKES));
+    loop {
+        let (incoming, source) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown_rx => break,
+        };
+        let connection = match limiter.admit_connection(source.ip(), Instant::now()) {
+            Ok(connection) => connection,
+            Err(e) => {
+                debug!(target: LOG_NET_API, err = %e, "Rejected TLS API connection");
+                continue;
+            }
+        };
+        let Ok(handshake) = handshakes.clone().try_acquire_owned() else {
+            debug!(target: LOG_NET_API, %source, "Too many pending TLS handshakes");
+            continue;
+        };
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                forward_tls_connection(acceptor, incoming, target, connection, handshake).await
+            {
+                debug!(target: LOG_NET_API, err = %e, "TLS API connection failed");
+            }
+        });
+    }
+
+    for task in background {
+        task.abort();
+    }
+    Ok(())
+}
+
+/// Completes the TLS handshake of `incoming`, holding `handshake` until it
+/// finished, and forwards the connection to the API server at `target`
+async fn forward_tls_connection(
+    acceptor: TlsAcceptor,
+    incoming: TcpStream,
+    target: SocketAddr,
+    connection: SourceConnection,
+    handshake: tokio::sync::OwnedSemaphorePermit,
+) -> anyhow::Result<()> {
+    let incoming = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(incoming))
+        .await
+        .map_err(|_| format_err!("TLS handshake timed out"))??;
+    drop(handshake);
+    // the ACME server only checks the certificate of a challenge
+    if incoming.get_ref().1.alpn_protocol() == Some(ACME_TLS_ALPN) {
+        return Ok(());
+    }
+
+    forward_api_connection(incoming, target, connection).await
+}
+
+/// Loads the certificate and renews it whenever it's due
+async fn keep_certificate_current(
+    certificate: ApiCertificate,
+    resolver: Arc<CertResolver>,
+    http_challenges: HttpChallenges,
+) {
+    loop {
+        let result = match &certificate {
+            ApiCertificate::Files {
+                cert_chain,
+                private_key,
+            } => load_certificate_files(cert_chain, private_key).await,
+            ApiCertificate::Acme(config) => {
+                acme_certificate(config, &resolver, &http_challenges).await
+            }
+        };
+
+        let retry_in = match result {
+            Ok(key) => {
+                *resolver.current.write().expect("lock poisoned") = Some(Arc::new(key));
+                RENEWAL_CHECK_INTERVAL
+            }
+            Err(e) => {
+                error!(target: LOG_NET_API, err = %e, "Could not get a TLS API certificate");
+                RENEWAL_RETRY_INTERVAL
+            }
+        };
+        tokio::time::sleep(retry_in).await;
+    }
+}
+
+async fn load_certificate_files(
+    cert_chain: &Path,
+    private_key: &Path,
+) -> anyhow::Result<CertifiedKey> {
+    let cert_chain_pem = tokio::fs::read(cert_chain)
+        .await
+        .with_context(|| format!("Could not read {}", cert_chain.display()))?;
+    let private_key_pem = tokio::fs::read(private_key)
+        .await
+        .with_context(|| format!("Could not read {}", private_key.display()))?;
+    certified_key(&cert_chain_pem, &private_key_pem)
+}
+
+/// Reads a PEM encoded certificate chain and its private key
+fn certified_key(cert_chain_pem: &[u8], private_key_pem: &[u8]) -> anyhow::Result<CertifiedKey> {
+    let certs = rustls_pemfile::certs(&mut &cert_chain_pem[..])?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        bail!("No certificate found in the certificate chain");
+    }
+
+    let private_key = rustls_pemfile::read_all(&mut &private_key_pem[..])?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(key),
+            _ => None,
+        })
+        .ok_or_else(|| format_err!("No private key found"))?;
+    let signing_key = any_supported_type(&PrivateKey(private_key))
+        .map_err(|_| format_err!("Unsupported private key type"))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// The stored ACME certificate, provisioning a new one if there is none yet
+/// or it is due for renewal
+async fn acme_certificate(
+    config: &AcmeConfig,
+    resolver: &CertResolver,
+    http_challenges: &HttpChallenges,
+) -> anyhow::Result<CertifiedKey> {
+    let path = config.cache_dir.join(ACME_CERTIFICATE_FILE);
+    let stored = tokio::fs::read(&path)
+        .await
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<StoredCertificate>(&bytes).ok());
+
+    let stored = match stored {
+        Some(stored) if !stored.needs_renewal(&config.domains) => stored,
+        _ => {
+            info!(target: LOG_NET_API, domains = ?config.domains, "Requesting an ACME certificate");
+            let provisioned = provision_certificate(config, resolver, http_challenges).await;
+            // challenges are only answered while the order is pending
+            http_challenges.write().expect("lock poisoned").clear();
+            resolver.challenges.write().expect("lock poisoned").clear();
+
+            let provisioned = provisioned?;
+            create_private_dir(&config.cache_dir).await?;
+            write_private(&path, &serde_json::to_vec(&provisioned)?).await?;
+            info!(target: LOG_NET_API, "Got a new certificate through ACME");
+            provisioned
+        }
+    };
+
+    certified_key(
+        stored.cert_chain_pem.as_bytes(),
+        stored.private_key_pem.as_bytes(),
+    )
+}
+
+/// Our ACME account, created on first use
+async fn acme_account(config: &AcmeConfig) -> anyhow::Result<Account> {
+    let path = config.cache_dir.join(ACME_ACCOUNT_FILE);
+    if let Ok(bytes) = tokio::fs::read(&path).await {
+        let credentials: AccountCredentials = serde_json::from_slice(&bytes)?;
+        return Ok(Account::from_credentials(credentials)?);
+    }
+
+    let contact = config
+        .contact_email
+        .iter()
+        .map(|email| format!("mailto:{email}"))
+        .collect::<Vec<_>>();
+    let contact = contact.iter().map(String::as_str).collect::<Vec<_>>();
+    let account = Account::create(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        config.directory.as_str(),
+    )
+    .await?;
+
+    create_private_dir(&config.cache_dir).await?;
+    write_private(&path, &serde_json::to_vec(&account.credentials())?).await?;
+    Ok(account)
+}
+
+/// Creates the ACME cache directory, only accessible by us
+async fn create_private_dir(path: &Path) -> anyhow::Result<()> {
+    tokio::fs::create_dir_all(path)
+        .await
+        .with_context(|| format!("Could not create {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700)).await?;
+    }
+    Ok(())
+}
+
+/// Writes `contents` to `path`, only readable by us since the ACME account
+/// credentials and the certificate contain private keys
+async fn write_private(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(path)
+        .await
+        .with_context(|| format!("Could not write {}", path.display()))?;
+    // the mode only applies to new files, earlier versions created them with
+    // the default permissions
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+async fn provision_certificate(
+    config: &AcmeConfig,
+    resolver: &CertResolver,
+    http_challenges: &HttpChallenges,
+) -> anyhow::Result<StoredCertificate> {
+    let account = acme_account(config).await?;
+    let identifiers = config
+        .domains
+        .iter()
+        .map(|domain| Identifier::Dns(domain.clone()))
+        .collect::<Vec<_>>();
+    let (mut order, state) = account
+        .new_order(&NewOrder {
+            identifiers: &identifiers,
+        })
+        .await?;
+
+    let challenge_type = match config.challenge {
+        AcmeChallenge::Http01 { .. } => ChallengeType::Http01,
+        AcmeChallenge::TlsAlpn01 => ChallengeType::TlsAlpn01,
+    };
+    let mut ready = vec![];
+    for authorization in order.authorizations(&state.authorizations).await? {
+        if matches!(authorization.status, AuthorizationStatus::Valid) {
+            continue;
+        }
+
+        let Identifier::Dns(domain) = &authorization.identifier;
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.r#type == challenge_type)
+            .ok_or_else(|| {
+                format_err!("ACME server offers no challenge we support for {domain}")
+            })?;
+        let key_authorization = order.key_authorization(challenge);
+
+        match config.challenge {
+            AcmeChallenge::Http01 { .. } => {
+                http_challenges.write().expect("lock poisoned").insert(
+                    challenge.token.clone(),
+                    key_authorization.as_str().to_string(),
+                );
+            }
+            AcmeChallenge::TlsAlpn01 => {
+                let key = challenge_certificate(domain, key_authorization.as_str())?;
+                resolver
+                    .challenges
+                    .write()
+                    .expect("lock poisoned")
+                    .insert(domain.clone(), Arc::new(key));
+            }
+        }
+        ready.push(challenge.url.clone());
+    }
+
+    for url in &ready {
+        order.set_challenge_ready(url).await?;
+    }
+    let finalize_url = await_order_ready(&mut order).await?;
+
+    let mut params = rcgen::CertificateParams::new(config.domains.clone());
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    let key = rcgen::Certificate::from_params(params)?;
+    let cert_chain_pem = order
+        .finalize(&key.serialize_request_der()?, &finalize_url)
+        .await?;
+
+    Ok(StoredCertificate {
+        issued_at: unix_now(),
+        domains: config.domains.clone(),
+        cert_chain_pem,
+        private_key_pem: key.serialize_private_key_pem(),
+    })
+}
+
+/// Polls the order until the ACME server validated all challenges, returns
+/// the url to finalize it at
+async fn await_order_ready(order: &mut Order) -> anyhow::Result<String> {
+    let mut delay = Duration::from_millis(250);
+    loop {
+        tokio::time::sleep(delay).await;
+        let state = order.state().await?;
+        match state.status {
+            OrderStatus::Ready => return Ok(state.finalize),
+            OrderStatus::Invalid => bail!("ACME server could not validate the challenges"),
+            _ if delay >= MAX_ORDER_POLL_DELAY => bail!("ACME server took too long to validate"),
+            _ => delay *= 2,
+        }
+    }
+}
+
+/// Self-signed certificate for `domain` proving our key authorization of a
+/// TLS-ALPN-01 challenge, see RFC 8737
+fn challenge_certificate(domain: &str, key_authorization: &str) -> anyhow::Result<CertifiedKey> {
+    let digest = sha256::Hash::hash(key_authorization.as_bytes());
+    let mut params = rcgen::CertificateParams::new(vec![domain.to_string()]);
+    params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(
+        &digest.into_inner(),
+    )];
+    let cert = rcgen::Certificate::from_params(params)?;
+    let signing_key = any_supported_type(&PrivateKey(cert.serialize_private_key_der()))
+        .map_err(|_| format_err!("Unsupported private key type"))?;
+
+    Ok(CertifiedKey::new(
+        vec![Certificate(cert.serialize_der()?)],
+        signing_key,
+    ))
+}
+
+/// Answers the HTTP-01 challenges of pending orders
+async fn serve_http_challenges(listener: TcpListener, challenges: HttpChallenges) {
+    let connections = Arc::new(Semaphore::new(MAX_CHALLENGE_CONNECTIONS));
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!(target: LOG_NET_API, err = %e, "Could not accept ACME challenge connection");
+                continue;
+            }
+        };
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            continue;
+        };
+        let challenges = challenges.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let mut request = vec![0u8; 1024];
+            let Ok(Ok(len)) =
+                tokio::time::timeout(HTTP_CHALLENGE_TIMEOUT, stream.read(&mut request)).await
+            else {
+                return;
+            };
+            let key_authorization = std::str::from_utf8(&request[..len])
+                .ok()
+                .and_then(challenge_token)
+                .and_then(|token| {
+                    challenges
+                        .read()
+                        .expect("lock poisoned")
+                        .get(token)
+                        .cloned()
+                });
+
+            let response = match key_authorization {
+                Some(body) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                ),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Token of the challenge an HTTP request asks for
+fn challenge_token(request: &str) -> Option<&str> {
+    let mut request_line = request.lines().next()?.split(' ');
+    if request_line.next()? != "GET" {
+        return None;
+    }
+    request_line.next()?.strip_prefix(ACME_CHALLENGE_PATH)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time to be after the unix epoch")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{certified_key, challenge_token, StoredCertificate, RENEW_AFTER};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn acme_files_are_only_accessible_by_us() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("acme");
+        super::create_private_dir(&cache_dir).await.unwrap();
+        let path = cache_dir.join(super::ACME_ACCOUNT_FILE);
+        // files written by earlier versions are made private too
+        std::fs::write(&path, b"old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        super::write_private(&path, b"credentials").await.unwrap();
+
+        let mode = |path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&cache_dir), 0o700);
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(std::fs::read(&path).unwrap(), b"credentials");
+    }
+
+    #[test]
+    fn reads_pem_certificate_and_key() {
+        let cert =
+            rcgen::generate_simple_self_signed(vec!["fedimint.example".to_string()]).unwrap();
+        let key = certified_key(
+            cert.serialize_pem().unwrap().as_bytes(),
+            cert.serialize_private_key_pem().as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(key.cert.len(), 1);
+
+        assert!(certified_key(b"", cert.serialize_private_key_pem().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn renews_old_certificates_and_changed_domains() {
+        let domains = vec!["fedimint.example".to_string()];
+        let mut stored = StoredCertificate {
+            issued_at: super::unix_now(),
+            domains: domains.clone(),
+            cert_chain_pem: String::new(),
+            private_key_pem: String::new(),
+        };
+        assert!(!stored.needs_renewal(&domains));
+        assert!(stored.needs_renewal(&["other.example".to_string()]));
+
+        stored.issued_at -= RENEW_AFTER.as_secs();
+        assert!(stored.needs_renewal(&domains));
+    }
+
+    #[test]
+    fn parses_challenge_requests() {
+        assert_eq!(
+            challenge_token("GET /.well-known/acme-challenge/abc HTTP/1.1\r\nHost: x\r\n\r\n"),
+            Some("abc")
+        );
+        assert_eq!(challenge_token("GET /other HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(
+            challenge_token("POST /.well-known/acme-challenge/abc HTTP/1.1\r\n\r\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,363 @@
+//! Connects to peers through the SOCKS5 proxy of a Tor daemon and publishes
+//! our peer endpoint as an onion service through its control port
+use std::net::{Ipv4Addr, SocketAddr};
+
+use anyhow::{bail, format_err};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use url::Url;
+
+/// How guardians reach each other through Tor
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct TorConfig {
+    /// SOCKS5 proxy of the Tor daemon that connections to `.onion` addresses
+    /// go through
+    pub socks_proxy: SocketAddr,
+    /// Whether connections to peers with clearnet addresses go through Tor as
+    /// well
+    #[serde(default)]
+    pub proxy_clearnet: bool,
+    /// Onion service we publish through the control port of the Tor daemon, if
+    /// `None` the onion service has to point to our bind address in the
+    /// config of the Tor daemon
+    #[serde(default)]
+    pub onion_service: Option<OnionServiceConfig>,
+}
+
+/// Onion service forwarding connections from our peers to our bind address
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct OnionServiceConfig {
+    /// Control port of the Tor daemon
+    pub control_addr: SocketAddr,
+    /// Password configured with `HashedControlPassword`, if `None` the control
+    /// port has to accept unauthenticated connections
+    #[serde(default)]
+    pub control_password: Option<String>,
+    /// Port of the onion service our peers connect to
+    pub port: u16,
+}
+
+impl TorConfig {
+    /// Opens a connection to the host and port of `destination`, through Tor
+    /// if it is an onion service or we route all connections through Tor
+    pub async fn connect(&self, destination: &Url) -> anyhow::Result<TcpStream> {
+        let host = destination
+            .host_str()
+            .ok_or_else(|| format_err!("Missing host in {destination}"))?;
+        let port = destination
+            .port()
+            .ok_or_else(|| format_err!("Missing port in {destination}"))?;
+
+        if self.proxy_clearnet || host.ends_with(".onion") {
+            socks5_connect(self.socks_proxy, host, port).await
+        } else {
+            Ok(TcpStream::connect((host, port)).await?)
+        }
+    }
+}
+
+/// Connects to `host:port` through the SOCKS5 proxy at `proxy`, letting the
+/// proxy resolve the host name
+pub async fn socks5_connect(proxy: SocketAddr, host: &str, port: u16) -> anyhow::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy).await?;
+
+    // version 5 offering the "no authentication" method
+    stream.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await?;
+    if method != [0x05, 0x00] {
+        bail!("SOCKS5 proxy {proxy} requires authentication");
+    }
+
+    let host_len =
+        u8::try_from(host.len()).map_err(|_| format_err!("Host name {host} is too long"))?;
+    // CONNECT command with a domain name address
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x00 {
+        bail!(
+            "SOCKS5 proxy {proxy} failed to connect to {host}:{port} with reply {}",
+            reply[1]
+        );
+    }
+
+    // skip the address the proxy bound, which is followed by a 2 byte port
+    let addr_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        }
+        address_type => bail!("SOCKS5 proxy {proxy} replied with address type {address_type}"),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(stream)
+}
+
+/// Authenticated connection to the control port of a Tor daemon, the onion
+/// services added through it are removed once it is dropped
+#[derive(Debug)]
+pub struct TorControl {
+    stream: BufReader<TcpStream>,
+}
+
+impl TorControl {
+    pub async fn connect(addr: SocketAddr, password: Option<&str>) -> anyhow::Result<Self> {
+        let mut control = Self {
+            stream: BufReader::new(TcpStream::connect(addr).await?),
+        };
+
+        let command = match password {
+            Some(password) => format!(
+                "AUTHENTICATE \"{}\"",
+                password.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            None => "AUTHENTICATE".to_string(),
+        };
+        control.command(&command).await?;
+        Ok(control)
+    }
+
+    /// Publishes an onion service with `private_key` that forwards `port` to
+    /// `target`, returns the onion address of the service
+    pub async fn add_onion(
+        &mut self,
+        private_key: &str,
+        port: u16,
+        target: SocketAddr,
+    ) -> anyhow::Result<String> {
+        let lines = self
+            .command(&format!("ADD_ONION {private_key} Port={port},{target}"))
+            .await?;
+
+        lines
+            .iter()
+            .find_map(|line| line.strip_prefix("ServiceID="))
+            .map(|service_id| format!("{service_id}.onion"))
+            .ok_or_else(|| format_err!("Tor didn't return the onion address"))
+    }
+
+    /// Creates an onion service with a new key that forwards `port` to the
+    /// same port on localhost and removes it again, returns the private key in
+    /// the format [`Self::add_onion`] expects and the onion address
+    pub async fn generate_onion(&mut self, port: u16) -> anyhow::Result<(String, String)> {
+        let lines = self
+            .command(&format!("ADD_ONION NEW:ED25519-V3 Port={port}"))
+            .await?;
+
+        let service_id = lines
+            .iter()
+            .find_map(|line| line.strip_prefix("ServiceID="))
+            .ok_or_else(|| format_err!("Tor didn't return the onion address"))?
+            .to_string();
+        let private_key = lines
+            .iter()
+            .find_map(|line| line.strip_prefix("PrivateKey="))
+            .ok_or_else(|| format_err!("Tor didn't return the private key"))?
+            .to_string();
+
+        self.command(&format!("DEL_ONION {service_id}")).await?;
+        Ok((private_key, format!("{service_id}.onion")))
+    }
+
+    /// Sends a command and returns the lines of the reply if it succeeded
+    async fn command(&mut self, command: &str) -> anyhow::Result<Vec<String>> {
+        self.stream
+            .get_mut()
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                bail!("Tor closed the control connection");
+            }
+
+            let line = line.trim_end();
+            if line.len() < 4 {
+                bail!("Invalid reply from the Tor control port: {line}");
+            }
+            let (status, text) = line.split_at(3);
+            if status != "250" {
+                bail!("Tor control command failed: {line}");
+            }
+
+            lines.push(text[1..].to_string());
+            // the last line of a reply separates status and text with a space
+            if text.starts_with(' ') {
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+/// Publishes the onion service of `config` forwarding to `bind_addr`, the
+/// service stays published while the returned connection is open
+pub async fn publish_onion_service(
+    config: &OnionServiceConfig,
+    private_key: &str,
+    bind_addr: SocketAddr,
+) -> anyhow::Result<(TorControl, String)> {
+    let target = if bind_addr.ip().is_unspecified() {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), bind_addr.port())
+    } else {
+        bind_addr
+    };
+
+    let mut control =
+        TorControl::connect(config.control_addr, config.control_password.as_deref()).await?;
+    let onion_address = control.add_onion(private_key, config.port, target).await?;
+    Ok((control, onion_address))
+}
+
+/// Generates the key of the onion service of `config`, returns the private key
+/// and the onion address our peers will reach us at
+///
+/// The service is only published once we run with the key.
+pub async fn generate_onion_service_key(
+    config: &OnionServiceConfig,
+) -> anyhow::Result<(String, String)> {
+    let mut control =
+        TorControl::connect(config.control_addr, config.control_password.as_deref()).await?;
+    control.generate_onion(config.port).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::{socks5_connect, TorControl};
+
+    #[tokio::test]
+    async fn connects_through_socks5_proxy() {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+
+        let proxy_task = tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [0x05, 0x01, 0x00]);
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+
+            let host = b"peer.onion";
+            let mut request = vec![0u8; 5 + host.len() + 2];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[..5], [0x05, 0x01, 0x00, 0x03, host.len() as u8]);
+            assert_eq!(&request[5..5 + host.len()], host);
+            assert_eq!(request[5 + host.len()..], 8173u16.to_be_bytes());
+
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            stream.write_all(b"hello").await.unwrap();
+        });
+
+        let mut stream = socks5_connect(proxy_addr, "peer.onion", 8173)
+            .await
+            .unwrap();
+        let mut received = [0u8; 5];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"hello");
+        proxy_task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn adds_onion_service_through_control_port() {
+        let tor = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control_addr = tor.local_addr().unwrap();
+
+        let tor_task = tokio::spawn(async move {
+            let (stream, _) = tor.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "AUTHENTICATE \"pass\\\"word\"\r\n");
+            stream.get_mut().write_all(b"250 OK\r\n").await.unwrap();
+
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+            assert_eq!(
+                line,
+                "ADD_ONION ED25519-V3:key Port=8173,127.0.0.1:8174\r\n"
+            );
+            stream
+                .get_mut()
+                .write_all(b"250-ServiceID=guardian\r\n250 OK\r\n")
+                .await
+                .unwrap();
+
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+            stream
+                .get_mut()
+                .write_all(b"552 Unrecognized command\r\n")
+                .await
+                .unwrap();
+        });
+
+        let mut control = TorControl::connect(control_addr, Some("pass\"word"))
+            .await
+            .unwrap();
+        assert_eq!(
+            control
+                .add_onion("ED25519-V3:key", 8173, "127.0.0.1:8174".parse().unwrap())
+                .await
+                .unwrap(),
+            "guardian.onion"
+        );
+        assert!(control.command("UNKNOWN").await.is_err());
+        tor_task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn generates_onion_service_key() {
+        let tor = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control_addr = tor.local_addr().unwrap();
+
+        let tor_task = tokio::spawn(async move {
+            let (stream, _) = tor.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "AUTHENTICATE\r\n");
+            stream.get_mut().write_all(b"250 OK\r\n").await.unwrap();
+
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "ADD_ONION NEW:ED25519-V3 Port=8173\r\n");
+            stream
+                .get_mut()
+                .write_all(b"250-ServiceID=guardian\r\n250-PrivateKey=ED25519-V3:key\r\n250 OK\r\n")
+                .await
+                .unwrap();
+
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "DEL_ONION guardian\r\n");
+            stream.get_mut().write_all(b"250 OK\r\n").await.unwrap();
+        });
+
+        let mut control = TorControl::connect(control_addr, None).await.unwrap();
+        assert_eq!(
+            control.generate_onion(8173).await.unwrap(),
+            ("ED25519-V3:key".to_string(), "guardian.onion".to_string())
+        );
+        tor_task.await.unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, format_err};
+use fedimint_core::db::Database;
+use fedimint_logging::LOG_CONSENSUS;
+use hbbft::honey_badger::Batch;
+use tracing::{debug, info};
+
+use crate::consensus::debug::epoch_message;
+use crate::consensus::FedimintConsensus;
+use crate::db::{EarliestEpochKey, EpochHistoryKey, LastEpochKey, StateSnapshotKey};
+
+/// Replays the epoch history recorded in `recorded`, a copy of a guardian's
+/// database, on the empty database of `consensus` and returns the last epoch
+/// it replayed
+///
+/// If the start of the history was pruned, the state snapshot in `recorded` is
+/// installed first. Epochs are processed like the guardian processed them
+/// without contacting its peers, replaying stops at `until_epoch` or with an
+/// error at the first epoch whose outcome differs from the recorded one.
+pub async fn replay_epoch_history(
+    consensus: &FedimintConsensus,
+    recorded: &Database,
+    until_epoch: Option<u64>,
+) -> anyhow::Result<Option<u64>> {
+    if consensus.get_epoch_count().await != 0 {
+        bail!("Epochs can only be replayed on an empty database");
+    }
+
+    let mut recorded_dbtx = recorded.begin_transaction().await;
+    let Some(last_epoch) = recorded_dbtx
+        .get_value(&LastEpochKey)
+        .await
+        .map(|key| key.0)
+    else {
+        info!(target: LOG_CONSENSUS, "The database has no epoch history to replay");
+        return Ok(None);
+    };
+    let last_epoch = until_epoch.map_or(last_epoch, |until| until.min(last_epoch));
+
+    let earliest_epoch = recorded_dbtx
+        .get_value(&EarliestEpochKey)
+        .await
+        .unwrap_or(0);
+    let first_epoch = if earliest_epoch == 0 {
+        0
+    } else {
+        let snapshot = recorded_dbtx
+            .get_value(&StateSnapshotKey)
+            .await
+            .filter(|snapshot| earliest_epoch <= snapshot.epoch + 1)
+            .ok_or_else(|| {
+                format_err!(
+                    "Epochs before {earliest_epoch} were pruned and no state snapshot covers them"
+                )
+            })?;
+        info!(
+            target: LOG_CONSENSUS,
+            "Installing state snapshot of epoch {}", snapshot.epoch
+        );
+        consensus
+            .install_state_snapshot(snapshot)
+            .await?
+            .outcome
+            .epoch
+            + 1
+    };
+    if last_epoch < first_epoch {
+        bail!("The replay starts after epoch {last_epoch} with the state snapshot");
+    }
+
+    for epoch in first_epoch..=last_epoch {
+        let recorded_outcome = recorded_dbtx
+            .get_value(&EpochHistoryKey(epoch))
+            .await
+            .ok_or_else(|| format_err!("Epoch {epoch} is missing from the epoch history"))?;
+
+        let outcome = Batch {
+            epoch,
+            contributions: BTreeMap::from_iter(recorded_outcome.outcome.items),
+        };
+        info!(target: LOG_CONSENSUS, "{}", epoch_message(&outcome));
+
+        let replayed = consensus.process_consensus_outcome(outcome, None).await?;
+        if replayed.hash != recorded_outcome.hash {
+            bail!(
+                "Epoch {epoch} diverged from the recorded history, rejecting {:?} instead of {:?}",
+                replayed.outcome.rejected_txs,
+                recorded_outcome.outcome.rejected_txs
+            );
+        }
+
+        debug!(
+            target: LOG_CONSENSUS,
+            epoch,
+            audit = %consensus.audit().await,
+            "Replayed epoch"
+        );
+    }
+
+    info!(
+        target: LOG_CONSENSUS,
+        "Replayed epochs {} to {}", first_epoch, last_epoch
+    );
+    Ok(Some(last_epoch))
+}
@@ -0,0 +1,146 @@
+//! Snapshots of the encodings of the core consensus types, see
+//! [`fedimint_core::encoding::compat`]
+
+use std::collections::BTreeMap;
+
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::config::{ApiEndpoint, ClientConfig, FederationId};
+use fedimint_core::core::{DynInput, DynModuleConsensusItem, DynOutput, DynOutputOutcome};
+use fedimint_core::epoch::{
+    ConsensusItem, ConsensusLimits, ConsensusParams, ConsensusUpgrade, ConsensusVersionActivation,
+    ParamsChangeProposal, PeerSetChange, SerdeSignatureShare, StateHash, SupportedConsensusVersion,
+};
+use fedimint_core::transaction::Transaction;
+use fedimint_core::{encoding_snapshot_test, PeerId};
+use fedimint_dummy_common::{DummyConsensusItem, DummyInput, DummyOutput, DummyOutputOutcome};
+use fedimint_server::config::ServerConfigConsensus;
+use fedimint_server::consensus::CONSENSUS_VERSION;
+use secp256k1_zkp::schnorr;
+use tbs::Scalar;
+use threshold_crypto::group::Curve;
+use threshold_crypto::poly::Commitment;
+use threshold_crypto::{G1Projective, G2Projective, PublicKeySet, SignatureShare};
+use tokio_rustls::rustls;
+
+/// Instance id of the dummy module the samples contain items of
+const MODULE_INSTANCE_ID: u16 = 0;
+
+/// Keys committing to the polynomial `7 + 3x`, the samples are built from
+/// multiples of the generators so their encodings can be reproduced without
+/// running the code
+fn sample_keys() -> PublicKeySet {
+    let coefficients = [7u64, 3]
+        .into_iter()
+        .map(|coefficient| G1Projective::generator() * Scalar::from(coefficient))
+        .collect::<Vec<_>>();
+    PublicKeySet::from(Commitment::from(coefficients))
+}
+
+fn sample_endpoints() -> BTreeMap<PeerId, ApiEndpoint> {
+    (0..2u16)
+        .map(|peer| {
+            let endpoint = ApiEndpoint {
+                url: format!("ws://127.0.0.1:{}", 5000 + peer).parse().unwrap(),
+                name: format!("peer-{peer}"),
+            };
+            (PeerId::from(peer), endpoint)
+        })
+        .collect()
+}
+
+fn sample_signature_share() -> SerdeSignatureShare {
+    let point = G2Projective::generator() * Scalar::from(5);
+    SerdeSignatureShare(
+        SignatureShare::from_bytes(point.to_affine().to_compressed())
+            .expect("Multiples of the generator are valid signature shares"),
+    )
+}
+
+fn sample_transaction() -> Transaction {
+    Transaction {
+        inputs: vec![DynInput::from_typed(MODULE_INSTANCE_ID, DummyInput)],
+        outputs: vec![DynOutput::from_typed(MODULE_INSTANCE_ID, DummyOutput)],
+        signature: Some(schnorr::Signature::from_slice(&[1; 64]).unwrap()),
+    }
+}
+
+fn sample_client_config() -> ClientConfig {
+    let keys = sample_keys();
+    ClientConfig {
+        federation_id: FederationId(keys.public_key()),
+        api_endpoints: sample_endpoints(),
+        epoch_pk: keys.public_key(),
+        api_pks: BTreeMap::from([(PeerId::from(0), keys.public_key_share(0))]),
+        modules: BTreeMap::new(),
+        meta: BTreeMap::from([("federation_name".to_string(), "sample".to_string())]),
+        limits: ConsensusLimits::default(),
+    }
+}
+
+fn sample_server_config() -> ServerConfigConsensus {
+    let keys = sample_keys();
+    ServerConfigConsensus {
+        code_version: "sample".to_string(),
+        auth_pk_set: keys.clone(),
+        hbbft_pk_set: keys.clone(),
+        epoch_pk_set: keys,
+        api_endpoints: sample_endpoints(),
+        tls_certs: BTreeMap::from([(PeerId::from(0), rustls::Certificate(vec![1, 2, 3]))]),
+        modules: BTreeMap::new(),
+        meta: BTreeMap::new(),
+        consensus_params: ConsensusParams::default(),
+        consensus_limits: ConsensusLimits::default(),
+    }
+}
+
+encoding_snapshot_test!(core_encodings, CONSENSUS_VERSION, {
+    "Transaction" => sample_transaction(),
+    "DynOutputOutcome" => DynOutputOutcome::from_typed(MODULE_INSTANCE_ID, DummyOutputOutcome),
+    "ConsensusItem::ConsensusUpgrade" => ConsensusItem::ConsensusUpgrade(ConsensusUpgrade),
+    "ConsensusItem::ClientConfigSignatureShare" =>
+        ConsensusItem::ClientConfigSignatureShare(sample_signature_share()),
+    "ConsensusItem::EpochOutcomeSignatureShare" =>
+        ConsensusItem::EpochOutcomeSignatureShare(sample_signature_share()),
+    "ConsensusItem::Transaction" => ConsensusItem::Transaction(sample_transaction()),
+    "ConsensusItem::Module" => ConsensusItem::Module(DynModuleConsensusItem::from_typed(
+        MODULE_INSTANCE_ID,
+        DummyConsensusItem,
+    )),
+    "ConsensusItem::PeerSetChange" => ConsensusItem::PeerSetChange(PeerSetChange {
+        from: sample_endpoints(),
+        to: sample_endpoints().into_iter().take(1).collect(),
+    }),
+    "ConsensusItem::EpochCheckpointSignatureShare" =>
+        ConsensusItem::EpochCheckpointSignatureShare(sample_signature_share()),
+    "ConsensusItem::SupportedConsensusVersion" =>
+        ConsensusItem::SupportedConsensusVersion(SupportedConsensusVersion { version: 1 }),
+    "ConsensusItem::ConsensusVersionActivation" =>
+        ConsensusItem::ConsensusVersionActivation(ConsensusVersionActivation {
+            version: 1,
+            epoch: 100,
+        }),
+    "ConsensusItem::ConsensusParams" => ConsensusItem::ConsensusParams(ConsensusParams {
+        round_interval_ms: 500,
+        max_proposal_items: 1_000,
+        module_batch_limits: BTreeMap::from([(MODULE_INSTANCE_ID, 100)]),
+    }),
+    "ConsensusItem::ParamsChangeProposal" =>
+        ConsensusItem::ParamsChangeProposal(ParamsChangeProposal {
+            epoch: 100,
+            limits: ConsensusLimits {
+                max_transaction_size: 100_000,
+                module_item_sizes: BTreeMap::from([(MODULE_INSTANCE_ID, 1_000)]),
+                ..ConsensusLimits::default()
+            },
+            module_params: BTreeMap::from([(
+                MODULE_INSTANCE_ID,
+                BTreeMap::from([("fee_msats".to_string(), 1_000)]),
+            )]),
+        }),
+    "ConsensusItem::StateHash" => ConsensusItem::StateHash(StateHash {
+        epoch: 100,
+        hash: sha256::Hash::hash(b"state"),
+    }),
+    "ClientConfig" => sample_client_config(),
+    "ServerConfigConsensus" => sample_server_config(),
+});
@@ -1,4 +1,5 @@
 #![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{anyhow, Result};
@@ -10,11 +11,18 @@ use fedimint_core::db::{
 use futures::stream;
 use sqlx::migrate::MigrateDatabase;
 use sqlx::sqlite::SqliteConnectOptions;
-use sqlx::{ConnectOptions, Error, Executor, Row, Sqlite, SqlitePool, Transaction};
+use sqlx::{ConnectOptions, Connection, Error, Executor, Row, Sqlite, SqlitePool, Transaction};
 use tracing::{info, warn};
 
 #[derive(Debug)]
-pub struct SqliteDb(SqlitePool);
+pub struct SqliteDb {
+    pool: SqlitePool,
+    /// Used to open a connection outside of the pool for checkpoints
+    opts: SqliteConnectOptions,
+    /// Path of the database file if it was opened by path, used to report its
+    /// size
+    path: Option<PathBuf>,
+}
 
 pub struct SqliteDbTransaction<'a> {
     tx: Transaction<'a, Sqlite>,
@@ -38,54 +46,136 @@ impl SqliteDb {
         // Disable statement logging otherwise the queries clutter the log
         let mut opts = SqliteConnectOptions::from_str(connection_string).unwrap();
         opts.disable_statement_logging();
-        let db = SqlitePool::connect_with(opts).await?;
+        let db = SqlitePool::connect_with(opts.clone()).await?;
 
-        sqlx::query("CREATE TABLE IF NOT EXISTS kv (key BLOB, value BLOB);")
+        // Blobs are compared using `memcmp`, so the primary key index orders the
+        // keys the same way RocksDB does, which is used by the prefix scans
+        sqlx::query("CREATE TABLE IF NOT EXISTS kv (key BLOB PRIMARY KEY, value BLOB);")
             .execute(&db)
             .await
             .expect("Error while creating the key-value table");
+        Self::migrate_primary_key(&db).await?;
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS key_index ON kv(key);")
-            .execute(&db)
-            .await
-            .expect("Error while creating the key index");
+        Ok(SqliteDb {
+            pool: db,
+            opts,
+            path: None,
+        })
+    }
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS hex_index ON kv(hex(key));")
-            .execute(&db)
-            .await
-            .expect("Error while creating the key index");
+    /// Databases created before keys were unique store them in a table
+    /// without primary key, which is copied into one with a primary key. The
+    /// last value written to a key wins.
+    async fn migrate_primary_key(db: &SqlitePool) -> Result<(), Error> {
+        let schema: String = sqlx::query_scalar(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'kv'",
+        )
+        .fetch_one(db)
+        .await?;
+        if schema.to_uppercase().contains("PRIMARY KEY") {
+            return Ok(());
+        }
+
+        info!("Migrating the key-value table to unique keys");
+        let mut tx = db.begin().await?;
+        for statement in [
+            "CREATE TABLE kv_unique (key BLOB PRIMARY KEY, value BLOB);",
+            "INSERT OR REPLACE INTO kv_unique (key, value) \
+             SELECT key, value FROM kv ORDER BY rowid;",
+            "DROP TABLE kv;",
+            "ALTER TABLE kv_unique RENAME TO kv;",
+        ] {
+            tx.execute(statement).await?;
+        }
+        tx.commit().await
+    }
 
-        Ok(SqliteDb(db))
+    /// Opens the database file at `path`, creating it if it doesn't exist yet
+    pub async fn open_path(path: impl AsRef<Path>) -> Result<SqliteDb, Error> {
+        let mut db = Self::open(&format!("sqlite://{}", path.as_ref().display())).await?;
+        db.path = Some(path.as_ref().to_path_buf());
+        Ok(db)
     }
 }
 
+/// Returns the smallest key that is larger than all keys starting with
+/// `key_prefix`, `None` if there is no such key
+fn prefix_upper_bound(key_prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut upper_bound = key_prefix.to_vec();
+    while let Some(last) = upper_bound.pop() {
+        if last != u8::MAX {
+            upper_bound.push(last + 1);
+            return Some(upper_bound);
+        }
+    }
+    None
+}
+
 #[async_trait]
 impl IDatabase for SqliteDb {
     async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>> {
         let mut tx = SqliteDbTransaction {
-            tx: self.0.begin().await.unwrap(),
+            tx: self.pool.begin().await.unwrap(),
             error: false,
         };
         tx.set_tx_savepoint().await;
         let single_use = SingleUseDatabaseTransaction::new(tx);
         Box::new(single_use)
     }
+
+    fn size(&self) -> Option<u64> {
+        // committed transactions may still live in the write-ahead log
+        let path = self.path.as_ref()?;
+        let wal_path = PathBuf::from(format!("{}-wal", path.display()));
+        let db_size = std::fs::metadata(path).ok()?.len();
+        let wal_size = std::fs::metadata(wal_path).map_or(0, |metadata| metadata.len());
+        Some(db_size + wal_size)
+    }
+
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid checkpoint path {path:?}"))?;
+        // `block_on` panics on a thread that drives a runtime, and
+        // `block_in_place` on a current-thread runtime, so the copy is made
+        // from a runtime of its own on a separate thread
+        let opts = self.opts.clone();
+        let path = path.to_owned();
+        std::thread::spawn(move || -> Result<()> {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            runtime.block_on(async move {
+                let mut conn = opts.connect().await?;
+                sqlx::query("VACUUM INTO ?")
+                    .bind(path)
+                    .execute(&mut conn)
+                    .await?;
+                conn.close().await?;
+                Ok(())
+            })
+        })
+        .join()
+        .map_err(|_| anyhow!("Checkpoint thread panicked"))?
+    }
 }
 
 #[async_trait]
 impl<'a> IDatabaseTransaction<'a> for SqliteDbTransaction<'a> {
     async fn raw_insert_bytes(&mut self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        let val = self.raw_get_bytes(key).await.unwrap();
-        let query_prepared = sqlx::query("INSERT INTO kv (key, value) VALUES (?, ?)")
-            .bind(key)
-            .bind(value);
+        let val = self.raw_get_bytes(key).await?;
+        let query_prepared = sqlx::query(
+            "INSERT INTO kv (key, value) VALUES (?, ?) \
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(key)
+        .bind(value);
         self.error |= self.tx.execute(query_prepared).await.is_err();
         Ok(val)
     }
 
     async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let query_prepared =
-            sqlx::query("SELECT value FROM kv WHERE key = ? ORDER BY value DESC LIMIT 1").bind(key);
+        let query_prepared = sqlx::query("SELECT value FROM kv WHERE key = ?").bind(key);
         self.tx
             .fetch_optional(query_prepared)
             .await
@@ -94,31 +184,25 @@ impl<'a> IDatabaseTransaction<'a> for SqliteDbTransaction<'a> {
     }
 
     async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        let query_prepared =
-            sqlx::query("SELECT rowid, value FROM kv WHERE key = ? ORDER BY value DESC LIMIT 1")
-                .bind(key);
-        let res = self.tx.fetch_optional(query_prepared).await;
-        if let Ok(Some(row)) = res {
-            let rowid = row.get::<i64, &str>("rowid");
-            let value = row.get::<Vec<u8>, &str>("value");
-
-            let query_prepared = sqlx::query("DELETE FROM kv WHERE rowid = ?").bind(rowid);
+        let val = self.raw_get_bytes(key).await?;
+        if val.is_some() {
+            let query_prepared = sqlx::query("DELETE FROM kv WHERE key = ?").bind(key);
             self.error |= self.tx.execute(query_prepared).await.is_err();
-            return Ok(Some(value));
         }
 
-        // Didnt find a key to delete
-        Ok(None)
+        Ok(val)
     }
 
     async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> PrefixStream<'_> {
-        let mut str_prefix = "".to_string();
-        for prefix in key_prefix {
-            str_prefix = format!("{str_prefix}{prefix:02X?}");
-        }
-        str_prefix = format!("{}{}", str_prefix, "%");
-        let query = "SELECT key, value FROM kv WHERE hex(key) LIKE ? ORDER BY value DESC";
-        let query_prepared = sqlx::query(query).bind(str_prefix);
+        let query_prepared = match prefix_upper_bound(key_prefix) {
+            Some(upper_bound) => {
+                sqlx::query("SELECT key, value FROM kv WHERE key >= ? AND key < ? ORDER BY key")
+                    .bind(key_prefix)
+                    .bind(upper_bound)
+            }
+            None => sqlx::query("SELECT key, value FROM kv WHERE key >= ? ORDER BY key")
+                .bind(key_prefix),
+        };
         let results = self.tx.fetch_all(query_prepared).await;
 
         if results.is_err() {
@@ -137,13 +221,12 @@ impl<'a> IDatabaseTransaction<'a> for SqliteDbTransaction<'a> {
     }
 
     async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
-        let mut str_prefix = "".to_string();
-        for prefix in key_prefix {
-            str_prefix = format!("{str_prefix}{prefix:02X?}");
-        }
-        str_prefix = format!("{}{}", str_prefix, "%");
-        let query = "DELETE FROM kv WHERE hex(key) LIKE ?";
-        let query_prepared = sqlx::query(query).bind(str_prefix);
+        let query_prepared = match prefix_upper_bound(key_prefix) {
+            Some(upper_bound) => sqlx::query("DELETE FROM kv WHERE key >= ? AND key < ?")
+                .bind(key_prefix)
+                .bind(upper_bound),
+            None => sqlx::query("DELETE FROM kv WHERE key >= ?").bind(key_prefix),
+        };
         self.error |= self.tx.execute(query_prepared).await.is_err();
         Ok(())
     }
@@ -171,12 +254,15 @@ mod fedimint_sqlite_tests {
     use std::fs;
 
     use fedimint_core::core::ModuleInstanceId;
-    use fedimint_core::db::Database;
+    use fedimint_core::db::{Database, IDatabase};
     use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use futures::StreamExt;
     use rand::rngs::OsRng;
     use rand::RngCore;
+    use sqlx::migrate::MigrateDatabase;
+    use sqlx::{Executor, Sqlite, SqlitePool};
 
-    use crate::SqliteDb;
+    use crate::{prefix_upper_bound, SqliteDb};
 
     async fn open_temp_db(db_name: &str) -> Database {
         let dir = format!("/tmp/sqlite-{}/{}", db_name, OsRng.next_u64());
@@ -199,6 +285,14 @@ mod fedimint_sqlite_tests {
         .new_isolated(module_instance_id)
     }
 
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(prefix_upper_bound(&[0x01, 0x02]), Some(vec![0x01, 0x03]));
+        assert_eq!(prefix_upper_bound(&[0x01, 0xff]), Some(vec![0x02]));
+        assert_eq!(prefix_upper_bound(&[0xff, 0xff]), None);
+        assert_eq!(prefix_upper_bound(&[]), None);
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_dbtx_insert_elements() {
         fedimint_core::db::verify_insert_elements(open_temp_db("insert-elements").await).await;
@@ -278,6 +372,70 @@ mod fedimint_sqlite_tests {
         fedimint_core::db::verify_module_prefix(open_temp_db("verify-module-prefix").await).await;
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_migrate_primary_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let connection_string = format!("sqlite://{}/old.db", dir.path().display());
+        Sqlite::create_database(&connection_string).await.unwrap();
+        let pool = SqlitePool::connect(&connection_string).await.unwrap();
+        for statement in [
+            "CREATE TABLE kv (key BLOB, value BLOB);",
+            "CREATE INDEX key_index ON kv(key);",
+            "INSERT INTO kv (key, value) VALUES (x'0101', x'01');",
+            "INSERT INTO kv (key, value) VALUES (x'0102', x'02');",
+            "INSERT INTO kv (key, value) VALUES (x'0101', x'03');",
+        ] {
+            pool.execute(statement).await.unwrap();
+        }
+        pool.close().await;
+
+        let db = SqliteDb::open(&connection_string).await.unwrap();
+        let mut dbtx = db.begin_transaction().await;
+        let entries = dbtx
+            .raw_find_by_prefix(&[0x01])
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            entries,
+            vec![
+                (vec![0x01, 0x01], vec![0x03]),
+                (vec![0x01, 0x02], vec![0x02])
+            ]
+        );
+        dbtx.commit_tx().await.unwrap();
+
+        // Inserting an existing key replaces it now
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[0x01, 0x02], vec![0x04])
+            .await
+            .unwrap();
+        dbtx.commit_tx().await.unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM kv")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_checkpoint_on_current_thread_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = SqliteDb::open_path(dir.path().join("db.sqlite"))
+            .await
+            .unwrap();
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[0x01], vec![0x01]).await.unwrap();
+        dbtx.commit_tx().await.unwrap();
+
+        let checkpoint = dir.path().join("checkpoint.sqlite");
+        db.checkpoint(&checkpoint).unwrap();
+
+        let copy = SqliteDb::open_path(&checkpoint).await.unwrap();
+        let mut dbtx = copy.begin_transaction().await;
+        assert_eq!(dbtx.raw_get_bytes(&[0x01]).await.unwrap(), Some(vec![0x01]));
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_module_db() {
         fedimint_core::db::verify_module_db(
@@ -91,7 +91,7 @@ async fn fedimint_client() -> anyhow::Result<UserClient> {
         DynClientModuleGen::from(MintClientGen),
         DynClientModuleGen::from(LightningClientGen),
     ]);
-    Ok(Client::new(cfg.clone(), decoders, module_gens, db, Default::default()).await)
+    Ok(Client::new(cfg.clone(), decoders, module_gens, db, Default::default()).await?)
 }
 
 /// Save PID to a $FM_PID_FILE which `kill_fedimint_processes` shell script
@@ -110,6 +110,17 @@ impl FakeBitcoindRpcController {
             })
     }
 
+    /// Transactions submitted since they were last added to a block
+    pub async fn pending_transactions(&self) -> Vec<Transaction> {
+        self.state
+            .lock()
+            .unwrap()
+            .transactions
+            .iter()
+            .cloned()
+            .collect()
+    }
+
     pub async fn add_pending_tx_to_block(&self, block: u64) {
         let block_hash = height_hash(block);
         let mut state = self.state.lock().unwrap();
@@ -1,5 +1,5 @@
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use bitcoin::hashes::{sha256, Hash};
@@ -9,11 +9,13 @@ use fedimint_core::Amount;
 use futures::stream;
 use lightning::ln::PaymentSecret;
 use lightning_invoice::{Currency, Invoice, InvoiceBuilder, SignedRawInvoice, DEFAULT_EXPIRY_TIME};
+use ln_gateway::gatewaylnrpc::list_payments_response::{Payment, PaymentStatus};
 use ln_gateway::gatewaylnrpc::{
-    self, CompleteHtlcsRequest, CompleteHtlcsResponse, GetNodeInfoResponse, GetRouteHintsResponse,
-    PayInvoiceRequest, PayInvoiceResponse, SubscribeInterceptHtlcsRequest,
+    self, CompleteHtlcsRequest, CompleteHtlcsResponse, GetLiquidityResponse, GetNodeInfoResponse,
+    GetRouteHintsResponse, ListPaymentsRequest, ListPaymentsResponse, PayInvoiceRequest,
+    PayInvoiceResponse, SendOnionMessageRequest, SubscribeInterceptHtlcsRequest,
 };
-use ln_gateway::lnrpc_client::{HtlcStream, ILnRpcClient};
+use ln_gateway::lnrpc_client::{ChannelUpdateStream, HtlcStream, ILnRpcClient, OnionMessageStream};
 use ln_gateway::GatewayError;
 use mint_client::modules::ln::contracts::Preimage;
 use rand::rngs::OsRng;
@@ -26,6 +28,7 @@ pub struct FakeLightningTest {
     pub gateway_node_pub_key: secp256k1::PublicKey,
     gateway_node_sec_key: secp256k1::SecretKey,
     amount_sent: Arc<Mutex<u64>>,
+    payments: Arc<Mutex<Vec<Payment>>>,
     is_connected: bool,
 }
 
@@ -40,6 +43,7 @@ impl FakeLightningTest {
             gateway_node_sec_key: SecretKey::from_keypair(&kp),
             gateway_node_pub_key: PublicKey::from_keypair(&kp),
             amount_sent,
+            payments: Arc::new(Mutex::new(vec![])),
             is_connected: true,
         }
     }
@@ -112,7 +116,10 @@ impl ILnRpcClient for FakeLightningTest {
         }
 
         Ok(GetRouteHintsResponse {
-            route_hints: vec![gatewaylnrpc::get_route_hints_response::RouteHint { hops: vec![] }],
+            route_hints: vec![gatewaylnrpc::get_route_hints_response::RouteHint {
+                hops: vec![],
+                ..Default::default()
+            }],
         })
     }
 
@@ -124,13 +131,22 @@ impl ILnRpcClient for FakeLightningTest {
         }
 
         let signed = invoice.invoice.parse::<SignedRawInvoice>().unwrap();
-        *self.amount_sent.lock().unwrap() += Invoice::from_signed(signed)
-            .unwrap()
-            .amount_milli_satoshis()
-            .unwrap();
+        let invoice = Invoice::from_signed(signed).unwrap();
+        *self.amount_sent.lock().unwrap() += invoice.amount_milli_satoshis().unwrap();
+        self.payments.lock().unwrap().push(Payment {
+            payment_hash: invoice.payment_hash().to_vec(),
+            status: PaymentStatus::Succeeded.into(),
+            preimage: self.preimage.0.to_vec(),
+            created_at: fedimint_core::time::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            fee_msat: 0,
+        });
 
         Ok(PayInvoiceResponse {
             preimage: self.preimage.0.to_vec(),
+            fee_msat: 0,
         })
     }
 
@@ -160,6 +176,68 @@ impl ILnRpcClient for FakeLightningTest {
         Ok(CompleteHtlcsResponse {})
     }
 
+    async fn list_payments(
+        &self,
+        request: ListPaymentsRequest,
+    ) -> ln_gateway::Result<ListPaymentsResponse> {
+        if !self.is_connected {
+            return Err(GatewayError::Other(anyhow::anyhow!(
+                "Error not connected to Lightning"
+            )));
+        }
+
+        // Payments of the fake node succeed right away
+        let payments = self
+            .payments
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|payment| payment.created_at >= request.created_after)
+            .cloned()
+            .collect();
+        Ok(ListPaymentsResponse { payments })
+    }
+
+    async fn liquidity(&self) -> ln_gateway::Result<GetLiquidityResponse> {
+        if !self.is_connected {
+            return Err(GatewayError::Other(anyhow::anyhow!(
+                "Error not connected to Lightning"
+            )));
+        }
+
+        // The fake node can route payments of any size
+        Ok(GetLiquidityResponse {
+            outbound_msat: u64::MAX,
+            inbound_msat: u64::MAX,
+        })
+    }
+
+    async fn subscribe_channel_updates<'a>(&self) -> ln_gateway::Result<ChannelUpdateStream<'a>> {
+        if !self.is_connected {
+            return Err(GatewayError::Other(anyhow::anyhow!(
+                "Error not connected to Lightning"
+            )));
+        }
+
+        // The channels of the fake node never change
+        Ok(Box::pin(stream::pending()))
+    }
+
+    async fn send_onion_message(
+        &self,
+        _message: SendOnionMessageRequest,
+    ) -> ln_gateway::Result<()> {
+        Err(GatewayError::Other(anyhow::anyhow!(
+            "The fake node doesn't support onion messages"
+        )))
+    }
+
+    async fn subscribe_onion_messages<'a>(&self) -> ln_gateway::Result<OnionMessageStream<'a>> {
+        Err(GatewayError::Other(anyhow::anyhow!(
+            "The fake node doesn't support onion messages"
+        )))
+    }
+
     async fn connect(&mut self) -> ln_gateway::Result<()> {
         self.is_connected = true;
         Ok(())
@@ -0,0 +1,488 @@
+//! Scriptable lightning node for testing the gateway without daemons
+//!
+//! [`MockLightningNode`] is handed to the gateway as its `ILnRpcClient`, while
+//! tests drive it through a [`MockLightningController`]: they inject HTLCs
+//! into the gateway's subscriptions, make payments fail a given number of
+//! times and inspect how the gateway completed the HTLCs.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{self, KeyPair, PublicKey, SecretKey};
+use fedimint_core::Amount;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use lightning::ln::PaymentSecret;
+use lightning_invoice::{Currency, Invoice, InvoiceBuilder, DEFAULT_EXPIRY_TIME};
+use ln_gateway::gatewaylnrpc::list_payments_response::{Payment, PaymentStatus};
+use ln_gateway::gatewaylnrpc::{
+    self, ChannelUpdate, CompleteHtlcsRequest, CompleteHtlcsResponse, GetLiquidityResponse,
+    GetNodeInfoResponse, GetRouteHintsResponse, ListPaymentsRequest, ListPaymentsResponse,
+    OnionMessage, PayInvoiceRequest, PayInvoiceResponse, SendOnionMessageRequest,
+    SubscribeInterceptHtlcsRequest, SubscribeInterceptHtlcsResponse,
+};
+use ln_gateway::lnrpc_client::{ChannelUpdateStream, HtlcStream, ILnRpcClient, OnionMessageStream};
+use ln_gateway::GatewayError;
+use mint_client::modules::ln::contracts::Preimage;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use super::LightningTest;
+
+/// Expiry of injected HTLCs, as block height
+const HTLC_EXPIRY: u32 = 1_000;
+
+#[derive(Debug, Default)]
+struct MockLightningState {
+    connected: bool,
+    /// Senders of the gateway's HTLC subscriptions by short channel id
+    subscriptions: BTreeMap<u64, mpsc::UnboundedSender<SubscribeInterceptHtlcsResponse>>,
+    /// HTLCs injected before the gateway subscribed to their short channel id
+    queued_htlcs: BTreeMap<u64, Vec<SubscribeInterceptHtlcsResponse>>,
+    /// Completions the gateway sent, in order
+    completions: Vec<CompleteHtlcsRequest>,
+    /// Remaining failures of completing HTLCs
+    completion_failures: u32,
+    /// Preimages of the invoices the node's payees created
+    preimages: BTreeMap<sha256::Hash, Preimage>,
+    /// Remaining failures of payments to an invoice
+    payment_failures: BTreeMap<String, u32>,
+    /// Fail every payment with this reason while set
+    fail_all_payments: Option<String>,
+    payments: Vec<Payment>,
+    amount_sent: Amount,
+    /// Fee charged for routing every successful payment
+    routing_fee: Amount,
+    /// Liquidity the node reports, unlimited if not set
+    liquidity: Option<GetLiquidityResponse>,
+    /// Senders of the gateway's channel update subscriptions
+    channel_subscriptions: Vec<mpsc::UnboundedSender<ChannelUpdate>>,
+    /// Senders of the gateway's onion message subscriptions
+    onion_subscriptions: Vec<mpsc::UnboundedSender<OnionMessage>>,
+    /// Onion messages the gateway sent, in order
+    sent_onion_messages: Vec<SendOnionMessageRequest>,
+}
+
+/// A lightning node whose behaviour tests script through its
+/// [`MockLightningController`]
+///
+/// The node also acts as the payee of the invoices it creates through
+/// [`LightningTest::invoice`], paying any other invoice fails.
+#[derive(Debug, Clone)]
+pub struct MockLightningNode {
+    pub node_pub_key: secp256k1::PublicKey,
+    node_sec_key: secp256k1::SecretKey,
+    state: Arc<Mutex<MockLightningState>>,
+}
+
+impl MockLightningNode {
+    pub fn new() -> Self {
+        let ctx = secp256k1::Secp256k1::new();
+        let kp = KeyPair::new(&ctx, &mut OsRng);
+
+        MockLightningNode {
+            node_pub_key: PublicKey::from_keypair(&kp),
+            node_sec_key: SecretKey::from_keypair(&kp),
+            state: Arc::new(Mutex::new(MockLightningState {
+                connected: true,
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn controller(&self) -> MockLightningController {
+        MockLightningController {
+            state: self.state.clone(),
+        }
+    }
+
+    fn ensure_connected(&self) -> ln_gateway::Result<()> {
+        if !self.state.lock().unwrap().connected {
+            return Err(GatewayError::Other(anyhow::anyhow!(
+                "Error not connected to Lightning"
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for MockLightningNode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scripts the behaviour of a [`MockLightningNode`] and inspects what the
+/// gateway did with it
+#[derive(Debug, Clone)]
+pub struct MockLightningController {
+    state: Arc<Mutex<MockLightningState>>,
+}
+
+impl MockLightningController {
+    /// Sends `htlc` to the gateway's subscription of its short channel id,
+    /// the HTLC is held back until the gateway subscribes if it didn't yet
+    pub fn inject_htlc(&self, htlc: SubscribeInterceptHtlcsResponse) {
+        let mut state = self.state.lock().unwrap();
+        let scid = htlc.short_channel_id;
+        let htlc = match state.subscriptions.get(&scid) {
+            Some(sender) => match sender.unbounded_send(htlc) {
+                Ok(()) => return,
+                // the gateway dropped its subscription
+                Err(e) => e.into_inner(),
+            },
+            None => htlc,
+        };
+        state.subscriptions.remove(&scid);
+        state.queued_htlcs.entry(scid).or_default().push(htlc);
+    }
+
+    /// Lets the next `times` payments to `invoice` fail before paying it
+    /// succeeds
+    pub fn fail_payment(&self, invoice: &Invoice, times: u32) {
+        self.state
+            .lock()
+            .unwrap()
+            .payment_failures
+            .insert(invoice.to_string(), times);
+    }
+
+    /// Charges `fee` for routing every following successful payment
+    pub fn set_routing_fee(&self, fee: Amount) {
+        self.state.lock().unwrap().routing_fee = fee;
+    }
+
+    /// Fails every payment with `reason` until called with `None`
+    pub fn fail_all_payments(&self, reason: Option<String>) {
+        self.state.lock().unwrap().fail_all_payments = reason;
+    }
+
+    /// Lets the next `times` completions of intercepted HTLCs fail
+    pub fn fail_completions(&self, times: u32) {
+        self.state.lock().unwrap().completion_failures = times;
+    }
+
+    /// Lets the node report that it can send `outbound` and receive `inbound`
+    pub fn set_liquidity(&self, outbound: Amount, inbound: Amount) {
+        self.state.lock().unwrap().liquidity = Some(GetLiquidityResponse {
+            outbound_msat: outbound.msats,
+            inbound_msat: inbound.msats,
+        });
+    }
+
+    /// Reports a change to the channel with `short_channel_id` to the
+    /// gateway's channel update subscriptions, e.g. after changing the
+    /// liquidity to simulate a splice
+    pub fn update_channel(&self, short_channel_id: Option<u64>) {
+        self.state
+            .lock()
+            .unwrap()
+            .channel_subscriptions
+            .retain(|sender| {
+                sender
+                    .unbounded_send(ChannelUpdate { short_channel_id })
+                    .is_ok()
+            });
+    }
+
+    /// Delivers `message` to the gateway's onion message subscriptions, as if
+    /// a peer sent it to the node
+    pub fn receive_onion_message(&self, message: OnionMessage) {
+        self.state
+            .lock()
+            .unwrap()
+            .onion_subscriptions
+            .retain(|sender| sender.unbounded_send(message.clone()).is_ok());
+    }
+
+    /// Onion messages the gateway sent through the node, in order
+    pub fn sent_onion_messages(&self) -> Vec<SendOnionMessageRequest> {
+        self.state.lock().unwrap().sent_onion_messages.clone()
+    }
+
+    /// Connects or disconnects the node, requests to a disconnected node fail
+    pub fn set_connected(&self, connected: bool) {
+        self.state.lock().unwrap().connected = connected;
+    }
+
+    /// Completions of intercepted HTLCs the gateway sent, in order
+    pub fn completions(&self) -> Vec<CompleteHtlcsRequest> {
+        self.state.lock().unwrap().completions.clone()
+    }
+
+    /// Payments the gateway made, including the failed ones
+    pub fn payments(&self) -> Vec<Payment> {
+        self.state.lock().unwrap().payments.clone()
+    }
+
+    /// Whether the gateway subscribed to HTLCs of `short_channel_id`
+    pub fn is_subscribed(&self, short_channel_id: u64) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .subscriptions
+            .get(&short_channel_id)
+            .map_or(false, |sender| !sender.is_closed())
+    }
+}
+
+/// An HTLC of `amount` paying to `payment_hash` over `short_channel_id`, as
+/// the node would intercept it
+pub fn intercepted_htlc(
+    short_channel_id: u64,
+    payment_hash: sha256::Hash,
+    amount: Amount,
+) -> SubscribeInterceptHtlcsResponse {
+    let mut intercepted_htlc_id = [0u8; 32];
+    OsRng.fill_bytes(&mut intercepted_htlc_id);
+
+    SubscribeInterceptHtlcsResponse {
+        payment_hash: payment_hash.into_inner().to_vec(),
+        incoming_amount_msat: amount.msats,
+        outgoing_amount_msat: amount.msats,
+        incoming_expiry: HTLC_EXPIRY,
+        short_channel_id,
+        intercepted_htlc_id: intercepted_htlc_id.to_vec(),
+    }
+}
+
+fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time to be after the unix epoch")
+        .as_secs()
+}
+
+#[async_trait]
+impl LightningTest for MockLightningNode {
+    async fn invoice(
+        &self,
+        amount: Amount,
+        expiry_time: Option<u64>,
+    ) -> ln_gateway::Result<Invoice> {
+        self.ensure_connected()?;
+
+        let mut preimage = [0u8; 32];
+        OsRng.fill_bytes(&mut preimage);
+        let payment_hash = sha256::Hash::hash(&preimage);
+        self.state
+            .lock()
+            .unwrap()
+            .preimages
+            .insert(payment_hash, Preimage(preimage));
+
+        let ctx = secp256k1::Secp256k1::new();
+        Ok(InvoiceBuilder::new(Currency::Regtest)
+            .description("".to_string())
+            .payment_hash(payment_hash)
+            .current_timestamp()
+            .min_final_cltv_expiry(0)
+            .payment_secret(PaymentSecret([0; 32]))
+            .amount_milli_satoshis(amount.msats)
+            .expiry_time(Duration::from_secs(
+                expiry_time.unwrap_or(DEFAULT_EXPIRY_TIME),
+            ))
+            .build_signed(|m| ctx.sign_ecdsa_recoverable(m, &self.node_sec_key))
+            .unwrap())
+    }
+
+    async fn amount_sent(&self) -> Amount {
+        self.state.lock().unwrap().amount_sent
+    }
+
+    fn is_shared(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl ILnRpcClient for MockLightningNode {
+    async fn info(&self) -> ln_gateway::Result<GetNodeInfoResponse> {
+        self.ensure_connected()?;
+
+        Ok(GetNodeInfoResponse {
+            pub_key: self.node_pub_key.serialize().to_vec(),
+            alias: "MockLightningNode".to_string(),
+        })
+    }
+
+    async fn routehints(&self) -> ln_gateway::Result<GetRouteHintsResponse> {
+        self.ensure_connected()?;
+
+        Ok(GetRouteHintsResponse {
+            route_hints: vec![gatewaylnrpc::get_route_hints_response::RouteHint {
+                hops: vec![],
+                ..Default::default()
+            }],
+        })
+    }
+
+    async fn pay(&self, request: PayInvoiceRequest) -> ln_gateway::Result<PayInvoiceResponse> {
+        self.ensure_connected()?;
+
+        let invoice: Invoice = request
+            .invoice
+            .parse()
+            .map_err(|e| GatewayError::Other(anyhow::anyhow!("Invalid invoice: {e:?}")))?;
+        let payment_hash = *invoice.payment_hash();
+
+        let mut state = self.state.lock().unwrap();
+        let failure = match state.payment_failures.get_mut(&request.invoice) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                Some("Scripted payment failure".to_string())
+            }
+            _ => state.fail_all_payments.clone(),
+        };
+        let preimage = match failure {
+            Some(reason) => Err(reason),
+            None => state
+                .preimages
+                .get(&payment_hash)
+                .cloned()
+                .ok_or_else(|| "No route to payee".to_string()),
+        };
+
+        let created_at = unix_now();
+        match preimage {
+            Ok(preimage) => {
+                let routing_fee = state.routing_fee;
+                state.amount_sent += Amount::from_msats(
+                    invoice
+                        .amount_milli_satoshis()
+                        .expect("gateway only pays invoices with amount"),
+                ) + routing_fee;
+                state.payments.push(Payment {
+                    payment_hash: payment_hash.into_inner().to_vec(),
+                    status: PaymentStatus::Succeeded.into(),
+                    preimage: preimage.0.to_vec(),
+                    created_at,
+                    fee_msat: routing_fee.msats,
+                });
+                Ok(PayInvoiceResponse {
+                    preimage: preimage.0.to_vec(),
+                    fee_msat: routing_fee.msats,
+                })
+            }
+            Err(reason) => {
+                state.payments.push(Payment {
+                    payment_hash: payment_hash.into_inner().to_vec(),
+                    status: PaymentStatus::Failed.into(),
+                    preimage: vec![],
+                    created_at,
+                    fee_msat: 0,
+                });
+                Err(GatewayError::Other(anyhow::anyhow!(reason)))
+            }
+        }
+    }
+
+    async fn subscribe_htlcs<'a>(
+        &self,
+        subscription: SubscribeInterceptHtlcsRequest,
+    ) -> ln_gateway::Result<HtlcStream<'a>> {
+        self.ensure_connected()?;
+
+        let scid = subscription.short_channel_id;
+        let (sender, receiver) = mpsc::unbounded();
+        let mut state = self.state.lock().unwrap();
+        for htlc in state.queued_htlcs.remove(&scid).unwrap_or_default() {
+            sender
+                .unbounded_send(htlc)
+                .expect("receiver is not dropped yet");
+        }
+        state.subscriptions.insert(scid, sender);
+
+        Ok(Box::pin(receiver.map(Ok)))
+    }
+
+    async fn complete_htlc(
+        &self,
+        complete: CompleteHtlcsRequest,
+    ) -> ln_gateway::Result<CompleteHtlcsResponse> {
+        self.ensure_connected()?;
+
+        let mut state = self.state.lock().unwrap();
+        if state.completion_failures > 0 {
+            state.completion_failures -= 1;
+            return Err(GatewayError::Other(anyhow::anyhow!(
+                "Error completing the HTLC"
+            )));
+        }
+        state.completions.push(complete);
+        Ok(CompleteHtlcsResponse {})
+    }
+
+    async fn list_payments(
+        &self,
+        request: ListPaymentsRequest,
+    ) -> ln_gateway::Result<ListPaymentsResponse> {
+        self.ensure_connected()?;
+
+        let payments = self
+            .state
+            .lock()
+            .unwrap()
+            .payments
+            .iter()
+            .filter(|payment| payment.created_at >= request.created_after)
+            .cloned()
+            .collect();
+        Ok(ListPaymentsResponse { payments })
+    }
+
+    async fn liquidity(&self) -> ln_gateway::Result<GetLiquidityResponse> {
+        self.ensure_connected()?;
+
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .liquidity
+            .clone()
+            .unwrap_or(GetLiquidityResponse {
+                outbound_msat: u64::MAX,
+                inbound_msat: u64::MAX,
+            }))
+    }
+
+    async fn subscribe_channel_updates<'a>(&self) -> ln_gateway::Result<ChannelUpdateStream<'a>> {
+        self.ensure_connected()?;
+
+        let (sender, receiver) = mpsc::unbounded();
+        self.state
+            .lock()
+            .unwrap()
+            .channel_subscriptions
+            .push(sender);
+        Ok(Box::pin(receiver.map(Ok)))
+    }
+
+    async fn send_onion_message(&self, message: SendOnionMessageRequest) -> ln_gateway::Result<()> {
+        self.ensure_connected()?;
+
+        self.state.lock().unwrap().sent_onion_messages.push(message);
+        Ok(())
+    }
+
+    async fn subscribe_onion_messages<'a>(&self) -> ln_gateway::Result<OnionMessageStream<'a>> {
+        self.ensure_connected()?;
+
+        let (sender, receiver) = mpsc::unbounded();
+        self.state.lock().unwrap().onion_subscriptions.push(sender);
+        Ok(Box::pin(receiver.map(Ok)))
+    }
+
+    async fn connect(&mut self) -> ln_gateway::Result<()> {
+        self.state.lock().unwrap().connected = true;
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> ln_gateway::Result<()> {
+        self.state.lock().unwrap().connected = false;
+        Ok(())
+    }
+}
@@ -3,6 +3,7 @@ use fedimint_core::Amount;
 use lightning_invoice::Invoice;
 
 pub mod fixtures;
+pub mod mock;
 
 #[async_trait]
 pub trait LightningTest {
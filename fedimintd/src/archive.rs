@@ -0,0 +1,82 @@
+//! Runs a third party archive of a federation's epoch history with
+//! `fedimintd archive`
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::Parser;
+use fedimint_core::api::{DynFederationApi, WsFederationApi};
+use fedimint_core::config::{ClientConfig, ServerModuleGenRegistry};
+use fedimint_core::task::TaskGroup;
+use fedimint_logging::LogFormat;
+use fedimint_server::archive::{run_epoch_archive, EpochArchive};
+use fedimint_server::config::io::DB_FILE;
+use fedimint_server::config::{ApiLimits, DatabaseBackend, DEFAULT_MAX_CLIENT_CONNECTIONS};
+use tracing::info;
+
+use crate::fedimintd::open_database;
+
+/// Options of `fedimintd archive`
+#[derive(Parser)]
+#[command(name = "fedimintd archive")]
+pub struct ArchiveOpts {
+    /// Directory the archived epochs are kept in
+    #[arg(long = "data-dir", env = "FM_DATA_DIR")]
+    pub data_dir: PathBuf,
+    /// Client config of the federation whose epochs are archived
+    #[arg(long = "client-config", env = "FM_CLIENT_CONFIG")]
+    pub client_config: PathBuf,
+    /// Address the archive is served on
+    #[arg(long = "bind-api", env = "FM_BIND_API")]
+    pub bind_api: SocketAddr,
+    /// First epoch to archive if the archive is empty, the guardians or their
+    /// archives have to still keep it
+    #[arg(long = "first-epoch", default_value = "0")]
+    pub first_epoch: u64,
+    /// Seconds to wait between asking the federation for new epochs
+    #[arg(long = "sync-interval-secs", default_value = "10")]
+    pub sync_interval_secs: u64,
+    /// Format of the logs, `text` or `json`
+    #[arg(long = "log-format", env = "FM_LOG_FORMAT", default_value = "text")]
+    pub log_format: LogFormat,
+}
+
+/// Archives the federation's epochs and serves them until shutdown
+pub async fn run_archive(
+    opts: ArchiveOpts,
+    mut task_group: TaskGroup,
+    module_gens: ServerModuleGenRegistry,
+) -> anyhow::Result<()> {
+    let client_config: ClientConfig = serde_json::from_slice(
+        &fs::read(&opts.client_config).context("Could not read the client config")?,
+    )
+    .context("Could not parse the client config")?;
+    let decoders = module_gens.decoders(client_config.iter_module_instances())?;
+
+    fs::create_dir_all(&opts.data_dir)?;
+    let db = open_database(
+        DatabaseBackend::default(),
+        opts.data_dir.join(DB_FILE),
+        &decoders,
+    )
+    .await?;
+    let api: DynFederationApi = WsFederationApi::from_config(&client_config).into();
+    let archive = EpochArchive::new(db, api, client_config.epoch_pk, decoders);
+
+    info!(
+        federation_id = %client_config.federation_id,
+        "Archiving the epoch history"
+    );
+    run_epoch_archive(
+        archive,
+        opts.bind_api,
+        DEFAULT_MAX_CLIENT_CONNECTIONS,
+        &ApiLimits::default(),
+        opts.first_epoch,
+        Duration::from_secs(opts.sync_interval_secs),
+        &mut task_group,
+    )
+    .await
+}
@@ -1,20 +1,38 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{Read, Write};
 use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
 
+use anyhow::bail;
+use bitcoin::hashes::hex::ToHex;
 use clap::{Parser, Subcommand};
 use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key};
-use fedimint_core::config::{DkgError, ServerModuleGenParamsRegistry, ServerModuleGenRegistry};
+use fedimint_core::admin_client::ConfigGenParamsRequest;
+use fedimint_core::config::{
+    DkgError, ModuleGenParams, ServerModuleGenParamsRegistry, ServerModuleGenRegistry,
+    META_FEDERATION_NAME_KEY,
+};
+use fedimint_core::core::ModuleKind;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::ServerModuleGen;
 use fedimint_core::task::{self, TaskGroup};
 use fedimint_core::Amount;
 use fedimint_ln_server::LightningGen;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_server::MintGen;
-use fedimint_server::config::io::{create_cert, write_server_config, CODE_VERSION, SALT_FILE};
+use fedimint_server::config::api::{run_server, ConfigGenConnections};
+use fedimint_server::config::io::{
+    create_cert, read_consensus_config, read_server_config_with_key, reencrypt_private_config,
+    write_private_config, write_server_config, write_tor_config, CODE_VERSION, SALT_FILE,
+};
+use fedimint_server::config::keys::ConfigKeySource;
 use fedimint_server::config::{ServerConfig, ServerConfigParams};
 use fedimint_server::net::peers::DelayCalculator;
+use fedimint_server::net::tor::{generate_onion_service_key, OnionServiceConfig, TorConfig};
 use fedimint_wallet_server::WalletGen;
 use tracing::info;
 use url::Url;
@@ -43,13 +61,30 @@ enum Command {
         api_url: Url,
 
         /// Our external address for communicating with our peers
-        #[arg(long = "p2p-url")]
-        p2p_url: Url,
+        #[arg(long = "p2p-url", required_unless_present = "tor_control")]
+        p2p_url: Option<Url>,
 
         /// Our node name, must be unique among peers
         #[arg(long = "name")]
         name: String,
 
+        /// SOCKS5 proxy of a Tor daemon we connect to our peers through
+        #[arg(long = "tor-socks-proxy")]
+        tor_socks_proxy: Option<SocketAddr>,
+
+        /// Control port of the Tor daemon, creates an onion service whose
+        /// address becomes our P2P url
+        #[arg(long = "tor-control", requires = "tor_socks_proxy")]
+        tor_control: Option<SocketAddr>,
+
+        /// Password of the Tor control port
+        #[arg(long = "tor-control-password", env = "FM_TOR_CONTROL_PASSWORD")]
+        tor_control_password: Option<String>,
+
+        /// Port of the onion service our peers connect to
+        #[arg(long = "onion-port", default_value = "8173")]
+        onion_port: u16,
+
         /// The password that encrypts the configs
         #[arg(env = "FM_PASSWORD")]
         password: String,
@@ -96,6 +131,91 @@ enum Command {
         #[arg(env = "FM_PASSWORD")]
         password: String,
     },
+    /// Serves the config gen API, guardians join the leader with a one-time
+    /// token created by the leader and run DKG through the API. The configs
+    /// are written once all guardians verified the config hashes.
+    ServeConfigGen {
+        /// Directory to output all the generated config files
+        #[arg(long = "out-dir", env = "FM_DATA_DIR")]
+        dir_out_path: PathBuf,
+
+        /// Address we bind to for federation communication
+        #[arg(long = "bind-p2p", default_value = "127.0.0.1:8173")]
+        bind_p2p: SocketAddr,
+
+        /// Address we bind to for exposing the API
+        #[arg(long = "bind-api", default_value = "127.0.0.1:8174")]
+        bind_api: SocketAddr,
+
+        /// Our API address for clients to connect to us
+        #[arg(long = "api-url")]
+        api_url: Url,
+
+        /// Our external address for communicating with our peers
+        #[arg(long = "p2p-url")]
+        p2p_url: Url,
+
+        /// Default federation name the leader can change
+        #[arg(long = "federation-name", default_value = "Hals_trusty_mint")]
+        federation_name: String,
+
+        /// Default max denomination of notes issued by the federation (in
+        /// millisats) the leader can change
+        #[arg(long = "max_denomination", default_value = "100000000000")]
+        max_denomination: Amount,
+
+        /// Default bitcoin network the leader can change
+        #[arg(long = "network", default_value = "regtest")]
+        network: bitcoin::network::constants::Network,
+
+        /// Default number of confirmations a deposit transaction requires
+        /// the leader can change
+        #[arg(long = "finalty", default_value = "10")]
+        finality_delay: u32,
+    },
+    /// After a threshold of guardians approved a change of the guardians all
+    /// new peers must run the resharing at the same time to create configs
+    /// that keep the keys of the federation
+    Reshare {
+        /// Directory to output all the generated config files
+        #[arg(long = "out-dir")]
+        dir_out_path: PathBuf,
+
+        /// Directory containing the configs of the federation before the
+        /// change, for new guardians only the consensus config is required
+        #[arg(long = "old-dir")]
+        old_dir_path: PathBuf,
+
+        /// Whether we were a guardian of the federation before the change,
+        /// the old configs are decrypted with the same password
+        #[arg(long = "old-guardian", default_value = "false")]
+        old_guardian: bool,
+
+        /// Source of the key the old private config is encrypted with
+        #[arg(
+            long = "old-config-key",
+            env = "FM_CONFIG_KEY_SOURCE",
+            default_value = "password"
+        )]
+        old_config_key: ConfigKeySource,
+
+        /// Address we bind to for federation communication
+        #[arg(long = "bind-p2p", default_value = "127.0.0.1:8173")]
+        bind_p2p: SocketAddr,
+
+        /// Address we bind to for exposing the API
+        #[arg(long = "bind-api", default_value = "127.0.0.1:8174")]
+        bind_api: SocketAddr,
+
+        /// Comma-separated list of connection certs from all new peers
+        /// (including ours)
+        #[arg(long = "certs", value_delimiter = ',')]
+        certs: Vec<String>,
+
+        /// The password that encrypts the configs
+        #[arg(env = "FM_PASSWORD")]
+        password: String,
+    },
 
     ConfigDecrypt {
         /// Encrypted config file
@@ -113,6 +233,46 @@ enum Command {
         password: String,
     },
 
+    /// Encrypts the private config with a key from another source, e.g. to
+    /// move from a password to a key managed by a KMS or HSM
+    ConfigRekey {
+        /// Directory containing the config files
+        #[arg(long = "dir", env = "FM_DATA_DIR")]
+        dir: PathBuf,
+        /// Source of the key the private config is encrypted with now
+        #[arg(long = "from", default_value = "password")]
+        from: ConfigKeySource,
+        /// Source of the key the private config will be encrypted with
+        #[arg(long = "to")]
+        to: ConfigKeySource,
+        /// The password that encrypts the configs, if either source is
+        /// `password`
+        #[arg(env = "FM_PASSWORD", default_value = "")]
+        password: String,
+    },
+
+    /// Moves our epoch key share out of the private config into a file it
+    /// can be loaded into the helper set with `--epoch-signer-command` from
+    ExportEpochKey {
+        /// Directory containing the config files
+        #[arg(long = "dir", env = "FM_DATA_DIR")]
+        dir: PathBuf,
+        /// File the hex encoded key share is written to, it must not exist yet
+        #[arg(long = "out-file")]
+        out_file: PathBuf,
+        /// Source of the key the private config is encrypted with
+        #[arg(
+            long = "config-key",
+            env = "FM_CONFIG_KEY_SOURCE",
+            default_value = "password"
+        )]
+        config_key: ConfigKeySource,
+        /// The password that encrypts the configs, if the config key source
+        /// is `password`
+        #[arg(env = "FM_PASSWORD", default_value = "")]
+        password: String,
+    },
+
     ConfigEncrypt {
         /// Plaintext config file
         #[arg(long = "in-file")]
@@ -135,6 +295,7 @@ enum Command {
 /// See [`super::fedimintd::Fedimintd`] for more info.
 pub struct DistributedGen {
     module_gens: ServerModuleGenRegistry,
+    module_gens_params: ServerModuleGenParamsRegistry,
     opts: Cli,
 }
 
@@ -147,6 +308,7 @@ impl DistributedGen {
 
         Ok(Self {
             module_gens: ServerModuleGenRegistry::new(),
+            module_gens_params: ServerModuleGenParamsRegistry::new(),
             opts,
         })
     }
@@ -159,6 +321,15 @@ impl DistributedGen {
         self
     }
 
+    pub fn with_extra_module_gens_params<P>(mut self, kind: ModuleKind, params: P) -> Self
+    where
+        P: ModuleGenParams,
+    {
+        self.module_gens_params
+            .attach_config_gen_params(kind, params);
+        self
+    }
+
     pub fn with_default_modules(self) -> Self {
         self.with_module(LightningGen)
             .with_module(MintGen)
@@ -174,9 +345,46 @@ impl DistributedGen {
                 p2p_url,
                 api_url,
                 name,
+                tor_socks_proxy,
+                tor_control,
+                tor_control_password,
+                onion_port,
                 password,
             } => {
-                let config_str = create_cert(dir_out_path, p2p_url, api_url, name, &password)?;
+                let onion_service = match tor_control {
+                    Some(control_addr) => {
+                        let onion_service = OnionServiceConfig {
+                            control_addr,
+                            control_password: tor_control_password,
+                            port: onion_port,
+                        };
+                        let (key, onion_address) =
+                            generate_onion_service_key(&onion_service).await?;
+                        info!("Our peers will reach us at the onion service {onion_address}");
+                        Some((onion_service, key, onion_address))
+                    }
+                    None => None,
+                };
+                let p2p_url = match &onion_service {
+                    Some((_, _, onion_address)) => {
+                        format!("ws://{onion_address}:{onion_port}").parse()?
+                    }
+                    None => p2p_url.ok_or_else(|| {
+                        anyhow::format_err!("Either --p2p-url or --tor-control is required")
+                    })?,
+                };
+
+                let config_str =
+                    create_cert(dir_out_path.clone(), p2p_url, api_url, name, &password)?;
+                if let Some(socks_proxy) = tor_socks_proxy {
+                    let tor = TorConfig {
+                        socks_proxy,
+                        proxy_clearnet: false,
+                        onion_service: onion_service.as_ref().map(|(config, ..)| config.clone()),
+                    };
+                    let key = onion_service.as_ref().map(|(_, key, _)| key.as_str());
+                    write_tor_config(&dir_out_path, &tor, key, &password)?;
+                }
                 Ok(println!("{config_str}"))
             }
             Command::Run {
@@ -190,7 +398,7 @@ impl DistributedGen {
                 finality_delay,
                 password,
             } => {
-                let mut module_gens_params = ServerModuleGenParamsRegistry::default();
+                let mut module_gens_params = self.module_gens_params.clone();
                 attach_default_module_gen_params(
                     &mut module_gens_params,
                     max_denomination,
@@ -221,6 +429,104 @@ impl DistributedGen {
 
                 write_server_config(&server, dir_out_path, &password, &self.module_gens)
             }
+            Command::ServeConfigGen {
+                dir_out_path,
+                bind_p2p,
+                bind_api,
+                api_url,
+                p2p_url,
+                federation_name,
+                max_denomination,
+                network,
+                finality_delay,
+            } => {
+                let mut module_gens_params = self.module_gens_params.clone();
+                attach_default_module_gen_params(
+                    &mut module_gens_params,
+                    max_denomination,
+                    network,
+                    finality_delay,
+                );
+                let default_params = ConfigGenParamsRequest {
+                    meta: BTreeMap::from([(META_FEDERATION_NAME_KEY.to_owned(), federation_name)]),
+                    modules: module_gens_params,
+                };
+                let connections = ConfigGenConnections {
+                    p2p_bind: bind_p2p,
+                    api_bind: bind_api,
+                    p2p_url,
+                    api_url,
+                    extra_p2p_binds: vec![],
+                    extra_p2p_urls: vec![],
+                };
+                let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+
+                let (server, api) = run_server(
+                    dir_out_path.clone(),
+                    connections,
+                    db,
+                    default_params,
+                    self.module_gens.clone().legacy_init_modules(),
+                    self.module_gens.clone(),
+                )
+                .await;
+                info!("Serving the config gen API on {bind_api}");
+
+                api.await_configs_written().await;
+                server.stop()?;
+                Ok(info!(
+                    "Configs were verified and written to {dir_out_path:?}"
+                ))
+            }
+            Command::Reshare {
+                dir_out_path,
+                old_dir_path,
+                old_guardian,
+                old_config_key,
+                bind_p2p,
+                bind_api,
+                certs,
+                password,
+            } => {
+                let (old, dealer) = if old_guardian {
+                    let key = old_config_key.config_key(&password, &old_dir_path).await?;
+                    let old = read_server_config_with_key(&key, old_dir_path)?;
+                    (old.consensus.clone(), Some(old))
+                } else {
+                    (read_consensus_config(old_dir_path)?, None)
+                };
+
+                // the module keys are reshared from the old configs, so the
+                // params of the modules don't matter
+                let params = ServerConfigParams::parse_from_connect_strings(
+                    bind_p2p,
+                    bind_api,
+                    &dir_out_path,
+                    old.meta
+                        .get(META_FEDERATION_NAME_KEY)
+                        .cloned()
+                        .unwrap_or_default(),
+                    certs,
+                    &password,
+                    ServerModuleGenParamsRegistry::default(),
+                )?;
+                let server = match ServerConfig::distributed_reshare(
+                    &params,
+                    &old,
+                    dealer.as_ref(),
+                    self.module_gens.clone().legacy_init_modules(),
+                    DelayCalculator::default(),
+                    &mut task_group,
+                )
+                .await
+                {
+                    Ok(server) => server,
+                    Err(DkgError::Cancelled(_)) => return Ok(info!("Resharing cancelled")),
+                    Err(DkgError::Failed(err)) => return Err(err),
+                };
+
+                write_server_config(&server, dir_out_path, &password, &self.module_gens)
+            }
             Command::VersionHash => Ok(println!("{CODE_VERSION}")),
             Command::ConfigDecrypt {
                 in_file,
@@ -238,6 +544,37 @@ impl DistributedGen {
                 out_file_handle.write_all(&decrypted_bytes)?;
                 Ok(())
             }
+            Command::ConfigRekey {
+                dir,
+                from,
+                to,
+                password,
+            } => {
+                let old_key = from.config_key(&password, &dir).await?;
+                let new_key = to.new_config_key(&password, &dir).await?;
+                reencrypt_private_config(&old_key, &new_key, dir)
+            }
+            Command::ExportEpochKey {
+                dir,
+                out_file,
+                config_key,
+                password,
+            } => {
+                let key = config_key.config_key(&password, &dir).await?;
+                let mut private = read_server_config_with_key(&key, dir.clone())?.private;
+                let Some(epoch_sks) = private.epoch_sks.take() else {
+                    bail!("The epoch key share was exported already");
+                };
+
+                fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&out_file)?
+                    .write_all(bincode::serialize(&epoch_sks)?.to_hex().as_bytes())?;
+                write_private_config(&private, &key, dir)?;
+                Ok(info!("Wrote the epoch key share to {out_file:?}"))
+            }
             Command::ConfigEncrypt {
                 in_file,
                 out_file,
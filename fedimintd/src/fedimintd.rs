@@ -1,33 +1,62 @@
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::{bail, format_err};
 use clap::Parser;
+use fedimint_core::admin_client::ConfigGenParamsRequest;
 use fedimint_core::config::{
     ModuleGenParams, ServerModuleGenParamsRegistry, ServerModuleGenRegistry,
 };
 use fedimint_core::core::ModuleKind;
+use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::db::Database;
-use fedimint_core::module::ServerModuleGen;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::{ApiAuth, ServerModuleGen};
 use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::Amount;
 use fedimint_ln_server::LightningGen;
-use fedimint_logging::TracingSetup;
+use fedimint_logging::{LogFormat, TracingSetup};
 use fedimint_mint_server::MintGen;
+use fedimint_server::backup::{
+    read_backup_info, restore_backup, verify_backup, BackupLocation, BACKUP_INFO_FILE,
+};
+use fedimint_server::config::api::{run_server as run_setup_server, ConfigGenConnections};
 use fedimint_server::config::io::{
-    read_server_config, CODE_VERSION, DB_FILE, JSON_EXT, LOCAL_CONFIG,
+    read_local_config, read_server_config_with_key, CODE_VERSION, DB_FILE, JSON_EXT, LOCAL_CONFIG,
 };
+use fedimint_server::config::keys::{check_epoch_signer, CommandEpochSigner, ConfigKeySource};
+use fedimint_server::config::DatabaseBackend;
 use fedimint_server::consensus::FedimintConsensus;
+use fedimint_server::net::tls::{
+    AcmeChallenge, AcmeConfig, ApiCertificate, ApiTlsConfig, LETS_ENCRYPT_DIRECTORY,
+};
 use fedimint_server::FedimintServer;
 use fedimint_wallet_server::WalletGen;
 use futures::FutureExt;
 use tokio::select;
 use tracing::{debug, error, info, warn};
+use url::Url;
 
+use crate::archive::{run_archive, ArchiveOpts};
+use crate::attach_default_module_gen_params;
+use crate::dev_fed::{run_dev_fed, DevFedOpts};
+use crate::metrics::run_metrics_server;
+use crate::replay::{run_replay, ReplayOpts, REPLAY_LOG_FILTER};
 use crate::ui::{run_ui, UiMessage};
 
 /// Time we will wait before forcefully shutting down tasks
 const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// HTTP servers only have to stop listening on shutdown, so they don't get the
+/// whole shutdown timeout
+const HTTP_SERVER_JOIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Directory in the data dir the ACME account and certificate are kept in
+const ACME_DIR: &str = "acme";
+
 #[derive(Parser)]
 pub struct ServerOpts {
     /// Path to folder containing federation config files
@@ -38,9 +67,131 @@ pub struct ServerOpts {
     // the API
     #[arg(env = "FM_PASSWORD")]
     pub password: String,
+    /// Where the key decrypting the private config comes from: `password`,
+    /// `env:<variable>` holding the hex encoded key or `command:<program>
+    /// [args]` printing it, e.g. a helper unwrapping the key with a KMS or HSM
+    #[arg(
+        long = "config-key",
+        env = "FM_CONFIG_KEY_SOURCE",
+        default_value = "password"
+    )]
+    pub config_key: ConfigKeySource,
+    /// Helper holding our epoch key share, e.g. in an HSM, that is called with
+    /// the hex encoded hash to sign and prints the hex encoded signature share
+    #[arg(long = "epoch-signer-command", env = "FM_EPOCH_SIGNER_COMMAND")]
+    pub epoch_signer_command: Option<String>,
     /// Port to run admin UI on
     #[arg(long = "listen-ui", env = "FM_LISTEN_UI")]
     pub listen_ui: Option<SocketAddr>,
+    /// Address the setup API binds to if the configs are missing and no admin
+    /// UI is run, the API is served over HTTP and websockets
+    #[arg(long = "bind-api", env = "FM_BIND_API")]
+    pub bind_api: Option<SocketAddr>,
+    /// Address DKG binds to for communicating with our peers during setup
+    #[arg(long = "bind-p2p", env = "FM_BIND_P2P")]
+    pub bind_p2p: Option<SocketAddr>,
+    /// More addresses to listen on for our peers, e.g. an IPv6 one, separated
+    /// by commas
+    #[arg(
+        long = "bind-p2p-extra",
+        env = "FM_BIND_P2P_EXTRA",
+        value_delimiter = ','
+    )]
+    pub bind_p2p_extra: Vec<SocketAddr>,
+    /// More addresses to serve the API on, separated by commas
+    #[arg(
+        long = "bind-api-extra",
+        env = "FM_BIND_API_EXTRA",
+        value_delimiter = ','
+    )]
+    pub bind_api_extra: Vec<SocketAddr>,
+    /// Our API address for clients to connect to us, used during setup
+    #[arg(long = "api-url", env = "FM_API_URL")]
+    pub api_url: Option<Url>,
+    /// Our external address for communicating with our peers, used during
+    /// setup
+    #[arg(long = "p2p-url", env = "FM_P2P_URL")]
+    pub p2p_url: Option<Url>,
+    /// More external addresses our peers can reach us at if `p2p-url` fails,
+    /// separated by commas, advertised to them during setup
+    #[arg(
+        long = "p2p-url-extra",
+        env = "FM_P2P_URL_EXTRA",
+        value_delimiter = ','
+    )]
+    pub p2p_url_extra: Vec<Url>,
+    /// Default max denomination of notes the leader can change during setup
+    #[arg(
+        long = "max-denomination",
+        env = "FM_MAX_DENOMINATION",
+        default_value = "100000000000"
+    )]
+    pub max_denomination: Amount,
+    /// Default bitcoin network the leader can change during setup
+    #[arg(long = "network", env = "FM_NETWORK", default_value = "regtest")]
+    pub network: bitcoin::network::constants::Network,
+    /// Default number of confirmations a deposit transaction requires the
+    /// leader can change during setup
+    #[arg(
+        long = "finality-delay",
+        env = "FM_FINALITY_DELAY",
+        default_value = "10"
+    )]
+    pub finality_delay: u32,
+    /// Address to serve the admin API on instead of the public API address
+    #[arg(long = "bind-admin", env = "FM_BIND_ADMIN")]
+    pub bind_admin: Option<SocketAddr>,
+    /// Unix domain socket to serve the admin API on in addition, only the user
+    /// running fedimintd may connect to it
+    #[arg(long = "admin-socket", env = "FM_ADMIN_SOCKET")]
+    pub admin_socket: Option<PathBuf>,
+    /// Address to serve the API on over TLS in addition, e.g. `0.0.0.0:443`,
+    /// with the certificate of `api-tls-cert` or one from ACME
+    #[arg(long = "bind-api-tls", env = "FM_BIND_API_TLS")]
+    pub bind_api_tls: Option<SocketAddr>,
+    /// PEM file of the certificate chain of the TLS API
+    #[arg(
+        long = "api-tls-cert",
+        env = "FM_API_TLS_CERT",
+        requires = "api_tls_key"
+    )]
+    pub api_tls_cert: Option<PathBuf>,
+    /// PEM file of the private key of the TLS API's certificate
+    #[arg(long = "api-tls-key", env = "FM_API_TLS_KEY")]
+    pub api_tls_key: Option<PathBuf>,
+    /// Domains to get the certificate of the TLS API for through ACME,
+    /// separated by commas
+    #[arg(long = "acme-domains", env = "FM_ACME_DOMAINS", value_delimiter = ',')]
+    pub acme_domains: Vec<String>,
+    /// Email the ACME server sends notices about the certificate to
+    #[arg(long = "acme-email", env = "FM_ACME_EMAIL")]
+    pub acme_email: Option<String>,
+    /// Directory of the ACME server
+    #[arg(
+        long = "acme-directory",
+        env = "FM_ACME_DIRECTORY",
+        default_value = LETS_ENCRYPT_DIRECTORY
+    )]
+    pub acme_directory: Url,
+    /// Address to answer HTTP-01 challenges on, e.g. `0.0.0.0:80`, if unset
+    /// the ACME server validates TLS-ALPN-01 challenges on the TLS API
+    #[arg(long = "acme-http-bind", env = "FM_ACME_HTTP_BIND")]
+    pub acme_http_bind: Option<SocketAddr>,
+    /// Auth users have to send to use the user endpoints of the API, the
+    /// public config and consensus queries stay open to everyone
+    #[arg(long = "user-auth", env = "FM_USER_AUTH")]
+    pub user_auth: Option<String>,
+    /// Sign API responses for clients that ask for it, so they can prove if
+    /// we gave them conflicting responses
+    #[arg(long = "sign-api-responses", env = "FM_SIGN_API_RESPONSES")]
+    pub sign_api_responses: bool,
+    /// Restores a backup from a directory or `s3://<bucket>/<prefix>` url into
+    /// the empty data dir before starting
+    #[arg(long = "restore-from", env = "FM_RESTORE_FROM")]
+    pub restore_from: Option<String>,
+    /// Address to serve Prometheus metrics on at `/metrics`
+    #[arg(long = "bind-metrics", env = "FM_BIND_METRICS")]
+    pub bind_metrics: Option<SocketAddr>,
     /// After an upgrade the epoch must be passed in
     #[arg(env = "FM_UPGRADE_EPOCH")]
     pub upgrade_epoch: Option<u64>,
@@ -50,6 +201,22 @@ pub struct ServerOpts {
     /// Enable telemetry logging
     #[arg(long, default_value = "false")]
     pub with_telemetry: bool,
+    /// Format of the logs, `text` or `json`, overrides the `log_format` of the
+    /// local config
+    #[arg(long = "log-format", env = "FM_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+}
+
+/// What `fedimintd` was started to do
+enum Mode {
+    /// Run a guardian
+    Guardian(ServerOpts),
+    /// Run a whole federation in-process with `fedimintd dev-fed`
+    DevFed(DevFedOpts),
+    /// Replay the epoch history of a guardian with `fedimintd replay`
+    Replay(ReplayOpts),
+    /// Archive the epoch history of a federation with `fedimintd archive`
+    Archive(ArchiveOpts),
 }
 
 /// `fedimintd` builder
@@ -85,7 +252,7 @@ pub struct ServerOpts {
 pub struct Fedimintd {
     module_gens: ServerModuleGenRegistry,
     module_gens_params: ServerModuleGenParamsRegistry,
-    opts: ServerOpts,
+    mode: Mode,
 }
 
 impl Fedimintd {
@@ -100,16 +267,44 @@ impl Fedimintd {
 
         info!("Starting fedimintd (version: {CODE_VERSION})");
 
-        let opts: ServerOpts = ServerOpts::parse();
-        TracingSetup::default()
-            .tokio_console_bind(opts.tokio_console_bind)
-            .with_jaeger(opts.with_telemetry)
-            .init()?;
+        let mode = if std::env::args().nth(1).as_deref() == Some("dev-fed") {
+            // `dev-fed` takes the place of the binary name, its options don't include
+            // the guardian's positional password
+            let opts = DevFedOpts::parse_from(std::env::args().skip(1));
+            TracingSetup::default().init()?;
+            Mode::DevFed(opts)
+        } else if std::env::args().nth(1).as_deref() == Some("replay") {
+            let opts = ReplayOpts::parse_from(std::env::args().skip(1));
+            TracingSetup::default()
+                .log_format(opts.log_format)
+                .default_filter(REPLAY_LOG_FILTER)
+                .init()?;
+            Mode::Replay(opts)
+        } else if std::env::args().nth(1).as_deref() == Some("archive") {
+            let opts = ArchiveOpts::parse_from(std::env::args().skip(1));
+            TracingSetup::default().log_format(opts.log_format).init()?;
+            Mode::Archive(opts)
+        } else {
+            let opts: ServerOpts = ServerOpts::parse();
+            // the local config is plaintext, so we can learn the format before tracing
+            // starts, it doesn't exist yet before the config generation though
+            let log_format = opts.log_format.unwrap_or_else(|| {
+                read_local_config(opts.data_dir.clone())
+                    .map(|local| local.log_format)
+                    .unwrap_or_default()
+            });
+            TracingSetup::default()
+                .log_format(log_format)
+                .tokio_console_bind(opts.tokio_console_bind)
+                .with_jaeger(opts.with_telemetry)
+                .init()?;
+            Mode::Guardian(opts)
+        };
 
         Ok(Self {
             module_gens: ServerModuleGenRegistry::new(),
             module_gens_params: ServerModuleGenParamsRegistry::new(),
-            opts,
+            mode,
         })
     }
 
@@ -147,14 +342,33 @@ impl Fedimintd {
         let task_group = root_task_group.clone();
         root_task_group
             .spawn_local("main", move |_task_handle| async move {
-                match run(
-                    self.opts,
-                    task_group.clone(),
-                    self.module_gens,
-                    self.module_gens_params,
-                )
-                .await
-                {
+                let result = match self.mode {
+                    Mode::Guardian(opts) => {
+                        run(
+                            opts,
+                            task_group.clone(),
+                            self.module_gens,
+                            self.module_gens_params,
+                        )
+                        .await
+                    }
+                    Mode::DevFed(opts) => {
+                        run_dev_fed(
+                            opts,
+                            task_group.clone(),
+                            self.module_gens,
+                            self.module_gens_params,
+                        )
+                        .await
+                    }
+                    Mode::Replay(opts) => {
+                        run_replay(opts, task_group.clone(), self.module_gens).await
+                    }
+                    Mode::Archive(opts) => {
+                        run_archive(opts, task_group.clone(), self.module_gens).await
+                    }
+                };
+                match result {
                     Ok(()) => {}
                     Err(e) => {
                         error!(?e, "Main task returned error, shutting down");
@@ -206,8 +420,28 @@ async fn run(
 ) -> anyhow::Result<()> {
     let (ui_sender, mut ui_receiver) = tokio::sync::mpsc::channel(1);
 
+    if let Some(restore_from) = &opts.restore_from {
+        let location: BackupLocation = restore_from.parse()?;
+        restore_backup(&location, &opts.data_dir).await?;
+    }
+
+    if let Some(bind_metrics) = opts.bind_metrics {
+        let metrics_task_group = task_group.clone();
+        task_group
+            .spawn_with_join_timeout(
+                "metrics-server",
+                HTTP_SERVER_JOIN_TIMEOUT,
+                move |_| async move {
+                    run_metrics_server(bind_metrics, metrics_task_group).await;
+                },
+            )
+            .await;
+    }
+
     info!("Starting pre-check");
 
+    let local_cfg_path = opts.data_dir.join(LOCAL_CONFIG).with_extension(JSON_EXT);
+
     // Run admin UI if a socket address was given for it
     if let Some(listen_ui) = opts.listen_ui {
         let module_gens = module_gens.clone();
@@ -216,7 +450,7 @@ async fn run(
         let ui_task_group = task_group.make_subgroup().await;
         let password = opts.password.clone();
         task_group
-            .spawn("admin-ui", move |_| async move {
+            .spawn_with_join_timeout("admin-ui", HTTP_SERVER_JOIN_TIMEOUT, move |_| async move {
                 run_ui(
                     data_dir,
                     ui_sender,
@@ -232,7 +466,6 @@ async fn run(
 
         // If federation configs (e.g. local.json) missing, wait for admin UI to report
         // DKG completion
-        let local_cfg_path = opts.data_dir.join(LOCAL_CONFIG).with_extension(JSON_EXT);
         if !std::path::Path::new(&local_cfg_path).exists() {
             loop {
                 if let UiMessage::DkgSuccess = ui_receiver
@@ -244,21 +477,77 @@ async fn run(
                 }
             }
         }
+    } else if !local_cfg_path.exists() {
+        run_setup_api(&opts, &module_gens, module_gens_params).await?;
     }
 
     info!("Starting consensus");
 
-    let cfg = read_server_config(&opts.password, opts.data_dir.clone())?;
+    let config_key = opts
+        .config_key
+        .config_key(&opts.password, &opts.data_dir)
+        .await?;
+    let mut cfg = read_server_config_with_key(&config_key, opts.data_dir.clone())?;
+    if let Some(bind_admin) = opts.bind_admin {
+        cfg.local.admin_bind = Some(bind_admin);
+    }
+    if !opts.bind_p2p_extra.is_empty() {
+        cfg.local.extra_fed_binds = opts.bind_p2p_extra.clone();
+    }
+    if !opts.bind_api_extra.is_empty() {
+        cfg.local.extra_api_binds = opts.bind_api_extra.clone();
+    }
+    if let Some(admin_socket) = opts.admin_socket.clone() {
+        cfg.local.admin_socket = Some(admin_socket);
+    }
+    if let Some(api_tls) = api_tls_config(&opts)? {
+        cfg.local.api_tls = Some(api_tls);
+    }
+    if let Some(user_auth) = opts.user_auth {
+        cfg.local.user_auth = Some(ApiAuth(user_auth));
+    }
+    if opts.sign_api_responses {
+        cfg.local.sign_api_responses = true;
+    }
 
     let decoders = module_gens.decoders(cfg.iter_module_instances())?;
 
-    let db = Database::new(
-        fedimint_rocksdb::RocksDb::open(opts.data_dir.join(DB_FILE))?,
-        decoders.clone(),
-    );
+    let db = open_database(
+        cfg.local.database_backend,
+        opts.data_dir.join(DB_FILE),
+        &decoders,
+    )
+    .await?;
+
+    // a restored backup has to match the federation's history before we rejoin
+    if let Some(backup_info) = read_backup_info(&opts.data_dir)? {
+        info!(
+            epoch_count = backup_info.epoch_count,
+            "Verifying restored backup against the federation"
+        );
+        verify_backup(&cfg, &backup_info, &decoders).await?;
+        std::fs::remove_file(
+            opts.data_dir
+                .join(BACKUP_INFO_FILE)
+                .with_extension(JSON_EXT),
+        )?;
+    }
 
-    let (consensus, api_receiver) =
+    let (mut consensus, api_receiver) =
         FedimintConsensus::new(cfg.clone(), db, module_gens, &mut task_group).await?;
+    consensus.config_dir = Some(opts.data_dir.clone());
+
+    if let Some(command) = &opts.epoch_signer_command {
+        let signer = CommandEpochSigner::new(command)?;
+        let pk_share = cfg
+            .consensus
+            .epoch_pk_set
+            .public_key_share(cfg.local.identity.to_usize());
+        check_epoch_signer(&signer, &pk_share).await?;
+        consensus.epoch_signer = Arc::new(signer);
+    } else if cfg.private.epoch_sks.is_none() {
+        bail!("Our epoch key share isn't in the config, set --epoch-signer-command");
+    }
 
     if let Some(epoch) = opts.upgrade_epoch {
         consensus.remove_upgrade_items(epoch).await?;
@@ -268,3 +557,110 @@ async fn run(
 
     Ok(())
 }
+
+/// Opens the guardian database at `path` with the backend it was created with
+pub(crate) async fn open_database(
+    backend: DatabaseBackend,
+    path: impl AsRef<Path>,
+    decoders: &ModuleDecoderRegistry,
+) -> anyhow::Result<Database> {
+    Ok(match backend {
+        DatabaseBackend::RocksDb => {
+            Database::new(fedimint_rocksdb::RocksDb::open(path)?, decoders.clone())
+        }
+        DatabaseBackend::Sqlite => Database::new(
+            fedimint_sqlite::SqliteDb::open_path(path).await?,
+            decoders.clone(),
+        ),
+    })
+}
+
+/// How the options ask to serve the API over TLS, `None` if they don't
+fn api_tls_config(opts: &ServerOpts) -> anyhow::Result<Option<ApiTlsConfig>> {
+    let Some(bind) = opts.bind_api_tls else {
+        return Ok(None);
+    };
+
+    let certificate = match (&opts.api_tls_cert, &opts.api_tls_key) {
+        (Some(cert_chain), Some(private_key)) => ApiCertificate::Files {
+            cert_chain: cert_chain.clone(),
+            private_key: private_key.clone(),
+        },
+        _ if !opts.acme_domains.is_empty() => ApiCertificate::Acme(AcmeConfig {
+            domains: opts.acme_domains.clone(),
+            contact_email: opts.acme_email.clone(),
+            directory: opts.acme_directory.clone(),
+            challenge: match opts.acme_http_bind {
+                Some(bind) => AcmeChallenge::Http01 { bind },
+                None => AcmeChallenge::TlsAlpn01,
+            },
+            cache_dir: opts.data_dir.join(ACME_DIR),
+        }),
+        _ => bail!(
+            "Serving the API over TLS needs --api-tls-cert and --api-tls-key or --acme-domains"
+        ),
+    };
+
+    Ok(Some(ApiTlsConfig { bind, certificate }))
+}
+
+/// Serves the config gen API until the guardian created and verified the
+/// configs with its peers and asked to start consensus
+///
+/// The API is authenticated with our password, so a setup wizard can drive
+/// every step of the federation creation through it.
+async fn run_setup_api(
+    opts: &ServerOpts,
+    module_gens: &ServerModuleGenRegistry,
+    mut module_gens_params: ServerModuleGenParamsRegistry,
+) -> anyhow::Result<()> {
+    let (Some(bind_api), Some(bind_p2p), Some(api_url), Some(p2p_url)) = (
+        opts.bind_api,
+        opts.bind_p2p,
+        opts.api_url.clone(),
+        opts.p2p_url.clone(),
+    ) else {
+        bail!("Configs are missing, set the bind addresses and urls to run the setup API");
+    };
+
+    // the leader can change these defaults through the API
+    attach_default_module_gen_params(
+        &mut module_gens_params,
+        opts.max_denomination,
+        opts.network,
+        opts.finality_delay,
+    );
+    let default_params = ConfigGenParamsRequest {
+        meta: BTreeMap::new(),
+        modules: module_gens_params,
+    };
+    let connections = ConfigGenConnections {
+        p2p_bind: bind_p2p,
+        api_bind: bind_api,
+        p2p_url,
+        api_url,
+        extra_p2p_binds: opts.bind_p2p_extra.clone(),
+        extra_p2p_urls: opts.p2p_url_extra.clone(),
+    };
+    let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+
+    let (server, api) = run_setup_server(
+        opts.data_dir.clone(),
+        connections,
+        db,
+        default_params,
+        module_gens.clone().legacy_init_modules(),
+        module_gens.clone(),
+    )
+    .await;
+    api.set_password(ApiAuth(opts.password.clone()))
+        .map_err(|_| format_err!("Password of the setup API was already set"))?;
+    info!("Setup API is listening on {bind_api}");
+
+    api.await_start_consensus().await;
+
+    // consensus binds to the same API address
+    server.stop()?;
+    server.stopped().await;
+    Ok(())
+}
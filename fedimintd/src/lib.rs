@@ -5,12 +5,19 @@ use fedimint_core::{Amount, Tiered};
 use fedimint_mint_server::{MintGen, MintGenParams};
 use fedimint_wallet_server::{WalletGen, WalletGenParams};
 
+mod metrics;
 mod ui;
 
+/// Archiving a federation's epoch history with `fedimintd archive`
+pub mod archive;
+/// In-process federation started with `fedimintd dev-fed`
+pub mod dev_fed;
 /// Module for creating `distributetgen` binary with custom modules
 pub mod distributed_gen;
 /// Module for creating `fedimintd` binary with custom modules
 pub mod fedimintd;
+/// Replaying a guardian's epoch history with `fedimintd replay`
+pub mod replay;
 
 /// Generates the configuration for the modules configured in the server binary
 pub fn attach_default_module_gen_params(
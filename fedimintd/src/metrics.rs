@@ -0,0 +1,29 @@
+use std::net::SocketAddr;
+
+use axum::routing::get;
+use axum::Router;
+use fedimint_core::task::TaskGroup;
+use tokio::select;
+use tracing::{debug, error, info};
+
+async fn metrics() -> String {
+    fedimint_server::metrics::render()
+}
+
+/// Serves the Prometheus metrics of the server at `/metrics`
+pub async fn run_metrics_server(bind_addr: SocketAddr, task_group: TaskGroup) {
+    let app = Router::new().route("/metrics", get(metrics));
+
+    let shutdown_future = task_group.make_handle().make_shutdown_rx().await;
+    let server_future = axum::Server::bind(&bind_addr).serve(app.into_make_service());
+
+    info!("Metrics server is listening on {}", bind_addr);
+    select! {
+        _ = shutdown_future => {
+            debug!("Metrics server shutting down");
+        },
+        Err(err) = server_future => {
+            error!(?err, "Metrics server encountered an error");
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! Replays the epoch history of a copied guardian database, so consensus bugs
+//! reported from production federations can be reproduced locally
+use std::path::PathBuf;
+
+use clap::Parser;
+use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
+use fedimint_core::task::TaskGroup;
+use fedimint_logging::LogFormat;
+use fedimint_server::config::io::{read_server_config_with_key, DB_FILE};
+use fedimint_server::config::keys::ConfigKeySource;
+use fedimint_server::consensus::FedimintConsensus;
+use fedimint_server::replay::replay_epoch_history;
+use tracing::info;
+
+use crate::fedimintd::open_database;
+
+/// Log filter of the replay if `RUST_LOG` is not set, so the processing of
+/// every module is traced
+pub const REPLAY_LOG_FILTER: &str = "debug";
+
+/// Options of `fedimintd replay`
+#[derive(Parser)]
+#[command(name = "fedimintd replay")]
+pub struct ReplayOpts {
+    /// Directory with the configs of the guardian whose database is replayed
+    #[arg(long = "data-dir", env = "FM_DATA_DIR")]
+    pub data_dir: PathBuf,
+    /// Password to decrypt the private config of the guardian
+    #[arg(long = "password", env = "FM_PASSWORD")]
+    pub password: String,
+    /// Where the key decrypting the private config comes from, see
+    /// `fedimintd --config-key`
+    #[arg(
+        long = "config-key",
+        env = "FM_CONFIG_KEY_SOURCE",
+        default_value = "password"
+    )]
+    pub config_key: ConfigKeySource,
+    /// Copy of the guardian's database to read the epoch history from, the
+    /// database in the data dir by default
+    #[arg(long = "db")]
+    pub db: Option<PathBuf>,
+    /// Last epoch to replay, all recorded epochs by default
+    #[arg(long = "until-epoch")]
+    pub until_epoch: Option<u64>,
+    /// Format of the logs, `text` or `json`
+    #[arg(long = "log-format", env = "FM_LOG_FORMAT", default_value = "text")]
+    pub log_format: LogFormat,
+}
+
+/// Processes the recorded epochs on an in-memory database without starting
+/// the API or connecting to the peers, the recorded database is only read
+///
+/// Modules still reach the services they depend on, e.g. the wallet asks
+/// `FM_BITCOIND_RPC` for the blocks the federation agreed on.
+pub async fn run_replay(
+    opts: ReplayOpts,
+    mut task_group: TaskGroup,
+    module_gens: ServerModuleGenRegistry,
+) -> anyhow::Result<()> {
+    let config_key = opts
+        .config_key
+        .config_key(&opts.password, &opts.data_dir)
+        .await?;
+    let cfg = read_server_config_with_key(&config_key, opts.data_dir.clone())?;
+    let decoders = module_gens.decoders(cfg.iter_module_instances())?;
+
+    let recorded_path = opts.db.unwrap_or_else(|| opts.data_dir.join(DB_FILE));
+    info!("Replaying the epoch history of {}", recorded_path.display());
+    let recorded = open_database(cfg.local.database_backend, recorded_path, &decoders).await?;
+
+    let db = Database::new(MemDatabase::new(), decoders);
+    let (consensus, _api_receiver) =
+        FedimintConsensus::new(cfg, db, module_gens, &mut task_group).await?;
+
+    if let Some(last_epoch) = replay_epoch_history(&consensus, &recorded, opts.until_epoch).await? {
+        println!("Replayed the epoch history up to epoch {last_epoch}");
+    }
+
+    // nothing keeps running after the replay
+    task_group.shutdown().await;
+    Ok(())
+}
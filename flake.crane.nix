@@ -187,6 +187,8 @@ rec {
   workspaceTest = craneLib.cargoTest (commonArgs // {
     version = "0.0.1";
     cargoArtifacts = workspaceDeps;
+    # encoding snapshots recorded in CI would be lost, fail instead
+    FM_FREEZE_ENCODINGS = "1";
   });
 
   workspaceTestDoc = craneLib.cargoTest (commonArgs // {
@@ -270,6 +272,7 @@ rec {
     installPhaseCommand = "true";
     nativeBuildInputs = commonArgs.nativeBuildInputs ++ [ cargo-llvm-cov ];
     doCheck = false;
+    FM_FREEZE_ENCODINGS = "1";
   });
 
   cliTestReconnect = craneLib.mkCargoDerivation (commonCliTestArgs // {
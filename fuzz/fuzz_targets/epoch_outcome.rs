@@ -0,0 +1,7 @@
+#![no_main]
+use fedimint_fuzz::{all_module_decoders, decode_and_reencode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    decode_and_reencode::<fedimint_core::epoch::SignedEpochOutcome>(data, &all_module_decoders());
+});
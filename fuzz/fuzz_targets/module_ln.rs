@@ -0,0 +1,7 @@
+#![no_main]
+use fedimint_fuzz::decode_module_item;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    decode_module_item::<fedimint_ln_common::LightningModuleTypes>(data);
+});
@@ -0,0 +1,78 @@
+//! Helpers shared by the fuzz targets, which feed arbitrary bytes to the
+//! decoders of the items guardians receive from peers and clients
+
+use std::fmt::Debug;
+use std::io::Cursor;
+
+use fedimint_core::core::{
+    LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
+    LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::ModuleCommon;
+
+/// Decoders of all modules, the modules without a hardcoded instance id follow
+/// the legacy ones
+pub fn all_module_decoders() -> ModuleDecoderRegistry {
+    ModuleDecoderRegistry::from_iter([
+        (
+            LEGACY_HARDCODED_INSTANCE_ID_LN,
+            fedimint_ln_common::LightningModuleTypes::decoder(),
+        ),
+        (
+            LEGACY_HARDCODED_INSTANCE_ID_MINT,
+            fedimint_mint_common::MintModuleTypes::decoder(),
+        ),
+        (
+            LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+            fedimint_wallet_common::WalletModuleTypes::decoder(),
+        ),
+        (3, fedimint_dummy_common::DummyModuleTypes::decoder()),
+        (4, fedimint_escrow_common::EscrowModuleTypes::decoder()),
+        (
+            5,
+            fedimint_stability_pool_common::StabilityPoolModuleTypes::decoder(),
+        ),
+    ])
+}
+
+/// Decodes a `T` from `data`, which may fail but mustn't panic, and checks
+/// that what was decoded survives another encoding roundtrip unchanged
+///
+/// The input itself may be encoded differently, e.g. a URL that isn't
+/// normalized, so only the encoding of the decoded item has to be stable.
+pub fn decode_and_reencode<T>(data: &[u8], modules: &ModuleDecoderRegistry)
+where
+    T: Decodable + Encodable + Debug,
+{
+    let Ok(decoded) = T::consensus_decode(&mut Cursor::new(data), modules) else {
+        return;
+    };
+
+    let encoded = decoded
+        .consensus_encode_to_vec()
+        .expect("encoding to a vec can't fail");
+    let redecoded = T::consensus_decode(&mut Cursor::new(&encoded), modules)
+        .unwrap_or_else(|e| panic!("Re-encoded {decoded:?} doesn't decode: {e}"));
+    let reencoded = redecoded
+        .consensus_encode_to_vec()
+        .expect("encoding to a vec can't fail");
+    // peers that decoded the same item have to agree on its hash
+    assert_eq!(encoded, reencoded, "Encoding of {decoded:?} isn't stable");
+}
+
+/// Decodes one of the types of the module `M`, the first byte selects which
+pub fn decode_module_item<M: ModuleCommon>(data: &[u8]) {
+    let Some((selector, data)) = data.split_first() else {
+        return;
+    };
+
+    let modules = ModuleDecoderRegistry::default();
+    match selector % 4 {
+        0 => decode_and_reencode::<M::Input>(data, &modules),
+        1 => decode_and_reencode::<M::Output>(data, &modules),
+        2 => decode_and_reencode::<M::OutputOutcome>(data, &modules),
+        _ => decode_and_reencode::<M::ConsensusItem>(data, &modules),
+    }
+}
@@ -1,15 +1,26 @@
+use std::path::PathBuf;
 use std::process::exit;
 
-use bitcoin::{Address, Amount, Transaction};
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Address, Amount, Transaction, XOnlyPublicKey};
 use clap::{Parser, Subcommand};
 use fedimint_core::config::FederationId;
 use fedimint_logging::TracingSetup;
+use lightning_invoice::Invoice;
+use ln_gateway::accounting::{parse_date, AccountingExport, AccountingFormat};
+use ln_gateway::migration::StateArchive;
 use ln_gateway::rpc::rpc_client::RpcClient;
 use ln_gateway::rpc::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    LightningReconnectPayload, RestorePayload, WithdrawPayload,
+    ExportAccountingPayload, ExportStatePayload, FirstHopConstraint, ImportStatePayload,
+    LightningReconnectPayload, LnurlPaymentsPayload, ManualPayInvoicePayload, PauseFedPayload,
+    RegisterLightningAddressPayload, RestorePayload, ResumeFedPayload, SendOnionMessagePayload,
+    SetHtlcAmountBandPayload, SetReservePayload, SweepToLnPayload, TestPaymentPayload,
+    WithdrawPayload,
 };
 use ln_gateway::Mode;
+use mint_client::ln::HtlcAmountBand;
+use mint_client::modules::ln::contracts::ContractId;
 use mint_client::modules::wallet::txoproof::TxOutProof;
 use mint_client::utils::from_hex;
 use url::Url;
@@ -50,10 +61,21 @@ pub enum Commands {
     /// Claim funds from a gateway federation
     Withdraw {
         federation_id: FederationId,
-        /// The amount to withdraw
-        amount: Amount,
         /// The address to send the funds to
         address: Address,
+        /// The amount to withdraw
+        #[clap(long, required_unless_present = "all")]
+        amount: Option<Amount>,
+        /// Withdraw the whole balance after fees
+        #[clap(long, conflicts_with = "amount")]
+        all: bool,
+    },
+    /// Pay out the ecash of a gateway federation to a lightning invoice, the
+    /// payment is routed by another gateway of the federation
+    SweepToLn {
+        federation_id: FederationId,
+        /// Invoice of at most the balance after fees
+        invoice: Invoice,
     },
     /// Register federation with the gateway
     ConnectFed {
@@ -64,11 +86,106 @@ pub enum Commands {
     Backup { federation_id: FederationId },
     /// Restore ecash from last available snapshot or from scratch
     Restore { federation_id: FederationId },
+    /// Write the state of all federations to an encrypted archive, to move
+    /// the gateway to another machine
+    ExportState {
+        /// File the archive is written to
+        path: PathBuf,
+        /// Password the archive is encrypted with, prompted for if unset
+        #[clap(long)]
+        archive_password: Option<String>,
+    },
+    /// Connect the federations of an archive written by `export-state`, the
+    /// gateway has to run on the same lightning node
+    ImportState {
+        /// File the archive is read from
+        path: PathBuf,
+        /// Password the archive was encrypted with, prompted for if unset
+        #[clap(long)]
+        archive_password: Option<String>,
+    },
+    /// Print the payments of all federations and their balances for
+    /// bookkeeping
+    ExportAccounting {
+        /// First day of the export as YYYY-MM-DD (UTC), from the first payment
+        /// if unset
+        #[clap(long)]
+        from: Option<String>,
+        /// Last day of the export as YYYY-MM-DD (UTC), until now if unset
+        #[clap(long)]
+        to: Option<String>,
+        #[clap(long, value_enum, default_value = "csv")]
+        format: AccountingFormat,
+    },
     // Reconnect to the Lightning Node
     Reconnect {
         #[clap(subcommand)]
         mode: Mode,
     },
+    /// Give a user of a federation the lightning address <name>@<gateway host>
+    RegisterLnaddr {
+        federation_id: FederationId,
+        /// User part of the lightning address
+        name: String,
+        /// Key whose secret key claims the payments to the address
+        pubkey: XOnlyPublicKey,
+    },
+    /// Print the payments to a lightning address with the tweaks its user
+    /// needs to claim them
+    LnaddrPayments {
+        /// User part of the lightning address
+        name: String,
+    },
+    /// Set the ecash kept in a federation besides what HTLCs need, HTLCs that
+    /// would eat into it are rejected
+    SetReserve {
+        federation_id: FederationId,
+        /// The reserve in msat
+        reserve: fedimint_core::Amount,
+    },
+    /// Set the amounts of the HTLCs the gateway intercepts for a federation,
+    /// HTLCs outside of them are failed back
+    SetHtlcBand {
+        federation_id: FederationId,
+        /// The smallest HTLC amount in msat, unbounded if unset
+        #[clap(long)]
+        min: Option<fedimint_core::Amount>,
+        /// The largest HTLC amount in msat, unbounded if unset
+        #[clap(long)]
+        max: Option<fedimint_core::Amount>,
+    },
+    /// Stop routing payments for a federation and announcing the gateway to it,
+    /// e.g. while it upgrades, other federations keep working
+    PauseFed { federation_id: FederationId },
+    /// Route payments for a paused federation again
+    ResumeFed { federation_id: FederationId },
+    /// Route payments between the lightning node and a federation to check the
+    /// gateway end to end
+    TestPayment {
+        federation_id: FederationId,
+        /// The amount in msat
+        amount: fedimint_core::Amount,
+    },
+    /// Pay the invoice of an outgoing contract, e.g. to drain a channel by
+    /// restricting the channel the payment leaves over
+    ManualPayInvoice {
+        federation_id: FederationId,
+        contract_id: ContractId,
+        /// Short channel id of the channel the payment has to leave over
+        #[clap(long)]
+        outgoing_channel_id: Option<u64>,
+        /// Peer the payment has to be forwarded to first
+        #[clap(long)]
+        first_hop: Option<PublicKey>,
+    },
+    /// Send an onion message from the lightning node to a peer of it
+    SendOnionMessage {
+        /// The public key of the peer
+        node_id: PublicKey,
+        /// A TLV record of the payload as `<type>=<hex value>`, can be repeated
+        #[clap(long = "field", value_parser = parse_onion_message_field)]
+        fields: Vec<(u64, String)>,
+    },
 }
 
 #[tokio::main]
@@ -127,8 +244,9 @@ async fn main() -> anyhow::Result<()> {
         }
         Commands::Withdraw {
             federation_id,
-            amount,
             address,
+            amount,
+            all: _,
         } => {
             let response = client
                 .withdraw(
@@ -143,6 +261,22 @@ async fn main() -> anyhow::Result<()> {
 
             print_response(response).await;
         }
+        Commands::SweepToLn {
+            federation_id,
+            invoice,
+        } => {
+            let response = client
+                .sweep_to_ln(
+                    source_password(cli.rpcpassword),
+                    SweepToLnPayload {
+                        federation_id,
+                        invoice,
+                    },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
         Commands::ConnectFed { connect } => {
             let response = client
                 .connect_federation(
@@ -173,6 +307,64 @@ async fn main() -> anyhow::Result<()> {
 
             print_response(response).await;
         }
+        Commands::ExportState {
+            path,
+            archive_password,
+        } => {
+            let response = client
+                .export_state(
+                    source_password(cli.rpcpassword),
+                    ExportStatePayload {
+                        password: source_archive_password(archive_password),
+                    },
+                )
+                .await?;
+            if !response.status().is_success() {
+                print_response(response).await;
+                return Ok(());
+            }
+
+            std::fs::write(&path, response.bytes().await?)?;
+            println!("Exported gateway state to {}", path.display());
+        }
+        Commands::ImportState {
+            path,
+            archive_password,
+        } => {
+            let archive: StateArchive = serde_json::from_slice(&std::fs::read(&path)?)?;
+            let response = client
+                .import_state(
+                    source_password(cli.rpcpassword),
+                    ImportStatePayload {
+                        archive,
+                        password: source_archive_password(archive_password),
+                    },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::ExportAccounting { from, to, format } => {
+            let from = from.as_deref().map(parse_date).transpose()?;
+            // The last day is included in the export
+            let to = to
+                .as_deref()
+                .map(|to| parse_date(to).map(|to| to + 86_400))
+                .transpose()?;
+            let response = client
+                .export_accounting(
+                    source_password(cli.rpcpassword),
+                    ExportAccountingPayload { from, to },
+                )
+                .await?;
+            if !response.status().is_success() {
+                print_response(response).await;
+                return Ok(());
+            }
+
+            let export: AccountingExport = response.json().await?;
+            print!("{}", export.render(format));
+        }
         Commands::Reconnect { mode } => {
             let payload = match mode {
                 Mode::Cln { cln_extension_addr } => LightningReconnectPayload {
@@ -195,11 +387,150 @@ async fn main() -> anyhow::Result<()> {
                 .await?;
             print_response(response).await;
         }
+        Commands::RegisterLnaddr {
+            federation_id,
+            name,
+            pubkey,
+        } => {
+            let response = client
+                .register_lightning_address(
+                    source_password(cli.rpcpassword),
+                    RegisterLightningAddressPayload {
+                        federation_id,
+                        name,
+                        pubkey,
+                    },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::LnaddrPayments { name } => {
+            let response = client
+                .lightning_address_payments(
+                    source_password(cli.rpcpassword),
+                    LnurlPaymentsPayload { name },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::SetReserve {
+            federation_id,
+            reserve,
+        } => {
+            let response = client
+                .set_reserve(
+                    source_password(cli.rpcpassword),
+                    SetReservePayload {
+                        federation_id,
+                        reserve,
+                    },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::SetHtlcBand {
+            federation_id,
+            min,
+            max,
+        } => {
+            let response = client
+                .set_htlc_band(
+                    source_password(cli.rpcpassword),
+                    SetHtlcAmountBandPayload {
+                        federation_id,
+                        band: HtlcAmountBand { min, max },
+                    },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::PauseFed { federation_id } => {
+            let response = client
+                .pause_fed(
+                    source_password(cli.rpcpassword),
+                    PauseFedPayload { federation_id },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::ResumeFed { federation_id } => {
+            let response = client
+                .resume_fed(
+                    source_password(cli.rpcpassword),
+                    ResumeFedPayload { federation_id },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::TestPayment {
+            federation_id,
+            amount,
+        } => {
+            let response = client
+                .test_payment(
+                    source_password(cli.rpcpassword),
+                    TestPaymentPayload {
+                        federation_id,
+                        amount,
+                    },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::ManualPayInvoice {
+            federation_id,
+            contract_id,
+            outgoing_channel_id,
+            first_hop,
+        } => {
+            let response = client
+                .manual_pay_invoice(
+                    source_password(cli.rpcpassword),
+                    ManualPayInvoicePayload {
+                        federation_id,
+                        contract_id,
+                        first_hop: FirstHopConstraint {
+                            outgoing_channel_id,
+                            first_hop,
+                        },
+                    },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
+        Commands::SendOnionMessage { node_id, fields } => {
+            let response = client
+                .send_onion_message(
+                    source_password(cli.rpcpassword),
+                    SendOnionMessagePayload {
+                        node_id,
+                        fields: fields.into_iter().collect(),
+                    },
+                )
+                .await?;
+
+            print_response(response).await;
+        }
     }
 
     Ok(())
 }
 
+fn parse_onion_message_field(field: &str) -> anyhow::Result<(u64, String)> {
+    let (tlv_type, value) = field
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected <type>=<hex value>"))?;
+    Ok((tlv_type.parse()?, value.to_owned()))
+}
+
 pub async fn print_response(response: reqwest::Response) {
     match response.status() {
         reqwest::StatusCode::OK => {
@@ -219,6 +550,11 @@ pub async fn print_response(response: reqwest::Response) {
     }
 }
 
+pub fn source_archive_password(archive_password: Option<String>) -> String {
+    archive_password
+        .unwrap_or_else(|| rpassword::prompt_password("Enter archive password:").unwrap())
+}
+
 pub fn source_password(rpcpassword: Option<String>) -> String {
     match rpcpassword {
         None => rpassword::prompt_password("Enter gateway password:").unwrap(),
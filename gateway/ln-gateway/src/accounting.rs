@@ -0,0 +1,293 @@
+//! Accounting export of the payments of the gateway
+//!
+//! The clients of all federations record the payments the gateway routes and
+//! its deposits and withdrawals, see [`PaymentHistoryEntry`].
+//! `export-accounting` renders them, along with a snapshot of every
+//! federation's ecash balance, as CSV or as a beancount ledger.
+
+use std::fmt::Write;
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use fedimint_core::config::FederationId;
+use fedimint_core::Amount;
+use mint_client::ln::history::{PaymentHistoryEntry, PaymentKind};
+use serde::{Deserialize, Serialize};
+
+const CSV_HEADER: &str = "date,timestamp,federation_id,kind,reference,amount_msat,fee_msat\n";
+const SECONDS_PER_DAY: u64 = 86_400;
+const MSATS_PER_BTC: u64 = 100_000_000_000;
+
+/// Lightning node of the gateway in the beancount ledger
+const LIGHTNING_ACCOUNT: &str = "Assets:Lightning";
+/// Wallets deposits come from and withdrawals go to in the beancount ledger
+const ONCHAIN_ACCOUNT: &str = "Assets:Bitcoin";
+const FEES_ACCOUNT: &str = "Income:Gateway:Fees";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum AccountingFormat {
+    Csv,
+    Beancount,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountingExport {
+    /// Seconds since the unix epoch, the time of the balance snapshots
+    pub exported_at: u64,
+    pub federations: Vec<FederationAccounting>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationAccounting {
+    pub federation_id: FederationId,
+    /// Ecash balance at the time of the export
+    pub balance: Amount,
+    /// Payments in the exported period, oldest first
+    pub payments: Vec<PaymentHistoryEntry>,
+}
+
+impl AccountingExport {
+    pub fn render(&self, format: AccountingFormat) -> String {
+        match format {
+            AccountingFormat::Csv => self.to_csv(),
+            AccountingFormat::Beancount => self.to_beancount(),
+        }
+    }
+
+    /// One row per payment, amounts are the signed changes of the ecash
+    /// balance, followed by a `balance` row per federation
+    pub fn to_csv(&self) -> String {
+        let mut csv = CSV_HEADER.to_string();
+        for federation in &self.federations {
+            for payment in &federation.payments {
+                let sign = if payment.kind.is_credit() { "" } else { "-" };
+                writeln!(
+                    csv,
+                    "{},{},{},{},{},{sign}{},{}",
+                    format_date(payment.timestamp),
+                    payment.timestamp,
+                    federation.federation_id,
+                    payment.kind.name(),
+                    payment.kind.reference(),
+                    payment.amount.msats,
+                    payment.fee.msats,
+                )
+                .expect("writing to a string can't fail");
+            }
+        }
+        for federation in &self.federations {
+            writeln!(
+                csv,
+                "{},{},{},balance,,{},0",
+                format_date(self.exported_at),
+                self.exported_at,
+                federation.federation_id,
+                federation.balance.msats,
+            )
+            .expect("writing to a string can't fail");
+        }
+        csv
+    }
+
+    /// Ledger with an ecash account per federation, the lightning node, the
+    /// on-chain wallet and the fees earned, amounts in BTC. Balance snapshots
+    /// are `custom` directives, since the ledger misses the ecash spent on
+    /// federation fees a `balance` assertion would fail on.
+    pub fn to_beancount(&self) -> String {
+        let mut ledger = String::new();
+        let mut accounts = vec![
+            LIGHTNING_ACCOUNT.to_string(),
+            ONCHAIN_ACCOUNT.to_string(),
+            FEES_ACCOUNT.to_string(),
+        ];
+        accounts.extend(
+            self.federations
+                .iter()
+                .map(|federation| ecash_account(&federation.federation_id)),
+        );
+        for account in accounts {
+            writeln!(ledger, "1970-01-01 open {account} BTC")
+                .expect("writing to a string can't fail");
+        }
+
+        for federation in &self.federations {
+            let ecash = ecash_account(&federation.federation_id);
+            for payment in &federation.payments {
+                let amount = payment.amount.msats as i128;
+                let fee = payment.fee.msats as i128;
+                let (description, postings) = match payment.kind {
+                    PaymentKind::Incoming { .. } => (
+                        "Incoming payment",
+                        vec![(ecash.as_str(), -amount), (LIGHTNING_ACCOUNT, amount + fee)],
+                    ),
+                    PaymentKind::Outgoing { .. } => (
+                        "Outgoing payment",
+                        vec![(ecash.as_str(), amount), (LIGHTNING_ACCOUNT, fee - amount)],
+                    ),
+                    PaymentKind::Deposit { .. } => (
+                        "Deposit",
+                        vec![(ecash.as_str(), amount), (ONCHAIN_ACCOUNT, -amount)],
+                    ),
+                    PaymentKind::Withdrawal { .. } => (
+                        "Withdrawal",
+                        vec![(ecash.as_str(), -amount), (ONCHAIN_ACCOUNT, amount)],
+                    ),
+                };
+
+                writeln!(
+                    ledger,
+                    "\n{} * \"{description}\" \"{}\"\n  federation: \"{}\"",
+                    format_date(payment.timestamp),
+                    payment.kind.reference(),
+                    federation.federation_id,
+                )
+                .expect("writing to a string can't fail");
+                let fee_posting = (fee != 0).then_some((FEES_ACCOUNT, -fee));
+                for (account, msats) in postings.into_iter().chain(fee_posting) {
+                    writeln!(ledger, "  {account}  {} BTC", format_btc(msats))
+                        .expect("writing to a string can't fail");
+                }
+            }
+        }
+
+        ledger.push('\n');
+        for federation in &self.federations {
+            writeln!(
+                ledger,
+                "{} custom \"balance-snapshot\" {} {} BTC",
+                format_date(self.exported_at),
+                ecash_account(&federation.federation_id),
+                format_btc(federation.balance.msats as i128),
+            )
+            .expect("writing to a string can't fail");
+        }
+        ledger
+    }
+}
+
+/// Beancount account of the ecash in a federation, named after the start of
+/// its id
+fn ecash_account(federation_id: &FederationId) -> String {
+    let id = federation_id.to_string();
+    format!("Assets:Fedimint:Fed{}", id[..8].to_uppercase())
+}
+
+fn format_btc(msats: i128) -> String {
+    let sign = if msats < 0 { "-" } else { "" };
+    let msats = msats.unsigned_abs();
+    let per_btc = u128::from(MSATS_PER_BTC);
+    format!("{sign}{}.{:011}", msats / per_btc, msats % per_btc)
+}
+
+/// Seconds since the unix epoch of the start of the UTC day `date`, given as
+/// `YYYY-MM-DD`
+pub fn parse_date(date: &str) -> anyhow::Result<u64> {
+    let parts = date
+        .split('-')
+        .map(|part| part.parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid date {date}, expected YYYY-MM-DD"))?;
+    let [year, month, day] = parts[..] else {
+        bail!("Invalid date {date}, expected YYYY-MM-DD");
+    };
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 || civil_from_days(days) != (year, month, day) {
+        bail!("Invalid date {date}");
+    }
+    Ok(days as u64 * SECONDS_PER_DAY)
+}
+
+/// UTC date of `timestamp` as `YYYY-MM-DD`
+pub fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / SECONDS_PER_DAY) as i64);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Days since the unix epoch of a date of the proleptic gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::Hash;
+    use fedimint_core::TransactionId;
+    use mint_client::modules::ln::contracts::ContractId;
+
+    use super::*;
+
+    #[test]
+    fn dates_round_trip() {
+        assert_eq!(parse_date("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_date("2024-02-29").unwrap(), 1_709_164_800);
+        assert_eq!(format_date(1_709_164_800 + 3_600), "2024-02-29");
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("2023-13-01").is_err());
+        assert!(parse_date("1969-12-31").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn ledger_transactions_balance() {
+        let timestamp = 1_700_000_000;
+        let export = AccountingExport {
+            exported_at: timestamp,
+            federations: vec![FederationAccounting {
+                federation_id: FederationId::dummy(),
+                balance: Amount::from_sats(1_000),
+                payments: vec![
+                    PaymentHistoryEntry {
+                        timestamp,
+                        kind: PaymentKind::Incoming {
+                            contract_id: ContractId::from_inner([1; 32]),
+                        },
+                        amount: Amount::from_sats(100),
+                        fee: Amount::from_sats(1),
+                    },
+                    PaymentHistoryEntry {
+                        timestamp,
+                        kind: PaymentKind::Deposit {
+                            txid: TransactionId::from_inner([2; 32]),
+                        },
+                        amount: Amount::from_sats(1_000),
+                        fee: Amount::ZERO,
+                    },
+                ],
+            }],
+        };
+
+        let csv = export.to_csv();
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.contains(",incoming,") && csv.contains(",-100000,1000\n"));
+
+        let ledger = export.to_beancount();
+        assert!(ledger.contains("  Assets:Lightning  0.00000101000 BTC"));
+        assert!(ledger.contains("  Income:Gateway:Fees  -0.00000001000 BTC"));
+        assert!(ledger.contains("custom \"balance-snapshot\""));
+    }
+}
@@ -1,35 +1,94 @@
+use std::collections::HashMap;
 use std::pin::Pin;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use bitcoin::{Address, Transaction};
+use bitcoin::{Address, KeyPair, Transaction, XOnlyPublicKey};
+use bitcoin_hashes::hex::ToHex;
 use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::config::FederationId;
 use fedimint_core::task::{RwLock, TaskGroup};
-use fedimint_core::{Amount, OutPoint, TransactionId};
+use fedimint_core::{Amount, AmountUnit, OutPoint, TransactionId};
 use futures::stream::StreamExt;
 use futures::Stream;
-use mint_client::modules::ln::contracts::{ContractId, Preimage};
+use lightning_invoice::Invoice;
+use mint_client::ln::history::PaymentKind;
+use mint_client::ln::incoming::PreimagePurchaseState;
+use mint_client::ln::lnurl::{LightningAddress, LnurlPayment};
+use mint_client::ln::HtlcAmountBand;
+use mint_client::modules::ln::contracts::{ContractId, IdentifiableContract, Preimage};
 use mint_client::modules::ln::route_hints::RouteHint;
+use mint_client::modules::ln::{GatewayFee, GatewayLiquidity, LightningGateway};
 use mint_client::modules::wallet::txoproof::TxOutProof;
-use mint_client::{GatewayClient, PaymentParameters};
+use mint_client::{
+    ClientError, GatewayClient, NoteRefreshEvent, PaymentParameters, NOTE_REFRESH_INTERVAL,
+};
+use prometheus::{IntCounter, IntGauge};
 use rand::{CryptoRng, RngCore};
 use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::Notify;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::Status;
 use tracing::{debug, error, info, instrument, warn};
+use url::Url;
 
+use crate::accounting::FederationAccounting;
+use crate::correlation::CorrelationId;
+use crate::events::OutgoingPaymentStatus;
+use crate::fees::DynFeeOracle;
+use crate::gatewaylnrpc::complete_htlcs_request::cancel::Failure;
 use crate::gatewaylnrpc::complete_htlcs_request::{Action, Cancel, Settle};
+use crate::gatewaylnrpc::list_payments_response::PaymentStatus;
 use crate::gatewaylnrpc::{
-    CompleteHtlcsRequest, PayInvoiceRequest, PayInvoiceResponse, SubscribeInterceptHtlcsRequest,
-    SubscribeInterceptHtlcsResponse,
+    CompleteHtlcsRequest, CompleteHtlcsResponse, ListPaymentsRequest, PayInvoiceRequest,
+    PayInvoiceResponse, SubscribeInterceptHtlcsRequest, SubscribeInterceptHtlcsResponse,
 };
+use crate::htlc_policy::{DynHtlcInterceptorPolicy, HtlcDecision, InterceptedHtlc};
 use crate::lnrpc_client::ILnRpcClient;
-use crate::rpc::{FederationInfo, GatewayRpcSender, LightningReconnectPayload};
-use crate::utils::retry;
+use crate::metrics::{HTLC_QUEUE_DEPTH, HTLC_QUEUE_REJECTED};
+use crate::migration::FederationState;
+use crate::rpc::{
+    BridgeHtlcPayload, FederationInfo, FirstHopConstraint, GatewayRpcSender, HtlcQueueInfo,
+    LightningReconnectPayload,
+};
+use crate::sweep::select_sweep_gateway;
+use crate::test_payment::{
+    TestPaymentReport, TEST_PAYMENT_MAX_DELAY, TEST_PAYMENT_MAX_FEE_PERCENT,
+};
+use crate::utils::{retry, Throttle};
 use crate::{GatewayError, Result};
 
 /// How long a gateway announcement stays valid
 const GW_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(600);
+/// How far back the payments of the lightning node are matched against
+/// unclaimed outgoing contracts
+const PAYMENT_RECONCILIATION_LOOKBACK: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+/// How often unclaimed outgoing contracts are matched against the payments of
+/// the lightning node, so payments still pending on the last pass get claimed
+/// once they succeed
+const PAYMENT_RECONCILIATION_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the gateway tries to reclaim the funds of failed preimage
+/// purchases whose preimage the federation is still decrypting
+const PREIMAGE_RECLAIM_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the gateway tries to settle an HTLC whose preimage it bought
+/// before leaving it to the background retries
+const HTLC_SETTLE_ATTEMPTS: u32 = 5;
+/// How often the gateway retries settling the HTLCs whose preimages it bought
+/// but couldn't settle right away
+const HTLC_SETTLE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the gateway fees of decrypted incoming contracts are claimed
+const GATEWAY_FEE_CLAIM_INTERVAL: Duration = Duration::from_secs(30);
+/// Most intercepted HTLCs of a federation waiting to be processed, further
+/// HTLCs are cancelled right away so a slow federation can't make them pile up
+/// in memory
+const HTLC_QUEUE_CAPACITY: usize = 256;
+/// Most intercepted HTLCs of a federation processed at once, so a slow HTLC
+/// policy webhook or federation doesn't hold up the HTLCs queued behind it
+const HTLC_PROCESSING_CONCURRENCY: usize = 16;
+/// Least time between two fetches of the notes pending issuance by the
+/// liquidity checks of intercepted HTLCs
+const LIQUIDITY_NOTE_FETCH_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Clone)]
 pub struct GatewayActor {
@@ -38,12 +97,84 @@ pub struct GatewayActor {
     task_group: TaskGroup,
     gw_rpc: GatewayRpcSender,
     sender: Option<Sender<Arc<AtomicBool>>>,
+    /// Route hints to the lightning node, refreshed when its channels change
+    route_hints: Arc<RwLock<Vec<RouteHint>>>,
+    fee_oracle: DynFeeOracle,
+    /// Fees last announced to the federation
+    fees: Arc<RwLock<GatewayFee>>,
+    /// Wakes the registration task to announce the gateway again right away
+    reregister: Arc<Notify>,
+    /// Operator policy asked about every intercepted HTLC
+    htlc_policy: DynHtlcInterceptorPolicy,
+    htlc_queue: Arc<HtlcQueueStats>,
+    /// Limits how often liquidity checks ask the federation for our notes
+    liquidity_note_fetches: Arc<Throttle>,
+    /// While set the gateway neither announces itself to the federation nor
+    /// routes payments for it
+    paused: Arc<AtomicBool>,
+    /// Whether intercepted HTLCs paying offers of the gateway's other
+    /// federations are routed through them
+    bridge_federations: bool,
+}
+
+/// Counters of the queue of intercepted HTLCs, see [`HtlcQueueInfo`], which
+/// are also exported as Prometheus metrics
+struct HtlcQueueStats {
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    rejected: AtomicU64,
+    depth_gauge: IntGauge,
+    rejected_counter: IntCounter,
+}
+
+impl HtlcQueueStats {
+    fn new(federation_id: &FederationId) -> Self {
+        let federation = federation_id.to_string();
+        Self {
+            depth: AtomicUsize::new(0),
+            max_depth: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+            depth_gauge: HTLC_QUEUE_DEPTH.with_label_values(&[&federation]),
+            rejected_counter: HTLC_QUEUE_REJECTED.with_label_values(&[&federation]),
+        }
+    }
+
+    fn push(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+        self.depth_gauge.inc();
+    }
+
+    fn pop(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        self.depth_gauge.dec();
+    }
+
+    fn reject(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        self.rejected_counter.inc();
+    }
+
+    fn info(&self) -> HtlcQueueInfo {
+        HtlcQueueInfo {
+            depth: self.depth.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
 }
 
+/// Receives the progress of an outgoing payment
+pub type ReportStatus<'a> = &'a (dyn Fn(OutgoingPaymentStatus) + Send + Sync);
+
 #[derive(Debug, Clone)]
 pub enum BuyPreimage {
     Internal((OutPoint, ContractId)),
-    External(Preimage),
+    /// Paid over lightning, which charged `routing_fee` on top of the invoice
+    External {
+        preimage: Preimage,
+        routing_fee: Amount,
+    },
 }
 
 type HTLCStream = Pin<
@@ -55,25 +186,81 @@ type HTLCStream = Pin<
 >;
 
 impl GatewayActor {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         client: Arc<GatewayClient>,
         lnrpc: Arc<RwLock<dyn ILnRpcClient>>,
         route_hints: Vec<RouteHint>,
         task_group: TaskGroup,
         gw_rpc: GatewayRpcSender,
+        fee_oracle: DynFeeOracle,
+        htlc_policy: DynHtlcInterceptorPolicy,
+        bridge_federations: bool,
     ) -> Result<Self> {
+        let fees = Arc::new(RwLock::new(
+            fee_oracle.fees(client.notes().await.total_amount()).await,
+        ));
+
+        let route_hints = Arc::new(RwLock::new(route_hints));
+        let reregister = Arc::new(Notify::new());
+        let paused = Arc::new(AtomicBool::new(client.routing_paused().await));
+
         let register_client = client.clone();
+        let register_paused = paused.clone();
+        let register_route_hints = route_hints.clone();
+        let register_notify = reregister.clone();
+        let register_fee_oracle = fee_oracle.clone();
+        let register_fees = fees.clone();
+        let register_lnrpc = lnrpc.clone();
         let mut tg = task_group.make_subgroup().await;
-        tg.spawn("Register with federation", |_| async move {
+        tg.spawn("Register with federation", |handle| async move {
+            let mut shutdown_rx = handle.make_shutdown_rx().await;
             loop {
+                // Clients stop routing through us once the registration we
+                // made before the pause expired
+                if register_paused.load(Ordering::Relaxed) {
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        _ = register_notify.notified() => continue,
+                    }
+                }
+
+                // Guardians may have upgraded since we last registered
+                if let Err(e) = register_client.negotiate_api_versions().await {
+                    warn!("Failed to negotiate API versions: {e}");
+                }
+
+                // Every announcement carries the fees the oracle currently asks for
+                let balance = register_client.notes().await.total_amount();
+                let fees = register_fee_oracle.fees(balance).await;
+                *register_fees.write().await = fees;
+
+                // Clients skip gateways whose channels can't route their payment
+                let liquidity = match register_lnrpc.read().await.liquidity().await {
+                    Ok(liquidity) => Some(GatewayLiquidity {
+                        max_receivable: Amount::from_msats(liquidity.inbound_msat),
+                        max_payable: Amount::from_msats(liquidity.outbound_msat),
+                    }),
+                    Err(e) => {
+                        warn!("Failed to get liquidity of the lightning node: {e}");
+                        None
+                    }
+                };
+
+                let route_hints = register_route_hints.read().await.clone();
+
                 // Retry gateway registration
-                match retry(
+                let retry_in = match retry(
                     String::from("Register With Federation"),
                     #[allow(clippy::unit_arg)]
                     || async {
-                        let gateway_registration = register_client
-                            .config()
-                            .to_gateway_registration_info(route_hints.clone(), GW_ANNOUNCEMENT_TTL);
+                        let gateway_registration =
+                            register_client.config().to_gateway_registration_info(
+                                route_hints.clone(),
+                                fees,
+                                liquidity,
+                                GW_ANNOUNCEMENT_TTL,
+                            );
                         Ok(register_client
                             .register_with_federation(gateway_registration.clone())
                             .await?)
@@ -85,30 +272,218 @@ impl GatewayActor {
                 {
                     Ok(_) => {
                         info!("Connected with federation");
-                        tokio::time::sleep(GW_ANNOUNCEMENT_TTL / 2).await;
+                        GW_ANNOUNCEMENT_TTL / 2
                     }
                     Err(e) => {
                         warn!("Failed to connect with federation: {}", e);
-                        tokio::time::sleep(GW_ANNOUNCEMENT_TTL / 4).await;
+                        GW_ANNOUNCEMENT_TTL / 4
                     }
+                };
+
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    _ = tokio::time::sleep(retry_in) => {}
+                    _ = register_notify.notified() => {}
                 }
             }
         })
         .await;
 
+        let refresh_client = client.clone();
+        tg.spawn("Refresh deprecated notes", |handle| async move {
+            let mut shutdown_rx = handle.make_shutdown_rx().await;
+            loop {
+                match refresh_client
+                    .refresh_deprecated_notes(rand::rngs::OsRng)
+                    .await
+                {
+                    Ok(Some(NoteRefreshEvent::ManualActionRequired { amount, reason, .. })) => {
+                        error!(
+                            "Notes worth {} in deprecated tiers need manual action: {}",
+                            amount, reason
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to check for deprecated note tiers: {}", e),
+                }
+
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    _ = tokio::time::sleep(NOTE_REFRESH_INTERVAL) => {}
+                }
+            }
+        })
+        .await;
+
+        let reclaim_client = client.clone();
+        tg.spawn("Reclaim failed preimage purchases", |handle| async move {
+            let mut shutdown_rx = handle.make_shutdown_rx().await;
+            loop {
+                for (contract_id, purchase) in reclaim_client.list_preimage_purchases().await {
+                    if purchase.state != PreimagePurchaseState::Reclaiming {
+                        continue;
+                    }
+                    match reclaim_client
+                        .reclaim_preimage_purchase(contract_id, rand::rngs::OsRng)
+                        .await
+                    {
+                        Ok(PreimagePurchaseState::Reclaiming) => {}
+                        Ok(state) => info!(%contract_id, ?state, "Finished reclaiming funds"),
+                        Err(e) => warn!(%contract_id, "Failed to reclaim funds: {}", e),
+                    }
+                }
+
+                tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    _ = tokio::time::sleep(PREIMAGE_RECLAIM_INTERVAL) => {}
+                }
+            }
+        })
+        .await;
+
+        let htlc_queue = Arc::new(HtlcQueueStats::new(
+            &client.config().client_config.federation_id,
+        ));
         let mut actor = Self {
             client,
             lnrpc,
             task_group: tg,
             gw_rpc,
             sender: None,
+            route_hints,
+            fee_oracle,
+            fees,
+            reregister,
+            htlc_policy,
+            htlc_queue,
+            liquidity_note_fetches: Arc::new(Throttle::new(LIQUIDITY_NOTE_FETCH_INTERVAL)),
+            paused,
+            bridge_federations,
         };
 
+        // Claims outgoing contracts paid before a crash right away, and the
+        // ones whose payments were still pending on later passes
+        let reconcile_actor = actor.clone();
+        actor
+            .task_group
+            .spawn("Reconcile outgoing payments", |handle| async move {
+                let mut shutdown_rx = handle.make_shutdown_rx().await;
+                loop {
+                    if let Err(e) = reconcile_actor.reconcile_outgoing_payments().await {
+                        warn!("Failed to reconcile outgoing payments: {}", e);
+                    }
+
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        _ = tokio::time::sleep(PAYMENT_RECONCILIATION_INTERVAL) => {}
+                    }
+                }
+            })
+            .await;
+
+        // The federation paid the recipients of these HTLCs with our funds
+        // already, so settling them is the only way to get the funds back
+        let settle_actor = actor.clone();
+        actor
+            .task_group
+            .spawn("Settle HTLCs of bought preimages", |handle| async move {
+                let mut shutdown_rx = handle.make_shutdown_rx().await;
+                loop {
+                    settle_actor.settle_pending_htlcs().await;
+
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        _ = tokio::time::sleep(HTLC_SETTLE_RETRY_INTERVAL) => {}
+                    }
+                }
+            })
+            .await;
+
+        // Claims the fees of the incoming contracts we funded, retrying the ones
+        // the federation didn't accept yet
+        let fee_actor = actor.clone();
+        actor
+            .task_group
+            .spawn("Claim gateway fees", |handle| async move {
+                let mut shutdown_rx = handle.make_shutdown_rx().await;
+                loop {
+                    fee_actor
+                        .client
+                        .claim_queued_gateway_fees(rand::rngs::OsRng)
+                        .await;
+
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        _ = tokio::time::sleep(GATEWAY_FEE_CLAIM_INTERVAL) => {}
+                    }
+                }
+            })
+            .await;
+
         actor.subscribe_htlcs().await?;
 
         Ok(actor)
     }
 
+    /// Advertises `route_hints` and the current liquidity of the lightning
+    /// node to the federation after its channels changed, e.g. were spliced,
+    /// returns whether the route hints changed
+    ///
+    /// The gateway only registers again right away if they did, a changed
+    /// liquidity alone is announced with the next periodic registration. The
+    /// HTLC subscription is kept, since intercepted HTLCs are routed by the
+    /// short channel id of the federation, not of a channel.
+    pub async fn update_channels(&self, route_hints: Vec<RouteHint>) -> bool {
+        let mut current = self.route_hints.write().await;
+        if *current == route_hints {
+            return false;
+        }
+        *current = route_hints;
+        self.reregister.notify_one();
+        true
+    }
+
+    /// Stops announcing the gateway to the federation and routing payments
+    /// for it, e.g. while it upgrades, keeping the state of the federation
+    ///
+    /// Intercepted HTLCs are cancelled with a temporary failure meanwhile,
+    /// payments already in progress are finished.
+    ///
+    /// The pause is saved, so it lasts until [`Self::resume`] even if the
+    /// gateway restarts.
+    pub async fn pause(&self) {
+        info!("Pausing federation");
+        self.client.set_routing_paused(true).await;
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Routes payments for the federation again after [`Self::pause`] and
+    /// announces the gateway right away
+    pub async fn resume(&self) {
+        info!("Resuming federation");
+        self.client.set_routing_paused(false).await;
+        self.paused.store(false, Ordering::Relaxed);
+        self.reregister.notify_one();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn federation_id(&self) -> FederationId {
+        self.client.config().client_config.federation_id.clone()
+    }
+
+    /// Whether a user of the federation offers to sell the preimage of
+    /// `payment_hash`, unknown offers are reported as missing
+    pub async fn offer_exists(&self, payment_hash: sha256::Hash) -> bool {
+        self.client
+            .ln_client()
+            .offer_exists(payment_hash)
+            .await
+            .unwrap_or(false)
+    }
+
     pub async fn stop_subscribing_htlcs(&mut self) -> Result<()> {
         if let Some(sender) = &self.sender {
             sender
@@ -162,10 +537,19 @@ impl GatewayActor {
             .lnrpc
             .read()
             .await
-            .subscribe_htlcs(SubscribeInterceptHtlcsRequest { short_channel_id })
+            .subscribe_htlcs(SubscribeInterceptHtlcsRequest {
+                short_channel_id,
+                // set by the `FencedLnRpcClient` if the gateway runs with standbys
+                fencing_token: None,
+            })
             .await?;
         info!("Subscribed to HTLCs with {:?}", short_channel_id);
 
+        // The stream is drained right away into a bounded queue, so HTLCs don't
+        // pile up in the buffers of the stream while the federation is slow
+        let (queue_sender, queue) =
+            mpsc::channel::<(SubscribeInterceptHtlcsResponse, CorrelationId)>(HTLC_QUEUE_CAPACITY);
+
         let actor = self.to_owned();
         let lnrpc_copy = self.lnrpc.to_owned();
         let gw_rpc_copy = self.gw_rpc.clone();
@@ -173,12 +557,7 @@ impl GatewayActor {
             .spawn(
                 "Subscribe to intercepted HTLCs in stream",
                 move |subscription| async move {
-                    while let Some(SubscribeInterceptHtlcsResponse {
-                        payment_hash,
-                        outgoing_amount_msat,
-                        intercepted_htlc_id,
-                        ..
-                    }) = Self::wait_for_htlc_or_shutdown(
+                    while let Some(htlc) = Self::wait_for_htlc_or_shutdown(
                         &mut stream,
                         &mut receiver,
                         gw_rpc_copy.clone(),
@@ -191,115 +570,410 @@ impl GatewayActor {
                             break;
                         }
 
-                        // TODO: Assert short channel id matches the one we subscribed to, or cancel
-                        // processing of intercepted HTLC TODO: Assert the offered
-                        // fee derived from invoice amount and outgoing amount is acceptable or
-                        // cancel processing of intercepted HTLC TODO:
-                        // Assert the HTLC expiry or cancel processing of
-                        // intercepted HTLC
-
-                        let hash = match sha256::Hash::from_slice(&payment_hash) {
-                            Ok(hash) => hash,
-                            Err(e) => {
-                                let fail = "Failed to parse payment hash";
-
-                                error!("{}: {:?}", fail, e);
-                                let _ = lnrpc_copy
-                                    .read()
-                                    .await
-                                    .complete_htlc(CompleteHtlcsRequest {
-                                        intercepted_htlc_id,
-                                        action: Some(Action::Cancel(Cancel {
-                                            reason: fail.to_string(),
-                                        })),
-                                    })
-                                    .await;
-                                continue;
-                            }
-                        };
+                        actor.enqueue_htlc(&queue_sender, htlc).await;
+                    }
+                },
+            )
+            .await;
 
-                        let amount_msat = Amount::from_msats(outgoing_amount_msat);
+        let actor = self.to_owned();
+        self.task_group
+            .spawn("Process intercepted HTLCs", move |_| async move {
+                // Ends once the subscription stopped and the queue is drained
+                ReceiverStream::new(queue)
+                    .for_each_concurrent(HTLC_PROCESSING_CONCURRENCY, |(htlc, correlation_id)| {
+                        actor.htlc_queue.pop();
+                        actor.process_intercepted_htlc(htlc, short_channel_id, correlation_id)
+                    })
+                    .await;
+            })
+            .await;
 
-                        let (outpoint, contract_id) = match actor
-                            .buy_preimage_from_federation(&hash, &amount_msat)
-                            .await
-                        {
-                            Ok((outpoint, contract_id)) => (outpoint, contract_id),
-                            Err(e) => {
-                                error!("Failed to buy preimage: {:?}", e);
-                                // Note: this specific complete htlc requires no further action.
-                                // If we fail to send the complete htlc message, or get an error
-                                // result, lightning node will still
-                                // cancel HTCL after expiry period lapses.
-                                // Result can be safely ignored.
-                                // TODO: make sure this succeeded?
-                                let _ = lnrpc_copy
-                                    .read()
-                                    .await
-                                    .complete_htlc(CompleteHtlcsRequest {
-                                        intercepted_htlc_id,
-                                        action: Some(Action::Cancel(Cancel {
-                                            reason: e.to_string(),
-                                        })),
-                                    })
-                                    .await;
-                                continue;
-                            }
-                        };
+        Ok(())
+    }
 
-                        match actor
-                            .pay_invoice_buy_preimage_finalize(BuyPreimage::Internal((
-                                outpoint,
-                                contract_id,
-                            )))
+    /// Queues an intercepted HTLC for processing, or cancels it with a
+    /// temporary failure if the queue is full
+    async fn enqueue_htlc(
+        &self,
+        queue: &Sender<(SubscribeInterceptHtlcsResponse, CorrelationId)>,
+        htlc: SubscribeInterceptHtlcsResponse,
+    ) {
+        let correlation_id = CorrelationId::random();
+        // Counted before sending, so the processing task never sees a negative depth
+        self.htlc_queue.push();
+        let Err(e) = queue.try_send((htlc, correlation_id)) else {
+            return;
+        };
+        self.htlc_queue.pop();
+
+        let (htlc, _) = e.into_inner();
+        self.htlc_queue.reject();
+        warn!(
+            %correlation_id,
+            payment_hash = %htlc.payment_hash.to_hex(),
+            "HTLC queue is full, cancelling intercepted HTLC"
+        );
+        let cancel = Cancel {
+            reason: "Gateway is overloaded".to_string(),
+            failure: Failure::TemporaryChannelFailure.into(),
+            htlc_msat: htlc.outgoing_amount_msat,
+        };
+        self.cancel_htlc(htlc.intercepted_htlc_id, cancel, correlation_id)
+            .await;
+    }
+
+    /// Routes an HTLC intercepted over the federation's channel, through
+    /// another federation of the gateway if only that one has an offer for it
+    #[instrument(
+        skip_all,
+        fields(
+            %correlation_id,
+            payment_hash = %htlc.payment_hash.to_hex(),
+            incoming_amount_msat = htlc.incoming_amount_msat,
+            outgoing_amount_msat = htlc.outgoing_amount_msat,
+        )
+    )]
+    async fn process_intercepted_htlc(
+        &self,
+        htlc: SubscribeInterceptHtlcsResponse,
+        short_channel_id: u64,
+        correlation_id: CorrelationId,
+    ) {
+        // The federation is only known to own HTLCs over the channel id we
+        // assigned it
+        let htlc_scid = htlc.short_channel_id;
+        if htlc_scid != short_channel_id {
+            warn!(
+                htlc_scid,
+                short_channel_id, "Rejecting HTLC over another federation's channel"
+            );
+            let cancel = Cancel {
+                reason: format!("Unknown short channel id {htlc_scid}"),
+                ..Default::default()
+            };
+            self.cancel_htlc(htlc.intercepted_htlc_id, cancel, correlation_id)
+                .await;
+            return;
+        }
+
+        // TODO: Assert the HTLC expiry or cancel processing of intercepted HTLC
+
+        let hash = match sha256::Hash::from_slice(&htlc.payment_hash) {
+            Ok(hash) => hash,
+            Err(e) => {
+                let fail = "Failed to parse payment hash";
+
+                error!("{}: {:?}", fail, e);
+                let cancel = Cancel {
+                    reason: fail.to_string(),
+                    ..Default::default()
+                };
+                self.cancel_htlc(htlc.intercepted_htlc_id, cancel, correlation_id)
+                    .await;
+                return;
+            }
+        };
+
+        if self.bridge_federations && !self.offer_exists(hash).await {
+            self.bridge_htlc(htlc, hash, correlation_id).await;
+            return;
+        }
+
+        self.route_htlc(htlc, hash, None, correlation_id).await;
+    }
+
+    /// Hands an HTLC that pays no offer of the federation to the gateway,
+    /// which routes it through another federation with an offer for it, or
+    /// cancels the HTLC if there is none
+    async fn bridge_htlc(
+        &self,
+        htlc: SubscribeInterceptHtlcsResponse,
+        payment_hash: sha256::Hash,
+        correlation_id: CorrelationId,
+    ) {
+        let intercepted_htlc_id = htlc.intercepted_htlc_id.clone();
+        let payload = BridgeHtlcPayload {
+            federation_id: self.federation_id(),
+            payment_hash,
+            htlc,
+            correlation_id,
+        };
+        match self.gw_rpc.send(payload).await {
+            Ok(federation_id) => {
+                debug!(%federation_id, "Bridged intercepted HTLC to another federation");
+            }
+            Err(e) => {
+                warn!("Failed to bridge intercepted HTLC: {}", e);
+                let cancel = Cancel {
+                    reason: e.to_string(),
+                    ..Default::default()
+                };
+                self.cancel_htlc(intercepted_htlc_id, cancel, correlation_id)
+                    .await;
+            }
+        }
+    }
+
+    /// Routes an HTLC intercepted for the federation `bridged_from` of the
+    /// gateway that pays an offer of this one, see [`Self::bridge_htlc`]
+    #[instrument(
+        skip_all,
+        fields(
+            %correlation_id,
+            payment_hash = %hash.to_hex(),
+            federation_id = %self.federation_id(),
+            %bridged_from,
+            incoming_amount_msat = htlc.incoming_amount_msat,
+            outgoing_amount_msat = htlc.outgoing_amount_msat,
+        )
+    )]
+    pub async fn process_bridged_htlc(
+        &self,
+        htlc: SubscribeInterceptHtlcsResponse,
+        hash: sha256::Hash,
+        bridged_from: FederationId,
+        correlation_id: CorrelationId,
+    ) {
+        self.route_htlc(htlc, hash, Some(bridged_from), correlation_id)
+            .await;
+    }
+
+    /// Buys the preimage of an intercepted HTLC from the federation and
+    /// settles the HTLC with it, or cancels the HTLC if that fails
+    ///
+    /// `bridged_from` is the federation the HTLC was intercepted for if it
+    /// isn't this one.
+    async fn route_htlc(
+        &self,
+        htlc: SubscribeInterceptHtlcsResponse,
+        hash: sha256::Hash,
+        bridged_from: Option<FederationId>,
+        correlation_id: CorrelationId,
+    ) {
+        let SubscribeInterceptHtlcsResponse {
+            incoming_amount_msat,
+            outgoing_amount_msat,
+            incoming_expiry,
+            short_channel_id: htlc_scid,
+            intercepted_htlc_id,
+            ..
+        } = htlc;
+
+        if self.is_paused() {
+            info!("Rejecting HTLC for paused federation");
+            let cancel = Cancel {
+                reason: GatewayError::FederationPaused.to_string(),
+                failure: Failure::TemporaryChannelFailure.into(),
+                htlc_msat: outgoing_amount_msat,
+            };
+            self.cancel_htlc(intercepted_htlc_id, cancel, correlation_id)
+                .await;
+            return;
+        }
+
+        let amount_msat = Amount::from_msats(outgoing_amount_msat);
+
+        // Out of band HTLCs are failed with a code telling the sender why
+        if let Err(failure) = self.ensure_htlc_amount(amount_msat).await {
+            info!("Rejecting intercepted HTLC: {}", failure.reason);
+            self.cancel_htlc(intercepted_htlc_id, failure, correlation_id)
+                .await;
+            return;
+        }
+
+        // Fail fast instead of after setting up the contract
+        let checks = async {
+            self.ensure_fee(Amount::from_msats(incoming_amount_msat), amount_msat)
+                .await?;
+            self.ensure_liquidity(amount_msat).await
+        };
+        if let Err(e) = checks.await {
+            warn!("Rejecting intercepted HTLC: {}", e);
+            let cancel = Cancel {
+                reason: e.to_string(),
+                ..Default::default()
+            };
+            self.cancel_htlc(intercepted_htlc_id, cancel, correlation_id)
+                .await;
+            return;
+        }
+
+        let intercepted = InterceptedHtlc {
+            federation_id: self.federation_id().to_string(),
+            bridged_from: bridged_from.map(|federation_id| federation_id.to_string()),
+            payment_hash: hash.to_hex(),
+            incoming_amount_msat,
+            outgoing_amount_msat,
+            incoming_expiry,
+            short_channel_id: htlc_scid,
+        };
+        if let HtlcDecision::Reject { reason } = self.htlc_policy.decide(&intercepted).await {
+            info!("HTLC policy rejected intercepted HTLC: {}", reason);
+            let cancel = Cancel {
+                reason,
+                ..Default::default()
+            };
+            self.cancel_htlc(intercepted_htlc_id, cancel, correlation_id)
+                .await;
+            return;
+        }
+
+        let (outpoint, contract_id) =
+            match self.buy_preimage_from_federation(&hash, &amount_msat).await {
+                Ok((outpoint, contract_id)) => (outpoint, contract_id),
+                Err(e) => {
+                    error!("Failed to buy preimage: {:?}", e);
+                    // Note: this specific complete htlc requires no further action.
+                    // If we fail to send the complete htlc message, or get an error
+                    // result, lightning node will still
+                    // cancel HTCL after expiry period lapses.
+                    // Result can be safely ignored.
+                    // TODO: make sure this succeeded?
+                    let cancel = Cancel {
+                        reason: e.to_string(),
+                        ..Default::default()
+                    };
+                    self.cancel_htlc(intercepted_htlc_id, cancel, correlation_id)
+                        .await;
+                    return;
+                }
+            };
+
+        match self
+            .pay_invoice_buy_preimage_finalize(BuyPreimage::Internal((outpoint, contract_id)))
+            .await
+        {
+            Ok(preimage) => {
+                info!("Successfully processed intercepted HTLC");
+                // The federation already paid the recipient with our funds, so
+                // settling the HTLC is the only way to get them back
+                let settled = retry(
+                    "Settle intercepted HTLC".to_string(),
+                    || async {
+                        let settle = Action::Settle(Settle {
+                            preimage: preimage.0.to_vec(),
+                        });
+                        self.complete_htlc(intercepted_htlc_id.clone(), settle, correlation_id)
                             .await
-                        {
-                            Ok(preimage) => {
-                                info!("Successfully processed intercepted HTLC");
-                                if let Err(e) = lnrpc_copy
-                                    .read()
-                                    .await
-                                    .complete_htlc(CompleteHtlcsRequest {
-                                        intercepted_htlc_id,
-                                        action: Some(Action::Settle(Settle {
-                                            preimage: preimage.0.to_vec(),
-                                        })),
-                                    })
-                                    .await
-                                {
-                                    error!("Failed to complete HTLC: {:?}", e);
-                                    // Note: To prevent loss of funds for the
-                                    // gateway,
-                                    // we should either retry completing the
-                                    // htlc or
-                                    // reclaim funds from the federation
-                                };
-                            }
-                            Err(e) => {
-                                error!("Failed to process intercepted HTLC: {:?}", e);
-                                // Note: this specific complete htlc requires no further action.
-                                // If we fail to send the complete htlc message, or get an error
-                                // result, lightning node will still
-                                // cancel HTCL after expiry period lapses.
-                                // Result can be safely ignored.
-                                let _ = lnrpc_copy
-                                    .read()
-                                    .await
-                                    .complete_htlc(CompleteHtlcsRequest {
-                                        intercepted_htlc_id,
-                                        action: Some(Action::Cancel(Cancel {
-                                            reason: e.to_string(),
-                                        })),
-                                    })
-                                    .await;
-                            }
+                            .map_err(anyhow::Error::from)
+                    },
+                    Duration::from_secs(1),
+                    HTLC_SETTLE_ATTEMPTS,
+                )
+                .await;
+                match settled {
+                    Ok(_) => {
+                        self.client
+                            .record_payment(
+                                PaymentKind::Incoming { contract_id },
+                                amount_msat,
+                                Amount::from_msats(
+                                    incoming_amount_msat.saturating_sub(outgoing_amount_msat),
+                                ),
+                            )
+                            .await;
+                    }
+                    Err(e) => {
+                        warn!(%contract_id, "Failed to settle HTLC, retrying later: {:?}", e);
+                        let state = PreimagePurchaseState::SettlePending {
+                            intercepted_htlc_id,
+                            preimage,
+                            fee: Amount::from_msats(
+                                incoming_amount_msat.saturating_sub(outgoing_amount_msat),
+                            ),
                         };
+                        self.client
+                            .set_preimage_purchase_state(contract_id, state)
+                            .await;
                     }
-                },
-            )
-            .await;
+                };
+            }
+            Err(e) => {
+                error!("Failed to process intercepted HTLC: {:?}", e);
+                // Note: this specific complete htlc requires no further action.
+                // If we fail to send the complete htlc message, or get an error
+                // result, lightning node will still
+                // cancel HTCL after expiry period lapses.
+                // Result can be safely ignored.
+                let cancel = Cancel {
+                    reason: e.to_string(),
+                    ..Default::default()
+                };
+                self.cancel_htlc(intercepted_htlc_id, cancel, correlation_id)
+                    .await;
+            }
+        };
+    }
 
-        Ok(())
+    /// Sends the outcome of an intercepted HTLC to the lightning node
+    async fn complete_htlc(
+        &self,
+        intercepted_htlc_id: Vec<u8>,
+        action: Action,
+        correlation_id: CorrelationId,
+    ) -> Result<CompleteHtlcsResponse> {
+        self.lnrpc
+            .read()
+            .await
+            .complete_htlc(CompleteHtlcsRequest {
+                intercepted_htlc_id,
+                // set by the `FencedLnRpcClient` if the gateway runs with standbys
+                fencing_token: None,
+                action: Some(action),
+                correlation_id: Some(correlation_id.to_string()),
+            })
+            .await
+    }
+
+    /// Retries settling the HTLCs whose preimages we bought, but which
+    /// couldn't be settled right away
+    pub async fn settle_pending_htlcs(&self) {
+        for (contract_id, purchase) in self.client.list_preimage_purchases().await {
+            let PreimagePurchaseState::SettlePending {
+                intercepted_htlc_id,
+                preimage,
+                fee,
+            } = purchase.state
+            else {
+                continue;
+            };
+
+            let settle = Action::Settle(Settle {
+                preimage: preimage.0.to_vec(),
+            });
+            match self
+                .complete_htlc(intercepted_htlc_id, settle, CorrelationId::random())
+                .await
+            {
+                Ok(_) => {
+                    info!(%contract_id, "Settled HTLC of bought preimage");
+                    self.client
+                        .set_preimage_purchase_state(contract_id, PreimagePurchaseState::Decrypted)
+                        .await;
+                    self.client
+                        .record_payment(PaymentKind::Incoming { contract_id }, purchase.amount, fee)
+                        .await;
+                }
+                Err(e) => warn!(%contract_id, "Failed to settle HTLC: {:?}", e),
+            }
+        }
+    }
+
+    /// Fails an intercepted HTLC, the lightning node cancels it after its
+    /// expiry anyway if that doesn't succeed
+    async fn cancel_htlc(
+        &self,
+        intercepted_htlc_id: Vec<u8>,
+        cancel: Cancel,
+        correlation_id: CorrelationId,
+    ) {
+        if let Err(e) = self
+            .complete_htlc(intercepted_htlc_id, Action::Cancel(cancel), correlation_id)
+            .await
+        {
+            debug!("Failed to cancel HTLC: {:?}", e);
+        }
     }
 
     async fn fetch_all_notes(&self) {
@@ -327,26 +1001,85 @@ impl GatewayActor {
         Ok(preimage)
     }
 
-    #[instrument(skip_all, fields(%contract_id))]
     pub async fn pay_invoice(&self, contract_id: ContractId) -> Result<OutPoint> {
-        self.pay_invoice_buy_preimage_finalize_and_claim(
-            contract_id,
-            self.pay_invoice_buy_preimage(contract_id).await?,
-        )
-        .await
+        self.pay_invoice_via(contract_id, FirstHopConstraint::default())
+            .await
+    }
+
+    /// Pays the invoice of an outgoing contract, a payment over lightning
+    /// leaves the node over a channel satisfying `first_hop`
+    pub async fn pay_invoice_via(
+        &self,
+        contract_id: ContractId,
+        first_hop: FirstHopConstraint,
+    ) -> Result<OutPoint> {
+        self.pay_invoice_reporting(contract_id, first_hop, CorrelationId::random(), &|_| {})
+            .await
+    }
+
+    /// Like [`Self::pay_invoice_via`], reporting every step of the payment to
+    /// `report`
+    #[instrument(skip_all, fields(%contract_id, ?first_hop, %correlation_id))]
+    pub async fn pay_invoice_reporting(
+        &self,
+        contract_id: ContractId,
+        first_hop: FirstHopConstraint,
+        correlation_id: CorrelationId,
+        report: ReportStatus<'_>,
+    ) -> Result<OutPoint> {
+        if self.is_paused() {
+            return Err(GatewayError::FederationPaused);
+        }
+        let buy_preimage = self
+            .buy_preimage_reporting(contract_id, first_hop, correlation_id, report)
+            .await?;
+        self.finalize_and_claim_reporting(contract_id, buy_preimage, report)
+            .await
     }
 
-    #[instrument(skip_all, fields(%contract_id), err)]
     pub async fn pay_invoice_buy_preimage(&self, contract_id: ContractId) -> Result<BuyPreimage> {
+        self.pay_invoice_buy_preimage_via(contract_id, FirstHopConstraint::default())
+            .await
+    }
+
+    pub async fn pay_invoice_buy_preimage_via(
+        &self,
+        contract_id: ContractId,
+        first_hop: FirstHopConstraint,
+    ) -> Result<BuyPreimage> {
+        self.buy_preimage_reporting(contract_id, first_hop, CorrelationId::random(), &|_| {})
+            .await
+    }
+
+    #[instrument(skip_all, fields(%contract_id, %correlation_id), err)]
+    async fn buy_preimage_reporting(
+        &self,
+        contract_id: ContractId,
+        first_hop: FirstHopConstraint,
+        correlation_id: CorrelationId,
+        report: ReportStatus<'_>,
+    ) -> Result<BuyPreimage> {
         debug!("Fetching contract");
         let contract_account = self.client.fetch_outgoing_contract(contract_id).await?;
+        report(OutgoingPaymentStatus::ContractFetched);
 
+        // We pay the contract's invoice but claim the contract, so both have to
+        // agree before any money leaves our node
         let payment_params = match self
             .client
             .validate_outgoing_account(&contract_account)
             .await
-        {
+            .and_then(|params| {
+                params.verify_invoice(&contract_account)?;
+                Ok(params)
+            }) {
             Ok(payment_params) => payment_params,
+            // Another gateway of the federation was chosen to pay the contract, it's
+            // neither ours to pay nor to cancel
+            Err(ClientError::NotOurKey) => {
+                debug!("Ignoring contract addressed to another gateway");
+                return Err(ClientError::NotOurKey.into());
+            }
             Err(e) => {
                 self.client
                     .cancel_outgoing_contract(contract_account)
@@ -359,10 +1092,11 @@ impl GatewayActor {
             account = ?contract_account,
             "Fetched and validated contract account"
         );
+        report(OutgoingPaymentStatus::ContractValidated);
 
         self.client
             .save_outgoing_payment(contract_account.clone())
-            .await;
+            .await?;
 
         let is_internal_payment = payment_params.maybe_internal
             && self
@@ -372,6 +1106,9 @@ impl GatewayActor {
                 .await
                 .unwrap_or(false);
 
+        report(OutgoingPaymentStatus::PaymentInFlight {
+            internal: is_internal_payment,
+        });
         Ok(if is_internal_payment {
             BuyPreimage::Internal(
                 self.buy_preimage_from_federation(
@@ -381,13 +1118,18 @@ impl GatewayActor {
                 .await?,
             )
         } else {
-            BuyPreimage::External(
-                self.buy_preimage_over_lightning(
+            let (preimage, routing_fee) = self
+                .buy_preimage_over_lightning(
                     contract_account.contract.invoice,
                     &payment_params,
+                    first_hop,
+                    correlation_id,
                 )
-                .await?,
-            )
+                .await?;
+            BuyPreimage::External {
+                preimage,
+                routing_fee,
+            }
         })
     }
 
@@ -400,24 +1142,41 @@ impl GatewayActor {
                 self.buy_preimage_from_federation_await_decryption(out_point, contract_id)
                     .await
             }
-            BuyPreimage::External(preimage) => Ok(preimage),
+            BuyPreimage::External { preimage, .. } => Ok(preimage),
         }
     }
 
-    #[instrument(skip_all, fields(?buy_preimage), err)]
     pub async fn pay_invoice_buy_preimage_finalize_and_claim(
         &self,
         contract_id: ContractId,
         buy_preimage: BuyPreimage,
+    ) -> Result<OutPoint> {
+        self.finalize_and_claim_reporting(contract_id, buy_preimage, &|_| {})
+            .await
+    }
+
+    #[instrument(skip_all, fields(?buy_preimage), err)]
+    async fn finalize_and_claim_reporting(
+        &self,
+        contract_id: ContractId,
+        buy_preimage: BuyPreimage,
+        report: ReportStatus<'_>,
     ) -> Result<OutPoint> {
         let rng = rand::rngs::OsRng;
+        // buying the preimage from the federation doesn't cost us a fee
+        let routing_fee = match buy_preimage {
+            BuyPreimage::Internal(_) => Amount::ZERO,
+            BuyPreimage::External { routing_fee, .. } => routing_fee,
+        };
 
         match self.pay_invoice_buy_preimage_finalize(buy_preimage).await {
             Ok(preimage) => {
+                report(OutgoingPaymentStatus::PreimageReceived);
                 let outpoint = self
                     .client
-                    .claim_outgoing_contract(contract_id, preimage, rng)
+                    .claim_outgoing_contract(contract_id, preimage, routing_fee, rng)
                     .await?;
+                report(OutgoingPaymentStatus::ClaimSubmitted);
                 Ok(outpoint)
             }
             Err(e) => {
@@ -451,25 +1210,48 @@ impl GatewayActor {
         out_point: OutPoint,
         contract_id: ContractId,
     ) -> Result<Preimage> {
-        let rng = rand::rngs::OsRng;
-
         match self.client.await_preimage_decryption(out_point).await {
-            Ok(preimage) => Ok(preimage),
+            Ok(preimage) => {
+                // the fee is ours whether or not the HTLC settles, so it is claimed in the
+                // background instead of delaying the payment
+                self.client
+                    .queue_gateway_fee_claim(contract_id, preimage.clone())
+                    .await;
+                self.client
+                    .set_preimage_purchase_state(contract_id, PreimagePurchaseState::Decrypted)
+                    .await;
+                Ok(preimage)
+            }
             Err(error) => {
-                warn!(%error, "Failed to decrypt preimage. Now requesting a refund");
+                warn!(%error, "Failed to decrypt preimage. Now reclaiming the funds");
+                self.client
+                    .set_preimage_purchase_state(contract_id, PreimagePurchaseState::Reclaiming)
+                    .await;
+                // if the federation is still decrypting the preimage the funds are reclaimed
+                // by the background task once it failed
                 self.client
-                    .refund_incoming_contract(contract_id, rng)
+                    .reclaim_preimage_purchase(contract_id, rand::rngs::OsRng)
                     .await?;
                 Err(GatewayError::ClientError(error))
             }
         }
     }
 
+    /// Pays `invoice` over lightning, returning its preimage and the fee the
+    /// payment was routed for
     pub async fn buy_preimage_over_lightning(
         &self,
         invoice: lightning_invoice::Invoice,
         payment_params: &PaymentParameters,
-    ) -> Result<Preimage> {
+        first_hop: FirstHopConstraint,
+        correlation_id: CorrelationId,
+    ) -> Result<(Preimage, Amount)> {
+        debug!(
+            max_fee = %payment_params.max_fee().to_string_in(AmountUnit::Sat),
+            max_fee_rate = %payment_params.max_fee_rate(),
+            ?first_hop,
+            "Paying invoice over lightning"
+        );
         match self
             .lnrpc
             .read()
@@ -478,12 +1260,17 @@ impl GatewayActor {
                 invoice: invoice.to_string(),
                 max_delay: payment_params.max_delay,
                 max_fee_percent: payment_params.max_fee_percent(),
+                outgoing_channel_id: first_hop.outgoing_channel_id,
+                first_hop_pubkey: first_hop
+                    .first_hop
+                    .map(|pubkey| pubkey.serialize().to_vec()),
+                correlation_id: Some(correlation_id.to_string()),
             })
             .await
         {
-            Ok(PayInvoiceResponse { preimage, .. }) => {
+            Ok(PayInvoiceResponse { preimage, fee_msat }) => {
                 let slice: [u8; 32] = preimage.try_into().expect("Failed to parse preimage");
-                Ok(Preimage(slice))
+                Ok((Preimage(slice), Amount::from_msats(fee_msat)))
             }
             Err(e) => Err(e),
         }
@@ -500,6 +1287,65 @@ impl GatewayActor {
             .await?)
     }
 
+    /// Claims the outgoing contracts whose invoices the lightning node paid
+    /// but that were never claimed, e.g. because the gateway crashed in between
+    ///
+    /// Contracts whose payments are still pending are left for the next pass.
+    pub async fn reconcile_outgoing_payments(&self) -> Result<()> {
+        let pending = self.client.list_pending_outgoing().await;
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let created_after = (fedimint_core::time::now() - PAYMENT_RECONCILIATION_LOOKBACK)
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        let payments: HashMap<Vec<u8>, (Vec<u8>, u64)> = self
+            .lnrpc
+            .read()
+            .await
+            .list_payments(ListPaymentsRequest { created_after })
+            .await?
+            .payments
+            .into_iter()
+            .filter(|payment| payment.status() == PaymentStatus::Succeeded)
+            .map(|payment| (payment.payment_hash, (payment.preimage, payment.fee_msat)))
+            .collect();
+
+        for contract_account in pending {
+            let contract_id = contract_account.contract.contract_id();
+            let Some((preimage, fee_msat)) = payments.get(&contract_account.contract.hash.to_vec())
+            else {
+                continue;
+            };
+            let preimage = match <[u8; 32]>::try_from(preimage.as_slice()) {
+                Ok(preimage) if sha256::Hash::hash(&preimage) == contract_account.contract.hash => {
+                    Preimage(preimage)
+                }
+                _ => {
+                    warn!(%contract_id, "Lightning node returned a wrong preimage");
+                    continue;
+                }
+            };
+
+            info!(%contract_id, "Claiming outgoing contract whose invoice was paid before");
+            if let Err(e) = self
+                .client
+                .claim_outgoing_contract(
+                    contract_id,
+                    preimage,
+                    Amount::from_msats(*fee_msat),
+                    rand::rngs::OsRng,
+                )
+                .await
+            {
+                warn!(%contract_id, "Failed to claim paid outgoing contract: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn get_deposit_address(&self) -> Result<Address> {
         let rng = rand::rngs::OsRng;
         Ok(self.client.get_new_pegin_address(rng).await)
@@ -512,31 +1358,106 @@ impl GatewayActor {
     ) -> Result<TransactionId> {
         let rng = rand::rngs::OsRng;
 
-        self.client
+        let amount = self.client.wallet_client().pegin_amount(&transaction).await;
+        let txid = self
+            .client
             .peg_in(txout_proof, transaction, rng)
             .await
-            .map_err(GatewayError::ClientError)
+            .map_err(GatewayError::ClientError)?;
+        if let Some(amount) = amount {
+            self.client
+                .record_payment(PaymentKind::Deposit { txid }, amount, Amount::ZERO)
+                .await;
+        }
+        Ok(txid)
     }
 
+    /// Pegs out `amount` to `address`, or the whole balance after fees if
+    /// `amount` is `None`
     pub async fn withdraw(
         &self,
-        amount: bitcoin::Amount,
+        amount: Option<bitcoin::Amount>,
         address: Address,
     ) -> Result<TransactionId> {
         self.fetch_all_notes().await;
 
         let rng = rand::rngs::OsRng;
 
-        let peg_out = self
+        let peg_out = match amount {
+            Some(amount) => {
+                self.client
+                    .new_peg_out_with_fees(amount, address, None)
+                    .await
+            }
+            None => self.client.new_peg_out_all(address).await,
+        }
+        .map_err(GatewayError::ClientError)?;
+        let amount = Amount::from(peg_out.amount + peg_out.fees.amount())
+            + self.client.wallet_client().config.fee_consensus.peg_out_abs;
+        let txid = self
             .client
-            .new_peg_out_with_fees(amount, address)
-            .await
-            .expect("Failed to create pegout with fees");
-        self.client
             .peg_out(peg_out, rng)
             .await
-            .map_err(GatewayError::ClientError)
-            .map(|out_point| out_point.txid)
+            .map_err(GatewayError::ClientError)?
+            .txid;
+        self.client
+            .record_payment(PaymentKind::Withdrawal { txid }, amount, Amount::ZERO)
+            .await;
+        Ok(txid)
+    }
+
+    /// Funds an outgoing contract paying `invoice` with our ecash that
+    /// another gateway of the federation can claim, see [`crate::sweep`]
+    ///
+    /// Returns the contract, the outpoint funding it and the gateway to pay
+    /// it with [`Self::complete_sweep_to_ln`].
+    pub async fn fund_sweep_to_ln(
+        &self,
+        invoice: Invoice,
+    ) -> Result<(ContractId, OutPoint, LightningGateway)> {
+        self.fetch_all_notes().await;
+
+        let amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .ok_or(ClientError::InvoiceMissingAmount)?,
+        );
+        let max = self.client.max_outgoing_payment().await;
+        if amount > max {
+            return Err(GatewayError::SweepExceedsBalance { amount, max });
+        }
+
+        let gateway = select_sweep_gateway(
+            self.client.fetch_registered_gateways().await?,
+            &self.client.config().node_pub_key,
+            amount,
+            fedimint_core::time::now(),
+        )
+        .ok_or(ClientError::NoGatewayWithLiquidity(amount))?;
+
+        let (contract_id, outpoint) = self
+            .client
+            .fund_outgoing_ln_contract_via(invoice, &gateway, rand::rngs::OsRng)
+            .await?;
+        Ok((contract_id, outpoint, gateway))
+    }
+
+    /// Waits for the federation to accept the contract funded by
+    /// [`Self::fund_sweep_to_ln`] and for `gateway` to pay its invoice, the
+    /// ecash is refunded if that gateway fails
+    pub async fn complete_sweep_to_ln(
+        &self,
+        contract_id: ContractId,
+        outpoint: OutPoint,
+        gateway: &LightningGateway,
+    ) -> Result<()> {
+        self.client
+            .await_outgoing_contract_acceptance(outpoint)
+            .await?;
+        self.client
+            .await_outgoing_contract_execution_via(contract_id, gateway, rand::rngs::OsRng)
+            .await?;
+        Ok(())
     }
 
     pub async fn backup(&self) -> Result<()> {
@@ -550,8 +1471,7 @@ impl GatewayActor {
     }
 
     pub async fn restore(&self) -> Result<()> {
-        // TODO: get the task group from `self`
-        let mut task_group = TaskGroup::new();
+        let mut task_group = self.task_group.make_subgroup().await;
 
         self.client
             .mint_client()
@@ -568,17 +1488,376 @@ impl GatewayActor {
         Ok(())
     }
 
+    /// Config and all database entries of the federation client, see
+    /// [`crate::migration`]
+    pub async fn export_state(&self) -> FederationState {
+        let entries = self
+            .client
+            .db()
+            .begin_transaction()
+            .await
+            .raw_find_by_prefix(&[])
+            .await;
+        FederationState::new(self.client.config(), entries)
+    }
+
+    /// Catches up on what happened since the state was exported from another
+    /// gateway, e.g. ecash issued or outgoing payments the lightning node
+    /// completed in the meantime
+    pub async fn reconcile_imported_state(&self) -> Result<()> {
+        self.fetch_all_notes().await;
+        self.reconcile_outgoing_payments().await
+    }
+
     pub async fn get_balance(&self) -> Result<Amount> {
         self.fetch_all_notes().await;
 
         Ok(self.client.notes().await.total_amount())
     }
 
-    pub fn get_info(&self) -> Result<FederationInfo> {
+    /// Payments recorded from `from` until before `to`, and the current
+    /// balance
+    pub async fn accounting(
+        &self,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> Result<FederationAccounting> {
+        Ok(FederationAccounting {
+            federation_id: self.client.config().client_config.federation_id,
+            balance: self.get_balance().await?,
+            payments: self.client.payment_history(from, to).await,
+        })
+    }
+
+    /// Public API of the gateway as announced to the federation
+    pub fn api(&self) -> Url {
+        self.client.config().api.clone()
+    }
+
+    pub async fn register_lightning_address(
+        &self,
+        name: String,
+        pubkey: XOnlyPublicKey,
+    ) -> Result<()> {
+        self.client
+            .register_lightning_address(name, pubkey)
+            .await
+            .map_err(GatewayError::ClientError)
+    }
+
+    pub async fn lightning_address(&self, name: &str) -> Option<LightningAddress> {
+        self.client.lightning_address(name).await
+    }
+
+    pub async fn create_lnurl_invoice(
+        &self,
+        name: &str,
+        amount: Amount,
+        description_hash: sha256::Hash,
+    ) -> Result<Invoice> {
+        let rng = rand::rngs::OsRng;
+        self.client
+            .create_lnurl_invoice(
+                name,
+                amount,
+                description_hash,
+                self.route_hints.read().await.clone(),
+                *self.fees.read().await,
+                rng,
+            )
+            .await
+            .map_err(GatewayError::ClientError)
+    }
+
+    pub async fn lnurl_payments(&self, name: &str) -> Vec<(ContractId, LnurlPayment)> {
+        self.client.lnurl_payments(name).await
+    }
+
+    /// Pays invoices of our own offers over our lightning node, so the
+    /// federation first receives and then sends a payment, and reports how
+    /// long each step took, see [`crate::test_payment`]
+    #[instrument(skip_all, fields(%amount, %correlation_id))]
+    pub async fn test_payment(
+        &self,
+        amount: Amount,
+        correlation_id: CorrelationId,
+    ) -> TestPaymentReport {
+        let mut rng = rand::rngs::OsRng;
+        let mut report = TestPaymentReport::new(amount);
+        self.fetch_all_notes().await;
+
+        // Lightning -> federation, our interception buys the preimage of the
+        // invoice our node pays
+        let Some((invoice, keypair)) = report
+            .step("create incoming invoice", self.create_test_invoice(amount))
+            .await
+        else {
+            return report;
+        };
+        if report
+            .step(
+                "pay incoming invoice",
+                self.pay_test_invoice(&invoice, correlation_id),
+            )
+            .await
+            .is_none()
+        {
+            self.client.abandon_test_invoice(&invoice).await;
+            return report;
+        }
+        let contract_id = ContractId::from_hash(*invoice.payment_hash());
+        if report
+            .step(
+                "claim incoming contract",
+                self.client
+                    .claim_test_invoice(contract_id, keypair, &mut rng),
+            )
+            .await
+            .is_none()
+        {
+            return report;
+        }
+
+        // Federation -> lightning, we route our own outgoing contract like
+        // the one of a user, paying it over lightning even though it's internal
+        let Some((invoice, keypair)) = report
+            .step("create outgoing invoice", self.create_test_invoice(amount))
+            .await
+        else {
+            return report;
+        };
+        let route_hints = self.route_hints.read().await.clone();
+        let Some(contract_id) = report
+            .step(
+                "fund outgoing contract",
+                self.client
+                    .fund_test_payment(invoice.clone(), route_hints, &mut rng),
+            )
+            .await
+        else {
+            self.client.abandon_test_invoice(&invoice).await;
+            return report;
+        };
+        let Some((preimage, routing_fee)) = report
+            .step(
+                "pay outgoing contract",
+                self.pay_test_payment(contract_id, correlation_id),
+            )
+            .await
+        else {
+            report
+                .step(
+                    "refund outgoing contract",
+                    self.client.refund_test_payment(contract_id, &mut rng),
+                )
+                .await;
+            self.client.abandon_test_invoice(&invoice).await;
+            return report;
+        };
+        if report
+            .step(
+                "claim outgoing contract",
+                self.claim_test_payment(contract_id, preimage, routing_fee),
+            )
+            .await
+            .is_none()
+        {
+            return report;
+        }
+        report
+            .step(
+                "claim incoming contract",
+                self.client.claim_test_invoice(
+                    ContractId::from_hash(*invoice.payment_hash()),
+                    keypair,
+                    &mut rng,
+                ),
+            )
+            .await;
+
+        report
+    }
+
+    async fn create_test_invoice(&self, amount: Amount) -> Result<(Invoice, KeyPair)> {
+        let rng = rand::rngs::OsRng;
+        Ok(self
+            .client
+            .create_test_invoice(
+                amount,
+                self.route_hints.read().await.clone(),
+                *self.fees.read().await,
+                rng,
+            )
+            .await?)
+    }
+
+    async fn pay_test_invoice(
+        &self,
+        invoice: &Invoice,
+        correlation_id: CorrelationId,
+    ) -> Result<()> {
+        self.lnrpc
+            .read()
+            .await
+            .pay(PayInvoiceRequest {
+                invoice: invoice.to_string(),
+                max_delay: TEST_PAYMENT_MAX_DELAY,
+                max_fee_percent: TEST_PAYMENT_MAX_FEE_PERCENT,
+                outgoing_channel_id: None,
+                first_hop_pubkey: None,
+                correlation_id: Some(correlation_id.to_string()),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Validates our own outgoing contract like the one of a user and buys its
+    /// preimage over lightning
+    async fn pay_test_payment(
+        &self,
+        contract_id: ContractId,
+        correlation_id: CorrelationId,
+    ) -> Result<(Preimage, Amount)> {
+        let contract_account = self.client.fetch_outgoing_contract(contract_id).await?;
+        let payment_params = self
+            .client
+            .validate_outgoing_account(&contract_account)
+            .await?;
+        payment_params
+            .verify_invoice(&contract_account)
+            .map_err(ClientError::from)?;
+        self.client
+            .save_outgoing_payment(contract_account.clone())
+            .await?;
+
+        self.buy_preimage_over_lightning(
+            contract_account.contract.invoice,
+            &payment_params,
+            FirstHopConstraint::default(),
+            correlation_id,
+        )
+        .await
+    }
+
+    async fn claim_test_payment(
+        &self,
+        contract_id: ContractId,
+        preimage: Preimage,
+        routing_fee: Amount,
+    ) -> Result<()> {
+        let rng = rand::rngs::OsRng;
+        let outpoint = self
+            .client
+            .claim_outgoing_contract(contract_id, preimage, routing_fee, rng)
+            .await?;
+        self.await_outgoing_contract_claimed(contract_id, outpoint)
+            .await?;
+        self.client.forget_test_payment(contract_id).await;
+        Ok(())
+    }
+
+    /// Checks that we can buy the preimage of an HTLC of `amount` and still
+    /// keep our ecash reserve
+    pub async fn ensure_liquidity(&self, amount: Amount) -> Result<()> {
+        // fetching the notes pending issuance asks the federation, while spent
+        // notes leave our balance right away, so it's enough to fetch them
+        // now and then
+        if self.liquidity_note_fetches.is_due(Instant::now()) {
+            self.fetch_all_notes().await;
+        }
+        let balance = self.client.notes().await.total_amount();
+        let required = amount.saturating_add(self.client.ecash_reserve().await);
+        if balance < required {
+            return Err(GatewayError::InsufficientLiquidity { balance, required });
+        }
+        Ok(())
+    }
+
+    /// Checks that an HTLC paying us `incoming` to forward `outgoing` to the
+    /// federation leaves us our fees
+    ///
+    /// Invoices created before our fees last changed still carry the fees
+    /// announced before, so the lower of those and the current ones suffices.
+    pub async fn ensure_fee(&self, incoming: Amount, outgoing: Amount) -> Result<()> {
+        let liquidity = self.client.notes().await.total_amount();
+        let current = self.fee_oracle.fees(liquidity).await.amount(outgoing);
+        let announced = self.fees.read().await.amount(outgoing);
+
+        let fee = incoming.saturating_sub(outgoing);
+        let required = current.min(announced);
+        if fee < required {
+            return Err(GatewayError::InsufficientFee { fee, required });
+        }
+        Ok(())
+    }
+
+    pub async fn set_ecash_reserve(&self, reserve: Amount) {
+        self.client.set_ecash_reserve(reserve).await
+    }
+
+    /// Checks that an HTLC of `amount` is within the amounts the operator
+    /// wants us to intercept, returning how to fail it back otherwise
+    pub async fn ensure_htlc_amount(&self, amount: Amount) -> std::result::Result<(), Cancel> {
+        let band = self.client.htlc_amount_band().await;
+        if band.contains(amount) {
+            return Ok(());
+        }
+
+        let failure = if band.min.map_or(false, |min| amount < min) {
+            Failure::AmountBelowMinimum
+        } else {
+            Failure::AmountAboveMaximum
+        };
+        Err(Cancel {
+            reason: GatewayError::HtlcAmountOutOfBand { amount, band }.to_string(),
+            failure: failure.into(),
+            htlc_msat: amount.msats,
+        })
+    }
+
+    pub async fn set_htlc_amount_band(&self, band: HtlcAmountBand) {
+        self.client.set_htlc_amount_band(band).await
+    }
+
+    pub async fn get_info(&self) -> Result<FederationInfo> {
         let cfg = self.client.config();
         Ok(FederationInfo {
             federation_id: cfg.client_config.federation_id.clone(),
             mint_pubkey: cfg.redeem_key.x_only_public_key().0,
+            mint_channel_id: cfg.mint_channel_id,
+            ecash_reserve: self.client.ecash_reserve().await,
+            fees: *self.fees.read().await,
+            htlc_amount_band: self.client.htlc_amount_band().await,
+            htlc_queue: self.htlc_queue.info(),
+            paused: self.is_paused(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::config::FederationId;
+
+    use super::HtlcQueueStats;
+    use crate::rpc::HtlcQueueInfo;
+
+    #[test]
+    fn htlc_queue_stats_track_depth_and_rejections() {
+        let stats = HtlcQueueStats::new(&FederationId::dummy());
+        stats.push();
+        stats.push();
+        stats.pop();
+        stats.reject();
+
+        assert_eq!(
+            stats.info(),
+            HtlcQueueInfo {
+                depth: 1,
+                max_depth: 2,
+                rejected: 1,
+            }
+        );
+        assert_eq!(stats.depth_gauge.get(), 1);
+        assert_eq!(stats.rejected_counter.get(), 1);
+    }
+}
@@ -3,28 +3,32 @@ use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::anyhow;
-use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::hex::{FromHex, ToHex};
 use bitcoin_hashes::{sha256, Hash};
 use clap::Parser;
 use cln_plugin::{options, Builder, Plugin};
 use cln_rpc::model;
 use cln_rpc::primitives::ShortChannelId;
 use fedimint_core::Amount;
-use ln_gateway::gatewaylnrpc::complete_htlcs_request::{Action, Cancel, Settle};
+use ln_gateway::gatewaylnrpc::complete_htlcs_request::{Action, Settle};
 use ln_gateway::gatewaylnrpc::gateway_lightning_server::{
     GatewayLightning, GatewayLightningServer,
 };
 use ln_gateway::gatewaylnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
+use ln_gateway::gatewaylnrpc::list_payments_response::{Payment, PaymentStatus};
 use ln_gateway::gatewaylnrpc::{
-    CompleteHtlcsRequest, CompleteHtlcsResponse, EmptyRequest, GetNodeInfoResponse,
-    GetRouteHintsResponse, PayInvoiceRequest, PayInvoiceResponse, SubscribeInterceptHtlcsRequest,
+    ChannelUpdate, CompleteHtlcsRequest, CompleteHtlcsResponse, EmptyRequest, GetLiquidityResponse,
+    GetNodeInfoResponse, GetRouteHintsResponse, ListPaymentsRequest, ListPaymentsResponse,
+    OnionMessage, OnionMessageField, PayInvoiceRequest, PayInvoiceResponse,
+    SendOnionMessageRequest, SendOnionMessageResponse, SubscribeInterceptHtlcsRequest,
     SubscribeInterceptHtlcsResponse,
 };
-use secp256k1::PublicKey;
+use secp256k1::{KeyPair, PublicKey};
 use serde::{Deserialize, Deserializer, Serialize};
 use thiserror::Error;
 use tokio::io::{stdin, stdout};
@@ -32,7 +36,13 @@ use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Server;
 use tonic::Status;
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, error, info, instrument, trace, warn, Span};
+
+/// Forwarding policy assumed for private channels whose channel update we don't
+/// know, matches the defaults of core-lightning
+const DEFAULT_BASE_FEE_MSAT: u32 = 1_000;
+const DEFAULT_FEE_PROPORTIONAL_MILLIONTHS: u32 = 10;
+const DEFAULT_CLTV_EXPIRY_DELTA: u32 = 34;
 
 #[derive(Parser)]
 pub struct ClnExtensionOpts {
@@ -106,12 +116,107 @@ pub struct HtlcAccepted {
     pub onion: Onion,
 }
 
+/// Payload of the `onion_message_recv` hook, which lightningd only calls
+/// with onion messages enabled, e.g. by `experimental-onion-messages`
+#[derive(Clone, Deserialize, Debug)]
+pub struct OnionMessageRecv {
+    pub onion_message: OnionMessagePayload,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct OnionMessagePayload {
+    pub reply_blindedpath: Option<BlindedPath>,
+    pub invoice_request: Option<String>,
+    pub invoice: Option<String>,
+    pub invoice_error: Option<String>,
+    /// Records lightningd doesn't know itself, e.g. of federation messages
+    #[serde(default)]
+    pub unknown_fields: Vec<UnknownField>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct BlindedPath {
+    pub first_node_id: String,
+    pub blinding: String,
+    pub hops: Vec<BlindedHop>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct BlindedHop {
+    #[serde(alias = "id")]
+    pub blinded_node_id: String,
+    pub encrypted_recipient_data: String,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct UnknownField {
+    pub number: u64,
+    pub value: String,
+}
+
+/// TLV types of the BOLT 12 records of onion messages, which lightningd
+/// reports by name
+const TLV_INVOICE_REQUEST: u64 = 64;
+const TLV_INVOICE: u64 = 66;
+const TLV_INVOICE_ERROR: u64 = 68;
+
+impl OnionMessagePayload {
+    fn into_onion_message(self) -> anyhow::Result<OnionMessage> {
+        let mut fields = vec![];
+        for (tlv_type, value) in [
+            (TLV_INVOICE_REQUEST, self.invoice_request),
+            (TLV_INVOICE, self.invoice),
+            (TLV_INVOICE_ERROR, self.invoice_error),
+        ] {
+            if let Some(value) = value {
+                fields.push(OnionMessageField {
+                    tlv_type,
+                    value: Vec::from_hex(&value)?,
+                });
+            }
+        }
+        for field in self.unknown_fields {
+            fields.push(OnionMessageField {
+                tlv_type: field.number,
+                value: Vec::from_hex(&field.value)?,
+            });
+        }
+        fields.sort_by_key(|field| field.tlv_type);
+
+        let reply_path = self
+            .reply_blindedpath
+            .map(|path| path.consensus_encode())
+            .transpose()?;
+
+        Ok(OnionMessage { fields, reply_path })
+    }
+}
+
+impl BlindedPath {
+    /// Encodes the path as the `blinded_path` of BOLT 4
+    fn consensus_encode(&self) -> anyhow::Result<Vec<u8>> {
+        let mut encoded = Vec::from_hex(&self.first_node_id)?;
+        encoded.extend(Vec::from_hex(&self.blinding)?);
+        encoded.push(u8::try_from(self.hops.len())?);
+        for hop in &self.hops {
+            let data = Vec::from_hex(&hop.encrypted_recipient_data)?;
+            encoded.extend(Vec::from_hex(&hop.blinded_node_id)?);
+            encoded.extend(u16::try_from(data.len())?.to_be_bytes());
+            encoded.extend(data);
+        }
+        Ok(encoded)
+    }
+}
+
 pub struct ClnRpcClient {}
 
 #[allow(dead_code)]
 pub struct ClnRpcService {
     socket: PathBuf,
     interceptor: Arc<ClnHtlcInterceptor>,
+    /// Highest fencing token a gateway sent, requests of gateways with lower
+    /// ones come from instances that were replaced and get rejected
+    fencing_token: AtomicU64,
 }
 
 impl ClnRpcService {
@@ -153,6 +258,22 @@ impl ClnRpcService {
                     plugin.shutdown()
                 },
             )
+            .hook(
+                "onion_message_recv",
+                |plugin: Plugin<Arc<ClnHtlcInterceptor>>, value: serde_json::Value| async move {
+                    plugin.state().onion_message_received(value).await;
+                    Ok(serde_json::json!({ "result": "continue" }))
+                },
+            )
+            // Report opened, closed and spliced channels to the gateways, so they refresh the
+            // liquidity and route hints they advertise
+            .subscribe(
+                "channel_state_changed",
+                |plugin: Plugin<Arc<ClnHtlcInterceptor>>, value: serde_json::Value| async move {
+                    plugin.state().channel_state_changed(value).await;
+                    Ok(())
+                },
+            )
             .dynamic() // Allow reloading the plugin
             .start(interceptor.clone())
             .await?
@@ -182,6 +303,7 @@ impl ClnRpcService {
                 Self {
                     socket,
                     interceptor,
+                    fencing_token: AtomicU64::new(0),
                 },
                 listen,
                 plugin,
@@ -221,6 +343,90 @@ impl ClnRpcService {
             })
             .map_err(ClnExtensionError::RpcError)?
     }
+
+    /// Returns the channels a payment has to exclude so its first hop
+    /// leaves over `outgoing_channel_id` and/or to `first_hop`, in the
+    /// `scid/direction` format of `pay`
+    async fn first_hop_exclusions(
+        &self,
+        outgoing_channel_id: Option<u64>,
+        first_hop: Option<PublicKey>,
+    ) -> Result<Vec<String>, Status> {
+        let (node_id, _) = self
+            .info()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let peers = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call(cln_rpc::Request::ListPeers(model::ListpeersRequest {
+                id: None,
+                level: None,
+            }))
+            .await
+            .map(|response| match response {
+                cln_rpc::Response::ListPeers(peers) => Ok(peers.peers),
+                _ => Err(ClnExtensionError::RpcWrongResponse),
+            })
+            .map_err(|e| {
+                error!("cln listpeers rpc returned error {:?}", e);
+                tonic::Status::internal(e.to_string())
+            })?
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+
+        let mut exclude = vec![];
+        let mut usable = false;
+        for peer in peers {
+            // the direction of a channel is 0 for the node with the lower id
+            let direction = u8::from(node_id > peer.id);
+            for chan in peer.channels {
+                let Some(scid) = chan.short_channel_id else {
+                    continue;
+                };
+                if !matches!(
+                    chan.state,
+                    model::ListpeersPeersChannelsState::CHANNELD_NORMAL
+                ) {
+                    continue;
+                }
+
+                if outgoing_channel_id.map_or(true, |id| id == scid_to_u64(scid))
+                    && first_hop.map_or(true, |first_hop| first_hop == peer.id)
+                {
+                    usable = true;
+                } else {
+                    exclude.push(format!("{scid}/{direction}"));
+                }
+            }
+        }
+
+        if !usable {
+            return Err(Status::failed_precondition(
+                "No active channel matches the first hop constraint",
+            ));
+        }
+        Ok(exclude)
+    }
+
+    /// Rejects requests fenced with a lower token than one seen before
+    fn check_fencing_token(&self, fencing_token: Option<u64>) -> Result<(), Status> {
+        let Some(fencing_token) = fencing_token else {
+            return Ok(());
+        };
+
+        // The same leader keeps sending its token, so only a higher one seen
+        // before fences this request
+        let highest = self
+            .fencing_token
+            .fetch_max(fencing_token, Ordering::SeqCst);
+        if highest > fencing_token {
+            return Err(Status::failed_precondition(format!(
+                "Fencing token {fencing_token} is lower than {highest}, the gateway was replaced"
+            )));
+        }
+        Ok(())
+    }
 }
 
 #[tonic::async_trait]
@@ -283,12 +489,28 @@ impl GatewayLightning for ClnRpcService {
                     return None;
                 }
 
-                let Some(scid) = chan.short_channel_id else {
-                    warn!("Encountered channel without short channel id");
+                // Payers can only route over a private channel with the alias our
+                // peer assigned to it, the real short channel id may be unknown to
+                // them or not exist yet
+                let private = chan.private.unwrap_or(false);
+                let alias = chan.alias.as_ref().and_then(|alias| alias.remote);
+                let hint_scid = if private {
+                    alias.or(chan.short_channel_id)
+                } else {
+                    chan.short_channel_id
+                };
+                let Some(hint_scid) = hint_scid else {
+                    warn!("Encountered channel without short channel id or alias");
                     return None;
                 };
 
-                Some((peer_id, scid))
+                Some(PeerChannel {
+                    peer_id,
+                    short_channel_id: chan.short_channel_id,
+                    hint_scid,
+                    private,
+                    inbound_capacity_msat: chan.receivable_msat.map(|amt| amt.msat()),
+                })
             })
             .collect::<Vec<_>>();
 
@@ -298,46 +520,69 @@ impl GatewayLightning for ClnRpcService {
         );
 
         let mut route_hints = vec![];
-        for (peer_id, scid) in active_peer_channels {
-            let channels_response = client
-                .call(cln_rpc::Request::ListChannels(model::ListchannelsRequest {
-                    short_channel_id: Some(scid),
-                    source: None,
-                    destination: None,
-                }))
-                .await
-                .map_err(|err| tonic::Status::internal(err.to_string()))?;
-
-            let channel = match channels_response {
-                cln_rpc::Response::ListChannels(channels) => {
-                    let Some(channel) = channels.channels.into_iter().find(|chan| chan.destination == node_info.0) else {
-                        warn!("Channel {:?} not found in graph", scid);
-                        continue;
-                    };
-                    Ok(channel)
+        for peer_channel in active_peer_channels {
+            // The gossip of private channels doesn't have to reach us, so their
+            // hops fall back to the default forwarding policy
+            let channel = match peer_channel.short_channel_id {
+                Some(scid) => {
+                    let channels_response = client
+                        .call(cln_rpc::Request::ListChannels(model::ListchannelsRequest {
+                            short_channel_id: Some(scid),
+                            source: None,
+                            destination: None,
+                        }))
+                        .await
+                        .map_err(|err| tonic::Status::internal(err.to_string()))?;
+
+                    match channels_response {
+                        cln_rpc::Response::ListChannels(channels) => Ok(channels
+                            .channels
+                            .into_iter()
+                            .find(|chan| chan.destination == node_info.0)),
+                        _ => Err(ClnExtensionError::RpcWrongResponse),
+                    }
+                    .map_err(|err| tonic::Status::internal(err.to_string()))?
+                }
+                None => None,
+            };
+
+            let route_hint_hop = match channel {
+                Some(channel) => RouteHintHop {
+                    src_node_id: peer_channel.peer_id.serialize().to_vec(),
+                    short_channel_id: scid_to_u64(peer_channel.hint_scid),
+                    base_msat: channel.base_fee_millisatoshi,
+                    proportional_millionths: channel.fee_per_millionth,
+                    cltv_expiry_delta: channel.delay,
+                    htlc_minimum_msat: Some(channel.htlc_minimum_msat.msat()),
+                    htlc_maximum_msat: channel.htlc_maximum_msat.map(|amt| amt.msat()),
+                },
+                None if peer_channel.private => RouteHintHop {
+                    src_node_id: peer_channel.peer_id.serialize().to_vec(),
+                    short_channel_id: scid_to_u64(peer_channel.hint_scid),
+                    base_msat: DEFAULT_BASE_FEE_MSAT,
+                    proportional_millionths: DEFAULT_FEE_PROPORTIONAL_MILLIONTHS,
+                    cltv_expiry_delta: DEFAULT_CLTV_EXPIRY_DELTA,
+                    htlc_minimum_msat: None,
+                    htlc_maximum_msat: None,
+                },
+                None => {
+                    warn!("Channel {:?} not found in graph", peer_channel.hint_scid);
+                    continue;
                 }
-                _ => Err(ClnExtensionError::RpcWrongResponse),
-            }.map_err(|err| tonic::Status::internal(err.to_string()))?;
-
-            let route_hint_hop = RouteHintHop {
-                src_node_id: peer_id.serialize().to_vec(),
-                short_channel_id: scid_to_u64(scid),
-                base_msat: channel.base_fee_millisatoshi,
-                proportional_millionths: channel.fee_per_millionth,
-                cltv_expiry_delta: channel.delay,
-                htlc_minimum_msat: Some(channel.htlc_minimum_msat.msat()),
-                htlc_maximum_msat: channel.htlc_maximum_msat.map(|amt| amt.msat()),
             };
 
             trace!("Constructed route hint {:?}", route_hint_hop);
             route_hints.push(RouteHint {
                 hops: vec![route_hint_hop],
+                private: peer_channel.private,
+                inbound_capacity_msat: peer_channel.inbound_capacity_msat,
             });
         }
 
         Ok(tonic::Response::new(GetRouteHintsResponse { route_hints }))
     }
 
+    #[instrument(skip_all, fields(correlation_id))]
     async fn pay_invoice(
         &self,
         request: tonic::Request<PayInvoiceRequest>,
@@ -346,7 +591,27 @@ impl GatewayLightning for ClnRpcService {
             invoice,
             max_delay,
             max_fee_percent,
+            outgoing_channel_id,
+            first_hop_pubkey,
+            correlation_id,
         } = request.into_inner();
+        Span::current().record(
+            "correlation_id",
+            correlation_id.as_deref().unwrap_or_default(),
+        );
+
+        let first_hop = first_hop_pubkey
+            .map(|pubkey| PublicKey::from_slice(&pubkey))
+            .transpose()
+            .map_err(|e| Status::invalid_argument(format!("Invalid first hop pubkey: {e}")))?;
+        let exclude = if outgoing_channel_id.is_some() || first_hop.is_some() {
+            Some(
+                self.first_hop_exclusions(outgoing_channel_id, first_hop)
+                    .await?,
+            )
+        } else {
+            None
+        };
 
         let outcome = self
             .rpc_client()
@@ -362,16 +627,20 @@ impl GatewayLightning for ClnRpcService {
                 maxdelay: Some(max_delay as u16),
                 exemptfee: None,
                 localinvreqid: None,
-                exclude: None,
+                exclude,
                 maxfee: None,
                 description: None,
             }))
             .await
             .map(|response| match response {
                 cln_rpc::Response::Pay(model::PayResponse {
-                    payment_preimage, ..
+                    payment_preimage,
+                    amount_msat,
+                    amount_sent_msat,
+                    ..
                 }) => Ok(PayInvoiceResponse {
                     preimage: payment_preimage.to_vec(),
+                    fee_msat: amount_sent_msat.msat().saturating_sub(amount_msat.msat()),
                 }),
                 _ => Err(ClnExtensionError::RpcWrongResponse),
             })
@@ -391,12 +660,71 @@ impl GatewayLightning for ClnRpcService {
         &self,
         request: tonic::Request<SubscribeInterceptHtlcsRequest>,
     ) -> Result<tonic::Response<Self::SubscribeInterceptHtlcsStream>, Status> {
-        let SubscribeInterceptHtlcsRequest { short_channel_id } = request.into_inner();
+        let SubscribeInterceptHtlcsRequest {
+            short_channel_id,
+            fencing_token,
+        } = request.into_inner();
+        self.check_fencing_token(fencing_token)?;
         let receiver = self.interceptor.add_htlc_subscriber(short_channel_id).await;
 
         Ok(tonic::Response::new(ReceiverStream::new(receiver)))
     }
 
+    type SubscribeChannelUpdatesStream = ReceiverStream<Result<ChannelUpdate, Status>>;
+
+    async fn subscribe_channel_updates(
+        &self,
+        _request: tonic::Request<EmptyRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeChannelUpdatesStream>, Status> {
+        let receiver = self.interceptor.add_channel_update_subscriber().await;
+
+        Ok(tonic::Response::new(ReceiverStream::new(receiver)))
+    }
+
+    /// Sends the message straight to `node_id`, which has to be a peer, as the
+    /// only hop of the onion, with the fields as its payload
+    async fn send_onion_message(
+        &self,
+        request: tonic::Request<SendOnionMessageRequest>,
+    ) -> Result<tonic::Response<SendOnionMessageResponse>, Status> {
+        let SendOnionMessageRequest { node_id, fields } = request.into_inner();
+        let node_id = PublicKey::from_slice(&node_id)
+            .map_err(|e| Status::invalid_argument(format!("Invalid node id: {e}")))?;
+        let blinding =
+            KeyPair::new(&secp256k1::Secp256k1::new(), &mut rand::rngs::OsRng).public_key();
+
+        self.rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call_raw::<_, serde_json::Value>(
+                "sendonionmessage",
+                &serde_json::json!({
+                    "first_id": node_id.to_string(),
+                    "blinding": blinding.to_string(),
+                    "hops": [{
+                        "id": node_id.to_string(),
+                        "tlv": encode_tlv_stream(fields).to_hex(),
+                    }],
+                }),
+            )
+            .await
+            .map_err(|e| Status::internal(format!("Failed to send onion message: {e:?}")))?;
+
+        Ok(tonic::Response::new(SendOnionMessageResponse {}))
+    }
+
+    type SubscribeOnionMessagesStream = ReceiverStream<Result<OnionMessage, Status>>;
+
+    async fn subscribe_onion_messages(
+        &self,
+        _request: tonic::Request<EmptyRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeOnionMessagesStream>, Status> {
+        let receiver = self.interceptor.add_onion_message_subscriber().await;
+
+        Ok(tonic::Response::new(ReceiverStream::new(receiver)))
+    }
+
+    #[instrument(skip_all, fields(correlation_id))]
     async fn complete_htlc(
         &self,
         request: tonic::Request<CompleteHtlcsRequest>,
@@ -404,7 +732,14 @@ impl GatewayLightning for ClnRpcService {
         let CompleteHtlcsRequest {
             action,
             intercepted_htlc_id,
+            fencing_token,
+            correlation_id,
         } = request.into_inner();
+        Span::current().record(
+            "correlation_id",
+            correlation_id.as_deref().unwrap_or_default(),
+        );
+        self.check_fencing_token(fencing_token)?;
 
         let hash = match sha256::Hash::from_slice(&intercepted_htlc_id) {
             Ok(hash) => hash,
@@ -428,10 +763,11 @@ impl GatewayLightning for ClnRpcService {
                         htlc_processing_failure()
                     }
                 }
-                Some(Action::Cancel(Cancel { reason: _ })) => {
-                    // TODO: Translate the reason into a BOLT 4 failure message
-                    // See: https://github.com/lightning/bolts/blob/master/04-onion-routing.md#failure-messages
-                    htlc_processing_failure()
+                Some(Action::Cancel(cancel)) => {
+                    serde_json::json!({
+                        "result": "fail",
+                        "failure_message": cancel.failure_message().to_hex()
+                    })
                 }
                 None => {
                     error!("No action specified for intercepted htlc id: {:?}", hash);
@@ -468,6 +804,101 @@ impl GatewayLightning for ClnRpcService {
             ));
         }
     }
+
+    async fn list_payments(
+        &self,
+        request: tonic::Request<ListPaymentsRequest>,
+    ) -> Result<tonic::Response<ListPaymentsResponse>, Status> {
+        let ListPaymentsRequest { created_after } = request.into_inner();
+
+        let pays = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call(cln_rpc::Request::ListPays(model::ListpaysRequest {
+                bolt11: None,
+                payment_hash: None,
+                status: None,
+            }))
+            .await
+            .map(|response| match response {
+                cln_rpc::Response::ListPays(model::ListpaysResponse { pays }) => Ok(pays),
+                _ => Err(ClnExtensionError::RpcWrongResponse),
+            })
+            .map_err(|e| {
+                error!("cln listpays rpc returned error {:?}", e);
+                tonic::Status::internal(e.to_string())
+            })?
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+
+        let payments = pays
+            .into_iter()
+            .filter(|pay| pay.created_at >= created_after)
+            .map(|pay| {
+                let status = match pay.status {
+                    model::ListpaysPaysStatus::COMPLETE => PaymentStatus::Succeeded,
+                    model::ListpaysPaysStatus::FAILED => PaymentStatus::Failed,
+                    model::ListpaysPaysStatus::PENDING => PaymentStatus::Pending,
+                };
+                Payment {
+                    payment_hash: pay.payment_hash.to_vec(),
+                    status: status.into(),
+                    preimage: pay
+                        .preimage
+                        .map(|preimage| preimage.to_vec())
+                        .unwrap_or_default(),
+                    created_at: pay.created_at,
+                    fee_msat: pay
+                        .amount_sent_msat
+                        .zip(pay.amount_msat)
+                        .map_or(0, |(sent, amount)| {
+                            sent.msat().saturating_sub(amount.msat())
+                        }),
+                }
+            })
+            .collect();
+
+        Ok(tonic::Response::new(ListPaymentsResponse { payments }))
+    }
+
+    async fn get_liquidity(
+        &self,
+        _request: tonic::Request<EmptyRequest>,
+    ) -> Result<tonic::Response<GetLiquidityResponse>, Status> {
+        let peers = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call(cln_rpc::Request::ListPeers(model::ListpeersRequest {
+                id: None,
+                level: None,
+            }))
+            .await
+            .map(|response| match response {
+                cln_rpc::Response::ListPeers(peers) => Ok(peers.peers),
+                _ => Err(ClnExtensionError::RpcWrongResponse),
+            })
+            .map_err(|e| {
+                error!("cln listpeers rpc returned error {:?}", e);
+                tonic::Status::internal(e.to_string())
+            })?
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+
+        let mut liquidity = GetLiquidityResponse::default();
+        for chan in peers.into_iter().flat_map(|peer| peer.channels) {
+            if !matches!(
+                chan.state,
+                model::ListpeersPeersChannelsState::CHANNELD_NORMAL
+            ) {
+                continue;
+            }
+
+            liquidity.outbound_msat += chan.spendable_msat.map_or(0, |amt| amt.msat());
+            liquidity.inbound_msat += chan.receivable_msat.map_or(0, |amt| amt.msat());
+        }
+
+        Ok(tonic::Response::new(liquidity))
+    }
 }
 
 #[derive(Debug, Error)]
@@ -480,6 +911,48 @@ pub enum ClnExtensionError {
     RpcWrongResponse,
 }
 
+/// A channel with a peer that route hints can lead over
+struct PeerChannel {
+    peer_id: PublicKey,
+    /// Short channel id the channel is known as in the graph, if it confirmed
+    short_channel_id: Option<ShortChannelId>,
+    /// Short channel id or alias payers have to route over
+    hint_scid: ShortChannelId,
+    private: bool,
+    inbound_capacity_msat: Option<u64>,
+}
+
+/// Encodes `fields` as a BOLT 1 TLV stream, in ascending order of their types
+fn encode_tlv_stream(mut fields: Vec<OnionMessageField>) -> Vec<u8> {
+    fields.sort_by_key(|field| field.tlv_type);
+
+    let mut stream = vec![];
+    for field in fields {
+        encode_bigsize(&mut stream, field.tlv_type);
+        encode_bigsize(&mut stream, field.value.len() as u64);
+        stream.extend(field.value);
+    }
+    stream
+}
+
+fn encode_bigsize(buf: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => buf.push(value as u8),
+        0xfd..=0xffff => {
+            buf.push(0xfd);
+            buf.extend((value as u16).to_be_bytes());
+        }
+        0x10000..=0xffffffff => {
+            buf.push(0xfe);
+            buf.extend((value as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(0xff);
+            buf.extend(value.to_be_bytes());
+        }
+    }
+}
+
 // TODO: upstream
 fn scid_to_u64(scid: ShortChannelId) -> u64 {
     let mut scid_num = scid.outnum() as u64;
@@ -502,6 +975,8 @@ fn htlc_processing_failure() -> serde_json::Value {
 
 type HtlcSubscriptionSender = mpsc::Sender<Result<SubscribeInterceptHtlcsResponse, Status>>;
 type HtlcOutcomeSender = oneshot::Sender<serde_json::Value>;
+type ChannelUpdateSender = mpsc::Sender<Result<ChannelUpdate, Status>>;
+type OnionMessageSender = mpsc::Sender<Result<OnionMessage, Status>>;
 
 /// Functional structure to filter intercepted HTLCs into subscription streams.
 /// Used as a CLN plugin
@@ -509,6 +984,10 @@ type HtlcOutcomeSender = oneshot::Sender<serde_json::Value>;
 pub struct ClnHtlcInterceptor {
     subscriptions: Arc<Mutex<HashMap<u64, HtlcSubscriptionSender>>>,
     pub outcomes: Arc<Mutex<HashMap<sha256::Hash, HtlcOutcomeSender>>>,
+    /// Gateways notified of changes to the node's channels
+    channel_subscriptions: Arc<Mutex<Vec<ChannelUpdateSender>>>,
+    /// Gateways the onion messages addressed to the node are forwarded to
+    onion_subscriptions: Arc<Mutex<Vec<OnionMessageSender>>>,
 }
 
 impl ClnHtlcInterceptor {
@@ -516,9 +995,72 @@ impl ClnHtlcInterceptor {
         Self {
             subscriptions: Arc::new(Mutex::new(HashMap::new())),
             outcomes: Arc::new(Mutex::new(HashMap::new())),
+            channel_subscriptions: Arc::new(Mutex::new(Vec::new())),
+            onion_subscriptions: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
+    /// Forwards an onion message lightningd received to the onion message
+    /// subscribers, lightningd keeps handling the BOLT 12 messages itself
+    async fn onion_message_received(&self, value: serde_json::Value) {
+        let message = match serde_json::from_value::<OnionMessageRecv>(value)
+            .map_err(anyhow::Error::from)
+            .and_then(|recv| recv.onion_message.into_onion_message())
+        {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Received invalid onion message: {:?}", e);
+                return;
+            }
+        };
+        debug!("Received onion message: {:?}", message);
+
+        // A gateway that lags behind misses messages rather than blocking
+        // lightningd's hook
+        self.onion_subscriptions.lock().await.retain(|sender| {
+            !matches!(
+                sender.try_send(Ok(message.clone())),
+                Err(mpsc::error::TrySendError::Closed(_))
+            )
+        });
+    }
+
+    async fn add_onion_message_subscriber(&self) -> mpsc::Receiver<Result<OnionMessage, Status>> {
+        let (sender, receiver) = mpsc::channel::<Result<OnionMessage, Status>>(100);
+        self.onion_subscriptions.lock().await.push(sender);
+        receiver
+    }
+
+    /// Forwards a `channel_state_changed` notification of lightningd to the
+    /// channel update subscribers
+    ///
+    /// A spliced channel goes back to `CHANNELD_NORMAL` under the short channel
+    /// id of the splice transaction.
+    async fn channel_state_changed(&self, value: serde_json::Value) {
+        let change = &value["channel_state_changed"];
+        debug!("Channel state changed: {:?}", change);
+
+        let short_channel_id = change["short_channel_id"]
+            .as_str()
+            .and_then(|scid| ShortChannelId::from_str(scid).ok())
+            .map(scid_to_u64);
+
+        // Updates only trigger a refresh, so a gateway that lags behind can
+        // skip some of them
+        self.channel_subscriptions.lock().await.retain(|sender| {
+            !matches!(
+                sender.try_send(Ok(ChannelUpdate { short_channel_id })),
+                Err(mpsc::error::TrySendError::Closed(_))
+            )
+        });
+    }
+
+    async fn add_channel_update_subscriber(&self) -> mpsc::Receiver<Result<ChannelUpdate, Status>> {
+        let (sender, receiver) = mpsc::channel::<Result<ChannelUpdate, Status>>(100);
+        self.channel_subscriptions.lock().await.push(sender);
+        receiver
+    }
+
     async fn intercept_htlc(&self, payload: HtlcAccepted) -> serde_json::Value {
         info!("Intercepted htlc with payload, {:?}", payload);
 
@@ -613,3 +1155,95 @@ impl ClnHtlcInterceptor {
 
     // TODO: Add a method to remove a HTLC subscriber
 }
+
+#[cfg(test)]
+mod tests {
+    use ln_gateway::gatewaylnrpc::{OnionMessage, OnionMessageField};
+
+    use super::{encode_bigsize, encode_tlv_stream, ClnHtlcInterceptor};
+
+    #[test]
+    fn tlv_stream_is_sorted_and_bigsize_encoded() {
+        let fields = vec![
+            OnionMessageField {
+                tlv_type: 300,
+                value: vec![],
+            },
+            OnionMessageField {
+                tlv_type: 1,
+                value: vec![0xaa, 0xbb],
+            },
+        ];
+        assert_eq!(
+            encode_tlv_stream(fields),
+            vec![0x01, 0x02, 0xaa, 0xbb, 0xfd, 0x01, 0x2c, 0x00]
+        );
+
+        for (value, expected) in [
+            (0xfc, vec![0xfc]),
+            (0xfd, vec![0xfd, 0x00, 0xfd]),
+            (0x10000, vec![0xfe, 0x00, 0x01, 0x00, 0x00]),
+            (0x1_0000_0000, vec![0xff, 0, 0, 0, 0x01, 0, 0, 0, 0]),
+        ] {
+            let mut buf = vec![];
+            encode_bigsize(&mut buf, value);
+            assert_eq!(buf, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn onion_messages_are_forwarded_to_subscribers() {
+        let interceptor = ClnHtlcInterceptor::new();
+        let mut subscriber = interceptor.add_onion_message_subscriber().await;
+        drop(interceptor.add_onion_message_subscriber().await);
+
+        interceptor
+            .onion_message_received(serde_json::json!({
+                "onion_message": {
+                    "invoice_request": "0102",
+                    "unknown_fields": [{ "number": 65537, "value": "ff" }],
+                    "reply_blindedpath": {
+                        "first_node_id": "02",
+                        "blinding": "03",
+                        "hops": [{ "id": "04", "encrypted_recipient_data": "0a0b" }],
+                    },
+                }
+            }))
+            .await;
+
+        let message = subscriber.try_recv().unwrap().unwrap();
+        assert_eq!(
+            message,
+            OnionMessage {
+                fields: vec![
+                    OnionMessageField {
+                        tlv_type: 64,
+                        value: vec![0x01, 0x02],
+                    },
+                    OnionMessageField {
+                        tlv_type: 65537,
+                        value: vec![0xff],
+                    },
+                ],
+                reply_path: Some(vec![0x02, 0x03, 0x01, 0x04, 0x00, 0x02, 0x0a, 0x0b]),
+            }
+        );
+        // the closed subscription was dropped
+        assert_eq!(interceptor.onion_subscriptions.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_onion_messages_are_not_forwarded() {
+        let interceptor = ClnHtlcInterceptor::new();
+        let mut subscriber = interceptor.add_onion_message_subscriber().await;
+
+        for invalid in [
+            serde_json::json!({ "onion_message": { "invoice": "not hex" } }),
+            serde_json::json!({ "onion_message": { "unknown_fields": [{ "number": 1 }] } }),
+            serde_json::json!({ "not_an_onion_message": {} }),
+        ] {
+            interceptor.onion_message_received(invalid).await;
+        }
+        assert!(subscriber.try_recv().is_err());
+    }
+}
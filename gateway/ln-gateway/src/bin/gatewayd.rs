@@ -2,27 +2,33 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::exit;
 use std::sync::Arc;
+use std::time::Duration;
 
 use clap::Parser;
 use fedimint_client::module::gen::{ClientModuleGenRegistry, DynClientModuleGen};
-use fedimint_core::core::{
-    LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
-    LEGACY_HARDCODED_INSTANCE_ID_WALLET,
-};
-use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::module::ModuleCommon;
-use fedimint_core::task::{RwLock, TaskGroup};
+use fedimint_core::task::{sleep, RwLock, TaskGroup};
+use fedimint_core::Amount;
 use fedimint_logging::TracingSetup;
 use ln_gateway::client::{DynGatewayClientBuilder, RocksDbFactory, StandardGatewayClientBuilder};
+use ln_gateway::fees::{
+    FeeOracleConfig, FeeOracleKind, DEFAULT_LIQUIDITY_TARGET, DEFAULT_MEMPOOL_API,
+};
+use ln_gateway::htlc_policy::{HtlcPolicyConfig, DEFAULT_WEBHOOK_TIMEOUT};
+use ln_gateway::lease::{FencedLnRpcClient, LeaderLease, DEFAULT_LEASE_TTL, LEASE_FILE};
 use ln_gateway::lnd::GatewayLndClient;
 use ln_gateway::lnrpc_client::{ILnRpcClient, NetworkLnRpcClient};
+use ln_gateway::metrics::run_metrics_server;
+use ln_gateway::route_hints::{parse_short_channel_id, RouteHintConfig, DEFAULT_MAX_ROUTE_HINTS};
 use ln_gateway::{Gateway, Mode};
-use mint_client::modules::ln::{LightningClientGen, LightningModuleTypes};
-use mint_client::modules::mint::{MintClientGen, MintModuleTypes};
-use mint_client::modules::wallet::{WalletClientGen, WalletModuleTypes};
+use mint_client::modules::ln::{GatewayFee, LightningClientGen};
+use mint_client::modules::mint::MintClientGen;
+use mint_client::modules::wallet::WalletClientGen;
 use tracing::{error, info};
 use url::Url;
 
+/// How long each task may take to finish once the gateway shuts down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Parser)]
 pub struct GatewayOpts {
     #[clap(subcommand)]
@@ -36,6 +42,10 @@ pub struct GatewayOpts {
     #[arg(long = "listen", env = "FM_GATEWAY_LISTEN_ADDR")]
     pub listen: SocketAddr,
 
+    /// Listen address of the gRPC API, which is only served if set
+    #[arg(long = "grpc-listen", env = "FM_GATEWAY_GRPC_LISTEN_ADDR")]
+    pub grpc_listen: Option<SocketAddr>,
+
     /// Public URL from which the webserver API is reachable
     #[arg(long = "api-addr", env = "FM_GATEWAY_API_ADDR")]
     pub api_addr: Url,
@@ -43,6 +53,107 @@ pub struct GatewayOpts {
     /// Gateway webserver authentication password
     #[arg(long = "password", env = "FM_GATEWAY_PASSWORD")]
     pub password: String,
+
+    /// Most route hints to the lightning node announced to federations and
+    /// included in invoices, nodes with only private channels need at least
+    /// one
+    #[arg(
+        long = "max-route-hints",
+        env = "FM_GATEWAY_MAX_ROUTE_HINTS",
+        default_value_t = DEFAULT_MAX_ROUTE_HINTS
+    )]
+    pub max_route_hints: usize,
+
+    /// Comma separated short channel ids or aliases of the channels whose
+    /// route hints come first, either as numbers or as `<block>x<tx>x<output>`
+    #[arg(
+        long = "preferred-route-hint-channels",
+        env = "FM_GATEWAY_PREFERRED_ROUTE_HINT_CHANNELS",
+        value_delimiter = ',',
+        value_parser = parse_short_channel_id
+    )]
+    pub preferred_route_hint_channels: Vec<u64>,
+
+    /// Unique id of this instance, enables active-standby failover between
+    /// instances sharing the data directory. Only the instance holding the
+    /// leader lease runs, the others wait to take over once it expires
+    #[arg(long = "instance-id", env = "FM_GATEWAY_INSTANCE_ID")]
+    pub instance_id: Option<String>,
+
+    /// Seconds the leader lease lasts unless the active instance renews it
+    #[arg(
+        long = "lease-ttl-secs",
+        env = "FM_GATEWAY_LEASE_TTL_SECS",
+        default_value_t = DEFAULT_LEASE_TTL.as_secs()
+    )]
+    pub lease_ttl_secs: u64,
+
+    /// How the gateway prices payments to its federations
+    #[arg(
+        long = "fee-oracle",
+        env = "FM_GATEWAY_FEE_ORACLE",
+        value_enum,
+        default_value_t = FeeOracleKind::Static
+    )]
+    pub fee_oracle: FeeOracleKind,
+
+    /// Base fee in msat charged for every payment to a federation, dynamic
+    /// oracles never charge less
+    #[arg(
+        long = "fee-base-msat",
+        env = "FM_GATEWAY_FEE_BASE_MSAT",
+        default_value_t = 0
+    )]
+    pub fee_base_msat: u32,
+
+    /// Fee in millionths of the amount charged for every payment to a
+    /// federation, dynamic oracles never charge less
+    #[arg(
+        long = "fee-proportional-millionths",
+        env = "FM_GATEWAY_FEE_PROPORTIONAL_MILLIONTHS",
+        default_value_t = 0
+    )]
+    pub fee_proportional_millionths: u32,
+
+    /// mempool.space compatible API the `mempool` fee oracle queries for
+    /// on-chain fee rates
+    #[arg(
+        long = "mempool-api-url",
+        env = "FM_GATEWAY_MEMPOOL_API_URL",
+        default_value = DEFAULT_MEMPOOL_API
+    )]
+    pub mempool_api_url: Url,
+
+    /// Ecash balance in msat below which the `liquidity` fee oracle raises
+    /// the fees
+    #[arg(
+        long = "liquidity-target-msat",
+        env = "FM_GATEWAY_LIQUIDITY_TARGET_MSAT",
+        default_value_t = DEFAULT_LIQUIDITY_TARGET.msats
+    )]
+    pub liquidity_target_msat: u64,
+
+    /// Endpoint every intercepted HTLC is POSTed to before the gateway buys
+    /// its preimage, rejecting the HTLC unless it answers `{"accept": true}`
+    #[arg(long = "htlc-policy-webhook", env = "FM_GATEWAY_HTLC_POLICY_WEBHOOK")]
+    pub htlc_policy_webhook: Option<Url>,
+
+    /// Seconds the HTLC policy webhook may take to answer
+    #[arg(
+        long = "htlc-policy-webhook-timeout-secs",
+        env = "FM_GATEWAY_HTLC_POLICY_WEBHOOK_TIMEOUT_SECS",
+        default_value_t = DEFAULT_WEBHOOK_TIMEOUT.as_secs()
+    )]
+    pub htlc_policy_webhook_timeout_secs: u64,
+
+    /// Route HTLCs intercepted for one federation that pay an offer in
+    /// another federation of the gateway through the latter
+    #[arg(long = "bridge-federations", env = "FM_GATEWAY_BRIDGE_FEDERATIONS")]
+    pub bridge_federations: bool,
+
+    /// Address to serve Prometheus metrics on at `/metrics`
+    #[arg(long = "bind-metrics", env = "FM_GATEWAY_BIND_METRICS")]
+    pub bind_metrics: Option<SocketAddr>,
 }
 
 // Fedimint Gateway Binary
@@ -68,8 +179,22 @@ async fn main() -> Result<(), anyhow::Error> {
         mode,
         data_dir,
         listen,
+        grpc_listen,
         api_addr,
         password,
+        max_route_hints,
+        preferred_route_hint_channels,
+        instance_id,
+        lease_ttl_secs,
+        fee_oracle,
+        fee_base_msat,
+        fee_proportional_millionths,
+        mempool_api_url,
+        liquidity_target_msat,
+        htlc_policy_webhook,
+        htlc_policy_webhook_timeout_secs,
+        bridge_federations,
+        bind_metrics,
     } = GatewayOpts::parse();
 
     info!(
@@ -81,8 +206,50 @@ async fn main() -> Result<(), anyhow::Error> {
     let client_builder: DynGatewayClientBuilder =
         StandardGatewayClientBuilder::new(data_dir.clone(), RocksDbFactory.into(), api_addr).into();
 
-    // Create task group for controlled shutdown of the gateway
-    let task_group = TaskGroup::new();
+    // Create task group for controlled shutdown of the gateway, all tasks run in
+    // subgroups of it
+    let mut task_group = TaskGroup::new();
+    task_group.install_kill_handler();
+
+    // A standby waits for the lease before it opens the database or talks to
+    // the lightning node, a leader that loses its lease stops right away so the
+    // instance taking over is the only one settling HTLCs
+    let lease = match instance_id {
+        Some(instance_id) => {
+            let ttl = Duration::from_secs(lease_ttl_secs);
+            let lease = Arc::new(LeaderLease::new(
+                data_dir.join(LEASE_FILE),
+                instance_id,
+                ttl,
+            ));
+            info!("Waiting for the leader lease");
+            lease.acquire().await?;
+
+            let renewing = lease.clone();
+            task_group
+                .spawn("renew leader lease", move |handle| async move {
+                    while !handle.is_shutting_down() {
+                        sleep(ttl / 3).await;
+                        if let Err(e) = renewing.renew() {
+                            error!("Stopping gateway: {}", e);
+                            exit(1);
+                        }
+                    }
+                })
+                .await;
+            Some(lease)
+        }
+        None => None,
+    };
+
+    if let Some(bind_metrics) = bind_metrics {
+        let metrics_task_group = task_group.clone();
+        task_group
+            .spawn("metrics-server", move |_| async move {
+                run_metrics_server(bind_metrics, metrics_task_group).await;
+            })
+            .await;
+    }
 
     let lnrpc: Arc<RwLock<dyn ILnRpcClient>> = match mode {
         Mode::Cln { cln_extension_addr } => {
@@ -114,22 +281,10 @@ async fn main() -> Result<(), anyhow::Error> {
             ))
         }
     };
-
-    // Create module decoder registry
-    let decoders = ModuleDecoderRegistry::from_iter([
-        (
-            LEGACY_HARDCODED_INSTANCE_ID_LN,
-            LightningModuleTypes::decoder(),
-        ),
-        (
-            LEGACY_HARDCODED_INSTANCE_ID_MINT,
-            MintModuleTypes::decoder(),
-        ),
-        (
-            LEGACY_HARDCODED_INSTANCE_ID_WALLET,
-            WalletModuleTypes::decoder(),
-        ),
-    ]);
+    let lnrpc: Arc<RwLock<dyn ILnRpcClient>> = match &lease {
+        Some(lease) => Arc::new(RwLock::new(FencedLnRpcClient::new(lnrpc, lease.clone()))),
+        None => lnrpc,
+    };
 
     // Create module generator registry
     let module_gens = ClientModuleGenRegistry::from(vec![
@@ -138,13 +293,39 @@ async fn main() -> Result<(), anyhow::Error> {
         DynClientModuleGen::from(LightningClientGen),
     ]);
 
+    let htlc_policy = HtlcPolicyConfig {
+        webhook: htlc_policy_webhook,
+        webhook_timeout: Duration::from_secs(htlc_policy_webhook_timeout_secs),
+    }
+    .build()
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to set up the HTLC policy: {e:?}");
+        exit(1)
+    });
+
     // Create gateway instance
     let gateway = Gateway::new(
         lnrpc,
         client_builder,
-        decoders,
         module_gens,
         task_group.make_subgroup().await,
+        RouteHintConfig {
+            max_route_hints,
+            preferred_channels: preferred_route_hint_channels,
+        },
+        lease,
+        FeeOracleConfig {
+            kind: fee_oracle,
+            base_fees: GatewayFee {
+                base_msat: fee_base_msat,
+                proportional_millionths: fee_proportional_millionths,
+            },
+            mempool_api: mempool_api_url,
+            liquidity_target: Amount::from_msats(liquidity_target_msat),
+        }
+        .build(),
+        htlc_policy,
+        bridge_federations,
     )
     .await
     .unwrap_or_else(|e| {
@@ -152,12 +333,11 @@ async fn main() -> Result<(), anyhow::Error> {
         exit(1)
     });
 
-    if let Err(e) = gateway.run(listen, password).await {
-        task_group.shutdown_join_all(None).await?;
-
+    let result = gateway.run(listen, grpc_listen, password).await;
+    if let Err(e) = &result {
         error!("Gateway stopped with error: {}", e);
-        return Err(e.into());
     }
+    task_group.shutdown_join_all(Some(SHUTDOWN_TIMEOUT)).await?;
 
-    Ok(())
+    Ok(result?)
 }
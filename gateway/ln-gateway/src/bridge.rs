@@ -0,0 +1,75 @@
+//! Bridging HTLCs between the federations of the gateway
+//!
+//! An HTLC intercepted over the short channel id of one federation that pays
+//! no offer of its users is handed to another federation of the gateway with
+//! an offer for its payment hash. The gateway buys the preimage there and
+//! settles the intercepted HTLC with it, so the payment moves between the
+//! federations over the single hop through the lightning node.
+
+use std::future::Future;
+
+use futures::future::join_all;
+
+/// Picks the first of `candidates` for which `has_offer` is true, asking all
+/// of them at once so a slow federation doesn't delay the others
+pub async fn select_bridge_federation<T, F, Fut>(candidates: Vec<T>, has_offer: F) -> Option<T>
+where
+    F: Fn(&T) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let offers = join_all(candidates.iter().map(has_offer)).await;
+    candidates
+        .into_iter()
+        .zip(offers)
+        .find_map(|(candidate, has_offer)| has_offer.then_some(candidate))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::sync::Barrier;
+
+    use super::select_bridge_federation;
+
+    #[tokio::test]
+    async fn bridge_picks_the_first_federation_with_an_offer() {
+        let candidates = vec![(1, false), (2, true), (3, true)];
+        let selected = select_bridge_federation(candidates, |(_, has_offer)| {
+            let has_offer = *has_offer;
+            async move { has_offer }
+        })
+        .await;
+        assert_eq!(selected, Some((2, true)));
+
+        let candidates = vec![(1, false), (2, false)];
+        let selected = select_bridge_federation(candidates, |(_, has_offer)| {
+            let has_offer = *has_offer;
+            async move { has_offer }
+        })
+        .await;
+        assert_eq!(selected, None);
+    }
+
+    #[tokio::test]
+    async fn bridge_looks_up_offers_concurrently() {
+        // every lookup waits for all others, so lookups one after another
+        // would never finish
+        let barrier = Arc::new(Barrier::new(3));
+        let selected = tokio::time::timeout(
+            Duration::from_secs(5),
+            select_bridge_federation(vec![1, 2, 3], |candidate| {
+                let barrier = barrier.clone();
+                let has_offer = *candidate == 3;
+                async move {
+                    barrier.wait().await;
+                    has_offer
+                }
+            }),
+        )
+        .await
+        .expect("lookups ran concurrently");
+        assert_eq!(selected, Some(3));
+    }
+}
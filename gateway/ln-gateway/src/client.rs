@@ -13,13 +13,18 @@ use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::db::Database;
 use fedimint_core::dyn_newtype_define;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use mint_client::{module_decode_stubs, Client, GatewayClientConfig};
+use mint_client::{Client, GatewayClientConfig};
 use secp256k1::{KeyPair, PublicKey};
 use tracing::{debug, warn};
 use url::Url;
 
+use crate::migration::{clear_database, import_database};
+use crate::scid::ScidMap;
 use crate::{GatewayError, Result};
 
+/// File in the work directory keeping the short channel ids of federations
+const SCID_MAP_FILE: &str = "scids.json";
+
 pub trait IDbFactory: Debug {
     fn create_database(
         &self,
@@ -78,6 +83,17 @@ pub trait IGatewayClientBuilder: Debug {
         module_gens: ClientModuleGenRegistry,
     ) -> Result<Client<GatewayClientConfig>>;
 
+    /// Build a new gateway federation client on a database holding the
+    /// `entries` exported from another gateway, see
+    /// [`crate::migration::import_database`]
+    async fn import(
+        &self,
+        config: GatewayClientConfig,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+        decoders: ModuleDecoderRegistry,
+        module_gens: ClientModuleGenRegistry,
+    ) -> Result<Client<GatewayClientConfig>>;
+
     /// Create a new gateway federation client config from connect info
     async fn create_config(
         &self,
@@ -92,6 +108,13 @@ pub trait IGatewayClientBuilder: Debug {
 
     /// Load all gateway client configs from the work directory
     fn load_configs(&self) -> Result<Vec<GatewayClientConfig>>;
+
+    /// Persist the short channel ids assigned to federations
+    fn save_scids(&self, scids: &ScidMap) -> Result<()>;
+
+    /// Load the short channel ids assigned to federations, none if they were
+    /// never saved
+    fn load_scids(&self) -> Result<ScidMap>;
 }
 
 dyn_newtype_define! {
@@ -130,11 +153,37 @@ impl IGatewayClientBuilder for StandardGatewayClientBuilder {
         let db = self.db_factory.create_database(
             federation_id,
             self.work_dir.clone(),
-            module_decode_stubs(),
+            decoders.clone(),
+        )?;
+        let ctx = secp256k1::Secp256k1::new();
+
+        Ok(Client::new(config, decoders, module_gens, db, ctx).await?)
+    }
+
+    async fn import(
+        &self,
+        config: GatewayClientConfig,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+        decoders: ModuleDecoderRegistry,
+        module_gens: ClientModuleGenRegistry,
+    ) -> Result<Client<GatewayClientConfig>> {
+        let federation_id = config.client_config.federation_id.clone();
+
+        let db = self.db_factory.create_database(
+            federation_id,
+            self.work_dir.clone(),
+            decoders.clone(),
         )?;
+        import_database(&db, entries).await?;
         let ctx = secp256k1::Secp256k1::new();
 
-        Ok(Client::new(config, decoders, module_gens, db, ctx).await)
+        match Client::new(config, decoders, module_gens, db.clone(), ctx).await {
+            Ok(client) => Ok(client),
+            Err(e) => {
+                clear_database(&db).await;
+                Err(e.into())
+            }
+        }
     }
 
     async fn create_config(
@@ -198,6 +247,10 @@ impl IGatewayClientBuilder for StandardGatewayClientBuilder {
                     return None;
                 }
 
+                if file.file_name() == SCID_MAP_FILE {
+                    return None;
+                }
+
                 if file
                     .path()
                     .extension()
@@ -219,4 +272,25 @@ impl IGatewayClientBuilder for StandardGatewayClientBuilder {
             })
             .collect())
     }
+
+    fn save_scids(&self, scids: &ScidMap) -> Result<()> {
+        let path = self.work_dir.join(SCID_MAP_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+
+        // written to a temporary file first, so a crash can't lose assigned ids
+        let file = File::create(&tmp_path).map_err(|e| GatewayError::Other(e.into()))?;
+        serde_json::to_writer_pretty(file, scids).map_err(|e| GatewayError::Other(e.into()))?;
+        std::fs::rename(tmp_path, path).map_err(|e| GatewayError::Other(e.into()))?;
+
+        Ok(())
+    }
+
+    fn load_scids(&self) -> Result<ScidMap> {
+        let path = self.work_dir.join(SCID_MAP_FILE);
+        if !path.is_file() {
+            return Ok(ScidMap::default());
+        }
+
+        load_from_file(&path).map_err(GatewayError::Other)
+    }
 }
@@ -0,0 +1,63 @@
+//! Correlation ids identifying a single payment in the logs
+//!
+//! An id is assigned when an HTLC is intercepted or an outgoing payment is
+//! requested. It is recorded as the `correlation_id` field of the span the
+//! payment is processed in, so every log line of its federation calls and
+//! lightning RPCs carries it, and sent along with the requests to the
+//! lightning node, so its logs can be matched with the ones of the gateway.
+
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CorrelationId(u64);
+
+impl CorrelationId {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Parses the hex encoding of [`CorrelationId`]'s `Display`, e.g. to look up
+/// the id sent along with a request to the lightning node
+impl FromStr for CorrelationId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::CorrelationId;
+
+    #[test]
+    fn correlation_id_is_padded_hex() {
+        assert_eq!(CorrelationId(0xab).to_string(), "00000000000000ab");
+        assert_eq!(CorrelationId(u64::MAX).to_string(), "ffffffffffffffff");
+        assert_ne!(CorrelationId::random(), CorrelationId::random());
+    }
+
+    #[test]
+    fn correlation_id_roundtrips() {
+        let id = CorrelationId::random();
+        assert_eq!(CorrelationId::from_str(&id.to_string()), Ok(id));
+    }
+
+    #[test]
+    fn invalid_correlation_id_is_rejected() {
+        for invalid in ["", "not hex", "1ffffffffffffffff"] {
+            assert!(CorrelationId::from_str(invalid).is_err(), "{invalid}");
+        }
+    }
+}
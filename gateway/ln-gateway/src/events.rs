@@ -0,0 +1,81 @@
+//! Events about the operations of the gateway, streamed to subscribers of the
+//! gRPC API and, for the payments they make, to callers of the webserver
+use bitcoin::secp256k1::PublicKey;
+use fedimint_core::config::FederationId;
+use fedimint_core::TransactionId;
+use mint_client::modules::ln::contracts::ContractId;
+use serde::Serialize;
+
+/// Events buffered per subscriber, a subscriber that falls further behind
+/// misses the oldest ones
+pub const EVENT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GatewayEvent {
+    FederationConnected {
+        federation_id: FederationId,
+    },
+    /// Progress of paying the invoice of an outgoing contract, which ends with
+    /// [`GatewayEvent::OutgoingPaymentSucceeded`] or
+    /// [`GatewayEvent::OutgoingPaymentFailed`]
+    OutgoingPaymentUpdated {
+        federation_id: FederationId,
+        contract_id: ContractId,
+        status: OutgoingPaymentStatus,
+    },
+    OutgoingPaymentSucceeded {
+        federation_id: FederationId,
+        contract_id: ContractId,
+    },
+    OutgoingPaymentFailed {
+        federation_id: FederationId,
+        contract_id: ContractId,
+        error: String,
+    },
+    DepositSubmitted {
+        federation_id: FederationId,
+        txid: TransactionId,
+    },
+    WithdrawalSubmitted {
+        federation_id: FederationId,
+        txid: TransactionId,
+    },
+    /// The invoice of a sweep funded with `contract_id` was paid by the
+    /// gateway with the node key `gateway`, see [`crate::sweep`]
+    SweepToLnSucceeded {
+        federation_id: FederationId,
+        contract_id: ContractId,
+        gateway: PublicKey,
+    },
+    SweepToLnFailed {
+        federation_id: FederationId,
+        contract_id: ContractId,
+        error: String,
+    },
+    /// An onion message addressed to the lightning node, see
+    /// [`crate::gatewaylnrpc::OnionMessage`]
+    OnionMessageReceived {
+        /// TLV records of the payload by type
+        fields: Vec<(u64, Vec<u8>)>,
+        reply_path: Option<Vec<u8>>,
+    },
+}
+
+/// Steps of paying the invoice of an outgoing contract, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutgoingPaymentStatus {
+    /// The contract was fetched from the federation
+    ContractFetched,
+    /// The contract and its invoice were validated, so paying it is safe
+    ContractValidated,
+    /// The payment is in flight, over lightning or, for invoices of other
+    /// users of the federation, by buying the preimage from it
+    PaymentInFlight { internal: bool },
+    /// The preimage was received, so the contract can be claimed
+    PreimageReceived,
+    /// The transaction claiming the contract was submitted, it's accepted
+    /// with [`GatewayEvent::OutgoingPaymentSucceeded`]
+    ClaimSubmitted,
+}
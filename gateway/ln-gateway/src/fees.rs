@@ -0,0 +1,295 @@
+//! Fee oracles decide the routing fees the gateway charges for payments to its
+//! federations
+//!
+//! The gateway announces the fees of its oracle to every federation, payers pay
+//! them on the last hop of the invoice's route, and intercepted HTLCs that
+//! leave the gateway less are rejected. Besides static fees, oracles can raise
+//! the fees while on-chain fees are high, since that makes opening and
+//! rebalancing channels expensive, or while the gateway runs low on ecash.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use clap::ValueEnum;
+use fedimint_core::{dyn_newtype_define, Amount};
+use mint_client::modules::ln::GatewayFee;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tracing::warn;
+use url::Url;
+
+/// Public mempool.space instance queried for on-chain fee rates by default
+pub const DEFAULT_MEMPOOL_API: &str = "https://mempool.space/api/";
+/// Ecash balance below which the liquidity-weighted oracle raises its fees
+pub const DEFAULT_LIQUIDITY_TARGET: Amount = Amount::from_sats(10_000_000);
+/// On-chain fee rate in sat/vB up to which the mempool-indexed oracle charges
+/// the base fees
+const REFERENCE_FEE_RATE: f64 = 10.0;
+/// Most dynamic fees can exceed the base fees by, as a multiple of them
+const MAX_FEE_MULTIPLIER: f64 = 10.0;
+/// How long the mempool-indexed oracle reuses a fetched fee rate
+const FEE_RATE_TTL: Duration = Duration::from_secs(5 * 60);
+/// How long the mempool-indexed oracle waits for the fee rate before charging
+/// the base fees
+const MEMPOOL_API_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[async_trait]
+pub trait FeeOracle: Debug + Send + Sync {
+    /// Fees for payments to a federation in which the gateway holds
+    /// `liquidity` of ecash
+    async fn fees(&self, liquidity: Amount) -> GatewayFee;
+}
+
+dyn_newtype_define! {
+    /// dyn newtype for a fee oracle
+    #[derive(Clone)]
+    pub DynFeeOracle(Arc<FeeOracle>)
+}
+
+/// Which fee oracle the gateway uses
+#[derive(Debug, Clone, Copy, Eq, PartialEq, ValueEnum)]
+pub enum FeeOracleKind {
+    /// Always charge the base fees
+    Static,
+    /// Scale the base fees with the on-chain fee rate
+    Mempool,
+    /// Scale the base fees with how far the ecash balance is below a target
+    Liquidity,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeOracleConfig {
+    pub kind: FeeOracleKind,
+    /// Fees charged by the static oracle, and at least charged by the others
+    pub base_fees: GatewayFee,
+    /// mempool.space compatible API the mempool-indexed oracle queries
+    pub mempool_api: Url,
+    /// Ecash balance below which the liquidity-weighted oracle raises its fees
+    pub liquidity_target: Amount,
+}
+
+impl Default for FeeOracleConfig {
+    fn default() -> Self {
+        Self {
+            kind: FeeOracleKind::Static,
+            base_fees: GatewayFee::default(),
+            mempool_api: Url::parse(DEFAULT_MEMPOOL_API).expect("valid url"),
+            liquidity_target: DEFAULT_LIQUIDITY_TARGET,
+        }
+    }
+}
+
+impl FeeOracleConfig {
+    pub fn build(&self) -> DynFeeOracle {
+        match self.kind {
+            FeeOracleKind::Static => StaticFeeOracle {
+                fees: self.base_fees,
+            }
+            .into(),
+            FeeOracleKind::Mempool => {
+                MempoolFeeOracle::new(self.base_fees, self.mempool_api.clone()).into()
+            }
+            FeeOracleKind::Liquidity => LiquidityFeeOracle {
+                base_fees: self.base_fees,
+                target: self.liquidity_target,
+            }
+            .into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticFeeOracle {
+    pub fees: GatewayFee,
+}
+
+#[async_trait]
+impl FeeOracle for StaticFeeOracle {
+    async fn fees(&self, _liquidity: Amount) -> GatewayFee {
+        self.fees
+    }
+}
+
+/// Charges the base fees times the on-chain fee rate over
+/// [`REFERENCE_FEE_RATE`]
+#[derive(Debug, Clone)]
+pub struct MempoolFeeOracle {
+    pub base_fees: GatewayFee,
+    pub api: Url,
+    client: reqwest::Client,
+    /// Last fetched fee rate and when it was fetched
+    cached: Arc<Mutex<Option<(Instant, f64)>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecommendedFees {
+    half_hour_fee: f64,
+}
+
+impl MempoolFeeOracle {
+    pub fn new(base_fees: GatewayFee, api: Url) -> Self {
+        Self {
+            base_fees,
+            api,
+            client: reqwest::Client::builder()
+                .timeout(MEMPOOL_API_TIMEOUT)
+                .build()
+                .expect("Failed to build HTTP client"),
+            cached: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The cached fee rate, or a freshly fetched one if it's older than
+    /// [`FEE_RATE_TTL`]
+    async fn fee_rate(&self) -> anyhow::Result<f64> {
+        // Holding the lock while fetching lets concurrent callers share a fetch
+        let mut cached = self.cached.lock().await;
+        if let Some((fetched_at, fee_rate)) = *cached {
+            if fetched_at.elapsed() < FEE_RATE_TTL {
+                return Ok(fee_rate);
+            }
+        }
+
+        let fee_rate = self.fetch_fee_rate().await?;
+        *cached = Some((Instant::now(), fee_rate));
+        Ok(fee_rate)
+    }
+
+    async fn fetch_fee_rate(&self) -> anyhow::Result<f64> {
+        let url = self.api.join("v1/fees/recommended")?;
+        let fees: RecommendedFees = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(fees.half_hour_fee)
+    }
+}
+
+#[async_trait]
+impl FeeOracle for MempoolFeeOracle {
+    async fn fees(&self, _liquidity: Amount) -> GatewayFee {
+        match self.fee_rate().await {
+            Ok(fee_rate) => scale(self.base_fees, fee_rate / REFERENCE_FEE_RATE),
+            Err(e) => {
+                warn!(
+                    "Failed to fetch on-chain fee rate, charging base fees: {}",
+                    e
+                );
+                self.base_fees
+            }
+        }
+    }
+}
+
+/// Charges the base fees times how many times the ecash balance fits into the
+/// target
+#[derive(Debug, Clone)]
+pub struct LiquidityFeeOracle {
+    pub base_fees: GatewayFee,
+    pub target: Amount,
+}
+
+#[async_trait]
+impl FeeOracle for LiquidityFeeOracle {
+    async fn fees(&self, liquidity: Amount) -> GatewayFee {
+        let multiplier = self.target.msats as f64 / liquidity.msats.max(1) as f64;
+        scale(self.base_fees, multiplier)
+    }
+}
+
+/// Multiplies `fees` by `multiplier`, clamped to `[1, MAX_FEE_MULTIPLIER]`
+fn scale(fees: GatewayFee, multiplier: f64) -> GatewayFee {
+    let multiplier = multiplier.clamp(1.0, MAX_FEE_MULTIPLIER);
+    GatewayFee {
+        base_msat: (fees.base_msat as f64 * multiplier) as u32,
+        proportional_millionths: (fees.proportional_millionths as f64 * multiplier) as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use fedimint_core::Amount;
+    use mint_client::modules::ln::GatewayFee;
+    use url::Url;
+
+    use super::{
+        FeeOracle, LiquidityFeeOracle, MempoolFeeOracle, FEE_RATE_TTL, MAX_FEE_MULTIPLIER,
+    };
+
+    const BASE_FEES: GatewayFee = GatewayFee {
+        base_msat: 1_000,
+        proportional_millionths: 100,
+    };
+
+    #[tokio::test]
+    async fn liquidity_oracle_raises_fees_below_target() {
+        let oracle = LiquidityFeeOracle {
+            base_fees: BASE_FEES,
+            target: Amount::from_sats(1_000),
+        };
+
+        assert_eq!(oracle.fees(Amount::from_sats(2_000)).await, BASE_FEES);
+        assert_eq!(
+            oracle.fees(Amount::from_sats(500)).await,
+            GatewayFee {
+                base_msat: 2_000,
+                proportional_millionths: 200,
+            }
+        );
+        assert_eq!(
+            oracle.fees(Amount::ZERO).await.base_msat,
+            (BASE_FEES.base_msat as f64 * MAX_FEE_MULTIPLIER) as u32
+        );
+    }
+
+    #[tokio::test]
+    async fn mempool_oracle_reuses_fee_rate_until_it_expires() {
+        // nothing listens on the discard port, so every fetch fails
+        let oracle = MempoolFeeOracle::new(BASE_FEES, Url::parse("http://127.0.0.1:9/").unwrap());
+        assert_eq!(oracle.fees(Amount::ZERO).await, BASE_FEES);
+
+        *oracle.cached.lock().await = Some((Instant::now(), 30.0));
+        assert_eq!(
+            oracle.fees(Amount::ZERO).await,
+            GatewayFee {
+                base_msat: 3_000,
+                proportional_millionths: 300,
+            }
+        );
+
+        let expired = Instant::now().checked_sub(FEE_RATE_TTL * 2).unwrap();
+        *oracle.cached.lock().await = Some((expired, 30.0));
+        assert_eq!(oracle.fees(Amount::ZERO).await, BASE_FEES);
+    }
+
+    #[tokio::test]
+    async fn mempool_oracle_caps_fees() {
+        let oracle = MempoolFeeOracle::new(BASE_FEES, Url::parse("http://127.0.0.1:9/").unwrap());
+        *oracle.cached.lock().await = Some((Instant::now(), 1_000.0));
+        assert_eq!(
+            oracle.fees(Amount::ZERO).await.base_msat,
+            (BASE_FEES.base_msat as f64 * MAX_FEE_MULTIPLIER) as u32
+        );
+    }
+
+    #[test]
+    fn fee_amount_is_base_plus_proportional() {
+        assert_eq!(
+            BASE_FEES.amount(Amount::from_sats(1_000)),
+            Amount::from_msats(1_100)
+        );
+        assert_eq!(
+            GatewayFee::default().amount(Amount::from_sats(1)),
+            Amount::ZERO
+        );
+    }
+}
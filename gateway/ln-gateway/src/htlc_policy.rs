@@ -0,0 +1,194 @@
+//! Policies deciding whether the gateway accepts an intercepted HTLC
+//!
+//! The policy is asked once an HTLC passed the gateway's own checks of its
+//! amount, fees and liquidity, right before the gateway buys the preimage from
+//! the federation. Operators can plug in custom rules this way, e.g. reject
+//! payments over a KYC threshold, either in code or through a webhook.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use fedimint_core::dyn_newtype_define;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use url::Url;
+
+/// How long the gateway waits for the webhook before rejecting the HTLC
+pub const DEFAULT_WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Details of an intercepted HTLC a policy decides on, also sent as JSON body
+/// to the webhook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InterceptedHtlc {
+    /// Id of the federation the HTLC is paying into
+    pub federation_id: String,
+    /// Id of the federation the HTLC was intercepted for if the gateway
+    /// bridges it to `federation_id`
+    #[serde(default)]
+    pub bridged_from: Option<String>,
+    /// Hex encoded payment hash
+    pub payment_hash: String,
+    pub incoming_amount_msat: u64,
+    pub outgoing_amount_msat: u64,
+    /// Block height at which the lightning node cancels the HTLC
+    pub incoming_expiry: u32,
+    /// Short channel id the HTLC was intercepted over, the one of
+    /// `bridged_from` for bridged HTLCs
+    pub short_channel_id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HtlcDecision {
+    Accept,
+    /// Cancel the HTLC, the reason is logged and sent to the lightning node
+    Reject {
+        reason: String,
+    },
+}
+
+#[async_trait]
+pub trait HtlcInterceptorPolicy: Debug + Send + Sync {
+    async fn decide(&self, htlc: &InterceptedHtlc) -> HtlcDecision;
+}
+
+dyn_newtype_define! {
+    /// dyn newtype for an HTLC interceptor policy
+    #[derive(Clone)]
+    pub DynHtlcInterceptorPolicy(Arc<HtlcInterceptorPolicy>)
+}
+
+#[derive(Debug, Clone)]
+pub struct HtlcPolicyConfig {
+    /// Endpoint asked about every HTLC, all HTLCs are accepted if unset
+    pub webhook: Option<Url>,
+    /// How long the webhook may take to answer
+    pub webhook_timeout: Duration,
+}
+
+impl Default for HtlcPolicyConfig {
+    fn default() -> Self {
+        Self {
+            webhook: None,
+            webhook_timeout: DEFAULT_WEBHOOK_TIMEOUT,
+        }
+    }
+}
+
+impl HtlcPolicyConfig {
+    pub fn build(&self) -> anyhow::Result<DynHtlcInterceptorPolicy> {
+        Ok(match &self.webhook {
+            None => PassThroughPolicy.into(),
+            Some(url) => WebhookPolicy {
+                url: url.clone(),
+                client: reqwest::Client::builder()
+                    .timeout(self.webhook_timeout)
+                    .build()?,
+            }
+            .into(),
+        })
+    }
+}
+
+/// Accepts every HTLC
+#[derive(Debug, Clone)]
+pub struct PassThroughPolicy;
+
+#[async_trait]
+impl HtlcInterceptorPolicy for PassThroughPolicy {
+    async fn decide(&self, _htlc: &InterceptedHtlc) -> HtlcDecision {
+        HtlcDecision::Accept
+    }
+}
+
+/// Answer of the webhook
+#[derive(Debug, Deserialize)]
+struct WebhookResponse {
+    accept: bool,
+    reason: Option<String>,
+}
+
+/// POSTs every HTLC to an external service and follows its decision. HTLCs
+/// are rejected while the service can't be reached, so an outage never lets
+/// through payments it would have rejected.
+#[derive(Debug, Clone)]
+pub struct WebhookPolicy {
+    pub url: Url,
+    client: reqwest::Client,
+}
+
+impl WebhookPolicy {
+    async fn ask(&self, htlc: &InterceptedHtlc) -> anyhow::Result<WebhookResponse> {
+        Ok(self
+            .client
+            .post(self.url.clone())
+            .json(htlc)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl HtlcInterceptorPolicy for WebhookPolicy {
+    async fn decide(&self, htlc: &InterceptedHtlc) -> HtlcDecision {
+        match self.ask(htlc).await {
+            Ok(WebhookResponse { accept: true, .. }) => HtlcDecision::Accept,
+            Ok(WebhookResponse {
+                accept: false,
+                reason,
+            }) => HtlcDecision::Reject {
+                reason: reason.unwrap_or_else(|| "Rejected by policy".to_string()),
+            },
+            Err(e) => {
+                warn!("Failed to ask the HTLC policy webhook, rejecting: {}", e);
+                HtlcDecision::Reject {
+                    reason: "HTLC policy unavailable".to_string(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::config::FederationId;
+
+    use super::{HtlcDecision, HtlcInterceptorPolicy, HtlcPolicyConfig, InterceptedHtlc};
+
+    fn htlc() -> InterceptedHtlc {
+        InterceptedHtlc {
+            federation_id: FederationId::dummy().to_string(),
+            bridged_from: None,
+            payment_hash: "00".repeat(32),
+            incoming_amount_msat: 1_100,
+            outgoing_amount_msat: 1_000,
+            incoming_expiry: 800_000,
+            short_channel_id: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn default_policy_accepts_everything() {
+        let policy = HtlcPolicyConfig::default().build().unwrap();
+        assert_eq!(policy.decide(&htlc()).await, HtlcDecision::Accept);
+    }
+
+    #[tokio::test]
+    async fn unreachable_webhook_rejects() {
+        let policy = HtlcPolicyConfig {
+            // Nothing listens on the discard port
+            webhook: Some("http://127.0.0.1:9/".parse().unwrap()),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        assert!(matches!(
+            policy.decide(&htlc()).await,
+            HtlcDecision::Reject { .. }
+        ));
+    }
+}
@@ -0,0 +1,395 @@
+//! Leader election between gatewayd instances sharing a data directory
+//!
+//! Only one instance, the leader, may intercept HTLCs and register with
+//! federations. The others wait as standbys until the leader's lease expires
+//! and one of them takes over. Every change of leader increments the lease's
+//! epoch, which the leader sends as fencing token along its HTLC subscriptions
+//! and completions so the lightning node ignores an old leader that didn't
+//! notice it was replaced.
+//!
+//! Every epoch has a lease file of its own, which the instance claiming the
+//! epoch creates atomically, so only one of several standbys racing for an
+//! expired lease can win it. Only the holder of an epoch writes its file
+//! afterwards, the lease with the highest epoch is the current one.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use fedimint_core::task::{sleep, RwLock};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::gatewaylnrpc::{
+    CompleteHtlcsRequest, CompleteHtlcsResponse, GetLiquidityResponse, GetNodeInfoResponse,
+    GetRouteHintsResponse, ListPaymentsRequest, ListPaymentsResponse, PayInvoiceRequest,
+    PayInvoiceResponse, SendOnionMessageRequest, SubscribeInterceptHtlcsRequest,
+};
+use crate::lnrpc_client::{ChannelUpdateStream, HtlcStream, ILnRpcClient, OnionMessageStream};
+use crate::{GatewayError, Result};
+
+/// Name of the lease files in the gateway's data directory, suffixed with
+/// their epoch
+pub const LEASE_FILE: &str = "leader.lease";
+/// How long a lease lasts unless the leader renews it
+pub const DEFAULT_LEASE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Lease {
+    /// Id of the instance holding the lease
+    pub holder: String,
+    /// Incremented whenever another instance takes over the lease
+    pub epoch: u64,
+    /// Unix time in seconds at which the lease expires unless renewed
+    pub expires_at: u64,
+}
+
+#[derive(Debug)]
+pub struct LeaderLease {
+    path: PathBuf,
+    instance_id: String,
+    ttl: Duration,
+    /// Epoch of the lease this instance acquired, 0 before it became leader
+    epoch: AtomicU64,
+}
+
+impl LeaderLease {
+    pub fn new(path: PathBuf, instance_id: String, ttl: Duration) -> Self {
+        Self {
+            path,
+            instance_id,
+            ttl,
+            epoch: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits until this instance holds the lease, returns its epoch
+    pub async fn acquire(&self) -> Result<u64> {
+        loop {
+            let current = self.read()?;
+            if let Some(lease) = &current {
+                if lease.holder != self.instance_id && lease.expires_at > unix_now() {
+                    sleep(self.ttl / 3).await;
+                    continue;
+                }
+            }
+
+            // Another standby may claim the next epoch at the same time, only
+            // the first one to create its lease file wins it
+            let epoch = current.map_or(1, |lease| lease.epoch + 1);
+            if self.claim(epoch)? {
+                info!(
+                    "Instance {} became leader in epoch {epoch}",
+                    self.instance_id
+                );
+                self.epoch.store(epoch, Ordering::SeqCst);
+                self.remove_stale(epoch);
+                return Ok(epoch);
+            }
+        }
+    }
+
+    /// Extends the lease by its time to live, fails if this instance lost it
+    pub fn renew(&self) -> Result<()> {
+        let epoch = self.fencing_token()?;
+        // If another instance claimed a newer epoch in the meantime, this only
+        // extends a lease that isn't the current one anymore
+        self.write(epoch)
+    }
+
+    /// Epoch of the lease while this instance holds it, to be sent along every
+    /// request only the leader may make
+    pub fn fencing_token(&self) -> Result<u64> {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        match self.read()? {
+            Some(lease)
+                if epoch != 0
+                    && lease.holder == self.instance_id
+                    && lease.epoch == epoch
+                    && lease.expires_at > unix_now() =>
+            {
+                Ok(epoch)
+            }
+            _ => Err(GatewayError::LostLeadership),
+        }
+    }
+
+    /// The lease with the highest epoch, `None` if nobody ever held one
+    fn read(&self) -> Result<Option<Lease>> {
+        loop {
+            let Some(epoch) = self.epochs()?.into_iter().max() else {
+                return Ok(None);
+            };
+            match fs::read_to_string(epoch_path(&self.path, epoch)) {
+                Ok(json) => {
+                    return Ok(Some(
+                        serde_json::from_str(&json).map_err(|e| GatewayError::Other(e.into()))?,
+                    ))
+                }
+                // removed by the leader of a newer epoch since we listed it
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(GatewayError::Other(e.into())),
+            }
+        }
+    }
+
+    /// Epochs of all lease files in the data directory
+    fn epochs(&self) -> Result<Vec<u64>> {
+        let prefix = format!("{}.", lease_file_name(&self.path));
+        let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(GatewayError::Other(e.into())),
+        };
+
+        let mut epochs = vec![];
+        for entry in entries {
+            let entry = entry.map_err(|e| GatewayError::Other(e.into()))?;
+            let name = entry.file_name();
+            if let Some(epoch) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|epoch| epoch.parse().ok())
+            {
+                epochs.push(epoch);
+            }
+        }
+        Ok(epochs)
+    }
+
+    /// Creates the lease file of `epoch`, `false` if another instance created
+    /// it first
+    fn claim(&self, epoch: u64) -> Result<bool> {
+        let tmp_path = self.write_tmp(epoch)?;
+        // Linking fails if the lease file exists already, unlike a rename
+        let claimed = match fs::hard_link(&tmp_path, epoch_path(&self.path, epoch)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(GatewayError::Other(e.into())),
+        };
+        let _ = fs::remove_file(&tmp_path);
+        claimed
+    }
+
+    /// Writes the lease file of `epoch`, which only its holder may do
+    fn write(&self, epoch: u64) -> Result<()> {
+        let tmp_path = self.write_tmp(epoch)?;
+        fs::rename(&tmp_path, epoch_path(&self.path, epoch))
+            .map_err(|e| GatewayError::Other(e.into()))
+    }
+
+    /// Writes the lease of `epoch` to a file of our own first, so others never
+    /// read a partial lease
+    fn write_tmp(&self, epoch: u64) -> Result<PathBuf> {
+        let lease = Lease {
+            holder: self.instance_id.clone(),
+            epoch,
+            expires_at: unix_now() + self.ttl.as_secs(),
+        };
+        let json = serde_json::to_string(&lease).map_err(|e| GatewayError::Other(e.into()))?;
+
+        let tmp_path = self.path.with_file_name(format!(
+            "{}.tmp.{}",
+            lease_file_name(&self.path),
+            self.instance_id
+        ));
+        fs::write(&tmp_path, json).map_err(|e| GatewayError::Other(e.into()))?;
+        Ok(tmp_path)
+    }
+
+    /// Removes the lease files of epochs before the previous one, the previous
+    /// one is kept so an old leader still finds out it was replaced
+    fn remove_stale(&self, epoch: u64) {
+        for stale in self.epochs().unwrap_or_default() {
+            if stale + 1 < epoch {
+                let _ = fs::remove_file(epoch_path(&self.path, stale));
+            }
+        }
+    }
+}
+
+fn lease_file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| LEASE_FILE.to_string())
+}
+
+/// Path of the lease file of `epoch`
+fn epoch_path(path: &Path, epoch: u64) -> PathBuf {
+    path.with_file_name(format!("{}.{epoch}", lease_file_name(path)))
+}
+
+fn unix_now() -> u64 {
+    fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time to be after the unix epoch")
+        .as_secs()
+}
+
+/// An `ILnRpcClient` that only makes requests while the gateway holds the
+/// leader lease, and fences HTLC subscriptions and completions with it
+#[derive(Debug)]
+pub struct FencedLnRpcClient {
+    inner: Arc<RwLock<dyn ILnRpcClient>>,
+    lease: Arc<LeaderLease>,
+}
+
+impl FencedLnRpcClient {
+    pub fn new(inner: Arc<RwLock<dyn ILnRpcClient>>, lease: Arc<LeaderLease>) -> Self {
+        Self { inner, lease }
+    }
+}
+
+#[async_trait]
+impl ILnRpcClient for FencedLnRpcClient {
+    async fn info(&self) -> Result<GetNodeInfoResponse> {
+        self.inner.read().await.info().await
+    }
+
+    async fn routehints(&self) -> Result<GetRouteHintsResponse> {
+        self.inner.read().await.routehints().await
+    }
+
+    async fn pay(&self, invoice: PayInvoiceRequest) -> Result<PayInvoiceResponse> {
+        self.lease.fencing_token()?;
+        self.inner.read().await.pay(invoice).await
+    }
+
+    async fn subscribe_htlcs<'a>(
+        &self,
+        mut subscription: SubscribeInterceptHtlcsRequest,
+    ) -> Result<HtlcStream<'a>> {
+        subscription.fencing_token = Some(self.lease.fencing_token()?);
+        self.inner.read().await.subscribe_htlcs(subscription).await
+    }
+
+    async fn complete_htlc(
+        &self,
+        mut outcome: CompleteHtlcsRequest,
+    ) -> Result<CompleteHtlcsResponse> {
+        outcome.fencing_token = Some(self.lease.fencing_token()?);
+        self.inner.read().await.complete_htlc(outcome).await
+    }
+
+    async fn list_payments(&self, request: ListPaymentsRequest) -> Result<ListPaymentsResponse> {
+        self.inner.read().await.list_payments(request).await
+    }
+
+    async fn liquidity(&self) -> Result<GetLiquidityResponse> {
+        self.inner.read().await.liquidity().await
+    }
+
+    async fn subscribe_channel_updates<'a>(&self) -> Result<ChannelUpdateStream<'a>> {
+        self.inner.read().await.subscribe_channel_updates().await
+    }
+
+    async fn send_onion_message(&self, message: SendOnionMessageRequest) -> Result<()> {
+        self.inner.read().await.send_onion_message(message).await
+    }
+
+    async fn subscribe_onion_messages<'a>(&self) -> Result<OnionMessageStream<'a>> {
+        self.inner.read().await.subscribe_onion_messages().await
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        self.inner.write().await.connect().await
+    }
+
+    async fn disconnect(&mut self) -> Result<()> {
+        self.inner.write().await.disconnect().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{epoch_path, LeaderLease, Lease};
+
+    fn lease_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lease-test-{name}-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(super::LEASE_FILE)
+    }
+
+    #[tokio::test]
+    async fn standby_takes_over_expired_lease() {
+        let path = lease_path("takeover");
+        let active = LeaderLease::new(path.clone(), "a".into(), Duration::from_secs(30));
+        let standby = LeaderLease::new(path.clone(), "b".into(), Duration::from_secs(30));
+
+        assert_eq!(active.acquire().await.unwrap(), 1);
+        assert_eq!(active.fencing_token().unwrap(), 1);
+        assert!(standby.fencing_token().is_err());
+        assert!(standby.renew().is_err());
+
+        // the active instance died and its lease ran out
+        let expired = Lease {
+            holder: "a".into(),
+            epoch: 1,
+            expires_at: 0,
+        };
+        std::fs::write(
+            epoch_path(&path, 1),
+            serde_json::to_string(&expired).unwrap(),
+        )
+        .unwrap();
+        assert!(active.fencing_token().is_err());
+
+        assert_eq!(standby.acquire().await.unwrap(), 2);
+        assert_eq!(standby.fencing_token().unwrap(), 2);
+        assert!(standby.renew().is_ok());
+        assert!(active.renew().is_err());
+    }
+
+    #[tokio::test]
+    async fn only_one_standby_wins_a_split_brain() {
+        let path = lease_path("split-brain");
+        let active = LeaderLease::new(path.clone(), "a".into(), Duration::from_secs(30));
+        let first = LeaderLease::new(path.clone(), "b".into(), Duration::from_secs(30));
+        let second = LeaderLease::new(path.clone(), "c".into(), Duration::from_secs(30));
+
+        assert_eq!(active.acquire().await.unwrap(), 1);
+        let expired = Lease {
+            holder: "a".into(),
+            epoch: 1,
+            expires_at: 0,
+        };
+        std::fs::write(
+            epoch_path(&path, 1),
+            serde_json::to_string(&expired).unwrap(),
+        )
+        .unwrap();
+
+        // both standbys see the expired lease and race for epoch 2
+        let timeout = Duration::from_secs(3);
+        let (first_won, second_won) = tokio::join!(
+            tokio::time::timeout(timeout, first.acquire()),
+            tokio::time::timeout(timeout, second.acquire())
+        );
+        let (winner, loser) = match (first_won, second_won) {
+            (Ok(epoch), Err(_)) => {
+                assert_eq!(epoch.unwrap(), 2);
+                (&first, &second)
+            }
+            (Err(_), Ok(epoch)) => {
+                assert_eq!(epoch.unwrap(), 2);
+                (&second, &first)
+            }
+            _ => panic!("Exactly one standby must become leader"),
+        };
+        assert_eq!(winner.fencing_token().unwrap(), 2);
+        assert!(loser.fencing_token().is_err());
+
+        // the old leader renewing late doesn't take the lease back
+        active.write(1).unwrap();
+        assert!(active.fencing_token().is_err());
+        assert_eq!(winner.fencing_token().unwrap(), 2);
+    }
+}
@@ -1,8 +1,22 @@
+pub mod accounting;
 pub mod actor;
+pub mod bridge;
 pub mod client;
+pub mod correlation;
+pub mod events;
+pub mod fees;
+pub mod htlc_policy;
+pub mod lease;
 pub mod lnd;
 pub mod lnrpc_client;
+pub mod lnurl;
+pub mod metrics;
+pub mod migration;
+pub mod route_hints;
 pub mod rpc;
+pub mod scid;
+pub mod sweep;
+pub mod test_payment;
 pub mod types;
 pub mod utils;
 
@@ -10,19 +24,22 @@ pub mod gatewaylnrpc {
     tonic::include_proto!("gatewaylnrpc");
 }
 
+pub mod gatewayrpc {
+    tonic::include_proto!("gatewayrpc");
+}
+
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 use anyhow::anyhow;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use bitcoin::Address;
-use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::hex::{FromHex, ToHex};
 use clap::Subcommand;
 use fedimint_client::module::gen::ClientModuleGenRegistry;
 use fedimint_core::api::{FederationError, WsClientConnectInfo};
@@ -30,33 +47,55 @@ use fedimint_core::config::FederationId;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::task::{RwLock, TaskGroup};
 use fedimint_core::{Amount, TransactionId};
-use gatewaylnrpc::GetNodeInfoResponse;
-use lnrpc_client::ILnRpcClient;
-use mint_client::ln::PayInvoicePayload;
+use futures::StreamExt;
+use gatewaylnrpc::{GetNodeInfoResponse, OnionMessageField, SendOnionMessageRequest};
+use lnrpc_client::{next_channel_update, ILnRpcClient};
+use mint_client::ln::{HtlcAmountBand, PayInvoicePayload};
+use mint_client::modules::ln::contracts::ContractId;
 use mint_client::modules::ln::route_hints::RouteHint;
-use mint_client::{ClientError, GatewayClient};
+use mint_client::{ClientError, GatewayClient, GatewayClientConfig};
 use rpc::{FederationInfo, LightningReconnectPayload};
 use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::{mpsc, Mutex};
-use tracing::{error, info, warn};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tracing::{debug, error, info, instrument, warn};
 use url::Url;
 
+use crate::accounting::AccountingExport;
 use crate::actor::GatewayActor;
+use crate::bridge::select_bridge_federation;
 use crate::client::DynGatewayClientBuilder;
+use crate::correlation::CorrelationId;
+use crate::events::{GatewayEvent, OutgoingPaymentStatus, EVENT_BUFFER_SIZE};
+use crate::fees::DynFeeOracle;
+use crate::htlc_policy::DynHtlcInterceptorPolicy;
+use crate::lease::{FencedLnRpcClient, LeaderLease};
 use crate::lnd::GatewayLndClient;
 use crate::lnrpc_client::NetworkLnRpcClient;
+use crate::lnurl::{LnurlInvoiceResponse, LnurlPayResponse};
+use crate::migration::{GatewayState, StateArchive};
+use crate::route_hints::RouteHintConfig;
+use crate::rpc::grpc_server::run_grpc_server;
 use crate::rpc::rpc_server::run_webserver;
 use crate::rpc::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    GatewayInfo, GatewayRequest, GatewayRpcSender, InfoPayload, RestorePayload, WithdrawPayload,
+    BackupPayload, BalancePayload, BridgeHtlcPayload, ChannelsUpdatedPayload, ConnectFedPayload,
+    DepositAddressPayload, DepositPayload, ExportAccountingPayload, ExportStatePayload,
+    FirstHopConstraint, GatewayInfo, GatewayRequest, GatewayRpcSender, ImportStatePayload,
+    InfoPayload, LnurlInvoicePayload, LnurlPayPayload, LnurlPaymentInfo, LnurlPaymentsPayload,
+    ManualPayInvoicePayload, PauseFedPayload, PayInvoiceWithStatusPayload,
+    RegisterLightningAddressPayload, RestorePayload, ResumeFedPayload, SendOnionMessagePayload,
+    SetHtlcAmountBandPayload, SetReservePayload, SweepToLnPayload, SweepToLnResponse,
+    TestPaymentPayload, WithdrawPayload,
 };
+use crate::scid::ScidMap;
+use crate::test_payment::TestPaymentReport;
 
 const ROUTE_HINT_RETRIES: usize = 10;
 const ROUTE_HINT_RETRY_SLEEP: Duration = Duration::from_secs(2);
-/// LND HTLC interceptor can't handle SCID of 0, so start from 1
-const INITIAL_SCID: u64 = 1;
+/// How long to wait for further channel updates before refreshing the route
+/// hints, a splice or a batch of channel opens reports several at once
+const CHANNEL_UPDATE_DEBOUNCE: Duration = Duration::from_secs(5);
 
 pub type Result<T> = std::result::Result<T, GatewayError>;
 
@@ -95,6 +134,21 @@ pub enum GatewayError {
     Other(#[from] anyhow::Error),
     #[error("Failed to fetch route hints")]
     FailedToFetchRouteHints,
+    #[error("Temporary liquidity shortage, have {balance} of ecash but need {required}")]
+    InsufficientLiquidity { balance: Amount, required: Amount },
+    #[error("Gateway instance is no longer the leader")]
+    LostLeadership,
+    #[error("HTLC leaves a fee of {fee} but the gateway charges {required}")]
+    InsufficientFee { fee: Amount, required: Amount },
+    #[error("Can sweep at most {max} of ecash, but the invoice asks for {amount}")]
+    SweepExceedsBalance { amount: Amount, max: Amount },
+    #[error("HTLC of {amount} is outside of the intercepted amounts, {band}")]
+    HtlcAmountOutOfBand {
+        amount: Amount,
+        band: HtlcAmountBand,
+    },
+    #[error("The gateway paused routing payments for the federation")]
+    FederationPaused,
 }
 
 impl GatewayError {
@@ -112,7 +166,6 @@ impl IntoResponse for GatewayError {
     }
 }
 pub struct Gateway {
-    decoders: ModuleDecoderRegistry,
     module_gens: ClientModuleGenRegistry,
     lnrpc: Arc<RwLock<dyn ILnRpcClient>>,
     actors: Mutex<HashMap<String, Arc<RwLock<GatewayActor>>>>,
@@ -120,7 +173,22 @@ pub struct Gateway {
     sender: mpsc::Sender<GatewayRequest>,
     receiver: mpsc::Receiver<GatewayRequest>,
     task_group: TaskGroup,
-    channel_id_generator: AtomicU64,
+    scids: Mutex<ScidMap>,
+    route_hint_config: RouteHintConfig,
+    /// Lease of the active instance if the gateway runs with standbys
+    lease: Option<Arc<LeaderLease>>,
+    fee_oracle: DynFeeOracle,
+    htlc_policy: DynHtlcInterceptorPolicy,
+    /// Events streamed to subscribers of the gRPC API
+    events: broadcast::Sender<GatewayEvent>,
+    /// Dropping it stops the subscription to the lightning node's channel
+    /// updates
+    channel_updates: Option<oneshot::Sender<()>>,
+    /// Dropping it stops the subscription to the onion messages addressed to
+    /// the lightning node
+    onion_messages: Option<oneshot::Sender<()>>,
+    /// Whether HTLCs intercepted for one federation may pay offers of another
+    bridge_federations: bool,
 }
 
 impl Gateway {
@@ -128,12 +196,18 @@ impl Gateway {
     pub async fn new(
         lnrpc: Arc<RwLock<dyn ILnRpcClient>>,
         client_builder: DynGatewayClientBuilder,
-        decoders: ModuleDecoderRegistry,
         module_gens: ClientModuleGenRegistry,
         task_group: TaskGroup,
+        route_hint_config: RouteHintConfig,
+        lease: Option<Arc<LeaderLease>>,
+        fee_oracle: DynFeeOracle,
+        htlc_policy: DynHtlcInterceptorPolicy,
+        bridge_federations: bool,
     ) -> Result<Self> {
         // Create message channels for the webserver
         let (sender, receiver) = mpsc::channel::<GatewayRequest>(100);
+        let scids = client_builder.load_scids()?;
+        let (events, _) = broadcast::channel(EVENT_BUFFER_SIZE);
 
         let gw = Self {
             lnrpc,
@@ -142,35 +216,53 @@ impl Gateway {
             receiver,
             client_builder,
             task_group,
-            channel_id_generator: AtomicU64::new(INITIAL_SCID),
-            decoders: decoders.clone(),
-            module_gens: module_gens.clone(),
+            scids: Mutex::new(scids),
+            module_gens,
+            route_hint_config,
+            lease,
+            fee_oracle,
+            htlc_policy,
+            events,
+            channel_updates: None,
+            onion_messages: None,
+            bridge_federations,
         };
 
-        gw.load_actors(decoders, module_gens).await?;
+        gw.load_actors().await?;
 
         Ok(gw)
     }
 
-    async fn load_actors(
-        &self,
-        decoders: ModuleDecoderRegistry,
-        module_gens: ClientModuleGenRegistry,
-    ) -> Result<()> {
+    /// Decoders of the modules of a federation, looked up by the instance ids
+    /// its config assigned them
+    fn decoders(&self, config: &GatewayClientConfig) -> Result<ModuleDecoderRegistry> {
+        config
+            .client_config
+            .module_registry()
+            .decoders(&self.module_gens)
+            .map_err(GatewayError::Other)
+    }
+
+    async fn load_actors(&self) -> Result<()> {
         // Fetch route hints form the LN node
         let mut num_retries = 0;
         let route_hints = loop {
             let route_hints: Vec<RouteHint> = self
-                .lnrpc
-                .read()
-                .await
-                .routehints()
-                .await
-                .expect("Could not fetch route hints")
-                .try_into()
+                .route_hint_config
+                .select(
+                    self.lnrpc
+                        .read()
+                        .await
+                        .routehints()
+                        .await
+                        .expect("Could not fetch route hints"),
+                )
                 .expect("Could not parse route hints");
 
-            if !route_hints.is_empty() || num_retries == ROUTE_HINT_RETRIES {
+            if !route_hints.is_empty()
+                || self.route_hint_config.max_route_hints == 0
+                || num_retries == ROUTE_HINT_RETRIES
+            {
                 break route_hints;
             }
 
@@ -183,25 +275,37 @@ impl Gateway {
             tokio::time::sleep(ROUTE_HINT_RETRY_SLEEP).await;
         };
         if let Ok(configs) = self.client_builder.load_configs() {
-            let mut next_channel_id = self.channel_id_generator.load(Ordering::SeqCst);
+            let mut scids = self.scids.lock().await;
 
             for config in configs {
+                // Configs from before the ids were persisted add theirs here, but no
+                // two federations may intercept the same HTLCs
+                let federation_id = config.client_config.federation_id.clone();
+                if let Err(e) = scids.insert(config.mint_channel_id, federation_id) {
+                    error!("Failed to connect federation: {}", e);
+                    continue;
+                }
+                let decoders = match self.decoders(&config) {
+                    Ok(decoders) => decoders,
+                    Err(e) => {
+                        error!("Failed to connect federation: {}", e);
+                        continue;
+                    }
+                };
+
                 let client = self
                     .client_builder
-                    .build(config.clone(), decoders.clone(), module_gens.clone())
+                    .build(config.clone(), decoders, self.module_gens.clone())
                     .await
                     .expect("Could not build federation client");
 
+                // The actor reconciles outgoing payments left from before the
+                // restart on its own
                 if let Err(e) = self.load_actor(Arc::new(client), route_hints.clone()).await {
                     error!("Failed to connect federation: {}", e);
                 }
-
-                if config.mint_channel_id > next_channel_id {
-                    next_channel_id = config.mint_channel_id + 1;
-                }
             }
-            self.channel_id_generator
-                .store(next_channel_id, Ordering::SeqCst);
+            self.client_builder.save_scids(&scids)?;
         } else {
             warn!("Could not load any previous federation configs");
         }
@@ -220,6 +324,9 @@ impl Gateway {
                 route_hints,
                 self.task_group.clone(),
                 GatewayRpcSender::new(self.sender.clone()),
+                self.fee_oracle.clone(),
+                self.htlc_policy.clone(),
+                self.bridge_federations,
             )
             .await?,
         ));
@@ -256,29 +363,39 @@ impl Gateway {
         let node_pub_key = PublicKey::from_slice(&pub_key)
             .map_err(|e| GatewayError::Other(anyhow!("Invalid node pubkey {}", e)))?;
 
-        // The gateway deterministically assigns a channel id (u64) to each federation
-        // connected. TODO: explicitly handle the case where the channel id
-        // overflows
-        let channel_id = self.channel_id_generator.fetch_add(1, Ordering::SeqCst);
+        // The gateway assigns each federation a channel id (u64) it keeps even if the
+        // federation is connected again
+        let channel_id = {
+            let mut scids = self.scids.lock().await;
+            let channel_id = scids.allocate(connect.id.clone());
+            self.client_builder.save_scids(&scids)?;
+            channel_id
+        };
 
+        let federation_id = connect.id.clone();
         let gw_client_cfg = self
             .client_builder
             .create_config(connect, channel_id, node_pub_key, self.module_gens.clone())
             .await?;
+        gw_client_cfg
+            .client_config
+            .check_legacy_module_instances()
+            .map_err(GatewayError::Other)?;
 
         let client = Arc::new(
             self.client_builder
                 .build(
                     gw_client_cfg.clone(),
-                    self.decoders.clone(),
+                    self.decoders(&gw_client_cfg)?,
                     self.module_gens.clone(),
                 )
                 .await
                 .expect("Failed to build gateway client"),
         );
 
-        if let Err(e) = self.load_actor(client.clone(), route_hints).await {
-            error!("Failed to connect federation: {}", e);
+        match self.load_actor(client.clone(), route_hints).await {
+            Ok(_) => self.emit(GatewayEvent::FederationConnected { federation_id }),
+            Err(e) => error!("Failed to connect federation: {}", e),
         }
 
         if let Err(e) = self.client_builder.save_config(client.config()) {
@@ -295,7 +412,7 @@ impl Gateway {
         let actors = self.actors.lock().await;
         let mut federations: Vec<FederationInfo> = Vec::new();
         for actor in actors.values() {
-            federations.push(actor.read().await.get_info()?);
+            federations.push(actor.read().await.get_info().await?);
         }
 
         let ln_info = self.lnrpc.read().await.info().await?;
@@ -314,13 +431,97 @@ impl Gateway {
             contract_id,
         } = payload;
 
-        let actor_lock = self.select_actor(federation_id).await?;
+        self.pay_invoice(
+            federation_id,
+            contract_id,
+            FirstHopConstraint::default(),
+            CorrelationId::random(),
+            None,
+        )
+        .await
+    }
+
+    async fn handle_manual_pay_invoice_msg(&self, payload: ManualPayInvoicePayload) -> Result<()> {
+        let ManualPayInvoicePayload {
+            federation_id,
+            contract_id,
+            first_hop,
+        } = payload;
+
+        self.pay_invoice(
+            federation_id,
+            contract_id,
+            first_hop,
+            CorrelationId::random(),
+            None,
+        )
+        .await
+    }
+
+    async fn handle_pay_invoice_with_status_msg(
+        &self,
+        payload: PayInvoiceWithStatusPayload,
+    ) -> Result<()> {
+        let PayInvoiceWithStatusPayload { payment, status } = payload;
+
+        self.pay_invoice(
+            payment.federation_id,
+            payment.contract_id,
+            payment.first_hop,
+            CorrelationId::random(),
+            Some(&status),
+        )
+        .await
+    }
+
+    /// Pays an outgoing contract, all log lines about the payment carry the
+    /// correlation id assigned to it here. Every step of the payment is also
+    /// sent to `status` if given.
+    #[instrument(skip_all, fields(%federation_id, %contract_id, %correlation_id))]
+    async fn pay_invoice(
+        &self,
+        federation_id: FederationId,
+        contract_id: ContractId,
+        first_hop: FirstHopConstraint,
+        correlation_id: CorrelationId,
+        status: Option<&mpsc::UnboundedSender<OutgoingPaymentStatus>>,
+    ) -> Result<()> {
+        let actor_lock = self.select_actor(federation_id.clone()).await?;
         let actor = actor_lock.read().await;
-        let outpoint = actor.pay_invoice(contract_id).await?;
-        actor
-            .await_outgoing_contract_claimed(contract_id, outpoint)
-            .await?;
-        Ok(())
+        let events = self.events.clone();
+        let report = |step| {
+            // only fails if the caller stopped following the payment
+            if let Some(status) = status {
+                let _ = status.send(step);
+            }
+            let _ = events.send(GatewayEvent::OutgoingPaymentUpdated {
+                federation_id: federation_id.clone(),
+                contract_id,
+                status: step,
+            });
+        };
+        let result: Result<()> = async {
+            let outpoint = actor
+                .pay_invoice_reporting(contract_id, first_hop, correlation_id, &report)
+                .await?;
+            actor
+                .await_outgoing_contract_claimed(contract_id, outpoint)
+                .await
+        }
+        .await;
+
+        self.emit(match &result {
+            Ok(()) => GatewayEvent::OutgoingPaymentSucceeded {
+                federation_id,
+                contract_id,
+            },
+            Err(e) => GatewayEvent::OutgoingPaymentFailed {
+                federation_id,
+                contract_id,
+                error: e.to_string(),
+            },
+        });
+        result
     }
 
     async fn handle_balance_msg(&self, payload: BalancePayload) -> Result<Amount> {
@@ -348,12 +549,18 @@ impl Gateway {
             federation_id,
         } = payload;
 
-        self.select_actor(federation_id)
+        let txid = self
+            .select_actor(federation_id.clone())
             .await?
             .read()
             .await
             .deposit(txout_proof, transaction)
-            .await
+            .await?;
+        self.emit(GatewayEvent::DepositSubmitted {
+            federation_id,
+            txid,
+        });
+        Ok(txid)
     }
 
     async fn handle_withdraw_msg(&self, payload: WithdrawPayload) -> Result<TransactionId> {
@@ -363,12 +570,68 @@ impl Gateway {
             federation_id,
         } = payload;
 
-        self.select_actor(federation_id)
+        let txid = self
+            .select_actor(federation_id.clone())
             .await?
             .read()
             .await
             .withdraw(amount, address)
+            .await?;
+        self.emit(GatewayEvent::WithdrawalSubmitted {
+            federation_id,
+            txid,
+        });
+        Ok(txid)
+    }
+
+    /// Funds the contract of a sweep and pays it in the background, see
+    /// [`crate::sweep`]
+    async fn handle_sweep_to_ln_msg(
+        &mut self,
+        SweepToLnPayload {
+            federation_id,
+            invoice,
+        }: SweepToLnPayload,
+    ) -> Result<SweepToLnResponse> {
+        let actor = self
+            .select_actor(federation_id.clone())
+            .await?
+            .read()
             .await
+            .clone();
+        let (contract_id, outpoint, gateway) = actor.fund_sweep_to_ln(invoice).await?;
+
+        let events = self.events.clone();
+        let node_pub_key = gateway.node_pub_key;
+        self.task_group
+            .spawn("Sweep ecash to lightning", move |_| async move {
+                let event = match actor
+                    .complete_sweep_to_ln(contract_id, outpoint, &gateway)
+                    .await
+                {
+                    Ok(()) => GatewayEvent::SweepToLnSucceeded {
+                        federation_id,
+                        contract_id,
+                        gateway: gateway.node_pub_key,
+                    },
+                    Err(e) => {
+                        warn!(%contract_id, "Sweep to lightning failed: {e}");
+                        GatewayEvent::SweepToLnFailed {
+                            federation_id,
+                            contract_id,
+                            error: e.to_string(),
+                        }
+                    }
+                };
+                // only fails if nobody is subscribed
+                let _ = events.send(event);
+            })
+            .await;
+
+        Ok(SweepToLnResponse {
+            contract_id,
+            gateway: node_pub_key,
+        })
     }
 
     async fn handle_backup_msg(
@@ -395,6 +658,324 @@ impl Gateway {
             .await
     }
 
+    async fn handle_export_accounting_msg(
+        &self,
+        ExportAccountingPayload { from, to }: ExportAccountingPayload,
+    ) -> Result<AccountingExport> {
+        let exported_at = fedimint_core::time::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time to be after the unix epoch")
+            .as_secs();
+
+        let mut federations = Vec::new();
+        for actor in self.actors.lock().await.values() {
+            federations.push(actor.read().await.accounting(from, to).await?);
+        }
+
+        Ok(AccountingExport {
+            exported_at,
+            federations,
+        })
+    }
+
+    async fn handle_export_state_msg(
+        &self,
+        ExportStatePayload { password }: ExportStatePayload,
+    ) -> Result<StateArchive> {
+        let node_pub_key = self.node_pub_key().await?;
+
+        let mut federations = Vec::new();
+        for actor in self.actors.lock().await.values() {
+            federations.push(actor.read().await.export_state().await);
+        }
+
+        let state = GatewayState {
+            code_version: env!("CODE_VERSION").to_string(),
+            node_pub_key,
+            federations,
+        };
+        Ok(StateArchive::encrypt(&state, &password)?)
+    }
+
+    /// Connects the federations of a state exported from another gateway of
+    /// the same lightning node, with their databases as they were exported and
+    /// migrated to the current version of the client
+    async fn handle_import_state_msg(
+        &self,
+        ImportStatePayload { archive, password }: ImportStatePayload,
+        route_hints: Vec<RouteHint>,
+    ) -> Result<()> {
+        let state = archive.decrypt(&password)?;
+        if state.code_version != env!("CODE_VERSION") {
+            warn!(
+                code_version = %state.code_version,
+                "Importing state exported by a different version of the gateway"
+            );
+        }
+
+        // The pending payments of the state were made by the node it was exported from
+        let node_pub_key = self.node_pub_key().await?;
+        if state.node_pub_key != node_pub_key {
+            return Err(GatewayError::Other(anyhow!(
+                "State was exported from a gateway of node {}, but this gateway runs on {}",
+                state.node_pub_key,
+                node_pub_key
+            )));
+        }
+
+        {
+            let actors = self.actors.lock().await;
+            if let Some(federation) = state.federations.iter().find(|federation| {
+                actors.contains_key(&federation.config.client_config.federation_id.to_string())
+            }) {
+                return Err(GatewayError::Other(anyhow!(
+                    "Federation {} is already connected",
+                    federation.config.client_config.federation_id
+                )));
+            }
+        }
+
+        // Nothing is saved until the databases of all federations were imported,
+        // so a failed import can simply be retried
+        let mut scids = self.scids.lock().await.clone();
+        let mut decoders = Vec::new();
+        for federation in &state.federations {
+            scids.insert(
+                federation.config.mint_channel_id,
+                federation.config.client_config.federation_id.clone(),
+            )?;
+            decoders.push(self.decoders(&federation.config)?);
+        }
+
+        let mut clients = Vec::new();
+        for (federation, decoders) in state.federations.into_iter().zip(decoders) {
+            let imported = match federation.db_entries() {
+                Ok(entries) => {
+                    self.client_builder
+                        .import(
+                            federation.config.clone(),
+                            entries,
+                            decoders,
+                            self.module_gens.clone(),
+                        )
+                        .await
+                }
+                Err(e) => Err(e.into()),
+            };
+            match imported {
+                Ok(client) => clients.push((federation.config, client)),
+                Err(e) => {
+                    for (_, client) in clients {
+                        migration::clear_database(client.db()).await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        self.client_builder.save_scids(&scids)?;
+        *self.scids.lock().await = scids;
+
+        for (config, client) in clients {
+            let federation_id = config.client_config.federation_id.clone();
+            self.client_builder.save_config(config)?;
+
+            let actor = self
+                .load_actor(Arc::new(client), route_hints.clone())
+                .await?;
+            if let Err(e) = actor.read().await.reconcile_imported_state().await {
+                warn!(
+                    "Failed to reconcile imported state of {}: {}",
+                    federation_id, e
+                );
+            }
+            info!(%federation_id, "Imported federation");
+            self.emit(GatewayEvent::FederationConnected { federation_id });
+        }
+
+        Ok(())
+    }
+
+    async fn node_pub_key(&self) -> Result<PublicKey> {
+        let GetNodeInfoResponse { pub_key, alias: _ } = self.lnrpc.read().await.info().await?;
+        PublicKey::from_slice(&pub_key)
+            .map_err(|e| GatewayError::Other(anyhow!("Invalid node pubkey {}", e)))
+    }
+
+    /// Returns the actor of the federation the lightning address `name` is
+    /// registered with
+    async fn select_lightning_address_actor(
+        &self,
+        name: &str,
+    ) -> Result<Arc<RwLock<GatewayActor>>> {
+        for actor in self.actors.lock().await.values() {
+            if actor.read().await.lightning_address(name).await.is_some() {
+                return Ok(actor.clone());
+            }
+        }
+        Err(GatewayError::other(format!(
+            "Unknown lightning address {name}"
+        )))
+    }
+
+    async fn handle_register_lightning_address_msg(
+        &self,
+        payload: RegisterLightningAddressPayload,
+    ) -> Result<()> {
+        let RegisterLightningAddressPayload {
+            federation_id,
+            name,
+            pubkey,
+        } = payload;
+
+        lnurl::validate_name(&name)?;
+        // names are unique across federations, since the address has no room
+        // for the federation
+        if self.select_lightning_address_actor(&name).await.is_ok() {
+            return Err(GatewayError::other(format!(
+                "Lightning address {name} is already registered"
+            )));
+        }
+
+        self.select_actor(federation_id)
+            .await?
+            .read()
+            .await
+            .register_lightning_address(name, pubkey)
+            .await
+    }
+
+    async fn handle_lnurl_pay_msg(&self, payload: LnurlPayPayload) -> Result<LnurlPayResponse> {
+        let api = self
+            .select_lightning_address_actor(&payload.name)
+            .await?
+            .read()
+            .await
+            .api();
+        LnurlPayResponse::new(&payload.name, &api)
+    }
+
+    /// Creates an invoice paying the lightning address of `actor`, which waits
+    /// for the federation to accept the offer, so it doesn't borrow the gateway
+    /// and is run in the background
+    async fn handle_lnurl_invoice_msg(
+        actor: Result<Arc<RwLock<GatewayActor>>>,
+        payload: LnurlInvoicePayload,
+    ) -> Result<LnurlInvoiceResponse> {
+        let LnurlInvoicePayload { name, amount } = payload;
+
+        lnurl::validate_amount(amount)?;
+        let actor = actor?.read().await.clone();
+        let description_hash = lnurl::metadata_hash(&name, &actor.api());
+        let invoice = actor
+            .create_lnurl_invoice(&name, amount, description_hash)
+            .await?;
+
+        Ok(LnurlInvoiceResponse {
+            pr: invoice.to_string(),
+            routes: vec![],
+        })
+    }
+
+    async fn handle_lnurl_payments_msg(
+        &self,
+        payload: LnurlPaymentsPayload,
+    ) -> Result<Vec<LnurlPaymentInfo>> {
+        let payments = self
+            .select_lightning_address_actor(&payload.name)
+            .await?
+            .read()
+            .await
+            .lnurl_payments(&payload.name)
+            .await;
+
+        Ok(payments
+            .into_iter()
+            .map(|(contract_id, payment)| LnurlPaymentInfo {
+                contract_id,
+                amount: payment.amount,
+                tweak: payment.tweak.to_hex(),
+            })
+            .collect())
+    }
+
+    async fn handle_set_reserve_msg(
+        &self,
+        SetReservePayload {
+            federation_id,
+            reserve,
+        }: SetReservePayload,
+    ) -> Result<()> {
+        self.select_actor(federation_id)
+            .await?
+            .read()
+            .await
+            .set_ecash_reserve(reserve)
+            .await;
+        Ok(())
+    }
+
+    async fn handle_set_htlc_amount_band_msg(
+        &self,
+        SetHtlcAmountBandPayload {
+            federation_id,
+            band,
+        }: SetHtlcAmountBandPayload,
+    ) -> Result<()> {
+        if let (Some(min), Some(max)) = (band.min, band.max) {
+            if max < min {
+                return Err(GatewayError::Other(anyhow!(
+                    "Maximum HTLC amount {max} is below the minimum {min}"
+                )));
+            }
+        }
+
+        self.select_actor(federation_id)
+            .await?
+            .read()
+            .await
+            .set_htlc_amount_band(band)
+            .await;
+        Ok(())
+    }
+
+    async fn handle_pause_fed_msg(
+        &self,
+        PauseFedPayload { federation_id }: PauseFedPayload,
+    ) -> Result<()> {
+        self.select_actor(federation_id)
+            .await?
+            .read()
+            .await
+            .pause()
+            .await;
+        Ok(())
+    }
+
+    async fn handle_resume_fed_msg(
+        &self,
+        ResumeFedPayload { federation_id }: ResumeFedPayload,
+    ) -> Result<()> {
+        self.select_actor(federation_id)
+            .await?
+            .read()
+            .await
+            .resume()
+            .await;
+        Ok(())
+    }
+
+    /// Routes test payments through the federation of `actor`, which waits for
+    /// the federation and the lightning network, so it doesn't borrow the
+    /// gateway and is run in the background
+    async fn handle_test_payment_msg(
+        actor: Result<Arc<RwLock<GatewayActor>>>,
+        TestPaymentPayload { amount, .. }: TestPaymentPayload,
+    ) -> Result<TestPaymentReport> {
+        let actor = actor?.read().await.clone();
+        Ok(actor.test_payment(amount, CorrelationId::random()).await)
+    }
+
     async fn handle_lightning_reconnect(
         &mut self,
         payload: LightningReconnectPayload,
@@ -413,14 +994,14 @@ impl Gateway {
         self.lnrpc.write().await.disconnect().await?;
 
         self.lnrpc = match node_type {
-            Some(Mode::Cln { cln_extension_addr }) => Arc::new(RwLock::new(
+            Some(Mode::Cln { cln_extension_addr }) => self.fence(Arc::new(RwLock::new(
                 NetworkLnRpcClient::new(cln_extension_addr).await?,
-            )),
+            ))),
             Some(Mode::Lnd {
                 lnd_rpc_addr,
                 lnd_tls_cert,
                 lnd_macaroon,
-            }) => Arc::new(RwLock::new(
+            }) => self.fence(Arc::new(RwLock::new(
                 GatewayLndClient::new(
                     lnd_rpc_addr,
                     lnd_tls_cert,
@@ -428,7 +1009,7 @@ impl Gateway {
                     self.task_group.make_subgroup().await,
                 )
                 .await?,
-            )),
+            ))),
             None => {
                 let new_client = self.lnrpc.clone();
                 // Reconnect the existing client without re-creating it
@@ -442,13 +1023,275 @@ impl Gateway {
         for actor in actors.values() {
             actor.write().await.subscribe_htlcs().await?;
         }
+        drop(actors);
+
+        self.subscribe_channel_updates().await;
+        self.subscribe_onion_messages().await;
 
         Ok(())
     }
 
-    pub async fn run(mut self, listen: SocketAddr, password: String) -> Result<()> {
+    /// Advertises fresh route hints and liquidity to every federation after
+    /// the capacity of the lightning node's channels changed
+    async fn handle_channels_updated_msg(&mut self, payload: ChannelsUpdatedPayload) -> Result<()> {
+        info!(
+            short_channel_id = ?payload.short_channel_id,
+            "Channels of the lightning node changed, refreshing route hints and liquidity"
+        );
+
+        let route_hints: Vec<RouteHint> = self
+            .route_hint_config
+            .select(self.lnrpc.read().await.routehints().await?)?;
+        for (federation_id, actor) in self.actors.lock().await.iter() {
+            if !actor
+                .read()
+                .await
+                .update_channels(route_hints.clone())
+                .await
+            {
+                debug!(%federation_id, "Route hints didn't change");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Actors of the federations other than `federation_id` an intercepted
+    /// HTLC can be bridged to, the paused ones don't route payments
+    async fn bridge_candidates(&self, federation_id: &FederationId) -> Vec<GatewayActor> {
+        let actors = self
+            .actors
+            .lock()
+            .await
+            .iter()
+            .filter(|(id, _)| **id != federation_id.to_string())
+            .map(|(_, actor)| actor.clone())
+            .collect::<Vec<_>>();
+
+        let mut candidates = vec![];
+        for actor in actors {
+            let actor = actor.read().await.clone();
+            if !actor.is_paused() {
+                candidates.push(actor);
+            }
+        }
+        candidates
+    }
+
+    /// Routes an HTLC intercepted for one federation through another one
+    /// whose user offers to sell its preimage, returning the latter, see
+    /// [`crate::bridge`]
+    ///
+    /// Runs in the background, so the gateway keeps handling requests while
+    /// the federations are asked for offers and the preimage is decrypted.
+    async fn handle_bridge_htlc_msg(
+        candidates: Vec<GatewayActor>,
+        BridgeHtlcPayload {
+            federation_id,
+            payment_hash,
+            htlc,
+            correlation_id,
+        }: BridgeHtlcPayload,
+    ) -> Result<FederationId> {
+        let actor = select_bridge_federation(candidates, |actor| {
+            let actor = actor.clone();
+            async move { actor.offer_exists(payment_hash).await }
+        })
+        .await
+        .ok_or_else(|| {
+            GatewayError::Other(anyhow!(
+                "No federation offers the preimage of {payment_hash}"
+            ))
+        })?;
+
+        let bridged_to = actor.federation_id();
+        info!(
+            %correlation_id,
+            from = %federation_id,
+            to = %bridged_to,
+            "Bridging intercepted HTLC between federations"
+        );
+        actor
+            .process_bridged_htlc(htlc, payment_hash, federation_id, correlation_id)
+            .await;
+        Ok(bridged_to)
+    }
+
+    /// Subscribes to changes to the capacity of the lightning node's
+    /// channels, e.g. splices, replacing a previous subscription
+    ///
+    /// Without a subscription the liquidity is still refreshed whenever the
+    /// gateway registers with its federations, but route hints are not.
+    async fn subscribe_channel_updates(&mut self) {
+        let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+        self.channel_updates = Some(stop_tx);
+
+        let lnrpc = self.lnrpc.clone();
+        let sender = GatewayRpcSender::new(self.sender.clone());
+        self.task_group
+            .spawn("Subscribe to channel updates", |handle| async move {
+                let mut updates = match lnrpc.read().await.subscribe_channel_updates().await {
+                    Ok(updates) => updates,
+                    Err(e) => {
+                        info!("Lightning node doesn't report channel updates: {}", e);
+                        return;
+                    }
+                };
+
+                let mut shutdown_rx = handle.make_shutdown_rx().await;
+                loop {
+                    let next_update = next_channel_update(&mut updates, CHANNEL_UPDATE_DEBOUNCE);
+                    let update = tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        _ = &mut stop_rx => break,
+                        update = next_update => update,
+                    };
+                    match update {
+                        Some(Ok(update)) => {
+                            let payload = ChannelsUpdatedPayload {
+                                short_channel_id: update.short_channel_id,
+                            };
+                            if let Err(e) = sender.send(payload).await {
+                                warn!("Failed to refresh channel capacity: {}", e);
+                            }
+                        }
+                        Some(Err(e)) => {
+                            warn!("Error sent over channel update subscription: {}", e);
+                            break;
+                        }
+                        None => {
+                            warn!("Channel update stream closed by service");
+                            break;
+                        }
+                    }
+                }
+            })
+            .await;
+    }
+
+    /// Sends an onion message from the lightning node to a peer of it
+    async fn handle_send_onion_message_msg(&self, payload: SendOnionMessagePayload) -> Result<()> {
+        let fields = payload
+            .fields
+            .into_iter()
+            .map(|(tlv_type, value)| {
+                Ok(OnionMessageField {
+                    tlv_type,
+                    value: Vec::from_hex(&value)?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        self.lnrpc
+            .read()
+            .await
+            .send_onion_message(SendOnionMessageRequest {
+                node_id: payload.node_id.serialize().to_vec(),
+                fields,
+            })
+            .await
+    }
+
+    /// Streams the onion messages addressed to the lightning node to the
+    /// subscribers of the gRPC API as events, replacing a previous
+    /// subscription
+    ///
+    /// Lays the groundwork for BOLT 12 offers and for messaging between
+    /// federations over lightning, the gateway doesn't act on the messages
+    /// itself yet.
+    async fn subscribe_onion_messages(&mut self) {
+        let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+        self.onion_messages = Some(stop_tx);
+
+        let lnrpc = self.lnrpc.clone();
+        let events = self.events.clone();
+        self.task_group
+            .spawn("Subscribe to onion messages", |handle| async move {
+                let mut messages = match lnrpc.read().await.subscribe_onion_messages().await {
+                    Ok(messages) => messages,
+                    Err(e) => {
+                        info!("Lightning node doesn't forward onion messages: {}", e);
+                        return;
+                    }
+                };
+
+                let mut shutdown_rx = handle.make_shutdown_rx().await;
+                loop {
+                    let message = tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        _ = &mut stop_rx => break,
+                        message = messages.next() => message,
+                    };
+                    match message {
+                        Some(Ok(message)) => {
+                            // only fails if nobody is subscribed
+                            let _ = events.send(GatewayEvent::OnionMessageReceived {
+                                fields: message
+                                    .fields
+                                    .into_iter()
+                                    .map(|field| (field.tlv_type, field.value))
+                                    .collect(),
+                                reply_path: message.reply_path,
+                            });
+                        }
+                        Some(Err(e)) => {
+                            warn!("Error sent over onion message subscription: {}", e);
+                            break;
+                        }
+                        None => {
+                            warn!("Onion message stream closed by service");
+                            break;
+                        }
+                    }
+                }
+            })
+            .await;
+    }
+
+    /// Sends `event` to the subscribers of the gRPC API
+    fn emit(&self, event: GatewayEvent) {
+        // only fails if nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    /// Guards `lnrpc` with the leader lease, if the gateway runs with standbys
+    fn fence(&self, lnrpc: Arc<RwLock<dyn ILnRpcClient>>) -> Arc<RwLock<dyn ILnRpcClient>> {
+        match &self.lease {
+            Some(lease) => Arc::new(RwLock::new(FencedLnRpcClient::new(lnrpc, lease.clone()))),
+            None => lnrpc,
+        }
+    }
+
+    /// Serves the webserver on `listen` and, if `grpc_listen` is set, the gRPC
+    /// API on it, both protected by `password`
+    pub async fn run(
+        mut self,
+        listen: SocketAddr,
+        grpc_listen: Option<SocketAddr>,
+        password: String,
+    ) -> Result<()> {
         let mut tg = self.task_group.clone();
 
+        if let Some(grpc_listen) = grpc_listen {
+            let sender = GatewayRpcSender::new(self.sender.clone());
+            let events = self.events.clone();
+            let password = password.clone();
+            tg.spawn("Gateway gRPC server", move |server_ctrl| async move {
+                let mut server = tokio::spawn(async move {
+                    if let Err(e) = run_grpc_server(password, grpc_listen, sender, events).await {
+                        error!("Gateway gRPC server failed: {e:?}");
+                    }
+                });
+
+                // Shut down gRPC server if requested
+                if server_ctrl.is_shutting_down() {
+                    server.abort();
+                    let _ = futures::executor::block_on(&mut server);
+                }
+            })
+            .await;
+        }
+
         let sender = GatewayRpcSender::new(self.sender.clone());
         tg.spawn("Gateway Webserver", move |server_ctrl| async move {
             let mut webserver = tokio::spawn(run_webserver(password, listen, sender));
@@ -461,6 +1304,11 @@ impl Gateway {
         })
         .await;
 
+        // Route hints and liquidity go stale when channels are opened, closed or
+        // spliced, while the HTLC subscriptions stay valid
+        self.subscribe_channel_updates().await;
+        self.subscribe_onion_messages().await;
+
         // TODO: try to drive forward outgoing and incoming payments that were
         // interrupted
         let loop_ctrl = tg.make_handle();
@@ -484,8 +1332,9 @@ impl Gateway {
                             .await;
                     }
                     GatewayRequest::ConnectFederation(inner) => {
-                        let route_hints: Vec<RouteHint> =
-                            self.lnrpc.read().await.routehints().await?.try_into()?;
+                        let route_hints: Vec<RouteHint> = self
+                            .route_hint_config
+                            .select(self.lnrpc.read().await.routehints().await?)?;
                         inner
                             .handle(&mut self, |gateway, payload| {
                                 gateway.handle_connect_federation(payload, route_hints.clone())
@@ -499,6 +1348,20 @@ impl Gateway {
                             })
                             .await;
                     }
+                    GatewayRequest::ManualPayInvoice(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_manual_pay_invoice_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::PayInvoiceWithStatus(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_pay_invoice_with_status_msg(payload)
+                            })
+                            .await;
+                    }
                     GatewayRequest::Balance(inner) => {
                         inner
                             .handle(&mut self, |gateway, payload| {
@@ -527,6 +1390,13 @@ impl Gateway {
                             })
                             .await;
                     }
+                    GatewayRequest::SweepToLn(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_sweep_to_ln_msg(payload)
+                            })
+                            .await;
+                    }
                     GatewayRequest::Backup(inner) => {
                         inner
                             .handle(&mut self, |gateway, payload| {
@@ -541,6 +1411,30 @@ impl Gateway {
                             })
                             .await;
                     }
+                    GatewayRequest::ExportState(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_export_state_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::ExportAccounting(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_export_accounting_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::ImportState(inner) => {
+                        let route_hints: Vec<RouteHint> = self
+                            .route_hint_config
+                            .select(self.lnrpc.read().await.routehints().await?)?;
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_import_state_msg(payload, route_hints.clone())
+                            })
+                            .await;
+                    }
                     GatewayRequest::LightningReconnect(inner) => {
                         inner
                             .handle(&mut self, |gateway, payload| {
@@ -548,6 +1442,104 @@ impl Gateway {
                             })
                             .await;
                     }
+                    GatewayRequest::ChannelsUpdated(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_channels_updated_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::BridgeHtlc(inner) => {
+                        let candidates =
+                            self.bridge_candidates(&inner.request().federation_id).await;
+                        inner
+                            .handle_in_background(
+                                &mut self.task_group,
+                                "Bridge intercepted HTLC",
+                                move |payload| Self::handle_bridge_htlc_msg(candidates, payload),
+                            )
+                            .await;
+                    }
+                    GatewayRequest::SendOnionMessage(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_send_onion_message_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::RegisterLightningAddress(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_register_lightning_address_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::LnurlPay(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_lnurl_pay_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::LnurlInvoice(inner) => {
+                        let actor = self
+                            .select_lightning_address_actor(&inner.request().name)
+                            .await;
+                        inner
+                            .handle_in_background(
+                                &mut self.task_group,
+                                "Create LNURL invoice",
+                                move |payload| Self::handle_lnurl_invoice_msg(actor, payload),
+                            )
+                            .await;
+                    }
+                    GatewayRequest::LnurlPayments(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_lnurl_payments_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::TestPayment(inner) => {
+                        let actor = self
+                            .select_actor(inner.request().federation_id.clone())
+                            .await;
+                        inner
+                            .handle_in_background(
+                                &mut self.task_group,
+                                "Test payment",
+                                move |payload| Self::handle_test_payment_msg(actor, payload),
+                            )
+                            .await;
+                    }
+                    GatewayRequest::SetReserve(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_set_reserve_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::SetHtlcAmountBand(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_set_htlc_amount_band_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::PauseFed(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_pause_fed_msg(payload)
+                            })
+                            .await;
+                    }
+                    GatewayRequest::ResumeFed(inner) => {
+                        inner
+                            .handle(&mut self, |gateway, payload| {
+                                gateway.handle_resume_fed_msg(payload)
+                            })
+                            .await;
+                    }
                 }
             }
 
@@ -1,29 +1,40 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
+use bitcoin_hashes::hex::FromHex;
 use bitcoin_hashes::{sha256, Hash};
 use fedimint_core::task::{sleep, TaskGroup};
+use futures::StreamExt;
 use secp256k1::PublicKey;
 use tokio::sync::{mpsc, Mutex};
 use tokio_stream::wrappers::ReceiverStream;
+use tonic_lnd::lnrpc::channel_event_update::Channel as ChannelEvent;
 use tonic_lnd::lnrpc::failure::FailureCode;
-use tonic_lnd::lnrpc::{GetInfoRequest, SendRequest};
+use tonic_lnd::lnrpc::payment::PaymentStatus as LndPaymentStatus;
+use tonic_lnd::lnrpc::{
+    Channel, ChannelBalanceRequest, ChannelEventSubscription, GetInfoRequest, ListChannelsRequest,
+    ListPaymentsRequest as LndListPaymentsRequest, SendRequest,
+};
 use tonic_lnd::routerrpc::{CircuitKey, ForwardHtlcInterceptResponse, ResolveHoldForwardAction};
 use tonic_lnd::{connect, LndClient};
 use tracing::{error, info, trace};
 
-use crate::gatewaylnrpc::complete_htlcs_request::{Action, Cancel, Settle};
+use crate::gatewaylnrpc::complete_htlcs_request::cancel::Failure;
+use crate::gatewaylnrpc::complete_htlcs_request::{Action, Settle};
 use crate::gatewaylnrpc::get_route_hints_response::RouteHint;
+use crate::gatewaylnrpc::list_payments_response::{Payment, PaymentStatus};
 use crate::gatewaylnrpc::{
-    CompleteHtlcsRequest, CompleteHtlcsResponse, GetNodeInfoResponse, GetRouteHintsResponse,
-    PayInvoiceRequest, PayInvoiceResponse, SubscribeInterceptHtlcsRequest,
+    ChannelUpdate, CompleteHtlcsRequest, CompleteHtlcsResponse, GetLiquidityResponse,
+    GetNodeInfoResponse, GetRouteHintsResponse, ListPaymentsRequest, ListPaymentsResponse,
+    PayInvoiceRequest, PayInvoiceResponse, SendOnionMessageRequest, SubscribeInterceptHtlcsRequest,
     SubscribeInterceptHtlcsResponse,
 };
-use crate::lnrpc_client::{HtlcStream, ILnRpcClient};
+use crate::lnrpc_client::{ChannelUpdateStream, HtlcStream, ILnRpcClient, OnionMessageStream};
 use crate::GatewayError;
 
 // Outcome map is needed to keep state between when an interecpted HTLC is sent
@@ -41,11 +52,17 @@ pub struct GatewayLndClient {
     outcomes: OutcomeMap,
     /// Used to spawn a task handling HTLC subscriptions
     task_group: TaskGroup,
+    /// Highest fencing token a gateway sent, requests of gateways with lower
+    /// tokens are rejected
+    fencing_token: AtomicU64,
     address: String,
     tls_cert: String,
     macaroon: String,
 }
 
+/// Payments fetched from LND per request when listing payments
+const LIST_PAYMENTS_PAGE_SIZE: u64 = 100;
+
 // Reference to a sender that forwards ForwardHtlcInterceptResponse messages to
 // LND
 type LndSenderRef = Arc<mpsc::Sender<ForwardHtlcInterceptResponse>>;
@@ -61,6 +78,7 @@ impl GatewayLndClient {
             client: None,
             outcomes: Arc::new(Mutex::new(HashMap::new())),
             task_group,
+            fencing_token: AtomicU64::new(0),
             address,
             tls_cert,
             macaroon,
@@ -70,6 +88,27 @@ impl GatewayLndClient {
     }
 }
 
+impl GatewayLndClient {
+    /// Rejects requests fenced with a lower token than one seen before
+    fn check_fencing_token(&self, fencing_token: Option<u64>) -> crate::Result<()> {
+        let Some(fencing_token) = fencing_token else {
+            return Ok(());
+        };
+
+        let highest = self
+            .fencing_token
+            .fetch_max(fencing_token, Ordering::SeqCst);
+        if highest > fencing_token {
+            return Err(GatewayError::LnRpcError(tonic::Status::failed_precondition(
+                format!(
+                    "Fencing token {fencing_token} is lower than {highest}, the gateway was replaced"
+                ),
+            )));
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for GatewayLndClient {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "LndClient")
@@ -114,16 +153,39 @@ impl ILnRpcClient for GatewayLndClient {
     async fn routehints(&self) -> crate::Result<GetRouteHintsResponse> {
         // TODO: Issue #1953: Implement full route hint fetching for LND gateways
         Ok(GetRouteHintsResponse {
-            route_hints: vec![RouteHint { hops: vec![] }],
+            route_hints: vec![RouteHint {
+                hops: vec![],
+                ..Default::default()
+            }],
         })
     }
 
     async fn pay(&self, invoice: PayInvoiceRequest) -> crate::Result<PayInvoiceResponse> {
         if let Some(mut client) = self.client.clone() {
+            let first_hop_channels = match invoice.first_hop_pubkey {
+                Some(first_hop) => Some(
+                    client
+                        .lightning()
+                        .list_channels(ListChannelsRequest {
+                            active_only: true,
+                            peer: first_hop,
+                            ..Default::default()
+                        })
+                        .await
+                        .map_err(|e| anyhow::anyhow!(format!("LND error: {e:?}")))?
+                        .into_inner()
+                        .channels,
+                ),
+                None => None,
+            };
+            let outgoing_chan_id =
+                select_outgoing_channel(invoice.outgoing_channel_id, first_hop_channels)?;
+
             let send_response = client
                 .lightning()
                 .send_payment_sync(SendRequest {
                     payment_request: invoice.invoice.to_string(),
+                    outgoing_chan_id,
                     ..Default::default()
                 })
                 .await
@@ -140,6 +202,9 @@ impl ILnRpcClient for GatewayLndClient {
 
             return Ok(PayInvoiceResponse {
                 preimage: send_response.payment_preimage,
+                fee_msat: send_response
+                    .payment_route
+                    .map_or(0, |route| route.total_fees_msat as u64),
             });
         }
 
@@ -158,6 +223,8 @@ impl ILnRpcClient for GatewayLndClient {
             ));
         }
 
+        self.check_fencing_token(subscription.fencing_token)?;
+
         const CHANNEL_SIZE: usize = 100;
 
         // Channel to send responses to LND after processing intercepted HTLC
@@ -291,10 +358,14 @@ impl ILnRpcClient for GatewayLndClient {
             ));
         }
 
+        // We log in the span of the HTLC, which carries the correlation id already
         let CompleteHtlcsRequest {
             action,
             intercepted_htlc_id,
+            fencing_token,
+            correlation_id: _,
         } = request;
+        self.check_fencing_token(fencing_token)?;
 
         let hash = match sha256::Hash::from_slice(&intercepted_htlc_id) {
             Ok(hash) => hash,
@@ -317,7 +388,19 @@ impl ILnRpcClient for GatewayLndClient {
                     failure_message: vec![],
                     failure_code: FailureCode::TemporaryChannelFailure.into(),
                 },
-                Some(Action::Cancel(Cancel { reason: _ })) => cancel_intercepted_htlc(None),
+                Some(Action::Cancel(cancel)) => match cancel.failure() {
+                    // lnd only takes a failure message if the failure code is left unset
+                    Failure::AmountBelowMinimum => ForwardHtlcInterceptResponse {
+                        incoming_circuit_key,
+                        action: ResolveHoldForwardAction::Fail.into(),
+                        preimage: vec![],
+                        failure_message: cancel.failure_message(),
+                        failure_code: FailureCode::Reserved.into(),
+                    },
+                    Failure::TemporaryChannelFailure | Failure::AmountAboveMaximum => {
+                        cancel_intercepted_htlc(incoming_circuit_key)
+                    }
+                },
                 None => {
                     error!("No action specified for intercepted htlc id: {:?}", hash);
                     return Err(GatewayError::LnRpcError(tonic::Status::internal(
@@ -340,6 +423,155 @@ impl ILnRpcClient for GatewayLndClient {
         }
     }
 
+    async fn list_payments(
+        &self,
+        request: ListPaymentsRequest,
+    ) -> crate::Result<ListPaymentsResponse> {
+        let Some(mut client) = self.client.clone() else {
+            return Err(GatewayError::other(
+                "Error: not connected to LND".to_string(),
+            ));
+        };
+
+        // Pages backwards from the newest payment until one was created before
+        // `created_after`, so older payments are never fetched
+        let mut lnd_payments = vec![];
+        let mut index_offset = 0;
+        loop {
+            let page = client
+                .lightning()
+                .list_payments(LndListPaymentsRequest {
+                    include_incomplete: true,
+                    index_offset,
+                    max_payments: LIST_PAYMENTS_PAGE_SIZE,
+                    reversed: true,
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| anyhow::anyhow!(format!("LND error: {e:?}")))?
+                .into_inner();
+
+            let page_len = page.payments.len() as u64;
+            let mut reached_older = false;
+            for payment in page.payments {
+                let created_at = (payment.creation_time_ns / 1_000_000_000) as u64;
+                if created_at < request.created_after {
+                    reached_older = true;
+                } else {
+                    lnd_payments.push((payment, created_at));
+                }
+            }
+
+            if reached_older || page_len < LIST_PAYMENTS_PAGE_SIZE || page.first_index_offset <= 1 {
+                break;
+            }
+            index_offset = page.first_index_offset;
+        }
+
+        let mut payments = vec![];
+        for (payment, created_at) in lnd_payments {
+            let status = match payment.status() {
+                LndPaymentStatus::Succeeded => PaymentStatus::Succeeded,
+                LndPaymentStatus::Failed => PaymentStatus::Failed,
+                LndPaymentStatus::Unknown | LndPaymentStatus::InFlight => PaymentStatus::Pending,
+            };
+            let payment_hash = Vec::<u8>::from_hex(&payment.payment_hash)
+                .map_err(|e| anyhow::anyhow!(format!("LND returned invalid payment hash: {e}")))?;
+            // LND returns a preimage of zeros for payments that didn't succeed
+            let (preimage, fee_msat) = match status {
+                PaymentStatus::Succeeded => (
+                    Vec::<u8>::from_hex(&payment.payment_preimage).map_err(|e| {
+                        anyhow::anyhow!(format!("LND returned invalid preimage: {e}"))
+                    })?,
+                    payment.fee_msat as u64,
+                ),
+                _ => (vec![], 0),
+            };
+
+            payments.push(Payment {
+                payment_hash,
+                status: status.into(),
+                preimage,
+                created_at,
+                fee_msat,
+            });
+        }
+
+        Ok(ListPaymentsResponse { payments })
+    }
+
+    async fn liquidity(&self) -> crate::Result<GetLiquidityResponse> {
+        let Some(mut client) = self.client.clone() else {
+            return Err(GatewayError::other(
+                "Error: not connected to LND".to_string(),
+            ));
+        };
+
+        let balance = client
+            .lightning()
+            .channel_balance(ChannelBalanceRequest {})
+            .await
+            .map_err(|e| anyhow::anyhow!(format!("LND error: {e:?}")))?
+            .into_inner();
+
+        Ok(GetLiquidityResponse {
+            outbound_msat: balance.local_balance.map_or(0, |amount| amount.msat),
+            inbound_msat: balance.remote_balance.map_or(0, |amount| amount.msat),
+        })
+    }
+
+    async fn subscribe_channel_updates<'a>(&self) -> crate::Result<ChannelUpdateStream<'a>> {
+        let Some(mut client) = self.client.clone() else {
+            return Err(GatewayError::other(
+                "Error: not connected to LND".to_string(),
+            ));
+        };
+
+        let events = client
+            .lightning()
+            .subscribe_channel_events(ChannelEventSubscription {})
+            .await
+            .map_err(|e| anyhow::anyhow!(format!("LND error: {e:?}")))?
+            .into_inner();
+
+        // LND reports a splice as the close of the old channel followed by the
+        // open of the new one, pending opens and resolved closes can't change
+        // the route hints
+        Ok(Box::pin(events.filter_map(|event| {
+            let update = match event {
+                Ok(event) => match event.channel {
+                    Some(ChannelEvent::OpenChannel(channel)) => Some(Ok(ChannelUpdate {
+                        short_channel_id: Some(channel.chan_id),
+                    })),
+                    Some(ChannelEvent::ClosedChannel(summary)) => Some(Ok(ChannelUpdate {
+                        short_channel_id: Some(summary.chan_id),
+                    })),
+                    Some(ChannelEvent::ActiveChannel(_) | ChannelEvent::InactiveChannel(_)) => {
+                        Some(Ok(ChannelUpdate {
+                            short_channel_id: None,
+                        }))
+                    }
+                    _ => None,
+                },
+                Err(e) => Some(Err(e)),
+            };
+            futures::future::ready(update)
+        })))
+    }
+
+    // LND neither sends nor forwards onion messages to its RPC clients yet
+    async fn send_onion_message(&self, _message: SendOnionMessageRequest) -> crate::Result<()> {
+        Err(GatewayError::other(
+            "LND doesn't support onion messages".to_string(),
+        ))
+    }
+
+    async fn subscribe_onion_messages<'a>(&self) -> crate::Result<OnionMessageStream<'a>> {
+        Err(GatewayError::other(
+            "LND doesn't support onion messages".to_string(),
+        ))
+    }
+
     async fn connect(&mut self) -> crate::Result<()> {
         let client = loop {
             match connect(
@@ -377,3 +609,72 @@ fn cancel_intercepted_htlc(key: Option<CircuitKey>) -> ForwardHtlcInterceptRespo
         failure_code: FailureCode::TemporaryChannelFailure.into(),
     }
 }
+
+/// Resolves the first hop constraint of a payment to the channel LND has to
+/// send it over, 0 lets LND pick the channel
+///
+/// LND can only pin the outgoing channel, so a first hop given by
+/// `first_hop_channels`, the active channels with it, resolves to the one that
+/// can send the most. A pinned channel has to lead to the first hop if both
+/// are given.
+fn select_outgoing_channel(
+    outgoing_channel_id: Option<u64>,
+    first_hop_channels: Option<Vec<Channel>>,
+) -> crate::Result<u64> {
+    match (outgoing_channel_id, first_hop_channels) {
+        (None, None) => Ok(0),
+        (Some(outgoing_channel_id), None) => Ok(outgoing_channel_id),
+        (Some(outgoing_channel_id), Some(channels)) => {
+            if channels
+                .iter()
+                .any(|channel| channel.chan_id == outgoing_channel_id)
+            {
+                Ok(outgoing_channel_id)
+            } else {
+                Err(GatewayError::other(
+                    "The outgoing channel isn't an active channel with the first hop".to_string(),
+                ))
+            }
+        }
+        (None, Some(channels)) => channels
+            .into_iter()
+            .max_by_key(|channel| channel.local_balance)
+            .map(|channel| channel.chan_id)
+            .ok_or_else(|| GatewayError::other("No active channel with the first hop".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic_lnd::lnrpc::Channel;
+
+    use super::select_outgoing_channel;
+
+    fn channel(chan_id: u64, local_balance: i64) -> Channel {
+        Channel {
+            chan_id,
+            local_balance,
+            active: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn first_hop_resolves_to_channel_with_most_local_balance() {
+        let channels = vec![channel(1, 100), channel(2, 300), channel(3, 200)];
+        assert_eq!(select_outgoing_channel(None, Some(channels)).unwrap(), 2);
+        assert!(select_outgoing_channel(None, Some(vec![])).is_err());
+    }
+
+    #[test]
+    fn pinned_channel_has_to_lead_to_first_hop() {
+        let channels = vec![channel(1, 100), channel(2, 300)];
+        assert_eq!(
+            select_outgoing_channel(Some(1), Some(channels.clone())).unwrap(),
+            1
+        );
+        assert!(select_outgoing_channel(Some(4), Some(channels)).is_err());
+        assert_eq!(select_outgoing_channel(Some(4), None).unwrap(), 4);
+        assert_eq!(select_outgoing_channel(None, None).unwrap(), 0);
+    }
+}
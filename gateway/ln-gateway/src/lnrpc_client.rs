@@ -3,33 +3,66 @@ use std::time::Duration;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
-use fedimint_core::task::sleep;
+use fedimint_core::task::{sleep, timeout};
 use futures::stream::BoxStream;
+use futures::StreamExt;
 use tonic::transport::{Channel, Endpoint};
 use tonic::Request;
 use tracing::error;
 use url::Url;
 
+use crate::gatewaylnrpc::complete_htlcs_request::cancel::Failure;
+use crate::gatewaylnrpc::complete_htlcs_request::Cancel;
 use crate::gatewaylnrpc::gateway_lightning_client::GatewayLightningClient;
 use crate::gatewaylnrpc::{
-    CompleteHtlcsRequest, CompleteHtlcsResponse, EmptyRequest, GetNodeInfoResponse,
-    GetRouteHintsResponse, PayInvoiceRequest, PayInvoiceResponse, SubscribeInterceptHtlcsRequest,
-    SubscribeInterceptHtlcsResponse,
+    ChannelUpdate, CompleteHtlcsRequest, CompleteHtlcsResponse, EmptyRequest, GetLiquidityResponse,
+    GetNodeInfoResponse, GetRouteHintsResponse, ListPaymentsRequest, ListPaymentsResponse,
+    OnionMessage, PayInvoiceRequest, PayInvoiceResponse, SendOnionMessageRequest,
+    SubscribeInterceptHtlcsRequest, SubscribeInterceptHtlcsResponse,
 };
 use crate::{GatewayError, Result};
 
 pub type HtlcStream<'a> =
     BoxStream<'a, std::result::Result<SubscribeInterceptHtlcsResponse, tonic::Status>>;
 
+pub type ChannelUpdateStream<'a> = BoxStream<'a, std::result::Result<ChannelUpdate, tonic::Status>>;
+
+pub type OnionMessageStream<'a> = BoxStream<'a, std::result::Result<OnionMessage, tonic::Status>>;
+
+/// Waits for the next channel update and swallows the ones following it until
+/// `debounce` passes without another, so a burst of updates is handled once
+///
+/// Returns the last update of the burst, or the error ending it.
+pub async fn next_channel_update(
+    updates: &mut ChannelUpdateStream<'_>,
+    debounce: Duration,
+) -> Option<std::result::Result<ChannelUpdate, tonic::Status>> {
+    let mut update = match updates.next().await? {
+        Ok(update) => update,
+        Err(e) => return Some(Err(e)),
+    };
+    loop {
+        match timeout(debounce, updates.next()).await {
+            Ok(Some(Ok(next))) => update = next,
+            Ok(Some(Err(e))) => return Some(Err(e)),
+            Ok(None) | Err(_) => return Some(Ok(update)),
+        }
+    }
+}
+
 #[async_trait]
 pub trait ILnRpcClient: Debug + Send + Sync {
     /// Get the public key and alias of the lightning node
     async fn info(&self) -> Result<GetNodeInfoResponse>;
 
-    /// Get route hints to the lightning node
+    /// Get route hints to the lightning node, including the ones over private
+    /// channels
     async fn routehints(&self) -> Result<GetRouteHintsResponse>;
 
     /// Attempt to pay an invoice using the lightning node
+    ///
+    /// If the request sets an outgoing channel id or a first hop pubkey the
+    /// payment has to leave the node over a matching channel.
     async fn pay(&self, invoice: PayInvoiceRequest) -> Result<PayInvoiceResponse>;
 
     /// Subscribe to intercept htlcs that belong to a specific mint identified
@@ -43,6 +76,31 @@ pub trait ILnRpcClient: Debug + Send + Sync {
     /// determining an outcome
     async fn complete_htlc(&self, outcome: CompleteHtlcsRequest) -> Result<CompleteHtlcsResponse>;
 
+    /// List the payments the lightning node made since
+    /// `request.created_after`
+    async fn list_payments(&self, request: ListPaymentsRequest) -> Result<ListPaymentsResponse>;
+
+    /// Get how much the lightning node can currently send and receive over
+    /// its active channels
+    async fn liquidity(&self) -> Result<GetLiquidityResponse>;
+
+    /// Subscribe to changes to the capacity of the lightning node's channels,
+    /// e.g. opens, closes and splices
+    ///
+    /// Fails if the lightning node can't report such changes, in which case
+    /// capacity is only refreshed periodically.
+    async fn subscribe_channel_updates<'a>(&self) -> Result<ChannelUpdateStream<'a>>;
+
+    /// Send an onion message to a peer of the lightning node
+    ///
+    /// Fails if the lightning node doesn't support onion messages.
+    async fn send_onion_message(&self, message: SendOnionMessageRequest) -> Result<()>;
+
+    /// Subscribe to the onion messages addressed to the lightning node
+    ///
+    /// Fails if the lightning node doesn't support onion messages.
+    async fn subscribe_onion_messages<'a>(&self) -> Result<OnionMessageStream<'a>>;
+
     /// Create a connection to the lightning node
     async fn connect(&mut self) -> Result<()>;
 
@@ -146,6 +204,71 @@ impl ILnRpcClient for NetworkLnRpcClient {
         ))
     }
 
+    async fn list_payments(&self, request: ListPaymentsRequest) -> Result<ListPaymentsResponse> {
+        if let Some(mut client) = self.client.clone() {
+            let req = Request::new(request);
+            let res = client.list_payments(req).await?;
+
+            return Ok(res.into_inner());
+        }
+
+        Err(GatewayError::other(
+            "Error: not connected to CLN extension".to_string(),
+        ))
+    }
+
+    async fn liquidity(&self) -> Result<GetLiquidityResponse> {
+        if let Some(mut client) = self.client.clone() {
+            let req = Request::new(EmptyRequest {});
+            let res = client.get_liquidity(req).await?;
+
+            return Ok(res.into_inner());
+        }
+
+        Err(GatewayError::other(
+            "Error: not connected to CLN extension".to_string(),
+        ))
+    }
+
+    async fn subscribe_channel_updates<'a>(&self) -> Result<ChannelUpdateStream<'a>> {
+        if let Some(mut client) = self.client.clone() {
+            let req = Request::new(EmptyRequest {});
+            let res = client.subscribe_channel_updates(req).await?;
+
+            return Ok(Box::pin(res.into_inner()));
+        }
+
+        Err(GatewayError::other(
+            "Error: not connected to CLN extension".to_string(),
+        ))
+    }
+
+    async fn send_onion_message(&self, message: SendOnionMessageRequest) -> Result<()> {
+        if let Some(mut client) = self.client.clone() {
+            let req = Request::new(message);
+            client.send_onion_message(req).await?;
+
+            return Ok(());
+        }
+
+        Err(GatewayError::other(
+            "Error: not connected to CLN extension".to_string(),
+        ))
+    }
+
+    async fn subscribe_onion_messages<'a>(&self) -> Result<OnionMessageStream<'a>> {
+        if let Some(mut client) = self.client.clone() {
+            let req = Request::new(EmptyRequest {});
+            let res = client.subscribe_onion_messages(req).await?;
+
+            return Ok(Box::pin(res.into_inner()));
+        }
+
+        Err(GatewayError::other(
+            "Error: not connected to CLN extension".to_string(),
+        ))
+    }
+
     async fn connect(&mut self) -> Result<()> {
         let client = loop {
             match GatewayLightningClient::connect(self.endpoint.clone()).await {
@@ -166,3 +289,27 @@ impl ILnRpcClient for NetworkLnRpcClient {
         Ok(())
     }
 }
+
+/// Flag of the BOLT 4 failures that concern the channel the HTLC was forwarded
+/// over
+const BOLT4_UPDATE: u16 = 0x1000;
+
+impl Cancel {
+    /// Encodes the BOLT 4 failure message the lightning node reports to the
+    /// sender, without the optional channel update
+    pub fn failure_message(&self) -> Vec<u8> {
+        let mut message = vec![];
+        match self.failure() {
+            Failure::TemporaryChannelFailure | Failure::AmountAboveMaximum => {
+                message.extend((BOLT4_UPDATE | 7).to_be_bytes());
+            }
+            Failure::AmountBelowMinimum => {
+                message.extend((BOLT4_UPDATE | 11).to_be_bytes());
+                message.extend(self.htlc_msat.to_be_bytes());
+            }
+        }
+        // zero length channel update
+        message.extend(0u16.to_be_bytes());
+        message
+    }
+}
@@ -0,0 +1,209 @@
+//! LNURL-pay and lightning address endpoints, which let users of connected
+//! federations receive lightning payments without running anything themselves
+//!
+//! A user registers a name with the public key claiming its payments. Paying
+//! `<name>@<gateway host>` makes the gateway submit an offer to the user's
+//! federation whose preimage is a tweak of that key, and return an invoice
+//! routed through the gateway's node. Once paid, the operator hands the user
+//! the tweaks of its payments to sweep their incoming contracts.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use crate::{GatewayError, Result};
+
+/// Smallest payment a lightning address accepts
+pub const MIN_SENDABLE: Amount = Amount::from_sats(1);
+/// Largest payment a lightning address accepts
+pub const MAX_SENDABLE: Amount = Amount::from_sats(1_000_000);
+/// Longest name of a lightning address
+const MAX_NAME_LEN: usize = 64;
+/// Requests an IP address may send to the public LNURL endpoints per
+/// [`RATE_LIMIT_WINDOW`]
+const MAX_REQUESTS_PER_WINDOW: u32 = 30;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+/// IP addresses whose requests are counted at once, new ones are rejected
+/// while all of them are within their window
+const MAX_RATE_LIMITED_SOURCES: usize = 10_000;
+
+/// First response of the LNURL-pay protocol, tells the payer what and how
+/// much it can pay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayResponse {
+    pub callback: Url,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    pub metadata: String,
+    pub tag: String,
+}
+
+/// Second response of the LNURL-pay protocol, carries the invoice to pay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LnurlInvoiceResponse {
+    pub pr: String,
+    pub routes: Vec<()>,
+}
+
+impl LnurlPayResponse {
+    /// Describes the lightning address `name` of the gateway reachable at `api`
+    pub fn new(name: &str, api: &Url) -> Result<Self> {
+        Ok(Self {
+            callback: api
+                .join(&format!("lnurlp/{name}/callback"))
+                .map_err(|e| GatewayError::Other(e.into()))?,
+            min_sendable: MIN_SENDABLE.msats,
+            max_sendable: MAX_SENDABLE.msats,
+            metadata: metadata(name, api),
+            tag: "payRequest".to_string(),
+        })
+    }
+}
+
+/// Metadata of the lightning address `name`, the invoices paying it commit to
+/// its hash
+pub fn metadata(name: &str, api: &Url) -> String {
+    let identifier = format!("{name}@{}", api.host_str().unwrap_or_default());
+    json!([
+        ["text/plain", format!("Payment to {identifier}")],
+        ["text/identifier", identifier],
+    ])
+    .to_string()
+}
+
+pub fn metadata_hash(name: &str, api: &Url) -> sha256::Hash {
+    sha256::Hash::hash(metadata(name, api).as_bytes())
+}
+
+/// Checks that `name` may be used as the user part of a lightning address
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.".contains(c));
+    if name.is_empty() || name.len() > MAX_NAME_LEN || !valid_chars {
+        return Err(GatewayError::other(format!(
+            "Invalid lightning address name {name}"
+        )));
+    }
+    Ok(())
+}
+
+/// Limits the requests to the public LNURL endpoints per IP address, since
+/// every invoice makes the gateway submit an offer to a federation
+#[derive(Debug, Default)]
+pub struct LnurlRateLimiter {
+    /// Start of the current window of every source and its requests in it
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl LnurlRateLimiter {
+    /// Counts a request of `source` at `now`, fails if the source exceeded its
+    /// requests in the current window
+    pub fn admit(&self, source: IpAddr, now: Instant) -> Result<()> {
+        let mut windows = self.windows.lock().expect("locking can't fail");
+        if windows.len() >= MAX_RATE_LIMITED_SOURCES && !windows.contains_key(&source) {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < RATE_LIMIT_WINDOW);
+            if windows.len() >= MAX_RATE_LIMITED_SOURCES {
+                return Err(GatewayError::Other(anyhow!(
+                    "Too many LNURL requests, try again later"
+                )));
+            }
+        }
+
+        let (start, requests) = windows.entry(source).or_insert((now, 0));
+        if now.duration_since(*start) >= RATE_LIMIT_WINDOW {
+            *start = now;
+            *requests = 0;
+        }
+        if *requests >= MAX_REQUESTS_PER_WINDOW {
+            return Err(GatewayError::Other(anyhow!(
+                "Too many LNURL requests from {source}, try again later"
+            )));
+        }
+        *requests += 1;
+        Ok(())
+    }
+}
+
+/// Checks that lightning addresses accept payments of `amount`
+pub fn validate_amount(amount: Amount) -> Result<()> {
+    if amount < MIN_SENDABLE || MAX_SENDABLE < amount {
+        return Err(GatewayError::other(format!(
+            "Amount {amount} is outside of [{MIN_SENDABLE}, {MAX_SENDABLE}]"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Instant;
+
+    use fedimint_core::Amount;
+    use url::Url;
+
+    use super::{
+        validate_amount, validate_name, LnurlPayResponse, LnurlRateLimiter,
+        MAX_REQUESTS_PER_WINDOW, RATE_LIMIT_WINDOW,
+    };
+
+    #[test]
+    fn pay_response_commits_to_metadata() {
+        let api = Url::parse("https://gateway.example.com/").unwrap();
+        let response = LnurlPayResponse::new("alice", &api).unwrap();
+
+        assert_eq!(
+            response.callback.as_str(),
+            "https://gateway.example.com/lnurlp/alice/callback"
+        );
+        assert_eq!(
+            response.metadata,
+            concat!(
+                r#"[["text/plain","Payment to alice@gateway.example.com"],"#,
+                r#"["text/identifier","alice@gateway.example.com"]]"#
+            )
+        );
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["tag"], "payRequest");
+        assert_eq!(json["minSendable"], 1000);
+    }
+
+    #[test]
+    fn validates_names_and_amounts() {
+        assert!(validate_name("alice.bob-1_2").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("Alice").is_err());
+        assert!(validate_name("alice/../admin").is_err());
+
+        assert!(validate_amount(Amount::from_sats(1)).is_ok());
+        assert!(validate_amount(Amount::from_msats(999)).is_err());
+        assert!(validate_amount(Amount::from_sats(1_000_001)).is_err());
+    }
+
+    #[test]
+    fn rate_limits_requests_per_source() {
+        let limiter = LnurlRateLimiter::default();
+        let source: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..MAX_REQUESTS_PER_WINDOW {
+            assert!(limiter.admit(source, now).is_ok());
+        }
+        assert!(limiter.admit(source, now).is_err());
+        assert!(limiter.admit(other, now).is_ok());
+
+        // a new window starts once the current one ended
+        assert!(limiter.admit(source, now + RATE_LIMIT_WINDOW).is_ok());
+    }
+}
@@ -0,0 +1,64 @@
+use std::net::SocketAddr;
+
+use axum::routing::get;
+use axum::Router;
+use fedimint_core::task::TaskGroup;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge_vec, Encoder, IntCounterVec, IntGaugeVec,
+    TextEncoder,
+};
+use tokio::select;
+use tracing::{debug, error, info};
+
+/// Number of intercepted HTLCs waiting to be processed, by federation id
+pub static HTLC_QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "fedimint_gateway_htlc_queue_depth",
+        "Number of intercepted HTLCs waiting to be processed",
+        &["federation"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Number of intercepted HTLCs cancelled because the queue was full, by
+/// federation id
+pub static HTLC_QUEUE_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "fedimint_gateway_htlc_queue_rejected_total",
+        "Number of intercepted HTLCs cancelled because the queue was full",
+        &["federation"]
+    )
+    .expect("metric is only registered once")
+});
+
+/// Renders all registered metrics in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("encoding metrics into a vec can't fail");
+    String::from_utf8(buffer).expect("Prometheus text format is utf-8")
+}
+
+async fn metrics() -> String {
+    render()
+}
+
+/// Serves the Prometheus metrics of the gateway at `/metrics`
+pub async fn run_metrics_server(bind_addr: SocketAddr, task_group: TaskGroup) {
+    let app = Router::new().route("/metrics", get(metrics));
+
+    let shutdown_future = task_group.make_handle().make_shutdown_rx().await;
+    let server_future = axum::Server::bind(&bind_addr).serve(app.into_make_service());
+
+    info!("Metrics server is listening on {}", bind_addr);
+    select! {
+        _ = shutdown_future => {
+            debug!("Metrics server shutting down");
+        },
+        Err(err) = server_future => {
+            error!(?err, "Metrics server encountered an error");
+        }
+    }
+}
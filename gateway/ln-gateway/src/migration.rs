@@ -0,0 +1,204 @@
+//! Archive of the state of a gateway, to move it to another machine
+//!
+//! The archive contains the config and the whole database of the client of
+//! every connected federation, including the journal of pending outgoing
+//! payments. It contains the redeem keys of the gateway, so it is encrypted
+//! with a password chosen by the operator.
+
+use anyhow::{anyhow, bail, Context};
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use fedimint_core::db::Database;
+use mint_client::db::migrate_client_database;
+use mint_client::GatewayClientConfig;
+use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Version of the layout of the archive, bumped whenever a gateway could not
+/// import archives of the previous version anymore
+pub const STATE_ARCHIVE_VERSION: u32 = 1;
+
+/// Encrypted [`GatewayState`] as returned by `export-state`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateArchive {
+    pub version: u32,
+    /// Salt the encryption key was derived from the password with
+    pub salt: String,
+    /// Hex encoded ciphertext of the JSON encoded [`GatewayState`]
+    pub ciphertext: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayState {
+    /// Code version of the gateway that exported the state
+    pub code_version: String,
+    /// Lightning node the gateway was connected to
+    pub node_pub_key: PublicKey,
+    pub federations: Vec<FederationState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationState {
+    pub config: GatewayClientConfig,
+    /// Hex encoded keys and values of all entries of the client database
+    pub db: Vec<(String, String)>,
+}
+
+impl FederationState {
+    pub fn new(config: GatewayClientConfig, entries: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        let db = entries
+            .into_iter()
+            .map(|(key, value)| (key.to_hex(), value.to_hex()))
+            .collect();
+        Self { config, db }
+    }
+
+    /// Decoded keys and values of the client database
+    pub fn db_entries(&self) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.db
+            .iter()
+            .map(|(key, value)| Ok((Vec::from_hex(key)?, Vec::from_hex(value)?)))
+            .collect()
+    }
+}
+
+impl StateArchive {
+    pub fn encrypt(state: &GatewayState, password: &str) -> anyhow::Result<Self> {
+        let salt = fedimint_aead::random_salt();
+        let key = fedimint_aead::get_encryption_key(password, &salt)?;
+        let ciphertext = fedimint_aead::encrypt(serde_json::to_vec(state)?, &key)?;
+
+        Ok(Self {
+            version: STATE_ARCHIVE_VERSION,
+            salt,
+            ciphertext: ciphertext.to_hex(),
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> anyhow::Result<GatewayState> {
+        if self.version != STATE_ARCHIVE_VERSION {
+            bail!(
+                "Archive has version {}, but this gateway only imports version {}",
+                self.version,
+                STATE_ARCHIVE_VERSION
+            );
+        }
+
+        let key = fedimint_aead::get_encryption_key(password, &self.salt)?;
+        let mut ciphertext = Vec::from_hex(&self.ciphertext).context("Invalid ciphertext")?;
+        let plaintext = fedimint_aead::decrypt(&mut ciphertext, &key)
+            .map_err(|_| anyhow!("Failed to decrypt the archive, wrong password?"))?;
+
+        serde_json::from_slice(plaintext).context("Invalid gateway state")
+    }
+}
+
+/// Writes the exported `entries` of a federation to its empty `db` and
+/// migrates them to the current version of the client, the same as if the
+/// client had been upgraded in place
+///
+/// Entries written by a newer version of the client can't be migrated, in that
+/// case (as in any other failure) the database is left empty again.
+pub async fn import_database(
+    db: &Database,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+    if !dbtx.raw_find_by_prefix(&[]).await.is_empty() {
+        bail!("Database of the federation already holds entries");
+    }
+    for (key, value) in entries {
+        dbtx.raw_insert_bytes(&key, value).await;
+    }
+    dbtx.commit_tx().await;
+
+    if let Err(e) = migrate_client_database(db).await {
+        clear_database(db).await;
+        return Err(e.context("Failed to migrate the imported database"));
+    }
+    Ok(())
+}
+
+/// Removes all entries of `db`, undoing [`import_database`]
+pub async fn clear_database(db: &Database) {
+    let mut dbtx = db.begin_transaction().await;
+    for (key, _) in dbtx.raw_find_by_prefix(&[]).await {
+        dbtx.raw_remove_entry(&key).await;
+    }
+    dbtx.commit_tx().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{DatabaseVersion, NamespaceVersionKey};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+
+    use super::*;
+
+    fn database() -> Database {
+        Database::new(MemDatabase::new(), ModuleDecoderRegistry::default())
+    }
+
+    async fn entries(db: &Database) -> Vec<(Vec<u8>, Vec<u8>)> {
+        db.begin_transaction().await.raw_find_by_prefix(&[]).await
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let state = GatewayState {
+            code_version: "sample".to_string(),
+            node_pub_key: PublicKey::from_secret_key(
+                &secp256k1::Secp256k1::new(),
+                &secp256k1::SecretKey::from_slice(&[1; 32]).unwrap(),
+            ),
+            federations: vec![],
+        };
+
+        let archive = StateArchive::encrypt(&state, "password").unwrap();
+        let decrypted = archive.decrypt("password").unwrap();
+        assert_eq!(decrypted.code_version, state.code_version);
+        assert_eq!(decrypted.node_pub_key, state.node_pub_key);
+
+        assert!(archive.decrypt("wrong").is_err());
+        assert!(StateArchive {
+            version: STATE_ARCHIVE_VERSION + 1,
+            ..archive
+        }
+        .decrypt("password")
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn import_keeps_current_entries() {
+        let exported = database();
+        migrate_client_database(&exported).await.unwrap();
+        let exported = entries(&exported).await;
+
+        let db = database();
+        import_database(&db, exported.clone()).await.unwrap();
+        assert_eq!(entries(&db).await, exported);
+
+        // importing twice would mix the states
+        assert!(import_database(&db, exported.clone()).await.is_err());
+        assert_eq!(entries(&db).await, exported);
+    }
+
+    #[tokio::test]
+    async fn import_rejects_newer_databases() {
+        let exported = database();
+        migrate_client_database(&exported).await.unwrap();
+        let mut dbtx = exported.begin_transaction().await;
+        dbtx.insert_entry(
+            &NamespaceVersionKey("ln".to_string()),
+            &DatabaseVersion(u64::MAX),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        let db = database();
+        assert!(import_database(&db, entries(&exported).await)
+            .await
+            .is_err());
+        assert!(entries(&db).await.is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+//! Picks the route hints to the lightning node that the gateway announces to
+//! federations and puts into the invoices it creates
+//!
+//! A node with only private channels can't be paid without them, so the
+//! operator decides how many hints to include and which channels come first.
+
+use std::cmp::Reverse;
+
+use anyhow::{bail, format_err};
+use mint_client::modules::ln::route_hints::RouteHint;
+
+use crate::gatewaylnrpc::GetRouteHintsResponse;
+
+/// Number of route hints included by default, more make invoices larger
+/// without helping payers much
+pub const DEFAULT_MAX_ROUTE_HINTS: usize = 3;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RouteHintConfig {
+    /// Most route hints to include
+    pub max_route_hints: usize,
+    /// Short channel ids or aliases of the channels whose hints come first, in
+    /// order of preference
+    pub preferred_channels: Vec<u64>,
+}
+
+impl Default for RouteHintConfig {
+    fn default() -> Self {
+        Self {
+            max_route_hints: DEFAULT_MAX_ROUTE_HINTS,
+            preferred_channels: vec![],
+        }
+    }
+}
+
+impl RouteHintConfig {
+    /// Returns the hints of `response` to include, hints over preferred
+    /// channels come first and the others by how much the node can receive
+    /// over them
+    pub fn select(&self, response: GetRouteHintsResponse) -> anyhow::Result<Vec<RouteHint>> {
+        let mut route_hints = response.route_hints;
+        route_hints.sort_by_key(|route_hint| {
+            let preference = route_hint
+                .hops
+                .last()
+                .and_then(|hop| {
+                    self.preferred_channels
+                        .iter()
+                        .position(|scid| *scid == hop.short_channel_id)
+                })
+                .unwrap_or(usize::MAX);
+            (
+                preference,
+                Reverse(route_hint.inbound_capacity_msat.unwrap_or(0)),
+            )
+        });
+        route_hints.truncate(self.max_route_hints);
+
+        GetRouteHintsResponse { route_hints }.try_into()
+    }
+}
+
+/// Parses a short channel id given as a number or as
+/// `<block>x<transaction>x<output>`
+pub fn parse_short_channel_id(s: &str) -> anyhow::Result<u64> {
+    if let Ok(scid) = s.parse() {
+        return Ok(scid);
+    }
+
+    let parts = s
+        .split('x')
+        .map(str::parse::<u64>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format_err!("Invalid short channel id {s}"))?;
+    let [block, tx, output] = parts[..] else {
+        bail!("Invalid short channel id {s}");
+    };
+    if block >= 1 << 24 || tx >= 1 << 24 || output >= 1 << 16 {
+        bail!("Invalid short channel id {s}");
+    }
+    Ok(block << 40 | tx << 16 | output)
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::PublicKey;
+
+    use super::{parse_short_channel_id, RouteHintConfig};
+    use crate::gatewaylnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
+    use crate::gatewaylnrpc::GetRouteHintsResponse;
+
+    fn route_hint(short_channel_id: u64, inbound_capacity_msat: u64) -> RouteHint {
+        let src_node_id = PublicKey::from_slice(&[2; 33])
+            .unwrap()
+            .serialize()
+            .to_vec();
+        RouteHint {
+            hops: vec![RouteHintHop {
+                src_node_id,
+                short_channel_id,
+                ..Default::default()
+            }],
+            private: true,
+            inbound_capacity_msat: Some(inbound_capacity_msat),
+        }
+    }
+
+    #[test]
+    fn selects_preferred_then_largest_channels() {
+        let response = GetRouteHintsResponse {
+            route_hints: vec![route_hint(1, 100), route_hint(2, 300), route_hint(3, 200)],
+        };
+        let config = RouteHintConfig {
+            max_route_hints: 2,
+            preferred_channels: vec![1],
+        };
+
+        let selected = config.select(response).unwrap();
+        let scids: Vec<u64> = selected
+            .iter()
+            .map(|route_hint| route_hint.0[0].short_channel_id)
+            .collect();
+        assert_eq!(scids, vec![1, 2]);
+    }
+
+    #[test]
+    fn parses_short_channel_ids() {
+        assert_eq!(parse_short_channel_id("42").unwrap(), 42);
+        assert_eq!(
+            parse_short_channel_id("103x1x0").unwrap(),
+            103 << 40 | 1 << 16
+        );
+        assert!(parse_short_channel_id("103x1").is_err());
+        assert!(parse_short_channel_id("103x1x70000").is_err());
+        assert!(parse_short_channel_id("alias").is_err());
+    }
+}
@@ -0,0 +1,483 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::Address;
+use fedimint_core::config::FederationId;
+use futures::{Stream, StreamExt};
+use mint_client::modules::ln::contracts::ContractId;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::warn;
+
+use super::{
+    BalancePayload, DepositAddressPayload, FirstHopConstraint, GatewayRpcSender, InfoPayload,
+    ManualPayInvoicePayload, WithdrawPayload,
+};
+use crate::events::{GatewayEvent, OutgoingPaymentStatus};
+use crate::gatewayrpc::gateway_event::Event;
+use crate::gatewayrpc::gateway_rpc_server::{GatewayRpc, GatewayRpcServer};
+use crate::gatewayrpc::{
+    self, BalanceRequest, BalanceResponse, DepositAddressRequest, DepositAddressResponse,
+    InfoRequest, InfoResponse, PayInvoiceRequest, PayInvoiceResponse, SubscribeEventsRequest,
+    WithdrawRequest, WithdrawResponse,
+};
+
+/// Serves the gateway's operations over gRPC, authenticated with the same
+/// password as the webserver
+pub async fn run_grpc_server(
+    authkey: String,
+    bind_addr: SocketAddr,
+    sender: GatewayRpcSender,
+    events: broadcast::Sender<GatewayEvent>,
+) -> anyhow::Result<()> {
+    let token: MetadataValue<Ascii> = format!("Bearer {authkey}")
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Gateway password is not valid metadata"))?;
+    let service =
+        GatewayRpcServer::with_interceptor(GatewayRpcService { sender, events }, move |request| {
+            authenticate(&token, request)
+        });
+
+    Server::builder()
+        .add_service(service)
+        .serve(bind_addr)
+        .await?;
+
+    Ok(())
+}
+
+fn authenticate(token: &MetadataValue<Ascii>, request: Request<()>) -> Result<Request<()>, Status> {
+    match request.metadata().get("authorization") {
+        Some(authorization) if token == authorization => Ok(request),
+        _ => Err(Status::unauthenticated("Invalid gateway password")),
+    }
+}
+
+struct GatewayRpcService {
+    sender: GatewayRpcSender,
+    events: broadcast::Sender<GatewayEvent>,
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<gatewayrpc::GatewayEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl GatewayRpc for GatewayRpcService {
+    type SubscribeEventsStream = EventStream;
+    type PayInvoiceWithStatusStream = EventStream;
+
+    async fn info(&self, _request: Request<InfoRequest>) -> Result<Response<InfoResponse>, Status> {
+        let info = self.sender.send(InfoPayload).await.map_err(internal)?;
+        Ok(Response::new(InfoResponse {
+            version_hash: info.version_hash,
+            lightning_pub_key: info.lightning_pub_key,
+            lightning_alias: info.lightning_alias,
+            federations: info
+                .federations
+                .into_iter()
+                .map(|federation| gatewayrpc::FederationInfo {
+                    federation_id: federation.federation_id.to_string(),
+                    mint_pubkey: federation.mint_pubkey.to_string(),
+                    mint_channel_id: federation.mint_channel_id,
+                    ecash_reserve_msat: federation.ecash_reserve.msats,
+                    fee_base_msat: federation.fees.base_msat,
+                    fee_proportional_millionths: federation.fees.proportional_millionths,
+                    min_htlc_msat: federation.htlc_amount_band.min.map(|min| min.msats),
+                    max_htlc_msat: federation.htlc_amount_band.max.map(|max| max.msats),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn balance(
+        &self,
+        request: Request<BalanceRequest>,
+    ) -> Result<Response<BalanceResponse>, Status> {
+        let payload = BalancePayload {
+            federation_id: parse_federation_id(&request.get_ref().federation_id)?,
+        };
+        let balance = self.sender.send(payload).await.map_err(internal)?;
+        Ok(Response::new(BalanceResponse {
+            balance_msat: balance.msats,
+        }))
+    }
+
+    async fn pay_invoice(
+        &self,
+        request: Request<PayInvoiceRequest>,
+    ) -> Result<Response<PayInvoiceResponse>, Status> {
+        let payload = parse_pay_invoice_request(request.into_inner())?;
+        self.sender.send(payload).await.map_err(internal)?;
+        Ok(Response::new(PayInvoiceResponse {}))
+    }
+
+    async fn pay_invoice_with_status(
+        &self,
+        request: Request<PayInvoiceRequest>,
+    ) -> Result<Response<Self::PayInvoiceWithStatusStream>, Status> {
+        let payload = parse_pay_invoice_request(request.into_inner())?;
+        let events = self
+            .sender
+            .pay_invoice_with_status(payload)
+            .map(|event| Ok(event.into()));
+        Ok(Response::new(Box::pin(events)))
+    }
+
+    async fn deposit_address(
+        &self,
+        request: Request<DepositAddressRequest>,
+    ) -> Result<Response<DepositAddressResponse>, Status> {
+        let payload = DepositAddressPayload {
+            federation_id: parse_federation_id(&request.get_ref().federation_id)?,
+        };
+        let address = self.sender.send(payload).await.map_err(internal)?;
+        Ok(Response::new(DepositAddressResponse {
+            address: address.to_string(),
+        }))
+    }
+
+    async fn withdraw(
+        &self,
+        request: Request<WithdrawRequest>,
+    ) -> Result<Response<WithdrawResponse>, Status> {
+        let request = request.into_inner();
+        let payload = WithdrawPayload {
+            federation_id: parse_federation_id(&request.federation_id)?,
+            amount: request.amount_sat.map(bitcoin::Amount::from_sat),
+            address: Address::from_str(&request.address)
+                .map_err(|e| Status::invalid_argument(format!("Invalid address: {e}")))?,
+        };
+        let txid = self.sender.send(payload).await.map_err(internal)?;
+        Ok(Response::new(WithdrawResponse {
+            txid: txid.to_string(),
+        }))
+    }
+
+    async fn subscribe_events(
+        &self,
+        _request: Request<SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        let events = BroadcastStream::new(self.events.subscribe()).filter_map(|event| async {
+            match event {
+                Ok(event) => Some(Ok(event.into())),
+                Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                    warn!("Event subscriber fell behind and missed {missed} events");
+                    None
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+impl From<GatewayEvent> for gatewayrpc::GatewayEvent {
+    fn from(event: GatewayEvent) -> Self {
+        let event = match event {
+            GatewayEvent::FederationConnected { federation_id } => {
+                Event::FederationConnected(gatewayrpc::FederationConnected {
+                    federation_id: federation_id.to_string(),
+                })
+            }
+            GatewayEvent::OutgoingPaymentUpdated {
+                federation_id,
+                contract_id,
+                status,
+            } => Event::OutgoingPaymentUpdated(gatewayrpc::OutgoingPaymentUpdated {
+                federation_id: federation_id.to_string(),
+                contract_id: contract_id.to_string(),
+                status: gatewayrpc::outgoing_payment_updated::Status::from(status) as i32,
+            }),
+            GatewayEvent::OutgoingPaymentSucceeded {
+                federation_id,
+                contract_id,
+            } => Event::OutgoingPaymentSucceeded(gatewayrpc::OutgoingPaymentSucceeded {
+                federation_id: federation_id.to_string(),
+                contract_id: contract_id.to_string(),
+            }),
+            GatewayEvent::OutgoingPaymentFailed {
+                federation_id,
+                contract_id,
+                error,
+            } => Event::OutgoingPaymentFailed(gatewayrpc::OutgoingPaymentFailed {
+                federation_id: federation_id.to_string(),
+                contract_id: contract_id.to_string(),
+                error,
+            }),
+            GatewayEvent::DepositSubmitted {
+                federation_id,
+                txid,
+            } => Event::DepositSubmitted(gatewayrpc::DepositSubmitted {
+                federation_id: federation_id.to_string(),
+                txid: txid.to_string(),
+            }),
+            GatewayEvent::WithdrawalSubmitted {
+                federation_id,
+                txid,
+            } => Event::WithdrawalSubmitted(gatewayrpc::WithdrawalSubmitted {
+                federation_id: federation_id.to_string(),
+                txid: txid.to_string(),
+            }),
+            GatewayEvent::SweepToLnSucceeded {
+                federation_id,
+                contract_id,
+                gateway,
+            } => Event::SweepToLnSucceeded(gatewayrpc::SweepToLnSucceeded {
+                federation_id: federation_id.to_string(),
+                contract_id: contract_id.to_string(),
+                gateway: gateway.to_string(),
+            }),
+            GatewayEvent::SweepToLnFailed {
+                federation_id,
+                contract_id,
+                error,
+            } => Event::SweepToLnFailed(gatewayrpc::SweepToLnFailed {
+                federation_id: federation_id.to_string(),
+                contract_id: contract_id.to_string(),
+                error,
+            }),
+            GatewayEvent::OnionMessageReceived { fields, reply_path } => {
+                Event::OnionMessageReceived(gatewayrpc::OnionMessageReceived {
+                    fields: fields
+                        .into_iter()
+                        .map(
+                            |(tlv_type, value)| gatewayrpc::onion_message_received::Field {
+                                tlv_type,
+                                value,
+                            },
+                        )
+                        .collect(),
+                    reply_path,
+                })
+            }
+        };
+        gatewayrpc::GatewayEvent { event: Some(event) }
+    }
+}
+
+impl From<OutgoingPaymentStatus> for gatewayrpc::outgoing_payment_updated::Status {
+    fn from(status: OutgoingPaymentStatus) -> Self {
+        match status {
+            OutgoingPaymentStatus::ContractFetched => Self::ContractFetched,
+            OutgoingPaymentStatus::ContractValidated => Self::ContractValidated,
+            OutgoingPaymentStatus::PaymentInFlight { internal: false } => {
+                Self::LightningPaymentInFlight
+            }
+            OutgoingPaymentStatus::PaymentInFlight { internal: true } => {
+                Self::InternalPaymentInFlight
+            }
+            OutgoingPaymentStatus::PreimageReceived => Self::PreimageReceived,
+            OutgoingPaymentStatus::ClaimSubmitted => Self::ClaimSubmitted,
+        }
+    }
+}
+
+fn parse_pay_invoice_request(
+    request: PayInvoiceRequest,
+) -> Result<ManualPayInvoicePayload, Status> {
+    let first_hop = request
+        .first_hop_pubkey
+        .as_deref()
+        .map(PublicKey::from_str)
+        .transpose()
+        .map_err(|e| Status::invalid_argument(format!("Invalid first hop pubkey: {e}")))?;
+    Ok(ManualPayInvoicePayload {
+        federation_id: parse_federation_id(&request.federation_id)?,
+        contract_id: ContractId::from_str(&request.contract_id)
+            .map_err(|e| Status::invalid_argument(format!("Invalid contract id: {e}")))?,
+        first_hop: FirstHopConstraint {
+            outgoing_channel_id: request.outgoing_channel_id,
+            first_hop,
+        },
+    })
+}
+
+fn parse_federation_id(federation_id: &str) -> Result<FederationId, Status> {
+    FederationId::from_str(federation_id)
+        .map_err(|e| Status::invalid_argument(format!("Invalid federation id: {e}")))
+}
+
+fn internal(error: anyhow::Error) -> Status {
+    Status::internal(format!("{error:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::constants::GENERATOR_X;
+    use bitcoin::secp256k1::PublicKey;
+    use bitcoin_hashes::Hash;
+    use fedimint_core::config::FederationId;
+    use fedimint_core::Amount;
+    use futures::StreamExt;
+    use mint_client::modules::ln::contracts::ContractId;
+    use tokio::sync::{broadcast, mpsc};
+    use tonic::metadata::MetadataValue;
+    use tonic::{Code, Request};
+
+    use super::{authenticate, parse_pay_invoice_request, GatewayRpcService};
+    use crate::events::{GatewayEvent, OutgoingPaymentStatus, EVENT_BUFFER_SIZE};
+    use crate::gatewayrpc::gateway_event::Event;
+    use crate::gatewayrpc::gateway_rpc_server::GatewayRpc;
+    use crate::gatewayrpc::{
+        self, outgoing_payment_updated, BalanceRequest, PayInvoiceRequest, SubscribeEventsRequest,
+    };
+    use crate::rpc::{FirstHopConstraint, GatewayRequest, GatewayRpcSender};
+
+    fn service() -> (
+        GatewayRpcService,
+        mpsc::Receiver<GatewayRequest>,
+        broadcast::Sender<GatewayEvent>,
+    ) {
+        let (sender, requests) = mpsc::channel(1);
+        let (events, _) = broadcast::channel(EVENT_BUFFER_SIZE);
+        let service = GatewayRpcService {
+            sender: GatewayRpcSender::new(sender),
+            events: events.clone(),
+        };
+        (service, requests, events)
+    }
+
+    fn pay_invoice_request() -> PayInvoiceRequest {
+        PayInvoiceRequest {
+            federation_id: FederationId::dummy().to_string(),
+            contract_id: ContractId::from_inner([1; 32]).to_string(),
+            outgoing_channel_id: Some(42),
+            first_hop_pubkey: None,
+        }
+    }
+
+    #[test]
+    fn authenticates_with_the_gateway_password() {
+        let token: MetadataValue<_> = "Bearer password".parse().unwrap();
+        let request = |authorization: Option<&str>| {
+            let mut request = Request::new(());
+            if let Some(authorization) = authorization {
+                request
+                    .metadata_mut()
+                    .insert("authorization", authorization.parse().unwrap());
+            }
+            request
+        };
+
+        assert!(authenticate(&token, request(Some("Bearer password"))).is_ok());
+        for authorization in [None, Some("Bearer wrong"), Some("password")] {
+            let status = authenticate(&token, request(authorization)).unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+    }
+
+    #[test]
+    fn pay_invoice_request_parses() {
+        let first_hop = PublicKey::from_slice(&[&[2][..], &GENERATOR_X[..]].concat()).unwrap();
+        let mut request = pay_invoice_request();
+        request.first_hop_pubkey = Some(first_hop.to_string());
+
+        let payload = parse_pay_invoice_request(request).unwrap();
+        assert_eq!(payload.federation_id, FederationId::dummy());
+        assert_eq!(payload.contract_id, ContractId::from_inner([1; 32]));
+        assert_eq!(
+            payload.first_hop,
+            FirstHopConstraint {
+                outgoing_channel_id: Some(42),
+                first_hop: Some(first_hop),
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_pay_invoice_request_is_rejected() {
+        let invalid = [
+            PayInvoiceRequest {
+                federation_id: "not a federation".to_string(),
+                ..pay_invoice_request()
+            },
+            PayInvoiceRequest {
+                contract_id: "not a contract".to_string(),
+                ..pay_invoice_request()
+            },
+            PayInvoiceRequest {
+                first_hop_pubkey: Some("not a pubkey".to_string()),
+                ..pay_invoice_request()
+            },
+        ];
+        for request in invalid {
+            let status = parse_pay_invoice_request(request).unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn balance_is_served_from_the_gateway() {
+        let (service, mut requests, _) = service();
+        let gateway = async move {
+            let Some(GatewayRequest::Balance(inner)) = requests.recv().await else {
+                panic!("Expected a balance request");
+            };
+            assert_eq!(inner.request.federation_id, FederationId::dummy());
+            inner.sender.send(Ok(Amount::from_msats(1_000))).unwrap();
+        };
+        let request = Request::new(BalanceRequest {
+            federation_id: FederationId::dummy().to_string(),
+        });
+
+        let ((), response) = tokio::join!(gateway, service.balance(request));
+        assert_eq!(response.unwrap().into_inner().balance_msat, 1_000);
+    }
+
+    #[tokio::test]
+    async fn balance_fails_on_invalid_request_or_without_gateway() {
+        let (service, requests, _) = service();
+
+        let request = Request::new(BalanceRequest {
+            federation_id: "not a federation".to_string(),
+        });
+        let status = service.balance(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        drop(requests);
+        let request = Request::new(BalanceRequest {
+            federation_id: FederationId::dummy().to_string(),
+        });
+        let status = service.balance(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_events() {
+        let (service, _requests, events) = service();
+        let stream = service
+            .subscribe_events(Request::new(SubscribeEventsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let contract_id = ContractId::from_inner([1; 32]);
+        events
+            .send(GatewayEvent::OutgoingPaymentUpdated {
+                federation_id: FederationId::dummy(),
+                contract_id,
+                status: OutgoingPaymentStatus::PaymentInFlight { internal: true },
+            })
+            .unwrap();
+        drop(service);
+        drop(events);
+
+        let received = stream.collect::<Vec<_>>().await;
+        let [Ok(gatewayrpc::GatewayEvent {
+            event: Some(Event::OutgoingPaymentUpdated(update)),
+        })] = received.as_slice()
+        else {
+            panic!("Expected a single payment update, got {received:?}");
+        };
+        assert_eq!(update.federation_id, FederationId::dummy().to_string());
+        assert_eq!(update.contract_id, contract_id.to_string());
+        assert_eq!(
+            update.status,
+            outgoing_payment_updated::Status::InternalPaymentInFlight as i32
+        );
+    }
+}
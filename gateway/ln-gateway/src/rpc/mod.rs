@@ -1,21 +1,36 @@
+pub mod grpc_server;
 pub mod rpc_client;
 pub mod rpc_server;
 
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::io::Cursor;
 
 use anyhow::{anyhow, Error};
+use bitcoin::secp256k1::PublicKey;
 use bitcoin::{Address, Transaction, XOnlyPublicKey};
 use bitcoin_hashes::hex::{FromHex, ToHex};
+use bitcoin_hashes::sha256;
 use fedimint_core::config::FederationId;
+use fedimint_core::task::TaskGroup;
 use fedimint_core::{Amount, TransactionId};
-use futures::Future;
-use mint_client::ln::PayInvoicePayload;
+use futures::{stream, Future, Stream};
+use lightning_invoice::Invoice;
+use mint_client::ln::{HtlcAmountBand, PayInvoicePayload};
+use mint_client::modules::ln::contracts::ContractId;
+use mint_client::modules::ln::GatewayFee;
 use mint_client::modules::wallet::txoproof::TxOutProof;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tokio::sync::{mpsc, oneshot};
 use tracing::error;
 
+use crate::accounting::AccountingExport;
+use crate::correlation::CorrelationId;
+use crate::events::{GatewayEvent, OutgoingPaymentStatus};
+use crate::gatewaylnrpc::SubscribeInterceptHtlcsResponse;
+use crate::lnurl::{LnurlInvoiceResponse, LnurlPayResponse};
+use crate::migration::StateArchive;
+use crate::test_payment::TestPaymentReport;
 use crate::{Gateway, GatewayError, Mode, Result};
 
 #[derive(Debug, Clone)]
@@ -54,6 +69,74 @@ impl GatewayRpcSender {
             })
             .map_err(|e| e.into())
     }
+
+    /// Pays the invoice of an outgoing contract and streams every step of the
+    /// payment as [`GatewayEvent::OutgoingPaymentUpdated`], the stream always
+    /// ends with the outcome of the payment
+    pub fn pay_invoice_with_status(
+        &self,
+        payload: ManualPayInvoicePayload,
+    ) -> impl Stream<Item = GatewayEvent> {
+        let ManualPayInvoicePayload {
+            federation_id,
+            contract_id,
+            ..
+        } = payload.clone();
+        let (status, statuses) = mpsc::unbounded_channel();
+        let sender = self.clone();
+        let payment = Box::pin(async move {
+            sender
+                .send(PayInvoiceWithStatusPayload {
+                    payment: payload,
+                    status,
+                })
+                .await
+        });
+
+        stream::unfold(Some((payment, statuses, None)), move |state| {
+            let federation_id = federation_id.clone();
+            async move {
+                let (mut payment, mut statuses, mut result) = state?;
+                if result.is_none() {
+                    tokio::select! {
+                        biased;
+                        Some(status) = statuses.recv() => {
+                            let event = GatewayEvent::OutgoingPaymentUpdated {
+                                federation_id,
+                                contract_id,
+                                status,
+                            };
+                            return Some((event, Some((payment, statuses, result))));
+                        }
+                        outcome = &mut payment => result = Some(outcome),
+                    }
+                }
+
+                // every step was sent before the payment returned
+                if let Ok(status) = statuses.try_recv() {
+                    let event = GatewayEvent::OutgoingPaymentUpdated {
+                        federation_id,
+                        contract_id,
+                        status,
+                    };
+                    return Some((event, Some((payment, statuses, result))));
+                }
+
+                let event = match result.expect("Set once the payment returned") {
+                    Ok(()) => GatewayEvent::OutgoingPaymentSucceeded {
+                        federation_id,
+                        contract_id,
+                    },
+                    Err(e) => GatewayEvent::OutgoingPaymentFailed {
+                        federation_id,
+                        contract_id,
+                        error: e.to_string(),
+                    },
+                };
+                Some((event, None))
+            }
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -74,6 +157,28 @@ pub struct RestorePayload {
     pub federation_id: FederationId,
 }
 
+/// Exports the state of the gateway encrypted with `password`, see
+/// [`crate::migration`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportStatePayload {
+    pub password: String,
+}
+
+/// Exports the payment history between `from` and before `to`, in seconds
+/// since the unix epoch, see [`crate::accounting`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportAccountingPayload {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportStatePayload {
+    pub archive: StateArchive,
+    /// Password the archive was exported with
+    pub password: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LightningReconnectPayload {
     // Sending `None` for node_type will be interpreted as just reconnecting using the existing
@@ -81,6 +186,34 @@ pub struct LightningReconnectPayload {
     pub node_type: Option<Mode>,
 }
 
+/// Sent by the gateway to itself when the lightning node reports that the
+/// capacity of its channels changed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelsUpdatedPayload {
+    /// The changed channel, if the lightning node reported it
+    pub short_channel_id: Option<u64>,
+}
+
+/// Sent by the actor of a federation to the gateway when an HTLC intercepted
+/// for it pays an offer of none of its users, so another federation of the
+/// gateway may buy its preimage
+#[derive(Debug)]
+pub struct BridgeHtlcPayload {
+    /// Federation the HTLC was intercepted for
+    pub federation_id: FederationId,
+    pub payment_hash: sha256::Hash,
+    pub htlc: SubscribeInterceptHtlcsResponse,
+    pub correlation_id: CorrelationId,
+}
+
+/// Sends an onion message from the lightning node to a peer of it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SendOnionMessagePayload {
+    pub node_id: PublicKey,
+    /// Hex encoded TLV records of the payload by type
+    pub fields: BTreeMap<u64, String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BalancePayload {
     pub federation_id: FederationId,
@@ -105,15 +238,154 @@ pub struct DepositPayload {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WithdrawPayload {
     pub federation_id: FederationId,
-    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
-    pub amount: bitcoin::Amount,
+    /// Withdraws the whole balance after fees if unset
+    #[serde(default, with = "bitcoin::util::amount::serde::as_sat::opt")]
+    pub amount: Option<bitcoin::Amount>,
     pub address: Address,
 }
 
+/// Pays out ecash of a federation to an invoice of the operator through
+/// another gateway of the federation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SweepToLnPayload {
+    pub federation_id: FederationId,
+    pub invoice: Invoice,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SweepToLnResponse {
+    /// Outgoing contract funded with the ecash, identifies the sweep in the
+    /// events reporting its outcome
+    pub contract_id: ContractId,
+    /// Node key of the gateway paying the invoice
+    pub gateway: PublicKey,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegisterLightningAddressPayload {
+    pub federation_id: FederationId,
+    pub name: String,
+    /// Key whose secret key claims the payments to the address
+    pub pubkey: XOnlyPublicKey,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LnurlPayPayload {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LnurlInvoicePayload {
+    pub name: String,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LnurlPaymentsPayload {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetReservePayload {
+    pub federation_id: FederationId,
+    pub reserve: Amount,
+}
+
+/// Limits the HTLCs the gateway intercepts for a federation to the amounts of
+/// `band`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetHtlcAmountBandPayload {
+    pub federation_id: FederationId,
+    pub band: HtlcAmountBand,
+}
+
+/// Stops routing payments for a federation and announcing the gateway to it,
+/// keeping its state
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PauseFedPayload {
+    pub federation_id: FederationId,
+}
+
+/// Routes payments for a paused federation again
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResumeFedPayload {
+    pub federation_id: FederationId,
+}
+
+/// Restricts the channel an outgoing payment leaves the lightning node over,
+/// e.g. to drain a particular channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirstHopConstraint {
+    /// Short channel id of the channel the payment has to leave over
+    pub outgoing_channel_id: Option<u64>,
+    /// Peer the payment has to be forwarded to first
+    pub first_hop: Option<PublicKey>,
+}
+
+/// Pays the invoice of an outgoing contract on behalf of the operator, who can
+/// pick the channel the payment leaves over unlike the client funding it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManualPayInvoicePayload {
+    pub federation_id: FederationId,
+    pub contract_id: ContractId,
+    #[serde(default)]
+    pub first_hop: FirstHopConstraint,
+}
+
+/// Pays like [`ManualPayInvoicePayload`], sending every step of the payment
+/// to `status`, see [`GatewayRpcSender::pay_invoice_with_status`]
+#[derive(Debug)]
+pub struct PayInvoiceWithStatusPayload {
+    pub payment: ManualPayInvoicePayload,
+    pub status: mpsc::UnboundedSender<OutgoingPaymentStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TestPaymentPayload {
+    pub federation_id: FederationId,
+    pub amount: Amount,
+}
+
+/// A payment to a lightning address, its incoming contract is claimed with the
+/// address' secret key tweaked with `tweak`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LnurlPaymentInfo {
+    pub contract_id: ContractId,
+    pub amount: Amount,
+    pub tweak: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FederationInfo {
     pub federation_id: FederationId,
     pub mint_pubkey: XOnlyPublicKey,
+    /// Short channel id of the HTLCs the gateway intercepts for the federation
+    pub mint_channel_id: u64,
+    /// Ecash kept besides what intercepted HTLCs need, HTLCs that would eat
+    /// into it are rejected
+    pub ecash_reserve: Amount,
+    /// Fees the gateway last announced to the federation
+    pub fees: GatewayFee,
+    /// Amounts of the HTLCs the gateway intercepts for the federation
+    pub htlc_amount_band: HtlcAmountBand,
+    /// Missing in the info of gateways from before the queue existed
+    #[serde(default)]
+    pub htlc_queue: HtlcQueueInfo,
+    /// Whether the operator paused routing payments for the federation,
+    /// missing in the info of gateways from before pausing existed
+    #[serde(default)]
+    pub paused: bool,
+}
+
+/// Intercepted HTLCs of a federation waiting to be processed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HtlcQueueInfo {
+    /// HTLCs waiting right now
+    pub depth: usize,
+    /// Most HTLCs that waited at once since the gateway started
+    pub max_depth: usize,
+    /// HTLCs cancelled because the queue was full
+    pub rejected: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -129,13 +401,31 @@ pub enum GatewayRequest {
     Info(GatewayRequestInner<InfoPayload>),
     ConnectFederation(GatewayRequestInner<ConnectFedPayload>),
     PayInvoice(GatewayRequestInner<PayInvoicePayload>),
+    ManualPayInvoice(GatewayRequestInner<ManualPayInvoicePayload>),
+    PayInvoiceWithStatus(GatewayRequestInner<PayInvoiceWithStatusPayload>),
     Balance(GatewayRequestInner<BalancePayload>),
     DepositAddress(GatewayRequestInner<DepositAddressPayload>),
     Deposit(GatewayRequestInner<DepositPayload>),
     Withdraw(GatewayRequestInner<WithdrawPayload>),
+    SweepToLn(GatewayRequestInner<SweepToLnPayload>),
     Backup(GatewayRequestInner<BackupPayload>),
     Restore(GatewayRequestInner<RestorePayload>),
+    ExportState(GatewayRequestInner<ExportStatePayload>),
+    ImportState(GatewayRequestInner<ImportStatePayload>),
+    ExportAccounting(GatewayRequestInner<ExportAccountingPayload>),
     LightningReconnect(GatewayRequestInner<LightningReconnectPayload>),
+    ChannelsUpdated(GatewayRequestInner<ChannelsUpdatedPayload>),
+    BridgeHtlc(GatewayRequestInner<BridgeHtlcPayload>),
+    SendOnionMessage(GatewayRequestInner<SendOnionMessagePayload>),
+    RegisterLightningAddress(GatewayRequestInner<RegisterLightningAddressPayload>),
+    LnurlPay(GatewayRequestInner<LnurlPayPayload>),
+    LnurlInvoice(GatewayRequestInner<LnurlInvoicePayload>),
+    LnurlPayments(GatewayRequestInner<LnurlPaymentsPayload>),
+    TestPayment(GatewayRequestInner<TestPaymentPayload>),
+    SetReserve(GatewayRequestInner<SetReservePayload>),
+    SetHtlcAmountBand(GatewayRequestInner<SetHtlcAmountBandPayload>),
+    PauseFed(GatewayRequestInner<PauseFedPayload>),
+    ResumeFed(GatewayRequestInner<ResumeFedPayload>),
 }
 
 #[derive(Debug)]
@@ -167,6 +457,16 @@ macro_rules! impl_gateway_request_trait {
 impl_gateway_request_trait!(InfoPayload, GatewayInfo, GatewayRequest::Info);
 impl_gateway_request_trait!(ConnectFedPayload, (), GatewayRequest::ConnectFederation);
 impl_gateway_request_trait!(PayInvoicePayload, (), GatewayRequest::PayInvoice);
+impl_gateway_request_trait!(
+    ManualPayInvoicePayload,
+    (),
+    GatewayRequest::ManualPayInvoice
+);
+impl_gateway_request_trait!(
+    PayInvoiceWithStatusPayload,
+    (),
+    GatewayRequest::PayInvoiceWithStatus
+);
 impl_gateway_request_trait!(BalancePayload, Amount, GatewayRequest::Balance);
 impl_gateway_request_trait!(
     DepositAddressPayload,
@@ -175,19 +475,75 @@ impl_gateway_request_trait!(
 );
 impl_gateway_request_trait!(DepositPayload, TransactionId, GatewayRequest::Deposit);
 impl_gateway_request_trait!(WithdrawPayload, TransactionId, GatewayRequest::Withdraw);
+impl_gateway_request_trait!(
+    SweepToLnPayload,
+    SweepToLnResponse,
+    GatewayRequest::SweepToLn
+);
 impl_gateway_request_trait!(BackupPayload, (), GatewayRequest::Backup);
 impl_gateway_request_trait!(RestorePayload, (), GatewayRequest::Restore);
+impl_gateway_request_trait!(
+    ExportStatePayload,
+    StateArchive,
+    GatewayRequest::ExportState
+);
+impl_gateway_request_trait!(ImportStatePayload, (), GatewayRequest::ImportState);
+impl_gateway_request_trait!(
+    ExportAccountingPayload,
+    AccountingExport,
+    GatewayRequest::ExportAccounting
+);
 impl_gateway_request_trait!(
     LightningReconnectPayload,
     (),
     GatewayRequest::LightningReconnect
 );
+impl_gateway_request_trait!(ChannelsUpdatedPayload, (), GatewayRequest::ChannelsUpdated);
+impl_gateway_request_trait!(BridgeHtlcPayload, FederationId, GatewayRequest::BridgeHtlc);
+impl_gateway_request_trait!(
+    SendOnionMessagePayload,
+    (),
+    GatewayRequest::SendOnionMessage
+);
+impl_gateway_request_trait!(
+    RegisterLightningAddressPayload,
+    (),
+    GatewayRequest::RegisterLightningAddress
+);
+impl_gateway_request_trait!(LnurlPayPayload, LnurlPayResponse, GatewayRequest::LnurlPay);
+impl_gateway_request_trait!(
+    LnurlInvoicePayload,
+    LnurlInvoiceResponse,
+    GatewayRequest::LnurlInvoice
+);
+impl_gateway_request_trait!(
+    LnurlPaymentsPayload,
+    Vec<LnurlPaymentInfo>,
+    GatewayRequest::LnurlPayments
+);
+impl_gateway_request_trait!(
+    TestPaymentPayload,
+    TestPaymentReport,
+    GatewayRequest::TestPayment
+);
+impl_gateway_request_trait!(SetReservePayload, (), GatewayRequest::SetReserve);
+impl_gateway_request_trait!(
+    SetHtlcAmountBandPayload,
+    (),
+    GatewayRequest::SetHtlcAmountBand
+);
+impl_gateway_request_trait!(PauseFedPayload, (), GatewayRequest::PauseFed);
+impl_gateway_request_trait!(ResumeFedPayload, (), GatewayRequest::ResumeFed);
 
 impl<T> GatewayRequestInner<T>
 where
     T: GatewayRequestTrait,
     T::Response: std::fmt::Debug,
 {
+    pub fn request(&self) -> &T {
+        &self.request
+    }
+
     pub async fn handle<
         'gateway,
         F: Fn(&'gateway mut Gateway, T) -> FF,
@@ -203,6 +559,29 @@ where
             tracing::error!("Plugin hung up");
         }
     }
+
+    /// Like [`Self::handle`], but awaits the future returned by `handler` in a
+    /// task of `task_group`, so requests that take long, e.g. because they
+    /// wait for the federation, don't block the gateway
+    pub async fn handle_in_background<FF>(
+        self,
+        task_group: &mut TaskGroup,
+        name: &str,
+        handler: impl FnOnce(T) -> FF,
+    ) where
+        FF: Future<Output = Result<T::Response>> + Send + 'static,
+        T::Response: Send + 'static,
+    {
+        let result = handler(self.request);
+        let sender = self.sender;
+        task_group
+            .spawn(name, move |_| async move {
+                if sender.send(result.await).is_err() {
+                    tracing::error!("Plugin hung up");
+                }
+            })
+            .await;
+    }
 }
 
 pub fn serde_hex_deserialize<'d, T: bitcoin::consensus::Decodable, D: Deserializer<'d>>(
@@ -231,3 +610,139 @@ pub fn serde_hex_serialize<T: bitcoin::consensus::Encodable, S: Serializer>(
         s.serialize_bytes(&bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::secp256k1::constants::GENERATOR_X;
+    use bitcoin::XOnlyPublicKey;
+    use bitcoin_hashes::Hash;
+    use fedimint_core::config::FederationId;
+    use fedimint_core::Amount;
+    use futures::StreamExt;
+    use mint_client::modules::ln::contracts::ContractId;
+    use tokio::sync::mpsc;
+
+    use super::{
+        FederationInfo, GatewayRequest, GatewayRpcSender, HtlcQueueInfo, ManualPayInvoicePayload,
+    };
+    use crate::events::{GatewayEvent, OutgoingPaymentStatus};
+    use crate::GatewayError;
+
+    const STEPS: [OutgoingPaymentStatus; 3] = [
+        OutgoingPaymentStatus::ContractFetched,
+        OutgoingPaymentStatus::ContractValidated,
+        OutgoingPaymentStatus::PaymentInFlight { internal: false },
+    ];
+
+    /// Streams a payment the gateway reports [`STEPS`] of before it returns
+    /// `succeed`
+    async fn stream_payment(succeed: bool) -> (ManualPayInvoicePayload, Vec<GatewayEvent>) {
+        let (sender, mut requests) = mpsc::channel(1);
+        let payload = ManualPayInvoicePayload {
+            federation_id: FederationId::dummy(),
+            contract_id: ContractId::from_inner([1; 32]),
+            first_hop: Default::default(),
+        };
+
+        let gateway = async move {
+            let Some(GatewayRequest::PayInvoiceWithStatus(inner)) = requests.recv().await else {
+                panic!("Expected a payment with status");
+            };
+            for step in STEPS {
+                inner.request.status.send(step).unwrap();
+            }
+            let result = if succeed {
+                Ok(())
+            } else {
+                Err(GatewayError::Other(anyhow::anyhow!("No route")))
+            };
+            inner.sender.send(result).unwrap();
+        };
+        let events = GatewayRpcSender::new(sender)
+            .pay_invoice_with_status(payload.clone())
+            .collect::<Vec<_>>();
+
+        let ((), events) = tokio::join!(gateway, events);
+        (payload, events)
+    }
+
+    fn updates(payload: &ManualPayInvoicePayload) -> Vec<GatewayEvent> {
+        STEPS
+            .into_iter()
+            .map(|status| GatewayEvent::OutgoingPaymentUpdated {
+                federation_id: payload.federation_id.clone(),
+                contract_id: payload.contract_id,
+                status,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn payment_stream_ends_with_success() {
+        let (payload, events) = stream_payment(true).await;
+
+        let mut expected = updates(&payload);
+        expected.push(GatewayEvent::OutgoingPaymentSucceeded {
+            federation_id: payload.federation_id,
+            contract_id: payload.contract_id,
+        });
+        assert_eq!(events, expected);
+    }
+
+    #[tokio::test]
+    async fn payment_stream_ends_with_failure() {
+        let (payload, mut events) = stream_payment(false).await;
+
+        let outcome = events.pop().unwrap();
+        assert_eq!(events, updates(&payload));
+        assert!(matches!(
+            outcome,
+            GatewayEvent::OutgoingPaymentFailed { error, .. } if error.contains("No route")
+        ));
+    }
+
+    #[test]
+    fn federation_info_of_older_gateways_parses() {
+        let info = FederationInfo {
+            federation_id: FederationId::dummy(),
+            mint_pubkey: XOnlyPublicKey::from_slice(&GENERATOR_X).unwrap(),
+            mint_channel_id: 1,
+            ecash_reserve: Amount::from_sats(1_000),
+            fees: Default::default(),
+            htlc_amount_band: Default::default(),
+            htlc_queue: HtlcQueueInfo {
+                depth: 1,
+                max_depth: 2,
+                rejected: 3,
+            },
+            paused: true,
+        };
+        let mut json = serde_json::to_value(info).unwrap();
+        json.as_object_mut().unwrap().remove("htlc_queue");
+        json.as_object_mut().unwrap().remove("paused");
+
+        let info: FederationInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(info.htlc_queue, HtlcQueueInfo::default());
+        assert!(!info.paused);
+    }
+
+    #[tokio::test]
+    async fn payment_stream_fails_without_gateway() {
+        let (sender, requests) = mpsc::channel(1);
+        drop(requests);
+        let payload = ManualPayInvoicePayload {
+            federation_id: FederationId::dummy(),
+            contract_id: ContractId::from_inner([1; 32]),
+            first_hop: Default::default(),
+        };
+
+        let events = GatewayRpcSender::new(sender)
+            .pay_invoice_with_status(payload)
+            .collect::<Vec<_>>()
+            .await;
+        assert!(matches!(
+            events.as_slice(),
+            [GatewayEvent::OutgoingPaymentFailed { .. }]
+        ));
+    }
+}
@@ -6,7 +6,11 @@ use url::Url;
 
 use super::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    LightningReconnectPayload, RestorePayload, WithdrawPayload,
+    ExportAccountingPayload, ExportStatePayload, ImportStatePayload, LightningReconnectPayload,
+    LnurlPaymentsPayload, ManualPayInvoicePayload, PauseFedPayload,
+    RegisterLightningAddressPayload, RestorePayload, ResumeFedPayload, SendOnionMessagePayload,
+    SetHtlcAmountBandPayload, SetReservePayload, SweepToLnPayload, TestPaymentPayload,
+    WithdrawPayload,
 };
 
 pub struct RpcClient {
@@ -65,6 +69,18 @@ impl RpcClient {
         self.call(url, password, payload).await
     }
 
+    pub async fn sweep_to_ln(
+        &self,
+        password: String,
+        payload: SweepToLnPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/sweep-to-ln")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
     pub async fn connect_federation(
         &self,
         password: String,
@@ -95,6 +111,42 @@ impl RpcClient {
         self.call(url, password, payload).await
     }
 
+    pub async fn export_state(
+        &self,
+        password: String,
+        payload: ExportStatePayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/export-state")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn export_accounting(
+        &self,
+        password: String,
+        payload: ExportAccountingPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/export-accounting")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn import_state(
+        &self,
+        password: String,
+        payload: ImportStatePayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/import-state")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
     pub async fn reconnect(
         &self,
         password: String,
@@ -104,6 +156,108 @@ impl RpcClient {
         self.call(url, password, payload).await
     }
 
+    pub async fn register_lightning_address(
+        &self,
+        password: String,
+        payload: RegisterLightningAddressPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/register-lnaddr")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn lightning_address_payments(
+        &self,
+        password: String,
+        payload: LnurlPaymentsPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/lnaddr-payments")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn set_reserve(
+        &self,
+        password: String,
+        payload: SetReservePayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/set-reserve")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn set_htlc_band(
+        &self,
+        password: String,
+        payload: SetHtlcAmountBandPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/set-htlc-band")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn manual_pay_invoice(
+        &self,
+        password: String,
+        payload: ManualPayInvoicePayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/manual-pay-invoice")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn pause_fed(
+        &self,
+        password: String,
+        payload: PauseFedPayload,
+    ) -> Result<Response, Error> {
+        let url = self.base_url.join("/pause-fed").expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn resume_fed(
+        &self,
+        password: String,
+        payload: ResumeFedPayload,
+    ) -> Result<Response, Error> {
+        let url = self.base_url.join("/resume-fed").expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn send_onion_message(
+        &self,
+        password: String,
+        payload: SendOnionMessagePayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/send-onion-message")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn test_payment(
+        &self,
+        password: String,
+        payload: TestPaymentPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/test-payment")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
     async fn call<P>(
         &self,
         url: Url,
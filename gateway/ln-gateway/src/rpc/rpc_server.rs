@@ -1,10 +1,17 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 
+use axum::extract::{ConnectInfo, Path, Query};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::IntoResponse;
-use axum::routing::post;
+use axum::routing::{get, post};
 use axum::{Extension, Json, Router};
 use axum_macros::debug_handler;
+use fedimint_core::Amount;
+use futures::{Stream, StreamExt};
 use mint_client::ln::PayInvoicePayload;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tower_http::auth::RequireAuthorizationLayer;
 use tower_http::cors::CorsLayer;
@@ -12,8 +19,13 @@ use tracing::instrument;
 
 use super::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    GatewayRpcSender, InfoPayload, LightningReconnectPayload, RestorePayload, WithdrawPayload,
+    ExportAccountingPayload, ExportStatePayload, GatewayRpcSender, ImportStatePayload, InfoPayload,
+    LightningReconnectPayload, LnurlInvoicePayload, LnurlPayPayload, LnurlPaymentsPayload,
+    ManualPayInvoicePayload, PauseFedPayload, RegisterLightningAddressPayload, RestorePayload,
+    ResumeFedPayload, SendOnionMessagePayload, SetHtlcAmountBandPayload, SetReservePayload,
+    SweepToLnPayload, TestPaymentPayload, WithdrawPayload,
 };
+use crate::lnurl::{LnurlInvoiceResponse, LnurlPayResponse, LnurlRateLimiter};
 use crate::GatewayError;
 
 pub async fn run_webserver(
@@ -22,7 +34,10 @@ pub async fn run_webserver(
     sender: GatewayRpcSender,
 ) -> axum::response::Result<()> {
     // Public routes on gateway webserver
-    let routes = Router::new().route("/pay_invoice", post(pay_invoice));
+    let routes = Router::new()
+        .route("/pay_invoice", post(pay_invoice))
+        .route("/.well-known/lnurlp/:name", get(lnurl_pay))
+        .route("/lnurlp/:name/callback", get(lnurl_invoice));
 
     // Authenticated, public routes used for gateway administration
     let admin_routes = Router::new()
@@ -31,20 +46,35 @@ pub async fn run_webserver(
         .route("/address", post(address))
         .route("/deposit", post(deposit))
         .route("/withdraw", post(withdraw))
+        .route("/sweep-to-ln", post(sweep_to_ln))
         .route("/connect-fed", post(connect_fed))
         .route("/backup", post(backup))
         .route("/restore", post(restore))
+        .route("/export-state", post(export_state))
+        .route("/import-state", post(import_state))
+        .route("/export-accounting", post(export_accounting))
         .route("/connect-ln", post(connect_ln))
+        .route("/register-lnaddr", post(register_lnaddr))
+        .route("/lnaddr-payments", post(lnurl_payments))
+        .route("/test-payment", post(test_payment))
+        .route("/manual-pay-invoice", post(manual_pay_invoice))
+        .route("/pay-invoice-with-status", post(pay_invoice_with_status))
+        .route("/set-reserve", post(set_reserve))
+        .route("/set-htlc-band", post(set_htlc_band))
+        .route("/pause-fed", post(pause_fed))
+        .route("/resume-fed", post(resume_fed))
+        .route("/send-onion-message", post(send_onion_message))
         .layer(RequireAuthorizationLayer::bearer(&authkey));
 
     let app = Router::new()
         .merge(routes)
         .merge(admin_routes)
         .layer(Extension(sender))
+        .layer(Extension(Arc::new(LnurlRateLimiter::default())))
         .layer(CorsLayer::permissive());
 
     axum::Server::bind(&bind_addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .expect("Failed to start webserver");
 
@@ -106,6 +136,17 @@ async fn withdraw(
     Ok(Json(json!({ "fedimint_txid": txid.to_string() })))
 }
 
+/// Pay out ecash of a gateway federation to a lightning invoice
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn sweep_to_ln(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<SweepToLnPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let response = rpc.send(payload).await?;
+    Ok(Json(json!(response)))
+}
+
 #[instrument(skip_all, err)]
 async fn pay_invoice(
     Extension(rpc): Extension<GatewayRpcSender>,
@@ -145,6 +186,36 @@ async fn restore(
     Ok(())
 }
 
+/// Export the encrypted state of the gateway
+#[instrument(skip_all, err)]
+async fn export_state(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<ExportStatePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let archive = rpc.send(payload).await?;
+    Ok(Json(json!(archive)))
+}
+
+/// Import the state exported by another gateway
+#[instrument(skip_all, err)]
+async fn import_state(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<ImportStatePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Export the payment history of all gateway federations
+#[instrument(skip_all, err)]
+async fn export_accounting(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<ExportAccountingPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let export = rpc.send(payload).await?;
+    Ok(Json(json!(export)))
+}
+
 // Reconnect to the lightning node
 #[instrument(skip_all, err)]
 async fn connect_ln(
@@ -154,3 +225,159 @@ async fn connect_ln(
     rpc.send(payload).await?;
     Ok(())
 }
+
+// Register a lightning address for a user of a connected federation
+#[instrument(skip_all, err)]
+async fn register_lnaddr(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<RegisterLightningAddressPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Route a payment from and back to the gateway through a federation
+#[instrument(skip_all, err)]
+async fn test_payment(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<TestPaymentPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let report = rpc.send(payload).await?;
+    Ok(Json(json!(report)))
+}
+
+/// Set the ecash a gateway federation keeps besides what HTLCs need
+#[instrument(skip_all, err)]
+async fn set_reserve(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<SetReservePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Stop routing payments for a gateway federation
+#[instrument(skip_all, err)]
+async fn pause_fed(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<PauseFedPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Route payments for a paused gateway federation again
+#[instrument(skip_all, err)]
+async fn resume_fed(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<ResumeFedPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Limit the amounts of the HTLCs a gateway federation intercepts
+#[instrument(skip_all, err)]
+async fn set_htlc_band(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<SetHtlcAmountBandPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Pay the invoice of an outgoing contract over the channel picked by the
+/// operator
+#[instrument(skip_all, err)]
+async fn manual_pay_invoice(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<ManualPayInvoicePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Pay the invoice of an outgoing contract like `/manual-pay-invoice`,
+/// streaming every step of the payment as server-sent events that end with its
+/// outcome
+#[instrument(skip_all)]
+async fn pay_invoice_with_status(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<ManualPayInvoicePayload>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let events = rpc
+        .pay_invoice_with_status(payload)
+        .map(|event| Event::default().json_data(event));
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Send an onion message from the lightning node to a peer of it
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn send_onion_message(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<SendOnionMessagePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Amount requested by the payer in the LNURL-pay callback
+#[derive(Debug, Deserialize)]
+struct LnurlCallbackParams {
+    /// Amount in msat
+    amount: u64,
+}
+
+/// LNURL wallets expect errors as a successful response with an error status
+fn lnurl_response<T: Serialize>(result: anyhow::Result<T>) -> impl IntoResponse {
+    match result {
+        Ok(response) => Json(json!(response)),
+        Err(e) => Json(json!({ "status": "ERROR", "reason": e.to_string() })),
+    }
+}
+
+/// First step of paying a lightning address
+#[instrument(skip_all)]
+async fn lnurl_pay(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Extension(limiter): Extension<Arc<LnurlRateLimiter>>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    if let Err(e) = limiter.admit(source.ip(), Instant::now()) {
+        return lnurl_response::<LnurlPayResponse>(Err(e.into()));
+    }
+    lnurl_response(rpc.send(LnurlPayPayload { name }).await)
+}
+
+/// Returns an invoice paying the lightning address
+#[instrument(skip_all)]
+async fn lnurl_invoice(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Extension(limiter): Extension<Arc<LnurlRateLimiter>>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    Path(name): Path<String>,
+    Query(params): Query<LnurlCallbackParams>,
+) -> impl IntoResponse {
+    if let Err(e) = limiter.admit(source.ip(), Instant::now()) {
+        return lnurl_response::<LnurlInvoiceResponse>(Err(e.into()));
+    }
+    let payload = LnurlInvoicePayload {
+        name,
+        amount: Amount::from_msats(params.amount),
+    };
+    lnurl_response(rpc.send(payload).await)
+}
+
+/// Lists the tweaks the owner of a lightning address needs to claim its
+/// payments
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn lnurl_payments(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<LnurlPaymentsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let payments = rpc.send(payload).await?;
+    Ok(Json(json!(payments)))
+}
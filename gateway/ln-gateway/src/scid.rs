@@ -0,0 +1,117 @@
+//! Virtual short channel ids the gateway assigns to the federations it
+//! connects to
+//!
+//! Invoices of a federation route over the channel with its id from the
+//! gateway's node to the payee, which lets the gateway tell which federation an
+//! intercepted HTLC belongs to. The ids are persisted, so every federation
+//! keeps its id across restarts and no two federations share one.
+
+use std::collections::BTreeMap;
+
+use fedimint_core::config::FederationId;
+use serde::{Deserialize, Serialize};
+
+use crate::{GatewayError, Result};
+
+/// LND HTLC interceptor can't handle SCID of 0, so start from 1
+pub const INITIAL_SCID: u64 = 1;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ScidMap {
+    federations: BTreeMap<u64, FederationId>,
+}
+
+impl ScidMap {
+    /// Short channel id assigned to `federation_id`, if any
+    pub fn get(&self, federation_id: &FederationId) -> Option<u64> {
+        self.federations
+            .iter()
+            .find(|(_, id)| *id == federation_id)
+            .map(|(scid, _)| *scid)
+    }
+
+    /// Federation the short channel id `scid` was assigned to, if any
+    pub fn federation(&self, scid: u64) -> Option<&FederationId> {
+        self.federations.get(&scid)
+    }
+
+    /// Records that `federation_id` uses `scid`, fails if another federation
+    /// or id got assigned before
+    pub fn insert(&mut self, scid: u64, federation_id: FederationId) -> Result<()> {
+        if scid < INITIAL_SCID {
+            return Err(GatewayError::other(format!(
+                "Short channel id {scid} can't be intercepted"
+            )));
+        }
+        if let Some(existing) = self.federations.get(&scid) {
+            if *existing != federation_id {
+                return Err(GatewayError::other(format!(
+                    "Short channel id {scid} is already assigned to federation {existing:?}"
+                )));
+            }
+            return Ok(());
+        }
+        if let Some(existing) = self.get(&federation_id) {
+            return Err(GatewayError::other(format!(
+                "Federation {federation_id:?} already has short channel id {existing}"
+            )));
+        }
+
+        self.federations.insert(scid, federation_id);
+        Ok(())
+    }
+
+    /// Returns the short channel id of `federation_id`, assigning it one that
+    /// was never used before if it has none yet
+    pub fn allocate(&mut self, federation_id: FederationId) -> u64 {
+        if let Some(scid) = self.get(&federation_id) {
+            return scid;
+        }
+
+        let scid = self
+            .federations
+            .keys()
+            .next_back()
+            .map_or(INITIAL_SCID, |last| last + 1);
+        self.federations.insert(scid, federation_id);
+        scid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::config::FederationId;
+
+    use super::{ScidMap, INITIAL_SCID};
+
+    fn federation_id() -> FederationId {
+        FederationId(threshold_crypto::SecretKey::random().public_key())
+    }
+
+    #[test]
+    fn allocates_unique_scids() {
+        let (fed_a, fed_b, fed_c) = (federation_id(), federation_id(), federation_id());
+        let mut scids = ScidMap::default();
+
+        assert_eq!(scids.allocate(fed_a.clone()), INITIAL_SCID);
+        scids.insert(5, fed_b.clone()).unwrap();
+        assert_eq!(scids.allocate(fed_c.clone()), 6);
+
+        // allocating again keeps the assigned id
+        assert_eq!(scids.allocate(fed_a.clone()), INITIAL_SCID);
+        assert_eq!(scids.federation(5), Some(&fed_b));
+        assert_eq!(scids.get(&fed_c), Some(6));
+    }
+
+    #[test]
+    fn rejects_conflicting_scids() {
+        let (fed_a, fed_b) = (federation_id(), federation_id());
+        let mut scids = ScidMap::default();
+        scids.insert(3, fed_a.clone()).unwrap();
+
+        assert!(scids.insert(3, fed_a.clone()).is_ok());
+        assert!(scids.insert(3, fed_b.clone()).is_err());
+        assert!(scids.insert(4, fed_a).is_err());
+        assert!(scids.insert(0, fed_b).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! Sweeping the ecash of a federation to a lightning invoice of the operator
+//!
+//! The gateway can't route the payment itself, since claiming its own
+//! outgoing contract would only return the ecash to it, so another gateway
+//! registered with the federation pays the invoice. Only funding the contract
+//! happens while handling the request, the payment itself runs in the
+//! background and ends with [`GatewayEvent::SweepToLnSucceeded`] or
+//! [`GatewayEvent::SweepToLnFailed`] for the funded contract.
+//!
+//! [`GatewayEvent::SweepToLnSucceeded`]: crate::events::GatewayEvent::SweepToLnSucceeded
+//! [`GatewayEvent::SweepToLnFailed`]: crate::events::GatewayEvent::SweepToLnFailed
+
+use std::time::SystemTime;
+
+use bitcoin::secp256k1::PublicKey;
+use fedimint_core::Amount;
+use mint_client::modules::ln::LightningGateway;
+
+/// Picks a gateway other than the one of `own_node` that is still registered
+/// at `now` and can pay `amount`
+pub fn select_sweep_gateway(
+    gateways: impl IntoIterator<Item = LightningGateway>,
+    own_node: &PublicKey,
+    amount: Amount,
+    now: SystemTime,
+) -> Option<LightningGateway> {
+    gateways.into_iter().find(|gateway| {
+        gateway.node_pub_key != *own_node && gateway.valid_until > now && gateway.can_pay(amount)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use fedimint_core::Amount;
+    use mint_client::modules::ln::{GatewayFee, GatewayLiquidity, LightningGateway};
+
+    use super::select_sweep_gateway;
+
+    fn node_key(seed: u8) -> PublicKey {
+        PublicKey::from_secret_key(
+            &Secp256k1::new(),
+            &SecretKey::from_slice(&[seed; 32]).unwrap(),
+        )
+    }
+
+    fn gateway(seed: u8, valid_until: SystemTime, max_payable: Amount) -> LightningGateway {
+        LightningGateway {
+            mint_channel_id: seed.into(),
+            mint_pub_key: node_key(seed).x_only_public_key().0,
+            node_pub_key: node_key(seed),
+            api: "http://example.com".parse().unwrap(),
+            route_hints: vec![],
+            fees: GatewayFee::default(),
+            liquidity: Some(GatewayLiquidity {
+                max_receivable: Amount::ZERO,
+                max_payable,
+            }),
+            valid_until,
+        }
+    }
+
+    #[test]
+    fn sweep_skips_own_expired_and_illiquid_gateways() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let later = now + Duration::from_secs(60);
+        let amount = Amount::from_sats(1_000);
+
+        let gateways = vec![
+            gateway(1, later, amount),
+            gateway(2, now, amount),
+            gateway(3, later, Amount::from_sats(999)),
+            gateway(4, later, amount),
+        ];
+
+        let selected = select_sweep_gateway(gateways.clone(), &node_key(1), amount, now);
+        assert_eq!(selected, Some(gateways[3].clone()));
+
+        let selected = select_sweep_gateway(gateways[..3].to_vec(), &node_key(1), amount, now);
+        assert_eq!(selected, None);
+    }
+}
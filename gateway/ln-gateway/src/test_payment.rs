@@ -0,0 +1,98 @@
+//! Test payments let operators check a new deployment end to end
+//!
+//! The gateway pays invoices of its own offers in the federation over its
+//! lightning node. The HTLCs leave over a channel to a peer and come back to
+//! be intercepted like any other payment to the federation, so a test payment
+//! needs at least one route hint. Only the gateway's own ecash and liquidity
+//! are used, minus the routing fees of the peer.
+
+use std::fmt::Display;
+use std::time::Instant;
+
+use fedimint_core::Amount;
+use futures::Future;
+use serde::{Deserialize, Serialize};
+
+/// Most time a node may spend routing a test payment, in blocks
+pub const TEST_PAYMENT_MAX_DELAY: u64 = 144;
+/// Most fees a node may pay routing a test payment, in percent of its amount
+pub const TEST_PAYMENT_MAX_FEE_PERCENT: f64 = 1.0;
+
+/// Timing and outcome of one step of a test payment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestPaymentStep {
+    pub name: String,
+    pub duration_ms: u64,
+    /// Why the step failed, the steps after a failed one are skipped
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestPaymentReport {
+    pub amount: Amount,
+    pub success: bool,
+    pub steps: Vec<TestPaymentStep>,
+}
+
+impl TestPaymentReport {
+    pub fn new(amount: Amount) -> Self {
+        Self {
+            amount,
+            success: true,
+            steps: vec![],
+        }
+    }
+
+    /// Runs the step `name` and records how long it took, returns `None` if
+    /// it failed
+    pub async fn step<T, E, F>(&mut self, name: &str, step: F) -> Option<T>
+    where
+        E: Display,
+        F: Future<Output = Result<T, E>>,
+    {
+        let start = Instant::now();
+        let result = step.await;
+        let duration = start.elapsed();
+
+        let (value, error) = match result {
+            Ok(value) => (Some(value), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        self.success &= error.is_none();
+        self.steps.push(TestPaymentStep {
+            name: name.to_string(),
+            duration_ms: duration.as_millis() as u64,
+            error,
+        });
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use super::TestPaymentReport;
+
+    #[tokio::test]
+    async fn report_records_failed_steps() {
+        let mut report = TestPaymentReport::new(Amount::from_sats(1));
+
+        assert_eq!(
+            report.step("ok", async { Ok::<_, String>(1) }).await,
+            Some(1)
+        );
+        assert!(report.success);
+        assert_eq!(
+            report
+                .step("fail", async { Err::<(), _>("no route".to_string()) })
+                .await,
+            None
+        );
+
+        assert!(!report.success);
+        assert_eq!(report.steps.len(), 2);
+        assert_eq!(report.steps[0].error, None);
+        assert_eq!(report.steps[1].error.as_deref(), Some("no route"));
+    }
+}
@@ -1,6 +1,7 @@
 use std::future::Future;
 use std::result::Result;
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use tokio::time::sleep;
 use tracing::info;
@@ -44,14 +45,56 @@ where
     }
 }
 
+/// Tells whether an operation that only needs to run once per `interval` is
+/// due, e.g. to reuse the results of requests to the federation in between
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    last_run: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_run: Mutex::new(None),
+        }
+    }
+
+    /// Whether the operation is due at `now`, if so the caller is expected to
+    /// run it and it isn't due again until `interval` passed
+    pub fn is_due(&self, now: Instant) -> bool {
+        let mut last_run = self.last_run.lock().expect("locking can't fail");
+        let due = last_run.map_or(true, |last_run| {
+            now.duration_since(last_run) >= self.interval
+        });
+        if due {
+            *last_run = Some(now);
+        }
+        due
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::atomic::{AtomicU8, Ordering};
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     use anyhow::anyhow;
 
-    use super::retry;
+    use super::{retry, Throttle};
+
+    #[test]
+    fn throttle_is_due_once_per_interval() {
+        let throttle = Throttle::new(Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(throttle.is_due(now));
+        assert!(!throttle.is_due(now));
+        assert!(!throttle.is_due(now + Duration::from_secs(9)));
+        assert!(throttle.is_due(now + Duration::from_secs(10)));
+        assert!(!throttle.is_due(now + Duration::from_secs(11)));
+    }
 
     #[tokio::test]
     async fn retry_succeed_with_one_attempt() {
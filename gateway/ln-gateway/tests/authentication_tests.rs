@@ -119,7 +119,7 @@ async fn gatewayd_api_authentication() -> anyhow::Result<()> {
             // * `withdraw` with incorrect password fails
             let payload = WithdrawPayload {
                 federation_id,
-                amount: bitcoin::Amount::from_sat(100),
+                amount: Some(bitcoin::Amount::from_sat(100)),
                 address: bitcoin.get_new_address().await,
             };
             test_auth(&gw_password, |pw| client_ref.withdraw(pw, payload.clone()))
@@ -11,6 +11,8 @@ use fedimint_core::core::LEGACY_HARDCODED_INSTANCE_ID_LN;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::PeerId;
 use ln_gateway::client::{DynDbFactory, IGatewayClientBuilder};
+use ln_gateway::migration::import_database;
+use ln_gateway::scid::ScidMap;
 use ln_gateway::GatewayError;
 use mint_client::{module_decode_stubs, Client, GatewayClient, GatewayClientConfig};
 use secp256k1::{PublicKey, Secp256k1};
@@ -63,7 +65,39 @@ impl IGatewayClientBuilder for TestGatewayClientBuilder {
             api,
             Default::default(),
         )
-        .await)
+        .await?)
+    }
+
+    async fn import(
+        &self,
+        config: GatewayClientConfig,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+        decoders: ModuleDecoderRegistry,
+        _module_gens: ClientModuleGenRegistry,
+    ) -> Result<Client<GatewayClientConfig>, GatewayError> {
+        let federation_id = config.client_config.federation_id.clone();
+        let members = BTreeSet::from([PeerId::from(0)]);
+
+        let api: DynFederationApi =
+            MockApi::make_test_fed(LEGACY_HARDCODED_INSTANCE_ID_LN, members)
+                .await
+                .into();
+        let db = self.db_factory.create_database(
+            federation_id,
+            PathBuf::new(),
+            module_decode_stubs(),
+        )?;
+        import_database(&db, entries).await?;
+
+        Ok(GatewayClient::new_with_api(
+            config,
+            decoders,
+            Default::default(),
+            db,
+            api,
+            Default::default(),
+        )
+        .await?)
     }
 
     async fn create_config(
@@ -81,9 +115,11 @@ impl IGatewayClientBuilder for TestGatewayClientBuilder {
         let client_config = ClientConfig {
             federation_id: FederationId(auth_pk),
             epoch_pk: threshold_crypto::SecretKey::random().public_key(),
+            api_pks: [].into(),
             api_endpoints: [].into(),
             modules: [].into(),
             meta: Default::default(),
+            limits: Default::default(),
         };
 
         let mut rng = rand::rngs::OsRng;
@@ -109,4 +145,14 @@ impl IGatewayClientBuilder for TestGatewayClientBuilder {
         // noop: return empty config list
         Ok([].into())
     }
+
+    fn save_scids(&self, _scids: &ScidMap) -> Result<(), GatewayError> {
+        // noop: don't save short channel ids
+        Ok(())
+    }
+
+    fn load_scids(&self) -> Result<ScidMap, GatewayError> {
+        // noop: no short channel ids were assigned before
+        Ok(ScidMap::default())
+    }
 }
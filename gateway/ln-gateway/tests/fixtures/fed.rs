@@ -2,6 +2,7 @@ use std::collections::BTreeSet;
 use std::sync::Arc;
 
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::pagination::PageRequest;
 use fedimint_core::PeerId;
 use fedimint_ln_client::LightningGateway;
 use mint_client::api::fake::FederationApiFaker;
@@ -37,5 +38,12 @@ impl MockApi {
                         .collect::<Vec<LightningGateway>>())
                 },
             )
+            .with(
+                format!("/module/{module_id}/list_gateways_page"),
+                |mint: Arc<Mutex<MockApi>>, request: PageRequest<secp256k1::PublicKey>| async move {
+                    let gateway = mint.lock().await.gateway.clone();
+                    Ok(request.page(gateway.map(|gateway| (gateway.node_pub_key, gateway))))
+                },
+            )
     }
 }
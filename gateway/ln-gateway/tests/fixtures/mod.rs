@@ -8,14 +8,16 @@ use fedimint_ln_client::LightningClientGen;
 use fedimint_mint_client::MintClientGen;
 use fedimint_testing::btc::fixtures::FakeBitcoinTest;
 use fedimint_testing::btc::BitcoinTest;
-use fedimint_testing::ln::fixtures::FakeLightningTest;
+use fedimint_testing::ln::mock::{MockLightningController, MockLightningNode};
 use fedimint_testing::ln::LightningTest;
 use futures::Future;
 use ln_gateway::client::{DynGatewayClientBuilder, MemDbFactory};
+use ln_gateway::fees::FeeOracleConfig;
+use ln_gateway::htlc_policy::PassThroughPolicy;
 use ln_gateway::lnrpc_client::ILnRpcClient;
+use ln_gateway::route_hints::RouteHintConfig;
 use ln_gateway::rpc::rpc_client::RpcClient;
 use ln_gateway::Gateway;
-use mint_client::module_decode_stubs;
 use mint_client::modules::wallet::WalletClientGen;
 use url::Url;
 
@@ -26,19 +28,22 @@ pub struct Fixtures {
     pub task_group: TaskGroup,
     pub bitcoin: Box<dyn BitcoinTest>,
     pub lightning: Box<dyn LightningTest>,
+    /// Scripts the lightning node of the gateway, which is also `lightning`
+    pub lightning_controller: MockLightningController,
     pub gateway: Gateway,
     pub rpc: RpcClient,
 }
 
 pub async fn fixtures(api_addr: Url) -> Result<Fixtures> {
     // Create a lightning rpc client
-    let lnrpc: Arc<RwLock<dyn ILnRpcClient>> = Arc::new(RwLock::new(FakeLightningTest::new()));
+    let node = MockLightningNode::new();
+    let lightning_controller = node.controller();
+    let lnrpc: Arc<RwLock<dyn ILnRpcClient>> = Arc::new(RwLock::new(node.clone()));
 
     // Create federation client builder
     let client_builder: DynGatewayClientBuilder =
         client::TestGatewayClientBuilder::new(MemDbFactory.into(), api_addr.clone()).into();
 
-    let decoders = module_decode_stubs();
     let module_gens = ClientModuleGenRegistry::from(vec![
         DynClientModuleGen::from(WalletClientGen),
         DynClientModuleGen::from(MintClientGen),
@@ -51,21 +56,26 @@ pub async fn fixtures(api_addr: Url) -> Result<Fixtures> {
     let gateway = Gateway::new(
         lnrpc,
         client_builder,
-        decoders,
         module_gens,
         task_group.clone(),
+        RouteHintConfig::default(),
+        None,
+        FeeOracleConfig::default().build(),
+        PassThroughPolicy.into(),
+        false,
     )
     .await
     .unwrap();
 
     let rpc = RpcClient::new(api_addr);
     let bitcoin = Box::new(FakeBitcoinTest::new());
-    let lightning = Box::new(FakeLightningTest::new());
+    let lightning = Box::new(node);
 
     Ok(Fixtures {
         task_group,
         bitcoin,
         lightning,
+        lightning_controller,
         gateway,
         rpc,
     })
@@ -88,6 +98,7 @@ where
         lightning,
         gateway,
         rpc,
+        ..
     } = fixtures(api_addr).await?;
 
     if listen.is_some() && password.is_some() {
@@ -96,7 +107,7 @@ where
 
         task_group
             .spawn("Run Gateway", move |_| async move {
-                if gateway.run(listen, password).await.is_err() {}
+                if gateway.run(listen, None, password).await.is_err() {}
             })
             .await;
 
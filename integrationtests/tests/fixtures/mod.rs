@@ -15,6 +15,7 @@ use cln_rpc::ClnRpc;
 use fedimint_bitcoind::bitcoincore_rpc::{make_bitcoind_rpc, make_electrum_rpc, make_esplora_rpc};
 use fedimint_bitcoind::DynBitcoindRpc;
 use fedimint_client::module::gen::{ClientModuleGenRegistry, DynClientModuleGen};
+use fedimint_core::admin_client::ParamsChangeStatus;
 use fedimint_core::api::WsFederationApi;
 use fedimint_core::bitcoin_rpc::read_bitcoin_backend_from_global_env;
 use fedimint_core::cancellable::Cancellable;
@@ -25,13 +26,14 @@ use fedimint_core::core::{
 };
 use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::db::Database;
+use fedimint_core::epoch::{ConsensusParams, ConsensusVersionActivation, ParamsChangeProposal};
 use fedimint_core::module::registry::{ModuleDecoderRegistry, ModuleRegistry};
 use fedimint_core::module::DynServerModuleGen;
-use fedimint_core::outcome::TransactionStatus;
+use fedimint_core::outcome::{TransactionStatus, TransactionValidation};
 use fedimint_core::server::DynServerModule;
 use fedimint_core::task::{timeout, RwLock, TaskGroup};
 use fedimint_core::{core, sats, Amount, OutPoint, PeerId, TieredMulti, TransactionId};
-use fedimint_ln_client::{LightningClientGen, LightningGateway};
+use fedimint_ln_client::{GatewayFee, LightningClientGen, LightningGateway};
 use fedimint_ln_server::LightningGen;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_client::MintClientGen;
@@ -42,14 +44,14 @@ use fedimint_server::config::{ServerConfig, ServerConfigParams};
 use fedimint_server::consensus::{
     ConsensusProposal, FedimintConsensus, HbbftConsensusOutcome, TransactionSubmissionError,
 };
-use fedimint_server::db::GLOBAL_DATABASE_VERSION;
+use fedimint_server::db::{EpochHistoryKey, GLOBAL_DATABASE_VERSION};
 use fedimint_server::net::connect::mock::{MockNetwork, StreamReliability};
 use fedimint_server::net::connect::{Connector, TlsTcpConnector};
 use fedimint_server::net::peers::{DelayCalculator, PeerConnector};
 use fedimint_server::{consensus, EpochMessage, FedimintServer};
 use fedimint_testing::btc::fixtures::FakeBitcoinTest;
 use fedimint_testing::btc::BitcoinTest;
-use fedimint_testing::ln::fixtures::FakeLightningTest;
+use fedimint_testing::ln::mock::{MockLightningController, MockLightningNode};
 use fedimint_testing::ln::LightningTest;
 use fedimint_wallet_client::WalletClientGen;
 use fedimint_wallet_server::common::config::WalletConfig;
@@ -63,8 +65,11 @@ use hbbft::honey_badger::Batch;
 use itertools::Itertools;
 use ln_gateway::actor::GatewayActor;
 use ln_gateway::client::{DynGatewayClientBuilder, MemDbFactory, StandardGatewayClientBuilder};
+use ln_gateway::fees::FeeOracleConfig;
+use ln_gateway::htlc_policy::PassThroughPolicy;
 use ln_gateway::lnd::GatewayLndClient;
 use ln_gateway::lnrpc_client::{ILnRpcClient, NetworkLnRpcClient};
+use ln_gateway::route_hints::RouteHintConfig;
 use ln_gateway::Gateway;
 use mint_client::mint::SpendableNote;
 use mint_client::transaction::legacy::Transaction;
@@ -361,6 +366,7 @@ pub async fn fixtures(num_peers: u16, gateway_node: GatewayNode) -> anyhow::Resu
                 client_module_inits.clone(),
                 lightning.gateway_node_pub_key,
                 base_port + (2 * num_peers) + 1,
+                None,
             )
             .await;
 
@@ -388,7 +394,7 @@ pub async fn fixtures(num_peers: u16, gateway_node: GatewayNode) -> anyhow::Resu
             let bitcoin_rpc = || bitcoin.clone().into();
             let bitcoin_rpc_2: DynBitcoindRpc = bitcoin.clone().into();
 
-            let lightning = FakeLightningTest::new();
+            let lightning = MockLightningNode::new();
             let ln_arc = Arc::new(RwLock::new(lightning.clone()));
             let lnrpc_adapter = LnRpcAdapter::new(ln_arc.clone());
 
@@ -455,8 +461,9 @@ pub async fn fixtures(num_peers: u16, gateway_node: GatewayNode) -> anyhow::Resu
                 client_config.clone(),
                 decoders,
                 client_module_inits,
-                lightning.gateway_node_pub_key,
+                lightning.node_pub_key,
                 base_port + (2 * num_peers) + 1,
+                Some(lightning.controller()),
             )
             .await;
 
@@ -509,7 +516,9 @@ pub async fn create_user_client(
     )
     .into();
 
-    UserClient::new_with_api(config, decoders, module_gens, db, api, Default::default()).await
+    UserClient::new_with_api(config, decoders, module_gens, db, api, Default::default())
+        .await
+        .expect("Failed to create the user client")
 }
 
 async fn distributed_config(
@@ -561,11 +570,15 @@ async fn sqlite(dir: String, db_name: String) -> fedimint_sqlite::SqliteDb {
 }
 
 pub struct GatewayTest {
+    /// Not running, only used to load actors
+    pub gateway: Arc<Gateway>,
     pub actor: Arc<RwLock<GatewayActor>>,
     pub adapter: Arc<RwLock<LnRpcAdapter>>,
     pub keys: LightningGateway,
     pub user: UserTest<GatewayClientConfig>,
     pub client: Arc<GatewayClient>,
+    /// Scripts the gateway's lightning node if it's mocked
+    pub lightning_controller: Option<MockLightningController>,
 }
 
 impl GatewayTest {
@@ -576,6 +589,7 @@ impl GatewayTest {
         module_gens: ClientModuleGenRegistry,
         node_pub_key: secp256k1::PublicKey,
         bind_port: u16,
+        lightning_controller: Option<MockLightningController>,
     ) -> Self {
         let mut rng = OsRng;
         let ctx = bitcoin::secp256k1::Secp256k1::new();
@@ -590,6 +604,8 @@ impl GatewayTest {
             api: Url::parse("http://example.com")
                 .expect("Could not parse URL to generate GatewayClientConfig API endpoint"),
             route_hints: vec![],
+            fees: GatewayFee::default(),
+            liquidity: None,
             valid_until: fedimint_core::time::now(),
         };
 
@@ -617,9 +633,13 @@ impl GatewayTest {
         let gateway = Gateway::new(
             Arc::new(RwLock::new(adapter.clone())),
             client_builder.clone(),
-            decoders.clone(),
             module_gens.clone(),
             TaskGroup::new(),
+            RouteHintConfig::default(),
+            None,
+            FeeOracleConfig::default().build(),
+            PassThroughPolicy.into(),
+            false,
         )
         .await
         .unwrap();
@@ -641,11 +661,13 @@ impl GatewayTest {
         let user = UserTest::new(client.clone());
 
         GatewayTest {
+            gateway: Arc::new(gateway),
             actor,
             adapter: Arc::new(RwLock::new(adapter)),
             keys,
             user,
             client,
+            lightning_controller,
         }
     }
 }
@@ -691,6 +713,7 @@ impl<T: AsRef<ClientConfig> + Clone + Send> UserTest<T> {
                 &secp(),
             )
             .await
+            .expect("notes to be unspent")
     }
 
     /// Helper for readability
@@ -705,7 +728,7 @@ impl<T: AsRef<ClientConfig> + Clone + Send> UserTest<T> {
     pub async fn peg_out(&self, amount: u64, address: &Address) -> (PegOutFees, OutPoint) {
         let peg_out = self
             .client
-            .new_peg_out_with_fees(bitcoin::Amount::from_sat(amount), address.clone())
+            .new_peg_out_with_fees(bitcoin::Amount::from_sat(amount), address.clone(), None)
             .await
             .unwrap();
         let out_point = self.client.peg_out(peg_out.clone(), rng()).await.unwrap();
@@ -743,6 +766,7 @@ pub struct FederationTest {
     pub wallet: WalletConfig,
     pub cfg: ServerConfig,
     decoders: ModuleDecoderRegistry,
+    module_inits: ServerModuleGenRegistry,
     pub mint_id: ModuleInstanceId,
     pub ln_id: ModuleInstanceId,
     pub wallet_id: ModuleInstanceId,
@@ -823,6 +847,39 @@ impl FederationTest {
         Ok(())
     }
 
+    /// Validate a fedimint transaction against all federation servers without
+    /// submitting it
+    #[allow(clippy::await_holding_refcell_ref)] // TODO: fix, it's just a test
+    pub async fn validate_transaction(
+        &self,
+        transaction: &fedimint_server::transaction::Transaction,
+    ) -> Vec<TransactionValidation> {
+        let mut result = Vec::new();
+        for server in &self.servers {
+            let validation = server
+                .lock()
+                .await
+                .fedimint
+                .consensus
+                .validate_transaction(transaction)
+                .await;
+            result.push(validation);
+        }
+        result
+    }
+
+    /// Returns the raw entries in the databases of all federation servers, to
+    /// check what an operation wrote
+    pub async fn database_entries(&self) -> Vec<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut result = Vec::new();
+        for server in &self.servers {
+            let svr = server.lock().await;
+            let mut dbtx = svr.database.begin_transaction().await;
+            result.push(dbtx.raw_find_by_prefix(&[]).await);
+        }
+        result
+    }
+
     /// Get fedimint transaction status from all federation servers
     #[allow(clippy::await_holding_refcell_ref)] // TODO: fix, it's just a test
     pub async fn transaction_status(&self, txid: TransactionId) -> Vec<Option<TransactionStatus>> {
@@ -986,6 +1043,110 @@ impl FederationTest {
         true
     }
 
+    /// Returns true if all fed members noticed that the state of this peer
+    /// diverged from theirs
+    pub async fn has_diverged_peer(&self, peer: u16) -> bool {
+        for server in &self.servers {
+            let status = server
+                .lock()
+                .await
+                .fedimint
+                .consensus
+                .state_hash_status()
+                .await;
+            if !status.divergences.contains_key(&PeerId::from(peer)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Votes for activating a consensus version on all fed members
+    pub async fn schedule_consensus_version(&self, activation: ConsensusVersionActivation) {
+        for server in &self.servers {
+            let consensus = server.lock().await.fedimint.consensus.clone();
+            consensus
+                .schedule_consensus_version(activation.clone())
+                .await
+                .expect("server is running");
+        }
+    }
+
+    /// Schedules consensus `version` on all fed members and runs the epochs
+    /// until it's active
+    pub async fn activate_consensus_version(&self, version: u32) {
+        let epoch = self.epoch_counts().await[0] + 2;
+        self.schedule_consensus_version(ConsensusVersionActivation { version, epoch })
+            .await;
+        self.run_consensus_epochs(1).await;
+        self.run_empty_epochs(2).await;
+    }
+
+    /// Votes for new consensus params on all fed members
+    pub async fn propose_consensus_params(&self, params: ConsensusParams) {
+        for server in &self.servers {
+            let consensus = server.lock().await.fedimint.consensus.clone();
+            consensus
+                .propose_consensus_params(params.clone())
+                .await
+                .expect("server is running");
+        }
+    }
+
+    /// Checks a params change and votes for it on all fed members
+    pub async fn propose_params_change(
+        &self,
+        proposal: ParamsChangeProposal,
+    ) -> anyhow::Result<()> {
+        for server in &self.servers {
+            let consensus = server.lock().await.fedimint.consensus.clone();
+            consensus.check_params_change(&proposal).await?;
+            consensus
+                .propose_params_change(proposal.clone())
+                .await
+                .expect("server is running");
+        }
+        Ok(())
+    }
+
+    /// Returns the active, scheduled and proposed params changes of the fed
+    /// members
+    pub async fn params_changes(&self) -> Vec<ParamsChangeStatus> {
+        let mut changes = vec![];
+        for server in &self.servers {
+            let consensus = server.lock().await.fedimint.consensus.clone();
+            changes.push(consensus.params_changes().await);
+        }
+        changes
+    }
+
+    /// Returns the activation epochs of `version` scheduled by the fed members
+    pub async fn scheduled_consensus_version(&self, version: u32) -> Vec<Option<u64>> {
+        let mut scheduled = vec![];
+        for server in &self.servers {
+            let consensus = server.lock().await.fedimint.consensus.clone();
+            scheduled.push(
+                consensus
+                    .consensus_versions()
+                    .await
+                    .scheduled
+                    .get(&version)
+                    .copied(),
+            );
+        }
+        scheduled
+    }
+
+    /// Returns the number of epochs processed by each fed member
+    pub async fn epoch_counts(&self) -> Vec<u64> {
+        let mut counts = vec![];
+        for server in &self.servers {
+            let consensus = server.lock().await.fedimint.consensus.clone();
+            counts.push(consensus.get_epoch_count().await);
+        }
+        counts
+    }
+
     /// Inserts notes directly into the databases of federation nodes
     pub async fn database_add_notes_for_user<C: AsRef<ClientConfig> + Clone + Send>(
         &self,
@@ -1040,6 +1201,30 @@ impl FederationTest {
         out_point
     }
 
+    /// Inserts a transaction directly into the databases of federation nodes,
+    /// so their state diverges from the other nodes
+    pub async fn database_add_unknown_transaction(&self) {
+        let bytes: [u8; 32] = rand::random();
+        let txid = fedimint_core::TransactionId::from_inner(bytes);
+        for server in &self.servers {
+            let svr = server.lock().await;
+            let mut dbtx = svr.database.begin_transaction().await;
+            dbtx.insert_entry(
+                &fedimint_server::db::AcceptedTransactionKey(txid),
+                &fedimint_server::consensus::AcceptedTransaction {
+                    epoch: 0,
+                    transaction: fedimint_server::transaction::Transaction {
+                        inputs: vec![],
+                        outputs: vec![],
+                        signature: None,
+                    },
+                },
+            )
+            .await;
+            dbtx.commit_tx().await;
+        }
+    }
+
     /// Has every federation node broadcast any transactions pending to the
     /// Bitcoin network, otherwise transactions will only get broadcast
     /// every 10 seconds.
@@ -1287,6 +1472,111 @@ impl FederationTest {
         }
     }
 
+    /// Replays the epoch history of the first server on an empty database like
+    /// `fedimintd replay` does, returns the last epoch replayed
+    pub async fn replay_epoch_history(&self) -> anyhow::Result<Option<u64>> {
+        let server = self.servers[0].lock().await;
+        let cfg = server.fedimint.cfg.clone();
+        let db = Database::new(MemDatabase::new(), self.decoders.clone());
+        let mut task_group = TaskGroup::new();
+
+        // the wallet has to ask the same bitcoind for the blocks the federation
+        // agreed on
+        let wallet = Wallet::new_with_bitcoind(
+            cfg.get_module_config_typed(self.wallet_id)?,
+            db.clone(),
+            server.bitcoin_rpc.clone(),
+            &mut task_group,
+        )
+        .await?;
+        let override_modules = BTreeMap::from([("wallet", wallet.into())]);
+        let modules = Self::init_modules(
+            &cfg,
+            &db,
+            &self.module_inits,
+            override_modules,
+            &mut task_group,
+        )
+        .await;
+        let (consensus, _tx_receiver) =
+            FedimintConsensus::new_with_modules(cfg, db, self.module_inits.clone(), modules);
+
+        let replayed =
+            fedimint_server::replay::replay_epoch_history(&consensus, &server.database, None).await;
+        task_group.shutdown().await;
+        replayed
+    }
+
+    /// Replaces the hash of an epoch in the history of the first server, so
+    /// replaying it diverges from the recorded history
+    pub async fn forge_epoch_hash(&self, epoch: u64) {
+        let server = self.servers[0].lock().await;
+        let mut dbtx = server.database.begin_transaction().await;
+        let mut outcome = dbtx
+            .get_value(&EpochHistoryKey(epoch))
+            .await
+            .expect("Epoch is in the history");
+        outcome.hash = sha256::Hash::hash(b"forged epoch");
+        dbtx.insert_entry(&EpochHistoryKey(epoch), &outcome).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Runs the global migrations and initializes the modules of `cfg` that
+    /// are not in `override_modules`
+    async fn init_modules(
+        cfg: &ServerConfig,
+        db: &Database,
+        module_inits: &ServerModuleGenRegistry,
+        mut override_modules: BTreeMap<&'static str, DynServerModule>,
+        task_group: &mut TaskGroup,
+    ) -> ModuleRegistry<DynServerModule> {
+        let mut modules = BTreeMap::new();
+        let env_vars = FedimintConsensus::get_env_vars_map();
+
+        fedimint_core::db::apply_migrations(
+            db,
+            "Global".to_string(),
+            GLOBAL_DATABASE_VERSION,
+            fedimint_server::db::get_global_database_migrations(),
+        )
+        .await
+        .unwrap_or_else(|_| panic!("Error while applying global database migrations"));
+
+        for (kind, gen) in module_inits.legacy_init_order_iter() {
+            let id = cfg.get_module_id_by_kind(kind.clone()).unwrap();
+            if let Some(module) = override_modules.remove(kind.as_str()) {
+                info!(module_instance_id = id, kind = %kind, "Use overridden module");
+                modules.insert(id, module);
+            } else {
+                info!(module_instance_id = id, kind = %kind, "Init module");
+
+                let isolated_db = db.new_isolated(id);
+                fedimint_core::db::apply_migrations(
+                    &isolated_db,
+                    kind.to_string(),
+                    gen.database_version(),
+                    gen.get_database_migrations(),
+                )
+                .await
+                .unwrap_or_else(|_| {
+                    panic!("Error while applying database migrations for module {kind}")
+                });
+
+                let module = gen
+                    .init(
+                        cfg.get_module_config(id).unwrap(),
+                        isolated_db,
+                        &env_vars,
+                        task_group,
+                    )
+                    .await
+                    .unwrap();
+                modules.insert(id, module);
+            }
+        }
+        ModuleRegistry::from(modules)
+    }
+
     async fn new(
         server_config: BTreeMap<PeerId, ServerConfig>,
         database_gen: &impl Fn(ModuleDecoderRegistry) -> Database,
@@ -1307,58 +1597,16 @@ impl FederationTest {
             let db = database_gen(decoders.clone());
             let mut task_group = task_group.clone();
 
-            let mut override_modules = override_modules(cfg.clone(), db.clone()).await;
-
-            let mut modules = BTreeMap::new();
-            let env_vars = FedimintConsensus::get_env_vars_map();
-
-            fedimint_core::db::apply_migrations(
-                &db,
-                "Global".to_string(),
-                GLOBAL_DATABASE_VERSION,
-                fedimint_server::db::get_global_database_migrations(),
-            )
-            .await
-            .unwrap_or_else(|_| panic!("Error while applying global database migrations"));
-
-            for (kind, gen) in module_inits.legacy_init_order_iter() {
-                let id = cfg.get_module_id_by_kind(kind.clone()).unwrap();
-                if let Some(module) = override_modules.remove(kind.as_str()) {
-                    info!(module_instance_id = id, kind = %kind, "Use overridden module");
-                    modules.insert(id, module);
-                } else {
-                    info!(module_instance_id = id, kind = %kind, "Init module");
-
-                    let isolated_db = db.new_isolated(id);
-                    fedimint_core::db::apply_migrations(
-                        &isolated_db,
-                        kind.to_string(),
-                        gen.database_version(),
-                        gen.get_database_migrations(),
-                    )
-                    .await
-                    .unwrap_or_else(|_| {
-                        panic!("Error while applying database migrations for module {kind}")
-                    });
-
-                    let module = gen
-                        .init(
-                            cfg.get_module_config(id).unwrap(),
-                            isolated_db,
-                            &env_vars,
-                            &mut task_group,
-                        )
-                        .await
-                        .unwrap();
-                    modules.insert(id, module);
-                }
-            }
+            let override_modules = override_modules(cfg.clone(), db.clone()).await;
+            let modules =
+                Self::init_modules(cfg, &db, &module_inits, override_modules, &mut task_group)
+                    .await;
 
             let (consensus, tx_receiver) = FedimintConsensus::new_with_modules(
                 cfg.clone(),
                 db.clone(),
                 module_inits.clone(),
-                ModuleRegistry::from(modules),
+                modules,
             );
             let decoders = consensus.decoders();
 
@@ -1375,9 +1623,11 @@ impl FederationTest {
 
             let cfg = cfg.clone();
             let consensus = fedimint.consensus.clone();
+            let api_task_group = task_group.make_subgroup().await;
             task_group
                 .spawn("rpc server", move |handle| async {
-                    fedimint_server::net::api::run_server(cfg, consensus, handle).await
+                    fedimint_server::net::api::run_server(cfg, consensus, handle, api_task_group)
+                        .await
                 })
                 .await;
 
@@ -1408,6 +1658,7 @@ impl FederationTest {
             max_balance_sheet,
             last_consensus,
             decoders: module_inits.decoders(cfg.iter_module_instances()).unwrap(),
+            module_inits,
             cfg,
             wallet,
             mint_id: LEGACY_HARDCODED_INSTANCE_ID_MINT,
@@ -5,10 +5,11 @@ use anyhow::anyhow;
 use async_trait::async_trait;
 use fedimint_core::task::RwLock;
 use ln_gateway::gatewaylnrpc::{
-    CompleteHtlcsRequest, CompleteHtlcsResponse, GetNodeInfoResponse, GetRouteHintsResponse,
-    PayInvoiceRequest, PayInvoiceResponse, SubscribeInterceptHtlcsRequest,
+    CompleteHtlcsRequest, CompleteHtlcsResponse, GetLiquidityResponse, GetNodeInfoResponse,
+    GetRouteHintsResponse, ListPaymentsRequest, ListPaymentsResponse, PayInvoiceRequest,
+    PayInvoiceResponse, SendOnionMessageRequest, SubscribeInterceptHtlcsRequest,
 };
-use ln_gateway::lnrpc_client::{HtlcStream, ILnRpcClient};
+use ln_gateway::lnrpc_client::{ChannelUpdateStream, HtlcStream, ILnRpcClient, OnionMessageStream};
 use ln_gateway::GatewayError;
 use tokio::sync::Mutex;
 
@@ -85,6 +86,29 @@ impl ILnRpcClient for LnRpcAdapter {
         self.client.read().await.complete_htlc(complete).await
     }
 
+    async fn list_payments(
+        &self,
+        request: ListPaymentsRequest,
+    ) -> ln_gateway::Result<ListPaymentsResponse> {
+        self.client.read().await.list_payments(request).await
+    }
+
+    async fn liquidity(&self) -> ln_gateway::Result<GetLiquidityResponse> {
+        self.client.read().await.liquidity().await
+    }
+
+    async fn subscribe_channel_updates<'a>(&self) -> ln_gateway::Result<ChannelUpdateStream<'a>> {
+        self.client.read().await.subscribe_channel_updates().await
+    }
+
+    async fn send_onion_message(&self, message: SendOnionMessageRequest) -> ln_gateway::Result<()> {
+        self.client.read().await.send_onion_message(message).await
+    }
+
+    async fn subscribe_onion_messages<'a>(&self) -> ln_gateway::Result<OnionMessageStream<'a>> {
+        self.client.read().await.subscribe_onion_messages().await
+    }
+
     async fn connect(&mut self) -> ln_gateway::Result<()> {
         self.client.write().await.connect().await
     }
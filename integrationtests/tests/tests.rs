@@ -17,32 +17,59 @@
 //! is thus undesirable.
 mod fixtures;
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter::repeat;
 use std::time::Duration;
 
 use anyhow::Result;
 use assert_matches::assert_matches;
+use bitcoin::hashes::Hash;
 use bitcoin::{Amount, KeyPair};
+use fedimint_core::api::GlobalFederationApi;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
+use fedimint_core::epoch::{
+    ConsensusLimits, ConsensusParams, ConsensusVersionActivation, EpochArchiveInfo, LimitError,
+    ParamsChangeProposal,
+};
+use fedimint_core::module::version::{PARAMS_CHANGE_VERSION, STATE_HASH_VERSION};
 use fedimint_core::outcome::TransactionStatus;
 use fedimint_core::task::TaskGroup;
-use fedimint_core::{msats, sats, TieredMulti};
-use fedimint_ln_client::contracts::{Preimage, PreimageDecryptionShare};
-use fedimint_ln_client::LightningConsensusItem;
+use fedimint_core::{msats, sats, Feerate, OutPoint, TieredMulti, TransactionId};
+use fedimint_ln_client::contracts::{ContractId, Preimage, PreimageDecryptionShare};
+use fedimint_ln_client::route_hints::{RouteHint, RouteHintHop};
+use fedimint_ln_client::{GatewayFee, GatewayLiquidity, LightningConsensusItem};
 use fedimint_logging::LOG_TEST;
+use fedimint_mint_server::common::config::NOTE_SPEND_FEE_PARAM;
 use fedimint_mint_server::common::{MintConsensusItem, MintOutputSignatureShare};
+use fedimint_server::archive::EpochArchive;
 use fedimint_server::consensus::TransactionSubmissionError::{
     TransactionError, TransactionReplayError,
 };
+use fedimint_server::consensus::{CONSENSUS_VERSION, STATE_HASH_INTERVAL};
 use fedimint_server::epoch::ConsensusItem;
 use fedimint_server::transaction::TransactionError::UnbalancedTransaction;
+use fedimint_testing::ln::mock;
 use fedimint_wallet_server::common::WalletConsensusItem::PegOutSignature;
-use fedimint_wallet_server::common::{PegOutFees, PegOutSignatureItem, Rbf};
+use fedimint_wallet_server::common::{PegOut, PegOutFees, PegOutSignatureItem, Rbf};
 use fixtures::{rng, secp, sha256};
 use futures::future::{join_all, Either};
-use ln_gateway::lnrpc_client::ILnRpcClient;
+use ln_gateway::gatewaylnrpc::complete_htlcs_request::{Action, Settle};
+use ln_gateway::gatewaylnrpc::PayInvoiceRequest;
+use ln_gateway::lnrpc_client::{next_channel_update, ILnRpcClient};
+use mint_client::db::{OperationKey, SubAccountKey};
+use mint_client::ln::db::OutgoingPaymentKey;
+use mint_client::ln::history::PaymentKind;
+use mint_client::ln::incoming::{PreimagePurchase, PreimagePurchaseState};
+use mint_client::mint::db::NoteKeyPrefix;
 use mint_client::mint::MintClient;
+use mint_client::modules::mint::config::TierDeprecation;
+use mint_client::operation::{Operation, OperationKind, OperationState};
+use mint_client::receipt::{verify_receipt, PaymentReceipt, ReceiptError};
+use mint_client::sub_account::{SpendLimit, SubAccountId};
 use mint_client::transaction::legacy::Output;
 use mint_client::transaction::TransactionBuilder;
-use mint_client::{ClientError, ConfigVerifyError};
+use mint_client::{ClientError, ConfigVerifyError, InvoiceRequest, NoteRefreshEvent, OrderInvoice};
 use threshold_crypto::{SecretKey, SecretKeyShare};
 use tracing::log::warn;
 use tracing::{debug, info, instrument};
@@ -118,7 +145,7 @@ async fn wallet_peg_outs_are_rejected_if_fees_are_too_low() -> Result<()> {
         fed.mine_and_mint(&user, &*bitcoin, sats(3000)).await;
         let mut peg_out = user
             .client
-            .new_peg_out_with_fees(peg_out_amount, peg_out_address.clone())
+            .new_peg_out_with_fees(peg_out_amount, peg_out_address.clone(), None)
             .await
             .unwrap();
 
@@ -126,6 +153,13 @@ async fn wallet_peg_outs_are_rejected_if_fees_are_too_low() -> Result<()> {
         peg_out.fees.fee_rate.sats_per_kvb = 10;
         // TODO: return a better error message to clients
         assert!(user.client.peg_out(peg_out, rng()).await.is_err());
+
+        let response = user.client.new_peg_out_with_fees(
+            peg_out_amount,
+            peg_out_address,
+            Some(Feerate { sats_per_kvb: 10 }),
+        );
+        assert_matches!(response.await, Err(ClientError::PegOutFeeRateTooLow { .. }));
     })
     .await
 }
@@ -194,7 +228,7 @@ async fn wallet_peg_outs_support_rbf() -> Result<()> {
             fees: PegOutFees::new(1000, fees.total_weight),
             txid,
         };
-        let out_point = user.client.rbf_tx(rbf.clone()).await.unwrap();
+        let out_point = user.client.rbf_tx(rbf.clone(), rng()).await.unwrap();
         fed.run_consensus_epochs(2).await;
         fed.broadcast_transactions().await;
         let txid = user
@@ -222,6 +256,77 @@ async fn wallet_peg_outs_support_rbf() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn wallet_peg_outs_can_be_bumped_by_their_owner() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        // Need lock to keep tx in mempool from getting mined
+        let bitcoin = bitcoin.lock_exclusive().await;
+        let address = bitcoin.get_new_address().await;
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(5000)).await;
+        fed.activate_consensus_version(1).await;
+        let (fees, out_point) = user.peg_out(1000, &address).await;
+        fed.run_consensus_epochs(2).await;
+        fed.broadcast_transactions().await;
+
+        let txid = user
+            .client
+            .wallet_client()
+            .await_peg_out_outcome(out_point)
+            .await
+            .unwrap();
+
+        // the bump pays for the weight of the stored peg-out transaction
+        let fee_rate = Feerate { sats_per_kvb: 1000 };
+        let bump = PegOutFees::new(fee_rate.sats_per_kvb, fees.total_weight);
+        user.client
+            .bump_peg_out_fees(txid, &address, fee_rate, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await;
+        fed.broadcast_transactions().await;
+
+        // the bump spends the same UTXOs, so only one of the txs can confirm
+        assert_eq!(
+            bitcoin.mine_block_and_get_received(&address).await,
+            sats(1000)
+        );
+        user.assert_total_notes(sats(5000 - 1000) - fees.amount().into() - bump.amount().into())
+            .await;
+
+        // other keys can't bump the peg-out
+        let other_address = bitcoin.get_new_address().await;
+        assert!(user
+            .client
+            .bump_peg_out_fees(txid, &other_address, fee_rate, rng())
+            .await
+            .is_err());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wallet_peg_outs_have_no_owner_until_activation() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        let address = bitcoin.get_new_address().await;
+        fed.mine_and_mint(&user, &*bitcoin, sats(5000)).await;
+        let peg_out = user
+            .client
+            .new_peg_out_with_fees(Amount::from_sat(1000), address, None)
+            .await
+            .unwrap();
+        assert_eq!(peg_out.owner, None);
+
+        // guardians of version 0 couldn't decode the owned peg-out
+        let owned = PegOut {
+            owner: Some(KeyPair::new(&secp(), &mut rng()).x_only_public_key().0),
+            ..peg_out
+        };
+        assert!(user.client.peg_out(owned, rng()).await.is_err());
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn wallet_peg_ins_that_are_unconfirmed_are_rejected() -> Result<()> {
     non_lightning_test(2, |_fed, user, bitcoin, _, _| async move {
@@ -264,9 +369,9 @@ async fn wallet_peg_outs_must_wait_for_available_utxos() -> Result<()> {
         );
 
         // The change UTXO is still finalizing
-        let response = user
-            .client
-            .new_peg_out_with_fees(Amount::from_sat(2000), address2.clone());
+        let response =
+            user.client
+                .new_peg_out_with_fees(Amount::from_sat(2000), address2.clone(), None);
         assert_matches!(response.await, Err(ClientError::PegOutWaitingForUTXOs));
 
         bitcoin.mine_blocks(100).await;
@@ -282,6 +387,58 @@ async fn wallet_peg_outs_must_wait_for_available_utxos() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn client_reissues_notes_in_deprecated_tiers() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(5000)).await;
+
+        // deprecate the largest tier we hold notes of
+        let notes = user.client.notes().await;
+        let deprecated = *notes.iter_tiers().last().unwrap();
+        assert!(notes.iter_tiers().next() != Some(&deprecated));
+        let deprecation = TierDeprecation {
+            tiers: BTreeSet::from([deprecated]),
+            deadline: None,
+        };
+
+        let event = user
+            .client
+            .reissue_deprecated_notes(deprecation.clone(), rng())
+            .await
+            .unwrap();
+        assert_matches!(event, Some(NoteRefreshEvent::Reissued { .. }));
+        fed.run_consensus_epochs(2).await; // process transaction + sign new notes
+
+        user.assert_total_notes(sats(5000)).await;
+        assert!(user.client.notes().await.get(deprecated).is_none());
+        // nothing left to reissue
+        assert_eq!(
+            user.client
+                .reissue_deprecated_notes(deprecation, rng())
+                .await
+                .unwrap(),
+            None
+        );
+
+        // notes can't be reissued automatically once the deadline passed
+        let smallest = *user.client.notes().await.iter_tiers().next().unwrap();
+        let event = user
+            .client
+            .reissue_deprecated_notes(
+                TierDeprecation {
+                    tiers: BTreeSet::from([smallest]),
+                    deadline: Some(0),
+                },
+                rng(),
+            )
+            .await
+            .unwrap();
+        assert_matches!(event, Some(NoteRefreshEvent::ManualActionRequired { .. }));
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn ecash_can_be_exchanged_directly_between_users() -> Result<()> {
     non_lightning_test(4, |fed, user_send, bitcoin, _, _| async move {
@@ -302,6 +459,159 @@ async fn ecash_can_be_exchanged_directly_between_users() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn recorded_epoch_history_can_be_replayed() -> Result<()> {
+    non_lightning_test(2, |fed, user_send, bitcoin, _, _| async move {
+        let user_receive = user_send.new_user_with_peers(peers(&[0, 1])).await;
+
+        fed.mine_and_mint(&user_send, &*bitcoin, sats(5000)).await;
+        let ecash = fed.spend_ecash(&user_send, sats(3500)).await;
+        user_receive.client.reissue(ecash, rng()).await.unwrap();
+        fed.run_consensus_epochs(2).await; // process transaction + sign new notes
+
+        let last_epoch = fed.epoch_counts().await[0] - 1;
+        assert_eq!(fed.replay_epoch_history().await.unwrap(), Some(last_epoch));
+
+        // an epoch processed differently than recorded stops the replay
+        fed.forge_epoch_hash(last_epoch).await;
+        assert!(fed.replay_epoch_history().await.is_err());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn module_items_exceeding_the_batch_limit_are_deferred() -> Result<()> {
+    non_lightning_test(2, |fed, user1, bitcoin, _, _| async move {
+        let user2 = user1.new_user_with_peers(peers(&[0, 1])).await;
+        fed.mine_and_mint(&user1, &*bitcoin, sats(1000)).await;
+        fed.mine_and_mint(&user2, &*bitcoin, sats(2000)).await;
+
+        fed.propose_consensus_params(ConsensusParams {
+            module_batch_limits: BTreeMap::from([(fed.mint_id, 1)]),
+            ..ConsensusParams::default()
+        })
+        .await;
+        fed.run_consensus_epochs(1).await;
+
+        let ecash1 = fed.spend_ecash(&user1, sats(1000)).await;
+        let ecash2 = fed.spend_ecash(&user2, sats(2000)).await;
+        user1.client.reissue(ecash1, rng()).await.unwrap();
+        user2.client.reissue(ecash2, rng()).await.unwrap();
+        fed.run_consensus_epochs(1).await; // process both transactions
+        fed.run_consensus_epochs(2).await; // sign the notes of one transaction per epoch
+
+        user1.assert_total_notes(sats(1000)).await;
+        user2.assert_total_notes(sats(2000)).await;
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spent_ecash_is_reported_by_validation() -> Result<()> {
+    non_lightning_test(2, |fed, user_send, bitcoin, _, _| async move {
+        let user_receive = user_send.new_user_with_peers(peers(&[0, 1])).await;
+        fed.mine_and_mint(&user_send, &*bitcoin, sats(5000)).await;
+
+        let ecash = fed.spend_ecash(&user_send, sats(3500)).await;
+        let validation = user_receive
+            .client
+            .check_notes_spendable(&ecash)
+            .await
+            .unwrap();
+        assert!(validation.is_valid());
+
+        user_receive
+            .client
+            .reissue(ecash.clone(), rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await;
+
+        let validation = user_receive
+            .client
+            .check_notes_spendable(&ecash)
+            .await
+            .unwrap();
+        let num_notes = ecash.count_items() as u64;
+        assert_eq!(validation.spent_inputs, (0..num_notes).collect());
+        assert_eq!(validation.input_errors.len() as u64, num_notes);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn validating_transactions_reports_errors_without_writing() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(5000)).await;
+        let notes = user.client.notes().await;
+        let spend_notes = || {
+            let mut builder = TransactionBuilder::default();
+            let (mut keys, input) = MintClient::ecash_input(notes.clone()).unwrap();
+            builder.input(&mut keys, input);
+            user.tx_with_change(builder, sats(5000))
+        };
+
+        // a valid transaction has no errors and nothing is written
+        let tx = spend_notes().await.into_type_erased();
+        let entries = fed.database_entries().await;
+        for validation in fed.validate_transaction(&tx).await {
+            assert!(validation.is_valid(), "{validation:?}");
+        }
+        assert_eq!(fed.database_entries().await, entries);
+
+        let underfunded = user
+            .tx_with_change(TransactionBuilder::default(), sats(1000))
+            .await
+            .into_type_erased();
+        for validation in fed.validate_transaction(&underfunded).await {
+            assert_eq!(validation.transaction_errors.len(), 1);
+            assert!(validation.transaction_errors[0].starts_with("The transaction is unbalanced"));
+        }
+
+        let max = ConsensusLimits::default().max_transaction_outputs;
+        let mut over_limit = tx.clone();
+        over_limit.outputs = repeat(tx.outputs[0].clone())
+            .take(max as usize + 1)
+            .collect();
+        let limit_error = LimitError::TooManyOutputs {
+            outputs: max + 1,
+            max,
+        };
+        for validation in fed.validate_transaction(&over_limit).await {
+            assert!(validation
+                .transaction_errors
+                .contains(&limit_error.to_string()));
+        }
+
+        fed.submit_transaction(tx.clone()).await.unwrap();
+        fed.run_empty_epochs(2).await;
+
+        // the accepted transaction can't be replayed
+        let replay_error = TransactionReplayError(tx.tx_hash()).to_string();
+        for validation in fed.validate_transaction(&tx).await {
+            assert!(validation.transaction_errors.contains(&replay_error));
+        }
+
+        // another transaction spending the same notes conflicts with it
+        let conflicting = spend_notes().await.into_type_erased();
+        assert_ne!(conflicting.tx_hash(), tx.tx_hash());
+        for validation in fed.validate_transaction(&conflicting).await {
+            assert_eq!(validation.spent_inputs, BTreeSet::from([0]));
+            assert!(validation.input_errors.contains_key(&0));
+            assert!(!validation.transaction_errors.contains(&replay_error));
+        }
+
+        // clients see the same through the API endpoint
+        let validation = user.client.check_notes_spendable(&notes).await.unwrap();
+        assert_eq!(
+            validation.spent_inputs,
+            (0..notes.count_items() as u64).collect()
+        );
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn ecash_cannot_double_spent_with_different_nodes() -> Result<()> {
     non_lightning_test(2, |fed, user1, bitcoin, _, _| async move {
@@ -327,6 +637,48 @@ async fn ecash_cannot_double_spent_with_different_nodes() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn conflicting_transactions_are_resubmitted_with_other_notes() -> Result<()> {
+    non_lightning_test(2, |fed, user_send, bitcoin, _, _| async move {
+        let user_spend = user_send.new_user_with_peers(peers(&[0])).await;
+        let user_receive = user_send.new_user_with_peers(peers(&[0])).await;
+
+        fed.mine_and_mint(&user_send, &*bitcoin, sats(5000)).await;
+
+        // the notes the next payment selects are spent by another client
+        let spent = user_send
+            .client
+            .mint_client()
+            .select_notes(sats(2000))
+            .await
+            .unwrap();
+        user_spend
+            .client
+            .reissue(spent.clone(), rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await;
+
+        user_receive
+            .client
+            .receive_notes(sats(2000), |notes| async {
+                user_send
+                    .client
+                    .pay_to_blind_nonces(notes, rng())
+                    .await
+                    .unwrap()
+            })
+            .await;
+        fed.run_consensus_epochs(2).await;
+
+        user_receive.assert_total_notes(sats(2000)).await;
+        user_send
+            .assert_total_notes(sats(5000) - spent.total_amount() - sats(2000))
+            .await;
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn ecash_in_wallet_can_sent_through_a_tx() -> Result<()> {
     non_lightning_test(2, |fed, user_send, bitcoin, _, _| async move {
@@ -717,58 +1069,168 @@ async fn lightning_gateway_pays_outgoing_invoice() -> Result<()> {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn lightning_gateway_claims_refund_for_internal_invoice() -> Result<()> {
+async fn lightning_gateway_records_the_routing_fee_of_outgoing_payments() -> Result<()> {
     lightning_test(2, |fed, user, bitcoin, gateway, lightning| async move {
-        // Fund the gateway so it can route internal payments
-        fed.mine_and_mint(&gateway.user, &*bitcoin, sats(2000))
-            .await;
-        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
-
-        let receiving_client = user.new_user_with_peers(peers(&[0])).await;
+        // Only the mocked node lets us charge a routing fee
+        let controller = match gateway.lightning_controller.clone() {
+            Some(controller) => controller,
+            None => return,
+        };
+        controller.set_routing_fee(sats(4));
+        let bitcoin = bitcoin.lock_exclusive().await;
 
-        let (txid, invoice, payment_keypair) = receiving_client
+        let invoice = lightning.invoice(sats(1000), None).await.unwrap();
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+        let (contract_id, outpoint) = user
             .client
-            .generate_unconfirmed_invoice_and_submit(sats(1000), "".into(), &mut rng(), None)
+            .fund_outgoing_ln_contract(invoice, rng())
             .await
             .unwrap();
         fed.run_consensus_epochs(1).await;
+        user.client
+            .await_outgoing_contract_acceptance(outpoint)
+            .await
+            .unwrap();
 
-        let confirmed_invoice = receiving_client
+        gateway
+            .actor
+            .read()
+            .await
+            .pay_invoice(contract_id)
+            .await
+            .unwrap();
+
+        // of the 1% fee of the contract we only keep what routing didn't cost
+        let payment = gateway
             .client
-            .await_invoice_confirmation(txid, invoice, payment_keypair)
+            .payment_history(None, None)
             .await
+            .into_iter()
+            .find(|entry| entry.kind == PaymentKind::Outgoing { contract_id })
             .unwrap();
+        assert_eq!(payment.amount, sats(1010));
+        assert_eq!(payment.fee, sats(6));
+        assert_eq!(lightning.amount_sent().await, sats(1004));
+    })
+    .await
+}
 
-        let invoice = confirmed_invoice.invoice;
-        debug!("Receiving User generated invoice: {:?}", invoice);
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_reconciles_paid_outgoing_contracts() -> Result<()> {
+    lightning_test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let bitcoin = bitcoin.lock_exclusive().await;
 
-        let (contract_id, funding_outpoint) = user
+        let invoice = lightning.invoice(sats(1000), None).await.unwrap();
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+
+        let (contract_id, outpoint) = user
             .client
-            .fund_outgoing_ln_contract(invoice, rng())
+            .fund_outgoing_ln_contract(invoice.clone(), rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+        user.client
+            .await_outgoing_contract_acceptance(outpoint)
             .await
             .unwrap();
-        fed.run_consensus_epochs(1).await; // send notes to LN contract
 
-        let contract_account = user
+        // the lightning node paid the invoice, but the gateway crashed before it
+        // claimed the contract
+        let contract_account = gateway
             .client
             .ln_client()
-            .get_contract_account(contract_id)
+            .get_outgoing_contract(contract_id)
             .await
             .unwrap();
-        assert_eq!(contract_account.amount, sats(1010));
-        // 1% LN fee
-        debug!(
-            "Sending User created outgoing contract: {:?}",
-            contract_account
-        );
-
-        user.client
-            .await_outgoing_contract_acceptance(funding_outpoint)
+        gateway
+            .client
+            .save_outgoing_payment(contract_account)
             .await
             .unwrap();
-        debug!("Outgoing contract accepted");
+        gateway
+            .adapter
+            .read()
+            .await
+            .pay(PayInvoiceRequest {
+                invoice: invoice.to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(gateway.client.list_pending_outgoing().await.len(), 1);
 
-        let response = {
+        gateway
+            .actor
+            .read()
+            .await
+            .reconcile_outgoing_payments()
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await; // contract to mint notes, sign notes
+        gateway.client.fetch_all_notes().await.unwrap();
+
+        assert!(gateway.client.list_pending_outgoing().await.is_empty());
+        user.assert_total_notes(sats(2000 - 1010)).await;
+        gateway.user.assert_total_notes(sats(1010)).await;
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_claims_refund_for_internal_invoice() -> Result<()> {
+    lightning_test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        // Fund the gateway so it can route internal payments
+        fed.mine_and_mint(&gateway.user, &*bitcoin, sats(2000))
+            .await;
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+
+        let receiving_client = user.new_user_with_peers(peers(&[0])).await;
+
+        let (txid, invoice, payment_keypair) = receiving_client
+            .client
+            .generate_unconfirmed_invoice_and_submit(sats(1000), "".into(), &mut rng(), None)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+
+        let confirmed_invoice = receiving_client
+            .client
+            .await_invoice_confirmation(txid, invoice, payment_keypair)
+            .await
+            .unwrap();
+
+        let invoice = confirmed_invoice.invoice;
+        debug!("Receiving User generated invoice: {:?}", invoice);
+
+        let (contract_id, funding_outpoint) = user
+            .client
+            .fund_outgoing_ln_contract(invoice, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await; // send notes to LN contract
+
+        let contract_account = user
+            .client
+            .ln_client()
+            .get_contract_account(contract_id)
+            .await
+            .unwrap();
+        assert_eq!(contract_account.amount, sats(1010));
+        // 1% LN fee
+        debug!(
+            "Sending User created outgoing contract: {:?}",
+            contract_account
+        );
+
+        user.client
+            .await_outgoing_contract_acceptance(funding_outpoint)
+            .await
+            .unwrap();
+        debug!("Outgoing contract accepted");
+
+        let response = {
             let buy_preimage = gateway
                 .actor
                 .read()
@@ -894,6 +1356,113 @@ async fn receive_lightning_payment_valid_preimage() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn create_invoices_saves_the_invoices_of_orders() -> Result<()> {
+    lightning_test(2, |fed, user, _, _, _| async move {
+        let batch = ["order-1", "order-2"]
+            .into_iter()
+            .zip([sats(100), sats(200)])
+            .map(|(order_id, amount)| InvoiceRequest {
+                order_id: order_id.to_string(),
+                amount,
+                description: "".into(),
+                expiry_time: None,
+            })
+            .collect::<Vec<_>>();
+
+        let (invoices, epochs) = tokio::join!(
+            user.client.create_invoices(batch.clone(), rng()),
+            fed.run_consensus_epochs_wait(1)
+        );
+        epochs.unwrap();
+        let invoices = invoices.unwrap();
+        assert_eq!(
+            invoices
+                .iter()
+                .map(|order| (
+                    order.order_id.as_str(),
+                    order.invoice.invoice.amount_milli_satoshis()
+                ))
+                .collect::<Vec<_>>(),
+            vec![("order-1", Some(100_000)), ("order-2", Some(200_000))]
+        );
+
+        // orders that already got an invoice get it back without a new offer
+        let again = user.client.create_invoices(batch, rng()).await.unwrap();
+        let contract_ids = |invoices: &[OrderInvoice]| {
+            invoices
+                .iter()
+                .map(|order| order.invoice.contract_id())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contract_ids(&again), contract_ids(&invoices));
+        assert_eq!(
+            user.client
+                .order_invoice("order-2")
+                .await
+                .map(|order| order.invoice.contract_id()),
+            Some(invoices[1].invoice.contract_id())
+        );
+        assert!(user.client.order_invoice("order-3").await.is_none());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_stays_paused_after_restart() -> Result<()> {
+    lightning_test(2, |_, _, _, gateway, _| async move {
+        gateway.actor.read().await.pause().await;
+
+        // the gateway loads the actor of the federation again when it restarts
+        let restarted = gateway
+            .gateway
+            .load_actor(gateway.client.clone(), vec![])
+            .await
+            .unwrap();
+        assert!(restarted.read().await.is_paused());
+
+        restarted.read().await.resume().await;
+        let restarted = gateway
+            .gateway
+            .load_actor(gateway.client.clone(), vec![])
+            .await
+            .unwrap();
+        assert!(!restarted.read().await.is_paused());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_does_not_buy_abandoned_test_offers() -> Result<()> {
+    lightning_test(2, |fed, user, bitcoin, gateway, _| async move {
+        fed.mine_and_mint(&gateway.user, &*bitcoin, sats(2000))
+            .await;
+
+        let (txid, invoice, payment_keypair) = user
+            .client
+            .generate_unconfirmed_invoice_and_submit(sats(100), "".into(), &mut rng(), None)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+        let invoice = user
+            .client
+            .await_invoice_confirmation(txid, invoice, payment_keypair)
+            .await
+            .unwrap();
+
+        gateway.client.abandon_test_invoice(&invoice.invoice).await;
+        assert_matches!(
+            gateway
+                .client
+                .buy_preimage_offer(invoice.invoice.payment_hash(), &sats(100), rng())
+                .await,
+            Err(ClientError::AbandonedOffer)
+        );
+        gateway.user.assert_total_notes(sats(2000)).await;
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn receive_lightning_payment_invalid_preimage() -> Result<()> {
     lightning_test(2, |fed, user, bitcoin, gateway, _| async move {
@@ -913,6 +1482,7 @@ async fn receive_lightning_payment_invalid_preimage() -> Result<()> {
             payment_hash,
             Preimage(kp.x_only_public_key().0.serialize()),
             None,
+            msats(0),
         );
         let mut builder = TransactionBuilder::default();
         builder.output(Output::LN(offer_output));
@@ -980,7 +1550,7 @@ async fn lightning_gateway_cannot_claim_invalid_preimage() -> Result<()> {
         let bad_preimage = Preimage(rand_slice);
         let response = gateway
             .client
-            .claim_outgoing_contract(contract_id, bad_preimage, rng())
+            .claim_outgoing_contract(contract_id, bad_preimage, msats(0), rng())
             .await;
         assert!(response.is_err());
 
@@ -1014,7 +1584,8 @@ async fn lightning_gateway_can_abort_payment_to_return_user_funds() -> Result<()
                     .await
                     .unwrap(),
             )
-            .await;
+            .await
+            .unwrap();
 
         // Gateway fails to acquire preimage, so it cancels the contract so the user can
         // try another one
@@ -1037,6 +1608,215 @@ async fn lightning_gateway_can_abort_payment_to_return_user_funds() -> Result<()
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn cancelling_payment_operation_refunds_aborted_contract() -> Result<()> {
+    lightning_test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let invoice = lightning.invoice(sats(1000), None).await.unwrap();
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(1010)).await; // 1% LN fee
+        let operation_id = [1; 32];
+        let active_gateway = user.client.fetch_active_gateway().await.unwrap();
+        let (contract_id, _) = user
+            .client
+            .fund_outgoing_ln_contract_with_id(operation_id, invoice, &active_gateway, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await; // send notes to LN contract
+
+        let operation = user.client.get_operation(operation_id).await.unwrap();
+        assert_eq!(
+            operation.kind,
+            OperationKind::OutgoingPayment { contract_id }
+        );
+        assert_matches!(operation.state, OperationState::Submitted { .. });
+        assert_matches!(
+            user.client.cancel_operation([2; 32], rng()).await,
+            Err(ClientError::UnknownOperation)
+        );
+        // the gateway may still pay the invoice until the contract times out
+        assert_matches!(
+            user.client.cancel_operation(operation_id, rng()).await,
+            Err(ClientError::OperationNotCancellable(_))
+        );
+
+        gateway
+            .client
+            .save_outgoing_payment(
+                gateway
+                    .client
+                    .ln_client()
+                    .get_outgoing_contract(contract_id)
+                    .await
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        gateway
+            .client
+            .abort_outgoing_payment(contract_id)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+
+        let operation = user
+            .client
+            .cancel_operation(operation_id, rng())
+            .await
+            .unwrap();
+        let OperationState::Refunded { out_point } = operation.state else {
+            panic!("Operation wasn't refunded: {:?}", operation.state);
+        };
+        assert_eq!(
+            user.client.list_operations().await,
+            vec![(operation_id, operation)]
+        );
+        fed.run_consensus_epochs(2).await;
+        user.client.fetch_notes(out_point).await.unwrap();
+        assert_eq!(user.total_notes().await, sats(1010));
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn operations_complete_or_fail_when_interrupted() -> Result<()> {
+    non_lightning_test(2, |fed, user_send, bitcoin, _, _| async move {
+        let user_receive = user_send.new_user_with_peers(peers(&[0, 1])).await;
+        fed.mine_and_mint(&user_send, &*bitcoin, sats(5000)).await;
+        let ecash = fed.spend_ecash(&user_send, sats(3500)).await;
+        let out_point = user_receive.client.reissue(ecash, rng()).await.unwrap();
+
+        let [(operation_id, operation)] = &user_receive.client.list_operations().await[..] else {
+            panic!("Reissuing isn't tracked as an operation");
+        };
+        assert_eq!(
+            operation.kind,
+            OperationKind::Reissue { amount: sats(3500) }
+        );
+        assert_eq!(
+            operation.state,
+            OperationState::Submitted {
+                txid: out_point.txid
+            }
+        );
+        fed.run_consensus_epochs(2).await;
+        let operation = user_receive
+            .client
+            .update_operation(*operation_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            operation.state,
+            OperationState::Completed {
+                txid: out_point.txid
+            }
+        );
+
+        // a crash interrupted the operation before its transaction was built
+        let interrupted = Operation {
+            kind: OperationKind::Reissue { amount: sats(1000) },
+            state: OperationState::Submitting,
+            created_at: operation.created_at,
+        };
+        let mut dbtx = user_receive.client.db().begin_transaction().await;
+        dbtx.insert_new_entry(&OperationKey([1; 32]), &interrupted)
+            .await;
+        dbtx.commit_tx().await;
+
+        assert_eq!(
+            user_receive.client.recover_interrupted_operations().await,
+            vec![[1; 32]]
+        );
+        assert_matches!(
+            user_receive
+                .client
+                .get_operation([1; 32])
+                .await
+                .unwrap()
+                .state,
+            OperationState::Failed { .. }
+        );
+        assert!(user_receive
+            .client
+            .recover_interrupted_operations()
+            .await
+            .is_empty());
+        user_receive.assert_total_notes(sats(3500)).await;
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn payments_skip_gateways_without_liquidity() -> Result<()> {
+    lightning_test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let invoice = lightning.invoice(sats(1000), None).await.unwrap();
+        fed.mine_and_mint(&user, &*bitcoin, sats(1010)).await; // 1% LN fee
+
+        let liquidity = GatewayLiquidity {
+            max_receivable: sats(2000),
+            max_payable: sats(500),
+        };
+        let registration = gateway.client.config().to_gateway_registration_info(
+            vec![],
+            GatewayFee::default(),
+            Some(liquidity),
+            Duration::from_secs(60),
+        );
+        gateway
+            .client
+            .register_with_federation(registration)
+            .await
+            .unwrap();
+        let active_gateway = user.client.switch_active_gateway(None).await.unwrap();
+        assert_eq!(active_gateway.liquidity, Some(liquidity));
+
+        assert_matches!(
+            user.client.fund_outgoing_ln_contract(invoice, rng()).await,
+            Err(ClientError::NoGatewayWithLiquidity(_))
+        );
+        assert_matches!(
+            user.client
+                .generate_unconfirmed_invoice_and_submit(sats(3000), "".into(), &mut rng(), None)
+                .await,
+            Err(ClientError::NoGatewayWithLiquidity(_))
+        );
+        // nothing was spent on the payment
+        assert_eq!(user.total_notes().await, sats(1010));
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receive_preview_includes_gateway_fees() -> Result<()> {
+    lightning_test(2, |_, user, _, gateway, _| async move {
+        let fees = GatewayFee {
+            base_msat: 1_000,
+            proportional_millionths: 10_000,
+        };
+        let registration = gateway.client.config().to_gateway_registration_info(
+            vec![],
+            fees,
+            None,
+            Duration::from_secs(60),
+        );
+        gateway
+            .client
+            .register_with_federation(registration)
+            .await
+            .unwrap();
+        user.client.switch_active_gateway(None).await.unwrap();
+
+        // the federation charges no fees for claiming contracts by default
+        let preview = user.client.preview_receive(sats(1000)).await.unwrap();
+        assert_eq!(preview.federation_fee, sats(0));
+        assert_eq!(preview.invoice_amount, sats(1000));
+        assert_eq!(preview.net_amount, sats(1000));
+        // 1 sat base fee and 1% of the invoice amount
+        assert_eq!(preview.gateway_fee, sats(11));
+        assert_eq!(preview.payer_amount, sats(1011));
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn runs_consensus_if_tx_submitted() -> Result<()> {
     non_lightning_test(2, |fed, user_send, bitcoin, _, _| async move {
@@ -1122,8 +1902,19 @@ async fn unbalanced_transactions_get_rejected() -> Result<()> {
     non_lightning_test(2, |fed, user, _, _, _| async move {
         // cannot make change for this invoice (results in unbalanced tx)
         let builder = TransactionBuilder::default();
-        let tx = user.tx_with_change(builder, sats(1000)).await;
-        let response = fed.submit_transaction(tx.into_type_erased()).await;
+        let tx = user
+            .tx_with_change(builder, sats(1000))
+            .await
+            .into_type_erased();
+
+        // validating reports the error without submitting the transaction
+        for validation in fed.validate_transaction(&tx).await {
+            assert!(validation.input_errors.is_empty());
+            assert!(validation.output_errors.is_empty());
+            assert_eq!(validation.transaction_errors.len(), 1);
+        }
+
+        let response = fed.submit_transaction(tx).await;
 
         assert_matches!(
             response,
@@ -1162,20 +1953,159 @@ async fn can_get_signed_epoch_history() -> Result<()> {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn rejoin_consensus_single_peer() -> Result<()> {
-    non_lightning_test(4, |fed, user, bitcoin, _, _| async move {
-        let bitcoin = bitcoin.lock_exclusive().await;
-        bitcoin.mine_blocks(1).await;
-        fed.run_consensus_epochs(1).await;
+async fn explorer_pages_through_all_epochs() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(1000)).await;
+        // more epochs than fit on a single page
+        fed.run_empty_epochs(25).await;
 
-        // Keep peer 3 out of consensus
-        let online_peers = fed.subset_peers(&[0, 1, 2]).await;
-        let peer3 = fed.subset_peers(&[3]).await;
-        bitcoin.mine_blocks(100).await;
-        online_peers.run_consensus_epochs(1).await;
-        bitcoin.mine_blocks(100).await;
-        online_peers.run_consensus_epochs(1).await;
-        let height = user.client.await_consensus_block_height(0).await.unwrap();
+        let api = user.client.context().api.clone();
+        let epoch_count = api.fetch_epoch_count().await.unwrap();
+        let explored = api
+            .explore_epochs(0, epoch_count as usize + 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            explored.iter().map(|epoch| epoch.epoch).collect::<Vec<_>>(),
+            (0..epoch_count).collect::<Vec<_>>()
+        );
+        assert_eq!(api.explore_epoch(1).await.unwrap(), explored[1]);
+        assert_eq!(api.explore_epochs(epoch_count, 10).await.unwrap(), vec![]);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn third_party_archives_keep_signed_epochs() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(1000)).await;
+
+        let pubkey = fed.cfg.consensus.epoch_pk_set.public_key();
+        let api = user.client.context().api.clone();
+        let decoders = user.client.decoders().clone();
+        let archive = EpochArchive::new(
+            Database::new(MemDatabase::new(), decoders.clone()),
+            api.clone(),
+            pubkey,
+            decoders,
+        );
+
+        // the last epoch isn't signed yet, so it's archived with the next sync
+        let epoch_count = api.fetch_epoch_count().await.unwrap();
+        assert_eq!(archive.sync(0).await.unwrap(), epoch_count - 1);
+        assert_eq!(
+            archive.info().await,
+            Some(EpochArchiveInfo {
+                earliest_epoch: 0,
+                epoch_count: epoch_count - 1,
+            })
+        );
+        for epoch in 0..epoch_count - 1 {
+            assert_eq!(
+                archive.epoch_history(epoch).await,
+                Some(
+                    user.client
+                        .fetch_epoch_history(epoch, pubkey)
+                        .await
+                        .unwrap()
+                )
+            );
+        }
+        assert_eq!(archive.sync(0).await.unwrap(), 0);
+
+        fed.run_consensus_epochs(2).await;
+        let new_epoch_count = api.fetch_epoch_count().await.unwrap();
+        assert_eq!(
+            archive.sync(0).await.unwrap(),
+            new_epoch_count - epoch_count
+        );
+        assert!(archive.epoch_history(new_epoch_count - 2).await.is_some());
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receipts_prove_accepted_transactions() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(5000)).await;
+        let ecash = fed.spend_ecash(&user, sats(2000)).await;
+        let out_point = user.client.reissue(ecash, rng()).await.unwrap();
+        fed.run_consensus_epochs(2).await; // process transaction + sign its epoch
+
+        let receipt = user
+            .client
+            .transaction_receipt(out_point.txid, Some(out_point))
+            .await
+            .unwrap();
+        let config = user.client.config();
+        let transaction = verify_receipt(&receipt, config.as_ref()).await.unwrap();
+        assert_eq!(transaction.tx_hash(), out_point.txid);
+
+        let wrong_output = OutPoint {
+            txid: out_point.txid,
+            out_idx: transaction.outputs.len() as u64,
+        };
+        assert_eq!(
+            verify_receipt(
+                &PaymentReceipt {
+                    out_point: Some(wrong_output),
+                    ..receipt.clone()
+                },
+                config.as_ref()
+            )
+            .await,
+            Err(ReceiptError::InvalidOutPoint {
+                txid: out_point.txid,
+                out_point: wrong_output,
+            })
+        );
+        let other_txid = TransactionId::from_inner([0; 32]);
+        assert_eq!(
+            verify_receipt(
+                &PaymentReceipt {
+                    txid: other_txid,
+                    out_point: None,
+                    ..receipt
+                },
+                config.as_ref()
+            )
+            .await,
+            Err(ReceiptError::TransactionNotIncluded(other_txid))
+        );
+
+        // the receipt of an operation points at the output it created, not at
+        // the change
+        let peg_out_address = bitcoin.get_new_address().await;
+        let (_, peg_out_point) = user.peg_out(1000, &peg_out_address).await;
+        fed.run_consensus_epochs(2).await; // peg-out tx + peg out signing epoch
+        let (operation_id, _) = user
+            .client
+            .list_operations()
+            .await
+            .into_iter()
+            .find(|(_, operation)| matches!(operation.kind, OperationKind::PegOut { .. }))
+            .unwrap();
+        let receipt = user.client.operation_receipt(operation_id).await.unwrap();
+        assert_eq!(receipt.out_point, Some(peg_out_point));
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejoin_consensus_single_peer() -> Result<()> {
+    non_lightning_test(4, |fed, user, bitcoin, _, _| async move {
+        let bitcoin = bitcoin.lock_exclusive().await;
+        bitcoin.mine_blocks(1).await;
+        fed.run_consensus_epochs(1).await;
+
+        // Keep peer 3 out of consensus
+        let online_peers = fed.subset_peers(&[0, 1, 2]).await;
+        let peer3 = fed.subset_peers(&[3]).await;
+        bitcoin.mine_blocks(100).await;
+        online_peers.run_consensus_epochs(1).await;
+        bitcoin.mine_blocks(100).await;
+        online_peers.run_consensus_epochs(1).await;
+        let height = user.client.await_consensus_block_height(0).await.unwrap();
 
         // Run until peer 3 has rejoined
         join_all(vec![
@@ -1284,6 +2214,182 @@ async fn ecash_can_be_recovered() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_notes_can_be_recovered_alone() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(5000)).await;
+        user.client
+            .mint_client()
+            .back_up_ecash_to_federation()
+            .await
+            .unwrap();
+
+        // only the note store is lost, the note indices survive
+        let mut dbtx = user.client.context().db.begin_transaction().await;
+        dbtx.remove_by_prefix(&NoteKeyPrefix).await;
+        dbtx.commit_tx().await;
+        user.assert_total_notes(sats(0)).await;
+
+        let mut task_group = TaskGroup::new();
+        user.client
+            .mint_client()
+            .restore_notes_from_federation(10, &mut task_group)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.total_notes().await, sats(5000));
+
+        task_group.join_all(None).await.unwrap();
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_notes_issued_after_backup_can_be_recovered_alone() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(5000)).await;
+        user.client
+            .mint_client()
+            .back_up_ecash_to_federation()
+            .await
+            .unwrap();
+        // notes issued since the backup can only be found by scanning the epochs
+        fed.mine_and_mint(&user, &*bitcoin, sats(3000)).await;
+
+        let mut dbtx = user.client.context().db.begin_transaction().await;
+        dbtx.remove_by_prefix(&NoteKeyPrefix).await;
+        dbtx.commit_tx().await;
+        user.assert_total_notes(sats(0)).await;
+
+        let mut task_group = TaskGroup::new();
+        user.client
+            .mint_client()
+            .restore_notes_from_federation(10, &mut task_group)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.total_notes().await, sats(8000));
+
+        task_group.join_all(None).await.unwrap();
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sub_accounts_are_funded_backed_up_and_withdrawn() -> Result<()> {
+    non_lightning_test(2, |fed, user, bitcoin, _, _| async move {
+        // funding the whole balance needs no change, which would wait for epochs
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+        let id = user
+            .client
+            .create_sub_account(
+                "pocket money".to_string(),
+                Some(SpendLimit {
+                    amount: sats(500),
+                    period_secs: 3600,
+                }),
+            )
+            .await
+            .unwrap();
+
+        user.client
+            .fund_sub_account(id, sats(2000), rng())
+            .await
+            .unwrap();
+        // the notes are the sub-account's before the federation reissued them
+        let sub_account = user.client.sub_account(id).await.unwrap();
+        assert_eq!(sub_account.balance().await, sats(2000));
+        user.assert_total_notes(sats(0)).await;
+        fed.run_consensus_epochs(2).await;
+        sub_account.fetch_all_notes().await.unwrap();
+        assert_eq!(sub_account.balance().await, sats(2000));
+
+        assert_matches!(
+            sub_account.spend_ecash(sats(1000), rng()).await,
+            Err(ClientError::SpendLimitExceeded { .. })
+        );
+        assert_eq!(sub_account.balance().await, sats(2000));
+
+        // the sub-account and its notes are lost
+        user.client.back_up_ecash_to_federation().await.unwrap();
+        let db = user.client.context().db.clone();
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.remove_entry(&SubAccountKey(id)).await;
+        dbtx.commit_tx().await;
+        let mut dbtx = db
+            .new_isolated(id.db_instance_id())
+            .begin_transaction()
+            .await;
+        dbtx.remove_by_prefix(&NoteKeyPrefix).await;
+        dbtx.commit_tx().await;
+        assert!(user.client.sub_account(id).await.is_err());
+
+        let mut task_group = TaskGroup::new();
+        let restored = user
+            .client
+            .restore_sub_accounts_from_federation(10, false, &mut task_group)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored, vec![SubAccountId(0)]);
+        let sub_account = user.client.sub_account(id).await.unwrap();
+        assert_eq!(sub_account.balance().await, sats(2000));
+        assert_eq!(sub_account.remaining().await.unwrap(), Some(sats(0)));
+
+        // withdrawing doesn't count towards the limit
+        user.client
+            .withdraw_from_sub_account(id, sats(2000), rng())
+            .await
+            .unwrap();
+        assert_eq!(sub_account.balance().await, sats(0));
+        fed.run_consensus_epochs(2).await;
+        user.client.fetch_all_notes().await.unwrap();
+        user.assert_total_notes(sats(2000)).await;
+
+        task_group.join_all(None).await.unwrap();
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_outgoing_contracts_can_be_restored() -> Result<()> {
+    lightning_test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        let invoice = lightning.invoice(sats(1000), None).await.unwrap();
+
+        fed.mine_and_mint(&user, &*bitcoin, sats(1010)).await; // 1% LN fee
+        let active_gateway = user.client.fetch_active_gateway().await.unwrap();
+        let (contract_id, _) = user
+            .client
+            .fund_outgoing_ln_contract_with_id([1; 32], invoice, &active_gateway, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await; // send notes to LN contract
+
+        let mut dbtx = user.client.context().db.begin_transaction().await;
+        dbtx.remove_entry(&OutgoingPaymentKey(contract_id)).await;
+        dbtx.commit_tx().await;
+
+        // the refund key is derived again from the payment hash
+        user.client.restore_contract_states().await.unwrap();
+
+        gateway
+            .client
+            .abort_outgoing_payment(contract_id)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+        let outpoint = user
+            .client
+            .try_refund_outgoing_contract(contract_id, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await;
+        user.client.fetch_notes(outpoint).await.unwrap();
+        assert_eq!(user.total_notes().await, sats(1010));
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn verifies_client_configs() -> Result<()> {
     non_lightning_test(2, |fed, user, _bitcoin, _, _| async move {
@@ -1348,6 +2454,132 @@ async fn cannot_replay_transactions() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn peers_detect_diverging_state_hashes() -> Result<()> {
+    non_lightning_test(4, |fed, user, bitcoin, _, _| async move {
+        fed.activate_consensus_version(STATE_HASH_VERSION.0).await;
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+        // every peer hashes its state and compares the hashes of its peers once
+        let epochs = STATE_HASH_INTERVAL as usize + 1;
+        fed.run_empty_epochs(epochs).await;
+        for peer in 0..4 {
+            assert!(!fed.has_diverged_peer(peer).await);
+        }
+
+        // peer 3 accepted a transaction its peers don't know about
+        fed.subset_peers(&[3])
+            .await
+            .database_add_unknown_transaction()
+            .await;
+        fed.run_empty_epochs(epochs).await;
+
+        let peers = fed.subset_peers(&[0, 1, 2]).await;
+        assert!(peers.has_diverged_peer(3).await);
+        assert!(!peers.has_diverged_peer(0).await);
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unsupported_consensus_version_stops_epoch_processing() -> Result<()> {
+    non_lightning_test(2, |fed, _, _, _, _| async move {
+        let activation_epoch = fed.epoch_counts().await[0] + 3;
+        let version = CONSENSUS_VERSION + 1;
+        fed.schedule_consensus_version(ConsensusVersionActivation {
+            version,
+            epoch: activation_epoch,
+        })
+        .await;
+        fed.run_consensus_epochs(1).await;
+        assert!(fed
+            .scheduled_consensus_version(version)
+            .await
+            .into_iter()
+            .all(|epoch| epoch == Some(activation_epoch)));
+
+        // no epoch from the activation epoch on is applied with the old rules
+        fed.run_empty_epochs(4).await;
+        assert!(fed
+            .epoch_counts()
+            .await
+            .into_iter()
+            .all(|count| count == activation_epoch));
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn params_change_activates_once_a_threshold_proposed_it() -> Result<()> {
+    non_lightning_test(4, |fed, _, _, _, _| async move {
+        let change = |epoch| ParamsChangeProposal {
+            epoch,
+            limits: ConsensusLimits {
+                max_transaction_inputs: 10,
+                ..ConsensusLimits::default()
+            },
+            module_params: BTreeMap::from([(
+                fed.mint_id,
+                BTreeMap::from([(NOTE_SPEND_FEE_PARAM.to_string(), 1_000)]),
+            )]),
+        };
+
+        // federations that don't run the params change version yet reject changes
+        let epoch = fed.epoch_counts().await[0] + 5;
+        assert!(fed.propose_params_change(change(epoch)).await.is_err());
+
+        fed.activate_consensus_version(PARAMS_CHANGE_VERSION.0)
+            .await;
+        let initial_limits = fed.params_changes().await[0].active.clone();
+        let activation_epoch = fed.epoch_counts().await[0] + 5;
+        let proposal = change(activation_epoch);
+
+        let mut unknown_param = proposal.clone();
+        unknown_param.module_params =
+            BTreeMap::from([(fed.mint_id, BTreeMap::from([("unknown".to_string(), 1)]))]);
+        assert!(fed.propose_params_change(unknown_param).await.is_err());
+
+        // proposals of less than a threshold of guardians aren't scheduled
+        fed.subset_peers(&[0, 1])
+            .await
+            .propose_params_change(proposal.clone())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+        for status in fed.params_changes().await {
+            assert!(status.scheduled.is_empty());
+            assert_eq!(status.proposals.len(), 2);
+        }
+
+        fed.subset_peers(&[2])
+            .await
+            .propose_params_change(proposal.clone())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+        for status in fed.params_changes().await {
+            assert_eq!(
+                status.scheduled,
+                BTreeMap::from([(activation_epoch, proposal.clone())])
+            );
+            assert!(status.proposals.is_empty());
+            assert_eq!(status.active, initial_limits);
+        }
+
+        // every guardian applies the change from its activation epoch on
+        fed.run_empty_epochs(4).await;
+        assert!(fed
+            .epoch_counts()
+            .await
+            .into_iter()
+            .all(|count| count >= activation_epoch));
+        for status in fed.params_changes().await {
+            assert!(status.scheduled.is_empty());
+            assert_eq!(status.active, proposal.limits);
+        }
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn lightning_gateway_can_reconnect() -> Result<()> {
     lightning_test(2, |fed, user, bitcoin, gateway, lightning| async move {
@@ -1405,3 +2637,197 @@ async fn lightning_gateway_can_reconnect() -> Result<()> {
     })
     .await
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_settles_intercepted_htlc() -> Result<()> {
+    lightning_test(2, |fed, user, bitcoin, gateway, _| async move {
+        // Only the mocked node lets us inject HTLCs
+        let controller = match gateway.lightning_controller.clone() {
+            Some(controller) => controller,
+            None => return,
+        };
+        fed.mine_and_mint(&gateway.user, &*bitcoin, sats(2000))
+            .await;
+
+        let (txid, invoice, payment_keypair) = user
+            .client
+            .generate_unconfirmed_invoice_and_submit(sats(1000), "".into(), &mut rng(), None)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+        let invoice = user
+            .client
+            .await_invoice_confirmation(txid, invoice, payment_keypair)
+            .await
+            .unwrap()
+            .invoice;
+
+        assert!(controller.is_subscribed(gateway.keys.mint_channel_id));
+        controller.inject_htlc(mock::intercepted_htlc(
+            gateway.keys.mint_channel_id,
+            *invoice.payment_hash(),
+            sats(1000),
+        ));
+
+        // Buying the preimage takes a few epochs
+        for _ in 0..10 {
+            if !controller.completions().is_empty() {
+                break;
+            }
+            fed.run_consensus_epochs(1).await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        let completions = controller.completions();
+        assert_eq!(completions.len(), 1);
+        assert_matches!(
+            &completions[0].action,
+            Some(Action::Settle(Settle { preimage }))
+                if bitcoin::hashes::sha256::Hash::hash(preimage) == *invoice.payment_hash()
+        );
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_refreshes_route_hints_once_per_burst_of_channel_updates() -> Result<()> {
+    lightning_test(2, |_, user, _, gateway, _| async move {
+        // Only the mocked node lets us report channel updates
+        let controller = match gateway.lightning_controller.clone() {
+            Some(controller) => controller,
+            None => return,
+        };
+
+        let mut updates = gateway
+            .adapter
+            .read()
+            .await
+            .subscribe_channel_updates()
+            .await
+            .unwrap();
+        for short_channel_id in 1..=3 {
+            controller.update_channel(Some(short_channel_id));
+        }
+        let debounce = Duration::from_millis(100);
+        let update = next_channel_update(&mut updates, debounce)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.short_channel_id, Some(3));
+        assert!(
+            tokio::time::timeout(debounce, next_channel_update(&mut updates, debounce))
+                .await
+                .is_err()
+        );
+
+        // only changed route hints make the gateway register again
+        let actor = gateway.actor.read().await;
+        assert!(!actor.update_channels(vec![]).await);
+        let route_hint = RouteHint(vec![RouteHintHop {
+            src_node_id: gateway.keys.node_pub_key,
+            short_channel_id: 3,
+            base_msat: 0,
+            proportional_millionths: 0,
+            cltv_expiry_delta: 40,
+            htlc_minimum_msat: None,
+            htlc_maximum_msat: None,
+        }]);
+        assert!(actor.update_channels(vec![route_hint.clone()]).await);
+        assert!(!actor.update_channels(vec![route_hint.clone()]).await);
+
+        let mut registered = false;
+        for _ in 0..50 {
+            registered = user
+                .client
+                .fetch_registered_gateways()
+                .await
+                .unwrap()
+                .iter()
+                .any(|registration| {
+                    registration.mint_pub_key == gateway.keys.mint_pub_key
+                        && registration.route_hints == vec![route_hint.clone()]
+                });
+            if registered {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(registered, "Gateway didn't register its new route hints");
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lightning_gateway_retries_settling_htlc_of_bought_preimage() -> Result<()> {
+    lightning_test(2, |fed, user, bitcoin, gateway, _| async move {
+        // Only the mocked node lets us fail completing HTLCs
+        let controller = match gateway.lightning_controller.clone() {
+            Some(controller) => controller,
+            None => return,
+        };
+        fed.mine_and_mint(&gateway.user, &*bitcoin, sats(2000))
+            .await;
+
+        let (txid, invoice, payment_keypair) = user
+            .client
+            .generate_unconfirmed_invoice_and_submit(sats(1000), "".into(), &mut rng(), None)
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(1).await;
+        let invoice = user
+            .client
+            .await_invoice_confirmation(txid, invoice, payment_keypair)
+            .await
+            .unwrap()
+            .invoice;
+
+        controller.fail_completions(u32::MAX);
+        controller.inject_htlc(mock::intercepted_htlc(
+            gateway.keys.mint_channel_id,
+            *invoice.payment_hash(),
+            sats(1000),
+        ));
+
+        // Buying the preimage takes a few epochs, settling is retried a few
+        // times before it's left to the background task
+        let settle_pending = |purchases: Vec<(ContractId, PreimagePurchase)>| {
+            purchases.into_iter().find_map(|(contract_id, purchase)| {
+                matches!(purchase.state, PreimagePurchaseState::SettlePending { .. })
+                    .then_some(contract_id)
+            })
+        };
+        let mut pending = None;
+        for _ in 0..100 {
+            pending = settle_pending(gateway.client.list_preimage_purchases().await);
+            if pending.is_some() {
+                break;
+            }
+            fed.run_consensus_epochs(1).await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let contract_id = pending.expect("Settling the HTLC wasn't left to the retries");
+        assert!(controller.completions().is_empty());
+
+        controller.fail_completions(0);
+        gateway.actor.read().await.settle_pending_htlcs().await;
+
+        let completions = controller.completions();
+        assert_eq!(completions.len(), 1);
+        assert_matches!(
+            &completions[0].action,
+            Some(Action::Settle(Settle { preimage }))
+                if bitcoin::hashes::sha256::Hash::hash(preimage) == *invoice.payment_hash()
+        );
+        assert_eq!(
+            gateway
+                .client
+                .list_preimage_purchases()
+                .await
+                .into_iter()
+                .find(|(id, _)| *id == contract_id)
+                .map(|(_, purchase)| purchase.state),
+            Some(PreimagePurchaseState::Decrypted)
+        );
+    })
+    .await
+}
@@ -0,0 +1,151 @@
+use fedimint_client::module::gen::ClientModuleGen;
+use fedimint_client::module::ClientModule;
+use fedimint_client::sm::{DynState, OperationId, State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::core::{IntoDynInstance, ModuleInstanceId};
+use fedimint_core::db::Database;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{ExtendsCommonModuleGen, ModuleCommon, TransactionItemAmount};
+use fedimint_core::{apply, async_trait_maybe_send, Amount};
+use fedimint_escrow_common::config::EscrowClientConfig;
+use fedimint_escrow_common::contract::{EscrowContract, EscrowId, EscrowParty};
+pub use fedimint_escrow_common::*;
+use secp256k1::{KeyPair, Message};
+
+#[derive(Debug, Clone)]
+pub struct EscrowClientGen;
+
+impl ExtendsCommonModuleGen for EscrowClientGen {
+    type Common = EscrowCommonGen;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientModuleGen for EscrowClientGen {
+    type Module = EscrowClientModule;
+    type Config = EscrowClientConfig;
+
+    async fn init(
+        &self,
+        cfg: Self::Config,
+        _db: Database,
+        _instance_id: ModuleInstanceId,
+    ) -> anyhow::Result<Self::Module> {
+        Ok(EscrowClientModule { cfg })
+    }
+}
+
+#[derive(Debug)]
+pub struct EscrowClientModule {
+    cfg: EscrowClientConfig,
+}
+
+impl EscrowClientModule {
+    /// Creates an output locking `amount` into the escrow described by
+    /// `contract`
+    pub fn fund_output(&self, amount: Amount, contract: EscrowContract) -> EscrowOutput {
+        EscrowOutput::Fund { amount, contract }
+    }
+
+    /// Creates an output with which the buyer releases the escrowed funds to
+    /// the seller
+    pub fn release_output(&self, contract: &EscrowContract, buyer: &KeyPair) -> EscrowOutput {
+        EscrowOutput::Release {
+            escrow_id: contract.escrow_id(),
+            buyer_signature: secp256k1::SECP256K1
+                .sign_schnorr(&Message::from(contract.release_message()), buyer),
+        }
+    }
+
+    /// Creates an output with which the seller refunds the escrowed funds to
+    /// the buyer
+    pub fn refund_output(&self, contract: &EscrowContract, seller: &KeyPair) -> EscrowOutput {
+        EscrowOutput::Refund {
+            escrow_id: contract.escrow_id(),
+            seller_signature: secp256k1::SECP256K1
+                .sign_schnorr(&Message::from(contract.refund_message()), seller),
+        }
+    }
+
+    /// Creates an output with which the arbiter awards the escrowed funds to
+    /// `beneficiary`
+    pub fn arbitrate_output(
+        &self,
+        contract: &EscrowContract,
+        beneficiary: EscrowParty,
+        arbiter: &KeyPair,
+    ) -> EscrowOutput {
+        EscrowOutput::Arbitrate {
+            escrow_id: contract.escrow_id(),
+            beneficiary,
+            arbiter_signature: secp256k1::SECP256K1.sign_schnorr(
+                &Message::from(contract.arbitration_message(beneficiary)),
+                arbiter,
+            ),
+        }
+    }
+
+    /// Creates an input claiming all `amount` held by the escrow. The
+    /// transaction has to be signed with the key of the party the escrow was
+    /// settled in favor of (or the buyer's once the timelock expired).
+    pub fn claim_input(&self, escrow_id: EscrowId, amount: Amount) -> EscrowInput {
+        EscrowInput { escrow_id, amount }
+    }
+}
+
+impl ClientModule for EscrowClientModule {
+    type Common = EscrowModuleTypes;
+    type ModuleStateMachineContext = ();
+    type States = EscrowClientStates;
+
+    fn context(&self) -> Self::ModuleStateMachineContext {}
+
+    fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
+        TransactionItemAmount {
+            amount: input.amount,
+            fee: self.cfg.fee_consensus.escrow_input,
+        }
+    }
+
+    fn output_amount(
+        &self,
+        output: &<Self::Common as ModuleCommon>::Output,
+    ) -> TransactionItemAmount {
+        match output {
+            EscrowOutput::Fund { amount, .. } => TransactionItemAmount {
+                amount: *amount,
+                fee: self.cfg.fee_consensus.escrow_output,
+            },
+            EscrowOutput::Release { .. }
+            | EscrowOutput::Refund { .. }
+            | EscrowOutput::Arbitrate { .. } => TransactionItemAmount::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum EscrowClientStates {}
+
+impl IntoDynInstance for EscrowClientStates {
+    type DynType = DynState<DynGlobalClientContext>;
+
+    fn into_dyn(self, instance_id: ModuleInstanceId) -> Self::DynType {
+        DynState::from_typed(instance_id, self)
+    }
+}
+
+impl State for EscrowClientStates {
+    type ModuleContext = ();
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        _global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        unimplemented!()
+    }
+
+    fn operation_id(&self) -> OperationId {
+        unimplemented!()
+    }
+}
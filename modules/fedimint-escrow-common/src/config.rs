@@ -0,0 +1,82 @@
+use fedimint_core::config::{
+    ClientModuleConfig, TypedClientModuleConfig, TypedServerModuleConfig,
+    TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::ModuleKind;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::KIND;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscrowConfig {
+    /// Contains all configuration that needs to be the same for every server
+    pub consensus: EscrowConfigConsensus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encodable)]
+pub struct EscrowConfigConsensus {
+    /// Fees charged for escrow transactions
+    pub fee_consensus: FeeConsensus,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
+pub struct EscrowClientConfig {
+    pub fee_consensus: FeeConsensus,
+}
+
+impl TypedClientModuleConfig for EscrowClientConfig {
+    fn kind(&self) -> ModuleKind {
+        KIND
+    }
+}
+
+impl TypedServerModuleConsensusConfig for EscrowConfigConsensus {
+    fn to_client_config(&self) -> ClientModuleConfig {
+        ClientModuleConfig::from_typed(
+            KIND,
+            &EscrowClientConfig {
+                fee_consensus: self.fee_consensus.clone(),
+            },
+        )
+        .expect("Serialization can't fail")
+    }
+}
+
+impl TypedServerModuleConfig for EscrowConfig {
+    type Local = ();
+    type Private = ();
+    type Consensus = EscrowConfigConsensus;
+
+    fn from_parts(
+        _local: Self::Local,
+        _private: Self::Private,
+        consensus: Self::Consensus,
+    ) -> Self {
+        Self { consensus }
+    }
+
+    fn to_parts(self) -> (ModuleKind, Self::Local, Self::Private, Self::Consensus) {
+        (KIND, (), (), self.consensus)
+    }
+
+    fn validate_config(&self, _identity: &PeerId) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
+pub struct FeeConsensus {
+    pub escrow_input: fedimint_core::Amount,
+    pub escrow_output: fedimint_core::Amount,
+}
+
+impl Default for FeeConsensus {
+    fn default() -> Self {
+        Self {
+            escrow_input: fedimint_core::Amount::ZERO,
+            escrow_output: fedimint_core::Amount::ZERO,
+        }
+    }
+}
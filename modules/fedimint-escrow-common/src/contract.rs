@@ -0,0 +1,131 @@
+use std::fmt;
+use std::io::Error;
+
+use bitcoin_hashes::sha256::Hash as Sha256;
+use bitcoin_hashes::{hash_newtype, Hash as BitcoinHash};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use serde::{Deserialize, Serialize};
+
+const RELEASE_TAG: &str = "escrow release";
+const REFUND_TAG: &str = "escrow refund";
+const ARBITRATION_TAG: &str = "escrow arbitration";
+
+hash_newtype!(EscrowId, Sha256, 32, doc = "The hash of an escrow contract");
+
+/// Terms of an escrow agreed upon by buyer and seller before it is funded.
+///
+/// The buyer funds the escrow and gets the funds back if the seller refunds
+/// them, the arbiter decides in the buyer's favor or the timelock expires
+/// before the escrow was settled. The seller receives the funds if the buyer
+/// releases them or the arbiter decides in the seller's favor.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct EscrowContract {
+    /// Public key of the party funding the escrow
+    pub buyer_key: secp256k1::XOnlyPublicKey,
+    /// Public key of the party the funds are meant for
+    pub seller_key: secp256k1::XOnlyPublicKey,
+    /// Public key of the third party resolving disputes
+    pub arbiter_key: secp256k1::XOnlyPublicKey,
+    /// Block height at which the buyer can claim back the funds of an
+    /// unsettled escrow
+    pub timelock: u32,
+}
+
+impl EscrowContract {
+    pub fn escrow_id(&self) -> EscrowId {
+        let mut engine = EscrowId::engine();
+        Encodable::consensus_encode(self, &mut engine).expect("Hashing never fails");
+        EscrowId::from_engine(engine)
+    }
+
+    pub fn party_key(&self, party: EscrowParty) -> secp256k1::XOnlyPublicKey {
+        match party {
+            EscrowParty::Buyer => self.buyer_key,
+            EscrowParty::Seller => self.seller_key,
+        }
+    }
+
+    /// Message the buyer has to sign to release the funds to the seller
+    pub fn release_message(&self) -> Sha256 {
+        self.tagged_message(RELEASE_TAG, None)
+    }
+
+    /// Message the seller has to sign to refund the funds to the buyer
+    pub fn refund_message(&self) -> Sha256 {
+        self.tagged_message(REFUND_TAG, None)
+    }
+
+    /// Message the arbiter has to sign to award the funds to `beneficiary`
+    pub fn arbitration_message(&self, beneficiary: EscrowParty) -> Sha256 {
+        self.tagged_message(ARBITRATION_TAG, Some(beneficiary))
+    }
+
+    fn tagged_message(&self, tag: &str, beneficiary: Option<EscrowParty>) -> Sha256 {
+        let mut engine = Sha256::engine();
+        Encodable::consensus_encode(&tag.as_bytes(), &mut engine).expect("Hashing never fails");
+        Encodable::consensus_encode(&self.escrow_id(), &mut engine).expect("Hashing never fails");
+        Encodable::consensus_encode(&beneficiary, &mut engine).expect("Hashing never fails");
+        Sha256::from_engine(engine)
+    }
+}
+
+/// One of the two parties that can receive the funds of an escrow
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum EscrowParty {
+    Buyer,
+    Seller,
+}
+
+impl fmt::Display for EscrowParty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EscrowParty::Buyer => write!(f, "buyer"),
+            EscrowParty::Seller => write!(f, "seller"),
+        }
+    }
+}
+
+/// Lifecycle of a funded escrow
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum EscrowState {
+    /// Neither party nor the arbiter settled the escrow yet
+    Locked,
+    /// The funds may be claimed by the given party
+    Settled(EscrowParty),
+    /// The funds were claimed
+    Claimed,
+}
+
+impl EscrowState {
+    pub fn is_settled(&self) -> bool {
+        !matches!(self, EscrowState::Locked)
+    }
+}
+
+impl fmt::Display for EscrowState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EscrowState::Locked => write!(f, "locked"),
+            EscrowState::Settled(party) => write!(f, "settled in favor of the {party}"),
+            EscrowState::Claimed => write!(f, "claimed"),
+        }
+    }
+}
+
+impl Encodable for EscrowId {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        self.as_inner().consensus_encode(writer)
+    }
+}
+
+impl Decodable for EscrowId {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(EscrowId::from_inner(Decodable::consensus_decode(
+            d, modules,
+        )?))
+    }
+}
@@ -0,0 +1,49 @@
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, OutPoint};
+use serde::Serialize;
+use strum_macros::EnumIter;
+
+use crate::contract::EscrowId;
+use crate::{EscrowAccount, EscrowOutputOutcome};
+
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    Escrow = 0x90,
+    EscrowOutcome = 0x91,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct EscrowKey(pub EscrowId);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+pub struct EscrowKeyPrefix;
+
+impl_db_record!(
+    key = EscrowKey,
+    value = EscrowAccount,
+    db_prefix = DbKeyPrefix::Escrow,
+);
+impl_db_lookup!(key = EscrowKey, query_prefix = EscrowKeyPrefix);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct EscrowOutcomeKey(pub OutPoint);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+pub struct EscrowOutcomeKeyPrefix;
+
+impl_db_record!(
+    key = EscrowOutcomeKey,
+    value = EscrowOutputOutcome,
+    db_prefix = DbKeyPrefix::EscrowOutcome,
+);
+impl_db_lookup!(
+    key = EscrowOutcomeKey,
+    query_prefix = EscrowOutcomeKeyPrefix
+);
@@ -0,0 +1,194 @@
+//! # Escrow Module
+//!
+//! This module allows two federation users, a *buyer* and a *seller*, to lock
+//! e-cash into a contract that can only be paid out according to the spend
+//! conditions agreed upon when it was created:
+//!
+//!   * the buyer releases the funds to the seller
+//!   * the seller refunds the funds to the buyer
+//!   * the arbiter decides which of the two parties receives the funds
+//!   * the timelock expires, after which the buyer can claim the funds back
+//!
+//! Releasing, refunding and arbitration are signed, zero-amount outputs that
+//! only change the state of the escrow. The funds are then claimed by the
+//! beneficiary using an [`EscrowInput`] signed with their key.
+//!
+//! ## Attention: only one operation per escrow and round
+//! If this module is active the consensus' conflict filter must ensure that at
+//! most one operation (claim, state change) happens per escrow per round
+
+pub mod config;
+pub mod contract;
+pub mod db;
+
+use std::fmt;
+
+use fedimint_core::core::{Decoder, ModuleKind};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{CommonModuleGen, ModuleCommon};
+use fedimint_core::{plugin_types_trait_impl_common, Amount};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config::EscrowClientConfig;
+use crate::contract::{EscrowContract, EscrowId, EscrowParty, EscrowState};
+
+const KIND: ModuleKind = ModuleKind::from_static_str("escrow");
+
+/// Claims the funds of an escrow once its state allows the claimant to do so.
+/// The claimant is authenticated by the transaction signature.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct EscrowInput {
+    pub escrow_id: EscrowId,
+    /// While for now we only support spending the entire escrow we need to
+    /// avoid ambiguity about the amount being spent
+    pub amount: Amount,
+}
+
+impl fmt::Display for EscrowInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Escrow {} claim of {}", self.escrow_id, self.amount)
+    }
+}
+
+/// Represents an output of the escrow module.
+///
+/// Apart from funding an escrow all outputs are zero-amount state changes that
+/// have to be signed by the party allowed to make them.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub enum EscrowOutput {
+    /// Lock funds into an escrow, creating it if it doesn't exist yet
+    Fund {
+        amount: Amount,
+        contract: EscrowContract,
+    },
+    /// The buyer allows the seller to claim the funds
+    Release {
+        escrow_id: EscrowId,
+        buyer_signature: secp256k1::schnorr::Signature,
+    },
+    /// The seller allows the buyer to claim the funds
+    Refund {
+        escrow_id: EscrowId,
+        seller_signature: secp256k1::schnorr::Signature,
+    },
+    /// The arbiter decides which party may claim the funds
+    Arbitrate {
+        escrow_id: EscrowId,
+        beneficiary: EscrowParty,
+        arbiter_signature: secp256k1::schnorr::Signature,
+    },
+}
+
+impl EscrowOutput {
+    pub fn escrow_id(&self) -> EscrowId {
+        match self {
+            EscrowOutput::Fund { contract, .. } => contract.escrow_id(),
+            EscrowOutput::Release { escrow_id, .. }
+            | EscrowOutput::Refund { escrow_id, .. }
+            | EscrowOutput::Arbitrate { escrow_id, .. } => *escrow_id,
+        }
+    }
+}
+
+impl fmt::Display for EscrowOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EscrowOutput::Fund { amount, contract } => {
+                write!(f, "Escrow {} funding of {}", contract.escrow_id(), amount)
+            }
+            EscrowOutput::Release { escrow_id, .. } => write!(f, "Escrow {escrow_id} release"),
+            EscrowOutput::Refund { escrow_id, .. } => write!(f, "Escrow {escrow_id} refund"),
+            EscrowOutput::Arbitrate {
+                escrow_id,
+                beneficiary,
+                ..
+            } => write!(f, "Escrow {escrow_id} arbitrated in favor of {beneficiary}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct EscrowOutputOutcome {
+    pub escrow_id: EscrowId,
+    /// State of the escrow right after the output was applied
+    pub state: EscrowState,
+}
+
+impl fmt::Display for EscrowOutputOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Escrow {} {}", self.escrow_id, self.state)
+    }
+}
+
+/// The escrow module does not need to agree on anything outside of
+/// transactions, so it never proposes consensus items
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct EscrowConsensusItem;
+
+impl fmt::Display for EscrowConsensusItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EscrowConsensusItem")
+    }
+}
+
+/// An escrow as saved in the database
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct EscrowAccount {
+    pub amount: Amount,
+    pub contract: EscrowContract,
+    pub state: EscrowState,
+}
+
+#[derive(Debug)]
+pub struct EscrowCommonGen;
+
+impl CommonModuleGen for EscrowCommonGen {
+    const KIND: ModuleKind = KIND;
+
+    fn decoder() -> Decoder {
+        EscrowModuleTypes::decoder()
+    }
+
+    fn hash_client_module(
+        config: serde_json::Value,
+    ) -> anyhow::Result<bitcoin_hashes::sha256::Hash> {
+        serde_json::from_value::<EscrowClientConfig>(config)?.consensus_hash()
+    }
+}
+
+pub struct EscrowModuleTypes;
+
+impl ModuleCommon for EscrowModuleTypes {
+    type Input = EscrowInput;
+    type Output = EscrowOutput;
+    type OutputOutcome = EscrowOutputOutcome;
+    type ConsensusItem = EscrowConsensusItem;
+}
+
+plugin_types_trait_impl_common!(
+    EscrowInput,
+    EscrowOutput,
+    EscrowOutputOutcome,
+    EscrowConsensusItem
+);
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum EscrowError {
+    #[error("The escrow {0} does not exist")]
+    UnknownEscrow(EscrowId),
+    #[error("Escrows can only be claimed in full, holds {0}, input spends {1}")]
+    PartialClaim(Amount, Amount),
+    #[error("Output funding an escrow may not be zero")]
+    ZeroOutput,
+    #[error("Buyer and seller of an escrow need to be different keys")]
+    SameParties,
+    #[error("The escrow is still locked until block height {0}")]
+    EscrowLocked(u32),
+    #[error("The escrow was already settled: {0}")]
+    AlreadySettled(EscrowState),
+    #[error("The escrow state change wasn't properly signed")]
+    InvalidSignature,
+    #[error("The block height is not available from the wallet module")]
+    BlockHeightUnavailable,
+}
@@ -0,0 +1,491 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+
+use async_trait::async_trait;
+use fedimint_core::config::{
+    ConfigGenParams, DkgResult, ModuleConfigResponse, ServerModuleConfig,
+    ServerModuleReshareConfig, TypedServerModuleConfig, TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::{ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_WALLET};
+use fedimint_core::db::{Database, DatabaseVersion, ModuleDatabaseTransaction};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::interconnect::ModuleInterconect;
+use fedimint_core::module::{
+    api_endpoint, ApiEndpoint, ApiError, ApiRequestErased, ApiVersion, ConsensusProposal,
+    CoreConsensusVersion, ExtendsCommonModuleGen, InputMeta, IntoModuleError,
+    ModuleConsensusVersion, ModuleError, PeerHandle, ServerModuleGen, TransactionItemAmount,
+};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::task::TaskGroup;
+use fedimint_core::{push_db_pair_items, Amount, OutPoint, PeerId, ServerModule};
+pub use fedimint_escrow_common as common;
+use fedimint_escrow_common::config::{EscrowConfig, EscrowConfigConsensus, FeeConsensus};
+use fedimint_escrow_common::contract::{EscrowId, EscrowParty, EscrowState};
+use fedimint_escrow_common::db::{
+    DbKeyPrefix, EscrowKey, EscrowKeyPrefix, EscrowOutcomeKey, EscrowOutcomeKeyPrefix,
+};
+use fedimint_escrow_common::{
+    EscrowAccount, EscrowCommonGen, EscrowConsensusItem, EscrowError, EscrowInput,
+    EscrowModuleTypes, EscrowOutput, EscrowOutputOutcome,
+};
+use strum::IntoEnumIterator;
+
+#[derive(Debug, Clone)]
+pub struct EscrowGen;
+
+impl ExtendsCommonModuleGen for EscrowGen {
+    type Common = EscrowCommonGen;
+}
+
+#[async_trait]
+impl ServerModuleGen for EscrowGen {
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+
+    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        &[ModuleConsensusVersion(0)]
+    }
+
+    async fn init(
+        &self,
+        cfg: ServerModuleConfig,
+        _db: Database,
+        _env: &BTreeMap<OsString, OsString>,
+        _task_group: &mut TaskGroup,
+    ) -> anyhow::Result<DynServerModule> {
+        Ok(Escrow::new(cfg.to_typed()?).into())
+    }
+
+    fn trusted_dealer_gen(
+        &self,
+        peers: &[PeerId],
+        _params: &ConfigGenParams,
+    ) -> BTreeMap<PeerId, ServerModuleConfig> {
+        peers
+            .iter()
+            .map(|&peer| {
+                let config = EscrowConfig {
+                    consensus: EscrowConfigConsensus {
+                        fee_consensus: FeeConsensus::default(),
+                    },
+                };
+                (peer, config.to_erased())
+            })
+            .collect()
+    }
+
+    async fn distributed_gen(
+        &self,
+        _peers: &PeerHandle,
+        _params: &ConfigGenParams,
+    ) -> DkgResult<ServerModuleConfig> {
+        let server = EscrowConfig {
+            consensus: EscrowConfigConsensus {
+                fee_consensus: FeeConsensus::default(),
+            },
+        };
+
+        Ok(server.to_erased())
+    }
+
+    async fn distributed_reshare(
+        &self,
+        _peers: &PeerHandle,
+        _params: &ConfigGenParams,
+        old: &ServerModuleReshareConfig,
+    ) -> DkgResult<ServerModuleConfig> {
+        // the escrow module has no keys, so the new guardians simply keep its config
+        let server = EscrowConfig {
+            consensus: serde_json::from_value(old.consensus.value().clone())
+                .map_err(anyhow::Error::from)?,
+        };
+
+        Ok(server.to_erased())
+    }
+
+    fn to_config_response(
+        &self,
+        config: serde_json::Value,
+    ) -> anyhow::Result<ModuleConfigResponse> {
+        let config = serde_json::from_value::<EscrowConfigConsensus>(config)?;
+
+        Ok(ModuleConfigResponse {
+            client: config.to_client_config(),
+            consensus_hash: config.consensus_hash()?,
+        })
+    }
+
+    fn validate_config(&self, identity: &PeerId, config: ServerModuleConfig) -> anyhow::Result<()> {
+        config.to_typed::<EscrowConfig>()?.validate_config(identity)
+    }
+
+    async fn dump_database(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        let mut escrow: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> = BTreeMap::new();
+        let filtered_prefixes = DbKeyPrefix::iter().filter(|f| {
+            prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+        });
+        for table in filtered_prefixes {
+            match table {
+                DbKeyPrefix::Escrow => {
+                    push_db_pair_items!(
+                        dbtx,
+                        EscrowKeyPrefix,
+                        EscrowKey,
+                        EscrowAccount,
+                        escrow,
+                        "Escrows"
+                    );
+                }
+                DbKeyPrefix::EscrowOutcome => {
+                    push_db_pair_items!(
+                        dbtx,
+                        EscrowOutcomeKeyPrefix,
+                        EscrowOutcomeKey,
+                        EscrowOutputOutcome,
+                        escrow,
+                        "Escrow Outcomes"
+                    );
+                }
+            }
+        }
+
+        Box::new(escrow.into_iter())
+    }
+}
+
+/// The escrow module locks funds of a buyer until either the buyer releases
+/// them to the seller, the seller refunds them to the buyer, an arbiter
+/// decides the dispute or the timelock expires. See
+/// [`fedimint_escrow_common`] for the details of the spend conditions.
+///
+/// The timelock is measured in block heights as agreed on by the wallet
+/// module, which thus needs to be present in the federation.
+#[derive(Debug)]
+pub struct Escrow {
+    cfg: EscrowConfig,
+}
+
+#[async_trait]
+impl ServerModule for Escrow {
+    type Common = EscrowModuleTypes;
+    type Gen = EscrowGen;
+    type VerificationCache = EscrowVerificationCache;
+
+    fn versions(&self) -> (ModuleConsensusVersion, &[ApiVersion]) {
+        (
+            ModuleConsensusVersion(0),
+            &[ApiVersion { major: 0, minor: 0 }],
+        )
+    }
+
+    async fn await_consensus_proposal(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) {
+        std::future::pending().await
+    }
+
+    async fn consensus_proposal(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> ConsensusProposal<EscrowConsensusItem> {
+        ConsensusProposal::empty()
+    }
+
+    async fn begin_consensus_epoch<'a, 'b>(
+        &'a self,
+        _dbtx: &mut ModuleDatabaseTransaction<'b, ModuleInstanceId>,
+        _consensus_items: Vec<(PeerId, EscrowConsensusItem)>,
+    ) {
+    }
+
+    fn build_verification_cache<'a>(
+        &'a self,
+        _inputs: impl Iterator<Item = &'a EscrowInput> + Send,
+    ) -> Self::VerificationCache {
+        EscrowVerificationCache
+    }
+
+    async fn validate_input<'a, 'b>(
+        &self,
+        interconnect: &dyn ModuleInterconect,
+        dbtx: &mut ModuleDatabaseTransaction<'b, ModuleInstanceId>,
+        _verification_cache: &Self::VerificationCache,
+        input: &'a EscrowInput,
+    ) -> Result<InputMeta, ModuleError> {
+        let account = self
+            .get_escrow_account(dbtx, input.escrow_id)
+            .await
+            .ok_or(EscrowError::UnknownEscrow(input.escrow_id))
+            .into_module_error_other()?;
+
+        if account.amount != input.amount {
+            return Err(EscrowError::PartialClaim(account.amount, input.amount))
+                .into_module_error_other();
+        }
+
+        let beneficiary = match account.state {
+            EscrowState::Settled(party) => party,
+            EscrowState::Locked => {
+                // An unsettled escrow returns to the buyer once the timelock expired
+                if account.contract.timelock > block_height(interconnect).await? {
+                    return Err(EscrowError::EscrowLocked(account.contract.timelock))
+                        .into_module_error_other();
+                }
+                EscrowParty::Buyer
+            }
+            EscrowState::Claimed => {
+                return Err(EscrowError::AlreadySettled(account.state)).into_module_error_other();
+            }
+        };
+
+        Ok(InputMeta {
+            amount: TransactionItemAmount {
+                amount: input.amount,
+                fee: self.cfg.consensus.fee_consensus.escrow_input,
+            },
+            puk_keys: vec![account.contract.party_key(beneficiary)],
+        })
+    }
+
+    async fn apply_input<'a, 'b, 'c>(
+        &'a self,
+        interconnect: &'a dyn ModuleInterconect,
+        dbtx: &mut ModuleDatabaseTransaction<'c, ModuleInstanceId>,
+        input: &'b EscrowInput,
+        cache: &Self::VerificationCache,
+    ) -> Result<InputMeta, ModuleError> {
+        let meta = self
+            .validate_input(interconnect, dbtx, cache, input)
+            .await?;
+
+        let escrow_db_key = EscrowKey(input.escrow_id);
+        let mut account = dbtx
+            .get_value(&escrow_db_key)
+            .await
+            .expect("Should fail validation if escrow doesn't exist");
+        account.amount -= meta.amount.amount;
+        account.state = EscrowState::Claimed;
+        dbtx.insert_entry(&escrow_db_key, &account).await;
+
+        Ok(meta)
+    }
+
+    async fn validate_output(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        output: &EscrowOutput,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        if let EscrowOutput::Fund { amount, contract } = output {
+            if *amount == Amount::ZERO {
+                return Err(EscrowError::ZeroOutput).into_module_error_other();
+            }
+
+            if contract.buyer_key == contract.seller_key {
+                return Err(EscrowError::SameParties).into_module_error_other();
+            }
+
+            // Funding is only possible as long as the escrow wasn't settled
+            if let Some(account) = self.get_escrow_account(dbtx, contract.escrow_id()).await {
+                if account.state.is_settled() {
+                    return Err(EscrowError::AlreadySettled(account.state))
+                        .into_module_error_other();
+                }
+            }
+
+            return Ok(TransactionItemAmount {
+                amount: *amount,
+                fee: self.cfg.consensus.fee_consensus.escrow_output,
+            });
+        }
+
+        let escrow_id = output.escrow_id();
+        let account = self
+            .get_escrow_account(dbtx, escrow_id)
+            .await
+            .ok_or(EscrowError::UnknownEscrow(escrow_id))
+            .into_module_error_other()?;
+
+        if account.state.is_settled() {
+            return Err(EscrowError::AlreadySettled(account.state)).into_module_error_other();
+        }
+
+        let contract = &account.contract;
+        let (signature, message, key) = match output {
+            EscrowOutput::Release {
+                buyer_signature, ..
+            } => (
+                buyer_signature,
+                contract.release_message(),
+                contract.buyer_key,
+            ),
+            EscrowOutput::Refund {
+                seller_signature, ..
+            } => (
+                seller_signature,
+                contract.refund_message(),
+                contract.seller_key,
+            ),
+            EscrowOutput::Arbitrate {
+                beneficiary,
+                arbiter_signature,
+                ..
+            } => (
+                arbiter_signature,
+                contract.arbitration_message(*beneficiary),
+                contract.arbiter_key,
+            ),
+            EscrowOutput::Fund { .. } => unreachable!("handled above"),
+        };
+
+        secp256k1::global::SECP256K1
+            .verify_schnorr(signature, &message.into(), &key)
+            .map_err(|_| EscrowError::InvalidSignature)
+            .into_module_error_other()?;
+
+        Ok(TransactionItemAmount::ZERO)
+    }
+
+    async fn apply_output<'a, 'b>(
+        &'a self,
+        dbtx: &mut ModuleDatabaseTransaction<'b, ModuleInstanceId>,
+        output: &'a EscrowOutput,
+        out_point: OutPoint,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        let amount = self.validate_output(dbtx, output).await?;
+
+        let escrow_db_key = EscrowKey(output.escrow_id());
+        let updated_account = match output {
+            EscrowOutput::Fund { contract, .. } => dbtx
+                .get_value(&escrow_db_key)
+                .await
+                .map(|mut account: EscrowAccount| {
+                    account.amount += amount.amount;
+                    account
+                })
+                .unwrap_or_else(|| EscrowAccount {
+                    amount: amount.amount,
+                    contract: contract.clone(),
+                    state: EscrowState::Locked,
+                }),
+            EscrowOutput::Release { .. }
+            | EscrowOutput::Refund { .. }
+            | EscrowOutput::Arbitrate { .. } => {
+                let mut account = dbtx
+                    .get_value(&escrow_db_key)
+                    .await
+                    .expect("Escrow exists if output is valid");
+                account.state = match output {
+                    EscrowOutput::Release { .. } => EscrowState::Settled(EscrowParty::Seller),
+                    EscrowOutput::Refund { .. } => EscrowState::Settled(EscrowParty::Buyer),
+                    EscrowOutput::Arbitrate { beneficiary, .. } => {
+                        EscrowState::Settled(*beneficiary)
+                    }
+                    EscrowOutput::Fund { .. } => unreachable!("handled above"),
+                };
+                account
+            }
+        };
+        dbtx.insert_entry(&escrow_db_key, &updated_account).await;
+
+        dbtx.insert_new_entry(
+            &EscrowOutcomeKey(out_point),
+            &EscrowOutputOutcome {
+                escrow_id: output.escrow_id(),
+                state: updated_account.state,
+            },
+        )
+        .await;
+
+        Ok(amount)
+    }
+
+    async fn end_consensus_epoch<'a, 'b>(
+        &'a self,
+        _consensus_peers: &HashSet<PeerId>,
+        _dbtx: &mut ModuleDatabaseTransaction<'b, ModuleInstanceId>,
+    ) -> Vec<PeerId> {
+        vec![]
+    }
+
+    async fn output_status(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        out_point: OutPoint,
+    ) -> Option<EscrowOutputOutcome> {
+        dbtx.get_value(&EscrowOutcomeKey(out_point)).await
+    }
+
+    async fn audit(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        audit: &mut Audit,
+    ) {
+        audit
+            .add_items(dbtx, &EscrowKeyPrefix, |_, v| -(v.amount.msats as i64))
+            .await;
+    }
+
+    fn consensus_db_prefixes(&self) -> Vec<u8> {
+        // escrows and their outcomes are only written by accepted transactions
+        vec![DbKeyPrefix::Escrow as u8, DbKeyPrefix::EscrowOutcome as u8]
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![api_endpoint! {
+            "/escrow",
+            async |module: &Escrow, context, escrow_id: EscrowId| -> EscrowAccount {
+                module
+                    .get_escrow_account(&mut context.dbtx(), escrow_id)
+                    .await
+                    .ok_or_else(|| ApiError::not_found(String::from("Escrow not found")))
+            }
+        }]
+    }
+}
+
+impl Escrow {
+    /// Create new module instance
+    pub fn new(cfg: EscrowConfig) -> Escrow {
+        Escrow { cfg }
+    }
+
+    pub async fn get_escrow_account(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        escrow_id: EscrowId,
+    ) -> Option<EscrowAccount> {
+        dbtx.get_value(&EscrowKey(escrow_id)).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EscrowVerificationCache;
+
+impl fedimint_core::server::VerificationCache for EscrowVerificationCache {}
+
+/// Block height as seen by the wallet module, an input can't be validated if
+/// the wallet module is missing or malfunctioning, but that's no reason to
+/// crash the guardian
+async fn block_height(interconnect: &dyn ModuleInterconect) -> Result<u32, ModuleError> {
+    // This is a future because we are normally reading from a network socket. But
+    // for internal calls the data is available instantly in one go, so we can
+    // just block on it.
+    let body = interconnect
+        .call(
+            LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+            "/block_height".to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+        .map_err(|_| EscrowError::BlockHeightUnavailable)
+        .into_module_error_other()?;
+
+    serde_json::from_value(body)
+        .map_err(|_| EscrowError::BlockHeightUnavailable)
+        .into_module_error_other()
+}
@@ -0,0 +1,230 @@
+use fedimint_core::config::ConfigGenParams;
+use fedimint_core::{Amount, BitcoinHash, OutPoint, TransactionId};
+use fedimint_escrow_common::contract::{EscrowContract, EscrowParty, EscrowState};
+use fedimint_escrow_common::{EscrowError, EscrowInput, EscrowOutput, EscrowOutputOutcome};
+use fedimint_escrow_server::{Escrow, EscrowGen};
+use fedimint_testing::FakeFed;
+use secp256k1::schnorr::Signature;
+use secp256k1::{KeyPair, Message};
+
+const ESCROW_INSTANCE_ID: u16 = 3;
+
+fn key_pair(seed: u8) -> KeyPair {
+    KeyPair::from_seckey_slice(secp256k1::global::SECP256K1, &[seed; 32]).unwrap()
+}
+
+fn sign(key: &KeyPair, message: impl Into<Message>) -> Signature {
+    secp256k1::global::SECP256K1.sign_schnorr_no_aux_rand(&message.into(), key)
+}
+
+fn out_point(tag: &[u8]) -> OutPoint {
+    OutPoint {
+        txid: TransactionId::hash(tag),
+        out_idx: 0,
+    }
+}
+
+struct Parties {
+    buyer: KeyPair,
+    seller: KeyPair,
+    arbiter: KeyPair,
+}
+
+impl Parties {
+    fn new() -> Parties {
+        Parties {
+            buyer: key_pair(1),
+            seller: key_pair(2),
+            arbiter: key_pair(3),
+        }
+    }
+
+    fn contract(&self, timelock: u32) -> EscrowContract {
+        EscrowContract {
+            buyer_key: self.buyer.x_only_public_key().0,
+            seller_key: self.seller.x_only_public_key().0,
+            arbiter_key: self.arbiter.x_only_public_key().0,
+            timelock,
+        }
+    }
+}
+
+async fn new_fed() -> FakeFed<Escrow> {
+    FakeFed::<Escrow>::new(
+        4,
+        |cfg, _db| async move { Ok(Escrow::new(cfg.to_typed()?)) },
+        &ConfigGenParams::null(),
+        &EscrowGen,
+        ESCROW_INSTANCE_ID,
+    )
+    .await
+    .unwrap()
+}
+
+/// Funds `contract` with `amount` and checks that it is locked afterwards
+async fn fund(fed: &mut FakeFed<Escrow>, contract: &EscrowContract, amount: Amount) {
+    let funding = out_point(b"fund");
+    let output = EscrowOutput::Fund {
+        amount,
+        contract: contract.clone(),
+    };
+    fed.consensus_round(&[], &[(funding, output)]).await;
+    assert_eq!(
+        fed.output_outcome(funding).await,
+        Some(EscrowOutputOutcome {
+            escrow_id: contract.escrow_id(),
+            state: EscrowState::Locked,
+        })
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn released_escrow_can_only_be_claimed_by_seller() {
+    let mut fed = new_fed().await;
+    let parties = Parties::new();
+    let contract = parties.contract(100);
+    let amount = Amount::from_sats(1_000);
+    fund(&mut fed, &contract, amount).await;
+
+    let claim = EscrowInput {
+        escrow_id: contract.escrow_id(),
+        amount,
+    };
+    let err = fed.verify_input(&claim).await.unwrap_err();
+    assert_eq!(
+        format!("{err}"),
+        format!("{}", EscrowError::EscrowLocked(100))
+    );
+
+    // Only the buyer may release the funds to the seller
+    let forged_release = EscrowOutput::Release {
+        escrow_id: contract.escrow_id(),
+        buyer_signature: sign(&parties.seller, contract.release_message()),
+    };
+    assert!(fed.verify_output(&forged_release).await);
+
+    let release = EscrowOutput::Release {
+        escrow_id: contract.escrow_id(),
+        buyer_signature: sign(&parties.buyer, contract.release_message()),
+    };
+    assert!(!fed.verify_output(&release).await);
+    fed.consensus_round(&[], &[(out_point(b"release"), release)])
+        .await;
+
+    let meta = fed.verify_input(&claim).await.unwrap();
+    assert_eq!(meta.keys, vec![parties.seller.x_only_public_key().0]);
+
+    // A settled escrow can't be settled a second time
+    let refund = EscrowOutput::Refund {
+        escrow_id: contract.escrow_id(),
+        seller_signature: sign(&parties.seller, contract.refund_message()),
+    };
+    assert!(fed.verify_output(&refund).await);
+
+    // Claiming empties the escrow, so it can't be claimed twice
+    fed.consensus_round(&[claim.clone()], &[]).await;
+    assert!(fed.verify_input(&claim).await.is_err());
+}
+
+#[test_log::test(tokio::test)]
+async fn arbiter_signs_for_one_beneficiary() {
+    let mut fed = new_fed().await;
+    let parties = Parties::new();
+    let contract = parties.contract(100);
+    let amount = Amount::from_sats(1_000);
+    fund(&mut fed, &contract, amount).await;
+
+    // The signature only covers the beneficiary it was made for
+    let arbitration = EscrowOutput::Arbitrate {
+        escrow_id: contract.escrow_id(),
+        beneficiary: EscrowParty::Buyer,
+        arbiter_signature: sign(
+            &parties.arbiter,
+            contract.arbitration_message(EscrowParty::Seller),
+        ),
+    };
+    assert!(fed.verify_output(&arbitration).await);
+
+    let arbitration = EscrowOutput::Arbitrate {
+        escrow_id: contract.escrow_id(),
+        beneficiary: EscrowParty::Buyer,
+        arbiter_signature: sign(
+            &parties.arbiter,
+            contract.arbitration_message(EscrowParty::Buyer),
+        ),
+    };
+    let arbitrated = out_point(b"arbitrate");
+    fed.consensus_round(&[], &[(arbitrated, arbitration)]).await;
+    assert_eq!(
+        fed.output_outcome(arbitrated).await.unwrap().state,
+        EscrowState::Settled(EscrowParty::Buyer)
+    );
+
+    let meta = fed
+        .verify_input(&EscrowInput {
+            escrow_id: contract.escrow_id(),
+            amount,
+        })
+        .await
+        .unwrap();
+    assert_eq!(meta.keys, vec![parties.buyer.x_only_public_key().0]);
+}
+
+#[test_log::test(tokio::test)]
+async fn buyer_claims_back_after_timelock() {
+    let mut fed = new_fed().await;
+    let parties = Parties::new();
+    let contract = parties.contract(100);
+    let amount = Amount::from_sats(1_000);
+    fund(&mut fed, &contract, amount).await;
+
+    // Escrows can only be claimed in full
+    let partial_claim = EscrowInput {
+        escrow_id: contract.escrow_id(),
+        amount: Amount::from_sats(500),
+    };
+    let err = fed.verify_input(&partial_claim).await.unwrap_err();
+    assert_eq!(
+        format!("{err}"),
+        format!(
+            "{}",
+            EscrowError::PartialClaim(amount, Amount::from_sats(500))
+        )
+    );
+
+    fed.set_block_height(100);
+    let meta = fed
+        .verify_input(&EscrowInput {
+            escrow_id: contract.escrow_id(),
+            amount,
+        })
+        .await
+        .unwrap();
+    assert_eq!(meta.keys, vec![parties.buyer.x_only_public_key().0]);
+}
+
+#[test_log::test(tokio::test)]
+async fn funding_rules() {
+    let fed = new_fed().await;
+    let parties = Parties::new();
+
+    let zero = EscrowOutput::Fund {
+        amount: Amount::ZERO,
+        contract: parties.contract(100),
+    };
+    assert!(fed.verify_output(&zero).await);
+
+    let mut same_parties = parties.contract(100);
+    same_parties.seller_key = same_parties.buyer_key;
+    let same_parties = EscrowOutput::Fund {
+        amount: Amount::from_sats(1_000),
+        contract: same_parties,
+    };
+    assert!(fed.verify_output(&same_parties).await);
+
+    let unknown = EscrowOutput::Refund {
+        escrow_id: parties.contract(100).escrow_id(),
+        seller_signature: sign(&parties.seller, parties.contract(100).refund_message()),
+    };
+    assert!(fed.verify_output(&unknown).await);
+}
@@ -93,6 +93,14 @@ impl TypedServerModuleConfig for LightningConfig {
     }
 }
 
+/// Name of the adjustable parameter replacing
+/// [`FeeConsensus::contract_input`], in msats
+pub const CONTRACT_INPUT_FEE_PARAM: &str = "contract_input_msats";
+
+/// Name of the adjustable parameter replacing
+/// [`FeeConsensus::contract_output`], in msats
+pub const CONTRACT_OUTPUT_FEE_PARAM: &str = "contract_output_msats";
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
 pub struct FeeConsensus {
     pub contract_input: fedimint_core::Amount,
@@ -4,7 +4,7 @@ use bitcoin_hashes::sha256::Hash as Sha256;
 use bitcoin_hashes::{hash_newtype, Hash as BitcoinHash};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::OutPoint;
+use fedimint_core::{Amount, OutPoint};
 use serde::{Deserialize, Serialize};
 
 use crate::contracts::{ContractId, DecryptedPreimage, EncryptedPreimage, IdentifiableContract};
@@ -16,12 +16,47 @@ pub struct IncomingContractOffer {
     pub hash: bitcoin_hashes::sha256::Hash,
     pub encrypted_preimage: EncryptedPreimage,
     pub expiry_time: Option<u64>,
+    /// Part of `amount` the gateway funding the contract earns for it, it can
+    /// claim it from the contract once the preimage was decrypted. Offers
+    /// have to sell the preimage for more than this fee.
+    ///
+    /// Only accepted from [`GATEWAY_FEE_VERSION`](crate::GATEWAY_FEE_VERSION)
+    /// on.
+    #[serde(default)]
+    pub gateway_fee: fedimint_core::Amount,
 }
 
 impl IncomingContractOffer {
     pub fn id(&self) -> OfferId {
         OfferId::from_hash(self.hash)
     }
+
+    /// Encodes the offer like before offers paid the gateway a fee
+    pub(crate) fn consensus_encode_without_fee<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, Error> {
+        let mut len = 0;
+        len += self.amount.consensus_encode(writer)?;
+        len += self.hash.consensus_encode(writer)?;
+        len += self.encrypted_preimage.consensus_encode(writer)?;
+        len += self.expiry_time.consensus_encode(writer)?;
+        Ok(len)
+    }
+
+    /// Decodes an offer encoded without its gateway fee, which is zero
+    pub(crate) fn consensus_decode_without_fee<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(IncomingContractOffer {
+            amount: Decodable::consensus_decode(d, modules)?,
+            hash: Decodable::consensus_decode(d, modules)?,
+            encrypted_preimage: Decodable::consensus_decode(d, modules)?,
+            expiry_time: Decodable::consensus_decode(d, modules)?,
+            gateway_fee: Amount::ZERO,
+        })
+    }
 }
 
 // FIXME: the protocol currently envisions the use of a pub key as preimage.
@@ -66,6 +101,42 @@ pub struct IncomingContract {
     pub decrypted_preimage: DecryptedPreimage,
     /// Key that can unlock contract in case the decrypted preimage was invalid
     pub gateway_key: secp256k1::XOnlyPublicKey,
+    /// Gateway fee as specified in offer, which the gateway can still claim
+    /// with the decrypted preimage. It is zero once the gateway claimed it.
+    ///
+    /// Only accepted from [`GATEWAY_FEE_VERSION`](crate::GATEWAY_FEE_VERSION)
+    /// on.
+    #[serde(default)]
+    pub gateway_fee: fedimint_core::Amount,
+}
+
+impl IncomingContract {
+    /// Encodes the contract like before offers paid the gateway a fee
+    pub(crate) fn consensus_encode_without_fee<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, Error> {
+        let mut len = 0;
+        len += self.hash.consensus_encode(writer)?;
+        len += self.encrypted_preimage.consensus_encode(writer)?;
+        len += self.decrypted_preimage.consensus_encode(writer)?;
+        len += self.gateway_key.consensus_encode(writer)?;
+        Ok(len)
+    }
+
+    /// Decodes a contract encoded without its gateway fee, which is zero
+    pub(crate) fn consensus_decode_without_fee<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        Ok(IncomingContract {
+            hash: Decodable::consensus_decode(d, modules)?,
+            encrypted_preimage: Decodable::consensus_decode(d, modules)?,
+            decrypted_preimage: Decodable::consensus_decode(d, modules)?,
+            gateway_key: Decodable::consensus_decode(d, modules)?,
+            gateway_fee: Amount::ZERO,
+        })
+    }
 }
 
 /// The funded version of an [`IncomingContract`] contains the [`OutPoint`] of
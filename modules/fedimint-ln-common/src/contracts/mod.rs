@@ -7,7 +7,7 @@ use bitcoin_hashes::sha256::Hash as Sha256;
 use bitcoin_hashes::{hash_newtype, Hash as BitcoinHash};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::OutPoint;
+use fedimint_core::{Amount, OutPoint};
 use serde::{Deserialize, Serialize};
 
 /// Anything representing a contract which thus has an associated [`ContractId`]
@@ -23,14 +23,60 @@ hash_newtype!(
 );
 
 /// A contract before execution as found in transaction outputs
+///
+/// Incoming contracts without a gateway fee encode like before the fee was
+/// added, ones with a fee use a variant of their own
 // TODO: investigate if this is actually a problem
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub enum Contract {
     Incoming(incoming::IncomingContract),
     Outgoing(outgoing::OutgoingContract),
 }
 
+impl Encodable for Contract {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut len = 0;
+        match self {
+            Contract::Incoming(incoming) => {
+                let with_fee = incoming.gateway_fee != Amount::ZERO;
+                let variant: u64 = if with_fee { 2 } else { 0 };
+                len += variant.consensus_encode(writer)?;
+                len += incoming.consensus_encode_without_fee(writer)?;
+                if with_fee {
+                    len += incoming.gateway_fee.consensus_encode(writer)?;
+                }
+            }
+            Contract::Outgoing(outgoing) => {
+                len += 1u64.consensus_encode(writer)?;
+                len += outgoing.consensus_encode(writer)?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for Contract {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        match u64::consensus_decode(d, modules)? {
+            0 => Ok(Contract::Incoming(
+                incoming::IncomingContract::consensus_decode_without_fee(d, modules)?,
+            )),
+            1 => Ok(Contract::Outgoing(Decodable::consensus_decode(d, modules)?)),
+            2 => {
+                let mut incoming =
+                    incoming::IncomingContract::consensus_decode_without_fee(d, modules)?;
+                incoming.gateway_fee = Decodable::consensus_decode(d, modules)?;
+                Ok(Contract::Incoming(incoming))
+            }
+            _ => Err(DecodeError::from_str("Invalid contract variant")),
+        }
+    }
+}
+
 /// A contract after execution as saved in the database
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
@@ -1,12 +1,22 @@
+use std::time::SystemTime;
+
+use fedimint_core::db::DatabaseTransaction;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{impl_db_lookup, impl_db_record, OutPoint, PeerId};
+use fedimint_core::epoch::ModuleParams;
+use fedimint_core::module::ModuleConsensusVersion;
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, OutPoint, PeerId};
+use futures::StreamExt;
 use secp256k1::PublicKey;
 use serde::Serialize;
 use strum_macros::EnumIter;
+use url::Url;
 
-use crate::contracts::incoming::IncomingContractOffer;
-use crate::contracts::{ContractId, PreimageDecryptionShare};
-use crate::{ContractAccount, LightningGateway, LightningOutputOutcome};
+use crate::contracts::incoming::{FundedIncomingContract, IncomingContract, IncomingContractOffer};
+use crate::contracts::outgoing::OutgoingContract;
+use crate::contracts::{
+    ContractId, DecryptedPreimage, EncryptedPreimage, FundedContract, PreimageDecryptionShare,
+};
+use crate::{route_hints, ContractAccount, GatewayFee, LightningGateway, LightningOutputOutcome};
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -17,6 +27,8 @@ pub enum DbKeyPrefix {
     AgreedDecryptionShare = 0x43,
     ContractUpdate = 0x44,
     LightningGateway = 0x45,
+    Params = 0x46,
+    ConsensusVersion = 0x47,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -118,3 +130,257 @@ impl_db_lookup!(
     key = LightningGatewayKey,
     query_prefix = LightningGatewayKeyPrefix
 );
+
+/// Fees the guardians changed from the ones of the config, see
+/// [`crate::config::CONTRACT_INPUT_FEE_PARAM`] and
+/// [`crate::config::CONTRACT_OUTPUT_FEE_PARAM`]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ParamsKey;
+
+impl_db_record!(
+    key = ParamsKey,
+    value = ModuleParams,
+    db_prefix = DbKeyPrefix::Params,
+);
+
+/// Consensus version the lightning module runs at, version 0 until the
+/// federation activates a newer one
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ConsensusVersionKey;
+
+impl_db_record!(
+    key = ConsensusVersionKey,
+    value = ModuleConsensusVersion,
+    db_prefix = DbKeyPrefix::ConsensusVersion,
+);
+
+/// [`LightningGateway`] before gateways announced their fees
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct LightningGatewayV0 {
+    pub mint_channel_id: u64,
+    pub mint_pub_key: secp256k1::XOnlyPublicKey,
+    pub node_pub_key: secp256k1::PublicKey,
+    pub api: Url,
+    pub route_hints: Vec<route_hints::RouteHint>,
+    pub valid_until: SystemTime,
+}
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct LightningGatewayKeyV0(pub PublicKey);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LightningGatewayKeyPrefixV0;
+
+impl_db_record!(
+    key = LightningGatewayKeyV0,
+    value = LightningGatewayV0,
+    db_prefix = DbKeyPrefix::LightningGateway,
+);
+impl_db_lookup!(
+    key = LightningGatewayKeyV0,
+    query_prefix = LightningGatewayKeyPrefixV0
+);
+
+/// [`LightningGateway`] before gateways reported their liquidity
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct LightningGatewayV1 {
+    pub mint_channel_id: u64,
+    pub mint_pub_key: secp256k1::XOnlyPublicKey,
+    pub node_pub_key: secp256k1::PublicKey,
+    pub api: Url,
+    pub route_hints: Vec<route_hints::RouteHint>,
+    pub fees: GatewayFee,
+    pub valid_until: SystemTime,
+}
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct LightningGatewayKeyV1(pub PublicKey);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct LightningGatewayKeyPrefixV1;
+
+impl_db_record!(
+    key = LightningGatewayKeyV1,
+    value = LightningGatewayV1,
+    db_prefix = DbKeyPrefix::LightningGateway,
+);
+impl_db_lookup!(
+    key = LightningGatewayKeyV1,
+    query_prefix = LightningGatewayKeyPrefixV1
+);
+
+/// Migrates the Lightning module's database from version 0 to version 1,
+/// which added [`LightningGatewayV1::fees`]. Gateways announced before didn't
+/// charge fees.
+pub async fn migrate_ln_db_version_0<'a, 'b>(
+    dbtx: &'b mut DatabaseTransaction<'a>,
+) -> Result<(), anyhow::Error> {
+    let gateways_v0 = dbtx
+        .find_by_prefix(&LightningGatewayKeyPrefixV0)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    dbtx.remove_by_prefix(&LightningGatewayKeyPrefixV0).await;
+    for (key, gateway) in gateways_v0 {
+        let gateway = LightningGatewayV1 {
+            mint_channel_id: gateway.mint_channel_id,
+            mint_pub_key: gateway.mint_pub_key,
+            node_pub_key: gateway.node_pub_key,
+            api: gateway.api,
+            route_hints: gateway.route_hints,
+            fees: GatewayFee::default(),
+            valid_until: gateway.valid_until,
+        };
+        dbtx.insert_new_entry(&LightningGatewayKeyV1(key.0), &gateway)
+            .await;
+    }
+    Ok(())
+}
+
+/// Migrates the Lightning module's database from version 1 to version 2,
+/// which added [`LightningGateway::liquidity`]. Gateways announced before
+/// didn't report any.
+pub async fn migrate_ln_db_version_1<'a, 'b>(
+    dbtx: &'b mut DatabaseTransaction<'a>,
+) -> Result<(), anyhow::Error> {
+    let gateways_v1 = dbtx
+        .find_by_prefix(&LightningGatewayKeyPrefixV1)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    dbtx.remove_by_prefix(&LightningGatewayKeyPrefixV1).await;
+    for (key, gateway) in gateways_v1 {
+        let gateway = LightningGateway {
+            mint_channel_id: gateway.mint_channel_id,
+            mint_pub_key: gateway.mint_pub_key,
+            node_pub_key: gateway.node_pub_key,
+            api: gateway.api,
+            route_hints: gateway.route_hints,
+            fees: gateway.fees,
+            liquidity: None,
+            valid_until: gateway.valid_until,
+        };
+        dbtx.insert_new_entry(&LightningGatewayKey(key.0), &gateway)
+            .await;
+    }
+    Ok(())
+}
+
+/// [`IncomingContractOffer`] before offers paid the gateway a fee
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct IncomingContractOfferV0 {
+    pub amount: Amount,
+    pub hash: bitcoin_hashes::sha256::Hash,
+    pub encrypted_preimage: EncryptedPreimage,
+    pub expiry_time: Option<u64>,
+}
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OfferKeyV0(pub bitcoin_hashes::sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OfferKeyPrefixV0;
+
+impl_db_record!(
+    key = OfferKeyV0,
+    value = IncomingContractOfferV0,
+    db_prefix = DbKeyPrefix::Offer,
+);
+impl_db_lookup!(key = OfferKeyV0, query_prefix = OfferKeyPrefixV0);
+
+/// [`IncomingContract`] before offers paid the gateway a fee
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct IncomingContractV0 {
+    pub hash: bitcoin_hashes::sha256::Hash,
+    pub encrypted_preimage: EncryptedPreimage,
+    pub decrypted_preimage: DecryptedPreimage,
+    pub gateway_key: secp256k1::XOnlyPublicKey,
+}
+
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct FundedIncomingContractV0 {
+    pub contract: IncomingContractV0,
+    pub out_point: OutPoint,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub enum FundedContractV0 {
+    Incoming(FundedIncomingContractV0),
+    Outgoing(OutgoingContract),
+}
+
+/// [`ContractAccount`] before offers paid the gateway a fee
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ContractAccountV0 {
+    pub amount: Amount,
+    pub contract: FundedContractV0,
+}
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ContractKeyV0(pub ContractId);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+pub struct ContractKeyPrefixV0;
+
+impl_db_record!(
+    key = ContractKeyV0,
+    value = ContractAccountV0,
+    db_prefix = DbKeyPrefix::Contract,
+);
+impl_db_lookup!(key = ContractKeyV0, query_prefix = ContractKeyPrefixV0);
+
+/// Migrates the Lightning module's database from version 2 to version 3,
+/// which added [`IncomingContractOffer::gateway_fee`] and
+/// [`IncomingContract::gateway_fee`]. Offers and contracts created before
+/// didn't pay the gateway a fee.
+pub async fn migrate_ln_db_version_2<'a, 'b>(
+    dbtx: &'b mut DatabaseTransaction<'a>,
+) -> Result<(), anyhow::Error> {
+    let offers_v0 = dbtx
+        .find_by_prefix(&OfferKeyPrefixV0)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    dbtx.remove_by_prefix(&OfferKeyPrefixV0).await;
+    for (key, offer) in offers_v0 {
+        let offer = IncomingContractOffer {
+            amount: offer.amount,
+            hash: offer.hash,
+            encrypted_preimage: offer.encrypted_preimage,
+            expiry_time: offer.expiry_time,
+            gateway_fee: Amount::ZERO,
+        };
+        dbtx.insert_new_entry(&OfferKey(key.0), &offer).await;
+    }
+
+    let contracts_v0 = dbtx
+        .find_by_prefix(&ContractKeyPrefixV0)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    dbtx.remove_by_prefix(&ContractKeyPrefixV0).await;
+    for (key, account) in contracts_v0 {
+        let contract = match account.contract {
+            FundedContractV0::Incoming(incoming) => {
+                FundedContract::Incoming(FundedIncomingContract {
+                    contract: IncomingContract {
+                        hash: incoming.contract.hash,
+                        encrypted_preimage: incoming.contract.encrypted_preimage,
+                        decrypted_preimage: incoming.contract.decrypted_preimage,
+                        gateway_key: incoming.contract.gateway_key,
+                        gateway_fee: Amount::ZERO,
+                    },
+                    out_point: incoming.out_point,
+                })
+            }
+            FundedContractV0::Outgoing(outgoing) => FundedContract::Outgoing(outgoing),
+        };
+        let account = ContractAccount {
+            amount: account.amount,
+            contract,
+        };
+        dbtx.insert_new_entry(&ContractKey(key.0), &account).await;
+    }
+    Ok(())
+}
@@ -15,8 +15,9 @@ pub mod db;
 use std::time::SystemTime;
 
 use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
-use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::module::{CommonModuleGen, ModuleCommon};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::{ApiVersion, CommonModuleGen, ModuleCommon, ModuleConsensusVersion};
 use fedimint_core::{plugin_types_trait_impl_common, Amount};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -24,10 +25,27 @@ use tracing::error;
 use url::Url;
 
 use crate::config::LightningClientConfig;
-use crate::contracts::incoming::OfferId;
+use crate::contracts::incoming::{IncomingContractOffer, OfferId};
 use crate::contracts::{Contract, ContractId, ContractOutcome, Preimage, PreimageDecryptionShare};
 const KIND: ModuleKind = ModuleKind::from_static_str("ln");
 
+/// Consensus version of the lightning module, has to be bumped when the
+/// encoding of its types changes
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = GATEWAY_FEE_VERSION;
+
+/// Consensus version that added offers and incoming contracts paying the
+/// gateway a [fee](IncomingContractOffer::gateway_fee), guardians reject them
+/// and the claims of the fees until the federation runs it
+pub const GATEWAY_FEE_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(1);
+
+/// API versions of the lightning module's endpoints, one per supported major
+/// version, minor version 2 added `/consensus_version`
+pub const API_VERSIONS: &[ApiVersion] = &[ApiVersion { major: 0, minor: 2 }];
+
+/// API version that added `/list_gateways_page`, `/list_gateways` keeps
+/// returning all gateways at once for older clients
+pub const PAGINATION_API_VERSION: ApiVersion = ApiVersion { major: 0, minor: 1 };
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct LightningInput {
     pub contract_id: contracts::ContractId,
@@ -62,7 +80,10 @@ impl std::fmt::Display for LightningInput {
 /// of transactions we let offers be a 0-amount output. We need to take care to
 /// allow 0-input, 1-output transactions for that to allow users to receive
 /// their fist notes via LN without already having notes.
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+///
+/// Offers without a gateway fee encode like before the fee was added, offers
+/// with one use a variant of their own.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub enum LightningOutput {
     /// Fund contract
     Contract(ContractOutput),
@@ -77,6 +98,62 @@ pub enum LightningOutput {
     },
 }
 
+impl Encodable for LightningOutput {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let mut len = 0;
+        match self {
+            LightningOutput::Contract(contract) => {
+                len += 0u64.consensus_encode(writer)?;
+                len += contract.consensus_encode(writer)?;
+            }
+            LightningOutput::Offer(offer) => {
+                let with_fee = offer.gateway_fee != Amount::ZERO;
+                let variant: u64 = if with_fee { 3 } else { 1 };
+                len += variant.consensus_encode(writer)?;
+                len += offer.consensus_encode_without_fee(writer)?;
+                if with_fee {
+                    len += offer.gateway_fee.consensus_encode(writer)?;
+                }
+            }
+            LightningOutput::CancelOutgoing {
+                contract,
+                gateway_signature,
+            } => {
+                len += 2u64.consensus_encode(writer)?;
+                len += contract.consensus_encode(writer)?;
+                len += gateway_signature.consensus_encode(writer)?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for LightningOutput {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        match u64::consensus_decode(d, modules)? {
+            0 => Ok(LightningOutput::Contract(Decodable::consensus_decode(
+                d, modules,
+            )?)),
+            1 => Ok(LightningOutput::Offer(
+                IncomingContractOffer::consensus_decode_without_fee(d, modules)?,
+            )),
+            2 => Ok(LightningOutput::CancelOutgoing {
+                contract: Decodable::consensus_decode(d, modules)?,
+                gateway_signature: Decodable::consensus_decode(d, modules)?,
+            }),
+            3 => {
+                let mut offer = IncomingContractOffer::consensus_decode_without_fee(d, modules)?;
+                offer.gateway_fee = Decodable::consensus_decode(d, modules)?;
+                Ok(LightningOutput::Offer(offer))
+            }
+            _ => Err(DecodeError::from_str("Invalid lightning output variant")),
+        }
+    }
+}
+
 impl std::fmt::Display for LightningOutput {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -166,10 +243,70 @@ pub struct LightningGateway {
     /// These will be appended with the route hint of the recipient's virtual
     /// channel. To keeps invoices small these should be used sparingly.
     pub route_hints: Vec<route_hints::RouteHint>,
+    /// Fees the gateway charges for payments to the federation, payers pay
+    /// them on the last hop to the recipient's virtual channel
+    #[serde(default)]
+    pub fees: GatewayFee,
+    /// Liquidity the LN node of the gateway reported when it registered,
+    /// `None` if it didn't report any
+    pub liquidity: Option<GatewayLiquidity>,
     /// Limits the validity of the announcement to allow updates
     pub valid_until: SystemTime,
 }
 
+impl LightningGateway {
+    /// Returns false if the gateway reported that it can't receive a payment
+    /// of `amount` over lightning, so it can't fund incoming contracts of it
+    pub fn can_receive(&self, amount: Amount) -> bool {
+        self.liquidity
+            .map_or(true, |liquidity| amount <= liquidity.max_receivable)
+    }
+
+    /// Returns false if the gateway reported that it can't send a payment of
+    /// `amount` over lightning, so it can't pay invoices of it
+    pub fn can_pay(&self, amount: Amount) -> bool {
+        self.liquidity
+            .map_or(true, |liquidity| amount <= liquidity.max_payable)
+    }
+}
+
+/// Self-reported liquidity of a gateway's LN node, derived from the balances
+/// of its channels
+///
+/// The liquidity can change any time after the gateway registered, so it only
+/// rules out gateways that can't possibly route a payment.
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, Encodable, Decodable, PartialEq, Eq, Hash,
+)]
+pub struct GatewayLiquidity {
+    /// Sum of the remote balances of the node's channels
+    pub max_receivable: Amount,
+    /// Sum of the local balances of the node's channels
+    pub max_payable: Amount,
+}
+
+/// Routing fees of a gateway, in the format of lightning channel policies
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, Encodable, Decodable, PartialEq, Eq, Hash,
+)]
+pub struct GatewayFee {
+    /// Flat fee in millisatoshis
+    pub base_msat: u32,
+    /// Fee in millionths of the forwarded amount
+    pub proportional_millionths: u32,
+}
+
+impl GatewayFee {
+    /// Fee for forwarding `amount`
+    pub fn amount(&self, amount: Amount) -> Amount {
+        let proportional =
+            u128::from(amount.msats) * u128::from(self.proportional_millionths) / 1_000_000;
+        Amount::from_msats(
+            u64::from(self.base_msat).saturating_add(proportional.try_into().unwrap_or(u64::MAX)),
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Encodable, Decodable, Serialize, Deserialize)]
 pub struct LightningConsensusItem {
     pub contract_id: ContractId,
@@ -283,13 +420,118 @@ pub enum LightningError {
     #[error("Offer contains invalid threshold-encrypted data")]
     InvalidEncryptedPreimage,
     #[error(
-        "The incoming LN account requires more funding according to the offer (need {0} got {1})"
+        "The incoming LN account must be funded with the amount of its offer (need {0} got {1})"
     )]
-    InsufficientIncomingFunding(Amount, Amount),
+    IncomingFundingMismatch(Amount, Amount),
+    #[error("The incoming LN account must charge the gateway fee of its offer (need {0} got {1})")]
+    IncomingGatewayFeeMismatch(Amount, Amount),
     #[error("No offer found for payment hash {0}")]
     NoOffer(secp256k1::hashes::sha256::Hash),
     #[error("Only outgoing contracts support cancellation")]
     NotOutgoingContract,
     #[error("Cancellation request wasn't properly signed")]
     InvalidCancellationSignature,
+    #[error("The offer sells the preimage for {0}, which doesn't exceed its gateway fee {1}")]
+    OfferBelowGatewayFee(Amount, Amount),
+    #[error("The gateway fee claim has to spend the unclaimed fee {0}")]
+    InvalidGatewayFeeClaim(Amount),
+    #[error("Gateway fees need lightning consensus version {0:?}, which isn't active yet")]
+    GatewayFeesInactive(ModuleConsensusVersion),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitcoin_hashes::Hash;
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::Amount;
+    use secp256k1::constants::GENERATOR_X;
+    use secp256k1::XOnlyPublicKey;
+    use threshold_crypto::group::Curve;
+    use threshold_crypto::G1Projective;
+
+    use super::{ContractOutput, LightningOutput};
+    use crate::contracts::incoming::{IncomingContract, IncomingContractOffer};
+    use crate::contracts::{Contract, DecryptedPreimage, EncryptedPreimage, Preimage};
+    use crate::db::{IncomingContractOfferV0, IncomingContractV0};
+
+    fn decode<T: Decodable>(bytes: &[u8]) -> Result<T, fedimint_core::encoding::DecodeError> {
+        T::consensus_decode(&mut Cursor::new(bytes), &ModuleDecoderRegistry::default())
+    }
+
+    fn offer(gateway_fee: Amount) -> IncomingContractOffer {
+        let threshold_pub_key = threshold_crypto::PublicKey::from_bytes(
+            G1Projective::generator().to_affine().to_compressed(),
+        )
+        .unwrap();
+        IncomingContractOffer {
+            amount: Amount::from_sats(42),
+            hash: bitcoin_hashes::sha256::Hash::hash(&[2; 32]),
+            encrypted_preimage: EncryptedPreimage::new(Preimage([2; 32]), &threshold_pub_key),
+            expiry_time: Some(3600),
+            gateway_fee,
+        }
+    }
+
+    fn contract(offer: &IncomingContractOffer) -> IncomingContract {
+        IncomingContract {
+            hash: offer.hash,
+            encrypted_preimage: offer.encrypted_preimage.clone(),
+            decrypted_preimage: DecryptedPreimage::Pending,
+            gateway_key: XOnlyPublicKey::from_slice(&GENERATOR_X).unwrap(),
+            gateway_fee: offer.gateway_fee,
+        }
+    }
+
+    #[test]
+    fn outputs_without_gateway_fee_encode_like_before() {
+        let offer = offer(Amount::ZERO);
+        let legacy_offer = IncomingContractOfferV0 {
+            amount: offer.amount,
+            hash: offer.hash,
+            encrypted_preimage: offer.encrypted_preimage.clone(),
+            expiry_time: offer.expiry_time,
+        };
+        let mut expected = 1u64.consensus_encode_to_vec().unwrap();
+        expected.extend(legacy_offer.consensus_encode_to_vec().unwrap());
+        assert_eq!(
+            LightningOutput::Offer(offer.clone())
+                .consensus_encode_to_vec()
+                .unwrap(),
+            expected
+        );
+
+        let contract = contract(&offer);
+        let legacy_contract = IncomingContractV0 {
+            hash: contract.hash,
+            encrypted_preimage: contract.encrypted_preimage.clone(),
+            decrypted_preimage: contract.decrypted_preimage.clone(),
+            gateway_key: contract.gateway_key,
+        };
+        let mut expected = 0u64.consensus_encode_to_vec().unwrap();
+        expected.extend(legacy_contract.consensus_encode_to_vec().unwrap());
+        assert_eq!(
+            Contract::Incoming(contract)
+                .consensus_encode_to_vec()
+                .unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn lightning_outputs_decode_as_encoded() {
+        for gateway_fee in [Amount::ZERO, Amount::from_sats(2)] {
+            let offer = offer(gateway_fee);
+            let contract = LightningOutput::Contract(ContractOutput {
+                amount: offer.amount,
+                contract: Contract::Incoming(contract(&offer)),
+            });
+            for output in [LightningOutput::Offer(offer), contract] {
+                let bytes = output.consensus_encode_to_vec().unwrap();
+                assert_eq!(decode::<LightningOutput>(&bytes).unwrap(), output);
+            }
+        }
+    }
 }
@@ -0,0 +1,108 @@
+//! Snapshots of the encodings of the lightning module's consensus types, see
+//! [`fedimint_core::encoding::compat`]
+
+use std::time::{Duration, SystemTime};
+
+use bitcoin_hashes::Hash;
+use fedimint_core::{encoding_snapshot_test, Amount};
+use fedimint_ln_common::config::{FeeConsensus, LightningClientConfig, LightningConfigConsensus};
+use fedimint_ln_common::contracts::incoming::OfferId;
+use fedimint_ln_common::contracts::{
+    ContractId, ContractOutcome, DecryptedPreimage, OutgoingContractOutcome, Preimage,
+};
+use fedimint_ln_common::route_hints::{RouteHint, RouteHintHop};
+use fedimint_ln_common::{
+    GatewayFee, GatewayLiquidity, LightningGateway, LightningInput, LightningOutput,
+    LightningOutputOutcome, CONSENSUS_VERSION,
+};
+use secp256k1::constants::GENERATOR_X;
+use secp256k1::{schnorr, PublicKey, XOnlyPublicKey};
+use threshold_crypto::group::Curve;
+use threshold_crypto::poly::Commitment;
+use threshold_crypto::{G1Projective, PublicKeySet};
+
+fn contract_id() -> ContractId {
+    ContractId::from_inner([1; 32])
+}
+
+fn preimage() -> Preimage {
+    Preimage([2; 32])
+}
+
+/// The generator, so the encodings can be reproduced without running the code
+fn node_pub_key() -> PublicKey {
+    PublicKey::from_slice(&[&[0x02][..], &GENERATOR_X[..]].concat()).unwrap()
+}
+
+fn threshold_pub_keys() -> PublicKeySet {
+    PublicKeySet::from(Commitment::from(vec![G1Projective::generator()]))
+}
+
+fn fee_consensus() -> FeeConsensus {
+    FeeConsensus {
+        contract_input: Amount::from_msats(100),
+        contract_output: Amount::from_msats(200),
+    }
+}
+
+fn gateway() -> LightningGateway {
+    LightningGateway {
+        mint_channel_id: 1,
+        mint_pub_key: XOnlyPublicKey::from_slice(&GENERATOR_X).unwrap(),
+        node_pub_key: node_pub_key(),
+        api: "http://gateway.example:8175".parse().unwrap(),
+        route_hints: vec![RouteHint(vec![RouteHintHop {
+            src_node_id: node_pub_key(),
+            short_channel_id: 2,
+            base_msat: 1_000,
+            proportional_millionths: 100,
+            cltv_expiry_delta: 40,
+            htlc_minimum_msat: Some(1_000),
+            htlc_maximum_msat: None,
+        }])],
+        fees: GatewayFee {
+            base_msat: 1_000,
+            proportional_millionths: 100,
+        },
+        liquidity: Some(GatewayLiquidity {
+            max_receivable: Amount::from_msats(1_000_000),
+            max_payable: Amount::from_msats(2_000_000),
+        }),
+        valid_until: SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000),
+    }
+}
+
+encoding_snapshot_test!(ln_encodings, CONSENSUS_VERSION.0, {
+    "LightningInput" => LightningInput {
+        contract_id: contract_id(),
+        amount: Amount::from_msats(1_000),
+        witness: Some(preimage()),
+    },
+    "LightningOutput::CancelOutgoing" => LightningOutput::CancelOutgoing {
+        contract: contract_id(),
+        gateway_signature: schnorr::Signature::from_slice(&[1; 64]).unwrap(),
+    },
+    "LightningOutputOutcome::Contract::Incoming" => LightningOutputOutcome::Contract {
+        id: contract_id(),
+        outcome: ContractOutcome::Incoming(DecryptedPreimage::Some(preimage())),
+    },
+    "LightningOutputOutcome::Contract::Outgoing" => LightningOutputOutcome::Contract {
+        id: contract_id(),
+        outcome: ContractOutcome::Outgoing(OutgoingContractOutcome {}),
+    },
+    "LightningOutputOutcome::Offer" => LightningOutputOutcome::Offer {
+        id: OfferId::from_inner([3; 32]),
+    },
+    "LightningGateway" => gateway(),
+    "LightningClientConfig" => LightningClientConfig {
+        threshold_pub_key: threshold_crypto::PublicKey::from_bytes(
+            G1Projective::generator().to_affine().to_compressed(),
+        )
+        .unwrap(),
+        fee_consensus: fee_consensus(),
+    },
+    "LightningConfigConsensus" => LightningConfigConsensus {
+        threshold_pub_keys: threshold_pub_keys(),
+        fee_consensus: fee_consensus(),
+    },
+});
@@ -1,15 +1,16 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::OsString;
 use std::ops::Sub;
 
 use bitcoin_hashes::Hash as BitcoinHash;
 use fedimint_core::config::{
-    ConfigGenParams, DkgResult, ModuleConfigResponse, ServerModuleConfig, TypedServerModuleConfig,
-    TypedServerModuleConsensusConfig,
+    ConfigGenParams, DkgResult, ModuleConfigResponse, ServerModuleConfig,
+    ServerModuleReshareConfig, TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
-use fedimint_core::core::{ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_WALLET};
-use fedimint_core::db::{Database, DatabaseVersion, ModuleDatabaseTransaction};
+use fedimint_core::core::{ModuleInstanceId, ModuleKind, LEGACY_HARDCODED_INSTANCE_ID_WALLET};
+use fedimint_core::db::{Database, DatabaseVersion, MigrationMap, ModuleDatabaseTransaction};
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::ModuleParams;
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::interconnect::ModuleInterconect;
 use fedimint_core::module::{
@@ -17,6 +18,7 @@ use fedimint_core::module::{
     CoreConsensusVersion, ExtendsCommonModuleGen, InputMeta, IntoModuleError,
     ModuleConsensusVersion, ModuleError, PeerHandle, ServerModuleGen, TransactionItemAmount,
 };
+use fedimint_core::pagination::{Page, PageRequest};
 use fedimint_core::server::DynServerModule;
 use fedimint_core::task::TaskGroup;
 use fedimint_core::{
@@ -26,6 +28,7 @@ use fedimint_core::{
 pub use fedimint_ln_common as common;
 use fedimint_ln_common::config::{
     FeeConsensus, LightningConfig, LightningConfigConsensus, LightningConfigPrivate,
+    CONTRACT_INPUT_FEE_PARAM, CONTRACT_OUTPUT_FEE_PARAM,
 };
 use fedimint_ln_common::contracts::incoming::IncomingContractOffer;
 use fedimint_ln_common::contracts::{
@@ -33,22 +36,24 @@ use fedimint_ln_common::contracts::{
     IdentifiableContract, Preimage, PreimageDecryptionShare,
 };
 use fedimint_ln_common::db::{
-    AgreedDecryptionShareKey, AgreedDecryptionShareKeyPrefix, ContractKey, ContractKeyPrefix,
-    ContractUpdateKey, ContractUpdateKeyPrefix, DbKeyPrefix, LightningGatewayKey,
-    LightningGatewayKeyPrefix, OfferKey, OfferKeyPrefix, ProposeDecryptionShareKey,
-    ProposeDecryptionShareKeyPrefix,
+    migrate_ln_db_version_0, migrate_ln_db_version_1, migrate_ln_db_version_2,
+    AgreedDecryptionShareKey, AgreedDecryptionShareKeyPrefix, ConsensusVersionKey, ContractKey,
+    ContractKeyPrefix, ContractUpdateKey, ContractUpdateKeyPrefix, DbKeyPrefix,
+    LightningGatewayKey, LightningGatewayKeyPrefix, OfferKey, OfferKeyPrefix, ParamsKey,
+    ProposeDecryptionShareKey, ProposeDecryptionShareKeyPrefix,
 };
 use fedimint_ln_common::{
     ContractAccount, LightningCommonGen, LightningConsensusItem, LightningError, LightningGateway,
-    LightningInput, LightningModuleTypes, LightningOutput, LightningOutputOutcome,
+    LightningInput, LightningModuleTypes, LightningOutput, LightningOutputOutcome, API_VERSIONS,
+    CONSENSUS_VERSION, GATEWAY_FEE_VERSION,
 };
-use fedimint_server::config::distributedgen::PeerHandleOps;
-use futures::StreamExt;
+use fedimint_server::config::distributedgen::{PeerHandleOps, ReshareKeys};
+use futures::{FutureExt, StreamExt};
 use itertools::Itertools;
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
-use tracing::{debug, error, info_span, instrument, trace, warn};
+use tracing::{debug, error, info, info_span, instrument, trace, warn};
 
 #[derive(Debug, Clone)]
 pub struct LightningGen;
@@ -59,10 +64,14 @@ impl ExtendsCommonModuleGen for LightningGen {
 
 #[apply(async_trait_maybe_send!)]
 impl ServerModuleGen for LightningGen {
-    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(3);
 
-    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
-        &[ModuleConsensusVersion(0)]
+    fn versions(&self, core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        if consensus_version_at(core) == CONSENSUS_VERSION {
+            &[CONSENSUS_VERSION]
+        } else {
+            &[ModuleConsensusVersion(0)]
+        }
     }
 
     async fn init(
@@ -75,6 +84,24 @@ impl ServerModuleGen for LightningGen {
         Ok(Lightning::new(cfg.to_typed()?).into())
     }
 
+    fn get_database_migrations(&self) -> MigrationMap {
+        let mut migrations = MigrationMap::new();
+
+        migrations.insert(DatabaseVersion(0), move |dbtx| {
+            migrate_ln_db_version_0(dbtx).boxed()
+        });
+
+        migrations.insert(DatabaseVersion(1), move |dbtx| {
+            migrate_ln_db_version_1(dbtx).boxed()
+        });
+
+        migrations.insert(DatabaseVersion(2), move |dbtx| {
+            migrate_ln_db_version_2(dbtx).boxed()
+        });
+
+        migrations
+    }
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -129,6 +156,49 @@ impl ServerModuleGen for LightningGen {
         Ok(server.to_erased())
     }
 
+    async fn distributed_reshare(
+        &self,
+        peers: &PeerHandle,
+        _params: &ConfigGenParams,
+        old: &ServerModuleReshareConfig,
+    ) -> DkgResult<ServerModuleConfig> {
+        let old_consensus =
+            serde_json::from_value::<LightningConfigConsensus>(old.consensus.value().clone())
+                .map_err(anyhow::Error::from)?;
+        let old_private = old
+            .dealer
+            .as_ref()
+            .map(|(peer, private)| {
+                serde_json::from_value::<LightningConfigPrivate>(private.value().clone())
+                    .map(|private| (*peer, private))
+            })
+            .transpose()
+            .map_err(anyhow::Error::from)?;
+
+        let old_keys = ReshareKeys::threshold_crypto(
+            &old_consensus.threshold_pub_keys,
+            &old.old_peers,
+            old_private
+                .as_ref()
+                .map(|(peer, private)| (*peer, &private.threshold_sec_key)),
+        )?;
+        let g1 = peers.reshare_g1((), old_keys).await?;
+
+        let keys = g1[&()].threshold_crypto();
+
+        let server = LightningConfig {
+            consensus: LightningConfigConsensus {
+                threshold_pub_keys: keys.public_key_set,
+                fee_consensus: old_consensus.fee_consensus,
+            },
+            private: LightningConfigPrivate {
+                threshold_sec_key: keys.secret_key_share,
+            },
+        };
+
+        Ok(server.to_erased())
+    }
+
     fn to_config_response(
         &self,
         config: serde_json::Value,
@@ -219,6 +289,16 @@ impl ServerModuleGen for LightningGen {
                         "Proposed Decryption Shares"
                     );
                 }
+                DbKeyPrefix::Params => {
+                    if let Some(params) = dbtx.get_value(&ParamsKey).await {
+                        lightning.insert("Params".to_string(), Box::new(params));
+                    }
+                }
+                DbKeyPrefix::ConsensusVersion => {
+                    if let Some(version) = dbtx.get_value(&ConsensusVersionKey).await {
+                        lightning.insert("Consensus Version".to_string(), Box::new(version));
+                    }
+                }
             }
         }
 
@@ -258,10 +338,7 @@ impl ServerModule for Lightning {
     type VerificationCache = LightningVerificationCache;
 
     fn versions(&self) -> (ModuleConsensusVersion, &[ApiVersion]) {
-        (
-            ModuleConsensusVersion(0),
-            &[ApiVersion { major: 0, minor: 0 }],
-        )
+        (CONSENSUS_VERSION, API_VERSIONS)
     }
 
     async fn await_consensus_proposal(
@@ -308,6 +385,35 @@ impl ServerModule for Lightning {
         }
     }
 
+    async fn activate_consensus_version(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        version: CoreConsensusVersion,
+    ) {
+        let version = consensus_version_at(version);
+        info!(?version, "Activating lightning consensus version");
+        dbtx.insert_entry(&ConsensusVersionKey, &version).await;
+    }
+
+    fn validate_params(&self, params: &ModuleParams) -> anyhow::Result<()> {
+        match params.keys().find(|name| {
+            ![CONTRACT_INPUT_FEE_PARAM, CONTRACT_OUTPUT_FEE_PARAM].contains(&name.as_str())
+        }) {
+            Some(name) => Err(anyhow::format_err!("Unknown parameter {name}")),
+            None => Ok(()),
+        }
+    }
+
+    async fn activate_params(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        params: &ModuleParams,
+    ) {
+        let mut active = dbtx.get_value(&ParamsKey).await.unwrap_or_default();
+        active.extend(params.clone());
+        dbtx.insert_entry(&ParamsKey, &active).await;
+    }
+
     fn build_verification_cache<'a>(
         &'a self,
         _inputs: impl Iterator<Item = &'a LightningInput>,
@@ -328,12 +434,22 @@ impl ServerModule for Lightning {
             .ok_or(LightningError::UnknownContract(input.contract_id))
             .into_module_error_other()?;
 
-        if account.amount < input.amount {
-            return Err(LightningError::InsufficientFunds(
-                account.amount,
-                input.amount,
-            ))
-            .into_module_error_other();
+        // The fee the gateway can still claim from an incoming contract isn't
+        // available to the seller of the preimage
+        let available = match &account.contract {
+            FundedContract::Incoming(incoming)
+                if matches!(
+                    incoming.contract.decrypted_preimage,
+                    DecryptedPreimage::Some(_)
+                ) && input.witness.is_none() =>
+            {
+                account.amount.saturating_sub(incoming.contract.gateway_fee)
+            }
+            _ => account.amount,
+        };
+        if available < input.amount {
+            return Err(LightningError::InsufficientFunds(available, input.amount))
+                .into_module_error_other();
         }
 
         let pub_key = match account.contract {
@@ -366,7 +482,20 @@ impl ServerModule for Lightning {
                 DecryptedPreimage::Pending => {
                     return Err(LightningError::ContractNotReady).into_module_error_other();
                 }
-                // … either the user may spend the funds since they sold a valid preimage …
+                // … either the gateway may claim its fee by presenting the preimage …
+                DecryptedPreimage::Some(preimage) if input.witness.is_some() => {
+                    self.require_gateway_fees(dbtx).await?;
+                    if input.witness.as_ref() != Some(&preimage) {
+                        return Err(LightningError::InvalidPreimage).into_module_error_other();
+                    }
+                    let gateway_fee = incoming.contract.gateway_fee;
+                    if gateway_fee == Amount::ZERO || input.amount != gateway_fee {
+                        return Err(LightningError::InvalidGatewayFeeClaim(gateway_fee))
+                            .into_module_error_other();
+                    }
+                    incoming.contract.gateway_key
+                }
+                // … or the user may spend the funds since they sold a valid preimage …
                 DecryptedPreimage::Some(preimage) => match preimage.to_public_key() {
                     Ok(pub_key) => pub_key,
                     Err(_) => {
@@ -381,7 +510,7 @@ impl ServerModule for Lightning {
         Ok(InputMeta {
             amount: TransactionItemAmount {
                 amount: input.amount,
-                fee: self.cfg.consensus.fee_consensus.contract_input,
+                fee: self.fee_consensus(dbtx).await.contract_input,
             },
             puk_keys: vec![pub_key],
         })
@@ -404,6 +533,12 @@ impl ServerModule for Lightning {
             .await
             .expect("Should fail validation if contract account doesn't exist");
         contract_account.amount -= meta.amount.amount;
+        // the gateway claims its fee with the preimage, and can only do so once
+        if let FundedContract::Incoming(incoming) = &mut contract_account.contract {
+            if input.witness.is_some() {
+                incoming.contract.gateway_fee = Amount::ZERO;
+            }
+        }
         dbtx.insert_entry(&account_db_key, &contract_account).await;
 
         Ok(meta)
@@ -416,22 +551,33 @@ impl ServerModule for Lightning {
     ) -> Result<TransactionItemAmount, ModuleError> {
         match output {
             LightningOutput::Contract(contract) => {
-                // Incoming contracts are special, they need to match an offer
+                // Incoming contracts are special, they need to match an offer. Funding them
+                // with a different amount or gateway fee fails the transaction, so the funder
+                // keeps its funds and the offer can still be paid.
                 if let Contract::Incoming(incoming) = &contract.contract {
+                    if incoming.gateway_fee != Amount::ZERO {
+                        self.require_gateway_fees(dbtx).await?;
+                    }
                     let offer = dbtx
                         .get_value(&OfferKey(incoming.hash))
                         .await
                         .ok_or(LightningError::NoOffer(incoming.hash))
                         .into_module_error_other()?;
 
-                    if contract.amount < offer.amount {
-                        // If the account is not sufficiently funded fail the output
-                        return Err(LightningError::InsufficientIncomingFunding(
+                    if contract.amount != offer.amount {
+                        return Err(LightningError::IncomingFundingMismatch(
                             offer.amount,
                             contract.amount,
                         ))
                         .into_module_error_other();
                     }
+                    if incoming.gateway_fee != offer.gateway_fee {
+                        return Err(LightningError::IncomingGatewayFeeMismatch(
+                            offer.gateway_fee,
+                            incoming.gateway_fee,
+                        ))
+                        .into_module_error_other();
+                    }
                 }
 
                 if contract.amount == Amount::ZERO {
@@ -439,13 +585,22 @@ impl ServerModule for Lightning {
                 } else {
                     Ok(TransactionItemAmount {
                         amount: contract.amount,
-                        fee: self.cfg.consensus.fee_consensus.contract_output,
+                        fee: self.fee_consensus(dbtx).await.contract_output,
                     })
                 }
             }
             LightningOutput::Offer(offer) => {
+                if offer.gateway_fee != Amount::ZERO {
+                    self.require_gateway_fees(dbtx).await?;
+                }
                 if !offer.encrypted_preimage.0.verify() {
                     Err(LightningError::InvalidEncryptedPreimage).into_module_error_other()
+                } else if offer.amount <= offer.gateway_fee {
+                    Err(LightningError::OfferBelowGatewayFee(
+                        offer.amount,
+                        offer.gateway_fee,
+                    ))
+                    .into_module_error_other()
                 } else {
                     Ok(TransactionItemAmount::ZERO)
                 }
@@ -516,10 +671,7 @@ impl ServerModule for Lightning {
                 .await;
 
                 if let Contract::Incoming(incoming) = &contract.contract {
-                    let offer = dbtx
-                        .get_value(&OfferKey(incoming.hash))
-                        .await
-                        .expect("offer exists if output is valid");
+                    dbtx.remove_entry(&OfferKey(incoming.hash)).await;
 
                     let decryption_share = self
                         .cfg
@@ -532,7 +684,6 @@ impl ServerModule for Lightning {
                         &PreimageDecryptionShare(decryption_share),
                     )
                     .await;
-                    dbtx.remove_entry(&OfferKey(offer.hash)).await;
                 }
             }
             LightningOutput::Offer(offer) => {
@@ -749,6 +900,83 @@ impl ServerModule for Lightning {
             .await;
     }
 
+    async fn verify_integrity(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Vec<String> {
+        let contracts = dbtx
+            .find_by_prefix(&ContractKeyPrefix)
+            .await
+            .map(|(key, _)| key.0)
+            .collect::<BTreeSet<_>>()
+            .await;
+        let outcomes = dbtx
+            .find_by_prefix(&ContractUpdateKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut violations = vec![];
+        let mut funded = BTreeSet::new();
+        for (key, outcome) in outcomes {
+            if let LightningOutputOutcome::Contract { id, .. } = outcome {
+                if !contracts.contains(&id) {
+                    violations.push(format!(
+                        "Output {} funded contract {id} which has no account",
+                        key.0
+                    ));
+                }
+                funded.insert(id);
+            }
+        }
+
+        for contract_id in contracts.difference(&funded) {
+            violations.push(format!(
+                "Contract {contract_id} was never funded by an output"
+            ));
+        }
+
+        violations
+    }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        // our own decryption shares and the gateways registered with our API
+        vec![
+            DbKeyPrefix::ProposeDecryptionShare as u8,
+            DbKeyPrefix::LightningGateway as u8,
+        ]
+    }
+
+    fn consensus_db_prefixes(&self) -> Vec<u8> {
+        vec![
+            DbKeyPrefix::Contract as u8,
+            DbKeyPrefix::Offer as u8,
+            DbKeyPrefix::AgreedDecryptionShare as u8,
+            DbKeyPrefix::ContractUpdate as u8,
+            DbKeyPrefix::Params as u8,
+            DbKeyPrefix::ConsensusVersion as u8,
+        ]
+    }
+
+    fn public_input_amount(&self, input: &LightningInput) -> Option<Amount> {
+        Some(input.amount)
+    }
+
+    fn public_output_amount(&self, output: &LightningOutput) -> Option<Amount> {
+        match output {
+            LightningOutput::Contract(contract) => Some(contract.amount),
+            // Offers and cancellations don't move any funds
+            LightningOutput::Offer(_) | LightningOutput::CancelOutgoing { .. } => {
+                Some(Amount::ZERO)
+            }
+        }
+    }
+
+    fn epoch_dependencies(&self) -> Vec<ModuleKind> {
+        // Decrypting preimages only touches the lightning module's own state
+        vec![]
+    }
+
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
         vec![
             api_endpoint! {
@@ -772,12 +1000,30 @@ impl ServerModule for Lightning {
                     Ok(offer)
                 }
             },
+            api_endpoint! {
+                "/consensus_version",
+                async |module: &Lightning, context, _params: ()| -> ModuleConsensusVersion {
+                    Ok(module.consensus_version(&mut context.dbtx()).await)
+                }
+            },
             api_endpoint! {
                 "/list_gateways",
                 async |module: &Lightning, context, _v: ()| -> Vec<LightningGateway> {
                     Ok(module.list_gateways(&mut context.dbtx()).await)
                 }
             },
+            api_endpoint! {
+                "/list_gateways_page",
+                async |module: &Lightning, context, request: PageRequest<secp256k1::PublicKey>| -> Page<LightningGateway, secp256k1::PublicKey> {
+                    let gateways = module
+                        .list_gateways(&mut context.dbtx())
+                        .await
+                        .into_iter()
+                        .map(|gateway| (gateway.node_pub_key, gateway))
+                        .sorted_by_key(|(node_pub_key, _)| *node_pub_key);
+                    Ok(request.page(gateways))
+                }
+            },
             api_endpoint! {
                 "/register_gateway",
                 async |module: &Lightning, context, gateway: LightningGateway| -> () {
@@ -793,6 +1039,46 @@ impl Lightning {
         Lightning { cfg }
     }
 
+    async fn consensus_version(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> ModuleConsensusVersion {
+        dbtx.get_value(&ConsensusVersionKey)
+            .await
+            .unwrap_or(ModuleConsensusVersion(0))
+    }
+
+    /// Fails unless the federation runs a consensus version with gateway fees,
+    /// guardians of older versions can't decode them
+    async fn require_gateway_fees(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Result<(), ModuleError> {
+        if self.consensus_version(dbtx).await < GATEWAY_FEE_VERSION {
+            return Err(LightningError::GatewayFeesInactive(GATEWAY_FEE_VERSION))
+                .into_module_error_other();
+        }
+        Ok(())
+    }
+
+    /// Fees of the config with the changes the guardians activated
+    async fn fee_consensus(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> FeeConsensus {
+        let params = dbtx.get_value(&ParamsKey).await.unwrap_or_default();
+        let fees = &self.cfg.consensus.fee_consensus;
+        let fee = |name: &str, default: Amount| {
+            params
+                .get(name)
+                .map_or(default, |msats| Amount::from_msats(*msats))
+        };
+        FeeConsensus {
+            contract_input: fee(CONTRACT_INPUT_FEE_PARAM, fees.contract_input),
+            contract_output: fee(CONTRACT_OUTPUT_FEE_PARAM, fees.contract_output),
+        }
+    }
+
     fn validate_decryption_share(
         &self,
         peer: PeerId,
@@ -840,7 +1126,7 @@ impl Lightning {
         let stream = dbtx.find_by_prefix(&LightningGatewayKeyPrefix).await;
         stream
             .filter_map(|(_, gw)| async {
-                // FIXME: actually remove from DB
+                // expired registrations are removed when the next gateway registers
                 if gw.valid_until > fedimint_core::time::now() {
                     Some(gw)
                 } else {
@@ -851,11 +1137,32 @@ impl Lightning {
             .await
     }
 
+    /// Registers `gateway`, replacing an earlier registration of its node.
+    /// Several gateways can serve the federation at once, contracts are
+    /// addressed to the key of one of them and only its holder can claim them.
+    ///
+    /// Registering is unauthenticated, so the key isn't required to be unique:
+    /// anyone could register the key of another gateway first to lock it out.
+    /// Registering a key one doesn't hold gains nothing, contracts addressed
+    /// to it can only be claimed by the gateway holding it or refunded.
     pub async fn register_gateway(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
         gateway: LightningGateway,
     ) {
+        let now = fedimint_core::time::now();
+        let expired = dbtx
+            .find_by_prefix(&LightningGatewayKeyPrefix)
+            .await
+            .filter_map(|(key, registration)| async move {
+                (registration.valid_until <= now).then_some(key)
+            })
+            .collect::<Vec<_>>()
+            .await;
+        for key in expired {
+            dbtx.remove_entry(&key).await;
+        }
+
         dbtx.insert_entry(&LightningGatewayKey(gateway.node_pub_key), &gateway)
             .await;
     }
@@ -881,6 +1188,16 @@ async fn block_height(interconnect: &dyn ModuleInterconect) -> u32 {
     serde_json::from_value(body).expect("Malformed block height response from wallet module!")
 }
 
+/// Lightning consensus version the federation runs at core consensus version
+/// `core`
+fn consensus_version_at(core: CoreConsensusVersion) -> ModuleConsensusVersion {
+    if core >= CoreConsensusVersion(3) {
+        GATEWAY_FEE_VERSION
+    } else {
+        ModuleConsensusVersion(0)
+    }
+}
+
 #[cfg(test)]
 mod fedimint_migration_tests {
     use std::str::FromStr;
@@ -892,18 +1209,17 @@ mod fedimint_migration_tests {
     use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::module::DynServerModuleGen;
     use fedimint_core::{OutPoint, ServerModule, TransactionId};
-    use fedimint_ln_common::contracts::incoming::{
-        FundedIncomingContract, IncomingContract, IncomingContractOffer, OfferId,
-    };
+    use fedimint_ln_common::contracts::incoming::OfferId;
     use fedimint_ln_common::contracts::{
-        outgoing, ContractId, DecryptedPreimage, EncryptedPreimage, FundedContract, Preimage,
+        outgoing, ContractId, DecryptedPreimage, EncryptedPreimage, Preimage,
         PreimageDecryptionShare,
     };
     use fedimint_ln_common::db::{
-        AgreedDecryptionShareKey, AgreedDecryptionShareKeyPrefix, ContractKey, ContractKeyPrefix,
-        ContractUpdateKey, ContractUpdateKeyPrefix, DbKeyPrefix, LightningGatewayKey,
-        LightningGatewayKeyPrefix, OfferKey, OfferKeyPrefix, ProposeDecryptionShareKey,
-        ProposeDecryptionShareKeyPrefix,
+        AgreedDecryptionShareKey, AgreedDecryptionShareKeyPrefix, ContractAccountV0,
+        ContractKeyPrefix, ContractKeyV0, ContractUpdateKey, ContractUpdateKeyPrefix, DbKeyPrefix,
+        FundedContractV0, FundedIncomingContractV0, IncomingContractOfferV0, IncomingContractV0,
+        LightningGatewayKeyPrefix, LightningGatewayKeyV0, LightningGatewayV0, OfferKeyPrefix,
+        OfferKeyV0, ProposeDecryptionShareKey, ProposeDecryptionShareKeyPrefix,
     };
     use fedimint_testing::{prepare_snapshot, validate_migrations, BYTE_32, BYTE_8, STRING_64};
     use futures::StreamExt;
@@ -915,9 +1231,7 @@ mod fedimint_migration_tests {
     use threshold_crypto::G1Projective;
     use url::Url;
 
-    use crate::{
-        ContractAccount, Lightning, LightningGateway, LightningGen, LightningOutputOutcome,
-    };
+    use crate::{Lightning, LightningGen, LightningOutputOutcome};
 
     /// Create a database with version 0 data. The database produced is not
     /// intended to be real data or semantically correct. It is only
@@ -930,7 +1244,7 @@ mod fedimint_migration_tests {
         let amount = fedimint_core::Amount { msats: 1000 };
         let threshold_key = threshold_crypto::PublicKey::from(G1Projective::identity());
         let (_, pk) = secp256k1::generate_keypair(&mut OsRng);
-        let incoming_contract = IncomingContract {
+        let incoming_contract = IncomingContractV0 {
             hash: secp256k1::hashes::sha256::Hash::hash(&BYTE_8),
             encrypted_preimage: EncryptedPreimage::new(Preimage(BYTE_32), &threshold_key),
             decrypted_preimage: DecryptedPreimage::Some(Preimage(BYTE_32)),
@@ -940,20 +1254,20 @@ mod fedimint_migration_tests {
             txid: TransactionId::all_zeros(),
             out_idx: 0,
         };
-        let incoming_contract = FundedContract::Incoming(FundedIncomingContract {
+        let incoming_contract = FundedContractV0::Incoming(FundedIncomingContractV0 {
             contract: incoming_contract,
             out_point,
         });
         dbtx.insert_new_entry(
-            &ContractKey(contract_id),
-            &ContractAccount {
+            &ContractKeyV0(contract_id),
+            &ContractAccountV0 {
                 amount,
                 contract: incoming_contract,
             },
         )
         .await;
         let invoice = str::parse::<Invoice>("lnbc10u1pjq37rgsp5cry9r0qqdzp0tl0m27jedvxtrazq0v8xh5rfvzuhm7yxydg50m9qpp5r0cjzjzt7pjwae8trp6dtteh6hstdakzv68atpqx0zshaexghpwsdqqcqpjrzjqfzekav6v27ra0lf3geqmg3hj3xvfu652cuyhk8aa7naqdqvwh6x7zagh5qqy3qqqyqqqqqpqqqqqqgq9q9qyysgq6vf5z83a2q2ua9nwanmc7pql26pwt8smt2xzwp7kjd0mgplmy925s5yz6nlfxt99p2dlffw82gw8kte7lv87pcf4nahslg2vyhhkzwqqxuqmgp");
-        let outgoing_contract = FundedContract::Outgoing(outgoing::OutgoingContract {
+        let outgoing_contract = FundedContractV0::Outgoing(outgoing::OutgoingContract {
             hash: secp256k1::hashes::sha256::Hash::hash(&[0, 2, 3, 4, 5, 6, 7, 8]),
             gateway_key: pk.x_only_public_key().0,
             timelock: 1000000,
@@ -962,21 +1276,21 @@ mod fedimint_migration_tests {
             cancelled: false,
         });
         dbtx.insert_new_entry(
-            &ContractKey(contract_id),
-            &ContractAccount {
+            &ContractKeyV0(contract_id),
+            &ContractAccountV0 {
                 amount,
                 contract: outgoing_contract,
             },
         )
         .await;
 
-        let incoming_offer = IncomingContractOffer {
+        let incoming_offer = IncomingContractOfferV0 {
             amount: fedimint_core::Amount { msats: 1000 },
             hash: secp256k1::hashes::sha256::Hash::hash(&BYTE_8),
             encrypted_preimage: EncryptedPreimage::new(Preimage(BYTE_32), &threshold_key),
             expiry_time: None,
         };
-        dbtx.insert_new_entry(&OfferKey(incoming_offer.hash), &incoming_offer)
+        dbtx.insert_new_entry(&OfferKeyV0(incoming_offer.hash), &incoming_offer)
             .await;
 
         let contract_update_key = ContractUpdateKey(OutPoint {
@@ -1002,7 +1316,7 @@ mod fedimint_migration_tests {
         )
         .await;
 
-        let gateway = LightningGateway {
+        let gateway = LightningGatewayV0 {
             mint_channel_id: 100,
             mint_pub_key: pk.x_only_public_key().0,
             node_pub_key: pk,
@@ -1011,7 +1325,7 @@ mod fedimint_migration_tests {
             route_hints: vec![],
             valid_until: SystemTime::now(),
         };
-        dbtx.insert_new_entry(&LightningGatewayKey(pk), &gateway)
+        dbtx.insert_new_entry(&LightningGatewayKeyV0(pk), &gateway)
             .await;
 
         dbtx.commit_tx().await;
@@ -1128,6 +1442,9 @@ mod fedimint_migration_tests {
                             "validate_migrations was not able to read any ProposeDecryptionShares"
                         );
                         }
+                        // Params and consensus versions were added after the snapshot was
+                        // taken
+                        DbKeyPrefix::Params | DbKeyPrefix::ConsensusVersion => {}
                     }
                 }
             },
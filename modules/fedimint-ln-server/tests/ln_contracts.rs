@@ -1,3 +1,5 @@
+use std::time::{Duration, SystemTime};
+
 use bitcoin_hashes::{sha256, Hash as BitcoinHash};
 use fedimint_core::config::ConfigGenParams;
 use fedimint_core::core::LEGACY_HARDCODED_INSTANCE_ID_LN;
@@ -10,11 +12,12 @@ use fedimint_ln_common::contracts::{
     OutgoingContractOutcome, Preimage,
 };
 use fedimint_ln_common::{
-    ContractOutput, LightningError, LightningInput, LightningOutput, LightningOutputOutcome,
+    ContractOutput, GatewayFee, LightningError, LightningGateway, LightningInput, LightningOutput,
+    LightningOutputOutcome,
 };
 use fedimint_ln_server::{Lightning, LightningGen};
 use fedimint_testing::FakeFed;
-use secp256k1::KeyPair;
+use secp256k1::{KeyPair, PublicKey, XOnlyPublicKey};
 
 #[test_log::test(tokio::test)]
 async fn test_outgoing() {
@@ -147,6 +150,7 @@ async fn test_incoming() {
                 .threshold_pub_key,
         ),
         expiry_time: None,
+        gateway_fee: Amount::ZERO,
     };
     let offer_output = LightningOutput::Offer(offer.clone());
     let offer_out_point = OutPoint {
@@ -175,6 +179,7 @@ async fn test_incoming() {
         decrypted_preimage: DecryptedPreimage::Pending, /* TODO: check what happens if this is
                                                          * not pending */
         gateway_key: gw_pk,
+        gateway_fee: Amount::ZERO,
     });
     let incoming_output = LightningOutput::Contract(ContractOutput {
         amount: Amount::from_sats(42),
@@ -224,3 +229,269 @@ async fn test_incoming() {
 
     // TODO: test faulty encrypted preimage
 }
+
+#[test_log::test(tokio::test)]
+async fn test_incoming_funding_mismatch() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let mut fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, _db| async move { Ok(Lightning::new(cfg.to_typed()?)) },
+        &ConfigGenParams::null(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    let ctx = secp256k1::Secp256k1::new();
+    let gw_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+
+    let preimage = Preimage(user_pk.serialize());
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+
+    let offer = IncomingContractOffer {
+        amount: Amount::from_sats(42),
+        hash,
+        encrypted_preimage: EncryptedPreimage::new(
+            preimage.clone(),
+            &fed.client_cfg_typed::<LightningClientConfig>()
+                .unwrap()
+                .threshold_pub_key,
+        ),
+        expiry_time: None,
+        gateway_fee: Amount::ZERO,
+    };
+    let offer_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 0,
+    };
+    fed.consensus_round(
+        &[],
+        &[(offer_out_point, LightningOutput::Offer(offer.clone()))],
+    )
+    .await;
+
+    // funding less or more than the user asked for is invalid …
+    let contract = Contract::Incoming(IncomingContract {
+        hash,
+        encrypted_preimage: offer.encrypted_preimage,
+        decrypted_preimage: DecryptedPreimage::Pending,
+        gateway_key: gw_pk,
+        gateway_fee: Amount::ZERO,
+    });
+    for amount in [Amount::from_sats(21), Amount::from_sats(43)] {
+        let mismatched_output = LightningOutput::Contract(ContractOutput {
+            amount,
+            contract: contract.clone(),
+        });
+        // `verify_output` returns whether the output is invalid
+        assert!(fed.verify_output(&mismatched_output).await);
+    }
+
+    // … as is charging a different gateway fee than the offer's
+    let mut fee_contract = contract.clone();
+    if let Contract::Incoming(incoming) = &mut fee_contract {
+        incoming.gateway_fee = Amount::from_sats(1);
+    }
+    let fee_output = LightningOutput::Contract(ContractOutput {
+        amount: offer.amount,
+        contract: fee_contract,
+    });
+    assert!(fed.verify_output(&fee_output).await);
+
+    // the offer wasn't consumed, so it can still be funded correctly
+    let incoming_output = LightningOutput::Contract(ContractOutput {
+        amount: offer.amount,
+        contract: contract.clone(),
+    });
+    assert!(!fed.verify_output(&incoming_output).await);
+    let incoming_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 1,
+    };
+    fed.consensus_round(&[], &[(incoming_out_point, incoming_output)])
+        .await;
+    fed.consensus_round(&[], &[]).await;
+    match fed.output_outcome(incoming_out_point).await.unwrap() {
+        LightningOutputOutcome::Contract { outcome, .. } => {
+            assert_eq!(
+                outcome,
+                ContractOutcome::Incoming(DecryptedPreimage::Some(preimage))
+            );
+        }
+        _ => panic!(),
+    };
+}
+
+#[test_log::test(tokio::test)]
+async fn test_incoming_gateway_fee() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let mut fed = FakeFed::<Lightning>::new(
+        4,
+        |cfg, _db| async move { Ok(Lightning::new(cfg.to_typed()?)) },
+        &ConfigGenParams::null(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+
+    let ctx = secp256k1::Secp256k1::new();
+    let gw_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let user_pk = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+
+    let preimage = Preimage(user_pk.serialize());
+    let hash = secp256k1::hashes::sha256::Hash::hash(&preimage.0);
+
+    let offer = IncomingContractOffer {
+        amount: Amount::from_sats(42),
+        hash,
+        encrypted_preimage: EncryptedPreimage::new(
+            preimage.clone(),
+            &fed.client_cfg_typed::<LightningClientConfig>()
+                .unwrap()
+                .threshold_pub_key,
+        ),
+        expiry_time: None,
+        gateway_fee: Amount::from_sats(2),
+    };
+
+    // the preimage has to sell for more than the gateway's fee
+    let below_fee = IncomingContractOffer {
+        gateway_fee: offer.amount,
+        ..offer.clone()
+    };
+    assert!(fed.verify_output(&LightningOutput::Offer(below_fee)).await);
+
+    let offer_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 0,
+    };
+    fed.consensus_round(
+        &[],
+        &[(offer_out_point, LightningOutput::Offer(offer.clone()))],
+    )
+    .await;
+
+    let contract = Contract::Incoming(IncomingContract {
+        hash,
+        encrypted_preimage: offer.encrypted_preimage,
+        decrypted_preimage: DecryptedPreimage::Pending,
+        gateway_key: gw_pk,
+        gateway_fee: offer.gateway_fee,
+    });
+    let incoming_out_point = OutPoint {
+        txid: sha256::Hash::hash(b"").into(),
+        out_idx: 1,
+    };
+    let incoming_output = LightningOutput::Contract(ContractOutput {
+        amount: offer.amount,
+        contract: contract.clone(),
+    });
+    fed.consensus_round(&[], &[(incoming_out_point, incoming_output)])
+        .await;
+    fed.consensus_round(&[], &[]).await;
+
+    // the user can't claim the gateway's fee …
+    let full_input = LightningInput {
+        contract_id: contract.contract_id(),
+        amount: Amount::from_sats(42),
+        witness: None,
+    };
+    assert!(fed.verify_input(&full_input).await.is_err());
+    let user_input = LightningInput {
+        amount: Amount::from_sats(40),
+        ..full_input
+    };
+    let meta = fed.verify_input(&user_input).await.unwrap();
+    assert_eq!(meta.keys, vec![user_pk]);
+
+    // … which the gateway claims with the preimage, once
+    let fee_input = LightningInput {
+        contract_id: contract.contract_id(),
+        amount: Amount::from_sats(2),
+        witness: Some(preimage),
+    };
+    let meta = fed.verify_input(&fee_input).await.unwrap();
+    assert_eq!(meta.keys, vec![gw_pk]);
+
+    fed.consensus_round(&[fee_input.clone()], &[]).await;
+    let error = fed.verify_input(&fee_input).await.unwrap_err();
+    assert_eq!(
+        format!("{error}"),
+        format!("{}", LightningError::InvalidGatewayFeeClaim(Amount::ZERO))
+    );
+    assert!(fed.verify_input(&user_input).await.is_ok());
+}
+
+fn gateway(
+    node_pub_key: PublicKey,
+    mint_pub_key: XOnlyPublicKey,
+    valid_until: SystemTime,
+) -> LightningGateway {
+    LightningGateway {
+        mint_channel_id: 1,
+        mint_pub_key,
+        node_pub_key,
+        api: format!("http://{node_pub_key}.example.com")
+            .parse()
+            .unwrap(),
+        route_hints: vec![],
+        fees: GatewayFee::default(),
+        liquidity: None,
+        valid_until,
+    }
+}
+
+#[test_log::test(tokio::test)]
+async fn test_gateways_registering_the_same_key() {
+    let mut rng = secp256k1::rand::rngs::OsRng;
+
+    let fed = FakeFed::<Lightning>::new(
+        1,
+        |cfg, _db| async move { Ok(Lightning::new(cfg.to_typed()?)) },
+        &ConfigGenParams::null(),
+        &LightningGen,
+        LEGACY_HARDCODED_INSTANCE_ID_LN,
+    )
+    .await
+    .unwrap();
+    let (_, module, db, module_instance_id) = &fed.members[0];
+
+    let ctx = secp256k1::Secp256k1::new();
+    let gateway_key = KeyPair::new(&ctx, &mut rng).x_only_public_key().0;
+    let honest = KeyPair::new(&ctx, &mut rng).public_key();
+    let squatter = KeyPair::new(&ctx, &mut rng).public_key();
+    let expired = KeyPair::new(&ctx, &mut rng).public_key();
+    let now = fedimint_core::time::now();
+    let later = now + Duration::from_secs(600);
+
+    let mut dbtx = db.begin_transaction().await;
+    let mut module_dbtx = dbtx.with_module_prefix(*module_instance_id);
+    module
+        .register_gateway(&mut module_dbtx, gateway(expired, gateway_key, now))
+        .await;
+
+    // the key of the honest gateway was registered by another one first, which
+    // doesn't lock it out
+    module
+        .register_gateway(&mut module_dbtx, gateway(squatter, gateway_key, later))
+        .await;
+    module
+        .register_gateway(&mut module_dbtx, gateway(honest, gateway_key, now))
+        .await;
+    let registered = gateway(honest, gateway_key, later);
+    module
+        .register_gateway(&mut module_dbtx, registered.clone())
+        .await;
+
+    let gateways = module.list_gateways(&mut module_dbtx).await;
+    assert_eq!(gateways.len(), 2);
+    assert!(gateways.contains(&registered));
+    assert!(gateways
+        .iter()
+        .any(|gateway| gateway.node_pub_key == squatter));
+}
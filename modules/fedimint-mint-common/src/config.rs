@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::iter::FromIterator;
 
 use anyhow::bail;
@@ -7,7 +7,7 @@ use fedimint_core::config::{
     TypedServerModuleConsensusConfig,
 };
 use fedimint_core::core::ModuleKind;
-use fedimint_core::encoding::Encodable;
+use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{Amount, NumPeers, PeerId, Tiered, TieredMultiZip};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
@@ -35,6 +35,20 @@ pub struct MintConfigConsensus {
     pub max_notes_per_denomination: u16,
 }
 
+/// Key under which the federation announces deprecated note tiers to clients in
+/// the `meta` part of the config, as JSON encoded [`TierDeprecation`]
+pub const META_TIER_DEPRECATION_KEY: &str = "tier_deprecation";
+
+/// Note tiers the federation stops accepting, e.g. before rotating their keys
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct TierDeprecation {
+    /// Tiers whose notes have to be reissued into notes of other tiers
+    pub tiers: BTreeSet<Amount>,
+    /// Unix time in seconds after which notes of the tiers can no longer be
+    /// spent, `None` if the federation didn't announce one
+    pub deadline: Option<u64>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MintConfigPrivate {
     /// Secret keys for blind-signing ecash of varying note denominations
@@ -117,6 +131,14 @@ impl TypedServerModuleConfig for MintConfig {
     }
 }
 
+/// Name of the adjustable parameter replacing
+/// [`FeeConsensus::note_issuance_abs`], in msats
+pub const NOTE_ISSUANCE_FEE_PARAM: &str = "note_issuance_abs_msats";
+
+/// Name of the adjustable parameter replacing [`FeeConsensus::note_spend_abs`],
+/// in msats
+pub const NOTE_SPEND_FEE_PARAM: &str = "note_spend_abs_msats";
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
 pub struct FeeConsensus {
     pub note_issuance_abs: fedimint_core::Amount,
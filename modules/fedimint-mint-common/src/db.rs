@@ -1,11 +1,12 @@
 use std::time::SystemTime;
 
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::ModuleParams;
 use fedimint_core::{impl_db_lookup, impl_db_record, Amount, OutPoint, PeerId};
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
-use crate::{MintOutputBlindSignatures, MintOutputSignatureShare, Nonce};
+use crate::{BlindNonce, MintOutputBlindSignatures, MintOutputSignatureShare, Nonce};
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -16,6 +17,9 @@ pub enum DbKeyPrefix {
     OutputOutcome = 0x13,
     MintAuditItem = 0x14,
     EcashBackup = 0x15,
+    IssuedNote = 0x16,
+    Params = 0x17,
+    SpentNote = 0x18,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -134,6 +138,48 @@ impl_db_record!(
 );
 impl_db_lookup!(key = EcashBackupKey, query_prefix = EcashBackupKeyPrefix);
 
+/// Blind signature issued for a blind nonce since the last checkpoint, moved
+/// into the next checkpoint so clients can recover their notes from it
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct IssuedNoteKey(pub BlindNonce);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct IssuedNoteKeyPrefix;
+
+impl_db_record!(
+    key = IssuedNoteKey,
+    value = tbs::BlindedSignature,
+    db_prefix = DbKeyPrefix::IssuedNote,
+);
+impl_db_lookup!(key = IssuedNoteKey, query_prefix = IssuedNoteKeyPrefix);
+
+/// Nonce of a note spent since the last checkpoint, moved into the next
+/// checkpoint
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct SpentNoteKey(pub Nonce);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SpentNoteKeyPrefix;
+
+impl_db_record!(
+    key = SpentNoteKey,
+    value = (),
+    db_prefix = DbKeyPrefix::SpentNote,
+);
+impl_db_lookup!(key = SpentNoteKey, query_prefix = SpentNoteKeyPrefix);
+
+/// Fees the guardians changed from the ones of the config, see
+/// [`crate::config::NOTE_ISSUANCE_FEE_PARAM`] and
+/// [`crate::config::NOTE_SPEND_FEE_PARAM`]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ParamsKey;
+
+impl_db_record!(
+    key = ParamsKey,
+    value = ModuleParams,
+    db_prefix = DbKeyPrefix::Params,
+);
+
 /// User's backup, received at certain time, containing encrypted payload
 #[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
 pub struct ECashUserBackupSnapshot {
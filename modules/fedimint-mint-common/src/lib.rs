@@ -4,7 +4,7 @@ pub use common::{BackupRequest, SignedBackupRequest};
 use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::__reexports::serde_json;
-use fedimint_core::module::{CommonModuleGen, ModuleCommon};
+use fedimint_core::module::{ApiVersion, CommonModuleGen, ModuleCommon, ModuleConsensusVersion};
 use fedimint_core::tiered::InvalidAmountTierError;
 use fedimint_core::{plugin_types_trait_impl_common, Amount, OutPoint, PeerId, TieredMulti};
 use impl_tools::autoimpl;
@@ -21,6 +21,13 @@ pub mod db;
 
 const KIND: ModuleKind = ModuleKind::from_static_str("mint");
 
+/// Consensus version of the mint, has to be bumped when the encoding of its
+/// types changes
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+
+/// API versions of the mint's endpoints, one per supported major version
+pub const API_VERSIONS: &[ApiVersion] = &[ApiVersion { major: 0, minor: 0 }];
+
 /// By default, the maximum notes per denomination when change-making for users
 pub const DEFAULT_MAX_NOTES_PER_DENOMINATION: u16 = 3;
 
@@ -88,6 +95,17 @@ pub struct Nonce(pub secp256k1_zkp::XOnlyPublicKey);
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct BlindNonce(pub tbs::BlindedMessage);
 
+/// Mint state changes contained in a checkpoint, lets clients recover the
+/// notes issued and spent since the previous checkpoint without the epoch
+/// history
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct MintCheckpoint {
+    /// Blind signatures of the notes issued since the previous checkpoint
+    pub issued: Vec<(BlindNonce, tbs::BlindedSignature)>,
+    /// Nonces of the notes spent since the previous checkpoint
+    pub spent: Vec<Nonce>,
+}
+
 #[derive(Debug)]
 pub struct MintCommonGen;
 
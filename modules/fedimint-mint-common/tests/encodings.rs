@@ -0,0 +1,87 @@
+//! Snapshots of the encodings of the mint's consensus types, see
+//! [`fedimint_core::encoding::compat`]
+
+use std::collections::BTreeMap;
+
+use bitcoin_hashes::Hash;
+use fedimint_core::{
+    encoding_snapshot_test, Amount, OutPoint, PeerId, Tiered, TieredMulti, TransactionId,
+};
+use fedimint_mint_common::config::{FeeConsensus, MintClientConfig, MintConfigConsensus};
+use fedimint_mint_common::{
+    BlindNonce, MintCheckpoint, MintConsensusItem, MintInput, MintOutput,
+    MintOutputBlindSignatures, MintOutputOutcome, MintOutputSignatureShare, Nonce, Note,
+    CONSENSUS_VERSION,
+};
+use tbs::{AggregatePublicKey, MessagePoint, Scalar, SecretKeyShare};
+
+fn tier() -> Amount {
+    Amount::from_msats(1024)
+}
+
+fn secret_key_share() -> SecretKeyShare {
+    SecretKeyShare(Scalar::from(7))
+}
+
+fn nonce() -> Nonce {
+    let keypair = secp256k1_zkp::KeyPair::from_seckey_slice(secp256k1_zkp::SECP256K1, &[1; 32])
+        .expect("valid secret key");
+    Nonce(keypair.x_only_public_key().0)
+}
+
+/// A multiple of the generator rather than a blinded message, so the
+/// encodings can be reproduced without running the code
+fn blind_nonce() -> BlindNonce {
+    BlindNonce(tbs::BlindedMessage(MessagePoint::from(
+        MessagePoint::generator() * Scalar::from(3),
+    )))
+}
+
+fn signature_share() -> tbs::BlindedSignatureShare {
+    tbs::sign_blinded_msg(blind_nonce().0, secret_key_share())
+}
+
+fn peer_tbs_pks() -> BTreeMap<PeerId, Tiered<tbs::PublicKeyShare>> {
+    BTreeMap::from([(
+        PeerId::from(0),
+        [(tier(), secret_key_share().to_pub_key_share())]
+            .into_iter()
+            .collect(),
+    )])
+}
+
+fn single<T>(item: T) -> TieredMulti<T> {
+    TieredMulti::new(BTreeMap::from([(tier(), vec![item])]))
+}
+
+encoding_snapshot_test!(mint_encodings, CONSENSUS_VERSION.0, {
+    "MintInput" => MintInput(single(Note(nonce(), tbs::Signature(signature_share().0)))),
+    "MintOutput" => MintOutput(single(blind_nonce())),
+    "MintOutputOutcome" => MintOutputOutcome(Some(MintOutputBlindSignatures(single(
+        tbs::BlindedSignature(signature_share().0),
+    )))),
+    "MintConsensusItem" => MintConsensusItem {
+        out_point: OutPoint {
+            txid: TransactionId::from_inner([1; 32]),
+            out_idx: 0,
+        },
+        signatures: MintOutputSignatureShare(single((blind_nonce().0, signature_share()))),
+    },
+    "MintCheckpoint" => MintCheckpoint {
+        issued: vec![(blind_nonce(), tbs::BlindedSignature(signature_share().0))],
+        spent: vec![nonce()],
+    },
+    "MintClientConfig" => MintClientConfig {
+        tbs_pks: [(tier(), AggregatePublicKey(secret_key_share().to_pub_key_share().0))]
+            .into_iter()
+            .collect(),
+        fee_consensus: FeeConsensus::default(),
+        peer_tbs_pks: peer_tbs_pks(),
+        max_notes_per_denomination: 3,
+    },
+    "MintConfigConsensus" => MintConfigConsensus {
+        peer_tbs_pks: peer_tbs_pks(),
+        fee_consensus: FeeConsensus::default(),
+        max_notes_per_denomination: 3,
+    },
+});
@@ -5,11 +5,12 @@ use std::ops::Sub;
 
 use fedimint_core::config::{
     ConfigGenParams, DkgResult, ModuleConfigResponse, ModuleGenParams, ServerModuleConfig,
-    TypedServerModuleConfig, TypedServerModuleConsensusConfig,
+    ServerModuleReshareConfig, TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
-use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
 use fedimint_core::db::{Database, DatabaseVersion, ModuleDatabaseTransaction};
 use fedimint_core::encoding::Encodable;
+use fedimint_core::epoch::ModuleParams;
 use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::interconnect::ModuleInterconect;
@@ -27,22 +28,25 @@ use fedimint_core::{
 };
 pub use fedimint_mint_common as common;
 use fedimint_mint_common::config::{
-    FeeConsensus, MintConfig, MintConfigConsensus, MintConfigPrivate,
+    FeeConsensus, MintConfig, MintConfigConsensus, MintConfigPrivate, NOTE_ISSUANCE_FEE_PARAM,
+    NOTE_SPEND_FEE_PARAM,
 };
 use fedimint_mint_common::db::{
-    DbKeyPrefix, ECashUserBackupSnapshot, EcashBackupKey, EcashBackupKeyPrefix, MintAuditItemKey,
-    MintAuditItemKeyPrefix, NonceKey, NonceKeyPrefix, OutputOutcomeKey, OutputOutcomeKeyPrefix,
-    ProposedPartialSignatureKey, ProposedPartialSignaturesKeyPrefix, ReceivedPartialSignatureKey,
-    ReceivedPartialSignatureKeyOutputPrefix, ReceivedPartialSignaturesKeyPrefix,
+    DbKeyPrefix, ECashUserBackupSnapshot, EcashBackupKey, EcashBackupKeyPrefix, IssuedNoteKey,
+    IssuedNoteKeyPrefix, MintAuditItemKey, MintAuditItemKeyPrefix, NonceKey, NonceKeyPrefix,
+    OutputOutcomeKey, OutputOutcomeKeyPrefix, ParamsKey, ProposedPartialSignatureKey,
+    ProposedPartialSignaturesKeyPrefix, ReceivedPartialSignatureKey,
+    ReceivedPartialSignatureKeyOutputPrefix, ReceivedPartialSignaturesKeyPrefix, SpentNoteKey,
+    SpentNoteKeyPrefix,
 };
 pub use fedimint_mint_common::{BackupRequest, SignedBackupRequest};
 use fedimint_mint_common::{
-    BlindNonce, CombineError, MintCommonGen, MintConsensusItem, MintError, MintInput,
-    MintModuleTypes, MintOutput, MintOutputBlindSignatures, MintOutputOutcome,
-    MintOutputSignatureShare, MintShareErrors, Note, PeerErrorType,
-    DEFAULT_MAX_NOTES_PER_DENOMINATION,
+    BlindNonce, CombineError, MintCheckpoint, MintCommonGen, MintConsensusItem, MintError,
+    MintInput, MintModuleTypes, MintOutput, MintOutputBlindSignatures, MintOutputOutcome,
+    MintOutputSignatureShare, MintShareErrors, Note, PeerErrorType, API_VERSIONS,
+    CONSENSUS_VERSION, DEFAULT_MAX_NOTES_PER_DENOMINATION,
 };
-use fedimint_server::config::distributedgen::{scalar, PeerHandleOps};
+use fedimint_server::config::distributedgen::{scalar, DkgKeys, PeerHandleOps, ReshareKeys};
 use futures::StreamExt;
 use itertools::Itertools;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
@@ -55,6 +59,7 @@ use tbs::{
     AggregatePublicKey, PublicKeyShare, SecretKeyShare,
 };
 use threshold_crypto::group::Curve;
+use threshold_crypto::G2Projective;
 use tracing::{debug, error, info, warn};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -76,7 +81,7 @@ impl ServerModuleGen for MintGen {
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
     fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
-        &[ModuleConsensusVersion(0)]
+        &[CONSENSUS_VERSION]
     }
 
     async fn init(
@@ -157,38 +162,79 @@ impl ServerModuleGen for MintGen {
 
         let g2 = peers.run_dkg_multi_g2(params.mint_amounts.to_vec()).await?;
 
-        let amounts_keys = g2
-            .into_iter()
-            .map(|(amount, keys)| (amount, keys.tbs()))
-            .collect::<HashMap<_, _>>();
+        let server = mint_config_from_keys(
+            peers,
+            g2,
+            Default::default(),
+            DEFAULT_MAX_NOTES_PER_DENOMINATION,
+        );
 
-        let server = MintConfig {
-            private: MintConfigPrivate {
-                tbs_sks: amounts_keys
-                    .iter()
-                    .map(|(amount, (_, sks))| (*amount, *sks))
-                    .collect(),
-            },
-            consensus: MintConfigConsensus {
-                peer_tbs_pks: peers
-                    .peer_ids()
-                    .iter()
-                    .map(|peer| {
-                        let pks = amounts_keys
-                            .iter()
-                            .map(|(amount, (pks, _))| {
-                                let pks = PublicKeyShare(pks.evaluate(scalar(peer)).to_affine());
-                                (*amount, pks)
-                            })
-                            .collect::<Tiered<PublicKeyShare>>();
+        Ok(server.to_erased())
+    }
+
+    async fn distributed_reshare(
+        &self,
+        peers: &PeerHandle,
+        _params: &ConfigGenParams,
+        old: &ServerModuleReshareConfig,
+    ) -> DkgResult<ServerModuleConfig> {
+        let old_consensus =
+            serde_json::from_value::<MintConfigConsensus>(old.consensus.value().clone())
+                .map_err(anyhow::Error::from)?;
+        let old_private = old
+            .dealer
+            .as_ref()
+            .map(|(peer, private)| {
+                serde_json::from_value::<MintConfigPrivate>(private.value().clone())
+                    .map(|private| (*peer, private))
+            })
+            .transpose()
+            .map_err(anyhow::Error::from)?;
 
-                        (*peer, pks)
+        let amounts = old_consensus
+            .peer_tbs_pks
+            .values()
+            .next()
+            .ok_or_else(|| anyhow::format_err!("Old mint config has no keys"))?
+            .tiers()
+            .copied()
+            .collect::<Vec<_>>();
+
+        let old_keys = amounts
+            .into_iter()
+            .map(|amount| {
+                let pk_shares = old_consensus
+                    .peer_tbs_pks
+                    .iter()
+                    .map(|(peer, pks)| {
+                        let pk = pks.get(amount).ok_or_else(|| {
+                            anyhow::format_err!("Peer {peer} has no key for {amount}")
+                        })?;
+                        Ok((*peer, *pk))
                     })
-                    .collect(),
-                fee_consensus: Default::default(),
-                max_notes_per_denomination: DEFAULT_MAX_NOTES_PER_DENOMINATION,
-            },
-        };
+                    .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+                let dealer = match &old_private {
+                    Some((peer, private)) => {
+                        let sks = private.tbs_sks.get(amount).ok_or_else(|| {
+                            anyhow::format_err!("Missing our secret key for {amount}")
+                        })?;
+                        Some((*peer, *sks))
+                    }
+                    None => None,
+                };
+
+                Ok((amount, ReshareKeys::tbs(pk_shares, dealer)))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        let g2 = peers.reshare_multi_g2(old_keys).await?;
+
+        let server = mint_config_from_keys(
+            peers,
+            g2,
+            old_consensus.fee_consensus,
+            old_consensus.max_notes_per_denomination,
+        );
 
         Ok(server.to_erased())
     }
@@ -273,12 +319,77 @@ impl ServerModuleGen for MintGen {
                         "User Ecash Backup"
                     );
                 }
+                DbKeyPrefix::IssuedNote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        IssuedNoteKeyPrefix,
+                        IssuedNoteKey,
+                        tbs::BlindedSignature,
+                        mint,
+                        "Issued Notes"
+                    );
+                }
+                DbKeyPrefix::SpentNote => {
+                    push_db_key_items!(
+                        dbtx,
+                        SpentNoteKeyPrefix,
+                        SpentNoteKey,
+                        mint,
+                        "Notes Spent Since Checkpoint"
+                    );
+                }
+                DbKeyPrefix::Params => {
+                    if let Some(params) = dbtx.get_value(&ParamsKey).await {
+                        mint.insert("Params".to_string(), Box::new(params));
+                    }
+                }
             }
         }
 
         Box::new(mint.into_iter())
     }
 }
+/// Builds our mint config from the keys generated for every note denomination
+fn mint_config_from_keys(
+    peers: &PeerHandle,
+    keys: HashMap<Amount, DkgKeys<G2Projective>>,
+    fee_consensus: FeeConsensus,
+    max_notes_per_denomination: u16,
+) -> MintConfig {
+    let amounts_keys = keys
+        .into_iter()
+        .map(|(amount, keys)| (amount, keys.tbs()))
+        .collect::<HashMap<_, _>>();
+
+    MintConfig {
+        private: MintConfigPrivate {
+            tbs_sks: amounts_keys
+                .iter()
+                .map(|(amount, (_, sks))| (*amount, *sks))
+                .collect(),
+        },
+        consensus: MintConfigConsensus {
+            peer_tbs_pks: peers
+                .peer_ids()
+                .iter()
+                .map(|peer| {
+                    let pks = amounts_keys
+                        .iter()
+                        .map(|(amount, (pks, _))| {
+                            let pks = PublicKeyShare(pks.evaluate(scalar(peer)).to_affine());
+                            (*amount, pks)
+                        })
+                        .collect::<Tiered<PublicKeyShare>>();
+
+                    (*peer, pks)
+                })
+                .collect(),
+            fee_consensus,
+            max_notes_per_denomination,
+        },
+    }
+}
+
 /// Federated mint member mint
 #[derive(Debug)]
 pub struct Mint {
@@ -303,10 +414,7 @@ impl ServerModule for Mint {
     }
 
     fn versions(&self) -> (ModuleConsensusVersion, &[ApiVersion]) {
-        (
-            ModuleConsensusVersion(0),
-            &[ApiVersion { major: 0, minor: 0 }],
-        )
+        (CONSENSUS_VERSION, API_VERSIONS)
     }
 
     async fn consensus_proposal(
@@ -341,6 +449,26 @@ impl ServerModule for Mint {
         }
     }
 
+    fn validate_params(&self, params: &ModuleParams) -> anyhow::Result<()> {
+        match params
+            .keys()
+            .find(|name| ![NOTE_ISSUANCE_FEE_PARAM, NOTE_SPEND_FEE_PARAM].contains(&name.as_str()))
+        {
+            Some(name) => Err(anyhow::format_err!("Unknown parameter {name}")),
+            None => Ok(()),
+        }
+    }
+
+    async fn activate_params(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        params: &ModuleParams,
+    ) {
+        let mut active = dbtx.get_value(&ParamsKey).await.unwrap_or_default();
+        active.extend(params.clone());
+        dbtx.insert_entry(&ParamsKey, &active).await;
+    }
+
     fn build_verification_cache<'a>(
         &'a self,
         inputs: impl Iterator<Item = &'a MintInput> + MaybeSend,
@@ -386,14 +514,14 @@ impl ServerModule for Mint {
             }
 
             if dbtx.get_value(&NonceKey(note.0)).await.is_some() {
-                return Err(MintError::SpentCoin).into_module_error_other();
+                return Err(MintError::SpentCoin).into_module_error_conflict();
             }
         }
 
         Ok(InputMeta {
             amount: TransactionItemAmount {
                 amount: input.total_amount(),
-                fee: self.cfg.consensus.fee_consensus.note_spend_abs * (input.count_items() as u64),
+                fee: self.fee_consensus(dbtx).await.note_spend_abs * (input.count_items() as u64),
             },
             puk_keys: input
                 .iter_items()
@@ -416,6 +544,7 @@ impl ServerModule for Mint {
         for (amount, note) in input.iter_items() {
             let key = NonceKey(note.0);
             dbtx.insert_new_entry(&key, &()).await;
+            dbtx.insert_new_entry(&SpentNoteKey(note.0), &()).await;
             dbtx.insert_new_entry(&MintAuditItemKey::Redemption(key), &amount)
                 .await;
         }
@@ -425,7 +554,7 @@ impl ServerModule for Mint {
 
     async fn validate_output(
         &self,
-        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
         output: &MintOutput,
     ) -> Result<TransactionItemAmount, ModuleError> {
         if output.longest_tier_len() > self.cfg.consensus.max_notes_per_denomination.into() {
@@ -447,7 +576,7 @@ impl ServerModule for Mint {
         } else {
             Ok(TransactionItemAmount {
                 amount: output.total_amount(),
-                fee: self.cfg.consensus.fee_consensus.note_issuance_abs
+                fee: self.fee_consensus(dbtx).await.note_issuance_abs
                     * (output.count_items() as u64),
             })
         }
@@ -527,6 +656,24 @@ impl ServerModule for Mint {
             .collect::<Vec<_>>();
 
         for (issuance_data, bsig_res, errors) in issuance_results {
+            let blind_nonces = issuance_data
+                .our_contribution
+                .as_ref()
+                .or_else(|| {
+                    issuance_data
+                        .signature_shares
+                        .first()
+                        .map(|(_, share)| share)
+                })
+                .map(|share| {
+                    share
+                        .0
+                        .iter_items()
+                        .map(|(_, (msg, _))| BlindNonce(*msg))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+
             // FIXME: validate shares before writing to DB to make combine infallible
             errors.0.iter().for_each(|(peer, error)| {
                 error!("Dropping {:?} for {:?}", peer, error);
@@ -553,6 +700,12 @@ impl ServerModule for Mint {
                     })
                     .await;
 
+                    for (blind_nonce, (_, bsig)) in
+                        blind_nonces.into_iter().zip(blind_signature.0.iter_items())
+                    {
+                        dbtx.insert_entry(&IssuedNoteKey(blind_nonce), bsig).await;
+                    }
+
                     dbtx.insert_entry(&OutputOutcomeKey(issuance_data.out_point), &blind_signature)
                         .await;
                 }
@@ -641,6 +794,109 @@ impl ServerModule for Mint {
             .await;
     }
 
+    async fn checkpoint(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Option<Vec<u8>> {
+        let issued = dbtx
+            .find_by_prefix(&IssuedNoteKeyPrefix)
+            .await
+            .map(|(key, bsig)| (key.0, bsig))
+            .collect::<Vec<_>>()
+            .await;
+        let spent = dbtx
+            .find_by_prefix(&SpentNoteKeyPrefix)
+            .await
+            .map(|(key, ())| key.0)
+            .collect::<Vec<_>>()
+            .await;
+
+        // the next checkpoint only contains the changes after this one
+        dbtx.remove_by_prefix(&IssuedNoteKeyPrefix).await;
+        dbtx.remove_by_prefix(&SpentNoteKeyPrefix).await;
+
+        let checkpoint = MintCheckpoint { issued, spent };
+        Some(
+            checkpoint
+                .consensus_encode_to_vec()
+                .expect("Encoding to vec can't fail"),
+        )
+    }
+
+    async fn verify_integrity(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Vec<String> {
+        let audit_items = dbtx
+            .find_by_prefix(&MintAuditItemKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut violations = vec![];
+        let mut issuances = Amount::ZERO;
+        let mut redemptions = Amount::ZERO;
+        for (key, amount) in audit_items {
+            match key {
+                MintAuditItemKey::Issuance(_) | MintAuditItemKey::IssuanceTotal => {
+                    issuances += amount
+                }
+                MintAuditItemKey::Redemption(nonce_key) => {
+                    redemptions += amount;
+                    if dbtx.get_value(&nonce_key).await.is_none() {
+                        violations.push(format!(
+                            "Redeemed note {:?} is not marked as spent",
+                            nonce_key.0
+                        ));
+                    }
+                }
+                MintAuditItemKey::RedemptionTotal => redemptions += amount,
+            }
+        }
+
+        if redemptions > issuances {
+            violations.push(format!(
+                "Redeemed {redemptions} in notes but only issued {issuances}"
+            ));
+        }
+
+        violations
+    }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        // our own signature shares and the backups submitted to our API
+        vec![
+            DbKeyPrefix::ProposedPartialSig as u8,
+            DbKeyPrefix::EcashBackup as u8,
+        ]
+    }
+
+    fn consensus_db_prefixes(&self) -> Vec<u8> {
+        // the partial signatures of our peers are left out, they are removed
+        // once combined
+        vec![
+            DbKeyPrefix::NoteNonce as u8,
+            DbKeyPrefix::OutputOutcome as u8,
+            DbKeyPrefix::MintAuditItem as u8,
+            DbKeyPrefix::IssuedNote as u8,
+            DbKeyPrefix::SpentNote as u8,
+            DbKeyPrefix::Params as u8,
+        ]
+    }
+
+    fn public_input_amount(&self, input: &MintInput) -> Option<Amount> {
+        Some(input.total_amount())
+    }
+
+    fn public_output_amount(&self, output: &MintOutput) -> Option<Amount> {
+        Some(output.total_amount())
+    }
+
+    fn epoch_dependencies(&self) -> Vec<ModuleKind> {
+        // Combining signature shares only touches the mint's own state
+        vec![]
+    }
+
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
         vec![
             api_endpoint! {
@@ -703,6 +959,24 @@ impl Mint {
 }
 
 impl Mint {
+    /// Fees of the config with the changes the guardians activated
+    async fn fee_consensus(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> FeeConsensus {
+        let params = dbtx.get_value(&ParamsKey).await.unwrap_or_default();
+        let fees = &self.cfg.consensus.fee_consensus;
+        let fee = |name: &str, default: Amount| {
+            params
+                .get(name)
+                .map_or(default, |msats| Amount::from_msats(*msats))
+        };
+        FeeConsensus {
+            note_issuance_abs: fee(NOTE_ISSUANCE_FEE_PARAM, fees.note_issuance_abs),
+            note_spend_abs: fee(NOTE_SPEND_FEE_PARAM, fees.note_spend_abs),
+        }
+    }
+
     /// Constructs a new mint
     ///
     /// # Panics
@@ -952,9 +1226,19 @@ mod test {
     use fedimint_core::config::{
         ClientModuleConfig, ConfigGenParams, ServerModuleConfig, TypedServerModuleConsensusConfig,
     };
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::encoding::Decodable;
+    use fedimint_core::epoch::ModuleParams;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::module::ServerModuleGen;
-    use fedimint_core::{Amount, PeerId, TieredMulti};
-    use fedimint_mint_common::config::{FeeConsensus, MintClientConfig};
+    use fedimint_core::{Amount, PeerId, ServerModule, TieredMulti};
+    use fedimint_mint_common::config::{
+        FeeConsensus, MintClientConfig, NOTE_ISSUANCE_FEE_PARAM, NOTE_SPEND_FEE_PARAM,
+    };
+    use fedimint_mint_common::db::{IssuedNoteKey, MintAuditItemKey, NonceKey, SpentNoteKey};
+    use fedimint_mint_common::{MintCheckpoint, Nonce};
+    use rand::rngs::OsRng;
     use tbs::{blind_message, unblind_signature, verify, AggregatePublicKey, BlindingKey, Message};
 
     use crate::{
@@ -1143,6 +1427,118 @@ mod test {
             .contains(&(PeerId::from(3), PeerErrorType::DifferentNonce)));
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_verify_integrity() {
+        let (_, mints) = build_mints();
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.with_module_prefix(0);
+
+        module_dbtx
+            .insert_new_entry(&MintAuditItemKey::IssuanceTotal, &Amount::from_sats(10))
+            .await;
+        assert!(mints[0].verify_integrity(&mut module_dbtx).await.is_empty());
+
+        // redeeming more than was issued without marking the note as spent
+        let (_, pk) = secp256k1::generate_keypair(&mut OsRng);
+        let nonce_key = NonceKey(Nonce(pk.x_only_public_key().0));
+        module_dbtx
+            .insert_new_entry(
+                &MintAuditItemKey::Redemption(nonce_key.clone()),
+                &Amount::from_sats(20),
+            )
+            .await;
+        assert_eq!(mints[0].verify_integrity(&mut module_dbtx).await.len(), 2);
+
+        module_dbtx.insert_new_entry(&nonce_key, &()).await;
+        assert_eq!(mints[0].verify_integrity(&mut module_dbtx).await.len(), 1);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_checkpoint_contains_changes_since_previous() {
+        let (_, mints) = build_mints();
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.with_module_prefix(0);
+
+        let blind_nonce = BlindNonce(blind_message(
+            Message::from_bytes(&b"test note"[..]),
+            BlindingKey::random(),
+        ));
+        let bsig = tbs::BlindedSignature(blind_nonce.0 .0);
+        let (_, pk) = secp256k1::generate_keypair(&mut OsRng);
+        let nonce = Nonce(pk.x_only_public_key().0);
+        module_dbtx
+            .insert_new_entry(&IssuedNoteKey(blind_nonce), &bsig)
+            .await;
+        module_dbtx
+            .insert_new_entry(&SpentNoteKey(nonce), &())
+            .await;
+
+        let checkpoint = mints[0].checkpoint(&mut module_dbtx).await.unwrap();
+        let checkpoint = MintCheckpoint::consensus_decode(
+            &mut &checkpoint[..],
+            &ModuleDecoderRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            checkpoint,
+            MintCheckpoint {
+                issued: vec![(blind_nonce, bsig)],
+                spent: vec![nonce],
+            }
+        );
+
+        // everything was moved into the first checkpoint
+        let checkpoint = mints[0].checkpoint(&mut module_dbtx).await.unwrap();
+        let checkpoint = MintCheckpoint::consensus_decode(
+            &mut &checkpoint[..],
+            &ModuleDecoderRegistry::default(),
+        )
+        .unwrap();
+        assert!(checkpoint.issued.is_empty());
+        assert!(checkpoint.spent.is_empty());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_activated_params_change_the_fees() {
+        let (_, mints) = build_mints();
+        let mint = &mints[0];
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.with_module_prefix(0);
+
+        assert!(mint
+            .validate_params(&ModuleParams::from([("max_notes".to_string(), 1)]))
+            .is_err());
+        assert_eq!(
+            mint.fee_consensus(&mut module_dbtx).await,
+            mint.cfg.consensus.fee_consensus
+        );
+
+        let spend_fee = ModuleParams::from([(NOTE_SPEND_FEE_PARAM.to_string(), 1_000)]);
+        assert!(mint.validate_params(&spend_fee).is_ok());
+        mint.activate_params(&mut module_dbtx, &spend_fee).await;
+        assert_eq!(
+            mint.fee_consensus(&mut module_dbtx).await,
+            FeeConsensus {
+                note_spend_abs: Amount::from_msats(1_000),
+                ..mint.cfg.consensus.fee_consensus.clone()
+            }
+        );
+
+        // later changes keep the params they leave out
+        let issuance_fee = ModuleParams::from([(NOTE_ISSUANCE_FEE_PARAM.to_string(), 2_000)]);
+        mint.activate_params(&mut module_dbtx, &issuance_fee).await;
+        assert_eq!(
+            mint.fee_consensus(&mut module_dbtx).await,
+            FeeConsensus {
+                note_issuance_abs: Amount::from_msats(2_000),
+                note_spend_abs: Amount::from_msats(1_000),
+            }
+        );
+    }
+
     #[test_log::test]
     #[should_panic(expected = "Own key not found among pub keys.")]
     fn test_new_panic_without_own_pub_key() {
@@ -1391,6 +1787,9 @@ mod fedimint_migration_tests {
                                 "validate_migrations was not able to read any EcashBackups"
                             );
                         }
+                        // Issued and spent notes and params were added after the v0
+                        // snapshot was taken
+                        DbKeyPrefix::IssuedNote | DbKeyPrefix::SpentNote | DbKeyPrefix::Params => {}
                     }
                 }
             },
@@ -0,0 +1,179 @@
+use fedimint_client::module::gen::ClientModuleGen;
+use fedimint_client::module::ClientModule;
+use fedimint_client::sm::{DynState, OperationId, State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::api::{FederationApiExt, FederationResult, IFederationApi};
+use fedimint_core::core::{IntoDynInstance, ModuleInstanceId};
+use fedimint_core::db::Database;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{
+    ApiRequestErased, ExtendsCommonModuleGen, ModuleCommon, TransactionItemAmount,
+};
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send, Amount};
+use fedimint_stability_pool_common::config::StabilityPoolClientConfig;
+pub use fedimint_stability_pool_common::*;
+
+#[derive(Debug, Clone)]
+pub struct StabilityPoolClientGen;
+
+impl ExtendsCommonModuleGen for StabilityPoolClientGen {
+    type Common = StabilityPoolCommonGen;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl ClientModuleGen for StabilityPoolClientGen {
+    type Module = StabilityPoolClientModule;
+    type Config = StabilityPoolClientConfig;
+
+    async fn init(
+        &self,
+        cfg: Self::Config,
+        _db: Database,
+        _instance_id: ModuleInstanceId,
+    ) -> anyhow::Result<Self::Module> {
+        Ok(StabilityPoolClientModule { cfg })
+    }
+}
+
+#[derive(Debug)]
+pub struct StabilityPoolClientModule {
+    cfg: StabilityPoolClientConfig,
+}
+
+impl StabilityPoolClientModule {
+    /// Creates an output depositing `amount` into the account of `account` on
+    /// the given `side` of the pool
+    pub fn deposit_output(
+        &self,
+        account: secp256k1::XOnlyPublicKey,
+        side: Side,
+        amount: Amount,
+    ) -> anyhow::Result<StabilityPoolOutput> {
+        if amount < self.cfg.min_deposit {
+            return Err(StabilityPoolError::DepositTooSmall(amount, self.cfg.min_deposit).into());
+        }
+
+        Ok(StabilityPoolOutput {
+            account,
+            side,
+            amount,
+        })
+    }
+
+    /// Creates an input withdrawing `amount` from the account of `account` on
+    /// the given `side` of the pool. The transaction has to be signed with the
+    /// account key.
+    pub fn withdraw_input(
+        &self,
+        account: secp256k1::XOnlyPublicKey,
+        side: Side,
+        amount: Amount,
+    ) -> StabilityPoolInput {
+        StabilityPoolInput {
+            account,
+            side,
+            amount,
+        }
+    }
+}
+
+impl ClientModule for StabilityPoolClientModule {
+    type Common = StabilityPoolModuleTypes;
+    type ModuleStateMachineContext = ();
+    type States = StabilityPoolClientStates;
+
+    fn context(&self) -> Self::ModuleStateMachineContext {}
+
+    fn input_amount(&self, input: &<Self::Common as ModuleCommon>::Input) -> TransactionItemAmount {
+        TransactionItemAmount {
+            amount: input.amount,
+            fee: self.cfg.fee_consensus.withdrawal,
+        }
+    }
+
+    fn output_amount(
+        &self,
+        output: &<Self::Common as ModuleCommon>::Output,
+    ) -> TransactionItemAmount {
+        TransactionItemAmount {
+            amount: output.amount,
+            fee: self.cfg.fee_consensus.deposit,
+        }
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+pub trait StabilityPoolFederationApi {
+    /// Fetch the balance of an account, which changes with every rebalancing
+    async fn fetch_account(
+        &self,
+        instance_id: ModuleInstanceId,
+        side: Side,
+        account: secp256k1::XOnlyPublicKey,
+    ) -> FederationResult<AccountInfo>;
+
+    /// Fetch the epoch and price of the last rebalancing
+    async fn fetch_pool_state(
+        &self,
+        instance_id: ModuleInstanceId,
+    ) -> FederationResult<Option<PoolState>>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> StabilityPoolFederationApi for T
+where
+    T: IFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn fetch_account(
+        &self,
+        instance_id: ModuleInstanceId,
+        side: Side,
+        account: secp256k1::XOnlyPublicKey,
+    ) -> FederationResult<AccountInfo> {
+        self.request_current_consensus(
+            format!("/module/{instance_id}/account"),
+            ApiRequestErased::new((side, account)),
+        )
+        .await
+    }
+
+    async fn fetch_pool_state(
+        &self,
+        instance_id: ModuleInstanceId,
+    ) -> FederationResult<Option<PoolState>> {
+        self.request_current_consensus(
+            format!("/module/{instance_id}/pool_state"),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub enum StabilityPoolClientStates {}
+
+impl IntoDynInstance for StabilityPoolClientStates {
+    type DynType = DynState<DynGlobalClientContext>;
+
+    fn into_dyn(self, instance_id: ModuleInstanceId) -> Self::DynType {
+        DynState::from_typed(instance_id, self)
+    }
+}
+
+impl State for StabilityPoolClientStates {
+    type ModuleContext = ();
+    type GlobalContext = DynGlobalClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        _global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        unimplemented!()
+    }
+
+    fn operation_id(&self) -> OperationId {
+        unimplemented!()
+    }
+}
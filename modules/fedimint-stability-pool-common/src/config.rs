@@ -0,0 +1,107 @@
+use fedimint_core::config::{
+    ClientModuleConfig, TypedClientModuleConfig, TypedServerModuleConfig,
+    TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::ModuleKind;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::{Amount, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::KIND;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StabilityPoolConfig {
+    /// Contains all configuration that needs to be the same for every server
+    pub consensus: StabilityPoolConfigConsensus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encodable)]
+pub struct StabilityPoolConfigConsensus {
+    /// Number of guardians that need to propose a price for the pool to be
+    /// rebalanced
+    pub threshold: u64,
+    /// Length of a rebalancing epoch in seconds
+    pub epoch_length: u64,
+    /// Fee seekers pay to providers every epoch in parts per million of their
+    /// balance
+    pub seeker_fee_ppm: u64,
+    /// Smallest amount that can be deposited into the pool
+    pub min_deposit: Amount,
+    /// Fees charged for stability pool transactions
+    pub fee_consensus: FeeConsensus,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
+pub struct StabilityPoolClientConfig {
+    pub epoch_length: u64,
+    pub seeker_fee_ppm: u64,
+    pub min_deposit: Amount,
+    pub fee_consensus: FeeConsensus,
+}
+
+impl TypedClientModuleConfig for StabilityPoolClientConfig {
+    fn kind(&self) -> ModuleKind {
+        KIND
+    }
+}
+
+impl TypedServerModuleConsensusConfig for StabilityPoolConfigConsensus {
+    fn to_client_config(&self) -> ClientModuleConfig {
+        ClientModuleConfig::from_typed(
+            KIND,
+            &StabilityPoolClientConfig {
+                epoch_length: self.epoch_length,
+                seeker_fee_ppm: self.seeker_fee_ppm,
+                min_deposit: self.min_deposit,
+                fee_consensus: self.fee_consensus.clone(),
+            },
+        )
+        .expect("Serialization can't fail")
+    }
+}
+
+impl TypedServerModuleConfig for StabilityPoolConfig {
+    type Local = ();
+    type Private = ();
+    type Consensus = StabilityPoolConfigConsensus;
+
+    fn from_parts(
+        _local: Self::Local,
+        _private: Self::Private,
+        consensus: Self::Consensus,
+    ) -> Self {
+        Self { consensus }
+    }
+
+    fn to_parts(self) -> (ModuleKind, Self::Local, Self::Private, Self::Consensus) {
+        (KIND, (), (), self.consensus)
+    }
+
+    fn validate_config(&self, _identity: &PeerId) -> anyhow::Result<()> {
+        if self.consensus.threshold == 0 {
+            anyhow::bail!("Stability pool price threshold must not be zero");
+        }
+        if self.consensus.epoch_length == 0 {
+            anyhow::bail!("Stability pool epoch length must not be zero");
+        }
+        if self.consensus.seeker_fee_ppm > 1_000_000 {
+            anyhow::bail!("Stability pool seeker fee can't exceed 100%");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
+pub struct FeeConsensus {
+    pub deposit: Amount,
+    pub withdrawal: Amount,
+}
+
+impl Default for FeeConsensus {
+    fn default() -> Self {
+        Self {
+            deposit: Amount::ZERO,
+            withdrawal: Amount::ZERO,
+        }
+    }
+}
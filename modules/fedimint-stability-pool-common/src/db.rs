@@ -0,0 +1,76 @@
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, OutPoint};
+use serde::Serialize;
+use strum_macros::EnumIter;
+
+use crate::{PoolState, StabilityPoolOutputOutcome};
+
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    Seeker = 0xa0,
+    Provider = 0xa1,
+    PoolState = 0xa2,
+    OutputOutcome = 0xa3,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct SeekerKey(pub secp256k1::XOnlyPublicKey);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+pub struct SeekerKeyPrefix;
+
+impl_db_record!(
+    key = SeekerKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::Seeker,
+);
+impl_db_lookup!(key = SeekerKey, query_prefix = SeekerKeyPrefix);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ProviderKey(pub secp256k1::XOnlyPublicKey);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+pub struct ProviderKeyPrefix;
+
+impl_db_record!(
+    key = ProviderKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::Provider,
+);
+impl_db_lookup!(key = ProviderKey, query_prefix = ProviderKeyPrefix);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct PoolStateKey;
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+pub struct PoolStateKeyPrefix;
+
+impl_db_record!(
+    key = PoolStateKey,
+    value = PoolState,
+    db_prefix = DbKeyPrefix::PoolState,
+);
+impl_db_lookup!(key = PoolStateKey, query_prefix = PoolStateKeyPrefix);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OutputOutcomeKey(pub OutPoint);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable)]
+pub struct OutputOutcomeKeyPrefix;
+
+impl_db_record!(
+    key = OutputOutcomeKey,
+    value = StabilityPoolOutputOutcome,
+    db_prefix = DbKeyPrefix::OutputOutcome,
+);
+impl_db_lookup!(
+    key = OutputOutcomeKey,
+    query_prefix = OutputOutcomeKeyPrefix
+);
@@ -0,0 +1,204 @@
+//! # Stability Pool Module
+//!
+//! This module allows users to hold a balance that keeps its value in a fiat
+//! currency. There are two kinds of participants:
+//!
+//!   * *seekers* lock e-cash into the pool to hold a stable fiat-denominated
+//!     balance
+//!   * *providers* lock e-cash into the pool to take the other, leveraged side
+//!     of the seekers' positions
+//!
+//! The guardians agree on the fiat price of bitcoin using an oracle-price
+//! consensus item. Once per rebalancing epoch the pool moves funds between
+//! seekers and providers so that the seekers' balances keep the fiat value
+//! they had at the last rebalancing. In exchange seekers pay a fee to the
+//! providers every epoch.
+//!
+//! If the price of bitcoin falls so far that the providers can't cover the
+//! seekers' losses anymore seekers will lose value too, so the pool is only
+//! as stable as its providers are capitalized.
+
+pub mod config;
+pub mod db;
+
+use std::fmt;
+
+use fedimint_core::core::{Decoder, ModuleKind};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::{CommonModuleGen, ModuleCommon};
+use fedimint_core::{plugin_types_trait_impl_common, Amount};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config::StabilityPoolClientConfig;
+
+const KIND: ModuleKind = ModuleKind::from_static_str("stability-pool");
+
+/// The side of the pool an account is on
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Deserialize,
+    Serialize,
+    Encodable,
+    Decodable,
+)]
+pub enum Side {
+    /// Holds a fiat-denominated balance
+    Seeker,
+    /// Absorbs the price movements of the seekers' balances
+    Provider,
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Seeker => write!(f, "seeker"),
+            Side::Provider => write!(f, "provider"),
+        }
+    }
+}
+
+/// Withdraws `amount` from the account of `account` on the given `side`. The
+/// withdrawal is authorized by the transaction signature of the account key.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct StabilityPoolInput {
+    pub account: secp256k1::XOnlyPublicKey,
+    pub side: Side,
+    pub amount: Amount,
+}
+
+impl fmt::Display for StabilityPoolInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Stability pool {} withdrawal of {} from {}",
+            self.side, self.amount, self.account
+        )
+    }
+}
+
+/// Deposits `amount` into the account of `account` on the given `side`,
+/// creating the account if it doesn't exist yet
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct StabilityPoolOutput {
+    pub account: secp256k1::XOnlyPublicKey,
+    pub side: Side,
+    pub amount: Amount,
+}
+
+impl fmt::Display for StabilityPoolOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Stability pool {} deposit of {} to {}",
+            self.side, self.amount, self.account
+        )
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct StabilityPoolOutputOutcome {
+    pub account: secp256k1::XOnlyPublicKey,
+    pub side: Side,
+}
+
+impl fmt::Display for StabilityPoolOutputOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Stability pool {} deposit to {}",
+            self.side, self.account
+        )
+    }
+}
+
+/// The price a guardian's oracle reported for the given rebalancing epoch
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct StabilityPoolConsensusItem {
+    /// Number of the rebalancing epoch, i.e. the unix time divided by the
+    /// configured epoch length
+    pub epoch: u64,
+    /// Price of one unit of the fiat currency in msat
+    pub price: u64,
+}
+
+impl fmt::Display for StabilityPoolConsensusItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Stability pool epoch {} price {} msat",
+            self.epoch, self.price
+        )
+    }
+}
+
+/// Result of the last rebalancing of the pool
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PoolState {
+    /// Rebalancing epoch the pool was last rebalanced in
+    pub epoch: u64,
+    /// Price of one unit of the fiat currency in msat agreed on in `epoch`
+    pub price: u64,
+}
+
+/// Balances of an account and the pool as returned by the API
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub side: Side,
+    pub amount: Amount,
+    /// The state of the pool at the time the balance was last rebalanced,
+    /// `None` if no price was agreed on yet
+    pub pool_state: Option<PoolState>,
+}
+
+#[derive(Debug)]
+pub struct StabilityPoolCommonGen;
+
+impl CommonModuleGen for StabilityPoolCommonGen {
+    const KIND: ModuleKind = KIND;
+
+    fn decoder() -> Decoder {
+        StabilityPoolModuleTypes::decoder()
+    }
+
+    fn hash_client_module(
+        config: serde_json::Value,
+    ) -> anyhow::Result<bitcoin_hashes::sha256::Hash> {
+        serde_json::from_value::<StabilityPoolClientConfig>(config)?.consensus_hash()
+    }
+}
+
+pub struct StabilityPoolModuleTypes;
+
+impl ModuleCommon for StabilityPoolModuleTypes {
+    type Input = StabilityPoolInput;
+    type Output = StabilityPoolOutput;
+    type OutputOutcome = StabilityPoolOutputOutcome;
+    type ConsensusItem = StabilityPoolConsensusItem;
+}
+
+plugin_types_trait_impl_common!(
+    StabilityPoolInput,
+    StabilityPoolOutput,
+    StabilityPoolOutputOutcome,
+    StabilityPoolConsensusItem
+);
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum StabilityPoolError {
+    #[error("The {0} account {1} does not exist")]
+    UnknownAccount(Side, secp256k1::XOnlyPublicKey),
+    #[error("The account only holds {0}, withdrawal of {1} requested")]
+    InsufficientBalance(Amount, Amount),
+    #[error("Deposits and withdrawals may not be zero")]
+    ZeroAmount,
+    #[error("Deposit of {0} is below the minimum of {1}")]
+    DepositTooSmall(Amount, Amount),
+}
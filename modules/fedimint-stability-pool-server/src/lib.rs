@@ -0,0 +1,724 @@
+pub mod oracle;
+
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use fedimint_core::config::{
+    ConfigGenParams, DkgResult, ModuleConfigResponse, ModuleGenParams, ServerModuleConfig,
+    ServerModuleReshareConfig, TypedServerModuleConfig, TypedServerModuleConsensusConfig,
+};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{Database, DatabaseVersion, ModuleDatabaseTransaction};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::interconnect::ModuleInterconect;
+use fedimint_core::module::{
+    api_endpoint, ApiEndpoint, ApiError, ApiVersion, ConsensusProposal, CoreConsensusVersion,
+    ExtendsCommonModuleGen, InputMeta, IntoModuleError, ModuleConsensusVersion, ModuleError,
+    PeerHandle, ServerModuleGen, TransactionItemAmount,
+};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::{push_db_pair_items, Amount, NumPeers, OutPoint, PeerId, ServerModule};
+pub use fedimint_stability_pool_common as common;
+use fedimint_stability_pool_common::config::{
+    FeeConsensus, StabilityPoolConfig, StabilityPoolConfigConsensus,
+};
+use fedimint_stability_pool_common::db::{
+    DbKeyPrefix, OutputOutcomeKey, OutputOutcomeKeyPrefix, PoolStateKey, ProviderKey,
+    ProviderKeyPrefix, SeekerKey, SeekerKeyPrefix,
+};
+use fedimint_stability_pool_common::{
+    AccountInfo, PoolState, Side, StabilityPoolCommonGen, StabilityPoolConsensusItem,
+    StabilityPoolError, StabilityPoolInput, StabilityPoolModuleTypes, StabilityPoolOutput,
+    StabilityPoolOutputOutcome,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::oracle::{HttpOracle, Oracle, FM_STABILITY_POOL_ORACLE_URL_ENV};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StabilityPoolGenParams {
+    /// Length of a rebalancing epoch in seconds
+    pub epoch_length: u64,
+    /// Fee seekers pay to providers every epoch in parts per million
+    pub seeker_fee_ppm: u64,
+    /// Smallest amount that can be deposited into the pool
+    pub min_deposit: Amount,
+}
+
+impl ModuleGenParams for StabilityPoolGenParams {}
+
+#[derive(Debug, Clone)]
+pub struct StabilityPoolGen;
+
+impl ExtendsCommonModuleGen for StabilityPoolGen {
+    type Common = StabilityPoolCommonGen;
+}
+
+#[async_trait]
+impl ServerModuleGen for StabilityPoolGen {
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+
+    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        &[ModuleConsensusVersion(0)]
+    }
+
+    async fn init(
+        &self,
+        cfg: ServerModuleConfig,
+        _db: Database,
+        env: &BTreeMap<OsString, OsString>,
+        _task_group: &mut TaskGroup,
+    ) -> anyhow::Result<DynServerModule> {
+        let oracle_url = env
+            .get(OsStr::new(FM_STABILITY_POOL_ORACLE_URL_ENV))
+            .and_then(|url| url.to_str())
+            .with_context(|| format!("{FM_STABILITY_POOL_ORACLE_URL_ENV} not set"))?;
+        let oracle = HttpOracle::new(Url::parse(oracle_url)?);
+
+        Ok(StabilityPool::new(cfg.to_typed()?, Arc::new(oracle)).into())
+    }
+
+    fn trusted_dealer_gen(
+        &self,
+        peers: &[PeerId],
+        params: &ConfigGenParams,
+    ) -> BTreeMap<PeerId, ServerModuleConfig> {
+        let params = params
+            .to_typed::<StabilityPoolGenParams>()
+            .expect("Invalid stability pool gen params");
+
+        peers
+            .iter()
+            .map(|&peer| {
+                let config = StabilityPoolConfig {
+                    consensus: StabilityPoolConfigConsensus {
+                        threshold: peers.threshold() as u64,
+                        epoch_length: params.epoch_length,
+                        seeker_fee_ppm: params.seeker_fee_ppm,
+                        min_deposit: params.min_deposit,
+                        fee_consensus: FeeConsensus::default(),
+                    },
+                };
+                (peer, config.to_erased())
+            })
+            .collect()
+    }
+
+    async fn distributed_gen(
+        &self,
+        peers: &PeerHandle,
+        params: &ConfigGenParams,
+    ) -> DkgResult<ServerModuleConfig> {
+        let params = params
+            .to_typed::<StabilityPoolGenParams>()
+            .expect("Invalid stability pool gen params");
+
+        let server = StabilityPoolConfig {
+            consensus: StabilityPoolConfigConsensus {
+                threshold: peers.peer_ids().threshold() as u64,
+                epoch_length: params.epoch_length,
+                seeker_fee_ppm: params.seeker_fee_ppm,
+                min_deposit: params.min_deposit,
+                fee_consensus: FeeConsensus::default(),
+            },
+        };
+
+        Ok(server.to_erased())
+    }
+
+    async fn distributed_reshare(
+        &self,
+        peers: &PeerHandle,
+        _params: &ConfigGenParams,
+        old: &ServerModuleReshareConfig,
+    ) -> DkgResult<ServerModuleConfig> {
+        // the pool has no keys, only the price threshold depends on the guardians
+        let old_consensus =
+            serde_json::from_value::<StabilityPoolConfigConsensus>(old.consensus.value().clone())
+                .map_err(anyhow::Error::from)?;
+
+        let server = StabilityPoolConfig {
+            consensus: StabilityPoolConfigConsensus {
+                threshold: peers.peer_ids().threshold() as u64,
+                ..old_consensus
+            },
+        };
+
+        Ok(server.to_erased())
+    }
+
+    fn to_config_response(
+        &self,
+        config: serde_json::Value,
+    ) -> anyhow::Result<ModuleConfigResponse> {
+        let config = serde_json::from_value::<StabilityPoolConfigConsensus>(config)?;
+
+        Ok(ModuleConfigResponse {
+            client: config.to_client_config(),
+            consensus_hash: config.consensus_hash()?,
+        })
+    }
+
+    fn validate_config(&self, identity: &PeerId, config: ServerModuleConfig) -> anyhow::Result<()> {
+        config
+            .to_typed::<StabilityPoolConfig>()?
+            .validate_config(identity)
+    }
+
+    async fn dump_database(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        prefix_names: Vec<String>,
+    ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
+        let mut pool: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> = BTreeMap::new();
+        let filtered_prefixes = DbKeyPrefix::iter().filter(|f| {
+            prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+        });
+        for table in filtered_prefixes {
+            match table {
+                DbKeyPrefix::Seeker => {
+                    push_db_pair_items!(dbtx, SeekerKeyPrefix, SeekerKey, Amount, pool, "Seekers");
+                }
+                DbKeyPrefix::Provider => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ProviderKeyPrefix,
+                        ProviderKey,
+                        Amount,
+                        pool,
+                        "Providers"
+                    );
+                }
+                DbKeyPrefix::PoolState => {
+                    if let Some(state) = dbtx.get_value(&PoolStateKey).await {
+                        pool.insert("Pool State".to_string(), Box::new(state));
+                    }
+                }
+                DbKeyPrefix::OutputOutcome => {
+                    push_db_pair_items!(
+                        dbtx,
+                        OutputOutcomeKeyPrefix,
+                        OutputOutcomeKey,
+                        StabilityPoolOutputOutcome,
+                        pool,
+                        "Output Outcomes"
+                    );
+                }
+            }
+        }
+
+        Box::new(pool.into_iter())
+    }
+}
+
+/// Server side of the stability pool, see [`fedimint_stability_pool_common`]
+/// for an overview.
+///
+/// Every guardian proposes the price reported by its [`Oracle`] once per
+/// rebalancing epoch. As soon as `threshold` guardians proposed a price for a
+/// new epoch the pool is rebalanced at the median of these prices.
+#[derive(Debug)]
+pub struct StabilityPool {
+    cfg: StabilityPoolConfig,
+    oracle: Arc<dyn Oracle>,
+}
+
+#[async_trait]
+impl ServerModule for StabilityPool {
+    type Common = StabilityPoolModuleTypes;
+    type Gen = StabilityPoolGen;
+    type VerificationCache = StabilityPoolVerificationCache;
+
+    fn versions(&self) -> (ModuleConsensusVersion, &[ApiVersion]) {
+        (
+            ModuleConsensusVersion(0),
+            &[ApiVersion { major: 0, minor: 0 }],
+        )
+    }
+
+    async fn await_consensus_proposal(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) {
+        while !self.consensus_proposal(dbtx).await.forces_new_epoch() {
+            sleep(Duration::from_millis(1000)).await;
+        }
+    }
+
+    async fn consensus_proposal(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> ConsensusProposal<StabilityPoolConsensusItem> {
+        let epoch = self.current_epoch();
+
+        // Only query the oracle once a new epoch started to not hammer it
+        if let Some(state) = dbtx.get_value(&PoolStateKey).await {
+            if state.epoch >= epoch {
+                return ConsensusProposal::empty();
+            }
+        }
+
+        match self.oracle.price().await {
+            Ok(price) => {
+                ConsensusProposal::Trigger(vec![StabilityPoolConsensusItem { epoch, price }])
+            }
+            Err(e) => {
+                warn!("Failed to fetch price from oracle: {e:?}");
+                ConsensusProposal::empty()
+            }
+        }
+    }
+
+    async fn begin_consensus_epoch<'a, 'b>(
+        &'a self,
+        dbtx: &mut ModuleDatabaseTransaction<'b, ModuleInstanceId>,
+        consensus_items: Vec<(PeerId, StabilityPoolConsensusItem)>,
+    ) {
+        let last_state = dbtx.get_value(&PoolStateKey).await;
+        let last_epoch = last_state.as_ref().map(|state| state.epoch);
+
+        let mut proposals = BTreeMap::new();
+        for (peer, item) in consensus_items {
+            if item.price == 0 || Some(item.epoch) <= last_epoch {
+                debug!(%peer, ?item, "Ignoring outdated or invalid price proposal");
+                continue;
+            }
+            if proposals.insert(peer, item).is_some() {
+                // FIXME: ban peers instead of warning
+                warn!("Peer {} submitted multiple price proposals", peer);
+            }
+        }
+
+        if (proposals.len() as u64) < self.cfg.consensus.threshold {
+            return;
+        }
+
+        let mut epochs: Vec<_> = proposals.values().map(|item| item.epoch).collect();
+        epochs.sort_unstable();
+        let mut prices: Vec<_> = proposals.values().map(|item| item.price).collect();
+        prices.sort_unstable();
+
+        let new_state = PoolState {
+            epoch: epochs[epochs.len() / 2],
+            price: prices[prices.len() / 2],
+        };
+
+        if let Some(last_state) = last_state {
+            self.rebalance(dbtx, last_state.price, new_state.price)
+                .await;
+        }
+
+        info!(
+            epoch = new_state.epoch,
+            price = new_state.price,
+            "Rebalanced stability pool"
+        );
+        dbtx.insert_entry(&PoolStateKey, &new_state).await;
+    }
+
+    fn build_verification_cache<'a>(
+        &'a self,
+        _inputs: impl Iterator<Item = &'a StabilityPoolInput> + Send,
+    ) -> Self::VerificationCache {
+        StabilityPoolVerificationCache
+    }
+
+    async fn validate_input<'a, 'b>(
+        &self,
+        _interconnect: &dyn ModuleInterconect,
+        dbtx: &mut ModuleDatabaseTransaction<'b, ModuleInstanceId>,
+        _verification_cache: &Self::VerificationCache,
+        input: &'a StabilityPoolInput,
+    ) -> Result<InputMeta, ModuleError> {
+        if input.amount == Amount::ZERO {
+            return Err(StabilityPoolError::ZeroAmount).into_module_error_other();
+        }
+
+        let balance = get_balance(dbtx, input.side, input.account)
+            .await
+            .ok_or(StabilityPoolError::UnknownAccount(
+                input.side,
+                input.account,
+            ))
+            .into_module_error_other()?;
+
+        if balance < input.amount {
+            return Err(StabilityPoolError::InsufficientBalance(
+                balance,
+                input.amount,
+            ))
+            .into_module_error_other();
+        }
+
+        Ok(InputMeta {
+            amount: TransactionItemAmount {
+                amount: input.amount,
+                fee: self.cfg.consensus.fee_consensus.withdrawal,
+            },
+            puk_keys: vec![input.account],
+        })
+    }
+
+    async fn apply_input<'a, 'b, 'c>(
+        &'a self,
+        interconnect: &'a dyn ModuleInterconect,
+        dbtx: &mut ModuleDatabaseTransaction<'c, ModuleInstanceId>,
+        input: &'b StabilityPoolInput,
+        cache: &Self::VerificationCache,
+    ) -> Result<InputMeta, ModuleError> {
+        let meta = self
+            .validate_input(interconnect, dbtx, cache, input)
+            .await?;
+
+        let balance = get_balance(dbtx, input.side, input.account)
+            .await
+            .expect("Should fail validation if account doesn't exist");
+        set_balance(dbtx, input.side, input.account, balance - input.amount).await;
+
+        Ok(meta)
+    }
+
+    async fn validate_output(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        output: &StabilityPoolOutput,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        if output.amount == Amount::ZERO {
+            return Err(StabilityPoolError::ZeroAmount).into_module_error_other();
+        }
+
+        if output.amount < self.cfg.consensus.min_deposit {
+            return Err(StabilityPoolError::DepositTooSmall(
+                output.amount,
+                self.cfg.consensus.min_deposit,
+            ))
+            .into_module_error_other();
+        }
+
+        Ok(TransactionItemAmount {
+            amount: output.amount,
+            fee: self.cfg.consensus.fee_consensus.deposit,
+        })
+    }
+
+    async fn apply_output<'a, 'b>(
+        &'a self,
+        dbtx: &mut ModuleDatabaseTransaction<'b, ModuleInstanceId>,
+        output: &'a StabilityPoolOutput,
+        out_point: OutPoint,
+    ) -> Result<TransactionItemAmount, ModuleError> {
+        let amount = self.validate_output(dbtx, output).await?;
+
+        let balance = get_balance(dbtx, output.side, output.account)
+            .await
+            .unwrap_or(Amount::ZERO);
+        set_balance(dbtx, output.side, output.account, balance + output.amount).await;
+
+        dbtx.insert_new_entry(
+            &OutputOutcomeKey(out_point),
+            &StabilityPoolOutputOutcome {
+                account: output.account,
+                side: output.side,
+            },
+        )
+        .await;
+
+        Ok(amount)
+    }
+
+    async fn end_consensus_epoch<'a, 'b>(
+        &'a self,
+        _consensus_peers: &HashSet<PeerId>,
+        _dbtx: &mut ModuleDatabaseTransaction<'b, ModuleInstanceId>,
+    ) -> Vec<PeerId> {
+        vec![]
+    }
+
+    async fn output_status(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        out_point: OutPoint,
+    ) -> Option<StabilityPoolOutputOutcome> {
+        dbtx.get_value(&OutputOutcomeKey(out_point)).await
+    }
+
+    async fn audit(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        audit: &mut Audit,
+    ) {
+        audit
+            .add_items(dbtx, &SeekerKeyPrefix, |_, v| -(v.msats as i64))
+            .await;
+        audit
+            .add_items(dbtx, &ProviderKeyPrefix, |_, v| -(v.msats as i64))
+            .await;
+    }
+
+    fn consensus_db_prefixes(&self) -> Vec<u8> {
+        // the pool state follows the agreed prices, the accounts and outcomes
+        // accepted transactions
+        vec![
+            DbKeyPrefix::Seeker as u8,
+            DbKeyPrefix::Provider as u8,
+            DbKeyPrefix::PoolState as u8,
+            DbKeyPrefix::OutputOutcome as u8,
+        ]
+    }
+
+    fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
+        vec![
+            api_endpoint! {
+                "/account",
+                async |_module: &StabilityPool, context, request: (Side, secp256k1::XOnlyPublicKey)| -> AccountInfo {
+                    let (side, account) = request;
+                    let mut dbtx = context.dbtx();
+                    let amount = get_balance(&mut dbtx, side, account)
+                        .await
+                        .ok_or_else(|| ApiError::not_found(String::from("Account not found")))?;
+                    let pool_state = dbtx.get_value(&PoolStateKey).await;
+
+                    Ok(AccountInfo {
+                        side,
+                        amount,
+                        pool_state,
+                    })
+                }
+            },
+            api_endpoint! {
+                "/pool_state",
+                async |_module: &StabilityPool, context, _v: ()| -> Option<PoolState> {
+                    Ok(context.dbtx().get_value(&PoolStateKey).await)
+                }
+            },
+        ]
+    }
+}
+
+impl StabilityPool {
+    /// Create new module instance
+    pub fn new(cfg: StabilityPoolConfig, oracle: Arc<dyn Oracle>) -> StabilityPool {
+        StabilityPool { cfg, oracle }
+    }
+
+    fn current_epoch(&self) -> u64 {
+        let now = fedimint_core::time::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        now / self.cfg.consensus.epoch_length
+    }
+
+    async fn rebalance(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        old_price: u64,
+        new_price: u64,
+    ) {
+        let seekers = dbtx
+            .find_by_prefix(&SeekerKeyPrefix)
+            .await
+            .map(|(key, amount)| (key.0, amount))
+            .collect::<Vec<_>>()
+            .await;
+        let providers = dbtx
+            .find_by_prefix(&ProviderKeyPrefix)
+            .await
+            .map(|(key, amount)| (key.0, amount))
+            .collect::<Vec<_>>()
+            .await;
+
+        let (seekers, providers) = rebalance_balances(
+            seekers,
+            providers,
+            old_price,
+            new_price,
+            self.cfg.consensus.seeker_fee_ppm,
+        );
+
+        for (account, amount) in seekers {
+            set_balance(dbtx, Side::Seeker, account, amount).await;
+        }
+        for (account, amount) in providers {
+            set_balance(dbtx, Side::Provider, account, amount).await;
+        }
+    }
+}
+
+async fn get_balance(
+    dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    side: Side,
+    account: secp256k1::XOnlyPublicKey,
+) -> Option<Amount> {
+    match side {
+        Side::Seeker => dbtx.get_value(&SeekerKey(account)).await,
+        Side::Provider => dbtx.get_value(&ProviderKey(account)).await,
+    }
+}
+
+/// Sets the balance of an account, removing it once it's empty
+async fn set_balance(
+    dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    side: Side,
+    account: secp256k1::XOnlyPublicKey,
+    amount: Amount,
+) {
+    match (side, amount == Amount::ZERO) {
+        (Side::Seeker, false) => {
+            dbtx.insert_entry(&SeekerKey(account), &amount).await;
+        }
+        (Side::Seeker, true) => {
+            dbtx.remove_entry(&SeekerKey(account)).await;
+        }
+        (Side::Provider, false) => {
+            dbtx.insert_entry(&ProviderKey(account), &amount).await;
+        }
+        (Side::Provider, true) => {
+            dbtx.remove_entry(&ProviderKey(account)).await;
+        }
+    }
+}
+
+/// Moves funds between seekers and providers so that the seekers' balances
+/// keep their fiat value when the price changes from `old_price` to
+/// `new_price`, after the seekers paid their fee to the providers.
+///
+/// Seekers can't gain more than the providers hold in total. Without any
+/// providers the balances stay untouched. Gains and losses are split between
+/// the accounts of each side proportionally to their balances, rounding down.
+fn rebalance_balances<K>(
+    seekers: Vec<(K, Amount)>,
+    providers: Vec<(K, Amount)>,
+    old_price: u64,
+    new_price: u64,
+    seeker_fee_ppm: u64,
+) -> (Vec<(K, Amount)>, Vec<(K, Amount)>) {
+    fn total<K>(accounts: &[(K, Amount)]) -> u128 {
+        accounts
+            .iter()
+            .map(|(_, amount)| amount.msats as u128)
+            .sum()
+    }
+
+    fn scale<K>(accounts: Vec<(K, Amount)>, old_total: u128, new_total: u128) -> Vec<(K, Amount)> {
+        accounts
+            .into_iter()
+            .map(|(key, amount)| {
+                let msats = amount.msats as u128 * new_total / old_total;
+                (key, Amount::from_msats(msats as u64))
+            })
+            .collect()
+    }
+
+    let seekers_total = total(&seekers);
+    let providers_total = total(&providers);
+
+    if seekers_total == 0 || providers_total == 0 {
+        return (seekers, providers);
+    }
+
+    let fee = seekers_total * seeker_fee_ppm as u128 / 1_000_000;
+    let seekers_after_fee = seekers_total - fee;
+    let providers_after_fee = providers_total + fee;
+
+    let seekers_target = seekers_after_fee * new_price as u128 / old_price as u128;
+    let (new_seekers_total, new_providers_total) = if seekers_target > seekers_after_fee {
+        let transfer = (seekers_target - seekers_after_fee).min(providers_after_fee);
+        (seekers_after_fee + transfer, providers_after_fee - transfer)
+    } else {
+        let transfer = seekers_after_fee - seekers_target;
+        (seekers_after_fee - transfer, providers_after_fee + transfer)
+    };
+
+    (
+        scale(seekers, seekers_total, new_seekers_total),
+        scale(providers, providers_total, new_providers_total),
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct StabilityPoolVerificationCache;
+
+impl fedimint_core::server::VerificationCache for StabilityPoolVerificationCache {}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use crate::rebalance_balances;
+
+    fn msats(accounts: &[(u8, Amount)]) -> Vec<u64> {
+        accounts.iter().map(|(_, amount)| amount.msats).collect()
+    }
+
+    #[test]
+    fn seekers_keep_fiat_value_when_price_falls() {
+        let seekers = vec![
+            (0, Amount::from_msats(1_000)),
+            (1, Amount::from_msats(3_000)),
+        ];
+        let providers = vec![(2, Amount::from_msats(10_000))];
+
+        // One unit of fiat costs twice as many msat as before
+        let (seekers, providers) = rebalance_balances(seekers, providers, 100, 200, 0);
+
+        assert_eq!(msats(&seekers), vec![2_000, 6_000]);
+        assert_eq!(msats(&providers), vec![6_000]);
+    }
+
+    #[test]
+    fn providers_gain_when_price_rises() {
+        let seekers = vec![(0, Amount::from_msats(4_000))];
+        let providers = vec![
+            (1, Amount::from_msats(1_000)),
+            (2, Amount::from_msats(1_000)),
+        ];
+
+        let (seekers, providers) = rebalance_balances(seekers, providers, 200, 100, 0);
+
+        assert_eq!(msats(&seekers), vec![2_000]);
+        assert_eq!(msats(&providers), vec![2_000, 2_000]);
+    }
+
+    #[test]
+    fn seeker_gains_are_capped_by_providers() {
+        let seekers = vec![(0, Amount::from_msats(1_000))];
+        let providers = vec![(1, Amount::from_msats(500))];
+
+        let (seekers, providers) = rebalance_balances(seekers, providers, 100, 1_000, 0);
+
+        assert_eq!(msats(&seekers), vec![1_500]);
+        assert_eq!(msats(&providers), vec![0]);
+    }
+
+    #[test]
+    fn seekers_pay_fee_to_providers() {
+        let seekers = vec![(0, Amount::from_msats(1_000_000))];
+        let providers = vec![(1, Amount::from_msats(1_000_000))];
+
+        let (seekers, providers) = rebalance_balances(seekers, providers, 100, 100, 1_000);
+
+        assert_eq!(msats(&seekers), vec![999_000]);
+        assert_eq!(msats(&providers), vec![1_001_000]);
+    }
+
+    #[test]
+    fn balances_untouched_without_providers() {
+        let seekers = vec![(0, Amount::from_msats(1_000))];
+
+        let (seekers, providers) = rebalance_balances(seekers, vec![], 100, 200, 1_000);
+
+        assert_eq!(msats(&seekers), vec![1_000]);
+        assert!(providers.is_empty());
+    }
+}
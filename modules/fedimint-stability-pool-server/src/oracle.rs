@@ -0,0 +1,58 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use url::Url;
+
+/// Environment variable pointing to the price feed the guardian proposes
+/// prices from, see [`HttpOracle`]
+pub const FM_STABILITY_POOL_ORACLE_URL_ENV: &str = "FM_STABILITY_POOL_ORACLE_URL";
+
+/// Source of the fiat price of bitcoin a guardian proposes to the federation
+#[async_trait]
+pub trait Oracle: Debug + Send + Sync {
+    /// Returns the price of one unit of the fiat currency in msat
+    async fn price(&self) -> anyhow::Result<u64>;
+}
+
+/// Fetches the price from an HTTP endpoint answering with a JSON object of the
+/// form `{"price": <msat per unit of fiat currency>}`
+#[derive(Debug)]
+pub struct HttpOracle {
+    url: Url,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct PriceResponse {
+    price: u64,
+}
+
+impl HttpOracle {
+    pub fn new(url: Url) -> Self {
+        HttpOracle {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Oracle for HttpOracle {
+    async fn price(&self) -> anyhow::Result<u64> {
+        let response = self
+            .client
+            .get(self.url.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<PriceResponse>()
+            .await?;
+
+        if response.price == 0 {
+            anyhow::bail!("Oracle returned a price of zero");
+        }
+
+        Ok(response.price)
+    }
+}
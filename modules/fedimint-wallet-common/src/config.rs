@@ -71,6 +71,18 @@ impl TypedClientModuleConfig for WalletClientConfig {
     }
 }
 
+/// Name of the adjustable parameter replacing [`FeeConsensus::peg_in_abs`],
+/// in msats
+pub const PEG_IN_FEE_PARAM: &str = "peg_in_abs_msats";
+
+/// Name of the adjustable parameter replacing [`FeeConsensus::peg_out_abs`],
+/// in msats
+pub const PEG_OUT_FEE_PARAM: &str = "peg_out_abs_msats";
+
+/// Name of the adjustable parameter replacing the `finality_delay`, the number
+/// of confirmations before the wallet considers a block final
+pub const FINALITY_DELAY_PARAM: &str = "finality_delay";
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
 pub struct FeeConsensus {
     pub peg_in_abs: fedimint_core::Amount,
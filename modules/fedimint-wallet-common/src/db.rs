@@ -1,5 +1,7 @@
 use bitcoin::{BlockHash, Txid};
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::ModuleParams;
+use fedimint_core::module::ModuleConsensusVersion;
 use fedimint_core::{impl_db_lookup, impl_db_record};
 use secp256k1::ecdsa::Signature;
 use serde::Serialize;
@@ -19,6 +21,9 @@ pub enum DbKeyPrefix {
     PendingTransaction = 0x35,
     PegOutTxSigCi = 0x36,
     PegOutBitcoinOutPoint = 0x37,
+    PegOutOwner = 0x38,
+    ConsensusVersion = 0x39,
+    Params = 0x3a,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -125,3 +130,40 @@ impl_db_lookup!(
     key = PegOutBitcoinTransaction,
     query_prefix = PegOutBitcoinTransactionPrefix
 );
+
+/// Owner of a pending peg-out, who can bump its fees with a
+/// [`WalletInput::Rbf`](crate::WalletInput::Rbf)
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutOwnerKey(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutOwnerPrefix;
+
+impl_db_record!(
+    key = PegOutOwnerKey,
+    value = secp256k1::XOnlyPublicKey,
+    db_prefix = DbKeyPrefix::PegOutOwner,
+);
+impl_db_lookup!(key = PegOutOwnerKey, query_prefix = PegOutOwnerPrefix);
+
+/// Consensus version the wallet runs at, version 0 until the federation
+/// activates a newer one
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct ConsensusVersionKey;
+
+impl_db_record!(
+    key = ConsensusVersionKey,
+    value = ModuleConsensusVersion,
+    db_prefix = DbKeyPrefix::ConsensusVersion,
+);
+
+/// Parameters the guardians changed from the ones of the config, see
+/// [`crate::config::FINALITY_DELAY_PARAM`] and the fee params next to it
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct ParamsKey;
+
+impl_db_record!(
+    key = ParamsKey,
+    value = ModuleParams,
+    db_prefix = DbKeyPrefix::Params,
+);
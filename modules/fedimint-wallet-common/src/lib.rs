@@ -1,4 +1,5 @@
 use std::hash::Hasher;
+use std::io::Read;
 
 use bitcoin::hashes::hex::ToHex;
 use bitcoin::hashes::sha256;
@@ -6,11 +7,11 @@ use bitcoin::util::psbt::raw::ProprietaryKey;
 use bitcoin::util::psbt::PartiallySignedTransaction;
 use bitcoin::{Amount, BlockHash, Network, Script, Transaction, Txid};
 use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
-use fedimint_core::encoding::{Decodable, Encodable, UnzipConsensus};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable, UnzipConsensus};
 use fedimint_core::module::__reexports::serde_json;
-use fedimint_core::module::{CommonModuleGen, ModuleCommon};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::{ApiVersion, CommonModuleGen, ModuleCommon, ModuleConsensusVersion};
 use fedimint_core::{plugin_types_trait_impl_common, Feerate, PeerId};
-use impl_tools::autoimpl;
 use miniscript::Descriptor;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -29,6 +30,18 @@ pub mod txoproof;
 
 const KIND: ModuleKind = ModuleKind::from_static_str("wallet");
 
+/// Consensus version of the wallet, has to be bumped when the encoding of its
+/// types changes
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = OWNED_PEG_OUTS_VERSION;
+
+/// Consensus version that added peg-outs with an [owner](PegOut::owner) and
+/// [`WalletInput::Rbf`], guardians reject both until the federation runs it
+pub const OWNED_PEG_OUTS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(1);
+
+/// API versions of the wallet's endpoints, one per supported major version,
+/// minor version 1 added `/consensus_version` and `/pending_peg_out_fees`
+pub const API_VERSIONS: &[ApiVersion] = &[ApiVersion { major: 0, minor: 1 }];
+
 pub const CONFIRMATION_TARGET: u16 = 10;
 
 pub type PartialSig = Vec<u8>;
@@ -168,6 +181,13 @@ pub struct PegOut {
     #[serde(with = "bitcoin::util::amount::serde::as_sat")]
     pub amount: bitcoin::Amount,
     pub fees: PegOutFees,
+    /// Key that authorizes bumping the fees of the peg-out with a
+    /// [`WalletInput::Rbf`], peg-outs without an owner can only be bumped with
+    /// a [`WalletOutput::Rbf`]
+    ///
+    /// Only accepted from [`OWNED_PEG_OUTS_VERSION`] on.
+    #[serde(default)]
+    pub owner: Option<secp256k1::XOnlyPublicKey>,
 }
 
 /// Contains the Bitcoin transaction id of the transaction created by the
@@ -195,26 +215,128 @@ impl CommonModuleGen for WalletCommonGen {
     }
 }
 
-#[autoimpl(Deref, DerefMut using self.0)]
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
-pub struct WalletInput(pub Box<PegInProof>);
+/// Peg-ins encode as their bare proof, like before the wallet had other
+/// inputs, so the encoding is the same at every consensus version
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub enum WalletInput {
+    PegIn(Box<PegInProof>),
+    /// Bumps the fees of a peg-out on behalf of its
+    /// [owner](PegOut::owner), who has to sign the transaction
+    ///
+    /// Only accepted from [`OWNED_PEG_OUTS_VERSION`] on.
+    Rbf(Rbf),
+}
+
+/// Prefix of the encoding of [`WalletInput::Rbf`], no peg-in proof starts with
+/// it since it is a block header followed by a merkle proof of no transactions
+const RBF_INPUT_PREFIX: [u8; 84] = [0; 84];
+
+impl Encodable for WalletInput {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        match self {
+            WalletInput::PegIn(proof) => proof.consensus_encode(writer),
+            WalletInput::Rbf(rbf) => {
+                writer.write_all(&RBF_INPUT_PREFIX)?;
+                Ok(RBF_INPUT_PREFIX.len() + rbf.consensus_encode(writer)?)
+            }
+        }
+    }
+}
+
+impl Decodable for WalletInput {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let mut prefix = [0; RBF_INPUT_PREFIX.len()];
+        d.read_exact(&mut prefix).map_err(DecodeError::from_err)?;
+
+        if prefix == RBF_INPUT_PREFIX {
+            return Ok(WalletInput::Rbf(Rbf::consensus_decode(d, modules)?));
+        }
+
+        let proof = PegInProof::consensus_decode(&mut prefix.as_slice().chain(d), modules)?;
+        Ok(WalletInput::PegIn(Box::new(proof)))
+    }
+}
 
 impl std::fmt::Display for WalletInput {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Wallet PegIn with Bitcoin TxId {}",
-            self.0.outpoint().txid
-        )
+        match self {
+            WalletInput::PegIn(proof) => {
+                write!(
+                    f,
+                    "Wallet PegIn with Bitcoin TxId {}",
+                    proof.outpoint().txid
+                )
+            }
+            WalletInput::Rbf(rbf) => {
+                write!(f, "Wallet owner RBF {:?} to {}", rbf.fees, rbf.txid)
+            }
+        }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+/// Peg-outs without an owner encode like before owners were added, peg-outs
+/// with one use a variant of their own
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub enum WalletOutput {
     PegOut(PegOut),
     Rbf(Rbf),
 }
 
+impl Encodable for WalletOutput {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let mut len = 0;
+        match self {
+            WalletOutput::PegOut(peg_out) => {
+                let variant: u64 = if peg_out.owner.is_some() { 2 } else { 0 };
+                len += variant.consensus_encode(writer)?;
+                len += peg_out.recipient.consensus_encode(writer)?;
+                len += peg_out.amount.consensus_encode(writer)?;
+                len += peg_out.fees.consensus_encode(writer)?;
+                if let Some(owner) = &peg_out.owner {
+                    len += owner.consensus_encode(writer)?;
+                }
+            }
+            WalletOutput::Rbf(rbf) => {
+                len += 1u64.consensus_encode(writer)?;
+                len += rbf.consensus_encode(writer)?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for WalletOutput {
+    fn consensus_decode<D: std::io::Read>(
+        d: &mut D,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let variant = u64::consensus_decode(d, modules)?;
+        if variant == 1 {
+            return Ok(WalletOutput::Rbf(Rbf::consensus_decode(d, modules)?));
+        }
+        if variant != 0 && variant != 2 {
+            return Err(DecodeError::from_str("Invalid wallet output variant"));
+        }
+
+        let recipient = Decodable::consensus_decode(d, modules)?;
+        let amount = Decodable::consensus_decode(d, modules)?;
+        let fees = Decodable::consensus_decode(d, modules)?;
+        let owner = match variant {
+            2 => Some(Decodable::consensus_decode(d, modules)?),
+            _ => None,
+        };
+        Ok(WalletOutput::PegOut(PegOut {
+            recipient,
+            amount,
+            fees,
+            owner,
+        }))
+    }
+}
+
 /// Allows a user to bump the fees of a `PendingTransaction`
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct Rbf {
@@ -307,6 +429,10 @@ pub enum WalletError {
     PegOutUnderDustLimit,
     #[error("RBF transaction id not found")]
     RbfTransactionIdNotFound,
+    #[error("Peg-out {0} has no owner that could bump its fees")]
+    PegOutWithoutOwner(Txid),
+    #[error("Owned peg-outs need wallet consensus version {0:?}, which isn't active yet")]
+    OwnedPegOutsInactive(ModuleConsensusVersion),
     #[error("Peg-out fee weight {0} doesn't match actual weight {1}")]
     TxWeightIncorrect(u64, u64),
     #[error("Peg-out fee rate is below min relay fee")]
@@ -343,3 +469,61 @@ impl PartialEq for WalletError {
 
 /// **WARNING**: this is only intended to be used for testing
 impl Eq for WalletError {}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use secp256k1::constants::GENERATOR_X;
+
+    use super::{PegOut, PegOutFees, Rbf, WalletInput, WalletOutput, RBF_INPUT_PREFIX};
+    use crate::txoproof::TxOutProof;
+
+    fn decode<T: Decodable>(bytes: &[u8]) -> Result<T, fedimint_core::encoding::DecodeError> {
+        T::consensus_decode(&mut Cursor::new(bytes), &ModuleDecoderRegistry::default())
+    }
+
+    fn rbf() -> Rbf {
+        Rbf {
+            fees: PegOutFees::new(1_000, 500),
+            txid: Txid::from_inner([2; 32]),
+        }
+    }
+
+    #[test]
+    fn rbf_input_prefix_is_no_peg_in_proof() {
+        assert!(decode::<TxOutProof>(&RBF_INPUT_PREFIX).is_err());
+    }
+
+    #[test]
+    fn wallet_items_decode_as_encoded() {
+        let input = WalletInput::Rbf(rbf());
+        let bytes = input.consensus_encode_to_vec().unwrap();
+        assert_eq!(decode::<WalletInput>(&bytes).unwrap(), input);
+
+        let peg_out = PegOut {
+            recipient: "tb1qunn0thpt8uk3yk2938ypjccn3urxprt78z9ccq"
+                .parse()
+                .unwrap(),
+            amount: bitcoin::Amount::from_sat(10_000),
+            fees: PegOutFees::new(1_000, 500),
+            owner: None,
+        };
+        let owned = PegOut {
+            owner: Some(secp256k1::XOnlyPublicKey::from_slice(&GENERATOR_X).unwrap()),
+            ..peg_out.clone()
+        };
+        for output in [
+            WalletOutput::PegOut(peg_out),
+            WalletOutput::PegOut(owned),
+            WalletOutput::Rbf(rbf()),
+        ] {
+            let bytes = output.consensus_encode_to_vec().unwrap();
+            assert_eq!(decode::<WalletOutput>(&bytes).unwrap(), output);
+        }
+    }
+}
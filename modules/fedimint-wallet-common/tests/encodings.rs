@@ -0,0 +1,81 @@
+//! Snapshots of the encodings of the wallet module's consensus types, see
+//! [`fedimint_core::encoding::compat`]
+
+use bitcoin::hashes::Hash;
+use bitcoin::{Address, Amount, Network, Txid};
+use fedimint_core::{encoding_snapshot_test, Feerate};
+use fedimint_wallet_common::config::{FeeConsensus, WalletClientConfig};
+use fedimint_wallet_common::keys::CompressedPublicKey;
+use fedimint_wallet_common::{
+    PegInDescriptor, PegOut, PegOutFees, PegOutSignatureItem, Rbf, RoundConsensusItem,
+    WalletConsensusItem, WalletInput, WalletOutput, WalletOutputOutcome, CONSENSUS_VERSION,
+};
+use miniscript::descriptor::Wsh;
+use secp256k1::constants::GENERATOR_X;
+use secp256k1::{ecdsa, PublicKey, XOnlyPublicKey};
+
+fn txid() -> Txid {
+    Txid::from_inner([2; 32])
+}
+
+fn fees() -> PegOutFees {
+    PegOutFees::new(1_000, 500)
+}
+
+/// The generator, so the encodings can be reproduced without running the code
+fn pub_key() -> PublicKey {
+    PublicKey::from_slice(&[&[0x02][..], &GENERATOR_X[..]].concat()).unwrap()
+}
+
+fn recipient() -> Address {
+    "tb1qunn0thpt8uk3yk2938ypjccn3urxprt78z9ccq"
+        .parse()
+        .unwrap()
+}
+
+fn peg_out(owner: Option<XOnlyPublicKey>) -> PegOut {
+    PegOut {
+        recipient: recipient(),
+        amount: Amount::from_sat(10_000),
+        fees: fees(),
+        owner,
+    }
+}
+
+encoding_snapshot_test!(wallet_encodings, CONSENSUS_VERSION.0, {
+    "WalletConsensusItem::RoundConsensus" =>
+        WalletConsensusItem::RoundConsensus(RoundConsensusItem {
+            block_height: 100,
+            fee_rate: Feerate { sats_per_kvb: 1_000 },
+            randomness: [1; 32],
+        }),
+    "WalletConsensusItem::PegOutSignature" =>
+        WalletConsensusItem::PegOutSignature(PegOutSignatureItem {
+            txid: txid(),
+            signature: vec![ecdsa::Signature::from_compact(&[1; 64]).unwrap()],
+        }),
+    "WalletInput::Rbf" => WalletInput::Rbf(Rbf {
+        fees: fees(),
+        txid: txid(),
+    }),
+    "WalletOutput::PegOut" => WalletOutput::PegOut(peg_out(None)),
+    "WalletOutput::PegOut::Owned" => WalletOutput::PegOut(peg_out(Some(
+        XOnlyPublicKey::from_slice(&GENERATOR_X).unwrap(),
+    ))),
+    "WalletOutput::Rbf" => WalletOutput::Rbf(Rbf {
+        fees: fees(),
+        txid: txid(),
+    }),
+    "WalletOutputOutcome" => WalletOutputOutcome(txid()),
+    "WalletClientConfig" => WalletClientConfig {
+        peg_in_descriptor: PegInDescriptor::Wsh(
+            Wsh::new_sortedmulti(1, vec![CompressedPublicKey::new(pub_key())]).unwrap(),
+        ),
+        network: Network::Regtest,
+        finality_delay: 10,
+        fee_consensus: FeeConsensus {
+            peg_in_abs: fedimint_core::Amount::from_msats(1_000),
+            peg_out_abs: fedimint_core::Amount::from_msats(2_000),
+        },
+    },
+});
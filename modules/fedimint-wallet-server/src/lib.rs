@@ -5,6 +5,7 @@ use std::ops::Sub;
 #[cfg(not(target_family = "wasm"))]
 use std::time::Duration;
 
+use anyhow::{bail, Context};
 use bitcoin::hashes::{sha256, Hash as BitcoinHash, HashEngine, Hmac, HmacEngine};
 use bitcoin::policy::DEFAULT_MIN_RELAY_TX_FEE;
 use bitcoin::secp256k1::{All, Secp256k1, Verification};
@@ -17,7 +18,7 @@ use bitcoin::{
 use common::config::WalletConfigConsensus;
 use common::db::DbKeyPrefix;
 use common::{
-    proprietary_tweak_key, IterUnzipWalletConsensusItem, PegOutFees, PegOutSignatureItem,
+    proprietary_tweak_key, IterUnzipWalletConsensusItem, PegOut, PegOutFees, PegOutSignatureItem,
     PendingTransaction, ProcessPegOutSigError, RoundConsensus, RoundConsensusItem, SpendableUTXO,
     UnsignedTransaction, UnzipWalletConsensusItem, WalletCommonGen, WalletConsensusItem,
     WalletError, WalletInput, WalletModuleTypes, WalletOutput, WalletOutputOutcome,
@@ -32,11 +33,12 @@ use fedimint_core::config::{
     ConfigGenParams, DkgResult, ModuleConfigResponse, ModuleGenParams, ServerModuleConfig,
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
-use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
 use fedimint_core::db::{
     Database, DatabaseTransaction, DatabaseVersion, ModuleDatabaseTransaction,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::ModuleParams;
 use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::interconnect::ModuleInterconect;
@@ -55,16 +57,20 @@ use fedimint_core::{
 };
 use fedimint_server::config::distributedgen::PeerHandleOps;
 pub use fedimint_wallet_common as common;
-use fedimint_wallet_common::config::WalletConfig;
+use fedimint_wallet_common::config::{
+    FeeConsensus, WalletConfig, FINALITY_DELAY_PARAM, PEG_IN_FEE_PARAM, PEG_OUT_FEE_PARAM,
+};
 use fedimint_wallet_common::db::{
-    BlockHashKey, BlockHashKeyPrefix, PegOutBitcoinTransaction, PegOutBitcoinTransactionPrefix,
-    PegOutTxSignatureCI, PegOutTxSignatureCIPrefix, PendingTransactionKey,
-    PendingTransactionPrefixKey, RoundConsensusKey, UTXOKey, UTXOPrefixKey, UnsignedTransactionKey,
+    BlockHashKey, BlockHashKeyPrefix, ConsensusVersionKey, ParamsKey, PegOutBitcoinTransaction,
+    PegOutBitcoinTransactionPrefix, PegOutOwnerKey, PegOutOwnerPrefix, PegOutTxSignatureCI,
+    PegOutTxSignatureCIPrefix, PendingTransactionKey, PendingTransactionPrefixKey,
+    RoundConsensusKey, UTXOKey, UTXOPrefixKey, UnsignedTransactionKey,
     UnsignedTransactionPrefixKey,
 };
 use fedimint_wallet_common::keys::CompressedPublicKey;
 use fedimint_wallet_common::tweakable::Tweakable;
-use fedimint_wallet_common::Rbf;
+use fedimint_wallet_common::txoproof::PegInProof;
+use fedimint_wallet_common::{Rbf, API_VERSIONS, CONSENSUS_VERSION, OWNED_PEG_OUTS_VERSION};
 use futures::{stream, StreamExt};
 use miniscript::psbt::PsbtExt;
 use miniscript::{Descriptor, TranslatePk};
@@ -94,8 +100,12 @@ impl ExtendsCommonModuleGen for WalletGen {
 impl ServerModuleGen for WalletGen {
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
-    fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
-        &[ModuleConsensusVersion(0)]
+    fn versions(&self, core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
+        if consensus_version_at(core) == CONSENSUS_VERSION {
+            &[CONSENSUS_VERSION]
+        } else {
+            &[ModuleConsensusVersion(0)]
+        }
     }
 
     async fn init(
@@ -208,6 +218,16 @@ impl ServerModuleGen for WalletGen {
                 DbKeyPrefix::BlockHash => {
                     push_db_key_items!(dbtx, BlockHashKeyPrefix, BlockHashKey, wallet, "Blocks");
                 }
+                DbKeyPrefix::ConsensusVersion => {
+                    if let Some(version) = dbtx.get_value(&ConsensusVersionKey).await {
+                        wallet.insert("Consensus Version".to_string(), Box::new(version));
+                    }
+                }
+                DbKeyPrefix::Params => {
+                    if let Some(params) = dbtx.get_value(&ParamsKey).await {
+                        wallet.insert("Params".to_string(), Box::new(params));
+                    }
+                }
                 DbKeyPrefix::PegOutBitcoinOutPoint => {
                     push_db_pair_items!(
                         dbtx,
@@ -218,6 +238,16 @@ impl ServerModuleGen for WalletGen {
                         "Peg Out Bitcoin Transaction"
                     );
                 }
+                DbKeyPrefix::PegOutOwner => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutOwnerPrefix,
+                        PegOutOwnerKey,
+                        secp256k1::XOnlyPublicKey,
+                        wallet,
+                        "Peg Out Owners"
+                    );
+                }
                 DbKeyPrefix::PegOutTxSigCi => {
                     push_db_pair_items!(
                         dbtx,
@@ -278,10 +308,7 @@ impl ServerModule for Wallet {
     type VerificationCache = WalletVerificationCache;
 
     fn versions(&self) -> (ModuleConsensusVersion, &[ApiVersion]) {
-        (
-            ModuleConsensusVersion(0),
-            &[ApiVersion { major: 0, minor: 0 }],
-        )
+        (CONSENSUS_VERSION, API_VERSIONS)
     }
 
     async fn await_consensus_proposal(
@@ -300,7 +327,7 @@ impl ServerModule for Wallet {
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
     ) -> ConsensusProposal<WalletConsensusItem> {
         // TODO: implement retry logic in case bitcoind is temporarily unreachable
-        let our_target_height = self.target_height().await;
+        let our_target_height = self.target_height(dbtx).await;
 
         // In case the wallet just got created the height is not committed to the DB yet
         // but will be set to 0 first, so we can assume that here.
@@ -379,6 +406,39 @@ impl ServerModule for Wallet {
             .await;
     }
 
+    async fn activate_consensus_version(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        version: CoreConsensusVersion,
+    ) {
+        let version = consensus_version_at(version);
+        info!(?version, "Activating wallet consensus version");
+        dbtx.insert_entry(&ConsensusVersionKey, &version).await;
+    }
+
+    fn validate_params(&self, params: &ModuleParams) -> anyhow::Result<()> {
+        for (name, value) in params {
+            match name.as_str() {
+                PEG_IN_FEE_PARAM | PEG_OUT_FEE_PARAM => {}
+                FINALITY_DELAY_PARAM => {
+                    u32::try_from(*value).context("Finality delay is too large")?;
+                }
+                _ => bail!("Unknown parameter {name}"),
+            }
+        }
+        Ok(())
+    }
+
+    async fn activate_params(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        params: &ModuleParams,
+    ) {
+        let mut active = dbtx.get_value(&ParamsKey).await.unwrap_or_default();
+        active.extend(params.clone());
+        dbtx.insert_entry(&ParamsKey, &active).await;
+    }
+
     fn build_verification_cache<'a>(
         &'a self,
         _inputs: impl Iterator<Item = &'a WalletInput>,
@@ -393,26 +453,23 @@ impl ServerModule for Wallet {
         _verification_cache: &Self::VerificationCache,
         input: &'a WalletInput,
     ) -> Result<InputMeta, ModuleError> {
-        if !self.block_is_known(dbtx, input.proof_block()).await {
-            return Err(WalletError::UnknownPegInProofBlock(input.proof_block()))
-                .into_module_error_other();
-        }
-
-        input
-            .verify(&self.secp, &self.cfg.consensus.peg_in_descriptor)
-            .into_module_error_other()?;
-
-        if dbtx.get_value(&UTXOKey(input.outpoint())).await.is_some() {
-            return Err(WalletError::PegInAlreadyClaimed).into_module_error_other();
+        match input {
+            WalletInput::PegIn(proof) => self.validate_peg_in(dbtx, proof).await,
+            WalletInput::Rbf(rbf) => {
+                self.require_owned_peg_outs(dbtx).await?;
+                let (_, owner) = self.create_owner_rbf_tx(dbtx, rbf).await?;
+
+                // The bump is paid like a peg-out, but as the fee of the input
+                // since it doesn't create an output of its own
+                Ok(InputMeta {
+                    amount: TransactionItemAmount {
+                        amount: fedimint_core::Amount::ZERO,
+                        fee: self.fee_consensus(dbtx).await.peg_out_abs + rbf.fees.amount().into(),
+                    },
+                    puk_keys: vec![owner],
+                })
+            }
         }
-
-        Ok(InputMeta {
-            amount: TransactionItemAmount {
-                amount: fedimint_core::Amount::from_sats(input.tx_output().value),
-                fee: self.cfg.consensus.fee_consensus.peg_in_abs,
-            },
-            puk_keys: vec![*input.tweak_contract_key()],
-        })
     }
 
     async fn apply_input<'a, 'b, 'c>(
@@ -425,16 +482,28 @@ impl ServerModule for Wallet {
         let meta = self
             .validate_input(interconnect, dbtx, cache, input)
             .await?;
-        debug!(outpoint = %input.outpoint(), amount = %meta.amount.amount, "Claiming peg-in");
 
-        dbtx.insert_new_entry(
-            &UTXOKey(input.outpoint()),
-            &SpendableUTXO {
-                tweak: input.tweak_contract_key().serialize(),
-                amount: bitcoin::Amount::from_sat(input.tx_output().value),
-            },
-        )
-        .await;
+        match input {
+            WalletInput::PegIn(proof) => {
+                debug!(outpoint = %proof.outpoint(), amount = %meta.amount.amount, "Claiming peg-in");
+
+                dbtx.insert_new_entry(
+                    &UTXOKey(proof.outpoint()),
+                    &SpendableUTXO {
+                        tweak: proof.tweak_contract_key().serialize(),
+                        amount: bitcoin::Amount::from_sat(proof.tx_output().value),
+                    },
+                )
+                .await;
+            }
+            WalletInput::Rbf(rbf) => {
+                let (tx, owner) = self
+                    .create_owner_rbf_tx(dbtx, rbf)
+                    .await
+                    .expect("Should have been validated");
+                self.sign_peg_out_tx(dbtx, tx, Some(owner)).await;
+            }
+        }
 
         Ok(meta)
     }
@@ -444,6 +513,10 @@ impl ServerModule for Wallet {
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
         output: &WalletOutput,
     ) -> Result<TransactionItemAmount, ModuleError> {
+        if let WalletOutput::PegOut(PegOut { owner: Some(_), .. }) = output {
+            self.require_owned_peg_outs(dbtx).await?;
+        }
+
         let fee_rate = self.current_round_consensus(dbtx).await.unwrap().fee_rate;
         let tx = self
             .create_peg_out_tx(dbtx, output)
@@ -456,7 +529,7 @@ impl ServerModule for Wallet {
 
         Ok(TransactionItemAmount {
             amount: output.amount().into(),
-            fee: self.cfg.consensus.fee_consensus.peg_out_abs,
+            fee: self.fee_consensus(dbtx).await.peg_out_abs,
         })
     }
 
@@ -468,52 +541,17 @@ impl ServerModule for Wallet {
     ) -> Result<TransactionItemAmount, ModuleError> {
         let amount = self.validate_output(dbtx, output).await?;
 
-        let mut tx = self
+        let tx = self
             .create_peg_out_tx(dbtx, output)
             .await
             .expect("Should have been validated");
-        self.offline_wallet().sign_psbt(&mut tx.psbt);
-        let txid = tx.psbt.unsigned_tx.txid();
-        info!(
-            %txid,
-            "Signing peg out",
-        );
-
-        let sigs = tx
-            .psbt
-            .inputs
-            .iter_mut()
-            .map(|input| {
-                assert_eq!(
-                    input.partial_sigs.len(),
-                    1,
-                    "There was already more than one (our) or no signatures in input"
-                );
-
-                // TODO: don't put sig into PSBT in the first place
-                // We actually take out our own signature so everyone finalizes the tx in the
-                // same epoch.
-                let sig = std::mem::take(&mut input.partial_sigs)
-                    .into_values()
-                    .next()
-                    .expect("asserted previously");
-
-                // We drop SIGHASH_ALL, because we always use that and it is only present in the
-                // PSBT for compatibility with other tools.
-                secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
-                    .expect("we serialized it ourselves that way")
-            })
-            .collect::<Vec<_>>();
-
-        // Delete used UTXOs
-        for input in tx.psbt.unsigned_tx.input.iter() {
-            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
-        }
+        // a bump keeps the owner of the peg-out it replaces
+        let owner = match output {
+            WalletOutput::PegOut(peg_out) => peg_out.owner,
+            WalletOutput::Rbf(rbf) => dbtx.get_value(&PegOutOwnerKey(rbf.txid)).await,
+        };
+        let txid = self.sign_peg_out_tx(dbtx, tx, owner).await;
 
-        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
-            .await;
-        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
-            .await;
         dbtx.insert_new_entry(
             &PegOutBitcoinTransaction(out_point),
             &WalletOutputOutcome(txid),
@@ -608,6 +646,80 @@ impl ServerModule for Wallet {
             .await;
     }
 
+    async fn verify_integrity(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Vec<String> {
+        // only bitcoind can tell us whether an output is unspent
+        if !matches!(self.btc_rpc.backend_type(), BitcoinRpcBackendType::Bitcoind) {
+            return vec![];
+        }
+
+        let utxos = dbtx
+            .find_by_prefix(&UTXOPrefixKey)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut violations = vec![];
+        for (key, utxo) in utxos {
+            match self.btc_rpc.get_utxo_amount(&key.0).await {
+                Ok(Some(amount)) if amount == utxo.amount => {}
+                Ok(Some(amount)) => violations.push(format!(
+                    "UTXO {} holds {amount} but we recorded {}",
+                    key.0, utxo.amount
+                )),
+                Ok(None) => violations.push(format!(
+                    "UTXO {} was spent or is unknown to bitcoind",
+                    key.0
+                )),
+                Err(error) => {
+                    warn!(%error, "Could not check our UTXOs against bitcoind");
+                    break;
+                }
+            }
+        }
+
+        violations
+    }
+
+    fn local_db_prefixes(&self) -> Vec<u8> {
+        // our own peg-out signatures
+        vec![DbKeyPrefix::PegOutTxSigCi as u8]
+    }
+
+    fn consensus_db_prefixes(&self) -> Vec<u8> {
+        vec![
+            DbKeyPrefix::BlockHash as u8,
+            DbKeyPrefix::Utxo as u8,
+            DbKeyPrefix::RoundConsensus as u8,
+            DbKeyPrefix::UnsignedTransaction as u8,
+            DbKeyPrefix::PendingTransaction as u8,
+            DbKeyPrefix::PegOutBitcoinOutPoint as u8,
+            DbKeyPrefix::PegOutOwner as u8,
+            DbKeyPrefix::ConsensusVersion as u8,
+            DbKeyPrefix::Params as u8,
+        ]
+    }
+
+    fn public_input_amount(&self, input: &WalletInput) -> Option<fedimint_core::Amount> {
+        match input {
+            WalletInput::PegIn(proof) => {
+                Some(fedimint_core::Amount::from_sats(proof.tx_output().value))
+            }
+            WalletInput::Rbf(_) => Some(fedimint_core::Amount::ZERO),
+        }
+    }
+
+    fn public_output_amount(&self, output: &WalletOutput) -> Option<fedimint_core::Amount> {
+        Some(output.amount().into())
+    }
+
+    fn epoch_dependencies(&self) -> Vec<ModuleKind> {
+        // Syncing blocks and signing peg-outs only touches the wallet's own state
+        vec![]
+    }
+
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
         vec![
             api_endpoint! {
@@ -616,6 +728,19 @@ impl ServerModule for Wallet {
                     Ok(module.consensus_height(&mut context.dbtx()).await.unwrap_or(0))
                 }
             },
+            api_endpoint! {
+                "/consensus_version",
+                async |module: &Wallet, context, _params: ()| -> ModuleConsensusVersion {
+                    Ok(module.consensus_version(&mut context.dbtx()).await)
+                }
+            },
+            api_endpoint! {
+                "/pending_peg_out_fees",
+                async |_module: &Wallet, context, txid: Txid| -> Option<PegOutFees> {
+                    let pending = context.dbtx().get_value(&PendingTransactionKey(txid)).await;
+                    Ok(pending.map(|tx| tx.fees))
+                }
+            },
             api_endpoint! {
                 "/peg_out_fees",
                 async |module: &Wallet, context, params: (Address, u64)| -> Option<PegOutFees> {
@@ -916,13 +1041,16 @@ impl Wallet {
         dbtx.get_value(&RoundConsensusKey).await
     }
 
-    pub async fn target_height(&self) -> u32 {
+    pub async fn target_height(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> u32 {
         let our_network_height = self
             .btc_rpc
             .get_block_height()
             .await
             .expect("bitcoind rpc failed") as u32;
-        our_network_height.saturating_sub(self.cfg.consensus.finality_delay)
+        our_network_height.saturating_sub(self.finality_delay(dbtx).await)
     }
 
     pub async fn consensus_height(
@@ -1072,6 +1200,7 @@ impl Wallet {
             all_transactions.remove(&removed.tx.txid());
             dbtx.remove_entry(&PendingTransactionKey(removed.tx.txid()))
                 .await;
+            dbtx.remove_entry(&PegOutOwnerKey(removed.tx.txid())).await;
 
             // Search for tx that this `removed` has as RBF
             if let Some(rbf) = &removed.rbf {
@@ -1139,6 +1268,169 @@ impl Wallet {
         }
     }
 
+    /// Signs a peg-out tx created by [`Self::create_peg_out_tx`] and stores
+    /// it until the signatures of our peers arrive
+    async fn sign_peg_out_tx(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        mut tx: UnsignedTransaction,
+        owner: Option<secp256k1::XOnlyPublicKey>,
+    ) -> Txid {
+        self.offline_wallet().sign_psbt(&mut tx.psbt);
+
+        let txid = tx.psbt.unsigned_tx.txid();
+        info!(
+            %txid,
+            "Signing peg out",
+        );
+
+        let sigs = tx
+            .psbt
+            .inputs
+            .iter_mut()
+            .map(|input| {
+                assert_eq!(
+                    input.partial_sigs.len(),
+                    1,
+                    "There was already more than one (our) or no signatures in input"
+                );
+
+                // TODO: don't put sig into PSBT in the first place
+                // We actually take out our own signature so everyone finalizes the tx in the
+                // same epoch.
+                let sig = std::mem::take(&mut input.partial_sigs)
+                    .into_values()
+                    .next()
+                    .expect("asserted previously");
+
+                // We drop SIGHASH_ALL, because we always use that and it is only present in the
+                // PSBT for compatibility with other tools.
+                secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
+                    .expect("we serialized it ourselves that way")
+            })
+            .collect::<Vec<_>>();
+
+        // Delete used UTXOs
+        for input in tx.psbt.unsigned_tx.input.iter() {
+            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
+        }
+
+        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
+            .await;
+        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
+            .await;
+        if let Some(owner) = owner {
+            dbtx.insert_new_entry(&PegOutOwnerKey(txid), &owner).await;
+        }
+        txid
+    }
+
+    /// Wallet consensus version the federation runs at
+    async fn consensus_version(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> ModuleConsensusVersion {
+        dbtx.get_value(&ConsensusVersionKey)
+            .await
+            .unwrap_or(ModuleConsensusVersion(0))
+    }
+
+    /// Fees of the config with the changes the guardians activated
+    async fn fee_consensus(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> FeeConsensus {
+        let params = dbtx.get_value(&ParamsKey).await.unwrap_or_default();
+        let fees = &self.cfg.consensus.fee_consensus;
+        let fee = |name: &str, default: fedimint_core::Amount| {
+            params
+                .get(name)
+                .map_or(default, |msats| fedimint_core::Amount::from_msats(*msats))
+        };
+        FeeConsensus {
+            peg_in_abs: fee(PEG_IN_FEE_PARAM, fees.peg_in_abs),
+            peg_out_abs: fee(PEG_OUT_FEE_PARAM, fees.peg_out_abs),
+        }
+    }
+
+    /// Number of confirmations of the config, or as the guardians changed it,
+    /// before a block is considered final
+    async fn finality_delay(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> u32 {
+        dbtx.get_value(&ParamsKey)
+            .await
+            .and_then(|params| params.get(FINALITY_DELAY_PARAM).copied())
+            .map_or(self.cfg.consensus.finality_delay, |delay| delay as u32)
+    }
+
+    /// Fails unless the federation runs a consensus version with owned
+    /// peg-outs, guardians of older versions can't decode them
+    async fn require_owned_peg_outs(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> Result<(), ModuleError> {
+        if self.consensus_version(dbtx).await < OWNED_PEG_OUTS_VERSION {
+            return Err(WalletError::OwnedPegOutsInactive(OWNED_PEG_OUTS_VERSION))
+                .into_module_error_other();
+        }
+        Ok(())
+    }
+
+    /// Creates the tx of a fee bump requested by the owner of a peg-out and
+    /// returns it with the owner's key
+    async fn create_owner_rbf_tx(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        rbf: &Rbf,
+    ) -> Result<(UnsignedTransaction, secp256k1::XOnlyPublicKey), ModuleError> {
+        let owner = dbtx
+            .get_value(&PegOutOwnerKey(rbf.txid))
+            .await
+            .ok_or(WalletError::PegOutWithoutOwner(rbf.txid))
+            .into_module_error_other()?;
+
+        let output = WalletOutput::Rbf(rbf.clone());
+        let fee_rate = self.current_round_consensus(dbtx).await.unwrap().fee_rate;
+        let tx = self
+            .create_peg_out_tx(dbtx, &output)
+            .await
+            .into_module_error_other()?;
+        self.offline_wallet()
+            .validate_tx(&tx, &output, fee_rate, self.cfg.consensus.network)
+            .into_module_error_other()?;
+
+        Ok((tx, owner))
+    }
+
+    async fn validate_peg_in(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        proof: &PegInProof,
+    ) -> Result<InputMeta, ModuleError> {
+        if !self.block_is_known(dbtx, proof.proof_block()).await {
+            return Err(WalletError::UnknownPegInProofBlock(proof.proof_block()))
+                .into_module_error_other();
+        }
+
+        proof
+            .verify(&self.secp, &self.cfg.consensus.peg_in_descriptor)
+            .into_module_error_other()?;
+
+        if dbtx.get_value(&UTXOKey(proof.outpoint())).await.is_some() {
+            return Err(WalletError::PegInAlreadyClaimed).into_module_error_other();
+        }
+
+        Ok(InputMeta {
+            amount: TransactionItemAmount {
+                amount: fedimint_core::Amount::from_sats(proof.tx_output().value),
+                fee: self.fee_consensus(dbtx).await.peg_in_abs,
+            },
+            puk_keys: vec![*proof.tweak_contract_key()],
+        })
+    }
+
     async fn available_utxos(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
@@ -1172,6 +1464,16 @@ impl Wallet {
 }
 
 #[instrument(level = "debug", skip_all)]
+/// Wallet consensus version the federation runs at core consensus version
+/// `core`
+fn consensus_version_at(core: CoreConsensusVersion) -> ModuleConsensusVersion {
+    if core >= CoreConsensusVersion(1) {
+        OWNED_PEG_OUTS_VERSION
+    } else {
+        ModuleConsensusVersion(0)
+    }
+}
+
 pub async fn run_broadcast_pending_tx(db: Database, rpc: DynBitcoindRpc, tg_handle: &TaskHandle) {
     while !tg_handle.is_shutting_down() {
         broadcast_pending_tx(db.begin_transaction().await, &rpc).await;
@@ -1705,6 +2007,7 @@ mod tests {
             recipient,
             amount: Amount::from_sat(1000),
             fees: PegOutFees::new(100, weight),
+            owner: None,
         });
         let res = wallet.validate_tx(&tx, &output, fee, Testnet);
         assert_eq!(res, Err(WalletError::WrongNetwork(Testnet, Bitcoin)));
@@ -1974,6 +2277,11 @@ mod fedimint_migration_tests {
                                 "validate_migrations was not able to read any PegOutTxSigCi"
                             );
                         }
+                        // Peg-out owners, consensus versions and params were added after
+                        // the v0 snapshot was taken
+                        DbKeyPrefix::PegOutOwner
+                        | DbKeyPrefix::ConsensusVersion
+                        | DbKeyPrefix::Params => {}
                         DbKeyPrefix::PendingTransaction => {
                             let pending_txs = dbtx
                                 .find_by_prefix(&PendingTransactionPrefixKey)
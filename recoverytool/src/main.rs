@@ -21,7 +21,8 @@ use fedimint_ln_server::Lightning;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_server::Mint;
 use fedimint_rocksdb::RocksDb;
-use fedimint_server::config::io::read_server_config;
+use fedimint_server::config::io::read_server_config_with_key;
+use fedimint_server::config::keys::ConfigKeySource;
 use fedimint_server::db::EpochHistoryKeyPrefix;
 use fedimint_server::epoch::{IterUnzipConsensusItem, SignedEpochOutcome, UnzipConsensusItem};
 use fedimint_server::transaction::Transaction;
@@ -52,6 +53,13 @@ struct RecoveryTool {
     /// The password that encrypts the configs
     #[arg(long = "password", env = "FM_PASSWORD", requires = "config")]
     password: String,
+    /// Source of the key the private config is encrypted with
+    #[arg(
+        long = "config-key",
+        env = "FM_CONFIG_KEY_SOURCE",
+        default_value = "password"
+    )]
+    config_key: ConfigKeySource,
     /// Wallet descriptor, can be used instead of --cfg
     #[arg(long = "descriptor")]
     descriptor: Option<PegInDescriptor>,
@@ -106,7 +114,12 @@ async fn main() -> anyhow::Result<()> {
     let opts: RecoveryTool = RecoveryTool::parse();
 
     let (base_descriptor, base_key, network) = if let Some(config) = opts.config {
-        let cfg = read_server_config(&opts.password, config).expect("Could not read config file");
+        let key = opts
+            .config_key
+            .config_key(&opts.password, &config)
+            .await
+            .expect("Could not get the config key");
+        let cfg = read_server_config_with_key(&key, config).expect("Could not read config file");
         let wallet_cfg: WalletConfig = cfg
             .get_module_config_typed(LEGACY_HARDCODED_INSTANCE_ID_WALLET)
             .expect("Malformed wallet config");
@@ -240,15 +253,14 @@ fn input_tweaks_output_present(
                         return None;
                     }
 
-                    Some(
-                        input
-                            .as_any()
-                            .downcast_ref::<WalletInput>()
-                            .expect("Instance id mapping incorrect")
-                            .0
-                            .tweak_contract_key()
-                            .serialize(),
-                    )
+                    match input
+                        .as_any()
+                        .downcast_ref::<WalletInput>()
+                        .expect("Instance id mapping incorrect")
+                    {
+                        WalletInput::PegIn(proof) => Some(proof.tweak_contract_key().serialize()),
+                        WalletInput::Rbf(_) => None,
+                    }
                 })
             })
             .collect::<BTreeSet<_>>();